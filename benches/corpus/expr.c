@@ -0,0 +1,119 @@
+/* Tokenizer and recursive-descent evaluator for integer expressions */
+
+enum token_kind { TOK_NUM, TOK_PLUS, TOK_MINUS, TOK_STAR, TOK_SLASH, TOK_LPAREN, TOK_RPAREN, TOK_END };
+
+struct token {
+    enum token_kind kind;
+    long value;
+};
+
+struct lexer {
+    const char *text;
+    int pos;
+    struct token current;
+};
+
+static int is_digit(char c) { return c >= '0' && c <= '9'; }
+static int is_space(char c) { return c == ' ' || c == '\t' || c == '\n'; }
+
+static void next(struct lexer *lx) {
+    while (is_space(lx->text[lx->pos]))
+        lx->pos++;
+    char c = lx->text[lx->pos];
+    if (c == '\0') {
+        lx->current.kind = TOK_END;
+        return;
+    }
+    if (is_digit(c)) {
+        long value = 0;
+        while (is_digit(lx->text[lx->pos]))
+            value = value * 10 + (lx->text[lx->pos++] - '0');
+        lx->current.kind = TOK_NUM;
+        lx->current.value = value;
+        return;
+    }
+    lx->pos++;
+    switch (c) {
+    case '+': lx->current.kind = TOK_PLUS; break;
+    case '-': lx->current.kind = TOK_MINUS; break;
+    case '*': lx->current.kind = TOK_STAR; break;
+    case '/': lx->current.kind = TOK_SLASH; break;
+    case '(': lx->current.kind = TOK_LPAREN; break;
+    case ')': lx->current.kind = TOK_RPAREN; break;
+    default: lx->current.kind = TOK_END; break;
+    }
+}
+
+static long expression(struct lexer *lx);
+
+static long primary(struct lexer *lx) {
+    if (lx->current.kind == TOK_NUM) {
+        long value = lx->current.value;
+        next(lx);
+        return value;
+    }
+    if (lx->current.kind == TOK_MINUS) {
+        next(lx);
+        return -primary(lx);
+    }
+    if (lx->current.kind == TOK_LPAREN) {
+        next(lx);
+        long value = expression(lx);
+        if (lx->current.kind == TOK_RPAREN)
+            next(lx);
+        return value;
+    }
+    return 0;
+}
+
+static long term(struct lexer *lx) {
+    long value = primary(lx);
+    for (;;) {
+        if (lx->current.kind == TOK_STAR) {
+            next(lx);
+            value *= primary(lx);
+        } else if (lx->current.kind == TOK_SLASH) {
+            next(lx);
+            long divisor = primary(lx);
+            value = divisor != 0 ? value / divisor : 0;
+        } else {
+            return value;
+        }
+    }
+}
+
+static long expression(struct lexer *lx) {
+    long value = term(lx);
+    for (;;) {
+        if (lx->current.kind == TOK_PLUS) {
+            next(lx);
+            value += term(lx);
+        } else if (lx->current.kind == TOK_MINUS) {
+            next(lx);
+            value -= term(lx);
+        } else {
+            return value;
+        }
+    }
+}
+
+static long evaluate(const char *text) {
+    struct lexer lx = { text, 0, { TOK_END, 0 } };
+    next(&lx);
+    return expression(&lx);
+}
+
+int main(void) {
+    static const char *inputs[] = {
+        "1 + 2 * 3",
+        "(4 + 5) * (6 - 7) / 3",
+        "-(12 * (3 + 4)) + 100",
+        "((((1))))+((((2))))*((((3))))",
+        "1000000 / 7 / 11 / 13",
+    };
+    long total = 0;
+    for (int round = 0; round < 100; round++)
+        for (unsigned i = 0; i < sizeof inputs / sizeof inputs[0]; i++)
+            total += evaluate(inputs[i]);
+    return total == 100 * (7 - 3 + 16 + 7 + 999) ? 0 : 1;
+}
@@ -0,0 +1,98 @@
+/* Open-addressing hash map from strings to counters */
+
+typedef unsigned long size_t;
+void *calloc(size_t count, size_t size);
+void free(void *ptr);
+
+struct entry {
+    const char *key;
+    unsigned hash;
+    int count;
+};
+
+struct map {
+    struct entry *slots;
+    size_t capacity;
+    size_t used;
+};
+
+static unsigned hash_string(const char *s) {
+    unsigned h = 2166136261u;
+    while (*s) {
+        h ^= (unsigned char)*s++;
+        h *= 16777619u;
+    }
+    return h;
+}
+
+static int same(const char *a, const char *b) {
+    while (*a && *a == *b) {
+        a++;
+        b++;
+    }
+    return *a == *b;
+}
+
+static int map_init(struct map *m, size_t capacity) {
+    m->slots = calloc(capacity, sizeof(struct entry));
+    m->capacity = capacity;
+    m->used = 0;
+    return m->slots != 0;
+}
+
+static int map_grow(struct map *m);
+
+static struct entry *map_slot(struct map *m, const char *key, unsigned hash) {
+    size_t mask = m->capacity - 1;
+    for (size_t i = hash & mask;; i = (i + 1) & mask) {
+        struct entry *e = &m->slots[i];
+        if (!e->key || (e->hash == hash && same(e->key, key)))
+            return e;
+    }
+}
+
+static int map_add(struct map *m, const char *key) {
+    if ((m->used + 1) * 4 > m->capacity * 3 && !map_grow(m))
+        return -1;
+    unsigned hash = hash_string(key);
+    struct entry *e = map_slot(m, key, hash);
+    if (!e->key) {
+        e->key = key;
+        e->hash = hash;
+        m->used++;
+    }
+    return ++e->count;
+}
+
+static int map_grow(struct map *m) {
+    struct map bigger;
+    if (!map_init(&bigger, m->capacity * 2))
+        return 0;
+    for (size_t i = 0; i < m->capacity; i++) {
+        struct entry *e = &m->slots[i];
+        if (e->key)
+            *map_slot(&bigger, e->key, e->hash) = *e;
+    }
+    bigger.used = m->used;
+    free(m->slots);
+    *m = bigger;
+    return 1;
+}
+
+int main(void) {
+    static const char *words[] = {
+        "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta",
+        "iota", "kappa", "lambda", "mu", "nu", "xi", "omicron", "pi",
+        "rho", "sigma", "tau", "upsilon", "phi", "chi", "psi", "omega",
+    };
+    struct map m;
+    if (!map_init(&m, 8))
+        return 1;
+    int last = 0;
+    for (int round = 0; round < 50; round++)
+        for (unsigned i = 0; i < sizeof words / sizeof words[0]; i++)
+            last = map_add(&m, words[i]);
+    int ok = m.used == sizeof words / sizeof words[0] && last == 50;
+    free(m.slots);
+    return ok ? 0 : 2;
+}
@@ -0,0 +1,80 @@
+/* Dense linear algebra: multiply, transpose and a Gaussian elimination */
+
+#define N 24
+
+typedef double matrix[N][N];
+
+static void identity(matrix m) {
+    for (int i = 0; i < N; i++)
+        for (int j = 0; j < N; j++)
+            m[i][j] = i == j ? 1.0 : 0.0;
+}
+
+static void fill(matrix m, unsigned seed) {
+    for (int i = 0; i < N; i++)
+        for (int j = 0; j < N; j++) {
+            seed = seed * 1103515245u + 12345u;
+            m[i][j] = (double)(seed % 1000) / 100.0 + (i == j ? N : 0);
+        }
+}
+
+static void multiply(matrix out, matrix a, matrix b) {
+    for (int i = 0; i < N; i++)
+        for (int j = 0; j < N; j++) {
+            double sum = 0.0;
+            for (int k = 0; k < N; k++)
+                sum += a[i][k] * b[k][j];
+            out[i][j] = sum;
+        }
+}
+
+static void transpose(matrix out, matrix m) {
+    for (int i = 0; i < N; i++)
+        for (int j = 0; j < N; j++)
+            out[j][i] = m[i][j];
+}
+
+static double fabs_(double x) { return x < 0 ? -x : x; }
+
+/* Determinant by elimination with partial pivoting; destroys `m` */
+static double determinant(matrix m) {
+    double det = 1.0;
+    for (int col = 0; col < N; col++) {
+        int pivot = col;
+        for (int row = col + 1; row < N; row++)
+            if (fabs_(m[row][col]) > fabs_(m[pivot][col]))
+                pivot = row;
+        if (m[pivot][col] == 0.0)
+            return 0.0;
+        if (pivot != col) {
+            for (int k = 0; k < N; k++) {
+                double t = m[col][k];
+                m[col][k] = m[pivot][k];
+                m[pivot][k] = t;
+            }
+            det = -det;
+        }
+        det *= m[col][col];
+        for (int row = col + 1; row < N; row++) {
+            double factor = m[row][col] / m[col][col];
+            for (int k = col; k < N; k++)
+                m[row][k] -= factor * m[col][k];
+        }
+    }
+    return det;
+}
+
+int main(void) {
+    static matrix a, b, c, t, id;
+    fill(a, 1);
+    fill(b, 2);
+    identity(id);
+    multiply(c, a, id);
+    for (int i = 0; i < N; i++)
+        for (int j = 0; j < N; j++)
+            if (c[i][j] != a[i][j])
+                return 1;
+    multiply(c, a, b);
+    transpose(t, c);
+    return determinant(t) != 0.0 ? 0 : 2;
+}
@@ -0,0 +1,82 @@
+/* A small stack machine: unions, function pointer tables and a switch loop */
+
+enum opcode { OP_PUSH, OP_ADD, OP_SUB, OP_MUL, OP_DUP, OP_OVER, OP_SWAP, OP_JMP, OP_JNZ, OP_DEC, OP_CALL, OP_HALT };
+
+struct instruction {
+    enum opcode op;
+    union {
+        long immediate;
+        int target;
+        int builtin;
+    } arg;
+};
+
+struct machine {
+    long stack[64];
+    int sp;
+    int pc;
+    unsigned long steps;
+};
+
+typedef void (*builtin_fn)(struct machine *);
+
+static void push(struct machine *m, long v) { m->stack[m->sp++] = v; }
+static long pop(struct machine *m) { return m->stack[--m->sp]; }
+
+static void builtin_square(struct machine *m) {
+    long v = pop(m);
+    push(m, v * v);
+}
+
+static void builtin_negate(struct machine *m) { push(m, -pop(m)); }
+
+static const builtin_fn builtins[] = { builtin_square, builtin_negate };
+
+static long run(const struct instruction *code, struct machine *m) {
+    m->sp = 0;
+    m->pc = 0;
+    m->steps = 0;
+    for (;;) {
+        const struct instruction *in = &code[m->pc++];
+        m->steps++;
+        switch (in->op) {
+        case OP_PUSH: push(m, in->arg.immediate); break;
+        case OP_ADD: { long b = pop(m), a = pop(m); push(m, a + b); break; }
+        case OP_SUB: { long b = pop(m), a = pop(m); push(m, a - b); break; }
+        case OP_MUL: { long b = pop(m), a = pop(m); push(m, a * b); break; }
+        case OP_DUP: { long a = pop(m); push(m, a); push(m, a); break; }
+        case OP_OVER: push(m, m->stack[m->sp - 2]); break;
+        case OP_SWAP: { long b = pop(m), a = pop(m); push(m, b); push(m, a); break; }
+        case OP_JMP: m->pc = in->arg.target; break;
+        case OP_DEC: push(m, pop(m) - 1); break;
+        case OP_JNZ:
+            if (m->stack[m->sp - 1] != 0)
+                m->pc = in->arg.target;
+            break;
+        case OP_CALL: builtins[in->arg.builtin](m); break;
+        case OP_HALT: return m->stack[0];
+        }
+    }
+}
+
+int main(void) {
+    /* Sum of the squares of 10..1, with [n, acc] on the stack */
+    static const struct instruction program[] = {
+        { OP_PUSH, { .immediate = 10 } },
+        { OP_PUSH, { .immediate = 0 } },
+        /* 2: */ { OP_OVER, { 0 } },
+        { OP_CALL, { .builtin = 0 } },
+        { OP_ADD, { 0 } },
+        { OP_SWAP, { 0 } },
+        { OP_DEC, { 0 } },
+        { OP_JNZ, { .target = 9 } },
+        { OP_HALT, { 0 } },
+        /* 9: */ { OP_SWAP, { 0 } },
+        { OP_JMP, { .target = 2 } },
+    };
+    struct machine m;
+    long result = 0;
+    for (int i = 0; i < 200; i++)
+        result += run(program, &m);
+    return result == 200 * 385 ? 0 : 1;
+}
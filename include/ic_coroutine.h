@@ -0,0 +1,61 @@
+/* include/ic_coroutine.h
+ *
+ * Cooperative coroutines for C programs run by Interpreter-C, compiled or
+ * interpreted with --engine=bytecode. The engine provides the functions;
+ * there is nothing to link. Compile with -Iinclude.
+ *
+ * Conventions:
+ *  - A coroutine runs only when resumed, and until it yields or its entry
+ *    returns. Coroutines belong to the thread that created them.
+ *  - ic_coroutine_create and ic_coroutine_destroy return 0 or an error
+ *    number. ic_coroutine_resume and ic_coroutine_yield return NULL and set
+ *    errno on failure, so check ic_coroutine_status when NULL is a value.
+ *  - Destroying a suspended coroutine discards it without unwinding it.
+ */
+#ifndef IC_COROUTINE_H
+#define IC_COROUTINE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* 0 is never a coroutine: ic_coroutine_self() returns it outside one */
+typedef uint64_t ic_coroutine_t;
+
+enum {
+    IC_COROUTINE_SUSPENDED = 0,
+    IC_COROUTINE_RUNNING = 1,
+    /* Resumed another coroutine and waits for it to yield */
+    IC_COROUTINE_NORMAL = 2,
+    IC_COROUTINE_DEAD = 3,
+};
+
+/* A suspended coroutine that will run entry(arg) on a stack of stack_size
+ * bytes (0: 256 KiB). EINVAL if entry can't be started, ENOMEM. */
+int ic_coroutine_create(ic_coroutine_t *co, void *(*entry)(void *), void *arg, size_t stack_size);
+
+/* Run co until it yields or returns, and evaluate to what it yielded or
+ * returned. value is what its ic_coroutine_yield evaluates to; the first
+ * resume's is dropped. ESRCH unknown, EINVAL finished, EBUSY running. */
+void *ic_coroutine_resume(ic_coroutine_t co, void *value);
+
+/* Suspend the running coroutine, passing value to its resumer, and
+ * evaluate to the value of the next resume. EPERM outside a coroutine. */
+void *ic_coroutine_yield(void *value);
+
+/* An IC_COROUTINE_ state, or -1 with errno ESRCH */
+int ic_coroutine_status(ic_coroutine_t co);
+
+ic_coroutine_t ic_coroutine_self(void);
+
+/* Free co and its stack. EBUSY if it is running or waiting on another. */
+int ic_coroutine_destroy(ic_coroutine_t co);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* IC_COROUTINE_H */
@@ -0,0 +1,120 @@
+/* include/interpreter_c.h
+ *
+ * C interface to the Interpreter-C embedding engine. Build the library with
+ *
+ *     cargo rustc --release --lib --features capi --crate-type cdylib
+ *
+ * and link against target/release/libinterpreter_c.so.
+ *
+ * Conventions:
+ *  - Every fallible function returns an ic_status; IC_OK is zero. After a
+ *    failure, ic_last_error() describes it.
+ *  - Strings passed in are NUL-terminated UTF-8 and only read during the call.
+ *  - Strings handed out belong to the library. The ic_last_error() text stays
+ *    valid until the calling thread's next ic_ call; copy it to keep it.
+ *  - Nothing the library returns must be freed by the caller, except the
+ *    engine itself, which ic_engine_free releases together with its code.
+ *  - An engine may move between threads, but calls on one engine must not
+ *    overlap. Compiled C runs on the calling thread, in this process.
+ */
+#ifndef INTERPRETER_C_H
+#define INTERPRETER_C_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct ic_engine ic_engine;
+
+typedef enum ic_status {
+    IC_OK = 0,
+    IC_ERR_NULL_ARGUMENT = 1,
+    IC_ERR_INVALID_UTF8 = 2,
+    IC_ERR_COMPILE = 3,
+    IC_ERR_IO = 4,
+    IC_ERR_SYMBOL_NOT_FOUND = 5,
+    /* Arguments beyond the registers need more than 16 stack slots */
+    IC_ERR_TOO_MANY_ARGUMENTS = 6,
+    IC_ERR_INVALID_ARGUMENT = 7,
+    /* The host isn't x86_64 or aarch64 */
+    IC_ERR_UNSUPPORTED = 8,
+    /* A bug in the library; the engine should not be used further */
+    IC_ERR_PANIC = 9,
+} ic_status;
+
+typedef enum ic_type {
+    IC_VOID = 0,
+    IC_I8 = 1,
+    IC_I16 = 2,
+    IC_I32 = 3,
+    IC_I64 = 4,
+    IC_FLOAT = 5,
+    IC_DOUBLE = 6,
+    IC_PTR = 7,
+} ic_type;
+
+/* A tagged value; ty says which member of value is meaningful */
+typedef struct ic_value {
+    ic_type ty;
+    union {
+        int8_t i8;
+        int16_t i16;
+        int32_t i32;
+        int64_t i64;
+        float f;
+        double d;
+        void *ptr;
+    } value;
+} ic_value;
+
+/* Called when compiled C calls a function registered with ic_set_callback.
+ * result->ty is preset to the declared return type; store the value in the
+ * matching member. Returning void (rather than ic_value) keeps the callback
+ * expressible with Python's ctypes.CFUNCTYPE. */
+typedef void (*ic_callback)(void *user_data, const ic_value *args, size_t nargs, ic_value *result);
+
+/* Create an engine for the host; *out is NULL on failure */
+ic_status ic_engine_new(ic_engine **out);
+
+/* Free the engine and all code compiled into it. NULL is ignored. */
+void ic_engine_free(ic_engine *engine);
+
+/* Compile one translation unit. Its functions and globals stay available to
+ * later units and to ic_call. If the unit defines main, main runs: *ran_main
+ * is set to true and its result stored in *main_result. Either output may be
+ * NULL. */
+ic_status ic_eval(ic_engine *engine, const char *source, int32_t *main_result, bool *ran_main);
+
+/* ic_eval on the contents of a file */
+ic_status ic_eval_file(ic_engine *engine, const char *path, int32_t *main_result, bool *ran_main);
+
+/* Call a compiled function. The prototype isn't checked: args and
+ * return_type must match it. *result may be NULL to discard the result. */
+ic_status ic_call(ic_engine *engine, const char *name, const ic_value *args, size_t nargs,
+                  ic_type return_type, ic_value *result);
+
+/* Copy size bytes of a global variable into out, or from value into it */
+ic_status ic_get_global(ic_engine *engine, const char *name, void *out, size_t size);
+ic_status ic_set_global(ic_engine *engine, const char *name, const void *value, size_t size);
+
+/* Let C compiled from now on call callback as name. The C code declares a
+ * prototype matching params and return_type; a mismatch fails that
+ * ic_eval. user_data is passed back on every call. */
+ic_status ic_set_callback(ic_engine *engine, const char *name, const ic_type *params, size_t nparams,
+                          ic_type return_type, ic_callback callback, void *user_data);
+
+/* The calling thread's last failure, or NULL if its last call succeeded */
+const char *ic_last_error(void);
+
+/* Static name of a status, such as "IC_ERR_COMPILE" */
+const char *ic_status_name(ic_status status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* INTERPRETER_C_H */
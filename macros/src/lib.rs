@@ -0,0 +1,176 @@
+// macros/src/lib.rs
+//! Procedural macros for embedding Interpreter-C
+//! `#[c_export]` turns an ordinary Rust function into a host function that
+//! C compiled by an `Engine` can call. It leaves the function untouched and
+//! adds a hidden constructor for its `HostExport`: an `extern "C"` wrapper
+//! plus the C signature, read off the parameter and return types through
+//! `HostType`. A type without a C equivalent is a compile error at the
+//! function, not a prototype mismatch at `eval_string`.
+//!
+//! `c_exports![a, b, path::c]` collects the exports of several functions
+//! into a `Vec<HostExport>` for `Engine::register_exports`. Enable the
+//! `macros` feature of `interpreter_c` rather than depending on this crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, FnArg, ItemFn, LitStr, Path, ReturnType, Token};
+
+/// `#[c_export]` or `#[c_export(name = "c_name")]`
+struct ExportArgs {
+    name: Option<LitStr>,
+}
+
+impl Parse for ExportArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.is_empty() {
+            return Ok(ExportArgs { name: None });
+        }
+        let key: Ident = input.parse()?;
+        if key != "name" {
+            return Err(syn::Error::new(key.span(), "expected `name = \"...\"`"));
+        }
+        input.parse::<Token![=]>()?;
+        let name: LitStr = input.parse()?;
+        if !is_c_identifier(&name.value()) {
+            return Err(syn::Error::new(name.span(), "not a valid C identifier"));
+        }
+        Ok(ExportArgs { name: Some(name) })
+    }
+}
+
+/// Make a Rust function callable from C under its own name, or `name`
+///
+/// ```ignore
+/// #[c_export]
+/// fn clamp(x: i32, lo: i32, hi: i32) -> i32 {
+///     x.max(lo).min(hi)
+/// }
+///
+/// engine.register_exports(c_exports![clamp])?;
+/// engine.eval_string("int clamp(int, int, int); int main(void) { return clamp(70, 0, 42); }")?;
+/// ```
+#[proc_macro_attribute]
+pub fn c_export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as ExportArgs);
+    let function = parse_macro_input!(item as ItemFn);
+    expand_export(args, function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// `Vec<HostExport>` of the listed `#[c_export]` functions
+#[proc_macro]
+pub fn c_exports(input: TokenStream) -> TokenStream {
+    let paths = parse_macro_input!(input with Punctuated::<Path, Token![,]>::parse_terminated);
+    let exports = paths.into_iter().map(|mut path| {
+        // A parsed path always has at least one segment
+        let last = path.segments.last_mut().unwrap();
+        last.ident = export_ident(&last.ident);
+        quote!(#path())
+    });
+    quote!(::std::vec![#(#exports),*]).into()
+}
+
+fn expand_export(args: ExportArgs, function: ItemFn) -> syn::Result<TokenStream2> {
+    let sig = &function.sig;
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(syn::Error::new(asyncness.span, "C can't await an async function"));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(&sig.generics, "a generic function has no single C signature"));
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(syn::Error::new_spanned(variadic, "variadic host functions aren't supported"));
+    }
+
+    let mut params = Vec::new();
+    let mut types = Vec::new();
+    for (index, input) in sig.inputs.iter().enumerate() {
+        match input {
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(receiver, "methods can't be exported; use a free function"));
+            }
+            FnArg::Typed(arg) => {
+                params.push(format_ident!("arg{}", index));
+                types.push(&arg.ty);
+            }
+        }
+    }
+    let output = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+
+    let ident = &sig.ident;
+    let c_name = match args.name {
+        Some(name) => name.value(),
+        None => ident.to_string(),
+    };
+    let call = if sig.unsafety.is_some() {
+        quote!(unsafe { #ident(#(#params),*) })
+    } else {
+        quote!(#ident(#(#params),*))
+    };
+    let vis = &function.vis;
+    let export = export_ident(ident);
+
+    Ok(quote! {
+        #function
+
+        #[doc(hidden)]
+        #[allow(non_snake_case)]
+        #vis fn #export() -> ::interpreter_c::HostExport {
+            extern "C" fn wrapper(#(#params: #types),*) -> #output {
+                ::interpreter_c::HostExport::guard(#c_name, || #call)
+            }
+            ::interpreter_c::HostExport {
+                name: #c_name,
+                signature: ::interpreter_c::HostSignature::new(
+                    ::std::vec![#(<#types as ::interpreter_c::HostType>::jit_type()),*],
+                    <#output as ::interpreter_c::HostType>::jit_type(),
+                ),
+                function: ::interpreter_c::HostFunction::Pointer(wrapper as *const ::std::ffi::c_void),
+            }
+        }
+    })
+}
+
+/// The hidden function `c_exports!` finds for `#[c_export] fn name`
+fn export_ident(ident: &Ident) -> Ident {
+    format_ident!("__c_export_{}", ident)
+}
+
+fn is_c_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+// Example usage:
+/*
+use interpreter_c::{c_export, c_exports, Engine};
+
+#[c_export]
+fn host_add(a: i64, b: i64) -> i64 {
+    a + b
+}
+
+#[c_export(name = "host_strlen")]
+unsafe fn strlen(s: *const u8) -> usize {
+    std::ffi::CStr::from_ptr(s.cast()).to_bytes().len()
+}
+
+fn main() -> Result<(), interpreter_c::EngineError> {
+    let mut engine = Engine::new()?;
+    engine.register_exports(c_exports![host_add, strlen])?;
+    engine.eval_string(r#"
+        long host_add(long, long);
+        unsigned long host_strlen(const char *);
+        int main(void) { return host_add(40, host_strlen("ab")); }
+    "#)?; // 42
+    Ok(())
+}
+*/
@@ -0,0 +1,458 @@
+// src/abi/aggregate.rs
+//! Structs and unions passed and returned by value
+//! C code receives a struct argument in whatever registers its ABI assigns
+//! to the struct's bytes, so a caller outside compiled code has to do the
+//! same classification the compiler did:
+//!
+//! - System V x86-64 splits an aggregate of up to 16 bytes into eightbytes.
+//!   An eightbyte holding only `float`/`double` goes in an SSE register,
+//!   anything else in a general-purpose one. Larger aggregates are MEMORY:
+//!   copied onto the stack as arguments, written through a hidden pointer
+//!   (in `rdi`) as results.
+//! - AAPCS64 passes a homogeneous floating-point aggregate (HFA: one to four
+//!   members of the same float type) in consecutive FP registers. Other
+//!   aggregates of up to 16 bytes go in one or two X registers; larger ones
+//!   are passed as a pointer to a copy and returned through `x8`.
+//!
+//! When an aggregate doesn't fit in the registers left it goes on the stack
+//! whole. `marshal` does the classification and register assignment and
+//! produces the raw words `abi::call` loads into registers and the stack.
+//! `long double`, `__int128`, vector types and over-aligned types aren't
+//! supported.
+
+use crate::arch::StructLayout;
+use crate::jit::JITValue;
+
+/// Integer registers for arguments
+const SYSV_INTEGER_REGISTERS: usize = 6;
+const AAPCS64_INTEGER_REGISTERS: usize = 8;
+/// FP registers for arguments, on both ABIs
+const FLOAT_REGISTERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Pointer,
+}
+
+impl Scalar {
+    pub fn size(self) -> usize {
+        match self {
+            Scalar::I8 => 1,
+            Scalar::I16 => 2,
+            Scalar::I32 | Scalar::F32 => 4,
+            Scalar::I64 | Scalar::F64 | Scalar::Pointer => 8,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Scalar::F32 | Scalar::F64)
+    }
+}
+
+/// A C object type, enough of it to lay it out and classify it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CType {
+    Scalar(Scalar),
+    Array(Box<CType>, usize),
+    /// Members in declaration order
+    Struct(Vec<CType>),
+    Union(Vec<CType>),
+}
+
+impl CType {
+    pub fn size(&self) -> usize {
+        self.layout().size
+    }
+
+    pub fn align(&self) -> usize {
+        self.layout().alignment
+    }
+
+    /// Size, alignment and member offsets under the usual C rules: each
+    /// member at the next multiple of its alignment, the size rounded up
+    /// to the largest alignment. An array's "members" are its elements.
+    pub fn layout(&self) -> StructLayout {
+        match self {
+            CType::Scalar(scalar) => StructLayout {
+                size: scalar.size(),
+                alignment: scalar.size(),
+                field_offsets: vec![0],
+            },
+            CType::Array(element, count) => {
+                let element = element.layout();
+                StructLayout {
+                    size: element.size * count,
+                    alignment: element.alignment,
+                    field_offsets: (0..*count).map(|index| index * element.size).collect(),
+                }
+            }
+            CType::Struct(members) => {
+                let mut offset = 0;
+                let mut alignment = 1;
+                let mut field_offsets = Vec::with_capacity(members.len());
+                for member in members {
+                    let member = member.layout();
+                    offset = round_up(offset, member.alignment);
+                    field_offsets.push(offset);
+                    offset += member.size;
+                    alignment = alignment.max(member.alignment);
+                }
+                StructLayout {
+                    size: round_up(offset, alignment),
+                    alignment,
+                    field_offsets,
+                }
+            }
+            CType::Union(members) => {
+                let layouts: Vec<StructLayout> = members.iter().map(CType::layout).collect();
+                let alignment = layouts.iter().map(|l| l.alignment).max().unwrap_or(1);
+                StructLayout {
+                    size: round_up(layouts.iter().map(|l| l.size).max().unwrap_or(0), alignment),
+                    alignment,
+                    field_offsets: vec![0; members.len()],
+                }
+            }
+        }
+    }
+
+    /// Every scalar in the type and its byte offset; union members overlap
+    pub fn scalars(&self) -> Vec<(usize, Scalar)> {
+        let mut scalars = Vec::new();
+        self.collect_scalars(0, &mut scalars);
+        scalars
+    }
+
+    fn collect_scalars(&self, base: usize, scalars: &mut Vec<(usize, Scalar)>) {
+        match self {
+            CType::Scalar(scalar) => scalars.push((base, *scalar)),
+            CType::Array(element, count) => {
+                let size = element.size();
+                for index in 0..*count {
+                    element.collect_scalars(base + index * size, scalars);
+                }
+            }
+            CType::Struct(members) | CType::Union(members) => {
+                for (member, offset) in members.iter().zip(self.layout().field_offsets) {
+                    member.collect_scalars(base + offset, scalars);
+                }
+            }
+        }
+    }
+}
+
+fn round_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    /// x86-64 Linux and macOS
+    SystemV,
+    /// AArch64 Linux
+    Aapcs64,
+}
+
+impl Abi {
+    /// The ABI of the machine this process runs on
+    pub fn host() -> Option<Self> {
+        if cfg!(target_arch = "x86_64") {
+            Some(Abi::SystemV)
+        } else if cfg!(target_arch = "aarch64") {
+            Some(Abi::Aapcs64)
+        } else {
+            None
+        }
+    }
+
+    pub fn integer_registers(self) -> usize {
+        match self {
+            Abi::SystemV => SYSV_INTEGER_REGISTERS,
+            Abi::Aapcs64 => AAPCS64_INTEGER_REGISTERS,
+        }
+    }
+}
+
+/// Register file an 8-byte chunk of a value travels in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegClass {
+    /// General purpose: `rdi`.., `x0`..
+    Integer,
+    /// Floating point: `xmm0`.., `v0`..
+    Sse,
+}
+
+/// How a value is passed or returned, before registers are assigned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Classification {
+    /// One register per 8-byte chunk of the value, of these classes
+    Registers(Vec<RegClass>),
+    /// AAPCS64 HFA: `count` members of `base`, one per FP register
+    Hfa { base: Scalar, count: usize },
+    /// System V MEMORY: on the stack, or written through a hidden pointer
+    Memory,
+    /// AAPCS64: a pointer to a copy, or written through `x8`
+    Reference,
+}
+
+pub fn classify(abi: Abi, ty: &CType) -> Result<Classification, AbiError> {
+    if let CType::Scalar(scalar) = ty {
+        let class = if scalar.is_float() { RegClass::Sse } else { RegClass::Integer };
+        return Ok(Classification::Registers(vec![class]));
+    }
+
+    let size = ty.size();
+    if size == 0 {
+        return Err(AbiError::EmptyAggregate);
+    }
+    if ty.align() > 8 {
+        return Err(AbiError::UnsupportedAlignment(ty.align()));
+    }
+    let words = size.div_ceil(8);
+
+    match abi {
+        Abi::SystemV => {
+            if size > 16 {
+                return Ok(Classification::Memory);
+            }
+            // An eightbyte is SSE only if everything in it is floating point
+            let mut classes = vec![RegClass::Sse; words];
+            for (offset, scalar) in ty.scalars() {
+                if !scalar.is_float() {
+                    classes[offset / 8] = RegClass::Integer;
+                }
+            }
+            Ok(Classification::Registers(classes))
+        }
+        Abi::Aapcs64 => {
+            if let Some((base, count)) = homogeneous_float(ty) {
+                return Ok(Classification::Hfa { base, count });
+            }
+            if size > 16 {
+                return Ok(Classification::Reference);
+            }
+            Ok(Classification::Registers(vec![RegClass::Integer; words]))
+        }
+    }
+}
+
+/// The base type and member count, if `ty` is an HFA
+fn homogeneous_float(ty: &CType) -> Option<(Scalar, usize)> {
+    let scalars = ty.scalars();
+    let base = scalars.first()?.1;
+    if !base.is_float() || scalars.iter().any(|&(_, scalar)| scalar != base) {
+        return None;
+    }
+    let size = ty.size();
+    let count = size / base.size();
+    (size % base.size() == 0 && (1..=4).contains(&count)).then_some((base, count))
+}
+
+/// An argument: a scalar, or the bytes of a struct or union
+#[derive(Debug, Clone, Copy)]
+pub enum Argument<'a> {
+    Value(JITValue),
+    /// `ty.size()` bytes laid out as `ty`
+    Aggregate(&'a CType, &'a [u8]),
+}
+
+impl<'a> Argument<'a> {
+    /// Pass `value`, a `#[repr(C)]` Rust type laid out as `ty`
+    pub fn aggregate<T: Copy>(ty: &'a CType, value: &'a T) -> Self {
+        // SAFETY: `T: Copy` has no drop glue and the slice covers exactly `value`
+        let bytes = unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) };
+        Argument::Aggregate(ty, bytes)
+    }
+}
+
+/// Where a result comes back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResultShape {
+    Void,
+    /// The first return registers of each class, one per 8-byte chunk
+    Registers(Vec<RegClass>),
+    /// `count` FP return registers, `base.size()` bytes used of each
+    Hfa { base: Scalar, count: usize },
+    /// Written to memory the caller provides
+    Memory { size: usize },
+}
+
+/// A call's arguments as register and stack contents
+#[derive(Debug, Default)]
+pub struct Marshalled {
+    /// General-purpose argument registers, in order
+    pub integers: Vec<u64>,
+    /// FP argument registers, as raw bits; an `f32` in the low half
+    pub floats: Vec<u64>,
+    /// The stack argument area, from the stack pointer up
+    pub stack: Vec<u64>,
+    /// Integer registers available to arguments; one fewer when System V
+    /// takes `rdi` for a hidden result pointer
+    pub integer_limit: usize,
+    /// A call to a variadic function, made through a variadic signature
+    pub variadic: bool,
+    result: Option<ResultShape>,
+    /// Copies of by-reference arguments, which must live until the call returns
+    copies: Vec<Box<[u64]>>,
+}
+
+impl Marshalled {
+    pub fn result(&self) -> &ResultShape {
+        self.result.as_ref().unwrap_or(&ResultShape::Void)
+    }
+
+    /// (integer registers, FP registers, stack words, by-reference copies)
+    pub fn stats(&self) -> (usize, usize, usize, usize) {
+        (self.integers.len(), self.floats.len(), self.stack.len(), self.copies.len())
+    }
+}
+
+/// Assign `args` and the result (`None` for `void`) to registers and stack
+/// slots, as a caller compiled for `abi` would
+pub fn marshal(abi: Abi, args: &[Argument], result: Option<&CType>) -> Result<Marshalled, AbiError> {
+    let shape = match result {
+        None => ResultShape::Void,
+        Some(ty) => match classify(abi, ty)? {
+            Classification::Registers(classes) => ResultShape::Registers(classes),
+            Classification::Hfa { base, count } => ResultShape::Hfa { base, count },
+            Classification::Memory | Classification::Reference => ResultShape::Memory { size: ty.size() },
+        },
+    };
+    let hidden_pointer = abi == Abi::SystemV && matches!(shape, ResultShape::Memory { .. });
+
+    let mut call = Marshalled {
+        integer_limit: abi.integer_registers() - usize::from(hidden_pointer),
+        result: Some(shape),
+        ..Marshalled::default()
+    };
+    for arg in args {
+        match *arg {
+            Argument::Value(JITValue::Void) => return Err(AbiError::VoidArgument),
+            Argument::Value(value @ (JITValue::Float(_) | JITValue::Double(_))) => call.push_float(value.to_raw()),
+            Argument::Value(value) => call.push_integer(value.to_raw()),
+            Argument::Aggregate(ty, bytes) => {
+                if bytes.len() != ty.size() {
+                    return Err(AbiError::SizeMismatch { expected: ty.size(), found: bytes.len() });
+                }
+                call.push_aggregate(abi, ty, bytes)?;
+            }
+        }
+    }
+    Ok(call)
+}
+
+impl Marshalled {
+    fn push_integer(&mut self, word: u64) {
+        if self.integers.len() < self.integer_limit {
+            self.integers.push(word);
+        } else {
+            self.stack.push(word);
+        }
+    }
+
+    fn push_float(&mut self, bits: u64) {
+        if self.floats.len() < FLOAT_REGISTERS {
+            self.floats.push(bits);
+        } else {
+            self.stack.push(bits);
+        }
+    }
+
+    fn push_aggregate(&mut self, abi: Abi, ty: &CType, bytes: &[u8]) -> Result<(), AbiError> {
+        match classify(abi, ty)? {
+            Classification::Registers(classes) => {
+                let integers = classes.iter().filter(|&&c| c == RegClass::Integer).count();
+                let floats = classes.len() - integers;
+                let fits = self.integers.len() + integers <= self.integer_limit
+                    && self.floats.len() + floats <= FLOAT_REGISTERS;
+                if !fits {
+                    // AAPCS64 closes the X registers to everything after
+                    if abi == Abi::Aapcs64 {
+                        self.integer_limit = self.integers.len();
+                    }
+                    self.stack.extend(words(bytes));
+                    return Ok(());
+                }
+                for (class, word) in classes.into_iter().zip(words(bytes)) {
+                    match class {
+                        RegClass::Integer => self.integers.push(word),
+                        RegClass::Sse => self.floats.push(word),
+                    }
+                }
+            }
+            Classification::Hfa { base, count } => {
+                if self.floats.len() + count > FLOAT_REGISTERS {
+                    // As are the V registers
+                    self.floats.resize(FLOAT_REGISTERS, 0);
+                    self.stack.extend(words(bytes));
+                    return Ok(());
+                }
+                for member in bytes.chunks(base.size()) {
+                    self.floats.push(word(member));
+                }
+            }
+            Classification::Memory => self.stack.extend(words(bytes)),
+            Classification::Reference => {
+                let copy: Box<[u64]> = words(bytes).collect();
+                self.push_integer(copy.as_ptr() as u64);
+                self.copies.push(copy);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `bytes` as little-endian 8-byte words, the last zero-padded
+fn words(bytes: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    bytes.chunks(8).map(word)
+}
+
+fn word(bytes: &[u8]) -> u64 {
+    let mut padded = [0u8; 8];
+    padded[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(padded)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiError {
+    /// A struct or union with no members
+    EmptyAggregate,
+    /// Alignment beyond 8, from `_Alignas` or a vector member
+    UnsupportedAlignment(usize),
+    /// An aggregate argument's bytes don't match its type's size
+    SizeMismatch { expected: usize, found: usize },
+    VoidArgument,
+    /// A variadic call with fewer arguments than named parameters
+    MissingFixedArguments { fixed: usize, found: usize },
+    /// More stack arguments than a call can carry
+    TooManyArguments,
+    /// A result larger than a call can receive
+    ResultTooLarge(usize),
+    /// Only x86-64 and AArch64 hosts can make calls
+    UnsupportedHost,
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), AbiError> {
+    // struct point { double x, y; }; struct rect { struct point a, b; };
+    let point = CType::Struct(vec![CType::Scalar(Scalar::F64), CType::Scalar(Scalar::F64)]);
+    let rect = CType::Struct(vec![point.clone(), point.clone()]);
+
+    assert_eq!(classify(Abi::SystemV, &point)?, Classification::Registers(vec![RegClass::Sse, RegClass::Sse]));
+    assert_eq!(classify(Abi::SystemV, &rect)?, Classification::Memory);
+    assert_eq!(classify(Abi::Aapcs64, &rect)?, Classification::Hfa { base: Scalar::F64, count: 4 });
+
+    // double area(struct rect r, int scale)
+    let r = [0.0f64, 0.0, 3.0, 4.0];
+    let call = marshal(Abi::SystemV, &[Argument::aggregate(&rect, &r), Argument::Value(JITValue::Int32(2))], Some(&CType::Scalar(Scalar::F64)))?;
+    assert_eq!(call.stack.len(), 4);
+    assert_eq!(call.integers, vec![2]);
+    Ok(())
+}
+*/
@@ -0,0 +1,184 @@
+// src/abi/call.rs
+//! Calling compiled C with arguments marshalled by `aggregate::marshal`
+//! Rust can't build a call frame at runtime, so every call goes through
+//! one wide signature: eight `u64`s, eight `f64`s, then sixteen more
+//! `u64`s. Rust's own `extern "C"` lowering puts the `f64`s in the FP
+//! argument registers and fills the integer registers with the first
+//! `u64`s; the `u64`s that don't fit continue on the stack in order. So
+//! filling the `u64`s with the integer registers (exactly as many as the
+//! ABI has) followed by the stack words reproduces the frame the callee
+//! expects. Unused trailing words are harmless; the caller pops them.
+//!
+//! Results come back the same way: the function is called as returning a
+//! `#[repr(C)]` type that Rust's ABI returns in the same registers, such as
+//! `Pair<u64, f64>` for an INTEGER+SSE struct in `rax` and `xmm0`. A struct
+//! returned in memory is received as a `LargeResult`; Rust passes the
+//! hidden pointer (`rdi` or `x8`) and the callee fills its first bytes.
+//!
+//! A call to a variadic function goes through the same words with a
+//! variadic signature, `fn(u64, ...)`. Both ABIs pass the words the same
+//! way (Apple's arm64 variant, which puts variadic arguments on the stack,
+//! isn't supported), and on System V Rust then sets `al` to the number of
+//! XMM registers used, as the callee's prologue expects.
+
+use std::mem::MaybeUninit;
+use super::aggregate::{Abi, AbiError, Marshalled, RegClass, ResultShape, Scalar};
+
+/// `u64` parameters of the wide signature: registers, then stack
+const INTEGER_WORDS: usize = 24;
+const FLOAT_WORDS: usize = 8;
+/// Largest result returned in memory
+pub const MAX_MEMORY_RESULT: usize = 512;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Pair<A, B>(A, B);
+
+/// An HFA of `N` members, returned in `N` FP registers
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FloatArray<T, const N: usize>([T; N]);
+
+#[repr(C)]
+struct LargeResult([MaybeUninit<u8>; MAX_MEMORY_RESULT]);
+
+/// Call `address` with `call`'s arguments and return the result's bytes:
+/// the return registers' contents in chunk order, or the memory written
+/// for a MEMORY result. A `void` function returns an empty vector.
+///
+/// # Safety
+/// `address` must be a function whose prototype is the one `call` was
+/// marshalled for.
+pub unsafe fn invoke(address: *const u8, call: &Marshalled) -> Result<Vec<u8>, AbiError> {
+    let abi = Abi::host().ok_or(AbiError::UnsupportedHost)?;
+    // Rust passes the hidden result pointer itself
+    let hidden_pointer = abi == Abi::SystemV && matches!(call.result(), ResultShape::Memory { .. });
+    let registers = abi.integer_registers() - usize::from(hidden_pointer);
+
+    // Pad the registers so the stack words start where the stack does. This
+    // is the ABI's count, not `integer_limit`, which AAPCS64 lowers once an
+    // aggregate has gone to the stack
+    let mut integers = [0u64; INTEGER_WORDS];
+    let padded = call.integers.iter().copied().chain(std::iter::repeat(0)).take(registers);
+    let words: Vec<u64> = padded.chain(call.stack.iter().copied()).collect();
+    if words.len() > INTEGER_WORDS {
+        return Err(AbiError::TooManyArguments);
+    }
+    integers[..words.len()].copy_from_slice(&words);
+    let mut floats = [0f64; FLOAT_WORDS];
+    for (slot, &bits) in floats.iter_mut().zip(&call.floats) {
+        *slot = f64::from_bits(bits);
+    }
+    let frame = Frame { integers, floats, variadic: call.variadic };
+
+    let bytes = match call.result() {
+        ResultShape::Void => {
+            wide::<()>(address, &frame);
+            Vec::new()
+        }
+        ResultShape::Registers(classes) => match classes.as_slice() {
+            [RegClass::Integer] => wide::<u64>(address, &frame).to_le_bytes().to_vec(),
+            [RegClass::Sse] => wide::<f64>(address, &frame).to_bits().to_le_bytes().to_vec(),
+            [RegClass::Integer, RegClass::Integer] => pair(wide::<Pair<u64, u64>>(address, &frame)),
+            [RegClass::Sse, RegClass::Sse] => pair(wide::<Pair<f64, f64>>(address, &frame)),
+            [RegClass::Integer, RegClass::Sse] => pair(wide::<Pair<u64, f64>>(address, &frame)),
+            [RegClass::Sse, RegClass::Integer] => pair(wide::<Pair<f64, u64>>(address, &frame)),
+            _ => unreachable!("classification yields at most two chunks"),
+        },
+        ResultShape::Hfa { base, count } => match (base, count) {
+            (Scalar::F32, 1) => hfa(wide::<FloatArray<f32, 1>>(address, &frame).0),
+            (Scalar::F32, 2) => hfa(wide::<FloatArray<f32, 2>>(address, &frame).0),
+            (Scalar::F32, 3) => hfa(wide::<FloatArray<f32, 3>>(address, &frame).0),
+            (Scalar::F32, 4) => hfa(wide::<FloatArray<f32, 4>>(address, &frame).0),
+            (Scalar::F64, 1) => hfa(wide::<FloatArray<f64, 1>>(address, &frame).0),
+            (Scalar::F64, 2) => hfa(wide::<FloatArray<f64, 2>>(address, &frame).0),
+            (Scalar::F64, 3) => hfa(wide::<FloatArray<f64, 3>>(address, &frame).0),
+            (Scalar::F64, 4) => hfa(wide::<FloatArray<f64, 4>>(address, &frame).0),
+            _ => unreachable!("an HFA has one to four float members"),
+        },
+        ResultShape::Memory { size } => {
+            if *size > MAX_MEMORY_RESULT {
+                return Err(AbiError::ResultTooLarge(*size));
+            }
+            let result = wide::<LargeResult>(address, &frame);
+            // The callee wrote the first `size` bytes
+            result.0[..*size].iter().map(|byte| byte.assume_init()).collect()
+        }
+    };
+    Ok(bytes)
+}
+
+trait RawBytes: Copy {
+    fn raw_bytes(self) -> Vec<u8>;
+}
+
+macro_rules! raw_bytes {
+    ($($ty:ty),*) => {
+        $(impl RawBytes for $ty {
+            fn raw_bytes(self) -> Vec<u8> {
+                self.to_le_bytes().to_vec()
+            }
+        })*
+    };
+}
+
+raw_bytes!(u64, f32, f64);
+
+fn pair<A: RawBytes, B: RawBytes>(Pair(first, second): Pair<A, B>) -> Vec<u8> {
+    let mut bytes = first.raw_bytes();
+    bytes.extend(second.raw_bytes());
+    bytes
+}
+
+fn hfa<T: RawBytes, const N: usize>(members: [T; N]) -> Vec<u8> {
+    members.into_iter().flat_map(RawBytes::raw_bytes).collect()
+}
+
+struct Frame {
+    integers: [u64; INTEGER_WORDS],
+    floats: [f64; FLOAT_WORDS],
+    variadic: bool,
+}
+
+type Wide<R> = unsafe extern "C" fn(
+    u64, u64, u64, u64, u64, u64, u64, u64,
+    f64, f64, f64, f64, f64, f64, f64, f64,
+    u64, u64, u64, u64, u64, u64, u64, u64,
+    u64, u64, u64, u64, u64, u64, u64, u64,
+) -> R;
+
+type WideVariadic<R> = unsafe extern "C" fn(u64, ...) -> R;
+
+unsafe fn wide<R>(address: *const u8, frame: &Frame) -> R {
+    let (i, f) = (&frame.integers, &frame.floats);
+    if frame.variadic {
+        let function: WideVariadic<R> = std::mem::transmute(address);
+        return function(
+            i[0], i[1], i[2], i[3], i[4], i[5], i[6], i[7],
+            f[0], f[1], f[2], f[3], f[4], f[5], f[6], f[7],
+            i[8], i[9], i[10], i[11], i[12], i[13], i[14], i[15],
+            i[16], i[17], i[18], i[19], i[20], i[21], i[22], i[23],
+        );
+    }
+    let function: Wide<R> = std::mem::transmute(address);
+    function(
+        i[0], i[1], i[2], i[3], i[4], i[5], i[6], i[7],
+        f[0], f[1], f[2], f[3], f[4], f[5], f[6], f[7],
+        i[8], i[9], i[10], i[11], i[12], i[13], i[14], i[15],
+        i[16], i[17], i[18], i[19], i[20], i[21], i[22], i[23],
+    )
+}
+
+// Example usage:
+/*
+// struct pair { long a; double b; };
+// struct pair swap(struct pair p) { return (struct pair){ (long)p.b, (double)p.a }; }
+unsafe fn call_swap(address: *const u8) -> Result<(), AbiError> {
+    let ty = CType::Struct(vec![CType::Scalar(Scalar::I64), CType::Scalar(Scalar::F64)]);
+    let input: (i64, f64) = (3, 4.5);
+    let call = marshal(Abi::host().ok_or(AbiError::UnsupportedHost)?, &[Argument::aggregate(&ty, &input)], Some(&ty))?;
+    let bytes = invoke(address, &call)?;
+    println!("{:?}", bytes); // 4, then 3.0
+    Ok(())
+}
+*/
@@ -1,3 +1,7 @@
+pub mod aggregate;
+pub mod call;
+pub mod varargs;
+
 pub struct PlatformABI {
     // Calling conventions
     cdecl: CDeclConvention,
@@ -0,0 +1,345 @@
+// src/abi/varargs.rs
+//! Variadic calls and `va_list`
+//! The arguments after the last named parameter get the default argument
+//! promotions (`float` to `double`, `char` and `short` to `int`) and are
+//! otherwise passed like any other argument, except that a System V caller
+//! also sets `al` to an upper bound on the vector registers used. A
+//! variadic function finds them again through a `va_list`:
+//!
+//! - System V x86-64: a 24-byte struct `{ u32 gp_offset; u32 fp_offset;
+//!   void *overflow_arg_area; void *reg_save_area; }`. The prologue spills
+//!   the six GP argument registers and eight XMM registers into a 176-byte
+//!   register save area; the offsets say how much of each part the named
+//!   parameters used (GP up to 48, XMM from 48 up to 176 in 16-byte steps).
+//! - AAPCS64: a 32-byte struct `{ void *__stack; void *__gr_top; void
+//!   *__vr_top; int __gr_offs; int __vr_offs; }`. The X and V register areas
+//!   end at the `_top` pointers and the offsets count up from minus the
+//!   unused part towards zero.
+//!
+//! `VaList` implements `va_arg` over such a struct, so the interpreter can
+//! walk a `va_list` made by compiled C or libc and the other way round;
+//! `SaveArea` builds the register save and overflow areas for a call that
+//! never went through a prologue. `va_copy` is a plain copy on both ABIs.
+
+use std::ptr;
+use super::aggregate::{classify, marshal, Abi, AbiError, Argument, CType, Classification, Marshalled, RegClass, Scalar};
+use crate::jit::JITValue;
+
+/// Size of `va_list` itself
+pub const SYSV_VA_LIST_SIZE: usize = 24;
+pub const AAPCS64_VA_LIST_SIZE: usize = 32;
+
+/// System V register save area: six GP registers, then eight XMM registers
+const SYSV_GP_AREA: usize = 6 * 8;
+const SYSV_SAVE_AREA: usize = SYSV_GP_AREA + 8 * 16;
+/// AAPCS64 register areas: x0-x7, and q0-q7
+const AAPCS64_GR_AREA: usize = 8 * 8;
+const AAPCS64_VR_AREA: usize = 8 * 16;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SysVVaList {
+    gp_offset: u32,
+    fp_offset: u32,
+    overflow_arg_area: *mut u8,
+    reg_save_area: *mut u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Aapcs64VaList {
+    stack: *mut u8,
+    gr_top: *mut u8,
+    vr_top: *mut u8,
+    gr_offs: i32,
+    vr_offs: i32,
+}
+
+impl Abi {
+    pub fn va_list_size(self) -> usize {
+        match self {
+            Abi::SystemV => SYSV_VA_LIST_SIZE,
+            Abi::Aapcs64 => AAPCS64_VA_LIST_SIZE,
+        }
+    }
+}
+
+/// The default argument promotions, applied to every variadic argument
+pub fn promote(value: JITValue) -> JITValue {
+    match value {
+        JITValue::Int8(v) => JITValue::Int32(v.into()),
+        JITValue::Int16(v) => JITValue::Int32(v.into()),
+        JITValue::Float(v) => JITValue::Double(v.into()),
+        other => other,
+    }
+}
+
+/// `marshal` for a call to a variadic function with `fixed` named
+/// parameters: the rest of `args` are promoted, and the call is made
+/// through a variadic signature so `al` is set
+pub fn marshal_variadic(abi: Abi, args: &[Argument], fixed: usize, result: Option<&CType>) -> Result<Marshalled, AbiError> {
+    if fixed > args.len() {
+        return Err(AbiError::MissingFixedArguments { fixed, found: args.len() });
+    }
+    let promoted: Vec<Argument> = args
+        .iter()
+        .enumerate()
+        .map(|(index, arg)| match *arg {
+            Argument::Value(value) if index >= fixed => Argument::Value(promote(value)),
+            other => other,
+        })
+        .collect();
+    let mut call = marshal(abi, &promoted, result)?;
+    call.variadic = true;
+    Ok(call)
+}
+
+/// `va_arg` over a `va_list` laid out for `abi`
+pub struct VaList {
+    abi: Abi,
+    raw: *mut u8,
+}
+
+impl VaList {
+    /// # Safety
+    /// `raw` must point to a `va_list` initialized by `va_start` (or
+    /// `SaveArea::start`) for `abi`, whose areas are still live.
+    pub unsafe fn from_raw(abi: Abi, raw: *mut u8) -> Self {
+        VaList { abi, raw }
+    }
+
+    /// `va_copy(dest, src)`
+    ///
+    /// # Safety
+    /// `dest` must be valid for `abi.va_list_size()` bytes.
+    pub unsafe fn copy_to(&self, dest: *mut u8) {
+        ptr::copy_nonoverlapping(self.raw, dest, self.abi.va_list_size());
+    }
+
+    /// `va_arg(ap, T)` for a scalar `T`. Reading a type that promotes
+    /// reads the promoted type and converts back, as C requires of
+    /// `va_arg(ap, int)` for a `char` argument.
+    ///
+    /// # Safety
+    /// The next argument must have been passed as (the promotion of) `scalar`.
+    pub unsafe fn next_value(&mut self, scalar: Scalar) -> Result<JITValue, AbiError> {
+        let promoted = match scalar {
+            Scalar::I8 | Scalar::I16 => Scalar::I32,
+            Scalar::F32 => Scalar::F64,
+            other => other,
+        };
+        let bytes = self.next(&CType::Scalar(promoted))?;
+        let word = bytes.iter().rev().fold(0u64, |word, &byte| word << 8 | u64::from(byte));
+        Ok(match scalar {
+            Scalar::I8 => JITValue::Int8(word as i8),
+            Scalar::I16 => JITValue::Int16(word as i16),
+            Scalar::I32 => JITValue::Int32(word as i32),
+            Scalar::I64 => JITValue::Int64(word as i64),
+            Scalar::F32 => JITValue::Float(f64::from_bits(word) as f32),
+            Scalar::F64 => JITValue::Double(f64::from_bits(word)),
+            Scalar::Pointer => JITValue::Pointer(word as *mut u8),
+        })
+    }
+
+    /// `va_arg(ap, T)`: the next argument's `ty.size()` bytes
+    ///
+    /// # Safety
+    /// The next argument must have been passed as `ty`.
+    pub unsafe fn next(&mut self, ty: &CType) -> Result<Vec<u8>, AbiError> {
+        let class = classify(self.abi, ty)?;
+        match self.abi {
+            Abi::SystemV => Ok(self.next_sysv(ty, class)),
+            Abi::Aapcs64 => Ok(self.next_aapcs64(ty, class)),
+        }
+    }
+
+    unsafe fn next_sysv(&mut self, ty: &CType, class: Classification) -> Vec<u8> {
+        let list = &mut *(self.raw as *mut SysVVaList);
+        let size = ty.size();
+        if let Classification::Registers(classes) = class {
+            let integers = classes.iter().filter(|&&c| c == RegClass::Integer).count();
+            let floats = classes.len() - integers;
+            // An argument is never split between registers and the stack
+            let fits = list.gp_offset as usize + integers * 8 <= SYSV_GP_AREA
+                && list.fp_offset as usize + floats * 16 <= SYSV_SAVE_AREA;
+            if fits {
+                let mut bytes = Vec::with_capacity(classes.len() * 8);
+                for class in classes {
+                    let offset = match class {
+                        RegClass::Integer => &mut list.gp_offset,
+                        RegClass::Sse => &mut list.fp_offset,
+                    };
+                    bytes.extend(read(list.reg_save_area.add(*offset as usize), 8));
+                    *offset += if class == RegClass::Integer { 8 } else { 16 };
+                }
+                bytes.truncate(size);
+                return bytes;
+            }
+        }
+        // MEMORY, or out of registers: the next 8-byte aligned slots of the
+        // overflow area (alignments above 8 are rejected by `classify`)
+        let bytes = read(list.overflow_arg_area, size);
+        list.overflow_arg_area = list.overflow_arg_area.add(size.div_ceil(8) * 8);
+        bytes
+    }
+
+    unsafe fn next_aapcs64(&mut self, ty: &CType, class: Classification) -> Vec<u8> {
+        let list = &mut *(self.raw as *mut Aapcs64VaList);
+        let size = ty.size();
+        match class {
+            Classification::Reference => {
+                // A pointer to the caller's copy, taken like a `void *`
+                let pointer = self.next_aapcs64(&CType::Scalar(Scalar::Pointer), Classification::Registers(vec![RegClass::Integer]));
+                let address = u64::from_le_bytes(pointer.try_into().expect("a pointer is 8 bytes"));
+                return read(address as *const u8, size);
+            }
+            Classification::Hfa { base, count } => {
+                if let Some(registers) = take(&mut list.vr_offs, count as i32 * 16) {
+                    let area = list.vr_top.offset(registers as isize);
+                    // One member in the low bytes of each 16-byte V register
+                    return (0..count).flat_map(|index| read(area.add(index * 16), base.size())).collect();
+                }
+            }
+            Classification::Registers(classes) if classes == [RegClass::Sse] => {
+                if let Some(register) = take(&mut list.vr_offs, 16) {
+                    return read(list.vr_top.offset(register as isize), size);
+                }
+            }
+            Classification::Registers(classes) => {
+                if let Some(registers) = take(&mut list.gr_offs, classes.len() as i32 * 8) {
+                    return read(list.gr_top.offset(registers as isize), size);
+                }
+            }
+            Classification::Memory => unreachable!("AAPCS64 has no MEMORY class"),
+        }
+        let bytes = read(list.stack, size);
+        list.stack = list.stack.add(size.div_ceil(8) * 8);
+        bytes
+    }
+}
+
+/// Claim `bytes` of a register area whose offset counts up to zero. Once
+/// one argument has gone to the stack the area stays closed: the offset is
+/// left non-negative.
+fn take(offset: &mut i32, bytes: i32) -> Option<i32> {
+    let start = *offset;
+    if start >= 0 {
+        return None;
+    }
+    *offset = start + bytes;
+    (*offset <= 0).then_some(start)
+}
+
+unsafe fn read(address: *const u8, size: usize) -> Vec<u8> {
+    std::slice::from_raw_parts(address, size).to_vec()
+}
+
+/// What a variadic function's prologue would have saved, for a call made
+/// without one: the interpreter calling an interpreted variadic function.
+/// Its `va_list`s are the same bytes compiled code uses, so they can be
+/// handed on to `vprintf` and the like.
+pub struct SaveArea {
+    abi: Abi,
+    /// The register save area(s), in 8-byte words
+    registers: Box<[u64]>,
+    /// The variadic arguments passed on the stack
+    overflow: Box<[u64]>,
+    /// Bytes of the GP and FP register areas the named parameters used
+    gp_used: usize,
+    fp_used: usize,
+    /// Holds the copies of AAPCS64 by-reference arguments
+    call: Marshalled,
+}
+
+impl SaveArea {
+    /// Lay out `args`, of which the first `fixed` are named parameters, as a
+    /// variadic function returning `result` receives them
+    pub fn new(abi: Abi, args: &[Argument], fixed: usize, result: Option<&CType>) -> Result<Self, AbiError> {
+        let named = marshal(abi, &args[..fixed.min(args.len())], result)?;
+        let call = marshal_variadic(abi, args, fixed, result)?;
+        // System V's hidden result pointer occupies `rdi`
+        let hidden = abi.integer_registers() - call.integer_limit;
+        let gp_registers = abi.integer_registers();
+
+        let (gp_area, fp_area) = match abi {
+            Abi::SystemV => (SYSV_GP_AREA, SYSV_SAVE_AREA - SYSV_GP_AREA),
+            Abi::Aapcs64 => (AAPCS64_GR_AREA, AAPCS64_VR_AREA),
+        };
+        let mut registers = vec![0u64; (gp_area + fp_area) / 8].into_boxed_slice();
+        for (slot, &word) in registers[hidden..gp_registers].iter_mut().zip(&call.integers) {
+            *slot = word;
+        }
+        // An FP register is 16 bytes; the argument is in its low half
+        for (index, &bits) in call.floats.iter().enumerate() {
+            registers[gp_area / 8 + index * 2] = bits;
+        }
+
+        // AAPCS64 closes the X registers once an aggregate has gone to the
+        // stack, and the V registers once an HFA has
+        let gp_used = if named.integer_limit < gp_registers - hidden { gp_area } else { (hidden + named.integers.len()) * 8 };
+        Ok(SaveArea {
+            abi,
+            registers,
+            overflow: call.stack[named.stack.len()..].into(),
+            gp_used,
+            fp_used: named.floats.len() * 16,
+            call,
+        })
+    }
+
+    /// `va_start(ap, last)`: point the `va_list` at `raw` at the first
+    /// variadic argument. The areas must outlive every copy of it.
+    ///
+    /// # Safety
+    /// `raw` must be valid for `self.abi.va_list_size()` bytes.
+    pub unsafe fn start(&mut self, raw: *mut u8) {
+        let registers = self.registers.as_mut_ptr() as *mut u8;
+        let overflow = self.overflow.as_mut_ptr() as *mut u8;
+        match self.abi {
+            Abi::SystemV => ptr::write_unaligned(
+                raw as *mut SysVVaList,
+                SysVVaList {
+                    gp_offset: self.gp_used as u32,
+                    fp_offset: (SYSV_GP_AREA + self.fp_used) as u32,
+                    overflow_arg_area: overflow,
+                    reg_save_area: registers,
+                },
+            ),
+            Abi::Aapcs64 => ptr::write_unaligned(
+                raw as *mut Aapcs64VaList,
+                Aapcs64VaList {
+                    stack: overflow,
+                    gr_top: registers.add(AAPCS64_GR_AREA),
+                    vr_top: registers.add(AAPCS64_GR_AREA + AAPCS64_VR_AREA),
+                    gr_offs: self.gp_used as i32 - AAPCS64_GR_AREA as i32,
+                    vr_offs: self.fp_used as i32 - AAPCS64_VR_AREA as i32,
+                },
+            ),
+        }
+    }
+}
+
+// Example usage:
+/*
+// int sum(int n, ...) called as sum(3, 1, 2.5f, (char)3): the float arrives
+// as a double and the char as an int
+fn main() -> Result<(), AbiError> {
+    let abi = Abi::host().ok_or(AbiError::UnsupportedHost)?;
+    let args = [
+        Argument::Value(JITValue::Int32(3)),
+        Argument::Value(JITValue::Int32(1)),
+        Argument::Value(JITValue::Float(2.5)),
+        Argument::Value(JITValue::Int8(3)),
+    ];
+    let mut area = SaveArea::new(abi, &args, 1, Some(&CType::Scalar(Scalar::I32)))?;
+    let mut raw = [0u8; AAPCS64_VA_LIST_SIZE];
+    unsafe {
+        area.start(raw.as_mut_ptr());
+        let mut ap = VaList::from_raw(abi, raw.as_mut_ptr());
+        assert!(matches!(ap.next_value(Scalar::I32)?, JITValue::Int32(1)));
+        assert!(matches!(ap.next_value(Scalar::F64)?, JITValue::Double(v) if v == 2.5));
+        assert!(matches!(ap.next_value(Scalar::I8)?, JITValue::Int8(3)));
+    }
+    Ok(())
+}
+*/
@@ -0,0 +1,12 @@
+// src/analysis/mod.rs
+//! Whole-program and cross-version analyses that don't run the code
+
+pub mod code_scanner;
+pub mod semdiff;
+// Over LLVM IR and the machine code LLVM generates
+#[cfg(feature = "llvm")]
+pub mod signal_safety;
+#[cfg(feature = "llvm")]
+pub mod stack_depth;
+#[cfg(feature = "llvm")]
+pub mod wcet;
@@ -0,0 +1,1275 @@
+// src/analysis/semdiff.rs
+//! Semantic diff of two versions of a C file
+//! Both files are reduced to their top-level declarations — functions,
+//! variables, structs, unions, enums and typedefs, each with a parsed type —
+//! and the two sets are compared by name. Formatting, comments, parameter
+//! names and declaration order never show up as changes; a reordered struct
+//! or a widened field does, with the offsets and sizes it moves.
+//!
+//! Files are read as written, without preprocessing: directives are skipped
+//! and macros are not expanded, so declarations hidden behind `#if` are all
+//! seen. Struct layouts follow the System V rules for the target's data
+//! model; a struct containing a type that isn't known from the file itself
+//! or the standard headers is compared field by field instead.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Literal(String),
+    Punct(&'static str),
+}
+
+impl Token {
+    fn is(&self, punct: &str) -> bool {
+        matches!(self, Token::Punct(p) if *p == punct)
+    }
+
+    fn ident(&self) -> Option<&str> {
+        match self {
+            Token::Ident(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(s) | Token::Number(s) | Token::Literal(s) => f.write_str(s),
+            Token::Punct(p) => f.write_str(p),
+        }
+    }
+}
+
+/// Longest first, so `<<=` is not read as `<<` `=`
+const PUNCTUATORS: &[&str] = &[
+    "...", "<<=", ">>=", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=", "+=",
+    "-=", "&=", "^=", "|=", "##", "::", "[", "]", "(", ")", "{", "}", ".", "&", "*", "+", "-", "~", "!", "/", "%",
+    "<", ">", "^", "|", "?", ":", ";", "=", ",", "#",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, SemdiffError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_start = true;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b'\n' => {
+                line += 1;
+                line_start = true;
+                i += 1;
+            }
+            b' ' | b'\t' | b'\r' | b'\x0c' | b'\x0b' => i += 1,
+            b'\\' if bytes.get(i + 1) == Some(&b'\n') => {
+                line += 1;
+                i += 2;
+            }
+            // Directives, including their continuation lines
+            b'#' if line_start => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'\n') {
+                        line += 1;
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let start = line;
+                i += 2;
+                loop {
+                    match bytes.get(i) {
+                        None => return Err(SemdiffError::Syntax { line: start, message: "unterminated comment".into() }),
+                        Some(b'*') if bytes.get(i + 1) == Some(&b'/') => break,
+                        Some(b'\n') => line += 1,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                i += 2;
+            }
+            b'"' | b'\'' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != c {
+                    if bytes[i] == b'\n' {
+                        return Err(SemdiffError::Syntax { line, message: "unterminated literal".into() });
+                    }
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+                tokens.push(Token::Literal(source[start..i.min(bytes.len())].to_string()));
+                line_start = false;
+            }
+            _ if c.is_ascii_alphabetic() || c == b'_' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(source[start..i].to_string()));
+                line_start = false;
+            }
+            _ if c.is_ascii_digit() || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) => {
+                let start = i;
+                while i < bytes.len() {
+                    let b = bytes[i];
+                    let exponent_sign = (b == b'+' || b == b'-') && matches!(bytes[i - 1], b'e' | b'E' | b'p' | b'P');
+                    if !(b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || b == b'\'' || exponent_sign) {
+                        break;
+                    }
+                    i += 1;
+                }
+                tokens.push(Token::Number(source[start..i].to_string()));
+                line_start = false;
+            }
+            _ => {
+                let rest = &source[i..];
+                let Some(punct) = PUNCTUATORS.iter().find(|p| rest.starts_with(**p)) else {
+                    return Err(SemdiffError::Syntax {
+                        line,
+                        message: format!("unexpected character {:?}", rest.chars().next().unwrap()),
+                    });
+                };
+                tokens.push(Token::Punct(punct));
+                i += punct.len();
+                line_start = false;
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A declared type, after typedef names are kept but spelling is normalized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CDecl {
+    /// A base type: `unsigned long`, `const char`, `struct point`, `size_t`
+    Named(String),
+    Pointer(Box<CDecl>, String),
+    Array(Box<CDecl>, Option<String>),
+    Function(Box<CDecl>, Vec<CDecl>, bool),
+}
+
+impl CDecl {
+    /// Parameters of array and function type are adjusted to pointers, and
+    /// top-level qualifiers don't affect the caller
+    fn into_parameter(self) -> CDecl {
+        match self {
+            CDecl::Array(element, _) => CDecl::Pointer(element, String::new()),
+            function @ CDecl::Function(..) => CDecl::Pointer(Box::new(function), String::new()),
+            CDecl::Pointer(pointee, _) => CDecl::Pointer(pointee, String::new()),
+            CDecl::Named(name) => CDecl::Named(strip_qualifiers(&name)),
+        }
+    }
+
+    fn render(&self, inner: &str) -> String {
+        match self {
+            CDecl::Named(name) if inner.is_empty() => name.clone(),
+            CDecl::Named(name) => format!("{} {}", name, inner),
+            CDecl::Pointer(pointee, quals) => {
+                let declarator = if quals.is_empty() { format!("*{}", inner) } else { format!("*{} {}", quals, inner).trim_end().to_string() };
+                match **pointee {
+                    CDecl::Array(..) | CDecl::Function(..) => pointee.render(&format!("({})", declarator)),
+                    _ => pointee.render(&declarator),
+                }
+            }
+            CDecl::Array(element, size) => element.render(&format!("{}[{}]", inner, size.as_deref().unwrap_or(""))),
+            CDecl::Function(ret, params, variadic) => {
+                let mut list: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                if *variadic {
+                    list.push("...".into());
+                }
+                if list.is_empty() {
+                    list.push("void".into());
+                }
+                ret.render(&format!("{}({})", inner, list.join(", ")))
+            }
+        }
+    }
+}
+
+impl fmt::Display for CDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(""))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Linkage {
+    External,
+    Internal,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionDecl {
+    pub name: String,
+    pub ty: CDecl,
+    pub linkage: Linkage,
+    /// Tokens of the body, None for a prototype
+    body: Option<Vec<Token>>,
+}
+
+impl FunctionDecl {
+    pub fn signature(&self) -> String {
+        self.ty.render(&self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VariableDecl {
+    pub name: String,
+    pub ty: CDecl,
+    pub linkage: Linkage,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// Empty for an unnamed bit-field or anonymous member
+    pub name: String,
+    pub ty: CDecl,
+    pub bit_width: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Struct,
+    Union,
+}
+
+impl fmt::Display for RecordKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RecordKind::Struct => "struct",
+            RecordKind::Union => "union",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordDecl {
+    pub kind: RecordKind,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDecl {
+    /// Each enumerator with its value, when it is a constant we can fold
+    pub constants: Vec<(String, Option<i128>)>,
+}
+
+/// Everything a file declares at file scope, keyed by name
+#[derive(Debug, Default)]
+pub struct Declarations {
+    pub functions: BTreeMap<String, FunctionDecl>,
+    pub variables: BTreeMap<String, VariableDecl>,
+    /// `struct tag`, `union tag`, or the typedef name of an untagged one
+    pub records: BTreeMap<String, RecordDecl>,
+    pub enums: BTreeMap<String, EnumDecl>,
+    pub typedefs: BTreeMap<String, CDecl>,
+    /// Declarations that could not be parsed, such as unexpanded macro calls
+    pub skipped: Vec<String>,
+}
+
+const STORAGE: &[&str] = &[
+    "static", "extern", "typedef", "inline", "__inline", "__inline__", "_Noreturn", "register", "auto",
+    "_Thread_local", "thread_local", "constexpr", "__extension__",
+];
+const QUALIFIERS: &[&str] = &["const", "volatile", "restrict", "_Atomic", "__restrict", "__restrict__", "__const", "__volatile__"];
+const BASIC: &[&str] = &[
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "__signed__", "_Bool", "bool",
+    "_Complex", "__int128", "_Float16", "_Float32", "_Float64", "_Float128", "__builtin_va_list",
+];
+
+fn is_keyword(name: &str) -> bool {
+    STORAGE.contains(&name)
+        || QUALIFIERS.contains(&name)
+        || BASIC.contains(&name)
+        || matches!(name, "struct" | "union" | "enum" | "typeof" | "__typeof__" | "_BitInt" | "_Alignas" | "alignas")
+}
+
+fn strip_qualifiers(name: &str) -> String {
+    name.split(' ').filter(|word| !QUALIFIERS.contains(word)).collect::<Vec<_>>().join(" ")
+}
+
+/// Index just past the bracket that closes the one at `open`
+fn skip_balanced(tokens: &[Token], open: usize) -> usize {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Punct("(" | "[" | "{") => depth += 1,
+            Token::Punct(")" | "]" | "}") => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+/// Split at top-level occurrences of `separator`
+fn split_top(tokens: &[Token], separator: &str) -> Vec<Vec<Token>> {
+    let mut parts = vec![Vec::new()];
+    let mut i = 0;
+    while i < tokens.len() {
+        if matches!(tokens[i], Token::Punct("(" | "[" | "{")) {
+            let end = skip_balanced(tokens, i);
+            parts.last_mut().unwrap().extend_from_slice(&tokens[i..end]);
+            i = end;
+            continue;
+        }
+        if tokens[i].is(separator) {
+            parts.push(Vec::new());
+        } else {
+            parts.last_mut().unwrap().push(tokens[i].clone());
+        }
+        i += 1;
+    }
+    parts
+}
+
+/// Drop `__attribute__((...))`, `[[...]]`, `__declspec(...)` and asm labels
+fn strip_attributes(tokens: &[Token]) -> Vec<Token> {
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let attribute = matches!(
+            tokens[i].ident(),
+            Some("__attribute__" | "__attribute" | "__declspec" | "__asm__" | "__asm" | "asm" | "_Alignas" | "alignas")
+        );
+        if attribute && tokens.get(i + 1).is_some_and(|t| t.is("(")) {
+            i = skip_balanced(tokens, i + 1);
+        } else if tokens[i].is("[") && tokens.get(i + 1).is_some_and(|t| t.is("[")) {
+            i = skip_balanced(tokens, i);
+        } else {
+            out.push(tokens[i].clone());
+            i += 1;
+        }
+    }
+    out
+}
+
+/// The declaration specifiers at the start of a declaration
+struct Specifiers {
+    storage: Vec<String>,
+    base: CDecl,
+    /// A struct, union or enum defined in the specifiers, with its tag
+    definition: Option<Definition>,
+}
+
+enum Definition {
+    Record(Option<String>, RecordDecl),
+    Enum(Option<String>, EnumDecl),
+}
+
+impl Definition {
+    /// `struct tag`; an untagged struct is named by its body, an untagged
+    /// enum by its first enumerator
+    fn key(&self) -> String {
+        match self {
+            Definition::Record(Some(tag), record) => format!("{} {}", record.kind, tag),
+            Definition::Record(None, record) => {
+                let fields: Vec<String> = record.fields.iter().map(|f| format!("{}; ", render_field(f))).collect();
+                format!("{} {{ {}}}", record.kind, fields.concat())
+            }
+            Definition::Enum(Some(tag), _) => format!("enum {}", tag),
+            Definition::Enum(None, enumeration) => match enumeration.constants.first() {
+                Some((first, _)) => format!("enum {{ {}, ... }}", first),
+                None => "enum { }".to_string(),
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    /// Typedef names seen so far; a declaration naming one has no other type specifier
+    typedefs: &'a BTreeSet<String>,
+    /// Structs, unions and enums defined inside another definition
+    nested: RefCell<Vec<Definition>>,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> SemdiffError {
+        SemdiffError::Declaration(message.into())
+    }
+
+    fn specifiers(&self, tokens: &[Token]) -> Result<(Specifiers, usize), SemdiffError> {
+        let mut storage = Vec::new();
+        let mut qualifiers = BTreeSet::new();
+        let mut basic: Vec<&str> = Vec::new();
+        let mut named = None;
+        let mut definition = None;
+        let mut i = 0;
+
+        while let Some(token) = tokens.get(i) {
+            let Some(word) = token.ident() else { break };
+            if STORAGE.contains(&word) {
+                storage.push(word.to_string());
+                i += 1;
+            } else if QUALIFIERS.contains(&word) {
+                qualifiers.insert(match word {
+                    "__restrict" | "__restrict__" => "restrict",
+                    "__const" => "const",
+                    "__volatile__" => "volatile",
+                    other => other,
+                });
+                i += 1;
+            } else if BASIC.contains(&word) {
+                basic.push(match word {
+                    "bool" => "_Bool",
+                    "__signed__" => "signed",
+                    other => other,
+                });
+                i += 1;
+            } else if matches!(word, "struct" | "union" | "enum") {
+                let keyword = word;
+                i += 1;
+                let tag = tokens.get(i).and_then(Token::ident).map(str::to_string);
+                if tag.is_some() {
+                    i += 1;
+                }
+                if tokens.get(i).is_some_and(|t| t.is("{")) {
+                    let end = skip_balanced(tokens, i);
+                    let body = &tokens[i + 1..end - 1];
+                    let defined = match keyword {
+                        "enum" => Definition::Enum(tag, self.enumerators(body)?),
+                        "struct" => Definition::Record(tag, self.record(RecordKind::Struct, body)?),
+                        _ => Definition::Record(tag, self.record(RecordKind::Union, body)?),
+                    };
+                    named = Some(defined.key());
+                    definition = Some(defined);
+                    i = end;
+                } else {
+                    let Some(tag) = tag else {
+                        return Err(self.error(format!("`{}` without a tag or body", keyword)));
+                    };
+                    named = Some(format!("{} {}", keyword, tag));
+                }
+            } else if matches!(word, "typeof" | "__typeof__" | "_BitInt") && tokens.get(i + 1).is_some_and(|t| t.is("(")) {
+                let end = skip_balanced(tokens, i + 1);
+                named = Some(tokens[i..end].iter().map(|t| t.to_string()).collect::<Vec<_>>().join(""));
+                i = end;
+            } else if named.is_none() && basic.is_empty() && !is_keyword(word) && self.is_type_name(tokens, i) {
+                named = Some(word.to_string());
+                i += 1;
+            } else {
+                break;
+            }
+        }
+
+        let base = match named {
+            Some(name) => name,
+            None if basic.is_empty() && storage.is_empty() && qualifiers.is_empty() => {
+                return Err(self.error("expected a declaration"));
+            }
+            // Implicit int, for old-style declarations
+            None => normalize_basic(&basic),
+        };
+        let base = qualifiers.into_iter().chain(std::iter::once(base.as_str())).collect::<Vec<_>>().join(" ");
+        Ok((Specifiers { storage, base: CDecl::Named(base), definition }, i))
+    }
+
+    /// Whether the identifier at `i` names a type rather than the thing being declared
+    fn is_type_name(&self, tokens: &[Token], i: usize) -> bool {
+        let name = tokens[i].ident().unwrap_or_default();
+        if self.typedefs.contains(name) || name.ends_with("_t") || matches!(name, "FILE" | "DIR" | "va_list" | "jmp_buf") {
+            return true;
+        }
+        // Unknown names (from an unexpanded header) are types when a declarator follows
+        match tokens.get(i + 1) {
+            Some(Token::Ident(_)) | Some(Token::Punct("*")) => true,
+            Some(Token::Punct("(")) => tokens.get(i + 2).is_some_and(|t| t.is("*") || t.is("^")),
+            _ => false,
+        }
+    }
+
+    /// Parse a declarator applied to `base`, returning the declared name
+    fn declarator(&self, tokens: &[Token], base: CDecl) -> Result<(Option<String>, CDecl), SemdiffError> {
+        let mut ty = base;
+        let mut i = 0;
+        while tokens.get(i).is_some_and(|t| t.is("*") || t.is("^")) {
+            i += 1;
+            let mut quals = BTreeSet::new();
+            while let Some(word) = tokens.get(i).and_then(Token::ident).filter(|w| QUALIFIERS.contains(w)) {
+                quals.insert(word.trim_start_matches("__").trim_end_matches("__"));
+                i += 1;
+            }
+            ty = CDecl::Pointer(Box::new(ty), quals.into_iter().collect::<Vec<_>>().join(" "));
+        }
+
+        let mut name = None;
+        let mut nested = None;
+        match tokens.get(i) {
+            Some(Token::Ident(word)) => {
+                name = Some(word.clone());
+                i += 1;
+            }
+            Some(Token::Punct("(")) if self.starts_nested_declarator(&tokens[i + 1..]) => {
+                let end = skip_balanced(tokens, i);
+                nested = Some(&tokens[i + 1..end - 1]);
+                i = end;
+            }
+            _ => {}
+        }
+
+        let mut suffixes = Vec::new();
+        while let Some(token) = tokens.get(i) {
+            let end = skip_balanced(tokens, i);
+            let inner = &tokens[i + 1..end.saturating_sub(1).max(i + 1)];
+            match token {
+                Token::Punct("[") => {
+                    let size = inner.iter().filter(|t| t.ident() != Some("static") && !is_qualifier(t)).map(|t| t.to_string()).collect::<Vec<_>>().join(" ");
+                    suffixes.push(CDecl::Array(Box::new(CDecl::Named(String::new())), (!size.is_empty()).then_some(size)));
+                }
+                Token::Punct("(") => {
+                    let (params, variadic) = self.parameters(inner)?;
+                    suffixes.push(CDecl::Function(Box::new(CDecl::Named(String::new())), params, variadic));
+                }
+                other => return Err(self.error(format!("unexpected `{}` in declarator", other))),
+            }
+            i = end;
+        }
+        // `a[2][3]` is an array of two arrays of three
+        for suffix in suffixes.into_iter().rev() {
+            ty = match suffix {
+                CDecl::Array(_, size) => CDecl::Array(Box::new(ty), size),
+                CDecl::Function(_, params, variadic) => CDecl::Function(Box::new(ty), params, variadic),
+                other => other,
+            };
+        }
+
+        match nested {
+            Some(inner) => self.declarator(inner, ty),
+            None => Ok((name, ty)),
+        }
+    }
+
+    /// After `(` in a declarator: a parenthesized declarator, not a parameter list
+    fn starts_nested_declarator(&self, rest: &[Token]) -> bool {
+        match rest.first() {
+            Some(Token::Punct("*" | "^" | "(")) => true,
+            Some(Token::Ident(word)) => !is_keyword(word) && !self.typedefs.contains(word) && rest.get(1).is_some_and(|t| t.is(")")),
+            _ => false,
+        }
+    }
+
+    fn parameters(&self, tokens: &[Token]) -> Result<(Vec<CDecl>, bool), SemdiffError> {
+        let mut params = Vec::new();
+        let mut variadic = false;
+        if tokens.is_empty() || matches!(tokens, [Token::Ident(v)] if v == "void") {
+            return Ok((params, variadic));
+        }
+        for param in split_top(tokens, ",") {
+            if matches!(param.as_slice(), [Token::Punct("...")]) {
+                variadic = true;
+                continue;
+            }
+            // A lone unknown name is a type from an unexpanded header
+            if let [Token::Ident(name)] = param.as_slice() {
+                if !is_keyword(name) {
+                    params.push(CDecl::Named(name.clone()));
+                    continue;
+                }
+            }
+            let (specifiers, used) = self.specifiers(&param)?;
+            let (_, ty) = self.declarator(&param[used..], specifiers.base)?;
+            params.push(ty.into_parameter());
+        }
+        Ok((params, variadic))
+    }
+
+    fn record(&self, kind: RecordKind, body: &[Token]) -> Result<RecordDecl, SemdiffError> {
+        let mut fields = Vec::new();
+        let mut unnamed = 0;
+        for member in split_top(body, ";").into_iter().filter(|m| !m.is_empty()) {
+            let member = strip_attributes(&member);
+            let (specifiers, used) = self.specifiers(&member)?;
+            if let Some(definition) = specifiers.definition {
+                self.nested.borrow_mut().push(definition);
+            }
+            let declarators = split_top(&member[used..], ",");
+            for declarator in declarators {
+                let (declarator, bit_width) = match declarator.iter().position(|t| t.is(":")) {
+                    Some(colon) => (
+                        &declarator[..colon],
+                        Some(declarator[colon + 1..].iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ")),
+                    ),
+                    None => (&declarator[..], None),
+                };
+                let (name, ty) = self.declarator(declarator, specifiers.base.clone())?;
+                // Unnamed bit-fields and anonymous members are matched by position
+                let name = name.unwrap_or_else(|| {
+                    unnamed += 1;
+                    format!("<unnamed {}>", unnamed)
+                });
+                fields.push(Field { name, ty, bit_width });
+            }
+        }
+        Ok(RecordDecl { kind, fields })
+    }
+
+    fn enumerators(&self, body: &[Token]) -> Result<EnumDecl, SemdiffError> {
+        let mut constants: Vec<(String, Option<i128>)> = Vec::new();
+        let mut next = Some(0i128);
+        for item in split_top(body, ",").into_iter().filter(|i| !i.is_empty()) {
+            let Some(name) = item[0].ident() else {
+                return Err(self.error("expected an enumerator"));
+            };
+            let value = match item.iter().position(|t| t.is("=")) {
+                Some(eq) => fold_constant(&item[eq + 1..], &constants),
+                None => next,
+            };
+            next = value.and_then(|v| v.checked_add(1));
+            constants.push((name.to_string(), value));
+        }
+        Ok(EnumDecl { constants })
+    }
+}
+
+fn is_qualifier(token: &Token) -> bool {
+    token.ident().is_some_and(|w| QUALIFIERS.contains(&w))
+}
+
+/// `unsigned` -> `unsigned int`, `long int signed` -> `long`, and so on
+fn normalize_basic(words: &[&str]) -> String {
+    let count = |word: &str| words.iter().filter(|w| **w == word).count();
+    let unsigned = count("unsigned") > 0;
+    let complex = if count("_Complex") > 0 { " _Complex" } else { "" };
+    let longs = count("long");
+
+    if count("void") > 0 {
+        "void".to_string()
+    } else if count("_Bool") > 0 {
+        "_Bool".to_string()
+    } else if count("char") > 0 {
+        match (unsigned, count("signed") > 0) {
+            (true, _) => "unsigned char".into(),
+            (false, true) => "signed char".into(),
+            _ => "char".into(),
+        }
+    } else if count("float") > 0 {
+        format!("float{}", complex)
+    } else if count("double") > 0 {
+        format!("{}double{}", if longs > 0 { "long " } else { "" }, complex)
+    } else if let Some(word) = words.iter().find(|w| w.starts_with("_Float") || **w == "__builtin_va_list") {
+        word.to_string()
+    } else {
+        let size = if count("__int128") > 0 {
+            "__int128"
+        } else if count("short") > 0 {
+            "short"
+        } else if longs >= 2 {
+            "long long"
+        } else if longs == 1 {
+            "long"
+        } else {
+            "int"
+        };
+        if unsigned { format!("unsigned {}", size) } else { size.to_string() }
+    }
+}
+
+/// The value of an enumerator initializer, for literals, earlier
+/// enumerators, unary minus and `|`, `+`, `<<` between those
+fn fold_constant(tokens: &[Token], earlier: &[(String, Option<i128>)]) -> Option<i128> {
+    let operand = |tokens: &[Token]| -> Option<i128> {
+        let (negative, rest) = match tokens {
+            [Token::Punct("-"), rest @ ..] => (true, rest),
+            rest => (false, rest),
+        };
+        let rest: Vec<&Token> = rest.iter().filter(|t| !t.is("(") && !t.is(")")).collect();
+        let value = match rest.as_slice() {
+            [Token::Number(n)] => parse_integer(n)?,
+            [Token::Literal(c)] if c.starts_with('\'') && c.len() == 3 => c.as_bytes()[1] as i128,
+            [Token::Ident(name)] => earlier.iter().find(|(n, _)| n == name)?.1?,
+            _ => return None,
+        };
+        Some(if negative { -value } else { value })
+    };
+
+    type Fold = fn(i128, i128) -> Option<i128>;
+    let operators: [(&str, Fold); 3] = [
+        ("|", |a, b| Some(a | b)),
+        ("+", i128::checked_add),
+        ("<<", |a, b| a.checked_shl(u32::try_from(b).ok()?)),
+    ];
+    for (op, apply) in operators {
+        let parts = split_top(tokens, op);
+        if parts.len() > 1 {
+            let mut values = parts.iter().map(|p| fold_constant(p, earlier));
+            let first = values.next()??;
+            return values.try_fold(first, |acc, v| apply(acc, v?));
+        }
+    }
+    operand(tokens)
+}
+
+fn parse_integer(literal: &str) -> Option<i128> {
+    let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']).replace('\'', "");
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i128::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i128::from_str_radix(bin, 2).ok()
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i128::from_str_radix(&digits[1..], 8).ok()
+    } else {
+        digits.parse().ok()
+    }
+}
+
+/// Parse the file-scope declarations of `source`
+pub fn parse(source: &str) -> Result<Declarations, SemdiffError> {
+    let tokens = tokenize(source)?;
+    let mut declarations = Declarations::default();
+    let mut typedef_names = BTreeSet::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        // One external declaration: up to `;`, or through a function body
+        let start = i;
+        let mut end_of_declarator = None;
+        let mut body = None;
+        while i < tokens.len() && !tokens[i].is(";") {
+            if tokens[i].is("{") {
+                let end = skip_balanced(&tokens, i);
+                let before = |back: usize| i.checked_sub(back).filter(|&j| j >= start).and_then(|j| tokens[j].ident());
+                let is_function = tokens[start..i].iter().any(|t| t.is("("))
+                    && !tokens[start..i].iter().any(|t| t.is("="))
+                    && !matches!(before(1), Some("struct" | "union" | "enum"))
+                    && !matches!(before(2), Some("struct" | "union" | "enum"));
+                if is_function {
+                    end_of_declarator = Some(i);
+                    body = Some(tokens[i + 1..end - 1].to_vec());
+                    i = end;
+                    break;
+                }
+                i = end;
+            } else if matches!(tokens[i], Token::Punct("(" | "[")) {
+                i = skip_balanced(&tokens, i);
+            } else {
+                i += 1;
+            }
+        }
+        let declaration = strip_attributes(&tokens[start..end_of_declarator.unwrap_or(i).min(tokens.len())]);
+        if body.is_none() {
+            i += 1; // the `;`
+        }
+        if declaration.is_empty() || matches!(declaration[0].ident(), Some("_Static_assert" | "static_assert")) {
+            continue;
+        }
+
+        let parser = Parser { typedefs: &typedef_names, nested: RefCell::new(Vec::new()) };
+        let parsed = declare(&parser, &declaration, body, &mut declarations);
+        let nested = parser.nested.into_inner();
+        match parsed {
+            Ok(new_typedefs) => typedef_names.extend(new_typedefs),
+            // Most often a macro invocation at file scope
+            Err(SemdiffError::Declaration(message)) => {
+                let text: String = declaration.iter().take(12).map(|t| format!("{} ", t)).collect();
+                log::warn!("semdiff: skipped `{}...`: {}", text.trim_end(), message);
+                declarations.skipped.push(text.trim_end().to_string());
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+        for definition in nested {
+            add_definition(&mut declarations, definition.key(), definition);
+        }
+    }
+    Ok(declarations)
+}
+
+/// Record one declaration; returns the typedef names it introduces
+fn declare(
+    parser: &Parser,
+    declaration: &[Token],
+    mut body: Option<Vec<Token>>,
+    declarations: &mut Declarations,
+) -> Result<Vec<String>, SemdiffError> {
+    let (specifiers, used) = parser.specifiers(declaration)?;
+    let is_typedef = specifiers.storage.iter().any(|s| s == "typedef");
+    let linkage = if specifiers.storage.iter().any(|s| s == "static") { Linkage::Internal } else { Linkage::External };
+
+    let mut declared = Vec::new();
+    for declarator in split_top(&declaration[used..], ",").into_iter().filter(|d| !d.is_empty()) {
+        // Initializers don't change the declaration
+        let declarator = match declarator.iter().position(|t| t.is("=")) {
+            Some(eq) => &declarator[..eq],
+            None => &declarator[..],
+        };
+        let (name, ty) = parser.declarator(declarator, specifiers.base.clone())?;
+        if let Some(name) = name {
+            declared.push((name, ty));
+        }
+    }
+
+    // `typedef struct { ... } name` names the struct itself
+    let untagged = matches!(specifiers.definition, Some(Definition::Record(None, _)) | Some(Definition::Enum(None, _)));
+    let alias = declared
+        .iter()
+        .find(|(_, ty)| untagged && is_typedef && *ty == specifiers.base)
+        .map(|(name, _)| name.clone());
+    if let Some(definition) = specifiers.definition {
+        let key = alias.clone().unwrap_or_else(|| definition.key());
+        add_definition(declarations, key, definition);
+    }
+
+    let mut typedefs = Vec::new();
+    for (name, ty) in declared {
+        if is_typedef {
+            if Some(&name) != alias.as_ref() {
+                declarations.typedefs.insert(name.clone(), ty);
+            }
+            typedefs.push(name);
+        } else if let CDecl::Function(..) = ty {
+            // A definition wins over a prototype; the first linkage seen sticks
+            let body = body.take();
+            match declarations.functions.get_mut(&name) {
+                Some(existing) => {
+                    existing.ty = ty;
+                    if body.is_some() {
+                        existing.body = body;
+                    }
+                }
+                None => {
+                    declarations.functions.insert(name.clone(), FunctionDecl { name, ty, linkage, body });
+                }
+            }
+        } else {
+            declarations.variables.insert(name.clone(), VariableDecl { name, ty, linkage });
+        }
+    }
+    Ok(typedefs)
+}
+
+fn add_definition(declarations: &mut Declarations, key: String, definition: Definition) {
+    match definition {
+        Definition::Record(_, record) => {
+            declarations.records.insert(key, record);
+        }
+        Definition::Enum(_, enumeration) => {
+            declarations.enums.insert(key, enumeration);
+        }
+    }
+}
+
+/// Sizes the layout computation assumes
+#[derive(Debug, Clone, Copy)]
+pub struct DataModel {
+    pub pointer: u64,
+    pub long: u64,
+    /// Size and alignment of `long double`
+    pub long_double: (u64, u64),
+    /// Alignment of `long long` and `double`
+    pub align_8: u64,
+}
+
+impl DataModel {
+    pub fn for_architecture(arch: &str) -> Self {
+        match arch {
+            "arm" => DataModel { pointer: 4, long: 4, long_double: (8, 8), align_8: 8 },
+            _ => DataModel { pointer: 8, long: 8, long_double: (16, 16), align_8: 8 },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    size: u64,
+    align: u64,
+}
+
+struct LayoutContext<'a> {
+    model: DataModel,
+    declarations: &'a Declarations,
+}
+
+impl LayoutContext<'_> {
+    fn of(&self, ty: &CDecl, depth: usize) -> Option<Layout> {
+        if depth > 32 {
+            return None;
+        }
+        let scalar = |size: u64| Some(Layout { size, align: size });
+        match ty {
+            CDecl::Pointer(..) => scalar(self.model.pointer),
+            CDecl::Function(..) => None,
+            CDecl::Array(element, size) => {
+                let element = self.of(element, depth + 1)?;
+                let count = size.as_deref().and_then(parse_array_size)?;
+                Some(Layout { size: element.size * count, align: element.align })
+            }
+            CDecl::Named(name) => {
+                let name = strip_qualifiers(name);
+                let wide = |size: u64| Some(Layout { size, align: size.min(self.model.align_8) });
+                match name.as_str() {
+                    "char" | "signed char" | "unsigned char" | "_Bool" | "int8_t" | "uint8_t" => scalar(1),
+                    "short" | "unsigned short" | "int16_t" | "uint16_t" | "_Float16" => scalar(2),
+                    "int" | "unsigned int" | "float" | "int32_t" | "uint32_t" | "_Float32" | "wchar_t" | "char32_t" => scalar(4),
+                    "char16_t" => scalar(2),
+                    "long long" | "unsigned long long" | "double" | "int64_t" | "uint64_t" | "_Float64" | "float _Complex" => wide(8),
+                    "long" | "unsigned long" | "size_t" | "ssize_t" | "ptrdiff_t" | "intptr_t" | "uintptr_t" | "off_t" => {
+                        scalar(self.model.long)
+                    }
+                    "long double" => Some(Layout { size: self.model.long_double.0, align: self.model.long_double.1 }),
+                    "double _Complex" => Some(Layout { size: 16, align: self.model.align_8 }),
+                    "__int128" | "unsigned __int128" | "_Float128" => scalar(16),
+                    _ if name.starts_with("enum ") => scalar(4),
+                    _ if self.declarations.enums.contains_key(&name) => scalar(4),
+                    _ => match self.declarations.records.get(&name) {
+                        Some(record) => self.record(record, depth + 1).map(|(layout, _)| layout),
+                        None => self.of(self.declarations.typedefs.get(&name)?, depth + 1),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Layout and field offsets; None when a field's size is unknown or it is a bit-field
+    fn record(&self, record: &RecordDecl, depth: usize) -> Option<(Layout, Vec<u64>)> {
+        let mut offsets = Vec::new();
+        let mut size = 0u64;
+        let mut align = 1;
+        for field in &record.fields {
+            if field.bit_width.is_some() {
+                return None;
+            }
+            let layout = match &field.ty {
+                // A flexible array member adds no size
+                CDecl::Array(element, None) => Layout { size: 0, align: self.of(element, depth)?.align },
+                ty => self.of(ty, depth)?,
+            };
+            align = align.max(layout.align);
+            match record.kind {
+                RecordKind::Struct => {
+                    let offset = size.next_multiple_of(layout.align);
+                    offsets.push(offset);
+                    size = offset + layout.size;
+                }
+                RecordKind::Union => {
+                    offsets.push(0);
+                    size = size.max(layout.size);
+                }
+            }
+        }
+        Some((Layout { size: size.next_multiple_of(align), align }, offsets))
+    }
+}
+
+fn parse_array_size(size: &str) -> Option<u64> {
+    let parts: Vec<&str> = size.split(" * ").collect();
+    parts.iter().try_fold(1u64, |acc, part| Some(acc * u64::try_from(parse_integer(part.trim())?).ok()?))
+}
+
+/// How much a change matters to code built against the old version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Impact {
+    /// Only affects the file itself: static functions, bodies
+    Internal,
+    /// New declarations; existing callers are unaffected
+    Compatible,
+    /// Existing source stops compiling, but binaries keep working
+    ApiBreak,
+    /// Binaries built against the old version misbehave
+    AbiBreak,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// `function parse_header`, `struct point`, `enum color`, ...
+    pub entity: String,
+    pub detail: String,
+    pub impact: Impact,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = match self.kind {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Changed => '~',
+        };
+        let impact = match self.impact {
+            Impact::Internal => "internal",
+            Impact::Compatible => "compatible",
+            Impact::ApiBreak => "api-break",
+            Impact::AbiBreak => "abi-break",
+        };
+        write!(f, "{} {}: {} [{}]", marker, self.entity, self.detail, impact)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SemanticDiff {
+    pub changes: Vec<Change>,
+}
+
+impl SemanticDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The most serious impact of any change
+    pub fn impact(&self) -> Option<Impact> {
+        self.changes.iter().map(|c| c.impact).max()
+    }
+
+    pub fn breaks_compatibility(&self) -> bool {
+        self.impact() >= Some(Impact::ApiBreak)
+    }
+}
+
+/// Compare the declarations of two versions of a file
+pub fn diff(old: &Declarations, new: &Declarations, model: DataModel) -> SemanticDiff {
+    let mut changes = Vec::new();
+    let mut push = |kind, entity: String, detail: String, impact| changes.push(Change { kind, entity, detail, impact });
+
+    let exported = |linkage: Linkage, impact| if linkage == Linkage::External { impact } else { Impact::Internal };
+
+    for (name, before) in &old.functions {
+        let entity = format!("function {}", name);
+        let Some(after) = new.functions.get(name) else {
+            push(ChangeKind::Removed, entity, before.signature(), exported(before.linkage, Impact::AbiBreak));
+            continue;
+        };
+        if before.linkage != after.linkage {
+            let (detail, impact) = match after.linkage {
+                Linkage::Internal => ("made static; the symbol is no longer exported", Impact::AbiBreak),
+                Linkage::External => ("no longer static; the symbol is now exported", Impact::Compatible),
+            };
+            push(ChangeKind::Changed, entity.clone(), detail.to_string(), impact);
+        }
+        if before.ty != after.ty {
+            let detail = format!("signature `{}` -> `{}`", before.signature(), after.signature());
+            push(ChangeKind::Changed, entity.clone(), detail, exported(before.linkage, Impact::AbiBreak));
+        }
+        if before.body.is_some() && after.body.is_some() && before.body != after.body {
+            push(ChangeKind::Changed, entity, "body changed".into(), Impact::Internal);
+        }
+    }
+    for (name, after) in new.functions.iter().filter(|(name, _)| !old.functions.contains_key(*name)) {
+        push(ChangeKind::Added, format!("function {}", name), after.signature(), exported(after.linkage, Impact::Compatible));
+    }
+
+    for (name, before) in &old.variables {
+        let entity = format!("variable {}", name);
+        match new.variables.get(name) {
+            None => push(ChangeKind::Removed, entity, before.ty.render(name), exported(before.linkage, Impact::AbiBreak)),
+            Some(after) if before.ty != after.ty => {
+                let detail = format!("type `{}` -> `{}`", before.ty, after.ty);
+                push(ChangeKind::Changed, entity, detail, exported(before.linkage, Impact::AbiBreak));
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, after) in new.variables.iter().filter(|(name, _)| !old.variables.contains_key(*name)) {
+        push(ChangeKind::Added, format!("variable {}", name), after.ty.render(name), exported(after.linkage, Impact::Compatible));
+    }
+
+    let old_layouts = LayoutContext { model, declarations: old };
+    let new_layouts = LayoutContext { model, declarations: new };
+    // Untagged members are compared through the field that holds them
+    let inline = |name: &String| name.starts_with("struct {") || name.starts_with("union {");
+    for (name, before) in old.records.iter().filter(|(name, _)| !inline(name)) {
+        match new.records.get(name) {
+            None => push(ChangeKind::Removed, name.clone(), "definition removed".into(), Impact::ApiBreak),
+            Some(after) => {
+                for (detail, impact) in record_changes(before, after, &old_layouts, &new_layouts) {
+                    push(ChangeKind::Changed, name.clone(), detail, impact);
+                }
+            }
+        }
+    }
+    for name in new.records.keys().filter(|name| !inline(name) && !old.records.contains_key(*name)) {
+        push(ChangeKind::Added, name.clone(), "defined".into(), Impact::Compatible);
+    }
+
+    for (name, before) in &old.enums {
+        let Some(after) = new.enums.get(name) else {
+            push(ChangeKind::Removed, name.clone(), "definition removed".into(), Impact::ApiBreak);
+            continue;
+        };
+        let values: HashMap<&str, Option<i128>> = after.constants.iter().map(|(n, v)| (n.as_str(), *v)).collect();
+        for (constant, value) in &before.constants {
+            match values.get(constant.as_str()) {
+                None => push(ChangeKind::Changed, name.clone(), format!("enumerator `{}` removed", constant), Impact::ApiBreak),
+                Some(new_value) if new_value != value => {
+                    let show = |v: &Option<i128>| v.map_or("?".to_string(), |v| v.to_string());
+                    let detail = format!("`{}` = {} -> {}", constant, show(value), show(new_value));
+                    push(ChangeKind::Changed, name.clone(), detail, Impact::AbiBreak);
+                }
+                Some(_) => {}
+            }
+        }
+        for (constant, _) in after.constants.iter().filter(|(c, _)| !before.constants.iter().any(|(b, _)| b == c)) {
+            push(ChangeKind::Changed, name.clone(), format!("enumerator `{}` added", constant), Impact::Compatible);
+        }
+    }
+    for name in new.enums.keys().filter(|name| !old.enums.contains_key(*name)) {
+        push(ChangeKind::Added, name.clone(), "defined".into(), Impact::Compatible);
+    }
+
+    for (name, before) in &old.typedefs {
+        let entity = format!("typedef {}", name);
+        match new.typedefs.get(name) {
+            None => push(ChangeKind::Removed, entity, before.to_string(), Impact::ApiBreak),
+            Some(after) if before != after => {
+                push(ChangeKind::Changed, entity, format!("`{}` -> `{}`", before, after), Impact::AbiBreak);
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, after) in new.typedefs.iter().filter(|(name, _)| !old.typedefs.contains_key(*name)) {
+        push(ChangeKind::Added, format!("typedef {}", name), after.to_string(), Impact::Compatible);
+    }
+
+    SemanticDiff { changes }
+}
+
+fn record_changes(
+    before: &RecordDecl,
+    after: &RecordDecl,
+    old_layouts: &LayoutContext,
+    new_layouts: &LayoutContext,
+) -> Vec<(String, Impact)> {
+    let mut changes = Vec::new();
+    if before.kind != after.kind {
+        changes.push(("changed between struct and union".to_string(), Impact::AbiBreak));
+        return changes;
+    }
+
+    let position = |record: &RecordDecl, name: &str| record.fields.iter().position(|f| !name.is_empty() && f.name == name);
+    for (index, field) in before.fields.iter().enumerate() {
+        match position(after, &field.name) {
+            Some(new_index) => {
+                let moved = after.fields[new_index].clone();
+                if moved.ty != field.ty || moved.bit_width != field.bit_width {
+                    let detail = format!("field `{}`: `{}` -> `{}`", field.name, render_field(field), render_field(&moved));
+                    changes.push((detail, Impact::AbiBreak));
+                }
+            }
+            None => {
+                // Same type in the same slot under a new name
+                let renamed = after.fields.get(index).filter(|f| {
+                    f.ty == field.ty && f.bit_width == field.bit_width && position(before, &f.name).is_none()
+                });
+                match renamed {
+                    Some(renamed) => changes.push((format!("field `{}` renamed to `{}`", field.name, renamed.name), Impact::ApiBreak)),
+                    None => changes.push((format!("field `{}` removed", field.name), Impact::AbiBreak)),
+                }
+            }
+        }
+    }
+    for (index, field) in after.fields.iter().enumerate() {
+        let renamed = before.fields.get(index).is_some_and(|old| position(after, &old.name).is_none() && old.ty == field.ty);
+        if position(before, &field.name).is_none() && !renamed {
+            let place = if index + 1 == after.fields.len() { "appended" } else { "inserted" };
+            changes.push((format!("field `{}` {}", field.name, place), Impact::AbiBreak));
+        }
+    }
+
+    let common: Vec<(usize, usize)> = before
+        .fields
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, position(after, &f.name)?)))
+        .collect();
+    let old_layout = old_layouts.record(before, 0);
+    let new_layout = new_layouts.record(after, 0);
+    match (old_layout, new_layout) {
+        (Some((old_layout, old_offsets)), Some((new_layout, new_offsets))) => {
+            for &(i, j) in &common {
+                if old_offsets[i] != new_offsets[j] {
+                    let detail = format!("field `{}` moved from offset {} to {}", before.fields[i].name, old_offsets[i], new_offsets[j]);
+                    changes.push((detail, Impact::AbiBreak));
+                }
+            }
+            if old_layout.size != new_layout.size {
+                changes.push((format!("size {} -> {} bytes", old_layout.size, new_layout.size), Impact::AbiBreak));
+            }
+            if old_layout.align != new_layout.align {
+                changes.push((format!("alignment {} -> {}", old_layout.align, new_layout.align), Impact::AbiBreak));
+            }
+        }
+        // Without layouts, any reordering is assumed to move fields
+        _ => {
+            if common.windows(2).any(|pair| pair[0].1 > pair[1].1) {
+                changes.push(("fields reordered".to_string(), Impact::AbiBreak));
+            }
+        }
+    }
+    changes
+}
+
+fn render_field(field: &Field) -> String {
+    let declaration = field.ty.render(&field.name);
+    match &field.bit_width {
+        Some(width) => format!("{} : {}", declaration, width),
+        None => declaration,
+    }
+}
+
+/// Parse both files and compare them
+pub fn diff_sources(old: &str, new: &str, model: DataModel) -> Result<SemanticDiff, SemdiffError> {
+    let old = parse(old).map_err(|e| e.in_version("old"))?;
+    let new = parse(new).map_err(|e| e.in_version("new"))?;
+    Ok(diff(&old, &new, model))
+}
+
+#[derive(Debug)]
+pub enum SemdiffError {
+    Syntax { line: usize, message: String },
+    /// A declaration the parser doesn't understand; skipped rather than fatal
+    Declaration(String),
+    /// Which of the two files failed to parse
+    InVersion(&'static str, Box<SemdiffError>),
+}
+
+impl SemdiffError {
+    fn in_version(self, version: &'static str) -> Self {
+        SemdiffError::InVersion(version, Box::new(self))
+    }
+}
+
+// Example usage:
+/*
+fn review(old_path: &str, new_path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let old = std::fs::read_to_string(old_path)?;
+    let new = std::fs::read_to_string(new_path)?;
+
+    let diff = diff_sources(&old, &new, DataModel::for_architecture("x86_64"))
+        .map_err(|e| format!("{:?}", e))?;
+    for change in &diff.changes {
+        println!("{}", change);
+        // ~ struct point: field `z` inserted [abi-break]
+        // ~ struct point: size 8 -> 12 bytes [abi-break]
+        // ~ function area: body changed [internal]
+    }
+    Ok(diff.breaks_compatibility())
+}
+*/
@@ -0,0 +1,265 @@
+// src/analysis/signal_safety.rs
+//! Async-signal-safety of signal handlers
+//! Finds the functions a program installs as signal handlers and reports
+//! every call they can reach, directly or through the program's own
+//! functions, to an external function POSIX doesn't list as
+//! async-signal-safe: the compile-time half of `--audit-signals`
+//! (`runtime::signal_safety`), which only sees the handlers that actually
+//! run and only when they're interpreted.
+//!
+//! Handlers are the functions passed to `signal`, `sigset` or
+//! `bsd_signal`, and the functions whose address is stored by a function
+//! that calls `sigaction`, which is how `sa.sa_handler = f` compiles; a
+//! `struct sigaction` initialized statically isn't seen, and `--handler`
+//! names such handlers. The search runs on the IR before optimization, so
+//! the call chains read like the source. Indirect calls in a handler's
+//! reach are listed apart rather than guessed at.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use serde::Serialize;
+use super::stack_depth::{called_function, for_each_call, value_name};
+use crate::runtime::signal_safety::is_async_signal_safe;
+
+#[derive(Debug, Clone, Default)]
+pub struct SignalSafetyOptions {
+    /// Handlers to check besides the ones found
+    pub handlers: Vec<String>,
+    /// External functions to accept from a handler, e.g. ones the program
+    /// knows to be safe on its platform
+    pub safe: Vec<String>,
+}
+
+/// A call from a handler's reach to a function that isn't async-signal-safe
+#[derive(Debug, Clone, Serialize)]
+pub struct UnsafeCall {
+    pub function: String,
+    /// From the handler to the function making the call
+    pub path: Vec<String>,
+    /// `file:line` of the call, with debug info
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HandlerReport {
+    pub handler: String,
+    /// Where it's installed; empty for one named with `--handler`
+    pub installed_by: Vec<String>,
+    pub unsafe_calls: Vec<UnsafeCall>,
+    /// Functions in its reach that make indirect calls
+    pub indirect_calls: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SignalSafetyReport {
+    pub handlers: Vec<HandlerReport>,
+}
+
+impl SignalSafetyReport {
+    pub fn is_clean(&self) -> bool {
+        self.handlers.iter().all(|handler| handler.unsafe_calls.is_empty())
+    }
+}
+
+impl fmt::Display for SignalSafetyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.handlers.is_empty() {
+            return writeln!(f, "no signal handlers found");
+        }
+        for handler in &self.handlers {
+            if handler.installed_by.is_empty() {
+                writeln!(f, "{}:", handler.handler)?;
+            } else {
+                writeln!(f, "{} (installed by {}):", handler.handler, handler.installed_by.join(", "))?;
+            }
+            if handler.unsafe_calls.is_empty() {
+                writeln!(f, "  async-signal-safe")?;
+            }
+            for call in &handler.unsafe_calls {
+                match &call.location {
+                    Some(location) => writeln!(f, "  calls {} at {}", call.function, location)?,
+                    None => writeln!(f, "  calls {}", call.function)?,
+                }
+                writeln!(f, "    via {}", call.path.join(" -> "))?;
+            }
+            if !handler.indirect_calls.is_empty() {
+                writeln!(f, "  indirect calls not checked in: {}", handler.indirect_calls.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Functions whose function-pointer argument becomes a handler
+const INSTALLERS: &[&str] = &["signal", "sigset", "bsd_signal"];
+
+/// Check the handlers of `module`
+pub unsafe fn analyze(module: LLVMModuleRef, options: &SignalSafetyOptions) -> Result<SignalSafetyReport, SignalSafetyError> {
+    let mut functions = HashMap::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMGetIntrinsicID(function) == 0 {
+            functions.insert(value_name(function), function);
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    // Handler -> the functions installing it, in module order
+    let mut handlers: Vec<(String, BTreeSet<String>)> = Vec::new();
+    let mut add = |handler: String, installer: Option<String>| {
+        let index = match handlers.iter().position(|(name, _)| *name == handler) {
+            Some(index) => index,
+            None => {
+                handlers.push((handler, BTreeSet::new()));
+                handlers.len() - 1
+            }
+        };
+        handlers[index].1.extend(installer);
+    };
+    for name in &options.handlers {
+        match functions.get(name) {
+            Some(&function) if LLVMIsDeclaration(function) == 0 => add(name.clone(), None),
+            _ => return Err(SignalSafetyError::UnknownHandler(name.clone())),
+        }
+    }
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 {
+            let installer = value_name(function);
+            let mut calls_sigaction = false;
+            for_each_call(function, |call| {
+                let callee = called_function(call);
+                if callee.is_null() {
+                    return;
+                }
+                let callee = value_name(callee);
+                calls_sigaction |= callee == "sigaction";
+                if INSTALLERS.contains(&callee.as_str()) {
+                    let handler = LLVMIsAFunction(LLVMGetOperand(call, 1));
+                    if !handler.is_null() && LLVMIsDeclaration(handler) == 0 {
+                        add(value_name(handler), Some(installer.clone()));
+                    }
+                }
+            });
+            if calls_sigaction {
+                for handler in stored_functions(function) {
+                    add(handler, Some(installer.clone()));
+                }
+            }
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    let report = handlers
+        .into_iter()
+        .map(|(handler, installers)| check_handler(&functions, handler, installers, options))
+        .collect();
+    Ok(SignalSafetyReport { handlers: report })
+}
+
+/// Breadth first from the handler, so each call's path is a shortest one
+unsafe fn check_handler(
+    functions: &HashMap<String, LLVMValueRef>,
+    handler: String,
+    installers: BTreeSet<String>,
+    options: &SignalSafetyOptions,
+) -> HandlerReport {
+    let mut unsafe_calls = Vec::new();
+    let mut indirect_calls = Vec::new();
+    let mut reported = BTreeSet::new();
+    let mut paths: HashMap<String, Vec<String>> = HashMap::from([(handler.clone(), vec![handler.clone()])]);
+    let mut queue = VecDeque::from([handler.clone()]);
+    while let Some(name) = queue.pop_front() {
+        let path = paths[&name].clone();
+        let mut indirect = false;
+        for_each_call(functions[&name], |call| {
+            let callee = called_function(call);
+            if callee.is_null() {
+                indirect |= LLVMIsAInlineAsm(LLVMGetCalledValue(call)).is_null();
+                return;
+            }
+            if LLVMGetIntrinsicID(callee) != 0 {
+                return;
+            }
+            let callee_name = value_name(callee);
+            if LLVMIsDeclaration(callee) == 0 {
+                if !paths.contains_key(&callee_name) {
+                    let mut callee_path = path.clone();
+                    callee_path.push(callee_name.clone());
+                    paths.insert(callee_name.clone(), callee_path);
+                    queue.push_back(callee_name);
+                }
+            } else if !is_async_signal_safe(&callee_name)
+                && !options.safe.contains(&callee_name)
+                && reported.insert((callee_name.clone(), name.clone()))
+            {
+                unsafe_calls.push(UnsafeCall { function: callee_name, path: path.clone(), location: location(call) });
+            }
+        });
+        if indirect {
+            indirect_calls.push(name);
+        }
+    }
+    HandlerReport { handler, installed_by: installers.into_iter().collect(), unsafe_calls, indirect_calls }
+}
+
+/// Defined functions `function` stores somewhere
+unsafe fn stored_functions(function: LLVMValueRef) -> Vec<String> {
+    let mut stored = Vec::new();
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        let mut instruction = LLVMGetFirstInstruction(block);
+        while !instruction.is_null() {
+            if !LLVMIsAStoreInst(instruction).is_null() {
+                let value = LLVMIsAFunction(LLVMGetOperand(instruction, 0));
+                if !value.is_null() && LLVMIsDeclaration(value) == 0 {
+                    stored.push(value_name(value));
+                }
+            }
+            instruction = LLVMGetNextInstruction(instruction);
+        }
+        block = LLVMGetNextBasicBlock(block);
+    }
+    stored
+}
+
+/// `file:line` from the instruction's debug location
+unsafe fn location(instruction: LLVMValueRef) -> Option<String> {
+    let line = LLVMGetDebugLocLine(instruction);
+    if line == 0 {
+        return None;
+    }
+    let mut length = 0;
+    let file = LLVMGetDebugLocFilename(instruction, &mut length);
+    if file.is_null() {
+        return Some(format!("line {}", line));
+    }
+    let file = String::from_utf8_lossy(std::slice::from_raw_parts(file as *const u8, length as usize));
+    Some(format!("{}:{}", file, line))
+}
+
+#[derive(Debug)]
+pub enum SignalSafetyError {
+    /// A `--handler` that isn't defined in the program
+    UnknownHandler(String),
+}
+
+impl fmt::Display for SignalSafetyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalSafetyError::UnknownHandler(name) => write!(f, "handler '{}' is not defined", name),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn check_handlers(module: LLVMModuleRef) -> Result<bool, SignalSafetyError> {
+    let options = SignalSafetyOptions { safe: vec!["backtrace_symbols_fd".to_string()], ..Default::default() };
+    let report = analyze(module, &options)?;
+    print!("{}", report);
+    Ok(report.is_clean())
+}
+*/
@@ -0,0 +1,641 @@
+// src/analysis/stack_depth.rs
+//! Whole-program stack depth
+//! Works out how much stack each entry point can use at worst, so a JIT
+//! thread or an embedded target can be given a guarded stack that fits: the
+//! deepest chain of frames through the call graph of the optimized module.
+//!
+//! Frame sizes come from the code generator, not from the IR: a copy of the
+//! module is compiled to assembly without frame pointers or a red zone and
+//! with asynchronous unwind tables, where the CFI describes every move of
+//! the stack pointer, and each function's frame is its largest CFA offset.
+//! Two words per frame cover the frame pointer that the real code may keep.
+//!
+//! What the call graph can't bound is reported apart from the number rather
+//! than folded into it: recursion cycles, functions with variable-size
+//! `alloca`s, and external functions whose frame isn't known. Indirect calls
+//! are assumed to reach every function whose address is taken and whose
+//! type matches; annotations narrow them, bound recursion, and give the
+//! frames of external functions. Calls the code generator adds itself
+//! (`memcpy`, `__stack_chk_fail`, libgcc helpers) are not seen.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::CStr;
+use std::fmt;
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::target_machine::{LLVMCodeGenFileType, LLVMTargetMachineEmitToMemoryBuffer, LLVMTargetMachineRef};
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMLinkage};
+use serde::Serialize;
+use crate::arch::Architecture;
+
+/// What the call graph alone doesn't say
+#[derive(Debug, Clone, Default)]
+pub struct StackAnnotations {
+    /// Possible targets of the indirect calls in a function, replacing the
+    /// address-taken functions of the right type
+    pub indirect_targets: HashMap<String, Vec<String>>,
+    /// Most times any function of a recursion cycle is on the stack at once
+    pub recursion_bounds: HashMap<String, u32>,
+    /// Frames of functions defined outside the module, e.g. libc's
+    pub external_frames: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StackDepthOptions {
+    /// Functions to report; empty means `main` and every externally
+    /// visible function nothing in the module calls
+    pub entries: Vec<String>,
+    pub annotations: StackAnnotations,
+}
+
+impl StackAnnotations {
+    /// `caller=target,target`, as `--indirect` takes it
+    pub fn add_indirect(&mut self, spec: &str) -> Result<(), StackDepthError> {
+        let (caller, targets) = split_annotation(spec)?;
+        let targets = targets.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect();
+        self.indirect_targets.insert(caller.to_string(), targets);
+        Ok(())
+    }
+
+    /// `function=count`, as `--recursion` takes it
+    pub fn add_recursion_bound(&mut self, spec: &str) -> Result<(), StackDepthError> {
+        let (function, bound) = split_annotation(spec)?;
+        let bound = bound.parse().map_err(|_| StackDepthError::Annotation(spec.to_string()))?;
+        self.recursion_bounds.insert(function.to_string(), bound);
+        Ok(())
+    }
+
+    /// `function=bytes`, as `--frame` takes it
+    pub fn add_external_frame(&mut self, spec: &str) -> Result<(), StackDepthError> {
+        let (function, bytes) = split_annotation(spec)?;
+        let bytes = bytes.parse().map_err(|_| StackDepthError::Annotation(spec.to_string()))?;
+        self.external_frames.insert(function.to_string(), bytes);
+        Ok(())
+    }
+}
+
+fn split_annotation(spec: &str) -> Result<(&str, &str), StackDepthError> {
+    match spec.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim(), value.trim())),
+        _ => Err(StackDepthError::Annotation(spec.to_string())),
+    }
+}
+
+/// One function's own frame
+#[derive(Debug, Clone, Serialize)]
+pub struct Frame {
+    pub bytes: u64,
+    /// Has a variable-size `alloca`, so `bytes` is only its fixed part
+    pub dynamic: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryDepth {
+    pub entry: String,
+    /// Worst case over the bounded part of the call graph
+    pub bytes: u64,
+    /// The chain of calls that reaches it
+    pub path: Vec<String>,
+    /// Why `bytes` may be exceeded: recursion and variable-size frames
+    pub unbounded: Vec<String>,
+    /// External functions reached whose frames weren't given, counted as 0
+    pub unknown: Vec<String>,
+    /// Whether the result relies on the targets assumed for indirect calls
+    pub indirect: bool,
+}
+
+impl EntryDepth {
+    pub fn is_bounded(&self) -> bool {
+        self.unbounded.is_empty() && self.unknown.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecursionCycle {
+    pub functions: Vec<String>,
+    /// From the annotations; without one the cycle is unbounded
+    pub bound: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndirectCalls {
+    pub caller: String,
+    pub targets: Vec<String>,
+    /// Targets from the annotations rather than assumed from types
+    pub annotated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StackReport {
+    pub frames: BTreeMap<String, Frame>,
+    pub entries: Vec<EntryDepth>,
+    pub cycles: Vec<RecursionCycle>,
+    pub indirect_calls: Vec<IndirectCalls>,
+}
+
+impl StackReport {
+    /// Entries that may need more than `limit` bytes, or can't be bounded
+    pub fn exceeding(&self, limit: u64) -> impl Iterator<Item = &EntryDepth> {
+        self.entries.iter().filter(move |entry| entry.bytes > limit || !entry.is_bounded())
+    }
+}
+
+impl fmt::Display for StackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            let qualifier = if entry.is_bounded() { "" } else { "at least " };
+            writeln!(f, "{}: {}{} bytes", entry.entry, qualifier, entry.bytes)?;
+            writeln!(f, "  via {}", entry.path.join(" -> "))?;
+            for reason in &entry.unbounded {
+                writeln!(f, "  unbounded: {}", reason)?;
+            }
+            if !entry.unknown.is_empty() {
+                writeln!(f, "  unknown frames: {}", entry.unknown.join(", "))?;
+            }
+            if entry.indirect {
+                writeln!(f, "  assumes the indirect call targets below")?;
+            }
+        }
+        if !self.cycles.is_empty() {
+            writeln!(f, "recursion:")?;
+            for cycle in &self.cycles {
+                match cycle.bound {
+                    Some(bound) => writeln!(f, "  {} (at most {} deep)", cycle.functions.join(" -> "), bound)?,
+                    None => writeln!(f, "  {} (unbounded)", cycle.functions.join(" -> "))?,
+                }
+            }
+        }
+        if !self.indirect_calls.is_empty() {
+            writeln!(f, "indirect calls:")?;
+            for calls in &self.indirect_calls {
+                let source = if calls.annotated { "annotated" } else { "assumed" };
+                let targets = if calls.targets.is_empty() { "nothing".to_string() } else { calls.targets.join(", ") };
+                writeln!(f, "  {} -> {} ({})", calls.caller, targets, source)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A function of the call graph
+struct Node {
+    name: String,
+    /// `None` for external functions
+    frame: Option<Frame>,
+    /// (callee, through an indirect call)
+    calls: Vec<(usize, bool)>,
+    external_linkage: bool,
+    called: bool,
+}
+
+/// Worst case below a strongly connected component, itself included
+#[derive(Clone, Default)]
+struct Summary {
+    bytes: u64,
+    path: Vec<String>,
+    unbounded: BTreeSet<String>,
+    unknown: BTreeSet<String>,
+    indirect: bool,
+}
+
+/// Analyze `module` as `target_machine` would compile it for `architecture`
+pub unsafe fn analyze(
+    module: LLVMModuleRef,
+    target_machine: LLVMTargetMachineRef,
+    architecture: Architecture,
+    options: &StackDepthOptions,
+) -> Result<StackReport, StackDepthError> {
+    let annotations = &options.annotations;
+    let measured = measure_frames(module, target_machine, architecture)?;
+    let slack = 2 * architecture.word_size() as u64;
+
+    // Nodes: every function defined or called
+    let mut nodes = Vec::new();
+    let mut index = HashMap::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMGetIntrinsicID(function) == 0 {
+            let name = value_name(function);
+            let frame = if LLVMIsDeclaration(function) != 0 {
+                None
+            } else {
+                let bytes = measured.get(&name).copied().unwrap_or(0);
+                Some(Frame { bytes: bytes + slack, dynamic: has_dynamic_alloca(function) })
+            };
+            index.insert(name.clone(), nodes.len());
+            nodes.push(Node {
+                name,
+                frame,
+                calls: Vec::new(),
+                external_linkage: !matches!(
+                    LLVMGetLinkage(function),
+                    LLVMLinkage::LLVMInternalLinkage | LLVMLinkage::LLVMPrivateLinkage
+                ),
+                called: false,
+            });
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    // Indirect calls may reach any address-taken function of their type
+    let mut address_taken: Vec<(LLVMTypeRef, usize)> = Vec::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if let Some(&node) = index.get(&value_name(function)) {
+            if is_address_taken(function) {
+                address_taken.push((LLVMGlobalGetValueType(function), node));
+            }
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    let mut indirect_calls = Vec::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 {
+            let caller = index[&value_name(function)];
+            let mut assumed = BTreeSet::new();
+            let mut calls_indirectly = false;
+            for_each_call(function, |call| {
+                let callee = called_function(call);
+                if !callee.is_null() {
+                    if let Some(&node) = index.get(&value_name(callee)) {
+                        nodes[caller].calls.push((node, false));
+                    }
+                } else if LLVMIsAInlineAsm(LLVMGetCalledValue(call)).is_null() {
+                    calls_indirectly = true;
+                    let ty = LLVMGetCalledFunctionType(call);
+                    assumed.extend(address_taken.iter().filter(|(target, _)| *target == ty).map(|(_, node)| *node));
+                }
+            });
+            if calls_indirectly {
+                let name = nodes[caller].name.clone();
+                let (targets, annotated) = match annotations.indirect_targets.get(&name) {
+                    Some(targets) => (targets.iter().filter_map(|target| index.get(target).copied()).collect(), true),
+                    None => (assumed, false),
+                };
+                for &target in &targets {
+                    nodes[caller].calls.push((target, true));
+                }
+                indirect_calls.push(IndirectCalls {
+                    caller: name,
+                    targets: targets.iter().map(|&target| nodes[target].name.clone()).collect(),
+                    annotated,
+                });
+            }
+        }
+        function = LLVMGetNextFunction(function);
+    }
+    for caller in 0..nodes.len() {
+        for (callee, _) in nodes[caller].calls.clone() {
+            if callee != caller {
+                nodes[callee].called = true;
+            }
+        }
+    }
+
+    // Components come out callees first, so each one's callees are done
+    let calls: Vec<Vec<usize>> = nodes.iter().map(|node| node.calls.iter().map(|&(callee, _)| callee).collect()).collect();
+    let components = strongly_connected(&calls);
+    let mut component_of = vec![0; nodes.len()];
+    for (c, members) in components.iter().enumerate() {
+        for &member in members {
+            component_of[member] = c;
+        }
+    }
+    let mut summaries: Vec<Summary> = Vec::with_capacity(components.len());
+    let mut cycles = Vec::new();
+    for (c, members) in components.iter().enumerate() {
+        let recursive = members.len() > 1 || nodes[members[0]].calls.iter().any(|&(callee, _)| callee == members[0]);
+        let names: Vec<String> = members.iter().map(|&member| nodes[member].name.clone()).collect();
+        let mut summary = Summary::default();
+
+        let mut own = 0;
+        for &member in members {
+            let node = &nodes[member];
+            match &node.frame {
+                Some(frame) => {
+                    own += frame.bytes;
+                    if frame.dynamic {
+                        summary.unbounded.insert(format!("variable-size alloca in {}", node.name));
+                    }
+                }
+                None => match annotations.external_frames.get(&node.name) {
+                    Some(&bytes) => own += bytes,
+                    None => {
+                        summary.unknown.insert(node.name.clone());
+                    }
+                },
+            }
+        }
+        if recursive {
+            let bound = names.iter().find_map(|name| annotations.recursion_bounds.get(name).copied());
+            match bound {
+                Some(bound) => own *= u64::from(bound.max(1)),
+                None => {
+                    summary.unbounded.insert(format!("recursion through {}", names.join(" -> ")));
+                }
+            }
+            cycles.push(RecursionCycle { functions: names.clone(), bound });
+        }
+
+        let mut deepest: Option<&Summary> = None;
+        for &member in members {
+            for &(callee, indirect) in &nodes[member].calls {
+                let target = component_of[callee];
+                if target == c {
+                    continue;
+                }
+                let below = &summaries[target];
+                summary.unbounded.extend(below.unbounded.iter().cloned());
+                summary.unknown.extend(below.unknown.iter().cloned());
+                summary.indirect |= indirect || below.indirect;
+                if deepest.is_none_or(|deepest| below.bytes > deepest.bytes) {
+                    deepest = Some(below);
+                }
+            }
+        }
+        summary.bytes = own + deepest.map_or(0, |deepest| deepest.bytes);
+        summary.path = names;
+        summary.path.extend(deepest.map(|deepest| deepest.path.clone()).unwrap_or_default());
+        summaries.push(summary);
+    }
+
+    let entries: Vec<usize> = if options.entries.is_empty() {
+        (0..nodes.len())
+            .filter(|&node| nodes[node].frame.is_some())
+            .filter(|&node| nodes[node].name == "main" || (nodes[node].external_linkage && !nodes[node].called))
+            .collect()
+    } else {
+        options
+            .entries
+            .iter()
+            .map(|name| index.get(name).copied().ok_or_else(|| StackDepthError::UnknownEntry(name.clone())))
+            .collect::<Result<_, _>>()?
+    };
+    let entries = entries
+        .into_iter()
+        .map(|node| {
+            let summary = &summaries[component_of[node]];
+            EntryDepth {
+                entry: nodes[node].name.clone(),
+                bytes: summary.bytes,
+                path: summary.path.clone(),
+                unbounded: summary.unbounded.iter().cloned().collect(),
+                unknown: summary.unknown.iter().cloned().collect(),
+                indirect: summary.indirect,
+            }
+        })
+        .collect();
+
+    let frames = nodes.into_iter().filter_map(|node| Some((node.name, node.frame?))).collect();
+    Ok(StackReport { frames, entries, cycles, indirect_calls })
+}
+
+/// Each defined function's largest CFA offset, from the assembly of a copy
+/// of `module` compiled so that the CFI tracks the stack pointer throughout
+unsafe fn measure_frames(
+    module: LLVMModuleRef,
+    target_machine: LLVMTargetMachineRef,
+    architecture: Architecture,
+) -> Result<HashMap<String, u64>, StackDepthError> {
+    let copy = LLVMCloneModule(module);
+    let context = LLVMGetModuleContext(copy);
+    let uwtable = LLVMGetEnumAttributeKindForName(c"uwtable".as_ptr(), 7);
+    let noredzone = LLVMGetEnumAttributeKindForName(c"noredzone".as_ptr(), 9);
+    let mut function = LLVMGetFirstFunction(copy);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 {
+            LLVMRemoveStringAttributeAtIndex(function, LLVMAttributeFunctionIndex, c"frame-pointer".as_ptr(), 13);
+            LLVMAddAttributeAtIndex(
+                function,
+                LLVMAttributeFunctionIndex,
+                LLVMCreateStringAttribute(context, c"frame-pointer".as_ptr(), 13, c"none".as_ptr(), 4),
+            );
+            // 2 is asynchronous: every instruction, not just call sites
+            LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, LLVMCreateEnumAttribute(context, uwtable, 2));
+            LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, LLVMCreateEnumAttribute(context, noredzone, 0));
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    let mut buffer = std::ptr::null_mut();
+    let mut error = std::ptr::null_mut();
+    let failed =
+        LLVMTargetMachineEmitToMemoryBuffer(target_machine, copy, LLVMCodeGenFileType::LLVMAssemblyFile, &mut error, &mut buffer);
+    LLVMDisposeModule(copy);
+    if failed != 0 {
+        let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+        LLVMDisposeMessage(error);
+        return Err(StackDepthError::CodeGeneration(message));
+    }
+    let bytes = std::slice::from_raw_parts(LLVMGetBufferStart(buffer) as *const u8, LLVMGetBufferSize(buffer));
+    let assembly = String::from_utf8_lossy(bytes).into_owned();
+    LLVMDisposeMemoryBuffer(buffer);
+
+    // The call pushes the return address on x86; elsewhere it's in a register
+    let initial = match architecture {
+        Architecture::X86_64 => architecture.word_size() as u64,
+        _ => 0,
+    };
+    Ok(parse_frames(&assembly, initial))
+}
+
+/// Largest stack-pointer-relative CFA offset between each function's
+/// `.cfi_startproc` and `.cfi_endproc`
+fn parse_frames(assembly: &str, initial: u64) -> HashMap<String, u64> {
+    let is_stack_pointer = |register: &str| matches!(register.trim(), "%rsp" | "rsp" | "sp" | "wsp");
+    let mut frames = HashMap::new();
+    let mut label: Option<&str> = None;
+    let mut current: Option<(&str, u64)> = None;
+    for line in assembly.lines() {
+        if !line.starts_with(|c: char| c.is_whitespace()) {
+            if let Some(name) = line.strip_suffix(':') {
+                if !name.starts_with('.') {
+                    label = Some(name.trim_matches('"'));
+                }
+            }
+            continue;
+        }
+        let line = line.trim();
+        let (directive, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let offset = |text: &str| text.trim().parse::<u64>().ok();
+        match (directive, current.as_mut()) {
+            (".cfi_startproc", _) => current = label.map(|name| (name, initial)),
+            (".cfi_endproc", Some((name, bytes))) => {
+                frames.insert(name.to_string(), *bytes);
+                current = None;
+            }
+            (".cfi_def_cfa_offset", Some((_, bytes))) => {
+                *bytes = (*bytes).max(offset(operands).unwrap_or(0));
+            }
+            (".cfi_def_cfa", Some((_, bytes))) => {
+                if let Some((register, value)) = operands.split_once(',') {
+                    if is_stack_pointer(register) {
+                        *bytes = (*bytes).max(offset(value).unwrap_or(0));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    frames
+}
+
+pub(crate) unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut length = 0;
+    let name = LLVMGetValueName2(value, &mut length);
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, length)).into_owned()
+}
+
+pub(crate) unsafe fn for_each_call(function: LLVMValueRef, mut f: impl FnMut(LLVMValueRef)) {
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        let mut instruction = LLVMGetFirstInstruction(block);
+        while !instruction.is_null() {
+            if !LLVMIsACallInst(instruction).is_null() || !LLVMIsAInvokeInst(instruction).is_null() {
+                f(instruction);
+            }
+            instruction = LLVMGetNextInstruction(instruction);
+        }
+        block = LLVMGetNextBasicBlock(block);
+    }
+}
+
+/// The function `call` calls directly, through an alias if need be; null
+/// for indirect calls and inline assembly
+pub(crate) unsafe fn called_function(call: LLVMValueRef) -> LLVMValueRef {
+    let mut callee = LLVMGetCalledValue(call);
+    if !LLVMIsAGlobalAlias(callee).is_null() {
+        callee = LLVMAliasGetAliasee(callee);
+    }
+    LLVMIsAFunction(callee)
+}
+
+/// Used other than as the callee of a call
+unsafe fn is_address_taken(function: LLVMValueRef) -> bool {
+    let mut use_ = LLVMGetFirstUse(function);
+    while !use_.is_null() {
+        let user = LLVMGetUser(use_);
+        let is_call = !LLVMIsACallInst(user).is_null() || !LLVMIsAInvokeInst(user).is_null();
+        if !is_call || LLVMGetCalledValue(user) != function {
+            return true;
+        }
+        use_ = LLVMGetNextUse(use_);
+    }
+    false
+}
+
+/// An `alloca` outside the entry block or of a run-time count
+unsafe fn has_dynamic_alloca(function: LLVMValueRef) -> bool {
+    let entry = LLVMGetEntryBasicBlock(function);
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        let mut instruction = LLVMGetFirstInstruction(block);
+        while !instruction.is_null() {
+            if !LLVMIsAAllocaInst(instruction).is_null()
+                && (block != entry || LLVMIsAConstantInt(LLVMGetOperand(instruction, 0)).is_null())
+            {
+                return true;
+            }
+            instruction = LLVMGetNextInstruction(instruction);
+        }
+        block = LLVMGetNextBasicBlock(block);
+    }
+    false
+}
+
+/// Tarjan's algorithm without recursion, since call graphs can be deep.
+/// Components come out in reverse topological order: callees first.
+pub(crate) fn strongly_connected(calls: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+    let mut order = vec![UNVISITED; calls.len()];
+    let mut low = vec![0; calls.len()];
+    let mut on_stack = vec![false; calls.len()];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut counter = 0;
+
+    for root in 0..calls.len() {
+        if order[root] != UNVISITED {
+            continue;
+        }
+        // (node, next edge to look at)
+        let mut work = vec![(root, 0)];
+        while let Some(&(node, edge)) = work.last() {
+            if edge == 0 && order[node] == UNVISITED {
+                order[node] = counter;
+                low[node] = counter;
+                counter += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+            if let Some(&callee) = calls[node].get(edge) {
+                work.last_mut().unwrap().1 += 1;
+                if order[callee] == UNVISITED {
+                    work.push((callee, 0));
+                } else if on_stack[callee] {
+                    low[node] = low[node].min(order[callee]);
+                }
+                continue;
+            }
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[node]);
+            }
+            if low[node] == order[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.reverse();
+                components.push(component);
+            }
+        }
+    }
+    components
+}
+
+#[derive(Debug)]
+pub enum StackDepthError {
+    CodeGeneration(String),
+    UnknownEntry(String),
+    /// An annotation that isn't `name=value`
+    Annotation(String),
+}
+
+impl fmt::Display for StackDepthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackDepthError::CodeGeneration(message) => write!(f, "measuring frames: {}", message),
+            StackDepthError::UnknownEntry(name) => write!(f, "entry point '{}' is not defined", name),
+            StackDepthError::Annotation(spec) => write!(f, "invalid annotation '{}': expected name=value", spec),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn size_thread_stack(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) -> Result<u64, StackDepthError> {
+    let mut options = StackDepthOptions { entries: vec!["worker".to_string()], ..Default::default() };
+    options.annotations.add_indirect("dispatch=on_read,on_write")?;
+    options.annotations.add_recursion_bound("walk_tree=32")?;
+    options.annotations.add_external_frame("memcpy=0")?;
+
+    let report = analyze(module, target_machine, Architecture::X86_64, &options)?;
+    print!("{}", report);
+    // worker: 1184 bytes
+    //   via worker -> dispatch -> on_read -> walk_tree
+    //   assumes the indirect call targets below
+    // recursion:
+    //   walk_tree (at most 32 deep)
+    // indirect calls:
+    //   dispatch -> on_read, on_write (annotated)
+    let worker = &report.entries[0];
+    Ok(worker.bytes.next_multiple_of(4096) + 4096)
+}
+*/
@@ -0,0 +1,1024 @@
+// src/analysis/wcet.rs
+//! Worst-case execution time
+//! A static bound on the cycles each function of a bare-metal program can
+//! take, for code whose loops are bounded or annotated: the longest path
+//! through its machine code, charged with the latencies of the target
+//! CPU's scheduling model.
+//!
+//! A copy of the module is compiled to an object file for the chosen CPU,
+//! without jump tables so that every branch target is visible, and each
+//! function is disassembled by LLVM with latency annotations. Every
+//! instruction costs its full latency, as if nothing overlapped; caches,
+//! branch prediction and pipelining aren't modelled, so the number is an
+//! upper estimate on an in-order core with fixed-latency memory and no more
+//! than a ranking anywhere else.
+//!
+//! Loops are the natural loops of each function's control flow graph. A
+//! loop costs its bound times the longest pass through its body, inner
+//! loops included; a function costs its longest path from entry to return,
+//! and a call the callee's worst case. What can't be bounded is reported
+//! apart from the number rather than guessed: loops without a bound,
+//! irreducible control flow, recursion, indirect jumps, indirect calls
+//! without annotated targets, and external functions without an annotated
+//! cost, the libgcc helpers and `memcpy`s the code generator adds included.
+
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use llvm_sys::core::*;
+use llvm_sys::disassembler::*;
+use llvm_sys::prelude::*;
+use llvm_sys::target::LLVM_InitializeAllDisassemblers;
+use llvm_sys::target_machine::*;
+use llvm_sys::LLVMAttributeFunctionIndex;
+use object::{Object, ObjectSection, ObjectSymbol, RelocationTarget, SectionIndex, SymbolKind};
+use serde::Serialize;
+use super::stack_depth::strongly_connected;
+
+/// What the machine code alone doesn't say
+#[derive(Debug, Clone, Default)]
+pub struct WcetAnnotations {
+    /// Most times a loop's header runs per entry into the loop, by function
+    /// and loop number; `None` bounds every loop of the function
+    pub loop_bounds: HashMap<(String, Option<usize>), u64>,
+    /// Possible targets of the indirect calls in a function
+    pub indirect_targets: HashMap<String, Vec<String>>,
+    /// Cycles of functions defined outside the module, e.g. libc's
+    pub external_costs: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WcetOptions {
+    /// Functions to report; empty means every function defined
+    pub functions: Vec<String>,
+    /// CPU whose scheduling model gives the latencies; `None` for the one
+    /// the compiler targets
+    pub cpu: Option<String>,
+    pub annotations: WcetAnnotations,
+}
+
+impl WcetAnnotations {
+    /// `function=count` or `function#loop=count`, as `--loop-bound` takes it
+    pub fn add_loop_bound(&mut self, spec: &str) -> Result<(), WcetError> {
+        let (target, bound) = split_annotation(spec)?;
+        let bound = bound.parse().map_err(|_| WcetError::Annotation(spec.to_string()))?;
+        let key = match target.split_once('#') {
+            Some((function, number)) => {
+                let number = number.trim().parse().map_err(|_| WcetError::Annotation(spec.to_string()))?;
+                (function.trim().to_string(), Some(number))
+            }
+            None => (target.to_string(), None),
+        };
+        self.loop_bounds.insert(key, bound);
+        Ok(())
+    }
+
+    /// `caller=target,target`, as `--indirect` takes it
+    pub fn add_indirect(&mut self, spec: &str) -> Result<(), WcetError> {
+        let (caller, targets) = split_annotation(spec)?;
+        let targets = targets.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect();
+        self.indirect_targets.insert(caller.to_string(), targets);
+        Ok(())
+    }
+
+    /// `function=cycles`, as `--cost` takes it
+    pub fn add_external_cost(&mut self, spec: &str) -> Result<(), WcetError> {
+        let (function, cycles) = split_annotation(spec)?;
+        let cycles = cycles.parse().map_err(|_| WcetError::Annotation(spec.to_string()))?;
+        self.external_costs.insert(function.to_string(), cycles);
+        Ok(())
+    }
+
+    fn loop_bound(&self, function: &str, number: usize) -> Option<u64> {
+        let specific = self.loop_bounds.get(&(function.to_string(), Some(number)));
+        specific.or_else(|| self.loop_bounds.get(&(function.to_string(), None))).copied()
+    }
+}
+
+fn split_annotation(spec: &str) -> Result<(&str, &str), WcetError> {
+    match spec.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim(), value.trim())),
+        _ => Err(WcetError::Annotation(spec.to_string())),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoopCost {
+    /// From 1, in address order of the headers: what `function#number`
+    /// bounds
+    pub number: usize,
+    /// Header's offset from the start of the function
+    pub offset: u64,
+    pub bound: Option<u64>,
+    /// Longest pass through the body, inner loops at their bounds
+    pub cycles_per_iteration: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionTime {
+    pub function: String,
+    /// Worst case over the bounded part, callees included
+    pub cycles: u64,
+    pub instructions: usize,
+    pub loops: Vec<LoopCost>,
+    /// Why `cycles` may be exceeded, here or in a callee
+    pub unbounded: Vec<String>,
+    /// External functions reached whose cost wasn't given, counted as 0
+    pub unknown: Vec<String>,
+}
+
+impl FunctionTime {
+    pub fn is_bounded(&self) -> bool {
+        self.unbounded.is_empty() && self.unknown.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WcetReport {
+    /// Whose scheduling model the latencies come from
+    pub cpu: String,
+    pub functions: Vec<FunctionTime>,
+}
+
+impl WcetReport {
+    /// Functions that may take more than `limit` cycles, or can't be bounded
+    pub fn exceeding(&self, limit: u64) -> impl Iterator<Item = &FunctionTime> {
+        self.functions.iter().filter(move |function| function.cycles > limit || !function.is_bounded())
+    }
+}
+
+impl fmt::Display for WcetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "latencies of {}", self.cpu)?;
+        for function in &self.functions {
+            let qualifier = if function.is_bounded() { "" } else { "at least " };
+            writeln!(
+                f,
+                "{}: {}{} cycles ({} instructions)",
+                function.function, qualifier, function.cycles, function.instructions
+            )?;
+            for cost in &function.loops {
+                match cost.bound {
+                    Some(bound) => writeln!(
+                        f,
+                        "  loop {} at +{:#x}: {} x {} cycles",
+                        cost.number, cost.offset, bound, cost.cycles_per_iteration
+                    )?,
+                    None => writeln!(
+                        f,
+                        "  loop {} at +{:#x}: no bound, {} cycles per iteration",
+                        cost.number, cost.offset, cost.cycles_per_iteration
+                    )?,
+                }
+            }
+            for reason in &function.unbounded {
+                writeln!(f, "  unbounded: {}", reason)?;
+            }
+            if !function.unknown.is_empty() {
+                writeln!(f, "  unknown cost: {}", function.unknown.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where control goes after an instruction
+#[derive(Debug, Clone, PartialEq)]
+enum Flow {
+    Next,
+    Jump(u64),
+    /// Conditional: to the target or the next instruction
+    Branch(u64),
+    Call(Callee),
+    /// A jump to another function, which returns for this one
+    TailCall(Callee),
+    IndirectJump,
+    Return,
+    /// Traps and halts: the path ends here
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Callee {
+    Function(String),
+    Indirect,
+}
+
+struct Instruction {
+    /// Address in the section
+    address: u64,
+    size: u64,
+    latency: u64,
+    flow: Flow,
+}
+
+/// A function as the object file has it
+struct Code {
+    name: String,
+    address: u64,
+    instructions: Vec<Instruction>,
+}
+
+struct Block {
+    /// Address of the first instruction
+    address: u64,
+    latency: u64,
+    calls: Vec<(u64, Callee)>,
+    successors: Vec<usize>,
+}
+
+/// Worst case of a function, callees included
+#[derive(Clone, Default)]
+struct Summary {
+    cycles: u64,
+    loops: Vec<LoopCost>,
+    unbounded: BTreeSet<String>,
+    unknown: BTreeSet<String>,
+}
+
+/// Analyze `module` as `target_machine` would compile it, with the
+/// latencies of `options.cpu`
+pub unsafe fn analyze(
+    module: LLVMModuleRef,
+    target_machine: LLVMTargetMachineRef,
+    options: &WcetOptions,
+) -> Result<WcetReport, WcetError> {
+    LLVM_InitializeAllDisassemblers();
+    let triple = LLVMGetTargetMachineTriple(target_machine);
+    let default_cpu = LLVMGetTargetMachineCPU(target_machine);
+    let cpu = match &options.cpu {
+        Some(cpu) => cpu.clone(),
+        None => CStr::from_ptr(default_cpu).to_string_lossy().into_owned(),
+    };
+    LLVMDisposeMessage(default_cpu);
+
+    let result = machine_for_cpu(target_machine, triple, &cpu).and_then(|machine| {
+        let object = emit_object(module, machine);
+        if machine != target_machine {
+            LLVMDisposeTargetMachine(machine);
+        }
+        disassemble(&object?, triple, &cpu)
+    });
+    LLVMDisposeMessage(triple);
+    let codes = result?;
+
+    let annotations = &options.annotations;
+    let index: HashMap<&str, usize> = codes.iter().enumerate().map(|(i, code)| (code.name.as_str(), i)).collect();
+    let calls: Vec<Vec<usize>> = codes
+        .iter()
+        .map(|code| {
+            let mut callees = Vec::new();
+            for instruction in &code.instructions {
+                match &instruction.flow {
+                    Flow::Call(Callee::Function(name)) | Flow::TailCall(Callee::Function(name)) => {
+                        callees.extend(index.get(name.as_str()));
+                    }
+                    Flow::Call(Callee::Indirect) | Flow::TailCall(Callee::Indirect) => {
+                        let targets = annotations.indirect_targets.get(&code.name).into_iter().flatten();
+                        callees.extend(targets.filter_map(|target| index.get(target.as_str())));
+                    }
+                    _ => {}
+                }
+            }
+            callees
+        })
+        .collect();
+
+    // Components come out callees first, so each one's callees are done
+    let components = strongly_connected(&calls);
+    let mut component_of = vec![0; codes.len()];
+    for (c, members) in components.iter().enumerate() {
+        for &member in members {
+            component_of[member] = c;
+        }
+    }
+    let mut summaries: Vec<Option<Summary>> = vec![None; codes.len()];
+    for (c, members) in components.iter().enumerate() {
+        let recursive = members.len() > 1 || calls[members[0]].contains(&members[0]);
+        for &member in members {
+            let code = &codes[member];
+            let mut summary = Summary::default();
+            if recursive {
+                let names: Vec<&str> = members.iter().map(|&member| codes[member].name.as_str()).collect();
+                summary.unbounded.insert(format!("recursion through {}", names.join(" -> ")));
+            }
+
+            // A function of this component costs nothing more, as the
+            // recursion is already reported
+            let callee_cost = |name: &str, summary: &mut Summary| -> u64 {
+                match index.get(name) {
+                    Some(&callee) if component_of[callee] == c => 0,
+                    Some(&callee) => {
+                        let below = summaries[callee].as_ref().expect("callees come first");
+                        summary.unbounded.extend(below.unbounded.iter().cloned());
+                        summary.unknown.extend(below.unknown.iter().cloned());
+                        below.cycles
+                    }
+                    None => match annotations.external_costs.get(name) {
+                        Some(&cycles) => cycles,
+                        None => {
+                            summary.unknown.insert(name.to_string());
+                            0
+                        }
+                    },
+                }
+            };
+            let call_cost = |address: u64, callee: &Callee, summary: &mut Summary| -> u64 {
+                match callee {
+                    Callee::Function(name) => callee_cost(name, summary),
+                    Callee::Indirect => match annotations.indirect_targets.get(&code.name) {
+                        Some(targets) => targets.iter().map(|target| callee_cost(target, summary)).max().unwrap_or(0),
+                        None => {
+                            summary.unbounded.insert(format!(
+                                "indirect call at +{:#x} in {} (see --indirect)",
+                                address - code.address,
+                                code.name
+                            ));
+                            0
+                        }
+                    },
+                }
+            };
+            time_function(code, annotations, &mut summary, &call_cost);
+            summaries[member] = Some(summary);
+        }
+    }
+
+    let selected: Vec<usize> = if options.functions.is_empty() {
+        (0..codes.len()).collect()
+    } else {
+        options
+            .functions
+            .iter()
+            .map(|name| index.get(name.as_str()).copied().ok_or_else(|| WcetError::UnknownFunction(name.clone())))
+            .collect::<Result<_, _>>()?
+    };
+    let functions = selected
+        .into_iter()
+        .map(|function| {
+            let summary = summaries[function].clone().unwrap_or_default();
+            FunctionTime {
+                function: codes[function].name.clone(),
+                cycles: summary.cycles,
+                instructions: codes[function].instructions.len(),
+                loops: summary.loops,
+                unbounded: summary.unbounded.into_iter().collect(),
+                unknown: summary.unknown.into_iter().collect(),
+            }
+        })
+        .collect();
+    Ok(WcetReport { cpu, functions })
+}
+
+/// `target_machine` itself, or one like it for `cpu`
+unsafe fn machine_for_cpu(
+    target_machine: LLVMTargetMachineRef,
+    triple: *const c_char,
+    cpu: &str,
+) -> Result<LLVMTargetMachineRef, WcetError> {
+    let current = LLVMGetTargetMachineCPU(target_machine);
+    let same = CStr::from_ptr(current).to_bytes() == cpu.as_bytes();
+    LLVMDisposeMessage(current);
+    if same {
+        return Ok(target_machine);
+    }
+    let name = CString::new(cpu).map_err(|_| WcetError::UnknownCpu(cpu.to_string()))?;
+    let features = LLVMGetTargetMachineFeatureString(target_machine);
+    let machine = LLVMCreateTargetMachine(
+        LLVMGetTargetMachineTarget(target_machine),
+        triple,
+        name.as_ptr(),
+        features,
+        LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+        LLVMRelocMode::LLVMRelocPIC,
+        LLVMCodeModel::LLVMCodeModelDefault,
+    );
+    LLVMDisposeMessage(features);
+    if machine.is_null() {
+        return Err(WcetError::UnknownCpu(cpu.to_string()));
+    }
+    Ok(machine)
+}
+
+/// An object file of a copy of `module` whose branches all have static
+/// targets: switches become compare chains rather than jump tables
+unsafe fn emit_object(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) -> Result<Vec<u8>, WcetError> {
+    let copy = LLVMCloneModule(module);
+    let context = LLVMGetModuleContext(copy);
+    let mut function = LLVMGetFirstFunction(copy);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 {
+            LLVMAddAttributeAtIndex(
+                function,
+                LLVMAttributeFunctionIndex,
+                LLVMCreateStringAttribute(context, c"no-jump-tables".as_ptr(), 14, c"true".as_ptr(), 4),
+            );
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    let mut buffer = std::ptr::null_mut();
+    let mut error = std::ptr::null_mut();
+    let failed =
+        LLVMTargetMachineEmitToMemoryBuffer(target_machine, copy, LLVMCodeGenFileType::LLVMObjectFile, &mut error, &mut buffer);
+    LLVMDisposeModule(copy);
+    if failed != 0 {
+        let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+        LLVMDisposeMessage(error);
+        return Err(WcetError::CodeGeneration(message));
+    }
+    let bytes = std::slice::from_raw_parts(LLVMGetBufferStart(buffer) as *const u8, LLVMGetBufferSize(buffer)).to_vec();
+    LLVMDisposeMemoryBuffer(buffer);
+    Ok(bytes)
+}
+
+/// The disassembler's symbol lookup, which sees every branch target; the
+/// last one is kept in the `Cell` behind `info`
+extern "C" fn record_branch(
+    info: *mut c_void,
+    value: u64,
+    reference_type: *mut u64,
+    _address: u64,
+    reference_name: *mut *const c_char,
+) -> *const c_char {
+    unsafe {
+        if *reference_type == LLVMDisassembler_ReferenceType_In_Branch {
+            (*(info as *const Cell<Option<u64>>)).set(Some(value));
+        }
+        *reference_type = LLVMDisassembler_ReferenceType_InOut_None;
+        *reference_name = std::ptr::null();
+    }
+    std::ptr::null()
+}
+
+/// Every function of `object`, decoded
+unsafe fn disassemble(object: &[u8], triple: *const c_char, cpu: &str) -> Result<Vec<Code>, WcetError> {
+    let file = object::File::parse(object).map_err(|e| WcetError::Object(e.to_string()))?;
+    let isa = match file.architecture() {
+        object::Architecture::X86_64 | object::Architecture::I386 => Isa::X86,
+        object::Architecture::Aarch64 => Isa::AArch64,
+        object::Architecture::Arm => Isa::Arm,
+        other => return Err(WcetError::Unsupported(format!("{:?}", other))),
+    };
+
+    let target = Box::new(Cell::new(None::<u64>));
+    let cpu_name = CString::new(cpu).map_err(|_| WcetError::UnknownCpu(cpu.to_string()))?;
+    let disassembler = LLVMCreateDisasmCPU(
+        triple,
+        cpu_name.as_ptr(),
+        &*target as *const Cell<Option<u64>> as *mut c_void,
+        0,
+        None,
+        Some(record_branch),
+    );
+    if disassembler.is_null() {
+        return Err(WcetError::Unsupported(CStr::from_ptr(triple).to_string_lossy().into_owned()));
+    }
+    LLVMSetDisasmOptions(disassembler, LLVMDisassembler_Option_PrintLatency);
+
+    // Functions, and the names relocations give call instructions; an
+    // indirect call through a variable has one too, for the variable
+    let mut functions: Vec<(String, SectionIndex, u64, u64)> = file
+        .symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.is_definition() && symbol.size() > 0)
+        .filter_map(|symbol| {
+            // Thumb functions' addresses have the low bit set
+            let address = if isa == Isa::Arm { symbol.address() & !1 } else { symbol.address() };
+            Some((symbol.name().ok()?.to_string(), symbol.section_index()?, address, symbol.size()))
+        })
+        .collect();
+    functions.sort_by_key(|&(_, section, address, _)| (section.0, address));
+    // ARM mapping symbols: `$d` starts data, e.g. a literal pool, and
+    // `$a`, `$t` or `$x` code again
+    let mut mapping: Vec<(SectionIndex, u64, bool)> = file
+        .symbols()
+        .filter_map(|symbol| {
+            let name = symbol.name().ok()?;
+            let kind = name.strip_prefix('$')?.split('.').next()?;
+            matches!(kind, "a" | "d" | "t" | "x").then_some((symbol.section_index()?, symbol.address(), kind == "d"))
+        })
+        .collect();
+    mapping.sort_by_key(|&(section, address, _)| (section.0, address));
+    let data_until = |section: SectionIndex, address: u64| {
+        let here = mapping.iter().rposition(|&(s, start, _)| s == section && start <= address)?;
+        if !mapping[here].2 {
+            return None;
+        }
+        let next = mapping[here + 1..].iter().find(|&&(s, _, _)| s == section);
+        Some(next.map_or(u64::MAX, |&(_, start, _)| start))
+    };
+    let mut relocations: HashMap<SectionIndex, Vec<(u64, String)>> = HashMap::new();
+    for section in file.sections() {
+        for (offset, relocation) in section.relocations() {
+            if let RelocationTarget::Symbol(index) = relocation.target() {
+                let symbol = file.symbol_by_index(index).map_err(|e| WcetError::Object(e.to_string()))?;
+                match symbol.name() {
+                    Ok(name) if !name.is_empty() && !matches!(symbol.kind(), SymbolKind::Data | SymbolKind::Tls | SymbolKind::Section | SymbolKind::File) => {
+                        relocations.entry(section.index()).or_default().push((offset, name.to_string()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    let function_at = |section: SectionIndex, address: u64| {
+        functions
+            .iter()
+            .find(|&&(_, s, start, size)| s == section && (start..start + size).contains(&address))
+            .map(|(name, ..)| name.clone())
+    };
+
+    let mut codes = Vec::new();
+    let mut text = [0 as c_char; 256];
+    for (name, section_index, start, size) in &functions {
+        let section = file.section_by_index(*section_index).map_err(|e| WcetError::Object(e.to_string()))?;
+        let data = section.data().map_err(|e| WcetError::Object(e.to_string()))?;
+        let end = start + size;
+        let relocated = relocations.get(section_index).map(Vec::as_slice).unwrap_or_default();
+        let mut instructions = Vec::new();
+        let mut address = *start;
+        while address < end {
+            if let Some(code) = data_until(*section_index, address) {
+                address = code.min(end);
+                continue;
+            }
+            target.set(None);
+            let offset = (address - section.address()) as usize;
+            let length = LLVMDisasmInstruction(
+                disassembler,
+                data.as_ptr().add(offset) as *mut u8,
+                end - address,
+                address,
+                text.as_mut_ptr(),
+                text.len(),
+            ) as u64;
+            if length == 0 {
+                LLVMDisasmDispose(disassembler);
+                return Err(WcetError::Undecodable { function: name.clone(), offset: address - start });
+            }
+            let line = CStr::from_ptr(text.as_ptr()).to_string_lossy();
+            let (mnemonic, operands) = split_instruction(&line);
+            let symbol = relocated
+                .iter()
+                .find(|(offset, _)| (address..address + length).contains(offset))
+                .map(|(_, name)| name.clone());
+            // A branch out of the function is a tail call
+            let callee = |target: Option<u64>| match (&symbol, target) {
+                (Some(symbol), _) => Some(Callee::Function(symbol.clone())),
+                (None, Some(target)) if !(*start..end).contains(&target) => {
+                    Some(function_at(*section_index, target).map_or(Callee::Indirect, Callee::Function))
+                }
+                _ => None,
+            };
+            let flow = match (isa.classify(&mnemonic, operands), target.get()) {
+                (Kind::Jump, target) => match (callee(target), target) {
+                    (Some(callee), _) => Flow::TailCall(callee),
+                    (None, Some(target)) => Flow::Jump(target),
+                    (None, None) => Flow::IndirectJump,
+                },
+                (Kind::Branch, target) => match (callee(target), target) {
+                    // Taken or not, counting the callee covers both
+                    (Some(callee), _) => Flow::Call(callee),
+                    (None, Some(target)) => Flow::Branch(target),
+                    (None, None) => Flow::IndirectJump,
+                },
+                (Kind::Call, target) => Flow::Call(symbol.map(Callee::Function).unwrap_or_else(|| {
+                    target.and_then(|target| function_at(*section_index, target)).map_or(Callee::Indirect, Callee::Function)
+                })),
+                (Kind::IndirectJump, _) => match symbol {
+                    Some(symbol) => Flow::TailCall(Callee::Function(symbol)),
+                    None => Flow::IndirectJump,
+                },
+                (Kind::IndirectCall, _) => Flow::Call(symbol.map_or(Callee::Indirect, Callee::Function)),
+                (Kind::Return, _) => Flow::Return,
+                (Kind::Stop, _) => Flow::Stop,
+                (Kind::Other, _) => Flow::Next,
+            };
+            instructions.push(Instruction { address, size: length, latency: latency(&line), flow });
+            address += length;
+        }
+        codes.push(Code { name: name.clone(), address: *start, instructions });
+    }
+    LLVMDisasmDispose(disassembler);
+    Ok(codes)
+}
+
+/// Mnemonic and operands of a line of disassembly, prefixes dropped
+fn split_instruction(line: &str) -> (String, &str) {
+    const PREFIXES: [&str; 9] = ["lock", "rep", "repe", "repz", "repne", "repnz", "notrack", "bnd", "data16"];
+    let mut rest = line.trim_start();
+    loop {
+        let (word, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if PREFIXES.contains(&word) && !operands.trim().is_empty() {
+            rest = operands.trim_start();
+            continue;
+        }
+        return (word.to_ascii_lowercase(), operands.trim());
+    }
+}
+
+/// The latency the disassembler annotated, which it leaves off below 2
+fn latency(line: &str) -> u64 {
+    line.split_once("Latency: ")
+        .and_then(|(_, rest)| rest.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok())
+        .unwrap_or(1)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Isa {
+    X86,
+    AArch64,
+    Arm,
+}
+
+enum Kind {
+    Other,
+    Jump,
+    Branch,
+    Call,
+    IndirectJump,
+    IndirectCall,
+    Return,
+    Stop,
+}
+
+impl Isa {
+    fn classify(self, mnemonic: &str, operands: &str) -> Kind {
+        match self {
+            Isa::X86 => {
+                let indirect = operands.starts_with('*');
+                match mnemonic {
+                    m if m.starts_with("ret") || m.starts_with("iret") || m.starts_with("sysret") => Kind::Return,
+                    "ud2" | "hlt" => Kind::Stop,
+                    m if m.starts_with("jmp") && indirect => Kind::IndirectJump,
+                    m if m.starts_with("jmp") => Kind::Jump,
+                    m if m.starts_with('j') || m.starts_with("loop") => Kind::Branch,
+                    m if m.starts_with("call") && indirect => Kind::IndirectCall,
+                    m if m.starts_with("call") => Kind::Call,
+                    _ => Kind::Other,
+                }
+            }
+            Isa::AArch64 => match mnemonic {
+                "ret" | "retaa" | "retab" | "eret" => Kind::Return,
+                "brk" | "hlt" | "udf" => Kind::Stop,
+                "b" => Kind::Jump,
+                "br" | "braa" | "brab" | "braaz" | "brabz" => Kind::IndirectJump,
+                "bl" => Kind::Call,
+                "blr" | "blraa" | "blrab" | "blraaz" | "blrabz" => Kind::IndirectCall,
+                "cbz" | "cbnz" | "tbz" | "tbnz" => Kind::Branch,
+                m if m.starts_with("b.") => Kind::Branch,
+                _ => Kind::Other,
+            },
+            // Predicated returns in IT blocks fall through, which can only
+            // lengthen the path
+            Isa::Arm => {
+                let mnemonic = mnemonic.trim_end_matches(".w").trim_end_matches(".n");
+                let writes_pc = operands.split(|c: char| !c.is_ascii_alphanumeric()).any(|operand| operand == "pc");
+                match mnemonic {
+                    "bx" if operands == "lr" => Kind::Return,
+                    "bx" => Kind::IndirectJump,
+                    "udf" | "bkpt" => Kind::Stop,
+                    "b" => Kind::Jump,
+                    "bl" => Kind::Call,
+                    "blx" => Kind::IndirectCall,
+                    "cbz" | "cbnz" => Kind::Branch,
+                    "pop" | "ldm" | "ldmia" | "ldmfd" if writes_pc => Kind::Return,
+                    m if m.len() == 3 && m.starts_with('b') && CONDITIONS.contains(&&m[1..]) => Kind::Branch,
+                    _ => Kind::Other,
+                }
+            }
+        }
+    }
+}
+
+const CONDITIONS: [&str; 16] =
+    ["eq", "ne", "cs", "hs", "cc", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le"];
+
+/// Cost `code` into `summary`, with `call_cost` giving each call's
+fn time_function(
+    code: &Code,
+    annotations: &WcetAnnotations,
+    summary: &mut Summary,
+    call_cost: &dyn Fn(u64, &Callee, &mut Summary) -> u64,
+) {
+    let blocks = basic_blocks(code, summary);
+    let count = blocks.len();
+    let mut cost: Vec<u64> = blocks
+        .iter()
+        .map(|block| {
+            let calls = block.calls.iter().fold(0u64, |sum, (address, callee)| {
+                sum.saturating_add(call_cost(*address, callee, summary))
+            });
+            block.latency.saturating_add(calls)
+        })
+        .collect();
+
+    // Reverse postorder from the entry; blocks it can't reach stay at MAX
+    let mut rpo = vec![usize::MAX; count];
+    let mut order = Vec::with_capacity(count);
+    let mut visited = vec![false; count];
+    let mut work = vec![(0, 0)];
+    visited[0] = true;
+    while let Some(&(block, edge)) = work.last() {
+        if let Some(&successor) = blocks[block].successors.get(edge) {
+            work.last_mut().unwrap().1 += 1;
+            if !visited[successor] {
+                visited[successor] = true;
+                work.push((successor, 0));
+            }
+            continue;
+        }
+        work.pop();
+        order.push(block);
+    }
+    order.reverse();
+    for (position, &block) in order.iter().enumerate() {
+        rpo[block] = position;
+    }
+
+    let mut predecessors = vec![Vec::new(); count];
+    for (block, data) in blocks.iter().enumerate() {
+        if rpo[block] != usize::MAX {
+            for &successor in &data.successors {
+                predecessors[successor].push(block);
+            }
+        }
+    }
+    let dominators = immediate_dominators(&order, &rpo, &predecessors);
+    let dominates = |a: usize, mut b: usize| loop {
+        if a == b {
+            return true;
+        }
+        if b == dominators[b] {
+            return false;
+        }
+        b = dominators[b];
+    };
+
+    // Natural loops, one per header; an edge back up the order to a block
+    // that doesn't dominate its source enters a cycle other than at its
+    // header, and is left out
+    let mut latches: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &block in &order {
+        for &successor in &blocks[block].successors {
+            if rpo[successor] <= rpo[block] {
+                if dominates(successor, block) {
+                    latches.entry(successor).or_default().push(block);
+                } else {
+                    summary.unbounded.insert(format!(
+                        "irreducible loop at +{:#x} in {}",
+                        blocks[successor].address - code.address,
+                        code.name
+                    ));
+                }
+            }
+        }
+    }
+    let mut headers: Vec<usize> = latches.keys().copied().collect();
+    headers.sort_by_key(|&header| blocks[header].address);
+    let mut loops: Vec<(usize, usize, HashSet<usize>)> = headers
+        .iter()
+        .enumerate()
+        .map(|(number, &header)| {
+            let mut body = HashSet::from([header]);
+            let mut work = latches[&header].clone();
+            while let Some(block) = work.pop() {
+                if body.insert(block) {
+                    work.extend(predecessors[block].iter().copied());
+                }
+            }
+            (number + 1, header, body)
+        })
+        .collect();
+
+    // Innermost first: each loop collapses into its header, which then
+    // costs the whole loop and leads to its exits
+    loops.sort_by_key(|(_, _, body)| body.len());
+    let mut representative: Vec<usize> = (0..count).collect();
+    let find = |representative: &[usize], mut block: usize| {
+        while representative[block] != block {
+            block = representative[block];
+        }
+        block
+    };
+    let mut successors: Vec<Vec<usize>> = blocks.iter().map(|block| block.successors.clone()).collect();
+    let mut costs = Vec::new();
+    for (number, header, body) in &loops {
+        let mut members: Vec<usize> = body.iter().copied().filter(|&block| representative[block] == block).collect();
+        members.sort_by_key(|&block| std::cmp::Reverse(rpo[block]));
+        let mut longest = HashMap::new();
+        for &block in &members {
+            let further = successors[block]
+                .iter()
+                .map(|&successor| find(&representative, successor))
+                .filter(|&next| rpo[next] > rpo[block] && body.contains(&next))
+                .filter_map(|next| longest.get(&next).copied())
+                .max()
+                .unwrap_or(0);
+            longest.insert(block, cost[block].saturating_add(further));
+        }
+        let iteration = longest[header];
+        let bound = annotations.loop_bound(&code.name, *number);
+        if bound.is_none() {
+            summary.unbounded.insert(format!(
+                "loop {} at +{:#x} in {} has no bound (see --loop-bound)",
+                number,
+                blocks[*header].address - code.address,
+                code.name
+            ));
+        }
+        costs.push(LoopCost {
+            number: *number,
+            offset: blocks[*header].address - code.address,
+            bound,
+            cycles_per_iteration: iteration,
+        });
+
+        let mut exits = Vec::new();
+        for &block in body {
+            for &successor in &successors[block] {
+                let next = find(&representative, successor);
+                if !body.contains(&next) && !exits.contains(&next) {
+                    exits.push(next);
+                }
+            }
+        }
+        for &block in body {
+            representative[block] = *header;
+        }
+        representative[*header] = *header;
+        successors[*header] = exits;
+        cost[*header] = iteration.saturating_mul(bound.unwrap_or(1).max(1));
+    }
+    costs.sort_by_key(|cost| cost.number);
+    summary.loops = costs;
+
+    // Longest path from the entry over the collapsed, acyclic graph
+    let mut longest = vec![0u64; count];
+    for &block in order.iter().rev() {
+        if representative[block] != block {
+            continue;
+        }
+        let further = successors[block]
+            .iter()
+            .map(|&successor| find(&representative, successor))
+            .filter(|&next| rpo[next] > rpo[block])
+            .map(|next| longest[next])
+            .max()
+            .unwrap_or(0);
+        longest[block] = cost[block].saturating_add(further);
+    }
+    summary.cycles = longest[find(&representative, 0)];
+}
+
+/// Split `code` at branches and their targets; calls stay inside blocks
+fn basic_blocks(code: &Code, summary: &mut Summary) -> Vec<Block> {
+    let instructions = &code.instructions;
+    let mut leaders = BTreeSet::from([code.address]);
+    for instruction in instructions {
+        match instruction.flow {
+            Flow::Next | Flow::Call(_) => {}
+            Flow::Jump(target) | Flow::Branch(target) => {
+                leaders.insert(target);
+                leaders.insert(instruction.address + instruction.size);
+            }
+            _ => {
+                leaders.insert(instruction.address + instruction.size);
+            }
+        }
+    }
+    let starts: Vec<usize> = instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instruction)| leaders.contains(&instruction.address))
+        .map(|(i, _)| i)
+        .collect();
+    let block_at: HashMap<u64, usize> =
+        starts.iter().enumerate().map(|(block, &i)| (instructions[i].address, block)).collect();
+
+    let mut blocks = Vec::with_capacity(starts.len());
+    for (block, &first) in starts.iter().enumerate() {
+        let last = starts.get(block + 1).map_or(instructions.len(), |&next| next) - 1;
+        let mut data = Block { address: instructions[first].address, latency: 0, calls: Vec::new(), successors: Vec::new() };
+        for instruction in &instructions[first..=last] {
+            data.latency = data.latency.saturating_add(instruction.latency);
+            if let Flow::Call(callee) | Flow::TailCall(callee) = &instruction.flow {
+                data.calls.push((instruction.address, callee.clone()));
+            }
+        }
+        let end = &instructions[last];
+        let next = block_at.get(&(end.address + end.size)).copied();
+        // A jump into the middle of an instruction isn't a leader; the path ends
+        let target = |address: u64| block_at.get(&address).copied();
+        data.successors = match end.flow {
+            Flow::Next | Flow::Call(_) => next.into_iter().collect(),
+            Flow::Jump(address) => target(address).into_iter().collect(),
+            Flow::Branch(address) => target(address).into_iter().chain(next).collect(),
+            Flow::IndirectJump => {
+                summary.unbounded.insert(format!("indirect jump at +{:#x} in {}", end.address - code.address, code.name));
+                Vec::new()
+            }
+            Flow::TailCall(_) | Flow::Return | Flow::Stop => Vec::new(),
+        };
+        data.successors.dedup();
+        blocks.push(data);
+    }
+    blocks
+}
+
+/// Cooper, Harvey and Kennedy's iterative algorithm over the reverse
+/// postorder; the entry and unreachable blocks are their own dominators
+pub(crate) fn immediate_dominators(order: &[usize], rpo: &[usize], predecessors: &[Vec<usize>]) -> Vec<usize> {
+    const UNDEFINED: usize = usize::MAX;
+    let mut dominators = vec![UNDEFINED; rpo.len()];
+    let Some(&entry) = order.first() else {
+        return (0..rpo.len()).collect();
+    };
+    dominators[entry] = entry;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &order[1..] {
+            let mut new = UNDEFINED;
+            for &predecessor in &predecessors[block] {
+                if dominators[predecessor] == UNDEFINED {
+                    continue;
+                }
+                new = if new == UNDEFINED {
+                    predecessor
+                } else {
+                    let (mut a, mut b) = (predecessor, new);
+                    while a != b {
+                        while rpo[a] > rpo[b] {
+                            a = dominators[a];
+                        }
+                        while rpo[b] > rpo[a] {
+                            b = dominators[b];
+                        }
+                    }
+                    a
+                };
+            }
+            if new != UNDEFINED && dominators[block] != new {
+                dominators[block] = new;
+                changed = true;
+            }
+        }
+    }
+    dominators.iter().enumerate().map(|(block, &dominator)| if dominator == UNDEFINED { block } else { dominator }).collect()
+}
+
+#[derive(Debug)]
+pub enum WcetError {
+    CodeGeneration(String),
+    /// The object file the code generator wrote couldn't be read
+    Object(String),
+    UnknownCpu(String),
+    /// No disassembler, or no control flow analysis, for the target
+    Unsupported(String),
+    Undecodable { function: String, offset: u64 },
+    UnknownFunction(String),
+    /// An annotation that isn't `name=value`
+    Annotation(String),
+}
+
+impl fmt::Display for WcetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WcetError::CodeGeneration(message) => write!(f, "compiling for analysis: {}", message),
+            WcetError::Object(message) => write!(f, "reading the compiled object: {}", message),
+            WcetError::UnknownCpu(cpu) => write!(f, "no target machine for CPU '{}'", cpu),
+            WcetError::Unsupported(target) => write!(f, "execution time analysis doesn't support {}", target),
+            WcetError::Undecodable { function, offset } => {
+                write!(f, "can't decode the instruction at +{:#x} in {}", offset, function)
+            }
+            WcetError::UnknownFunction(name) => write!(f, "function '{}' is not defined", name),
+            WcetError::Annotation(spec) => write!(f, "invalid annotation '{}': expected name=value", spec),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn control_loop_budget(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) -> Result<bool, WcetError> {
+    let mut options = WcetOptions {
+        functions: vec!["control_step".to_string()],
+        cpu: Some("cortex-m4".to_string()),
+        ..Default::default()
+    };
+    options.annotations.add_loop_bound("filter=16")?;
+    options.annotations.add_loop_bound("control_step#2=4")?;
+    options.annotations.add_indirect("control_step=pid_update,bang_bang")?;
+    options.annotations.add_external_cost("__aeabi_ldivmod=90")?;
+
+    let report = analyze(module, target_machine, &options)?;
+    print!("{}", report);
+    // latencies of cortex-m4
+    // control_step: 2310 cycles (184 instructions)
+    //   loop 1 at +0x1c: 8 x 41 cycles
+    //   loop 2 at +0x64: 4 x 212 cycles
+    // A 168 MHz core gives a 10 kHz loop 16800 cycles
+    Ok(report.exceeding(16_800).next().is_none())
+}
+*/
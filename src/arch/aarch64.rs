@@ -12,8 +12,13 @@ use crate::arch::{
     Architecture, ArchitectureSupport, AssemblyParser, ABIHandler,
     InstructionEncoder, FeatureDetector, AssemblyParseError, EncodingError,
     Register, RegisterClass, Operand, MemoryOperand, Instruction,
-    AssemblyBlock, AssemblyAST, CallingConvention, StructLayout, CPUFeatures,
+    AssemblyBlock, AssemblyAST, CallingConvention, StructLayout, CPUFeatures, host_cache_sizes,
 };
+use crate::arch::assembler::{self, Encoded, Fixup, FixupEncoder, FixupKind};
+use crate::arch::intrinsics::{
+    self, ElementType, FenceOrdering, Intrinsic, IntrinsicCall, IntrinsicLowering, Lowering, LoweringError,
+};
+use crate::arch::schedule::{self, Effects, Location, OpClass, SchedModel, SchedulingHazards};
 
 /// Create AArch64 architecture support
 pub fn create_support() -> ArchitectureSupport {
@@ -23,6 +28,8 @@ pub fn create_support() -> ArchitectureSupport {
         abi_handler: Box::new(AArch64ABIHandler::new()),
         instruction_encoder: Box::new(AArch64InstructionEncoder::new()),
         feature_detector: Box::new(AArch64FeatureDetector::new()),
+        intrinsic_lowering: Box::new(AArch64IntrinsicLowering),
+        scheduling_hazards: Box::new(AArch64Hazards),
     }
 }
 
@@ -145,6 +152,28 @@ impl AArch64AssemblyParser {
             });
         }
         
+        // SVE vectors, at the architecture's shortest length
+        for i in 0..32 {
+            let name = format!("z{}", i);
+            self.registers.insert(name.clone(), Register {
+                name,
+                size: 128,
+                number: i,
+                class: RegisterClass::Vector,
+            });
+        }
+        
+        // SVE predicates, one bit per byte of a vector
+        for i in 0..16 {
+            let name = format!("p{}", i);
+            self.registers.insert(name.clone(), Register {
+                name,
+                size: 16,
+                number: i,
+                class: RegisterClass::Predicate,
+            });
+        }
+        
         // System registers
         let system_regs = [
             "nzcv", "fpsr", "fpcr", "spsr_el1", "elr_el1", "sp_el0",
@@ -982,6 +1011,154 @@ impl AArch64InstructionEncoder {
         imm26 // Offset
     }
     
+    /// Encode `b`, `bl`, `b.<cond>`, `cbz`, `cbnz`, `tbz` or `tbnz` to a
+    /// label (left as a fixup) or an immediate byte offset from the branch.
+    /// With `veneer`, a conditional branch becomes the inverted condition
+    /// skipping over a `b`, for a target beyond its ±1 MiB (±32 KiB for
+    /// `tbz`). `None` if `instruction` isn't one of these branches.
+    fn encode_branch_instruction(
+        &self,
+        instruction: &Instruction,
+        veneer: bool
+    ) -> Result<Option<Encoded>, EncodingError> {
+        let mnemonic = instruction.mnemonic.to_lowercase();
+        let (word, kind, target) = match (mnemonic.as_str(), instruction.operands.as_slice()) {
+            ("b", [target]) => (0x1400_0000, FixupKind::Branch26, target),
+            ("bl", [target]) => (0x9400_0000, FixupKind::Branch26, target),
+            (conditional, [target]) if conditional.starts_with("b.") => {
+                let cond = &conditional[2..];
+                let code = self.get_condition_code(cond);
+                if code == 0b1110 && cond != "al" {
+                    return Err(EncodingError::InvalidInstruction(format!("unknown condition '{}'", cond)));
+                }
+                (self.encode_conditional_branch(code, 0), FixupKind::Branch19, target)
+            }
+            ("cbz" | "cbnz", [Operand::Register(rt), target]) => {
+                let word = ((rt.size == 64) as u32) << 31
+                    | 0x3400_0000
+                    | ((mnemonic == "cbnz") as u32) << 24
+                    | self.get_register_code(rt);
+                (word, FixupKind::Branch19, target)
+            }
+            ("tbz" | "tbnz", [Operand::Register(rt), Operand::Immediate(bit), target]) => {
+                if !(0..rt.size as i64).contains(bit) {
+                    return Err(EncodingError::OperandOutOfRange(format!("bit {} of {}", bit, rt.name)));
+                }
+                let word = ((*bit as u32) >> 5) << 31
+                    | 0x3600_0000
+                    | ((mnemonic == "tbnz") as u32) << 24
+                    | ((*bit as u32) & 0x1F) << 19
+                    | self.get_register_code(rt);
+                (word, FixupKind::Branch14, target)
+            }
+            _ => return Ok(None),
+        };
+        
+        // b.al can't be inverted, but a plain b does the same
+        let always = kind == FixupKind::Branch19 && word & 0xFF00_001F == 0x5400_000E;
+        let (mut bytes, offset, kind) = match (veneer, kind) {
+            (false, _) => (word.to_le_bytes().to_vec(), 0, kind),
+            (true, FixupKind::Branch26) => return Ok(None),
+            (true, _) if always => (0x1400_0000u32.to_le_bytes().to_vec(), 0, FixupKind::Branch26),
+            (true, _) => {
+                // b.<!cond> +8 (cbz and cbnz, tbz and tbnz differ in bit 24)
+                let inverted = if word >> 24 == 0x54 { word ^ 1 } else { word ^ 1 << 24 };
+                let skip = kind.insert(inverted, 8).expect("8 bytes is in range");
+                let mut bytes = skip.to_le_bytes().to_vec();
+                bytes.extend_from_slice(&0x1400_0000u32.to_le_bytes());
+                (bytes, 4, FixupKind::Branch26)
+            }
+        };
+        
+        let fixup = match target {
+            Operand::Label(label) => Some(Fixup { offset, label: label.clone(), kind }),
+            Operand::Immediate(displacement) => {
+                let word = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+                let patched = kind.insert(word, *displacement - offset as i64).ok_or_else(|| {
+                    EncodingError::OperandOutOfRange(format!("branch offset {}", displacement))
+                })?;
+                bytes[offset..offset + 4].copy_from_slice(&patched.to_le_bytes());
+                None
+            }
+            _ => return Err(EncodingError::InvalidOperand("branch target must be a label or an offset".to_string())),
+        };
+        Ok(Some(Encoded { bytes, fixup }))
+    }
+    
+    /// Element size field for the suffix of an SVE register: 0 for `z0.b`
+    /// to 3 for `p1.d`
+    fn sve_element_size(&self, register: &Register) -> Result<u32, EncodingError> {
+        let suffix = register.name.split('/').next().and_then(|name| name.rsplit_once('.')).map(|(_, suffix)| suffix);
+        match suffix {
+            Some("b") => Ok(0),
+            Some("h") => Ok(1),
+            Some("s") => Ok(2),
+            Some("d") => Ok(3),
+            _ => Err(EncodingError::InvalidOperand(format!("'{}' needs an element size, as in z0.s", register.name))),
+        }
+    }
+    
+    /// Encode an SVE contiguous `ld1b`-`ld1d` or `st1b`-`st1d`: `{ zt.s },
+    /// pg/z, [xn]` or `[xn, xm, lsl #2]`, with elements the size the
+    /// mnemonic loads. Loads take a zeroing predicate, stores a plain one.
+    fn encode_sve_memory(&self, instruction: &Instruction) -> Result<u32, EncodingError> {
+        let mnemonic = instruction.mnemonic.as_str();
+        let load = mnemonic.starts_with("ld");
+        let msize = match &mnemonic[3..] {
+            "b" => 0,
+            "h" => 1,
+            "w" => 2,
+            _ => 3,
+        };
+        let (zt, pg, memory) = match instruction.operands.as_slice() {
+            [Operand::Register(zt), Operand::Register(pg), Operand::Memory(memory)]
+                if zt.class == RegisterClass::Vector && pg.class == RegisterClass::Predicate =>
+            {
+                (zt, pg, memory)
+            }
+            _ => {
+                return Err(EncodingError::InvalidOperand(format!(
+                    "{} takes a vector, a predicate and an address",
+                    mnemonic.to_uppercase()
+                )))
+            }
+        };
+        if self.sve_element_size(zt)? != msize {
+            return Err(EncodingError::InvalidOperand(format!("'{}' doesn't hold the elements {} moves", zt.name, mnemonic)));
+        }
+        // The governing predicate field is 3 bits
+        if pg.number >= 8 || pg.name.ends_with("/z") != load {
+            let form = if load { "p0/z-p7/z" } else { "p0-p7" };
+            return Err(EncodingError::InvalidOperand(format!("{} is governed by {}, not '{}'", mnemonic, form, pg.name)));
+        }
+        let base = memory.base.as_ref().ok_or_else(|| {
+            EncodingError::InvalidOperand("Memory operand requires a base register".to_string())
+        })?;
+        if memory.displacement != 0 {
+            return Err(EncodingError::InvalidOperand(format!(
+                "{} offsets are in vector lengths; add {} to the base instead",
+                mnemonic, memory.displacement
+            )));
+        }
+        // dtype and msz:size both repeat the element size
+        let size = (msize << 2 | msize) << 21;
+        let word = match &memory.index {
+            None if load => 0xA400_A000 | size,
+            None => 0xE400_E000 | size,
+            Some(index) => {
+                if memory.scale as u32 != 1 << msize || index.number == 31 {
+                    return Err(EncodingError::InvalidOperand(format!(
+                        "{} indexes by a register other than xzr, shifted by the element size",
+                        mnemonic
+                    )));
+                }
+                let opcode = if load { 0xA400_4000 } else { 0xE400_4000 };
+                opcode | size | self.get_register_code(index) << 16
+            }
+        };
+        Ok(word | (pg.number as u32) << 10 | self.get_register_code(base) << 5 | self.get_register_code(zt))
+    }
+    
     /// Encode a conditional branch instruction
     fn encode_conditional_branch(
         &self,
@@ -997,6 +1174,19 @@ impl AArch64InstructionEncoder {
     }
 }
 
+/// Level 1 is the veneer for a conditional branch that can't reach; `b`
+/// and `bl` have no longer form
+impl FixupEncoder for AArch64InstructionEncoder {
+    fn encode_at(&self, instruction: &Instruction, level: usize) -> Result<Option<Encoded>, EncodingError> {
+        match (level, self.encode_branch_instruction(instruction, level == 1)?) {
+            (0, Some(branch)) => Ok(Some(branch)),
+            (0, None) => Ok(Some(Encoded { bytes: self.encode_instruction(instruction)?, fixup: None })),
+            (1, veneer) => Ok(veneer),
+            _ => Ok(None),
+        }
+    }
+}
+
 impl InstructionEncoder for AArch64InstructionEncoder {
     fn encode_instruction(&self, instruction: &Instruction) -> Result<Vec<u8>, EncodingError> {
         // This is a simplified encoder that handles only basic instructions
@@ -1005,6 +1195,16 @@ impl InstructionEncoder for AArch64InstructionEncoder {
         let mut encoded = Vec::new();
         let mut ins_word: u32 = 0;
         
+        // Branches, which may leave a label to resolve
+        if let Some(branch) = self.encode_branch_instruction(instruction, false)? {
+            if let Some(fixup) = branch.fixup {
+                return Err(EncodingError::InvalidOperand(
+                    format!("branch to label '{}' needs encode_asm_block or a fixup", fixup.label)
+                ));
+            }
+            return Ok(branch.bytes);
+        }
+        
         match instruction.mnemonic.as_str() {
             "mov" => {
                 if instruction.operands.len() != 2 {
@@ -1208,89 +1408,37 @@ impl InstructionEncoder for AArch64InstructionEncoder {
                     }
                 }
             },
-            "b" => {
-                if instruction.operands.len() != 1 {
-                    return Err(EncodingError::InvalidInstruction(
-                        "B requires 1 operand".to_string()
-                    ));
-                }
-                
-                match &instruction.operands[0] {
-                    Operand::Label(_) => {
-                        // B label
-                        // For a real encoder, we'd compute the actual branch offset
-                        // This is simplified to always branch to PC+8
-                        let offset = 8;
-                        
-                        ins_word = self.encode_branch(
-                            0, // Unconditional branch
-                            offset
-                        );
-                    },
-                    _ => {
-                        return Err(EncodingError::InvalidOperand(
-                            "Branch target must be a label".to_string()
-                        ));
-                    }
+            // SVE predicates and predicated loads and stores
+            "whilelo" => match instruction.operands.as_slice() {
+                [Operand::Register(pd), Operand::Register(rn), Operand::Register(rm)] if pd.class == RegisterClass::Predicate => {
+                    // U and lt set, eq clear: unsigned lower
+                    ins_word = 0x2520_0C00
+                        | self.sve_element_size(pd)? << 22
+                        | self.get_register_code(rm) << 16
+                        | ((rn.size == 64) as u32) << 12
+                        | self.get_register_code(rn) << 5
+                        | pd.number as u32;
                 }
-            },
-            "bl" => {
-                if instruction.operands.len() != 1 {
-                    return Err(EncodingError::InvalidInstruction(
-                        "BL requires 1 operand".to_string()
+                _ => {
+                    return Err(EncodingError::InvalidOperand(
+                        "WHILELO takes a predicate and two general registers".to_string()
                     ));
                 }
-                
-                match &instruction.operands[0] {
-                    Operand::Label(_) => {
-                        // BL label
-                        // For a real encoder, we'd compute the actual branch offset
-                        // This is simplified to always branch to PC+8
-                        let offset = 8;
-                        
-                        ins_word = self.encode_branch(
-                            1, // Branch with link
-                            offset
-                        );
-                    },
-                    _ => {
-                        return Err(EncodingError::InvalidOperand(
-                            "Branch target must be a label".to_string()
-                        ));
-                    }
-                }
             },
-            "b.cond" => {
-                // Handle conditional branches (b.eq, b.ne, etc.)
-                if instruction.operands.len() != 1 {
-                    return Err(EncodingError::InvalidInstruction(
-                        "Conditional branch requires 1 operand".to_string()
-                    ));
+            "ptrue" => match instruction.operands.as_slice() {
+                // Every lane: the pattern ALL
+                [Operand::Register(pd)] if pd.class == RegisterClass::Predicate => {
+                    ins_word = 0x2518_E3E0 | self.sve_element_size(pd)? << 22 | pd.number as u32;
                 }
-                
-                // Extract condition from mnemonic (e.g. "b.eq" -> "eq")
-                let cond_str = &instruction.mnemonic[2..];
-                let cond = self.get_condition_code(cond_str);
-                
-                match &instruction.operands[0] {
-                    Operand::Label(_) => {
-                        // B.cond label
-                        // For a real encoder, we'd compute the actual branch offset
-                        // This is simplified to always branch to PC+8
-                        let offset = 8;
-                        
-                        ins_word = self.encode_conditional_branch(
-                            cond,
-                            offset
-                        );
-                    },
-                    _ => {
-                        return Err(EncodingError::InvalidOperand(
-                            "Branch target must be a label".to_string()
-                        ));
-                    }
+                _ => {
+                    return Err(EncodingError::InvalidOperand(
+                        "PTRUE takes a predicate".to_string()
+                    ));
                 }
             },
+            "ld1b" | "ld1h" | "ld1w" | "ld1d" | "st1b" | "st1h" | "st1w" | "st1d" => {
+                ins_word = self.encode_sve_memory(instruction)?;
+            },
             // Apple Silicon specific instructions
             "pacibsp" => {
                 // PACIBSP has no operands and fixed encoding
@@ -1315,18 +1463,14 @@ impl InstructionEncoder for AArch64InstructionEncoder {
         Ok(encoded)
     }
     
+    /// Encode a block on its own; its branches may only target its labels
     fn encode_asm_block(&self, block: &AssemblyBlock) -> Result<Vec<u8>, EncodingError> {
-        let mut encoded = Vec::new();
-        
-        // This is a simplified implementation that doesn't handle labels and jumps correctly
-        // A full implementation would need to resolve labels and calculate jump offsets
-        
-        for instruction in &block.instructions {
-            let inst_bytes = self.encode_instruction(instruction)?;
-            encoded.extend_from_slice(&inst_bytes);
-        }
-        
-        Ok(encoded)
+        assembler::assemble(self, std::slice::from_ref(block)).map(|assembled| assembled.code)
+    }
+    
+    /// Encode every block, with branches between them resolved
+    fn encode_asm(&self, ast: &AssemblyAST) -> Result<Vec<u8>, EncodingError> {
+        assembler::assemble(self, &ast.blocks).map(|assembled| assembled.code)
     }
     
     fn instruction_size(&self, _instruction: &Instruction) -> usize {
@@ -1388,11 +1532,16 @@ impl AArch64FeatureDetector {
         features.push("sha1".to_string());
         features.push("sha2".to_string());
         
+        // The host's caches when we run on this architecture, else common sizes
+        let (line, l1d, l2) = host_cache_sizes().filter(|_| cfg!(target_arch = "aarch64")).unwrap_or((64, 64 * 1024, 1024 * 1024));
+
         CPUFeatures {
             architecture: Architecture::AArch64,
             extensions,
             vector_width: 16, // 128-bit (NEON/ASIMD)
-            cache_line_size: 64, // Common cache line size for ARM64
+            cache_line_size: line,
+            l1d_cache_size: l1d,
+            l2_cache_size: l2,
             features,
         }
     }
@@ -1458,6 +1607,318 @@ impl FeatureDetector for AArch64FeatureDetector {
     }
 }
 
+/// AArch64 sequences for `arch::intrinsics`
+pub struct AArch64IntrinsicLowering;
+
+impl AArch64IntrinsicLowering {
+    fn has_neon(features: &CPUFeatures) -> bool {
+        intrinsics::has(features, "neon") || intrinsics::has(features, "asimd")
+    }
+
+    /// `register` as `x<n>` or `w<n>`
+    fn gpr(register: &Register, bits: u32) -> Operand {
+        let (prefix, size) = if bits == 64 { ("x", 64) } else { ("w", 32) };
+        Operand::Register(intrinsics::view(register, format!("{}{}", prefix, register.number), size))
+    }
+
+    /// `register` as a NEON vector with `arrangement`, e.g. `v3.4s`, or a
+    /// scalar view with a prefix like `d`
+    fn fp(register: &Register, name: &str, size: usize) -> Operand {
+        let name = if name.starts_with('.') {
+            format!("v{}{}", register.number, name)
+        } else {
+            format!("{}{}", name, register.number)
+        };
+        Operand::Register(intrinsics::view(register, name, size))
+    }
+
+    /// `register` as an SVE vector or predicate register, e.g. `z3.s` or
+    /// `p1/z`
+    fn sve(register: &Register, name: &str, suffix: &str) -> Operand {
+        Operand::Register(intrinsics::view(register, format!("{}{}{}", name, register.number, suffix), register.size))
+    }
+
+    /// The arrangement suffix for lanes of `element`
+    fn lane_suffix(element: ElementType) -> char {
+        match element.bits() {
+            8 => 'b',
+            16 => 'h',
+            32 => 's',
+            _ => 'd',
+        }
+    }
+
+    /// `prfm` operation: type (load/store), target cache level, policy
+    fn prefetch_operation(write: bool, locality: u8) -> i64 {
+        let kind = if write { 0b10000 } else { 0 };
+        let (target, policy) = match locality {
+            3 => (0, 0),
+            2 => (1, 0),
+            1 => (2, 0),
+            // Streaming: use once, from L1
+            _ => (0, 1),
+        };
+        kind | target << 1 | policy
+    }
+}
+
+impl IntrinsicLowering for AArch64IntrinsicLowering {
+    fn max_lanes(&self, element: ElementType, features: &CPUFeatures) -> u32 {
+        if Self::has_neon(features) {
+            128 / element.bits()
+        } else {
+            1
+        }
+    }
+
+    /// The lanes of SVE's shortest vector; the same code runs more of them
+    /// on longer ones
+    fn max_masked_lanes(&self, element: ElementType, features: &CPUFeatures) -> u32 {
+        if intrinsics::has(features, "sve") {
+            128 / element.bits()
+        } else {
+            0
+        }
+    }
+
+    fn scratch(&self, intrinsic: &Intrinsic, features: &CPUFeatures) -> Vec<RegisterClass> {
+        // Without CSSC's scalar cnt, popcount goes through a vector register
+        match intrinsic {
+            Intrinsic::Popcount { .. } if !intrinsics::has(features, "cssc") && Self::has_neon(features) => {
+                vec![RegisterClass::Vector]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn lower_call(&self, call: &IntrinsicCall, features: &CPUFeatures) -> Result<Lowering, LoweringError> {
+        let instruction = intrinsics::instruction;
+        match call.intrinsic {
+            Intrinsic::VectorAdd { element, lanes } => {
+                let (result, a) = intrinsics::result_and_source(call)?;
+                let b = intrinsics::argument(call, 1)?;
+                // The 64-bit form for vectors that fit, else the 128-bit one
+                let size = if element.bits() * lanes <= 64 { 64 } else { 128 };
+                let suffix = Self::lane_suffix(element);
+                let arrangement = format!(".{}{}", size / element.bits(), suffix);
+                let mnemonic = if element.is_float() { "fadd" } else { "add" };
+                let operands = [result, a, b].iter().map(|register| Self::fp(register, &arrangement, size as usize)).collect();
+                Ok(Lowering::Instructions(vec![instruction(mnemonic, operands)]))
+            }
+            Intrinsic::Popcount { bits } => {
+                let cssc = intrinsics::has(features, "cssc");
+                if !cssc && !Self::has_neon(features) {
+                    return Ok(Lowering::Libcall(if bits == 64 { "__popcountdi2" } else { "__popcountsi2" }));
+                }
+                let (result, source) = intrinsics::result_and_source(call)?;
+                let mut body = Vec::new();
+                // Clear the bits above a narrow value
+                let source = match bits {
+                    8 | 16 => {
+                        let extend = if bits == 8 { "uxtb" } else { "uxth" };
+                        body.push(instruction(extend, vec![Self::gpr(result, 32), Self::gpr(source, 32)]));
+                        result
+                    }
+                    _ => source,
+                };
+                if cssc {
+                    body.push(instruction("cnt", vec![Self::gpr(result, bits), Self::gpr(source, bits)]));
+                    return Ok(Lowering::Instructions(body));
+                }
+                // Count each byte in a vector register and add the counts up
+                let vector = &call.scratch[0];
+                let (scalar, size) = if bits == 64 { ("d", 64) } else { ("s", 32) };
+                body.push(instruction("fmov", vec![Self::fp(vector, scalar, size), Self::gpr(source, bits)]));
+                body.push(instruction("cnt", vec![Self::fp(vector, ".8b", 64), Self::fp(vector, ".8b", 64)]));
+                body.push(instruction("addv", vec![Self::fp(vector, "b", 8), Self::fp(vector, ".8b", 64)]));
+                body.push(instruction("fmov", vec![Self::gpr(result, 32), Self::fp(vector, "s", 32)]));
+                Ok(Lowering::Instructions(body))
+            }
+            Intrinsic::Prefetch { write, locality } => {
+                let mut operands = vec![Operand::Immediate(Self::prefetch_operation(write, locality))];
+                operands.extend(call.arguments.iter().cloned());
+                Ok(Lowering::Instructions(vec![instruction("prfm", operands)]))
+            }
+            Intrinsic::Fence(ordering) => {
+                // dmb ishld orders earlier loads only; anything else needs ish
+                let option = if ordering == FenceOrdering::Acquire { 0b1001 } else { 0b1011 };
+                Ok(Lowering::Instructions(vec![instruction("dmb", vec![Operand::Immediate(option)])]))
+            }
+            Intrinsic::ByteSwap { bits } => {
+                let (result, source) = intrinsics::result_and_source(call)?;
+                let mnemonic = if bits == 16 { "rev16" } else { "rev" };
+                let width = if bits == 64 { 64 } else { 32 };
+                Ok(Lowering::Instructions(vec![instruction(mnemonic, vec![Self::gpr(result, width), Self::gpr(source, width)])]))
+            }
+            Intrinsic::LoopMask { element, .. } => {
+                let result = call.result.as_ref().ok_or(LoweringError::Operands(call.intrinsic, "has no result".to_string()))?;
+                let (index, limit) = (intrinsics::argument(call, 0)?, intrinsics::argument(call, 1)?);
+                let predicate = Self::sve(result, "p", &format!(".{}", Self::lane_suffix(element)));
+                Ok(Lowering::Instructions(vec![instruction("whilelo", vec![predicate, Self::gpr(index, 64), Self::gpr(limit, 64)])]))
+            }
+            Intrinsic::MaskedLoad { element, .. } | Intrinsic::MaskedStore { element, .. } => {
+                let mask = intrinsics::argument(call, 0)?;
+                if mask.number >= 8 {
+                    return Err(LoweringError::Operands(call.intrinsic, "takes its mask in p0-p7".to_string()));
+                }
+                let suffix = Self::lane_suffix(element);
+                let width = match suffix {
+                    'b' => 'b',
+                    'h' => 'h',
+                    's' => 'w',
+                    _ => 'd',
+                };
+                let address = call.arguments[1].clone();
+                let (mnemonic, vector, predicate) = match (&call.result, call.arguments.get(2)) {
+                    (Some(result), _) => ("ld1", result, Self::sve(mask, "p", "/z")),
+                    (None, Some(Operand::Register(value))) => ("st1", value, Self::sve(mask, "p", "")),
+                    _ => return Err(LoweringError::Operands(call.intrinsic, "stores a register".to_string())),
+                };
+                let vector = Self::sve(vector, "z", &format!(".{}", suffix));
+                Ok(Lowering::Instructions(vec![instruction(&format!("{}{}", mnemonic, width), vec![vector, predicate, address])]))
+            }
+        }
+    }
+}
+
+/// AArch64 instruction effects for `arch::schedule`
+pub struct AArch64Hazards;
+
+impl AArch64Hazards {
+    /// Exclusive, acquire/release and atomic memory operations order other
+    /// accesses, so they stay where they are
+    const ORDERED: &'static [&'static str] = &[
+        "ldar", "ldapr", "ldax", "ldx", "stlr", "stlx", "stx", "cas", "swp",
+        "ldadd", "ldclr", "ldeor", "ldset", "ldsmax", "ldsmin", "ldumax", "ldumin",
+    ];
+
+    /// Read the destination as well as writing it
+    const ACCUMULATE: &'static [&'static str] = &[
+        "movk", "bfi", "bfxil", "bfm", "fmla", "fmls", "mla", "mls", "bsl", "bit", "bif",
+        "ins", "sli", "sri", "saba", "uaba", "sadalp", "uadalp", "sdot", "udot",
+    ];
+
+    /// Set the flags and write no register
+    const COMPARE: &'static [&'static str] = &["cmp", "cmn", "tst", "ccmp", "ccmn", "fcmp", "fcmpe", "fccmp", "fccmpe"];
+
+    const SETS_FLAGS: &'static [&'static str] = &[
+        "cmp", "cmn", "tst", "ccmp", "ccmn", "fcmp", "fcmpe", "fccmp", "fccmpe", "adds", "subs", "ands",
+        "bics", "adcs", "sbcs", "negs", "ngcs", "ptrues", "whilelo", "whilelt", "whilele", "whilels",
+        "whilehi", "whilehs", "whilege", "whilegt",
+    ];
+
+    const READS_FLAGS: &'static [&'static str] = &[
+        "csel", "csinc", "csinv", "csneg", "cset", "csetm", "cinc", "cinv", "cneg", "adc", "adcs",
+        "sbc", "sbcs", "ngc", "ngcs", "ccmp", "ccmn", "fcsel", "fccmp", "fccmpe",
+    ];
+
+    /// FP, NEON and SVE registers share one file: `s3`, `d3`, `v3` and
+    /// `z3` are one location
+    fn location(register: &Register) -> Location {
+        match register.class {
+            RegisterClass::Float => Location::Register(RegisterClass::Vector, register.number),
+            class => Location::Register(class, register.number),
+        }
+    }
+
+    fn class(mnemonic: &str, vector: bool) -> OpClass {
+        match mnemonic {
+            "sdiv" | "udiv" => OpClass::Divide,
+            "fdiv" | "fsqrt" => OpClass::FloatDivide,
+            "fmul" | "fmulx" | "fnmul" | "fmla" | "fmls" | "fmadd" | "fmsub" | "fnmadd" | "fnmsub" => {
+                OpClass::FloatMultiply
+            }
+            "mul" | "mneg" | "madd" | "msub" | "smull" | "umull" | "smulh" | "umulh" | "smaddl" | "umaddl"
+            | "smsubl" | "umsubl" | "smnegl" | "umnegl" if !vector => OpClass::Multiply,
+            "scvtf" | "ucvtf" => OpClass::Float,
+            _ if mnemonic.starts_with('f') => OpClass::Float,
+            _ if vector => OpClass::Vector,
+            _ => OpClass::Alu,
+        }
+    }
+}
+
+impl SchedulingHazards for AArch64Hazards {
+    fn effects(&self, instruction: &Instruction) -> Effects {
+        let mnemonic = instruction.mnemonic.to_lowercase();
+        let mnemonic = mnemonic.as_str();
+        let branch = matches!(
+            mnemonic,
+            "b" | "bl" | "br" | "blr" | "ret" | "cbz" | "cbnz" | "tbz" | "tbnz" | "eret"
+        ) || mnemonic.starts_with("b.");
+        if branch {
+            return Effects::barrier(OpClass::Branch);
+        }
+        let system = matches!(
+            mnemonic,
+            "dmb" | "dsb" | "isb" | "msr" | "mrs" | "svc" | "hvc" | "smc" | "brk" | "hlt" | "wfi" | "wfe"
+                | "sev" | "sevl" | "yield" | "hint" | "clrex" | "sys" | "sysl" | "dc" | "ic" | "tlbi" | "at"
+        );
+        if system || Self::ORDERED.iter().any(|prefix| mnemonic.starts_with(prefix)) {
+            return Effects::barrier(OpClass::System);
+        }
+
+        let registers: Vec<&Register> = instruction
+            .operands
+            .iter()
+            .filter_map(|operand| match operand {
+                Operand::Register(register) => Some(register),
+                _ => None,
+            })
+            .collect();
+        let address = instruction.operands.iter().filter_map(|operand| match operand {
+            Operand::Memory(memory) => Some(memory),
+            _ => None,
+        });
+        let mut reads: Vec<Location> =
+            address.flat_map(|memory| memory.base.iter().chain(memory.index.iter())).map(Self::location).collect();
+        let mut writes = Vec::new();
+
+        let prefetch = mnemonic == "prfm";
+        let loads = mnemonic.starts_with("ld") && !prefetch;
+        let stores = mnemonic.starts_with("st");
+        let vector = registers.iter().any(|r| matches!(r.class, RegisterClass::Vector | RegisterClass::Float));
+        let class = if loads || prefetch {
+            OpClass::Load
+        } else if stores {
+            OpClass::Store
+        } else {
+            Self::class(mnemonic, vector)
+        };
+
+        if loads {
+            // Data registers are written; a governing predicate is read
+            for register in &registers {
+                if register.class == RegisterClass::Predicate {
+                    reads.push(Self::location(register));
+                } else {
+                    writes.push(Self::location(register));
+                }
+            }
+        } else if stores || prefetch || Self::COMPARE.contains(&mnemonic) {
+            reads.extend(registers.iter().map(|r| Self::location(r)));
+        } else if let Some((destination, sources)) = registers.split_first() {
+            writes.push(Self::location(destination));
+            if Self::ACCUMULATE.contains(&mnemonic) {
+                reads.push(Self::location(destination));
+            }
+            reads.extend(sources.iter().map(|r| Self::location(r)));
+        }
+        if Self::SETS_FLAGS.contains(&mnemonic) {
+            writes.push(Location::Flags);
+        }
+        if Self::READS_FLAGS.contains(&mnemonic) {
+            reads.push(Location::Flags);
+        }
+        Effects { class, reads, writes, loads, stores, barrier: false }
+    }
+
+    fn models(&self) -> &'static [&'static SchedModel] {
+        static MODELS: [&SchedModel; 2] = [&schedule::CORTEX_A53, &schedule::CORTEX_A72];
+        &MODELS
+    }
+}
+
 // This struct is referenced but not defined in the module interfaces
 pub struct StructType {
     pub name: String,
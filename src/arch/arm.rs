@@ -12,8 +12,10 @@ use crate::arch::{
     Architecture, ArchitectureSupport, AssemblyParser, ABIHandler,
     InstructionEncoder, FeatureDetector, AssemblyParseError, EncodingError,
     Register, RegisterClass, Operand, MemoryOperand, Instruction,
-    AssemblyBlock, AssemblyAST, CallingConvention, StructLayout, CPUFeatures,
+    AssemblyBlock, AssemblyAST, CallingConvention, StructLayout, CPUFeatures, host_cache_sizes,
 };
+use crate::arch::schedule;
+use crate::arch::intrinsics::{self, ElementType, Intrinsic, IntrinsicCall, IntrinsicLowering, Lowering, LoweringError};
 
 /// Create ARM architecture support
 pub fn create_support() -> ArchitectureSupport {
@@ -23,6 +25,8 @@ pub fn create_support() -> ArchitectureSupport {
         abi_handler: Box::new(ArmABIHandler::new()),
         instruction_encoder: Box::new(ArmInstructionEncoder::new()),
         feature_detector: Box::new(ArmFeatureDetector::new()),
+        intrinsic_lowering: Box::new(ArmIntrinsicLowering),
+        scheduling_hazards: Box::new(schedule::Unscheduled),
     }
 }
 
@@ -902,11 +906,16 @@ impl ArmFeatureDetector {
         features.push("edsp".to_string());
         features.push("fastmult".to_string());
         
+        // The host's caches when we run on this architecture, else common sizes
+        let (line, l1d, l2) = host_cache_sizes().filter(|_| cfg!(target_arch = "arm")).unwrap_or((32, 32 * 1024, 512 * 1024));
+
         CPUFeatures {
             architecture: Architecture::Arm,
             extensions,
             vector_width: 16, // 128-bit (NEON)
-            cache_line_size: 32, // Common cache line size for ARMv7
+            cache_line_size: line,
+            l1d_cache_size: l1d,
+            l2_cache_size: l2,
             features,
         }
     }
@@ -963,6 +972,106 @@ impl FeatureDetector for ArmFeatureDetector {
     }
 }
 
+/// ARM sequences for `arch::intrinsics`
+pub struct ArmIntrinsicLowering;
+
+impl ArmIntrinsicLowering {
+    fn named(register: &Register, name: String, size: usize) -> Operand {
+        Operand::Register(intrinsics::view(register, name, size))
+    }
+}
+
+impl IntrinsicLowering for ArmIntrinsicLowering {
+    fn max_lanes(&self, element: ElementType, features: &CPUFeatures) -> u32 {
+        // NEON has no double-precision lanes
+        if intrinsics::has(features, "neon") && element != ElementType::F64 {
+            128 / element.bits()
+        } else {
+            1
+        }
+    }
+
+    fn scratch(&self, intrinsic: &Intrinsic, features: &CPUFeatures) -> Vec<RegisterClass> {
+        match intrinsic {
+            Intrinsic::Popcount { bits } if *bits <= 32 && intrinsics::has(features, "neon") => vec![RegisterClass::Vector],
+            _ => Vec::new(),
+        }
+    }
+
+    fn lower_call(&self, call: &IntrinsicCall, features: &CPUFeatures) -> Result<Lowering, LoweringError> {
+        let instruction = intrinsics::instruction;
+        // A 64-bit value needs a register pair, which an `IntrinsicCall`
+        // can't name; libgcc has both
+        match call.intrinsic {
+            Intrinsic::Popcount { bits: 64 } => return Ok(Lowering::Libcall("__popcountdi2")),
+            Intrinsic::ByteSwap { bits: 64 } => return Ok(Lowering::Libcall("__bswapdi2")),
+            _ => {}
+        }
+        let r = |register: &Register| Self::named(register, format!("r{}", register.number), 32);
+
+        match call.intrinsic {
+            Intrinsic::VectorAdd { element, lanes } => {
+                let (result, a) = intrinsics::result_and_source(call)?;
+                let b = intrinsics::argument(call, 1)?;
+                // D registers for vectors that fit, else Q registers
+                let (bank, size) = if element.bits() * lanes <= 64 { ("d", 64) } else { ("q", 128) };
+                let mnemonic = format!("vadd.{}{}", if element.is_float() { "f" } else { "i" }, element.bits());
+                let operands = [result, a, b]
+                    .iter()
+                    .map(|register| Self::named(register, format!("{}{}", bank, register.number), size))
+                    .collect();
+                Ok(Lowering::Instructions(vec![instruction(&mnemonic, operands)]))
+            }
+            Intrinsic::Popcount { bits } => {
+                if !intrinsics::has(features, "neon") {
+                    return Ok(Lowering::Libcall("__popcountsi2"));
+                }
+                let (result, source) = intrinsics::result_and_source(call)?;
+                let mut body = Vec::new();
+                let source = match bits {
+                    8 | 16 => {
+                        let extend = if bits == 8 { "uxtb" } else { "uxth" };
+                        body.push(instruction(extend, vec![r(result), r(source)]));
+                        result
+                    }
+                    _ => source,
+                };
+                // Count each byte, then add neighbouring counts twice
+                let d = &call.scratch[0];
+                let lane = Self::named(d, format!("d{}[0]", d.number), 32);
+                let whole = || Self::named(d, format!("d{}", d.number), 64);
+                body.push(instruction("vmov.32", vec![lane.clone(), r(source)]));
+                body.push(instruction("vcnt.8", vec![whole(), whole()]));
+                body.push(instruction("vpaddl.u8", vec![whole(), whole()]));
+                body.push(instruction("vpaddl.u16", vec![whole(), whole()]));
+                body.push(instruction("vmov.32", vec![r(result), lane]));
+                Ok(Lowering::Instructions(body))
+            }
+            Intrinsic::Prefetch { write, .. } => {
+                // pldw is part of the multiprocessing extensions
+                let mnemonic = if write && intrinsics::has(features, "multiproc") { "pldw" } else { "pld" };
+                Ok(Lowering::Instructions(vec![instruction(mnemonic, call.arguments.clone())]))
+            }
+            Intrinsic::Fence(_) => {
+                // ARMv6 has no dmb; its CP15 barrier is privileged on some kernels
+                if !intrinsics::has(features, "armv7") && !intrinsics::has(features, "armv8") {
+                    return Ok(Lowering::Libcall("__sync_synchronize"));
+                }
+                Ok(Lowering::Instructions(vec![instruction("dmb", vec![Operand::Immediate(0b1011)])]))
+            }
+            Intrinsic::ByteSwap { bits } => {
+                let (result, source) = intrinsics::result_and_source(call)?;
+                let mnemonic = if bits == 16 { "rev16" } else { "rev" };
+                Ok(Lowering::Instructions(vec![instruction(mnemonic, vec![r(result), r(source)])]))
+            }
+            // NEON has no predication; `lower` turns these away first
+            Intrinsic::LoopMask { .. } | Intrinsic::MaskedLoad { .. } | Intrinsic::MaskedStore { .. } => {
+                Err(LoweringError::Unmasked(call.intrinsic))
+            }
+        }
+    }
+}
+
 // This struct is referenced but not defined in the module interfaces
 pub struct StructType {
     pub name: String,
@@ -0,0 +1,211 @@
+// src/arch/assembler.rs
+//! Label resolution for assembled code
+//! An encoder can't know a branch's displacement until every label has an
+//! address, and on x86 the addresses depend on which branches fit in an
+//! 8-bit displacement. So `assemble` works in passes:
+//!
+//! 1. Lay out every block in order, encoding each instruction at its
+//!    current relaxation level (all start at 0, the shortest form). A
+//!    branch to a label gets a placeholder and a `Fixup`; the labels get
+//!    the offset of their block.
+//! 2. Patch each fixup. A branch whose target is out of reach moves up a
+//!    level: rel8 to rel32 on x86, and on AArch64 a conditional branch
+//!    becomes a veneer, the inverted condition skipping over a `b` with
+//!    its ±128 MiB range. A branch already at its longest form is an error.
+//!
+//! Forms only ever grow, so the passes stop once nothing changed. Targets
+//! must be labels of the blocks being assembled; there is no relocation
+//! against outside symbols.
+
+use std::collections::HashMap;
+use super::{AssemblyBlock, EncodingError, Instruction};
+
+/// Field a fixup patches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixupKind {
+    /// x86 displacement from the end of the instruction, in bytes
+    Rel8,
+    Rel32,
+    /// AArch64 `b`/`bl`: imm26 at bit 0, in words from the instruction
+    Branch26,
+    /// AArch64 `b.cond`, `cbz`, `cbnz`: imm19 at bit 5
+    Branch19,
+    /// AArch64 `tbz`, `tbnz`: imm14 at bit 5
+    Branch14,
+}
+
+impl FixupKind {
+    /// Bytes in the displacement field, or the instruction word it sits in
+    pub fn width(self) -> usize {
+        match self {
+            FixupKind::Rel8 => 1,
+            FixupKind::Rel32 | FixupKind::Branch26 | FixupKind::Branch19 | FixupKind::Branch14 => 4,
+        }
+    }
+
+    /// (bits of the field, lowest bit) for AArch64 fields, which count words
+    fn field(self) -> Option<(u32, u32)> {
+        match self {
+            FixupKind::Rel8 | FixupKind::Rel32 => None,
+            FixupKind::Branch26 => Some((26, 0)),
+            FixupKind::Branch19 => Some((19, 5)),
+            FixupKind::Branch14 => Some((14, 5)),
+        }
+    }
+
+    /// Put a displacement of `displacement` bytes into an AArch64 branch
+    /// `word`; `None` if it isn't a whole number of words in range
+    pub fn insert(self, word: u32, displacement: i64) -> Option<u32> {
+        let (bits, shift) = self.field()?;
+        if displacement % 4 != 0 || !fits_signed(displacement / 4, bits) {
+            return None;
+        }
+        let mask = ((1u32 << bits) - 1) << shift;
+        Some(word & !mask | ((displacement / 4) as u32) << shift & mask)
+    }
+}
+
+/// A branch displacement to patch once its label's address is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixup {
+    /// Offset of the displacement field in the encoded bytes; for AArch64
+    /// kinds, of the instruction word holding it
+    pub offset: usize,
+    pub label: String,
+    pub kind: FixupKind,
+}
+
+impl Fixup {
+    /// Move the fixup `by` bytes, for an instruction placed at that offset
+    pub fn shifted(mut self, by: usize) -> Fixup {
+        self.offset += by;
+        self
+    }
+
+    /// Write the displacement to `target`, both offsets into `code`. x86
+    /// branch displacements are the last bytes of their instruction and
+    /// count from its end; AArch64 ones count from the branch itself.
+    pub fn apply(&self, code: &mut [u8], target: usize) -> Result<(), EncodingError> {
+        let end = self.offset + self.kind.width();
+        let out_of_range = |displacement: i64| {
+            EncodingError::OperandOutOfRange(format!("branch to '{}' is {} bytes away", self.label, displacement))
+        };
+        match self.kind.field() {
+            None => {
+                let displacement = target as i64 - end as i64;
+                match self.kind {
+                    FixupKind::Rel8 if fits_signed(displacement, 8) => code[self.offset] = displacement as u8,
+                    FixupKind::Rel32 if fits_signed(displacement, 32) => {
+                        code[self.offset..end].copy_from_slice(&(displacement as i32).to_le_bytes());
+                    }
+                    _ => return Err(out_of_range(displacement)),
+                }
+            }
+            Some(_) => {
+                let displacement = target as i64 - self.offset as i64;
+                let word = u32::from_le_bytes(code[self.offset..end].try_into().expect("a 4-byte instruction"));
+                let patched = self.kind.insert(word, displacement).ok_or_else(|| out_of_range(displacement))?;
+                code[self.offset..end].copy_from_slice(&patched.to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An encoded instruction
+#[derive(Debug, Clone)]
+pub struct Encoded {
+    pub bytes: Vec<u8>,
+    /// The branch displacement still to patch, for a branch to a label
+    pub fixup: Option<Fixup>,
+}
+
+/// An encoder that can lay out branches to labels
+pub trait FixupEncoder {
+    /// Encode `instruction` at relaxation `level`, 0 being the shortest
+    /// form. `None` if it has no form at that level; every instruction has
+    /// one at level 0.
+    fn encode_at(&self, instruction: &Instruction, level: usize) -> Result<Option<Encoded>, EncodingError>;
+}
+
+/// Code for a sequence of blocks, with its labels resolved
+#[derive(Debug, Default)]
+pub struct Assembled {
+    pub code: Vec<u8>,
+    /// Offset of each label in `code`
+    pub labels: HashMap<String, usize>,
+    /// Branches that needed more than their shortest form
+    pub relaxed: usize,
+}
+
+/// Lay out `blocks` one after another and resolve every branch to their
+/// labels
+pub fn assemble(encoder: &dyn FixupEncoder, blocks: &[AssemblyBlock]) -> Result<Assembled, EncodingError> {
+    let instructions: Vec<&Instruction> = blocks.iter().flat_map(|block| &block.instructions).collect();
+    let mut levels = vec![0; instructions.len()];
+
+    loop {
+        // Lay out at the current levels
+        let mut code = Vec::new();
+        let mut labels = HashMap::new();
+        let mut fixups = Vec::new();
+        let mut index = 0;
+        for block in blocks {
+            for label in &block.labels {
+                if labels.insert(label.clone(), code.len()).is_some() {
+                    return Err(EncodingError::InvalidOperand(format!("label '{}' is defined twice", label)));
+                }
+            }
+            for instruction in &block.instructions {
+                let encoded = encoder.encode_at(instruction, levels[index])?.ok_or_else(|| {
+                    EncodingError::InvalidInstruction(format!("no encoding of '{}'", instruction.mnemonic))
+                })?;
+                fixups.extend(encoded.fixup.map(|fixup| (index, fixup.shifted(code.len()))));
+                code.extend_from_slice(&encoded.bytes);
+                index += 1;
+            }
+        }
+
+        // Patch, growing the branches that don't reach
+        let mut grown = false;
+        for (index, fixup) in &fixups {
+            let target = *labels
+                .get(&fixup.label)
+                .ok_or_else(|| EncodingError::InvalidOperand(format!("label '{}' is not defined", fixup.label)))?;
+            match fixup.apply(&mut code, target) {
+                Ok(()) => {}
+                Err(EncodingError::OperandOutOfRange(message)) => {
+                    if encoder.encode_at(instructions[*index], levels[*index] + 1)?.is_none() {
+                        return Err(EncodingError::OperandOutOfRange(message));
+                    }
+                    levels[*index] += 1;
+                    grown = true;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !grown {
+            let relaxed = levels.iter().filter(|&&level| level > 0).count();
+            return Ok(Assembled { code, labels, relaxed });
+        }
+    }
+}
+
+fn fits_signed(value: i64, bits: u32) -> bool {
+    let limit = 1i64 << (bits - 1);
+    (-limit..limit).contains(&value)
+}
+
+// Example usage:
+/*
+fn assemble_loop() -> Result<(), EncodingError> {
+    // The backward branch fits rel8; the forward one over 200 bytes needs rel32
+    let parser = X86_64AssemblyParser::new();
+    let source = format!("top:\n nop\n jmp top\n jz done\n{}done:\n ret", " nop\n".repeat(200));
+    let ast = parser.parse(&source).expect("valid assembly");
+    let assembled = assemble(&X86_64InstructionEncoder::new(), &ast.blocks)?;
+    println!("{} bytes, {} relaxed, done at {}", assembled.code.len(), assembled.relaxed, assembled.labels["done"]);
+    Ok(())
+}
+*/
@@ -0,0 +1,723 @@
+// src/arch/frame.rs
+//! Stack frame layout
+//! One description of a function's frame that the code generator emits its
+//! prologue and epilogue from, and that the unwinder and the debug info
+//! read back: where each callee-saved register and local lives, how far the
+//! stack pointer moves, whether there is a frame pointer and whether locals
+//! sit in the red zone. Because CFI and `DW_AT_frame_base` come from the
+//! same `FrameLayout` as the instructions, they can't disagree with them.
+//!
+//! Every offset is relative to the canonical frame address (CFA), the
+//! stack pointer just before the call instruction, so offsets are negative
+//! and don't depend on whether the frame pointer is kept. The frame base
+//! the debug info hands the debugger is the CFA itself
+//! (`DW_OP_call_frame_cfa`), and a local's location is its CFA offset.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use super::{Architecture, CallingConvention};
+
+/// Caller-chosen id of a local variable or spill slot
+pub type SlotId = usize;
+
+const DW_OP_FBREG: u8 = 0x91;
+const DW_OP_CALL_FRAME_CFA: u8 = 0x9c;
+
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_OFFSET_EXTENDED: u8 = 0x05;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+const DW_CFA_OFFSET: u8 = 0x80;
+
+/// When a function keeps a frame pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramePointerPolicy {
+    /// Every function, so the frame-pointer chain can be walked without CFI
+    Always,
+    /// Functions that make calls; leaves address their frame from the stack pointer
+    NonLeaf,
+    /// Only functions whose stack pointer moves by a run-time amount (`alloca`, VLAs)
+    Omit,
+}
+
+/// What each architecture's prologue is built from
+struct Target {
+    word: usize,
+    stack_pointer: &'static str,
+    frame_pointer: &'static str,
+    /// Register the call leaves the return address in; `None` if it's pushed
+    link_register: Option<&'static str>,
+    /// Bytes one saved register takes, 16 on AArch64 to keep `sp` aligned
+    push_size: usize,
+    /// Whether callee-saved registers are stored two at a time (`stp`)
+    pairs: bool,
+    data_alignment: i64,
+}
+
+fn target(architecture: Architecture) -> Target {
+    match architecture {
+        Architecture::X86_64 => Target {
+            word: 8,
+            stack_pointer: "rsp",
+            frame_pointer: "rbp",
+            link_register: None,
+            push_size: 8,
+            pairs: false,
+            data_alignment: -8,
+        },
+        Architecture::AArch64 => Target {
+            word: 8,
+            stack_pointer: "sp",
+            frame_pointer: "x29",
+            link_register: Some("x30"),
+            push_size: 16,
+            pairs: true,
+            data_alignment: -8,
+        },
+        Architecture::Arm => Target {
+            word: 4,
+            stack_pointer: "sp",
+            frame_pointer: "r11",
+            link_register: Some("lr"),
+            push_size: 4,
+            pairs: false,
+            data_alignment: -4,
+        },
+    }
+}
+
+/// DWARF register number of `name` on `architecture`, as CFI and location
+/// expressions use it
+pub fn dwarf_register(architecture: Architecture, name: &str) -> Option<u16> {
+    let name = name.to_lowercase();
+    let numbered = |prefix: &str, limit: u16| {
+        name.strip_prefix(prefix).and_then(|n| n.parse::<u16>().ok()).filter(|&n| n < limit)
+    };
+    match architecture {
+        Architecture::X86_64 => {
+            let legacy = ["rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp"];
+            if let Some(index) = legacy.iter().position(|&r| r == name) {
+                return Some(index as u16);
+            }
+            if name == "rip" {
+                return Some(16);
+            }
+            numbered("xmm", 16).map(|n| 17 + n).or_else(|| numbered("r", 16).filter(|&n| n >= 8))
+        }
+        Architecture::AArch64 => match name.as_str() {
+            "fp" => Some(29),
+            "lr" => Some(30),
+            "sp" => Some(31),
+            _ => numbered("x", 31).or_else(|| ["d", "v", "q"].iter().find_map(|p| numbered(p, 32)).map(|n| 64 + n)),
+        },
+        Architecture::Arm => match name.as_str() {
+            "fp" => Some(11),
+            "ip" => Some(12),
+            "sp" => Some(13),
+            "lr" => Some(14),
+            "pc" => Some(15),
+            _ => numbered("r", 16).or_else(|| numbered("d", 32).map(|n| 256 + n)),
+        },
+    }
+}
+
+fn is_float_register(architecture: Architecture, name: &str) -> bool {
+    dwarf_register(architecture, name).is_some_and(|number| match architecture {
+        Architecture::X86_64 => number >= 17,
+        Architecture::AArch64 => number >= 64,
+        Architecture::Arm => number >= 256,
+    })
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+/// Collects what a function needs from its frame; `build` decides where it goes
+#[derive(Debug, Clone)]
+pub struct FrameBuilder {
+    // Target
+    architecture: Architecture,
+    stack_alignment: usize,
+    red_zone_size: usize,
+
+    // Function properties
+    policy: FramePointerPolicy,
+    makes_calls: bool,
+    dynamic_allocation: bool,
+
+    // Contents
+    saved: Vec<String>,
+    locals: Vec<(SlotId, usize, usize)>,
+    spills: Vec<(SlotId, usize, usize)>,
+    outgoing_arguments: usize,
+}
+
+impl FrameBuilder {
+    pub fn new(architecture: Architecture, convention: &CallingConvention) -> Self {
+        FrameBuilder {
+            architecture,
+            stack_alignment: convention.stack_alignment.max(target(architecture).word),
+            red_zone_size: convention.red_zone_size,
+            policy: FramePointerPolicy::Always,
+            makes_calls: true,
+            dynamic_allocation: false,
+            saved: Vec::new(),
+            locals: Vec::new(),
+            spills: Vec::new(),
+            outgoing_arguments: 0,
+        }
+    }
+
+    pub fn frame_pointer(mut self, policy: FramePointerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Whether the function calls anything; a leaf can use the red zone and,
+    /// on Arm targets, leave the return address in the link register
+    pub fn makes_calls(mut self, calls: bool) -> Self {
+        self.makes_calls = calls;
+        self
+    }
+
+    /// The function moves the stack pointer by a run-time amount, so it
+    /// needs a frame pointer whatever the policy
+    pub fn dynamic_allocation(mut self) -> Self {
+        self.dynamic_allocation = true;
+        self
+    }
+
+    /// A callee-saved register the function writes. The stack and frame
+    /// pointers and the link register are the layout's business and ignored.
+    pub fn save(mut self, register: &str) -> Self {
+        let register = register.to_lowercase();
+        if !self.saved.contains(&register) {
+            self.saved.push(register);
+        }
+        self
+    }
+
+    /// A local variable, whose slot the debug info will describe
+    pub fn local(mut self, id: SlotId, size: usize, alignment: usize) -> Self {
+        self.locals.push((id, size, alignment));
+        self
+    }
+
+    /// A register allocator spill slot
+    pub fn spill(mut self, id: SlotId, size: usize, alignment: usize) -> Self {
+        self.spills.push((id, size, alignment));
+        self
+    }
+
+    /// Bytes at the bottom of the frame for arguments of calls that don't
+    /// fit in registers
+    pub fn outgoing_arguments(mut self, bytes: usize) -> Self {
+        self.outgoing_arguments = bytes;
+        self
+    }
+
+    pub fn build(self) -> Result<FrameLayout, FrameError> {
+        let architecture = self.architecture;
+        let target = target(architecture);
+        let frame_pointer = self.dynamic_allocation || match self.policy {
+            FramePointerPolicy::Always => true,
+            FramePointerPolicy::NonLeaf => self.makes_calls,
+            FramePointerPolicy::Omit => false,
+        };
+
+        // The return address: pushed by the call on x86, in the link
+        // register elsewhere until a call would overwrite it
+        let entry = if target.link_register.is_none() { target.word } else { 0 };
+        let mut depth = entry;
+        let mut prologue = Vec::new();
+        let mut saved = Vec::new();
+        let mut pushes = Vec::new();
+
+        // Frame record first, so the frame pointer always points at the
+        // caller's frame pointer with the return address above it
+        let record = frame_pointer || (self.makes_calls && target.link_register.is_some());
+        if record {
+            let step = match target.link_register {
+                None => FrameStep::Push { register: target.frame_pointer.to_string(), size: target.word },
+                Some(link) => FrameStep::PushPair {
+                    first: target.frame_pointer.to_string(),
+                    second: link.to_string(),
+                    size: 2 * target.word,
+                },
+            };
+            depth += step.size();
+            saved.extend(step.stores(depth));
+            pushes.push(step.clone());
+            prologue.push(step);
+        }
+        let frame_pointer_depth = frame_pointer.then_some(depth);
+        if frame_pointer {
+            prologue.push(FrameStep::SetFramePointer);
+        }
+
+        // Then the callee-saved registers the function uses
+        let reserved = [target.stack_pointer, target.frame_pointer, target.link_register.unwrap_or(""), "fp", "lr"];
+        let registers: Vec<&String> = self.saved.iter().filter(|r| !reserved.contains(&r.as_str())).collect();
+        for register in &registers {
+            if dwarf_register(architecture, register).is_none() {
+                return Err(FrameError::UnknownRegister(register.to_string()));
+            }
+            if architecture == Architecture::X86_64 && is_float_register(architecture, register) {
+                return Err(FrameError::CannotSave(register.to_string()));
+            }
+        }
+        let mut index = 0;
+        while index < registers.len() {
+            let first = registers[index];
+            let second = registers.get(index + 1).filter(|second| {
+                target.pairs && is_float_register(architecture, first) == is_float_register(architecture, second)
+            });
+            let step = match second {
+                Some(second) => {
+                    index += 2;
+                    FrameStep::PushPair { first: first.clone(), second: second.to_string(), size: 2 * target.word }
+                }
+                None => {
+                    index += 1;
+                    FrameStep::Push { register: first.clone(), size: target.push_size }
+                }
+            };
+            depth += step.size();
+            saved.extend(step.stores(depth));
+            pushes.push(step.clone());
+            prologue.push(step);
+        }
+        let saves_end = depth;
+
+        // Locals nearest the CFA, most aligned first to waste the least
+        // padding, then spill slots, then outgoing arguments at the bottom
+        let mut slots = HashMap::new();
+        let mut cursor = saves_end;
+        for group in [&self.locals, &self.spills] {
+            let mut group = group.clone();
+            group.sort_by_key(|&(_, _, alignment)| Reverse(alignment));
+            for (id, size, alignment) in group {
+                if !alignment.is_power_of_two() {
+                    return Err(FrameError::Alignment(alignment));
+                }
+                cursor = align_up(cursor + size, alignment);
+                if slots.insert(id, Slot { offset: -(cursor as i64), size }).is_some() {
+                    return Err(FrameError::DuplicateSlot(id));
+                }
+            }
+        }
+        let size = align_up(cursor + self.outgoing_arguments, self.stack_alignment);
+        let mut allocation = size - saves_end;
+
+        // A leaf whose locals fit below the stack pointer doesn't move it
+        let mut red_zone = 0;
+        if !self.makes_calls && !self.dynamic_allocation && allocation <= self.red_zone_size {
+            red_zone = cursor - saves_end;
+            allocation = 0;
+        }
+        if allocation > 0 {
+            prologue.push(FrameStep::Allocate(allocation));
+        }
+
+        let mut epilogue = Vec::new();
+        match frame_pointer_depth {
+            Some(frame_pointer_depth) if allocation > 0 || self.dynamic_allocation => {
+                epilogue.push(FrameStep::RestoreStackPointer { below_frame_pointer: saves_end - frame_pointer_depth });
+            }
+            _ if allocation > 0 => epilogue.push(FrameStep::Deallocate(allocation)),
+            _ => {}
+        }
+        epilogue.extend(pushes.into_iter().rev().map(FrameStep::reverse));
+
+        Ok(FrameLayout {
+            architecture,
+            prologue,
+            epilogue,
+            saved,
+            size: saves_end + allocation,
+            red_zone,
+            frame_pointer: frame_pointer_depth,
+            entry,
+            slots,
+        })
+    }
+}
+
+/// One instruction of a prologue or epilogue, in target-independent terms
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameStep {
+    /// Move the stack pointer down by `size` and store `register` at it
+    /// (`push rbx`, `str x19, [sp, #-16]!`)
+    Push { register: String, size: usize },
+    /// Move the stack pointer down by `size` and store `first` at it and
+    /// `second` above (`stp x29, x30, [sp, #-16]!`, `push {r11, lr}`)
+    PushPair { first: String, second: String, size: usize },
+    /// Copy the stack pointer to the frame pointer
+    SetFramePointer,
+    Allocate(usize),
+    Deallocate(usize),
+    /// Set the stack pointer to the frame pointer minus `below_frame_pointer`,
+    /// the registers saved after it; undoes allocations of any size
+    RestoreStackPointer { below_frame_pointer: usize },
+    Pop { register: String, size: usize },
+    PopPair { first: String, second: String, size: usize },
+}
+
+impl FrameStep {
+    fn size(&self) -> usize {
+        match self {
+            FrameStep::Push { size, .. } | FrameStep::PushPair { size, .. } => *size,
+            FrameStep::Pop { size, .. } | FrameStep::PopPair { size, .. } => *size,
+            FrameStep::Allocate(size) | FrameStep::Deallocate(size) => *size,
+            FrameStep::SetFramePointer | FrameStep::RestoreStackPointer { .. } => 0,
+        }
+    }
+
+    /// CFA offsets of the registers a push stores, `depth` being the
+    /// stack pointer's distance below the CFA after it
+    fn stores(&self, depth: usize) -> Vec<(String, i64)> {
+        let depth = depth as i64;
+        match self {
+            FrameStep::Push { register, .. } => vec![(register.clone(), -depth)],
+            FrameStep::PushPair { first, second, size } => {
+                vec![(first.clone(), -depth), (second.clone(), -depth + (*size as i64) / 2)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn reverse(self) -> FrameStep {
+        match self {
+            FrameStep::Push { register, size } => FrameStep::Pop { register, size },
+            FrameStep::PushPair { first, second, size } => FrameStep::PopPair { first, second, size },
+            FrameStep::Allocate(size) => FrameStep::Deallocate(size),
+            step => step,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: i64,
+    size: usize,
+}
+
+/// A call frame instruction, with registers as DWARF numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cfi {
+    DefCfa { register: u16, offset: i64 },
+    DefCfaOffset(i64),
+    DefCfaRegister(u16),
+    /// `register` is saved at CFA + `offset`
+    Offset { register: u16, offset: i64 },
+}
+
+/// Where a function's frame puts everything, from `FrameBuilder::build`
+#[derive(Debug, Clone)]
+pub struct FrameLayout {
+    pub architecture: Architecture,
+    /// Steps the code generator emits on entry, in order
+    pub prologue: Vec<FrameStep>,
+    /// Steps it emits before each return, in order
+    pub epilogue: Vec<FrameStep>,
+    /// CFA offset of every register the prologue stores
+    pub saved: Vec<(String, i64)>,
+    /// Bytes between the CFA and the stack pointer after the prologue
+    pub size: usize,
+    /// Bytes of locals below the stack pointer, in the red zone
+    pub red_zone: usize,
+    /// Distance from the frame pointer down from the CFA, if there is one
+    pub frame_pointer: Option<usize>,
+    entry: usize,
+    slots: HashMap<SlotId, Slot>,
+}
+
+impl FrameLayout {
+    /// CFA offset of a local or spill slot
+    pub fn slot_offset(&self, id: SlotId) -> Option<i64> {
+        self.slots.get(&id).map(|slot| slot.offset)
+    }
+
+    pub fn slot_size(&self, id: SlotId) -> Option<usize> {
+        self.slots.get(&id).map(|slot| slot.size)
+    }
+
+    /// The register and displacement code addresses a slot with after the
+    /// prologue: the frame pointer if there is one, else the stack pointer
+    pub fn address(&self, id: SlotId) -> Option<(&'static str, i64)> {
+        let offset = self.slot_offset(id)?;
+        let target = target(self.architecture);
+        Some(match self.frame_pointer {
+            Some(depth) => (target.frame_pointer, offset + depth as i64),
+            None => (target.stack_pointer, offset + self.size as i64),
+        })
+    }
+
+    /// `DW_AT_frame_base` for the function: the CFA
+    pub fn frame_base(&self) -> Vec<u8> {
+        vec![DW_OP_CALL_FRAME_CFA]
+    }
+
+    /// `DW_AT_location` of a slot, relative to `frame_base`
+    pub fn location(&self, id: SlotId) -> Option<Vec<u8>> {
+        let mut expression = vec![DW_OP_FBREG];
+        write_sleb128(&mut expression, self.slot_offset(id)?);
+        Some(expression)
+    }
+
+    /// The CFA rule on entry, before any prologue instruction; the same for
+    /// every function of the architecture, so it belongs in the CIE
+    pub fn initial_cfi(&self) -> Vec<Cfi> {
+        let sp = self.dwarf(target(self.architecture).stack_pointer);
+        let mut rules = vec![Cfi::DefCfa { register: sp, offset: self.entry as i64 }];
+        if self.entry > 0 {
+            rules.push(Cfi::Offset { register: self.return_address_register(), offset: -(self.entry as i64) });
+        }
+        rules
+    }
+
+    /// The CFI for the prologue, each rule at the code offset where it takes
+    /// effect. `step_ends[i]` is the offset just after `prologue[i]`'s
+    /// instructions. Unwinding from inside an epilogue isn't described.
+    pub fn prologue_cfi(&self, step_ends: &[usize]) -> Vec<(usize, Cfi)> {
+        let target = target(self.architecture);
+        let mut rows = Vec::new();
+        let mut depth = self.entry;
+        let mut on_stack_pointer = true;
+        for (step, &end) in self.prologue.iter().zip(step_ends) {
+            match step {
+                FrameStep::Push { .. } | FrameStep::PushPair { .. } | FrameStep::Allocate(_) => {
+                    depth += step.size();
+                    if on_stack_pointer {
+                        rows.push((end, Cfi::DefCfaOffset(depth as i64)));
+                    }
+                    for (register, offset) in step.stores(depth) {
+                        rows.push((end, Cfi::Offset { register: self.dwarf(&register), offset }));
+                    }
+                }
+                FrameStep::SetFramePointer => {
+                    on_stack_pointer = false;
+                    rows.push((end, Cfi::DefCfaRegister(self.dwarf(target.frame_pointer))));
+                }
+                _ => {}
+            }
+        }
+        rows
+    }
+
+    /// DWARF column holding the return address
+    pub fn return_address_register(&self) -> u16 {
+        match self.architecture {
+            Architecture::X86_64 => 16,
+            Architecture::AArch64 => 30,
+            Architecture::Arm => 14,
+        }
+    }
+
+    pub fn code_alignment(&self) -> u64 {
+        1
+    }
+
+    pub fn data_alignment(&self) -> i64 {
+        target(self.architecture).data_alignment
+    }
+
+    fn dwarf(&self, register: &str) -> u16 {
+        // `build` rejected registers without a number
+        dwarf_register(self.architecture, register).unwrap_or(0)
+    }
+}
+
+/// Encode CFI rows as DWARF call frame instructions, advancing the location
+/// with `DW_CFA_advance_loc4` from `start`
+pub fn encode_cfi(rows: &[(usize, Cfi)], start: usize, data_alignment: i64, out: &mut Vec<u8>) {
+    let mut location = start;
+    for &(offset, rule) in rows {
+        if offset > location {
+            out.push(DW_CFA_ADVANCE_LOC4);
+            out.extend_from_slice(&((offset - location) as u32).to_le_bytes());
+            location = offset;
+        }
+        match rule {
+            Cfi::DefCfa { register, offset } => {
+                out.push(DW_CFA_DEF_CFA);
+                write_uleb128(out, register as u64);
+                write_uleb128(out, offset as u64);
+            }
+            Cfi::DefCfaOffset(offset) => {
+                out.push(DW_CFA_DEF_CFA_OFFSET);
+                write_uleb128(out, offset as u64);
+            }
+            Cfi::DefCfaRegister(register) => {
+                out.push(DW_CFA_DEF_CFA_REGISTER);
+                write_uleb128(out, register as u64);
+            }
+            Cfi::Offset { register, offset } => {
+                if register < 64 {
+                    out.push(DW_CFA_OFFSET | register as u8);
+                } else {
+                    out.push(DW_CFA_OFFSET_EXTENDED);
+                    write_uleb128(out, register as u64);
+                }
+                write_uleb128(out, (offset / data_alignment) as u64);
+            }
+        }
+    }
+}
+
+/// A function's code and the layout its prologue was emitted from
+#[derive(Debug, Clone)]
+pub struct FunctionFrame {
+    pub address: u64,
+    pub size: u64,
+    pub layout: FrameLayout,
+    /// Code offset just after each prologue step
+    pub step_ends: Vec<usize>,
+}
+
+/// A `.debug_frame` section: one CIE with the architecture's entry rule
+/// and an FDE for each function
+pub fn debug_frame(architecture: Architecture, functions: &[FunctionFrame]) -> Vec<u8> {
+    let address_size = target(architecture).word;
+    let mut section = Vec::new();
+    let Some(first) = functions.first() else { return section };
+
+    let mut cie = Vec::new();
+    cie.extend_from_slice(&u32::MAX.to_le_bytes());
+    cie.push(1); // version
+    cie.push(0); // no augmentation
+    write_uleb128(&mut cie, first.layout.code_alignment());
+    write_sleb128(&mut cie, first.layout.data_alignment());
+    cie.push(first.layout.return_address_register() as u8);
+    let initial: Vec<(usize, Cfi)> = first.layout.initial_cfi().into_iter().map(|rule| (0, rule)).collect();
+    encode_cfi(&initial, 0, first.layout.data_alignment(), &mut cie);
+    push_entry(&mut section, cie, address_size);
+
+    for function in functions {
+        let mut fde = Vec::new();
+        fde.extend_from_slice(&0u32.to_le_bytes()); // the CIE, at offset 0
+        fde.extend_from_slice(&function.address.to_le_bytes()[..address_size]);
+        fde.extend_from_slice(&function.size.to_le_bytes()[..address_size]);
+        let rows = function.layout.prologue_cfi(&function.step_ends);
+        encode_cfi(&rows, 0, function.layout.data_alignment(), &mut fde);
+        push_entry(&mut section, fde, address_size);
+    }
+    section
+}
+
+/// Append `entry` with its length, padded with `DW_CFA_nop` to the address size
+fn push_entry(section: &mut Vec<u8>, mut entry: Vec<u8>, address_size: usize) {
+    while !(entry.len() + 4).is_multiple_of(address_size) {
+        entry.push(0);
+    }
+    section.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+    section.extend_from_slice(&entry);
+}
+
+/// Where the frame-pointer chain keeps the caller's frame pointer and the
+/// return address, relative to a frame pointer, for walking frames this
+/// layout engine didn't build (LLVM's, the host's)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRecord {
+    pub caller_frame: usize,
+    pub return_address: usize,
+    /// Distance from the frame pointer up to the CFA when the record is
+    /// always at the top of the frame, as on x86-64 where `push rbp`
+    /// follows the call; `None` where the record can sit anywhere in the
+    /// callee-save area, as LLVM places it on AArch64
+    pub cfa: Option<usize>,
+}
+
+impl FrameRecord {
+    pub fn of(architecture: Architecture) -> FrameRecord {
+        match architecture {
+            Architecture::X86_64 => FrameRecord { caller_frame: 0, return_address: 8, cfa: Some(16) },
+            Architecture::AArch64 => FrameRecord { caller_frame: 0, return_address: 8, cfa: None },
+            Architecture::Arm => FrameRecord { caller_frame: 0, return_address: 4, cfa: None },
+        }
+    }
+
+    /// The record of the machine this process runs on
+    pub fn host() -> Option<FrameRecord> {
+        if cfg!(target_arch = "x86_64") {
+            Some(FrameRecord::of(Architecture::X86_64))
+        } else if cfg!(target_arch = "aarch64") {
+            Some(FrameRecord::of(Architecture::AArch64))
+        } else if cfg!(target_arch = "arm") {
+            Some(FrameRecord::of(Architecture::Arm))
+        } else {
+            None
+        }
+    }
+}
+
+fn write_uleb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_sleb128(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// Not a register of the architecture
+    UnknownRegister(String),
+    /// A callee-saved register there is no push for, like Windows' xmm6
+    CannotSave(String),
+    Alignment(usize),
+    DuplicateSlot(SlotId),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::UnknownRegister(register) => write!(f, "unknown register {}", register),
+            FrameError::CannotSave(register) => write!(f, "cannot save {} in the prologue", register),
+            FrameError::Alignment(alignment) => write!(f, "alignment {} is not a power of two", alignment),
+            FrameError::DuplicateSlot(id) => write!(f, "slot {} placed twice", id),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn example(convention: &CallingConvention) -> Result<(), FrameError> {
+    // int f(int n) { long a[4]; double x; ... g(a); }  with rbx and r12 live
+    let layout = FrameBuilder::new(Architecture::X86_64, convention)
+        .frame_pointer(FramePointerPolicy::NonLeaf)
+        .save("rbx")
+        .save("r12")
+        .local(0, 32, 8)
+        .local(1, 8, 8)
+        .spill(2, 8, 8)
+        .build()?;
+
+    for step in &layout.prologue {
+        println!("{:?}", step); // Push rbp, SetFramePointer, Push rbx, Push r12, Allocate(48)
+    }
+    println!("a is at {:?}", layout.address(0));          // ("rbp", -48)
+    println!("DW_AT_location {:x?}", layout.location(0)); // fbreg -64
+    Ok(())
+}
+*/
@@ -0,0 +1,579 @@
+// src/arch/inline_asm.rs
+//! Constraint resolution for GNU `asm` statements
+//! Each operand constraint is checked against the target: register-class
+//! letters (`r`, `x`, `w`, ...) resolve to the registers the ABI handler
+//! knows in that class, and single-register letters (`a`, `D`, ...) to the
+//! register the assembly parser names. Clobbers are validated the same way.
+//! The result carries the LLVM constraint string the statement is emitted
+//! with. The template's mnemonics are checked against the architecture's
+//! parser too, but only as warnings: LLVM's assembler has the final say.
+//!
+//! Each constraint can be valid on its own while the statement as a whole
+//! can't be allocated: two operands pinned to one register, or more `r`
+//! operands live at once than the class has registers once the clobbers
+//! and pinned registers are taken out. GCC reports these as "impossible
+//! constraints"; `resolve` names the operands and registers involved.
+//! Inputs are all live together, along with the outputs that are early
+//! clobber, read-write or tied to an input; plain outputs can reuse the
+//! inputs' registers. Clobbered callee-saved registers are allowed and
+//! listed in `ResolvedAsm::saved`, for the prologue to save.
+
+use std::fmt;
+use super::{Architecture, ArchitectureSupport, Register, RegisterClass};
+use crate::frontend::inline_asm::{AsmOperand, AsmStatement};
+
+/// What a single constraint alternative accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintKind {
+    /// Any register of the class; the candidates exclude the stack and frame pointers
+    Class(RegisterClass, Vec<Register>),
+    /// One specific register
+    Register(Register),
+    /// A memory operand, passed by address
+    Memory,
+    /// A compile-time constant
+    Immediate,
+    /// Register, memory or immediate (`g`)
+    Any,
+    /// Same location as output operand N
+    Tied(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedOperand {
+    pub kind: ConstraintKind,
+    /// `=&`: written before all inputs are consumed
+    pub early_clobber: bool,
+    /// `+`: read as well as written
+    pub read_write: bool,
+    /// Memory is an alternative too (`rm`), so it needs no register when
+    /// none is free
+    pub memory_alternative: bool,
+}
+
+impl ResolvedOperand {
+    /// Passed by address rather than by value
+    pub fn is_indirect(&self) -> bool {
+        self.kind == ConstraintKind::Memory
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedAsm {
+    pub outputs: Vec<ResolvedOperand>,
+    pub inputs: Vec<ResolvedOperand>,
+    /// LLVM constraint string: outputs, inputs, the inputs implied by `+`
+    /// outputs, labels, then clobbers
+    pub constraints: String,
+    pub clobbers_memory: bool,
+    /// Registers named in the clobber list
+    pub clobbered: Vec<Register>,
+    /// Callee-saved registers the statement clobbers or writes an output
+    /// to, which the enclosing function must save and restore
+    pub saved: Vec<Register>,
+    pub warnings: Vec<String>,
+}
+
+/// Resolve every constraint and clobber of `statement` for `support`'s architecture
+pub fn resolve(statement: &AsmStatement, support: &ArchitectureSupport) -> Result<ResolvedAsm, ConstraintError> {
+    let arch = support.architecture;
+    let warnings = check_mnemonics(statement, support);
+
+    let outputs = statement
+        .outputs
+        .iter()
+        .map(|operand| resolve_operand(operand, true, statement.outputs.len(), support))
+        .collect::<Result<Vec<_>, _>>()?;
+    let inputs = statement
+        .inputs
+        .iter()
+        .map(|operand| resolve_operand(operand, false, statement.outputs.len(), support))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut constraints = Vec::new();
+    for (operand, resolved) in statement.outputs.iter().zip(&outputs) {
+        let prefix = if resolved.is_indirect() { "=*" } else { "=" };
+        let early = if resolved.early_clobber { "&" } else { "" };
+        constraints.push(format!("{}{}{}", prefix, early, llvm_code(&operand.constraint, resolved, arch)));
+    }
+    for (operand, resolved) in statement.inputs.iter().zip(&inputs) {
+        let prefix = if resolved.is_indirect() { "*" } else { "" };
+        constraints.push(format!("{}{}", prefix, llvm_code(&operand.constraint, resolved, arch)));
+    }
+    // `+r` is an output plus an input tied to it; the input is numbered after
+    // the written inputs so GCC's operand numbers stay valid
+    for (index, resolved) in outputs.iter().enumerate() {
+        if resolved.read_write && !resolved.is_indirect() {
+            constraints.push(index.to_string());
+        }
+    }
+    constraints.extend(statement.labels.iter().map(|_| "!i".to_string()));
+
+    let mut clobbers_memory = false;
+    let mut clobbered = Vec::new();
+    let fixed: Vec<&Register> = outputs
+        .iter()
+        .chain(&inputs)
+        .filter_map(|operand| match &operand.kind {
+            ConstraintKind::Register(register) => Some(register),
+            _ => None,
+        })
+        .collect();
+    for clobber in &statement.clobbers {
+        let name = clobber.trim().trim_start_matches('%').to_lowercase();
+        match name.as_str() {
+            "memory" => {
+                clobbers_memory = true;
+                constraints.push("~{memory}".to_string());
+            }
+            "cc" => constraints.push("~{cc}".to_string()),
+            _ => {
+                let register = support
+                    .asm_parser
+                    .parse_register(&name)
+                    .ok_or_else(|| ConstraintError::UnknownClobber(clobber.clone()))?;
+                if is_reserved(&register, arch) {
+                    return Err(ConstraintError::ReservedClobber(clobber.clone()));
+                }
+                if fixed.iter().any(|operand| operand.number == register.number && operand.class == register.class) {
+                    return Err(ConstraintError::ClobberConflict(clobber.clone()));
+                }
+                // Callee-saved registers are fine: LLVM saves them in the prologue
+                constraints.push(format!("~{{{}}}", name));
+                clobbered.push(register);
+            }
+        }
+    }
+    // Clang always treats these as clobbered on x86
+    if arch == Architecture::X86_64 {
+        constraints.extend(["~{dirflag}", "~{fpsr}", "~{flags}"].map(String::from));
+    }
+
+    let saved = check_allocation(statement, &outputs, &inputs, &clobbered, support)?;
+
+    Ok(ResolvedAsm {
+        outputs,
+        inputs,
+        constraints: constraints.join(","),
+        clobbers_memory,
+        clobbered,
+        saved,
+        warnings,
+    })
+}
+
+fn resolve_operand(
+    operand: &AsmOperand,
+    output: bool,
+    output_count: usize,
+    support: &ArchitectureSupport,
+) -> Result<ResolvedOperand, ConstraintError> {
+    let constraint = operand.constraint.as_str();
+    let mut early_clobber = false;
+    let mut read_write = false;
+    let mut kinds = Vec::new();
+
+    let mut chars = constraint.chars().peekable();
+    let mut first = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '=' if output && first => {}
+            '+' if output && first => read_write = true,
+            '&' if output => early_clobber = true,
+            '%' | '?' | '!' | '*' | ',' => {}
+            '=' | '+' => return Err(ConstraintError::MisplacedModifier(constraint.to_string())),
+            d if d.is_ascii_digit() => {
+                let mut digits = d.to_string();
+                while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+                    digits.push(d);
+                }
+                let index: usize = digits.parse().unwrap_or(usize::MAX);
+                if output || index >= output_count {
+                    return Err(ConstraintError::BadTie(constraint.to_string()));
+                }
+                kinds.push(ConstraintKind::Tied(index));
+            }
+            letter => kinds.push(constraint_kind(letter, support)?),
+        }
+        first = false;
+    }
+
+    if output && !constraint.starts_with(['=', '+']) {
+        return Err(ConstraintError::OutputWithoutModifier(constraint.to_string()));
+    }
+
+    // With alternatives (`rm`, `ri`), prefer a register: LLVM picks among
+    // them, we only need the one that decides how the value is passed
+    let memory_alternative = kinds.iter().any(|kind| matches!(kind, ConstraintKind::Memory | ConstraintKind::Any));
+    let kind = kinds
+        .iter()
+        .find(|kind| matches!(kind, ConstraintKind::Class(..) | ConstraintKind::Register(_)))
+        .or_else(|| kinds.first())
+        .cloned()
+        .ok_or_else(|| ConstraintError::Empty(constraint.to_string()))?;
+    if output && kind == ConstraintKind::Immediate {
+        return Err(ConstraintError::ImmediateOutput(constraint.to_string()));
+    }
+
+    Ok(ResolvedOperand { kind, early_clobber, read_write, memory_alternative })
+}
+
+/// Check that the operands can all have registers at once, and return the
+/// callee-saved registers the statement changes
+fn check_allocation(
+    statement: &AsmStatement,
+    outputs: &[ResolvedOperand],
+    inputs: &[ResolvedOperand],
+    clobbered: &[Register],
+    support: &ArchitectureSupport,
+) -> Result<Vec<Register>, ConstraintError> {
+    let pinned = |resolved: &ResolvedOperand| match &resolved.kind {
+        ConstraintKind::Register(register) => Some(register.clone()),
+        _ => None,
+    };
+
+    // Outputs given a value by an input ("0"(x)) hold it from the start
+    let mut tied: Vec<Option<String>> = vec![None; outputs.len()];
+    for (operand, resolved) in statement.inputs.iter().zip(inputs) {
+        if let ConstraintKind::Tied(index) = resolved.kind {
+            if outputs[index].is_indirect() {
+                return Err(ConstraintError::TiedToMemory(describe(operand)));
+            }
+            let output = &statement.outputs[index];
+            if outputs[index].read_write {
+                let first = "its own `+`".to_string();
+                return Err(ConstraintError::TiedTwice { output: describe(output), first, second: describe(operand) });
+            }
+            if let Some(first) = &tied[index] {
+                return Err(ConstraintError::TiedTwice { output: describe(output), first: first.clone(), second: describe(operand) });
+            }
+            tied[index] = Some(describe(operand));
+        }
+    }
+    let live_at_entry = |index: usize| outputs[index].early_clobber || outputs[index].read_write || tied[index].is_some();
+
+    // Registers in use while the inputs are read, and once the outputs are written
+    let mut reading: Vec<(Register, String)> = Vec::new();
+    let mut writing: Vec<(Register, String)> = Vec::new();
+    for (index, (operand, resolved)) in statement.outputs.iter().zip(outputs).enumerate() {
+        if let Some(register) = pinned(resolved) {
+            claim(&mut writing, register.clone(), describe(operand))?;
+            if live_at_entry(index) {
+                claim(&mut reading, register, describe(operand))?;
+            }
+        }
+    }
+    for (operand, resolved) in statement.inputs.iter().zip(inputs) {
+        if let Some(register) = pinned(resolved) {
+            claim(&mut reading, register, describe(operand))?;
+        }
+    }
+
+    // Then the operands that may have any register of a class
+    let mut classes: Vec<(RegisterClass, &[Register])> = Vec::new();
+    for resolved in outputs.iter().chain(inputs) {
+        if let ConstraintKind::Class(class, candidates) = &resolved.kind {
+            if !classes.iter().any(|(seen, _)| same_class(*seen, *class)) {
+                classes.push((*class, candidates.as_slice()));
+            }
+        }
+    }
+    for (class, candidates) in classes {
+        let wants = |resolved: &ResolvedOperand| {
+            matches!(&resolved.kind, ConstraintKind::Class(c, _) if same_class(*c, class)) && !resolved.memory_alternative
+        };
+        let held = statement
+            .outputs
+            .iter()
+            .zip(outputs)
+            .enumerate()
+            .filter(|&(index, (_, resolved))| wants(resolved) && live_at_entry(index))
+            .map(|(_, pair)| pair);
+        let read: Vec<String> = statement
+            .inputs
+            .iter()
+            .zip(inputs)
+            .filter(|(_, resolved)| wants(resolved))
+            .chain(held)
+            .map(|(operand, _)| describe(operand))
+            .collect();
+        let written: Vec<String> = statement
+            .outputs
+            .iter()
+            .zip(outputs)
+            .filter(|(_, resolved)| wants(resolved))
+            .map(|(operand, _)| describe(operand))
+            .collect();
+        check_class(class, candidates, read, &reading, clobbered)?;
+        check_class(class, candidates, written, &writing, clobbered)?;
+    }
+
+    let convention = support.abi_handler.calling_convention();
+    let mut saved: Vec<Register> = Vec::new();
+    for register in clobbered.iter().chain(writing.iter().map(|(register, _)| register)) {
+        let callee_saved = convention.callee_saved.iter().any(|r| same_register(r, register));
+        if callee_saved && !saved.iter().any(|r| same_register(r, register)) {
+            saved.push(register.clone());
+        }
+    }
+    Ok(saved)
+}
+
+/// Add `register` to the ones in use, unless another operand has it
+fn claim(in_use: &mut Vec<(Register, String)>, register: Register, operand: String) -> Result<(), ConstraintError> {
+    if let Some((_, first)) = in_use.iter().find(|(r, _)| same_register(r, &register)) {
+        return Err(ConstraintError::RegisterConflict { register: register.name, first: first.clone(), second: operand });
+    }
+    in_use.push((register, operand));
+    Ok(())
+}
+
+/// Whether `operands` fit in `candidates` left over after the pinned and
+/// clobbered registers
+fn check_class(
+    class: RegisterClass,
+    candidates: &[Register],
+    operands: Vec<String>,
+    pinned: &[(Register, String)],
+    clobbered: &[Register],
+) -> Result<(), ConstraintError> {
+    let mut taken = Vec::new();
+    for candidate in candidates {
+        if let Some((_, operand)) = pinned.iter().find(|(r, _)| same_register(r, candidate)) {
+            taken.push(format!("{} by {}", candidate.name, operand));
+        } else if clobbered.iter().any(|r| same_register(r, candidate)) {
+            taken.push(format!("{} clobbered", candidate.name));
+        }
+    }
+    let available = candidates.len() - taken.len();
+    if operands.len() > available {
+        return Err(ConstraintError::OutOfRegisters { class, needed: operands.len(), available, operands, taken });
+    }
+    Ok(())
+}
+
+/// Sub-registers (`eax`, `ax`) count as the register they are part of
+fn same_register(a: &Register, b: &Register) -> bool {
+    a.number == b.number && same_class(a.class, b.class)
+}
+
+/// The ABI tables call SSE/NEON registers "float"; asm treats them alike
+fn same_class(a: RegisterClass, b: RegisterClass) -> bool {
+    a == b || matches!((a, b), (RegisterClass::Float, RegisterClass::Vector) | (RegisterClass::Vector, RegisterClass::Float))
+}
+
+/// An operand as it was written: `"=r"(x)`
+fn describe(operand: &AsmOperand) -> String {
+    let name = operand.name.as_ref().map_or(String::new(), |name| format!("[{}] ", name));
+    format!("{}\"{}\"({})", name, operand.constraint, operand.expression.trim())
+}
+
+fn constraint_kind(letter: char, support: &ArchitectureSupport) -> Result<ConstraintKind, ConstraintError> {
+    let arch = support.architecture;
+    let register = |name: &str| {
+        support
+            .asm_parser
+            .parse_register(name)
+            .map(ConstraintKind::Register)
+            .ok_or_else(|| ConstraintError::UnknownRegister(name.to_string()))
+    };
+    let class = |class: RegisterClass| {
+        let candidates = class_registers(support, class);
+        if candidates.is_empty() {
+            Err(ConstraintError::NoRegisters(letter))
+        } else {
+            Ok(ConstraintKind::Class(class, candidates))
+        }
+    };
+
+    match (arch, letter) {
+        (_, 'm' | 'o' | 'V' | '<' | '>') => Ok(ConstraintKind::Memory),
+        (_, 'i' | 'n' | 'I' | 'J' | 'K' | 'L' | 'M' | 'N' | 'E' | 'F' | 's') => Ok(ConstraintKind::Immediate),
+        (_, 'g' | 'X' | 'p') => Ok(ConstraintKind::Any),
+        (_, 'r') => class(RegisterClass::General),
+
+        (Architecture::X86_64, 'a') => register("rax"),
+        (Architecture::X86_64, 'b') => register("rbx"),
+        (Architecture::X86_64, 'c') => register("rcx"),
+        (Architecture::X86_64, 'd') => register("rdx"),
+        (Architecture::X86_64, 'S') => register("rsi"),
+        (Architecture::X86_64, 'D') => register("rdi"),
+        (Architecture::X86_64, 'q' | 'Q' | 'R' | 'l') => class(RegisterClass::General),
+        (Architecture::X86_64, 'x' | 'v' | 'Y') => class(RegisterClass::Float),
+        (Architecture::X86_64, 'e' | 'Z' | 'O') => Ok(ConstraintKind::Immediate),
+
+        (Architecture::AArch64, 'w' | 'x' | 'y') => class(RegisterClass::Vector),
+        (Architecture::AArch64, 'Q' | 'U') => Ok(ConstraintKind::Memory),
+        (Architecture::AArch64, 'S' | 'Z') => Ok(ConstraintKind::Immediate),
+
+        (Architecture::Arm, 'l' | 'h' | 'k') => class(RegisterClass::General),
+        (Architecture::Arm, 'w' | 't' | 'x') => class(RegisterClass::Float),
+        (Architecture::Arm, 'Q' | 'U') => Ok(ConstraintKind::Memory),
+        (Architecture::Arm, 'j') => Ok(ConstraintKind::Immediate),
+
+        (_, other) => Err(ConstraintError::UnknownConstraint(other, arch)),
+    }
+}
+
+/// Registers of `class` the ABI handler knows, without the reserved ones
+fn class_registers(support: &ArchitectureSupport, class: RegisterClass) -> Vec<Register> {
+    let convention = support.abi_handler.calling_convention();
+    let mut registers: Vec<Register> = Vec::new();
+    for register in convention
+        .caller_saved
+        .iter()
+        .chain(&convention.callee_saved)
+        .chain(&convention.parameter_registers)
+    {
+        if same_class(register.class, class) && !is_reserved(register, support.architecture) && !registers.iter().any(|r| r.name == register.name) {
+            registers.push(register.clone());
+        }
+    }
+    registers
+}
+
+/// Stack and frame pointers, plus aarch64's platform register
+fn is_reserved(register: &Register, arch: Architecture) -> bool {
+    let name = register.name.as_str();
+    match arch {
+        Architecture::X86_64 => matches!(name, "rsp" | "esp" | "sp" | "spl" | "rbp" | "ebp" | "bp" | "bpl"),
+        Architecture::AArch64 => matches!(name, "sp" | "x29" | "w29" | "x18" | "w18"),
+        Architecture::Arm => matches!(name, "sp" | "r13" | "r11" | "fp" | "pc" | "r15"),
+    }
+}
+
+/// The constraint as LLVM spells it. Single registers become `{name}` so
+/// LLVM allocates exactly what the ABI handler resolved; on x86 the 16-bit
+/// name, which LLVM widens or narrows to the operand's type like clang does.
+fn llvm_code(constraint: &str, resolved: &ResolvedOperand, arch: Architecture) -> String {
+    match &resolved.kind {
+        ConstraintKind::Register(register) if arch == Architecture::X86_64 => {
+            format!("{{{}}}", register.name.strip_prefix('r').unwrap_or(&register.name))
+        }
+        ConstraintKind::Register(register) => format!("{{{}}}", register.name),
+        ConstraintKind::Tied(index) => index.to_string(),
+        _ => {
+            let letters: String = constraint
+                .chars()
+                .filter(|c| !matches!(c, '=' | '+' | '&' | '%' | '?' | '!' | '*' | ','))
+                .collect();
+            // LLVM has no x86 `g` with memory on outputs; `rm` means the same
+            if arch == Architecture::X86_64 && letters == "g" {
+                "imr".to_string()
+            } else {
+                letters
+            }
+        }
+    }
+}
+
+/// Mnemonics the architecture's parser doesn't know, as warnings
+fn check_mnemonics(statement: &AsmStatement, support: &ArchitectureSupport) -> Vec<String> {
+    statement
+        .template
+        .split(['\n', ';'])
+        .filter_map(|line| {
+            let line = line.trim();
+            // `1: insn`, but not `%%gs:(%0)`
+            let line = match line.split_once(':') {
+                Some((label, rest)) if !label.contains(char::is_whitespace) && !label.contains('%') => rest.trim(),
+                _ => line,
+            };
+            let mnemonic = line.split_whitespace().next()?;
+            if mnemonic.starts_with(['.', '#', '%', '/']) {
+                return None;
+            }
+            let known = support.asm_parser.is_mnemonic_supported(mnemonic)
+                // AT&T operand-size suffixes: movl, addq
+                || (support.architecture == Architecture::X86_64
+                    && mnemonic
+                        .strip_suffix(['b', 'w', 'l', 'q'])
+                        .map_or(false, |base| support.asm_parser.is_mnemonic_supported(base)));
+            (!known).then(|| format!("`{}` is not known to the {} assembler tables", mnemonic, support.architecture))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum ConstraintError {
+    UnknownConstraint(char, Architecture),
+    UnknownRegister(String),
+    UnknownClobber(String),
+    /// The stack or frame pointer can't be clobbered
+    ReservedClobber(String),
+    /// A clobber names a register an operand is pinned to
+    ClobberConflict(String),
+    NoRegisters(char),
+    OutputWithoutModifier(String),
+    MisplacedModifier(String),
+    ImmediateOutput(String),
+    BadTie(String),
+    Empty(String),
+    /// Two operands live at the same time need the same register
+    RegisterConflict { register: String, first: String, second: String },
+    /// More operands of `class` live at once than registers left for them
+    OutOfRegisters { class: RegisterClass, needed: usize, available: usize, operands: Vec<String>, taken: Vec<String> },
+    /// Two inputs give an output its initial value
+    TiedTwice { output: String, first: String, second: String },
+    /// An input tied to an output that is in memory
+    TiedToMemory(String),
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstraintError::UnknownConstraint(letter, arch) => write!(f, "unknown constraint '{}' for {}", letter, arch),
+            ConstraintError::UnknownRegister(name) => write!(f, "unknown register '{}'", name),
+            ConstraintError::UnknownClobber(name) => write!(f, "unknown register name '{}' in asm clobbers", name),
+            ConstraintError::ReservedClobber(name) => write!(f, "the stack or frame pointer '{}' can't be clobbered", name),
+            ConstraintError::ClobberConflict(name) => {
+                write!(f, "'{}' is clobbered but also holds an operand", name)
+            }
+            ConstraintError::NoRegisters(letter) => write!(f, "no registers are available for constraint '{}'", letter),
+            ConstraintError::OutputWithoutModifier(constraint) => {
+                write!(f, "output constraint \"{}\" must start with '=' or '+'", constraint)
+            }
+            ConstraintError::MisplacedModifier(constraint) => write!(f, "misplaced '=' or '+' in \"{}\"", constraint),
+            ConstraintError::ImmediateOutput(constraint) => write!(f, "output constraint \"{}\" is an immediate", constraint),
+            ConstraintError::BadTie(constraint) => write!(f, "matching constraint \"{}\" doesn't name an output", constraint),
+            ConstraintError::Empty(constraint) => write!(f, "constraint \"{}\" allows nothing", constraint),
+            ConstraintError::RegisterConflict { register, first, second } => {
+                write!(f, "impossible constraints: {} and {} both need {} at the same time", first, second, register)
+            }
+            ConstraintError::OutOfRegisters { class, needed, available, operands, taken } => {
+                let class = format!("{:?}", class).to_lowercase();
+                write!(f, "impossible constraints: {} need {} {} registers at once, but only {} are left", operands.join(", "), needed, class, available)?;
+                if !taken.is_empty() {
+                    write!(f, " ({})", taken.join(", "))?;
+                }
+                Ok(())
+            }
+            ConstraintError::TiedTwice { output, first, second } => {
+                write!(f, "{} is tied to both {} and {}", output, first, second)
+            }
+            ConstraintError::TiedToMemory(operand) => write!(f, "{} is tied to an output in memory", operand),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let registry = ArchitectureRegistry::new();
+    let support = registry.get_support(Architecture::X86_64).unwrap();
+
+    let statement = parse_asm_statement(r#"asm("cpuid" : "=a"(a), "=b"(b), "=c"(c), "=d"(d) : "0"(leaf));"#)?;
+    let resolved = resolve(&statement, support)?;
+    // ={ax},={bx},={cx},={dx},0,~{dirflag},~{fpsr},~{flags}
+    println!("{}", resolved.constraints);
+    // rbx is callee-saved: the function saves it around the statement
+    assert_eq!(resolved.saved[0].name, "rbx");
+
+    // impossible constraints: "a"(x) and "a"(y) both need rax at the same time
+    let statement = parse_asm_statement(r#"asm("addl %1, %0" : "=r"(r) : "a"(x), "a"(y));"#)?;
+    if let Err(e) = resolve(&statement, support) {
+        eprintln!("error: {}", e);
+    }
+    Ok(())
+}
+*/
@@ -0,0 +1,353 @@
+// src/arch/intrinsics.rs
+//! Target-independent intrinsics
+//! Operations every target has a good instruction sequence for, but a
+//! different one: a lane-wise vector add, population count, prefetch,
+//! memory fence and byte swap, and on targets with mask registers the
+//! loop mask and masked loads and stores of a predicated loop. Passes emit an `IntrinsicCall` and each
+//! backend's `IntrinsicLowering` picks the sequence for the CPU features it
+//! is compiling for, so the vectorizer asks `max_lanes` how wide to go
+//! instead of matching on SIMD extensions, and a memcpy or byte-order idiom
+//! recognized once works on every target.
+//!
+//! A predicated loop runs its last, partial iteration as a vector
+//! iteration too: `LoopMask` turns the induction variable and the bound
+//! into a mask of the lanes still in range, an AVX-512 opmask or an SVE
+//! predicate, and the loads and stores of the body take it, so the lanes
+//! past the end neither read nor write memory. A conditional store takes
+//! the mask of its condition the same way.
+//!
+//! A lowering that has no instruction for an intrinsic on the given
+//! features returns a library call with the libgcc/compiler-rt name, never
+//! an error; errors are for calls that are malformed for the target, like
+//! a vector wider than its registers.
+
+use std::fmt;
+use super::{CPUFeatures, Instruction, Operand, Register, RegisterClass};
+
+/// Lane type of a vector intrinsic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ElementType {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ElementType {
+    pub fn bits(self) -> u32 {
+        match self {
+            ElementType::I8 => 8,
+            ElementType::I16 => 16,
+            ElementType::I32 | ElementType::F32 => 32,
+            ElementType::I64 | ElementType::F64 => 64,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, ElementType::F32 | ElementType::F64)
+    }
+
+    /// The LLVM type of a lane: `i32`, `double`
+    pub fn llvm_type(self) -> &'static str {
+        match self {
+            ElementType::I8 => "i8",
+            ElementType::I16 => "i16",
+            ElementType::I32 => "i32",
+            ElementType::I64 => "i64",
+            ElementType::F32 => "float",
+            ElementType::F64 => "double",
+        }
+    }
+}
+
+/// Ordering a fence establishes, as in C11's `atomic_thread_fence`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FenceOrdering {
+    Acquire,
+    Release,
+    AcqRel,
+    SeqCst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intrinsic {
+    /// `result = a + b` lane by lane, wrapping for integers
+    VectorAdd { element: ElementType, lanes: u32 },
+    /// Set bits of an integer of `bits` bits: `result = popcount(a)`
+    Popcount { bits: u32 },
+    /// A hint that `address` will be read, or written, soon. `locality` is
+    /// `__builtin_prefetch`'s: 0 (no reuse) to 3 (keep in every cache).
+    Prefetch { write: bool, locality: u8 },
+    Fence(FenceOrdering),
+    /// Reverse the bytes of an integer of `bits` bits: `result = bswap(a)`
+    ByteSwap { bits: u32 },
+    /// The mask of the lanes a vector loop still has to run:
+    /// `result[i] = index + i < limit`, unsigned, for 64-bit `index` and
+    /// `limit`. `result` is a predicate register.
+    LoopMask { element: ElementType, lanes: u32 },
+    /// `result[i] = address[i]` where `mask[i]` is set, else 0; lanes
+    /// masked off don't fault
+    MaskedLoad { element: ElementType, lanes: u32 },
+    /// `address[i] = value[i]` where `mask[i]` is set; memory under the
+    /// lanes masked off is left alone
+    MaskedStore { element: ElementType, lanes: u32 },
+}
+
+impl Intrinsic {
+    /// The LLVM intrinsic or instruction with the same meaning, for messages
+    /// and the LLVM pipeline
+    pub fn llvm_equivalent(&self) -> String {
+        match *self {
+            Intrinsic::VectorAdd { element, lanes } => {
+                let kind = if element.is_float() { "fadd" } else { "add" };
+                let lane = if element.is_float() { "f" } else { "i" };
+                format!("{} <{} x {}{}>", kind, lanes, lane, element.bits())
+            }
+            Intrinsic::Popcount { bits } => format!("llvm.ctpop.i{}", bits),
+            Intrinsic::Prefetch { .. } => "llvm.prefetch.p0".to_string(),
+            Intrinsic::Fence(ordering) => format!("fence {:?}", ordering).to_lowercase(),
+            Intrinsic::ByteSwap { bits } => format!("llvm.bswap.i{}", bits),
+            Intrinsic::LoopMask { lanes, .. } => format!("llvm.get.active.lane.mask.v{}i1.i64", lanes),
+            Intrinsic::MaskedLoad { element, lanes } => format!("llvm.masked.load <{} x {}>", lanes, element.llvm_type()),
+            Intrinsic::MaskedStore { element, lanes } => format!("llvm.masked.store <{} x {}>", lanes, element.llvm_type()),
+        }
+    }
+
+    /// The lane type and count of a masked intrinsic
+    pub fn masked(&self) -> Option<(ElementType, u32)> {
+        match *self {
+            Intrinsic::LoopMask { element, lanes } | Intrinsic::MaskedLoad { element, lanes } | Intrinsic::MaskedStore { element, lanes } => {
+                Some((element, lanes))
+            }
+            _ => None,
+        }
+    }
+
+    fn check(&self) -> Result<(), LoweringError> {
+        let valid = match *self {
+            Intrinsic::VectorAdd { lanes, .. }
+            | Intrinsic::LoopMask { lanes, .. }
+            | Intrinsic::MaskedLoad { lanes, .. }
+            | Intrinsic::MaskedStore { lanes, .. } => lanes.is_power_of_two() && lanes > 1,
+            Intrinsic::Popcount { bits } => matches!(bits, 8 | 16 | 32 | 64),
+            Intrinsic::Prefetch { locality, .. } => locality <= 3,
+            Intrinsic::Fence(_) => true,
+            Intrinsic::ByteSwap { bits } => matches!(bits, 16 | 32 | 64),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(LoweringError::Invalid(*self))
+        }
+    }
+}
+
+/// An intrinsic applied to operands the register allocator has placed
+#[derive(Debug, Clone)]
+pub struct IntrinsicCall {
+    pub intrinsic: Intrinsic,
+    /// `None` for `Prefetch`, `Fence` and `MaskedStore`
+    pub result: Option<Register>,
+    /// Registers, except the memory operand of `Prefetch` and the second
+    /// of `MaskedLoad` and `MaskedStore`, which are `mask, address` and
+    /// `mask, address, value`; `Fence` has none
+    pub arguments: Vec<Operand>,
+    /// One register of each class `IntrinsicLowering::scratch` asked for
+    pub scratch: Vec<Register>,
+}
+
+/// How a backend implements an `IntrinsicCall`
+#[derive(Debug, Clone)]
+pub enum Lowering {
+    /// These instructions, possibly none (a fence the memory model already
+    /// provides, a prefetch the target can't express)
+    Instructions(Vec<Instruction>),
+    /// Call this runtime library function with the arguments in the
+    /// calling convention's registers
+    Libcall(&'static str),
+}
+
+/// Implemented by each backend in `src/arch`
+pub trait IntrinsicLowering: Send + Sync {
+    /// Widest `VectorAdd` of `element` the target does in one instruction
+    /// with `features`; 1 if it can't add vectors of `element` at all
+    fn max_lanes(&self, element: ElementType, features: &CPUFeatures) -> u32;
+
+    /// Widest vector of `element` the target can run under a mask, for
+    /// `LoopMask`, `MaskedLoad` and `MaskedStore`; 0 without mask registers
+    fn max_masked_lanes(&self, _element: ElementType, _features: &CPUFeatures) -> u32 {
+        0
+    }
+
+    /// Registers the sequence for `intrinsic` needs besides its operands
+    fn scratch(&self, _intrinsic: &Intrinsic, _features: &CPUFeatures) -> Vec<RegisterClass> {
+        Vec::new()
+    }
+
+    /// The instructions for `call`, already checked by `lower`
+    fn lower_call(&self, call: &IntrinsicCall, features: &CPUFeatures) -> Result<Lowering, LoweringError>;
+}
+
+/// Lower `call` with `lowering` after checking the parts every target
+/// agrees on: the intrinsic itself, the operand counts, the vector width
+/// and the scratch registers
+pub fn lower(lowering: &dyn IntrinsicLowering, call: &IntrinsicCall, features: &CPUFeatures) -> Result<Lowering, LoweringError> {
+    let intrinsic = call.intrinsic;
+    intrinsic.check()?;
+    let (results, arguments) = match intrinsic {
+        Intrinsic::VectorAdd { .. } => (1, 2),
+        Intrinsic::Popcount { .. } | Intrinsic::ByteSwap { .. } => (1, 1),
+        Intrinsic::Prefetch { .. } => (0, 1),
+        Intrinsic::Fence(_) => (0, 0),
+        Intrinsic::LoopMask { .. } | Intrinsic::MaskedLoad { .. } => (1, 2),
+        Intrinsic::MaskedStore { .. } => (0, 3),
+    };
+    if call.result.is_some() as usize != results || call.arguments.len() != arguments {
+        return Err(LoweringError::Operands(intrinsic, format!(
+            "takes {} argument(s) and {} result(s), got {} and {}",
+            arguments, results, call.arguments.len(), call.result.is_some() as usize,
+        )));
+    }
+
+    if let Intrinsic::VectorAdd { element, lanes } = intrinsic {
+        let max = lowering.max_lanes(element, features);
+        if lanes > max {
+            return Err(LoweringError::TooWide { intrinsic, max_lanes: max });
+        }
+    }
+    if let Intrinsic::Prefetch { .. } = intrinsic {
+        if !matches!(call.arguments[0], Operand::Memory(_)) {
+            return Err(LoweringError::Operands(intrinsic, "takes a memory operand".to_string()));
+        }
+    }
+    if let Some((element, lanes)) = intrinsic.masked() {
+        let max = lowering.max_masked_lanes(element, features);
+        if max == 0 {
+            return Err(LoweringError::Unmasked(intrinsic));
+        }
+        if lanes > max {
+            return Err(LoweringError::TooWide { intrinsic, max_lanes: max });
+        }
+        let predicate = |operand: Option<&Operand>| matches!(operand, Some(Operand::Register(r)) if r.class == RegisterClass::Predicate);
+        let wrong = match intrinsic {
+            Intrinsic::LoopMask { .. } => {
+                (!call.result.as_ref().is_some_and(|r| r.class == RegisterClass::Predicate)).then_some("returns a predicate register")
+            }
+            _ if !predicate(call.arguments.first()) => Some("takes a predicate register as its mask"),
+            _ if !matches!(call.arguments[1], Operand::Memory(_)) => Some("takes a memory operand after the mask"),
+            _ => None,
+        };
+        if let Some(wrong) = wrong {
+            return Err(LoweringError::Operands(intrinsic, wrong.to_string()));
+        }
+    }
+
+    let wanted = lowering.scratch(&intrinsic, features);
+    let given: Vec<RegisterClass> = call.scratch.iter().map(|register| register.class).collect();
+    if given != wanted {
+        return Err(LoweringError::Operands(intrinsic, format!("needs scratch registers {:?}", wanted)));
+    }
+    lowering.lower_call(call, features)
+}
+
+/// The widest `VectorAdd` of `element` worth emitting: the target's limit,
+/// capped at `trip_count` lanes rounded down to a power of two. `None` if
+/// the target doesn't add vectors of `element`.
+pub fn vector_add_for(
+    lowering: &dyn IntrinsicLowering,
+    element: ElementType,
+    trip_count: u64,
+    features: &CPUFeatures,
+) -> Option<Intrinsic> {
+    let max = lowering.max_lanes(element, features) as u64;
+    let lanes = max.min(trip_count);
+    if lanes < 2 {
+        return None;
+    }
+    let lanes = 1u64 << (63 - lanes.leading_zeros());
+    Some(Intrinsic::VectorAdd { element, lanes: lanes as u32 })
+}
+
+/// Whether `features` lists `name`, as an extension or a feature
+pub fn has(features: &CPUFeatures, name: &str) -> bool {
+    features.extensions.iter().chain(&features.features).any(|f| f == name)
+}
+
+/// An instruction for a lowering to return
+pub fn instruction(mnemonic: &str, operands: Vec<Operand>) -> Instruction {
+    Instruction { mnemonic: mnemonic.to_string(), operands, prefixes: Vec::new(), suffixes: Vec::new() }
+}
+
+/// `register` renamed for a width or view, keeping its number and class:
+/// `eax` for `rax` at 32 bits, `v1.4s` for `q1`
+pub fn view(register: &Register, name: String, size: usize) -> Register {
+    Register { name, size, number: register.number, class: register.class }
+}
+
+/// The registers of `call`'s result and first argument
+pub fn result_and_source(call: &IntrinsicCall) -> Result<(&Register, &Register), LoweringError> {
+    let result = call.result.as_ref().ok_or(LoweringError::Operands(call.intrinsic, "has no result".to_string()))?;
+    match call.arguments.first() {
+        Some(Operand::Register(source)) => Ok((result, source)),
+        _ => Err(LoweringError::Operands(call.intrinsic, "takes register arguments".to_string())),
+    }
+}
+
+/// The register of `call`'s argument `index`
+pub fn argument(call: &IntrinsicCall, index: usize) -> Result<&Register, LoweringError> {
+    match call.arguments.get(index) {
+        Some(Operand::Register(register)) => Ok(register),
+        _ => Err(LoweringError::Operands(call.intrinsic, "takes register arguments".to_string())),
+    }
+}
+
+#[derive(Debug)]
+pub enum LoweringError {
+    /// Lane count not a power of two, popcount of 24 bits, and the like
+    Invalid(Intrinsic),
+    Operands(Intrinsic, String),
+    /// More lanes than the target's vectors hold; split it
+    TooWide { intrinsic: Intrinsic, max_lanes: u32 },
+    /// A masked intrinsic for a target without mask registers; keep a
+    /// scalar epilogue or a branch instead
+    Unmasked(Intrinsic),
+}
+
+impl fmt::Display for LoweringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoweringError::Invalid(intrinsic) => write!(f, "invalid intrinsic {:?}", intrinsic),
+            LoweringError::Operands(intrinsic, message) => write!(f, "{} {}", intrinsic.llvm_equivalent(), message),
+            LoweringError::TooWide { intrinsic, max_lanes } => {
+                write!(f, "{} is wider than the target's {} lanes", intrinsic.llvm_equivalent(), max_lanes)
+            }
+            LoweringError::Unmasked(intrinsic) => {
+                write!(f, "{} needs mask registers, which the target doesn't have", intrinsic.llvm_equivalent())
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn sum_arrays(support: &ArchitectureSupport, features: &CPUFeatures) -> Result<(), LoweringError> {
+    // for (i = 0; i < 1024; i++) a[i] += b[i];  with int a[], b[]
+    let Some(add) = vector_add_for(support.intrinsic_lowering.as_ref(), ElementType::I32, 1024, features) else {
+        return Ok(()); // leave the loop scalar
+    };
+    let (a, b) = (vector_register(0), vector_register(1));
+    let call = IntrinsicCall {
+        intrinsic: add,
+        result: Some(a.clone()),
+        arguments: vec![Operand::Register(a), Operand::Register(b)],
+        scratch: Vec::new(),
+    };
+    match lower(support.intrinsic_lowering.as_ref(), &call, features)? {
+        Lowering::Instructions(body) => println!("{} instruction(s) per iteration", body.len()),
+        Lowering::Libcall(name) => println!("call {}", name),
+    }
+    Ok(())
+}
+*/
@@ -6,6 +6,12 @@
 pub mod aarch64;  // ARM64/Apple Silicon
 pub mod x86_64;   // AMD64
 pub mod arm;      // ARM (32-bit)
+pub mod assembler;
+pub mod frame;
+pub mod inline_asm;
+pub mod intrinsics;
+pub mod schedule;
+pub mod x86_64_encoding;  // x86_64.isa tables
 
 use std::fmt;
 use std::str::FromStr;
@@ -40,6 +46,15 @@ impl Architecture {
         }
     }
     
+    /// The triple for this architecture on the host's OS: Apple's on macOS,
+    /// Linux's everywhere else
+    pub fn host_target_triple(&self) -> &'static str {
+        match self.apple_target_triple() {
+            Some(triple) if cfg!(target_os = "macos") => triple,
+            _ => self.default_target_triple(),
+        }
+    }
+
     /// Check if this architecture is big endian by default
     pub fn is_big_endian(&self) -> bool {
         match self {
@@ -138,6 +153,10 @@ pub struct ArchitectureSupport {
     pub instruction_encoder: Box<dyn InstructionEncoder>,
     /// Feature detection for this architecture
     pub feature_detector: Box<dyn FeatureDetector>,
+    /// Instruction sequences for the target-independent intrinsics
+    pub intrinsic_lowering: Box<dyn intrinsics::IntrinsicLowering>,
+    /// Instruction effects and per-CPU latencies for scheduling
+    pub scheduling_hazards: Box<dyn schedule::SchedulingHazards>,
 }
 
 /// Trait for assembly parsers
@@ -178,6 +197,16 @@ pub trait InstructionEncoder: Send + Sync {
     /// Encode a full assembly block
     fn encode_asm_block(&self, block: &AssemblyBlock) -> Result<Vec<u8>, EncodingError>;
     
+    /// Encode a whole AST, its blocks laid out in order. Encoders that
+    /// resolve labels across blocks override this; see `assembler`.
+    fn encode_asm(&self, ast: &AssemblyAST) -> Result<Vec<u8>, EncodingError> {
+        let mut code = Vec::new();
+        for block in &ast.blocks {
+            code.extend(self.encode_asm_block(block)?);
+        }
+        Ok(code)
+    }
+    
     /// Get the size of an encoded instruction
     fn instruction_size(&self, instruction: &Instruction) -> usize;
 }
@@ -236,7 +265,7 @@ pub struct Register {
 }
 
 /// Register classes
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RegisterClass {
     /// General purpose register
     General,
@@ -244,6 +273,8 @@ pub enum RegisterClass {
     Float,
     /// Vector register
     Vector,
+    /// Lane mask: an AVX-512 opmask (k0-k7) or an SVE predicate (p0-p15)
+    Predicate,
     /// Special/control register
     Special,
 }
@@ -352,10 +383,39 @@ pub struct CPUFeatures {
     pub vector_width: usize,
     /// Cache line size in bytes
     pub cache_line_size: usize,
+    /// L1 data cache size in bytes, per core
+    pub l1d_cache_size: usize,
+    /// L2 cache size in bytes
+    pub l2_cache_size: usize,
     /// Available instruction set features
     pub features: Vec<String>,
 }
 
+/// Cache line, L1 data and L2 sizes of the machine we run on, in bytes, as
+/// Linux reports them for the first CPU; `None` when sysfs doesn't say
+pub fn host_cache_sizes() -> Option<(usize, usize, usize)> {
+    let mut sizes = (0, 0, 0);
+    for entry in std::fs::read_dir("/sys/devices/system/cpu/cpu0/cache").ok()?.flatten() {
+        let read = |file: &str| std::fs::read_to_string(entry.path().join(file)).map(|s| s.trim().to_string()).unwrap_or_default();
+        // "32K", "1024K", "1M"
+        let size = read("size");
+        let size = match size.strip_suffix('K').or_else(|| size.strip_suffix('M')) {
+            Some(n) if size.ends_with('M') => n.parse::<usize>().unwrap_or(0) << 20,
+            Some(n) => n.parse::<usize>().unwrap_or(0) << 10,
+            None => size.parse().unwrap_or(0),
+        };
+        match (read("level").as_str(), read("type").as_str()) {
+            ("1", "Data" | "Unified") => {
+                sizes.0 = read("coherency_line_size").parse().unwrap_or(0);
+                sizes.1 = size;
+            }
+            ("2", "Data" | "Unified") => sizes.2 = size,
+            _ => {}
+        }
+    }
+    (sizes.0 > 0 && sizes.1 > 0 && sizes.2 > 0).then_some(sizes)
+}
+
 /// Structure type for ABI layout
 #[derive(Debug, Clone)]
 pub struct StructType {
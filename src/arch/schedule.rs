@@ -0,0 +1,399 @@
+// src/arch/schedule.rs
+//! Instruction scheduling within basic blocks
+//! In-order cores like the Cortex-A53 stall an instruction until its
+//! operands are ready, so a load followed directly by its use wastes the
+//! load's latency. `schedule_block` reorders a block's instructions with a
+//! list scheduler so that independent work fills those cycles:
+//!
+//! 1. Each architecture's `SchedulingHazards` says what an instruction
+//!    reads and writes (registers, the condition flags, memory) and which
+//!    `OpClass` it is. Dependences between instructions become the edges
+//!    of a DAG: a read after a write waits for the writer's latency, the
+//!    other orderings only keep their order.
+//! 2. Branches, barriers and system instructions are fixed points: nothing
+//!    moves across them. Loads and stores keep their order relative to
+//!    stores, as nothing here knows whether two addresses alias.
+//! 3. The scheduler simulates the CPU's `SchedModel` cycle by cycle,
+//!    issuing up to its issue width from the instructions whose operands
+//!    are ready and whose pipe is free, longest path to the end of the
+//!    block first, program order breaking ties.
+//!
+//! The new order is kept only if the model says the block gets faster.
+//! This is for code we generate; inline assembly keeps the order its
+//! author wrote. The latencies follow LLVM's scheduling models for the
+//! same cores. The Cortex-A72 issues out of order, so order matters less
+//! there, but its three-wide dispatch still favours starting long-latency
+//! operations early.
+
+use std::collections::HashMap;
+
+use super::{ArchitectureSupport, AssemblyBlock, Instruction, RegisterClass};
+
+/// What an instruction is, for its latency and the pipe it issues to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpClass {
+    /// Integer add, logic, move, compare and extend
+    Alu,
+    Multiply,
+    Divide,
+    Load,
+    Store,
+    Branch,
+    /// FP add, compare, convert, and moves between register files
+    Float,
+    FloatMultiply,
+    FloatDivide,
+    /// SIMD and SVE integer arithmetic, permutes and reductions
+    Vector,
+    /// Barriers and system register accesses
+    System,
+}
+
+/// Functional units instructions issue to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pipe {
+    Integer,
+    Multiply,
+    LoadStore,
+    Branch,
+    FpSimd,
+}
+
+/// Cost of one class of instructions on one CPU
+#[derive(Debug, Clone, Copy)]
+pub struct ClassCost {
+    pub class: OpClass,
+    /// Cycles from issue until a dependent instruction can issue
+    pub latency: u32,
+    pub pipe: Pipe,
+    /// Cycles before the pipe takes another instruction; 1 if pipelined
+    pub occupancy: u32,
+}
+
+/// Scheduling model of a CPU
+#[derive(Debug)]
+pub struct SchedModel {
+    /// As `--cpu` takes it, e.g. `cortex-a53`
+    pub name: &'static str,
+    /// Instructions issued per cycle
+    pub issue_width: u32,
+    pub in_order: bool,
+    /// Instances of each pipe
+    pub pipes: &'static [(Pipe, u32)],
+    pub costs: &'static [ClassCost],
+}
+
+impl SchedModel {
+    /// Cost of `class`; a one-cycle integer operation if the model doesn't
+    /// say
+    pub fn cost(&self, class: OpClass) -> ClassCost {
+        self.costs.iter().find(|cost| cost.class == class).copied().unwrap_or(ClassCost {
+            class,
+            latency: 1,
+            pipe: Pipe::Integer,
+            occupancy: 1,
+        })
+    }
+
+    fn pipe_count(&self, pipe: Pipe) -> usize {
+        self.pipes.iter().find(|(p, _)| *p == pipe).map_or(1, |(_, count)| (*count).max(1) as usize)
+    }
+}
+
+const fn cost(class: OpClass, latency: u32, pipe: Pipe, occupancy: u32) -> ClassCost {
+    ClassCost { class, latency, pipe, occupancy }
+}
+
+/// Cortex-A53: dual issue, in order, one load/store pipe and an
+/// unpipelined divider
+pub static CORTEX_A53: SchedModel = SchedModel {
+    name: "cortex-a53",
+    issue_width: 2,
+    in_order: true,
+    pipes: &[(Pipe::Integer, 2), (Pipe::Multiply, 1), (Pipe::LoadStore, 1), (Pipe::Branch, 1), (Pipe::FpSimd, 2)],
+    costs: &[
+        cost(OpClass::Alu, 1, Pipe::Integer, 1),
+        cost(OpClass::Multiply, 4, Pipe::Multiply, 1),
+        cost(OpClass::Divide, 4, Pipe::Multiply, 4),
+        cost(OpClass::Load, 4, Pipe::LoadStore, 1),
+        cost(OpClass::Store, 1, Pipe::LoadStore, 1),
+        cost(OpClass::Branch, 1, Pipe::Branch, 1),
+        cost(OpClass::Float, 6, Pipe::FpSimd, 1),
+        cost(OpClass::FloatMultiply, 6, Pipe::FpSimd, 1),
+        cost(OpClass::FloatDivide, 33, Pipe::FpSimd, 29),
+        cost(OpClass::Vector, 6, Pipe::FpSimd, 1),
+        cost(OpClass::System, 1, Pipe::Integer, 1),
+    ],
+};
+
+/// Cortex-A72: three-wide dispatch to two integer pipes, a multi-cycle
+/// integer pipe, two FP/SIMD pipes, and a load and a store pipe taken as
+/// two pipes that do either
+pub static CORTEX_A72: SchedModel = SchedModel {
+    name: "cortex-a72",
+    issue_width: 3,
+    in_order: false,
+    pipes: &[(Pipe::Integer, 2), (Pipe::Multiply, 1), (Pipe::LoadStore, 2), (Pipe::Branch, 1), (Pipe::FpSimd, 2)],
+    costs: &[
+        cost(OpClass::Alu, 1, Pipe::Integer, 1),
+        cost(OpClass::Multiply, 3, Pipe::Multiply, 1),
+        cost(OpClass::Divide, 19, Pipe::Multiply, 19),
+        cost(OpClass::Load, 4, Pipe::LoadStore, 1),
+        cost(OpClass::Store, 1, Pipe::LoadStore, 1),
+        cost(OpClass::Branch, 1, Pipe::Branch, 1),
+        cost(OpClass::Float, 5, Pipe::FpSimd, 1),
+        cost(OpClass::FloatMultiply, 5, Pipe::FpSimd, 1),
+        cost(OpClass::FloatDivide, 18, Pipe::FpSimd, 18),
+        cost(OpClass::Vector, 3, Pipe::FpSimd, 1),
+        cost(OpClass::System, 1, Pipe::Integer, 1),
+    ],
+};
+
+/// A register, by class and number so that views of it (`w3`, `x3`)
+/// are the same location, or the condition flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Register(RegisterClass, usize),
+    Flags,
+}
+
+/// What an instruction does, as far as reordering it goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Effects {
+    pub class: OpClass,
+    pub reads: Vec<Location>,
+    pub writes: Vec<Location>,
+    pub loads: bool,
+    pub stores: bool,
+    /// Nothing moves across it
+    pub barrier: bool,
+}
+
+impl Effects {
+    /// A fixed point in the block
+    pub fn barrier(class: OpClass) -> Effects {
+        Effects { class, reads: Vec::new(), writes: Vec::new(), loads: false, stores: false, barrier: true }
+    }
+}
+
+/// Trait for an architecture's scheduling hazards
+pub trait SchedulingHazards: Send + Sync {
+    /// Registers, flags and memory `instruction` reads and writes
+    fn effects(&self, instruction: &Instruction) -> Effects;
+
+    /// Scheduling models of the CPUs this architecture knows
+    fn models(&self) -> &'static [&'static SchedModel] {
+        &[]
+    }
+
+    /// The model named `cpu`, as `--cpu` spells it
+    fn model(&self, cpu: &str) -> Option<&'static SchedModel> {
+        self.models().iter().copied().find(|model| model.name.eq_ignore_ascii_case(cpu))
+    }
+}
+
+/// Hazards of an architecture nothing is scheduled for yet: every
+/// instruction stays where it is
+pub struct Unscheduled;
+
+impl SchedulingHazards for Unscheduled {
+    fn effects(&self, _instruction: &Instruction) -> Effects {
+        Effects::barrier(OpClass::System)
+    }
+}
+
+/// Cycles a block takes on the model, before and after scheduling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ScheduleStats {
+    pub before: u32,
+    pub after: u32,
+}
+
+/// Dependence DAG of a block: for each instruction, the earlier ones it
+/// waits for and how many cycles after their issue
+struct Dag {
+    costs: Vec<ClassCost>,
+    predecessors: Vec<Vec<(usize, u32)>>,
+    successors: Vec<Vec<(usize, u32)>>,
+}
+
+impl Dag {
+    fn new(effects: &[Effects], model: &SchedModel) -> Dag {
+        let costs: Vec<ClassCost> = effects.iter().map(|e| model.cost(e.class)).collect();
+        let mut predecessors = vec![Vec::new(); effects.len()];
+        let mut successors = vec![Vec::new(); effects.len()];
+        for (j, later) in effects.iter().enumerate() {
+            for (i, earlier) in effects[..j].iter().enumerate() {
+                let writes = |e: &Effects, locations: &[Location]| e.writes.iter().any(|l| locations.contains(l));
+                let mut latency = None;
+                // Read after write through a register or through memory
+                if writes(earlier, &later.reads) || (earlier.stores && later.loads) {
+                    latency = Some(costs[i].latency);
+                } else if writes(earlier, &later.writes) {
+                    latency = Some(1);
+                } else if earlier.barrier
+                    || later.barrier
+                    || writes(later, &earlier.reads)
+                    || (earlier.stores && later.stores)
+                    || (earlier.loads && later.stores)
+                {
+                    latency = Some(0);
+                }
+                if let Some(latency) = latency {
+                    predecessors[j].push((i, latency));
+                    successors[i].push((j, latency));
+                }
+            }
+        }
+        Dag { costs, predecessors, successors }
+    }
+
+    /// Longest latency path from each instruction to the end of the block
+    fn heights(&self) -> Vec<u32> {
+        let mut heights = vec![0; self.costs.len()];
+        for i in (0..self.costs.len()).rev() {
+            heights[i] = self.successors[i]
+                .iter()
+                .map(|&(s, latency)| latency + heights[s])
+                .max()
+                .unwrap_or(0)
+                .max(self.costs[i].latency);
+        }
+        heights
+    }
+}
+
+/// Issue slots and pipe occupancy, cycle by cycle
+struct Machine<'m> {
+    model: &'m SchedModel,
+    cycle: u32,
+    issued: u32,
+    /// Per pipe, the cycle each instance is free again
+    busy: HashMap<Pipe, Vec<u32>>,
+}
+
+impl<'m> Machine<'m> {
+    fn new(model: &'m SchedModel) -> Self {
+        Machine { model, cycle: 0, issued: 0, busy: HashMap::new() }
+    }
+
+    fn advance(&mut self) {
+        self.cycle += 1;
+        self.issued = 0;
+    }
+
+    fn free_instance(&self, pipe: Pipe) -> Option<usize> {
+        match self.busy.get(&pipe) {
+            Some(instances) => instances.iter().position(|&free| free <= self.cycle),
+            None => Some(0),
+        }
+    }
+
+    fn can_issue(&self, cost: &ClassCost) -> bool {
+        self.issued < self.model.issue_width.max(1) && self.free_instance(cost.pipe).is_some()
+    }
+
+    fn issue(&mut self, cost: &ClassCost) {
+        if let Some(instance) = self.free_instance(cost.pipe) {
+            let count = self.model.pipe_count(cost.pipe);
+            self.busy.entry(cost.pipe).or_insert_with(|| vec![0; count])[instance] = self.cycle + cost.occupancy.max(1);
+        }
+        self.issued += 1;
+    }
+}
+
+/// Cycles until every result of the block, issued in `order`, is ready
+fn cycles(dag: &Dag, model: &SchedModel, order: &[usize]) -> u32 {
+    let mut machine = Machine::new(model);
+    let mut issue_cycle = vec![0; order.len()];
+    let mut end = 0;
+    for &i in order {
+        let ready = dag.predecessors[i].iter().map(|&(p, latency)| issue_cycle[p] + latency).max().unwrap_or(0);
+        while machine.cycle < ready || !machine.can_issue(&dag.costs[i]) {
+            machine.advance();
+        }
+        machine.issue(&dag.costs[i]);
+        issue_cycle[i] = machine.cycle;
+        end = end.max(machine.cycle + dag.costs[i].latency.max(1));
+    }
+    end
+}
+
+/// List-schedule the DAG: each cycle, issue the ready instructions with
+/// the longest path to the end of the block first
+fn list_schedule(dag: &Dag, model: &SchedModel) -> Vec<usize> {
+    let count = dag.costs.len();
+    let heights = dag.heights();
+    let mut waiting: Vec<usize> = dag.predecessors.iter().map(Vec::len).collect();
+    let mut ready_at = vec![0; count];
+    let mut scheduled = vec![false; count];
+    let mut order = Vec::with_capacity(count);
+    let mut machine = Machine::new(model);
+    while order.len() < count {
+        let candidate = (0..count)
+            .filter(|&i| !scheduled[i] && waiting[i] == 0 && ready_at[i] <= machine.cycle)
+            .filter(|&i| machine.can_issue(&dag.costs[i]))
+            .max_by_key(|&i| (heights[i], std::cmp::Reverse(i)));
+        let Some(i) = candidate else {
+            machine.advance();
+            continue;
+        };
+        machine.issue(&dag.costs[i]);
+        scheduled[i] = true;
+        order.push(i);
+        for &(s, latency) in &dag.successors[i] {
+            waiting[s] -= 1;
+            ready_at[s] = ready_at[s].max(machine.cycle + latency);
+        }
+    }
+    order
+}
+
+/// Reorder `block` for `model`, keeping the new order only if it's faster
+pub fn schedule_block(hazards: &dyn SchedulingHazards, model: &SchedModel, block: &mut AssemblyBlock) -> ScheduleStats {
+    let effects: Vec<Effects> = block.instructions.iter().map(|i| hazards.effects(i)).collect();
+    let dag = Dag::new(&effects, model);
+    let original: Vec<usize> = (0..effects.len()).collect();
+    let before = cycles(&dag, model, &original);
+    let order = list_schedule(&dag, model);
+    let after = cycles(&dag, model, &order);
+    if after >= before {
+        return ScheduleStats { before, after: before };
+    }
+    let mut instructions: Vec<Option<Instruction>> = block.instructions.drain(..).map(Some).collect();
+    block.instructions = order.iter().filter_map(|&i| instructions[i].take()).collect();
+    ScheduleStats { before, after }
+}
+
+/// Schedule each of `blocks` for `cpu`; `None` if the architecture has no
+/// model by that name, in which case the blocks are left alone
+pub fn schedule_blocks(support: &ArchitectureSupport, cpu: &str, blocks: &mut [AssemblyBlock]) -> Option<ScheduleStats> {
+    let hazards = support.scheduling_hazards.as_ref();
+    let model = hazards.model(cpu)?;
+    let mut total = ScheduleStats::default();
+    for block in blocks {
+        let stats = schedule_block(hazards, model, block);
+        total.before += stats.before;
+        total.after += stats.after;
+    }
+    log::debug!("scheduled for {}: {} -> {} cycles", model.name, total.before, total.after);
+    Some(total)
+}
+
+// Example usage:
+/*
+fn lower_for(support: &ArchitectureSupport, calls: &[IntrinsicCall], features: &CPUFeatures, cpu: &str) -> Result<AssemblyBlock, LoweringError> {
+    let mut block = AssemblyBlock { instructions: Vec::new(), labels: Vec::new(), comments: Vec::new() };
+    for call in calls {
+        match lower(support.intrinsic_lowering.as_ref(), call, features)? {
+            Lowering::Instructions(body) => block.instructions.extend(body),
+            Lowering::Libcall(name) => block.instructions.push(instruction("bl", vec![Operand::Label(name.to_string())])),
+        }
+    }
+    // A `bl` is a fixed point; the sequences between calls are interleaved
+    if let Some(stats) = schedule_blocks(support, cpu, std::slice::from_mut(&mut block)) {
+        println!("{} -> {} cycles", stats.before, stats.after);
+    }
+    Ok(block)
+}
+*/
@@ -12,8 +12,14 @@ use crate::arch::{
     Architecture, ArchitectureSupport, AssemblyParser, ABIHandler,
     InstructionEncoder, FeatureDetector, AssemblyParseError, EncodingError,
     Register, RegisterClass, Operand, MemoryOperand, Instruction,
-    AssemblyBlock, AssemblyAST, CallingConvention, StructLayout, CPUFeatures,
+    AssemblyBlock, AssemblyAST, CallingConvention, StructLayout, CPUFeatures, host_cache_sizes,
 };
+use crate::arch::assembler::{self, FixupEncoder};
+use crate::arch::schedule;
+use crate::arch::intrinsics::{
+    self, ElementType, FenceOrdering, Intrinsic, IntrinsicCall, IntrinsicLowering, Lowering, LoweringError,
+};
+use crate::arch::x86_64_encoding::{self, Encoded, EncodingTables, TABLES};
 
 /// Create x86_64 architecture support
 pub fn create_support() -> ArchitectureSupport {
@@ -23,29 +29,37 @@ pub fn create_support() -> ArchitectureSupport {
         abi_handler: Box::new(X86_64ABIHandler::new()),
         instruction_encoder: Box::new(X86_64InstructionEncoder::new()),
         feature_detector: Box::new(X86_64FeatureDetector::new()),
+        intrinsic_lowering: Box::new(X86_64IntrinsicLowering),
+        scheduling_hazards: Box::new(schedule::Unscheduled),
     }
 }
 
-/// x86_64 assembly parser
+/// x86_64 assembly parser (Intel syntax)
 pub struct X86_64AssemblyParser {
     // Map of register names to registers
     registers: HashMap<String, Register>,
-    // Map of instruction mnemonics to their handlers
-    instruction_handlers: HashMap<String, InstructionHandler>,
+    // Instruction forms, shared with the encoder
+    tables: &'static EncodingTables,
 }
 
-type InstructionHandler = fn(&str, &[&str]) -> Result<Instruction, AssemblyParseError>;
+/// Prefixes written before the mnemonic
+const LEGACY_PREFIXES: &[&str] = &["lock", "rep", "repe", "repz", "repne", "repnz"];
+
+/// Memory operand size keywords, as in `qword ptr [rax]`
+const SIZE_KEYWORDS: &[(&str, usize)] = &[
+    ("byte", 8), ("word", 16), ("dword", 32), ("qword", 64), ("xmmword", 128), ("ymmword", 256),
+    ("zmmword", 512),
+];
 
 impl X86_64AssemblyParser {
     /// Create a new x86_64 assembly parser
     pub fn new() -> Self {
         let mut parser = Self {
             registers: HashMap::new(),
-            instruction_handlers: HashMap::new(),
+            tables: &TABLES,
         };
         
         parser.setup_registers();
-        parser.setup_instruction_handlers();
         
         parser
     }
@@ -155,6 +169,17 @@ impl X86_64AssemblyParser {
             });
         }
         
+        // AVX-512 opmask registers
+        for i in 0..8 {
+            let name = format!("k{}", i);
+            self.registers.insert(name.clone(), Register {
+                name,
+                size: 64,
+                number: i,
+                class: RegisterClass::Predicate,
+            });
+        }
+        
         // Control registers
         for i in 0..16 {
             let name = format!("cr{}", i);
@@ -178,242 +203,178 @@ impl X86_64AssemblyParser {
         }
     }
     
-    /// Set up instruction handlers
-    fn setup_instruction_handlers(&mut self) {
-        // Register instruction handlers for x86_64
-        self.instruction_handlers.insert("mov".to_string(), Self::handle_mov);
-        self.instruction_handlers.insert("add".to_string(), Self::handle_add);
-        self.instruction_handlers.insert("sub".to_string(), Self::handle_sub);
-        self.instruction_handlers.insert("and".to_string(), Self::handle_and);
-        self.instruction_handlers.insert("or".to_string(), Self::handle_or);
-        self.instruction_handlers.insert("xor".to_string(), Self::handle_xor);
-        self.instruction_handlers.insert("cmp".to_string(), Self::handle_cmp);
-        self.instruction_handlers.insert("test".to_string(), Self::handle_test);
-        self.instruction_handlers.insert("imul".to_string(), Self::handle_imul);
-        self.instruction_handlers.insert("idiv".to_string(), Self::handle_idiv);
-        self.instruction_handlers.insert("inc".to_string(), Self::handle_inc);
-        self.instruction_handlers.insert("dec".to_string(), Self::handle_dec);
-        self.instruction_handlers.insert("neg".to_string(), Self::handle_neg);
-        self.instruction_handlers.insert("not".to_string(), Self::handle_not);
-        self.instruction_handlers.insert("lea".to_string(), Self::handle_lea);
-        self.instruction_handlers.insert("push".to_string(), Self::handle_push);
-        self.instruction_handlers.insert("pop".to_string(), Self::handle_pop);
-        self.instruction_handlers.insert("jmp".to_string(), Self::handle_jmp);
-        self.instruction_handlers.insert("je".to_string(), Self::handle_je);
-        self.instruction_handlers.insert("jne".to_string(), Self::handle_jne);
-        self.instruction_handlers.insert("jl".to_string(), Self::handle_jl);
-        self.instruction_handlers.insert("jle".to_string(), Self::handle_jle);
-        self.instruction_handlers.insert("jg".to_string(), Self::handle_jg);
-        self.instruction_handlers.insert("jge".to_string(), Self::handle_jge);
-        self.instruction_handlers.insert("call".to_string(), Self::handle_call);
-        self.instruction_handlers.insert("ret".to_string(), Self::handle_ret);
-        self.instruction_handlers.insert("syscall".to_string(), Self::handle_syscall);
-        
-        // SIMD instructions
-        self.instruction_handlers.insert("movaps".to_string(), Self::handle_movaps);
-        self.instruction_handlers.insert("movups".to_string(), Self::handle_movups);
-        self.instruction_handlers.insert("movapd".to_string(), Self::handle_movapd);
-        self.instruction_handlers.insert("movupd".to_string(), Self::handle_movupd);
-        self.instruction_handlers.insert("addps".to_string(), Self::handle_addps);
-        self.instruction_handlers.insert("addpd".to_string(), Self::handle_addpd);
-        self.instruction_handlers.insert("subps".to_string(), Self::handle_subps);
-        self.instruction_handlers.insert("subpd".to_string(), Self::handle_subpd);
-        self.instruction_handlers.insert("mulps".to_string(), Self::handle_mulps);
-        self.instruction_handlers.insert("mulpd".to_string(), Self::handle_mulpd);
-        self.instruction_handlers.insert("divps".to_string(), Self::handle_divps);
-        self.instruction_handlers.insert("divpd".to_string(), Self::handle_divpd);
-    }
-    
-    // Handler functions for instructions
-    fn handle_mov(_mnemonic: &str, operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        // Check operand count
-        if operands.len() != 2 {
-            return Err(AssemblyParseError::SyntaxError(
-                format!("MOV instruction requires 2 operands, got {}", operands.len())
+    /// Parse one instruction: optional prefixes, the mnemonic, and operands
+    /// separated by commas outside brackets. An opmask on the destination,
+    /// `zmm0{k1}{z}`, goes in the suffixes.
+    fn parse_instruction(&self, text: &str) -> Result<Instruction, AssemblyParseError> {
+        let mut rest = text.trim();
+        let mut prefixes = Vec::new();
+        let mnemonic = loop {
+            let (word, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            rest = tail.trim();
+            let word = word.to_lowercase();
+            if LEGACY_PREFIXES.contains(&word.as_str()) && !rest.is_empty() {
+                prefixes.push(word);
+            } else {
+                break word;
+            }
+        };
+        
+        if !self.is_mnemonic_supported(&mnemonic) {
+            return Err(AssemblyParseError::UnknownMnemonic(
+                format!("Unknown mnemonic '{}'", mnemonic)
             ));
         }
         
-        // We're not actually encoding the instruction here, just creating the representation
-        let instruction = Instruction {
-            mnemonic: "mov".to_string(),
-            operands: Vec::new(), // Will be filled in by the parser
-            prefixes: Vec::new(),
-            suffixes: Vec::new(),
-        };
+        let mut operands = Vec::new();
+        let mut suffixes = Vec::new();
+        for (index, text) in split_operands(rest).into_iter().enumerate() {
+            let (decorations, text) = strip_decorations(text);
+            for decoration in decorations {
+                let opmask = decoration.strip_prefix('k').is_some_and(|k| matches!(k, "1" | "2" | "3" | "4" | "5" | "6" | "7"));
+                if index != 0 || !(opmask || decoration == "z") {
+                    return Err(AssemblyParseError::InvalidOperand(
+                        format!("Unexpected {{{}}}: only the destination takes an opmask {{k1}}-{{k7}} and {{z}}", decoration)
+                    ));
+                }
+                suffixes.push(format!("{{{}}}", decoration));
+            }
+            let (size, operand) = strip_size_keyword(text);
+            if let Some(size) = size {
+                if !text.contains('[') {
+                    return Err(AssemblyParseError::InvalidOperand(
+                        format!("Size keyword on a non-memory operand: {}", text)
+                    ));
+                }
+                suffixes.extend(x86_64_encoding::size_suffix(size).map(str::to_string));
+            }
+            operands.push(self.parse_operand(operand)?);
+        }
         
-        Ok(instruction)
+        Ok(Instruction {
+            mnemonic,
+            operands,
+            prefixes,
+            suffixes,
+        })
     }
     
-    fn handle_add(_mnemonic: &str, operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        // Check operand count
-        if operands.len() != 2 {
-            return Err(AssemblyParseError::SyntaxError(
-                format!("ADD instruction requires 2 operands, got {}", operands.len())
-            ));
+    /// Parse the inside of a memory operand: `[base + index*scale + disp]`
+    /// or `[rip + disp]`
+    fn parse_memory_operand(&self, operand: &str) -> Result<Operand, AssemblyParseError> {
+        // Extract the part inside brackets
+        let start = operand.find('[').unwrap();
+        let end = operand.rfind(']').unwrap();
+        let inner = operand[start+1..end].trim();
+        
+        let mut base = None;
+        let mut index = None;
+        let mut scale: u8 = 1;
+        let mut displacement: i64 = 0;
+        let mut pc_relative = false;
+        
+        // Split by + and -
+        let mut parts = Vec::new();
+        let mut current = String::new();
+        let mut positive = true;
+        
+        for c in inner.chars() {
+            if c == '+' || c == '-' {
+                if !current.trim().is_empty() {
+                    parts.push((positive, current.trim().to_string()));
+                    current = String::new();
+                }
+                positive = c == '+';
+            } else {
+                current.push(c);
+            }
         }
         
-        // We're not actually encoding the instruction here, just creating the representation
-        let instruction = Instruction {
-            mnemonic: "add".to_string(),
-            operands: Vec::new(), // Will be filled in by the parser
-            prefixes: Vec::new(),
-            suffixes: Vec::new(),
-        };
+        if !current.trim().is_empty() {
+            parts.push((positive, current.trim().to_string()));
+        }
         
-        Ok(instruction)
-    }
-    
-    // Other handler functions would be implemented here
-    fn handle_sub(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_and(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_or(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_xor(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_cmp(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_test(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_imul(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_idiv(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_inc(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_dec(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_neg(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_not(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_lea(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_push(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_pop(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_jmp(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_je(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_jne(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_jl(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_jle(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_jg(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_jge(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_call(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_ret(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_syscall(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_movaps(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_movups(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_movapd(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_movupd(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_addps(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_addpd(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_subps(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_subpd(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_mulps(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_mulpd(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_divps(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
-    }
-    
-    fn handle_divpd(_mnemonic: &str, _operands: &[&str]) -> Result<Instruction, AssemblyParseError> {
-        unimplemented!()
+        // Process each part
+        for (is_positive, part) in parts {
+            if part.contains('*') {
+                // This is an index*scale component
+                let index_scale: Vec<&str> = part.split('*').collect();
+                if index_scale.len() != 2 || !is_positive {
+                    return Err(AssemblyParseError::InvalidAddressingMode(
+                        format!("Invalid index*scale format: {}", part)
+                    ));
+                }
+                
+                let idx_reg = index_scale[0].trim();
+                if let Some(reg) = self.parse_register(idx_reg) {
+                    index = Some(reg);
+                } else {
+                    return Err(AssemblyParseError::InvalidRegister(
+                        format!("Invalid index register: {}", idx_reg)
+                    ));
+                }
+                
+                // Parse scale
+                match index_scale[1].trim() {
+                    "1" => scale = 1,
+                    "2" => scale = 2,
+                    "4" => scale = 4,
+                    "8" => scale = 8,
+                    _ => return Err(AssemblyParseError::InvalidAddressingMode(
+                        format!("Invalid scale factor: {}", index_scale[1])
+                    )),
+                }
+            } else if part.eq_ignore_ascii_case("rip") {
+                // RIP-relative addressing
+                pc_relative = true;
+            } else if let Some(reg) = self.parse_register(&part) {
+                // This is a register (base or index)
+                if base.is_none() {
+                    base = Some(reg);
+                } else if index.is_none() {
+                    index = Some(reg);
+                } else {
+                    return Err(AssemblyParseError::InvalidAddressingMode(
+                        format!("Too many registers in memory operand: {}", operand)
+                    ));
+                }
+            } else {
+                // This is a displacement
+                let disp_val = match parse_integer(&part) {
+                    Some(v) => v,
+                    None => {
+                        // Might be a symbolic reference
+                        if part.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+                            0 // Will be resolved by the linker
+                        } else {
+                            return Err(AssemblyParseError::InvalidAddressingMode(
+                                format!("Invalid displacement: {}", part)
+                            ));
+                        }
+                    }
+                };
+                
+                displacement = if is_positive {
+                    displacement.wrapping_add(disp_val)
+                } else {
+                    displacement.wrapping_sub(disp_val)
+                };
+            }
+        }
+        
+        Ok(Operand::Memory(MemoryOperand {
+            base,
+            index,
+            scale,
+            displacement,
+            pc_relative,
+        }))
     }
 }
 
 impl AssemblyParser for X86_64AssemblyParser {
+    /// Parse Intel-syntax assembly. Each label starts a new block, so a
+    /// block's labels are the addresses of its first instruction.
     fn parse(&self, code: &str) -> Result<AssemblyAST, AssemblyParseError> {
         let mut blocks = Vec::new();
         let mut current_block = AssemblyBlock {
@@ -441,7 +402,7 @@ impl AssemblyParser for X86_64AssemblyParser {
             }
             
             // Check for inline comments
-            let code_part = if let Some(comment_idx) = line.find(';') {
+            let mut code_part = if let Some(comment_idx) = line.find(';') {
                 let (code, comment) = line.split_at(comment_idx);
                 current_block.comments.push(comment.to_string());
                 code.trim()
@@ -449,46 +410,39 @@ impl AssemblyParser for X86_64AssemblyParser {
                 line
             };
             
-            if code_part.is_empty() {
-                continue;
-            }
-            
             // Handle directives
-            if code_part.starts_with('.') {
+            if code_part.starts_with('.') && !code_part.ends_with(':') {
                 global_directives.push(code_part.to_string());
                 continue;
             }
             
-            // Handle labels
-            if code_part.ends_with(':') {
-                let label = code_part[..code_part.len() - 1].trim().to_string();
-                current_block.labels.push(label);
-                continue;
+            // Handle labels, alone or before an instruction
+            if let Some((label, rest)) = code_part.split_once(':') {
+                let label = label.trim();
+                if !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '$') {
+                    if !current_block.instructions.is_empty() {
+                        blocks.push(std::mem::replace(&mut current_block, AssemblyBlock {
+                            instructions: Vec::new(),
+                            labels: Vec::new(),
+                            comments: Vec::new(),
+                        }));
+                    }
+                    current_block.labels.push(label.to_string());
+                    code_part = rest.trim();
+                }
             }
             
-            // Parse instruction
-            let mut parts = code_part.split_whitespace();
-            let mnemonic = match parts.next() {
-                Some(m) => m.to_lowercase(),
-                None => continue, // Skip line if no mnemonic
-            };
-            
-            // Check if mnemonic is supported
-            if !self.is_mnemonic_supported(&mnemonic) {
-                return Err(AssemblyParseError::UnknownMnemonic(
-                    format!("Unknown mnemonic '{}' at line {}", mnemonic, line_num)
-                ));
+            if code_part.is_empty() {
+                continue;
             }
             
-            // Parse operands
-            let operands_str = parts.collect::<Vec<_>>();
-            
-            // Use the appropriate instruction handler
-            let handler = self.instruction_handlers.get(&mnemonic).unwrap();
-            let instruction = handler(&mnemonic, &operands_str)
+            // Parse instruction
+            let instruction = self.parse_instruction(code_part)
                 .map_err(|e| match e {
                     AssemblyParseError::SyntaxError(msg) => 
                         AssemblyParseError::SyntaxError(format!("{} at line {}", msg, line_num)),
+                    AssemblyParseError::UnknownMnemonic(msg) => 
+                        AssemblyParseError::UnknownMnemonic(format!("{} at line {}", msg, line_num)),
                     AssemblyParseError::InvalidOperand(msg) => 
                         AssemblyParseError::InvalidOperand(format!("{} at line {}", msg, line_num)),
                     _ => e,
@@ -509,7 +463,7 @@ impl AssemblyParser for X86_64AssemblyParser {
     }
     
     fn is_mnemonic_supported(&self, mnemonic: &str) -> bool {
-        self.instruction_handlers.contains_key(&mnemonic.to_lowercase())
+        self.tables.supports(mnemonic)
     }
     
     fn parse_register(&self, reg_name: &str) -> Option<Register> {
@@ -532,36 +486,14 @@ impl AssemblyParser for X86_64AssemblyParser {
         }
         
         // Immediate operand (decimal, hex, octal, binary)
-        if operand.starts_with('$') || operand.starts_with('#') ||
-           (operand.starts_with('-') && operand[1..].chars().all(|c| c.is_digit(10))) ||
-           operand.chars().all(|c| c.is_digit(10)) {
-            
-            let value_str = if operand.starts_with('$') || operand.starts_with('#') {
-                &operand[1..]
-            } else {
-                operand
-            };
-            
-            let value = if value_str.starts_with("0x") || value_str.starts_with("0X") {
-                // Hexadecimal
-                i64::from_str_radix(&value_str[2..], 16)
-            } else if value_str.starts_with("0b") || value_str.starts_with("0B") {
-                // Binary
-                i64::from_str_radix(&value_str[2..], 2)
-            } else if value_str.starts_with('0') && value_str.len() > 1 {
-                // Octal
-                i64::from_str_radix(&value_str[1..], 8)
-            } else {
-                // Decimal
-                value_str.parse::<i64>()
-            };
-            
-            match value {
-                Ok(v) => return Ok(Operand::Immediate(v)),
-                Err(_) => return Err(AssemblyParseError::InvalidOperand(
+        let value_str = operand.strip_prefix('$').or_else(|| operand.strip_prefix('#')).unwrap_or(operand);
+        if value_str.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            return match parse_integer(value_str) {
+                Some(v) => Ok(Operand::Immediate(v)),
+                None => Err(AssemblyParseError::InvalidOperand(
                     format!("Invalid immediate value: {}", operand)
                 )),
-            }
+            };
         }
         
         // Memory operand
@@ -570,7 +502,7 @@ impl AssemblyParser for X86_64AssemblyParser {
         }
         
         // Label/symbol reference
-        if operand.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+        if operand.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '$') {
             return Ok(Operand::Label(operand.to_string()));
         }
         
@@ -580,121 +512,72 @@ impl AssemblyParser for X86_64AssemblyParser {
     }
 }
 
-fn parse_memory_operand(&self, operand: &str) -> Result<Operand, AssemblyParseError> {
-    // Parse x86_64 memory operand syntax:
-    // [base + index*scale + displacement]
-    
-    // Extract the part inside brackets
-    let start = operand.find('[').unwrap();
-    let end = operand.rfind(']').unwrap();
-    let inner = &operand[start+1..end].trim();
-    
-    let mut base = None;
-    let mut index = None;
-    let mut scale: u8 = 1;
-    let mut displacement = 0;
-    let mut pc_relative = false;
-    
-    // Split by + and -
-    let mut parts = Vec::new();
-    let mut current = String::new();
-    let mut positive = true;
-    
-    for c in inner.chars() {
-        if c == '+' {
-            if !current.is_empty() {
-                parts.push((positive, current.trim().to_string()));
-                current = String::new();
+/// Split operands on commas that aren't inside brackets
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
             }
-            positive = true;
-        } else if c == '-' {
-            if !current.is_empty() {
-                parts.push((positive, current.trim().to_string()));
-                current = String::new();
-            }
-            positive = false;
-        } else {
-            current.push(c);
+            _ => {}
         }
     }
-    
-    if !current.is_empty() {
-        parts.push((positive, current.trim().to_string()));
+    if !text[start..].trim().is_empty() {
+        operands.push(text[start..].trim());
     }
-    
-    // Process each part
-    for (is_positive, part) in parts {
-        if part.contains('*') {
-            // This is an index*scale component
-            let index_scale: Vec<&str> = part.split('*').collect();
-            if index_scale.len() != 2 {
-                return Err(AssemblyParseError::InvalidAddressingMode(
-                    format!("Invalid index*scale format: {}", part)
-                ));
-            }
-            
-            let idx_reg = index_scale[0].trim();
-            if let Some(reg) = self.parse_register(idx_reg) {
-                index = Some(reg);
-            } else {
-                return Err(AssemblyParseError::InvalidRegister(
-                    format!("Invalid index register: {}", idx_reg)
-                ));
-            }
-            
-            // Parse scale
-            match index_scale[1].trim() {
-                "1" => scale = 1,
-                "2" => scale = 2,
-                "4" => scale = 4,
-                "8" => scale = 8,
-                _ => return Err(AssemblyParseError::InvalidAddressingMode(
-                    format!("Invalid scale factor: {}", index_scale[1])
-                )),
-            }
-        } else if let Some(reg) = self.parse_register(&part) {
-            // This is a register (base or index)
-            if base.is_none() {
-                base = Some(reg);
-            } else if index.is_none() {
-                index = Some(reg);
-            } else {
-                return Err(AssemblyParseError::InvalidAddressingMode(
-                    format!("Too many registers in memory operand: {}", operand)
-                ));
+    operands
+}
+
+/// Remove the `{k1}` and `{z}` after an operand, returning them in order
+fn strip_decorations(operand: &str) -> (Vec<String>, &str) {
+    let mut rest = operand.trim_end();
+    let mut decorations = Vec::new();
+    while let Some(inner) = rest.strip_suffix('}') {
+        let Some(open) = inner.rfind('{') else { break };
+        decorations.insert(0, inner[open + 1..].trim().to_lowercase());
+        rest = inner[..open].trim_end();
+    }
+    (decorations, rest)
+}
+
+/// Remove a leading `qword ptr` and the like, returning the size in bits
+fn strip_size_keyword(operand: &str) -> (Option<usize>, &str) {
+    let lower = operand.to_lowercase();
+    for (keyword, size) in SIZE_KEYWORDS {
+        if let Some(rest) = lower.strip_prefix(keyword) {
+            if let Some(after) = rest.trim_start().strip_prefix("ptr") {
+                return (Some(*size), operand[operand.len() - after.len()..].trim());
             }
-        } else if part.starts_with("rip") {
-            // RIP-relative addressing
-            pc_relative = true;
-        } else {
-            // This is a displacement
-            let disp_val = match part.parse::<i64>() {
-                Ok(v) => v,
-                Err(_) => {
-                    // Might be a symbolic reference
-                    if part.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
-                        0 // Will be resolved by the linker
-                    } else {
-                        return Err(AssemblyParseError::InvalidAddressingMode(
-                            format!("Invalid displacement: {}", part)
-                        ));
-                    }
-                }
-            };
-            
-            displacement += if is_positive { disp_val } else { -disp_val };
         }
     }
-    
-    Ok(Operand::Memory(MemoryOperand {
-        base,
-        index,
-        scale,
-        displacement,
-        pc_relative,
-    }))
+    (None, operand)
 }
 
+/// Parse a decimal, 0x hex, 0b binary or 0-prefixed octal integer, with an
+/// optional sign. Values above `i64::MAX` wrap, as `0xffffffffffffffff` is -1.
+fn parse_integer(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits.trim_start()),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        u64::from_str_radix(binary, 2)
+    } else if digits.starts_with('0') && digits.len() > 1 {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<u64>()
+    }
+    .ok()? as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
 /// x86_64 ABI handler
 pub struct X86_64ABIHandler {
     // System V ABI calling convention (default for Unix-like systems)
@@ -1008,454 +891,65 @@ impl ABIHandler for X86_64ABIHandler {
     }
 }
 
-/// x86_64 instruction encoder
+/// x86_64 instruction encoder, driven by the forms in `x86_64.isa`
 pub struct X86_64InstructionEncoder {
     // Encoder tables
-    encoding_tables: Arc<EncodingTables>,
-}
-
-struct EncodingTables {
-    // Tables for instruction encoding
-    // Implementation omitted for brevity
+    encoding_tables: &'static EncodingTables,
 }
 
 impl X86_64InstructionEncoder {
     /// Create a new x86_64 instruction encoder
     pub fn new() -> Self {
         Self {
-            encoding_tables: Arc::new(EncodingTables::new()),
+            encoding_tables: &TABLES,
         }
     }
-}
-
-impl EncodingTables {
-    /// Create new encoding tables
-    fn new() -> Self {
-        Self {}
-    }
-    
-    /// Get REX prefix for 64-bit operation
-    fn get_rex_prefix(&self, w: bool, r: bool, x: bool, b: bool) -> u8 {
-        0x40 | (w as u8) << 3 | (r as u8) << 2 | (x as u8) << 1 | (b as u8)
-    }
     
-    /// Get ModR/M byte
-    fn get_modrm(&self, mod_val: u8, reg: u8, rm: u8) -> u8 {
-        (mod_val & 0x3) << 6 | (reg & 0x7) << 3 | (rm & 0x7)
+    /// Encode an instruction, leaving a branch to a label for the caller to
+    /// patch with the returned fixup
+    pub fn encode(&self, instruction: &Instruction) -> Result<Encoded, EncodingError> {
+        self.encoding_tables.encode(instruction)
     }
-    
-    /// Get SIB byte
-    fn get_sib(&self, scale: u8, index: u8, base: u8) -> u8 {
-        let scale_bits = match scale {
-            1 => 0,
-            2 => 1,
-            4 => 2,
-            8 => 3,
-            _ => 0, // Default to scale factor of 1
-        };
-        
-        (scale_bits & 0x3) << 6 | (index & 0x7) << 3 | (base & 0x7)
+}
+
+/// Branches to labels start as rel8 where the mnemonic has a rel8 form
+/// (`jmp`, `jcc`) and grow to rel32 when the target is out of reach
+impl FixupEncoder for X86_64InstructionEncoder {
+    fn encode_at(&self, instruction: &Instruction, level: usize) -> Result<Option<Encoded>, EncodingError> {
+        let short = self.encoding_tables.encode_short_branch(instruction)?;
+        match (level, short) {
+            (0, Some(short)) => Ok(Some(short)),
+            (0, None) | (1, Some(_)) => self.encode(instruction).map(Some),
+            _ => Ok(None),
+        }
     }
 }
 
 impl InstructionEncoder for X86_64InstructionEncoder {
     fn encode_instruction(&self, instruction: &Instruction) -> Result<Vec<u8>, EncodingError> {
-        // This is a simplified encoder that handles only basic instructions
-        // A full implementation would handle all x86_64 instructions with their encoding variants
-        
-        let mut encoded = Vec::new();
-        
-        match instruction.mnemonic.as_str() {
-            "mov" => {
-                if instruction.operands.len() != 2 {
-                    return Err(EncodingError::InvalidInstruction(
-                        "MOV requires 2 operands".to_string()
-                    ));
-                }
-                
-                match (&instruction.operands[0], &instruction.operands[1]) {
-                    (Operand::Register(dst), Operand::Register(src)) => {
-                        // MOV r64, r64
-                        if dst.size == 64 && src.size == 64 {
-                            // REX.W prefix for 64-bit operation
-                            encoded.push(self.encoding_tables.get_rex_prefix(
-                                true,  // W=1 for 64-bit
-                                (src.number & 0x8) != 0,  // R bit
-                                false, // X bit
-                                (dst.number & 0x8) != 0   // B bit
-                            ));
-                            
-                            // Opcode for MOV between registers
-                            encoded.push(0x89);
-                            
-                            // ModR/M byte
-                            encoded.push(self.encoding_tables.get_modrm(
-                                0b11,  // Mod=11 for register direct
-                                (src.number & 0x7) as u8,
-                                (dst.number & 0x7) as u8
-                            ));
-                        } else {
-                            // Handle other sizes
-                            return Err(EncodingError::UnsupportedFeature(
-                                "Register size combination not supported".to_string()
-                            ));
-                        }
-                    },
-                    (Operand::Register(dst), Operand::Immediate(imm)) => {
-                        // MOV r64, imm64
-                        if dst.size == 64 {
-                            // REX.W prefix for 64-bit operation
-                            encoded.push(self.encoding_tables.get_rex_prefix(
-                                true,  // W=1 for 64-bit
-                                false, // R bit
-                                false, // X bit
-                                (dst.number & 0x8) != 0  // B bit
-                            ));
-                            
-                            // Opcode for MOV immediate to register
-                            encoded.push(0xB8 + (dst.number & 0x7) as u8);
-                            
-                            // Immediate value (64-bit)
-                            let imm_bytes = (*imm as u64).to_le_bytes();
-                            encoded.extend_from_slice(&imm_bytes);
-                        } else {
-                            // Handle other sizes
-                            return Err(EncodingError::UnsupportedFeature(
-                                "Register size not supported".to_string()
-                            ));
-                        }
-                    },
-                    (Operand::Register(dst), Operand::Memory(mem)) => {
-                        // MOV r64, [mem]
-                        if dst.size == 64 {
-                            // REX.W prefix for 64-bit operation
-                            encoded.push(self.encoding_tables.get_rex_prefix(
-                                true,  // W=1 for 64-bit
-                                (dst.number & 0x8) != 0,  // R bit
-                                mem.index.as_ref().map_or(false, |r| (r.number & 0x8) != 0),  // X bit
-                                mem.base.as_ref().map_or(false, |r| (r.number & 0x8) != 0)    // B bit
-                            ));
-                            
-                            // Opcode for MOV from memory to register
-                            encoded.push(0x8B);
-                            
-                            // Encode the memory operand (simplified)
-                            self.encode_memory_operand(&mut encoded, dst.number as u8, mem)?;
-                        } else {
-                            // Handle other sizes
-                            return Err(EncodingError::UnsupportedFeature(
-                                "Register size not supported".to_string()
-                            ));
-                        }
-                    },
-                    (Operand::Memory(mem), Operand::Register(src)) => {
-                        // MOV [mem], r64
-                        if src.size == 64 {
-                            // REX.W prefix for 64-bit operation
-                            encoded.push(self.encoding_tables.get_rex_prefix(
-                                true,  // W=1 for 64-bit
-                                (src.number & 0x8) != 0,  // R bit
-                                mem.index.as_ref().map_or(false, |r| (r.number & 0x8) != 0),  // X bit
-                                mem.base.as_ref().map_or(false, |r| (r.number & 0x8) != 0)    // B bit
-                            ));
-                            
-                            // Opcode for MOV from register to memory
-                            encoded.push(0x89);
-                            
-                            // Encode the memory operand (simplified)
-                            self.encode_memory_operand(&mut encoded, src.number as u8, mem)?;
-                        } else {
-                            // Handle other sizes
-                            return Err(EncodingError::UnsupportedFeature(
-                                "Register size not supported".to_string()
-                            ));
-                        }
-                    },
-                    _ => {
-                        return Err(EncodingError::InvalidOperand(
-                            "Unsupported operand combination for MOV".to_string()
-                        ));
-                    }
-                }
-            },
-            "add" => {
-                if instruction.operands.len() != 2 {
-                    return Err(EncodingError::InvalidInstruction(
-                        "ADD requires 2 operands".to_string()
-                    ));
-                }
-                
-                match (&instruction.operands[0], &instruction.operands[1]) {
-                    (Operand::Register(dst), Operand::Register(src)) => {
-                        // ADD r64, r64
-                        if dst.size == 64 && src.size == 64 {
-                            // REX.W prefix for 64-bit operation
-                            encoded.push(self.encoding_tables.get_rex_prefix(
-                                true,  // W=1 for 64-bit
-                                (src.number & 0x8) != 0,  // R bit
-                                false, // X bit
-                                (dst.number & 0x8) != 0   // B bit
-                            ));
-                            
-                            // Opcode for ADD between registers
-                            encoded.push(0x01);
-                            
-                            // ModR/M byte
-                            encoded.push(self.encoding_tables.get_modrm(
-                                0b11,  // Mod=11 for register direct
-                                (src.number & 0x7) as u8,
-                                (dst.number & 0x7) as u8
-                            ));
-                        } else {
-                            // Handle other sizes
-                            return Err(EncodingError::UnsupportedFeature(
-                                "Register size combination not supported".to_string()
-                            ));
-                        }
-                    },
-                    // More ADD variants would be implemented here
-                    _ => {
-                        return Err(EncodingError::InvalidOperand(
-                            "Unsupported operand combination for ADD".to_string()
-                        ));
-                    }
-                }
-            },
-            // More instructions would be implemented here
-            _ => {
-                return Err(EncodingError::InvalidInstruction(
-                    format!("Instruction {} not implemented", instruction.mnemonic)
-                ));
-            }
+        let encoded = self.encode(instruction)?;
+        if let Some(fixup) = encoded.fixup {
+            return Err(EncodingError::InvalidOperand(
+                format!("branch to label '{}' needs encode_asm_block or a fixup", fixup.label)
+            ));
         }
-        
-        Ok(encoded)
+        Ok(encoded.bytes)
     }
     
+    /// Encode a block on its own; its branches may only target its labels
     fn encode_asm_block(&self, block: &AssemblyBlock) -> Result<Vec<u8>, EncodingError> {
-        let mut encoded = Vec::new();
-        
-        // This is a simplified implementation that doesn't handle labels and jumps correctly
-        // A full implementation would need to resolve labels and calculate jump offsets
-        
-        for instruction in &block.instructions {
-            let inst_bytes = self.encode_instruction(instruction)?;
-            encoded.extend_from_slice(&inst_bytes);
-        }
-        
-        Ok(encoded)
+        assembler::assemble(self, std::slice::from_ref(block)).map(|assembled| assembled.code)
     }
     
-    fn instruction_size(&self, instruction: &Instruction) -> usize {
-        // For simplicity, we'll estimate sizes very approximately
-        // A full implementation would calculate exact instruction sizes
-        
-        match instruction.mnemonic.as_str() {
-            // Typically 2-3 bytes for register-register ops, 3-7 for immediate/memory ops
-            "mov" | "add" | "sub" | "and" | "or" | "xor" | "cmp" | "test" => {
-                match instruction.operands.as_slice() {
-                    [Operand::Register(_), Operand::Register(_)] => 3,
-                    [Operand::Register(_), Operand::Immediate(_)] => 5,
-                    [Operand::Register(_), Operand::Memory(_)] |
-                    [Operand::Memory(_), Operand::Register(_)] => 5,
-                    _ => 3, // Default estimate
-                }
-            },
-            // Jump instructions
-            "jmp" | "je" | "jne" | "jl" | "jle" | "jg" | "jge" => {
-                match instruction.operands.as_slice() {
-                    [Operand::Label(_)] => 5, // Typically 5 bytes for near jumps
-                    _ => 2, // Short jumps
-                }
-            },
-            // Call instruction
-            "call" => 5, // Typically 5 bytes
-            // String instructions usually 1-3 bytes
-            "movs" | "cmps" | "stos" | "lods" | "scas" => 3,
-            // Default for other instructions
-            _ => 3,
-        }
+    /// Encode every block, with branches between them resolved
+    fn encode_asm(&self, ast: &AssemblyAST) -> Result<Vec<u8>, EncodingError> {
+        assembler::assemble(self, &ast.blocks).map(|assembled| assembled.code)
     }
     
-    // Helper method to encode memory operands
-    fn encode_memory_operand(&self, encoded: &mut Vec<u8>, reg: u8, mem: &MemoryOperand) -> Result<(), EncodingError> {
-        // This is a simplified implementation that doesn't handle all addressing modes
-        
-        if let Some(base) = &mem.base {
-            let base_reg = (base.number & 0x7) as u8;
-            
-            if let Some(index) = &mem.index {
-                // [base + index*scale + disp]
-                let index_reg = (index.number & 0x7) as u8;
-                
-                // Always use SIB byte when there's an index register
-                
-                if mem.displacement == 0 {
-                    // [base + index*scale]
-                    encoded.push(self.encoding_tables.get_modrm(
-                        0b00,    // Mod=00
-                        reg & 0x7, // Reg field
-                        0b100    // R/M=4 (SIB)
-                    ));
-                    
-                    encoded.push(self.encoding_tables.get_sib(
-                        mem.scale,
-                        index_reg,
-                        base_reg
-                    ));
-                } else if mem.displacement >= -128 && mem.displacement <= 127 {
-                    // [base + index*scale + disp8]
-                    encoded.push(self.encoding_tables.get_modrm(
-                        0b01,    // Mod=01
-                        reg & 0x7, // Reg field
-                        0b100    // R/M=4 (SIB)
-                    ));
-                    
-                    encoded.push(self.encoding_tables.get_sib(
-                        mem.scale,
-                        index_reg,
-                        base_reg
-                    ));
-                    
-                    encoded.push(mem.displacement as u8);
-                } else {
-                    // [base + index*scale + disp32]
-                    encoded.push(self.encoding_tables.get_modrm(
-                        0b10,    // Mod=10
-                        reg & 0x7, // Reg field
-                        0b100    // R/M=4 (SIB)
-                    ));
-                    
-                    encoded.push(self.encoding_tables.get_sib(
-                        mem.scale,
-                        index_reg,
-                        base_reg
-                    ));
-                    
-                    // Displacement as 32-bit immediate
-                    let disp_bytes = (mem.displacement as i32).to_le_bytes();
-                    encoded.extend_from_slice(&disp_bytes);
-                }
-            } else {
-                // [base + disp]
-                
-                if base_reg == 0b100 {
-                    // Special case: need SIB byte when base is RSP/ESP (R/M=4)
-                    
-                    if mem.displacement == 0 {
-                        // [rsp]
-                        encoded.push(self.encoding_tables.get_modrm(
-                            0b00,    // Mod=00
-                            reg & 0x7, // Reg field
-                            0b100    // R/M=4 (SIB)
-                        ));
-                        
-                        encoded.push(self.encoding_tables.get_sib(
-                            1,      // Scale=1
-                            0b100,  // Index=4 (no index)
-                            0b100   // Base=RSP
-                        ));
-                    } else if mem.displacement >= -128 && mem.displacement <= 127 {
-                        // [rsp + disp8]
-                        encoded.push(self.encoding_tables.get_modrm(
-                            0b01,    // Mod=01
-                            reg & 0x7, // Reg field
-                            0b100    // R/M=4 (SIB)
-                        ));
-                        
-                        encoded.push(self.encoding_tables.get_sib(
-                            1,      // Scale=1
-                            0b100,  // Index=4 (no index)
-                            0b100   // Base=RSP
-                        ));
-                        
-                        encoded.push(mem.displacement as u8);
-                    } else {
-                        // [rsp + disp32]
-                        encoded.push(self.encoding_tables.get_modrm(
-                            0b10,    // Mod=10
-                            reg & 0x7, // Reg field
-                            0b100    // R/M=4 (SIB)
-                        ));
-                        
-                        encoded.push(self.encoding_tables.get_sib(
-                            1,      // Scale=1
-                            0b100,  // Index=4 (no index)
-                            0b100   // Base=RSP
-                        ));
-                        
-                        // Displacement as 32-bit immediate
-                        let disp_bytes = (mem.displacement as i32).to_le_bytes();
-                        encoded.extend_from_slice(&disp_bytes);
-                    }
-                } else {
-                    // Normal case: no SIB needed
-                    
-                    if mem.displacement == 0 && base_reg != 0b101 {
-                        // [reg]
-                        encoded.push(self.encoding_tables.get_modrm(
-                            0b00,    // Mod=00
-                            reg & 0x7, // Reg field
-                            base_reg // R/M field
-                        ));
-                    } else if mem.displacement >= -128 && mem.displacement <= 127 {
-                        // [reg + disp8]
-                        encoded.push(self.encoding_tables.get_modrm(
-                            0b01,    // Mod=01
-                            reg & 0x7, // Reg field
-                            base_reg // R/M field
-                        ));
-                        
-                        encoded.push(mem.displacement as u8);
-                    } else {
-                        // [reg + disp32]
-                        encoded.push(self.encoding_tables.get_modrm(
-                            0b10,    // Mod=10
-                            reg & 0x7, // Reg field
-                            base_reg // R/M field
-                        ));
-                        
-                        // Displacement as 32-bit immediate
-                        let disp_bytes = (mem.displacement as i32).to_le_bytes();
-                        encoded.extend_from_slice(&disp_bytes);
-                    }
-                }
-            }
-        } else {
-            // [disp] or RIP-relative addressing
-            
-            if mem.pc_relative {
-                // [RIP + disp32] - RIP relative addressing
-                encoded.push(self.encoding_tables.get_modrm(
-                    0b00,    // Mod=00
-                    reg & 0x7, // Reg field
-                    0b101    // R/M=5 (RIP-relative)
-                ));
-                
-                // Displacement as 32-bit immediate
-                let disp_bytes = (mem.displacement as i32).to_le_bytes();
-                encoded.extend_from_slice(&disp_bytes);
-            } else {
-                // [disp32] - Absolute addressing
-                encoded.push(self.encoding_tables.get_modrm(
-                    0b00,    // Mod=00
-                    reg & 0x7, // Reg field
-                    0b100    // R/M=4 (SIB)
-                ));
-                
-                encoded.push(self.encoding_tables.get_sib(
-                    1,      // Scale=1
-                    0b100,  // Index=4 (no index)
-                    0b101   // Base=5 (no base, disp32 only)
-                ));
-                
-                // Displacement as 32-bit immediate
-                let disp_bytes = (mem.displacement as i32).to_le_bytes();
-                encoded.extend_from_slice(&disp_bytes);
-            }
-        }
-        
-        Ok(())
+    /// Exact size of the encoding, with a 32-bit displacement for branches
+    /// to labels; 0 if the instruction can't be encoded
+    fn instruction_size(&self, instruction: &Instruction) -> usize {
+        self.encode(instruction).map(|encoded| encoded.bytes.len()).unwrap_or(0)
     }
 }
 
@@ -1506,20 +1000,41 @@ impl X86_64FeatureDetector {
         features.push("movbe".to_string());
         features.push("rdrand".to_string());
         
+        // AVX-512 only when the host has it, since the masked code the
+        // vectorizer plans for it won't run anywhere else
+        let avx512 = Self::has_avx512();
+        if avx512 {
+            extensions.push("avx512f".to_string());
+            #[cfg(target_arch = "x86_64")]
+            if std::is_x86_feature_detected!("avx512bw") {
+                extensions.push("avx512bw".to_string());
+            }
+        }
+        
+        // The host's caches when we run on this architecture, else common sizes
+        let (line, l1d, l2) = host_cache_sizes().filter(|_| cfg!(target_arch = "x86_64")).unwrap_or((64, 32 * 1024, 1024 * 1024));
+
         CPUFeatures {
             architecture: Architecture::X86_64,
             extensions,
-            vector_width: 32, // 256-bit (AVX2)
-            cache_line_size: 64, // Common cache line size
+            vector_width: if avx512 { 64 } else { 32 }, // 512-bit (AVX-512) or 256-bit (AVX2)
+            cache_line_size: line,
+            l1d_cache_size: l1d,
+            l2_cache_size: l2,
             features,
         }
     }
     
     /// Detect if AVX-512 is supported
     fn has_avx512() -> bool {
-        // In a real implementation, we would use CPUID to check for AVX-512 support
-        // For this simplified version, we'll just return false
-        false
+        #[cfg(target_arch = "x86_64")]
+        {
+            std::is_x86_feature_detected!("avx512f")
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            false
+        }
     }
     
     /// Get optimization flags for various instruction set extensions
@@ -1565,6 +1080,222 @@ impl FeatureDetector for X86_64FeatureDetector {
     }
 }
 
+/// x86_64 sequences for `arch::intrinsics`
+pub struct X86_64IntrinsicLowering;
+
+impl X86_64IntrinsicLowering {
+    /// `register` under its name at `bits` bits: `eax`, `r9w`, `sil`
+    fn gpr(register: &Register, bits: u32) -> Register {
+        const LEGACY: [&str; 8] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+        let number = register.number;
+        let name = match (LEGACY.get(number), bits) {
+            (Some(base), 8) if number < 4 => format!("{}l", &base[..1]),
+            (Some(base), 8) => format!("{}l", base),
+            (Some(base), 16) => base.to_string(),
+            (Some(base), 32) => format!("e{}", base),
+            (Some(base), _) => format!("r{}", base),
+            (None, 8) => format!("r{}b", number),
+            (None, 16) => format!("r{}w", number),
+            (None, 32) => format!("r{}d", number),
+            (None, _) => format!("r{}", number),
+        };
+        intrinsics::view(register, name, bits as usize)
+    }
+
+    /// The SIMD register numbered like `register`, wide enough for `bits`
+    fn simd(register: &Register, bits: u32) -> Operand {
+        let (prefix, size) = match bits {
+            0..=128 => ("xmm", 128),
+            129..=256 => ("ymm", 256),
+            _ => ("zmm", 512),
+        };
+        Operand::Register(intrinsics::view(register, format!("{}{}", prefix, register.number), size))
+    }
+
+    fn vector_add(call: &IntrinsicCall, element: ElementType, lanes: u32, features: &CPUFeatures) -> Result<Lowering, LoweringError> {
+        let bits = element.bits() * lanes;
+        let (result, a) = intrinsics::result_and_source(call)?;
+        let b = intrinsics::argument(call, 1)?;
+        let mnemonic = match element {
+            ElementType::I8 => "paddb",
+            ElementType::I16 => "paddw",
+            ElementType::I32 => "paddd",
+            ElementType::I64 => "paddq",
+            ElementType::F32 => "addps",
+            ElementType::F64 => "addpd",
+        };
+        let (result, a, b) = (Self::simd(result, bits), Self::simd(a, bits), Self::simd(b, bits));
+
+        // VEX/EVEX forms take a separate destination; 256 bits and up need them
+        if intrinsics::has(features, "avx") || bits > 128 {
+            return Ok(Lowering::Instructions(vec![intrinsics::instruction(&format!("v{}", mnemonic), vec![result, a, b])]));
+        }
+        let same = |x: &Operand, y: &Operand| match (x, y) {
+            (Operand::Register(x), Operand::Register(y)) => x.number == y.number,
+            _ => false,
+        };
+        let mut body = Vec::new();
+        let other = if same(&result, &a) {
+            b
+        } else if same(&result, &b) {
+            // Addition commutes
+            a
+        } else {
+            let copy = if element.is_float() { "movaps" } else { "movdqa" };
+            body.push(intrinsics::instruction(copy, vec![result.clone(), a]));
+            b
+        };
+        body.push(intrinsics::instruction(mnemonic, vec![result, other]));
+        Ok(Lowering::Instructions(body))
+    }
+
+    /// `min(limit - index, lanes)` low bits set in the opmask `result`, none
+    /// once `index` reaches `limit`
+    fn loop_mask(call: &IntrinsicCall, lanes: u32) -> Result<Lowering, LoweringError> {
+        let instruction = intrinsics::instruction;
+        let result = call.result.as_ref().ok_or(LoweringError::Operands(call.intrinsic, "has no result".to_string()))?;
+        let (index, limit) = (intrinsics::argument(call, 0)?, intrinsics::argument(call, 1)?);
+        let q = |register: &Register| Operand::Register(Self::gpr(register, 64));
+        let (count, bits) = (&call.scratch[0], &call.scratch[1]);
+        // mov leaves the flags of the sub for the cmovb
+        let mut body = vec![
+            instruction("mov", vec![q(count), q(limit)]),
+            instruction("sub", vec![q(count), q(index)]),
+            instruction("mov", vec![q(bits), Operand::Immediate(0)]),
+            instruction("cmovb", vec![q(count), q(bits)]),
+            instruction("mov", vec![q(bits), Operand::Immediate(lanes as i64)]),
+            instruction("cmp", vec![q(count), q(bits)]),
+            instruction("cmova", vec![q(count), q(bits)]),
+            instruction("mov", vec![q(bits), Operand::Immediate(-1)]),
+            instruction("bzhi", vec![q(bits), q(bits), q(count)]),
+        ];
+        let (kmov, width) = match lanes {
+            64 => ("kmovq", 64),
+            32 => ("kmovd", 32),
+            _ => ("kmovw", 32),
+        };
+        body.push(instruction(kmov, vec![Operand::Register(result.clone()), Operand::Register(Self::gpr(bits, width))]));
+        Ok(Lowering::Instructions(body))
+    }
+
+    /// A load or store of a whole zmm register under the opmask in
+    /// `call`'s first argument; the mask keeps it to the lanes asked for
+    fn masked_move(call: &IntrinsicCall, element: ElementType) -> Result<Lowering, LoweringError> {
+        let mask = intrinsics::argument(call, 0)?;
+        if mask.number == 0 {
+            return Err(LoweringError::Operands(call.intrinsic, "can't be masked by k0, which means no mask".to_string()));
+        }
+        let mnemonic = match element {
+            ElementType::I8 => "vmovdqu8",
+            ElementType::I16 => "vmovdqu16",
+            ElementType::I32 => "vmovdqu32",
+            ElementType::I64 => "vmovdqu64",
+            ElementType::F32 => "vmovups",
+            ElementType::F64 => "vmovupd",
+        };
+        let address = call.arguments[1].clone();
+        let mut body = match (&call.result, call.arguments.get(2)) {
+            (Some(result), _) => {
+                let mut load = intrinsics::instruction(mnemonic, vec![Self::simd(result, 512), address]);
+                load.suffixes.push("{z}".to_string());
+                load
+            }
+            (None, Some(Operand::Register(value))) => intrinsics::instruction(mnemonic, vec![address, Self::simd(value, 512)]),
+            _ => return Err(LoweringError::Operands(call.intrinsic, "stores a register".to_string())),
+        };
+        body.suffixes.insert(0, format!("{{k{}}}", mask.number));
+        Ok(Lowering::Instructions(vec![body]))
+    }
+}
+
+impl IntrinsicLowering for X86_64IntrinsicLowering {
+    fn max_lanes(&self, element: ElementType, features: &CPUFeatures) -> u32 {
+        // SSE2 is part of x86_64; 8- and 16-bit lanes at 512 bits need BW
+        let bits = if intrinsics::has(features, "avx512f") && (element.bits() >= 32 || intrinsics::has(features, "avx512bw")) {
+            512
+        } else if intrinsics::has(features, "avx2") || (element.is_float() && intrinsics::has(features, "avx")) {
+            256
+        } else {
+            128
+        };
+        bits / element.bits()
+    }
+
+    fn max_masked_lanes(&self, element: ElementType, features: &CPUFeatures) -> u32 {
+        // The loop mask is built with BMI2, which every AVX-512 core has;
+        // masks of more than 16 lanes take BW's kmovd and kmovq
+        let masks = intrinsics::has(features, "avx512f") && intrinsics::has(features, "bmi2");
+        if masks && (element.bits() >= 32 || intrinsics::has(features, "avx512bw")) {
+            512 / element.bits()
+        } else {
+            0
+        }
+    }
+
+    fn scratch(&self, intrinsic: &Intrinsic, _features: &CPUFeatures) -> Vec<RegisterClass> {
+        match intrinsic {
+            Intrinsic::LoopMask { .. } => vec![RegisterClass::General, RegisterClass::General],
+            _ => Vec::new(),
+        }
+    }
+
+    fn lower_call(&self, call: &IntrinsicCall, features: &CPUFeatures) -> Result<Lowering, LoweringError> {
+        let instruction = intrinsics::instruction;
+        match call.intrinsic {
+            Intrinsic::VectorAdd { element, lanes } => Self::vector_add(call, element, lanes, features),
+            Intrinsic::Popcount { bits } => {
+                if !intrinsics::has(features, "popcnt") {
+                    return Ok(Lowering::Libcall(if bits == 64 { "__popcountdi2" } else { "__popcountsi2" }));
+                }
+                let (result, source) = intrinsics::result_and_source(call)?;
+                let mut body = Vec::new();
+                // There is no 8-bit popcnt: widen first
+                let source = if bits == 8 {
+                    let wide = Self::gpr(result, 32);
+                    body.push(instruction("movzx", vec![Operand::Register(wide.clone()), Operand::Register(Self::gpr(source, 8))]));
+                    wide
+                } else {
+                    Self::gpr(source, bits)
+                };
+                let width = if bits == 8 { 32 } else { bits };
+                body.push(instruction("popcnt", vec![Operand::Register(Self::gpr(result, width)), Operand::Register(source)]));
+                Ok(Lowering::Instructions(body))
+            }
+            Intrinsic::Prefetch { write, locality } => {
+                let mnemonic = match (write, locality) {
+                    (true, _) if intrinsics::has(features, "prfchw") => "prefetchw",
+                    (_, 0) => "prefetchnta",
+                    (_, 1) => "prefetcht2",
+                    (_, 2) => "prefetcht1",
+                    _ => "prefetcht0",
+                };
+                Ok(Lowering::Instructions(vec![instruction(mnemonic, call.arguments.clone())]))
+            }
+            // x86 only reorders stores after later loads, which only
+            // sequential consistency forbids
+            Intrinsic::Fence(FenceOrdering::SeqCst) => Ok(Lowering::Instructions(vec![instruction("mfence", Vec::new())])),
+            Intrinsic::Fence(_) => Ok(Lowering::Instructions(Vec::new())),
+            Intrinsic::ByteSwap { bits } => {
+                let (result, source) = intrinsics::result_and_source(call)?;
+                let (result, source) = (Self::gpr(result, bits), Self::gpr(source, bits));
+                let mut body = Vec::new();
+                if result.number != source.number {
+                    body.push(instruction("mov", vec![Operand::Register(result.clone()), Operand::Register(source)]));
+                }
+                // bswap of a 16-bit register is undefined; rotate instead
+                body.push(if bits == 16 {
+                    instruction("rol", vec![Operand::Register(result), Operand::Immediate(8)])
+                } else {
+                    instruction("bswap", vec![Operand::Register(result)])
+                });
+                Ok(Lowering::Instructions(body))
+            }
+            Intrinsic::LoopMask { lanes, .. } => Self::loop_mask(call, lanes),
+            Intrinsic::MaskedLoad { element, .. } | Intrinsic::MaskedStore { element, .. } => Self::masked_move(call, element),
+        }
+    }
+}
+
 // This struct is referenced but not defined in the module interfaces
 pub struct StructType {
     pub name: String,
@@ -0,0 +1,982 @@
+// src/arch/x86_64_encoding.rs
+//! Table-driven x86_64 instruction encoding
+//! The instruction forms live in `x86_64.isa`, a machine-readable
+//! description with one line per form: the mnemonic, the kinds of operand it
+//! takes and the encoding in the Intel manual's notation. The description is
+//! compiled into the binary and parsed once into `TABLES`; encoding an
+//! instruction picks the first form whose operand kinds accept its operands
+//! and emits prefixes, REX, VEX or EVEX, opcode, ModRM/SIB, displacement
+//! and immediate from the form's encoding.
+//!
+//! EVEX forms are AVX-512's: zmm registers, xmm16-31 and friends, and an
+//! opmask on the destination. The parser records `zmm1{k1}{z}` as the
+//! suffixes `{k1}` and `{z}`: only lanes whose k1 bit is set are written,
+//! and the rest are zeroed, or with `{k1}` alone left as they were. A
+//! memory displacement in an EVEX form is in units of the memory operand's
+//! size when it fits a byte that way (disp8*N).
+//!
+//! A branch to a label can't be encoded on its own, so it gets a 32-bit
+//! placeholder (or an 8-bit one from `encode_short_branch`) and a `Fixup`
+//! saying where the displacement goes; `arch::assembler` patches it once
+//! the label's address is known. An immediate branch operand is the
+//! displacement from the end of the instruction.
+
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+
+pub use crate::arch::assembler::{Encoded, Fixup, FixupKind};
+use crate::arch::{EncodingError, Instruction, MemoryOperand, Operand, Register, RegisterClass};
+
+/// The ISA description the tables are built from
+pub const ISA_DESCRIPTION: &str = include_str!("x86_64.isa");
+
+lazy_static! {
+    /// Forms parsed from `ISA_DESCRIPTION`
+    pub static ref TABLES: EncodingTables = match EncodingTables::parse(ISA_DESCRIPTION) {
+        Ok(tables) => tables,
+        Err(e) => panic!("invalid x86_64.isa: {:?}", e),
+    };
+}
+
+/// Condition-code suffixes of `{cc}` mnemonics, with their encodings
+const CONDITION_CODES: &[(&str, u8)] = &[
+    ("o", 0x0), ("no", 0x1), ("b", 0x2), ("c", 0x2), ("nae", 0x2),
+    ("ae", 0x3), ("nb", 0x3), ("nc", 0x3), ("e", 0x4), ("z", 0x4),
+    ("ne", 0x5), ("nz", 0x5), ("be", 0x6), ("na", 0x6), ("a", 0x7),
+    ("nbe", 0x7), ("s", 0x8), ("ns", 0x9), ("p", 0xA), ("pe", 0xA),
+    ("np", 0xB), ("po", 0xB), ("l", 0xC), ("nge", 0xC), ("ge", 0xD),
+    ("nl", 0xD), ("le", 0xE), ("ng", 0xE), ("g", 0xF), ("nle", 0xF),
+];
+
+/// Size suffixes the assembly parser records for `byte ptr` and friends
+const SIZE_SUFFIXES: &[(&str, usize)] = &[
+    ("b", 8), ("w", 16), ("d", 32), ("q", 64), ("x", 128), ("y", 256), ("z", 512),
+];
+
+/// Suffix recording a memory operand's size in `Instruction::suffixes`
+pub fn size_suffix(bits: usize) -> Option<&'static str> {
+    SIZE_SUFFIXES.iter().find(|(_, size)| *size == bits).map(|(suffix, _)| *suffix)
+}
+
+/// Memory operand size named by an instruction's suffixes, in bits
+pub fn memory_size(instruction: &Instruction) -> Option<usize> {
+    instruction.suffixes.iter().find_map(|suffix| {
+        SIZE_SUFFIXES.iter().find(|(name, _)| name == suffix).map(|(_, size)| *size)
+    })
+}
+
+/// Kind of operand a form accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// General register of a fixed size, or of the operand size (`None`)
+    Register(Option<usize>),
+    /// General register or memory, sized like `Register`
+    RegisterOrMemory(Option<usize>),
+    /// Memory of any size
+    Memory,
+    /// Sign-extended 8-bit immediate; 0-255 too in a byte-sized form
+    Imm8,
+    /// 8-bit immediate, signed or unsigned, not sign-extended
+    Unsigned8,
+    /// 16-bit immediate
+    Imm16,
+    /// 32-bit immediate, signed or unsigned
+    Imm32,
+    /// Immediate of the operand size, at most 32 bits sign-extended
+    Imm,
+    /// Full 64-bit immediate
+    Imm64,
+    /// The constant 1 (shift by one)
+    One,
+    /// The `cl` register (shift count)
+    Cl,
+    /// Branch target within an 8-bit displacement
+    Rel8,
+    /// Branch target within a 32-bit displacement, or a label
+    Rel32,
+    /// xmm, ymm or zmm register of the given width
+    Vector(usize),
+    /// Vector register of the first width, or memory of the second
+    VectorOrMemory(usize, usize),
+    /// AVX-512 opmask register, k0-k7
+    Mask,
+}
+
+impl OperandKind {
+    fn parse(text: &str) -> Option<OperandKind> {
+        let kind = match text {
+            "r8" => OperandKind::Register(Some(8)),
+            "r16" => OperandKind::Register(Some(16)),
+            "r32" => OperandKind::Register(Some(32)),
+            "r64" => OperandKind::Register(Some(64)),
+            "r" => OperandKind::Register(None),
+            "rm8" => OperandKind::RegisterOrMemory(Some(8)),
+            "rm16" => OperandKind::RegisterOrMemory(Some(16)),
+            "rm32" => OperandKind::RegisterOrMemory(Some(32)),
+            "rm64" => OperandKind::RegisterOrMemory(Some(64)),
+            "rm" => OperandKind::RegisterOrMemory(None),
+            "m" => OperandKind::Memory,
+            "imm8" => OperandKind::Imm8,
+            "u8" => OperandKind::Unsigned8,
+            "imm16" => OperandKind::Imm16,
+            "imm32" => OperandKind::Imm32,
+            "imm" => OperandKind::Imm,
+            "imm64" => OperandKind::Imm64,
+            "one" => OperandKind::One,
+            "cl" => OperandKind::Cl,
+            "rel8" => OperandKind::Rel8,
+            "rel32" => OperandKind::Rel32,
+            "x" => OperandKind::Vector(128),
+            "y" => OperandKind::Vector(256),
+            "xm32" => OperandKind::VectorOrMemory(128, 32),
+            "xm64" => OperandKind::VectorOrMemory(128, 64),
+            "xm128" => OperandKind::VectorOrMemory(128, 128),
+            "ym256" => OperandKind::VectorOrMemory(256, 256),
+            "z" => OperandKind::Vector(512),
+            "zm512" => OperandKind::VectorOrMemory(512, 512),
+            "k" => OperandKind::Mask,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    /// Whether the operand's size is the instruction's operand size
+    pub fn is_generic(self) -> bool {
+        matches!(self, OperandKind::Register(None) | OperandKind::RegisterOrMemory(None) | OperandKind::Imm)
+    }
+
+    fn is_immediate(self) -> bool {
+        matches!(
+            self,
+            OperandKind::Imm8 | OperandKind::Unsigned8 | OperandKind::Imm16
+                | OperandKind::Imm32 | OperandKind::Imm | OperandKind::Imm64
+        )
+    }
+
+    /// Operands that can go in ModRM.rm
+    fn is_register_or_memory(self) -> bool {
+        matches!(
+            self,
+            OperandKind::RegisterOrMemory(_) | OperandKind::Memory | OperandKind::VectorOrMemory(..)
+        )
+    }
+
+    fn is_register(self) -> bool {
+        matches!(self, OperandKind::Register(_) | OperandKind::Vector(_) | OperandKind::Mask)
+    }
+
+    /// Bytes of memory the operand reads or writes, where it can be memory
+    fn memory_bytes(self) -> Option<i64> {
+        match self {
+            OperandKind::VectorOrMemory(_, bits) => Some(bits as i64 / 8),
+            OperandKind::RegisterOrMemory(Some(bits)) => Some(bits as i64 / 8),
+            _ => None,
+        }
+    }
+}
+
+/// Where ModRM.reg comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModRm {
+    /// `/r`: a register operand
+    Register,
+    /// `/0`-`/7`: an opcode extension
+    Extension(u8),
+}
+
+/// Size of an immediate field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImmediateSize {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+    /// `iz`: a word with a 16-bit operand size, otherwise a dword
+    OperandSize,
+}
+
+/// VEX prefix fields of a form
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vex {
+    /// VEX.L: 256-bit vectors
+    pub wide: bool,
+    /// VEX.pp: implied 66, F3 or F2 prefix
+    pub pp: u8,
+    /// VEX.mmmmm: 0F, 0F38 or 0F3A opcode map
+    pub map: u8,
+    /// VEX.W
+    pub w: bool,
+}
+
+/// EVEX prefix fields of a form; the opmask and zeroing bits come from
+/// the instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Evex {
+    /// EVEX.L'L: 128, 256 or 512-bit vectors
+    pub bits: usize,
+    /// EVEX.pp, as in VEX
+    pub pp: u8,
+    /// EVEX.mmm, as in VEX
+    pub map: u8,
+    /// EVEX.W
+    pub w: bool,
+}
+
+/// How a form is encoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoding {
+    /// Mandatory 66, F2 or F3 prefixes
+    pub prefixes: Vec<u8>,
+    /// REX.W regardless of operand size
+    pub rex_w: bool,
+    pub vex: Option<Vex>,
+    pub evex: Option<Evex>,
+    pub opcode: Vec<u8>,
+    /// `+r`: the register operand goes in the low bits of the last opcode byte
+    pub register_in_opcode: bool,
+    pub modrm: Option<ModRm>,
+    pub immediate: Option<ImmediateSize>,
+    pub branch: Option<FixupKind>,
+}
+
+/// One line of the ISA description
+#[derive(Debug, Clone)]
+pub struct Form {
+    pub mnemonic: String,
+    pub operands: Vec<OperandKind>,
+    pub encoding: Encoding,
+    /// The description line, for diagnostics
+    pub line: usize,
+    // Which operand fills each field
+    rm: Option<usize>,
+    reg: Option<usize>,
+    vvvv: Option<usize>,
+    opcode_register: Option<usize>,
+    immediate: Option<usize>,
+    branch: Option<usize>,
+}
+
+impl Form {
+    /// Whether r, rm or imm operands take their size from the instruction
+    pub fn is_generic(&self) -> bool {
+        self.operands.iter().any(|kind| kind.is_generic())
+    }
+
+    /// Operand size an instruction's operands give this form: the first
+    /// generic register, or else the size of a generic memory operand
+    fn operand_size(&self, operands: &[Operand], memory: Option<usize>) -> Option<usize> {
+        let mut size = None;
+        for (kind, operand) in self.operands.iter().zip(operands) {
+            if !matches!(kind, OperandKind::Register(None) | OperandKind::RegisterOrMemory(None)) {
+                continue;
+            }
+            match operand {
+                Operand::Register(register) => return Some(register.size),
+                Operand::Memory(_) => size = size.or(memory),
+                _ => {}
+            }
+        }
+        size
+    }
+
+    /// The operand size if this form accepts `operands`, `Some(None)` for a
+    /// form without generic operands
+    fn accepts(&self, operands: &[Operand], memory: Option<usize>) -> Option<Option<usize>> {
+        if operands.len() != self.operands.len() {
+            return None;
+        }
+        let size = self.operand_size(operands, memory);
+        if self.is_generic() && !matches!(size, Some(16 | 32 | 64)) {
+            return None;
+        }
+        let byte_form = self.operands.iter().any(|kind| {
+            matches!(kind, OperandKind::Register(Some(8)) | OperandKind::RegisterOrMemory(Some(8)))
+        });
+
+        for (index, (kind, operand)) in self.operands.iter().zip(operands).enumerate() {
+            let accepted = match (kind, operand) {
+                (OperandKind::Register(bits), Operand::Register(register)) => {
+                    register.class == RegisterClass::General && register.size == bits.or(size).unwrap_or(0)
+                }
+                (OperandKind::RegisterOrMemory(bits), Operand::Register(register)) => {
+                    register.class == RegisterClass::General && register.size == bits.or(size).unwrap_or(0)
+                }
+                (OperandKind::RegisterOrMemory(None), Operand::Memory(_)) => {
+                    memory.is_none_or(|bits| Some(bits) == size)
+                }
+                // An unsized memory operand takes its size from a register
+                // operand of the same size, as in `mov [rax], cl`
+                (OperandKind::RegisterOrMemory(Some(bits)), Operand::Memory(_)) => match memory {
+                    Some(memory) => memory == *bits,
+                    None => self.operands.iter().zip(operands).enumerate().any(|(other, pair)| {
+                        other != index
+                            && matches!(pair, (OperandKind::Register(_), Operand::Register(r)) if r.size == *bits)
+                    }),
+                },
+                (OperandKind::Memory, Operand::Memory(_)) => true,
+                (OperandKind::Vector(bits), Operand::Register(register)) => {
+                    register.class == RegisterClass::Vector && register.size == *bits
+                }
+                (OperandKind::VectorOrMemory(bits, _), Operand::Register(register)) => {
+                    register.class == RegisterClass::Vector && register.size == *bits
+                }
+                (OperandKind::VectorOrMemory(_, bits), Operand::Memory(_)) => {
+                    memory.is_none_or(|memory| memory == *bits)
+                }
+                (OperandKind::Mask, Operand::Register(register)) => register.class == RegisterClass::Predicate,
+                (OperandKind::Cl, Operand::Register(register)) => register.name.eq_ignore_ascii_case("cl"),
+                (OperandKind::One, Operand::Immediate(value)) => *value == 1,
+                (OperandKind::Imm8, Operand::Immediate(value)) => {
+                    fits_signed(*value, 8) || (byte_form && (0..=0xFF).contains(value))
+                }
+                (OperandKind::Unsigned8, Operand::Immediate(value)) => fits(*value, 8),
+                (OperandKind::Imm16, Operand::Immediate(value)) => fits(*value, 16),
+                (OperandKind::Imm32, Operand::Immediate(value)) => fits(*value, 32),
+                (OperandKind::Imm, Operand::Immediate(value)) => match size {
+                    Some(64) => fits_signed(*value, 32),
+                    Some(bits) => fits(*value, bits as u32),
+                    None => false,
+                },
+                (OperandKind::Imm64, Operand::Immediate(_)) => true,
+                (OperandKind::Rel8, Operand::Immediate(value)) => fits_signed(*value, 8),
+                (OperandKind::Rel32, Operand::Immediate(value)) => fits_signed(*value, 32),
+                (OperandKind::Rel32, Operand::Label(_)) => true,
+                _ => false,
+            };
+            if !accepted {
+                return None;
+            }
+        }
+        Some(size)
+    }
+
+    /// Work out which operand fills each field of the encoding
+    fn assign_fields(&mut self) -> Result<(), String> {
+        let kinds = &self.operands;
+        let encoding = &self.encoding;
+
+        if let Some(modrm) = encoding.modrm {
+            let rm = kinds.iter().position(|kind| kind.is_register_or_memory());
+            let mut registers = (0..kinds.len()).filter(|&i| kinds[i].is_register() && Some(i) != rm);
+            if modrm == ModRm::Register {
+                self.reg = registers.next();
+                if self.reg.is_none() {
+                    return Err("/r needs a register operand".to_string());
+                }
+            }
+            // Without an r/m operand kind, as in `kandw k, k, k`, the last
+            // register goes in ModRM.rm and the one before it in vvvv
+            let rest: Vec<usize> = registers.collect();
+            self.rm = rm.or_else(|| rest.last().copied());
+            if self.rm.is_none() {
+                return Err("ModRM needs an r/m operand".to_string());
+            }
+            self.vvvv = rest.iter().copied().find(|&i| Some(i) != self.rm);
+            if self.vvvv.is_some() && encoding.vex.is_none() && encoding.evex.is_none() {
+                return Err("only VEX and EVEX forms take a third register operand".to_string());
+            }
+        }
+        if encoding.register_in_opcode {
+            self.opcode_register = kinds.iter().position(|kind| matches!(kind, OperandKind::Register(_)));
+            if self.opcode_register.is_none() {
+                return Err("+r needs a register operand".to_string());
+            }
+        }
+        if encoding.immediate.is_some() {
+            self.immediate = kinds.iter().position(|kind| kind.is_immediate());
+            if self.immediate.is_none() {
+                return Err("immediate field without an immediate operand".to_string());
+            }
+        }
+        if encoding.branch.is_some() {
+            self.branch = kinds.iter().position(|kind| matches!(kind, OperandKind::Rel8 | OperandKind::Rel32));
+            if self.branch.is_none() {
+                return Err("branch displacement without a rel operand".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The instruction forms of the ISA description
+pub struct EncodingTables {
+    // Forms in description order
+    forms: Vec<Form>,
+    // Mnemonic to indices into `forms`
+    by_mnemonic: HashMap<String, Vec<usize>>,
+}
+
+impl EncodingTables {
+    /// Parse an ISA description
+    pub fn parse(description: &str) -> Result<EncodingTables, EncodingError> {
+        let mut tables = EncodingTables {
+            forms: Vec::new(),
+            by_mnemonic: HashMap::new(),
+        };
+
+        for (number, line) in description.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| {
+                EncodingError::InvalidInstruction(format!("line {}: {}", number + 1, message))
+            };
+
+            let (syntax, encoding) = line
+                .split_once(" : ")
+                .ok_or_else(|| invalid("expected 'mnemonic operands : encoding'".to_string()))?;
+            let (mnemonic, operands) = match syntax.trim().split_once(char::is_whitespace) {
+                Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+                None => (syntax.trim(), ""),
+            };
+            let operands = if operands.is_empty() {
+                Vec::new()
+            } else {
+                operands
+                    .split(',')
+                    .map(|kind| OperandKind::parse(kind.trim()).ok_or_else(|| invalid(format!("unknown operand kind '{}'", kind.trim()))))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+
+            // A {cc} mnemonic is one form per condition code
+            let conditions: Vec<(String, u8)> = match mnemonic.split_once("{cc}") {
+                Some((prefix, suffix)) => CONDITION_CODES
+                    .iter()
+                    .map(|(cc, code)| (format!("{}{}{}", prefix, cc, suffix), *code))
+                    .collect(),
+                None => vec![(mnemonic.to_string(), 0)],
+            };
+            for (mnemonic, condition) in conditions {
+                let mut form = Form {
+                    mnemonic,
+                    operands: operands.clone(),
+                    encoding: parse_encoding(encoding, condition).map_err(invalid)?,
+                    line: number + 1,
+                    rm: None,
+                    reg: None,
+                    vvvv: None,
+                    opcode_register: None,
+                    immediate: None,
+                    branch: None,
+                };
+                form.assign_fields().map_err(invalid)?;
+                tables.by_mnemonic.entry(form.mnemonic.clone()).or_default().push(tables.forms.len());
+                tables.forms.push(form);
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Every form, in description order
+    pub fn forms(&self) -> &[Form] {
+        &self.forms
+    }
+
+    /// Whether any form has this mnemonic
+    pub fn supports(&self, mnemonic: &str) -> bool {
+        self.by_mnemonic.contains_key(&mnemonic.to_lowercase())
+    }
+
+    /// The form `instruction` would be encoded with, and its operand size
+    pub fn select(&self, instruction: &Instruction) -> Result<(&Form, Option<usize>), EncodingError> {
+        let mnemonic = instruction.mnemonic.to_lowercase();
+        let candidates = self
+            .by_mnemonic
+            .get(&mnemonic)
+            .ok_or_else(|| EncodingError::InvalidInstruction(format!("unknown mnemonic '{}'", mnemonic)))?;
+        let memory = memory_size(instruction);
+        candidates
+            .iter()
+            .map(|&index| &self.forms[index])
+            .find_map(|form| form.accepts(&instruction.operands, memory).map(|size| (form, size)))
+            .ok_or_else(|| {
+                let ambiguous = memory.is_none() && instruction.operands.iter().any(|o| matches!(o, Operand::Memory(_)));
+                EncodingError::InvalidOperand(format!(
+                    "no form of '{}' takes these operands{}",
+                    mnemonic,
+                    if ambiguous { " (memory operand size not given)" } else { "" }
+                ))
+            })
+    }
+
+    /// Encode `instruction`
+    pub fn encode(&self, instruction: &Instruction) -> Result<Encoded, EncodingError> {
+        let (form, size) = self.select(instruction)?;
+        encode_form(form, instruction, size)
+    }
+
+    /// Encode a branch to a label with an 8-bit displacement, if its
+    /// mnemonic has a rel8 form; `None` for anything else
+    pub fn encode_short_branch(&self, instruction: &Instruction) -> Result<Option<Encoded>, EncodingError> {
+        let Some((position, label)) = instruction.operands.iter().enumerate().find_map(|(i, operand)| match operand {
+            Operand::Label(label) => Some((i, label.clone())),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        // A zero displacement picks the rel8 form where there is one
+        let mut probe = instruction.clone();
+        probe.operands[position] = Operand::Immediate(0);
+        let (form, size) = self.select(&probe)?;
+        if form.encoding.branch != Some(FixupKind::Rel8) {
+            return Ok(None);
+        }
+        let mut encoded = encode_form(form, &probe, size)?;
+        let offset = encoded.bytes.len() - 1;
+        encoded.fixup = Some(Fixup { offset, label, kind: FixupKind::Rel8 });
+        Ok(Some(encoded))
+    }
+}
+
+fn parse_encoding(text: &str, condition: u8) -> Result<Encoding, String> {
+    let mut encoding = Encoding {
+        prefixes: Vec::new(),
+        rex_w: false,
+        vex: None,
+        evex: None,
+        opcode: Vec::new(),
+        register_in_opcode: false,
+        modrm: None,
+        immediate: None,
+        branch: None,
+    };
+
+    for token in text.split_whitespace() {
+        let opcode_done = encoding.modrm.is_some() || encoding.immediate.is_some() || encoding.branch.is_some();
+        match token {
+            "66" | "F2" | "F3" if encoding.opcode.is_empty() && encoding.vex.is_none() && encoding.evex.is_none() => {
+                encoding.prefixes.push(u8::from_str_radix(token, 16).unwrap());
+            }
+            "REX.W" => encoding.rex_w = true,
+            "/r" => encoding.modrm = Some(ModRm::Register),
+            "ib" => encoding.immediate = Some(ImmediateSize::Byte),
+            "iw" => encoding.immediate = Some(ImmediateSize::Word),
+            "id" => encoding.immediate = Some(ImmediateSize::Dword),
+            "io" => encoding.immediate = Some(ImmediateSize::Qword),
+            "iz" => encoding.immediate = Some(ImmediateSize::OperandSize),
+            "cb" => encoding.branch = Some(FixupKind::Rel8),
+            "cd" => encoding.branch = Some(FixupKind::Rel32),
+            _ if token.starts_with("VEX.") => encoding.vex = Some(parse_vex(token)?),
+            _ if token.starts_with("EVEX.") => encoding.evex = Some(parse_evex(token)?),
+            _ if token.len() == 2 && token.starts_with('/') => {
+                let extension = token[1..].parse::<u8>().ok().filter(|e| *e < 8);
+                encoding.modrm = Some(ModRm::Extension(extension.ok_or_else(|| format!("bad extension '{}'", token))?));
+            }
+            _ if !opcode_done => {
+                let (byte, plus) = match token.split_once('+') {
+                    Some((byte, plus)) => (byte, Some(plus)),
+                    None => (token, None),
+                };
+                let mut byte = u8::from_str_radix(byte, 16).map_err(|_| format!("bad token '{}'", token))?;
+                match plus {
+                    None => {}
+                    Some("r") => encoding.register_in_opcode = true,
+                    Some("cc") => byte += condition,
+                    Some(_) => return Err(format!("bad token '{}'", token)),
+                }
+                encoding.opcode.push(byte);
+            }
+            _ => return Err(format!("unexpected '{}' after the opcode", token)),
+        }
+    }
+
+    if encoding.opcode.is_empty() {
+        return Err("no opcode".to_string());
+    }
+    Ok(encoding)
+}
+
+/// `VEX.L.pp.map.W`
+fn parse_vex(token: &str) -> Result<Vex, String> {
+    let (length, pp, map, w) = prefix_fields(token)?;
+    let wide = match length {
+        "128" | "L0" | "LZ" => false,
+        "256" | "L1" => true,
+        _ => return Err(format!("bad VEX prefix '{}'", token)),
+    };
+    Ok(Vex { wide, pp, map, w })
+}
+
+/// `EVEX.L.pp.map.W`
+fn parse_evex(token: &str) -> Result<Evex, String> {
+    let (length, pp, map, w) = prefix_fields(token)?;
+    let bits = match length {
+        "128" => 128,
+        "256" => 256,
+        "512" => 512,
+        _ => return Err(format!("bad EVEX prefix '{}'", token)),
+    };
+    Ok(Evex { bits, pp, map, w })
+}
+
+/// The length, pp, map and W fields of a VEX or EVEX prefix, the length
+/// unparsed
+fn prefix_fields(token: &str) -> Result<(&str, u8, u8, bool), String> {
+    let fields: Vec<&str> = token.split('.').collect();
+    let bad = || format!("bad {} prefix '{}'", fields[0], token);
+    if fields.len() != 5 {
+        return Err(bad());
+    }
+    let pp = match fields[2] {
+        "NP" => 0,
+        "66" => 1,
+        "F3" => 2,
+        "F2" => 3,
+        _ => return Err(bad()),
+    };
+    let map = match fields[3] {
+        "0F" => 1,
+        "0F38" => 2,
+        "0F3A" => 3,
+        _ => return Err(bad()),
+    };
+    let w = match fields[4] {
+        "W0" | "WIG" => false,
+        "W1" => true,
+        _ => return Err(bad()),
+    };
+    Ok((fields[1], pp, map, w))
+}
+
+/// Whether `value` fits in `bits` as a signed or unsigned number
+fn fits(value: i64, bits: u32) -> bool {
+    fits_signed(value, bits) || (value >= 0 && (value as u64) < (1u64 << bits))
+}
+
+fn fits_signed(value: i64, bits: u32) -> bool {
+    let limit = 1i64 << (bits - 1);
+    (-limit..limit).contains(&value)
+}
+
+/// REX prefix
+pub fn rex(w: bool, r: bool, x: bool, b: bool) -> u8 {
+    0x40 | (w as u8) << 3 | (r as u8) << 2 | (x as u8) << 1 | (b as u8)
+}
+
+/// ModR/M byte
+pub fn modrm(mode: u8, reg: u8, rm: u8) -> u8 {
+    (mode & 0x3) << 6 | (reg & 0x7) << 3 | (rm & 0x7)
+}
+
+/// SIB byte; `scale` is 1, 2, 4 or 8
+pub fn sib(scale: u8, index: u8, base: u8) -> u8 {
+    let scale_bits = scale.trailing_zeros() as u8;
+    (scale_bits & 0x3) << 6 | (index & 0x7) << 3 | (base & 0x7)
+}
+
+/// ModRM.rm, SIB and displacement of a memory operand
+struct MemoryEncoding {
+    mode: u8,
+    rm: u8,
+    sib: Option<u8>,
+    displacement: Vec<u8>,
+    // REX.X and REX.B
+    index_high: bool,
+    base_high: bool,
+}
+
+fn address_register(register: &Register, role: &str) -> Result<u8, EncodingError> {
+    if register.class != RegisterClass::General || register.size != 64 {
+        return Err(EncodingError::UnsupportedFeature(format!(
+            "{} register '{}' must be a 64-bit general register",
+            role, register.name
+        )));
+    }
+    Ok(register.number as u8)
+}
+
+/// `unit` scales an 8-bit displacement: 1, or the size of the memory
+/// operand in an EVEX form
+fn encode_memory(memory: &MemoryOperand, unit: i64) -> Result<MemoryEncoding, EncodingError> {
+    let displacement = memory.displacement;
+    if !fits_signed(displacement, 32) {
+        return Err(EncodingError::OperandOutOfRange(format!("displacement {} doesn't fit in 32 bits", displacement)));
+    }
+    let disp32 = (displacement as i32).to_le_bytes().to_vec();
+
+    // [rip + disp32]
+    if memory.pc_relative {
+        if memory.base.is_some() || memory.index.is_some() {
+            return Err(EncodingError::InvalidOperand("rip-relative operands can't have other registers".to_string()));
+        }
+        return Ok(MemoryEncoding { mode: 0b00, rm: 0b101, sib: None, displacement: disp32, index_high: false, base_high: false });
+    }
+
+    let index = match &memory.index {
+        Some(index) => {
+            let number = address_register(index, "index")?;
+            if number == 4 {
+                return Err(EncodingError::InvalidOperand("rsp can't be an index register".to_string()));
+            }
+            Some(number)
+        }
+        None => None,
+    };
+    if !matches!(memory.scale, 1 | 2 | 4 | 8) {
+        return Err(EncodingError::InvalidOperand(format!("scale {} isn't 1, 2, 4 or 8", memory.scale)));
+    }
+    // SIB.index 100 is "no index"
+    let sib_index = index.unwrap_or(0b100);
+    let index_high = sib_index >= 8;
+
+    let base = match &memory.base {
+        Some(base) => address_register(base, "base")?,
+        // [index*scale + disp32], or an absolute disp32, through SIB.base 101
+        None => {
+            return Ok(MemoryEncoding {
+                mode: 0b00,
+                rm: 0b100,
+                sib: Some(sib(memory.scale, sib_index, 0b101)),
+                displacement: disp32,
+                index_high,
+                base_high: false,
+            });
+        }
+    };
+
+    // rbp and r13 have no mod=00 form; that encoding means disp32 or RIP
+    let (mode, displacement) = if displacement == 0 && base & 0x7 != 0b101 {
+        (0b00, Vec::new())
+    } else if displacement % unit == 0 && fits_signed(displacement / unit, 8) {
+        (0b01, vec![(displacement / unit) as u8])
+    } else {
+        (0b10, disp32)
+    };
+    // rsp and r12 as rm mean "SIB follows"
+    let needs_sib = index.is_some() || base & 0x7 == 0b100;
+    Ok(MemoryEncoding {
+        mode,
+        rm: if needs_sib { 0b100 } else { base & 0x7 },
+        sib: needs_sib.then(|| sib(memory.scale, sib_index, base)),
+        displacement,
+        index_high,
+        base_high: base >= 8,
+    })
+}
+
+fn legacy_prefix(prefix: &str) -> Result<u8, EncodingError> {
+    match prefix.to_lowercase().as_str() {
+        "lock" => Ok(0xF0),
+        "rep" | "repe" | "repz" => Ok(0xF3),
+        "repne" | "repnz" => Ok(0xF2),
+        other => Err(EncodingError::InvalidInstruction(format!("unknown prefix '{}'", other))),
+    }
+}
+
+/// Register number in ModRM, VEX.vvvv or the opcode; up to 31 in an
+/// EVEX form
+fn register_number(operand: &Operand, evex: bool) -> Result<u8, EncodingError> {
+    match operand {
+        Operand::Register(register) => {
+            // xmm16-31 and the ymm/zmm registers above them
+            if register.number >= 16 && !evex {
+                return Err(EncodingError::UnsupportedFeature(format!(
+                    "'{}' needs an EVEX encoding",
+                    register.name
+                )));
+            }
+            Ok(register.number as u8)
+        }
+        _ => Err(EncodingError::InvalidOperand("expected a register".to_string())),
+    }
+}
+
+/// The opmask register and zeroing an instruction's `{k1}` and `{z}`
+/// suffixes ask for; 0 is no mask
+pub fn opmask(instruction: &Instruction) -> Result<(u8, bool), EncodingError> {
+    let mut mask = 0;
+    let mut zeroing = false;
+    for suffix in &instruction.suffixes {
+        if suffix == "{z}" {
+            zeroing = true;
+        } else if let Some(number) = suffix.strip_prefix("{k").and_then(|rest| rest.strip_suffix('}')) {
+            // k0 as a mask means no mask, so it can't be written
+            mask = number.parse::<u8>().ok().filter(|k| (1..8).contains(k)).ok_or_else(|| {
+                EncodingError::InvalidOperand(format!("'{}' isn't an opmask; use k1-k7", suffix))
+            })?;
+        }
+    }
+    if zeroing && mask == 0 {
+        return Err(EncodingError::InvalidOperand("{z} needs an opmask, as in {k1}{z}".to_string()));
+    }
+    Ok((mask, zeroing))
+}
+
+/// ah, bh, ch and dh can't be encoded with a REX prefix
+fn is_high_byte(register: &Register) -> bool {
+    register.size == 8 && matches!(register.name.to_lowercase().as_str(), "ah" | "bh" | "ch" | "dh")
+}
+
+fn encode_form(form: &Form, instruction: &Instruction, size: Option<usize>) -> Result<Encoded, EncodingError> {
+    let encoding = &form.encoding;
+    let operands = &instruction.operands;
+    let mut bytes = Vec::new();
+
+    for prefix in &instruction.prefixes {
+        bytes.push(legacy_prefix(prefix)?);
+    }
+    if form.is_generic() && size == Some(16) {
+        bytes.push(0x66);
+    }
+    bytes.extend_from_slice(&encoding.prefixes);
+
+    let w = encoding.rex_w || (form.is_generic() && size == Some(64));
+    let evex = encoding.evex.is_some();
+    let (mask, zeroing) = opmask(instruction)?;
+    if mask != 0 && !evex {
+        return Err(EncodingError::InvalidOperand(format!("'{}' takes no opmask", form.mnemonic)));
+    }
+    if zeroing && matches!(operands.first(), Some(Operand::Memory(_))) {
+        return Err(EncodingError::InvalidOperand("a store can't zero the lanes it masks off".to_string()));
+    }
+    let mut opcode = encoding.opcode.clone();
+    let mut r = false;
+    let mut b = false;
+    // EVEX.R' and EVEX.X: bit 4 of ModRM.reg and of a register in ModRM.rm
+    let mut r_high = false;
+    let mut rm_high = false;
+    let mut memory = None;
+    let mut reg_field = 0;
+    let mut rm_field = 0;
+
+    if let Some(modrm) = encoding.modrm {
+        reg_field = match modrm {
+            ModRm::Extension(extension) => extension,
+            ModRm::Register => register_number(&operands[form.reg.unwrap()], evex)?,
+        };
+        r = reg_field & 8 != 0;
+        r_high = reg_field >= 16;
+        let rm = form.rm.unwrap();
+        match &operands[rm] {
+            Operand::Memory(operand) => {
+                let unit = match encoding.evex {
+                    Some(prefix) => form.operands[rm].memory_bytes().unwrap_or(prefix.bits as i64 / 8),
+                    None => 1,
+                };
+                memory = Some(encode_memory(operand, unit)?)
+            }
+            operand => {
+                rm_field = register_number(operand, evex)?;
+                b = rm_field & 8 != 0;
+                rm_high = rm_field >= 16;
+            }
+        }
+    }
+    if let Some(index) = form.opcode_register {
+        let number = register_number(&operands[index], false)?;
+        b = number >= 8;
+        *opcode.last_mut().unwrap() += number & 0x7;
+    }
+    let x = memory.as_ref().is_some_and(|m| m.index_high);
+    b |= memory.as_ref().is_some_and(|m| m.base_high);
+
+    let vvvv = match form.vvvv {
+        Some(index) => register_number(&operands[index], evex)?,
+        None => 0,
+    };
+    match (encoding.evex, encoding.vex) {
+        (Some(prefix), _) => {
+            // EVEX.L'L is 0, 1 or 2 for 128, 256 or 512 bits
+            let length = prefix.bits.trailing_zeros() as u8 - 7;
+            bytes.extend_from_slice(&[
+                0x62,
+                (!r as u8) << 7 | (!(x || rm_high) as u8) << 6 | (!b as u8) << 5 | (!r_high as u8) << 4 | prefix.map,
+                (prefix.w as u8) << 7 | (!vvvv & 0xF) << 3 | 1 << 2 | prefix.pp,
+                (zeroing as u8) << 7 | length << 5 | ((vvvv < 16) as u8) << 3 | mask,
+            ]);
+        }
+        (None, Some(prefix)) => {
+            let inverted = (!vvvv & 0xF) << 3 | (prefix.wide as u8) << 2 | prefix.pp;
+            if prefix.map == 1 && !prefix.w && !x && !b {
+                bytes.extend_from_slice(&[0xC5, (!r as u8) << 7 | inverted]);
+            } else {
+                bytes.extend_from_slice(&[
+                    0xC4,
+                    (!r as u8) << 7 | (!x as u8) << 6 | (!b as u8) << 5 | prefix.map,
+                    (prefix.w as u8) << 7 | inverted,
+                ]);
+            }
+        }
+        (None, None) => {
+            let registers = operands.iter().filter_map(|operand| match operand {
+                Operand::Register(register) if register.class == RegisterClass::General => Some(register),
+                _ => None,
+            });
+            // spl, bpl, sil and dil exist only with a REX prefix
+            let byte_register = registers.clone().any(|r| r.size == 8 && (4..8).contains(&r.number) && !is_high_byte(r));
+            if w || r || x || b || byte_register {
+                if let Some(high) = registers.clone().find(|r| is_high_byte(r)) {
+                    return Err(EncodingError::InvalidOperand(format!(
+                        "'{}' can't be used in an instruction that needs a REX prefix",
+                        high.name
+                    )));
+                }
+                bytes.push(rex(w, r, x, b));
+            }
+        }
+    }
+    bytes.extend_from_slice(&opcode);
+
+    if encoding.modrm.is_some() {
+        match memory {
+            Some(memory) => {
+                bytes.push(modrm(memory.mode, reg_field, memory.rm));
+                bytes.extend(memory.sib);
+                bytes.extend_from_slice(&memory.displacement);
+            }
+            None => bytes.push(modrm(0b11, reg_field, rm_field)),
+        }
+    }
+
+    if let (Some(immediate), Some(index)) = (encoding.immediate, form.immediate) {
+        let value = match &operands[index] {
+            Operand::Immediate(value) => *value,
+            _ => return Err(EncodingError::InvalidOperand("expected an immediate".to_string())),
+        };
+        let width = match immediate {
+            ImmediateSize::Byte => 1,
+            ImmediateSize::Word => 2,
+            ImmediateSize::Dword => 4,
+            ImmediateSize::Qword => 8,
+            ImmediateSize::OperandSize if size == Some(16) => 2,
+            ImmediateSize::OperandSize => 4,
+        };
+        bytes.extend_from_slice(&value.to_le_bytes()[..width]);
+    }
+
+    let mut fixup = None;
+    if let (Some(kind), Some(index)) = (encoding.branch, form.branch) {
+        match &operands[index] {
+            Operand::Immediate(displacement) => {
+                bytes.extend_from_slice(&displacement.to_le_bytes()[..kind.width()]);
+            }
+            Operand::Label(label) => {
+                fixup = Some(Fixup { offset: bytes.len(), label: label.clone(), kind });
+                bytes.extend(std::iter::repeat_n(0, kind.width()));
+            }
+            _ => return Err(EncodingError::InvalidOperand("expected a branch target".to_string())),
+        }
+    }
+
+    Ok(Encoded { bytes, fixup })
+}
+
+// Example usage:
+/*
+fn encode_loop() -> Result<(), EncodingError> {
+    let parser = X86_64AssemblyParser::new();
+    let ast = parser.parse("top:\n  add rax, qword ptr [rbx + rcx*8 + 0x10]\n  dec rcx\n  jnz top")
+        .expect("valid assembly");
+    let code = X86_64InstructionEncoder::new().encode_asm_block(&ast.blocks[0])?;
+    println!("{:02x?}", code);
+
+    // Or one instruction at a time, patching the branch yourself
+    let encoded = TABLES.encode(&ast.blocks[0].instructions[2])?;
+    let mut bytes = encoded.bytes;
+    if let Some(fixup) = encoded.fixup {
+        fixup.apply(&mut bytes, 0)?;
+    }
+    Ok(())
+}
+*/
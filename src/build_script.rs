@@ -0,0 +1,318 @@
+// src/build_script.rs
+//! Build script API
+//! Compiles the C sources bundled with a Rust crate into a static library
+//! from its `build.rs`, the way the `cc` crate does, but without a C
+//! compiler on the build machine: the sources go through this crate's own
+//! pipeline. Units compile in parallel, the objects are archived into
+//! `lib<name>.a` in `OUT_DIR`, and cargo is told to link it and to rerun
+//! the build script when a source or a header it includes with quotes
+//! changes. Its API follows semver.
+//!
+//! The defaults come from the environment cargo gives build scripts:
+//! `OUT_DIR`, `OPT_LEVEL`, `DEBUG`, `CARGO_CFG_TARGET_ARCH` and `NUM_JOBS`.
+//! Outside a build script, set `out_dir` and the rest explicitly.
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::compiler::CompilerSystem;
+use crate::driver::parallel::{DiagnosticsSink, ParallelCompiler};
+use crate::options::Options;
+use crate::stdlib::bundled::write_archive;
+
+/// Compiles C sources into a static library for cargo to link
+#[derive(Debug, Clone, Default)]
+pub struct Build {
+    files: Vec<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    defines: Vec<(String, Option<String>)>,
+    opt_level: Option<u32>,
+    debug: Option<bool>,
+    architecture: Option<String>,
+    out_dir: Option<PathBuf>,
+    jobs: Option<usize>,
+    cargo_metadata: Option<bool>,
+}
+
+impl Build {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A C source to compile into the library
+    pub fn file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn files<P: AsRef<Path>>(&mut self, paths: impl IntoIterator<Item = P>) -> &mut Self {
+        for path in paths {
+            self.file(path);
+        }
+        self
+    }
+
+    /// As for `-I`
+    pub fn include<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// `#define name value` before every source; `None` defines it as `1`
+    pub fn define(&mut self, name: &str, value: Option<&str>) -> &mut Self {
+        self.defines.push((name.to_string(), value.map(str::to_string)));
+        self
+    }
+
+    /// 0-3; defaults to cargo's `OPT_LEVEL`, with `s` and `z` as 2
+    pub fn opt_level(&mut self, level: u32) -> &mut Self {
+        self.opt_level = Some(level);
+        self
+    }
+
+    /// Emit debug info; defaults to cargo's `DEBUG`
+    pub fn debug(&mut self, enabled: bool) -> &mut Self {
+        self.debug = Some(enabled);
+        self
+    }
+
+    /// One of `options::ARCHITECTURES`; defaults to cargo's
+    /// `CARGO_CFG_TARGET_ARCH`, then to the host
+    pub fn target(&mut self, architecture: &str) -> &mut Self {
+        self.architecture = Some(architecture.to_string());
+        self
+    }
+
+    /// Where objects and the library go; defaults to `OUT_DIR`
+    pub fn out_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.out_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Units compiled concurrently; defaults to cargo's `NUM_JOBS`, then
+    /// one per CPU
+    pub fn jobs(&mut self, jobs: usize) -> &mut Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Print the `cargo:` lines that link the library and track its
+    /// inputs; on by default
+    pub fn cargo_metadata(&mut self, enabled: bool) -> &mut Self {
+        self.cargo_metadata = Some(enabled);
+        self
+    }
+
+    /// `try_compile`, panicking with the error as `cc` does, which is what
+    /// a build script wants
+    pub fn compile(&self, name: &str) {
+        if let Err(e) = self.try_compile(name) {
+            panic!("failed to build lib{}.a: {}", name, e);
+        }
+    }
+
+    /// Compile every file into `lib<name>.a` in the output directory and
+    /// return the library's path
+    pub fn try_compile(&self, name: &str) -> Result<PathBuf, BuildError> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.ends_with(".a") {
+            return Err(BuildError::Name(name.to_string()));
+        }
+        if self.files.is_empty() {
+            return Err(BuildError::NoSources);
+        }
+        let out_dir = match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => env::var_os("OUT_DIR").map(PathBuf::from).ok_or(BuildError::Environment("OUT_DIR"))?,
+        };
+        let options = self.options()?;
+        let metadata = self.cargo_metadata.unwrap_or(true);
+
+        let obj_dir = out_dir.join(format!("{}.objs", name));
+        fs::create_dir_all(&obj_dir).map_err(|e| BuildError::Io(obj_dir.clone(), e))?;
+
+        let mut units = Vec::with_capacity(self.files.len());
+        for (index, file) in self.files.iter().enumerate() {
+            let source = file.canonicalize().map_err(|e| BuildError::Io(file.clone(), e))?;
+            let stem = source.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let member = member_name(index, &stem);
+            units.push(Unit { input: self.wrap(&source, &obj_dir, &member)?, object: obj_dir.join(&member), source, member });
+        }
+
+        let jobs = self.jobs.or_else(|| env::var("NUM_JOBS").ok()?.parse().ok()).unwrap_or(0);
+        let parallel = ParallelCompiler::new(jobs).map_err(|e| BuildError::Parallel(format!("{:?}", e)))?;
+        let sink = DiagnosticsSink::new();
+        let triple = options.target_triple();
+        let compiler_options = options.compiler_options(None);
+
+        // One compiler per worker thread; LLVM contexts aren't shareable
+        let results = parallel.compile_all(
+            &units,
+            &sink,
+            || unsafe { CompilerSystem::new(triple) },
+            |compiler, _, unit, _| {
+                let compiler = compiler.as_ref().map_err(|e| BuildError::Compile(unit.source.clone(), format!("{:?}", e)))?;
+                let input = unit.input.to_string_lossy();
+                let object = unit.object.to_string_lossy();
+                unsafe { compiler.compile_file(&input, &object, &compiler_options) }
+                    .map_err(|e| BuildError::Compile(unit.source.clone(), format!("{:?}", e)))
+            },
+        );
+        for result in results {
+            result?;
+        }
+
+        let mut members = Vec::with_capacity(units.len());
+        for unit in &units {
+            let data = fs::read(&unit.object).map_err(|e| BuildError::Io(unit.object.clone(), e))?;
+            members.push((unit.member.clone(), data));
+        }
+        let library = out_dir.join(format!("lib{}.a", name));
+        write_archive(&library, &members).map_err(|e| BuildError::Io(library.clone(), e))?;
+
+        if metadata {
+            println!("cargo:rustc-link-search=native={}", out_dir.display());
+            println!("cargo:rustc-link-lib=static={}", name);
+            let mut seen = HashSet::new();
+            for unit in &units {
+                self.track(&unit.source, &mut seen);
+            }
+        }
+        Ok(library)
+    }
+
+    fn options(&self) -> Result<Options, BuildError> {
+        let opt_level = match self.opt_level {
+            Some(level) => level,
+            None => match env::var("OPT_LEVEL").as_deref() {
+                Ok("0") => 0,
+                Ok("1") => 1,
+                Ok("3") => 3,
+                _ => 2,
+            },
+        };
+        let debug = self.debug.unwrap_or_else(|| env::var("DEBUG").map(|debug| debug != "false").unwrap_or(false));
+        let architecture = self
+            .architecture
+            .clone()
+            .or_else(|| env::var("CARGO_CFG_TARGET_ARCH").ok())
+            .unwrap_or_else(|| std::env::consts::ARCH.to_string());
+        let mut builder = Options::builder().optimization_level(opt_level).architecture(&architecture).debug_info(debug);
+        for dir in &self.include_dirs {
+            builder = builder.include_dir(dir.clone());
+        }
+        let options = builder.build().map_err(|e| BuildError::Options(e.to_string()))?;
+        // The GPU targets make kernels, not objects to link
+        if options.cpu_architecture().is_none() {
+            return Err(BuildError::Target(architecture));
+        }
+        Ok(options)
+    }
+
+    /// The file to hand the compiler for `source`: `source` itself, or a
+    /// unit that makes the defines and includes it
+    fn wrap(&self, source: &Path, obj_dir: &Path, member: &str) -> Result<PathBuf, BuildError> {
+        if self.defines.is_empty() {
+            return Ok(source.to_path_buf());
+        }
+        let mut text = String::new();
+        for (name, value) in &self.defines {
+            text.push_str(&format!("#define {} {}\n", name, value.as_deref().unwrap_or("1")));
+        }
+        text.push_str(&format!("#include \"{}\"\n", source.display()));
+
+        let wrapper = obj_dir.join(Path::new(member).with_extension("c"));
+        fs::write(&wrapper, text).map_err(|e| BuildError::Io(wrapper.clone(), e))?;
+        Ok(wrapper)
+    }
+
+    /// `rerun-if-changed` for `file` and the headers it includes with
+    /// quotes, found next to it or in the include directories
+    fn track(&self, file: &Path, seen: &mut HashSet<PathBuf>) {
+        if !seen.insert(file.to_path_buf()) {
+            return;
+        }
+        println!("cargo:rerun-if-changed={}", file.display());
+        let Ok(text) = fs::read_to_string(file) else { return };
+
+        let dir = file.parent().unwrap_or(Path::new("."));
+        for line in text.lines() {
+            let Some(rest) = line.trim_start().strip_prefix('#') else { continue };
+            let Some(rest) = rest.trim_start().strip_prefix("include") else { continue };
+            let Some(header) = rest.trim().strip_prefix('"').and_then(|rest| rest.split('"').next()) else { continue };
+            let found = std::iter::once(dir)
+                .chain(self.include_dirs.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(header))
+                .find(|path| path.is_file());
+            if let Some(path) = found {
+                self.track(&path.canonicalize().unwrap_or(path), seen);
+            }
+        }
+    }
+}
+
+struct Unit {
+    source: PathBuf,
+    /// `source`, or the wrapper that adds the defines
+    input: PathBuf,
+    object: PathBuf,
+    member: String,
+}
+
+/// `<index>-<stem>.o`, cut to the 15 characters a short `ar` member name
+/// allows; the index keeps `a/util.c` and `b/util.c` apart
+fn member_name(index: usize, stem: &str) -> String {
+    let prefix = format!("{}-", index);
+    let room = 15usize.saturating_sub(prefix.len() + 2);
+    let stem: String = stem.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_').take(room).collect();
+    format!("{}{}.o", prefix, stem)
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// Library names are given without `lib` and `.a`
+    Name(String),
+    NoSources,
+    /// A cargo variable the build needs isn't set; not in a build script?
+    Environment(&'static str),
+    /// No code generator for the architecture
+    Target(String),
+    Options(String),
+    Parallel(String),
+    /// (source, error)
+    Compile(PathBuf, String),
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Name(name) => write!(f, "'{}' is not a library name (use \"foo\" for libfoo.a)", name),
+            BuildError::NoSources => write!(f, "no source files"),
+            BuildError::Environment(variable) => write!(f, "{} is not set; is this a build script?", variable),
+            BuildError::Target(architecture) => write!(f, "no code generator for '{}'", architecture),
+            BuildError::Options(message) => write!(f, "{}", message),
+            BuildError::Parallel(message) => write!(f, "{}", message),
+            BuildError::Compile(source, message) => write!(f, "{}: {}", source.display(), message),
+            BuildError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+// Example usage:
+/*
+// build.rs
+fn main() {
+    interpreter_c::build_script::Build::new()
+        .files(["csrc/parser.c", "csrc/tables.c"])
+        .include("csrc/include")
+        .define("NDEBUG", None)
+        .define("TABLE_SIZE", Some("256"))
+        .compile("parser");
+}
+*/
@@ -0,0 +1,419 @@
+// src/capi.rs
+//! C interface to the embedding API
+//! Built with `--features capi` as a cdylib; `include/interpreter_c.h`
+//! declares everything here. Conventions, which the header repeats:
+//!
+//! - Every fallible function returns an `ic_status`; `IC_OK` is zero.
+//!   `ic_last_error()` describes the last failure on the calling thread.
+//! - Strings passed in are NUL-terminated UTF-8, borrowed for the duration
+//!   of the call. Strings handed out are owned by the library: the
+//!   `ic_last_error()` text stays valid until the thread's next `ic_` call.
+//!   No function hands out memory the caller must free, except the engine
+//!   itself, which `ic_engine_free` releases.
+//! - A panic never crosses into C; it becomes `IC_ERR_PANIC`.
+//!
+//! An engine is not thread-safe. It may move between threads, but calls on
+//! one engine must not overlap.
+
+// Each function's contract is documented in the header, for its C callers
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use crate::engine::{Engine, EngineError};
+use crate::jit::host::HostSignature;
+use crate::jit::{JITType, JITValue};
+
+#[allow(non_camel_case_types)]
+pub struct ic_engine {
+    engine: Engine,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ic_status {
+    IC_OK = 0,
+    IC_ERR_NULL_ARGUMENT = 1,
+    IC_ERR_INVALID_UTF8 = 2,
+    IC_ERR_COMPILE = 3,
+    IC_ERR_IO = 4,
+    IC_ERR_SYMBOL_NOT_FOUND = 5,
+    IC_ERR_TOO_MANY_ARGUMENTS = 6,
+    IC_ERR_INVALID_ARGUMENT = 7,
+    IC_ERR_UNSUPPORTED = 8,
+    IC_ERR_PANIC = 9,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ic_type {
+    IC_VOID = 0,
+    IC_I8 = 1,
+    IC_I16 = 2,
+    IC_I32 = 3,
+    IC_I64 = 4,
+    IC_FLOAT = 5,
+    IC_DOUBLE = 6,
+    IC_PTR = 7,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union ic_payload {
+    pub i8: i8,
+    pub i16: i16,
+    pub i32: i32,
+    pub i64: i64,
+    pub f: f32,
+    pub d: f64,
+    pub ptr: *mut c_void,
+}
+
+/// A tagged C value; `ty` says which member of `value` is meaningful
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ic_value {
+    pub ty: ic_type,
+    pub value: ic_payload,
+}
+
+/// Host callback: read `nargs` arguments, store the result in `*result`
+/// (its `ty` is preset to the declared return type)
+#[allow(non_camel_case_types)]
+pub type ic_callback = unsafe extern "C" fn(user_data: *mut c_void, args: *const ic_value, nargs: usize, result: *mut ic_value);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Failure of one call, as reported to C
+struct Failure {
+    status: ic_status,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: ic_status, message: impl Into<String>) -> Self {
+        Failure { status, message: message.into() }
+    }
+}
+
+impl From<EngineError> for Failure {
+    fn from(error: EngineError) -> Self {
+        let status = match &error {
+            EngineError::Compile(_) => ic_status::IC_ERR_COMPILE,
+            EngineError::Io(_) => ic_status::IC_ERR_IO,
+            EngineError::SymbolNotFound(_) => ic_status::IC_ERR_SYMBOL_NOT_FOUND,
+            EngineError::TooManyArguments => ic_status::IC_ERR_TOO_MANY_ARGUMENTS,
+            EngineError::VoidArgument | EngineError::Abi(_) => ic_status::IC_ERR_INVALID_ARGUMENT,
+            EngineError::UnsupportedHost(_) => ic_status::IC_ERR_UNSUPPORTED,
+        };
+        Failure::new(status, format!("{:?}", error))
+    }
+}
+
+/// Run one API call: record its error for `ic_last_error` and keep panics
+/// out of C
+fn guard(call: impl FnOnce() -> Result<(), Failure>) -> ic_status {
+    let outcome = catch_unwind(AssertUnwindSafe(call))
+        .unwrap_or_else(|_| Err(Failure::new(ic_status::IC_ERR_PANIC, "internal panic")));
+    let (status, message) = match outcome {
+        Ok(()) => (ic_status::IC_OK, None),
+        // Interior NULs would truncate the message anyway
+        Err(failure) => (failure.status, CString::new(failure.message.replace('\0', " ")).ok()),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+unsafe fn engine_mut<'a>(engine: *mut ic_engine) -> Result<&'a mut Engine, Failure> {
+    engine
+        .as_mut()
+        .map(|handle| &mut handle.engine)
+        .ok_or_else(|| Failure::new(ic_status::IC_ERR_NULL_ARGUMENT, "engine is NULL"))
+}
+
+unsafe fn string<'a>(ptr: *const c_char, what: &str) -> Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err(Failure::new(ic_status::IC_ERR_NULL_ARGUMENT, format!("{} is NULL", what)));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| Failure::new(ic_status::IC_ERR_INVALID_UTF8, format!("{} is not UTF-8", what)))
+}
+
+fn jit_type(ty: ic_type) -> JITType {
+    match ty {
+        ic_type::IC_VOID => JITType::Void,
+        ic_type::IC_I8 => JITType::Int8,
+        ic_type::IC_I16 => JITType::Int16,
+        ic_type::IC_I32 => JITType::Int32,
+        ic_type::IC_I64 => JITType::Int64,
+        ic_type::IC_FLOAT => JITType::Float,
+        ic_type::IC_DOUBLE => JITType::Double,
+        ic_type::IC_PTR => JITType::Pointer(Box::new(JITType::Int8)),
+    }
+}
+
+unsafe fn to_jit_value(value: &ic_value) -> JITValue {
+    match value.ty {
+        ic_type::IC_VOID => JITValue::Void,
+        ic_type::IC_I8 => JITValue::Int8(value.value.i8),
+        ic_type::IC_I16 => JITValue::Int16(value.value.i16),
+        ic_type::IC_I32 => JITValue::Int32(value.value.i32),
+        ic_type::IC_I64 => JITValue::Int64(value.value.i64),
+        ic_type::IC_FLOAT => JITValue::Float(value.value.f),
+        ic_type::IC_DOUBLE => JITValue::Double(value.value.d),
+        ic_type::IC_PTR => JITValue::Pointer(value.value.ptr as *mut u8),
+    }
+}
+
+fn from_jit_value(value: JITValue) -> ic_value {
+    let (ty, value) = match value {
+        JITValue::Void => (ic_type::IC_VOID, ic_payload { i64: 0 }),
+        JITValue::Int8(v) => (ic_type::IC_I8, ic_payload { i8: v }),
+        JITValue::Int16(v) => (ic_type::IC_I16, ic_payload { i16: v }),
+        JITValue::Int32(v) => (ic_type::IC_I32, ic_payload { i32: v }),
+        JITValue::Int64(v) => (ic_type::IC_I64, ic_payload { i64: v }),
+        JITValue::Float(v) => (ic_type::IC_FLOAT, ic_payload { f: v }),
+        JITValue::Double(v) => (ic_type::IC_DOUBLE, ic_payload { d: v }),
+        JITValue::Pointer(p) => (ic_type::IC_PTR, ic_payload { ptr: p as *mut c_void }),
+    };
+    ic_value { ty, value }
+}
+
+unsafe fn slice<'a, T>(ptr: *const T, len: usize, what: &str) -> Result<&'a [T], Failure> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(Failure::new(ic_status::IC_ERR_NULL_ARGUMENT, format!("{} is NULL", what))),
+        (false, _) => Ok(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Store `main`'s result, if the unit defined one, in the optional outputs
+unsafe fn report_main(result: Option<i32>, main_result: *mut i32, ran_main: *mut bool) {
+    if let Some(ran_main) = ran_main.as_mut() {
+        *ran_main = result.is_some();
+    }
+    if let (Some(status), Some(out)) = (result, main_result.as_mut()) {
+        *out = status;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ic_engine_new(out: *mut *mut ic_engine) -> ic_status {
+    guard(|| {
+        let out = out.as_mut().ok_or_else(|| Failure::new(ic_status::IC_ERR_NULL_ARGUMENT, "out is NULL"))?;
+        *out = std::ptr::null_mut();
+        let engine = Engine::new()?;
+        *out = Box::into_raw(Box::new(ic_engine { engine }));
+        Ok(())
+    })
+}
+
+/// Code compiled by the engine is freed with it; NULL is ignored
+#[no_mangle]
+pub unsafe extern "C" fn ic_engine_free(engine: *mut ic_engine) {
+    if !engine.is_null() {
+        // Dropping LLVM state shouldn't panic, but C must never see it if it does
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(engine))));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ic_eval(
+    engine: *mut ic_engine,
+    source: *const c_char,
+    main_result: *mut i32,
+    ran_main: *mut bool,
+) -> ic_status {
+    guard(|| {
+        let engine = engine_mut(engine)?;
+        let source = string(source, "source")?;
+        report_main(engine.eval_string(source)?, main_result, ran_main);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ic_eval_file(
+    engine: *mut ic_engine,
+    path: *const c_char,
+    main_result: *mut i32,
+    ran_main: *mut bool,
+) -> ic_status {
+    guard(|| {
+        let engine = engine_mut(engine)?;
+        let path = string(path, "path")?;
+        report_main(engine.eval_file(path)?, main_result, ran_main);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ic_call(
+    engine: *mut ic_engine,
+    name: *const c_char,
+    args: *const ic_value,
+    nargs: usize,
+    return_type: ic_type,
+    result: *mut ic_value,
+) -> ic_status {
+    guard(|| {
+        let engine = engine_mut(engine)?;
+        let name = string(name, "name")?;
+        let args: Vec<JITValue> = slice(args, nargs, "args")?.iter().map(|arg| to_jit_value(arg)).collect();
+
+        let ty = jit_type(return_type);
+        let raw = match return_type {
+            ic_type::IC_FLOAT | ic_type::IC_DOUBLE => engine.call_function::<f64>(name, &args)?.to_bits(),
+            _ => engine.call_function::<u64>(name, &args)?,
+        };
+        if let Some(result) = result.as_mut() {
+            *result = from_jit_value(JITValue::from_raw(raw, &ty));
+        }
+        Ok(())
+    })
+}
+
+/// Copy `size` bytes of the global `name` into `out`
+#[no_mangle]
+pub unsafe extern "C" fn ic_get_global(engine: *mut ic_engine, name: *const c_char, out: *mut c_void, size: usize) -> ic_status {
+    guard(|| {
+        let engine = engine_mut(engine)?;
+        let name = string(name, "name")?;
+        if out.is_null() {
+            return Err(Failure::new(ic_status::IC_ERR_NULL_ARGUMENT, "out is NULL"));
+        }
+        let address = engine.symbol_address(name).ok_or(EngineError::SymbolNotFound(name.to_string()))?;
+        std::ptr::copy_nonoverlapping(address, out as *mut u8, size);
+        Ok(())
+    })
+}
+
+/// Copy `size` bytes from `value` over the global `name`
+#[no_mangle]
+pub unsafe extern "C" fn ic_set_global(engine: *mut ic_engine, name: *const c_char, value: *const c_void, size: usize) -> ic_status {
+    guard(|| {
+        let engine = engine_mut(engine)?;
+        let name = string(name, "name")?;
+        if value.is_null() {
+            return Err(Failure::new(ic_status::IC_ERR_NULL_ARGUMENT, "value is NULL"));
+        }
+        let address = engine.symbol_address(name).ok_or(EngineError::SymbolNotFound(name.to_string()))?;
+        std::ptr::copy_nonoverlapping(value as *const u8, address, size);
+        Ok(())
+    })
+}
+
+/// `user_data` travels with the callback to whichever thread runs the C code
+struct CallbackData(*mut c_void);
+
+unsafe impl Send for CallbackData {}
+unsafe impl Sync for CallbackData {}
+
+impl CallbackData {
+    // A method, so closures capture the whole wrapper rather than the raw field
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Let C code compiled from now on call `callback` as `name`
+#[no_mangle]
+pub unsafe extern "C" fn ic_set_callback(
+    engine: *mut ic_engine,
+    name: *const c_char,
+    params: *const ic_type,
+    nparams: usize,
+    return_type: ic_type,
+    callback: Option<ic_callback>,
+    user_data: *mut c_void,
+) -> ic_status {
+    guard(|| {
+        let engine = engine_mut(engine)?;
+        let name = string(name, "name")?;
+        let params = slice(params, nparams, "params")?;
+        let callback = callback.ok_or_else(|| Failure::new(ic_status::IC_ERR_NULL_ARGUMENT, "callback is NULL"))?;
+
+        let signature = HostSignature::new(params.iter().map(|&ty| jit_type(ty)).collect(), jit_type(return_type));
+        let user_data = CallbackData(user_data);
+        engine
+            .register_host_closure(name, signature, move |args| {
+                let args: Vec<ic_value> = args.iter().copied().map(from_jit_value).collect();
+                let mut result = from_jit_value(JITValue::from_raw(0, &jit_type(return_type)));
+                callback(user_data.get(), args.as_ptr(), args.len(), &mut result);
+                // A mismatched tag is caught by the host dispatcher
+                to_jit_value(&result)
+            })
+            .map_err(|error| match error {
+                EngineError::Compile(error) => Failure::new(ic_status::IC_ERR_INVALID_ARGUMENT, format!("{:?}", error)),
+                other => other.into(),
+            })
+    })
+}
+
+/// Description of the calling thread's last failure, or NULL after a
+/// success. Owned by the library; valid until the thread's next `ic_` call.
+#[no_mangle]
+pub extern "C" fn ic_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |message| message.as_ptr()))
+}
+
+/// Static name of a status code, e.g. "IC_ERR_COMPILE"
+#[no_mangle]
+pub extern "C" fn ic_status_name(status: ic_status) -> *const c_char {
+    let name: &'static CStr = match status {
+        ic_status::IC_OK => c"IC_OK",
+        ic_status::IC_ERR_NULL_ARGUMENT => c"IC_ERR_NULL_ARGUMENT",
+        ic_status::IC_ERR_INVALID_UTF8 => c"IC_ERR_INVALID_UTF8",
+        ic_status::IC_ERR_COMPILE => c"IC_ERR_COMPILE",
+        ic_status::IC_ERR_IO => c"IC_ERR_IO",
+        ic_status::IC_ERR_SYMBOL_NOT_FOUND => c"IC_ERR_SYMBOL_NOT_FOUND",
+        ic_status::IC_ERR_TOO_MANY_ARGUMENTS => c"IC_ERR_TOO_MANY_ARGUMENTS",
+        ic_status::IC_ERR_INVALID_ARGUMENT => c"IC_ERR_INVALID_ARGUMENT",
+        ic_status::IC_ERR_UNSUPPORTED => c"IC_ERR_UNSUPPORTED",
+        ic_status::IC_ERR_PANIC => c"IC_ERR_PANIC",
+    };
+    name.as_ptr()
+}
+
+// Example usage:
+/*
+// cargo rustc --release --lib --features capi --crate-type cdylib
+// cc host.c -Iinclude -Ltarget/release -linterpreter_c
+
+#include "interpreter_c.h"
+
+static void on_progress(void *user_data, const ic_value *args, size_t nargs, ic_value *result) {
+    *(int *)user_data += args[0].value.i32;
+    result->value.i32 = 0;
+}
+
+int main(void) {
+    ic_engine *engine;
+    int total = 0;
+    if (ic_engine_new(&engine) != IC_OK) {
+        fprintf(stderr, "%s\n", ic_last_error());
+        return 1;
+    }
+
+    ic_type params[] = { IC_I32 };
+    ic_set_callback(engine, "progress", params, 1, IC_I32, on_progress, &total);
+    if (ic_eval(engine, "int progress(int); int work(int n) { for (int i = 0; i < n; i++) progress(i); return n; }", NULL, NULL) != IC_OK) {
+        fprintf(stderr, "%s\n", ic_last_error());
+        return 1;
+    }
+
+    ic_value arg = { .ty = IC_I32, .value.i32 = 5 }, result;
+    ic_call(engine, "work", &arg, 1, IC_I32, &result);
+    printf("%d %d\n", result.value.i32, total); // 5 10
+    ic_engine_free(engine);
+    return 0;
+}
+*/
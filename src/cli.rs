@@ -0,0 +1,1091 @@
+// src/cli.rs
+//! Command-line definition shared by argument parsing, shell completions
+//! and man page generation.
+//!
+//! Running `c-interpreter [OPTIONS] FILE` without a subcommand behaves like
+//! `c-interpreter run FILE` (or `compile` with `-c`), so existing scripts
+//! keep working.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use clap::{Arg, ArgAction, ArgGroup, Command};
+use clap_complete::Shell;
+
+/// Options understood by every execution subcommand
+fn common_args() -> Vec<Arg> {
+    vec![
+        Arg::new("optimization")
+            .long("opt")
+            .short('O')
+            .help("Optimization level (0-3)")
+            .default_value("2")
+            .global(true),
+        Arg::new("architecture")
+            .long("arch")
+            .short('a')
+            .help("Target architecture (x86_64, aarch64, arm, amdgpu, nvptx)")
+            .value_parser(["x86_64", "aarch64", "arm", "amdgpu", "nvptx"])
+            .default_value(std::env::consts::ARCH)
+            .global(true),
+        Arg::new("include")
+            .long("include")
+            .short('I')
+            .help("Add directory to include search path")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("vm-stats")
+            .long("vm-stats")
+            .help("Print per-opcode and per-function interpreter counters after execution")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("provenance")
+            .long("provenance")
+            .help("Interpreter: track which object every pointer came from and report cross-object arithmetic, comparisons and use after free or realloc")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("audit-signals")
+            .long("audit-signals")
+            .help("Interpreter: end the program with a report and the stack when a signal handler calls a function that isn't async-signal-safe or re-enters one the code it interrupted was in")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("record")
+            .long("record")
+            .value_name("FILE")
+            .help("Interpreter: record statements, memory writes, syscalls and other inputs to FILE for replay and reverse debugging")
+            .global(true),
+        Arg::new("replay")
+            .long("replay")
+            .value_name("FILE")
+            .help("Interpreter: rerun with the syscall results and inputs recorded in FILE; with `debug`, step backwards through it")
+            .conflicts_with("record")
+            .global(true),
+        Arg::new("dir")
+            .long("dir")
+            .value_name("HOST[::GUEST]")
+            .help("Interpreter: sandbox file access to HOST, seen by the program as GUEST (repeatable)")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("dir-ro")
+            .long("dir-ro")
+            .value_name("HOST[::GUEST]")
+            .help("Interpreter: like --dir, but read-only")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("max-time")
+            .long("max-time")
+            .value_name("DURATION")
+            .help("Interpreter: stop the program after DURATION of real time (e.g. 10s, 500ms, 2m)")
+            .global(true),
+        Arg::new("max-cpu-time")
+            .long("max-cpu-time")
+            .value_name("DURATION")
+            .help("Interpreter: stop the program after DURATION of CPU time")
+            .global(true),
+        Arg::new("max-memory")
+            .long("max-memory")
+            .value_name("SIZE")
+            .help("Interpreter: stop the program when its heap would grow past SIZE bytes (e.g. 256M)")
+            .global(true),
+        Arg::new("max-stack")
+            .long("max-stack")
+            .value_name("SIZE")
+            .help("Interpreter: stop the program when its call stack grows past SIZE bytes")
+            .global(true),
+        Arg::new("max-output")
+            .long("max-output")
+            .value_name("SIZE")
+            .help("Interpreter: stop the program once it writes more than SIZE bytes to stdout")
+            .global(true),
+        Arg::new("engine")
+            .long("engine")
+            .value_name("ENGINE")
+            .help("Interpreter: `tree` walks the syntax tree; `bytecode` compiles it to bytecode first and falls back to `tree` for programs it can't run; `native` also runs the bytecode functions it can as x86_64 machine code")
+            .value_parser(["tree", "bytecode", "native"])
+            .default_value("tree")
+            .global(true),
+        Arg::new("emit-ir")
+            .long("emit-ir")
+            .value_name("FILE")
+            .help("Interpreter: write the bytecode program to FILE in the versioned IR format; see dump-ir")
+            .global(true),
+        Arg::new("usdt")
+            .long("usdt")
+            .value_name("PROVIDER[:NAME]")
+            .help("Interpreter: enable the DTRACE_PROBE/STAP_PROBE sites matching the pattern (`*` wildcards) and print each hit to stderr (repeatable)")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("instrument")
+            .long("instrument")
+            .value_name("FUNCTION")
+            .help("Log calls to the functions matching the pattern (`*` wildcards) to stderr, by rewriting the source before it is compiled (repeatable)")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("instrument-log")
+            .long("instrument-log")
+            .value_name("LIST")
+            .help("What --instrument logs: a comma-separated list of entry, exit, args and time")
+            .default_value("entry,exit")
+            .global(true),
+        Arg::new("deterministic")
+            .long("deterministic")
+            .help("Run the same way every time: fixed srand() seed, virtual clock, no address randomization and ordered threads")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("seed")
+            .long("seed")
+            .value_name("N")
+            .help("Seed srand() always uses with --deterministic (default 1)")
+            .value_parser(clap::value_parser!(u32))
+            .requires("deterministic")
+            .global(true),
+        Arg::new("stdin-file")
+            .long("stdin-file")
+            .value_name("FILE")
+            .help("Connect the program's stdin to FILE")
+            .global(true),
+        Arg::new("print-exit-status")
+            .long("print-exit-status")
+            .help("Report how the program exited on stderr (stdout is left to the program)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("sanitize")
+            .long("sanitize")
+            .value_name("LIST")
+            .help("Insert runtime checks into generated code (undefined)")
+            .global(true),
+        Arg::new("cache-dir")
+            .long("cache-dir")
+            .value_name("DIR")
+            .help("Compilation cache location (default: $C_INTERPRETER_CACHE_DIR or ~/.cache/c-interpreter)")
+            .global(true),
+        Arg::new("no-cache")
+            .long("no-cache")
+            .help("Always recompile instead of reusing cached objects")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("jobs")
+            .long("jobs")
+            .value_name("N")
+            .help("Compile up to N translation units, or run up to N test programs, in parallel (default: one per CPU)")
+            .value_parser(clap::value_parser!(usize))
+            .global(true),
+        Arg::new("embed-provenance")
+            .long("embed-provenance")
+            .help("Record the tool version, options and input digests in a note in every object; see verify-provenance")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("libc")
+            .long("libc")
+            .value_name("MODE")
+            .help("C library to compile and link against: the host's, or the bundled minimal libc")
+            .value_parser(["host", "bundled"])
+            .default_value("host")
+            .global(true),
+        Arg::new("wrapv")
+            .long("fwrapv")
+            .help("Signed integer overflow wraps around (two's complement)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("trapv")
+            .long("ftrapv")
+            .help("Report signed integer overflow and abort (default: report and continue)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("fast-math")
+            .long("ffast-math")
+            .help("Allow reassociation, FMA contraction and ignoring NaN/Inf/signed zeros (results may differ from -i)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("no-fast-math")
+            .long("fno-fast-math")
+            .help("Strict IEEE 754 arithmetic (default)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("math-errno")
+            .long("fmath-errno")
+            .help("libm functions set errno (default unless -ffast-math)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("no-math-errno")
+            .long("fno-math-errno")
+            .help("Treat libm functions as pure so they can be inlined as instructions")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("fp-contract")
+            .long("ffp-contract")
+            .value_name("MODE")
+            .help("Fuse multiply-add into FMA: off (default), on (within an expression) or fast")
+            .value_parser(["off", "on", "fast"])
+            .global(true),
+        Arg::new("opt-time-budget")
+            .long("opt-time-budget")
+            .value_name("DURATION")
+            .help("Compiling: give up optimizing a function after DURATION (e.g. 30s) and compile it at -O0")
+            .global(true),
+        Arg::new("opt-memory-budget")
+            .long("opt-memory-budget")
+            .value_name("SIZE")
+            .help("Compiling: give up optimizing a function that needs more than SIZE of memory (e.g. 2G) and compile it at -O0")
+            .global(true),
+        Arg::new("loop-interchange")
+            .long("floop-interchange")
+            .help("At -O3, swap the loops of a nest so the innermost walks memory contiguously (default)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("no-loop-interchange")
+            .long("fno-loop-interchange")
+            .help("Leave the order of nested loops as written")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("loop-fusion")
+            .long("floop-fusion")
+            .help("At -O3, merge adjacent loops with the same trip count (default)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("no-loop-fusion")
+            .long("fno-loop-fusion")
+            .help("Leave adjacent loops separate")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("loop-tiling")
+            .long("floop-tiling")
+            .help("At -O3 in JIT mode, tile perfect loop nests for this machine's caches (default)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("no-loop-tiling")
+            .long("fno-loop-tiling")
+            .help("Leave loop nests untiled")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("heap-to-stack")
+            .long("fheap-to-stack")
+            .help("From -O2, move small allocations that never leave their function to the stack (default)")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("no-heap-to-stack")
+            .long("fno-heap-to-stack")
+            .help("Keep every malloc on the heap")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("remarks")
+            .long("Rpass")
+            .value_name("PASSES")
+            .help("Print what the passes named (e.g. vectorize, heap-to-stack, or .* for all) did, as remarks; separate names with |")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("remarks-missed")
+            .long("Rpass-missed")
+            .value_name("PASSES")
+            .help("Print what the passes named tried and didn't do, and why")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("remarks-analysis")
+            .long("Rpass-analysis")
+            .value_name("PASSES")
+            .help("Print the analysis the passes named based their decisions on, such as the vectorizer's cost per width")
+            .action(ArgAction::Append)
+            .global(true),
+        Arg::new("profile-generate")
+            .long("profile-generate")
+            .value_name("FILE")
+            .help("Count basic block executions; the program writes them to FILE (default: default.profraw, or $LLVM_PROFILE_FILE)")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("default.profraw")
+            .conflicts_with("profile-use")
+            .global(true),
+        Arg::new("profile-use")
+            .long("profile-use")
+            .value_name("FILE")
+            .help("Optimize with the counts in a .profraw, .profdata or JSON profile")
+            .global(true),
+        Arg::new("tiered")
+            .long("tiered")
+            .help("JIT: start on the bytecode interpreter and compile hot functions in the background, at -O1 and then -O3")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("capture-io")
+            .long("capture-io")
+            .value_name("FILE")
+            .help("JIT: write everything the program reads from stdin, files and sockets to a capture bundle FILE")
+            .conflicts_with("replay-io")
+            .global(true),
+        Arg::new("replay-io")
+            .long("replay-io")
+            .value_name("FILE")
+            .help("JIT: run the program on the input in a capture bundle FILE instead of the real stdin, files and network")
+            .global(true),
+        Arg::new("sample-profile")
+            .long("sample-profile")
+            .value_name("FILE")
+            .help("JIT: sample where the program spends its time with perf events (or a SIGPROF timer) and print the hottest functions, or write them to FILE as JSON")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("-")
+            .global(true),
+        Arg::new("detect-leaks")
+            .long("detect-leaks")
+            .help("JIT and interpreter: track the program's malloc/calloc/realloc/strdup calls and print the blocks it didn't free at exit, with where they were allocated")
+            .action(ArgAction::SetTrue)
+            .global(true),
+        Arg::new("leak-suppressions")
+            .long("leak-suppressions")
+            .value_name("FILE")
+            .help("JIT and interpreter: leaks not to report, in Valgrind's suppression format; may be repeated")
+            .action(ArgAction::Append)
+            .requires("detect-leaks")
+            .global(true),
+        Arg::new("show-reachable")
+            .long("show-reachable")
+            .help("With --detect-leaks, also list the blocks still reachable from a global")
+            .action(ArgAction::SetTrue)
+            .requires("detect-leaks")
+            .global(true),
+        Arg::new("crash-bundle")
+            .long("crash-bundle")
+            .value_name("DIR")
+            .help("When the program dies by a signal, write a repro bundle (source, command line, environment) under DIR")
+            .global(true),
+        Arg::new("report")
+            .long("report")
+            .value_name("FILE")
+            .help("Write a machine-readable JSON compilation report to FILE")
+            .global(true),
+        Arg::new("error-limit")
+            .long("ferror-limit")
+            .alias("fmax-errors")
+            .value_name("N")
+            .help("Stop after N errors (0 = no limit); -fmax-errors=N is accepted too")
+            .value_parser(clap::value_parser!(usize))
+            .global(true),
+        Arg::new("locale")
+            .long("locale")
+            .value_name("LANG")
+            .help("Language for diagnostic messages (en, zh, es); defaults to LANG")
+            .global(true),
+        Arg::new("log")
+            .long("log")
+            .value_name("SPEC")
+            .help("Per-module log levels, e.g. info,jit=debug,linker=warn (overrides C_INTERPRETER_LOG)")
+            .global(true),
+        Arg::new("log-format")
+            .long("log-format")
+            .help("Log output format")
+            .value_parser(["text", "json"])
+            .global(true),
+        Arg::new("verbose")
+            .long("verbose")
+            .short('v')
+            .help("Verbose output")
+            .action(ArgAction::SetTrue)
+            .global(true),
+    ]
+}
+
+fn file_arg(help: &'static str) -> Arg {
+    Arg::new("file").help(help).index(1)
+}
+
+fn interpret_arg() -> Arg {
+    Arg::new("interpret")
+        .long("interpret")
+        .short('i')
+        .help("Use interpretation only (no JIT)")
+        .action(ArgAction::SetTrue)
+}
+
+fn output_arg() -> Arg {
+    Arg::new("output")
+        .long("output")
+        .short('o')
+        .help("Output file (for compiled mode)")
+}
+
+/// Shared libraries: dlopened for JIT runs, passed to the linker when compiling;
+/// pkg-config fills in their directories and dependencies
+fn library_args() -> Vec<Arg> {
+    vec![
+        Arg::new("library")
+            .long("library")
+            .short('l')
+            .value_name("LIB")
+            .help("Use the shared library libLIB.so (or -l:FILE for an exact file name)")
+            .action(ArgAction::Append),
+        Arg::new("library-path")
+            .long("library-path")
+            .short('L')
+            .value_name("DIR")
+            .help("Search DIR for -l libraries before the system directories")
+            .action(ArgAction::Append),
+        Arg::new("no-pkg-config")
+            .long("no-pkg-config")
+            .help("Don't look up -l and #pragma comment(lib) libraries with pkg-config (vcpkg on Windows)")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+/// Linking and objcopy-style conversion of the compiled output
+fn link_args() -> Vec<Arg> {
+    vec![
+        Arg::new("nostdlib")
+            .long("nostdlib")
+            .help("Link without libc or the system CRT files, using the built-in crt0 startup code")
+            .action(ArgAction::SetTrue),
+        Arg::new("linker")
+            .long("linker")
+            .value_name("LINKER")
+            .help("Link with the system linker, or the built-in static linker (needs --nostdlib or --libc=bundled)")
+            .value_parser(["system", "builtin"])
+            .default_value("system"),
+        Arg::new("gc-sections")
+            .long("gc-sections")
+            .help("Drop unreferenced functions and variables at link time (needs --linker=builtin)")
+            .action(ArgAction::SetTrue),
+        Arg::new("linker-script")
+            .long("linker-script")
+            .short('T')
+            .value_name("FILE")
+            .help("Place sections with a GNU ld script: MEMORY, SECTIONS, KEEP (needs --linker=builtin)"),
+        Arg::new("oformat")
+            .long("oformat")
+            .value_name("FORMAT")
+            .help("Output format for compiled mode")
+            .value_parser(["elf", "binary", "ihex", "srec"])
+            .default_value("elf"),
+        Arg::new("load-address")
+            .long("load-address")
+            .value_name("ADDR")
+            .help("Relocate the image so it starts at ADDR (e.g. 0x08000000) for binary/ihex/srec"),
+        Arg::new("gap-fill")
+            .long("gap-fill")
+            .value_name("BYTE")
+            .help("Fill byte between sections in binary output")
+            .default_value("0x00"),
+    ]
+}
+
+/// Build the full command tree
+pub fn build_cli() -> Command {
+    Command::new("c-interpreter")
+        .version("0.1.0")
+        .author("Interpreter-C Team")
+        .about("A high-performance C interpreter with JIT compilation")
+        // A bare positional file means `run`, not an unknown subcommand
+        .args_conflicts_with_subcommands(true)
+        .arg(file_arg("The C source file to interpret, or - for stdin"))
+        .arg(
+            Arg::new("jit")
+                .long("jit")
+                .short('j')
+                .help("Use JIT compilation (default)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(interpret_arg())
+        .arg(output_arg())
+        .args(link_args())
+        .args(library_args())
+        .arg(
+            Arg::new("compile")
+                .long("compile")
+                .short('c')
+                .help("Compile to object file instead of executing")
+                .action(ArgAction::SetTrue),
+        )
+        .args(common_args())
+        .subcommand(
+            Command::new("run")
+                .about("JIT compile and run a program (the default without a subcommand)")
+                .arg(file_arg("The C source file to run, or - for stdin"))
+                .arg(interpret_arg())
+                .args(library_args()),
+        )
+        .subcommand(
+            Command::new("compile")
+                .about("Compile to an object file")
+                .arg(file_arg("The C source file to compile, or - for stdin"))
+                .arg(output_arg())
+                .args(link_args())
+                .args(library_args()),
+        )
+        .subcommand(
+            Command::new("build")
+                .about("Compile and link several files, delegating configured ones to the host clang/gcc")
+                .arg(
+                    Arg::new("files")
+                        .help("C source files")
+                        .action(ArgAction::Append)
+                        .required(true),
+                )
+                .arg(output_arg().default_value("a.out"))
+                .arg(
+                    Arg::new("toolchain-config")
+                        .long("toolchain-config")
+                        .value_name("FILE")
+                        .help("Host toolchain fallback configuration (default: ./c-interpreter.toml)"),
+                )
+                .arg(
+                    Arg::new("library")
+                        .long("library")
+                        .short('l')
+                        .value_name("LIB")
+                        .help("Link against LIB")
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            Command::new("repl")
+                .about("Interactive read-eval-print loop"),
+        )
+        .subcommand(
+            Command::new("test")
+                .about("Run C test programs and check their exit status")
+                .arg(
+                    Arg::new("paths")
+                        .help("Test files or directories containing *.c files")
+                        .action(ArgAction::Append)
+                        .default_value("tests"),
+                )
+                .arg(
+                    Arg::new("libc-headers")
+                        .long("libc-headers")
+                        .value_name("DIR")
+                        .help("Instead, parse every system libc header (glibc and musl, or the libc in DIR) and report compatibility")
+                        .num_args(0..=1)
+                        .default_missing_value("auto"),
+                )
+                .arg(
+                    Arg::new("compat-history")
+                        .long("compat-history")
+                        .value_name("FILE")
+                        .help("History file for --libc-headers; runs that regress exit non-zero")
+                        .default_value("header-compat.json"),
+                )
+                .arg(
+                    Arg::new("encoder")
+                        .long("encoder")
+                        .help("Instead, round-trip every x86_64 encoder form through a disassembler")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("dispatch")
+                        .long("dispatch")
+                        .help("Instead, time the bytecode VM's dispatch loops on the built-in kernels and fail below the speedup gate or on a regression")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("dispatch-baseline")
+                        .long("dispatch-baseline")
+                        .value_name("FILE")
+                        .help("Timings --dispatch compares against; the first run records them")
+                        .default_value("benches/dispatch.json"),
+                )
+                .arg(
+                    Arg::new("min-speedup")
+                        .long("min-speedup")
+                        .value_name("FACTOR")
+                        .help("--dispatch: how much faster than the switch loop the threaded loop with superinstructions must be")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("1.15"),
+                )
+                .arg(
+                    Arg::new("max-regression")
+                        .long("max-regression")
+                        .value_name("PERCENT")
+                        .help("--dispatch: slowdown of a kernel against the baseline that fails the run")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("10"),
+                ),
+        )
+        .subcommand(
+            Command::new("debug")
+                .about("Run a program under the interpreter with tracing enabled, or under a gdbstub")
+                .arg(file_arg("The C source file to debug").required(true))
+                .arg(
+                    Arg::new("gdb-port")
+                        .long("gdb-port")
+                        .value_name("PORT")
+                        .help("JIT the program and wait for GDB/LLDB to connect with `target remote :PORT`")
+                        .value_parser(clap::value_parser!(u16)),
+                )
+                .args(library_args()),
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("Parse and check a program without running it")
+                .arg(file_arg("The C source file to analyze, or - for stdin")),
+        )
+        .subcommand(
+            Command::new("instrument")
+                .about("Write the source with logging around the functions selected by --instrument (default: all) instead of running it")
+                .arg(file_arg("The C source file to instrument, or - for stdin").required(true))
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Output file (default: stdout)"),
+                ),
+        )
+        .subcommand(
+            Command::new("reduce")
+                .about("Shrink a C file that crashes the compiler or is miscompiled, keeping it failing the same way")
+                .arg(file_arg("The C source file to reduce").required(true))
+                .arg(
+                    Arg::new("crash-cmd")
+                        .long("crash-cmd")
+                        .value_name("CMD")
+                        .help("Keep CMD failing as it does on the original: the same signal, exit status or timeout. `{}` stands for the file; otherwise it is appended"),
+                )
+                .arg(
+                    Arg::new("test-cmd")
+                        .long("test-cmd")
+                        .value_name("CMD")
+                        .help("Keep CMD exiting with status 0, as with C-Reduce's interestingness tests"),
+                )
+                .arg(
+                    Arg::new("differential")
+                        .long("differential")
+                        .help("Keep `run -i` and `run` at the -O level disagreeing on stdout or exit status")
+                        .action(ArgAction::SetTrue),
+                )
+                .group(ArgGroup::new("test").args(["crash-cmd", "test-cmd", "differential"]).required(true))
+                .arg(
+                    Arg::new("match")
+                        .long("match")
+                        .value_name("TEXT")
+                        .help("With --crash-cmd or --test-cmd, also require TEXT in the command's output"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Longest one test may run")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Where the reduced file goes, updated as it shrinks (default: FILE with .reduced.c)"),
+                ),
+        )
+        .subcommand(
+            Command::new("stack-depth")
+                .about("Report the worst-case stack depth of each entry point of a compiled program, to size its stacks")
+                .arg(file_arg("The C source file to analyze, or - for stdin").required(true))
+                .arg(
+                    Arg::new("entry")
+                        .long("entry")
+                        .value_name("FUNCTION")
+                        .help("Entry point to report (default: main and every external function nothing calls)")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("indirect")
+                        .long("indirect")
+                        .value_name("CALLER=TARGETS")
+                        .help("Functions the indirect calls in CALLER can reach, comma-separated, instead of every address-taken function of the right type")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("recursion")
+                        .long("recursion")
+                        .value_name("FUNCTION=DEPTH")
+                        .help("Bound the recursion cycle through FUNCTION to DEPTH levels")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("frame")
+                        .long("frame")
+                        .value_name("FUNCTION=BYTES")
+                        .help("Stack an external function (e.g. from libc) uses")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("BYTES")
+                        .help("Exit with status 1 if an entry point may need more than BYTES or can't be bounded")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FMT")
+                        .help("Report format")
+                        .value_parser(["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            Command::new("wcet")
+                .about("Estimate the worst-case execution time of each function of a compiled program from the CPU's instruction latencies")
+                .arg(file_arg("The C source file to analyze, or - for stdin").required(true))
+                .arg(
+                    Arg::new("function")
+                        .long("function")
+                        .value_name("FUNCTION")
+                        .help("Function to report (default: every function defined)")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("cpu")
+                        .long("cpu")
+                        .value_name("CPU")
+                        .help("CPU whose scheduling model gives the latencies, e.g. cortex-m4 or skylake"),
+                )
+                .arg(
+                    Arg::new("loop-bound")
+                        .long("loop-bound")
+                        .value_name("FUNCTION[#LOOP]=COUNT")
+                        .help("Most iterations of every loop in FUNCTION, or of its LOOP-th loop as the report numbers them")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("indirect")
+                        .long("indirect")
+                        .value_name("CALLER=TARGETS")
+                        .help("Functions the indirect calls in CALLER can reach, comma-separated")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("cost")
+                        .long("cost")
+                        .value_name("FUNCTION=CYCLES")
+                        .help("Cycles an external function (e.g. from libc or libgcc) takes")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("CYCLES")
+                        .help("Exit with status 1 if a function may take more than CYCLES or can't be bounded")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FMT")
+                        .help("Report format")
+                        .value_parser(["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            Command::new("signal-safety")
+                .about("Report the calls that aren't async-signal-safe reachable from each signal handler of a program")
+                .arg(file_arg("The C source file to analyze, or - for stdin").required(true))
+                .arg(
+                    Arg::new("handler")
+                        .long("handler")
+                        .value_name("FUNCTION")
+                        .help("Check FUNCTION as a handler too, e.g. one installed through a static struct sigaction")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("safe")
+                        .long("safe")
+                        .value_name("FUNCTION")
+                        .help("Accept calls to FUNCTION from handlers")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FMT")
+                        .help("Report format")
+                        .value_parser(["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            Command::new("merge-profiles")
+                .about("Add up the counts of several profiles into one")
+                .arg(
+                    Arg::new("profiles")
+                        .help(".profraw, .profdata or JSON profiles")
+                        .required(true)
+                        .num_args(1..),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Merged profile; .profraw and .json select those formats, anything else is indexed")
+                        .default_value("default.profdata"),
+                ),
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("Print the extended description of a diagnostic code")
+                .arg(
+                    Arg::new("code")
+                        .help("Diagnostic code, e.g. E0042")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("semdiff")
+                .about("Compare the declarations of two versions of a C file and report API and ABI changes")
+                .arg(Arg::new("old").help("The earlier version").required(true))
+                .arg(Arg::new("new").help("The later version").required(true))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FMT")
+                        .help("Report format")
+                        .value_parser(["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            Command::new("abi-check")
+                .about("Compare the exported symbols, signatures and type layouts of two object files or shared libraries")
+                .arg(Arg::new("old").help("The artifact existing binaries were built against").required(true))
+                .arg(Arg::new("new").help("The replacement").required(true))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FMT")
+                        .help("Report format")
+                        .value_parser(["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Run many independent programs from a job file in parallel, each with its own limits")
+                .arg(
+                    Arg::new("jobs-file")
+                        .help("JSON job file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("results")
+                        .long("results")
+                        .value_name("FILE")
+                        .help("Where to write the per-job results")
+                        .default_value("batch-results.json"),
+                )
+                .arg(
+                    Arg::new("live-output")
+                        .long("live-output")
+                        .help("Echo each job's output as it runs, one whole line at a time, prefixed with the job name")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("timestamps")
+                        .long("timestamps")
+                        .help("With --live-output, prefix lines with the time since the batch started")
+                        .requires("live-output")
+                        .action(ArgAction::SetTrue),
+                )
+                .args(library_args()),
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("Serve compile and run requests from a warm background process")
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Unix socket to listen on (default: $XDG_RUNTIME_DIR/c-interpreter/daemon.sock)"),
+                )
+                .arg(
+                    Arg::new("idle-timeout")
+                        .long("idle-timeout")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1800")
+                        .help("Exit after this many seconds without requests; 0 never exits"),
+                )
+                .arg(
+                    Arg::new("status")
+                        .long("status")
+                        .help("Report on the running daemon instead of starting one")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("stop")
+                        .long("stop")
+                        .help("Ask the running daemon to exit")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("status"),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Capture the host environment for bug reports, or compare against a capture")
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Where to write the captured environment")
+                        .default_value("environment.json"),
+                )
+                .arg(
+                    Arg::new("compare")
+                        .long("compare")
+                        .value_name("FILE|BUNDLE")
+                        .help("Compare the current host against a captured environment or a crash repro bundle")
+                        .conflicts_with("output"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify-provenance")
+                .about("Check the provenance notes of an object or executable against its inputs")
+                .arg(
+                    Arg::new("artifact")
+                        .help("Object file or executable built with --embed-provenance")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("source")
+                        .long("source")
+                        .value_name("FILE")
+                        .help("A source the artifact should have been built from, as it is now; repeatable")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("expect-profile")
+                        .long("expect-profile")
+                        .value_name("FILE")
+                        .help("The profile every unit should have been optimized with"),
+                )
+                .arg(
+                    Arg::new("attestation")
+                        .long("attestation")
+                        .help("Print an in-toto statement with a SLSA provenance predicate")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("dump-ir")
+                .about("Print the format version, sections and disassembly of an IR file written by --emit-ir")
+                .arg(
+                    Arg::new("input")
+                        .help("IR file")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Show the usage statistics recorded on this machine; nothing is sent anywhere")
+                .arg(
+                    Arg::new("enable")
+                        .long("enable")
+                        .help("Start recording compile counts, durations, flags and errors locally")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("disable")
+                        .long("disable")
+                        .help("Stop recording; the runs so far are kept")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("enable"),
+                )
+                .arg(
+                    Arg::new("reset")
+                        .long("reset")
+                        .help("Delete every recorded run")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("days")
+                        .long("days")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Only count the runs of the last N days"),
+                )
+                .arg(
+                    Arg::new("top")
+                        .long("top")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10")
+                        .help("How many flags and errors to list"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the summary as JSON")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::new("shell")
+                        .help("Target shell")
+                        .required(true)
+                        .value_parser(["bash", "zsh", "fish", "powershell"]),
+                ),
+        )
+        .subcommand(
+            Command::new("man")
+                .about("Generate man pages for the command and every subcommand")
+                .arg(
+                    Arg::new("dir")
+                        .help("Output directory")
+                        .default_value("man"),
+                ),
+        )
+}
+
+/// Write a completion script for `shell` to stdout
+pub fn print_completions(shell: &str) -> Result<(), CliError> {
+    let shell = match shell {
+        "bash" => Shell::Bash,
+        "zsh" => Shell::Zsh,
+        "fish" => Shell::Fish,
+        "powershell" => Shell::PowerShell,
+        other => return Err(CliError::UnsupportedShell(other.to_string())),
+    };
+
+    let mut cmd = build_cli();
+    clap_complete::generate(shell, &mut cmd, "c-interpreter", &mut io::stdout());
+    Ok(())
+}
+
+/// Write `c-interpreter.1` plus `c-interpreter-<sub>.1` pages into `dir`
+pub fn generate_man_pages(dir: &Path) -> Result<Vec<String>, CliError> {
+    fs::create_dir_all(dir).map_err(CliError::Io)?;
+
+    let cmd = build_cli();
+    let mut written = Vec::new();
+
+    let mut pages = vec![("c-interpreter".to_string(), cmd.clone())];
+    for sub in cmd.get_subcommands() {
+        let name = format!("c-interpreter-{}", sub.get_name());
+        pages.push((name.clone(), sub.clone().name(name)));
+    }
+
+    for (name, page) in pages {
+        let mut buffer = Vec::new();
+        clap_mangen::Man::new(page).render(&mut buffer).map_err(CliError::Io)?;
+        let file = format!("{}.1", name);
+        fs::write(dir.join(&file), buffer).map_err(CliError::Io)?;
+        written.push(file);
+    }
+
+    Ok(written)
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    UnsupportedShell(String),
+    Io(io::Error),
+}
+
+// Example usage:
+/*
+fn main() {
+    // c-interpreter completions zsh > ~/.zfunc/_c-interpreter
+    print_completions("zsh").unwrap();
+
+    // c-interpreter man /usr/local/share/man/man1
+    generate_man_pages(Path::new("/usr/local/share/man/man1")).unwrap();
+}
+*/
@@ -92,11 +92,13 @@ impl CompilerCore {
         &self,
         name: &str,
         args: &[Type],
+        variadic: bool,
         return_type: Type,
         body: &[Instruction],
     ) -> Result<*mut u8, CompilerError> {
-        // Create function type
-        let func_type = self.create_function_type(args, return_type)?;
+        // Create function type; a variadic body reaches its extra
+        // arguments through compiler::varargs
+        let func_type = self.create_function_type(args, variadic, return_type)?;
         
         // Create function
         let name = CString::new(name)?;
@@ -156,6 +158,7 @@ impl CompilerCore {
     unsafe fn create_function_type(
         &self,
         args: &[Type],
+        variadic: bool,
         return_type: Type,
     ) -> Result<LLVMTypeRef, CompilerError> {
         let mut param_types: Vec<LLVMTypeRef> = Vec::with_capacity(args.len());
@@ -170,7 +173,7 @@ impl CompilerCore {
             return_type,
             param_types.as_mut_ptr(),
             param_types.len() as u32,
-            0 // Not vararg
+            variadic as LLVMBool
         ))
     }
 
@@ -282,6 +285,7 @@ fn main() -> Result<(), CompilerError> {
         let code = compiler.compile_function(
             "add",
             &[Type::Int32, Type::Int32],
+            false,
             Type::Int32,
             &[
                 Instruction::Add(
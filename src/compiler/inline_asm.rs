@@ -0,0 +1,160 @@
+// src/compiler/inline_asm.rs
+//! Emitting GNU `asm` statements into LLVM IR
+//! A statement becomes a call to an LLVM inline-asm value, or a `callbr`
+//! for `asm goto`. Constraints come from `arch::inline_asm::resolve`, so by
+//! the time we get here every register and clobber is known to be valid for
+//! the target. LLVM's integrated assembler encodes the template, for the
+//! JIT and for object files alike.
+//!
+//! Register outputs come back as the call's result (a struct when there are
+//! several) and are stored to the output lvalues. Memory operands are passed
+//! by address with an `elementtype` attribute.
+
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::LLVMInlineAsmDialect;
+use crate::arch::inline_asm::ResolvedAsm;
+use crate::frontend::inline_asm::{AsmStatement, InlineAsmError};
+
+/// IR values for a statement's operands, in source order
+pub struct AsmOperandValues {
+    /// Address of each output lvalue and the type stored there
+    pub outputs: Vec<(LLVMValueRef, LLVMTypeRef)>,
+    /// Each input: its value, or its address for memory constraints, and its type
+    pub inputs: Vec<(LLVMValueRef, LLVMTypeRef)>,
+    /// `asm goto`: where execution continues normally, and the blocks of the labels
+    pub targets: Option<(LLVMBasicBlockRef, Vec<LLVMBasicBlockRef>)>,
+}
+
+/// Emit `statement` at the builder's position. For `asm goto` the builder
+/// is left at the end of the fall-through block, after the output stores.
+pub unsafe fn emit(
+    builder: LLVMBuilderRef,
+    context: LLVMContextRef,
+    statement: &AsmStatement,
+    resolved: &ResolvedAsm,
+    values: &AsmOperandValues,
+) -> Result<LLVMValueRef, InlineAsmError> {
+    let template = statement.llvm_template()?;
+
+    let mut args = Vec::new();
+    let mut arg_types = Vec::new();
+    let mut element_types = Vec::new(); // (argument index, pointee) for memory operands
+    let mut returned = Vec::new(); // indices of outputs returned in registers
+
+    for (index, (operand, &(address, ty))) in resolved.outputs.iter().zip(&values.outputs).enumerate() {
+        if operand.is_indirect() {
+            element_types.push((args.len(), ty));
+            args.push(address);
+            arg_types.push(LLVMTypeOf(address));
+        } else {
+            returned.push(index);
+        }
+    }
+    for (operand, &(value, ty)) in resolved.inputs.iter().zip(&values.inputs) {
+        if operand.is_indirect() {
+            element_types.push((args.len(), ty));
+        }
+        args.push(value);
+        arg_types.push(LLVMTypeOf(value));
+    }
+    // The inputs implied by `+`, in the order `resolve` numbered them
+    for (operand, &(address, ty)) in resolved.outputs.iter().zip(&values.outputs) {
+        if operand.read_write && !operand.is_indirect() {
+            args.push(LLVMBuildLoad2(builder, ty, address, c"asm.inout".as_ptr()));
+            arg_types.push(ty);
+        }
+    }
+
+    let mut returned_types: Vec<LLVMTypeRef> = returned.iter().map(|&i| values.outputs[i].1).collect();
+    let return_type = match returned_types.len() {
+        0 => LLVMVoidTypeInContext(context),
+        1 => returned_types[0],
+        n => LLVMStructTypeInContext(context, returned_types.as_mut_ptr(), n as u32, 0),
+    };
+    let function_type = LLVMFunctionType(return_type, arg_types.as_mut_ptr(), arg_types.len() as u32, 0);
+
+    let asm = LLVMGetInlineAsm(
+        function_type,
+        template.as_ptr() as *mut _,
+        template.len(),
+        resolved.constraints.as_ptr() as *mut _,
+        resolved.constraints.len(),
+        statement.has_side_effects() as LLVMBool,
+        0,
+        LLVMInlineAsmDialect::LLVMInlineAsmDialectATT,
+        0,
+    );
+
+    let call = match &values.targets {
+        Some((fallthrough, labels)) => {
+            let mut labels = labels.clone();
+            let call = LLVMBuildCallBr(
+                builder,
+                function_type,
+                asm,
+                *fallthrough,
+                labels.as_mut_ptr(),
+                labels.len() as u32,
+                args.as_mut_ptr(),
+                args.len() as u32,
+                std::ptr::null_mut(),
+                0,
+                c"".as_ptr(),
+            );
+            // Outputs are only defined on the fall-through path
+            LLVMPositionBuilderAtEnd(builder, *fallthrough);
+            call
+        }
+        None => LLVMBuildCall2(
+            builder,
+            function_type,
+            asm,
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c"".as_ptr(),
+        ),
+    };
+
+    let kind = attribute_kind("elementtype");
+    for (index, ty) in element_types {
+        let attribute = LLVMCreateTypeAttribute(context, kind, ty);
+        LLVMAddCallSiteAttribute(call, index as u32 + 1, attribute);
+    }
+    let nounwind = LLVMCreateEnumAttribute(context, attribute_kind("nounwind"), 0);
+    LLVMAddCallSiteAttribute(call, llvm_sys::LLVMAttributeFunctionIndex, nounwind);
+
+    for (position, &output) in returned.iter().enumerate() {
+        let value = if returned.len() == 1 {
+            call
+        } else {
+            LLVMBuildExtractValue(builder, call, position as u32, c"asm.out".as_ptr())
+        };
+        LLVMBuildStore(builder, value, values.outputs[output].0);
+    }
+
+    Ok(call)
+}
+
+unsafe fn attribute_kind(name: &str) -> u32 {
+    LLVMGetEnumAttributeKindForName(name.as_ptr() as *const _, name.len())
+}
+
+// Example usage:
+/*
+unsafe fn lower(builder: LLVMBuilderRef, context: LLVMContextRef, a: LLVMValueRef, b: LLVMValueRef) -> Result<(), Box<dyn std::error::Error>> {
+    // int a, b; __asm__ ("addl %1, %0" : "+r"(a) : "r"(b));
+    let statement = parse_asm_statement(r#"__asm__ ("addl %1, %0" : "+r"(a) : "r"(b));"#)?;
+    let registry = ArchitectureRegistry::new();
+    let resolved = resolve(&statement, registry.get_support(Architecture::X86_64).unwrap())?;
+
+    let i32_ty = LLVMInt32TypeInContext(context);
+    let b_value = LLVMBuildLoad2(builder, i32_ty, b, c"b".as_ptr());
+    emit(builder, context, &statement, &resolved, &AsmOperandValues {
+        outputs: vec![(a, i32_ty)],
+        inputs: vec![(b_value, i32_ty)],
+        targets: None,
+    })?;
+    Ok(())
+}
+*/
@@ -7,9 +7,54 @@ use llvm_sys::core::*;
 use llvm_sys::target::*;
 use llvm_sys::execution_engine::*;
 use std::ffi::{CString, CStr};
+use std::path::{Path, PathBuf};
+use parking_lot::RwLock;
 
 // New imports for architecture support
+use crate::analysis::signal_safety::{self, SignalSafetyError, SignalSafetyOptions, SignalSafetyReport};
+use crate::analysis::stack_depth::{self, StackDepthError, StackDepthOptions, StackReport};
+use crate::analysis::wcet::{self, WcetError, WcetOptions, WcetReport};
 use crate::arch::{Architecture, ArchitectureRegistry};
+use crate::debug::jit_interface::{JitRegistration, SymfileBuilder};
+use crate::debug::stack_capture::JitSymbols;
+use crate::diagnostics::codes;
+use crate::diagnostics::engine::Diagnostic;
+use crate::frontend::apple;
+use crate::frontend::usdt::{self, Lowering, UsdtError};
+use crate::jit::host::{HostFunction, HostFunctions, HostSignature};
+use crate::jit::probes::{self, ProbeError, ProbeTable};
+use crate::jit::stackmap::{self, StackMapError, StackMaps};
+use crate::jit::JITError;
+use crate::driver::fallback::find_host_compiler;
+use crate::linker::script::LinkerScript;
+use crate::linker::static_elf::{StaticLinkError, StaticLinker};
+use crate::memory::leaks;
+use crate::optimizer::budget::{self as function_budget, BudgetError, FunctionBudget};
+use crate::optimizer::escape::{EscapeError, HeapToStackPass};
+use crate::optimizer::evaluate::{Budget, CompileTimeEvaluation};
+use crate::optimizer::fastmath::{FastMathPass, FpOptions, FpPragmas};
+use crate::optimizer::fenv::{instructions, FenvAccessPass, FenvAccessRegions};
+use crate::optimizer::leaks::LeakInstrumentation;
+use crate::optimizer::linkage::{LinkagePass, LinkageError, SymbolAttributes};
+use crate::optimizer::loops::{LoopTransformError, LoopTransformPass, LoopTransforms};
+use crate::optimizer::tiling::CacheGeometry;
+use crate::optimizer::vectorize::{VectorizeError, VectorizePass};
+use crate::optimizer::overflow::{OverflowMode, OverflowPass};
+use crate::optimizer::sanitize::{SanitizerSet, UndefinedSanitizer};
+use crate::pgo::instrument::{ProfileInstrumentation, ProfileUse};
+use crate::pgo::profile::{Profile, ProfileError};
+use crate::pipeline::cache::{CacheKey, CachedArtifact, CompilationCache};
+use crate::report::{self, OptimizationRemark};
+use crate::report::provenance::{self, Provenance, ProvenanceError};
+use crate::runtime::capture::{self, CaptureMode};
+use crate::runtime::coroutine;
+use crate::runtime::deterministic::{self, DeterministicConfig, DeterministicError};
+use crate::runtime::dynamic_loader::{DynamicLoader, DynamicLoaderError, LibrarySearch};
+
+pub mod core;
+pub mod inline_asm;
+pub mod setjmp;
+pub mod varargs;
 
 pub struct CompilerSystem {
     // Core compilation components
@@ -18,6 +63,7 @@ pub struct CompilerSystem {
     backend: Backend,
     
     // Target information
+    target_triple: String,
     target_machine: LLVMTargetMachineRef,
     target_data: LLVMTargetDataRef,
     
@@ -31,6 +77,24 @@ pub struct CompilerSystem {
     // Architecture support
     architecture_registry: Arc<ArchitectureRegistry>,
     current_architecture: Architecture,
+
+    // Rust functions callable from JIT-compiled C
+    host_functions: RwLock<HostFunctions>,
+
+    // Safepoints of JIT-compiled functions that hold managed pointers
+    stack_maps: Arc<RwLock<StackMaps>>,
+
+    // Functions with patchable prologues, for live probes
+    probes: Arc<RwLock<ProbeTable>>,
+
+    // Where JIT-compiled functions start, to name them in stack captures
+    jit_symbols: Arc<RwLock<JitSymbols>>,
+
+    // JIT-compiled functions announced to GDB/LLDB, unregistered on drop
+    debug_registrations: RwLock<Vec<JitRegistration>>,
+
+    // Remarks of functions that fell back to -O0, until taken
+    remarks: RwLock<Vec<OptimizationRemark>>,
 }
 
 impl CompilerSystem {
@@ -56,6 +120,7 @@ impl CompilerSystem {
             frontend: Frontend::new()?,
             middle_end: MiddleEnd::new()?,
             backend: Backend::new(target_machine, arch)?,
+            target_triple: target_triple.to_string(),
             target_machine,
             target_data,
             runtime: RuntimeSystem::new()?,
@@ -63,6 +128,12 @@ impl CompilerSystem {
             abi_handler: ABIHandler::new(target_data)?,
             architecture_registry,
             current_architecture: arch,
+            host_functions: RwLock::new(HostFunctions::new()),
+            stack_maps: Arc::new(RwLock::new(StackMaps::new())),
+            probes: Arc::new(RwLock::new(ProbeTable::new())),
+            jit_symbols: Arc::new(RwLock::new(JitSymbols::new())),
+            debug_registrations: RwLock::new(Vec::new()),
+            remarks: RwLock::new(Vec::new()),
         })
     }
 
@@ -85,23 +156,123 @@ impl CompilerSystem {
         output_file: &str,
         options: &CompilerOptions
     ) -> Result<(), CompilerError> {
+        // Unchanged translation units skip straight to linking
+        let mut cache = options
+            .cache_dir
+            .as_ref()
+            .and_then(|dir| CompilationCache::open(dir).ok());
+        let triple = self.target_triple.as_str();
+        // The SDK headers choose their Apple branches on these
+        let apple_target = triple.contains("apple");
+        let predefined = if apple_target { apple::predefined_macros() } else { Vec::new() };
+        let mut preprocessed = report::timed("preprocess", || {
+            self.frontend.preprocess_file(input_file, &options.include_dirs, &options.system_include_dirs, &predefined)
+        })?;
+
+        // macOS SDK headers use clang extensions the parser doesn't know
+        if apple_target {
+            let normalized = apple::normalize(&preprocessed, input_file);
+            if !normalized.diagnostics.is_empty() {
+                return Err(CompilerError::Unsupported(normalized.diagnostics));
+            }
+            preprocessed = normalized.source;
+        }
+
+        // Loaded before the cache lookup, so a bad profile fails here; its
+        // path and contents are part of `codegen_fingerprint`
+        let profile = match &options.profile_use {
+            Some(path) => Some(Profile::load(path).map_err(CompilerError::Profile)?),
+            None => None,
+        };
+        // The note names the source file, which the preprocessed text doesn't
+        let provenance_note = if options.embed_provenance {
+            let record = Provenance::collect(
+                Path::new(input_file),
+                &preprocessed,
+                &options.codegen_fingerprint(),
+                triple,
+                options.profile_use.as_deref(),
+            )
+            .map_err(CompilerError::Provenance)?;
+            Some(record.to_note().map_err(CompilerError::Provenance)?)
+        } else {
+            None
+        };
+        let cache_key = cache.as_ref().map(|_| {
+            let mut fingerprint = options.codegen_fingerprint();
+            if let Some(note) = &provenance_note {
+                fingerprint.push_str(&format!(";note={}", provenance::sha256(note)));
+            }
+            CacheKey::new(&preprocessed, &fingerprint, triple)
+        });
+
+        if let (Some(cache), Some(key)) = (cache.as_mut(), cache_key.as_ref()) {
+            let cached = cache.get(key);
+            report::note_cache_access(cached.is_some());
+            if let Some(artifact) = cached {
+                let obj_file = self.backend.load_object(&artifact.object, output_file)?;
+                if options.link {
+                    self.link(obj_file, output_file, &options.link_options, options.target_architecture)?;
+                }
+                return Ok(());
+            }
+        }
+
         // Parse input file
-        let ast = self.frontend.parse_file(input_file)?;
+        let ast = report::timed("parse", || self.frontend.parse_preprocessed(input_file, &preprocessed))?;
         
-        // Generate IR
-        let module = self.middle_end.generate_ir(&ast)?;
+        // Generate IR; FENV_ACCESS regions must not have their FP math folded
+        let fenv_regions = FenvAccessRegions::scan(&preprocessed);
+        let module = report::timed("ir", || -> Result<_, CompilerError> {
+            let module = self.middle_end.generate_ir(&ast, &fenv_regions)?;
+            self.run_semantic_passes(module.as_llvm_ref(), &preprocessed, &fenv_regions, options.sanitizers, options.fp, options.overflow)?;
+            self.apply_profile(module.as_llvm_ref(), options.profile_generate.as_deref(), profile.as_ref())?;
+            Ok(module)
+        })?;
         
         // Optimize
         if options.optimization_level > 0 {
-            self.middle_end.optimize_module(&module, options.optimization_level)?;
+            report::timed("optimize", || -> Result<(), CompilerError> {
+                self.guard_functions(module.as_llvm_ref(), options)?;
+                self.transform_loops(module.as_llvm_ref(), options.optimization_level, options.loop_transforms, None, input_file)?;
+                self.plan_vectorization(module.as_llvm_ref(), options.optimization_level, options.target_architecture)?;
+                self.middle_end.optimize_module(&module, options.optimization_level)?;
+                self.promote_heap(module.as_llvm_ref(), options.optimization_level, options.heap_to_stack)
+            })?;
         }
         
+        // A section per function and variable for the linker to collect
+        if options.link && options.link_options.gc_sections {
+            split_sections(module.as_llvm_ref());
+        }
+
+        if let Some(note) = &provenance_note {
+            provenance::embed(module.as_llvm_ref(), triple, note);
+        }
+
+        // What the optimizer left of each function, for --report
+        let ir_sizes = if report::is_active() { ir_instruction_counts(module.as_llvm_ref()) } else { Vec::new() };
+
         // Generate code
-        let obj_file = self.backend.generate_code(&module, output_file)?;
+        let obj_file = report::timed("codegen", || self.backend.generate_code(&module, output_file))?;
+        if !ir_sizes.is_empty() {
+            report_function_sizes(obj_file.path(), &ir_sizes);
+        }
+
+        if let (Some(cache), Some(key)) = (cache.as_mut(), cache_key.as_ref()) {
+            match std::fs::read(obj_file.path()) {
+                Ok(bytes) => {
+                    if let Err(e) = cache.put(key, &CachedArtifact::from_object(bytes)) {
+                        log::warn!("could not store {} in the compilation cache: {:?}", input_file, e);
+                    }
+                }
+                Err(e) => log::warn!("could not read object for caching: {}", e),
+            }
+        }
         
         // Link if needed
         if options.link {
-            self.linker.link(obj_file, output_file, &options.link_options)?;
+            report::timed("link", || self.link(obj_file, output_file, &options.link_options, options.target_architecture))?;
         }
         
         Ok(())
@@ -112,23 +283,544 @@ impl CompilerSystem {
         source: &str,
         options: &JITOptions
     ) -> Result<*mut u8, CompilerError> {
+        // Probe points only do anything interpreted; keep their arguments
+        let rewritten = usdt::rewrite(source, "<jit>", Lowering::Compiled).map_err(CompilerError::Usdt)?;
+        let source = rewritten.source.as_str();
+
         // Parse source
-        let ast = self.frontend.parse_string(source)?;
+        let ast = report::timed("parse", || self.frontend.parse_string(source, &options.include_dirs, &options.system_include_dirs))?;
         
         // Generate IR with JIT options
-        let module = self.middle_end.generate_ir_for_jit(&ast, options)?;
+        let fenv_regions = FenvAccessRegions::scan(source);
+        let module = report::timed("ir", || -> Result<_, CompilerError> {
+            let module = self.middle_end.generate_ir_for_jit(&ast, options, &fenv_regions)?;
+            self.run_semantic_passes(module.as_llvm_ref(), source, &fenv_regions, options.sanitizers, options.fp, options.overflow)?;
+            let profile = match &options.profile_use {
+                Some(path) => Some(Profile::load(path).map_err(CompilerError::Profile)?),
+                None => None,
+            };
+            self.apply_profile(module.as_llvm_ref(), options.profile_generate.as_deref(), profile.as_ref())?;
+            Ok(module)
+        })?;
+
+        // Before heap-to-stack promotion and inlining can hide allocations
+        if options.detect_leaks {
+            let mut instrumentation = LeakInstrumentation::new(Some(self.frontend.source_map()));
+            instrumentation.run(module.as_llvm_ref());
+            log::debug!("leak detection: {:?} call(s) tracked", instrumentation.stats());
+        }
+
+        // Run what doesn't depend on run time now: pure calls and static constructors
+        if let Some(budget) = options.evaluation_budget {
+            let mut evaluation = CompileTimeEvaluation::new(budget);
+            match evaluation.run(module.as_llvm_ref()) {
+                Ok(()) => {
+                    let (folded, constructors, steps) = evaluation.stats();
+                    log::debug!("compile-time evaluation: {} call(s) folded, {} constructor(s) run, {} steps", folded, constructors, steps);
+                }
+                Err(e) => log::debug!("compile-time evaluation skipped: {}", e),
+            }
+        }
+
+        // A nop at each function's entry that a probe can be patched into later
+        let patchable = if options.patchable_prologues {
+            probes::mark_patchable(module.as_llvm_ref()).map_err(CompilerError::Probe)?
+        } else {
+            Vec::new()
+        };
         
         // Optimize for JIT
-        self.middle_end.optimize_for_jit(&module)?;
+        // The code runs here, so tiles can be sized for this machine's caches
+        let cache = self
+            .architecture_registry
+            .get_support(self.current_architecture)
+            .map(|support| CacheGeometry::from_features(&support.feature_detector.detect_features()));
+        report::timed("optimize", || -> Result<(), CompilerError> {
+            self.transform_loops(module.as_llvm_ref(), options.optimization_level, options.loop_transforms, cache, "<jit>")?;
+            self.plan_vectorization(module.as_llvm_ref(), options.optimization_level, None)?;
+            self.middle_end.optimize_for_jit(&module)?;
+            self.promote_heap(module.as_llvm_ref(), options.optimization_level, options.heap_to_stack)
+        })?;
+
+        // Safepoints for managed pointers; the optimizer can't see through them
+        let gc_functions = stackmap::rewrite_statepoints(module.as_llvm_ref(), self.target_machine)
+            .map_err(CompilerError::StackMap)?;
         
+        // Resolve library calls against these before falling back to the host process
+        for archive in &options.archives {
+            self.backend.jit_add_archive(archive)?;
+        }
+
+        // Bind calls into -l libraries before the engine falls back to the host process
+        if !options.shared_libraries.is_empty() {
+            let mut loader = DynamicLoader::new(&options.shared_libraries).map_err(CompilerError::DynamicLoader)?;
+            let resolved = loader.resolve_module(self.backend.jit_engine(), module.as_llvm_ref());
+            // With archives loaded the engine still has somewhere else to look
+            if options.archives.is_empty() && !resolved.unresolved.is_empty() {
+                return Err(CompilerError::DynamicLoader(DynamicLoaderError::UnresolvedSymbols(resolved.unresolved)));
+            }
+        }
+
+        // Coroutines, deterministic mode, I/O capture and leak detection go
+        // through host functions; ones the embedder registered under the
+        // same names win
+        let mut overrides = coroutine::host_exports();
+        if options.detect_leaks {
+            overrides.extend(leaks::host_exports());
+        }
+        if let Some(config) = &options.deterministic {
+            overrides.extend(deterministic::host_overrides(config).map_err(CompilerError::Deterministic)?);
+        }
+        if options.capture.is_some() {
+            overrides.extend(capture::host_overrides());
+        }
+        {
+            let mut host_functions = self.host_functions.write();
+            for export in overrides {
+                if host_functions.contains(export.name) {
+                    continue;
+                }
+                host_functions
+                    .register(export.name, export.signature, export.function)
+                    .map_err(CompilerError::HostFunction)?;
+            }
+        }
+
+        // Calls to registered host functions
+        self.host_functions
+            .write()
+            .bind(LLVMGetModuleContext(module.as_llvm_ref()), module.as_llvm_ref(), self.backend.jit_engine())
+            .map_err(CompilerError::HostFunction)?;
+
+        // Functions that survived optimization, named before the module is
+        // consumed, with their IR instruction counts
+        let defined = ir_instruction_counts(module.as_llvm_ref());
+
+        // Globals and their sizes: where leak detection looks for pointers
+        let mut globals = Vec::new();
+        if options.detect_leaks {
+            let target_data = LLVMCreateTargetData(LLVMGetDataLayoutStr(module.as_llvm_ref()));
+            let mut global = LLVMGetFirstGlobal(module.as_llvm_ref());
+            while !global.is_null() {
+                if LLVMIsDeclaration(global) == 0 {
+                    let mut len = 0;
+                    let name = LLVMGetValueName2(global, &mut len);
+                    let size = LLVMABISizeOfType(target_data, LLVMGlobalGetValueType(global)) as usize;
+                    globals.push((CStr::from_ptr(name).to_string_lossy().into_owned(), size));
+                }
+                global = LLVMGetNextGlobal(global);
+            }
+            LLVMDisposeTargetData(target_data);
+        }
+
         // JIT compile
-        let code_ptr = self.backend.jit_compile(&module)?;
+        let code_ptr = report::timed("codegen", || self.backend.jit_compile(&module))?;
+
+        for (name, size) in &globals {
+            if let Some(address) = self.jit_symbol_address(name) {
+                leaks::add_root(address as usize, *size);
+            }
+        }
+
+        let mut addresses = Vec::new();
+        for (name, instructions) in &defined {
+            if let Some(address) = self.jit_symbol_address(name) {
+                self.jit_symbols.write().insert(name, address);
+                addresses.push((name.as_str(), address as u64, *instructions));
+            }
+        }
+        self.register_debug_symbols(addresses);
+
+        if gc_functions > 0 {
+            for section in self.backend.jit_sections(&module, ".llvm_stackmaps") {
+                let maps = StackMaps::parse(section).map_err(CompilerError::StackMap)?;
+                self.stack_maps.write().extend(maps);
+            }
+        }
+
+        // Functions the optimizer inlined away have no address to patch
+        for name in patchable {
+            if let Some(address) = self.jit_symbol_address(&name) {
+                self.probes.write().add_function(&name, address).map_err(CompilerError::Probe)?;
+            }
+        }
         
         // Setup runtime
         self.runtime.setup_jit_function(code_ptr)?;
         
         Ok(code_ptr)
     }
+
+    /// Announce the finalized functions to an attached (or later attaching)
+    /// debugger. Each one extends to the next function's start; the last is
+    /// sized from its IR. Symbols only, without DWARF.
+    fn register_debug_symbols(&self, mut functions: Vec<(&str, u64, usize)>) {
+        const BYTES_PER_INSTRUCTION: u64 = 6;
+        if functions.is_empty() {
+            return;
+        }
+        functions.sort_by_key(|&(_, address, _)| address);
+
+        let mut builder = SymfileBuilder::new();
+        for (index, &(name, address, instructions)) in functions.iter().enumerate() {
+            let size = match functions.get(index + 1) {
+                Some(&(_, next, _)) => next - address,
+                None => instructions.max(1) as u64 * BYTES_PER_INSTRUCTION,
+            };
+            builder = builder.function(name, address, size);
+        }
+        match builder.build() {
+            Ok(symfile) => self.debug_registrations.write().push(JitRegistration::register(symfile)),
+            Err(e) => log::warn!("JIT functions not registered with the debugger: {:?}", e),
+        }
+    }
+
+    /// The IR or assembly `compile_file` would produce for `source`, as
+    /// text; `test` matches it against `// CHECK:` lines
+    pub unsafe fn emit(
+        &self,
+        source: &str,
+        options: &CompilerOptions,
+        stage: EmitStage,
+    ) -> Result<String, CompilerError> {
+        let ast = self.frontend.parse_string(source, &options.include_dirs, &options.system_include_dirs)?;
+        let fenv_regions = FenvAccessRegions::scan(source);
+        let module = self.middle_end.generate_ir(&ast, &fenv_regions)?;
+        self.run_semantic_passes(module.as_llvm_ref(), source, &fenv_regions, options.sanitizers, options.fp, options.overflow)?;
+        if stage != EmitStage::Ir && options.optimization_level > 0 {
+            self.guard_functions(module.as_llvm_ref(), options)?;
+            self.transform_loops(module.as_llvm_ref(), options.optimization_level, options.loop_transforms, None, "<source>")?;
+            self.plan_vectorization(module.as_llvm_ref(), options.optimization_level, options.target_architecture)?;
+            self.middle_end.optimize_module(&module, options.optimization_level)?;
+            self.promote_heap(module.as_llvm_ref(), options.optimization_level, options.heap_to_stack)?;
+        }
+
+        let text = match stage {
+            EmitStage::Ir | EmitStage::OptimizedIr => LLVMPrintModuleToString(module.as_llvm_ref()),
+            EmitStage::Assembly => {
+                let mut buffer = std::ptr::null_mut();
+                let mut error = std::ptr::null_mut();
+                let failed = llvm_sys::target_machine::LLVMTargetMachineEmitToMemoryBuffer(
+                    self.target_machine,
+                    module.as_llvm_ref(),
+                    llvm_sys::target_machine::LLVMCodeGenFileType::LLVMAssemblyFile,
+                    &mut error,
+                    &mut buffer,
+                );
+                if failed != 0 {
+                    let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+                    LLVMDisposeMessage(error);
+                    return Err(CompilerError::Backend(BackendError::CodeGeneration(message)));
+                }
+                let start = LLVMGetBufferStart(buffer) as *const u8;
+                let bytes = std::slice::from_raw_parts(start, LLVMGetBufferSize(buffer));
+                let text = String::from_utf8_lossy(bytes).into_owned();
+                LLVMDisposeMemoryBuffer(buffer);
+                return Ok(text);
+            }
+        };
+        let result = CStr::from_ptr(text).to_string_lossy().into_owned();
+        LLVMDisposeMessage(text);
+        Ok(result)
+    }
+
+    /// Worst-case stack depth of the entry points of `source`, compiled as
+    /// `compile_file` would; see `analysis::stack_depth`
+    pub unsafe fn stack_depth(
+        &self,
+        source: &str,
+        options: &CompilerOptions,
+        analysis: &StackDepthOptions,
+    ) -> Result<StackReport, CompilerError> {
+        let ast = self.frontend.parse_string(source, &options.include_dirs, &options.system_include_dirs)?;
+        let fenv_regions = FenvAccessRegions::scan(source);
+        let module = self.middle_end.generate_ir(&ast, &fenv_regions)?;
+        self.run_semantic_passes(module.as_llvm_ref(), source, &fenv_regions, options.sanitizers, options.fp, options.overflow)?;
+        if options.optimization_level > 0 {
+            self.guard_functions(module.as_llvm_ref(), options)?;
+            self.transform_loops(module.as_llvm_ref(), options.optimization_level, options.loop_transforms, None, "<source>")?;
+            self.plan_vectorization(module.as_llvm_ref(), options.optimization_level, options.target_architecture)?;
+            self.middle_end.optimize_module(&module, options.optimization_level)?;
+            self.promote_heap(module.as_llvm_ref(), options.optimization_level, options.heap_to_stack)?;
+        }
+        let architecture = options.target_architecture.unwrap_or(self.current_architecture);
+        stack_depth::analyze(module.as_llvm_ref(), self.target_machine, architecture, analysis).map_err(CompilerError::StackDepth)
+    }
+
+    /// Worst-case execution time of the functions of `source`, compiled as
+    /// `compile_file` would; see `analysis::wcet`
+    pub unsafe fn wcet(
+        &self,
+        source: &str,
+        options: &CompilerOptions,
+        analysis: &WcetOptions,
+    ) -> Result<WcetReport, CompilerError> {
+        let ast = self.frontend.parse_string(source, &options.include_dirs, &options.system_include_dirs)?;
+        let fenv_regions = FenvAccessRegions::scan(source);
+        let module = self.middle_end.generate_ir(&ast, &fenv_regions)?;
+        self.run_semantic_passes(module.as_llvm_ref(), source, &fenv_regions, options.sanitizers, options.fp, options.overflow)?;
+        if options.optimization_level > 0 {
+            self.guard_functions(module.as_llvm_ref(), options)?;
+            self.transform_loops(module.as_llvm_ref(), options.optimization_level, options.loop_transforms, None, "<source>")?;
+            self.plan_vectorization(module.as_llvm_ref(), options.optimization_level, options.target_architecture)?;
+            self.middle_end.optimize_module(&module, options.optimization_level)?;
+            self.promote_heap(module.as_llvm_ref(), options.optimization_level, options.heap_to_stack)?;
+        }
+        wcet::analyze(module.as_llvm_ref(), self.target_machine, analysis).map_err(CompilerError::Wcet)
+    }
+
+    /// Calls that aren't async-signal-safe reachable from the signal
+    /// handlers of `source`, on its unoptimized IR; see
+    /// `analysis::signal_safety`
+    pub unsafe fn signal_safety(
+        &self,
+        source: &str,
+        options: &CompilerOptions,
+        analysis: &SignalSafetyOptions,
+    ) -> Result<SignalSafetyReport, CompilerError> {
+        let ast = self.frontend.parse_string(source, &options.include_dirs, &options.system_include_dirs)?;
+        let fenv_regions = FenvAccessRegions::scan(source);
+        let module = self.middle_end.generate_ir(&ast, &fenv_regions)?;
+        self.run_semantic_passes(module.as_llvm_ref(), source, &fenv_regions, options.sanitizers, options.fp, options.overflow)?;
+        signal_safety::analyze(module.as_llvm_ref(), analysis).map_err(CompilerError::SignalSafety)
+    }
+
+    /// Passes that give C semantics to freshly generated IR, in the order
+    /// every path must run them: before the optimizer can exploit UB or
+    /// fold FP math the program asked to keep
+    unsafe fn run_semantic_passes(
+        &self,
+        module: LLVMModuleRef,
+        source: &str,
+        fenv_regions: &FenvAccessRegions,
+        sanitizers: SanitizerSet,
+        fp: FpOptions,
+        overflow: OverflowMode,
+    ) -> Result<(), CompilerError> {
+        if !fenv_regions.is_empty() {
+            FenvAccessPass::new(fenv_regions).run(module).map_err(CompilerError::Fenv)?;
+        }
+
+        // Weak, alias and versioned symbols, before the optimizer inlines
+        // or drops what they name
+        let symbol_attributes = SymbolAttributes::scan(source);
+        if !symbol_attributes.is_empty() {
+            LinkagePass::new(&symbol_attributes).run(module).map_err(CompilerError::Linkage)?;
+        }
+
+        // Resolve signed overflow before the optimizer can assume it away
+        OverflowPass::new(overflow, Some(self.frontend.source_map())).run(module);
+
+        // Instrument before the optimizer exploits the UB being checked
+        if sanitizers.undefined {
+            UndefinedSanitizer::new(Some(self.frontend.source_map()))
+                .instrument_module(module)
+                .map_err(CompilerError::Sanitizer)?;
+        }
+
+        // Strict IEEE unless a flag or pragma relaxes it, matching the interpreter
+        let fp_pragmas = FpPragmas::scan(source);
+        if !fp.is_strict() || !fp_pragmas.is_empty() {
+            FastMathPass::new(fp, &fp_pragmas).run(module);
+        }
+        Ok(())
+    }
+
+    /// Count block executions for `--profile-generate`, or hand the counts
+    /// of `--profile-use` to the optimizer; see `pgo::instrument`. Runs at
+    /// the same point for both so the blocks line up.
+    unsafe fn apply_profile(&self, module: LLVMModuleRef, generate: Option<&Path>, profile: Option<&Profile>) -> Result<(), CompilerError> {
+        if let Some(path) = generate {
+            let mut instrumentation = ProfileInstrumentation::new(&path.to_string_lossy());
+            instrumentation.run(module).map_err(CompilerError::Profile)?;
+            let (functions, counters) = instrumentation.stats();
+            log::debug!("profile instrumentation: {} function(s), {} counter(s)", functions, counters);
+        }
+        if let Some(profile) = profile {
+            let mut profile_use = ProfileUse::new(profile);
+            profile_use.run(module);
+            let (annotated, stale, missing) = profile_use.stats();
+            log::debug!("profile: {} function(s) annotated, {} out of date, {} not profiled", annotated, stale, missing);
+        }
+        Ok(())
+    }
+
+    /// Fall back to -O0 for the functions that can't be optimized within
+    /// `options.function_budget`; see `optimizer::budget`
+    unsafe fn guard_functions(&self, module: LLVMModuleRef, options: &CompilerOptions) -> Result<(), CompilerError> {
+        let Some(budget) = &options.function_budget else { return Ok(()) };
+        let remarks = function_budget::guard_functions(module, options.optimization_level, self.target_machine, budget)
+            .map_err(CompilerError::FunctionBudget)?;
+        for remark in &remarks {
+            log::warn!("remark: {}: {}", remark.function, remark.message);
+        }
+        self.remarks.write().extend(remarks);
+        Ok(())
+    }
+
+    /// Interchange and fuse loops at -O3, before the module pipeline, and
+    /// tile them for `cache` when it's known; see `optimizer::loops`. The
+    /// before/after metrics are exported under `unit`.
+    unsafe fn transform_loops(
+        &self,
+        module: LLVMModuleRef,
+        level: u32,
+        transforms: LoopTransforms,
+        cache: Option<CacheGeometry>,
+        unit: &str,
+    ) -> Result<(), CompilerError> {
+        if level < 3 || transforms.is_empty() {
+            return Ok(());
+        }
+        let mut pass = LoopTransformPass::new(transforms, self.target_machine);
+        if let Some(cache) = cache {
+            pass = pass.with_tiling(cache);
+        }
+        let metrics = pass.run(module).map_err(CompilerError::LoopTransform)?;
+        metrics.export(unit);
+        let (functions, fused, contiguous) = pass.stats();
+        log::debug!(
+            "loop transforms: {} function(s) with loops, {} nest(s) tiled, {} loop(s) fused, {} access(es) made contiguous",
+            functions,
+            metrics.tiled,
+            fused,
+            contiguous
+        );
+        self.remarks.write().extend(pass.remarks());
+        Ok(())
+    }
+
+    /// Choose the vector width and interleave count of each innermost loop
+    /// at -O2 and up, from what they cost on the features of
+    /// `architecture` (the host's for `None`); see `optimizer::vectorize`
+    unsafe fn plan_vectorization(&self, module: LLVMModuleRef, level: u32, architecture: Option<Architecture>) -> Result<(), CompilerError> {
+        if level < 2 {
+            return Ok(());
+        }
+        let Some(support) = self.architecture_registry.get_support(architecture.unwrap_or(self.current_architecture)) else {
+            return Ok(());
+        };
+        let mut pass = VectorizePass::new(&support.feature_detector.detect_features(), self.target_machine);
+        pass.run(module).map_err(CompilerError::Vectorize)?;
+        let (vectorized, scalar, rejected) = pass.stats();
+        log::debug!(
+            "vectorization ({}): {} loop(s) vectorized, {} cheaper as scalar code, {} not vectorizable",
+            pass.isa(),
+            vectorized,
+            scalar,
+            rejected
+        );
+        self.remarks.write().extend(pass.remarks().iter().cloned());
+        Ok(())
+    }
+
+    /// Move the allocations that don't escape their function to the stack
+    /// at -O2 and up, after the module pipeline inlined what it would; see
+    /// `optimizer::escape`
+    unsafe fn promote_heap(&self, module: LLVMModuleRef, level: u32, enabled: bool) -> Result<(), CompilerError> {
+        if level < 2 || !enabled {
+            return Ok(());
+        }
+        let mut pass = HeapToStackPass::new(self.target_machine);
+        pass.run(module).map_err(CompilerError::HeapToStack)?;
+        let (promoted, removed, frees, rejected) = pass.stats();
+        log::debug!(
+            "heap-to-stack: {} allocation(s) moved to the stack, {} removed, {} free(s) deleted, {} left on the heap",
+            promoted,
+            removed,
+            frees,
+            rejected
+        );
+        self.remarks.write().extend(pass.remarks().iter().cloned());
+        Ok(())
+    }
+
+    /// Link `obj_file` into `output_file` with the system linker, or with
+    /// `linker::static_elf` for `LinkOptions::builtin_linker`
+    fn link(
+        &self,
+        obj_file: ObjectFile,
+        output_file: &str,
+        options: &LinkOptions,
+        target_architecture: Option<Architecture>,
+    ) -> Result<(), CompilerError> {
+        if !options.builtin_linker {
+            // The system linker would add its own CRT files and libc
+            if options.nostdlib {
+                return link_without_stdlib(obj_file.path(), output_file, options);
+            }
+            self.linker.link(obj_file, output_file, options)?;
+            return Ok(());
+        }
+
+        let mut linker = StaticLinker::new(target_architecture.unwrap_or(self.current_architecture));
+        let search_dirs: Vec<PathBuf> = options.library_paths.iter().map(PathBuf::from).collect();
+        for startup in &options.startup_objects {
+            linker.add_object_file(Path::new(startup)).map_err(CompilerError::StaticLink)?;
+        }
+        linker.add_object_file(obj_file.path()).map_err(CompilerError::StaticLink)?;
+        for library in &options.libraries {
+            linker.add_library(library, &search_dirs).map_err(CompilerError::StaticLink)?;
+        }
+        let mut linker = linker.with_gc_sections(options.gc_sections);
+        if let Some(path) = &options.linker_script {
+            let script = LinkerScript::load(Path::new(path)).map_err(|e| CompilerError::StaticLink(StaticLinkError::Script(e)))?;
+            linker = linker.with_script(script);
+        }
+        let summary = linker.write_executable(Path::new(output_file)).map_err(CompilerError::StaticLink)?;
+        log::info!("linked {}:\n{}", output_file, summary);
+        Ok(())
+    }
+
+    /// Parse `source` as the other entry points do first, with
+    /// `system_include_dirs` in place of the host's; checks that headers
+    /// parse without generating any code
+    pub fn parse_only(&self, source: &str, include_dirs: &[PathBuf], system_include_dirs: &[PathBuf]) -> Result<(), CompilerError> {
+        self.frontend.parse_string(source, include_dirs, system_include_dirs).map(|_| ())
+    }
+
+    /// Optimization remarks of everything compiled since the last call
+    pub fn take_remarks(&self) -> Vec<OptimizationRemark> {
+        std::mem::take(&mut *self.remarks.write())
+    }
+
+    /// Safepoints of every unit JIT-compiled so far, shared so a host
+    /// closure can walk them; see `jit::stackmap`
+    pub fn stack_maps(&self) -> Arc<RwLock<StackMaps>> {
+        Arc::clone(&self.stack_maps)
+    }
+
+    /// Functions JIT-compiled with `patchable_prologues`, to attach probes
+    /// to; see `jit::probes`
+    pub fn probes(&self) -> Arc<RwLock<ProbeTable>> {
+        Arc::clone(&self.probes)
+    }
+
+    /// Start addresses of every function JIT-compiled so far, for
+    /// `debug::stack_capture`
+    pub fn jit_symbols(&self) -> Arc<RwLock<JitSymbols>> {
+        Arc::clone(&self.jit_symbols)
+    }
+
+    /// Let JIT-compiled C call `function` as `name`; see `JITCompiler::register_host_function`
+    pub fn register_host_function(
+        &self,
+        name: &str,
+        signature: HostSignature,
+        function: HostFunction,
+    ) -> Result<(), CompilerError> {
+        self.host_functions
+            .write()
+            .register(name, signature, function)
+            .map_err(CompilerError::HostFunction)
+    }
+
+    /// Address of a function or global defined by the code JIT-compiled so far
+    pub unsafe fn jit_symbol_address(&self, name: &str) -> Option<*mut u8> {
+        let name = CString::new(name).ok()?;
+        let address = LLVMGetGlobalValueAddress(self.backend.jit_engine(), name.as_ptr());
+        (address != 0).then_some(address as *mut u8)
+    }
     
     /// Compile assembly code directly
     pub unsafe fn compile_assembly(
@@ -154,7 +846,7 @@ impl CompilerSystem {
         
         // Link if needed
         if options.link {
-            self.linker.link(obj_file, output_file, &options.link_options)?;
+            self.link(obj_file, output_file, &options.link_options, options.target_architecture)?;
         }
         
         Ok(())
@@ -202,6 +894,73 @@ impl CompilerSystem {
     }
 }
 
+/// Put each function and variable defined in `module` in a section of its
+/// own, as -ffunction-sections -fdata-sections do, so `--gc-sections` can
+/// drop them one at a time. Explicit `section` attributes are kept.
+unsafe fn split_sections(module: LLVMModuleRef) {
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 {
+            set_own_section(function, ".text");
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    let mut global = LLVMGetFirstGlobal(module);
+    while !global.is_null() {
+        // The built-in linker doesn't take TLS sections anyway
+        if LLVMIsDeclaration(global) == 0 && LLVMIsThreadLocal(global) == 0 {
+            let prefix = if LLVMIsGlobalConstant(global) != 0 {
+                ".rodata"
+            } else if LLVMIsNull(LLVMGetInitializer(global)) != 0 {
+                ".bss"
+            } else {
+                ".data"
+            };
+            set_own_section(global, prefix);
+        }
+        global = LLVMGetNextGlobal(global);
+    }
+}
+
+/// Move `value` to `<prefix>.<name>` unless it's unnamed or already has a
+/// section
+unsafe fn set_own_section(value: LLVMValueRef, prefix: &str) {
+    let mut length = 0;
+    let name = LLVMGetValueName2(value, &mut length);
+    if length == 0 || !LLVMGetSection(value).is_null() {
+        return;
+    }
+    let name = String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, length));
+    if let Ok(section) = CString::new(format!("{}.{}", prefix, name)) {
+        LLVMSetSection(value, section.as_ptr());
+    }
+}
+
+/// What `CompilerSystem::emit` prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitStage {
+    /// LLVM IR as generated, after the semantic passes
+    Ir,
+    /// LLVM IR after the optimizer, at the options' level
+    OptimizedIr,
+    /// Target assembly
+    Assembly,
+}
+
+impl std::str::FromStr for EmitStage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ir" => Ok(EmitStage::Ir),
+            "opt-ir" => Ok(EmitStage::OptimizedIr),
+            "asm" => Ok(EmitStage::Assembly),
+            other => Err(format!("unknown emit stage '{}' (expected ir, opt-ir or asm)", other)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CompilerOptions {
     pub optimization_level: u32,
@@ -210,15 +969,112 @@ pub struct CompilerOptions {
     pub debug_info: bool,
     pub target_features: Vec<String>,
     pub target_architecture: Option<Architecture>,
+    pub sanitizers: SanitizerSet,
+    /// -ffast-math, -fno-math-errno, -ffp-contract; strict IEEE by default
+    pub fp: FpOptions,
+    /// -fwrapv, -ftrapv; overflow is diagnosed by default
+    pub overflow: OverflowMode,
+    /// Persistent object cache; `None` always recompiles
+    pub cache_dir: Option<std::path::PathBuf>,
+    /// `-I` and the include directories of pkg-config packages, searched
+    /// before the system ones
+    pub include_dirs: Vec<PathBuf>,
+    /// Replace the host's system include directories when non-empty (bundled libc)
+    pub system_include_dirs: Vec<std::path::PathBuf>,
+    /// Compile functions that can't be optimized within this at -O0;
+    /// `None` optimizes everything however long it takes
+    pub function_budget: Option<FunctionBudget>,
+    /// Loop interchange and fusion at -O3
+    pub loop_transforms: LoopTransforms,
+    /// Move small allocations that don't escape to the stack at -O2 and up
+    pub heap_to_stack: bool,
+    /// Instrument for a profile the program writes here; see `pgo::instrument`
+    pub profile_generate: Option<PathBuf>,
+    /// Optimize with this profile's counts
+    pub profile_use: Option<PathBuf>,
+    /// Record how each object was made in a note; see `report::provenance`
+    pub embed_provenance: bool,
+}
+
+impl CompilerOptions {
+    /// Everything that changes the generated object, for cache keys. The
+    /// profile of `--profile-use` counts by its contents as well as its
+    /// path, since re-running the program rewrites it in place.
+    pub fn codegen_fingerprint(&self) -> String {
+        // An unreadable profile fails the compilation before the key is used
+        let profile_sha256 = self
+            .profile_use
+            .as_ref()
+            .map(|path| std::fs::read(path).map_or_else(|_| "unreadable".to_string(), |data| provenance::sha256(&data)));
+        format!(
+            "O{};debug={};features={};arch={:?};sanitize={:?};fp={:?};overflow={:?};inc={:?};sysinc={:?};budget={:?};loops={:?};heap={};split={};profgen={:?};profuse={:?};profsha={:?};provenance={}",
+            self.optimization_level,
+            self.debug_info,
+            self.target_features.join(","),
+            self.target_architecture,
+            self.sanitizers,
+            self.fp,
+            self.overflow,
+            self.include_dirs,
+            self.system_include_dirs,
+            self.function_budget,
+            self.loop_transforms,
+            self.heap_to_stack,
+            self.link && self.link_options.gc_sections,
+            self.profile_generate,
+            self.profile_use,
+            profile_sha256,
+            self.embed_provenance,
+        )
+    }
 }
 
 #[derive(Debug)]
 pub struct JITOptions {
     pub optimization_level: u32,
     pub enable_fast_isel: bool,
+    /// Run compiled code on a stack of `stack_size` bytes between guard
+    /// pages, so that overflowing it is reported with a trace instead of
+    /// killing the host (`runtime::stack_guard`)
     pub enable_guard_pages: bool,
     pub stack_size: usize,
     pub target_architecture: Option<Architecture>,
+    pub sanitizers: SanitizerSet,
+    pub fp: FpOptions,
+    pub overflow: OverflowMode,
+    /// Evaluate pure calls with constant arguments and static constructors
+    /// before the program runs, within this budget; `None` leaves them to run time
+    pub evaluation_budget: Option<Budget>,
+    /// Loop interchange and fusion at -O3
+    pub loop_transforms: LoopTransforms,
+    /// Move small allocations that don't escape to the stack at -O2 and up
+    pub heap_to_stack: bool,
+    /// Start every function with a nop that `jit::probes` can swap for a
+    /// call at run time
+    pub patchable_prologues: bool,
+    /// Bind the clock, `srand` and pthread calls to the deterministic ones
+    /// of `runtime::deterministic`
+    pub deterministic: Option<DeterministicConfig>,
+    /// Wrap the program's I/O calls in those of `runtime::capture`, which
+    /// capture or replay once `capture::start` runs
+    pub capture: Option<CaptureMode>,
+    /// `-I` and the include directories of pkg-config packages, searched
+    /// before the system ones
+    pub include_dirs: Vec<PathBuf>,
+    /// Replace the host's system include directories when non-empty
+    pub system_include_dirs: Vec<std::path::PathBuf>,
+    /// Static archives loaded into the JIT before the program, so its
+    /// symbols resolve against them instead of the host process (bundled libc)
+    pub archives: Vec<std::path::PathBuf>,
+    /// Shared libraries (`-l`, `-L`) dlopened and bound for the program
+    pub shared_libraries: LibrarySearch,
+    /// Instrument for a profile the program writes here; see `pgo::instrument`
+    pub profile_generate: Option<PathBuf>,
+    /// Optimize with this profile's counts
+    pub profile_use: Option<PathBuf>,
+    /// Track the program's allocations through `memory::leaks`, for a
+    /// leak report at exit
+    pub detect_leaks: bool,
 }
 
 #[derive(Debug)]
@@ -234,6 +1090,19 @@ pub struct LinkOptions {
     pub library_paths: Vec<String>,
     pub static_link: bool,
     pub strip_symbols: bool,
+    /// Don't link the system CRT files or libc
+    pub nostdlib: bool,
+    /// Objects linked ahead of everything else (e.g. our built-in crt0)
+    pub startup_objects: Vec<String>,
+    /// Link a static executable with `linker::static_elf` instead of the
+    /// system linker; needs `nostdlib` and archives for every library
+    pub builtin_linker: bool,
+    /// Give every function and variable its own section and have the
+    /// built-in linker drop those nothing refers to
+    pub gc_sections: bool,
+    /// GNU ld script the built-in linker places sections by (`MEMORY`,
+    /// `SECTIONS`, `KEEP`); see `linker::script`
+    pub linker_script: Option<String>,
 }
 
 #[derive(Debug)]
@@ -249,7 +1118,141 @@ pub enum CompilerError {
     Backend(BackendError),
     Runtime(RuntimeError),
     Linker(LinkerError),
+    /// The host driver failed a `nostdlib` link; its stderr
+    SystemLink(String),
     ABI(ABIError),
+    Sanitizer(crate::optimizer::sanitize::SanitizeError),
+    Fenv(crate::optimizer::fenv::FenvError),
+    Linkage(LinkageError),
+    Profile(ProfileError),
+    InlineAsm(crate::frontend::inline_asm::InlineAsmError),
+    DynamicLoader(DynamicLoaderError),
+    AsmConstraint(crate::arch::inline_asm::ConstraintError),
+    HostFunction(JITError),
+    StackMap(StackMapError),
+    Probe(ProbeError),
+    Deterministic(DeterministicError),
+    Usdt(UsdtError),
+    FunctionBudget(BudgetError),
+    LoopTransform(LoopTransformError),
+    HeapToStack(EscapeError),
+    Vectorize(VectorizeError),
+    StaticLink(StaticLinkError),
+    StackDepth(StackDepthError),
+    Wcet(WcetError),
+    SignalSafety(SignalSafetyError),
+    Provenance(ProvenanceError),
+    /// Source uses an extension we recognise but can't compile
+    Unsupported(Vec<Diagnostic>),
+}
+
+impl CompilerError {
+    /// The stable code it's reported under, see `diagnostics::codes`
+    pub fn code(&self) -> &'static str {
+        use crate::arch::inline_asm::ConstraintError;
+        match self {
+            CompilerError::Frontend(_) => codes::E0018,
+            CompilerError::InlineAsm(_) => codes::E0019,
+            CompilerError::AsmConstraint(ConstraintError::OutOfRegisters { .. } | ConstraintError::RegisterConflict { .. }) => codes::E0021,
+            CompilerError::AsmConstraint(_) => codes::E0020,
+            CompilerError::InvalidTargetTriple
+            | CompilerError::TargetInitialization(_)
+            | CompilerError::TargetMachineCreation
+            | CompilerError::UnsupportedArchitecture(_) => codes::E0022,
+            CompilerError::Linker(_)
+            | CompilerError::SystemLink(_)
+            | CompilerError::StaticLink(_)
+            | CompilerError::DynamicLoader(_)
+            | CompilerError::Linkage(_) => codes::E0023,
+            CompilerError::Runtime(_) => codes::E0015,
+            // Each of its diagnostics has its own; Blocks are what it holds
+            CompilerError::Unsupported(diagnostics) => diagnostics.first().and_then(|diagnostic| diagnostic.code).unwrap_or(codes::E0017),
+            _ => codes::E0024,
+        }
+    }
+
+    /// What to print for it: the diagnostics of `Unsupported`, else one
+    /// error under its code
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            CompilerError::Unsupported(diagnostics) => diagnostics.clone(),
+            error => vec![Diagnostic::error(format!("{:?}", error)).with_code(error.code())],
+        }
+    }
+}
+
+/// Link with the host compiler driver but none of its startup files or
+/// libraries; `startup_objects` (our crt0 or the bundled libc's crt1) take
+/// their place ahead of the program
+fn link_without_stdlib(object: &Path, output_file: &str, options: &LinkOptions) -> Result<(), CompilerError> {
+    let driver = find_host_compiler(None)
+        .ok_or_else(|| CompilerError::SystemLink("no C compiler driver (cc, clang or gcc) to link with".to_string()))?;
+    let mut command = std::process::Command::new(&driver);
+    command.arg("-nostdlib");
+    if options.static_link {
+        command.arg("-static");
+    }
+    if options.strip_symbols {
+        command.arg("-s");
+    }
+    command
+        .args(&options.startup_objects)
+        .arg(object)
+        .args(options.library_paths.iter().map(|dir| format!("-L{}", dir)))
+        .args(options.libraries.iter().map(|library| format!("-l{}", library)))
+        .arg("-o")
+        .arg(output_file);
+
+    let output = command
+        .output()
+        .map_err(|e| CompilerError::SystemLink(format!("{}: {}", driver.display(), e)))?;
+    if !output.status.success() {
+        return Err(CompilerError::SystemLink(String::from_utf8_lossy(&output.stderr).into_owned()));
+    }
+    Ok(())
+}
+
+/// Instructions in each function defined in `module`
+unsafe fn ir_instruction_counts(module: LLVMModuleRef) -> Vec<(String, usize)> {
+    let mut counts = Vec::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMCountBasicBlocks(function) > 0 {
+            let mut len = 0;
+            let name = LLVMGetValueName2(function, &mut len);
+            counts.push((CStr::from_ptr(name).to_string_lossy().into_owned(), instructions(function).len()));
+        }
+        function = LLVMGetNextFunction(function);
+    }
+    counts
+}
+
+/// Add each function to the report with its machine code size, from the
+/// symbol table of the object it was compiled into
+fn report_function_sizes(object_path: impl AsRef<Path>, ir_sizes: &[(String, usize)]) {
+    use object::{Object, ObjectSymbol};
+    let object_path = object_path.as_ref();
+    let sizes: HashMap<String, u64> = match std::fs::read(object_path) {
+        Ok(data) => match object::File::parse(&*data) {
+            Ok(file) => file
+                .symbols()
+                .filter(|symbol| symbol.kind() == object::SymbolKind::Text && symbol.is_definition())
+                .filter_map(|symbol| Some((symbol.name().ok()?.to_string(), symbol.size())))
+                .collect(),
+            Err(e) => {
+                log::warn!("cannot read function sizes from {}: {}", object_path.display(), e);
+                HashMap::new()
+            }
+        },
+        Err(e) => {
+            log::warn!("cannot read function sizes from {}: {}", object_path.display(), e);
+            HashMap::new()
+        }
+    };
+    for (name, ir_instructions) in ir_sizes {
+        let code_size = sizes.get(name).copied().unwrap_or(0) as usize;
+        report::note_function(name, code_size, *ir_instructions);
+    }
 }
 
 // Example usage:
@@ -267,10 +1270,27 @@ fn main() -> Result<(), CompilerError> {
                 library_paths: vec![],
                 static_link: false,
                 strip_symbols: false,
+                nostdlib: false,
+                startup_objects: vec![],
+                builtin_linker: false,
+                gc_sections: false,
+                linker_script: None,
             },
             debug_info: true,
             target_features: vec!["+sse4.2".to_string()],
             target_architecture: None,
+            sanitizers: SanitizerSet::default(),
+            fp: FpOptions::default(),
+            overflow: OverflowMode::default(),
+            cache_dir: Some(CompilationCache::default_root()),
+            include_dirs: vec![],
+            system_include_dirs: vec![],
+            function_budget: Some(FunctionBudget::default()),
+            loop_transforms: LoopTransforms::default(),
+            heap_to_stack: true,
+            profile_generate: None,
+            profile_use: None,
+            embed_provenance: false,
         };
 
         compiler.compile_file("input.c", "output", &options)?;
@@ -282,6 +1302,22 @@ fn main() -> Result<(), CompilerError> {
             enable_guard_pages: true,
             stack_size: 8 * 1024 * 1024,
             target_architecture: None,
+            sanitizers: SanitizerSet::default(),
+            fp: FpOptions::default(),
+            overflow: OverflowMode::default(),
+            evaluation_budget: Some(Budget::default()),
+            loop_transforms: LoopTransforms::default(),
+            heap_to_stack: true,
+            patchable_prologues: false,
+            deterministic: None,
+            capture: None,
+            include_dirs: vec![],
+            system_include_dirs: vec![],
+            archives: vec![],
+            shared_libraries: LibrarySearch::default(),
+            profile_generate: None,
+            profile_use: None,
+            detect_leaks: false,
         };
 
         let code = r#"
@@ -304,6 +1340,11 @@ fn main() -> Result<(), CompilerError> {
                 library_paths: vec![],
                 static_link: false,
                 strip_symbols: false,
+                nostdlib: false,
+                startup_objects: vec![],
+                builtin_linker: false,
+                gc_sections: false,
+                linker_script: None,
             },
             target_architecture: Some(Architecture::X86_64),
         };
@@ -0,0 +1,91 @@
+// src/compiler/setjmp.rs
+//! Code generation for `setjmp` and `longjmp`
+//! Compiled code calls the host's `setjmp`, whose `jmp_buf` holds the
+//! callee-saved registers, stack pointer and return address. LLVM has to
+//! know that the call can return a second time: otherwise it may keep a
+//! value in a caller-saved register across it, or fold the result to the
+//! first return's 0. `mark_returns_twice` gives every `setjmp`-family
+//! declaration and call `returns_twice`, as clang does.
+//!
+//! The `longjmp` family is bound to `runtime::setjmp::jit_longjmp` instead
+//! of libc, so a `longjmp` to a `jmp_buf` that interpreted code filled in
+//! goes back to the interpreter; any other goes on to the host's.
+
+use std::ffi::{c_void, CString};
+use llvm_sys::core::*;
+use llvm_sys::execution_engine::{LLVMAddGlobalMapping, LLVMExecutionEngineRef};
+use llvm_sys::prelude::*;
+use llvm_sys::LLVMAttributeFunctionIndex;
+use crate::runtime::setjmp::{jit_longjmp, jit_siglongjmp};
+
+/// Functions that return twice, under the names `<setjmp.h>` and
+/// `<unistd.h>` expand to on the supported hosts
+pub const RETURNS_TWICE: &[&str] = &["setjmp", "_setjmp", "sigsetjmp", "__sigsetjmp", "vfork"];
+
+/// (name, replacement) for each function that unwinds to a `jmp_buf`
+const LONGJMPS: &[(&str, unsafe extern "C" fn(*mut c_void, i32) -> !)] = &[
+    ("longjmp", jit_longjmp),
+    ("_longjmp", jit_longjmp),
+    ("siglongjmp", jit_siglongjmp),
+];
+
+/// Add `returns_twice` to the `setjmp`-family functions `module` declares
+/// and to each call of them. Returns the number of calls marked.
+pub unsafe fn mark_returns_twice(module: LLVMModuleRef) -> usize {
+    let context = LLVMGetModuleContext(module);
+    let returns_twice = enum_attribute(context, "returns_twice");
+    let mut marked = 0;
+    for name in RETURNS_TWICE {
+        let Some(function) = declaration(module, name) else { continue };
+        LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, returns_twice);
+
+        // The caller needs it on the call too; `-O0` doesn't look further
+        let mut each = LLVMGetFirstUse(function);
+        while !each.is_null() {
+            let user = LLVMGetUser(each);
+            if !LLVMIsACallInst(user).is_null() && LLVMGetCalledValue(user) == function {
+                LLVMAddCallSiteAttribute(user, LLVMAttributeFunctionIndex, returns_twice);
+                marked += 1;
+            }
+            each = LLVMGetNextUse(each);
+        }
+    }
+    marked
+}
+
+/// Point the `longjmp`-family functions `module` declares at the runtime's.
+/// Must run before the engine compiles the module. Returns the number bound.
+pub unsafe fn bind_longjmp(module: LLVMModuleRef, engine: LLVMExecutionEngineRef) -> usize {
+    let context = LLVMGetModuleContext(module);
+    let noreturn = enum_attribute(context, "noreturn");
+    let mut bound = 0;
+    for &(name, replacement) in LONGJMPS {
+        let Some(function) = declaration(module, name) else { continue };
+        LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, noreturn);
+        LLVMAddGlobalMapping(engine, function, replacement as *mut c_void);
+        bound += 1;
+    }
+    bound
+}
+
+/// `name` if `module` declares it without defining it
+unsafe fn declaration(module: LLVMModuleRef, name: &str) -> Option<LLVMValueRef> {
+    let c_name = CString::new(name).ok()?;
+    let function = LLVMGetNamedFunction(module, c_name.as_ptr());
+    (!function.is_null() && LLVMIsDeclaration(function) != 0).then_some(function)
+}
+
+unsafe fn enum_attribute(context: LLVMContextRef, name: &str) -> LLVMAttributeRef {
+    let kind = LLVMGetEnumAttributeKindForName(name.as_ptr() as *const _, name.len());
+    LLVMCreateEnumAttribute(context, kind, 0)
+}
+
+// Example usage:
+/*
+// int f(jmp_buf env) { if (setjmp(env)) return 1; g(env); return 0; }
+unsafe fn compile(module: LLVMModuleRef, engine: LLVMExecutionEngineRef) {
+    let calls = mark_returns_twice(module);
+    let bound = bind_longjmp(module, engine);
+    println!("{} setjmp calls, {} longjmp functions bound", calls, bound);
+}
+*/
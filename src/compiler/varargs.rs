@@ -0,0 +1,369 @@
+// src/compiler/varargs.rs
+//! Code generation for variadic functions
+//! `va_start`, `va_end` and `va_copy` map onto LLVM's intrinsics, which the
+//! backends lower into the prologue's register spills. LLVM's own `va_arg`
+//! instruction only handles scalars, and on AArch64 ignores the register
+//! save areas altogether, so `va_arg` is emitted inline the way clang does
+//! it: check the `va_list` offsets, take the argument from the register
+//! save area if it was passed in registers, and from the stack otherwise.
+//! The layouts and rules are the ones `abi::varargs::VaList` follows, so
+//! a `va_list` can cross between compiled and interpreted code.
+//!
+//! The frontend applies the default argument promotions to variadic
+//! arguments (`promote`) and calls through a function type with
+//! `IsVarArg` set, which on System V makes the backend set `al`.
+
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::{LLVMIntPredicate, LLVMTypeKind};
+use crate::abi::aggregate::{classify, Abi, AbiError, CType, Classification, RegClass, Scalar};
+
+/// System V register save area: six GP registers, then eight XMM registers
+const SYSV_GP_AREA: u64 = 6 * 8;
+const SYSV_SAVE_AREA: u64 = SYSV_GP_AREA + 8 * 16;
+
+pub struct VarargsCodegen {
+    abi: Abi,
+    context: LLVMContextRef,
+    va_start: (LLVMValueRef, LLVMTypeRef),
+    va_end: (LLVMValueRef, LLVMTypeRef),
+    va_copy: (LLVMValueRef, LLVMTypeRef),
+    va_list: LLVMTypeRef,
+}
+
+impl VarargsCodegen {
+    pub unsafe fn new(module: LLVMModuleRef, abi: Abi) -> Result<Self, VarargsCodegenError> {
+        let context = LLVMGetModuleContext(module);
+        // Overloaded on the va_list's address space since LLVM 19
+        let ptr = LLVMPointerTypeInContext(context, 0);
+        let i32_type = LLVMInt32TypeInContext(context);
+        let va_list = match abi {
+            // typedef struct { ... } va_list[1];
+            Abi::SystemV => {
+                let mut fields = [i32_type, i32_type, ptr, ptr];
+                let tag = LLVMStructTypeInContext(context, fields.as_mut_ptr(), 4, 0);
+                LLVMArrayType(tag, 1)
+            }
+            Abi::Aapcs64 => {
+                let mut fields = [ptr, ptr, ptr, i32_type, i32_type];
+                LLVMStructTypeInContext(context, fields.as_mut_ptr(), 5, 0)
+            }
+        };
+        Ok(VarargsCodegen {
+            abi,
+            context,
+            va_start: intrinsic(module, context, "llvm.va_start", ptr)?,
+            va_end: intrinsic(module, context, "llvm.va_end", ptr)?,
+            va_copy: intrinsic(module, context, "llvm.va_copy", ptr)?,
+            va_list,
+        })
+    }
+
+    /// The IR type of `va_list`, for declaring one
+    pub fn va_list_type(&self) -> LLVMTypeRef {
+        self.va_list
+    }
+
+    /// `va_start(ap, last)`; `ap` points to a `va_list`
+    pub unsafe fn start(&self, builder: LLVMBuilderRef, ap: LLVMValueRef) {
+        call(builder, self.va_start, &mut [ap]);
+    }
+
+    pub unsafe fn end(&self, builder: LLVMBuilderRef, ap: LLVMValueRef) {
+        call(builder, self.va_end, &mut [ap]);
+    }
+
+    /// `va_copy(dest, src)`
+    pub unsafe fn copy(&self, builder: LLVMBuilderRef, dest: LLVMValueRef, src: LLVMValueRef) {
+        call(builder, self.va_copy, &mut [dest, src]);
+    }
+
+    /// The default argument promotions for a variadic argument: `float` to
+    /// `double`, integers narrower than `int` to `int`
+    pub unsafe fn promote(&self, builder: LLVMBuilderRef, value: LLVMValueRef, signed: bool) -> LLVMValueRef {
+        let ty = LLVMTypeOf(value);
+        match LLVMGetTypeKind(ty) {
+            LLVMTypeKind::LLVMFloatTypeKind => {
+                LLVMBuildFPExt(builder, value, LLVMDoubleTypeInContext(self.context), c"promoted".as_ptr())
+            }
+            LLVMTypeKind::LLVMIntegerTypeKind if LLVMGetIntTypeWidth(ty) < 32 => {
+                let int = LLVMInt32TypeInContext(self.context);
+                if signed {
+                    LLVMBuildSExt(builder, value, int, c"promoted".as_ptr())
+                } else {
+                    LLVMBuildZExt(builder, value, int, c"promoted".as_ptr())
+                }
+            }
+            _ => value,
+        }
+    }
+
+    /// `va_arg(ap, T)`: the address of the next argument, laid out as `ty`.
+    /// The caller loads it with `T`'s IR type. Aggregates taken from
+    /// registers are reassembled in a temporary in the entry block.
+    pub unsafe fn arg(&self, builder: LLVMBuilderRef, ap: LLVMValueRef, ty: &CType) -> Result<LLVMValueRef, VarargsCodegenError> {
+        let class = classify(self.abi, ty).map_err(VarargsCodegenError::Abi)?;
+        Ok(match self.abi {
+            Abi::SystemV => self.arg_sysv(builder, ap, ty, class),
+            Abi::Aapcs64 => self.arg_aapcs64(builder, ap, ty, class),
+        })
+    }
+
+    unsafe fn arg_sysv(&self, builder: LLVMBuilderRef, ap: LLVMValueRef, ty: &CType, class: Classification) -> LLVMValueRef {
+        let classes = match class {
+            Classification::Registers(classes) => classes,
+            // MEMORY arguments are always on the stack
+            _ => return self.from_stack(builder, ap, 2, ty.size() as u64),
+        };
+        let i32_type = LLVMInt32TypeInContext(self.context);
+        let integers = classes.iter().filter(|&&c| c == RegClass::Integer).count() as u64;
+        let floats = classes.len() as u64 - integers;
+
+        // Both parts must have room: an argument is never split between
+        // registers and the stack
+        let gp_field = self.field(builder, ap, 0);
+        let fp_field = self.field(builder, ap, 1);
+        let gp_offset = LLVMBuildLoad2(builder, i32_type, gp_field, c"gp_offset".as_ptr());
+        let fp_offset = LLVMBuildLoad2(builder, i32_type, fp_field, c"fp_offset".as_ptr());
+        let mut fits = LLVMConstInt(LLVMInt1TypeInContext(self.context), 1, 0);
+        if integers > 0 {
+            let limit = LLVMConstInt(i32_type, SYSV_GP_AREA - integers * 8, 0);
+            let room = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntULE, gp_offset, limit, c"gp_room".as_ptr());
+            fits = LLVMBuildAnd(builder, fits, room, c"".as_ptr());
+        }
+        if floats > 0 {
+            let limit = LLVMConstInt(i32_type, SYSV_SAVE_AREA - floats * 16, 0);
+            let room = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntULE, fp_offset, limit, c"fp_room".as_ptr());
+            fits = LLVMBuildAnd(builder, fits, room, c"".as_ptr());
+        }
+        let (in_registers, on_stack, done) = self.blocks(builder);
+        LLVMBuildCondBr(builder, fits, in_registers, on_stack);
+
+        // Copy each eightbyte out of its register's slot
+        LLVMPositionBuilderAtEnd(builder, in_registers);
+        let save_area = LLVMBuildLoad2(builder, self.ptr(), self.field(builder, ap, 3), c"reg_save_area".as_ptr());
+        let temporary = self.temporary(builder, classes.len() as u64);
+        let (mut gp, mut fp) = (gp_offset, fp_offset);
+        for (index, class) in classes.iter().enumerate() {
+            let (offset, step) = match class {
+                RegClass::Integer => (&mut gp, 8),
+                RegClass::Sse => (&mut fp, 16),
+            };
+            let word = self.load_word(builder, self.byte_offset(builder, save_area, *offset));
+            self.store_word(builder, word, temporary, index as u64 * 8);
+            *offset = LLVMBuildAdd(builder, *offset, LLVMConstInt(i32_type, step, 0), c"".as_ptr());
+        }
+        LLVMBuildStore(builder, gp, gp_field);
+        LLVMBuildStore(builder, fp, fp_field);
+        let from_registers = LLVMGetInsertBlock(builder);
+        LLVMBuildBr(builder, done);
+
+        LLVMPositionBuilderAtEnd(builder, on_stack);
+        let stack = self.from_stack(builder, ap, 2, ty.size() as u64);
+        let from_stack = LLVMGetInsertBlock(builder);
+        LLVMBuildBr(builder, done);
+
+        LLVMPositionBuilderAtEnd(builder, done);
+        self.phi(builder, [(temporary, from_registers), (stack, from_stack)])
+    }
+
+    unsafe fn arg_aapcs64(&self, builder: LLVMBuilderRef, ap: LLVMValueRef, ty: &CType, class: Classification) -> LLVMValueRef {
+        let i32_type = LLVMInt32TypeInContext(self.context);
+        // (offset field, top field, registers, bytes per register, bytes used of each)
+        let (offs_index, top_index, registers, slot, member) = match &class {
+            // A pointer to the caller's copy, taken like a `void *`
+            Classification::Reference => {
+                let integer = Classification::Registers(vec![RegClass::Integer]);
+                let pointer = self.arg_aapcs64(builder, ap, &CType::Scalar(Scalar::Pointer), integer);
+                return LLVMBuildLoad2(builder, self.ptr(), pointer, c"va.ref".as_ptr());
+            }
+            Classification::Hfa { base, count } => (4, 2, *count as u64, 16, base.size() as u64),
+            Classification::Registers(classes) if classes == &[RegClass::Sse] => (4, 2, 1, 16, ty.size() as u64),
+            Classification::Registers(classes) => (3, 1, classes.len() as u64, 8, 8),
+            Classification::Memory => unreachable!("AAPCS64 has no MEMORY class"),
+        };
+
+        // A non-negative offset means the register area is used up (or was
+        // closed by an earlier argument that went to the stack)
+        let offs_field = self.field(builder, ap, offs_index);
+        let offs = LLVMBuildLoad2(builder, i32_type, offs_field, c"va.offs".as_ptr());
+        let zero = LLVMConstInt(i32_type, 0, 0);
+        let exhausted = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntSGE, offs, zero, c"".as_ptr());
+        let maybe_registers = LLVMAppendBasicBlockInContext(self.context, self.function(builder), c"va.maybe_reg".as_ptr());
+        let (in_registers, on_stack, done) = self.blocks(builder);
+        LLVMBuildCondBr(builder, exhausted, on_stack, maybe_registers);
+
+        // Claim the registers; if that overshoots the area the argument is
+        // on the stack and the area stays closed
+        LLVMPositionBuilderAtEnd(builder, maybe_registers);
+        let next = LLVMBuildAdd(builder, offs, LLVMConstInt(i32_type, registers * slot, 0), c"va.next".as_ptr());
+        LLVMBuildStore(builder, next, offs_field);
+        let fits = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntSLE, next, zero, c"".as_ptr());
+        LLVMBuildCondBr(builder, fits, in_registers, on_stack);
+
+        LLVMPositionBuilderAtEnd(builder, in_registers);
+        let top = LLVMBuildLoad2(builder, self.ptr(), self.field(builder, ap, top_index), c"va.top".as_ptr());
+        let area = self.byte_offset(builder, top, offs);
+        // X registers are contiguous, as is a single V register's value; an
+        // HFA has one member in the low bytes of each 16-byte V register
+        let address = if slot == 8 || registers == 1 {
+            area
+        } else {
+            let temporary = self.temporary(builder, (registers * member).div_ceil(8));
+            for index in 0..registers {
+                let source = LLVMBuildGEP2(
+                    builder,
+                    LLVMInt8TypeInContext(self.context),
+                    area,
+                    [LLVMConstInt(LLVMInt64TypeInContext(self.context), index * 16, 0)].as_mut_ptr(),
+                    1,
+                    c"".as_ptr(),
+                );
+                let dest = LLVMBuildGEP2(
+                    builder,
+                    LLVMInt8TypeInContext(self.context),
+                    temporary,
+                    [LLVMConstInt(LLVMInt64TypeInContext(self.context), index * member, 0)].as_mut_ptr(),
+                    1,
+                    c"".as_ptr(),
+                );
+                let value_type = LLVMIntTypeInContext(self.context, (member * 8) as u32);
+                let value = LLVMBuildLoad2(builder, value_type, source, c"".as_ptr());
+                LLVMBuildStore(builder, value, dest);
+            }
+            temporary
+        };
+        let from_registers = LLVMGetInsertBlock(builder);
+        LLVMBuildBr(builder, done);
+
+        LLVMPositionBuilderAtEnd(builder, on_stack);
+        let stack = self.from_stack(builder, ap, 0, ty.size() as u64);
+        let from_stack = LLVMGetInsertBlock(builder);
+        LLVMBuildBr(builder, done);
+
+        LLVMPositionBuilderAtEnd(builder, done);
+        self.phi(builder, [(address, from_registers), (stack, from_stack)])
+    }
+
+    /// Take `size` bytes from the stack area at `field` and step past
+    /// them, rounded up to 8-byte slots
+    unsafe fn from_stack(&self, builder: LLVMBuilderRef, ap: LLVMValueRef, field: u32, size: u64) -> LLVMValueRef {
+        let field = self.field(builder, ap, field);
+        let current = LLVMBuildLoad2(builder, self.ptr(), field, c"va.stack".as_ptr());
+        let step = LLVMConstInt(LLVMInt32TypeInContext(self.context), size.div_ceil(8) * 8, 0);
+        LLVMBuildStore(builder, self.byte_offset(builder, current, step), field);
+        current
+    }
+
+    unsafe fn field(&self, builder: LLVMBuilderRef, ap: LLVMValueRef, index: u32) -> LLVMValueRef {
+        let tag = match self.abi {
+            Abi::SystemV => LLVMGetElementType(self.va_list),
+            Abi::Aapcs64 => self.va_list,
+        };
+        LLVMBuildStructGEP2(builder, tag, ap, index, c"va.field".as_ptr())
+    }
+
+    /// `base + offset` bytes, `offset` a signed `i32`
+    unsafe fn byte_offset(&self, builder: LLVMBuilderRef, base: LLVMValueRef, offset: LLVMValueRef) -> LLVMValueRef {
+        let offset = LLVMBuildSExt(builder, offset, LLVMInt64TypeInContext(self.context), c"".as_ptr());
+        LLVMBuildGEP2(builder, LLVMInt8TypeInContext(self.context), base, [offset].as_mut_ptr(), 1, c"".as_ptr())
+    }
+
+    unsafe fn load_word(&self, builder: LLVMBuilderRef, address: LLVMValueRef) -> LLVMValueRef {
+        let load = LLVMBuildLoad2(builder, LLVMInt64TypeInContext(self.context), address, c"va.word".as_ptr());
+        // The XMM part of the save area is only 16-byte aligned as a whole
+        LLVMSetAlignment(load, 8);
+        load
+    }
+
+    unsafe fn store_word(&self, builder: LLVMBuilderRef, word: LLVMValueRef, base: LLVMValueRef, offset: u64) {
+        let i64_type = LLVMInt64TypeInContext(self.context);
+        let mut index = [LLVMConstInt(i64_type, offset / 8, 0)];
+        let address = LLVMBuildGEP2(builder, i64_type, base, index.as_mut_ptr(), 1, c"".as_ptr());
+        LLVMBuildStore(builder, word, address);
+    }
+
+    /// `words` 8-byte words of stack in the entry block, so a `va_arg` in
+    /// a loop doesn't grow the frame
+    unsafe fn temporary(&self, builder: LLVMBuilderRef, words: u64) -> LLVMValueRef {
+        let entry = LLVMGetEntryBasicBlock(self.function(builder));
+        let entry_builder = LLVMCreateBuilderInContext(self.context);
+        match LLVMGetFirstInstruction(entry) {
+            first if first.is_null() => LLVMPositionBuilderAtEnd(entry_builder, entry),
+            first => LLVMPositionBuilderBefore(entry_builder, first),
+        }
+        let ty = LLVMArrayType(LLVMInt64TypeInContext(self.context), words as u32);
+        let temporary = LLVMBuildAlloca(entry_builder, ty, c"va.tmp".as_ptr());
+        LLVMSetAlignment(temporary, 8);
+        LLVMDisposeBuilder(entry_builder);
+        temporary
+    }
+
+    unsafe fn blocks(&self, builder: LLVMBuilderRef) -> (LLVMBasicBlockRef, LLVMBasicBlockRef, LLVMBasicBlockRef) {
+        let function = self.function(builder);
+        (
+            LLVMAppendBasicBlockInContext(self.context, function, c"va.reg".as_ptr()),
+            LLVMAppendBasicBlockInContext(self.context, function, c"va.stack".as_ptr()),
+            LLVMAppendBasicBlockInContext(self.context, function, c"va.end".as_ptr()),
+        )
+    }
+
+    unsafe fn phi(&self, builder: LLVMBuilderRef, incoming: [(LLVMValueRef, LLVMBasicBlockRef); 2]) -> LLVMValueRef {
+        let phi = LLVMBuildPhi(builder, self.ptr(), c"va.arg".as_ptr());
+        let mut values = incoming.map(|(value, _)| value);
+        let mut blocks = incoming.map(|(_, block)| block);
+        LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+        phi
+    }
+
+    unsafe fn function(&self, builder: LLVMBuilderRef) -> LLVMValueRef {
+        LLVMGetBasicBlockParent(LLVMGetInsertBlock(builder))
+    }
+
+    unsafe fn ptr(&self) -> LLVMTypeRef {
+        LLVMPointerTypeInContext(self.context, 0)
+    }
+}
+
+unsafe fn call(builder: LLVMBuilderRef, (function, ty): (LLVMValueRef, LLVMTypeRef), args: &mut [LLVMValueRef]) {
+    LLVMBuildCall2(builder, ty, function, args.as_mut_ptr(), args.len() as u32, c"".as_ptr());
+}
+
+unsafe fn intrinsic(
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    name: &str,
+    ptr: LLVMTypeRef,
+) -> Result<(LLVMValueRef, LLVMTypeRef), VarargsCodegenError> {
+    let id = LLVMLookupIntrinsicID(name.as_ptr() as *const _, name.len());
+    if id == 0 {
+        return Err(VarargsCodegenError::MissingIntrinsic(name.to_string()));
+    }
+    // va_copy takes two pointers in the same address space, one overload
+    let mut overloads = [ptr];
+    let function = LLVMGetIntrinsicDeclaration(module, id, overloads.as_mut_ptr(), 1);
+    let ty = LLVMIntrinsicGetType(context, id, overloads.as_mut_ptr(), 1);
+    Ok((function, ty))
+}
+
+#[derive(Debug)]
+pub enum VarargsCodegenError {
+    MissingIntrinsic(String),
+    /// A `va_arg` type the ABI layer can't classify
+    Abi(AbiError),
+}
+
+// Example usage:
+/*
+// int sum(int n, ...) { va_list ap; va_start(ap, n); ... va_arg(ap, int) ... va_end(ap); }
+unsafe fn lower_sum_body(module: LLVMModuleRef, builder: LLVMBuilderRef) -> Result<LLVMValueRef, VarargsCodegenError> {
+    let context = LLVMGetModuleContext(module);
+    let varargs = VarargsCodegen::new(module, Abi::SystemV)?;
+    let ap = LLVMBuildAlloca(builder, varargs.va_list_type(), c"ap".as_ptr());
+    varargs.start(builder, ap);
+    let address = varargs.arg(builder, ap, &CType::Scalar(Scalar::I32))?;
+    let value = LLVMBuildLoad2(builder, LLVMInt32TypeInContext(context), address, c"".as_ptr());
+    varargs.end(builder, ap);
+    Ok(value)
+}
+*/
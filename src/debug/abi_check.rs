@@ -0,0 +1,643 @@
+// src/debug/abi_check.rs
+//! ABI compatibility between two compiled artifacts
+//! Reads the exported symbols of two object files or shared libraries and,
+//! when they were built with `-g`, the DWARF describing them: function
+//! signatures, exported variables, and the structs, unions, enums and
+//! typedefs those reach. Layouts come from the recorded `DW_AT_byte_size`
+//! and member offsets, so the check sees exactly what the compiler did,
+//! packing and alignment attributes included.
+//!
+//! Changes are reported with the same `Change`/`Impact` vocabulary as
+//! `semdiff`. A type change counts as an ABI break only when the type is
+//! reachable from an exported function or variable of the old artifact;
+//! anything else is internal to the library.
+
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+use gimli::{AttributeValue, UnitOffset};
+use object::{Object, ObjectKind, ObjectSection, ObjectSymbol};
+use crate::analysis::semdiff::{Change, ChangeKind, Impact, SemanticDiff};
+
+type Reader<'a> = gimli::EndianSlice<'a, gimli::RunTimeEndian>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolClass {
+    Function,
+    Object,
+    ThreadLocal,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportedSymbol {
+    pub class: SymbolClass,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionAbi {
+    pub return_type: String,
+    /// Parameter types; `...` last for a variadic function
+    pub params: Vec<String>,
+    /// Type names the signature mentions, directly or behind pointers
+    referenced: BTreeSet<String>,
+}
+
+impl FunctionAbi {
+    fn render(&self, name: &str) -> String {
+        let params = if self.params.is_empty() { "void".to_string() } else { self.params.join(", ") };
+        format!("{} {}({})", self.return_type, name, params)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberAbi {
+    /// Members of anonymous structs and unions are flattened into their parent
+    pub name: String,
+    pub ty: String,
+    pub offset: u64,
+    /// (bit offset from the start of the record, width) for a bit-field
+    pub bits: Option<(u64, u64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordAbi {
+    pub size: Option<u64>,
+    pub members: Vec<MemberAbi>,
+    referenced: BTreeSet<String>,
+}
+
+/// What one artifact exports, as far as its symbols and debug info tell
+#[derive(Debug, Default)]
+pub struct AbiSnapshot {
+    pub symbols: BTreeMap<String, ExportedSymbol>,
+    pub functions: BTreeMap<String, FunctionAbi>,
+    /// Exported variables: type and the type names it mentions
+    pub variables: BTreeMap<String, (String, BTreeSet<String>)>,
+    /// `struct tag`, `union tag`, or the typedef naming an untagged one
+    pub records: BTreeMap<String, RecordAbi>,
+    pub enums: BTreeMap<String, Vec<(String, i64)>>,
+    pub typedefs: BTreeMap<String, (String, BTreeSet<String>)>,
+    pub has_debug_info: bool,
+}
+
+impl AbiSnapshot {
+    pub fn load(path: &Path) -> Result<Self, AbiCheckError> {
+        let data = fs::read(path)?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, AbiCheckError> {
+        let file = object::File::parse(data).map_err(|e| AbiCheckError::Parse(e.to_string()))?;
+        let mut snapshot = AbiSnapshot::default();
+
+        // A shared library exports through its dynamic symbol table
+        let symbols: Vec<object::Symbol> = if file.kind() == ObjectKind::Dynamic {
+            file.dynamic_symbols().collect()
+        } else {
+            file.symbols().collect()
+        };
+        for symbol in symbols {
+            if !symbol.is_global() || symbol.is_undefined() {
+                continue;
+            }
+            let class = match symbol.kind() {
+                object::SymbolKind::Text => SymbolClass::Function,
+                object::SymbolKind::Data => SymbolClass::Object,
+                object::SymbolKind::Tls => SymbolClass::ThreadLocal,
+                _ => continue,
+            };
+            if let Ok(name) = symbol.name() {
+                if !name.is_empty() {
+                    snapshot.symbols.insert(name.to_string(), ExportedSymbol { class, size: symbol.size() });
+                }
+            }
+        }
+
+        let endian = if file.is_little_endian() { gimli::RunTimeEndian::Little } else { gimli::RunTimeEndian::Big };
+        let load = |id: gimli::SectionId| -> Result<Cow<[u8]>, gimli::Error> {
+            Ok(match file.section_by_name(id.name()) {
+                Some(section) => debug_section_data(&file, &section),
+                None => Cow::Borrowed(&[]),
+            })
+        };
+        let sections = gimli::Dwarf::load(load)?;
+        let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            let unit = dwarf.unit(header)?;
+            snapshot.has_debug_info = true;
+            UnitReader { dwarf: &dwarf, unit: &unit }.read_into(&mut snapshot)?;
+        }
+        Ok(snapshot)
+    }
+
+    /// Everything reachable from the exported functions and variables
+    fn public_types(&self) -> BTreeSet<String> {
+        let mut queue: VecDeque<&String> = VecDeque::new();
+        for (name, function) in &self.functions {
+            if self.symbols.contains_key(name) {
+                queue.extend(&function.referenced);
+            }
+        }
+        for (name, (_, referenced)) in &self.variables {
+            if self.symbols.contains_key(name) {
+                queue.extend(referenced);
+            }
+        }
+
+        let mut public = BTreeSet::new();
+        while let Some(name) = queue.pop_front() {
+            if !public.insert(name.clone()) {
+                continue;
+            }
+            if let Some(record) = self.records.get(name) {
+                queue.extend(&record.referenced);
+            }
+            if let Some((_, referenced)) = self.typedefs.get(name) {
+                queue.extend(referenced);
+            }
+        }
+        public
+    }
+}
+
+/// Section contents with relocations applied. In a relocatable object the
+/// references from `.debug_info` into `.debug_str` and `.debug_abbrev` are
+/// relocations, and with RELA the section bytes themselves hold zeros.
+fn debug_section_data<'data>(file: &object::File<'data>, section: &object::Section<'data, '_>) -> Cow<'data, [u8]> {
+    let Ok(data) = section.uncompressed_data() else { return Cow::Borrowed(&[]) };
+    if file.kind() != ObjectKind::Relocatable || section.relocations().next().is_none() {
+        return data;
+    }
+
+    let mut data = data.into_owned();
+    for (offset, relocation) in section.relocations() {
+        if relocation.kind() != object::RelocationKind::Absolute {
+            continue;
+        }
+        let base = match relocation.target() {
+            object::RelocationTarget::Symbol(index) => match file.symbol_by_index(index) {
+                Ok(symbol) => symbol.address(),
+                Err(_) => continue,
+            },
+            object::RelocationTarget::Section(_) => 0,
+            _ => continue,
+        };
+        let (offset, width) = (offset as usize, relocation.size() as usize / 8);
+        let Some(bytes) = data.get_mut(offset..offset + width) else { continue };
+        let implicit = if relocation.has_implicit_addend() { read_uint(bytes, file.is_little_endian()) } else { 0 };
+        let value = base.wrapping_add(implicit).wrapping_add(relocation.addend() as u64);
+        write_uint(bytes, value, file.is_little_endian());
+    }
+    Cow::Owned(data)
+}
+
+fn read_uint(bytes: &[u8], little_endian: bool) -> u64 {
+    let fold = |value: u64, byte: &u8| (value << 8) | *byte as u64;
+    if little_endian {
+        bytes.iter().rev().fold(0, fold)
+    } else {
+        bytes.iter().fold(0, fold)
+    }
+}
+
+fn write_uint(bytes: &mut [u8], value: u64, little_endian: bool) {
+    let width = bytes.len();
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let shift = if little_endian { i } else { width - 1 - i };
+        *byte = (value >> (8 * shift)) as u8;
+    }
+}
+
+struct UnitReader<'a, 'u> {
+    dwarf: &'u gimli::Dwarf<Reader<'a>>,
+    unit: &'u gimli::Unit<Reader<'a>>,
+}
+
+impl UnitReader<'_, '_> {
+    fn read_into(&self, snapshot: &mut AbiSnapshot) -> Result<(), gimli::Error> {
+        let mut tree = self.unit.entries_tree(None)?;
+        let root = tree.root()?.entry().offset();
+
+        for offset in self.children(root)? {
+            let entry = self.unit.entry(offset)?;
+            let declaration = self.flag(offset, gimli::DW_AT_declaration)?;
+            let Some(name) = self.name(offset)? else { continue };
+
+            match entry.tag() {
+                // The first definition seen of anything wins
+                gimli::DW_TAG_subprogram if self.flag(offset, gimli::DW_AT_external)? && !declaration => {
+                    if let Entry::Vacant(slot) = snapshot.functions.entry(name) {
+                        let mut referenced = BTreeSet::new();
+                        let (return_type, params) = self.signature(offset, &mut referenced, 0)?;
+                        slot.insert(FunctionAbi { return_type, params, referenced });
+                    }
+                }
+                gimli::DW_TAG_variable if self.flag(offset, gimli::DW_AT_external)? && !declaration => {
+                    if let Entry::Vacant(slot) = snapshot.variables.entry(name) {
+                        let mut referenced = BTreeSet::new();
+                        let ty = self.type_name(self.type_ref(offset)?, &mut referenced, 0)?;
+                        slot.insert((ty, referenced));
+                    }
+                }
+                gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type if !declaration => {
+                    if let Entry::Vacant(slot) = snapshot.records.entry(format!("{} {}", keyword(entry.tag()), name)) {
+                        slot.insert(self.record(offset)?);
+                    }
+                }
+                gimli::DW_TAG_enumeration_type if !declaration => {
+                    if let Entry::Vacant(slot) = snapshot.enums.entry(format!("enum {}", name)) {
+                        slot.insert(self.enumerators(offset)?);
+                    }
+                }
+                gimli::DW_TAG_typedef => {
+                    if snapshot.typedefs.contains_key(&name) || snapshot.records.contains_key(&name) || snapshot.enums.contains_key(&name) {
+                        continue;
+                    }
+                    // `typedef struct { ... } name` names the struct itself
+                    let target = self.type_ref(offset)?;
+                    if let Some(target) = target.filter(|&t| self.is_anonymous_definition(t).unwrap_or(false)) {
+                        match self.unit.entry(target)?.tag() {
+                            gimli::DW_TAG_enumeration_type => {
+                                snapshot.enums.insert(name, self.enumerators(target)?);
+                            }
+                            _ => {
+                                snapshot.records.insert(name, self.record(target)?);
+                            }
+                        }
+                        continue;
+                    }
+                    let mut referenced = BTreeSet::new();
+                    let ty = self.type_name(target, &mut referenced, 0)?;
+                    snapshot.typedefs.insert(name, (ty, referenced));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn children(&self, offset: UnitOffset) -> Result<Vec<UnitOffset>, gimli::Error> {
+        let mut tree = self.unit.entries_tree(Some(offset))?;
+        let root = tree.root()?;
+        let mut children = root.children();
+        let mut offsets = Vec::new();
+        while let Some(child) = children.next()? {
+            offsets.push(child.entry().offset());
+        }
+        Ok(offsets)
+    }
+
+    fn attr(&self, offset: UnitOffset, name: gimli::DwAt) -> Result<Option<AttributeValue<Reader<'_>>>, gimli::Error> {
+        self.unit.entry(offset)?.attr_value(name)
+    }
+
+    fn name(&self, offset: UnitOffset) -> Result<Option<String>, gimli::Error> {
+        match self.attr(offset, gimli::DW_AT_name)? {
+            Some(value) => Ok(Some(self.dwarf.attr_string(self.unit, value)?.to_string_lossy().into_owned())),
+            None => Ok(None),
+        }
+    }
+
+    fn flag(&self, offset: UnitOffset, name: gimli::DwAt) -> Result<bool, gimli::Error> {
+        Ok(matches!(self.attr(offset, name)?, Some(AttributeValue::Flag(true))))
+    }
+
+    fn udata(&self, offset: UnitOffset, name: gimli::DwAt) -> Result<Option<u64>, gimli::Error> {
+        Ok(self.attr(offset, name)?.and_then(|value| value.udata_value()))
+    }
+
+    fn type_ref(&self, offset: UnitOffset) -> Result<Option<UnitOffset>, gimli::Error> {
+        Ok(match self.attr(offset, gimli::DW_AT_type)? {
+            Some(AttributeValue::UnitRef(target)) => Some(target),
+            _ => None,
+        })
+    }
+
+    fn is_anonymous_definition(&self, offset: UnitOffset) -> Result<bool, gimli::Error> {
+        let tag = self.unit.entry(offset)?.tag();
+        let aggregate = matches!(tag, gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type | gimli::DW_TAG_enumeration_type);
+        Ok(aggregate && self.name(offset)?.is_none() && !self.flag(offset, gimli::DW_AT_declaration)?)
+    }
+
+    /// A C spelling of the type at `offset`; every struct, union, enum and
+    /// typedef it names is added to `referenced`
+    fn type_name(&self, offset: Option<UnitOffset>, referenced: &mut BTreeSet<String>, depth: usize) -> Result<String, gimli::Error> {
+        let Some(offset) = offset else { return Ok("void".to_string()) };
+        if depth > 16 {
+            return Ok("...".to_string());
+        }
+        let tag = self.unit.entry(offset)?.tag();
+        let target = self.type_ref(offset)?;
+
+        Ok(match tag {
+            gimli::DW_TAG_base_type | gimli::DW_TAG_unspecified_type => self.name(offset)?.unwrap_or_default(),
+            gimli::DW_TAG_typedef => {
+                let name = self.name(offset)?.unwrap_or_default();
+                referenced.insert(name.clone());
+                name
+            }
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type | gimli::DW_TAG_enumeration_type => match self.name(offset)? {
+                Some(name) => {
+                    let key = format!("{} {}", keyword(tag), name);
+                    referenced.insert(key.clone());
+                    key
+                }
+                None => format!("{} <anonymous>", keyword(tag)),
+            },
+            gimli::DW_TAG_pointer_type => match target {
+                Some(function) if self.unit.entry(function)?.tag() == gimli::DW_TAG_subroutine_type => {
+                    let (ret, params) = self.signature(function, referenced, depth + 1)?;
+                    format!("{} (*)({})", ret, params.join(", "))
+                }
+                _ => format!("{} *", self.type_name(target, referenced, depth + 1)?),
+            },
+            gimli::DW_TAG_const_type => format!("const {}", self.type_name(target, referenced, depth + 1)?),
+            gimli::DW_TAG_volatile_type => format!("volatile {}", self.type_name(target, referenced, depth + 1)?),
+            gimli::DW_TAG_atomic_type => format!("_Atomic {}", self.type_name(target, referenced, depth + 1)?),
+            gimli::DW_TAG_restrict_type => format!("{} restrict", self.type_name(target, referenced, depth + 1)?),
+            gimli::DW_TAG_array_type => {
+                let mut dimensions = String::new();
+                for child in self.children(offset)? {
+                    let count = match self.udata(child, gimli::DW_AT_count)? {
+                        Some(count) => Some(count),
+                        None => self.udata(child, gimli::DW_AT_upper_bound)?.map(|bound| bound + 1),
+                    };
+                    dimensions.push_str(&format!("[{}]", count.map(|c| c.to_string()).unwrap_or_default()));
+                }
+                format!("{}{}", self.type_name(target, referenced, depth + 1)?, dimensions)
+            }
+            gimli::DW_TAG_subroutine_type => {
+                let (ret, params) = self.signature(offset, referenced, depth + 1)?;
+                format!("{} ({})", ret, params.join(", "))
+            }
+            other => format!("<{}>", other),
+        })
+    }
+
+    /// Return type and parameter types of a subprogram or subroutine type
+    fn signature(&self, offset: UnitOffset, referenced: &mut BTreeSet<String>, depth: usize) -> Result<(String, Vec<String>), gimli::Error> {
+        let return_type = self.type_name(self.type_ref(offset)?, referenced, depth)?;
+        let mut params = Vec::new();
+        for child in self.children(offset)? {
+            match self.unit.entry(child)?.tag() {
+                gimli::DW_TAG_formal_parameter => params.push(self.type_name(self.type_ref(child)?, referenced, depth)?),
+                gimli::DW_TAG_unspecified_parameters => params.push("...".to_string()),
+                _ => {}
+            }
+        }
+        Ok((return_type, params))
+    }
+
+    fn record(&self, offset: UnitOffset) -> Result<RecordAbi, gimli::Error> {
+        let mut record = RecordAbi {
+            size: self.udata(offset, gimli::DW_AT_byte_size)?,
+            members: Vec::new(),
+            referenced: BTreeSet::new(),
+        };
+        self.members(offset, 0, &mut record)?;
+        Ok(record)
+    }
+
+    fn members(&self, offset: UnitOffset, base: u64, record: &mut RecordAbi) -> Result<(), gimli::Error> {
+        for child in self.children(offset)? {
+            if self.unit.entry(child)?.tag() != gimli::DW_TAG_member {
+                continue;
+            }
+            // Union members have no location
+            let location = base + self.udata(child, gimli::DW_AT_data_member_location)?.unwrap_or(0);
+            let target = self.type_ref(child)?;
+            let name = self.name(child)?;
+
+            if name.is_none() {
+                if let Some(target) = target.filter(|&t| self.is_anonymous_definition(t).unwrap_or(false)) {
+                    self.members(target, location, record)?;
+                    continue;
+                }
+            }
+
+            let bits = match self.udata(child, gimli::DW_AT_bit_size)? {
+                Some(width) => {
+                    let start = self.udata(child, gimli::DW_AT_data_bit_offset)?.map_or(location * 8, |bit| base * 8 + bit);
+                    Some((start, width))
+                }
+                None => None,
+            };
+            let ty = self.type_name(target, &mut record.referenced, 0)?;
+            record.members.push(MemberAbi {
+                name: name.unwrap_or_default(),
+                ty,
+                offset: bits.map_or(location, |(start, _)| start / 8),
+                bits,
+            });
+        }
+        Ok(())
+    }
+
+    fn enumerators(&self, offset: UnitOffset) -> Result<Vec<(String, i64)>, gimli::Error> {
+        let mut enumerators = Vec::new();
+        for child in self.children(offset)? {
+            if self.unit.entry(child)?.tag() != gimli::DW_TAG_enumerator {
+                continue;
+            }
+            let value = self.attr(child, gimli::DW_AT_const_value)?;
+            let value = value.and_then(|v| v.sdata_value().or_else(|| v.udata_value().map(|u| u as i64)));
+            enumerators.push((self.name(child)?.unwrap_or_default(), value.unwrap_or_default()));
+        }
+        Ok(enumerators)
+    }
+}
+
+fn keyword(tag: gimli::DwTag) -> &'static str {
+    match tag {
+        gimli::DW_TAG_union_type => "union",
+        gimli::DW_TAG_enumeration_type => "enum",
+        _ => "struct",
+    }
+}
+
+/// Compare two artifacts; `old` is what existing binaries were built against
+pub fn compare(old: &AbiSnapshot, new: &AbiSnapshot) -> SemanticDiff {
+    let mut changes = Vec::new();
+    let mut push = |kind, entity: String, detail: String, impact| changes.push(Change { kind, entity, detail, impact });
+
+    let describe = |name: &str, symbol: &ExportedSymbol, snapshot: &AbiSnapshot| match symbol.class {
+        SymbolClass::Function => match snapshot.functions.get(name) {
+            Some(function) => function.render(name),
+            None => "exported function".to_string(),
+        },
+        SymbolClass::Object => format!("exported object, {} bytes", symbol.size),
+        SymbolClass::ThreadLocal => format!("exported thread-local object, {} bytes", symbol.size),
+    };
+    let entity = |name: &str, symbol: &ExportedSymbol| match symbol.class {
+        SymbolClass::Function => format!("function {}", name),
+        _ => format!("variable {}", name),
+    };
+
+    for (name, before) in &old.symbols {
+        let Some(after) = new.symbols.get(name) else {
+            push(ChangeKind::Removed, entity(name, before), describe(name, before, old), Impact::AbiBreak);
+            continue;
+        };
+        if before.class != after.class {
+            let detail = format!("{:?} -> {:?}", before.class, after.class);
+            push(ChangeKind::Changed, entity(name, before), detail, Impact::AbiBreak);
+            continue;
+        }
+        // Copy relocations in executables fix the size of exported data
+        if before.class != SymbolClass::Function && before.size != after.size {
+            let detail = format!("size {} -> {} bytes", before.size, after.size);
+            push(ChangeKind::Changed, entity(name, before), detail, Impact::AbiBreak);
+        }
+        match (old.functions.get(name), new.functions.get(name)) {
+            (Some(f), Some(g)) if f.return_type != g.return_type || f.params != g.params => {
+                let detail = format!("signature `{}` -> `{}`", f.render(name), g.render(name));
+                push(ChangeKind::Changed, entity(name, before), detail, Impact::AbiBreak);
+            }
+            _ => {}
+        }
+        match (old.variables.get(name), new.variables.get(name)) {
+            (Some((t, _)), Some((u, _))) if t != u => {
+                push(ChangeKind::Changed, entity(name, before), format!("type `{}` -> `{}`", t, u), Impact::AbiBreak);
+            }
+            _ => {}
+        }
+    }
+    for (name, after) in new.symbols.iter().filter(|(name, _)| !old.symbols.contains_key(*name)) {
+        push(ChangeKind::Added, entity(name, after), describe(name, after, new), Impact::Compatible);
+    }
+
+    // Without DWARF on both sides there are no types to compare
+    if !old.has_debug_info || !new.has_debug_info {
+        return SemanticDiff { changes };
+    }
+
+    // Type changes only break callers that can see the type
+    let public = old.public_types();
+    let breaking = |name: &str, impact: Impact| if public.contains(name) { impact } else { Impact::Internal };
+
+    for (name, before) in &old.records {
+        let Some(after) = new.records.get(name) else {
+            push(ChangeKind::Removed, name.clone(), "definition removed".to_string(), breaking(name, Impact::ApiBreak));
+            continue;
+        };
+        let resized = before.size != after.size;
+        if resized {
+            let show = |size: Option<u64>| size.map_or("?".to_string(), |s| s.to_string());
+            let detail = format!("size {} -> {} bytes", show(before.size), show(after.size));
+            push(ChangeKind::Changed, name.clone(), detail, breaking(name, Impact::AbiBreak));
+        }
+        for member in &before.members {
+            let detail = match after.members.iter().find(|m| m.name == member.name) {
+                None => format!("field `{}` removed", member.name),
+                Some(m) if m.ty != member.ty => format!("field `{}`: `{}` -> `{}`", member.name, member.ty, m.ty),
+                Some(m) if m.offset != member.offset || m.bits != member.bits => {
+                    format!("field `{}` moved from offset {} to {}", member.name, describe_offset(member), describe_offset(m))
+                }
+                Some(_) => continue,
+            };
+            push(ChangeKind::Changed, name.clone(), detail, breaking(name, Impact::AbiBreak));
+        }
+        for member in after.members.iter().filter(|m| !before.members.iter().any(|b| b.name == m.name)) {
+            // A field added into padding leaves every existing offset alone
+            let impact = if resized { Impact::AbiBreak } else { Impact::Compatible };
+            let detail = format!("field `{}` added at offset {}", member.name, describe_offset(member));
+            push(ChangeKind::Changed, name.clone(), detail, breaking(name, impact));
+        }
+    }
+    for name in new.records.keys().filter(|name| !old.records.contains_key(*name)) {
+        push(ChangeKind::Added, name.clone(), "defined".to_string(), Impact::Compatible);
+    }
+
+    for (name, before) in &old.enums {
+        let Some(after) = new.enums.get(name) else {
+            push(ChangeKind::Removed, name.clone(), "definition removed".to_string(), breaking(name, Impact::ApiBreak));
+            continue;
+        };
+        for (constant, value) in before {
+            match after.iter().find(|(c, _)| c == constant) {
+                None => push(ChangeKind::Changed, name.clone(), format!("enumerator `{}` removed", constant), breaking(name, Impact::ApiBreak)),
+                Some((_, new_value)) if new_value != value => {
+                    let detail = format!("`{}` = {} -> {}", constant, value, new_value);
+                    push(ChangeKind::Changed, name.clone(), detail, breaking(name, Impact::AbiBreak));
+                }
+                Some(_) => {}
+            }
+        }
+        for (constant, _) in after.iter().filter(|(c, _)| !before.iter().any(|(b, _)| b == c)) {
+            push(ChangeKind::Changed, name.clone(), format!("enumerator `{}` added", constant), Impact::Compatible);
+        }
+    }
+
+    for (name, (before, _)) in &old.typedefs {
+        match new.typedefs.get(name) {
+            Some((after, _)) if after != before => {
+                let detail = format!("`{}` -> `{}`", before, after);
+                push(ChangeKind::Changed, format!("typedef {}", name), detail, breaking(name, Impact::AbiBreak));
+            }
+            _ => {}
+        }
+    }
+
+    SemanticDiff { changes }
+}
+
+fn describe_offset(member: &MemberAbi) -> String {
+    match member.bits {
+        Some((start, width)) => format!("bit {} (width {})", start, width),
+        None => member.offset.to_string(),
+    }
+}
+
+/// Load both artifacts and compare them
+pub fn compare_files(old: &Path, new: &Path) -> Result<SemanticDiff, AbiCheckError> {
+    let old_snapshot = AbiSnapshot::load(old)?;
+    let new_snapshot = AbiSnapshot::load(new)?;
+    for (path, snapshot) in [(old, &old_snapshot), (new, &new_snapshot)] {
+        if !snapshot.has_debug_info {
+            log::warn!("{} has no DWARF; only exported symbols are compared (rebuild with -g)", path.display());
+        }
+    }
+    Ok(compare(&old_snapshot, &new_snapshot))
+}
+
+#[derive(Debug)]
+pub enum AbiCheckError {
+    Io(io::Error),
+    /// Not an object file or shared library `object` understands
+    Parse(String),
+    Dwarf(gimli::Error),
+}
+
+impl From<io::Error> for AbiCheckError {
+    fn from(e: io::Error) -> Self {
+        AbiCheckError::Io(e)
+    }
+}
+
+impl From<gimli::Error> for AbiCheckError {
+    fn from(e: gimli::Error) -> Self {
+        AbiCheckError::Dwarf(e)
+    }
+}
+
+// Example usage:
+/*
+fn check_release(old: &Path, new: &Path) -> Result<(), AbiCheckError> {
+    // c-interpreter abi-check build-1.2/libgeom.so build-1.3/libgeom.so
+    let diff = compare_files(old, new)?;
+    for change in &diff.changes {
+        println!("{}", change);
+        // - function geom_free: void geom_free(struct shape *) [abi-break]
+        // ~ struct shape: field `area` moved from offset 8 to 16 [abi-break]
+        // ~ struct cache_node: size 24 -> 32 bytes [internal]
+    }
+    assert!(!diff.breaks_compatibility(), "1.3 is not a drop-in replacement for 1.2");
+    Ok(())
+}
+*/
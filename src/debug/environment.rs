@@ -0,0 +1,261 @@
+// src/debug/environment.rs
+//! Host environment capture for bug reports
+//! A snapshot of the host (CPU features, OS, LLVM version, locale and the
+//! environment variables that influence compilation) is stored next to crash
+//! repro bundles so a replay can warn when it runs somewhere materially different.
+//!
+//! A repro bundle is a directory `--crash-bundle` writes when a program
+//! dies by a signal: the program as `program.c`, how it was run and how it
+//! ended as `bundle.json`, and the snapshot as `environment.json`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+/// Environment variables that affect how programs are compiled or run
+const RELEVANT_ENV_VARS: &[&str] = &[
+    "PATH",
+    "CC",
+    "CFLAGS",
+    "CPATH",
+    "C_INCLUDE_PATH",
+    "LIBRARY_PATH",
+    "LD_LIBRARY_PATH",
+    "LLVM_SYS_180_PREFIX",
+    "RUST_LOG",
+    "TZ",
+];
+
+/// Files of a crash repro bundle
+pub const BUNDLE_SOURCE: &str = "program.c";
+pub const BUNDLE_MANIFEST: &str = "bundle.json";
+pub const BUNDLE_ENVIRONMENT: &str = "environment.json";
+
+/// Locale variables, in the order libc consults them
+const LOCALE_ENV_VARS: &[&str] = &["LC_ALL", "LC_CTYPE", "LANG"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentSnapshot {
+    pub tool_version: String,
+    pub os: String,
+    pub os_family: String,
+    pub architecture: String,
+    pub cpu_features: Vec<String>,
+    pub llvm_version: Option<String>,
+    pub locale: Option<String>,
+    pub env_vars: BTreeMap<String, String>,
+}
+
+/// A difference between the captured and the current environment
+#[derive(Debug, Clone)]
+pub struct EnvironmentDifference {
+    pub field: String,
+    pub captured: String,
+    pub current: String,
+    /// Whether the difference is likely to change compiler behavior
+    pub material: bool,
+}
+
+impl EnvironmentSnapshot {
+    /// Capture the current host environment
+    pub fn capture() -> Self {
+        let env_vars = RELEVANT_ENV_VARS
+            .iter()
+            .filter_map(|name| std::env::var(name).ok().map(|v| (name.to_string(), v)))
+            .collect();
+
+        EnvironmentSnapshot {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            os_family: std::env::consts::FAMILY.to_string(),
+            architecture: std::env::consts::ARCH.to_string(),
+            cpu_features: detect_cpu_features(),
+            llvm_version: llvm_version(),
+            locale: LOCALE_ENV_VARS
+                .iter()
+                .find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty())),
+            env_vars,
+        }
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<(), EnvironmentError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(EnvironmentError::Serialization)?;
+        fs::write(path, json).map_err(EnvironmentError::Io)
+    }
+
+    /// Load a snapshot from a file, or from a repro bundle's directory
+    pub fn load(path: &Path) -> Result<Self, EnvironmentError> {
+        let path = if path.is_dir() { path.join(BUNDLE_ENVIRONMENT) } else { path.to_path_buf() };
+        let json = fs::read_to_string(path).map_err(EnvironmentError::Io)?;
+        serde_json::from_str(&json).map_err(EnvironmentError::Serialization)
+    }
+
+    /// Compare a captured snapshot against another (usually the current host)
+    pub fn diff(&self, current: &EnvironmentSnapshot) -> Vec<EnvironmentDifference> {
+        let mut diffs = Vec::new();
+
+        let mut check = |field: &str, captured: &str, now: &str, material: bool| {
+            if captured != now {
+                diffs.push(EnvironmentDifference {
+                    field: field.to_string(),
+                    captured: captured.to_string(),
+                    current: now.to_string(),
+                    material,
+                });
+            }
+        };
+
+        check("os", &self.os, &current.os, true);
+        check("architecture", &self.architecture, &current.architecture, true);
+        check("tool_version", &self.tool_version, &current.tool_version, true);
+        check(
+            "llvm_version",
+            self.llvm_version.as_deref().unwrap_or("unknown"),
+            current.llvm_version.as_deref().unwrap_or("unknown"),
+            true,
+        );
+        check(
+            "locale",
+            self.locale.as_deref().unwrap_or("C"),
+            current.locale.as_deref().unwrap_or("C"),
+            false,
+        );
+
+        // Missing CPU features can change code generation, extra ones cannot
+        let missing: Vec<&str> = self.cpu_features
+            .iter()
+            .filter(|f| !current.cpu_features.contains(f))
+            .map(|f| f.as_str())
+            .collect();
+        if !missing.is_empty() {
+            diffs.push(EnvironmentDifference {
+                field: "cpu_features".to_string(),
+                captured: missing.join(","),
+                current: "(missing)".to_string(),
+                material: true,
+            });
+        }
+
+        for name in RELEVANT_ENV_VARS {
+            let captured = self.env_vars.get(*name).map(|s| s.as_str()).unwrap_or("");
+            let now = current.env_vars.get(*name).map(|s| s.as_str()).unwrap_or("");
+            check(&format!("env.{}", name), captured, now, *name != "PATH" && *name != "RUST_LOG");
+        }
+
+        diffs
+    }
+}
+
+/// How a crashed run was started and how it ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReproBundle {
+    /// The arguments the tool was run with, its own name first
+    pub command_line: Vec<String>,
+    /// Execution mode (jit, interpret)
+    pub mode: String,
+    /// How the program ended, e.g. `terminated by signal SIGSEGV (11)`
+    pub exit: String,
+}
+
+impl ReproBundle {
+    /// Write a bundle for `source` to a new directory under `dir`, with the
+    /// current environment; returns the directory
+    pub fn write(&self, dir: &Path, source: &str) -> Result<PathBuf, EnvironmentError> {
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let bundle = dir.join(format!("crash-{}-{}", seconds, std::process::id()));
+        fs::create_dir_all(&bundle).map_err(EnvironmentError::Io)?;
+
+        fs::write(bundle.join(BUNDLE_SOURCE), source).map_err(EnvironmentError::Io)?;
+        let json = serde_json::to_string_pretty(self).map_err(EnvironmentError::Serialization)?;
+        fs::write(bundle.join(BUNDLE_MANIFEST), json).map_err(EnvironmentError::Io)?;
+        EnvironmentSnapshot::capture().write_to(&bundle.join(BUNDLE_ENVIRONMENT))?;
+        Ok(bundle)
+    }
+
+    pub fn load(bundle: &Path) -> Result<Self, EnvironmentError> {
+        let json = fs::read_to_string(bundle.join(BUNDLE_MANIFEST)).map_err(EnvironmentError::Io)?;
+        serde_json::from_str(&json).map_err(EnvironmentError::Serialization)
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn llvm_version() -> Option<String> {
+    let (mut major, mut minor, mut patch) = (0u32, 0u32, 0u32);
+    unsafe {
+        llvm_sys::core::LLVMGetVersion(&mut major, &mut minor, &mut patch);
+    }
+
+    if major == 0 {
+        None
+    } else {
+        Some(format!("{}.{}.{}", major, minor, patch))
+    }
+}
+
+/// Built without the `llvm` feature, there is no LLVM to ask
+#[cfg(not(feature = "llvm"))]
+fn llvm_version() -> Option<String> {
+    None
+}
+
+fn detect_cpu_features() -> Vec<String> {
+    let mut features = Vec::new();
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        macro_rules! probe {
+            ($($feature:tt),*) => {
+                $(if std::is_x86_feature_detected!($feature) {
+                    features.push($feature.to_string());
+                })*
+            };
+        }
+        probe!("sse2", "sse3", "ssse3", "sse4.1", "sse4.2", "popcnt", "avx", "avx2",
+               "fma", "bmi1", "bmi2", "lzcnt", "avx512f", "avx512bw", "avx512dq", "avx512vl");
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        macro_rules! probe {
+            ($($feature:tt),*) => {
+                $(if std::arch::is_aarch64_feature_detected!($feature) {
+                    features.push($feature.to_string());
+                })*
+            };
+        }
+        probe!("neon", "crc", "lse", "rdm", "fp16", "dotprod", "sve", "sve2");
+    }
+
+    features
+}
+
+#[derive(Debug)]
+pub enum EnvironmentError {
+    Io(std::io::Error),
+    Serialization(serde_json::Error),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), EnvironmentError> {
+    // Capture when writing a crash bundle
+    EnvironmentSnapshot::capture().write_to(Path::new("crash/environment.json"))?;
+
+    // Or the whole bundle, on a crash
+    let bundle = ReproBundle { command_line: vec!["c-interpreter".into(), "prog.c".into()], mode: "jit".into(), exit: "terminated by signal SIGSEGV (11)".into() };
+    let dir = bundle.write(Path::new("crashes"), "int main(void) { return *(int *)0; }")?;
+
+    // Later, when replaying the bundle
+    let captured = EnvironmentSnapshot::load(&dir)?;
+    for diff in captured.diff(&EnvironmentSnapshot::capture()) {
+        if diff.material {
+            eprintln!("warning: {} differs: {} -> {}", diff.field, diff.captured, diff.current);
+        }
+    }
+
+    Ok(())
+}
+*/
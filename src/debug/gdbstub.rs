@@ -0,0 +1,557 @@
+// src/debug/gdbstub.rs
+//! GDB remote serial protocol server
+//! Lets an existing GDB or LLDB attach to a JIT'd program with
+//! `target remote :PORT` / `gdb-remote PORT`. The stub controls the program
+//! through ptrace and supports stop queries, register and memory access,
+//! software breakpoints, continue and single-step, and a Ctrl-C from the
+//! debugger interrupts the running program. Symbols and line tables
+//! for JIT'd code reach the debugger through the JIT interface
+//! (see `jit_interface`), not through this protocol.
+//!
+//! With a `ProbeTable` (`with_probes`), `monitor probe ...` attaches and
+//! detaches entry/exit probes on the program's functions and reports their
+//! counts; see `jit::probes`.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use crate::jit::probes::{ProbeError, ProbeMemory, ProbeTable};
+use crate::runtime::exit_status::ProgramExit;
+
+const INT3: u8 = 0xCC;
+const PACKET_SIZE: usize = 0x4000;
+
+/// How long a wait for the program blocks on the debugger before checking
+/// the program again
+const INTERRUPT_POLL_MS: i32 = 50;
+
+/// Why the target last stopped, in protocol terms
+#[derive(Debug, Clone, Copy)]
+enum StopReply {
+    Signal(i32),
+    Exited(i32),
+    Killed(i32),
+}
+
+impl StopReply {
+    fn encode(&self) -> String {
+        match self {
+            StopReply::Signal(sig) => format!("S{:02x}", sig),
+            StopReply::Exited(code) => format!("W{:02x}", code & 0xff),
+            StopReply::Killed(sig) => format!("X{:02x}", sig),
+        }
+    }
+}
+
+pub struct GdbStub {
+    // Traced process, already stopped
+    pid: Pid,
+
+    // Software breakpoints: address -> original byte
+    breakpoints: HashMap<u64, u8>,
+
+    // Last stop state
+    last_stop: StopReply,
+
+    // Patchable functions of the program, for `monitor probe`
+    probes: Option<ProbeTable>,
+
+    // The debugger's connection, watched for Ctrl-C while the program runs
+    interrupt: Option<TcpStream>,
+}
+
+impl GdbStub {
+    /// Take over a process that is ptrace-stopped (e.g. after PTRACE_TRACEME + SIGSTOP)
+    pub fn new(pid: i32) -> Self {
+        GdbStub {
+            pid: Pid::from_raw(pid),
+            breakpoints: HashMap::new(),
+            last_stop: StopReply::Signal(libc::SIGTRAP),
+            probes: None,
+            interrupt: None,
+        }
+    }
+
+    /// Offer `monitor probe` on the functions of `probes`, which the program
+    /// was forked with after they were registered
+    pub fn with_probes(mut self, probes: ProbeTable) -> Self {
+        self.probes = Some(probes);
+        self
+    }
+
+    /// How the program ended, if it ended while the debugger was attached
+    pub fn exit_status(&self) -> Option<ProgramExit> {
+        match self.last_stop {
+            StopReply::Exited(code) => Some(ProgramExit::Exited(code)),
+            StopReply::Killed(sig) => Some(ProgramExit::Signaled(sig)),
+            StopReply::Signal(_) => None,
+        }
+    }
+
+    /// Wait for a debugger on `addr` and serve it until it detaches or the program exits
+    pub fn serve(&mut self, addr: &str) -> Result<(), GdbStubError> {
+        let listener = TcpListener::bind(addr).map_err(GdbStubError::Io)?;
+        log::info!("gdbstub listening on {}", addr);
+
+        let (stream, peer) = listener.accept().map_err(GdbStubError::Io)?;
+        log::info!("debugger connected from {}", peer);
+        stream.set_nodelay(true).map_err(GdbStubError::Io)?;
+
+        self.session(stream)
+    }
+
+    fn session(&mut self, stream: TcpStream) -> Result<(), GdbStubError> {
+        let mut writer = stream.try_clone().map_err(GdbStubError::Io)?;
+        self.interrupt = Some(stream.try_clone().map_err(GdbStubError::Io)?);
+        let mut reader = BufReader::new(stream);
+
+        while let Some(packet) = read_packet(&mut reader, &mut writer)? {
+            let (reply, done) = self.handle(&packet);
+            write_packet(&mut writer, &reply)?;
+            if done {
+                break;
+            }
+        }
+
+        self.interrupt = None;
+        Ok(())
+    }
+
+    /// Handle one packet; returns the reply and whether the session is over
+    fn handle(&mut self, packet: &str) -> (String, bool) {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => self.last_stop.encode(),
+            "g" => self.read_registers().unwrap_or_else(|_| "E01".to_string()),
+            "p" => u64::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| self.read_register(n as usize).ok())
+                .unwrap_or_else(|| "E01".to_string()),
+            "m" => self.read_memory_packet(args).unwrap_or_else(|| "E01".to_string()),
+            "M" => self.write_memory_packet(args).unwrap_or_else(|| "E01".to_string()),
+            "Z" | "z" => self.breakpoint_packet(command == "Z", args),
+            "c" => self.resume(false),
+            "s" => self.resume(true),
+            "H" | "T" => "OK".to_string(),
+            "k" => {
+                let _ = nix::sys::signal::kill(self.pid, Signal::SIGKILL);
+                return (String::new(), true);
+            }
+            "D" => {
+                self.remove_all_breakpoints();
+                let _ = ptrace::detach(self.pid, None);
+                return ("OK".to_string(), true);
+            }
+            "q" if packet.starts_with("qRcmd,") => self.monitor(&packet["qRcmd,".len()..]),
+            "q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        let done = matches!(self.last_stop, StopReply::Exited(_) | StopReply::Killed(_));
+        (reply, done)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            format!("PacketSize={:x};swbreak+", PACKET_SIZE)
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            format!("QC{:x}", self.pid.as_raw())
+        } else if packet == "qfThreadInfo" {
+            format!("m{:x}", self.pid.as_raw())
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// A `monitor` command; the reply is its output, hex encoded
+    fn monitor(&mut self, hex: &str) -> String {
+        let Some(command) = from_hex(hex).and_then(|bytes| String::from_utf8(bytes).ok()) else {
+            return "E01".to_string();
+        };
+        // The program is stopped while we patch it
+        let output = match self.probes.take() {
+            Some(mut probes) => {
+                let output = probes.command(self, &command).unwrap_or_else(|e| format!("{}\n", e));
+                self.probes = Some(probes);
+                output
+            }
+            None => "probes need the program compiled with patchable prologues\n".to_string(),
+        };
+        to_hex(output.as_bytes())
+    }
+
+    fn resume(&mut self, single_step: bool) -> String {
+        match self.step_over_breakpoint() {
+            // The step over the breakpoint was all that was asked for, or
+            // something other than the step trap happened
+            Ok(Some(stop)) if single_step || !matches!(stop, StopReply::Signal(libc::SIGTRAP)) => {
+                self.last_stop = stop;
+                return stop.encode();
+            }
+            Ok(_) => {}
+            Err(_) => return "E01".to_string(),
+        }
+
+        let result = if single_step {
+            ptrace::step(self.pid, None)
+        } else {
+            ptrace::cont(self.pid, None)
+        };
+        if result.is_err() {
+            return "E01".to_string();
+        }
+
+        let stop = self.wait_stop(!single_step);
+        self.last_stop = stop;
+        stop.encode()
+    }
+
+    /// If stopped on one of our breakpoints, execute the original instruction
+    /// first. Returns the stop after that step, or `None` if not on a breakpoint.
+    fn step_over_breakpoint(&mut self) -> Result<Option<StopReply>, GdbStubError> {
+        let pc = self.pc()?;
+        let original = match self.breakpoints.get(&pc) {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+
+        self.write_memory(pc, &[original])?;
+        ptrace::step(self.pid, None).map_err(GdbStubError::Ptrace)?;
+        let stop = self.wait_stop(false);
+        if let StopReply::Signal(_) = stop {
+            self.write_memory(pc, &[INT3])?;
+        }
+        Ok(Some(stop))
+    }
+
+    /// Wait for the next stop. `after_continue` rewinds the pc over an int3
+    /// we hit; after a single step the pc is already correct.
+    fn wait_stop(&mut self, after_continue: bool) -> StopReply {
+        let status = loop {
+            match waitpid(self.pid, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => self.poll_interrupt(),
+                status => break status,
+            }
+        };
+        match status {
+            Ok(WaitStatus::Exited(_, code)) => StopReply::Exited(code),
+            Ok(WaitStatus::Signaled(_, sig, _)) => StopReply::Killed(sig as i32),
+            Ok(WaitStatus::Stopped(_, sig)) => {
+                // An int3 leaves the pc one past the breakpoint
+                if after_continue && sig == Signal::SIGTRAP {
+                    if let Some(address) = self.pc().ok().and_then(|pc| pc.checked_sub(1)) {
+                        if self.breakpoints.contains_key(&address) {
+                            let _ = self.set_pc(address);
+                        }
+                    }
+                }
+                StopReply::Signal(sig as i32)
+            }
+            _ => StopReply::Signal(libc::SIGTRAP),
+        }
+    }
+
+    /// Wait a little for the debugger while the program runs. A Ctrl-C
+    /// (0x03) stops the program with SIGINT, which `wait_stop` then reports;
+    /// in all-stop mode the debugger sends nothing else until it gets a reply.
+    fn poll_interrupt(&mut self) {
+        let Some(stream) = &mut self.interrupt else {
+            std::thread::sleep(std::time::Duration::from_millis(INTERRUPT_POLL_MS as u64));
+            return;
+        };
+
+        let mut fd = libc::pollfd { fd: stream.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut fd, 1, INTERRUPT_POLL_MS) } <= 0 {
+            return;
+        }
+        let mut byte = [0u8];
+        match stream.read(&mut byte) {
+            Ok(1) if byte[0] == 0x03 => {
+                let _ = nix::sys::signal::kill(self.pid, Signal::SIGINT);
+            }
+            // The debugger hung up; keep waiting for the program alone
+            Ok(0) | Err(_) => self.interrupt = None,
+            Ok(_) => {}
+        }
+    }
+
+    fn breakpoint_packet(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = fields.next().and_then(|a| u64::from_str_radix(a, 16).ok());
+
+        // Only software breakpoints (type 0) are supported
+        let addr = match (kind, addr) {
+            (Some("0"), Some(addr)) => addr,
+            _ => return String::new(),
+        };
+
+        let result = if insert {
+            self.insert_breakpoint(addr)
+        } else {
+            self.remove_breakpoint(addr)
+        };
+        if result.is_ok() { "OK".to_string() } else { "E01".to_string() }
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> Result<(), GdbStubError> {
+        if self.breakpoints.contains_key(&addr) {
+            return Ok(());
+        }
+        let mut original = [0u8; 1];
+        self.read_memory(addr, &mut original)?;
+        self.write_memory(addr, &[INT3])?;
+        self.breakpoints.insert(addr, original[0]);
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> Result<(), GdbStubError> {
+        if let Some(original) = self.breakpoints.remove(&addr) {
+            self.write_memory(addr, &[original])?;
+        }
+        Ok(())
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        let addrs: Vec<u64> = self.breakpoints.keys().copied().collect();
+        for addr in addrs {
+            let _ = self.remove_breakpoint(addr);
+        }
+    }
+
+    fn read_memory_packet(&self, args: &str) -> Option<String> {
+        let (addr, len) = args.split_once(',')?;
+        let addr = u64::from_str_radix(addr, 16).ok()?;
+        let len = usize::from_str_radix(len, 16).ok()?.min(PACKET_SIZE / 2);
+
+        let mut buf = vec![0u8; len];
+        self.read_memory(addr, &mut buf).ok()?;
+
+        // Hide our int3 bytes from the debugger
+        for (i, byte) in buf.iter_mut().enumerate() {
+            if let Some(original) = self.breakpoints.get(&(addr + i as u64)) {
+                *byte = *original;
+            }
+        }
+        Some(to_hex(&buf))
+    }
+
+    fn write_memory_packet(&self, args: &str) -> Option<String> {
+        let (header, data) = args.split_once(':')?;
+        let (addr, _) = header.split_once(',')?;
+        let addr = u64::from_str_radix(addr, 16).ok()?;
+        self.write_memory(addr, &from_hex(data)?).ok()?;
+        Some("OK".to_string())
+    }
+
+    fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<(), GdbStubError> {
+        let mem = OpenOptions::new()
+            .read(true)
+            .open(format!("/proc/{}/mem", self.pid))
+            .map_err(GdbStubError::Io)?;
+        mem.read_exact_at(buf, addr).map_err(GdbStubError::Io)
+    }
+
+    fn write_memory(&self, addr: u64, data: &[u8]) -> Result<(), GdbStubError> {
+        // /proc/pid/mem writes go through even to read-only code pages
+        let mem = OpenOptions::new()
+            .write(true)
+            .open(format!("/proc/{}/mem", self.pid))
+            .map_err(GdbStubError::Io)?;
+        mem.write_all_at(data, addr).map_err(GdbStubError::Io)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn register_values(&self) -> Result<Vec<(u64, usize)>, GdbStubError> {
+        let r = ptrace::getregs(self.pid).map_err(GdbStubError::Ptrace)?;
+        // GDB's amd64 register order: 16 GPRs, rip, eflags, 6 segment registers
+        Ok(vec![
+            (r.rax, 8), (r.rbx, 8), (r.rcx, 8), (r.rdx, 8),
+            (r.rsi, 8), (r.rdi, 8), (r.rbp, 8), (r.rsp, 8),
+            (r.r8, 8), (r.r9, 8), (r.r10, 8), (r.r11, 8),
+            (r.r12, 8), (r.r13, 8), (r.r14, 8), (r.r15, 8),
+            (r.rip, 8), (r.eflags, 4),
+            (r.cs, 4), (r.ss, 4), (r.ds, 4), (r.es, 4), (r.fs, 4), (r.gs, 4),
+        ])
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn register_values(&self) -> Result<Vec<(u64, usize)>, GdbStubError> {
+        Err(GdbStubError::UnsupportedArchitecture)
+    }
+
+    fn read_registers(&self) -> Result<String, GdbStubError> {
+        Ok(self
+            .register_values()?
+            .iter()
+            .map(|(value, size)| to_hex(&value.to_le_bytes()[..*size]))
+            .collect())
+    }
+
+    fn read_register(&self, n: usize) -> Result<String, GdbStubError> {
+        let regs = self.register_values()?;
+        let (value, size) = regs.get(n).ok_or(GdbStubError::UnknownRegister(n))?;
+        Ok(to_hex(&value.to_le_bytes()[..*size]))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pc(&self) -> Result<u64, GdbStubError> {
+        Ok(ptrace::getregs(self.pid).map_err(GdbStubError::Ptrace)?.rip)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn pc(&self) -> Result<u64, GdbStubError> {
+        Err(GdbStubError::UnsupportedArchitecture)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn set_pc(&self, pc: u64) -> Result<(), GdbStubError> {
+        let mut regs = ptrace::getregs(self.pid).map_err(GdbStubError::Ptrace)?;
+        regs.rip = pc;
+        ptrace::setregs(self.pid, regs).map_err(GdbStubError::Ptrace)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn set_pc(&self, _pc: u64) -> Result<(), GdbStubError> {
+        Err(GdbStubError::UnsupportedArchitecture)
+    }
+}
+
+impl ProbeMemory for GdbStub {
+    fn read(&self, address: u64, buf: &mut [u8]) -> Result<(), ProbeError> {
+        self.read_memory(address, buf).map_err(|e| ProbeError::Memory(format!("{:?}", e)))
+    }
+
+    fn write_data(&self, address: u64, data: &[u8]) -> Result<(), ProbeError> {
+        self.write_memory(address, data).map_err(|e| ProbeError::Memory(format!("{:?}", e)))
+    }
+
+    fn write_code(&self, address: u64, data: &[u8]) -> Result<(), ProbeError> {
+        self.write_memory(address, data).map_err(|e| ProbeError::Memory(format!("{:?}", e)))
+    }
+}
+
+/// Fork a child that stops itself under ptrace before running `program`.
+/// Returns the child's pid once it has reached the initial stop.
+pub fn spawn_stopped(program: impl FnOnce() -> i32) -> Result<i32, GdbStubError> {
+    io::stdout().flush().map_err(GdbStubError::Io)?;
+
+    unsafe {
+        let pid = libc::fork();
+        if pid < 0 {
+            return Err(GdbStubError::Io(io::Error::last_os_error()));
+        }
+
+        if pid == 0 {
+            if ptrace::traceme().is_err() {
+                libc::_exit(127);
+            }
+            libc::raise(libc::SIGSTOP);
+            let status = program();
+            libc::fflush(std::ptr::null_mut());
+            libc::_exit(status);
+        }
+
+        match waitpid(Pid::from_raw(pid), None) {
+            Ok(WaitStatus::Stopped(_, Signal::SIGSTOP)) => Ok(pid),
+            Ok(other) => Err(GdbStubError::UnexpectedStop(format!("{:?}", other))),
+            Err(e) => Err(GdbStubError::Ptrace(e)),
+        }
+    }
+}
+
+/// Read the next `$payload#checksum` packet, acknowledging it
+fn read_packet(reader: &mut impl Read, writer: &mut impl Write) -> Result<Option<String>, GdbStubError> {
+    let mut byte = [0u8; 1];
+
+    loop {
+        // Skip acks and wait for the start of a packet
+        loop {
+            if reader.read(&mut byte).map_err(GdbStubError::Io)? == 0 {
+                return Ok(None);
+            }
+            match byte[0] {
+                b'$' => break,
+                // Ctrl-C from the debugger: the target is already stopped between packets
+                0x03 => continue,
+                _ => continue,
+            }
+        }
+
+        let mut payload = Vec::new();
+        loop {
+            if reader.read(&mut byte).map_err(GdbStubError::Io)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            payload.push(byte[0]);
+        }
+
+        let mut checksum = [0u8; 2];
+        reader.read_exact(&mut checksum).map_err(GdbStubError::Io)?;
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+        if expected == Some(checksum_of(&payload)) {
+            writer.write_all(b"+").map_err(GdbStubError::Io)?;
+            return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+        }
+        writer.write_all(b"-").map_err(GdbStubError::Io)?;
+    }
+}
+
+fn write_packet(writer: &mut impl Write, payload: &str) -> Result<(), GdbStubError> {
+    let packet = format!("${}#{:02x}", payload, checksum_of(payload.as_bytes()));
+    writer.write_all(packet.as_bytes()).map_err(GdbStubError::Io)?;
+    writer.flush().map_err(GdbStubError::Io)
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum GdbStubError {
+    Io(io::Error),
+    Ptrace(nix::Error),
+    UnknownRegister(usize),
+    UnexpectedStop(String),
+    UnsupportedArchitecture,
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), GdbStubError> {
+    let pid = spawn_stopped(|| unsafe { main_fn(0, argv.as_ptr()) })?;
+
+    // $ gdb -ex 'target remote :1234'
+    // (gdb) monitor probe attach parse_line
+    GdbStub::new(pid).with_probes(compiler.probes().read().clone()).serve("127.0.0.1:1234")
+}
+*/
@@ -0,0 +1,189 @@
+// src/debug/jit_interface.rs
+//! GDB JIT compilation interface
+//! GDB and LLDB set a breakpoint on `__jit_debug_register_code` and walk
+//! `__jit_debug_descriptor` to find in-memory object files describing JIT'd
+//! code. We hand them an ELF image holding the DWARF sections produced by
+//! `DebugInfoGenerator` plus absolute symbols for each compiled function.
+
+use std::ptr;
+use object::write::{Object, StandardSection, Symbol, SymbolSection};
+use object::{Architecture, BinaryFormat, Endianness, SymbolFlags, SymbolKind, SymbolScope};
+use parking_lot::Mutex;
+
+#[repr(u32)]
+#[derive(Clone, Copy)]
+enum JitAction {
+    NoAction = 0,
+    RegisterFn = 1,
+    UnregisterFn = 2,
+}
+
+#[repr(C)]
+pub struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+#[repr(C)]
+pub struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+// Names and layout are fixed by the GDB JIT interface
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: JitAction::NoAction as u32,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+/// Debuggers break here; it must not be inlined or optimized away
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn __jit_debug_register_code() {
+    unsafe { std::arch::asm!("", options(nomem, nostack, preserves_flags)) };
+}
+
+// Serializes updates to the descriptor's linked list
+static DESCRIPTOR_LOCK: Mutex<()> = Mutex::new(());
+
+/// A registered symbol file; unregistered from the debugger on drop
+pub struct JitRegistration {
+    entry: Box<JitCodeEntry>,
+    // The debugger reads the image in place, so it must outlive the entry
+    _symfile: Vec<u8>,
+}
+
+// The raw pointers are only touched under DESCRIPTOR_LOCK
+unsafe impl Send for JitRegistration {}
+unsafe impl Sync for JitRegistration {}
+
+impl JitRegistration {
+    /// Register an in-memory object file with any attached debugger
+    pub fn register(symfile: Vec<u8>) -> Self {
+        let mut entry = Box::new(JitCodeEntry {
+            next_entry: ptr::null_mut(),
+            prev_entry: ptr::null_mut(),
+            symfile_addr: symfile.as_ptr(),
+            symfile_size: symfile.len() as u64,
+        });
+
+        let _guard = DESCRIPTOR_LOCK.lock();
+        unsafe {
+            let descriptor = &mut *ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry_ptr: *mut JitCodeEntry = &mut *entry;
+
+            entry.next_entry = descriptor.first_entry;
+            if !descriptor.first_entry.is_null() {
+                (*descriptor.first_entry).prev_entry = entry_ptr;
+            }
+            descriptor.first_entry = entry_ptr;
+            descriptor.relevant_entry = entry_ptr;
+            descriptor.action_flag = JitAction::RegisterFn as u32;
+            __jit_debug_register_code();
+        }
+
+        JitRegistration { entry, _symfile: symfile }
+    }
+}
+
+impl Drop for JitRegistration {
+    fn drop(&mut self) {
+        let _guard = DESCRIPTOR_LOCK.lock();
+        unsafe {
+            let descriptor = &mut *ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry_ptr: *mut JitCodeEntry = &mut *self.entry;
+
+            if !self.entry.prev_entry.is_null() {
+                (*self.entry.prev_entry).next_entry = self.entry.next_entry;
+            } else {
+                descriptor.first_entry = self.entry.next_entry;
+            }
+            if !self.entry.next_entry.is_null() {
+                (*self.entry.next_entry).prev_entry = self.entry.prev_entry;
+            }
+
+            descriptor.relevant_entry = entry_ptr;
+            descriptor.action_flag = JitAction::UnregisterFn as u32;
+            __jit_debug_register_code();
+        }
+    }
+}
+
+/// Builds the ELF image handed to the debugger
+pub struct SymfileBuilder {
+    object: Object<'static>,
+}
+
+impl SymfileBuilder {
+    pub fn new() -> Self {
+        let arch = if cfg!(target_arch = "aarch64") {
+            Architecture::Aarch64
+        } else {
+            Architecture::X86_64
+        };
+        SymfileBuilder {
+            object: Object::new(BinaryFormat::Elf, arch, Endianness::Little),
+        }
+    }
+
+    /// Add a DWARF section such as `.debug_info` or `.debug_line`
+    pub fn debug_section(mut self, name: &str, data: Vec<u8>) -> Self {
+        let section = self.object.add_section(
+            Vec::new(),
+            name.as_bytes().to_vec(),
+            object::SectionKind::Debug,
+        );
+        self.object.set_section_data(section, data, 1);
+        self
+    }
+
+    /// Add a function symbol at its runtime address
+    pub fn function(mut self, name: &str, address: u64, size: u64) -> Self {
+        self.object.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: address,
+            size,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Compilation,
+            weak: false,
+            section: SymbolSection::Absolute,
+            flags: SymbolFlags::None,
+        });
+        self
+    }
+
+    pub fn build(mut self) -> Result<Vec<u8>, JitInterfaceError> {
+        // GDB expects a .text section to exist even if it is empty
+        self.object.section_id(StandardSection::Text);
+        self.object
+            .write()
+            .map_err(|e| JitInterfaceError::ObjectWrite(e.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub enum JitInterfaceError {
+    ObjectWrite(String),
+}
+
+// Example usage:
+/*
+fn register(debug_info: &DebugInfo, code: *const u8, size: usize) -> Result<JitRegistration, JitInterfaceError> {
+    let mut builder = SymfileBuilder::new();
+    for (name, data) in debug_info.sections.iter() {
+        builder = builder.debug_section(name, data.clone());
+    }
+    let symfile = builder.function("main", code as u64, size as u64).build()?;
+
+    // Keep the registration alive for as long as the code is mapped
+    Ok(JitRegistration::register(symfile))
+}
+*/
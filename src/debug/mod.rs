@@ -1,10 +1,21 @@
 // src/debug/mod.rs
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
 use gimli::{self, write::*};
 use object::{write::*, SymbolSection};
 use nix::sys::ptrace;
 use libc::{self, pid_t};
+use crate::arch::frame::{self, FunctionFrame};
+use crate::interpreter::record::{RecordError, Trace};
+use reverse::{Stop, TimeTravel};
+
+pub mod abi_check;
+pub mod environment;
+pub mod gdbstub;
+pub mod jit_interface;
+pub mod reverse;
+pub mod stack_capture;
 
 pub struct DebugSystem {
     // DWARF generation
@@ -25,6 +36,9 @@ pub struct DebugSystem {
     
     // Process control
     process_controller: ProcessController,
+
+    // Recorded interpreter run, navigated instead of a live process
+    recording: Option<TimeTravel>,
 }
 
 impl DebugSystem {
@@ -37,9 +51,38 @@ impl DebugSystem {
             frame_handler: StackFrameHandler::new()?,
             var_inspector: VariableInspector::new()?,
             process_controller: ProcessController::new()?,
+            recording: None,
         })
     }
 
+    /// Debug a run recorded with `--record`, starting from its last step
+    pub fn load_recording(&mut self, path: &Path) -> Result<&mut TimeTravel, DebugError> {
+        let trace = Trace::load(path).map_err(DebugError::Recording)?;
+        Ok(self.recording.insert(TimeTravel::new(trace)))
+    }
+
+    pub fn recording(&mut self) -> Option<&mut TimeTravel> {
+        self.recording.as_mut()
+    }
+
+    /// Undo the last statement of the recorded run
+    pub fn reverse_step(&mut self) -> Result<Stop, DebugError> {
+        Ok(self.recording.as_mut().ok_or(DebugError::NoRecording)?.reverse_step())
+    }
+
+    /// Run the recording backwards to a breakpoint or watched write
+    pub fn reverse_continue(&mut self) -> Result<Stop, DebugError> {
+        Ok(self.recording.as_mut().ok_or(DebugError::NoRecording)?.reverse_continue())
+    }
+
+    pub fn replay_step(&mut self) -> Result<Stop, DebugError> {
+        Ok(self.recording.as_mut().ok_or(DebugError::NoRecording)?.step())
+    }
+
+    pub fn replay_continue(&mut self) -> Result<Stop, DebugError> {
+        Ok(self.recording.as_mut().ok_or(DebugError::NoRecording)?.continue_forward())
+    }
+
     /// Set a breakpoint at the specified address
     pub unsafe fn set_breakpoint(
         &mut self,
@@ -133,7 +176,7 @@ impl DebugSystem {
         
         // Find relevant mapping
         if let Some(mapping) = maps.find_mapping(fault_addr) {
-            println!(
+            log::error!(
                 "Segmentation fault at 0x{:x} in mapping: {:?}",
                 fault_addr, mapping
             );
@@ -141,9 +184,9 @@ impl DebugSystem {
 
         // Get stack trace
         let trace = self.generate_stack_trace(pid)?;
-        println!("Stack trace at fault:");
+        log::error!("Stack trace at fault:");
         for frame in trace {
-            println!("  {}", frame);
+            log::error!("  {}", frame);
         }
 
         Ok(())
@@ -184,6 +227,11 @@ pub enum DebugError {
     InvalidMemoryAccess(usize),
     StackUnwindError(String),
     ProcessError(String),
+    Recording(RecordError),
+    /// A reverse command without a loaded recording
+    NoRecording,
+    /// A variable in a stack slot its function's frame layout doesn't have
+    NoFrameSlot(VariableId),
 }
 
 impl DebugSystem {
@@ -245,6 +293,9 @@ pub struct DebugInfoGenerator {
     
     // Variable tracking
     variable_locations: VariableLocations,
+
+    // Frame layouts, for .debug_frame
+    call_frames: Vec<FunctionFrame>,
 }
 
 impl DebugInfoGenerator {
@@ -255,6 +306,7 @@ impl DebugInfoGenerator {
             symbols: SymbolTable::new(),
             line_program: LineProgram::new()?,
             variable_locations: VariableLocations::new(),
+            call_frames: Vec::new(),
         })
     }
 
@@ -391,8 +443,17 @@ impl DebugInfoGenerator {
         ir: &IR,
         machine_code: &MachineCode
     ) -> Result<(), DebugError> {
+        self.call_frames.clear();
         for func in ir.functions() {
             let mut frame_info = FrameInfo::new(func.id());
+
+            // The frame base is the CFA and stack slots are CFA offsets, both
+            // from the layout the code generator emitted the prologue from
+            let frame = machine_code.get_frame(func.id());
+            if let Some(frame) = frame {
+                frame_info.set_frame_base(frame.layout.frame_base());
+                self.call_frames.push(frame.clone());
+            }
             
             // Track register allocations
             for var in func.variables() {
@@ -401,8 +462,11 @@ impl DebugInfoGenerator {
                         Location::Register(reg) => {
                             frame_info.add_register_location(var.id(), reg);
                         }
-                        Location::Stack(offset) => {
-                            frame_info.add_stack_location(var.id(), offset);
+                        Location::Stack(slot) => {
+                            let offset = frame
+                                .and_then(|frame| frame.layout.slot_offset(slot))
+                                .ok_or(DebugError::NoFrameSlot(var.id()))?;
+                            frame_info.add_stack_location(var.id(), offset as i32);
                         }
                         Location::Constant(value) => {
                             frame_info.add_constant_location(var.id(), value);
@@ -446,6 +510,13 @@ impl DebugInfoGenerator {
         self.variable_locations.write(&mut loc)?;
         sections.add(".debug_loc", loc);
 
+        // .debug_frame section
+        if let Some(first) = self.call_frames.first() {
+            let mut frame = Section::new();
+            frame.append(&frame::debug_frame(first.layout.architecture, &self.call_frames));
+            sections.add(".debug_frame", frame);
+        }
+
         Ok(sections)
     }
 }
@@ -474,6 +545,22 @@ impl SourceMap {
     pub fn get_location(&self, inst: InstructionId) -> Option<&SourceLocation> {
         self.locations.get(&inst)
     }
+
+    /// Look up a registered file by the path recorded in debug info
+    pub fn find_file(&self, path: &str) -> Option<FileId> {
+        self.files
+            .iter()
+            .find(|(_, file)| file.path.to_string_lossy() == path)
+            .map(|(id, _)| *id)
+    }
+
+    /// `file:line:column` for runtime diagnostics
+    pub fn describe(&self, loc: &SourceLocation) -> String {
+        match self.files.get(&loc.file_id) {
+            Some(file) => format!("{}:{}:{}", file.path.display(), loc.line, loc.column),
+            None => format!("<unknown>:{}:{}", loc.line, loc.column),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -508,6 +595,7 @@ impl VariableLocations {
 
 pub struct FrameInfo {
     function_id: FunctionId,
+    frame_base: Option<Vec<u8>>,
     register_locations: HashMap<VariableId, Register>,
     stack_locations: HashMap<VariableId, i32>,
     constant_locations: HashMap<VariableId, u64>,
@@ -517,12 +605,17 @@ impl FrameInfo {
     fn new(function_id: FunctionId) -> Self {
         FrameInfo {
             function_id,
+            frame_base: None,
             register_locations: HashMap::new(),
             stack_locations: HashMap::new(),
             constant_locations: HashMap::new(),
         }
     }
 
+    fn set_frame_base(&mut self, expression: Vec<u8>) {
+        self.frame_base = Some(expression);
+    }
+
     fn add_register_location(&mut self, var: VariableId, reg: Register) {
         self.register_locations.insert(var, reg);
     }
@@ -537,6 +630,10 @@ impl FrameInfo {
 
     fn write(&self, section: &mut Section) -> Result<(), DebugError> {
         // Write locations in DWARF format
+        if let Some(base) = &self.frame_base {
+            section.write_frame_base(self.function_id, base)?;
+        }
+
         for (var, reg) in &self.register_locations {
             section.write_register_location(*var, *reg)?;
         }
@@ -0,0 +1,335 @@
+// src/debug/reverse.rs
+//! Reverse execution over a recorded run (`debug --replay`)
+//! Every memory write in a trace carries the bytes it replaced, so the
+//! debugger can move through the run in either direction without executing
+//! anything: stepping forward applies a step's writes, stepping back undoes
+//! them. A session opens at the last recorded step, which is where the
+//! program crashed or stopped, so the usual way in is to set a breakpoint
+//! or watchpoint and `reverse-continue` to the cause.
+//!
+//! Positions count steps: at position `p`, steps before `p` have executed
+//! and step `p` is the statement about to run. Memory is known for every
+//! byte the program or kernel wrote at some point in the run; bytes it
+//! never touched read as unknown.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use crate::interpreter::record::{MemoryWrite, StepLocation, Trace, TraceEnd};
+
+/// Why a step or continue stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// Moved one step
+    Stepped,
+    Breakpoint(usize),
+    /// A watched range was written: going forward, by the step just run;
+    /// in reverse, by the step about to run
+    Watchpoint { address: u64, old: Vec<u8>, new: Vec<u8> },
+    /// Back at the beginning of the recording
+    Start,
+    /// At the last recorded step
+    End,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Stepped => Ok(()),
+            Stop::Breakpoint(index) => write!(f, "breakpoint {}", index + 1),
+            Stop::Watchpoint { address, old, new } => {
+                write!(f, "watchpoint: {:#x} changed from {:02x?} to {:02x?}", address, old, new)
+            }
+            Stop::Start => write!(f, "no more reverse execution history"),
+            Stop::End => write!(f, "end of the recording"),
+        }
+    }
+}
+
+/// A `file:line` breakpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineBreakpoint {
+    pub file: String,
+    pub line: u32,
+}
+
+impl LineBreakpoint {
+    fn hit(&self, location: &StepLocation) -> bool {
+        location.line == self.line && (location.file == self.file || location.file.ends_with(&format!("/{}", self.file)))
+    }
+}
+
+pub struct TimeTravel {
+    trace: Trace,
+    position: usize,
+
+    // Stop conditions
+    breakpoints: Vec<LineBreakpoint>,
+    watchpoints: Vec<(u64, usize)>,
+
+    // Statistics
+    steps_moved: u64,
+}
+
+impl TimeTravel {
+    /// A session positioned at the last recorded step
+    pub fn new(trace: Trace) -> Self {
+        let position = trace.steps.len() - 1;
+        TimeTravel {
+            trace,
+            position,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            steps_moved: 0,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of the last step; positions run from 0 to this
+    pub fn last_position(&self) -> usize {
+        self.trace.steps.len() - 1
+    }
+
+    pub fn location(&self) -> Option<&StepLocation> {
+        self.trace.steps[self.position].location.as_ref()
+    }
+
+    pub fn end(&self) -> Option<TraceEnd> {
+        self.trace.end
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    pub fn break_at(&mut self, file: &str, line: u32) -> usize {
+        self.breakpoints.push(LineBreakpoint { file: file.to_string(), line });
+        self.breakpoints.len() - 1
+    }
+
+    pub fn watch(&mut self, address: u64, len: usize) {
+        self.watchpoints.push((address, len));
+    }
+
+    pub fn clear_stops(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    /// Run one step forward
+    pub fn step(&mut self) -> Stop {
+        if self.position == self.last_position() {
+            return Stop::End;
+        }
+        self.position += 1;
+        self.steps_moved += 1;
+        Stop::Stepped
+    }
+
+    /// Undo the previous step
+    pub fn reverse_step(&mut self) -> Stop {
+        if self.position == 0 {
+            return Stop::Start;
+        }
+        self.position -= 1;
+        self.steps_moved += 1;
+        Stop::Stepped
+    }
+
+    /// Run forward to the next breakpoint or watched write
+    pub fn continue_forward(&mut self) -> Stop {
+        while self.position < self.last_position() {
+            // The statement at the current position executes first
+            let executed = self.position;
+            self.position += 1;
+            self.steps_moved += 1;
+            if let Some(stop) = self.watch_hit(executed) {
+                return stop;
+            }
+            if let Some(stop) = self.breakpoint_hit(self.position) {
+                return stop;
+            }
+        }
+        Stop::End
+    }
+
+    /// Run backward to the previous breakpoint, or to the statement that
+    /// last wrote a watched range
+    pub fn reverse_continue(&mut self) -> Stop {
+        while self.position > 0 {
+            self.position -= 1;
+            self.steps_moved += 1;
+            if let Some(stop) = self.watch_hit(self.position) {
+                return stop;
+            }
+            if let Some(stop) = self.breakpoint_hit(self.position) {
+                return stop;
+            }
+        }
+        Stop::Start
+    }
+
+    /// Memory as of the current position; `None` for bytes the run never
+    /// wrote. Scans the trace, which is fast enough for interactive use.
+    pub fn read_memory(&self, address: u64, len: usize) -> Vec<Option<u8>> {
+        (0..len as u64)
+            .map(|offset| self.read_byte(address + offset))
+            .collect()
+    }
+
+    /// (steps, breakpoints, watchpoints, steps moved this session)
+    pub fn stats(&self) -> (usize, usize, usize, u64) {
+        (self.trace.steps.len(), self.breakpoints.len(), self.watchpoints.len(), self.steps_moved)
+    }
+
+    fn read_byte(&self, address: u64) -> Option<u8> {
+        let byte_of = |write: &MemoryWrite, bytes: &[u8]| bytes[(address - write.address) as usize];
+
+        // The latest write before this position wins
+        let before = self.trace.steps[..self.position].iter().rev();
+        for step in before {
+            if let Some(write) = step.writes().filter(|w| w.overlaps(address, 1)).last() {
+                return Some(byte_of(write, &write.new));
+            }
+        }
+        // Otherwise whatever the first later write found there
+        let after = self.trace.steps[self.position..].iter();
+        for step in after {
+            if let Some(write) = step.writes().find(|w| w.overlaps(address, 1)) {
+                return Some(byte_of(write, &write.old));
+            }
+        }
+        None
+    }
+
+    fn watch_hit(&self, position: usize) -> Option<Stop> {
+        self.trace.steps[position]
+            .writes()
+            .find(|write| self.watchpoints.iter().any(|&(address, len)| write.overlaps(address, len)))
+            .map(|write| Stop::Watchpoint {
+                address: write.address,
+                old: write.old.clone(),
+                new: write.new.clone(),
+            })
+    }
+
+    fn breakpoint_hit(&self, position: usize) -> Option<Stop> {
+        let location = self.trace.steps[position].location.as_ref()?;
+        self.breakpoints.iter().position(|bp| bp.hit(location)).map(Stop::Breakpoint)
+    }
+}
+
+/// Read commands from stdin and drive `session` until EOF or `quit`
+pub fn run_prompt(session: &mut TimeTravel) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut stdout = io::stdout();
+
+    match session.end() {
+        Some(end) => println!("Recording of {} steps, ended with {:?}", session.last_position(), end),
+        None => println!("Recording of {} steps, cut short", session.last_position()),
+    }
+    print_position(session);
+    loop {
+        print!("(replay) ");
+        stdout.flush()?;
+        let Some(line) = lines.next() else { break };
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let stop = match words.as_slice() {
+            [] => continue,
+            ["quit" | "q"] => break,
+            ["help" | "h"] => {
+                println!("step (s), continue (c)            run forward");
+                println!("reverse-step (rs), reverse-continue (rc)  run backward");
+                println!("break FILE:LINE (b), watch ADDR [LEN] (w), delete");
+                println!("x ADDR [LEN]                      show memory at this point");
+                println!("where                             show the current statement");
+                continue;
+            }
+            ["step" | "s"] => session.step(),
+            ["continue" | "c"] => session.continue_forward(),
+            ["reverse-step" | "rs"] => session.reverse_step(),
+            ["reverse-continue" | "rc"] => session.reverse_continue(),
+            ["where"] => {
+                print_position(session);
+                continue;
+            }
+            ["delete"] => {
+                session.clear_stops();
+                continue;
+            }
+            ["break" | "b", spec] => {
+                match spec.rsplit_once(':').and_then(|(file, line)| Some((file, line.parse().ok()?))) {
+                    Some((file, line)) => println!("Breakpoint {} at {}:{}", session.break_at(file, line) + 1, file, line),
+                    None => eprintln!("usage: break FILE:LINE"),
+                }
+                continue;
+            }
+            ["watch" | "w", address, rest @ ..] | ["x", address, rest @ ..] => {
+                let len = rest.first().and_then(|len| len.parse().ok()).unwrap_or(8);
+                let Some(address) = parse_address(address) else {
+                    eprintln!("invalid address '{}'", address);
+                    continue;
+                };
+                if words[0] == "x" {
+                    let bytes: Vec<String> = session
+                        .read_memory(address, len)
+                        .iter()
+                        .map(|byte| byte.map_or("??".to_string(), |b| format!("{:02x}", b)))
+                        .collect();
+                    println!("{:#x}: {}", address, bytes.join(" "));
+                } else {
+                    session.watch(address, len);
+                    println!("Watching {} bytes at {:#x}", len, address);
+                }
+                continue;
+            }
+            _ => {
+                eprintln!("unknown command '{}'; try help", line.trim());
+                continue;
+            }
+        };
+
+        if stop != Stop::Stepped {
+            println!("{}", stop);
+        }
+        print_position(session);
+    }
+    Ok(())
+}
+
+fn print_position(session: &TimeTravel) {
+    match session.location() {
+        Some(location) => println!("#{} {}", session.position(), location),
+        None => println!("#{} <startup>", session.position()),
+    }
+}
+
+fn parse_address(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), RecordError> {
+    let trace = Trace::load(Path::new("crash.trace"))?;
+    let mut session = TimeTravel::new(trace);
+
+    // Where did the corrupted length at 0x7ffc1000 come from?
+    session.watch(0x7ffc1000, 4);
+    if let Stop::Watchpoint { old, new, .. } = session.reverse_continue() {
+        println!("{:?} -> {:?} at {}", old, new, session.location().unwrap());
+    }
+
+    // The statement that wrote it hasn't run yet, so memory shows the old value
+    println!("{:?}", session.read_memory(0x7ffc1000, 4));
+    Ok(())
+}
+*/
@@ -0,0 +1,590 @@
+// src/debug/stack_capture.rs
+//! Stack traces of a running program, taken from another thread
+//! An embedder registers the thread that runs a guest (`ThreadHandle::current`)
+//! and a watchdog can later ask what it is doing (`ThreadHandle::capture`)
+//! without stopping it for longer than a frame walk.
+//!
+//! Compiled frames: the thread is interrupted with `capture_signal()`. Its
+//! handler reads the interrupted pc and frame pointer from the signal
+//! context and follows the frame-pointer chain (see `arch::frame::FrameRecord`)
+//! within the thread's stack, writing raw addresses to a static buffer; it
+//! only loads and stores, so it is async-signal-safe wherever it lands.
+//! The capturing thread symbolizes the addresses afterwards: host code with
+//! `dladdr`, JIT-compiled functions through `JitSymbols`.
+//!
+//! Interpreted frames: the interpreter publishes its calls and the line each
+//! is on in a `ShadowStack`, which another thread reads without
+//! interrupting anything. Every entry is one atomic, so no frame is torn,
+//! but a call made during the read may be missing from it.
+//!
+//! Code without frame pointers, and a function interrupted before its
+//! prologue saved one, hide their caller: the walk is best effort, like
+//! any sampling profiler's.
+
+use std::collections::{BTreeMap, HashMap};
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::fmt;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use libc::c_int;
+use parking_lot::RwLock;
+use crate::arch::frame::FrameRecord;
+
+/// Native frames kept per capture
+pub const MAX_FRAMES: usize = 128;
+
+/// Interpreted calls the shadow stack keeps lines for; deeper ones count
+/// toward its depth but aren't shown
+pub const MAX_SHADOW_DEPTH: usize = 1024;
+
+const SUPPORTED: bool = cfg!(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")));
+
+/// The realtime signal captures interrupt a thread with. Reserved: a guest
+/// can't install a handler for it (`SignalError::Reserved`).
+pub fn capture_signal() -> c_int {
+    libc::SIGRTMAX() - 1
+}
+
+// The capture in progress; one at a time, under `CAPTURE`
+struct Request {
+    /// Generation of the capture waiting for its handler, 0 when none is.
+    /// The handler claims it by swapping it to 0, a timed-out capture
+    /// withdraws it the same way, so exactly one of them wins.
+    pending: AtomicU64,
+    /// Generation whose frames are in `frames`
+    done: AtomicU64,
+    thread: AtomicI64,
+    stack_low: AtomicUsize,
+    stack_high: AtomicUsize,
+    count: AtomicUsize,
+    frames: [AtomicUsize; MAX_FRAMES],
+}
+
+static REQUEST: Request = Request {
+    pending: AtomicU64::new(0),
+    done: AtomicU64::new(0),
+    thread: AtomicI64::new(0),
+    stack_low: AtomicUsize::new(0),
+    stack_high: AtomicUsize::new(0),
+    count: AtomicUsize::new(0),
+    frames: [const { AtomicUsize::new(0) }; MAX_FRAMES],
+};
+
+static CAPTURE: Mutex<u64> = Mutex::new(0);
+static INSTALLED: OnceLock<Result<(), i32>> = OnceLock::new();
+
+thread_local! {
+    static REGISTRATION: RefCell<Option<Registration>> = const { RefCell::new(None) };
+}
+
+/// Marks the thread gone when it exits. A capture holds the lock while it
+/// signals the thread, so the thread can't exit under it.
+struct Registration(Arc<Mutex<bool>>);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = false;
+    }
+}
+
+/// A thread running a guest, to capture stacks of
+#[derive(Clone)]
+pub struct ThreadHandle {
+    pthread: libc::pthread_t,
+    tid: i64,
+    stack: (usize, usize),
+    alive: Arc<Mutex<bool>>,
+
+    // Where frame names come from
+    shadow: Option<Arc<ShadowStack>>,
+    symbols: Option<Arc<RwLock<JitSymbols>>>,
+}
+
+impl ThreadHandle {
+    /// The calling thread; call it on the thread that will run the program
+    pub fn current() -> Result<Self, StackCaptureError> {
+        if !SUPPORTED || FrameRecord::host().is_none() {
+            return Err(StackCaptureError::UnsupportedHost);
+        }
+        let stack = current_stack()?;
+        let alive = REGISTRATION.with(|registration| {
+            let mut registration = registration.borrow_mut();
+            let registration = registration.get_or_insert_with(|| Registration(Arc::new(Mutex::new(true))));
+            Arc::clone(&registration.0)
+        });
+        Ok(ThreadHandle {
+            pthread: unsafe { libc::pthread_self() },
+            tid: gettid(),
+            stack,
+            alive,
+            shadow: None,
+            symbols: None,
+        })
+    }
+
+    /// Report the interpreted calls `stack` records; give the same stack to
+    /// `CRuntimeEnvironment::set_shadow_stack`
+    pub fn with_shadow_stack(mut self, stack: Arc<ShadowStack>) -> Self {
+        self.shadow = Some(stack);
+        self
+    }
+
+    /// Name JIT-compiled frames from `symbols` (`Engine::jit_symbols`)
+    pub fn with_jit_symbols(mut self, symbols: Arc<RwLock<JitSymbols>>) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub fn is_alive(&self) -> bool {
+        *self.alive.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The interpreted calls only, innermost first; doesn't signal the thread
+    pub fn interpreted(&self) -> Vec<InterpretedFrame> {
+        self.shadow.as_ref().map_or_else(Vec::new, |stack| stack.snapshot())
+    }
+
+    /// Interrupt the thread, walk its native stack and read its shadow
+    /// stack. Fails with `Timeout` if the thread didn't run the handler in
+    /// time, for example because it blocks the signal.
+    pub fn capture(&self, timeout: Duration) -> Result<StackTrace, StackCaptureError> {
+        install()?;
+        let mut generation = CAPTURE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let alive = self.alive.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !*alive {
+            return Err(StackCaptureError::ThreadExited);
+        }
+
+        *generation += 1;
+        let generation = *generation;
+        REQUEST.thread.store(self.tid, Ordering::Relaxed);
+        REQUEST.stack_low.store(self.stack.0, Ordering::Relaxed);
+        REQUEST.stack_high.store(self.stack.1, Ordering::Relaxed);
+        REQUEST.pending.store(generation, Ordering::Release);
+        let result = unsafe { libc::pthread_kill(self.pthread, capture_signal()) };
+        if result != 0 {
+            REQUEST.pending.store(0, Ordering::Release);
+            return Err(StackCaptureError::Host(std::io::Error::from_raw_os_error(result)));
+        }
+
+        let deadline = Instant::now() + timeout;
+        while REQUEST.done.load(Ordering::Acquire) != generation {
+            // Once the handler claimed the request it finishes quickly
+            if Instant::now() >= deadline
+                && REQUEST.pending.compare_exchange(generation, 0, Ordering::AcqRel, Ordering::Acquire).is_ok()
+            {
+                return Err(StackCaptureError::Timeout(timeout));
+            }
+            std::thread::sleep(Duration::from_micros(20));
+        }
+        let count = REQUEST.count.load(Ordering::Acquire);
+        let addresses: Vec<usize> = REQUEST.frames[..count].iter().map(|frame| frame.load(Ordering::Relaxed)).collect();
+        let interpreted = self.interpreted();
+        drop(alive);
+
+        let symbols = self.symbols.as_ref().map(|symbols| symbols.read());
+        let native = symbolize_frames(&addresses, symbols.as_deref());
+        Ok(StackTrace { native, interpreted })
+    }
+}
+
+fn install() -> Result<(), StackCaptureError> {
+    if !SUPPORTED {
+        return Err(StackCaptureError::UnsupportedHost);
+    }
+    let installed = INSTALLED.get_or_init(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = capture_handler as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(capture_signal(), &action, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(0));
+        }
+        Ok(())
+    });
+    installed.map_err(|errno| StackCaptureError::Host(std::io::Error::from_raw_os_error(errno)))
+}
+
+extern "C" fn capture_handler(_signal: c_int, _info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    let generation = REQUEST.pending.load(Ordering::Acquire);
+    // Sent by someone else, or to another thread
+    if generation == 0 || REQUEST.thread.load(Ordering::Relaxed) != gettid() {
+        return;
+    }
+    if REQUEST.pending.compare_exchange(generation, 0, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        return;
+    }
+    let count = unsafe { walk(context) };
+    REQUEST.count.store(count, Ordering::Relaxed);
+    REQUEST.done.store(generation, Ordering::Release);
+}
+
+/// Follow the frame pointers from the interrupted context into `REQUEST.frames`
+unsafe fn walk(context: *mut libc::c_void) -> usize {
+    let mut frames = [0; MAX_FRAMES];
+    let stack = (REQUEST.stack_low.load(Ordering::Relaxed), REQUEST.stack_high.load(Ordering::Relaxed));
+    let count = walk_frames(context, stack, &mut frames);
+    for (slot, &address) in REQUEST.frames.iter().zip(&frames[..count]) {
+        slot.store(address, Ordering::Relaxed);
+    }
+    count
+}
+
+/// Write the interrupted pc and then the return addresses found by
+/// following the frame pointers from `context`, within `stack` (lowest and
+/// highest address), to `frames`. Returns how many were written. Only
+/// loads and stores, so a signal handler may call it.
+///
+/// # Safety
+/// `context` must be the `ucontext_t` a signal handler was passed, and
+/// `stack` readable memory.
+pub unsafe fn walk_frames(context: *mut libc::c_void, stack: (usize, usize), frames: &mut [usize]) -> usize {
+    let Some((first, rest)) = frames.split_first_mut() else { return 0 };
+    let (pc, frame_pointer) = interrupted(context);
+    *first = pc;
+    1 + walk_from(frame_pointer, stack, rest)
+}
+
+/// Write the return addresses of the frame-pointer chain from
+/// `frame_pointer`, within the stack `low..high`, to `frames`; returns how
+/// many were written. Only loads and stores.
+///
+/// # Safety
+/// `low..high` must be readable memory.
+pub unsafe fn walk_from(mut frame_pointer: usize, (low, high): (usize, usize), frames: &mut [usize]) -> usize {
+    let Some(record) = FrameRecord::host() else { return 0 };
+    let word = std::mem::size_of::<usize>();
+    let mut count = 0;
+    while count < frames.len() {
+        // Only follow records that are on this thread's stack, aligned, and
+        // further up it than the last one
+        let record_end = frame_pointer.saturating_add(record.return_address.max(record.caller_frame) + word);
+        if frame_pointer % word != 0 || frame_pointer < low || record_end > high {
+            break;
+        }
+        let return_address = *((frame_pointer + record.return_address) as *const usize);
+        let caller_frame = *((frame_pointer + record.caller_frame) as *const usize);
+        if return_address == 0 {
+            break;
+        }
+        frames[count] = return_address;
+        count += 1;
+        if caller_frame <= frame_pointer {
+            break;
+        }
+        frame_pointer = caller_frame;
+    }
+    count
+}
+
+/// Program counter and frame pointer of the interrupted code
+#[allow(unused_variables)]
+unsafe fn interrupted(context: *mut libc::c_void) -> (usize, usize) {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    {
+        let context = &*(context as *const libc::ucontext_t);
+        let registers = &context.uc_mcontext.gregs;
+        (registers[libc::REG_RIP as usize] as usize, registers[libc::REG_RBP as usize] as usize)
+    }
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    {
+        let context = &*(context as *const libc::ucontext_t);
+        (context.uc_mcontext.pc as usize, context.uc_mcontext.regs[29] as usize)
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    {
+        (0, 0)
+    }
+}
+
+fn gettid() -> i64 {
+    unsafe { libc::syscall(libc::SYS_gettid) }
+}
+
+/// Lowest and highest address of the calling thread's stack
+pub fn current_stack() -> Result<(usize, usize), StackCaptureError> {
+    unsafe {
+        let mut attributes: libc::pthread_attr_t = std::mem::zeroed();
+        let result = libc::pthread_getattr_np(libc::pthread_self(), &mut attributes);
+        if result != 0 {
+            return Err(StackCaptureError::Host(std::io::Error::from_raw_os_error(result)));
+        }
+        let (mut address, mut size) = (std::ptr::null_mut(), 0);
+        let result = libc::pthread_attr_getstack(&attributes, &mut address, &mut size);
+        libc::pthread_attr_destroy(&mut attributes);
+        if result != 0 {
+            return Err(StackCaptureError::Host(std::io::Error::from_raw_os_error(result)));
+        }
+        Ok((address as usize, address as usize + size))
+    }
+}
+
+/// Name the addresses `walk_frames` found, innermost first
+pub fn symbolize_frames(addresses: &[usize], symbols: Option<&JitSymbols>) -> Vec<NativeFrame> {
+    addresses
+        .iter()
+        .enumerate()
+        // Past the first frame the addresses are return addresses, which
+        // may already belong to the next line or function
+        .map(|(index, &address)| symbolize(address, if index == 0 { address } else { address - 1 }, symbols))
+        .collect()
+}
+
+/// Name the code at `lookup`, reported as `address`
+fn symbolize(address: usize, lookup: usize, symbols: Option<&JitSymbols>) -> NativeFrame {
+    let mut frame = NativeFrame { address, symbol: None, offset: 0, object: None };
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    // JIT-compiled code isn't part of any object the loader knows
+    if unsafe { libc::dladdr(lookup as *const libc::c_void, &mut info) } == 0 {
+        if let Some((name, start)) = symbols.and_then(|symbols| symbols.lookup(lookup)) {
+            frame.symbol = Some(name.to_string());
+            frame.offset = address - start;
+            frame.object = Some("<jit>".to_string());
+        }
+        return frame;
+    }
+    if !info.dli_fname.is_null() {
+        frame.object = Some(unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy().into_owned());
+    }
+    if !info.dli_sname.is_null() {
+        frame.symbol = Some(unsafe { CStr::from_ptr(info.dli_sname) }.to_string_lossy().into_owned());
+        frame.offset = address.wrapping_sub(info.dli_saddr as usize);
+    }
+    frame
+}
+
+/// Start addresses of JIT-compiled functions. A function's extent isn't
+/// recorded, so an address is attributed to the closest function below it.
+#[derive(Debug, Default)]
+pub struct JitSymbols {
+    functions: BTreeMap<usize, String>,
+}
+
+impl JitSymbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, address: *const u8) {
+        self.functions.insert(address as usize, name.to_string());
+    }
+
+    /// The function containing `address`, and where it starts
+    pub fn lookup(&self, address: usize) -> Option<(&str, usize)> {
+        self.functions.range(..=address).next_back().map(|(&start, name)| (name.as_str(), start))
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
+
+/// The interpreter's calls, readable from any thread while it runs.
+/// Written by one interpreter thread through a `ShadowWriter`.
+pub struct ShadowStack {
+    /// Per call: function number (1-based index into `names`, 0 while the
+    /// call hasn't run a statement) in the high half, line in the low half
+    frames: Box<[AtomicU64]>,
+    depth: AtomicUsize,
+    names: Mutex<Names>,
+}
+
+#[derive(Default)]
+struct Names {
+    numbers: HashMap<Arc<str>, u32>,
+    names: Vec<Arc<str>>,
+}
+
+impl ShadowStack {
+    pub fn new() -> Self {
+        ShadowStack {
+            frames: (0..MAX_SHADOW_DEPTH).map(|_| AtomicU64::new(0)).collect(),
+            depth: AtomicUsize::new(0),
+            names: Mutex::new(Names::default()),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Acquire)
+    }
+
+    fn number(&self, function: &str) -> u32 {
+        let mut names = self.names.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(&number) = names.numbers.get(function) {
+            return number;
+        }
+        let name: Arc<str> = Arc::from(function);
+        names.names.push(Arc::clone(&name));
+        let number = names.names.len() as u32;
+        names.numbers.insert(name, number);
+        number
+    }
+
+    /// The calls in progress, innermost first
+    pub fn snapshot(&self) -> Vec<InterpretedFrame> {
+        let depth = self.depth().min(MAX_SHADOW_DEPTH);
+        let entries: Vec<u64> = self.frames[..depth].iter().rev().map(|entry| entry.load(Ordering::Relaxed)).collect();
+        let names = self.names.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        entries
+            .into_iter()
+            .map(|entry| InterpretedFrame {
+                function: (entry >> 32)
+                    .checked_sub(1)
+                    .and_then(|index| names.names.get(index as usize))
+                    .cloned(),
+                line: entry as u32,
+            })
+            .collect()
+    }
+}
+
+impl Default for ShadowStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The interpreter thread's side of a `ShadowStack`
+pub struct ShadowWriter {
+    stack: Arc<ShadowStack>,
+    /// Address and length of the last function name seen, and its number:
+    /// the interpreter passes the same string for every statement of a
+    /// function, so only calls and returns look names up
+    function: (usize, usize),
+    number: u32,
+}
+
+impl ShadowWriter {
+    pub fn new(stack: Arc<ShadowStack>) -> Self {
+        ShadowWriter { stack, function: (0, 0), number: 0 }
+    }
+
+    pub fn stack(&self) -> &Arc<ShadowStack> {
+        &self.stack
+    }
+
+    /// A call starting with `depth` calls below it
+    pub fn enter(&mut self, depth: usize) {
+        if let Some(entry) = self.stack.frames.get(depth) {
+            entry.store(0, Ordering::Relaxed);
+        }
+        self.stack.depth.store(depth + 1, Ordering::Release);
+    }
+
+    /// Only the `depth` outermost calls are left; a larger `depth` (a
+    /// return a `longjmp` already unwound) changes nothing
+    pub fn truncate(&mut self, depth: usize) {
+        self.stack.depth.fetch_min(depth, Ordering::AcqRel);
+    }
+
+    /// The innermost call is at `line` of `function`
+    #[inline]
+    pub fn statement(&mut self, function: &str, line: u32) {
+        let key = (function.as_ptr() as usize, function.len());
+        if key != self.function {
+            self.number = self.stack.number(function);
+            self.function = key;
+        }
+        let depth = self.stack.depth.load(Ordering::Relaxed);
+        if let Some(entry) = depth.checked_sub(1).and_then(|top| self.stack.frames.get(top)) {
+            entry.store(((self.number as u64) << 32) | line as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeFrame {
+    pub address: usize,
+    pub symbol: Option<String>,
+    /// From the start of `symbol`
+    pub offset: usize,
+    /// Shared object or executable, or `<jit>`
+    pub object: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterpretedFrame {
+    /// `None` for a call that hasn't run its first statement yet
+    pub function: Option<Arc<str>>,
+    pub line: u32,
+}
+
+/// Innermost frame first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackTrace {
+    pub native: Vec<NativeFrame>,
+    pub interpreted: Vec<InterpretedFrame>,
+}
+
+impl fmt::Display for StackTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.interpreted.is_empty() {
+            writeln!(f, "Interpreted:")?;
+            for (index, frame) in self.interpreted.iter().enumerate() {
+                match &frame.function {
+                    Some(function) => writeln!(f, "  #{:<3} {} line {}", index, function, frame.line)?,
+                    None => writeln!(f, "  #{:<3} <entering>", index)?,
+                }
+            }
+        }
+        writeln!(f, "Native:")?;
+        for (index, frame) in self.native.iter().enumerate() {
+            write!(f, "  #{:<3} 0x{:016x}", index, frame.address)?;
+            if let Some(symbol) = &frame.symbol {
+                write!(f, " in {}+0x{:x}", symbol, frame.offset)?;
+            }
+            match &frame.object {
+                Some(object) => writeln!(f, " ({})", object)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum StackCaptureError {
+    /// Captures need Linux on x86-64 or AArch64
+    UnsupportedHost,
+    ThreadExited,
+    Timeout(Duration),
+    Host(std::io::Error),
+}
+
+impl fmt::Display for StackCaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackCaptureError::UnsupportedHost => write!(f, "stack capture is only supported on Linux x86-64 and AArch64"),
+            StackCaptureError::ThreadExited => write!(f, "the thread exited"),
+            StackCaptureError::Timeout(timeout) => write!(f, "the thread didn't answer the capture signal within {:?}", timeout),
+            StackCaptureError::Host(error) => write!(f, "stack capture failed: {}", error),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn serve(engine: &Engine, runtime: &mut CRuntimeEnvironment, watchdog: Sender<ThreadHandle>) -> Result<(), StackCaptureError> {
+    // On the tenant's thread, before running its program
+    let shadow = Arc::new(ShadowStack::new());
+    runtime.set_shadow_stack(Arc::clone(&shadow));
+    let handle = ThreadHandle::current()?.with_shadow_stack(shadow).with_jit_symbols(engine.jit_symbols());
+    watchdog.send(handle).unwrap();
+    // ... run the program ...
+    Ok(())
+}
+
+// On the watchdog thread, when a request has run too long
+fn report(handle: &ThreadHandle) {
+    match handle.capture(Duration::from_millis(100)) {
+        Ok(trace) => eprintln!("tenant is at:\n{}", trace),
+        Err(error) => eprintln!("no stack: {}", error),
+    }
+}
+*/
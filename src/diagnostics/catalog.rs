@@ -0,0 +1,235 @@
+// src/diagnostics/catalog.rs
+//! Localized diagnostic message catalog
+//! Every catalogued diagnostic has a stable identifier that tooling can rely
+//! on; only the human-readable text is translated. Messages take positional
+//! `{0}`, `{1}`, ... arguments so translations can reorder them.
+
+use std::fmt;
+use super::codes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Locale {
+    En,
+    Zh,
+    Es,
+}
+
+impl Locale {
+    /// Parse `zh`, `zh_CN.UTF-8`, `es-MX`, ...; unknown languages give `None`
+    pub fn parse(s: &str) -> Option<Self> {
+        let lang = s
+            .split(|c| c == '_' || c == '-' || c == '.' || c == '@')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        match lang.as_str() {
+            "en" | "c" | "posix" => Some(Locale::En),
+            "zh" => Some(Locale::Zh),
+            "es" => Some(Locale::Es),
+            _ => None,
+        }
+    }
+
+    /// Locale from the environment, following gettext's precedence
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::parse(&value))
+            .unwrap_or(Locale::En)
+    }
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Zh => "zh",
+            Locale::Es => "es",
+        }
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::En
+    }
+}
+
+/// Catalogued diagnostic messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageId {
+    // Severity labels
+    LabelError,
+    LabelWarning,
+    LabelNote,
+    LabelRemark,
+    LabelFatal,
+
+    // Driver and engine messages
+    ParseError,
+    RuntimeError,
+    TooManyErrors,
+    ErrorLimitHint,
+    InMacroExpansion,
+    FoldedMacroDiagnostics,
+    Summary,
+
+    // Input checks
+    CxxConstruct,
+    BlockLiteral,
+}
+
+impl MessageId {
+    /// Stable identifier, never translated
+    pub fn id(&self) -> &'static str {
+        match self {
+            MessageId::LabelError => "label-error",
+            MessageId::LabelWarning => "label-warning",
+            MessageId::LabelNote => "label-note",
+            MessageId::LabelRemark => "label-remark",
+            MessageId::LabelFatal => "label-fatal",
+            MessageId::ParseError => "parse-error",
+            MessageId::RuntimeError => "runtime-error",
+            MessageId::TooManyErrors => "too-many-errors",
+            MessageId::ErrorLimitHint => "error-limit-hint",
+            MessageId::InMacroExpansion => "in-macro-expansion",
+            MessageId::FoldedMacroDiagnostics => "folded-macro-diagnostics",
+            MessageId::Summary => "summary",
+            MessageId::CxxConstruct => "cxx-construct",
+            MessageId::BlockLiteral => "block-literal",
+        }
+    }
+
+    /// Stable diagnostic code (see `codes`) for messages that are diagnostics
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            MessageId::ParseError => Some(codes::E0001),
+            MessageId::TooManyErrors => Some(codes::E0014),
+            MessageId::RuntimeError => Some(codes::E0015),
+            MessageId::CxxConstruct => Some(codes::E0016),
+            MessageId::BlockLiteral => Some(codes::E0017),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+fn template(locale: Locale, id: MessageId) -> &'static str {
+    use MessageId::*;
+
+    match (locale, id) {
+        (Locale::En, LabelError) => "error",
+        (Locale::En, LabelWarning) => "warning",
+        (Locale::En, LabelNote) => "note",
+        (Locale::En, LabelRemark) => "remark",
+        (Locale::En, LabelFatal) => "fatal error",
+        (Locale::En, ParseError) => "parse error: {0}",
+        (Locale::En, RuntimeError) => "runtime error: {0}",
+        (Locale::En, TooManyErrors) => "too many errors emitted, stopping now",
+        (Locale::En, ErrorLimitHint) => "use --ferror-limit=0 to disable the limit (currently {0})",
+        (Locale::En, InMacroExpansion) => "in expansion of macro '{0}'",
+        (Locale::En, FoldedMacroDiagnostics) => "{0} similar diagnostic(s) in expansions of macro '{1}' not shown",
+        (Locale::En, Summary) => "{0} error(s), {1} warning(s) generated.",
+        (Locale::En, CxxConstruct) => "{0} is a C++ construct; only C is supported",
+        (Locale::En, BlockLiteral) => "block literals (Apple Blocks extension) are not supported",
+
+        (Locale::Zh, LabelError) => "错误",
+        (Locale::Zh, LabelWarning) => "警告",
+        (Locale::Zh, LabelNote) => "注",
+        (Locale::Zh, LabelRemark) => "备注",
+        (Locale::Zh, LabelFatal) => "致命错误",
+        (Locale::Zh, ParseError) => "解析错误：{0}",
+        (Locale::Zh, RuntimeError) => "运行时错误：{0}",
+        (Locale::Zh, TooManyErrors) => "错误过多，现在停止",
+        (Locale::Zh, ErrorLimitHint) => "使用 --ferror-limit=0 取消限制（当前为 {0}）",
+        (Locale::Zh, InMacroExpansion) => "在宏“{0}”的展开中",
+        (Locale::Zh, FoldedMacroDiagnostics) => "宏“{1}”展开中的 {0} 条类似诊断未显示",
+        (Locale::Zh, Summary) => "产生了 {0} 个错误，{1} 个警告。",
+        (Locale::Zh, CxxConstruct) => "{0} 是 C++ 语法；仅支持 C",
+        (Locale::Zh, BlockLiteral) => "不支持块字面量（Apple Blocks 扩展）",
+
+        (Locale::Es, LabelError) => "error",
+        (Locale::Es, LabelWarning) => "advertencia",
+        (Locale::Es, LabelNote) => "nota",
+        (Locale::Es, LabelRemark) => "observación",
+        (Locale::Es, LabelFatal) => "error fatal",
+        (Locale::Es, ParseError) => "error de análisis: {0}",
+        (Locale::Es, RuntimeError) => "error en tiempo de ejecución: {0}",
+        (Locale::Es, TooManyErrors) => "demasiados errores, deteniendo ahora",
+        (Locale::Es, ErrorLimitHint) => "use --ferror-limit=0 para desactivar el límite (actualmente {0})",
+        (Locale::Es, InMacroExpansion) => "en la expansión de la macro '{0}'",
+        (Locale::Es, FoldedMacroDiagnostics) => "{0} diagnóstico(s) similar(es) en expansiones de la macro '{1}' no mostrado(s)",
+        (Locale::Es, Summary) => "se generaron {0} error(es) y {1} advertencia(s).",
+        (Locale::Es, CxxConstruct) => "{0} es una construcción de C++; solo se admite C",
+        (Locale::Es, BlockLiteral) => "los literales de bloque (extensión Blocks de Apple) no son compatibles",
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Catalog {
+    locale: Locale,
+}
+
+impl Catalog {
+    pub fn new(locale: Locale) -> Self {
+        Catalog { locale }
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
+
+    /// The text of `id` with `{0}`, `{1}`, ... replaced by `args`, in one
+    /// left-to-right pass so braces in an argument are never substituted
+    /// themselves. A placeholder with no argument is kept as written.
+    pub fn format(&self, id: MessageId, args: &[String]) -> String {
+        let mut rest = template(self.locale, id);
+        let mut text = String::with_capacity(rest.len());
+        while let Some(open) = rest.find('{') {
+            text.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            let placeholder = after.find('}').and_then(|close| {
+                let index: usize = after[..close].parse().ok()?;
+                let arg = args.get(index)?;
+                Some((close, arg))
+            });
+            match placeholder {
+                Some((close, arg)) => {
+                    text.push_str(arg);
+                    rest = &after[close + 1..];
+                }
+                None => {
+                    text.push('{');
+                    rest = after;
+                }
+            }
+        }
+        text.push_str(rest);
+        text
+    }
+
+    pub fn text(&self, id: MessageId) -> String {
+        self.format(id, &[])
+    }
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Catalog::new(Locale::En)
+    }
+}
+
+// Example usage:
+/*
+fn main() {
+    let catalog = Catalog::new(Locale::parse("es_ES.UTF-8").unwrap_or_default());
+    let text = catalog.format(MessageId::ParseError, &["expected ';'".to_string()]);
+    eprintln!("{}: {} [{}]", catalog.text(MessageId::LabelError), text, MessageId::ParseError);
+}
+*/
@@ -0,0 +1,268 @@
+// src/diagnostics/codes.rs
+//! Stable diagnostic codes and their extended explanations
+//! Codes are never renumbered or reused; retired codes stay in the table
+//! with `retired: true`, and numbers skipped in the table were never
+//! assigned. The same metadata drives `c-interpreter explain`
+//! and the generated error reference in the documentation.
+
+#[derive(Debug, Clone, Copy)]
+pub struct CodeInfo {
+    /// Stable code, e.g. `E0001`
+    pub code: &'static str,
+    /// One-line summary
+    pub title: &'static str,
+    /// Frontend stage that emits the diagnostic
+    pub category: Category,
+    /// Extended description
+    pub description: &'static str,
+    /// Code that triggers the diagnostic
+    pub example: &'static str,
+    /// Corrected version of the example
+    pub fix: &'static str,
+    pub retired: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Driver,
+    Syntax,
+    Semantic,
+    Codegen,
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Driver => "driver",
+            Category::Syntax => "syntax",
+            Category::Semantic => "semantic",
+            Category::Codegen => "codegen",
+        }
+    }
+}
+
+pub const E0001: &str = "E0001";
+pub const E0014: &str = "E0014";
+pub const E0015: &str = "E0015";
+pub const E0016: &str = "E0016";
+pub const E0017: &str = "E0017";
+pub const E0018: &str = "E0018";
+pub const E0019: &str = "E0019";
+pub const E0020: &str = "E0020";
+pub const E0021: &str = "E0021";
+pub const E0022: &str = "E0022";
+pub const E0023: &str = "E0023";
+pub const E0024: &str = "E0024";
+pub const E0025: &str = "E0025";
+pub const E0026: &str = "E0026";
+
+pub static CODES: &[CodeInfo] = &[
+    CodeInfo {
+        code: E0001,
+        title: "syntax error",
+        category: Category::Syntax,
+        description: "The parser found a token it did not expect at this point. \
+            The location points at the first token that could not be parsed; the \
+            actual mistake is often just before it, such as a missing ';' or ')'.",
+        example: "int main(void) {\n    int x = 1\n    return x;\n}",
+        fix: "int main(void) {\n    int x = 1;\n    return x;\n}",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0014,
+        title: "too many errors",
+        category: Category::Driver,
+        description: "Compilation stopped after reaching the error limit. Fix the first \
+            errors, which often cause the rest, or raise the limit with \
+            --ferror-limit=N (0 disables it).",
+        example: "c-interpreter --ferror-limit=5 generated.c",
+        fix: "c-interpreter --ferror-limit=0 generated.c",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0015,
+        title: "runtime error in interpreted program",
+        category: Category::Driver,
+        description: "The interpreter stopped executing the program because of an \
+            unrecoverable runtime fault such as a null dereference or division by zero.",
+        example: "int main(void) {\n    int *p = 0;\n    return *p;\n}",
+        fix: "int main(void) {\n    int v = 0;\n    int *p = &v;\n    return *p;\n}",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0016,
+        title: "C++ construct in C source",
+        category: Category::Syntax,
+        description: "The source uses a construct that exists only in C++, such as a \
+            class, template, namespace or C++ standard header. Only C is supported; \
+            the file is rejected before parsing instead of producing a cascade of \
+            parse errors. Compile C++ sources with a C++ compiler.",
+        example: "#include <iostream>\n\nclass Counter {\npublic:\n    int value;\n};",
+        fix: "#include <stdio.h>\n\nstruct Counter {\n    int value;\n};",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0017,
+        title: "block literal",
+        category: Category::Syntax,
+        description: "The source creates an Apple Blocks closure with '^'. Block \
+            pointer types in declarations (as in the macOS SDK headers) are accepted \
+            as opaque pointers, but creating or calling a block needs the Blocks \
+            runtime, which is not supported. Use a function pointer and, where the \
+            API has one, the non-block variant of the function.",
+        example: "qsort_b(v, n, sizeof *v, ^(const void *a, const void *b) {\n    return *(const int *)a - *(const int *)b;\n});",
+        fix: "static int cmp(const void *a, const void *b) {\n    return *(const int *)a - *(const int *)b;\n}\n\nqsort(v, n, sizeof *v, cmp);",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0018,
+        title: "semantic error",
+        category: Category::Semantic,
+        description: "The program parses but breaks a rule of C: it uses an undeclared \
+            identifier, gives an operator operands of the wrong type, has a _Generic \
+            selection no association matches, declares `auto` without an initializer, \
+            jumps into the scope of a variable length array, and so on. The message \
+            names the rule and the line.",
+        example: "int main(void) {\n    return count;\n}",
+        fix: "int main(void) {\n    int count = 0;\n    return count;\n}",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0019,
+        title: "invalid inline assembly statement",
+        category: Category::Syntax,
+        description: "An `asm` statement is malformed: a string is unterminated, a section \
+            is missing, it has labels without `goto`, or its template refers to an \
+            operand that doesn't exist, by number or by `[name]`.",
+        example: "asm(\"movl %2, %0\" : \"=r\"(x) : \"r\"(y));",
+        fix: "asm(\"movl %1, %0\" : \"=r\"(x) : \"r\"(y));",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0020,
+        title: "invalid asm constraint or clobber",
+        category: Category::Semantic,
+        description: "An `asm` operand's constraint isn't one the target has, an output \
+            lacks its '=' or '+', an input is tied to an output that can't take it, or \
+            a clobber names an unknown register or the stack or frame pointer, which \
+            the compiler can't give up.",
+        example: "asm volatile(\"call helper\" : : : \"rsp\");",
+        fix: "asm volatile(\"call helper\" : : : \"memory\");",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0021,
+        title: "asm operands don't fit in the registers",
+        category: Category::Codegen,
+        description: "The operands of an `asm` statement that are live at the same time \
+            need more registers of a class than are left after clobbers and pinned \
+            operands, or two of them are pinned to the same register. Let some \
+            operands live in memory (\"m\"), clobber fewer registers, or split the \
+            statement.",
+        example: "asm(\"addl %1, %0\" : \"=a\"(x) : \"a\"(y), \"a\"(z));",
+        fix: "asm(\"addl %2, %0\" : \"=a\"(x) : \"0\"(y), \"r\"(z));",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0022,
+        title: "unsupported target",
+        category: Category::Driver,
+        description: "The target triple or architecture given with -a or --target isn't one \
+            the compiler can generate code for, or LLVM couldn't set up a code \
+            generator for it.",
+        example: "c-interpreter compile -a sparc64 prog.c",
+        fix: "c-interpreter compile -a x86_64 prog.c",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0023,
+        title: "link failure",
+        category: Category::Driver,
+        description: "Linking the compiled objects failed: a symbol is undefined or defined \
+            twice, a library wasn't found, or the system linker reported an error, \
+            which follows the message.",
+        example: "int helper(void);\n\nint main(void) {\n    return helper();\n}",
+        fix: "int helper(void) {\n    return 0;\n}\n\nint main(void) {\n    return helper();\n}",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0024,
+        title: "internal compiler error",
+        category: Category::Codegen,
+        description: "A stage after the frontend (optimization, instrumentation, code \
+            generation, an analysis) failed on a program the frontend accepted. This is \
+            a bug in the compiler; please report it with the program. Until it is \
+            fixed, the interpreter may still run the program.",
+        example: "c-interpreter compile prog.c",
+        fix: "c-interpreter -i prog.c",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0025,
+        title: "compiled with the host toolchain",
+        category: Category::Driver,
+        description: "A warning: the file uses something the built-in compiler doesn't \
+            support, so it was compiled with the host C compiler instead, as \
+            `on-unsupported` in c-interpreter.toml allows. The message says what \
+            wasn't supported. Without `on-unsupported`, such a file is an error.",
+        example: "[fallback]\non-unsupported = true",
+        fix: "[fallback]\non-unsupported = false",
+        retired: false,
+    },
+    CodeInfo {
+        code: E0026,
+        title: "file or configuration error",
+        category: Category::Driver,
+        description: "A source file couldn't be read, an output couldn't be written, or \
+            c-interpreter.toml isn't valid. The message names the file and what the \
+            system reported.",
+        example: "c-interpreter compile missing.c",
+        fix: "c-interpreter compile src/main.c",
+        retired: false,
+    },
+];
+
+/// Look up a code; accepts `E0042`, `e0042` and `42`
+pub fn lookup(code: &str) -> Option<&'static CodeInfo> {
+    let digits = code.trim().trim_start_matches(|c| c == 'E' || c == 'e');
+    let number: u32 = digits.parse().ok()?;
+    let normalized = format!("E{:04}", number);
+    CODES.iter().find(|info| info.code == normalized)
+}
+
+/// Text printed by `c-interpreter explain`
+pub fn explain(info: &CodeInfo) -> String {
+    let mut out = format!("{}: {}\n\n{}\n", info.code, info.title, info.description);
+    if info.retired {
+        out.push_str("\nThis diagnostic is no longer emitted.\n");
+    }
+    out.push_str(&format!("\nErroneous code example:\n\n{}\n", indent(info.example)));
+    out.push_str(&format!("\nCorrected:\n\n{}\n", indent(info.fix)));
+    out
+}
+
+/// Markdown reference of every code, consumed by the docs generator
+pub fn markdown_reference() -> String {
+    let mut out = String::from("# Diagnostic reference\n\n");
+    for info in CODES {
+        out.push_str(&format!("## {}: {}\n\n", info.code, info.title));
+        out.push_str(&format!("*Category: {}*\n\n{}\n\n", info.category.name(), info.description));
+        out.push_str(&format!("```c\n{}\n```\n\nCorrected:\n\n```c\n{}\n```\n\n", info.example, info.fix));
+    }
+    out
+}
+
+fn indent(code: &str) -> String {
+    code.lines().map(|line| format!("    {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+// Example usage:
+/*
+fn main() {
+    if let Some(info) = lookup("E0016") {
+        print!("{}", explain(info));
+    }
+
+    std::fs::write("docs/diagnostics.md", markdown_reference()).unwrap();
+}
+*/
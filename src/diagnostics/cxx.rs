@@ -0,0 +1,375 @@
+// src/diagnostics/cxx.rs
+//! Detection of C++ sources fed to the C frontend
+//! A C++ file produces a cascade of unrelated parse errors. Before parsing,
+//! the source is scanned for constructs that only exist in C++, and the
+//! driver reports those instead. The checks avoid valid C that shares
+//! spelling with C++: `class`, `new` and `this` are ordinary C identifiers,
+//! `::` is allowed inside C23 `[[vendor::attr]]` attributes, and `//`
+//! comments, `bool`, `nullptr` and `constexpr` are all C. Code that only a
+//! C++ compiler sees (`#ifdef __cplusplus`, the `#else` of `#ifndef
+//! __cplusplus`) is left out, so C headers that also serve C++ pass.
+
+use std::fmt;
+use super::catalog::MessageId;
+use super::engine::{Diagnostic, Severity, SourceLocation};
+
+/// Headers from the C++ standard library (no `.h`, not in C)
+const CXX_HEADERS: &[&str] = &[
+    "iostream", "istream", "ostream", "sstream", "fstream", "iomanip",
+    "string", "vector", "map", "set", "unordered_map", "unordered_set",
+    "list", "deque", "queue", "stack", "array", "algorithm", "memory",
+    "functional", "utility", "tuple", "optional", "variant", "thread",
+    "mutex", "chrono", "exception", "stdexcept", "cstdio", "cstdlib",
+    "cstring", "cmath", "cstdint", "cassert",
+];
+
+/// Stop scanning after this many findings; the first few make the point
+const MAX_FINDINGS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CxxKind {
+    ClassDefinition(String),
+    Template,
+    Namespace,
+    UsingNamespace,
+    ScopeResolution(String),
+    StandardHeader(String),
+    ExternCxx,
+    TryCatch,
+    OperatorOverload,
+    AccessSpecifier(String),
+}
+
+impl fmt::Display for CxxKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CxxKind::ClassDefinition(name) => write!(f, "class definition 'class {}'", name),
+            CxxKind::Template => write!(f, "template declaration"),
+            CxxKind::Namespace => write!(f, "namespace"),
+            CxxKind::UsingNamespace => write!(f, "using-directive 'using namespace'"),
+            CxxKind::ScopeResolution(name) => write!(f, "scope resolution '{}::'", name),
+            CxxKind::StandardHeader(name) => write!(f, "C++ standard header <{}>", name),
+            CxxKind::ExternCxx => write!(f, "extern \"C++\" linkage"),
+            CxxKind::TryCatch => write!(f, "try/catch exception handling"),
+            CxxKind::OperatorOverload => write!(f, "operator overload"),
+            CxxKind::AccessSpecifier(name) => write!(f, "access specifier '{}:'", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CxxConstruct {
+    pub kind: CxxKind,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Punct(&'static str),
+    Str(String),
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: u32,
+    column: u32,
+}
+
+/// Scan `source` for C++-only constructs
+pub fn detect(source: &str) -> Vec<CxxConstruct> {
+    let mut found = Vec::new();
+    let source = &without_cxx_regions(source);
+
+    // Preprocessor: #include <iostream> and friends
+    for (index, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(rest) = trimmed.strip_prefix('#') {
+            let rest = rest.trim_start();
+            if let Some(target) = rest.strip_prefix("include") {
+                let target = target.trim();
+                if let Some(name) = target.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+                    if CXX_HEADERS.contains(&name) {
+                        found.push(CxxConstruct {
+                            kind: CxxKind::StandardHeader(name.to_string()),
+                            line: index as u32 + 1,
+                            column: (line.len() - trimmed.len()) as u32 + 1,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    let tokens = tokenize(source);
+    let ident = |i: usize| match tokens.get(i).map(|t| &t.kind) {
+        Some(TokenKind::Ident(name)) => Some(name.as_str()),
+        _ => None,
+    };
+    let punct = |i: usize, p: &str| matches!(tokens.get(i).map(|t| &t.kind), Some(TokenKind::Punct(q)) if *q == p);
+
+    // Depth inside C23 `[[ ... ]]` attributes, where `::` is legal
+    let mut attribute_depth = 0;
+    let mut in_class = false;
+
+    for i in 0..tokens.len() {
+        if found.len() >= MAX_FINDINGS {
+            break;
+        }
+        if punct(i, "[") && punct(i + 1, "[") {
+            attribute_depth += 1;
+        }
+        if punct(i, "]") && punct(i + 1, "]") && attribute_depth > 0 {
+            attribute_depth -= 1;
+        }
+
+        let kind = match ident(i) {
+            // `class Foo {`, `class Foo : public Bar`, `class Foo final`
+            Some("class") => match ident(i + 1) {
+                Some(name) if punct(i + 2, "{") || punct(i + 2, ":") || ident(i + 2) == Some("final") => {
+                    in_class = true;
+                    Some(CxxKind::ClassDefinition(name.to_string()))
+                }
+                _ => None,
+            },
+            // `template <typename T>`, `template <>`, `template <int N>`; not
+            // `template < limit` with `template` a C variable
+            Some("template") if punct(i + 1, "<") && is_parameter_list(&tokens, i + 2) => Some(CxxKind::Template),
+            Some("namespace") if i > 0 && ident(i - 1) == Some("using") => Some(CxxKind::UsingNamespace),
+            Some("namespace") if punct(i + 1, "{") || (ident(i + 1).is_some() && (punct(i + 2, "{") || punct(i + 2, "="))) => {
+                Some(CxxKind::Namespace)
+            }
+            Some("extern") => match tokens.get(i + 1).map(|t| &t.kind) {
+                Some(TokenKind::Str(s)) if s == "C++" => Some(CxxKind::ExternCxx),
+                _ => None,
+            },
+            Some("try") if punct(i + 1, "{") => Some(CxxKind::TryCatch),
+            Some("operator") if is_overloadable(&tokens, i + 1) => Some(CxxKind::OperatorOverload),
+            Some(name @ ("public" | "private" | "protected")) if in_class && punct(i + 1, ":") => {
+                Some(CxxKind::AccessSpecifier(name.to_string()))
+            }
+            Some(name) if attribute_depth == 0 && punct(i + 1, "::") => {
+                Some(CxxKind::ScopeResolution(name.to_string()))
+            }
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            // One report per kind is enough
+            if !found.iter().any(|c: &CxxConstruct| same_kind(&c.kind, &kind)) {
+                found.push(CxxConstruct { kind, line: tokens[i].line, column: tokens[i].column });
+            }
+        }
+    }
+
+    found.sort_by_key(|c| (c.line, c.column));
+    found.truncate(MAX_FINDINGS);
+    found
+}
+
+fn same_kind(a: &CxxKind, b: &CxxKind) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+/// Whether `tokens[i..]` starts a template parameter list after its `<`
+fn is_parameter_list(tokens: &[Token], i: usize) -> bool {
+    let kind = |i: usize| tokens.get(i).map(|t| &t.kind);
+    match (kind(i), kind(i + 1), kind(i + 2)) {
+        (Some(TokenKind::Punct(">")), ..) => true,
+        // `typename T`, `class T`, `typename = void`
+        (Some(TokenKind::Ident(name)), ..) if name == "typename" || name == "class" => true,
+        // `int N`
+        (Some(TokenKind::Ident(_)), Some(TokenKind::Ident(_)), Some(TokenKind::Punct(">" | "," | "="))) => true,
+        _ => false,
+    }
+}
+
+/// `source` with the lines only a C++ compiler sees blanked out, keeping
+/// the line and column of everything else
+fn without_cxx_regions(source: &str) -> String {
+    // Per open conditional: whether its current branch is C++-only, and
+    // which branch is (true: the `#if` one, false: the `#else` one)
+    let mut stack: Vec<(bool, Option<bool>)> = Vec::new();
+    let mut out = String::with_capacity(source.len());
+    let mut continued = false;
+
+    for line in source.split_inclusive('\n') {
+        let skipped_before = stack.iter().any(|&(skipped, _)| skipped);
+        let directive = if continued { None } else { line.trim_start().strip_prefix('#').map(str::trim_start) };
+        if let Some(directive) = directive {
+            let word_end = directive.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(directive.len());
+            let (word, condition) = directive.split_at(word_end);
+            match word {
+                "if" | "ifdef" | "ifndef" => {
+                    let cxx_branch = condition.contains("__cplusplus").then(|| {
+                        let negated = word == "ifndef" || condition.trim_start().starts_with('!');
+                        !negated
+                    });
+                    stack.push((cxx_branch == Some(true), cxx_branch));
+                }
+                "elif" | "elifdef" | "elifndef" | "else" => {
+                    // After `#ifndef __cplusplus`, every later branch is C++
+                    if let Some(top) = stack.last_mut() {
+                        top.0 = top.1 == Some(false);
+                    }
+                }
+                "endif" => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+        if skipped_before || stack.iter().any(|&(skipped, _)| skipped) {
+            out.extend(line.chars().map(|c| if c == '\n' { '\n' } else { ' ' }));
+        } else {
+            out.push_str(line);
+        }
+        continued = line.trim_end_matches(['\n', '\r']).ends_with('\\');
+    }
+    out
+}
+
+/// `operator+(`, `operator==(`, `operator[](`, `operator()(`
+fn is_overloadable(tokens: &[Token], i: usize) -> bool {
+    match tokens.get(i).map(|t| &t.kind) {
+        Some(TokenKind::Punct(p)) => {
+            let next = tokens.get(i + 1).map(|t| &t.kind);
+            matches!(next, Some(TokenKind::Punct("("))) || (*p == "(" && matches!(next, Some(TokenKind::Punct(")"))))
+                || (*p == "[" && matches!(next, Some(TokenKind::Punct("]"))))
+        }
+        _ => false,
+    }
+}
+
+/// Diagnostics for the driver; the first one carries the summary note
+pub fn diagnostics(file: &str, found: &[CxxConstruct]) -> Vec<Diagnostic> {
+    let mut out: Vec<Diagnostic> = found
+        .iter()
+        .map(|construct| {
+            Diagnostic::catalogued(Severity::Error, MessageId::CxxConstruct, vec![construct.kind.to_string()])
+                .at(SourceLocation {
+                    file: file.to_string(),
+                    line: construct.line,
+                    column: construct.column,
+                })
+        })
+        .collect();
+
+    if let Some(first) = out.first_mut() {
+        first.notes.push(
+            "this looks like C++ source; only C is supported. Compile it with a C++ compiler such as clang++ or g++"
+                .to_string(),
+        );
+    }
+    out
+}
+
+/// Whether the file name says C++ regardless of contents
+pub fn has_cxx_extension(path: &str) -> bool {
+    let extension = path.rsplit('.').next().unwrap_or("");
+    path.contains('.') && matches!(extension, "cpp" | "cc" | "cxx" | "c++" | "C" | "hpp" | "hh" | "hxx")
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    const PUNCTS: &[&str] = &["::", "==", "!=", "<=", ">=", "->", "++", "--", "<<", ">>",
+        "{", "}", "(", ")", "[", "]", "<", ">", ":", ";", ",", "=", "+", "-", "*", "/",
+        "%", "&", "|", "^", "!", "~", "?", ".", "#"];
+
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut column) = (0, 1u32, 1u32);
+    let mut at_line_start = true;
+
+    // Advance over `n` chars, tracking line and column
+    let advance = |i: &mut usize, line: &mut u32, column: &mut u32, n: usize| {
+        for _ in 0..n {
+            if chars.get(*i) == Some(&'\n') {
+                *line += 1;
+                *column = 1;
+            } else {
+                *column += 1;
+            }
+            *i += 1;
+        }
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (start_line, start_column) = (line, column);
+
+        if c == '\n' {
+            at_line_start = true;
+            advance(&mut i, &mut line, &mut column, 1);
+        } else if c.is_whitespace() {
+            advance(&mut i, &mut line, &mut column, 1);
+        } else if c == '#' && at_line_start {
+            // Skip directives, honouring line continuations
+            while i < chars.len() && !(chars[i] == '\n' && chars.get(i.wrapping_sub(1)) != Some(&'\\')) {
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            advance(&mut i, &mut line, &mut column, 2);
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+            let rest = 2.min(chars.len() - i);
+            advance(&mut i, &mut line, &mut column, rest);
+        } else if c == '"' || c == '\'' {
+            let mut text = String::new();
+            advance(&mut i, &mut line, &mut column, 1);
+            while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                if chars[i] == '\\' {
+                    advance(&mut i, &mut line, &mut column, 1);
+                }
+                if i < chars.len() {
+                    text.push(chars[i]);
+                    advance(&mut i, &mut line, &mut column, 1);
+                }
+            }
+            let rest = 1.min(chars.len() - i);
+            advance(&mut i, &mut line, &mut column, rest);
+            let kind = if c == '"' { TokenKind::Str(text) } else { TokenKind::Other };
+            tokens.push(Token { kind, line: start_line, column: start_column });
+            at_line_start = false;
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                name.push(chars[i]);
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+            tokens.push(Token { kind: TokenKind::Ident(name), line: start_line, column: start_column });
+            at_line_start = false;
+        } else {
+            let rest: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let punct = PUNCTS.iter().find(|p| rest.starts_with(**p));
+            let (kind, len) = match punct {
+                Some(p) => (TokenKind::Punct(p), p.len()),
+                None => (TokenKind::Other, 1),
+            };
+            advance(&mut i, &mut line, &mut column, len);
+            tokens.push(Token { kind, line: start_line, column: start_column });
+            at_line_start = false;
+        }
+    }
+    tokens
+}
+
+// Example usage:
+/*
+fn main() {
+    let source = "#include <iostream>\nclass Point { public: int x; };\n";
+    let found = detect(source);
+    for diagnostic in diagnostics("point.c", &found) {
+        eprintln!("{}", diagnostic);
+    }
+    // point.c:1:1: error[E0016]: C++ standard header <iostream> is a C++ construct; only C is supported
+    // note: this looks like C++ source; ...
+}
+*/
@@ -0,0 +1,355 @@
+// src/diagnostics/engine.rs
+//! Diagnostic collection with error limits and note folding
+//! Pathological inputs (fuzzers, generated code, runaway macro expansions)
+//! can produce unbounded diagnostics. The engine caps the number of errors,
+//! folds repeated diagnostics from the same macro expansion into a single
+//! note, and tells the frontend when to stop.
+
+use std::collections::HashMap;
+use std::fmt;
+use super::catalog::{Catalog, Locale, MessageId};
+use crate::driver::usage;
+use crate::report;
+
+/// Default matching clang's -ferror-limit
+pub const DEFAULT_ERROR_LIMIT: usize = 20;
+
+/// Repeats of the same diagnostic in one macro expansion shown before folding
+const FOLD_AFTER: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// What an optimization did or didn't do, for `-Rpass`
+    Remark,
+    Note,
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl Severity {
+    fn label(&self) -> MessageId {
+        match self {
+            Severity::Remark => MessageId::LabelRemark,
+            Severity::Note => MessageId::LabelNote,
+            Severity::Warning => MessageId::LabelWarning,
+            Severity::Error => MessageId::LabelError,
+            Severity::Fatal => MessageId::LabelFatal,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Catalog::default().text(self.label()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// English text; used verbatim when the diagnostic is not catalogued
+    pub message: String,
+    /// Stable code such as `E0001`, see `codes`
+    pub code: Option<&'static str>,
+    /// Catalog entry and arguments used to localize `message`
+    pub id: Option<MessageId>,
+    pub args: Vec<String>,
+    pub location: Option<SourceLocation>,
+    /// Name of the macro whose expansion produced this diagnostic
+    pub macro_expansion: Option<String>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            code: None,
+            id: None,
+            args: Vec::new(),
+            location: None,
+            macro_expansion: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// A diagnostic whose text comes from the message catalog
+    pub fn catalogued(severity: Severity, id: MessageId, args: Vec<String>) -> Self {
+        Diagnostic {
+            severity,
+            message: Catalog::default().format(id, &args),
+            code: id.code(),
+            id: Some(id),
+            args,
+            ..Diagnostic::error("")
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message)
+        }
+    }
+
+    pub fn remark(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Remark,
+            ..Diagnostic::error(message)
+        }
+    }
+
+    /// A stable code from `codes`, for a message that isn't catalogued
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn at(mut self, location: SourceLocation) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn in_macro(mut self, name: impl Into<String>) -> Self {
+        self.macro_expansion = Some(name.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Render in the catalog's language
+    pub fn render(&self, catalog: &Catalog) -> String {
+        let mut out = String::new();
+        if let Some(loc) = &self.location {
+            out.push_str(&format!("{}:{}:{}: ", loc.file, loc.line, loc.column));
+        }
+
+        let message = match self.id {
+            Some(id) => catalog.format(id, &self.args),
+            None => self.message.clone(),
+        };
+        let label = catalog.text(self.severity.label());
+        match self.code {
+            Some(code) => out.push_str(&format!("{}[{}]: {}", label, code, message)),
+            None => out.push_str(&format!("{}: {}", label, message)),
+        }
+
+        let note = catalog.text(MessageId::LabelNote);
+        if let Some(name) = &self.macro_expansion {
+            let text = catalog.format(MessageId::InMacroExpansion, &[name.clone()]);
+            out.push_str(&format!("\n{}: {}", note, text));
+        }
+        for text in &self.notes {
+            out.push_str(&format!("\n{}: {}", note, text));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(&Catalog::default()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticsConfig {
+    /// Stop after this many errors; 0 means unlimited
+    pub error_limit: usize,
+    /// Treat warnings as errors
+    pub warnings_as_errors: bool,
+    /// Language for human-readable text
+    pub locale: Locale,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        DiagnosticsConfig {
+            error_limit: DEFAULT_ERROR_LIMIT,
+            warnings_as_errors: false,
+            locale: Locale::from_env(),
+        }
+    }
+}
+
+/// Whether the caller may keep going after reporting a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    Continue,
+    Stop,
+}
+
+pub struct DiagnosticsEngine {
+    // Limits
+    config: DiagnosticsConfig,
+
+    // Localized text
+    catalog: Catalog,
+
+    // Emitted diagnostics, in order
+    emitted: Vec<Diagnostic>,
+
+    // Counters
+    error_count: usize,
+    warning_count: usize,
+
+    // Repeats per (macro, message) and how many were folded away
+    macro_repeats: HashMap<(String, String), usize>,
+    folded: HashMap<String, usize>,
+
+    // Set once the error limit has been hit
+    stopped: bool,
+}
+
+impl DiagnosticsEngine {
+    pub fn new(config: DiagnosticsConfig) -> Self {
+        DiagnosticsEngine {
+            catalog: Catalog::new(config.locale),
+            config,
+            emitted: Vec::new(),
+            error_count: 0,
+            warning_count: 0,
+            macro_repeats: HashMap::new(),
+            folded: HashMap::new(),
+            stopped: false,
+        }
+    }
+
+    /// Report a diagnostic. Returns `Recovery::Stop` once the error limit is hit.
+    pub fn report(&mut self, mut diagnostic: Diagnostic) -> Recovery {
+        if self.stopped {
+            return Recovery::Stop;
+        }
+
+        if diagnostic.severity == Severity::Warning && self.config.warnings_as_errors {
+            diagnostic.severity = Severity::Error;
+        }
+
+        // Fold repeated diagnostics coming out of the same macro
+        if let Some(name) = &diagnostic.macro_expansion {
+            let key = (name.clone(), diagnostic.message.clone());
+            let repeats = self.macro_repeats.entry(key).or_insert(0);
+            *repeats += 1;
+            if *repeats > FOLD_AFTER {
+                *self.folded.entry(name.clone()).or_insert(0) += 1;
+                return self.count(diagnostic.severity);
+            }
+        }
+
+        let severity = diagnostic.severity;
+        self.emitted.push(diagnostic);
+        self.count(severity)
+    }
+
+    fn count(&mut self, severity: Severity) -> Recovery {
+        match severity {
+            Severity::Fatal => {
+                self.error_count += 1;
+                self.stopped = true;
+                return Recovery::Stop;
+            }
+            Severity::Error => self.error_count += 1,
+            Severity::Warning => self.warning_count += 1,
+            Severity::Remark | Severity::Note => {}
+        }
+
+        if self.config.error_limit != 0 && self.error_count >= self.config.error_limit {
+            self.stopped = true;
+            let hint = self.catalog.format(
+                MessageId::ErrorLimitHint,
+                &[self.config.error_limit.to_string()],
+            );
+            self.emitted.push(
+                Diagnostic::catalogued(Severity::Fatal, MessageId::TooManyErrors, Vec::new())
+                    .with_note(hint),
+            );
+            return Recovery::Stop;
+        }
+
+        Recovery::Continue
+    }
+
+    pub fn should_stop(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count > 0
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.error_count
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.warning_count
+    }
+
+    /// Diagnostics to display, with a summary note for each folded macro
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut out = self.emitted.clone();
+
+        let mut folded: Vec<_> = self.folded.iter().collect();
+        folded.sort();
+        for (name, count) in folded {
+            out.push(Diagnostic::catalogued(
+                Severity::Note,
+                MessageId::FoldedMacroDiagnostics,
+                vec![count.to_string(), name.clone()],
+            ));
+        }
+
+        out
+    }
+
+    /// Print everything to stderr followed by an error/warning summary
+    pub fn flush_to_stderr(&self) {
+        for diagnostic in self.diagnostics() {
+            eprintln!("{}", diagnostic.render(&self.catalog));
+            report::note_diagnostic(&diagnostic);
+            if diagnostic.severity >= Severity::Error {
+                usage::note_error(diagnostic.code.unwrap_or(usage::UNCODED));
+            }
+        }
+        if self.error_count > 0 || self.warning_count > 0 {
+            eprintln!("{}", self.catalog.format(
+                MessageId::Summary,
+                &[self.error_count.to_string(), self.warning_count.to_string()],
+            ));
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() {
+    let mut diags = DiagnosticsEngine::new(DiagnosticsConfig {
+        error_limit: 5,
+        warnings_as_errors: false,
+        locale: Locale::parse("zh_CN.UTF-8").unwrap_or_default(),
+    });
+
+    for token in tokens {
+        if let Err(e) = parse(token) {
+            let d = Diagnostic::error(e.message).at(e.location).in_macro("CHECK");
+            if diags.report(d) == Recovery::Stop {
+                break;
+            }
+        }
+    }
+
+    diags.flush_to_stderr();
+}
+*/
@@ -0,0 +1,8 @@
+// src/diagnostics/mod.rs
+//! Diagnostic reporting for the C frontend
+
+pub mod c23;
+pub mod catalog;
+pub mod codes;
+pub mod cxx;
+pub mod engine;
@@ -24,6 +24,16 @@ impl DocumentationGenerator {
         // Generate usage documentation
         self.generate_usage_docs().await?;
         
+        // Generate the diagnostic code reference
+        self.generate_diagnostic_reference()?;
+        
         Ok(())
     }
+
+    /// Write the error code reference from the same metadata `explain` uses
+    fn generate_diagnostic_reference(&self) -> Result<(), DocError> {
+        let path = self.config.output_dir.join("diagnostics.md");
+        std::fs::write(path, crate::diagnostics::codes::markdown_reference())
+            .map_err(DocError::Io)
+    }
 } 
@@ -0,0 +1,619 @@
+// src/driver/batch.rs
+//! Batch execution of independent programs
+//! `c-interpreter batch jobs.json` runs many small programs against their
+//! inputs, e.g. grading submissions or triaging fuzzer crashes. The shared
+//! prelude is compiled once in this process before anything forks, so every
+//! job starts with LLVM and the common headers warm. Each job then runs in
+//! its own forked child: it compiles, applies its resource limits, and runs
+//! `main` with stdout and stderr captured. Up to `--jobs` children run at
+//! once, and results come back in job file order whatever finished first.
+//!
+//! With `--live-output` each job's output is also echoed as it arrives,
+//! through an `OutputMultiplexer` that tags whole lines with the job name.
+//!
+//! Limits are applied after compilation, so they bound the program and not
+//! the compiler. Wall-clock time and output size are enforced here; memory,
+//! CPU time, file size and open files are rlimits in the child.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::runtime::output_mux::{OutputHandle, OutputMultiplexer, OutputStream};
+
+/// Signature of the compiled program's `main`
+pub type ProgramMain = extern "C" fn(i32, *const *const i8) -> i32;
+
+/// Compilation isn't covered by a job's limits, but shouldn't hang the batch
+const COMPILE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest the event loop sleeps before rechecking deadlines
+const POLL_INTERVAL_MS: i32 = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchFile {
+    /// C text placed before every job's source, typically `#include`s and
+    /// helpers shared by all jobs
+    #[serde(default)]
+    pub prelude: String,
+    /// Defaults for every job
+    #[serde(default)]
+    pub limits: JobLimits,
+    pub jobs: Vec<BatchJob>,
+}
+
+impl BatchFile {
+    pub fn load(path: &Path) -> Result<Self, BatchError> {
+        let text = fs::read_to_string(path).map_err(|e| BatchError::Io(path.to_path_buf(), e))?;
+        let batch: BatchFile = serde_json::from_str(&text).map_err(|e| BatchError::Parse(e.to_string()))?;
+        for job in &batch.jobs {
+            if job.source.is_some() == job.code.is_some() {
+                return Err(BatchError::Source(job.name.clone()));
+            }
+        }
+        Ok(batch)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchJob {
+    pub name: String,
+    /// Source file, relative to the job file; exactly one of this and `code`
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    #[serde(default)]
+    pub code: Option<String>,
+    /// Program input; `/dev/null` when neither is given
+    #[serde(default)]
+    pub stdin: Option<String>,
+    #[serde(default)]
+    pub stdin_file: Option<PathBuf>,
+    /// `argv[1..]`; `argv[0]` is the job name
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Compared ignoring trailing whitespace
+    #[serde(default)]
+    pub expected_stdout: Option<String>,
+    #[serde(default)]
+    pub expected_exit: Option<i32>,
+    #[serde(default)]
+    pub limits: LimitOverrides,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobLimits {
+    /// Wall-clock time for running, not compiling
+    pub timeout_ms: u64,
+    /// Captured bytes per stream; the job is killed past this
+    pub output_bytes: usize,
+    pub cpu_secs: Option<u64>,
+    /// Address space, which includes the JIT's code and stack
+    pub memory_bytes: Option<u64>,
+    pub file_size_bytes: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl Default for JobLimits {
+    fn default() -> Self {
+        JobLimits {
+            timeout_ms: 10_000,
+            output_bytes: 1024 * 1024,
+            cpu_secs: None,
+            memory_bytes: None,
+            file_size_bytes: None,
+            open_files: None,
+        }
+    }
+}
+
+/// Per-job limits; anything unset comes from the batch defaults
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitOverrides {
+    pub timeout_ms: Option<u64>,
+    pub output_bytes: Option<usize>,
+    pub cpu_secs: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub file_size_bytes: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl LimitOverrides {
+    pub fn apply(&self, base: JobLimits) -> JobLimits {
+        JobLimits {
+            timeout_ms: self.timeout_ms.unwrap_or(base.timeout_ms),
+            output_bytes: self.output_bytes.unwrap_or(base.output_bytes),
+            cpu_secs: self.cpu_secs.or(base.cpu_secs),
+            memory_bytes: self.memory_bytes.or(base.memory_bytes),
+            file_size_bytes: self.file_size_bytes.or(base.file_size_bytes),
+            open_files: self.open_files.or(base.open_files),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Passed,
+    WrongOutput,
+    WrongExit,
+    CompileError,
+    /// Wall-clock or CPU limit
+    Timeout,
+    /// Killed by a signal other than the CPU limit
+    Crashed,
+    OutputLimit,
+    /// The job couldn't be started, e.g. a missing source file
+    SetupError,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobResult {
+    pub name: String,
+    pub status: JobStatus,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub output_truncated: bool,
+    pub compile_ms: u64,
+    pub run_ms: u64,
+    pub cpu_ms: u64,
+    pub max_rss_kb: u64,
+}
+
+impl JobResult {
+    fn setup_error(name: &str, message: String) -> Self {
+        JobResult {
+            name: name.to_string(),
+            status: JobStatus::SetupError,
+            exit_code: None,
+            signal: None,
+            stdout: String::new(),
+            stderr: message,
+            output_truncated: false,
+            compile_ms: 0,
+            run_ms: 0,
+            cpu_ms: 0,
+            max_rss_kb: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    pub total: usize,
+    pub passed: usize,
+    pub elapsed_ms: u64,
+    /// In job file order
+    pub jobs: Vec<JobResult>,
+}
+
+impl BatchReport {
+    pub fn write_to(&self, path: &Path) -> Result<(), BatchError> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| BatchError::Parse(e.to_string()))?;
+        fs::write(path, json).map_err(|e| BatchError::Io(path.to_path_buf(), e))
+    }
+}
+
+pub struct BatchRunner<'a> {
+    /// Jobs running at once
+    parallel: usize,
+    /// Where relative `source` and `stdin_file` paths start
+    base_dir: PathBuf,
+    /// Runs in the job's child; may print to stderr and exit on errors
+    compile: &'a dyn Fn(&str) -> ProgramMain,
+    /// Echoes job output while it runs
+    live: Option<&'a OutputMultiplexer>,
+}
+
+impl<'a> BatchRunner<'a> {
+    /// `parallel == 0` means one job per CPU
+    pub fn new(parallel: usize, base_dir: PathBuf, compile: &'a dyn Fn(&str) -> ProgramMain) -> Self {
+        let parallel = if parallel == 0 { super::parallel::default_jobs() } else { parallel };
+        BatchRunner { parallel, base_dir, compile, live: None }
+    }
+
+    /// Echo every job's stdout and stderr through `mux` as it arrives
+    pub fn with_live_output(mut self, mux: &'a OutputMultiplexer) -> Self {
+        self.live = Some(mux);
+        self
+    }
+
+    pub fn run(&self, batch: &BatchFile) -> BatchReport {
+        let start = Instant::now();
+        let mut results: Vec<Option<JobResult>> = vec![None; batch.jobs.len()];
+        let mut queue = batch.jobs.iter().enumerate();
+        let mut running: Vec<RunningJob> = Vec::new();
+
+        loop {
+            while running.len() < self.parallel {
+                let Some((index, job)) = queue.next() else { break };
+                match self.spawn(index, job, batch) {
+                    Ok(child) => running.push(child),
+                    Err(message) => results[index] = Some(JobResult::setup_error(&job.name, message)),
+                }
+            }
+            if running.is_empty() {
+                break;
+            }
+
+            wait_for_activity(&running);
+            let mut i = 0;
+            while i < running.len() {
+                running[i].pump();
+                running[i].enforce_deadline();
+                match running[i].try_wait() {
+                    Some(exit) => {
+                        let child = running.swap_remove(i);
+                        let job = &batch.jobs[child.index];
+                        results[child.index] = Some(child.finish(job, exit));
+                    }
+                    None => i += 1,
+                }
+            }
+        }
+
+        let jobs: Vec<JobResult> = results.into_iter().flatten().collect();
+        BatchReport {
+            total: jobs.len(),
+            passed: jobs.iter().filter(|r| r.status == JobStatus::Passed).count(),
+            elapsed_ms: start.elapsed().as_millis() as u64,
+            jobs,
+        }
+    }
+
+    fn spawn(&self, index: usize, job: &BatchJob, batch: &BatchFile) -> Result<RunningJob, String> {
+        let source = match (&job.source, &job.code) {
+            (Some(path), _) => {
+                let path = self.base_dir.join(path);
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            (None, Some(code)) => code.clone(),
+            (None, None) => unreachable!("checked by BatchFile::load"),
+        };
+        let source = format!("{}\n{}", batch.prelude, source);
+        let limits = job.limits.apply(batch.limits);
+
+        let (stdout_read, stdout_write) = pipe().map_err(|e| e.to_string())?;
+        let (stderr_read, stderr_write) = pipe().map_err(|e| e.to_string())?;
+        let (ready_read, ready_write) = pipe().map_err(|e| e.to_string())?;
+
+        // Buffered output would otherwise be written again by the child
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(format!("fork: {}", io::Error::last_os_error()));
+        }
+        if pid == 0 {
+            let status = self.run_child(job, &source, &limits, [&stdout_write, &stderr_write], &ready_write);
+            let _ = io::stdout().flush();
+            unsafe {
+                libc::fflush(std::ptr::null_mut());
+                libc::_exit(status)
+            }
+        }
+
+        for fd in [&stdout_read, &stderr_read, &ready_read] {
+            set_nonblocking(fd.as_raw_fd());
+        }
+        let now = Instant::now();
+        Ok(RunningJob {
+            index,
+            pid,
+            limits,
+            stdout: Capture::new(stdout_read, self.live.map(|mux| mux.handle(&job.name, OutputStream::Stdout))),
+            stderr: Capture::new(stderr_read, self.live.map(|mux| mux.handle(&job.name, OutputStream::Stderr))),
+            ready: Some(ready_read),
+            started: now,
+            compiled_at: None,
+            killed: None,
+        })
+    }
+
+    /// Everything the job's child does; returns its exit status
+    fn run_child(
+        &self,
+        job: &BatchJob,
+        source: &str,
+        limits: &JobLimits,
+        output: [&OwnedFd; 2],
+        ready: &OwnedFd,
+    ) -> i32 {
+        unsafe {
+            libc::dup2(output[0].as_raw_fd(), 1);
+            libc::dup2(output[1].as_raw_fd(), 2);
+        }
+        if let Err(e) = self.connect_stdin(job) {
+            eprintln!("batch: cannot open stdin: {}", e);
+            return 1;
+        }
+
+        let main_fn = (self.compile)(source);
+        unsafe { libc::write(ready.as_raw_fd(), b"1".as_ptr() as *const libc::c_void, 1) };
+
+        if let Err(e) = apply_limits(limits) {
+            eprintln!("batch: cannot apply limits: {}", e);
+            return 1;
+        }
+
+        let argv0 = std::ffi::CString::new(job.name.replace('\0', "")).unwrap_or_default();
+        let args: Vec<std::ffi::CString> = job
+            .args
+            .iter()
+            .map(|arg| std::ffi::CString::new(arg.replace('\0', "")).unwrap_or_default())
+            .collect();
+        let mut argv: Vec<*const i8> = std::iter::once(&argv0).chain(&args).map(|arg| arg.as_ptr()).collect();
+        argv.push(std::ptr::null());
+        main_fn(argv.len() as i32 - 1, argv.as_ptr())
+    }
+
+    fn connect_stdin(&self, job: &BatchJob) -> io::Result<()> {
+        let fd = match (&job.stdin, &job.stdin_file) {
+            (Some(text), _) => unsafe {
+                let fd = libc::memfd_create(c"batch-stdin".as_ptr(), 0);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut file = File::from_raw_fd(fd);
+                file.write_all(text.as_bytes())?;
+                libc::lseek(fd, 0, libc::SEEK_SET);
+                file.into_raw_fd()
+            },
+            (None, Some(path)) => File::open(self.base_dir.join(path))?.into_raw_fd(),
+            (None, None) => File::open("/dev/null")?.into_raw_fd(),
+        };
+        unsafe {
+            libc::dup2(fd, 0);
+            libc::close(fd);
+        }
+        Ok(())
+    }
+}
+
+/// A stream captured from a job, up to its output limit
+struct Capture {
+    fd: Option<OwnedFd>,
+    data: Vec<u8>,
+    truncated: bool,
+    // Live echo; dropping it prints a trailing partial line
+    echo: Option<OutputHandle>,
+}
+
+impl Capture {
+    fn new(fd: OwnedFd, echo: Option<OutputHandle>) -> Self {
+        Capture { fd: Some(fd), data: Vec::new(), truncated: false, echo }
+    }
+
+    /// Read what's available; returns false once over `limit`
+    fn pump(&mut self, limit: usize) -> bool {
+        let Some(fd) = &self.fd else { return true };
+        let mut buffer = [0u8; 16 * 1024];
+        loop {
+            let n = unsafe { libc::read(fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if n < 0 {
+                let kind = io::Error::last_os_error().kind();
+                if kind != io::ErrorKind::WouldBlock && kind != io::ErrorKind::Interrupted {
+                    self.fd = None;
+                }
+                return true;
+            }
+            if n == 0 {
+                self.fd = None;
+                return true;
+            }
+            let room = limit.saturating_sub(self.data.len());
+            let kept = &buffer[..(n as usize).min(room)];
+            if let Some(echo) = &mut self.echo {
+                let _ = echo.write_all(kept);
+            }
+            self.data.extend_from_slice(kept);
+            if n as usize > room {
+                self.truncated = true;
+                self.fd = None;
+                return false;
+            }
+        }
+    }
+}
+
+struct RunningJob {
+    index: usize,
+    pid: libc::pid_t,
+    limits: JobLimits,
+    stdout: Capture,
+    stderr: Capture,
+    // Gets one byte when compilation succeeds; closes without it otherwise
+    ready: Option<OwnedFd>,
+    started: Instant,
+    compiled_at: Option<Instant>,
+    // Why we killed the child, if we did
+    killed: Option<JobStatus>,
+}
+
+impl RunningJob {
+    fn fds(&self) -> impl Iterator<Item = RawFd> + '_ {
+        [&self.stdout.fd, &self.stderr.fd, &self.ready]
+            .into_iter()
+            .flatten()
+            .map(|fd| fd.as_raw_fd())
+    }
+
+    fn pump(&mut self) {
+        if let Some(fd) = &self.ready {
+            let mut byte = 0u8;
+            let n = unsafe { libc::read(fd.as_raw_fd(), &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if n == 1 {
+                self.compiled_at = Some(Instant::now());
+            }
+            if n >= 0 {
+                self.ready = None;
+            }
+        }
+
+        let limit = self.limits.output_bytes;
+        if !(self.stdout.pump(limit) && self.stderr.pump(limit)) {
+            self.kill(JobStatus::OutputLimit);
+        }
+    }
+
+    fn enforce_deadline(&mut self) {
+        let deadline = match self.compiled_at {
+            Some(compiled) => compiled + Duration::from_millis(self.limits.timeout_ms),
+            None => self.started + COMPILE_TIMEOUT,
+        };
+        if Instant::now() >= deadline {
+            let reason = if self.compiled_at.is_some() { JobStatus::Timeout } else { JobStatus::CompileError };
+            self.kill(reason);
+        }
+    }
+
+    fn kill(&mut self, reason: JobStatus) {
+        if self.killed.is_none() {
+            self.killed = Some(reason);
+            unsafe { libc::kill(self.pid, libc::SIGKILL) };
+        }
+    }
+
+    /// Reap the child if it has exited: (wait status, resource usage)
+    fn try_wait(&mut self) -> Option<(i32, libc::rusage)> {
+        let mut status = 0;
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        let pid = unsafe { libc::wait4(self.pid, &mut status, libc::WNOHANG, &mut usage) };
+        if pid != self.pid {
+            return None;
+        }
+        // Whatever it wrote before exiting is still in the pipes
+        self.pump();
+        Some((status, usage))
+    }
+
+    fn finish(self, job: &BatchJob, (status, usage): (i32, libc::rusage)) -> JobResult {
+        let now = Instant::now();
+        let (exit_code, signal) = if libc::WIFSIGNALED(status) {
+            (None, Some(libc::WTERMSIG(status)))
+        } else {
+            (Some(libc::WEXITSTATUS(status)), None)
+        };
+        let stdout = String::from_utf8_lossy(&self.stdout.data).into_owned();
+
+        let outcome = if let Some(reason) = self.killed {
+            reason
+        } else if self.compiled_at.is_none() {
+            JobStatus::CompileError
+        } else if signal == Some(libc::SIGXCPU) {
+            JobStatus::Timeout
+        } else if signal.is_some() {
+            JobStatus::Crashed
+        } else if exit_code != Some(job.expected_exit.unwrap_or(0)) {
+            JobStatus::WrongExit
+        } else if job.expected_stdout.as_ref().is_some_and(|expected| expected.trim_end() != stdout.trim_end()) {
+            JobStatus::WrongOutput
+        } else {
+            JobStatus::Passed
+        };
+
+        let millis = |tv: libc::timeval| tv.tv_sec as u64 * 1000 + tv.tv_usec as u64 / 1000;
+        JobResult {
+            name: job.name.clone(),
+            status: outcome,
+            exit_code,
+            signal,
+            stdout,
+            stderr: String::from_utf8_lossy(&self.stderr.data).into_owned(),
+            output_truncated: self.stdout.truncated || self.stderr.truncated,
+            compile_ms: self.compiled_at.map_or(0, |c| (c - self.started).as_millis() as u64),
+            run_ms: self.compiled_at.map_or(0, |c| (now - c).as_millis() as u64),
+            cpu_ms: millis(usage.ru_utime) + millis(usage.ru_stime),
+            max_rss_kb: usage.ru_maxrss as u64,
+        }
+    }
+}
+
+/// Sleep until a job has output or the poll interval passes
+fn wait_for_activity(running: &[RunningJob]) {
+    let mut fds: Vec<libc::pollfd> = running
+        .iter()
+        .flat_map(|job| job.fds())
+        .map(|fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 })
+        .collect();
+    unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, POLL_INTERVAL_MS) };
+}
+
+fn apply_limits(limits: &JobLimits) -> io::Result<()> {
+    // Crashing submissions are expected; don't litter the disk with cores
+    set_limit(libc::RLIMIT_CORE, 0)?;
+    if let Some(bytes) = limits.memory_bytes {
+        set_limit(libc::RLIMIT_AS, bytes)?;
+    }
+    if let Some(secs) = limits.cpu_secs {
+        set_limit(libc::RLIMIT_CPU, secs)?;
+    }
+    if let Some(bytes) = limits.file_size_bytes {
+        set_limit(libc::RLIMIT_FSIZE, bytes)?;
+    }
+    if let Some(count) = limits.open_files {
+        set_limit(libc::RLIMIT_NOFILE, count)?;
+    }
+    Ok(())
+}
+
+fn set_limit(resource: libc::__rlimit_resource_t, value: u64) -> io::Result<()> {
+    let limit = libc::rlimit { rlim_cur: value as libc::rlim_t, rlim_max: value as libc::rlim_t };
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn set_nonblocking(fd: RawFd) {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    Io(PathBuf, io::Error),
+    Parse(String),
+    /// A job with neither or both of `source` and `code`
+    Source(String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), BatchError> {
+    // {"prelude": "#include <stdio.h>",
+    //  "limits": {"timeout_ms": 2000, "memory_bytes": 268435456},
+    //  "jobs": [{"name": "alice", "source": "alice/sum.c", "stdin": "1 2\n", "expected_stdout": "3"}]}
+    let batch = BatchFile::load(Path::new("jobs.json"))?;
+
+    let compile = |source: &str| -> ProgramMain {
+        let (compiler, main_fn) = jit_compile_main(source, 2, "x86_64", SanitizerSet::default(), FpOptions::default(), None);
+        std::mem::forget(compiler); // the child exits when main returns
+        main_fn
+    };
+    // [alice] 3
+    let mux = OutputMultiplexer::new(PrefixConfig::default());
+    let report = BatchRunner::new(0, PathBuf::from("."), &compile).with_live_output(&mux).run(&batch);
+
+    println!("{}/{} passed in {} ms", report.passed, report.total, report.elapsed_ms);
+    report.write_to(Path::new("batch-results.json"))
+}
+*/
@@ -0,0 +1,591 @@
+// src/driver/daemon.rs
+//! Warm compile server
+//! `c-interpreter daemon` pays for startup once and then serves requests on a
+//! Unix socket. Startup here means registering the LLVM targets, loading and
+//! relocating the LLVM libraries, and a warm-up compile that pulls the common
+//! system headers and the compilation cache into memory. Each run request is
+//! executed by a fork of the warmed process (`orchestrator::fork_server`), so
+//! the work starts from that state copy-on-write instead of from a fresh exec.
+//!
+//! A client sends its arguments, working directory and environment as one
+//! line of JSON, with its stdin, stdout and stderr attached as SCM_RIGHTS.
+//! The worker runs the ordinary command line on those descriptors, so output,
+//! terminal detection and the exit status are the same as running locally.
+//! Setting `C_INTERPRETER_DAEMON` makes every invocation try the daemon first,
+//! falling back to running in-process when none is listening.
+//!
+//! Forks don't write back into the template: objects compiled by one request
+//! reach the next through the on-disk compilation cache.
+
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::orchestrator::fork_server::{ForkOutcome, ForkServer, ForkServerConfig, ForkServerError};
+use crate::runtime::exit_status::ProgramExit;
+
+/// Socket path to forward invocations to; `1` or `auto` for the default path
+pub const DAEMON_ENV_VAR: &str = "C_INTERPRETER_DAEMON";
+
+/// Compiled once at startup so the first real request finds everything warm
+pub const WARMUP_SOURCE: &str = "#include <stdio.h>\n#include <stdlib.h>\n#include <string.h>\n\nint main(void) { return 0; }\n";
+
+/// A program may legitimately run for a long time; this only reclaims
+/// workers whose client is long gone
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Arguments plus environment; anything larger is not a real command line
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// How long a client gets to send its request once connected
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Granularity of idle and worker bookkeeping
+const POLL_INTERVAL_MS: i32 = 1000;
+
+/// The command line entry point run for each request
+pub type DaemonEntry = fn(Vec<String>) -> io::Result<()>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    /// Run a command line; stdin, stdout and stderr travel with the message
+    Run {
+        args: Vec<String>,
+        cwd: PathBuf,
+        env: Vec<(String, String)>,
+    },
+    Status,
+    Shutdown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum DaemonReply {
+    /// The command finished with this process exit status
+    Exited { code: i32 },
+    Status(DaemonStatus),
+    Stopping,
+    /// The request was refused before anything ran
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub uptime_secs: u64,
+    pub served: u64,
+    /// Requests still running
+    pub active: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    pub socket: PathBuf,
+    /// Exit after this long with no requests and none running
+    pub idle_timeout: Option<Duration>,
+    pub request_timeout: Duration,
+}
+
+impl DaemonConfig {
+    pub fn new(socket: PathBuf) -> Self {
+        DaemonConfig {
+            socket,
+            idle_timeout: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/c-interpreter/daemon.sock`, or a per-user directory
+/// under the temp dir when there is no runtime dir
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("c-interpreter").join("daemon.sock"),
+        _ => std::env::temp_dir()
+            .join(format!("c-interpreter-{}", unsafe { libc::getuid() }))
+            .join("daemon.sock"),
+    }
+}
+
+/// One run request as handed to the forked worker
+#[derive(Serialize, Deserialize)]
+struct Job {
+    args: Vec<String>,
+    cwd: PathBuf,
+    env: Vec<(String, String)>,
+    stdio: [RawFd; 3],
+}
+
+/// What the workers are forked from
+struct Template {
+    entry: DaemonEntry,
+}
+
+pub struct Daemon {
+    listener: UnixListener,
+    config: DaemonConfig,
+
+    // Warmed state, forked per request
+    server: ForkServer<Template>,
+
+    // Connection handlers still running
+    workers: Vec<libc::pid_t>,
+
+    // Statistics
+    started: Instant,
+    last_request: Instant,
+    served: u64,
+}
+
+impl Daemon {
+    /// Run `warm_up` in this process, then listen on the configured socket.
+    /// Fails if another daemon already answers there.
+    pub fn bind(
+        config: DaemonConfig,
+        entry: DaemonEntry,
+        warm_up: impl FnOnce() -> Result<(), String>,
+    ) -> Result<Self, DaemonError> {
+        if UnixStream::connect(&config.socket).is_ok() {
+            return Err(DaemonError::AlreadyRunning(config.socket.clone()));
+        }
+
+        let server = ForkServer::new(
+            || {
+                warm_up().map_err(ForkServerError::Initialization)?;
+                Ok(Template { entry })
+            },
+            run_job,
+            ForkServerConfig {
+                timeout: config.request_timeout,
+                ..ForkServerConfig::default()
+            },
+        )
+        .map_err(DaemonError::Fork)?;
+
+        if let Some(dir) = config.socket.parent() {
+            DirBuilder::new().recursive(true).mode(0o700).create(dir).map_err(DaemonError::Io)?;
+        }
+        // Nobody answered above, so whatever is there is left over from a crash
+        match fs::remove_file(&config.socket) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(DaemonError::Io(e)),
+            _ => {}
+        }
+        let listener = UnixListener::bind(&config.socket).map_err(DaemonError::Io)?;
+        fs::set_permissions(&config.socket, fs::Permissions::from_mode(0o600)).map_err(DaemonError::Io)?;
+
+        let now = Instant::now();
+        Ok(Daemon {
+            listener,
+            config,
+            server,
+            workers: Vec::new(),
+            started: now,
+            last_request: now,
+            served: 0,
+        })
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.config.socket
+    }
+
+    pub fn status(&self) -> DaemonStatus {
+        DaemonStatus {
+            pid: std::process::id(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            served: self.served,
+            active: self.workers.len(),
+        }
+    }
+
+    /// Serve until asked to shut down or idle for too long. Requests still
+    /// running at that point finish on their own.
+    pub fn serve(mut self) -> Result<DaemonStatus, DaemonError> {
+        loop {
+            self.reap_workers();
+
+            let mut pollfd = libc::pollfd { fd: self.listener.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, POLL_INTERVAL_MS) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(DaemonError::Io(err));
+            }
+
+            if ready == 0 {
+                let idle = self.workers.is_empty()
+                    && self.config.idle_timeout.is_some_and(|limit| self.last_request.elapsed() >= limit);
+                if idle {
+                    break;
+                }
+                continue;
+            }
+
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::warn!("daemon: accept failed: {}", e);
+                    continue;
+                }
+            };
+            self.last_request = Instant::now();
+            match self.handle_connection(stream) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => log::error!("daemon: dropped request: {:?}", e),
+            }
+        }
+
+        let _ = fs::remove_file(&self.config.socket);
+        Ok(self.status())
+    }
+
+    /// Returns false once the daemon should stop
+    fn handle_connection(&mut self, stream: UnixStream) -> Result<bool, DaemonError> {
+        let uid = peer_uid(&stream).map_err(DaemonError::Io)?;
+        if uid != unsafe { libc::getuid() } {
+            let _ = send_reply(&stream, &DaemonReply::Error { message: "permission denied".to_string() });
+            return Err(DaemonError::ForeignUser(uid));
+        }
+
+        stream.set_read_timeout(Some(REQUEST_READ_TIMEOUT)).map_err(DaemonError::Io)?;
+        let (request, fds) = read_request(&stream)?;
+
+        match request {
+            DaemonRequest::Status => send_reply(&stream, &DaemonReply::Status(self.status()))?,
+            DaemonRequest::Shutdown => {
+                send_reply(&stream, &DaemonReply::Stopping)?;
+                return Ok(false);
+            }
+            DaemonRequest::Run { args, cwd, env } => {
+                let stdio = match fds.as_slice() {
+                    [stdin, stdout, stderr] => [stdin.as_raw_fd(), stdout.as_raw_fd(), stderr.as_raw_fd()],
+                    _ => {
+                        let message = format!("expected 3 stdio descriptors, got {}", fds.len());
+                        send_reply(&stream, &DaemonReply::Error { message: message.clone() })?;
+                        return Err(DaemonError::Protocol(message));
+                    }
+                };
+                let job = Job { args, cwd, env, stdio };
+                let pid = self.spawn_worker(stream, &job)?;
+                self.workers.push(pid);
+                self.served += 1;
+                // Our copies of the client's descriptors close as `fds` drops
+            }
+        }
+        Ok(true)
+    }
+
+    /// Fork a handler for one run request. The handler in turn runs the job
+    /// in a fork of the template, which may exit however it likes, and then
+    /// reports the exit status to the client.
+    fn spawn_worker(&mut self, stream: UnixStream, job: &Job) -> Result<libc::pid_t, DaemonError> {
+        let input = serde_json::to_vec(job).map_err(|e| DaemonError::Protocol(e.to_string()))?;
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(DaemonError::Io(io::Error::last_os_error()));
+        }
+        if pid > 0 {
+            return Ok(pid);
+        }
+
+        unsafe { libc::close(self.listener.as_raw_fd()) };
+        let reply = match self.server.execute(&input) {
+            Ok(result) => DaemonReply::Exited { code: exit_code(&result.outcome) },
+            Err(e) => DaemonReply::Error { message: format!("{:?}", e) },
+        };
+        let _ = send_reply(&stream, &reply);
+        // Skip destructors and atexit handlers inherited from the daemon
+        unsafe { libc::_exit(0) }
+    }
+
+    fn reap_workers(&mut self) {
+        let mut status = 0;
+        loop {
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            if pid <= 0 {
+                break;
+            }
+            self.workers.retain(|&worker| worker != pid);
+        }
+    }
+}
+
+/// Runs in the fork of the template: adopt the client's stdio, directory and
+/// environment, then run the command line as if it had been exec'd
+fn run_job(template: &mut Template, input: &[u8]) -> Result<Vec<u8>, String> {
+    let job: Job = serde_json::from_slice(input).map_err(|e| e.to_string())?;
+
+    unsafe {
+        for (target, &fd) in job.stdio.iter().enumerate() {
+            if libc::dup2(fd, target as RawFd) < 0 {
+                return Err(format!("dup2: {}", io::Error::last_os_error()));
+            }
+        }
+        // The only thread in this process, so the environment is ours to change
+        for (key, _) in std::env::vars_os() {
+            std::env::remove_var(key);
+        }
+        for (key, value) in &job.env {
+            std::env::set_var(key, value);
+        }
+    }
+    std::env::set_current_dir(&job.cwd).map_err(|e| format!("{}: {}", job.cwd.display(), e))?;
+
+    let result = (template.entry)(job.args);
+    if let Err(e) = &result {
+        // What returning the error from `main` would have printed
+        eprintln!("Error: {:?}", e);
+    }
+
+    // The fork server exits without running the usual flushes
+    let _ = io::stdout().flush();
+    unsafe { libc::fflush(ptr::null_mut()) };
+
+    result.map(|()| Vec::new()).map_err(|e| e.to_string())
+}
+
+/// The status the command would have exited with had it run in-process
+fn exit_code(outcome: &ForkOutcome) -> i32 {
+    match outcome {
+        ForkOutcome::Completed { .. } => 0,
+        ForkOutcome::Failed { .. } => 1,
+        ForkOutcome::Signaled { signal } => ProgramExit::Signaled(*signal).code(),
+        // `process::exit` was called before the worker could report
+        ForkOutcome::NoResult { exit_code } => *exit_code,
+    }
+}
+
+/// Forward this invocation to a daemon if `C_INTERPRETER_DAEMON` names one.
+/// Returns the exit status, or `None` to run in-process instead.
+pub fn forward_from_env(args: &[String]) -> Option<i32> {
+    let socket = match std::env::var(DAEMON_ENV_VAR) {
+        Ok(value) if value == "1" || value == "auto" => default_socket_path(),
+        Ok(value) if !value.is_empty() => PathBuf::from(value),
+        _ => return None,
+    };
+    // Managing the daemon always happens locally
+    if args.get(1).map(String::as_str) == Some("daemon") {
+        return None;
+    }
+
+    // This runs before logging is set up, so problems go straight to stderr
+    let stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => return None,
+        Err(e) => {
+            eprintln!("warning: daemon at '{}' unreachable ({}), running locally", socket.display(), e);
+            return None;
+        }
+    };
+
+    let request = DaemonRequest::Run {
+        args: args.to_vec(),
+        cwd: std::env::current_dir().ok()?,
+        env: std::env::vars().collect(),
+    };
+    if let Err(e) = send_request(&stream, &request, &[0, 1, 2]) {
+        eprintln!("warning: daemon at '{}' failed ({:?}), running locally", socket.display(), e);
+        return None;
+    }
+
+    // From here the command may already be running; never run it twice
+    match read_reply(&stream) {
+        Ok(DaemonReply::Exited { code }) => Some(code),
+        Ok(DaemonReply::Error { message }) => {
+            eprintln!("warning: daemon refused the request ({}), running locally", message);
+            None
+        }
+        Ok(reply) => {
+            eprintln!("Error: unexpected reply from daemon: {:?}", reply);
+            Some(1)
+        }
+        Err(e) => {
+            eprintln!("Error: lost connection to daemon: {:?}", e);
+            Some(1)
+        }
+    }
+}
+
+/// Send a control request (no descriptors) and wait for the reply
+pub fn request(socket: &Path, request: &DaemonRequest) -> Result<DaemonReply, DaemonError> {
+    let stream = UnixStream::connect(socket).map_err(DaemonError::Io)?;
+    send_request(&stream, request, &[])?;
+    read_reply(&stream)
+}
+
+fn send_request(stream: &UnixStream, request: &DaemonRequest, fds: &[RawFd]) -> Result<(), DaemonError> {
+    let mut line = serde_json::to_vec(request).map_err(|e| DaemonError::Protocol(e.to_string()))?;
+    line.push(b'\n');
+    send_with_fds(stream, &line, fds).map_err(DaemonError::Io)
+}
+
+fn send_reply(stream: &UnixStream, reply: &DaemonReply) -> Result<(), DaemonError> {
+    let mut line = serde_json::to_vec(reply).map_err(|e| DaemonError::Protocol(e.to_string()))?;
+    line.push(b'\n');
+    (&*stream).write_all(&line).map_err(DaemonError::Io)
+}
+
+fn read_reply(stream: &UnixStream) -> Result<DaemonReply, DaemonError> {
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).map_err(DaemonError::Io)?;
+    if line.is_empty() {
+        return Err(DaemonError::Protocol("connection closed without a reply".to_string()));
+    }
+    serde_json::from_str(&line).map_err(|e| DaemonError::Protocol(e.to_string()))
+}
+
+/// Read one request line and any descriptors that came with it
+fn read_request(stream: &UnixStream) -> Result<(DaemonRequest, Vec<OwnedFd>), DaemonError> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let (received, fds) = receive_with_fds(stream, &mut buffer).map_err(DaemonError::Io)?;
+    buffer.truncate(received);
+
+    while !buffer.contains(&b'\n') {
+        if received == 0 || buffer.len() > MAX_REQUEST_SIZE {
+            return Err(DaemonError::Protocol("incomplete or oversized request".to_string()));
+        }
+        let mut chunk = [0u8; 8192];
+        let n = (&*stream).read(&mut chunk).map_err(DaemonError::Io)?;
+        if n == 0 {
+            return Err(DaemonError::Protocol("incomplete request".to_string()));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+
+    let end = buffer.iter().position(|&b| b == b'\n').unwrap_or(buffer.len());
+    let request = serde_json::from_slice(&buffer[..end]).map_err(|e| DaemonError::Protocol(e.to_string()))?;
+    Ok((request, fds))
+}
+
+/// Write `data` with `fds` attached to its first bytes
+fn send_with_fds(stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let payload = mem::size_of_val(fds) as u32;
+    let space = unsafe { libc::CMSG_SPACE(payload) } as usize;
+    // u64 elements keep the control buffer aligned for cmsghdr
+    let mut control = vec![0u64; space.div_ceil(8)];
+
+    let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    unsafe {
+        if !fds.is_empty() {
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = space as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(payload) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+
+        let sent = libc::sendmsg(stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL);
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The descriptors went with the first chunk; the rest is a plain write
+        (&*stream).write_all(&data[sent as usize..])
+    }
+}
+
+/// Receive into `buffer`, collecting any SCM_RIGHTS descriptors
+fn receive_with_fds(stream: &UnixStream, buffer: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>)> {
+    // Room for the three stdio descriptors and then some
+    let mut control = [0u64; 16];
+    let mut iov = libc::iovec { iov_base: buffer.as_mut_ptr() as *mut libc::c_void, iov_len: buffer.len() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    unsafe {
+        let received = libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut fds = Vec::new();
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many descriptors"));
+        }
+        Ok((received as usize, fds))
+    }
+}
+
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut credentials: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(credentials.uid)
+}
+
+#[derive(Debug)]
+pub enum DaemonError {
+    Io(io::Error),
+    /// Another daemon is answering on this socket
+    AlreadyRunning(PathBuf),
+    Fork(ForkServerError),
+    Protocol(String),
+    /// A client running as a different user
+    ForeignUser(u32),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), DaemonError> {
+    // Server: c-interpreter daemon
+    let config = DaemonConfig {
+        idle_timeout: Some(Duration::from_secs(30 * 60)),
+        ..DaemonConfig::new(default_socket_path())
+    };
+    let daemon = Daemon::bind(config, run_command_line, || {
+        jit_eval(WARMUP_SOURCE, 2, "x86_64").map(|_| ())
+    })?;
+    let status = daemon.serve()?;
+    println!("served {} requests", status.served);
+
+    // Client: C_INTERPRETER_DAEMON=1 c-interpreter run hello.c
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = forward_from_env(&args) {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+*/
@@ -0,0 +1,399 @@
+// src/driver/fallback.rs
+//! Host toolchain fallback
+//! Files that use constructs we don't support yet, or that target an
+//! architecture we can't generate code for, can be handed to an installed
+//! clang/gcc instead. The driver compiles each file to an object with
+//! whichever toolchain applies and links the mix with the host driver, so a
+//! project can move over one file at a time.
+//!
+//! Configured in `c-interpreter.toml`:
+//!
+//! ```toml
+//! [fallback]
+//! compiler = "clang"              # or "gcc", or a path; default: $CC, clang, gcc
+//! flags = ["-O2", "-DLEGACY"]
+//! files = ["src/legacy/**/*.c"]   # always compiled by the host compiler
+//! targets = ["riscv64"]           # architectures (or triples) we delegate entirely
+//! on-unsupported = true           # retry with the host compiler on unsupported constructs
+//! ```
+//!
+//! Delegated targets are built for their triple: clang gets `--target`, and
+//! any other compiler must already be a cross compiler for it (checked with
+//! `-dumpmachine`), such as `riscv64-linux-gnu-gcc`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use serde::Deserialize;
+use crate::arch::Architecture;
+use crate::diagnostics::codes;
+use crate::diagnostics::engine::Diagnostic;
+use super::parallel::{DiagnosticsSink, ParallelCompiler};
+
+/// Looked up in the working directory when no config is given
+pub const DEFAULT_CONFIG_FILE: &str = "c-interpreter.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ToolchainConfig {
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FallbackConfig {
+    pub compiler: Option<String>,
+    #[serde(default)]
+    pub flags: Vec<String>,
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub targets: Vec<String>,
+    #[serde(default)]
+    pub on_unsupported: bool,
+    /// Extra flags for the final link
+    #[serde(default)]
+    pub link_flags: Vec<String>,
+}
+
+impl ToolchainConfig {
+    pub fn load(path: &Path) -> Result<Self, FallbackError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| FallbackError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&text).map_err(|e| FallbackError::Config(e.to_string()))
+    }
+
+    /// Explicit config, else `c-interpreter.toml` if present, else defaults
+    pub fn discover(explicit: Option<&Path>) -> Result<Self, FallbackError> {
+        match explicit {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::load(Path::new(DEFAULT_CONFIG_FILE)),
+            None => Ok(ToolchainConfig::default()),
+        }
+    }
+}
+
+/// Why a file was routed to the host compiler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Native,
+    HostTarget(String),
+    HostFile(String),
+    HostUnsupported(String),
+}
+
+impl Route {
+    pub fn is_host(&self) -> bool {
+        *self != Route::Native
+    }
+}
+
+/// Failure from our own compiler for one file
+#[derive(Debug)]
+pub enum NativeError {
+    /// A construct or target we don't implement; eligible for fallback
+    Unsupported(String),
+    /// A genuine error in the program
+    Failed(String),
+}
+
+/// Compiles one source file to one object file with our own pipeline.
+/// Called from several worker threads at once when `jobs > 1`.
+pub type NativeCompiler<'a> = dyn Fn(&Path, &Path) -> Result<(), NativeError> + Sync + 'a;
+
+#[derive(Debug, Clone)]
+pub struct CompiledUnit {
+    pub source: PathBuf,
+    pub object: PathBuf,
+    pub route: Route,
+}
+
+pub struct MixedBuild {
+    // Configuration
+    config: FallbackConfig,
+    target: String,
+    host_compiler: Option<PathBuf>,
+    jobs: usize,
+
+    // Build state
+    object_dir: PathBuf,
+    units: Vec<CompiledUnit>,
+}
+
+impl MixedBuild {
+    pub fn new(config: FallbackConfig, target: &str, object_dir: &Path) -> Self {
+        let host_compiler = find_host_compiler(config.compiler.as_deref());
+        MixedBuild {
+            config,
+            target: target.to_string(),
+            host_compiler,
+            jobs: 1,
+            object_dir: object_dir.to_path_buf(),
+            units: Vec::new(),
+        }
+    }
+
+    /// Compile up to `jobs` files at once; 0 means one per CPU
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Decide up front whether a file skips our compiler entirely
+    pub fn route(&self, source: &Path) -> Route {
+        if self.config.targets.iter().any(|t| t == &self.target) {
+            return Route::HostTarget(self.target.clone());
+        }
+
+        let path = source.to_string_lossy().replace('\\', "/");
+        match self.config.files.iter().find(|pattern| glob_match(pattern, &path)) {
+            Some(pattern) => Route::HostFile(pattern.clone()),
+            None => Route::Native,
+        }
+    }
+
+    /// Compile every source to an object, falling back where configured
+    pub fn compile_all(
+        &mut self,
+        sources: &[PathBuf],
+        native: &NativeCompiler
+    ) -> Result<&[CompiledUnit], FallbackError> {
+        fs::create_dir_all(&self.object_dir)
+            .map_err(|e| FallbackError::Io(self.object_dir.clone(), e))?;
+
+        // Object names are assigned up front so they don't depend on scheduling;
+        // the same stem in different directories must not collide
+        let mut stems: HashMap<String, usize> = HashMap::new();
+        let jobs: Vec<(PathBuf, PathBuf)> = sources
+            .iter()
+            .map(|source| {
+                let stem = source.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                let count = stems.entry(stem.clone()).or_insert(0);
+                let name = if *count == 0 { format!("{}.o", stem) } else { format!("{}-{}.o", stem, count) };
+                *count += 1;
+                (source.clone(), self.object_dir.join(name))
+            })
+            .collect();
+
+        let workers = ParallelCompiler::new(self.jobs)
+            .map_err(|e| FallbackError::Parallel(format!("{:?}", e)))?;
+        let sink = DiagnosticsSink::new();
+        let results = workers.compile_all(&jobs, &sink, || (), |_, index, (source, object), sink| {
+            self.compile_one(index, source, object, native, sink)
+        });
+
+        // Report in command-line order regardless of completion order
+        for diagnostic in sink.into_ordered() {
+            log::warn!("{}", diagnostic);
+        }
+        for result in results {
+            let unit = result?;
+            log::info!("{} -> {} ({:?})", unit.source.display(), unit.object.display(), unit.route);
+            self.units.push(unit);
+        }
+
+        Ok(&self.units)
+    }
+
+    fn compile_one(
+        &self,
+        index: usize,
+        source: &Path,
+        object: &Path,
+        native: &NativeCompiler,
+        sink: &DiagnosticsSink
+    ) -> Result<CompiledUnit, FallbackError> {
+        let mut route = self.route(source);
+        if route == Route::Native {
+            match native(source, object) {
+                Ok(()) => {}
+                Err(NativeError::Unsupported(reason)) if self.config.on_unsupported => {
+                    sink.report(index, Diagnostic::warning(format!(
+                        "{}: {}; compiling with the host toolchain",
+                        source.display(),
+                        reason
+                    )).with_code(codes::E0025));
+                    route = Route::HostUnsupported(reason);
+                }
+                Err(NativeError::Unsupported(reason)) | Err(NativeError::Failed(reason)) => {
+                    return Err(FallbackError::Native(source.to_path_buf(), reason));
+                }
+            }
+        }
+
+        if route.is_host() {
+            self.compile_with_host(source, object, &route)?;
+        }
+
+        Ok(CompiledUnit { source: source.to_path_buf(), object: object.to_path_buf(), route })
+    }
+
+    fn compile_with_host(&self, source: &Path, object: &Path, route: &Route) -> Result<(), FallbackError> {
+        let compiler = self.host_compiler.as_ref().ok_or(FallbackError::NoHostCompiler)?;
+        let target = match route {
+            Route::HostTarget(target) => Some(target.as_str()),
+            _ => None,
+        };
+        let output = self
+            .host_command(compiler, target)?
+            .arg("-c")
+            .args(&self.config.flags)
+            .arg(source)
+            .arg("-o")
+            .arg(object)
+            .output()
+            .map_err(|e| FallbackError::Io(compiler.clone(), e))?;
+
+        if !output.status.success() {
+            return Err(FallbackError::HostFailed(
+                source.to_path_buf(),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Link all objects with the host driver, which knows the system CRT and libc
+    pub fn link(&self, output: &Path, libraries: &[String]) -> Result<(), FallbackError> {
+        let linker = self.host_compiler.as_ref().ok_or(FallbackError::NoHostCompiler)?;
+        let delegated = self.config.targets.iter().any(|t| t == &self.target);
+        let result = self
+            .host_command(linker, delegated.then_some(self.target.as_str()))?
+            .args(self.units.iter().map(|u| &u.object))
+            .args(&self.config.link_flags)
+            .args(libraries.iter().map(|l| format!("-l{}", l)))
+            .arg("-o")
+            .arg(output)
+            .output()
+            .map_err(|e| FallbackError::Io(linker.clone(), e))?;
+
+        if !result.status.success() {
+            return Err(FallbackError::LinkFailed(String::from_utf8_lossy(&result.stderr).into_owned()));
+        }
+        Ok(())
+    }
+
+    pub fn units(&self) -> &[CompiledUnit] {
+        &self.units
+    }
+
+    /// The host compiler, set up to build for `target` when one is given
+    fn host_command(&self, compiler: &Path, target: Option<&str>) -> Result<Command, FallbackError> {
+        let mut command = Command::new(compiler);
+        let Some(target) = target else {
+            return Ok(command);
+        };
+
+        let triple = target_triple(target);
+        let name = compiler.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        if name.contains("clang") {
+            command.arg(format!("--target={}", triple));
+            return Ok(command);
+        }
+
+        // Anything else has its target built in
+        let output = Command::new(compiler)
+            .arg("-dumpmachine")
+            .output()
+            .map_err(|e| FallbackError::Io(compiler.to_path_buf(), e))?;
+        let machine = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let arch = |triple: &str| triple.split('-').next().unwrap_or("").to_string();
+        if arch(&machine) != arch(&triple) {
+            return Err(FallbackError::WrongTarget { compiler: compiler.to_path_buf(), target: triple, builds_for: machine });
+        }
+        Ok(command)
+    }
+}
+
+/// The triple for a `targets` entry: a triple as given, else the Linux
+/// triple of the architecture
+pub fn target_triple(target: &str) -> String {
+    if target.contains('-') {
+        return target.to_string();
+    }
+    match Architecture::from_str(target) {
+        Ok(architecture) => architecture.default_target_triple().to_string(),
+        Err(_) => format!("{}-unknown-linux-gnu", target),
+    }
+}
+
+/// Configured compiler, then $CC, then clang or gcc on PATH
+pub fn find_host_compiler(configured: Option<&str>) -> Option<PathBuf> {
+    let env_cc = std::env::var("CC").ok();
+    let candidates = configured
+        .into_iter()
+        .chain(env_cc.as_deref())
+        .chain(["clang", "gcc", "cc"]);
+
+    for candidate in candidates {
+        let path = Path::new(candidate);
+        if path.components().count() > 1 {
+            if path.is_file() {
+                return Some(path.to_path_buf());
+            }
+            continue;
+        }
+        let paths = std::env::var_os("PATH").unwrap_or_default();
+        for dir in std::env::split_paths(&paths) {
+            let full = dir.join(candidate);
+            if full.is_file() {
+                return Some(full);
+            }
+        }
+    }
+    None
+}
+
+/// `*` matches within a path component, `**` across components
+fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(p: &[u8], s: &[u8]) -> bool {
+        match p.first() {
+            None => s.is_empty(),
+            Some(b'*') if p.get(1) == Some(&b'*') => {
+                // `**/` also matches zero directories
+                let rest = if p.get(2) == Some(&b'/') { &p[3..] } else { &p[2..] };
+                (0..=s.len()).any(|i| matches(rest, &s[i..]))
+            }
+            Some(b'*') => {
+                let rest = &p[1..];
+                (0..=s.len())
+                    .take_while(|&i| i == 0 || s[i - 1] != b'/')
+                    .any(|i| matches(rest, &s[i..]))
+            }
+            Some(b'?') => !s.is_empty() && s[0] != b'/' && matches(&p[1..], &s[1..]),
+            Some(c) => s.first() == Some(c) && matches(&p[1..], &s[1..]),
+        }
+    }
+    let path = path.strip_prefix("./").unwrap_or(path);
+    matches(pattern.as_bytes(), path.as_bytes())
+}
+
+#[derive(Debug)]
+pub enum FallbackError {
+    Config(String),
+    Io(PathBuf, std::io::Error),
+    NoHostCompiler,
+    Native(PathBuf, String),
+    HostFailed(PathBuf, String),
+    LinkFailed(String),
+    Parallel(String),
+    /// A delegated target's compiler is neither clang nor a cross compiler for it
+    WrongTarget { compiler: PathBuf, target: String, builds_for: String },
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), FallbackError> {
+    let config = ToolchainConfig::discover(None)?;
+    let mut build = MixedBuild::new(config.fallback, "x86_64", Path::new("build/obj")).with_jobs(4);
+
+    let sources = vec![PathBuf::from("src/main.c"), PathBuf::from("src/legacy/io.c")];
+    build.compile_all(&sources, &|source, object| {
+        compile_object(source, object).map_err(NativeError::Unsupported)
+    })?;
+    build.link(Path::new("app"), &["m".to_string()])?;
+    Ok(())
+}
+*/
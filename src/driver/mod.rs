@@ -4,11 +4,29 @@ use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use parking_lot::RwLock;
 use tokio::sync::mpsc;
+#[cfg(feature = "llvm")]
+use llvm_sys::target_machine::{LLVMCodeModel, LLVMRelocMode};
 
 // New imports for architecture support
 use crate::arch::{Architecture, ArchitectureRegistry};
+#[cfg(feature = "llvm")]
 use crate::compiler::{CompilerSystem, CompilerOptions, AssemblyOptions, LinkOptions};
+use crate::diagnostics::codes;
+use crate::diagnostics::engine::{Diagnostic, DiagnosticsConfig, DiagnosticsEngine};
+use crate::lto::LtoMode;
+#[cfg(feature = "llvm")]
+use crate::lto::{self, LTOError, LTOSystem, LtoOptions, DEFAULT_IMPORT_LIMIT};
 
+pub mod batch;
+pub mod daemon;
+pub mod fallback;
+pub mod parallel;
+pub mod repl;
+pub mod usage;
+
+use parallel::{DiagnosticsSink, ParallelCompiler, ParallelError};
+
+#[cfg(feature = "llvm")]
 pub struct CompilerDriver {
     // Core components
     context: CompilerContext,
@@ -26,6 +44,7 @@ pub struct CompilerDriver {
     diagnostics: DiagnosticsEngine,
 }
 
+#[cfg(feature = "llvm")]
 pub struct CompilerContext {
     source_files: Vec<SourceFile>,
     options: CompilerOptions,
@@ -33,6 +52,7 @@ pub struct CompilerContext {
     features: FeatureSet,
 }
 
+#[cfg(feature = "llvm")]
 impl CompilerDriver {
     pub fn new(options: CompilerOptions) -> Result<Self, CompilerError> {
         // Initialize target
@@ -53,58 +73,126 @@ impl CompilerDriver {
             backend: Backend::new(&target)?,
             target,
             file_manager: FileManager::new()?,
-            diagnostics: DiagnosticsEngine::new()?,
+            diagnostics: DiagnosticsEngine::new(DiagnosticsConfig::default()),
         })
     }
 
     pub fn compile(&mut self) -> Result<(), CompilerError> {
-        // For each input file
-        for source in &self.context.source_files {
-            // 1. Parse and validate
-            let ast = self.frontend.parse(source)?;
-            
-            // 2. Generate IR
-            let ir = self.frontend.generate_ir(&ast)?;
-            
-            // 3. Run optimization passes
-            let optimized_ir = self.run_optimization_pipeline(ir)?;
-            
-            // 4. Generate code
-            let obj = self.backend.generate_code(&optimized_ir)?;
-            
-            // 5. Write output
+        let opt_level = self.context.options.opt_level;
+        let objects = match self.context.options.lto {
+            LtoMode::Off => self.compile_units(|pipeline, source| pipeline.compile_unit(source, opt_level))?,
+            mode => {
+                // Optimized across units: every unit to bitcode, then one LTO
+                // step generates the objects
+                let bitcode = self.compile_units(|pipeline, source| pipeline.compile_unit_to_bitcode(source, opt_level, mode))?;
+                let mut lto = LTOSystem::new(self.lto_options());
+                for (source, bitcode) in self.context.source_files.iter().zip(bitcode) {
+                    lto.add_bitcode(&source.path().display().to_string(), bitcode);
+                }
+                lto.perform_lto()
+                    .map_err(CompilerError::Lto)?
+                    .into_iter()
+                    .map(|object| ObjectFile::from_bytes(PathBuf::from(object.name), object.bytes))
+                    .collect()
+            }
+        };
+        for obj in objects {
             self.write_output(obj)?;
         }
-        
+
         Ok(())
     }
 
-    fn run_optimization_pipeline(&self, ir: IR) -> Result<IR, CompilerError> {
+    /// Run `compile` on every translation unit, in parallel
+    fn compile_units<T, F>(&mut self, compile: F) -> Result<Vec<T>, CompilerError>
+    where
+        T: Send,
+        F: Fn(&mut UnitPipeline, &SourceFile) -> Result<T, CompilerError> + Sync + Send,
+    {
+        let workers = ParallelCompiler::new(self.context.options.jobs).map_err(CompilerError::Parallel)?;
+        let sink = DiagnosticsSink::new();
+
+        // Translation units are independent up to linking; each worker
+        // thread gets its own frontend/optimizer/backend
+        let target = &self.target;
+        let options = &self.context.options;
+        let results = workers.compile_all(
+            &self.context.source_files,
+            &sink,
+            || UnitPipeline::new(target, options),
+            |pipeline, index, source, sink| {
+                let pipeline = pipeline.as_mut().map_err(|e| {
+                    CompilerError::Parallel(ParallelError::WorkerInit(format!("{:?}", e)))
+                })?;
+                let result = compile(pipeline, source);
+                if let Err(e) = &result {
+                    let diagnostic = Diagnostic::error(format!("{}: {:?}", source.path().display(), e)).with_code(e.code());
+                    sink.report(index, diagnostic);
+                }
+                result
+            },
+        );
+
+        // Diagnostics and outputs in command-line order, whatever finished first
+        sink.flush_into(&mut self.diagnostics);
+        results.into_iter().collect()
+    }
+
+    fn lto_options(&self) -> LtoOptions {
+        let options = &self.context.options;
+        LtoOptions {
+            mode: options.lto,
+            optimization_level: options.opt_level.level(),
+            target_triple: options.target_triple.clone(),
+            target_cpu: options.target_cpu.clone(),
+            target_features: options.target_features.join(","),
+            relocation_model: match options.relocation_model {
+                RelocModel::Static => LLVMRelocMode::LLVMRelocStatic,
+                RelocModel::PIC => LLVMRelocMode::LLVMRelocPIC,
+                RelocModel::DynamicNoPIC => LLVMRelocMode::LLVMRelocDynamicNoPic,
+            },
+            code_model: match options.code_model {
+                CodeModel::Tiny => LLVMCodeModel::LLVMCodeModelTiny,
+                CodeModel::Small => LLVMCodeModel::LLVMCodeModelSmall,
+                CodeModel::Kernel => LLVMCodeModel::LLVMCodeModelKernel,
+                CodeModel::Medium => LLVMCodeModel::LLVMCodeModelMedium,
+                CodeModel::Large => LLVMCodeModel::LLVMCodeModelLarge,
+            },
+            // Anything may use the symbols of an object or library
+            preserve_all: !matches!(options.output_type, OutputType::Executable),
+            preserve: Vec::new(),
+            import_limit: DEFAULT_IMPORT_LIMIT,
+            jobs: options.jobs,
+            cache_dir: options.cache_dir.clone(),
+        }
+    }
+
+    fn run_optimization_pipeline(optimizer: &Optimizer, opt_level: OptLevel, ir: IR) -> Result<IR, CompilerError> {
         let mut current_ir = ir;
         
         // Run passes based on optimization level
-        match self.context.options.opt_level {
+        match opt_level {
             OptLevel::None => {
                 // Only run essential passes
-                current_ir = self.optimizer.run_pass(Pass::DCE, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::DCE, current_ir)?;
             },
             OptLevel::Less => {
                 // Basic optimizations
-                current_ir = self.optimizer.run_pass(Pass::DCE, current_ir)?;
-                current_ir = self.optimizer.run_pass(Pass::CSE, current_ir)?;
-                current_ir = self.optimizer.run_pass(Pass::Inline, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::DCE, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::CSE, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::Inline, current_ir)?;
             },
             OptLevel::Default => {
                 // Standard optimization pipeline
-                current_ir = self.optimizer.run_pass(Pass::DCE, current_ir)?;
-                current_ir = self.optimizer.run_pass(Pass::CSE, current_ir)?;
-                current_ir = self.optimizer.run_pass(Pass::Inline, current_ir)?;
-                current_ir = self.optimizer.run_pass(Pass::LoopOpt, current_ir)?;
-                current_ir = self.optimizer.run_pass(Pass::GVN, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::DCE, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::CSE, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::Inline, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::LoopOpt, current_ir)?;
+                current_ir = optimizer.run_pass(Pass::GVN, current_ir)?;
             },
             OptLevel::Aggressive => {
                 // All optimizations
-                current_ir = self.optimizer.run_aggressive_pipeline(current_ir)?;
+                current_ir = optimizer.run_aggressive_pipeline(current_ir)?;
             }
         }
         
@@ -133,6 +221,50 @@ impl CompilerDriver {
     }
 }
 
+/// Per-thread pipeline state for one worker
+#[cfg(feature = "llvm")]
+struct UnitPipeline {
+    frontend: Frontend,
+    optimizer: Optimizer,
+    backend: Backend,
+}
+
+#[cfg(feature = "llvm")]
+impl UnitPipeline {
+    fn new(target: &TargetInfo, options: &CompilerOptions) -> Result<Self, CompilerError> {
+        Ok(UnitPipeline {
+            frontend: Frontend::new(target)?,
+            optimizer: Optimizer::new(options)?,
+            backend: Backend::new(target)?,
+        })
+    }
+
+    fn compile_unit(&mut self, source: &SourceFile, opt_level: OptLevel) -> Result<ObjectFile, CompilerError> {
+        // 1. Parse and validate
+        let ast = self.frontend.parse(source)?;
+
+        // 2. Generate IR
+        let ir = self.frontend.generate_ir(&ast)?;
+
+        // 3. Run optimization passes
+        let optimized_ir = CompilerDriver::run_optimization_pipeline(&self.optimizer, opt_level, ir)?;
+
+        // 4. Generate code
+        self.backend.generate_code(&optimized_ir)
+    }
+
+    /// Parse and run the LTO pre-link pipeline; code is generated at link
+    /// time, from the bitcode
+    fn compile_unit_to_bitcode(&mut self, source: &SourceFile, opt_level: OptLevel, mode: LtoMode) -> Result<Vec<u8>, CompilerError> {
+        let ast = self.frontend.parse(source)?;
+        let ir = self.frontend.generate_ir(&ast)?;
+        unsafe {
+            lto::pre_link(ir.as_llvm_ref(), mode, opt_level.level()).map_err(CompilerError::Lto)?;
+            Ok(lto::write_bitcode(ir.as_llvm_ref()))
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct CompilerOptions {
     // Basic options
@@ -163,6 +295,14 @@ pub struct CompilerOptions {
     
     // Linker options
     pub linker_options: LinkerOptions,
+
+    // Translation units compiled concurrently; 0 = one per CPU
+    pub jobs: usize,
+
+    // Link-time optimization across the input files
+    pub lto: LtoMode,
+    // Where ThinLTO keeps module summaries between links; None recomputes them
+    pub cache_dir: Option<PathBuf>,
 }
 
 #[derive(Clone, Copy)]
@@ -180,6 +320,18 @@ pub enum OptLevel {
     Aggressive,
 }
 
+impl OptLevel {
+    /// As for `-O`
+    pub fn level(self) -> u32 {
+        match self {
+            OptLevel::None => 0,
+            OptLevel::Less => 1,
+            OptLevel::Default => 2,
+            OptLevel::Aggressive => 3,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum PICLevel {
     NotPIC,
@@ -212,6 +364,22 @@ pub enum CompilerError {
     IO(std::io::Error),
     Target(TargetError),
     Config(ConfigError),
+    Parallel(ParallelError),
+    #[cfg(feature = "llvm")]
+    Lto(LTOError),
+}
+
+impl CompilerError {
+    /// The stable code it's reported under, see `diagnostics::codes`
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompilerError::Frontend(_) => codes::E0018,
+            CompilerError::Linker(_) => codes::E0023,
+            CompilerError::Target(_) => codes::E0022,
+            CompilerError::IO(_) | CompilerError::Config(_) => codes::E0026,
+            _ => codes::E0024,
+        }
+    }
 }
 
 // Example usage:
@@ -235,6 +403,9 @@ fn main() -> Result<(), CompilerError> {
         inline_threshold: 225,
         unroll_threshold: 250,
         linker_options: LinkerOptions::default(),
+        jobs: 0,
+        lto: LtoMode::Thin,
+        cache_dir: None,
     };
 
     let mut compiler = CompilerDriver::new(options)?;
@@ -0,0 +1,139 @@
+// src/driver/parallel.rs
+//! Parallel compilation of independent translation units
+//! Units are compiled on a work-stealing rayon pool. Each worker thread owns
+//! its own pipeline state (LLVM contexts are not shareable), and diagnostics
+//! go to a shared sink tagged with the unit's position on the command line.
+//! Results and diagnostics are always handed back in input order, so
+//! `--jobs 8` prints exactly what `--jobs 1` prints.
+
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use crate::diagnostics::engine::{Diagnostic, DiagnosticsEngine, Recovery};
+
+/// `--jobs` default: one job per available CPU
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Diagnostics reported from worker threads, replayed in unit order
+#[derive(Default)]
+pub struct DiagnosticsSink {
+    // (unit index, per-unit sequence, diagnostic)
+    entries: Mutex<Vec<(usize, usize, Diagnostic)>>,
+}
+
+impl DiagnosticsSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report(&self, unit: usize, diagnostic: Diagnostic) {
+        let mut entries = self.entries.lock();
+        let seq = entries.iter().filter(|(u, _, _)| *u == unit).count();
+        entries.push((unit, seq, diagnostic));
+    }
+
+    /// Everything reported so far, ordered by unit then by report order
+    pub fn into_ordered(self) -> Vec<Diagnostic> {
+        let mut entries = self.entries.into_inner();
+        entries.sort_by_key(|(unit, seq, _)| (*unit, *seq));
+        entries.into_iter().map(|(_, _, d)| d).collect()
+    }
+
+    /// Replay into an engine; stops early if the engine hits its error limit
+    pub fn flush_into(self, engine: &mut DiagnosticsEngine) -> Recovery {
+        for diagnostic in self.into_ordered() {
+            if engine.report(diagnostic) == Recovery::Stop {
+                return Recovery::Stop;
+            }
+        }
+        Recovery::Continue
+    }
+}
+
+pub struct ParallelCompiler {
+    jobs: usize,
+    pool: rayon::ThreadPool,
+}
+
+impl ParallelCompiler {
+    /// `jobs == 0` means one per CPU
+    pub fn new(jobs: usize) -> Result<Self, ParallelError> {
+        let jobs = if jobs == 0 { default_jobs() } else { jobs };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .thread_name(|i| format!("cc-worker-{}", i))
+            .build()
+            .map_err(|e| ParallelError::Pool(e.to_string()))?;
+        Ok(ParallelCompiler { jobs, pool })
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    /// Run `compile` on every unit and return the results in input order.
+    ///
+    /// `init` builds the per-thread pipeline state; it runs at most once per
+    /// worker split, not once per unit.
+    pub fn compile_all<U, S, T, E, I, F>(
+        &self,
+        units: &[U],
+        sink: &DiagnosticsSink,
+        init: I,
+        compile: F,
+    ) -> Vec<Result<T, E>>
+    where
+        U: Sync,
+        T: Send,
+        E: Send,
+        I: Fn() -> S + Sync + Send,
+        F: Fn(&mut S, usize, &U, &DiagnosticsSink) -> Result<T, E> + Sync + Send,
+    {
+        // Nothing to win, and keeps single-file builds on the calling thread
+        if self.jobs == 1 || units.len() <= 1 {
+            let mut state = init();
+            return units
+                .iter()
+                .enumerate()
+                .map(|(i, unit)| compile(&mut state, i, unit, sink))
+                .collect();
+        }
+
+        self.pool.install(|| {
+            units
+                .par_iter()
+                .enumerate()
+                .map_init(&init, |state, (i, unit)| compile(state, i, unit, sink))
+                .collect()
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ParallelError {
+    Pool(String),
+    WorkerInit(String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ParallelError> {
+    let sources = vec![PathBuf::from("a.c"), PathBuf::from("b.c"), PathBuf::from("c.c")];
+    let compiler = ParallelCompiler::new(4)?;
+    let sink = DiagnosticsSink::new();
+
+    let objects = compiler.compile_all(&sources, &sink, Frontend::new, |frontend, i, source, sink| {
+        let unit = frontend.compile(source)?;
+        for warning in unit.warnings() {
+            sink.report(i, warning);
+        }
+        Ok(unit.object)
+    });
+
+    let mut engine = DiagnosticsEngine::new(DiagnosticsConfig::default());
+    sink.flush_into(&mut engine);
+    engine.flush_to_stderr();
+    Ok(())
+}
+*/
@@ -0,0 +1,223 @@
+// src/driver/repl.rs
+//! Interactive read-eval-print loop
+//! Each input is spliced into a synthesized translation unit: preprocessor
+//! lines, type definitions and functions go to file scope, local declarations
+//! are kept inside `main` for later inputs, and a bare expression becomes the
+//! value `main` returns.
+
+use std::io::{self, BufRead, Write};
+
+/// Compiles and runs a complete translation unit, returning `main`'s result
+pub type Evaluator = Box<dyn FnMut(&str) -> Result<i32, String>>;
+
+const TYPE_KEYWORDS: &[&str] = &[
+    "int", "char", "short", "long", "float", "double", "unsigned", "signed",
+    "_Bool", "bool", "const", "static", "auto", "size_t", "struct", "union", "enum",
+];
+
+const STATEMENT_KEYWORDS: &[&str] = &[
+    "if", "for", "while", "do", "switch", "return", "goto", "break", "continue",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Input {
+    /// Goes to file scope and is kept
+    TopLevel(String),
+    /// Local declaration, replayed in every later `main`
+    Declaration(String),
+    /// Executed once
+    Statement(String),
+    /// Evaluated and printed
+    Expression(String),
+}
+
+pub struct Repl {
+    // Accumulated session state
+    top_level: Vec<String>,
+    declarations: Vec<String>,
+
+    // Backend
+    evaluator: Evaluator,
+}
+
+impl Repl {
+    pub fn new(evaluator: Evaluator) -> Self {
+        Repl {
+            top_level: vec!["#include <stdio.h>".to_string()],
+            declarations: Vec::new(),
+            evaluator,
+        }
+    }
+
+    /// Run until EOF or `:quit`
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let mut stdout = io::stdout();
+
+        println!("c-interpreter REPL. Type :help for commands.");
+        loop {
+            print!("c> ");
+            stdout.flush()?;
+
+            let mut input = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+
+            // Keep reading until braces and parentheses balance
+            while !is_balanced(&input) {
+                print!("..> ");
+                stdout.flush()?;
+                match lines.next() {
+                    Some(line) => {
+                        input.push('\n');
+                        input.push_str(&line?);
+                    }
+                    None => break,
+                }
+            }
+
+            let trimmed = input.trim();
+            match trimmed {
+                "" => continue,
+                ":quit" | ":q" => break,
+                ":help" => {
+                    println!(":show   print the current translation unit");
+                    println!(":reset  forget all declarations");
+                    println!(":quit   leave the REPL");
+                }
+                ":show" => println!("{}", self.translation_unit(None)),
+                ":reset" => {
+                    self.top_level.truncate(1);
+                    self.declarations.clear();
+                }
+                _ => match self.eval(trimmed) {
+                    Ok(Some(value)) => println!("{}", value),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("{}", e),
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate one input. Returns the value for expressions.
+    pub fn eval(&mut self, input: &str) -> Result<Option<i32>, String> {
+        let classified = classify(input);
+
+        let body = match &classified {
+            Input::TopLevel(code) => {
+                self.top_level.push(code.clone());
+                let unit = self.translation_unit(Some("return 0;"));
+                // Validate before committing
+                if let Err(e) = (self.evaluator)(&unit) {
+                    self.top_level.pop();
+                    return Err(e);
+                }
+                return Ok(None);
+            }
+            Input::Declaration(code) | Input::Statement(code) => format!("{}\n    return 0;", code),
+            Input::Expression(expr) => format!("return (int)({});", expr),
+        };
+
+        let unit = self.translation_unit(Some(&body));
+        let value = (self.evaluator)(&unit)?;
+
+        match classified {
+            Input::Declaration(code) => {
+                self.declarations.push(code);
+                Ok(None)
+            }
+            Input::Expression(_) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    fn translation_unit(&self, body: Option<&str>) -> String {
+        let mut unit = self.top_level.join("\n");
+        unit.push_str("\n\nint main(void) {\n");
+        for decl in &self.declarations {
+            unit.push_str("    ");
+            unit.push_str(decl);
+            unit.push('\n');
+        }
+        if let Some(body) = body {
+            unit.push_str("    ");
+            unit.push_str(body);
+            unit.push('\n');
+        }
+        unit.push_str("}\n");
+        unit
+    }
+}
+
+fn classify(input: &str) -> Input {
+    let first_word = input
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .find(|w| !w.is_empty())
+        .unwrap_or("");
+
+    if input.starts_with('#') || first_word == "typedef" {
+        return Input::TopLevel(input.to_string());
+    }
+
+    let starts_with_type = TYPE_KEYWORDS.contains(&first_word) || first_word == "void";
+
+    // Function definitions and tag definitions belong at file scope
+    if starts_with_type && input.ends_with('}') {
+        return Input::TopLevel(input.to_string());
+    }
+    if matches!(first_word, "struct" | "union" | "enum") && input.contains('{') {
+        return Input::TopLevel(input.to_string());
+    }
+
+    if input.ends_with(';') || input.ends_with('}') {
+        if starts_with_type && !STATEMENT_KEYWORDS.contains(&first_word) {
+            Input::Declaration(input.to_string())
+        } else {
+            Input::Statement(input.to_string())
+        }
+    } else {
+        Input::Expression(input.to_string())
+    }
+}
+
+fn is_balanced(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for c in input.chars() {
+        if in_string {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => in_string = false,
+                _ => escaped = false,
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '(' => depth += 1,
+            '}' | ')' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth <= 0
+}
+
+// Example usage:
+/*
+fn main() -> io::Result<()> {
+    let mut repl = Repl::new(Box::new(|unit| jit_eval(unit, 0, "x86_64")));
+    repl.run()
+}
+
+// c> int square(int x) { return x * x; }
+// c> int n = 7;
+// c> square(n) + 1
+// 50
+*/
@@ -0,0 +1,357 @@
+// src/driver/usage.rs
+//! Local usage statistics
+//! An opt-in record of how this machine uses the compiler, for teams that
+//! want to know which commands and flags they rely on and which errors
+//! they hit most, without telemetry: each run appends one line to
+//! `runs.jsonl` in the usage directory and `c-interpreter stats` adds them
+//! up. Nothing leaves the machine, and nothing that identifies a program
+//! is kept: flags are stored by name without their values, errors by
+//! diagnostic code, and file names and source never reach the log.
+//!
+//! Recording is off until `stats --enable` creates the `enabled` marker
+//! next to the log; `--disable` removes it and keeps the runs, `--reset`
+//! deletes them. The directory is `C_INTERPRETER_USAGE_DIR`, else
+//! `c-interpreter/usage` under `XDG_DATA_HOME` or `~/.local/share`. Runs
+//! are appended in a single write to a file opened for appending, so
+//! concurrent invocations don't interleave.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::diagnostics::codes;
+
+/// Error key for an error diagnostic without a code
+pub const UNCODED: &str = "uncoded";
+/// Error key for a run the driver ended early, after printing `Error: ...`
+pub const FATAL: &str = "fatal";
+/// Error key for a run that failed with an I/O error
+pub const IO: &str = "io";
+
+const ENABLED: &str = "enabled";
+const RUNS: &str = "runs.jsonl";
+
+/// One invocation, as stored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Seconds since the Unix epoch when the run started
+    pub timestamp: u64,
+    /// `jit`, `interpret`, `compile`, or the subcommand
+    pub command: String,
+    pub duration_ms: u64,
+    /// The process's exit status; `None` when it exited somewhere else
+    pub exit: Option<i32>,
+    /// Flags as given on the command line, without their values
+    pub flags: Vec<String>,
+    /// Diagnostic codes of the errors reported, or one of `UNCODED`,
+    /// `FATAL` and `IO`
+    pub errors: Vec<String>,
+    pub version: String,
+}
+
+/// The usage directory
+#[derive(Debug, Clone)]
+pub struct UsageStore {
+    root: PathBuf,
+}
+
+impl UsageStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        UsageStore { root: root.into() }
+    }
+
+    /// `C_INTERPRETER_USAGE_DIR`, else `c-interpreter/usage` under the XDG
+    /// data directory
+    pub fn default_root() -> PathBuf {
+        if let Some(dir) = std::env::var_os("C_INTERPRETER_USAGE_DIR") {
+            return PathBuf::from(dir);
+        }
+        let base = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+            .unwrap_or_else(std::env::temp_dir);
+        base.join("c-interpreter").join("usage")
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.root.join(ENABLED).is_file()
+    }
+
+    pub fn enable(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.root)?;
+        fs::write(
+            self.root.join(ENABLED),
+            "Local usage statistics are recorded while this file exists; see `c-interpreter stats`.\n",
+        )
+    }
+
+    /// Stop recording; the runs so far are kept
+    pub fn disable(&self) -> io::Result<()> {
+        remove_if_present(&self.root.join(ENABLED))
+    }
+
+    /// Delete every recorded run
+    pub fn reset(&self) -> io::Result<()> {
+        remove_if_present(&self.root.join(RUNS))
+    }
+
+    pub fn append(&self, record: &UsageRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(self.root.join(RUNS))?;
+        file.write_all(&line)
+    }
+
+    /// Every run recorded, oldest first; lines that don't parse, such as
+    /// those of another version, are skipped
+    pub fn records(&self) -> io::Result<Vec<UsageRecord>> {
+        let file = match fs::File::open(self.root.join(RUNS)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Ok(record) = serde_json::from_str(&line?) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The run this process is recording
+struct Session {
+    /// A forked child inherits the session and must not record it
+    pid: u32,
+    started: Instant,
+    record: UsageRecord,
+    store: UsageStore,
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+static AT_EXIT: Once = Once::new();
+
+/// Start recording this process's run, if recording is enabled
+pub fn begin(command: &str, flags: Vec<String>) {
+    let store = UsageStore::new(UsageStore::default_root());
+    if !store.is_enabled() {
+        return;
+    }
+    let record = UsageRecord {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        command: command.to_string(),
+        duration_ms: 0,
+        exit: None,
+        flags,
+        errors: Vec::new(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    *SESSION.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some(Session { pid: std::process::id(), started: Instant::now(), record, store });
+
+    // The driver's error paths end in `process::exit`, which skips
+    // destructors but not atexit handlers
+    AT_EXIT.call_once(|| unsafe {
+        libc::atexit(finish_at_exit);
+    });
+}
+
+/// Name the run's command once it is known
+pub fn set_command(command: &str) {
+    if let Some(session) = SESSION.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        session.record.command = command.to_string();
+    }
+}
+
+/// Count an error reported during the run, by code or error key
+pub fn note_error(key: &str) {
+    if let Some(session) = SESSION.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        session.record.errors.push(key.to_string());
+    }
+}
+
+/// Record the run as exiting with `code`
+pub fn finish(code: i32) {
+    finish_with(Some(code));
+}
+
+extern "C" fn finish_at_exit() {
+    finish_with(None);
+}
+
+fn finish_with(exit: Option<i32>) {
+    let Some(mut session) = SESSION.lock().unwrap_or_else(|e| e.into_inner()).take() else { return };
+    if session.pid != std::process::id() {
+        return;
+    }
+    session.record.duration_ms = session.started.elapsed().as_millis() as u64;
+    session.record.exit = exit;
+    // Only the driver exits without going through `finish`, after an error
+    if exit.is_none() && session.record.errors.is_empty() {
+        session.record.errors.push(FATAL.to_string());
+    }
+    if let Err(e) = session.store.append(&session.record) {
+        log::debug!("could not record usage in '{}': {}", session.store.root().display(), e);
+    }
+}
+
+/// Runs of one command
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CommandUsage {
+    pub command: String,
+    pub runs: usize,
+    /// Runs that reported an error
+    pub failed: usize,
+    pub total_ms: u64,
+    pub max_ms: u64,
+}
+
+impl CommandUsage {
+    pub fn mean_ms(&self) -> u64 {
+        self.total_ms / self.runs.max(1) as u64
+    }
+}
+
+/// The recorded runs added up
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UsageSummary {
+    pub runs: usize,
+    pub failed: usize,
+    /// Timestamps of the first and last run
+    pub first: Option<u64>,
+    pub last: Option<u64>,
+    /// Most runs first
+    pub commands: Vec<CommandUsage>,
+    /// (flag, runs using it), most used first
+    pub flags: Vec<(String, usize)>,
+    /// (error key, times reported), most frequent first
+    pub errors: Vec<(String, usize)>,
+}
+
+impl UsageSummary {
+    /// Add up `records`, keeping the `top` most used flags and most
+    /// frequent errors
+    pub fn from_records(records: &[UsageRecord], top: usize) -> Self {
+        let mut summary = UsageSummary::default();
+        let mut commands: HashMap<&str, CommandUsage> = HashMap::new();
+        let mut flags: HashMap<&str, usize> = HashMap::new();
+        let mut errors: HashMap<&str, usize> = HashMap::new();
+
+        for record in records {
+            summary.runs += 1;
+            summary.first = Some(summary.first.map_or(record.timestamp, |first| first.min(record.timestamp)));
+            summary.last = Some(summary.last.map_or(record.timestamp, |last| last.max(record.timestamp)));
+            let failed = !record.errors.is_empty();
+            summary.failed += failed as usize;
+
+            let command = commands
+                .entry(&record.command)
+                .or_insert_with(|| CommandUsage { command: record.command.clone(), ..CommandUsage::default() });
+            command.runs += 1;
+            command.failed += failed as usize;
+            command.total_ms += record.duration_ms;
+            command.max_ms = command.max_ms.max(record.duration_ms);
+
+            // A flag given twice in one run counts once
+            let mut seen: Vec<&str> = record.flags.iter().map(String::as_str).collect();
+            seen.sort_unstable();
+            seen.dedup();
+            for flag in seen {
+                *flags.entry(flag).or_insert(0) += 1;
+            }
+            for error in &record.errors {
+                *errors.entry(error).or_insert(0) += 1;
+            }
+        }
+
+        summary.commands = commands.into_values().collect();
+        summary.commands.sort_by(|a, b| b.runs.cmp(&a.runs).then_with(|| a.command.cmp(&b.command)));
+        summary.flags = ranked(flags, top);
+        summary.errors = ranked(errors, top);
+        summary
+    }
+}
+
+fn ranked(counts: HashMap<&str, usize>, top: usize) -> Vec<(String, usize)> {
+    let mut ranked: Vec<(String, usize)> = counts.into_iter().map(|(key, count)| (key.to_string(), count)).collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ranked.truncate(top);
+    ranked
+}
+
+impl fmt::Display for UsageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.runs == 0 {
+            return write!(f, "No runs recorded");
+        }
+        let days = match (self.first, self.last) {
+            (Some(first), Some(last)) => (last - first) / 86_400 + 1,
+            _ => 1,
+        };
+        writeln!(f, "{} run(s) over {} day(s), {} with errors", self.runs, days, self.failed)?;
+
+        writeln!(f, "\nCommands:")?;
+        for command in &self.commands {
+            let name = if command.command.is_empty() { "-" } else { command.command.as_str() };
+            writeln!(
+                f,
+                "  {:<16} {:>6} run(s)  mean {:>8.3}s  max {:>8.3}s  {} failed",
+                name,
+                command.runs,
+                command.mean_ms() as f64 / 1000.0,
+                command.max_ms as f64 / 1000.0,
+                command.failed
+            )?;
+        }
+
+        if !self.flags.is_empty() {
+            writeln!(f, "\nMost used flags:")?;
+            for (flag, runs) in &self.flags {
+                writeln!(f, "  {:<28} {:>6} run(s)", flag, runs)?;
+            }
+        }
+
+        if !self.errors.is_empty() {
+            writeln!(f, "\nMost frequent errors:")?;
+            for (key, count) in &self.errors {
+                let title = match key.as_str() {
+                    UNCODED => "error without a code",
+                    FATAL => "the driver stopped with an error",
+                    IO => "I/O error",
+                    code => codes::lookup(code).map_or("", |info| info.title),
+                };
+                writeln!(f, "  {:<8} {:>6}x  {}", key, count, title)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Example usage:
+/*
+fn show_usage() -> io::Result<()> {
+    let store = UsageStore::new(UsageStore::default_root());
+    if !store.is_enabled() {
+        println!("Usage statistics are off; `c-interpreter stats --enable` turns them on");
+    }
+    let summary = UsageSummary::from_records(&store.records()?, 10);
+    println!("{}", summary);
+    Ok(())
+}
+*/
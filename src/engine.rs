@@ -0,0 +1,415 @@
+// src/engine.rs
+//! Embedding API
+//! An `Engine` owns a JIT compiler for the host. Each `eval_string` compiles
+//! one translation unit into it; functions and globals it defines stay
+//! available to later units and to `call_function`, `get_global` and
+//! `set_global` for as long as the engine lives. Compiled code runs on the
+//! calling thread, in this process: a crash or `exit()` in C takes the
+//! application with it, as with any linked C library.
+//!
+//! Calls follow the host's C ABI (System V on x86_64, AAPCS64 on aarch64),
+//! including arguments passed on the stack and structs and unions passed
+//! and returned by value; see `abi::aggregate`. The C prototype isn't known
+//! at runtime; matching it is the caller's job.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::abi::aggregate::{marshal, Abi, AbiError, Argument, CType, Scalar};
+use crate::abi::call;
+use crate::abi::varargs::marshal_variadic;
+use crate::compiler::{CompilerError, CompilerSystem, JITOptions};
+use crate::debug::stack_capture::JitSymbols;
+use crate::jit::host::{HostExport, HostFunction, HostSignature};
+use crate::jit::probes::ProbeTable;
+use crate::jit::stackmap::StackMaps;
+use crate::jit::JITValue;
+use crate::options::Options;
+use crate::runtime::deterministic::DeterministicConfig;
+use crate::runtime::virtual_time::{self, VirtualTime};
+
+/// How an `Engine` compiles the code it is given
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EngineOptions {
+    /// 0-3, as for `-O`
+    pub optimization_level: u32,
+    /// Shared libraries the C code may call into, as for `-l`
+    pub libraries: Vec<String>,
+    /// Directories searched for `libraries` first, as for `-L`
+    pub library_paths: Vec<String>,
+    /// Trap undefined behaviour at runtime, as for `--sanitize=undefined`
+    pub sanitize_undefined: bool,
+    /// Compile functions so probes can be attached to them; see `Engine::probes`
+    pub patchable_prologues: bool,
+    /// Run the same way every time: fixed `srand` seed, virtual clock and
+    /// ordered threads; see `runtime::deterministic`. Stable addresses also
+    /// need `deterministic::disable_aslr` at startup.
+    pub deterministic: Option<DeterministicConfig>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            optimization_level: 2,
+            libraries: Vec::new(),
+            library_paths: Vec::new(),
+            sanitize_undefined: false,
+            patchable_prologues: false,
+            deterministic: None,
+        }
+    }
+}
+
+pub struct Engine {
+    // Compilation
+    compiler: CompilerSystem,
+    options: EngineOptions,
+
+    // Statistics
+    units_compiled: usize,
+}
+
+impl Engine {
+    pub fn new() -> Result<Self, EngineError> {
+        Self::with_options(EngineOptions::default())
+    }
+
+    pub fn with_options(options: EngineOptions) -> Result<Self, EngineError> {
+        if !cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
+            return Err(EngineError::UnsupportedHost(std::env::consts::ARCH));
+        }
+        let triple = crate::options::target_triple(std::env::consts::ARCH);
+        let compiler = unsafe { CompilerSystem::new(triple) }.map_err(EngineError::Compile)?;
+        Ok(Engine { compiler, options, units_compiled: 0 })
+    }
+
+    /// Compile `source` as one translation unit. If it defines `main`, `main`
+    /// runs with no arguments and its result is returned.
+    pub fn eval_string(&mut self, source: &str) -> Result<Option<i32>, EngineError> {
+        let had_main = self.symbol_address("main").is_some();
+        unsafe { self.compiler.jit_compile(source, &self.jit_options()) }.map_err(EngineError::Compile)?;
+        self.units_compiled += 1;
+
+        // Only a `main` this unit introduced is run
+        let main = match self.symbol_address("main") {
+            Some(address) if !had_main => address,
+            _ => return Ok(None),
+        };
+        let main: extern "C" fn(i32, *const *const i8) -> i32 = unsafe { std::mem::transmute(main) };
+        let argv: [*const i8; 1] = [std::ptr::null()];
+        Ok(Some(main(0, argv.as_ptr())))
+    }
+
+    /// `eval_string` on the contents of `path`
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Option<i32>, EngineError> {
+        let source = fs::read_to_string(path.as_ref()).map_err(EngineError::Io)?;
+        self.eval_string(&source)
+    }
+
+    /// Call the C function `name` and convert its result to `T`.
+    ///
+    /// # Safety
+    /// `args` and `T` must match the function's prototype: the right number
+    /// of arguments, each of the type the function expects.
+    pub unsafe fn call_function<T: ReturnValue>(&self, name: &str, args: &[JITValue]) -> Result<T, EngineError> {
+        let args: Vec<Argument> = args.iter().map(|&arg| Argument::Value(arg)).collect();
+        // Narrower results are read from the low bits of the register
+        let result = CType::Scalar(match T::CLASS {
+            ReturnClass::Integer => Scalar::I64,
+            ReturnClass::Float => Scalar::F64,
+        });
+        let bytes = self.call_function_bytes(name, &args, Some(&result))?;
+        let raw = u64::from_le_bytes(bytes[..8].try_into().expect("an 8-byte scalar result"));
+        Ok(T::from_raw(raw))
+    }
+
+    /// Call the C function `name`, which returns a struct or union of type
+    /// `result`, and read the result as `T`, a `#[repr(C)]` Rust type with
+    /// the same layout. Aggregate arguments are built with
+    /// `Argument::aggregate`.
+    ///
+    /// # Safety
+    /// As for `call_function`; `T` must also be valid for any bytes the
+    /// function can return.
+    pub unsafe fn call_function_struct<T: Copy>(&self, name: &str, args: &[Argument], result: &CType) -> Result<T, EngineError> {
+        if std::mem::size_of::<T>() != result.size() {
+            return Err(EngineError::Abi(AbiError::SizeMismatch {
+                expected: result.size(),
+                found: std::mem::size_of::<T>(),
+            }));
+        }
+        let bytes = self.call_function_bytes(name, args, Some(result))?;
+        Ok(std::ptr::read_unaligned(bytes.as_ptr() as *const T))
+    }
+
+    /// Call the C function `name` and return its result's bytes, laid out
+    /// as `result` (`None` for `void`, which returns no bytes).
+    ///
+    /// # Safety
+    /// `args` and `result` must match the function's prototype.
+    pub unsafe fn call_function_bytes(&self, name: &str, args: &[Argument], result: Option<&CType>) -> Result<Vec<u8>, EngineError> {
+        let address = self.symbol_address(name).ok_or_else(|| EngineError::SymbolNotFound(name.to_string()))?;
+        let abi = Abi::host().ok_or(EngineError::UnsupportedHost(std::env::consts::ARCH))?;
+        let call = marshal(abi, args, result)?;
+        let mut bytes = call::invoke(address, &call)?;
+        // Registers come back whole; keep the bytes the type covers
+        bytes.truncate(result.map_or(0, CType::size));
+        Ok(bytes)
+    }
+
+    /// `call_function_bytes` for a variadic function with `fixed` named
+    /// parameters. The arguments after them get C's default argument
+    /// promotions, so a `JITValue::Float` arrives as a `double`.
+    ///
+    /// # Safety
+    /// As for `call_function_bytes`.
+    pub unsafe fn call_function_variadic(
+        &self,
+        name: &str,
+        args: &[Argument],
+        fixed: usize,
+        result: Option<&CType>,
+    ) -> Result<Vec<u8>, EngineError> {
+        let address = self.symbol_address(name).ok_or_else(|| EngineError::SymbolNotFound(name.to_string()))?;
+        let abi = Abi::host().ok_or(EngineError::UnsupportedHost(std::env::consts::ARCH))?;
+        let call = marshal_variadic(abi, args, fixed, result)?;
+        let mut bytes = call::invoke(address, &call)?;
+        bytes.truncate(result.map_or(0, CType::size));
+        Ok(bytes)
+    }
+
+    /// Read the global variable `name`.
+    ///
+    /// # Safety
+    /// `T` must be the variable's C type, or one with the same layout.
+    pub unsafe fn get_global<T: Copy>(&self, name: &str) -> Result<T, EngineError> {
+        let address = self.symbol_address(name).ok_or_else(|| EngineError::SymbolNotFound(name.to_string()))?;
+        Ok(std::ptr::read_unaligned(address as *const T))
+    }
+
+    /// Overwrite the global variable `name`.
+    ///
+    /// # Safety
+    /// As for `get_global`; the variable must also not be `const`.
+    pub unsafe fn set_global<T: Copy>(&mut self, name: &str, value: T) -> Result<(), EngineError> {
+        let address = self.symbol_address(name).ok_or_else(|| EngineError::SymbolNotFound(name.to_string()))?;
+        std::ptr::write_unaligned(address as *mut T, value);
+        Ok(())
+    }
+
+    /// Let C code compiled from now on call `function` as `name`. The C
+    /// side declares a prototype matching `signature`.
+    pub fn register_host_function(
+        &mut self,
+        name: &str,
+        signature: HostSignature,
+        function: HostFunction,
+    ) -> Result<(), EngineError> {
+        self.compiler
+            .register_host_function(name, signature, function)
+            .map_err(EngineError::Compile)
+    }
+
+    /// `register_host_function` for a Rust closure over `Value`s
+    pub fn register_host_closure(
+        &mut self,
+        name: &str,
+        signature: HostSignature,
+        closure: impl Fn(&[JITValue]) -> JITValue + Send + Sync + 'static,
+    ) -> Result<(), EngineError> {
+        self.register_host_function(name, signature, HostFunction::Closure(Box::new(closure)))
+    }
+
+    /// Register functions marked `#[c_export]`, usually as `c_exports![...]`.
+    /// Stops at the first name that is already taken.
+    pub fn register_exports(&mut self, exports: impl IntoIterator<Item = HostExport>) -> Result<(), EngineError> {
+        for export in exports {
+            self.register_host_function(export.name, export.signature, export.function)?;
+        }
+        Ok(())
+    }
+
+    /// Bind the clock, sleep and `alarm`/`setitimer` calls of C code
+    /// compiled from now on to `time`, which the application can pause,
+    /// scale and advance; see `runtime::virtual_time`. Takes the place of
+    /// the `deterministic` clock. Fails if called twice.
+    pub fn use_virtual_time(&mut self, time: Arc<VirtualTime>) -> Result<(), EngineError> {
+        self.register_exports(virtual_time::host_exports(&time))
+    }
+
+    /// Address of a function or global defined by a unit compiled so far
+    pub fn symbol_address(&self, name: &str) -> Option<*mut u8> {
+        unsafe { self.compiler.jit_symbol_address(name) }
+    }
+
+    /// Safepoints of the units compiled so far. A host closure that runs a
+    /// precise collection captures this and passes it to `for_each_root`
+    /// to find the managed pointers C frames hold.
+    pub fn stack_maps(&self) -> Arc<RwLock<StackMaps>> {
+        self.compiler.stack_maps()
+    }
+
+    /// Functions of the units compiled with `patchable_prologues`. Attach
+    /// probes with `jit::probes::LocalMemory`; calls already running are
+    /// unaffected, later ones are counted.
+    pub fn probes(&self) -> Arc<RwLock<ProbeTable>> {
+        self.compiler.probes()
+    }
+
+    /// Where the compiled functions start. Give it to
+    /// `debug::stack_capture::ThreadHandle::with_jit_symbols` so captured
+    /// stacks name them.
+    pub fn jit_symbols(&self) -> Arc<RwLock<JitSymbols>> {
+        self.compiler.jit_symbols()
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    /// Translation units compiled
+    pub fn stats(&self) -> usize {
+        self.units_compiled
+    }
+
+    fn jit_options(&self) -> JITOptions {
+        Options::from(&self.options).jit_options()
+    }
+}
+
+/// Where a C function leaves its result
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnClass {
+    Integer,
+    Float,
+}
+
+/// Rust types a C function's result can be read as
+pub trait ReturnValue: Sized {
+    #[doc(hidden)]
+    const CLASS: ReturnClass;
+    #[doc(hidden)]
+    fn from_raw(raw: u64) -> Self;
+}
+
+macro_rules! integer_return_value {
+    ($($ty:ty),*) => {
+        $(impl ReturnValue for $ty {
+            const CLASS: ReturnClass = ReturnClass::Integer;
+            fn from_raw(raw: u64) -> Self {
+                raw as $ty
+            }
+        })*
+    };
+}
+
+integer_return_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl ReturnValue for () {
+    const CLASS: ReturnClass = ReturnClass::Integer;
+    fn from_raw(_: u64) -> Self {}
+}
+
+impl ReturnValue for bool {
+    const CLASS: ReturnClass = ReturnClass::Integer;
+    fn from_raw(raw: u64) -> Self {
+        raw as u8 != 0
+    }
+}
+
+impl ReturnValue for f32 {
+    const CLASS: ReturnClass = ReturnClass::Float;
+    fn from_raw(raw: u64) -> Self {
+        f32::from_bits(raw as u32)
+    }
+}
+
+impl ReturnValue for f64 {
+    const CLASS: ReturnClass = ReturnClass::Float;
+    fn from_raw(raw: u64) -> Self {
+        f64::from_bits(raw)
+    }
+}
+
+impl<T> ReturnValue for *mut T {
+    const CLASS: ReturnClass = ReturnClass::Integer;
+    fn from_raw(raw: u64) -> Self {
+        raw as usize as *mut T
+    }
+}
+
+impl<T> ReturnValue for *const T {
+    const CLASS: ReturnClass = ReturnClass::Integer;
+    fn from_raw(raw: u64) -> Self {
+        raw as usize as *const T
+    }
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    Compile(CompilerError),
+    Io(io::Error),
+    /// No compiled unit defines a function or global by this name
+    SymbolNotFound(String),
+    /// More arguments than a call can carry
+    TooManyArguments,
+    VoidArgument,
+    /// A struct or union that can't be passed or returned
+    Abi(AbiError),
+    /// Only x86_64 and aarch64 hosts can run JIT-compiled code in process
+    UnsupportedHost(&'static str),
+}
+
+impl From<AbiError> for EngineError {
+    fn from(error: AbiError) -> Self {
+        match error {
+            AbiError::TooManyArguments => EngineError::TooManyArguments,
+            AbiError::VoidArgument => EngineError::VoidArgument,
+            AbiError::UnsupportedHost => EngineError::UnsupportedHost(std::env::consts::ARCH),
+            error => EngineError::Abi(error),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn embed() -> Result<(), EngineError> {
+    let mut options = EngineOptions::default();
+    options.libraries.push("m".to_string());
+    let mut engine = Engine::with_options(options)?;
+
+    engine.eval_string(r#"
+        #include <math.h>
+        double threshold = 0.5;
+        int above(double x) { return sqrt(x) > threshold; }
+    "#)?;
+
+    unsafe {
+        engine.set_global("threshold", 2.0f64)?;
+        let hit: bool = engine.call_function("above", &[JITValue::Double(9.0)])?;
+        let threshold: f64 = engine.get_global("threshold")?;
+        println!("{} {}", hit, threshold); // true 2
+    }
+
+    // Structs by value, as a #[repr(C)] mirror of the C type
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Point { x: f64, y: f64 }
+    engine.eval_string("struct point { double x, y; }; struct point mid(struct point a, struct point b) { return (struct point){ (a.x + b.x) / 2, (a.y + b.y) / 2 }; }")?;
+    let point = CType::Struct(vec![CType::Scalar(Scalar::F64), CType::Scalar(Scalar::F64)]);
+    let (a, b) = (Point { x: 0.0, y: 0.0 }, Point { x: 4.0, y: 2.0 });
+    let m: Point = unsafe {
+        engine.call_function_struct("mid", &[Argument::aggregate(&point, &a), Argument::aggregate(&point, &b)], &point)?
+    };
+    println!("{} {}", m.x, m.y); // 2 1
+
+    // A unit with a main runs it
+    let status = engine.eval_string("int main(void) { return 7; }")?;
+    assert_eq!(status, Some(7));
+    Ok(())
+}
+*/
@@ -0,0 +1,376 @@
+// src/frontend/apple.rs
+//! Clang extensions used by Apple SDK headers
+//! Runs on preprocessed source, before parsing, so the macOS SDK's
+//! <stdio.h>, <stdlib.h> and friends get through the C parser:
+//!  - nullability qualifiers (`_Nullable`, `_Nonnull`, ...) are dropped
+//!  - availability and Swift/ObjC bridging attributes are dropped
+//!  - `__builtin_available(...)` / `@available(...)` become `1`; JIT'd code
+//!    always runs on the host it was compiled on
+//!  - block pointer types (`int (^)(int)`) in declarations become opaque
+//!    `void *`, so prototypes such as `qsort_b` parse
+//!  - block literals (`^{ ... }`) are reported as unsupported, since calling
+//!    a block needs the Blocks runtime ABI
+
+use crate::diagnostics::catalog::MessageId;
+use crate::diagnostics::engine::{Diagnostic, Severity, SourceLocation};
+
+const NULLABILITY: &[&str] = &[
+    "_Nullable", "_Nonnull", "_Null_unspecified", "_Nullable_result",
+    "__nullable", "__nonnull", "__null_unspecified",
+];
+
+/// Attributes with no effect on C code generation
+const IGNORED_ATTRIBUTES: &[&str] = &[
+    "availability", "swift_name", "swift_private", "swift_attr", "swift_async",
+    "swift_async_name", "swift_error", "objc_bridge", "objc_bridge_mutable",
+    "objc_returns_inner_pointer", "ns_returns_retained", "ns_returns_not_retained",
+    "cf_returns_retained", "cf_returns_not_retained", "cf_consumed", "ns_consumed",
+    "enum_extensibility", "flag_enum", "noescape",
+];
+
+/// Storage qualifier that only matters for variables captured by blocks
+const BLOCK_STORAGE: &str = "__block";
+
+/// Macros Apple headers test for, in addition to the target's usual set
+pub fn predefined_macros() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("__APPLE__", "1"),
+        ("__MACH__", "1"),
+        ("__APPLE_CC__", "6000"),
+        // Block prototypes are guarded by this; we accept them as opaque pointers
+        ("__BLOCKS__", "1"),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tok {
+    Ident,
+    Punct,
+    Literal,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: Tok,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionStats {
+    pub nullability_removed: usize,
+    pub attributes_removed: usize,
+    pub availability_checks: usize,
+    pub block_types: usize,
+}
+
+#[derive(Debug)]
+pub struct Normalized {
+    pub source: String,
+    pub stats: ExtensionStats,
+    /// One error per block literal; the caller decides whether to stop
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Rewrite Apple/clang extensions in `source` into plain C
+pub fn normalize(source: &str, file: &str) -> Normalized {
+    let tokens = tokenize(source);
+    let text = |t: &Token| &source[t.start..t.end];
+    let is = |i: usize, s: &str| tokens.get(i).map_or(false, |t| text(t) == s);
+
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    let mut stats = ExtensionStats::default();
+    let mut diagnostics = Vec::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let tok = tokens[i];
+        let word = text(&tok);
+
+        if tok.kind == Tok::Ident && (NULLABILITY.contains(&word) || word == BLOCK_STORAGE) {
+            edits.push((tok.start, tok.end, String::new()));
+            stats.nullability_removed += 1;
+            i += 1;
+            continue;
+        }
+
+        // __attribute__((a, b(...), c))
+        if word == "__attribute__" && is(i + 1, "(") && is(i + 2, "(") {
+            if let Some(close) = matching(&tokens, source, i + 1) {
+                let inner_end = close - 1; // the inner ')'
+                let items = split_top_level(&tokens, source, i + 3, inner_end);
+                let kept: Vec<String> = items
+                    .iter()
+                    .filter(|(s, e)| {
+                        let name = text(&tokens[*s]).trim_matches('_');
+                        s < e && !IGNORED_ATTRIBUTES.contains(&name)
+                    })
+                    .map(|(s, e)| source[tokens[*s].start..tokens[*e - 1].end].to_string())
+                    .collect();
+
+                if kept.len() != items.len() {
+                    stats.attributes_removed += items.len() - kept.len();
+                    let replacement = if kept.is_empty() {
+                        String::new()
+                    } else {
+                        format!("__attribute__(({}))", kept.join(", "))
+                    };
+                    edits.push((tok.start, tokens[close].end, replacement));
+                }
+                i = close + 1;
+                continue;
+            }
+        }
+
+        // __builtin_available(macos 10.15, *) and @available(...)
+        let availability = if word == "__builtin_available" && is(i + 1, "(") {
+            Some((tok.start, i + 1))
+        } else if word == "@" && is(i + 1, "available") && is(i + 2, "(") {
+            Some((tok.start, i + 2))
+        } else {
+            None
+        };
+        if let Some((start, open)) = availability {
+            if let Some(close) = matching(&tokens, source, open) {
+                edits.push((start, tokens[close].end, "1".to_string()));
+                stats.availability_checks += 1;
+                i = close + 1;
+                continue;
+            }
+        }
+
+        if word == "^" {
+            // Block pointer declarator: `( ^ [name] ) ( params )`
+            if i > 0 && is(i - 1, "(") {
+                if let Some(edit) = block_type_edit(&tokens, source, i - 1) {
+                    edits.push(edit.0);
+                    stats.block_types += 1;
+                    i = edit.1;
+                    continue;
+                }
+            }
+
+            // Unary `^` starts a block literal
+            let unary = i == 0 || {
+                let prev = tokens[i - 1];
+                let prev_text = text(&prev);
+                match prev.kind {
+                    Tok::Ident => prev_text == "return",
+                    Tok::Literal => false,
+                    Tok::Punct => prev_text != ")" && prev_text != "]",
+                }
+            };
+            if unary {
+                let (line, column) = line_column(source, tok.start);
+                diagnostics.push(
+                    Diagnostic::catalogued(Severity::Error, MessageId::BlockLiteral, Vec::new())
+                        .at(SourceLocation { file: file.to_string(), line, column })
+                        .with_note("pass a function pointer to the non-block variant (e.g. qsort instead of qsort_b)"),
+                );
+            }
+        }
+
+        i += 1;
+    }
+
+    Normalized {
+        source: apply_edits(source, edits),
+        stats,
+        diagnostics,
+    }
+}
+
+/// Replace the whole parameter or declaration around `(^name)(...)` with
+/// `void *name`, keeping a leading `typedef`/storage class
+fn block_type_edit(tokens: &[Token], source: &str, open: usize) -> Option<((usize, usize, String), usize)> {
+    let text = |i: usize| &source[tokens[i].start..tokens[i].end];
+
+    let close = matching(tokens, source, open)?;
+    let name = (open + 2..close)
+        .rev()
+        .find(|&j| tokens[j].kind == Tok::Ident && !NULLABILITY.contains(&text(j)))
+        .map(|j| text(j).to_string());
+
+    // The parameter list of the block type
+    if close + 1 >= tokens.len() || text(close + 1) != "(" {
+        return None;
+    }
+    let params_close = matching(tokens, source, close + 1)?;
+
+    // Back to the start of this declaration or parameter
+    let mut start = open;
+    let mut depth = 0i32;
+    while start > 0 {
+        let prev = text(start - 1);
+        match prev {
+            ")" | "]" => depth += 1,
+            "(" | "[" if depth == 0 => break,
+            "(" | "[" => depth -= 1,
+            "," | ";" | "{" | "}" if depth == 0 => break,
+            _ => {}
+        }
+        start -= 1;
+    }
+
+    let mut prefix = String::new();
+    for j in start..open {
+        if matches!(text(j), "typedef" | "extern" | "static" | "register") {
+            prefix.push_str(text(j));
+            prefix.push(' ');
+        }
+    }
+    let replacement = match name {
+        Some(name) => format!("{}void *{}", prefix, name),
+        None => format!("{}void *", prefix),
+    };
+    Some(((tokens[start].start, tokens[params_close].end, replacement), params_close + 1))
+}
+
+/// Index of the `)` matching the `(` at `open`
+fn matching(tokens: &[Token], source: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (j, tok) in tokens.iter().enumerate().skip(open) {
+        match &source[tok.start..tok.end] {
+            "(" => depth += 1,
+            ")" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(j);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Comma-separated items in tokens[from..to] at nesting depth 0
+fn split_top_level(tokens: &[Token], source: &str, from: usize, to: usize) -> Vec<(usize, usize)> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = from;
+    for j in from..to {
+        match &source[tokens[j].start..tokens[j].end] {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            "," if depth == 0 => {
+                items.push((start, j));
+                start = j + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < to {
+        items.push((start, to));
+    }
+    items
+}
+
+fn apply_edits(source: &str, mut edits: Vec<(usize, usize, String)>) -> String {
+    edits.sort_by_key(|e| e.0);
+    let mut out = String::with_capacity(source.len());
+    let mut pos = 0;
+    for (start, end, replacement) in edits {
+        // Overlapping edits come from nested constructs; the outer one wins
+        if start < pos {
+            continue;
+        }
+        out.push_str(&source[pos..start]);
+        out.push_str(&replacement);
+        pos = end;
+    }
+    out.push_str(&source[pos..]);
+    out
+}
+
+fn line_column(source: &str, offset: usize) -> (u32, u32) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() as u32 + 1;
+    let column = (offset - before.rfind('\n').map_or(0, |n| n + 1)) as u32 + 1;
+    (line, column)
+}
+
+/// Tokens with byte spans; comments and `#` line markers are skipped
+fn tokenize(source: &str) -> Vec<Token> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line_start = true;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        if c == b'\n' {
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == b'#' && line_start {
+            // Line markers from the preprocessor: # 1 "file.h"
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        line_start = false;
+
+        if c == b'/' && bytes.get(i + 1) == Some(&b'/') {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            i += 2;
+            while i + 1 < bytes.len() && !(bytes[i] == b'*' && bytes[i + 1] == b'/') {
+                i += 1;
+            }
+            i = (i + 2).min(bytes.len());
+            continue;
+        }
+
+        let kind = if c == b'"' || c == b'\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != c && bytes[i] != b'\n' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i = (i + 1).min(bytes.len());
+            Tok::Literal
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.' || bytes[i] == b'_' || bytes[i] == b'\'') {
+                i += 1;
+            }
+            Tok::Literal
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'$') {
+                i += 1;
+            }
+            Tok::Ident
+        } else {
+            // Single-character punctuators are enough for these rewrites,
+            // but never split a multi-byte UTF-8 character
+            i += source[i..].chars().next().map_or(1, char::len_utf8);
+            Tok::Punct
+        };
+        tokens.push(Token { kind, start, end: i });
+    }
+    tokens
+}
+
+// Example usage:
+/*
+fn main() {
+    let preprocessed = "void qsort_b(void *, size_t, size_t, int (^ _Nonnull)(const void *, const void *))\n\
+        __attribute__((availability(macos,introduced=10.6)));\n\
+        int f(void) { if (__builtin_available(macos 10.15, *)) return 1; return 0; }\n";
+    let normalized = normalize(preprocessed, "<sdk>/stdlib.h");
+    // void qsort_b(void *, size_t, size_t, void *);
+    // int f(void) { if (1) return 1; return 0; }
+    println!("{}", normalized.source);
+    assert!(normalized.diagnostics.is_empty());
+}
+*/
@@ -0,0 +1,445 @@
+// src/frontend/ast.rs
+//! The C syntax tree
+//! What `C23Parser::parse` produces: one `TranslationUnit` per preprocessed
+//! source file. Typedef names and enum constants are kept as written; the
+//! consumers (interpreter, bytecode compiler) resolve them in scope.
+//! Qualifiers are checked by the parser and not kept.
+//!
+//! Every statement, expression and declaration carries the `Span` of its
+//! text in the preprocessed source.
+//!
+//! Re-exported as `syntax` for external tools, so changes here follow
+//! semver: the kind enums are `#[non_exhaustive]`.
+
+use std::fmt;
+pub use super::inline_asm::AsmStatement;
+
+/// Where a node's text is: byte offsets into the preprocessed source, and
+/// the line and column it starts at (1-based)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Span {
+    /// The span of a node built by a transform rather than parsed; it has
+    /// no text of its own (line 0 never occurs in parsed spans)
+    pub fn synthetic() -> Self {
+        Span::default()
+    }
+
+    pub fn is_synthetic(&self) -> bool {
+        self.line == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslationUnit {
+    pub file: String,
+    pub items: Vec<ExternalDeclaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternalDeclaration {
+    Function(FunctionDefinition),
+    Declaration(Declaration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub storage: Option<StorageClass>,
+    pub return_type: TypeName,
+    pub parameters: Vec<Parameter>,
+    /// Ends in `, ...`
+    pub variadic: bool,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: Option<String>,
+    pub ty: TypeName,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageClass {
+    Typedef,
+    Extern,
+    Static,
+    ThreadLocal,
+    Auto,
+    Register,
+}
+
+/// `static int a = 1, *b;`: one declaration, two declarators. A declaration
+/// without declarators only defines a tag (`struct s { ... };`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub storage: Option<StorageClass>,
+    /// The specifiers' type, for declarations without declarators
+    pub ty: TypeName,
+    pub declarators: Vec<Declarator>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declarator {
+    pub name: String,
+    /// The full type: specifiers with this declarator's pointers, arrays
+    /// and parameters applied
+    pub ty: TypeName,
+    pub initializer: Option<Initializer>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Initializer {
+    Expression(Expression),
+    /// `{ [2] = x, .member = y, z }`
+    List(Vec<(Option<Designator>, Initializer)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Designator {
+    Index(Expression),
+    Member(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum TypeName {
+    Void,
+    Bool,
+    /// `char` is signed on x86-64 and unsigned on AArch64; the parser
+    /// resolves plain `char` for the target
+    Char { signed: bool },
+    Short { signed: bool },
+    Int { signed: bool },
+    Long { signed: bool },
+    LongLong { signed: bool },
+    Float,
+    Double,
+    LongDouble,
+    Pointer(Box<TypeName>),
+    /// Length `None` for `[]`; a non-constant length is a VLA
+    Array(Box<TypeName>, Option<Box<Expression>>),
+    Function {
+        return_type: Box<TypeName>,
+        parameters: Vec<TypeName>,
+        variadic: bool,
+    },
+    Struct(RecordType),
+    Union(RecordType),
+    Enum(EnumType),
+    /// A name declared with `typedef`
+    Named(String),
+    /// `typeof(...)`, or `typeof_unqual(...)` when `unqual`
+    Typeof { unqual: bool, operand: Box<TypeOperand> },
+    /// C23 `auto` without a type specifier: the initializer's type. The
+    /// declaration's `storage` doesn't repeat it.
+    Auto,
+}
+
+/// What `typeof` and `_Generic` take: an expression, which is never
+/// evaluated, or a type name
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeOperand {
+    Expression(Expression),
+    TypeName(TypeName),
+}
+
+/// A struct or union specifier. `members: None` refers to a tag defined
+/// elsewhere (`struct node *next`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordType {
+    pub tag: Option<String>,
+    pub members: Option<Vec<Member>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    /// `None` for an anonymous struct or union member, or unnamed bit-field
+    pub name: Option<String>,
+    pub ty: TypeName,
+    pub bit_width: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumType {
+    pub tag: Option<String>,
+    pub enumerators: Option<Vec<(String, Option<Expression>)>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub items: Vec<BlockItem>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockItem {
+    Declaration(Declaration),
+    Statement(Statement),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum StatementKind {
+    /// `expression;`, or `;` alone
+    Expression(Option<Expression>),
+    Compound(Block),
+    If {
+        condition: Expression,
+        then: Box<Statement>,
+        otherwise: Option<Box<Statement>>,
+    },
+    While {
+        condition: Expression,
+        body: Box<Statement>,
+    },
+    DoWhile {
+        body: Box<Statement>,
+        condition: Expression,
+    },
+    For {
+        init: Option<ForInit>,
+        condition: Option<Expression>,
+        step: Option<Expression>,
+        body: Box<Statement>,
+    },
+    Switch {
+        value: Expression,
+        body: Box<Statement>,
+    },
+    Case {
+        value: Expression,
+        body: Box<Statement>,
+    },
+    Default(Box<Statement>),
+    Labeled {
+        label: String,
+        body: Box<Statement>,
+    },
+    Goto(String),
+    Break,
+    Continue,
+    Return(Option<Expression>),
+    /// GNU `asm`, as `inline_asm::parse_asm_statement` reads it, with the
+    /// operands' expressions parsed: `outputs` and `inputs` line up with
+    /// the statement's
+    Asm {
+        statement: AsmStatement,
+        outputs: Vec<Expression>,
+        inputs: Vec<Expression>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForInit {
+    Declaration(Declaration),
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ExpressionKind {
+    Integer { value: u64, suffix: IntegerSuffix },
+    Float { value: f64, single: bool },
+    /// A character constant, already an `int`
+    Character(i64),
+    /// Without the terminating NUL
+    String(Vec<u8>),
+    Identifier(String),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expression>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    /// `target = value`, or `target op= value`
+    Assign {
+        op: Option<BinaryOp>,
+        target: Box<Expression>,
+        value: Box<Expression>,
+    },
+    Conditional {
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+    Call {
+        function: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
+    Index {
+        array: Box<Expression>,
+        index: Box<Expression>,
+    },
+    /// `base.member`, or `base->member` when `arrow`
+    Member {
+        base: Box<Expression>,
+        member: String,
+        arrow: bool,
+    },
+    Cast {
+        ty: TypeName,
+        operand: Box<Expression>,
+    },
+    SizeofType(TypeName),
+    SizeofExpression(Box<Expression>),
+    AlignofType(TypeName),
+    Comma(Box<Expression>, Box<Expression>),
+    /// `_Generic(control, type: expression, ..., default: expression)`,
+    /// with `None` for `default`
+    Generic {
+        control: Box<TypeOperand>,
+        associations: Vec<(Option<TypeName>, Expression)>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerSuffix {
+    None,
+    Unsigned,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+    BitNot,
+    Not,
+    Deref,
+    AddressOf,
+    PreIncrement,
+    PreDecrement,
+    PostIncrement,
+    PostDecrement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    BitAnd,
+    BitOr,
+    BitXor,
+    LogicalAnd,
+    LogicalOr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+            UnaryOp::BitNot => "~",
+            UnaryOp::Not => "!",
+            UnaryOp::Deref => "*",
+            UnaryOp::AddressOf => "&",
+            UnaryOp::PreIncrement | UnaryOp::PostIncrement => "++",
+            UnaryOp::PreDecrement | UnaryOp::PostDecrement => "--",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::LogicalAnd => "&&",
+            BinaryOp::LogicalOr => "||",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+        }
+    }
+
+    /// Produces 0 or 1 of type `int`
+    pub fn is_comparison(self) -> bool {
+        matches!(self, BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge)
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind, span }
+    }
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Statement { kind, span }
+    }
+}
+
+// Example usage:
+/*
+fn count_functions(source: &str) -> usize {
+    let unit: TranslationUnit = C23Parser::new().parse(source).unwrap();
+    unit.items
+        .iter()
+        .filter(|item| matches!(item, ExternalDeclaration::Function(_)))
+        .count()
+}
+*/
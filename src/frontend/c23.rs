@@ -1,3 +1,1352 @@
+// src/frontend/c23.rs
+//! The C23 parser
+//! `C23Parser::parse` turns a preprocessed translation unit into the
+//! `ast` tree. Directives still in the text are skipped by the lexer, so
+//! source with `#include` lines pasted in by `stdlib::bundled` parses too;
+//! macros must already be expanded.
+//!
+//! Typedef names are tracked per scope, which is what tells `T * x;` (a
+//! declaration) from a multiplication. Qualifiers, attributes, `inline`,
+//! `_Noreturn` and alignment specifiers are accepted and dropped; `_Atomic(T)`
+//! is `T`. `typeof`/`typeof_unqual`, `_Generic` and a declaration's lone
+//! `auto` are kept for the consumers to resolve, since they need the
+//! operand's type. Compound literals, `_Complex`, `_BitInt` and K&R
+//! definitions are rejected as unsupported. A GNU `asm` statement is read
+//! by `inline_asm::parse_asm_statement`, its operand expressions here.
+
+use std::collections::HashMap;
+use std::fmt;
+use super::ast::*;
+use super::inline_asm::{parse_asm_statement, InlineAsmError};
+use super::lexer::{tokenize, LexError, Token, TokenKind, PLAIN_CHAR_SIGNED};
+
+/// Words that can start a declaration, besides typedef names
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "typedef", "extern", "static", "thread_local", "_Thread_local", "__thread", "auto", "register", "constexpr",
+    "void", "char", "short", "int", "long", "float", "double", "signed", "__signed__", "unsigned", "bool", "_Bool",
+    "_Complex", "_BitInt", "struct", "union", "enum", "typeof", "typeof_unqual", "__typeof__", "__typeof",
+    "const", "volatile", "restrict", "__restrict", "__restrict__", "__const", "__volatile__", "_Atomic", "inline",
+    "__inline", "__inline__", "_Noreturn", "alignas", "_Alignas", "__attribute__", "__extension__",
+    "static_assert", "_Static_assert",
+];
+
+/// Other keywords, which are never identifiers
+const KEYWORDS: &[&str] = &[
+    "if", "else", "while", "do", "for", "switch", "case", "default", "goto", "break", "continue", "return",
+    "sizeof", "alignof", "_Alignof", "_Generic", "asm", "__asm", "__asm__",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Expected { expected: String, found: String, line: u32, column: u32 },
+    Unsupported { what: String, line: u32, column: u32 },
+    Lexical { message: String, line: u32, column: u32 },
+    /// Well-formed tokens that don't make a valid construct, such as an
+    /// `asm` statement naming an operand it doesn't have
+    Invalid { message: String, line: u32, column: u32 },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Expected { expected, found, line, column } => {
+                write!(f, "{}:{}: expected {}, found {}", line, column, expected, found)
+            }
+            ParseError::Unsupported { what, line, column } => write!(f, "{}:{}: {} aren't supported", line, column, what),
+            ParseError::Lexical { message, line, column } | ParseError::Invalid { message, line, column } => {
+                write!(f, "{}:{}: {}", line, column, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        ParseError::Lexical { message: e.message, line: e.line, column: e.column }
+    }
+}
+
+/// What the declaration specifiers said
+struct Specifiers {
+    storage: Option<StorageClass>,
+    ty: TypeName,
+}
+
+/// A declarator before it's applied to its specifiers' type: pointers bind
+/// looser than the array and function suffixes, and a parenthesized inner
+/// declarator looser than both
+#[derive(Default)]
+struct Shape {
+    pointers: usize,
+    inner: Option<Box<Shape>>,
+    suffixes: Vec<Suffix>,
+    name: Option<String>,
+}
+
+enum Suffix {
+    Array(Option<Box<Expression>>),
+    Function { parameters: Vec<Parameter>, variadic: bool },
+}
+
+impl Shape {
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref().or_else(|| self.inner.as_ref().and_then(|inner| inner.name()))
+    }
+
+    /// `base` with this declarator applied
+    fn apply(&self, base: TypeName) -> TypeName {
+        let mut ty = base;
+        for _ in 0..self.pointers {
+            ty = TypeName::Pointer(Box::new(ty));
+        }
+        for suffix in self.suffixes.iter().rev() {
+            ty = match suffix {
+                Suffix::Array(length) => TypeName::Array(Box::new(ty), length.clone()),
+                Suffix::Function { parameters, variadic } => TypeName::Function {
+                    return_type: Box::new(ty),
+                    parameters: parameters.iter().map(|parameter| parameter.ty.clone()).collect(),
+                    variadic: *variadic,
+                },
+            };
+        }
+        match &self.inner {
+            Some(inner) => inner.apply(ty),
+            None => ty,
+        }
+    }
+
+    /// The parameters of the function the name is declared as, for a
+    /// definition: the suffix nearest the name
+    fn function_parameters(&self) -> Option<(&[Parameter], bool)> {
+        if let Some(parameters) = self.inner.as_ref().and_then(|inner| inner.function_parameters()) {
+            return Some(parameters);
+        }
+        if self.inner.as_ref().is_some_and(|inner| !inner.suffixes.is_empty()) {
+            return None;
+        }
+        match self.suffixes.first() {
+            Some(Suffix::Function { parameters, variadic }) => Some((parameters, *variadic)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DeclaratorKind {
+    Named,
+    Abstract,
+    /// Parameters may be either
+    Either,
+}
+
+pub struct C23Parser {
+    /// The text being parsed, which `asm` statements are read from
+    source: String,
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Per scope, whether each name is a typedef or an ordinary identifier
+    /// (which hides an outer typedef)
+    scopes: Vec<HashMap<String, bool>>,
+    /// The function being defined, for `__func__`
+    function: Option<String>,
+}
+
+impl Default for C23Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl C23Parser {
+    pub fn new() -> Self {
+        C23Parser { source: String::new(), tokens: Vec::new(), pos: 0, scopes: Vec::new(), function: None }
+    }
+
+    pub fn parse(&mut self, source: &str) -> Result<TranslationUnit, ParseError> {
+        let (tokens, _) = tokenize(source)?;
+        self.source = source.to_string();
+        self.tokens = tokens;
+        self.pos = 0;
+        self.scopes = vec![HashMap::from([("__builtin_va_list".to_string(), true)])];
+        self.function = None;
+
+        let mut items = Vec::new();
+        while !self.at_end() {
+            if self.eat(";") {
+                continue;
+            }
+            if let Some(item) = self.external_declaration()? {
+                items.push(item);
+            }
+        }
+        Ok(TranslationUnit { file: "<input>".to_string(), items })
+    }
+
+    // Tokens
+
+    fn peek_kind(&self, ahead: usize) -> &TokenKind {
+        let index = (self.pos + ahead).min(self.tokens.len() - 1);
+        &self.tokens[index].kind
+    }
+
+    fn at_end(&self) -> bool {
+        *self.peek_kind(0) == TokenKind::End
+    }
+
+    fn is_ahead(&self, ahead: usize, punctuator: &str) -> bool {
+        matches!(self.peek_kind(ahead), TokenKind::Punctuator(p) if *p == punctuator)
+    }
+
+    fn is(&self, punctuator: &str) -> bool {
+        self.is_ahead(0, punctuator)
+    }
+
+    fn word_ahead(&self, ahead: usize) -> Option<&str> {
+        match self.peek_kind(ahead) {
+            TokenKind::Identifier(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn word(&self) -> Option<&str> {
+        self.word_ahead(0)
+    }
+
+    fn is_word(&self, words: &[&str]) -> bool {
+        self.word().is_some_and(|word| words.contains(&word))
+    }
+
+    fn eat(&mut self, punctuator: &str) -> bool {
+        let found = self.is(punctuator);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_word(&mut self, words: &[&str]) -> bool {
+        let found = self.is_word(words);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punctuator: &str) -> Result<(), ParseError> {
+        if self.eat(punctuator) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("'{}'", punctuator)))
+        }
+    }
+
+    fn expected(&self, what: &str) -> ParseError {
+        let token = &self.tokens[self.pos.min(self.tokens.len() - 1)];
+        let found = match &token.kind {
+            TokenKind::Identifier(word) => format!("'{}'", word),
+            TokenKind::Punctuator(p) => format!("'{}'", p),
+            TokenKind::End => "end of input".to_string(),
+            _ => "a constant".to_string(),
+        };
+        ParseError::Expected { expected: what.to_string(), found, line: token.span.line, column: token.span.column }
+    }
+
+    fn unsupported(&self, what: &str) -> ParseError {
+        let span = self.span();
+        ParseError::Unsupported { what: what.to_string(), line: span.line, column: span.column }
+    }
+
+    /// The current token's span
+    fn span(&self) -> Span {
+        self.tokens[self.pos.min(self.tokens.len() - 1)].span
+    }
+
+    /// From the token at `start` to the last one consumed
+    fn span_from(&self, start: usize) -> Span {
+        let first = self.tokens[start].span;
+        let last = self.tokens[self.pos.saturating_sub(1).max(start)].span;
+        Span { end: last.end, ..first }
+    }
+
+    fn identifier(&mut self) -> Result<String, ParseError> {
+        match self.word() {
+            Some(word) if !is_keyword(word) => {
+                let word = word.to_string();
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.expected("an identifier")),
+        }
+    }
+
+    // Scopes
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &str, typedef: bool) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), typedef);
+        }
+    }
+
+    fn is_typedef(&self, name: &str) -> bool {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).copied().unwrap_or(false)
+    }
+
+    fn typedef_ahead(&self, ahead: usize) -> bool {
+        self.word_ahead(ahead).is_some_and(|word| self.is_typedef(word))
+    }
+
+    /// Whether the tokens from `ahead` start a type name
+    fn type_name_ahead(&self, ahead: usize) -> bool {
+        match self.word_ahead(ahead) {
+            Some(word) => {
+                DECLARATION_KEYWORDS.contains(&word)
+                    && !matches!(word, "typedef" | "extern" | "static" | "auto" | "register" | "static_assert" | "_Static_assert")
+                    || self.is_typedef(word)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a declaration starts here rather than a statement
+    fn declaration_ahead(&self) -> bool {
+        match self.word() {
+            Some(word) if DECLARATION_KEYWORDS.contains(&word) => true,
+            // `name:` is a label even when `name` is a typedef
+            Some(_) => self.typedef_ahead(0) && !self.is_ahead(1, ":"),
+            None => self.is("[") && self.is_ahead(1, "["),
+        }
+    }
+
+    // Declarations
+
+    fn external_declaration(&mut self) -> Result<Option<ExternalDeclaration>, ParseError> {
+        if self.static_assert()? {
+            return Ok(None);
+        }
+        let start = self.pos;
+        let specifiers = self.specifiers()?;
+        if self.eat(";") {
+            return Ok(Some(ExternalDeclaration::Declaration(Declaration {
+                storage: specifiers.storage,
+                ty: specifiers.ty,
+                declarators: Vec::new(),
+                span: self.span_from(start),
+            })));
+        }
+
+        let declarator_start = self.pos;
+        let shape = self.declarator(DeclaratorKind::Named)?;
+        self.skip_attributes()?;
+        if !self.is("{") {
+            let declaration = self.init_declarators(start, specifiers, declarator_start, shape)?;
+            return Ok(Some(ExternalDeclaration::Declaration(declaration)));
+        }
+
+        let name = shape.name().unwrap_or_default().to_string();
+        let TypeName::Function { return_type, .. } = shape.apply(specifiers.ty) else {
+            return Err(self.expected("';'"));
+        };
+        let Some((parameters, variadic)) = shape.function_parameters() else {
+            return Err(self.unsupported("K&R function definitions"));
+        };
+        if parameters.iter().any(|parameter| parameter.name.is_none() && parameter.ty != TypeName::Void) {
+            return Err(self.expected("a parameter name"));
+        }
+        let parameters = parameters.to_vec();
+
+        self.declare(&name, false);
+        self.push_scope();
+        for parameter in &parameters {
+            if let Some(name) = &parameter.name {
+                self.declare(name, false);
+            }
+        }
+        self.function = Some(name.clone());
+        let body = self.compound()?;
+        self.function = None;
+        self.pop_scope();
+
+        Ok(Some(ExternalDeclaration::Function(FunctionDefinition {
+            name,
+            storage: specifiers.storage,
+            return_type: *return_type,
+            parameters,
+            variadic,
+            body,
+            span: self.span_from(start),
+        })))
+    }
+
+    /// A declaration in a block or `for`, `;` included
+    fn declaration(&mut self) -> Result<Declaration, ParseError> {
+        let start = self.pos;
+        let specifiers = self.specifiers()?;
+        if self.eat(";") {
+            return Ok(Declaration { storage: specifiers.storage, ty: specifiers.ty, declarators: Vec::new(), span: self.span_from(start) });
+        }
+        let declarator_start = self.pos;
+        let shape = self.declarator(DeclaratorKind::Named)?;
+        self.init_declarators(start, specifiers, declarator_start, shape)
+    }
+
+    /// The declarators from `first` on, with their initializers, and the `;`
+    fn init_declarators(
+        &mut self,
+        start: usize,
+        specifiers: Specifiers,
+        mut declarator_start: usize,
+        first: Shape,
+    ) -> Result<Declaration, ParseError> {
+        let mut declarators = Vec::new();
+        let mut shape = first;
+        loop {
+            let name = shape.name().unwrap_or_default().to_string();
+            let ty = shape.apply(specifiers.ty.clone());
+            self.declare(&name, specifiers.storage == Some(StorageClass::Typedef));
+            self.skip_attributes()?;
+            let initializer = if self.eat("=") { Some(self.initializer()?) } else { None };
+            declarators.push(Declarator { name, ty, initializer, span: self.span_from(declarator_start) });
+            if !self.eat(",") {
+                break;
+            }
+            declarator_start = self.pos;
+            shape = self.declarator(DeclaratorKind::Named)?;
+        }
+        self.expect(";")?;
+        Ok(Declaration { storage: specifiers.storage, ty: specifiers.ty, declarators, span: self.span_from(start) })
+    }
+
+    /// `static_assert(expression, "message");`, checked by the consumers
+    /// rather than here; returns whether there was one
+    fn static_assert(&mut self) -> Result<bool, ParseError> {
+        if !self.eat_word(&["static_assert", "_Static_assert"]) {
+            return Ok(false);
+        }
+        self.expect("(")?;
+        self.conditional()?;
+        if self.eat(",") {
+            self.primary()?;
+        }
+        self.expect(")")?;
+        self.expect(";")?;
+        Ok(true)
+    }
+
+    /// `[[...]]` and `__attribute__((...))`, any number
+    fn skip_attributes(&mut self) -> Result<(), ParseError> {
+        loop {
+            if self.is("[") && self.is_ahead(1, "[") {
+                self.pos += 1;
+                self.skip_balanced("[", "]")?;
+            } else if self.eat_word(&["__attribute__", "__attribute"]) {
+                self.skip_balanced("(", ")")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// From an `open` to its matching `close`
+    fn skip_balanced(&mut self, open: &str, close: &str) -> Result<(), ParseError> {
+        self.expect(open)?;
+        let mut depth = 1;
+        while depth > 0 {
+            if self.at_end() {
+                return Err(self.expected(&format!("'{}'", close)));
+            }
+            if self.is(open) {
+                depth += 1;
+            } else if self.is(close) {
+                depth -= 1;
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn specifiers(&mut self) -> Result<Specifiers, ParseError> {
+        let mut storage = None;
+        let (mut void, mut bool_, mut char_, mut short, mut int, mut long) = (false, false, false, false, false, 0);
+        let (mut float, mut double, mut signed, mut unsigned) = (false, false, false, false);
+        let mut other: Option<TypeName> = None;
+
+        loop {
+            self.skip_attributes()?;
+            let Some(word) = self.word().map(str::to_string) else { break };
+            let any_type = void || bool_ || char_ || short || int || long > 0 || float || double || signed || unsigned || other.is_some();
+            let class = match word.as_str() {
+                "typedef" => Some(StorageClass::Typedef),
+                "extern" => Some(StorageClass::Extern),
+                "static" => Some(StorageClass::Static),
+                "thread_local" | "_Thread_local" | "__thread" => Some(StorageClass::ThreadLocal),
+                "auto" => Some(StorageClass::Auto),
+                "register" => Some(StorageClass::Register),
+                _ => None,
+            };
+            if let Some(class) = class {
+                self.pos += 1;
+                // `static thread_local` keeps the thread-local storage
+                if storage.is_none() || class == StorageClass::ThreadLocal {
+                    storage = Some(class);
+                }
+                continue;
+            }
+            match word.as_str() {
+                "constexpr" | "const" | "volatile" | "restrict" | "__restrict" | "__restrict__" | "__const" | "__volatile__"
+                | "inline" | "__inline" | "__inline__" | "_Noreturn" | "__extension__" => self.pos += 1,
+                "_Atomic" if self.is_ahead(1, "(") => {
+                    self.pos += 2;
+                    other = Some(self.type_name()?);
+                    self.expect(")")?;
+                }
+                "_Atomic" => self.pos += 1,
+                "alignas" | "_Alignas" => {
+                    self.pos += 1;
+                    self.skip_balanced("(", ")")?;
+                }
+                "void" => (void, self.pos) = (true, self.pos + 1),
+                "bool" | "_Bool" => (bool_, self.pos) = (true, self.pos + 1),
+                "char" => (char_, self.pos) = (true, self.pos + 1),
+                "short" => (short, self.pos) = (true, self.pos + 1),
+                "int" => (int, self.pos) = (true, self.pos + 1),
+                "long" => (long, self.pos) = (long + 1, self.pos + 1),
+                "float" => (float, self.pos) = (true, self.pos + 1),
+                "double" => (double, self.pos) = (true, self.pos + 1),
+                "signed" | "__signed__" => (signed, self.pos) = (true, self.pos + 1),
+                "unsigned" => (unsigned, self.pos) = (true, self.pos + 1),
+                "_Complex" => return Err(self.unsupported("complex types")),
+                "_BitInt" => return Err(self.unsupported("_BitInt types")),
+                "struct" | "union" => {
+                    self.pos += 1;
+                    let record = self.record()?;
+                    other = Some(if word == "struct" { TypeName::Struct(record) } else { TypeName::Union(record) });
+                }
+                "enum" => {
+                    self.pos += 1;
+                    other = Some(TypeName::Enum(self.enumeration()?));
+                }
+                "typeof" | "typeof_unqual" | "__typeof__" | "__typeof" => {
+                    self.pos += 1;
+                    self.expect("(")?;
+                    let operand = if self.type_name_ahead(0) {
+                        TypeOperand::TypeName(self.type_name()?)
+                    } else {
+                        TypeOperand::Expression(self.expression()?)
+                    };
+                    self.expect(")")?;
+                    other = Some(TypeName::Typeof { unqual: word == "typeof_unqual", operand: Box::new(operand) });
+                }
+                // A typedef name only names the type when nothing else has:
+                // in `typedef int T; { long T; }` the second T is declared
+                _ if !any_type && self.is_typedef(&word) => {
+                    self.pos += 1;
+                    other = Some(TypeName::Named(word));
+                }
+                _ => break,
+            }
+        }
+
+        let ty = if let Some(ty) = other {
+            ty
+        } else if void {
+            TypeName::Void
+        } else if bool_ {
+            TypeName::Bool
+        } else if char_ {
+            TypeName::Char { signed: if signed || unsigned { signed } else { PLAIN_CHAR_SIGNED } }
+        } else if short {
+            TypeName::Short { signed: !unsigned }
+        } else if long >= 2 {
+            TypeName::LongLong { signed: !unsigned }
+        } else if long == 1 && double {
+            TypeName::LongDouble
+        } else if long == 1 {
+            TypeName::Long { signed: !unsigned }
+        } else if float {
+            TypeName::Float
+        } else if double {
+            TypeName::Double
+        } else if int || signed || unsigned {
+            TypeName::Int { signed: !unsigned }
+        } else if storage == Some(StorageClass::Auto) {
+            // C23's `auto x = ...;` infers the type
+            storage = None;
+            TypeName::Auto
+        } else {
+            return Err(self.expected("a type specifier"));
+        };
+        Ok(Specifiers { storage, ty })
+    }
+
+    /// After `struct` or `union`
+    fn record(&mut self) -> Result<RecordType, ParseError> {
+        self.skip_attributes()?;
+        let tag = if self.is("{") { None } else { Some(self.identifier()?) };
+        if !self.eat("{") {
+            return Ok(RecordType { tag, members: None });
+        }
+
+        let mut members = Vec::new();
+        while !self.eat("}") {
+            if self.static_assert()? {
+                continue;
+            }
+            let specifiers = self.specifiers()?;
+            if specifiers.storage.is_some() {
+                return Err(self.expected("a member"));
+            }
+            if self.eat(";") {
+                // An anonymous struct or union
+                members.push(Member { name: None, ty: specifiers.ty, bit_width: None });
+                continue;
+            }
+            loop {
+                let (name, ty) = if self.is(":") {
+                    (None, specifiers.ty.clone())
+                } else {
+                    let shape = self.declarator(DeclaratorKind::Named)?;
+                    (shape.name().map(str::to_string), shape.apply(specifiers.ty.clone()))
+                };
+                let bit_width = if self.eat(":") {
+                    let width = self.conditional()?;
+                    let width = constant(&width).filter(|width| (0..=64).contains(width));
+                    Some(width.ok_or_else(|| self.expected("a constant bit-field width"))? as u32)
+                } else {
+                    None
+                };
+                self.skip_attributes()?;
+                members.push(Member { name, ty, bit_width });
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(";")?;
+        }
+        Ok(RecordType { tag, members: Some(members) })
+    }
+
+    /// After `enum`
+    fn enumeration(&mut self) -> Result<EnumType, ParseError> {
+        self.skip_attributes()?;
+        let tag = if self.is("{") || self.is(":") { None } else { Some(self.identifier()?) };
+        // C23's fixed underlying type; the enumerators' values don't need it
+        if self.eat(":") {
+            self.specifiers()?;
+        }
+        if !self.eat("{") {
+            return Ok(EnumType { tag, enumerators: None });
+        }
+
+        let mut enumerators = Vec::new();
+        while !self.eat("}") {
+            let name = self.identifier()?;
+            self.skip_attributes()?;
+            let value = if self.eat("=") { Some(self.conditional()?) } else { None };
+            self.declare(&name, false);
+            enumerators.push((name, value));
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(EnumType { tag, enumerators: Some(enumerators) })
+    }
+
+    /// Specifiers and an abstract declarator, as in casts and `sizeof`
+    fn type_name(&mut self) -> Result<TypeName, ParseError> {
+        let specifiers = self.specifiers()?;
+        if specifiers.storage.is_some() {
+            return Err(self.expected("a type name"));
+        }
+        let shape = self.declarator(DeclaratorKind::Abstract)?;
+        Ok(shape.apply(specifiers.ty))
+    }
+
+    fn declarator(&mut self, kind: DeclaratorKind) -> Result<Shape, ParseError> {
+        let mut shape = Shape::default();
+        loop {
+            self.skip_attributes()?;
+            if !self.eat("*") {
+                break;
+            }
+            shape.pointers += 1;
+            while self.eat_word(&["const", "volatile", "restrict", "__restrict", "__restrict__", "__const", "_Atomic", "_Nonnull", "_Nullable"]) {}
+        }
+
+        if self.is("(") && self.nested_declarator_ahead(kind) {
+            self.pos += 1;
+            shape.inner = Some(Box::new(self.declarator(kind)?));
+            self.expect(")")?;
+        } else if kind != DeclaratorKind::Abstract && self.word().is_some_and(|word| !is_keyword(word)) {
+            shape.name = Some(self.identifier()?);
+        } else if kind == DeclaratorKind::Named {
+            return Err(self.expected("an identifier"));
+        }
+
+        loop {
+            if self.eat("[") {
+                while self.eat_word(&["static", "const", "volatile", "restrict", "__restrict"]) {}
+                let length = if self.is("]") || (self.is("*") && self.is_ahead(1, "]")) {
+                    // `[*]` is a VLA of unspecified length, only in prototypes
+                    self.eat("*");
+                    None
+                } else {
+                    Some(Box::new(self.assignment()?))
+                };
+                self.expect("]")?;
+                shape.suffixes.push(Suffix::Array(length));
+            } else if self.is("(") {
+                let (parameters, variadic) = self.parameters()?;
+                shape.suffixes.push(Suffix::Function { parameters, variadic });
+            } else {
+                break;
+            }
+        }
+
+        // `int f(void) __asm__("f_v2")` renames the symbol; ignored
+        if self.is_word(&["asm", "__asm", "__asm__"]) && self.is_ahead(1, "(") {
+            self.pos += 1;
+            self.skip_balanced("(", ")")?;
+        }
+        Ok(shape)
+    }
+
+    /// Whether the `(` here opens a nested declarator rather than a
+    /// parameter list
+    fn nested_declarator_ahead(&self, kind: DeclaratorKind) -> bool {
+        if self.is_ahead(1, "*") || self.is_ahead(1, "(") || (self.is_ahead(1, "[") && self.is_ahead(2, "[")) {
+            return true;
+        }
+        kind != DeclaratorKind::Abstract
+            && self.word_ahead(1).is_some_and(|word| !is_keyword(word) && !self.is_typedef(word) && !word.starts_with("__attribute"))
+    }
+
+    fn parameters(&mut self) -> Result<(Vec<Parameter>, bool), ParseError> {
+        self.expect("(")?;
+        let mut parameters = Vec::new();
+        let mut variadic = false;
+        // `()` has no parameters in C23
+        if self.eat(")") {
+            return Ok((parameters, variadic));
+        }
+        loop {
+            if self.eat("...") {
+                variadic = true;
+                break;
+            }
+            let start = self.pos;
+            self.skip_attributes()?;
+            if !self.type_name_ahead(0) && !self.is_word(&["register"]) {
+                return Err(match self.word() {
+                    Some(word) if !is_keyword(word) => self.unsupported("K&R parameter lists"),
+                    _ => self.expected("a parameter declaration"),
+                });
+            }
+            let specifiers = self.specifiers()?;
+            let shape = self.declarator(DeclaratorKind::Either)?;
+            parameters.push(Parameter {
+                name: shape.name().map(str::to_string),
+                ty: shape.apply(specifiers.ty),
+                span: self.span_from(start),
+            });
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(")")?;
+        Ok((parameters, variadic))
+    }
+
+    fn initializer(&mut self) -> Result<Initializer, ParseError> {
+        if !self.eat("{") {
+            return Ok(Initializer::Expression(self.assignment()?));
+        }
+        let mut items = Vec::new();
+        while !self.eat("}") {
+            let mut designators = Vec::new();
+            loop {
+                if self.eat("[") {
+                    designators.push(Designator::Index(self.conditional()?));
+                    self.expect("]")?;
+                } else if self.eat(".") {
+                    designators.push(Designator::Member(self.identifier()?));
+                } else {
+                    break;
+                }
+            }
+            if !designators.is_empty() {
+                self.expect("=")?;
+            }
+            let mut initializer = self.initializer()?;
+            // `.a.b[1] = x` is `.a = { .b = { [1] = x } }`
+            while designators.len() > 1 {
+                let designator = designators.pop();
+                initializer = Initializer::List(vec![(designator, initializer)]);
+            }
+            items.push((designators.pop(), initializer));
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(Initializer::List(items))
+    }
+
+    // Statements
+
+    /// `{ ... }` in a new scope
+    fn block(&mut self) -> Result<Block, ParseError> {
+        self.push_scope();
+        let block = self.compound();
+        self.pop_scope();
+        block
+    }
+
+    /// `{ ... }` in the current scope, which a function body shares with
+    /// its parameters
+    fn compound(&mut self) -> Result<Block, ParseError> {
+        let start = self.pos;
+        self.expect("{")?;
+        let mut items = Vec::new();
+        while !self.eat("}") {
+            if self.at_end() {
+                return Err(self.expected("'}'"));
+            }
+            if self.static_assert()? {
+                continue;
+            }
+            if self.declaration_ahead() {
+                items.push(BlockItem::Declaration(self.declaration()?));
+            } else {
+                items.push(BlockItem::Statement(self.statement()?));
+            }
+        }
+        Ok(Block { items, span: self.span_from(start) })
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.pos;
+        self.skip_attributes()?;
+        if self.eat(";") {
+            return Ok(Statement::new(StatementKind::Expression(None), self.span_from(start)));
+        }
+        if self.is("{") {
+            let block = self.block()?;
+            return Ok(Statement::new(StatementKind::Compound(block), self.span_from(start)));
+        }
+        let word = self.word().map(str::to_string);
+        let kind = match word.as_deref() {
+            Some("if") => {
+                self.pos += 1;
+                let condition = self.parenthesized()?;
+                let then = Box::new(self.statement()?);
+                let otherwise = if self.eat_word(&["else"]) { Some(Box::new(self.statement()?)) } else { None };
+                StatementKind::If { condition, then, otherwise }
+            }
+            Some("while") => {
+                self.pos += 1;
+                let condition = self.parenthesized()?;
+                StatementKind::While { condition, body: Box::new(self.statement()?) }
+            }
+            Some("do") => {
+                self.pos += 1;
+                let body = Box::new(self.statement()?);
+                if !self.eat_word(&["while"]) {
+                    return Err(self.expected("'while'"));
+                }
+                let condition = self.parenthesized()?;
+                self.expect(";")?;
+                StatementKind::DoWhile { body, condition }
+            }
+            Some("for") => {
+                self.pos += 1;
+                self.push_scope();
+                let result = self.for_statement();
+                self.pop_scope();
+                result?
+            }
+            Some("switch") => {
+                self.pos += 1;
+                let value = self.parenthesized()?;
+                StatementKind::Switch { value, body: Box::new(self.statement()?) }
+            }
+            Some("case") => {
+                self.pos += 1;
+                let value = self.conditional()?;
+                if self.is("...") {
+                    return Err(self.unsupported("case ranges"));
+                }
+                self.expect(":")?;
+                StatementKind::Case { value, body: Box::new(self.labeled_body()?) }
+            }
+            Some("default") if self.is_ahead(1, ":") => {
+                self.pos += 2;
+                StatementKind::Default(Box::new(self.labeled_body()?))
+            }
+            Some("goto") => {
+                self.pos += 1;
+                if self.is("*") {
+                    return Err(self.unsupported("computed gotos"));
+                }
+                let label = self.identifier()?;
+                self.expect(";")?;
+                StatementKind::Goto(label)
+            }
+            Some("break") => {
+                self.pos += 1;
+                self.expect(";")?;
+                StatementKind::Break
+            }
+            Some("continue") => {
+                self.pos += 1;
+                self.expect(";")?;
+                StatementKind::Continue
+            }
+            Some("return") => {
+                self.pos += 1;
+                let value = if self.is(";") { None } else { Some(self.expression()?) };
+                self.expect(";")?;
+                StatementKind::Return(value)
+            }
+            Some("asm" | "__asm" | "__asm__") => self.asm_statement()?,
+            Some(label) if !is_keyword(label) && self.is_ahead(1, ":") => {
+                let label = label.to_string();
+                self.pos += 2;
+                StatementKind::Labeled { label, body: Box::new(self.labeled_body()?) }
+            }
+            _ => {
+                let expression = self.expression()?;
+                self.expect(";")?;
+                StatementKind::Expression(Some(expression))
+            }
+        };
+        Ok(Statement::new(kind, self.span_from(start)))
+    }
+
+    /// What a label is attached to; C23 allows a declaration or the end of
+    /// the block, which get an empty statement
+    fn labeled_body(&mut self) -> Result<Statement, ParseError> {
+        if self.is("}") || self.declaration_ahead() {
+            let span = self.span();
+            return Ok(Statement::new(StatementKind::Expression(None), Span { end: span.start, ..span }));
+        }
+        self.statement()
+    }
+
+    /// After `for`, in the loop's own scope
+    /// GNU `asm`. `parse_asm_statement` reads the statement's text; the
+    /// operand expressions are then parsed from its tokens, each being the
+    /// parenthesized one after a constraint string.
+    fn asm_statement(&mut self) -> Result<StatementKind, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        while self.eat_word(&["volatile", "__volatile", "__volatile__", "inline", "__inline", "__inline__", "goto"]) {}
+        let open = self.pos;
+        self.skip_balanced("(", ")")?;
+        let close = self.pos - 1;
+        self.expect(";")?;
+
+        let keyword = self.tokens[start].span;
+        let text = &self.source[keyword.start..self.tokens[close].span.end];
+        let statement = parse_asm_statement(text).map_err(|e| match e {
+            InlineAsmError::Expected(what, offset) => {
+                let at = self.tokens[start..=close].partition_point(|token| token.span.start < keyword.start + offset);
+                let token = &self.tokens[start + at.min(close - start)];
+                let found = text.get(token.span.start - keyword.start..token.span.end - keyword.start).unwrap_or_default();
+                let expected = what.replace('`', "'");
+                ParseError::Expected { expected, found: format!("'{}'", found), line: token.span.line, column: token.span.column }
+            }
+            other => ParseError::Invalid { message: other.to_string(), line: keyword.line, column: keyword.column },
+        })?;
+
+        let end = self.pos;
+        let mut operands = Vec::new();
+        self.pos = open + 1;
+        while self.pos < close {
+            if matches!(self.peek_kind(0), TokenKind::String(_)) && self.is_ahead(1, "(") {
+                self.pos += 2;
+                operands.push(self.expression()?);
+                self.expect(")")?;
+            } else {
+                self.pos += 1;
+            }
+        }
+        self.pos = end;
+        if operands.len() != statement.outputs.len() + statement.inputs.len() {
+            let message = "asm operands must be expressions in parentheses".to_string();
+            return Err(ParseError::Invalid { message, line: keyword.line, column: keyword.column });
+        }
+        let inputs = operands.split_off(statement.outputs.len());
+        Ok(StatementKind::Asm { statement, outputs: operands, inputs })
+    }
+
+    fn for_statement(&mut self) -> Result<StatementKind, ParseError> {
+        self.expect("(")?;
+        let init = if self.eat(";") {
+            None
+        } else if self.declaration_ahead() {
+            Some(ForInit::Declaration(self.declaration()?))
+        } else {
+            let expression = self.expression()?;
+            self.expect(";")?;
+            Some(ForInit::Expression(expression))
+        };
+        let condition = if self.is(";") { None } else { Some(self.expression()?) };
+        self.expect(";")?;
+        let step = if self.is(")") { None } else { Some(self.expression()?) };
+        self.expect(")")?;
+        let body = Box::new(self.statement()?);
+        Ok(StatementKind::For { init, condition, step, body })
+    }
+
+    fn parenthesized(&mut self) -> Result<Expression, ParseError> {
+        self.expect("(")?;
+        let expression = self.expression()?;
+        self.expect(")")?;
+        Ok(expression)
+    }
+
+    // Expressions
+
+    fn expression(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.assignment()?;
+        while self.eat(",") {
+            let right = self.assignment()?;
+            let span = join(expression.span, right.span);
+            expression = Expression::new(ExpressionKind::Comma(Box::new(expression), Box::new(right)), span);
+        }
+        Ok(expression)
+    }
+
+    fn assignment(&mut self) -> Result<Expression, ParseError> {
+        let target = self.conditional()?;
+        let op = match self.peek_kind(0) {
+            TokenKind::Punctuator("=") => None,
+            TokenKind::Punctuator(p) => match p.strip_suffix('=').and_then(binary_op) {
+                Some((op, _)) if !op.is_comparison() && !matches!(op, BinaryOp::LogicalAnd | BinaryOp::LogicalOr) => Some(op),
+                _ => return Ok(target),
+            },
+            _ => return Ok(target),
+        };
+        self.pos += 1;
+        let value = self.assignment()?;
+        let span = join(target.span, value.span);
+        Ok(Expression::new(ExpressionKind::Assign { op, target: Box::new(target), value: Box::new(value) }, span))
+    }
+
+    fn conditional(&mut self) -> Result<Expression, ParseError> {
+        let condition = self.binary(1)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        if self.is(":") {
+            return Err(self.unsupported("conditionals with the middle operand omitted"));
+        }
+        let then = self.expression()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        let span = join(condition.span, otherwise.span);
+        Ok(Expression::new(
+            ExpressionKind::Conditional { condition: Box::new(condition), then: Box::new(then), otherwise: Box::new(otherwise) },
+            span,
+        ))
+    }
+
+    /// Binary operators binding at least as tightly as `precedence`
+    fn binary(&mut self, precedence: u8) -> Result<Expression, ParseError> {
+        let mut left = self.cast()?;
+        loop {
+            let (op, op_precedence) = match self.peek_kind(0) {
+                TokenKind::Punctuator(p) => match binary_op(p) {
+                    Some(found) if found.1 >= precedence => found,
+                    _ => return Ok(left),
+                },
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.binary(op_precedence + 1)?;
+            let span = join(left.span, right.span);
+            left = Expression::new(ExpressionKind::Binary { op, left: Box::new(left), right: Box::new(right) }, span);
+        }
+    }
+
+    fn cast(&mut self) -> Result<Expression, ParseError> {
+        if !(self.is("(") && self.type_name_ahead(1)) {
+            return self.unary();
+        }
+        let start = self.pos;
+        self.pos += 1;
+        let ty = self.type_name()?;
+        self.expect(")")?;
+        if self.is("{") {
+            return Err(self.unsupported("compound literals"));
+        }
+        let operand = self.cast()?;
+        Ok(Expression::new(ExpressionKind::Cast { ty, operand: Box::new(operand) }, self.span_from(start)))
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        let start = self.pos;
+        let op = match self.peek_kind(0) {
+            TokenKind::Punctuator("++") => Some(UnaryOp::PreIncrement),
+            TokenKind::Punctuator("--") => Some(UnaryOp::PreDecrement),
+            TokenKind::Punctuator("&") => Some(UnaryOp::AddressOf),
+            TokenKind::Punctuator("*") => Some(UnaryOp::Deref),
+            TokenKind::Punctuator("+") => Some(UnaryOp::Plus),
+            TokenKind::Punctuator("-") => Some(UnaryOp::Minus),
+            TokenKind::Punctuator("~") => Some(UnaryOp::BitNot),
+            TokenKind::Punctuator("!") => Some(UnaryOp::Not),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.pos += 1;
+            let operand = match op {
+                UnaryOp::PreIncrement | UnaryOp::PreDecrement => self.unary()?,
+                _ => self.cast()?,
+            };
+            return Ok(Expression::new(ExpressionKind::Unary { op, operand: Box::new(operand) }, self.span_from(start)));
+        }
+
+        if self.eat_word(&["sizeof"]) {
+            let kind = if self.is("(") && self.type_name_ahead(1) {
+                self.pos += 1;
+                let ty = self.type_name()?;
+                self.expect(")")?;
+                if self.is("{") {
+                    return Err(self.unsupported("compound literals"));
+                }
+                ExpressionKind::SizeofType(ty)
+            } else {
+                ExpressionKind::SizeofExpression(Box::new(self.unary()?))
+            };
+            return Ok(Expression::new(kind, self.span_from(start)));
+        }
+        if self.eat_word(&["alignof", "_Alignof", "__alignof__"]) {
+            self.expect("(")?;
+            if !self.type_name_ahead(0) {
+                return Err(self.unsupported("alignof of expressions"));
+            }
+            let ty = self.type_name()?;
+            self.expect(")")?;
+            return Ok(Expression::new(ExpressionKind::AlignofType(ty), self.span_from(start)));
+        }
+        if self.eat_word(&["__extension__"]) {
+            return self.cast();
+        }
+
+        let primary = self.primary()?;
+        self.postfix(primary)
+    }
+
+    fn postfix(&mut self, mut expression: Expression) -> Result<Expression, ParseError> {
+        loop {
+            let first = expression.span;
+            let kind = if self.eat("[") {
+                let index = self.expression()?;
+                self.expect("]")?;
+                ExpressionKind::Index { array: Box::new(expression), index: Box::new(index) }
+            } else if self.eat("(") {
+                let mut arguments = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        arguments.push(self.assignment()?);
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                    self.expect(")")?;
+                }
+                ExpressionKind::Call { function: Box::new(expression), arguments }
+            } else if self.is(".") || self.is("->") {
+                let arrow = self.is("->");
+                self.pos += 1;
+                let member = self.identifier()?;
+                ExpressionKind::Member { base: Box::new(expression), member, arrow }
+            } else if self.eat("++") {
+                ExpressionKind::Unary { op: UnaryOp::PostIncrement, operand: Box::new(expression) }
+            } else if self.eat("--") {
+                ExpressionKind::Unary { op: UnaryOp::PostDecrement, operand: Box::new(expression) }
+            } else {
+                return Ok(expression);
+            };
+            expression = Expression::new(kind, join(first, self.tokens[self.pos - 1].span));
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        let start = self.pos;
+        let kind = match self.peek_kind(0).clone() {
+            TokenKind::Integer { value, suffix } => ExpressionKind::Integer { value, suffix },
+            TokenKind::Float { value, single } => ExpressionKind::Float { value, single },
+            TokenKind::Character(value) => ExpressionKind::Character(value),
+            TokenKind::String(mut bytes) => {
+                // Adjacent literals are one
+                while let TokenKind::String(more) = self.peek_kind(1) {
+                    bytes.extend_from_slice(more);
+                    self.pos += 1;
+                }
+                ExpressionKind::String(bytes)
+            }
+            TokenKind::Punctuator("(") => {
+                if self.is_ahead(1, "{") {
+                    return Err(self.unsupported("statement expressions"));
+                }
+                self.pos += 1;
+                let mut expression = self.expression()?;
+                self.expect(")")?;
+                // The parentheses are part of its text
+                expression.span = self.span_from(start);
+                return Ok(expression);
+            }
+            TokenKind::Identifier(word) => match word.as_str() {
+                "true" | "false" => ExpressionKind::Integer { value: (word == "true") as u64, suffix: IntegerSuffix::None },
+                "nullptr" => {
+                    let span = self.span();
+                    ExpressionKind::Cast {
+                        ty: TypeName::Pointer(Box::new(TypeName::Void)),
+                        operand: Box::new(Expression::new(ExpressionKind::Integer { value: 0, suffix: IntegerSuffix::None }, span)),
+                    }
+                }
+                "__func__" | "__FUNCTION__" => match &self.function {
+                    Some(name) => ExpressionKind::String(name.as_bytes().to_vec()),
+                    None => return Err(self.expected("an expression (__func__ outside a function)")),
+                },
+                "_Generic" => {
+                    self.pos += 1;
+                    return self.generic(start);
+                }
+                _ if is_keyword(&word) => return Err(self.expected("an expression")),
+                _ => ExpressionKind::Identifier(word),
+            },
+            _ => return Err(self.expected("an expression")),
+        };
+        self.pos += 1;
+        Ok(Expression::new(kind, self.span_from(start)))
+    }
+
+    /// After `_Generic`
+    fn generic(&mut self, start: usize) -> Result<Expression, ParseError> {
+        self.expect("(")?;
+        let control = if self.type_name_ahead(0) {
+            TypeOperand::TypeName(self.type_name()?)
+        } else {
+            TypeOperand::Expression(self.assignment()?)
+        };
+        let mut associations = Vec::new();
+        while self.eat(",") {
+            let ty = if self.eat_word(&["default"]) { None } else { Some(self.type_name()?) };
+            self.expect(":")?;
+            associations.push((ty, self.assignment()?));
+        }
+        self.expect(")")?;
+        if associations.is_empty() {
+            return Err(self.expected("a generic association"));
+        }
+        Ok(Expression::new(ExpressionKind::Generic { control: Box::new(control), associations }, self.span_from(start)))
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    DECLARATION_KEYWORDS.contains(&word) || KEYWORDS.contains(&word)
+}
+
+/// The operator a punctuator is and its precedence, tightest highest
+fn binary_op(punctuator: &str) -> Option<(BinaryOp, u8)> {
+    Some(match punctuator {
+        "||" => (BinaryOp::LogicalOr, 1),
+        "&&" => (BinaryOp::LogicalAnd, 2),
+        "|" => (BinaryOp::BitOr, 3),
+        "^" => (BinaryOp::BitXor, 4),
+        "&" => (BinaryOp::BitAnd, 5),
+        "==" => (BinaryOp::Eq, 6),
+        "!=" => (BinaryOp::Ne, 6),
+        "<" => (BinaryOp::Lt, 7),
+        ">" => (BinaryOp::Gt, 7),
+        "<=" => (BinaryOp::Le, 7),
+        ">=" => (BinaryOp::Ge, 7),
+        "<<" => (BinaryOp::Shl, 8),
+        ">>" => (BinaryOp::Shr, 8),
+        "+" => (BinaryOp::Add, 9),
+        "-" => (BinaryOp::Sub, 9),
+        "*" => (BinaryOp::Mul, 10),
+        "/" => (BinaryOp::Div, 10),
+        "%" => (BinaryOp::Rem, 10),
+        _ => return None,
+    })
+}
+
+/// From the start of `first` to the end of `last`
+fn join(first: Span, last: Span) -> Span {
+    Span { end: last.end, ..first }
+}
+
+/// The value of an integer constant expression made of literals, for
+/// bit-field widths
+fn constant(expression: &Expression) -> Option<i64> {
+    Some(match &expression.kind {
+        ExpressionKind::Integer { value, .. } => *value as i64,
+        ExpressionKind::Character(value) => *value,
+        ExpressionKind::Cast { operand, .. } => constant(operand)?,
+        ExpressionKind::Unary { op, operand } => {
+            let value = constant(operand)?;
+            match op {
+                UnaryOp::Plus => value,
+                UnaryOp::Minus => value.wrapping_neg(),
+                UnaryOp::BitNot => !value,
+                UnaryOp::Not => (value == 0) as i64,
+                _ => return None,
+            }
+        }
+        ExpressionKind::Binary { op, left, right } => {
+            let (a, b) = (constant(left)?, constant(right)?);
+            match op {
+                BinaryOp::Add => a.wrapping_add(b),
+                BinaryOp::Sub => a.wrapping_sub(b),
+                BinaryOp::Mul => a.wrapping_mul(b),
+                BinaryOp::Div => a.checked_div(b)?,
+                BinaryOp::Rem => a.checked_rem(b)?,
+                BinaryOp::Shl => a.checked_shl(b as u32)?,
+                BinaryOp::Shr => a.checked_shr(b as u32)?,
+                BinaryOp::BitAnd => a & b,
+                BinaryOp::BitOr => a | b,
+                BinaryOp::BitXor => a ^ b,
+                BinaryOp::LogicalAnd => (a != 0 && b != 0) as i64,
+                BinaryOp::LogicalOr => (a != 0 || b != 0) as i64,
+                BinaryOp::Eq => (a == b) as i64,
+                BinaryOp::Ne => (a != b) as i64,
+                BinaryOp::Lt => (a < b) as i64,
+                BinaryOp::Le => (a <= b) as i64,
+                BinaryOp::Gt => (a > b) as i64,
+                BinaryOp::Ge => (a >= b) as i64,
+            }
+        }
+        ExpressionKind::Conditional { condition, then, otherwise } => {
+            if constant(condition)? != 0 { constant(then)? } else { constant(otherwise)? }
+        }
+        _ => return None,
+    })
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ParseError> {
+    let unit = C23Parser::new().parse("typedef int T; T square(T x) { auto y = x * x; return y; }")?;
+    let ExternalDeclaration::Function(square) = &unit.items[1] else { unreachable!() };
+    assert_eq!(square.return_type, TypeName::Named("T".to_string()));
+    Ok(())
+}
+*/
+
 pub struct C23Features {
     // New C23 features
     attributes: AttributeHandler,
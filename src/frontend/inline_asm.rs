@@ -0,0 +1,402 @@
+// src/frontend/inline_asm.rs
+//! GNU `asm` statements
+//! `asm [volatile] [inline] [goto] ("template" : outputs : inputs : clobbers : labels);`
+//! Operands are `[name] "constraint" (expression)`. The expressions are
+//! returned as source text for the expression parser; constraints are
+//! resolved per target by `arch::inline_asm` and the statement is emitted
+//! by `compiler::inline_asm`, or lowered for the bytecode engine's native
+//! tier by `interpreter::bytecode::inline_asm`. `C23Parser` calls this on
+//! each `asm` statement's text and parses the operand expressions itself.
+//!
+//! Templates reference operands GCC-style (`%0`, `%[name]`, `%w1`, `%l[label]`,
+//! `%=`, `%%`). `llvm_template` rewrites them into LLVM's `$0`/`${0:w}` syntax.
+
+use std::fmt;
+
+/// GCC's limit on operands per statement
+const MAX_OPERANDS: usize = 30;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AsmQualifiers {
+    pub volatile: bool,
+    pub inline: bool,
+    pub goto: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmOperand {
+    /// `[name]`, for `%[name]` in the template
+    pub name: Option<String>,
+    pub constraint: String,
+    /// The parenthesized C expression, without the parentheses
+    pub expression: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmStatement {
+    pub qualifiers: AsmQualifiers,
+    /// Adjacent string literals concatenated, escapes decoded
+    pub template: String,
+    pub outputs: Vec<AsmOperand>,
+    pub inputs: Vec<AsmOperand>,
+    pub clobbers: Vec<String>,
+    pub labels: Vec<String>,
+    /// `asm("...")` without any colon: the template is emitted verbatim,
+    /// `%` included
+    pub basic: bool,
+}
+
+impl AsmStatement {
+    /// GCC treats asm without outputs, and `asm goto`, as volatile
+    pub fn has_side_effects(&self) -> bool {
+        self.qualifiers.volatile || self.qualifiers.goto || self.outputs.is_empty()
+    }
+
+    /// Index of `[name]`: outputs first, then inputs, then labels
+    pub fn operand_index(&self, name: &str) -> Option<usize> {
+        let operands = self.outputs.len() + self.inputs.len();
+        self.outputs
+            .iter()
+            .chain(&self.inputs)
+            .position(|operand| operand.name.as_deref() == Some(name))
+            .or_else(|| self.labels.iter().position(|label| label == name).map(|i| operands + i))
+    }
+
+    /// The template in LLVM inline-asm syntax
+    pub fn llvm_template(&self) -> Result<String, InlineAsmError> {
+        if self.basic {
+            return Ok(self.template.replace('$', "$$"));
+        }
+
+        let count = self.outputs.len() + self.inputs.len() + self.labels.len();
+        let mut out = String::with_capacity(self.template.len());
+        let mut chars = self.template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '$' => out.push_str("$$"),
+                '%' => match chars.peek().copied() {
+                    Some('%') => {
+                        chars.next();
+                        out.push('%');
+                    }
+                    // Unique number per expansion, for local labels
+                    Some('=') => {
+                        chars.next();
+                        out.push_str("${:uid}");
+                    }
+                    Some(_) => {
+                        let modifier = match chars.peek() {
+                            Some(m) if m.is_ascii_alphabetic() => chars.next(),
+                            _ => None,
+                        };
+                        let index = match chars.peek() {
+                            Some('[') => {
+                                chars.next();
+                                let name: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                                self.operand_index(name.trim())
+                                    .ok_or_else(|| InlineAsmError::UnknownOperandName(name.trim().to_string()))?
+                            }
+                            Some(d) if d.is_ascii_digit() => {
+                                let mut digits = String::new();
+                                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                                    digits.push(*d);
+                                    chars.next();
+                                }
+                                digits.parse().unwrap_or(usize::MAX)
+                            }
+                            _ => return Err(InlineAsmError::BadOperandReference(self.template.clone())),
+                        };
+                        if index >= count {
+                            return Err(InlineAsmError::OperandOutOfRange(index));
+                        }
+                        match modifier {
+                            Some(m) => out.push_str(&format!("${{{}:{}}}", index, m)),
+                            None => out.push_str(&format!("${{{}}}", index)),
+                        }
+                    }
+                    None => return Err(InlineAsmError::BadOperandReference(self.template.clone())),
+                },
+                other => out.push(other),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Parse one `asm` statement starting at its keyword
+pub fn parse_asm_statement(text: &str) -> Result<AsmStatement, InlineAsmError> {
+    let mut cursor = Cursor { text, pos: 0 };
+
+    match cursor.identifier().as_deref() {
+        Some("asm" | "__asm" | "__asm__") => {}
+        _ => return Err(cursor.expected("`asm`")),
+    }
+
+    let mut qualifiers = AsmQualifiers::default();
+    loop {
+        let save = cursor.pos;
+        match cursor.identifier().as_deref() {
+            Some("volatile" | "__volatile" | "__volatile__") => qualifiers.volatile = true,
+            Some("inline" | "__inline" | "__inline__") => qualifiers.inline = true,
+            Some("goto") => qualifiers.goto = true,
+            _ => {
+                cursor.pos = save;
+                break;
+            }
+        }
+    }
+
+    cursor.expect('(')?;
+    let template = cursor.string_literals()?;
+
+    let mut statement = AsmStatement {
+        qualifiers,
+        template,
+        outputs: Vec::new(),
+        inputs: Vec::new(),
+        clobbers: Vec::new(),
+        labels: Vec::new(),
+        basic: true,
+    };
+
+    let mut section = 0;
+    while cursor.eat(':') {
+        statement.basic = false;
+        section += 1;
+        match section {
+            1 => statement.outputs = cursor.operands()?,
+            2 => statement.inputs = cursor.operands()?,
+            3 => statement.clobbers = cursor.list(|c| c.string_literals())?,
+            4 if qualifiers.goto => {
+                statement.labels = cursor.list(|c| c.identifier().ok_or_else(|| c.expected("a label")))?
+            }
+            4 => return Err(InlineAsmError::LabelsWithoutGoto),
+            _ => return Err(cursor.expected("`)`")),
+        }
+    }
+    cursor.expect(')')?;
+    cursor.eat(';');
+
+    if statement.basic && qualifiers.goto {
+        return Err(InlineAsmError::LabelsWithoutGoto);
+    }
+    if statement.outputs.len() + statement.inputs.len() + statement.labels.len() > MAX_OPERANDS {
+        return Err(InlineAsmError::TooManyOperands);
+    }
+    let mut names: Vec<&str> = statement
+        .outputs
+        .iter()
+        .chain(&statement.inputs)
+        .filter_map(|operand| operand.name.as_deref())
+        .chain(statement.labels.iter().map(String::as_str))
+        .collect();
+    names.sort_unstable();
+    if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(InlineAsmError::DuplicateOperandName(pair[0].to_string()));
+    }
+
+    Ok(statement)
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.text[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), InlineAsmError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.expected(match c {
+                '(' => "`(`",
+                ')' => "`)`",
+                _ => "punctuation",
+            }))
+        }
+    }
+
+    fn expected(&self, what: &'static str) -> InlineAsmError {
+        InlineAsmError::Expected(what, self.pos)
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        self.pos += len;
+        Some(rest[..len].to_string())
+    }
+
+    /// One or more adjacent string literals, concatenated
+    fn string_literals(&mut self) -> Result<String, InlineAsmError> {
+        if self.peek() != Some('"') {
+            return Err(self.expected("a string literal"));
+        }
+        let mut value = String::new();
+        while self.peek() == Some('"') {
+            self.pos += 1;
+            let text = self.text;
+            let mut chars = text[self.pos..].char_indices();
+            loop {
+                let (offset, c) = chars.next().ok_or(InlineAsmError::UnterminatedString)?;
+                match c {
+                    '"' => {
+                        self.pos += offset + 1;
+                        break;
+                    }
+                    '\\' => {
+                        let (_, escaped) = chars.next().ok_or(InlineAsmError::UnterminatedString)?;
+                        value.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            '0' => '\0',
+                            'a' => '\x07',
+                            'b' => '\x08',
+                            'f' => '\x0c',
+                            'v' => '\x0b',
+                            other => other,
+                        });
+                    }
+                    other => value.push(other),
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// `item, item, ...` up to the next `:` or `)`; empty is fine
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, InlineAsmError>) -> Result<Vec<T>, InlineAsmError> {
+        let mut items = Vec::new();
+        if matches!(self.peek(), Some(':' | ')')) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if !self.eat(',') {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn operands(&mut self) -> Result<Vec<AsmOperand>, InlineAsmError> {
+        self.list(|cursor| {
+            let name = if cursor.eat('[') {
+                let name = cursor.identifier().ok_or_else(|| cursor.expected("an operand name"))?;
+                if !cursor.eat(']') {
+                    return Err(cursor.expected("`]`"));
+                }
+                Some(name)
+            } else {
+                None
+            };
+            let constraint = cursor.string_literals()?;
+            cursor.expect('(')?;
+            let expression = cursor.balanced()?;
+            Ok(AsmOperand { name, constraint, expression })
+        })
+    }
+
+    /// Text up to the `)` matching an already consumed `(`
+    fn balanced(&mut self) -> Result<String, InlineAsmError> {
+        let start = self.pos;
+        let text = self.text;
+        let mut depth = 1;
+        let mut chars = text[start..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos = start + offset + 1;
+                        return Ok(text[start..start + offset].trim().to_string());
+                    }
+                }
+                '"' | '\'' => {
+                    while let Some((_, d)) = chars.next() {
+                        if d == '\\' {
+                            chars.next();
+                        } else if d == c {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(self.expected("`)`"))
+    }
+}
+
+#[derive(Debug)]
+pub enum InlineAsmError {
+    /// What was expected, at this byte offset
+    Expected(&'static str, usize),
+    UnterminatedString,
+    LabelsWithoutGoto,
+    TooManyOperands,
+    DuplicateOperandName(String),
+    UnknownOperandName(String),
+    OperandOutOfRange(usize),
+    BadOperandReference(String),
+}
+
+impl fmt::Display for InlineAsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InlineAsmError::Expected(what, offset) => write!(f, "expected {} at byte {} of the asm statement", what, offset),
+            InlineAsmError::UnterminatedString => write!(f, "unterminated string in an asm statement"),
+            InlineAsmError::LabelsWithoutGoto => write!(f, "asm labels need `asm goto`"),
+            InlineAsmError::TooManyOperands => write!(f, "more than {} asm operands", MAX_OPERANDS),
+            InlineAsmError::DuplicateOperandName(name) => write!(f, "asm operand name `{}` is used twice", name),
+            InlineAsmError::UnknownOperandName(name) => write!(f, "no asm operand is named `{}`", name),
+            InlineAsmError::OperandOutOfRange(index) => write!(f, "asm operand %{} doesn't exist", index),
+            InlineAsmError::BadOperandReference(template) => write!(f, "bad operand reference in `{}`", template),
+        }
+    }
+}
+
+impl std::error::Error for InlineAsmError {}
+
+// Example usage:
+/*
+fn main() -> Result<(), InlineAsmError> {
+    let statement = parse_asm_statement(
+        r#"__asm__ volatile ("addl %[b], %0" : "+r"(a) : [b] "ri"(b) : "cc");"#,
+    )?;
+    assert_eq!(statement.outputs[0].expression, "a");
+    assert_eq!(statement.inputs[0].name.as_deref(), Some("b"));
+    assert_eq!(statement.llvm_template()?, "addl ${1}, ${0}");
+
+    // asm goto ("jc %l[fail]" :::: fail);
+    let statement = parse_asm_statement(r#"asm goto ("jc %l[fail]" :::: fail);"#)?;
+    assert_eq!(statement.llvm_template()?, "jc ${0:l}");
+    Ok(())
+}
+*/
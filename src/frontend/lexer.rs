@@ -0,0 +1,396 @@
+// src/frontend/lexer.rs
+//! Tokens of C source, for `C23Parser`
+//! Comments are skipped, and so are preprocessing directives: the parser
+//! takes source whose macros are already expanded. `#line` is the one
+//! directive that's read, so spans keep the line numbers of the file a
+//! pasted header came back to. Keywords are identifiers here; the parser
+//! tells them apart.
+//!
+//! Literals are decoded: integers with their suffix (`'` digit separators
+//! and `0b` included), floats, character constants as the `int` they are,
+//! and narrow or `u8` strings as their bytes.
+
+use super::ast::{IntegerSuffix, Span};
+
+/// Whether plain `char` is signed on the target; the tree only keeps the
+/// resolved signedness
+pub const PLAIN_CHAR_SIGNED: bool = !cfg!(target_arch = "aarch64");
+
+/// Longest first, so `>>=` isn't read as `>>` `=`
+const PUNCTUATORS: &[&str] = &[
+    "...", "<<=", ">>=", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=", "+=", "-=",
+    "&=", "^=", "|=", "[", "]", "(", ")", "{", "}", ".", "&", "*", "+", "-", "~", "!", "/", "%", "<", ">", "^", "|", "?",
+    ":", ";", "=", ",",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Identifier(String),
+    Integer { value: u64, suffix: IntegerSuffix },
+    Float { value: f64, single: bool },
+    Character(i64),
+    String(Vec<u8>),
+    Punctuator(&'static str),
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Line numbers of byte offsets, `#line` applied
+pub struct Lines {
+    starts: Vec<usize>,
+    /// (physical line, the line it's numbered as), in order
+    renumbered: Vec<(u32, u32)>,
+}
+
+impl Lines {
+    /// Line and column (both 1-based) of `offset`
+    pub fn position(&self, offset: usize) -> (u32, u32) {
+        let index = self.starts.partition_point(|&start| start <= offset).saturating_sub(1);
+        let physical = index as u32 + 1;
+        let column = (offset - self.starts[index]) as u32 + 1;
+        let line = match self.renumbered.iter().rev().find(|&&(from, _)| from <= physical) {
+            Some(&(from, number)) => number + (physical - from),
+            None => physical,
+        };
+        (line, column)
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let (line, column) = self.position(start);
+        Span { start, end, line, column }
+    }
+}
+
+/// `source` as tokens, ending with `TokenKind::End`
+pub fn tokenize(source: &str) -> Result<(Vec<Token>, Lines), LexError> {
+    let mut starts = vec![0];
+    starts.extend(source.match_indices('\n').map(|(offset, _)| offset + 1));
+    let mut lexer = Lexer { text: source.as_bytes(), pos: 0, lines: Lines { starts, renumbered: Vec::new() } };
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.token()?;
+        let end = token.kind == TokenKind::End;
+        tokens.push(token);
+        if end {
+            return Ok((tokens, lexer.lines));
+        }
+    }
+}
+
+struct Lexer<'a> {
+    text: &'a [u8],
+    pos: usize,
+    lines: Lines,
+}
+
+impl Lexer<'_> {
+    fn peek(&self, ahead: usize) -> u8 {
+        self.text.get(self.pos + ahead).copied().unwrap_or(0)
+    }
+
+    fn error(&self, at: usize, message: impl Into<String>) -> LexError {
+        let (line, column) = self.lines.position(at);
+        LexError { message: message.into(), line, column }
+    }
+
+    /// Whether only blanks come before `pos` on its line
+    fn at_line_start(&self) -> bool {
+        self.text[..self.pos].iter().rev().take_while(|&&c| c != b'\n').all(|c| c.is_ascii_whitespace())
+    }
+
+    /// Skip whitespace, comments and directives
+    fn skip(&mut self) -> Result<(), LexError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (c, _) if c.is_ascii_whitespace() => self.pos += 1,
+                (b'\\', b'\n') => self.pos += 2,
+                (b'/', b'/') => {
+                    while !matches!(self.peek(0), b'\n' | 0) {
+                        self.pos += 1;
+                    }
+                }
+                (b'/', b'*') => {
+                    let start = self.pos;
+                    self.pos += 2;
+                    while !(self.peek(0) == b'*' && self.peek(1) == b'/') {
+                        if self.pos >= self.text.len() {
+                            return Err(self.error(start, "unterminated comment"));
+                        }
+                        self.pos += 1;
+                    }
+                    self.pos += 2;
+                }
+                (b'#', _) if self.at_line_start() => self.directive(),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Skip a directive and its continuation lines, reading `#line`
+    fn directive(&mut self) {
+        let start = self.pos;
+        while !(self.peek(0) == b'\n' && self.text[self.pos - 1] != b'\\' || self.pos >= self.text.len()) {
+            self.pos += 1;
+        }
+        let text = String::from_utf8_lossy(&self.text[start + 1..self.pos]);
+        let mut words = text.split_whitespace();
+        let number = match words.next() {
+            Some("line") => words.next(),
+            // GCC's `# 12 "file"` markers
+            Some(word) if word.starts_with(|c: char| c.is_ascii_digit()) => Some(word),
+            _ => None,
+        };
+        if let Some(number) = number.and_then(|number| number.parse::<u32>().ok()) {
+            // It numbers the line after it
+            let physical = self.lines.starts.partition_point(|&start| start <= self.pos) as u32 + 1;
+            self.lines.renumbered.push((physical, number));
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, LexError> {
+        self.skip()?;
+        let start = self.pos;
+        let c = self.peek(0);
+        let kind = if self.pos >= self.text.len() {
+            TokenKind::End
+        } else if c.is_ascii_digit() || (c == b'.' && self.peek(1).is_ascii_digit()) {
+            self.number()?
+        } else if c == b'"' || c == b'\'' || self.literal_prefix().is_some() {
+            self.literal()?
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' || c >= 0x80 {
+            while matches!(self.peek(0), b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'$' | 0x80..) {
+                self.pos += 1;
+            }
+            TokenKind::Identifier(String::from_utf8_lossy(&self.text[start..self.pos]).into_owned())
+        } else {
+            let rest = &self.text[self.pos..];
+            let punctuator = PUNCTUATORS
+                .iter()
+                .find(|p| rest.starts_with(p.as_bytes()))
+                .ok_or_else(|| self.error(start, format!("unexpected character '{}'", c as char)))?;
+            self.pos += punctuator.len();
+            TokenKind::Punctuator(punctuator)
+        };
+        Ok(Token { kind, span: self.lines.span(start, self.pos) })
+    }
+
+    /// `L`, `u`, `U` or `u8` before a quote
+    fn literal_prefix(&self) -> Option<&'static str> {
+        ["u8", "L", "u", "U"].into_iter().find(|prefix| {
+            self.text[self.pos..].starts_with(prefix.as_bytes()) && matches!(self.peek(prefix.len()), b'"' | b'\'')
+        })
+    }
+
+    fn literal(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos;
+        let prefix = self.literal_prefix();
+        self.pos += prefix.map_or(0, str::len);
+        let quote = self.peek(0);
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        // Code points, for wide character constants
+        let mut units = Vec::new();
+        loop {
+            match self.peek(0) {
+                0 | b'\n' => return Err(self.error(start, "missing terminating quote")),
+                c if c == quote => {
+                    self.pos += 1;
+                    break;
+                }
+                b'\\' => {
+                    self.pos += 1;
+                    let (value, unicode) = self.escape()?;
+                    units.push(value);
+                    if unicode {
+                        let c = char::from_u32(value).ok_or_else(|| self.error(start, "invalid universal character name"))?;
+                        bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    } else {
+                        bytes.push(value as u8);
+                    }
+                }
+                _ => {
+                    let rest = std::str::from_utf8(&self.text[self.pos..]).unwrap_or("\u{fffd}");
+                    let c = rest.chars().next().unwrap_or('\u{fffd}');
+                    units.push(c as u32);
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+
+        if quote == b'"' {
+            return match prefix {
+                None | Some("u8") => Ok(TokenKind::String(bytes)),
+                Some(_) => Err(self.error(start, "wide string literals aren't supported")),
+            };
+        }
+        let value = match prefix {
+            Some("L" | "u" | "U") => *units.last().ok_or_else(|| self.error(start, "empty character constant"))? as i64,
+            _ => match bytes.as_slice() {
+                [] => return Err(self.error(start, "empty character constant")),
+                [byte] if PLAIN_CHAR_SIGNED && prefix.is_none() => *byte as i8 as i64,
+                [byte] => *byte as i64,
+                // Multi-character constants, as GCC packs them
+                bytes => bytes.iter().fold(0i64, |value, &byte| (value << 8) | byte as i64) as i32 as i64,
+            },
+        };
+        Ok(TokenKind::Character(value))
+    }
+
+    /// An escape sequence after its backslash: its value, and whether it's
+    /// a universal character name
+    fn escape(&mut self) -> Result<(u32, bool), LexError> {
+        let start = self.pos - 1;
+        let c = self.peek(0);
+        self.pos += 1;
+        let value = match c {
+            b'n' => b'\n' as u32,
+            b't' => b'\t' as u32,
+            b'r' => b'\r' as u32,
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'v' => 0x0b,
+            b'e' => 0x1b,
+            b'\\' | b'\'' | b'"' | b'?' => c as u32,
+            b'0'..=b'7' => {
+                let mut value = (c - b'0') as u32;
+                for _ in 0..2 {
+                    match self.peek(0) {
+                        d @ b'0'..=b'7' => {
+                            value = value * 8 + (d - b'0') as u32;
+                            self.pos += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                value
+            }
+            b'x' => {
+                let digits = self.digits(16);
+                if digits.is_empty() {
+                    return Err(self.error(start, "\\x used with no following hex digits"));
+                }
+                u32::from_str_radix(&digits, 16).unwrap_or(u32::MAX) & 0xff
+            }
+            b'u' | b'U' => {
+                let length = if c == b'u' { 4 } else { 8 };
+                let digits: String = self.text[self.pos..].iter().take(length).map(|&d| d as char).collect();
+                if digits.len() != length || !digits.chars().all(|d| d.is_ascii_hexdigit()) {
+                    return Err(self.error(start, "incomplete universal character name"));
+                }
+                self.pos += length;
+                return Ok((u32::from_str_radix(&digits, 16).unwrap_or(0), true));
+            }
+            _ => return Err(self.error(start, format!("unknown escape sequence '\\{}'", c as char))),
+        };
+        Ok((value, false))
+    }
+
+    /// Digits of `radix` and `'` separators from `pos`, separators dropped
+    fn digits(&mut self, radix: u32) -> String {
+        let mut digits = String::new();
+        loop {
+            let c = self.peek(0) as char;
+            if c.is_digit(radix) {
+                digits.push(c);
+            } else if c != '\'' || !(self.peek(1) as char).is_digit(radix) || digits.is_empty() {
+                return digits;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn number(&mut self) -> Result<TokenKind, LexError> {
+        let start = self.pos;
+        let lower = |c: u8| c.to_ascii_lowercase();
+        let radix = match (self.peek(0), lower(self.peek(1))) {
+            (b'0', b'x') if self.peek(2).is_ascii_hexdigit() || self.peek(2) == b'.' => 16,
+            (b'0', b'b') if matches!(self.peek(2), b'0' | b'1') => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.pos += 2;
+        }
+        let whole = self.digits(if radix == 10 { 10 } else { radix });
+        let fraction = match self.peek(0) {
+            b'.' if radix != 2 => {
+                self.pos += 1;
+                Some(self.digits(radix))
+            }
+            _ => None,
+        };
+        let exponent_mark = if radix == 16 { b'p' } else { b'e' };
+        let exponent = if lower(self.peek(0)) == exponent_mark
+            && (self.peek(1).is_ascii_digit() || matches!(self.peek(1), b'+' | b'-') && self.peek(2).is_ascii_digit())
+        {
+            self.pos += 1;
+            let negative = self.peek(0) == b'-';
+            if matches!(self.peek(0), b'+' | b'-') {
+                self.pos += 1;
+            }
+            let digits = self.digits(10);
+            let value: i32 = digits.parse().unwrap_or(i32::MAX);
+            Some(if negative { -value } else { value })
+        } else {
+            None
+        };
+
+        let suffix_start = self.pos;
+        while self.peek(0).is_ascii_alphanumeric() || self.peek(0) == b'_' {
+            self.pos += 1;
+        }
+        let suffix = String::from_utf8_lossy(&self.text[suffix_start..self.pos]).to_ascii_lowercase();
+
+        if fraction.is_some() || exponent.is_some() {
+            let single = match suffix.as_str() {
+                "" | "l" => false,
+                "f" => true,
+                _ => return Err(self.error(start, format!("invalid suffix '{}' on floating constant", suffix))),
+            };
+            let value = if radix == 16 {
+                let exponent = exponent.ok_or_else(|| self.error(start, "hexadecimal floating constant requires an exponent"))?;
+                let fraction = fraction.unwrap_or_default();
+                let mantissa = u128::from_str_radix(&format!("{}{}0", whole, fraction), 16).unwrap_or(0) as f64 / 16.0;
+                mantissa * 2f64.powi(exponent - 4 * fraction.len() as i32)
+            } else {
+                let text = format!("{}.{}e{}", if whole.is_empty() { "0" } else { &whole }, fraction.unwrap_or_default(), exponent.unwrap_or(0));
+                text.parse().map_err(|_| self.error(start, "invalid floating constant"))?
+            };
+            return Ok(TokenKind::Float { value, single });
+        }
+
+        // A leading 0 makes it octal
+        let (digits, radix) = match radix {
+            10 if whole.len() > 1 && whole.starts_with('0') => (&whole[1..], 8),
+            radix => (whole.as_str(), radix),
+        };
+        let value = u64::from_str_radix(digits, radix).map_err(|e| match e.kind() {
+            std::num::IntErrorKind::PosOverflow => self.error(start, "integer constant is too large"),
+            _ => self.error(start, "invalid integer constant"),
+        })?;
+        let suffix = match suffix.as_str() {
+            "" => IntegerSuffix::None,
+            "u" => IntegerSuffix::Unsigned,
+            "l" => IntegerSuffix::Long,
+            "ul" | "lu" => IntegerSuffix::UnsignedLong,
+            "ll" => IntegerSuffix::LongLong,
+            "ull" | "llu" => IntegerSuffix::UnsignedLongLong,
+            "wb" | "uwb" => return Err(self.error(start, "_BitInt constants aren't supported")),
+            _ => return Err(self.error(start, format!("invalid suffix '{}' on integer constant", suffix))),
+        };
+        Ok(TokenKind::Integer { value, suffix })
+    }
+}
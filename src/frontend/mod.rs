@@ -0,0 +1,23 @@
+// src/frontend/mod.rs
+//! C frontend: preprocessing, parsing and type checking
+
+pub mod apple;
+pub mod ast;
+pub mod attributes;
+pub mod auto_type;
+pub mod c23;
+pub mod c23_complete;
+pub mod contraints;
+pub mod embed;
+pub mod impl_defined;
+pub mod inline_asm;
+pub mod instrument;
+pub mod lexer;
+pub mod parser;
+pub mod preprocessor;
+pub mod preprocessor_c23;
+pub mod recovery;
+pub mod rewrite;
+pub mod types;
+pub mod usdt;
+pub mod visit;
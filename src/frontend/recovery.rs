@@ -0,0 +1,212 @@
+// src/frontend/recovery.rs
+//! Reporting more than the first parse error
+//! The parser stops at the first error. When it fails, the translation
+//! unit is cut at top-level declaration boundaries and re-parsed one
+//! declaration at a time, keeping the declarations that parsed (so later
+//! ones still see their typedefs) and blanking the ones that didn't (so
+//! line numbers stay put). Every declaration that fails gets its own
+//! diagnostic, until the engine's error limit says stop. A declaration that
+//! starts with a call to a function-like macro defined in the file is
+//! attributed to that macro, so a broken macro used many times folds into
+//! one note.
+
+use std::collections::HashSet;
+use std::fmt;
+use crate::diagnostics::catalog::MessageId;
+use crate::diagnostics::engine::{Diagnostic, DiagnosticsEngine, Recovery, Severity, SourceLocation};
+
+/// One top-level declaration, directive or function definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Byte range in the source, including the whitespace before it
+    pub start: usize,
+    pub end: usize,
+    /// Where its first token is
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Parse `source` with `parse`, reporting every failing declaration to
+/// `diagnostics`. Returns the tree when the whole unit parses.
+pub fn parse_reporting<T, E: fmt::Debug>(
+    source: &str,
+    file: &str,
+    mut parse: impl FnMut(&str) -> Result<T, E>,
+    diagnostics: &mut DiagnosticsEngine,
+) -> Option<T> {
+    let first = match parse(source) {
+        Ok(tree) => return Some(tree),
+        Err(e) => e,
+    };
+
+    let macros = function_like_macros(source);
+    let mut accepted = String::with_capacity(source.len());
+    let mut reported = false;
+    for chunk in chunks(source) {
+        let text = &source[chunk.start..chunk.end];
+        let candidate_len = accepted.len();
+        accepted.push_str(text);
+        let e = match parse(&accepted) {
+            Ok(_) => continue,
+            Err(e) => e,
+        };
+
+        // Keep the newlines so later declarations keep their line numbers
+        accepted.truncate(candidate_len);
+        accepted.extend(text.chars().map(|c| if c == '\n' { '\n' } else { ' ' }));
+
+        let mut diagnostic = Diagnostic::catalogued(Severity::Error, MessageId::ParseError, vec![format!("{:?}", e)])
+            .at(SourceLocation { file: file.to_string(), line: chunk.line, column: chunk.column });
+        if let Some(name) = leading_macro(text, &macros) {
+            diagnostic = diagnostic.in_macro(name);
+        }
+        reported = true;
+        if diagnostics.report(diagnostic) == Recovery::Stop {
+            return None;
+        }
+    }
+
+    // Every declaration parses on its own: the unit as a whole doesn't
+    // (an unclosed brace, say), so the parser's own error is the one to show
+    if !reported {
+        diagnostics.report(Diagnostic::catalogued(Severity::Error, MessageId::ParseError, vec![format!("{:?}", first)]));
+    }
+    None
+}
+
+/// Cut `source` into top-level chunks covering all of it. A chunk ends at a
+/// `;` outside any brackets, at the `}` closing a function body, or at the
+/// end of a preprocessor directive.
+pub fn chunks(source: &str) -> Vec<Chunk> {
+    let bytes = source.as_bytes();
+    let mut out = Vec::new();
+    let (mut i, mut line, mut column) = (0, 1u32, 1u32);
+    let mut start = 0;
+    let mut first_token: Option<(u32, u32)> = None;
+    let mut depth = 0usize;
+    let mut at_line_start = true;
+    // The last non-blank byte outside comments, and whether the outermost
+    // brace opened a function body
+    let mut previous = 0u8;
+    let mut function_body = false;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let here = (line, column);
+        let mut len = 1;
+        let comment = c == b'/' && matches!(bytes.get(i + 1), Some(b'/' | b'*'));
+
+        if comment && bytes[i + 1] == b'/' {
+            len = bytes[i..].iter().position(|&b| b == b'\n').unwrap_or(bytes.len() - i);
+        } else if comment {
+            len = source[i + 2..].find("*/").map_or(bytes.len() - i, |end| end + 4);
+        } else if c.is_ascii_whitespace() {
+            // Nothing to do
+        } else if c == b'#' && at_line_start && depth == 0 {
+            // A directive is a chunk of its own, continuations included
+            end_chunk(&mut out, &mut start, i, &mut first_token);
+            len = 0;
+            while i + len < bytes.len() && !(bytes[i + len] == b'\n' && (len == 0 || bytes[i + len - 1] != b'\\')) {
+                len += 1;
+            }
+            first_token = Some(here);
+            advance(bytes, &mut i, &mut line, &mut column, len);
+            end_chunk(&mut out, &mut start, i, &mut first_token);
+            previous = 0;
+            continue;
+        } else if c == b'"' || c == b'\'' {
+            len = 1;
+            while i + len < bytes.len() && bytes[i + len] != c && bytes[i + len] != b'\n' {
+                len += if bytes[i + len] == b'\\' { 2 } else { 1 };
+            }
+            len = (len + 1).min(bytes.len() - i);
+        } else {
+            match c {
+                b'(' | b'[' => depth += 1,
+                b'{' => {
+                    if depth == 0 {
+                        function_body = previous == b')';
+                    }
+                    depth += 1;
+                }
+                b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+
+        if !(c.is_ascii_whitespace() || comment) {
+            first_token.get_or_insert(here);
+            previous = c;
+        }
+        at_line_start = c == b'\n' || (at_line_start && c.is_ascii_whitespace());
+        advance(bytes, &mut i, &mut line, &mut column, len);
+
+        if depth == 0 && (c == b';' || (c == b'}' && function_body)) {
+            function_body = false;
+            end_chunk(&mut out, &mut start, i, &mut first_token);
+        }
+    }
+
+    // Trailing whitespace joins the last chunk; an unterminated one stands alone
+    match (first_token, out.last_mut()) {
+        (Some((line, column)), _) => out.push(Chunk { start, end: bytes.len(), line, column }),
+        (None, Some(last)) => last.end = bytes.len(),
+        (None, None) => {}
+    }
+    out
+}
+
+fn end_chunk(out: &mut Vec<Chunk>, start: &mut usize, end: usize, first_token: &mut Option<(u32, u32)>) {
+    if let Some((line, column)) = first_token.take() {
+        out.push(Chunk { start: *start, end, line, column });
+        *start = end;
+    }
+}
+
+fn advance(bytes: &[u8], i: &mut usize, line: &mut u32, column: &mut u32, n: usize) {
+    for &b in &bytes[*i..(*i + n).min(bytes.len())] {
+        if b == b'\n' {
+            *line += 1;
+            *column = 1;
+        } else if b & 0xC0 != 0x80 {
+            *column += 1;
+        }
+    }
+    *i = (*i + n).min(bytes.len());
+}
+
+/// Names from `#define NAME(` lines
+fn function_like_macros(source: &str) -> HashSet<&str> {
+    source
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix('#'))
+        .filter_map(|rest| rest.trim_start().strip_prefix("define"))
+        .filter_map(|rest| {
+            let rest = rest.trim_start();
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+            (end > 0 && rest[end..].starts_with('(')).then(|| &rest[..end])
+        })
+        .collect()
+}
+
+/// The macro a chunk starts by calling, if it is one of `macros`
+fn leading_macro<'a>(text: &str, macros: &HashSet<&'a str>) -> Option<&'a str> {
+    let text = text.trim_start();
+    let end = text.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(text.len());
+    let name = macros.get(&text[..end])?;
+    text[end..].trim_start().starts_with('(').then_some(*name)
+}
+
+// Example usage:
+/*
+fn main() {
+    let source = "#define FIELD(n) int n =\nFIELD(a);\nint ok;\nint broken = ;\n";
+    let mut diagnostics = DiagnosticsEngine::new(DiagnosticsConfig::default());
+    let tree = parse_reporting(source, "fields.c", |text| C23Parser::new().parse(text), &mut diagnostics);
+    assert!(tree.is_none());
+    diagnostics.flush_to_stderr();
+    // fields.c:2:1: error[E0001]: parse error: ...
+    // note: in expansion of macro 'FIELD'
+    // fields.c:4:1: error[E0001]: parse error: ...
+}
+*/
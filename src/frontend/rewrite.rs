@@ -0,0 +1,901 @@
+// src/frontend/rewrite.rs
+//! Source-to-source rewriting
+//! A `Rewriter` edits the text a `TranslationUnit` was parsed from rather
+//! than regenerating it from the tree, so everything outside the edited
+//! spans (comments, formatting, macro uses) comes out byte for byte. Edits
+//! are recorded against the original spans and applied together by
+//! `finish`, so they can be made in any order while walking the tree.
+//!
+//! New code is either text or a tree. `Printer` turns a tree back into C:
+//! a node with a real span is copied from the source as written and only
+//! synthetic nodes (`Span::synthetic()`) are printed, with parentheses
+//! wherever precedence needs them. Wrapping an existing expression in a
+//! new call, for example, keeps the expression's original text.
+
+use std::fmt;
+use super::ast::*;
+use super::lexer::PLAIN_CHAR_SIGNED;
+
+/// What one nesting level of printed statements is indented by
+const INDENT: &str = "    ";
+
+// Where edits at the same offset go relative to each other: text inserted
+// after a node ending there, then text inserted before a node starting
+// there, then the replacement of that node
+const AFTER: u8 = 0;
+const BEFORE: u8 = 1;
+const REPLACE: u8 = 2;
+
+#[derive(Debug, Clone)]
+struct Edit {
+    start: usize,
+    end: usize,
+    line: u32,
+    rank: u8,
+    text: String,
+}
+
+pub struct Rewriter<'src> {
+    source: &'src str,
+    edits: Vec<Edit>,
+    // Spans that could not be edited, reported by `finish`
+    invalid: Vec<RewriteError>,
+}
+
+impl<'src> Rewriter<'src> {
+    /// `source` is the text the tree being rewritten was parsed from
+    pub fn new(source: &'src str) -> Self {
+        Rewriter {
+            source,
+            edits: Vec::new(),
+            invalid: Vec::new(),
+        }
+    }
+
+    pub fn source(&self) -> &'src str {
+        self.source
+    }
+
+    /// The original text of a node; `None` for a synthetic span
+    pub fn text(&self, span: Span) -> Option<&'src str> {
+        if span.is_synthetic() {
+            return None;
+        }
+        self.source.get(span.start..span.end)
+    }
+
+    /// A printer that copies parsed nodes from this source
+    pub fn printer(&self) -> Printer<'src> {
+        Printer::new(self.source)
+    }
+
+    pub fn insert_before(&mut self, span: Span, text: impl Into<String>) -> &mut Self {
+        self.push(span, span.start, span.start, BEFORE, text.into())
+    }
+
+    pub fn insert_after(&mut self, span: Span, text: impl Into<String>) -> &mut Self {
+        self.push(span, span.end, span.end, AFTER, text.into())
+    }
+
+    pub fn replace(&mut self, span: Span, text: impl Into<String>) -> &mut Self {
+        self.push(span, span.start, span.end, REPLACE, text.into())
+    }
+
+    pub fn remove(&mut self, span: Span) -> &mut Self {
+        self.push(span, span.start, span.end, REPLACE, String::new())
+    }
+
+    /// Replace the text at `span` with `expression`, keeping the original
+    /// text of its parsed subexpressions
+    pub fn replace_expression(&mut self, span: Span, expression: &Expression) -> &mut Self {
+        let text = self.printer().expression(expression);
+        self.replace(span, text)
+    }
+
+    /// Replace the text at `span` with `statement`, indented to match the
+    /// line it starts on
+    pub fn replace_statement(&mut self, span: Span, statement: &Statement) -> &mut Self {
+        let indent = self.line_indent(span);
+        let text = self.printer().statement_at(statement, &indent);
+        self.replace(span, text)
+    }
+
+    /// Insert `statement` on its own line before the statement or
+    /// declaration at `span`
+    pub fn insert_statement_before(&mut self, span: Span, statement: &Statement) -> &mut Self {
+        let indent = self.line_indent(span);
+        let text = self.printer().statement_at(statement, &indent);
+        self.insert_before(span, format!("{}\n{}", text, indent))
+    }
+
+    /// Insert `statement` on its own line after the statement or
+    /// declaration at `span`
+    pub fn insert_statement_after(&mut self, span: Span, statement: &Statement) -> &mut Self {
+        let indent = self.line_indent(span);
+        let text = self.printer().statement_at(statement, &indent);
+        self.insert_after(span, format!("\n{}{}", indent, text))
+    }
+
+    /// Apply the edits. Insertions at the same place keep the order they
+    /// were made in; overlapping replacements are an error rather than a
+    /// guess.
+    pub fn finish(mut self) -> Result<String, RewriteError> {
+        if let Some(error) = self.invalid.drain(..).next() {
+            return Err(error);
+        }
+        self.edits.sort_by_key(|edit| (edit.start, edit.rank, edit.end));
+
+        let mut output = String::with_capacity(self.source.len());
+        let mut cursor = 0;
+        let mut last_line = 0;
+        for edit in &self.edits {
+            if edit.start < cursor {
+                return Err(RewriteError::Overlap {
+                    first_line: last_line,
+                    second_line: edit.line,
+                });
+            }
+            output.push_str(&self.source[cursor..edit.start]);
+            output.push_str(&edit.text);
+            cursor = edit.end;
+            if edit.end > edit.start {
+                last_line = edit.line;
+            }
+        }
+        output.push_str(&self.source[cursor..]);
+        Ok(output)
+    }
+
+    fn push(&mut self, span: Span, start: usize, end: usize, rank: u8, text: String) -> &mut Self {
+        if span.is_synthetic() {
+            self.invalid.push(RewriteError::SyntheticSpan);
+        } else if start > end
+            || end > self.source.len()
+            || !self.source.is_char_boundary(start)
+            || !self.source.is_char_boundary(end)
+        {
+            self.invalid.push(RewriteError::OutOfRange { start, end, len: self.source.len() });
+        } else {
+            self.edits.push(Edit { start, end, line: span.line, rank, text });
+        }
+        self
+    }
+
+    /// Leading whitespace of the line `span` starts on
+    fn line_indent(&self, span: Span) -> String {
+        let start = span.start.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        self.source[line_start..]
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect()
+    }
+}
+
+/// Prints syntax trees as C
+pub struct Printer<'src> {
+    source: &'src str,
+}
+
+impl<'src> Printer<'src> {
+    /// Parsed nodes are copied from `source`; use `""` to print every node
+    pub fn new(source: &'src str) -> Self {
+        Printer { source }
+    }
+
+    pub fn expression(&self, expression: &Expression) -> String {
+        let mut out = String::new();
+        self.write_expression(expression, COMMA, &mut out);
+        out
+    }
+
+    pub fn statement(&self, statement: &Statement) -> String {
+        self.statement_at(statement, "")
+    }
+
+    /// `statement`, with the lines after its first indented by `indent`
+    pub fn statement_at(&self, statement: &Statement, indent: &str) -> String {
+        let mut out = String::new();
+        self.write_statement(statement, indent, &mut out);
+        out
+    }
+
+    pub fn declaration(&self, declaration: &Declaration) -> String {
+        match self.original(declaration.span) {
+            Some(text) => text.to_string(),
+            None => self.print_declaration(declaration),
+        }
+    }
+
+    /// A declaration of `name` with type `ty`; an empty name gives the
+    /// abstract type as used in casts and `sizeof`
+    pub fn type_name(&self, ty: &TypeName, name: &str) -> String {
+        let (specifiers, declarator) = self.declarator(ty, name.to_string());
+        join_declarator(specifiers, &declarator)
+    }
+
+    pub fn function(&self, function: &FunctionDefinition) -> String {
+        if let Some(text) = self.original(function.span) {
+            return text.to_string();
+        }
+        let mut out = self.prototype(function, &function.name);
+        out.push(' ');
+        self.write_block(&function.body, "", &mut out);
+        out
+    }
+
+    /// `function`'s storage class, return type and parameters, declaring
+    /// `name`; without the body or a `;`
+    pub fn prototype(&self, function: &FunctionDefinition, name: &str) -> String {
+        let mut out = String::new();
+        if let Some(storage) = function.storage {
+            out.push_str(storage_keyword(storage));
+            out.push(' ');
+        }
+        let mut parameters: Vec<String> =
+            function.parameters.iter().map(|p| self.type_name(&p.ty, p.name.as_deref().unwrap_or(""))).collect();
+        if function.variadic {
+            parameters.push("...".to_string());
+        } else if parameters.is_empty() {
+            parameters.push("void".to_string());
+        }
+        let (specifiers, declarator) =
+            self.declarator(&function.return_type, format!("{}({})", name, parameters.join(", ")));
+        out.push_str(&join_declarator(specifiers, &declarator));
+        out
+    }
+
+    /// The source text of a parsed node
+    fn original(&self, span: Span) -> Option<&'src str> {
+        if span.is_synthetic() {
+            return None;
+        }
+        self.source.get(span.start..span.end).filter(|text| !text.is_empty())
+    }
+
+    fn write_expression(&self, expression: &Expression, min_precedence: u8, out: &mut String) {
+        let precedence = precedence(&expression.kind);
+        let parenthesize = precedence < min_precedence;
+        if parenthesize {
+            out.push('(');
+        }
+        match self.original(expression.span) {
+            Some(text) => out.push_str(text),
+            None => self.print_expression(expression, out),
+        }
+        if parenthesize {
+            out.push(')');
+        }
+    }
+
+    fn print_expression(&self, expression: &Expression, out: &mut String) {
+        match &expression.kind {
+            ExpressionKind::Integer { value, suffix } => {
+                out.push_str(&value.to_string());
+                out.push_str(match suffix {
+                    IntegerSuffix::None => "",
+                    IntegerSuffix::Unsigned => "u",
+                    IntegerSuffix::Long => "l",
+                    IntegerSuffix::UnsignedLong => "ul",
+                    IntegerSuffix::LongLong => "ll",
+                    IntegerSuffix::UnsignedLongLong => "ull",
+                });
+            }
+            ExpressionKind::Float { value, single } => {
+                if value.is_nan() {
+                    out.push_str(if *single { "__builtin_nanf(\"\")" } else { "__builtin_nan(\"\")" });
+                } else if value.is_infinite() {
+                    if *value < 0.0 {
+                        out.push('-');
+                    }
+                    out.push_str(if *single { "__builtin_inff()" } else { "__builtin_inf()" });
+                } else {
+                    // Debug always includes a '.' or exponent, so it stays a float
+                    out.push_str(&format!("{:?}", value));
+                    if *single {
+                        out.push('f');
+                    }
+                }
+            }
+            ExpressionKind::Character(value) => match u8::try_from(*value) {
+                Ok(byte) if byte.is_ascii_graphic() || byte == b' ' => {
+                    out.push('\'');
+                    if byte == b'\'' || byte == b'\\' {
+                        out.push('\\');
+                    }
+                    out.push(byte as char);
+                    out.push('\'');
+                }
+                _ => out.push_str(&value.to_string()),
+            },
+            ExpressionKind::String(bytes) => out.push_str(&string_literal(bytes)),
+            ExpressionKind::Identifier(name) => out.push_str(name),
+            ExpressionKind::Unary { op, operand } => match op {
+                UnaryOp::PostIncrement | UnaryOp::PostDecrement => {
+                    self.write_expression(operand, POSTFIX, out);
+                    out.push_str(op.symbol());
+                }
+                _ => {
+                    out.push_str(op.symbol());
+                    let mut operand_text = String::new();
+                    self.write_expression(operand, UNARY, &mut operand_text);
+                    // `- -x` is not `--x`, nor `& &x` `&&x`
+                    if operand_text.starts_with(op.symbol().chars().last().unwrap_or(' ')) {
+                        out.push(' ');
+                    }
+                    out.push_str(&operand_text);
+                }
+            },
+            ExpressionKind::Binary { op, left, right } => {
+                let precedence = binary_precedence(*op);
+                self.write_expression(left, precedence, out);
+                out.push(' ');
+                out.push_str(op.symbol());
+                out.push(' ');
+                self.write_expression(right, precedence + 1, out);
+            }
+            ExpressionKind::Assign { op, target, value } => {
+                self.write_expression(target, UNARY, out);
+                out.push(' ');
+                if let Some(op) = op {
+                    out.push_str(op.symbol());
+                }
+                out.push_str("= ");
+                self.write_expression(value, ASSIGN, out);
+            }
+            ExpressionKind::Conditional { condition, then, otherwise } => {
+                self.write_expression(condition, CONDITIONAL + 1, out);
+                out.push_str(" ? ");
+                self.write_expression(then, COMMA, out);
+                out.push_str(" : ");
+                self.write_expression(otherwise, CONDITIONAL, out);
+            }
+            ExpressionKind::Call { function, arguments } => {
+                self.write_expression(function, POSTFIX, out);
+                out.push('(');
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.write_expression(argument, ASSIGN, out);
+                }
+                out.push(')');
+            }
+            ExpressionKind::Index { array, index } => {
+                self.write_expression(array, POSTFIX, out);
+                out.push('[');
+                self.write_expression(index, COMMA, out);
+                out.push(']');
+            }
+            ExpressionKind::Member { base, member, arrow } => {
+                self.write_expression(base, POSTFIX, out);
+                out.push_str(if *arrow { "->" } else { "." });
+                out.push_str(member);
+            }
+            ExpressionKind::Cast { ty, operand } => {
+                out.push('(');
+                out.push_str(&self.type_name(ty, ""));
+                out.push(')');
+                self.write_expression(operand, UNARY, out);
+            }
+            ExpressionKind::SizeofType(ty) => {
+                out.push_str("sizeof(");
+                out.push_str(&self.type_name(ty, ""));
+                out.push(')');
+            }
+            ExpressionKind::SizeofExpression(operand) => {
+                out.push_str("sizeof ");
+                self.write_expression(operand, UNARY, out);
+            }
+            ExpressionKind::AlignofType(ty) => {
+                out.push_str("alignof(");
+                out.push_str(&self.type_name(ty, ""));
+                out.push(')');
+            }
+            ExpressionKind::Comma(left, right) => {
+                self.write_expression(left, COMMA, out);
+                out.push_str(", ");
+                self.write_expression(right, ASSIGN, out);
+            }
+            ExpressionKind::Generic { control, associations } => {
+                out.push_str("_Generic(");
+                self.write_type_operand(control, out);
+                for (ty, value) in associations {
+                    out.push_str(", ");
+                    match ty {
+                        Some(ty) => out.push_str(&self.type_name(ty, "")),
+                        None => out.push_str("default"),
+                    }
+                    out.push_str(": ");
+                    self.write_expression(value, ASSIGN, out);
+                }
+                out.push(')');
+            }
+        }
+    }
+
+    fn write_type_operand(&self, operand: &TypeOperand, out: &mut String) {
+        match operand {
+            TypeOperand::Expression(expression) => self.write_expression(expression, ASSIGN, out),
+            TypeOperand::TypeName(ty) => out.push_str(&self.type_name(ty, "")),
+        }
+    }
+
+    fn write_statement(&self, statement: &Statement, indent: &str, out: &mut String) {
+        if let Some(text) = self.original(statement.span) {
+            out.push_str(text);
+            return;
+        }
+        match &statement.kind {
+            StatementKind::Expression(expression) => {
+                if let Some(expression) = expression {
+                    self.write_expression(expression, COMMA, out);
+                }
+                out.push(';');
+            }
+            StatementKind::Compound(block) => self.write_block(block, indent, out),
+            StatementKind::If { condition, then, otherwise } => {
+                out.push_str("if (");
+                self.write_expression(condition, COMMA, out);
+                out.push_str(") ");
+                // An `else` would attach to an inner `if` that has none
+                let dangling = otherwise.is_some()
+                    && matches!(&then.kind, StatementKind::If { otherwise: None, .. })
+                    && then.span.is_synthetic();
+                if dangling {
+                    let inner = format!("{}{}", indent, INDENT);
+                    out.push_str("{\n");
+                    out.push_str(&inner);
+                    self.write_statement(then, &inner, out);
+                    out.push('\n');
+                    out.push_str(indent);
+                    out.push('}');
+                } else {
+                    self.write_statement(then, indent, out);
+                }
+                if let Some(otherwise) = otherwise {
+                    out.push_str(" else ");
+                    self.write_statement(otherwise, indent, out);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                out.push_str("while (");
+                self.write_expression(condition, COMMA, out);
+                out.push_str(") ");
+                self.write_statement(body, indent, out);
+            }
+            StatementKind::DoWhile { body, condition } => {
+                out.push_str("do ");
+                self.write_statement(body, indent, out);
+                out.push_str(" while (");
+                self.write_expression(condition, COMMA, out);
+                out.push_str(");");
+            }
+            StatementKind::For { init, condition, step, body } => {
+                out.push_str("for (");
+                match init {
+                    Some(ForInit::Declaration(declaration)) => out.push_str(&self.declaration(declaration)),
+                    Some(ForInit::Expression(expression)) => {
+                        self.write_expression(expression, COMMA, out);
+                        out.push(';');
+                    }
+                    None => out.push(';'),
+                }
+                if let Some(condition) = condition {
+                    out.push(' ');
+                    self.write_expression(condition, COMMA, out);
+                }
+                out.push(';');
+                if let Some(step) = step {
+                    out.push(' ');
+                    self.write_expression(step, COMMA, out);
+                }
+                out.push_str(") ");
+                self.write_statement(body, indent, out);
+            }
+            StatementKind::Switch { value, body } => {
+                out.push_str("switch (");
+                self.write_expression(value, COMMA, out);
+                out.push_str(") ");
+                self.write_statement(body, indent, out);
+            }
+            StatementKind::Case { value, body } => {
+                out.push_str("case ");
+                self.write_expression(value, CONDITIONAL, out);
+                out.push_str(": ");
+                self.write_statement(body, indent, out);
+            }
+            StatementKind::Default(body) => {
+                out.push_str("default: ");
+                self.write_statement(body, indent, out);
+            }
+            StatementKind::Labeled { label, body } => {
+                out.push_str(label);
+                out.push_str(": ");
+                self.write_statement(body, indent, out);
+            }
+            StatementKind::Goto(label) => {
+                out.push_str("goto ");
+                out.push_str(label);
+                out.push(';');
+            }
+            StatementKind::Break => out.push_str("break;"),
+            StatementKind::Continue => out.push_str("continue;"),
+            StatementKind::Return(value) => {
+                out.push_str("return");
+                if let Some(value) = value {
+                    out.push(' ');
+                    self.write_expression(value, COMMA, out);
+                }
+                out.push(';');
+            }
+            StatementKind::Asm { statement, outputs, inputs } => {
+                out.push_str("__asm__");
+                let qualifiers = statement.qualifiers;
+                for (set, word) in [(qualifiers.volatile, " volatile"), (qualifiers.inline, " inline"), (qualifiers.goto, " goto")] {
+                    if set {
+                        out.push_str(word);
+                    }
+                }
+                out.push_str(" (");
+                out.push_str(&string_literal(statement.template.as_bytes()));
+                if !statement.basic {
+                    for (operands, expressions) in [(&statement.outputs, outputs), (&statement.inputs, inputs)] {
+                        out.push_str(" :");
+                        for (index, (operand, expression)) in operands.iter().zip(expressions).enumerate() {
+                            out.push_str(if index == 0 { " " } else { ", " });
+                            if let Some(name) = &operand.name {
+                                out.push_str(&format!("[{}] ", name));
+                            }
+                            out.push_str(&string_literal(operand.constraint.as_bytes()));
+                            out.push_str(" (");
+                            self.write_expression(expression, COMMA, out);
+                            out.push(')');
+                        }
+                    }
+                    let clobbers: Vec<String> = statement.clobbers.iter().map(|clobber| string_literal(clobber.as_bytes())).collect();
+                    out.push_str(" : ");
+                    out.push_str(&clobbers.join(", "));
+                    if qualifiers.goto {
+                        out.push_str(" : ");
+                        out.push_str(&statement.labels.join(", "));
+                    }
+                }
+                out.push_str(");");
+            }
+        }
+    }
+
+    fn write_block(&self, block: &Block, indent: &str, out: &mut String) {
+        if let Some(text) = self.original(block.span) {
+            out.push_str(text);
+            return;
+        }
+        let inner = format!("{}{}", indent, INDENT);
+        out.push('{');
+        for item in &block.items {
+            out.push('\n');
+            out.push_str(&inner);
+            match item {
+                BlockItem::Declaration(declaration) => out.push_str(&self.declaration(declaration)),
+                BlockItem::Statement(statement) => self.write_statement(statement, &inner, out),
+            }
+        }
+        out.push('\n');
+        out.push_str(indent);
+        out.push('}');
+    }
+
+    fn print_declaration(&self, declaration: &Declaration) -> String {
+        let storage = declaration.storage.map_or(String::new(), |s| format!("{} ", storage_keyword(s)));
+        if declaration.declarators.is_empty() {
+            return format!("{}{};", storage, self.specifiers(&declaration.ty));
+        }
+
+        // Declarators share one specifier list when their base types agree,
+        // which also keeps a tag definition from being repeated
+        let parts: Vec<(String, String)> = declaration
+            .declarators
+            .iter()
+            .map(|declarator| {
+                let (specifiers, mut text) = self.declarator(&declarator.ty, declarator.name.clone());
+                if let Some(initializer) = &declarator.initializer {
+                    text.push_str(" = ");
+                    self.write_initializer(initializer, &mut text);
+                }
+                (specifiers, text)
+            })
+            .collect();
+        if parts.iter().all(|(specifiers, _)| *specifiers == parts[0].0) {
+            let declarators: Vec<&str> = parts.iter().map(|(_, text)| text.as_str()).collect();
+            format!("{}{};", storage, join_declarator(parts[0].0.clone(), &declarators.join(", ")))
+        } else {
+            parts
+                .into_iter()
+                .map(|(specifiers, text)| format!("{}{};", storage, join_declarator(specifiers, &text)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    }
+
+    fn write_initializer(&self, initializer: &Initializer, out: &mut String) {
+        match initializer {
+            Initializer::Expression(expression) => self.write_expression(expression, ASSIGN, out),
+            Initializer::List(entries) => {
+                out.push('{');
+                for (i, (designator, initializer)) in entries.iter().enumerate() {
+                    out.push_str(if i == 0 { " " } else { ", " });
+                    match designator {
+                        Some(Designator::Index(index)) => {
+                            out.push('[');
+                            self.write_expression(index, CONDITIONAL, out);
+                            out.push_str("] = ");
+                        }
+                        Some(Designator::Member(member)) => {
+                            out.push('.');
+                            out.push_str(member);
+                            out.push_str(" = ");
+                        }
+                        None => {}
+                    }
+                    self.write_initializer(initializer, out);
+                }
+                out.push_str(if entries.is_empty() { "}" } else { " }" });
+            }
+        }
+    }
+
+    /// Split `ty` applied to `inner` into its specifiers and declarator,
+    /// working outwards from the name: `int *(*f)[3]` is
+    /// `("int", "*(*f)[3]")`
+    fn declarator(&self, ty: &TypeName, inner: String) -> (String, String) {
+        match ty {
+            TypeName::Pointer(target) => self.declarator(target, format!("*{}", inner)),
+            TypeName::Array(element, length) => {
+                let mut text = group(inner);
+                text.push('[');
+                if let Some(length) = length {
+                    self.write_expression(length, ASSIGN, &mut text);
+                }
+                text.push(']');
+                self.declarator(element, text)
+            }
+            TypeName::Function { return_type, parameters, variadic } => {
+                let mut parameters: Vec<String> = parameters.iter().map(|p| self.type_name(p, "")).collect();
+                if *variadic {
+                    parameters.push("...".to_string());
+                } else if parameters.is_empty() {
+                    parameters.push("void".to_string());
+                }
+                self.declarator(return_type, format!("{}({})", group(inner), parameters.join(", ")))
+            }
+            _ => (self.specifiers(ty), inner),
+        }
+    }
+
+    fn specifiers(&self, ty: &TypeName) -> String {
+        let integer = |signed: bool, name: &str| if signed { name.to_string() } else { format!("unsigned {}", name) };
+        match ty {
+            TypeName::Void => "void".to_string(),
+            TypeName::Bool => "bool".to_string(),
+            TypeName::Char { signed } if *signed == PLAIN_CHAR_SIGNED => "char".to_string(),
+            TypeName::Char { signed: true } => "signed char".to_string(),
+            TypeName::Char { signed: false } => "unsigned char".to_string(),
+            TypeName::Short { signed } => integer(*signed, "short"),
+            TypeName::Int { signed } => integer(*signed, "int"),
+            TypeName::Long { signed } => integer(*signed, "long"),
+            TypeName::LongLong { signed } => integer(*signed, "long long"),
+            TypeName::Float => "float".to_string(),
+            TypeName::Double => "double".to_string(),
+            TypeName::LongDouble => "long double".to_string(),
+            TypeName::Struct(record) => self.record("struct", record),
+            TypeName::Union(record) => self.record("union", record),
+            TypeName::Enum(enumeration) => {
+                let mut out = "enum".to_string();
+                if let Some(tag) = &enumeration.tag {
+                    out.push(' ');
+                    out.push_str(tag);
+                }
+                if let Some(enumerators) = &enumeration.enumerators {
+                    out.push_str(" { ");
+                    for (i, (name, value)) in enumerators.iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        out.push_str(name);
+                        if let Some(value) = value {
+                            out.push_str(" = ");
+                            self.write_expression(value, CONDITIONAL, &mut out);
+                        }
+                    }
+                    out.push_str(" }");
+                }
+                out
+            }
+            TypeName::Named(name) => name.clone(),
+            TypeName::Typeof { unqual, operand } => {
+                let mut out = if *unqual { "typeof_unqual(" } else { "typeof(" }.to_string();
+                self.write_type_operand(operand, &mut out);
+                out.push(')');
+                out
+            }
+            TypeName::Auto => "auto".to_string(),
+            // Derived types are handled by `declarator`
+            TypeName::Pointer(_) | TypeName::Array(..) | TypeName::Function { .. } => self.type_name(ty, ""),
+        }
+    }
+
+    fn record(&self, keyword: &str, record: &RecordType) -> String {
+        let mut out = keyword.to_string();
+        if let Some(tag) = &record.tag {
+            out.push(' ');
+            out.push_str(tag);
+        }
+        if let Some(members) = &record.members {
+            out.push_str(" {");
+            for member in members {
+                out.push(' ');
+                out.push_str(&self.type_name(&member.ty, member.name.as_deref().unwrap_or("")));
+                if let Some(width) = member.bit_width {
+                    out.push_str(&format!(" : {}", width));
+                }
+                out.push(';');
+            }
+            out.push_str(" }");
+        }
+        out
+    }
+}
+
+// Expression precedence, loosest first
+const COMMA: u8 = 1;
+const ASSIGN: u8 = 2;
+const CONDITIONAL: u8 = 3;
+const UNARY: u8 = 14;
+const POSTFIX: u8 = 15;
+const PRIMARY: u8 = 16;
+
+fn binary_precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::LogicalOr => 4,
+        BinaryOp::LogicalAnd => 5,
+        BinaryOp::BitOr => 6,
+        BinaryOp::BitXor => 7,
+        BinaryOp::BitAnd => 8,
+        BinaryOp::Eq | BinaryOp::Ne => 9,
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 10,
+        BinaryOp::Shl | BinaryOp::Shr => 11,
+        BinaryOp::Add | BinaryOp::Sub => 12,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 13,
+    }
+}
+
+fn precedence(kind: &ExpressionKind) -> u8 {
+    match kind {
+        ExpressionKind::Comma(..) => COMMA,
+        ExpressionKind::Assign { .. } => ASSIGN,
+        ExpressionKind::Conditional { .. } => CONDITIONAL,
+        ExpressionKind::Binary { op, .. } => binary_precedence(*op),
+        ExpressionKind::Unary { op: UnaryOp::PostIncrement | UnaryOp::PostDecrement, .. } => POSTFIX,
+        ExpressionKind::Unary { .. }
+        | ExpressionKind::Cast { .. }
+        | ExpressionKind::SizeofType(_)
+        | ExpressionKind::SizeofExpression(_)
+        | ExpressionKind::AlignofType(_) => UNARY,
+        ExpressionKind::Call { .. } | ExpressionKind::Index { .. } | ExpressionKind::Member { .. } => POSTFIX,
+        // A negative literal is printed with its sign
+        ExpressionKind::Float { value, .. } if value.is_sign_negative() => UNARY,
+        ExpressionKind::Character(value) if *value < 0 => UNARY,
+        _ => PRIMARY,
+    }
+}
+
+/// `*p` needs parentheses before `[]` or `()` binds to it
+fn group(declarator: String) -> String {
+    if declarator.starts_with('*') {
+        format!("({})", declarator)
+    } else {
+        declarator
+    }
+}
+
+fn join_declarator(specifiers: String, declarator: &str) -> String {
+    if declarator.is_empty() {
+        specifiers
+    } else {
+        format!("{} {}", specifiers, declarator)
+    }
+}
+
+fn storage_keyword(storage: StorageClass) -> &'static str {
+    match storage {
+        StorageClass::Typedef => "typedef",
+        StorageClass::Extern => "extern",
+        StorageClass::Static => "static",
+        StorageClass::ThreadLocal => "thread_local",
+        StorageClass::Auto => "auto",
+        StorageClass::Register => "register",
+    }
+}
+
+/// A string literal for `bytes`, with octal escapes so a following digit
+/// can't extend them
+fn string_literal(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            // `??` starts a trigraph in older dialects
+            b'?' => out.push_str("\\?"),
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewriteError {
+    /// An edit was anchored at a node without source text
+    SyntheticSpan,
+    /// A span outside the source, or splitting a character; the tree was
+    /// parsed from different text
+    OutOfRange { start: usize, end: usize, len: usize },
+    /// Two edits change the same text
+    Overlap { first_line: u32, second_line: u32 },
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::SyntheticSpan => write!(f, "cannot edit at a synthetic node; it has no source text"),
+            RewriteError::OutOfRange { start, end, len } => {
+                write!(f, "span {}..{} is not within the {}-byte source", start, end, len)
+            }
+            RewriteError::Overlap { first_line, second_line } => {
+                write!(f, "edits at lines {} and {} overlap", first_line, second_line)
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+/// Route every `malloc(n)` through `xmalloc(n, __FILE__, __LINE__)`
+fn migrate(source: &str, unit: &TranslationUnit) -> Result<String, RewriteError> {
+    struct Calls<'a, 'src> {
+        rewriter: &'a mut Rewriter<'src>,
+    }
+
+    impl Visit for Calls<'_, '_> {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let ExpressionKind::Call { function, arguments } = &expression.kind {
+                if matches!(&function.kind, ExpressionKind::Identifier(name) if name == "malloc") {
+                    let mut arguments = arguments.clone();
+                    arguments.push(Expression::new(ExpressionKind::Identifier("__FILE__".into()), Span::synthetic()));
+                    arguments.push(Expression::new(ExpressionKind::Identifier("__LINE__".into()), Span::synthetic()));
+                    let call = Expression::new(
+                        ExpressionKind::Call {
+                            function: Box::new(Expression::new(ExpressionKind::Identifier("xmalloc".into()), Span::synthetic())),
+                            arguments,
+                        },
+                        Span::synthetic(),
+                    );
+                    // The size argument keeps its original text
+                    self.rewriter.replace_expression(expression.span, &call);
+                    return;
+                }
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    let mut rewriter = Rewriter::new(source);
+    Calls { rewriter: &mut rewriter }.visit_translation_unit(unit);
+    rewriter.finish()
+}
+*/
@@ -1,5 +1,31 @@
+use bitflags::bitflags;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Qualifiers: u8 {
+        const CONST = 0b0001;
+        const VOLATILE = 0b0010;
+        const RESTRICT = 0b0100;
+        const ATOMIC = 0b1000;
+    }
+}
+
+/// Length of a variable length array. The size expression is evaluated once,
+/// when the declaration is reached, and kept in a hidden local for `sizeof`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VlaBound {
+    /// `[*]`: unspecified, only allowed in prototype scope
+    Star,
+    /// Function-local slot holding the evaluated length
+    Runtime(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum CType {
     Void,
+    Bool,
+    /// Plain `char`: its own type for _Generic, whatever its signedness
+    PlainChar,
     Char { signed: bool },
     Short { signed: bool },
     Int { signed: bool },
@@ -10,9 +36,13 @@ pub enum CType {
     LongDouble,
     Pointer(Box<CType>),
     Array(Box<CType>, Option<usize>),
+    /// `T[n]` where `n` isn't a constant expression, or `T[*]` in a prototype
+    VariableArray(Box<CType>, VlaBound),
     Struct(StructType),
     Union(UnionType),
     Enum(EnumType),
     Function(FunctionType),
     Typedef(String),
+    /// `const`, `volatile`, `restrict` and `_Atomic` applied to a type
+    Qualified(Qualifiers, Box<CType>),
 } 
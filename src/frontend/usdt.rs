@@ -0,0 +1,259 @@
+// src/frontend/usdt.rs
+//! User-defined probe points (USDT)
+//! Runs on the source before preprocessing, so programs instrumented for
+//! SystemTap or DTrace work without the host's `<sys/sdt.h>`:
+//!  - `#include <sys/sdt.h>` is dropped
+//!  - `DTRACE_PROBEn(provider, name, args...)`, `STAP_PROBEn(...)` and
+//!    `STAP_PROBEV(...)` become probe sites, numbered in source order
+//!
+//! Interpreted, a site is `__builtin_probe(site, args...)`: the interpreter
+//! evaluates the arguments and checks the site's enabled flag, recording
+//! them only if a tool turned it on (see `interpreter::probe_points`).
+//! Compiled, a site keeps only its arguments' side effects.
+//!
+//! A probe inside a `#define` body is rewritten there, so every expansion of
+//! the macro shares its site. Line breaks inside an invocation are kept, so
+//! line numbers don't move.
+
+use std::fmt;
+
+/// What a probe site turns into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lowering {
+    /// `__builtin_probe(site, args...)`
+    Interpreted,
+    /// `((void)(arg), ..., (void)0)`
+    Compiled,
+}
+
+/// Name of the call interpreted sites become
+pub const PROBE_BUILTIN: &str = "__builtin_probe";
+
+/// USDT probes take at most 12 arguments
+pub const MAX_ARGUMENTS: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeSite {
+    pub provider: String,
+    pub name: String,
+    pub file: String,
+    pub line: usize,
+    pub arguments: usize,
+}
+
+impl fmt::Display for ProbeSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{} ({}:{}, {} argument(s))", self.provider, self.name, self.file, self.line, self.arguments)
+    }
+}
+
+#[derive(Debug)]
+pub struct Rewritten {
+    pub source: String,
+    /// Indexed by the site number passed to `__builtin_probe`
+    pub sites: Vec<ProbeSite>,
+}
+
+/// Rewrite the probe macros in `source`
+pub fn rewrite(source: &str, file: &str, lowering: Lowering) -> Result<Rewritten, UsdtError> {
+    let bytes = source.as_bytes();
+    let mut output = String::with_capacity(source.len());
+    let mut sites = Vec::new();
+    let mut copied = 0;
+    let mut i = 0;
+    let mut line_start = true;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => {
+                line_start = true;
+                i += 1;
+            }
+            b' ' | b'\t' | b'\r' => i += 1,
+            b'#' if line_start => {
+                let end = source[i..].find('\n').map_or(source.len(), |n| i + n);
+                if is_sdt_include(&source[i..end]) {
+                    output.push_str(&source[copied..i]);
+                    copied = end;
+                }
+                // Continuation lines belong to the directive, which may be a
+                // `#define` holding a probe: only skip the `#`
+                line_start = false;
+                i += 1;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = source[i..].find('\n').map_or(source.len(), |n| i + n);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = source[i + 2..].find("*/").map_or(source.len(), |n| i + 2 + n + 2);
+            }
+            quote @ (b'"' | b'\'') => {
+                line_start = false;
+                i = skip_literal(bytes, i, quote);
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                line_start = false;
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let Some(declared) = probe_arity(&source[start..i]) else { continue };
+                let open = i + source[i..].len() - source[i..].trim_start().len();
+                if bytes.get(open) != Some(&b'(') {
+                    continue;
+                }
+                let line = source[..start].matches('\n').count() + 1;
+                let (arguments, close) = split_arguments(source, open)
+                    .ok_or_else(|| UsdtError::Unterminated { file: file.to_string(), line })?;
+                let site = probe_site(&arguments, declared, file, line)?;
+
+                output.push_str(&source[copied..start]);
+                let emitted = output.len();
+                let values = &arguments[2..];
+                match lowering {
+                    Lowering::Interpreted => {
+                        output.push_str(&format!("{}({}", PROBE_BUILTIN, sites.len()));
+                        for value in values {
+                            output.push_str(&format!(", (long)({})", value.trim()));
+                        }
+                        output.push(')');
+                    }
+                    Lowering::Compiled => {
+                        output.push('(');
+                        for value in values {
+                            output.push_str(&format!("(void)({}), ", value.trim()));
+                        }
+                        output.push_str("(void)0)");
+                    }
+                }
+                // Keep the line count: a multi-line invocation becomes one line
+                // followed by its line breaks, escaped if it is in a `#define`
+                let breaks = source[start..close].matches('\n').count() - output[emitted..].matches('\n').count();
+                let in_directive = source[start..close].contains("\\\n");
+                for _ in 0..breaks {
+                    output.push_str(if in_directive { "\\\n" } else { "\n" });
+                }
+                sites.push(site);
+                i = close;
+                copied = close;
+            }
+            _ => {
+                line_start = false;
+                i += 1;
+            }
+        }
+    }
+    output.push_str(&source[copied..]);
+    Ok(Rewritten { source: output, sites })
+}
+
+fn is_sdt_include(directive: &str) -> bool {
+    let rest = directive[1..].trim_start();
+    rest.strip_prefix("include").is_some_and(|path| path.trim() == "<sys/sdt.h>")
+}
+
+/// The argument count a probe macro declares, not counting provider and
+/// name; `None` for other identifiers, `Some(None)` for variadic ones
+fn probe_arity(identifier: &str) -> Option<Option<usize>> {
+    let suffix = identifier.strip_prefix("DTRACE_PROBE").or_else(|| identifier.strip_prefix("STAP_PROBE"))?;
+    match suffix {
+        "" => Some(Some(0)),
+        "V" if identifier.starts_with("STAP") => Some(None),
+        digits => digits.parse().ok().filter(|&n| n <= MAX_ARGUMENTS).map(Some),
+    }
+}
+
+fn probe_site(arguments: &[String], declared: Option<usize>, file: &str, line: usize) -> Result<ProbeSite, UsdtError> {
+    let error = |message: String| UsdtError::Invalid { file: file.to_string(), line, message };
+    if arguments.len() < 2 {
+        return Err(error("a probe needs a provider and a name".to_string()));
+    }
+    let count = arguments.len() - 2;
+    if declared.is_some_and(|declared| declared != count) {
+        return Err(error(format!("expected {} argument(s) after the name, found {}", declared.unwrap(), count)));
+    }
+    if count > MAX_ARGUMENTS {
+        return Err(error(format!("at most {} arguments, found {}", MAX_ARGUMENTS, count)));
+    }
+    let identifier = |text: &str| {
+        let text = text.trim();
+        let valid = text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        valid.then(|| text.to_string())
+    };
+    let provider = identifier(&arguments[0]).ok_or_else(|| error(format!("provider '{}' is not an identifier", arguments[0].trim())))?;
+    let name = identifier(&arguments[1]).ok_or_else(|| error(format!("probe name '{}' is not an identifier", arguments[1].trim())))?;
+    Ok(ProbeSite { provider, name, file: file.to_string(), line, arguments: count })
+}
+
+/// The top-level arguments of the call whose `(` is at `open`, and the
+/// offset after its `)`
+fn split_arguments(source: &str, open: usize) -> Option<(Vec<String>, usize)> {
+    let bytes = source.as_bytes();
+    let mut arguments = Vec::new();
+    let mut depth = 0usize;
+    let mut start = open + 1;
+    let mut i = open + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' if depth == 0 => {
+                let last = &source[start..i];
+                if !(arguments.is_empty() && last.trim().is_empty()) {
+                    arguments.push(last.to_string());
+                }
+                return Some((arguments, i + 1));
+            }
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            b',' if depth == 0 => {
+                arguments.push(source[start..i].to_string());
+                start = i + 1;
+            }
+            quote @ (b'"' | b'\'') => {
+                i = skip_literal(bytes, i, quote);
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Offset after the string or character literal starting at `start`
+fn skip_literal(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() && bytes[i] != quote && bytes[i] != b'\n' {
+        i += if bytes[i] == b'\\' { 2 } else { 1 };
+    }
+    (i + 1).min(bytes.len())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsdtError {
+    /// A probe macro's `(` has no matching `)`
+    Unterminated { file: String, line: usize },
+    Invalid { file: String, line: usize, message: String },
+}
+
+impl fmt::Display for UsdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsdtError::Unterminated { file, line } => write!(f, "{}:{}: unterminated probe", file, line),
+            UsdtError::Invalid { file, line, message } => write!(f, "{}:{}: invalid probe: {}", file, line, message),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), UsdtError> {
+    let source = "#include <sys/sdt.h>\n\
+                  int main(void) { int n = 3; DTRACE_PROBE2(app, start, n, n * 2); return 0; }\n";
+    let rewritten = rewrite(source, "app.c", Lowering::Interpreted)?;
+    // ... { int n = 3; __builtin_probe(0, (long)(n), (long)(n * 2)); return 0; }
+    println!("{}", rewritten.source);
+    println!("{}", rewritten.sites[0]); // app:start (app.c:2, 2 argument(s))
+    Ok(())
+}
+*/
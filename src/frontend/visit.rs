@@ -0,0 +1,561 @@
+// src/frontend/visit.rs
+//! Traversal of the C syntax tree
+//! `Visit` walks a `TranslationUnit` by shared reference and `VisitMut` by
+//! mutable reference. Every method has a default that calls the matching
+//! `walk_*` function, which visits the node's children in source order, so
+//! an implementation overrides only the nodes it cares about and calls
+//! `walk_*` itself to keep descending (or doesn't, to prune).
+//!
+//! A node kind added to the tree gets a method with a default here, so
+//! existing visitors keep compiling.
+
+use super::ast::*;
+
+pub trait Visit {
+    fn visit_translation_unit(&mut self, unit: &TranslationUnit) {
+        walk_translation_unit(self, unit);
+    }
+
+    fn visit_function(&mut self, function: &FunctionDefinition) {
+        walk_function(self, function);
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        walk_parameter(self, parameter);
+    }
+
+    fn visit_declaration(&mut self, declaration: &Declaration) {
+        walk_declaration(self, declaration);
+    }
+
+    fn visit_declarator(&mut self, declarator: &Declarator) {
+        walk_declarator(self, declarator);
+    }
+
+    fn visit_initializer(&mut self, initializer: &Initializer) {
+        walk_initializer(self, initializer);
+    }
+
+    fn visit_type_name(&mut self, ty: &TypeName) {
+        walk_type_name(self, ty);
+    }
+
+    fn visit_type_operand(&mut self, operand: &TypeOperand) {
+        walk_type_operand(self, operand);
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+}
+
+pub fn walk_translation_unit<V: Visit + ?Sized>(visitor: &mut V, unit: &TranslationUnit) {
+    for item in &unit.items {
+        match item {
+            ExternalDeclaration::Function(function) => visitor.visit_function(function),
+            ExternalDeclaration::Declaration(declaration) => visitor.visit_declaration(declaration),
+        }
+    }
+}
+
+pub fn walk_function<V: Visit + ?Sized>(visitor: &mut V, function: &FunctionDefinition) {
+    visitor.visit_type_name(&function.return_type);
+    for parameter in &function.parameters {
+        visitor.visit_parameter(parameter);
+    }
+    visitor.visit_block(&function.body);
+}
+
+pub fn walk_parameter<V: Visit + ?Sized>(visitor: &mut V, parameter: &Parameter) {
+    visitor.visit_type_name(&parameter.ty);
+}
+
+pub fn walk_declaration<V: Visit + ?Sized>(visitor: &mut V, declaration: &Declaration) {
+    if declaration.declarators.is_empty() {
+        visitor.visit_type_name(&declaration.ty);
+    }
+    for declarator in &declaration.declarators {
+        visitor.visit_declarator(declarator);
+    }
+}
+
+pub fn walk_declarator<V: Visit + ?Sized>(visitor: &mut V, declarator: &Declarator) {
+    visitor.visit_type_name(&declarator.ty);
+    if let Some(initializer) = &declarator.initializer {
+        visitor.visit_initializer(initializer);
+    }
+}
+
+pub fn walk_initializer<V: Visit + ?Sized>(visitor: &mut V, initializer: &Initializer) {
+    match initializer {
+        Initializer::Expression(expression) => visitor.visit_expression(expression),
+        Initializer::List(entries) => {
+            for (designator, initializer) in entries {
+                if let Some(Designator::Index(index)) = designator {
+                    visitor.visit_expression(index);
+                }
+                visitor.visit_initializer(initializer);
+            }
+        }
+    }
+}
+
+/// Types hold expressions too: array lengths and enumerator values
+pub fn walk_type_name<V: Visit + ?Sized>(visitor: &mut V, ty: &TypeName) {
+    match ty {
+        TypeName::Pointer(target) => visitor.visit_type_name(target),
+        TypeName::Array(element, length) => {
+            visitor.visit_type_name(element);
+            if let Some(length) = length {
+                visitor.visit_expression(length);
+            }
+        }
+        TypeName::Function { return_type, parameters, .. } => {
+            visitor.visit_type_name(return_type);
+            for parameter in parameters {
+                visitor.visit_type_name(parameter);
+            }
+        }
+        TypeName::Struct(record) | TypeName::Union(record) => {
+            for member in record.members.iter().flatten() {
+                visitor.visit_type_name(&member.ty);
+            }
+        }
+        TypeName::Enum(enumeration) => {
+            for (_, value) in enumeration.enumerators.iter().flatten() {
+                if let Some(value) = value {
+                    visitor.visit_expression(value);
+                }
+            }
+        }
+        TypeName::Typeof { operand, .. } => visitor.visit_type_operand(operand),
+        _ => {}
+    }
+}
+
+pub fn walk_type_operand<V: Visit + ?Sized>(visitor: &mut V, operand: &TypeOperand) {
+    match operand {
+        TypeOperand::Expression(expression) => visitor.visit_expression(expression),
+        TypeOperand::TypeName(ty) => visitor.visit_type_name(ty),
+    }
+}
+
+pub fn walk_block<V: Visit + ?Sized>(visitor: &mut V, block: &Block) {
+    for item in &block.items {
+        match item {
+            BlockItem::Declaration(declaration) => visitor.visit_declaration(declaration),
+            BlockItem::Statement(statement) => visitor.visit_statement(statement),
+        }
+    }
+}
+
+pub fn walk_statement<V: Visit + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match &statement.kind {
+        StatementKind::Expression(expression) => {
+            if let Some(expression) = expression {
+                visitor.visit_expression(expression);
+            }
+        }
+        StatementKind::Compound(block) => visitor.visit_block(block),
+        StatementKind::If { condition, then, otherwise } => {
+            visitor.visit_expression(condition);
+            visitor.visit_statement(then);
+            if let Some(otherwise) = otherwise {
+                visitor.visit_statement(otherwise);
+            }
+        }
+        StatementKind::While { condition, body } => {
+            visitor.visit_expression(condition);
+            visitor.visit_statement(body);
+        }
+        StatementKind::DoWhile { body, condition } => {
+            visitor.visit_statement(body);
+            visitor.visit_expression(condition);
+        }
+        StatementKind::For { init, condition, step, body } => {
+            match init {
+                Some(ForInit::Declaration(declaration)) => visitor.visit_declaration(declaration),
+                Some(ForInit::Expression(expression)) => visitor.visit_expression(expression),
+                None => {}
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expression(condition);
+            }
+            if let Some(step) = step {
+                visitor.visit_expression(step);
+            }
+            visitor.visit_statement(body);
+        }
+        StatementKind::Switch { value, body } | StatementKind::Case { value, body } => {
+            visitor.visit_expression(value);
+            visitor.visit_statement(body);
+        }
+        StatementKind::Default(body) | StatementKind::Labeled { body, .. } => visitor.visit_statement(body),
+        StatementKind::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        StatementKind::Goto(_) | StatementKind::Break | StatementKind::Continue => {}
+        StatementKind::Asm { outputs, inputs, .. } => {
+            for operand in outputs.iter().chain(inputs) {
+                visitor.visit_expression(operand);
+            }
+        }
+    }
+}
+
+pub fn walk_expression<V: Visit + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match &expression.kind {
+        ExpressionKind::Unary { operand, .. } | ExpressionKind::SizeofExpression(operand) => visitor.visit_expression(operand),
+        ExpressionKind::Binary { left, right, .. } | ExpressionKind::Comma(left, right) => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        ExpressionKind::Assign { target, value, .. } => {
+            visitor.visit_expression(target);
+            visitor.visit_expression(value);
+        }
+        ExpressionKind::Conditional { condition, then, otherwise } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(then);
+            visitor.visit_expression(otherwise);
+        }
+        ExpressionKind::Call { function, arguments } => {
+            visitor.visit_expression(function);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        ExpressionKind::Index { array, index } => {
+            visitor.visit_expression(array);
+            visitor.visit_expression(index);
+        }
+        ExpressionKind::Member { base, .. } => visitor.visit_expression(base),
+        ExpressionKind::Cast { ty, operand } => {
+            visitor.visit_type_name(ty);
+            visitor.visit_expression(operand);
+        }
+        ExpressionKind::SizeofType(ty) | ExpressionKind::AlignofType(ty) => visitor.visit_type_name(ty),
+        ExpressionKind::Generic { control, associations } => {
+            visitor.visit_type_operand(control);
+            for (ty, value) in associations {
+                if let Some(ty) = ty {
+                    visitor.visit_type_name(ty);
+                }
+                visitor.visit_expression(value);
+            }
+        }
+        ExpressionKind::Integer { .. }
+        | ExpressionKind::Float { .. }
+        | ExpressionKind::Character(_)
+        | ExpressionKind::String(_)
+        | ExpressionKind::Identifier(_) => {}
+    }
+}
+
+/// `Visit` for in-place changes. A node whose meaning changes should get
+/// `Span::synthetic()`, so `rewrite::Rewriter` prints it instead of copying
+/// its old text.
+pub trait VisitMut {
+    fn visit_translation_unit_mut(&mut self, unit: &mut TranslationUnit) {
+        walk_translation_unit_mut(self, unit);
+    }
+
+    fn visit_function_mut(&mut self, function: &mut FunctionDefinition) {
+        walk_function_mut(self, function);
+    }
+
+    fn visit_parameter_mut(&mut self, parameter: &mut Parameter) {
+        walk_parameter_mut(self, parameter);
+    }
+
+    fn visit_declaration_mut(&mut self, declaration: &mut Declaration) {
+        walk_declaration_mut(self, declaration);
+    }
+
+    fn visit_declarator_mut(&mut self, declarator: &mut Declarator) {
+        walk_declarator_mut(self, declarator);
+    }
+
+    fn visit_initializer_mut(&mut self, initializer: &mut Initializer) {
+        walk_initializer_mut(self, initializer);
+    }
+
+    fn visit_type_name_mut(&mut self, ty: &mut TypeName) {
+        walk_type_name_mut(self, ty);
+    }
+
+    fn visit_type_operand_mut(&mut self, operand: &mut TypeOperand) {
+        walk_type_operand_mut(self, operand);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+}
+
+pub fn walk_translation_unit_mut<V: VisitMut + ?Sized>(visitor: &mut V, unit: &mut TranslationUnit) {
+    for item in &mut unit.items {
+        match item {
+            ExternalDeclaration::Function(function) => visitor.visit_function_mut(function),
+            ExternalDeclaration::Declaration(declaration) => visitor.visit_declaration_mut(declaration),
+        }
+    }
+}
+
+pub fn walk_function_mut<V: VisitMut + ?Sized>(visitor: &mut V, function: &mut FunctionDefinition) {
+    visitor.visit_type_name_mut(&mut function.return_type);
+    for parameter in &mut function.parameters {
+        visitor.visit_parameter_mut(parameter);
+    }
+    visitor.visit_block_mut(&mut function.body);
+}
+
+pub fn walk_parameter_mut<V: VisitMut + ?Sized>(visitor: &mut V, parameter: &mut Parameter) {
+    visitor.visit_type_name_mut(&mut parameter.ty);
+}
+
+pub fn walk_declaration_mut<V: VisitMut + ?Sized>(visitor: &mut V, declaration: &mut Declaration) {
+    if declaration.declarators.is_empty() {
+        visitor.visit_type_name_mut(&mut declaration.ty);
+    }
+    for declarator in &mut declaration.declarators {
+        visitor.visit_declarator_mut(declarator);
+    }
+}
+
+pub fn walk_declarator_mut<V: VisitMut + ?Sized>(visitor: &mut V, declarator: &mut Declarator) {
+    visitor.visit_type_name_mut(&mut declarator.ty);
+    if let Some(initializer) = &mut declarator.initializer {
+        visitor.visit_initializer_mut(initializer);
+    }
+}
+
+pub fn walk_initializer_mut<V: VisitMut + ?Sized>(visitor: &mut V, initializer: &mut Initializer) {
+    match initializer {
+        Initializer::Expression(expression) => visitor.visit_expression_mut(expression),
+        Initializer::List(entries) => {
+            for (designator, initializer) in entries {
+                if let Some(Designator::Index(index)) = designator {
+                    visitor.visit_expression_mut(index);
+                }
+                visitor.visit_initializer_mut(initializer);
+            }
+        }
+    }
+}
+
+pub fn walk_type_name_mut<V: VisitMut + ?Sized>(visitor: &mut V, ty: &mut TypeName) {
+    match ty {
+        TypeName::Pointer(target) => visitor.visit_type_name_mut(target),
+        TypeName::Array(element, length) => {
+            visitor.visit_type_name_mut(element);
+            if let Some(length) = length {
+                visitor.visit_expression_mut(length);
+            }
+        }
+        TypeName::Function { return_type, parameters, .. } => {
+            visitor.visit_type_name_mut(return_type);
+            for parameter in parameters {
+                visitor.visit_type_name_mut(parameter);
+            }
+        }
+        TypeName::Struct(record) | TypeName::Union(record) => {
+            for member in record.members.iter_mut().flatten() {
+                visitor.visit_type_name_mut(&mut member.ty);
+            }
+        }
+        TypeName::Enum(enumeration) => {
+            for (_, value) in enumeration.enumerators.iter_mut().flatten() {
+                if let Some(value) = value {
+                    visitor.visit_expression_mut(value);
+                }
+            }
+        }
+        TypeName::Typeof { operand, .. } => visitor.visit_type_operand_mut(operand),
+        _ => {}
+    }
+}
+
+pub fn walk_type_operand_mut<V: VisitMut + ?Sized>(visitor: &mut V, operand: &mut TypeOperand) {
+    match operand {
+        TypeOperand::Expression(expression) => visitor.visit_expression_mut(expression),
+        TypeOperand::TypeName(ty) => visitor.visit_type_name_mut(ty),
+    }
+}
+
+pub fn walk_block_mut<V: VisitMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for item in &mut block.items {
+        match item {
+            BlockItem::Declaration(declaration) => visitor.visit_declaration_mut(declaration),
+            BlockItem::Statement(statement) => visitor.visit_statement_mut(statement),
+        }
+    }
+}
+
+pub fn walk_statement_mut<V: VisitMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match &mut statement.kind {
+        StatementKind::Expression(expression) => {
+            if let Some(expression) = expression {
+                visitor.visit_expression_mut(expression);
+            }
+        }
+        StatementKind::Compound(block) => visitor.visit_block_mut(block),
+        StatementKind::If { condition, then, otherwise } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_statement_mut(then);
+            if let Some(otherwise) = otherwise {
+                visitor.visit_statement_mut(otherwise);
+            }
+        }
+        StatementKind::While { condition, body } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_statement_mut(body);
+        }
+        StatementKind::DoWhile { body, condition } => {
+            visitor.visit_statement_mut(body);
+            visitor.visit_expression_mut(condition);
+        }
+        StatementKind::For { init, condition, step, body } => {
+            match init {
+                Some(ForInit::Declaration(declaration)) => visitor.visit_declaration_mut(declaration),
+                Some(ForInit::Expression(expression)) => visitor.visit_expression_mut(expression),
+                None => {}
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expression_mut(condition);
+            }
+            if let Some(step) = step {
+                visitor.visit_expression_mut(step);
+            }
+            visitor.visit_statement_mut(body);
+        }
+        StatementKind::Switch { value, body } | StatementKind::Case { value, body } => {
+            visitor.visit_expression_mut(value);
+            visitor.visit_statement_mut(body);
+        }
+        StatementKind::Default(body) | StatementKind::Labeled { body, .. } => visitor.visit_statement_mut(body),
+        StatementKind::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        StatementKind::Goto(_) | StatementKind::Break | StatementKind::Continue => {}
+        StatementKind::Asm { outputs, inputs, .. } => {
+            for operand in outputs.iter_mut().chain(inputs) {
+                visitor.visit_expression_mut(operand);
+            }
+        }
+    }
+}
+
+pub fn walk_expression_mut<V: VisitMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match &mut expression.kind {
+        ExpressionKind::Unary { operand, .. } | ExpressionKind::SizeofExpression(operand) => {
+            visitor.visit_expression_mut(operand)
+        }
+        ExpressionKind::Binary { left, right, .. } | ExpressionKind::Comma(left, right) => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(right);
+        }
+        ExpressionKind::Assign { target, value, .. } => {
+            visitor.visit_expression_mut(target);
+            visitor.visit_expression_mut(value);
+        }
+        ExpressionKind::Conditional { condition, then, otherwise } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_expression_mut(then);
+            visitor.visit_expression_mut(otherwise);
+        }
+        ExpressionKind::Call { function, arguments } => {
+            visitor.visit_expression_mut(function);
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        }
+        ExpressionKind::Index { array, index } => {
+            visitor.visit_expression_mut(array);
+            visitor.visit_expression_mut(index);
+        }
+        ExpressionKind::Member { base, .. } => visitor.visit_expression_mut(base),
+        ExpressionKind::Cast { ty, operand } => {
+            visitor.visit_type_name_mut(ty);
+            visitor.visit_expression_mut(operand);
+        }
+        ExpressionKind::SizeofType(ty) | ExpressionKind::AlignofType(ty) => visitor.visit_type_name_mut(ty),
+        ExpressionKind::Generic { control, associations } => {
+            visitor.visit_type_operand_mut(control);
+            for (ty, value) in associations {
+                if let Some(ty) = ty {
+                    visitor.visit_type_name_mut(ty);
+                }
+                visitor.visit_expression_mut(value);
+            }
+        }
+        ExpressionKind::Integer { .. }
+        | ExpressionKind::Float { .. }
+        | ExpressionKind::Character(_)
+        | ExpressionKind::String(_)
+        | ExpressionKind::Identifier(_) => {}
+    }
+}
+
+// Example usage:
+/*
+/// Names of the functions each function calls directly
+struct Calls {
+    current: String,
+    calls: Vec<(String, String)>,
+}
+
+impl Visit for Calls {
+    fn visit_function(&mut self, function: &FunctionDefinition) {
+        self.current = function.name.clone();
+        walk_function(self, function);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::Call { function, .. } = &expression.kind {
+            if let ExpressionKind::Identifier(callee) = &function.kind {
+                self.calls.push((self.current.clone(), callee.clone()));
+            }
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/// Rename every use of `old` to `new`
+struct Rename<'a> {
+    old: &'a str,
+    new: &'a str,
+}
+
+impl VisitMut for Rename<'_> {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        if let ExpressionKind::Identifier(name) = &mut expression.kind {
+            if name == self.old {
+                *name = self.new.to_string();
+                expression.span = Span::synthetic();
+            }
+        }
+        walk_expression_mut(self, expression);
+    }
+}
+*/
@@ -0,0 +1,2807 @@
+// src/interpreter/bytecode/compiler.rs
+//! Syntax tree to bytecode
+//! One pass over each function body, after a pass over the file that
+//! numbers every function and object the file defines, so calls can precede
+//! definitions. Types are resolved here once: the instructions only see
+//! widths, signedness and byte offsets.
+//!
+//! Registers are handed out like a stack. A block's variables take the
+//! registers above its enclosing block's, and temporaries go above those
+//! for the length of one full expression. A variable whose address is taken
+//! anywhere in its function, and every array and struct, gets frame memory
+//! instead of a register.
+//!
+//! So do the `malloc` and `calloc` objects `escape` finds never leave the
+//! function that allocates them; the `free`s of those compile to nothing.
+//!
+//! `_Generic` and `auto` are resolved by the rules in `types::inference`,
+//! over the types here; `typeof` is the type `sizeof` would measure.
+//!
+//! `asm` statements become `Asm` instructions, lowered for x86_64 by
+//! `inline_asm`; only the native tier runs them.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use crate::arch::inline_asm::{self as arch_asm, ConstraintKind};
+use crate::arch::x86_64;
+use crate::frontend::ast::{
+    AsmStatement, BinaryOp, Block, BlockItem, Declaration, Declarator, Designator, EnumType, Expression, ExpressionKind, ExternalDeclaration,
+    ForInit, FunctionDefinition, Initializer, IntegerSuffix, RecordType, Statement, StatementKind, StorageClass,
+    TranslationUnit, TypeName, TypeOperand, UnaryOp,
+};
+use crate::frontend::types::Qualifiers;
+use crate::frontend::usdt::PROBE_BUILTIN;
+use crate::optimizer::overflow::SignedOp;
+use crate::types::inference::{self, AutoDeclarator, GenericControl, InferenceType, TypeError};
+use crate::types::vla;
+use super::escape::{self, HeapPromotions};
+use super::inline_asm::{self, LowerError};
+use super::{
+    BytecodeError, External, Function, Instruction, Kind, Program, Reg, Relocation, RelocationTarget, Signature, SwitchTable,
+};
+
+/// Functions only the tree walker can run: they need the interpreter's own
+/// frames (`setjmp`) or call back into interpreted code
+const UNSUPPORTED_CALLS: &[&str] = &[
+    "setjmp", "_setjmp", "sigsetjmp", "__sigsetjmp", "longjmp", "_longjmp", "siglongjmp", "signal", "sigaction",
+    "pthread_create", "atexit", "at_quick_exit", "__builtin_va_start", "__builtin_va_arg", "__builtin_va_end",
+    "__builtin_va_copy",
+];
+
+/// Compile `unit`; fails with `Unsupported` for programs only the tree
+/// walker runs
+pub fn compile(unit: &TranslationUnit) -> Result<Program, BytecodeError> {
+    let mut compiler = Compiler::new(unit);
+    compiler.unit(unit)?;
+    Ok(compiler.program)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Void,
+    Bool,
+    Int { bytes: u8, signed: bool },
+    Float,
+    Double,
+    Pointer(Box<Ty>),
+    Array(Box<Ty>, Option<u32>),
+    /// `T[n]` with `n` evaluated at run time into the register, which
+    /// holds it for as long as the type is in scope
+    VariableArray(Box<Ty>, Reg),
+    Record(usize),
+    Function(Rc<FunctionType>),
+}
+
+#[derive(Debug, PartialEq)]
+struct FunctionType {
+    result: Ty,
+    parameters: Vec<Ty>,
+    variadic: bool,
+    /// `int f()`: any arguments, with the default promotions
+    prototyped: bool,
+}
+
+const INT: Ty = Ty::Int { bytes: 4, signed: true };
+const UNSIGNED_LONG: Ty = Ty::Int { bytes: 8, signed: false };
+const LONG: Ty = Ty::Int { bytes: 8, signed: true };
+const CHAR: Ty = Ty::Int { bytes: 1, signed: true };
+
+impl Ty {
+    fn is_integer(&self) -> bool {
+        matches!(self, Ty::Int { .. } | Ty::Bool)
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Ty::Float | Ty::Double)
+    }
+
+    fn is_arithmetic(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+
+    fn is_pointer(&self) -> bool {
+        matches!(self, Ty::Pointer(_))
+    }
+
+    fn is_scalar(&self) -> bool {
+        self.is_arithmetic() || self.is_pointer()
+    }
+
+    fn is_signed(&self) -> bool {
+        matches!(self, Ty::Int { signed: true, .. })
+    }
+
+    /// Width in bits of an integer type
+    fn bits(&self) -> u8 {
+        match self {
+            Ty::Int { bytes, .. } => bytes * 8,
+            Ty::Bool => 8,
+            _ => 64,
+        }
+    }
+
+    fn pointee(&self) -> Option<&Ty> {
+        match self {
+            Ty::Pointer(pointee) => Some(pointee),
+            _ => None,
+        }
+    }
+
+    /// How a value of this type is stored; `None` for aggregates
+    fn kind(&self) -> Option<Kind> {
+        Some(match self {
+            Ty::Bool => Kind::U8,
+            Ty::Int { bytes: 1, signed } => if *signed { Kind::I8 } else { Kind::U8 },
+            Ty::Int { bytes: 2, signed } => if *signed { Kind::I16 } else { Kind::U16 },
+            Ty::Int { bytes: 4, signed } => if *signed { Kind::I32 } else { Kind::U32 },
+            Ty::Int { signed, .. } => if *signed { Kind::I64 } else { Kind::U64 },
+            Ty::Float => Kind::F32,
+            Ty::Double => Kind::F64,
+            Ty::Pointer(_) | Ty::Function(_) => Kind::U64,
+            Ty::Void | Ty::Array(..) | Ty::VariableArray(..) | Ty::Record(_) => return None,
+        })
+    }
+
+    /// Integer promotion
+    fn promoted(&self) -> Ty {
+        match self {
+            Ty::Bool => INT,
+            Ty::Int { bytes, .. } if *bytes < 4 => INT,
+            other => other.clone(),
+        }
+    }
+
+    /// What an array or function decays to as a value
+    fn decayed(&self) -> Ty {
+        match self {
+            Ty::Array(element, _) | Ty::VariableArray(element, _) => Ty::Pointer(element.clone()),
+            Ty::Function(_) => Ty::Pointer(Box::new(self.clone())),
+            other => other.clone(),
+        }
+    }
+}
+
+/// The bytecode keeps no qualifiers, so they are always empty
+impl InferenceType for Ty {
+    fn qualifiers(&self) -> Qualifiers {
+        Qualifiers::empty()
+    }
+
+    fn unqualified(&self) -> Ty {
+        self.clone()
+    }
+
+    fn qualify(self, _extra: Qualifiers) -> Ty {
+        self
+    }
+
+    fn decay(&self) -> Option<Ty> {
+        matches!(self, Ty::Array(..) | Ty::VariableArray(..) | Ty::Function(_)).then(|| self.decayed())
+    }
+
+    fn compatible(&self, other: &Ty) -> bool {
+        match (self, other) {
+            (Ty::Pointer(a), Ty::Pointer(b)) => a.compatible(b),
+            // An array of unknown size is compatible with any size
+            (Ty::Array(a, n), Ty::Array(b, m)) => a.compatible(b) && (n.is_none() || m.is_none() || n == m),
+            // A VLA is compatible with any array; differing lengths at run time are UB
+            (Ty::VariableArray(a, _), Ty::Array(b, _) | Ty::VariableArray(b, _)) | (Ty::Array(a, _), Ty::VariableArray(b, _)) => {
+                a.compatible(b)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    fn is_void(&self) -> bool {
+        *self == Ty::Void
+    }
+}
+
+#[derive(Debug)]
+struct Record {
+    union: bool,
+    complete: bool,
+    fields: Vec<Field>,
+    size: u32,
+    align: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    ty: Ty,
+    offset: u32,
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    Register(Reg, Ty),
+    Frame(u32, Ty),
+    /// A VLA, at the address in the register
+    VariableArray(Reg, Ty),
+    Global(u32, Ty),
+    /// An object defined outside the program (`stdout`, `environ`)
+    ExternalObject(u32, Ty),
+    Function { name: String, ty: Rc<FunctionType>, internal: Option<u32> },
+    Typedef(Ty),
+    Constant(i64),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Tag {
+    Record(usize),
+    Enum,
+}
+
+/// A value in a register
+#[derive(Debug, Clone)]
+struct Value {
+    reg: Reg,
+    ty: Ty,
+}
+
+/// Something that can be assigned to or have its address taken
+#[derive(Debug, Clone)]
+enum Place {
+    Register(Reg, Ty),
+    /// At the address in the register
+    Memory(Reg, Ty),
+}
+
+impl Place {
+    fn ty(&self) -> &Ty {
+        match self {
+            Place::Register(_, ty) | Place::Memory(_, ty) => ty,
+        }
+    }
+}
+
+enum Operand {
+    Value(Value),
+    Place(Place),
+}
+
+impl Operand {
+    fn ty(&self) -> &Ty {
+        match self {
+            Operand::Value(value) => &value.ty,
+            Operand::Place(place) => place.ty(),
+        }
+    }
+}
+
+/// A constant expression, for static initializers, case labels and array
+/// lengths
+#[derive(Debug, Clone)]
+enum Constant {
+    Int(i64, Ty),
+    Float(f64, Ty),
+    Address { target: RelocationTarget, addend: i64 },
+}
+
+struct Breakable {
+    breaks: Vec<usize>,
+    /// `None` for a `switch`: `continue` goes to the enclosing loop
+    continues: Option<Vec<usize>>,
+    /// VLAs in scope outside it
+    vlas: usize,
+}
+
+struct SwitchState {
+    ty: Ty,
+    cases: Vec<(i64, u32)>,
+    default: Option<u32>,
+    /// Slots of the VLAs in scope at the `switch`
+    vlas: Vec<u32>,
+}
+
+/// A `goto`, resolved once the whole body is compiled
+struct Goto {
+    at: usize,
+    label: String,
+    line: u32,
+    vlas: Vec<(u32, Reg)>,
+}
+
+struct FunctionState {
+    name: String,
+    result: Ty,
+    code: Vec<Instruction>,
+    next: u32,
+    max: u32,
+    frame: u32,
+    address_taken: HashSet<String>,
+    /// Heap objects that can live in the frame instead
+    heap: HeapPromotions,
+    breakables: Vec<Breakable>,
+    switches: Vec<SwitchState>,
+    /// Where each label is, and the slots of the VLAs in scope there
+    labels: HashMap<String, (u32, Vec<u32>)>,
+    gotos: Vec<Goto>,
+    /// The VLAs in scope, outermost first: a slot numbering each VLA
+    /// declaration, and the register holding the VLA stack's height just
+    /// before it was allocated
+    vlas: Vec<(u32, Reg)>,
+    /// How many VLAs each enclosing block found in scope
+    vla_scopes: Vec<usize>,
+    next_vla: u32,
+}
+
+struct Compiler {
+    program: Program,
+
+    // Names
+    scopes: Vec<HashMap<String, Binding>>,
+    tags: Vec<HashMap<String, Tag>>,
+    records: Vec<Record>,
+
+    // File scope
+    defined_functions: HashMap<String, u32>,
+    defined_objects: HashSet<String>,
+    globals: HashMap<String, (u32, Ty)>,
+    externals: HashMap<String, u32>,
+    strings: HashMap<Vec<u8>, u32>,
+    /// Bodies still to compile, by function index
+    bodies: Vec<Option<FunctionDefinition>>,
+
+    function: Option<FunctionState>,
+}
+
+impl Compiler {
+    fn new(unit: &TranslationUnit) -> Self {
+        let mut defined_functions = HashMap::new();
+        let mut defined_objects = HashSet::new();
+        for item in &unit.items {
+            match item {
+                ExternalDeclaration::Function(definition) => {
+                    let next = defined_functions.len() as u32;
+                    defined_functions.entry(definition.name.clone()).or_insert(next);
+                }
+                ExternalDeclaration::Declaration(declaration) => {
+                    for declarator in &declaration.declarators {
+                        let is_function = matches!(declarator.ty, TypeName::Function { .. });
+                        let defines = match declaration.storage {
+                            Some(StorageClass::Typedef) => false,
+                            Some(StorageClass::Extern) => declarator.initializer.is_some(),
+                            _ => !is_function,
+                        };
+                        if defines {
+                            defined_objects.insert(declarator.name.clone());
+                        }
+                    }
+                }
+            }
+        }
+        Compiler {
+            program: Program { file: unit.file.clone(), ..Program::default() },
+            scopes: vec![HashMap::new()],
+            tags: vec![HashMap::new()],
+            records: Vec::new(),
+            bodies: vec![None; defined_functions.len()],
+            defined_functions,
+            defined_objects,
+            globals: HashMap::new(),
+            externals: HashMap::new(),
+            strings: HashMap::new(),
+            function: None,
+        }
+    }
+
+    fn unit(&mut self, unit: &TranslationUnit) -> Result<(), BytecodeError> {
+        self.program.functions = self
+            .bodies
+            .iter()
+            .map(|_| Function { name: String::new(), parameters: 0, registers: 0, frame_size: 0, code: Vec::new(), signature: None })
+            .collect();
+
+        // Declarations in order, so every body sees the file scope it would
+        // have in C; bodies compiled after, where all of it is known
+        let mut order = Vec::new();
+        for item in &unit.items {
+            match item {
+                ExternalDeclaration::Declaration(declaration) => self.file_declaration(declaration)?,
+                ExternalDeclaration::Function(definition) => {
+                    let index = self.defined_functions[&definition.name];
+                    let ty = self.function_type(definition)?;
+                    self.bind(&definition.name, Binding::Function { name: definition.name.clone(), ty, internal: Some(index) });
+                    if self.bodies[index as usize].is_some() {
+                        return Err(invalid(format!("redefinition of '{}'", definition.name), definition.span.line));
+                    }
+                    self.bodies[index as usize] = Some(definition.clone());
+                    order.push((index, self.scopes[0].clone(), self.tags[0].clone()));
+                }
+            }
+        }
+        // Each body sees the file scope as it was at its definition
+        for (index, scope, tags) in order {
+            let definition = self.bodies[index as usize].take().expect("body recorded above");
+            let file_scope = std::mem::replace(&mut self.scopes[0], scope);
+            let file_tags = std::mem::replace(&mut self.tags[0], tags);
+            let function = self.function_body(&definition)?;
+            self.scopes[0] = file_scope;
+            self.tags[0] = file_tags;
+            self.program.functions[index as usize] = function;
+        }
+        Ok(())
+    }
+
+    // Scopes
+
+    fn bind(&mut self, name: &str, binding: Binding) {
+        self.scopes.last_mut().expect("file scope").insert(name.to_string(), binding);
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn push_scope(&mut self) -> u32 {
+        self.scopes.push(HashMap::new());
+        self.tags.push(HashMap::new());
+        let state = self.state();
+        state.vla_scopes.push(state.vlas.len());
+        state.next
+    }
+
+    /// Leave a block by falling off its end, freeing its VLAs
+    fn pop_scope(&mut self, registers: u32) {
+        self.scopes.pop();
+        self.tags.pop();
+        let outer = self.state().vla_scopes.pop().expect("pushed with the scope");
+        self.release_variable_arrays(outer);
+        self.state().vlas.truncate(outer);
+        self.state().next = registers;
+    }
+
+    /// Free the VLAs after the first `outer` in scope, for a jump to where
+    /// only those are
+    fn release_variable_arrays(&mut self, outer: usize) {
+        if let Some(&(_, mark)) = self.state().vlas.get(outer) {
+            self.emit(Instruction::VlaRelease { mark });
+        }
+    }
+
+    fn vla_slots(&mut self) -> Vec<u32> {
+        self.state().vlas.iter().map(|&(slot, _)| slot).collect()
+    }
+
+    // Types
+
+    fn resolve(&mut self, ty: &TypeName, line: u32) -> Result<Ty, BytecodeError> {
+        Ok(match ty {
+            TypeName::Void => Ty::Void,
+            TypeName::Bool => Ty::Bool,
+            TypeName::Char { signed } => Ty::Int { bytes: 1, signed: *signed },
+            TypeName::Short { signed } => Ty::Int { bytes: 2, signed: *signed },
+            TypeName::Int { signed } => Ty::Int { bytes: 4, signed: *signed },
+            TypeName::Long { signed } | TypeName::LongLong { signed } => Ty::Int { bytes: 8, signed: *signed },
+            TypeName::Float => Ty::Float,
+            TypeName::Double => Ty::Double,
+            TypeName::LongDouble => return Err(unsupported("long double", line)),
+            TypeName::Pointer(pointee) => Ty::Pointer(Box::new(self.resolve(pointee, line)?)),
+            TypeName::Array(element, length) => {
+                let element = self.resolve(element, line)?;
+                let length = match length {
+                    None => None,
+                    Some(length) => match self.constant(length) {
+                        Ok(Constant::Int(value, _)) if value >= 0 => Some(value as u32),
+                        Ok(_) => return Err(invalid("array length must be a non-negative integer".to_string(), line)),
+                        Err(_) if self.function.is_some() => {
+                            return Ok(Ty::VariableArray(Box::new(element), self.array_length(length)?));
+                        }
+                        Err(_) => return Err(unsupported("variable length arrays outside a function body", line)),
+                    },
+                };
+                // An array of VLAs is one too, its size known only at run time
+                if matches!(element, Ty::VariableArray(..)) {
+                    let length = length.ok_or_else(|| invalid("an array of variable length arrays needs a length".to_string(), line))?;
+                    let reg = self.temp()?;
+                    self.load_integer(reg, length as i64);
+                    return Ok(Ty::VariableArray(Box::new(element), reg));
+                }
+                Ty::Array(Box::new(element), length)
+            }
+            TypeName::Function { return_type, parameters, variadic } => {
+                let result = self.resolve(return_type, line)?;
+                let parameters: Vec<Ty> = match parameters.as_slice() {
+                    [TypeName::Void] => Vec::new(),
+                    parameters => parameters.iter().map(|p| self.parameter_type(p, line)).collect::<Result<_, _>>()?,
+                };
+                let prototyped = !parameters.is_empty() || *variadic;
+                Ty::Function(Rc::new(FunctionType { result, parameters, variadic: *variadic, prototyped }))
+            }
+            TypeName::Struct(record) => Ty::Record(self.record(record, false, line)?),
+            TypeName::Union(record) => Ty::Record(self.record(record, true, line)?),
+            TypeName::Enum(enumeration) => {
+                self.enumeration(enumeration)?;
+                INT
+            }
+            TypeName::Named(name) => match self.lookup(name) {
+                Some(Binding::Typedef(ty)) => ty.clone(),
+                _ => return Err(BytecodeError::Undeclared { name: name.clone(), line }),
+            },
+            TypeName::Typeof { unqual, operand } => {
+                let ty = self.operand_type(operand, line)?;
+                if *unqual { ty.unqualified() } else { ty }
+            }
+            // `declared_type` takes the whole-declarator case
+            TypeName::Auto => return Err(type_error(TypeError::AutoDeclarator, line)),
+        })
+    }
+
+    /// Evaluate a VLA's length, once, into a register of its own
+    fn array_length(&mut self, length: &Expression) -> Result<Reg, BytecodeError> {
+        let line = length.span.line;
+        let reg = self.temp()?;
+        let mark = self.state().next;
+        let value = self.rvalue(length)?;
+        if !value.ty.is_integer() {
+            return Err(invalid("array length must be an integer".to_string(), line));
+        }
+        let value = self.convert(value, &LONG, line)?;
+        self.emit(Instruction::Move { dst: reg, src: value.reg });
+        self.state().next = mark;
+        Ok(reg)
+    }
+
+    /// A parameter's type as adjusted: an array parameter is a pointer, and
+    /// its length, a VLA's included, isn't evaluated
+    fn parameter_type(&mut self, ty: &TypeName, line: u32) -> Result<Ty, BytecodeError> {
+        match ty {
+            TypeName::Array(element, _) => Ok(Ty::Pointer(Box::new(self.resolve(element, line)?))),
+            ty => Ok(self.resolve(ty, line)?.decayed()),
+        }
+    }
+
+    /// The type of a `typeof` or `_Generic` operand; an expression isn't
+    /// evaluated
+    fn operand_type(&mut self, operand: &TypeOperand, line: u32) -> Result<Ty, BytecodeError> {
+        match operand {
+            TypeOperand::Expression(expression) => self.type_of(expression),
+            TypeOperand::TypeName(ty) => self.resolve(ty, line),
+        }
+    }
+
+    /// The type `declarator` declares, deduced from the initializer for `auto`
+    fn declared_type(&mut self, declarator: &Declarator, line: u32) -> Result<Ty, BytecodeError> {
+        if declarator.ty != TypeName::Auto {
+            return self.resolve(&declarator.ty, line);
+        }
+        let initializer = match &declarator.initializer {
+            None => None,
+            Some(Initializer::Expression(expression)) => Some(self.type_of(expression)?),
+            Some(Initializer::List(items)) => match items.as_slice() {
+                [(None, Initializer::Expression(expression))] => Some(self.type_of(expression)?),
+                _ => return Err(invalid("`auto` needs a single initializer expression".to_string(), line)),
+            },
+        };
+        let auto = AutoDeclarator { initializer, plain_identifier: true, qualifiers: Qualifiers::empty() };
+        inference::deduce_auto(&auto).map_err(|e| type_error(e, line))
+    }
+
+    /// Index of the association a `_Generic` selection picks
+    fn generic(&mut self, control: &TypeOperand, associations: &[(Option<TypeName>, Expression)], line: u32) -> Result<usize, BytecodeError> {
+        let control = match control {
+            TypeOperand::Expression(expression) => GenericControl::Expression(self.type_of(expression)?),
+            TypeOperand::TypeName(ty) => GenericControl::TypeName(self.resolve(ty, line)?),
+        };
+        let types = associations
+            .iter()
+            .map(|(ty, _)| ty.as_ref().map(|ty| self.resolve(ty, line)).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let complete_object = |ty: &Ty| match ty {
+            Ty::Void | Ty::Function(_) | Ty::Array(_, None) | Ty::VariableArray(..) => false,
+            Ty::Record(index) => self.records[*index].complete,
+            _ => true,
+        };
+        inference::select_generic(&control, &types, complete_object).map_err(|e| type_error(e, line))
+    }
+
+    fn function_type(&mut self, definition: &FunctionDefinition) -> Result<Rc<FunctionType>, BytecodeError> {
+        let line = definition.span.line;
+        let result = self.resolve(&definition.return_type, line)?;
+        let mut parameters = Vec::new();
+        for parameter in &definition.parameters {
+            match self.parameter_type(&parameter.ty, line)? {
+                Ty::Void if definition.parameters.len() == 1 => {}
+                ty => parameters.push(ty),
+            }
+        }
+        let prototyped = !parameters.is_empty() || definition.variadic;
+        Ok(Rc::new(FunctionType { result, parameters, variadic: definition.variadic, prototyped }))
+    }
+
+    /// The record a struct or union specifier names, defining it if it has members
+    fn record(&mut self, specifier: &RecordType, union: bool, line: u32) -> Result<usize, BytecodeError> {
+        let existing = specifier.tag.as_ref().and_then(|tag| match specifier.members {
+            // A definition only completes a declaration of the same scope
+            Some(_) => self.tags.last().and_then(|scope| scope.get(tag)).copied(),
+            None => self.tags.iter().rev().find_map(|scope| scope.get(tag)).copied(),
+        });
+        let index = match existing {
+            Some(Tag::Record(index)) => index,
+            Some(Tag::Enum) => return Err(invalid("tag declared as an enum".to_string(), line)),
+            None => {
+                self.records.push(Record { union, complete: false, fields: Vec::new(), size: 0, align: 1 });
+                let index = self.records.len() - 1;
+                if let Some(tag) = &specifier.tag {
+                    self.tags.last_mut().expect("file scope").insert(tag.clone(), Tag::Record(index));
+                }
+                index
+            }
+        };
+        let Some(members) = &specifier.members else { return Ok(index) };
+        if self.records[index].complete {
+            return Err(invalid("redefinition of a struct or union".to_string(), line));
+        }
+
+        let (mut fields, mut size, mut align) = (Vec::new(), 0u32, 1u32);
+        for member in members {
+            if member.bit_width.is_some() {
+                return Err(unsupported("bit-fields", line));
+            }
+            let ty = self.resolve(&member.ty, line)?;
+            let (member_size, member_align) = (self.size(&ty, line)?, self.align(&ty, line)?);
+            let offset = if union { 0 } else { size.next_multiple_of(member_align) };
+            match (&member.name, &ty) {
+                (Some(name), _) => fields.push(Field { name: name.clone(), ty, offset }),
+                // Anonymous struct or union: its members are this one's
+                (None, Ty::Record(inner)) => {
+                    for field in &self.records[*inner].fields {
+                        fields.push(Field { offset: offset + field.offset, ..field.clone() });
+                    }
+                }
+                (None, _) => {}
+            }
+            size = if union { size.max(member_size) } else { offset + member_size };
+            align = align.max(member_align);
+        }
+        self.records[index] = Record { union, complete: true, fields, size: size.next_multiple_of(align), align };
+        Ok(index)
+    }
+
+    fn enumeration(&mut self, specifier: &EnumType) -> Result<(), BytecodeError> {
+        if let Some(tag) = &specifier.tag {
+            self.tags.last_mut().expect("file scope").insert(tag.clone(), Tag::Enum);
+        }
+        let mut next = 0i64;
+        for (name, value) in specifier.enumerators.iter().flatten() {
+            if let Some(value) = value {
+                next = self.integer_constant(value)?;
+            }
+            self.bind(name, Binding::Constant(next));
+            next += 1;
+        }
+        Ok(())
+    }
+
+    fn size(&self, ty: &Ty, line: u32) -> Result<u32, BytecodeError> {
+        Ok(match ty {
+            Ty::Void | Ty::Bool => 1,
+            Ty::Int { bytes, .. } => *bytes as u32,
+            Ty::Float => 4,
+            Ty::Double | Ty::Pointer(_) => 8,
+            Ty::Array(element, Some(length)) => self.size(element, line)?.checked_mul(*length).ok_or_else(|| invalid("array too large".to_string(), line))?,
+            Ty::Record(index) if self.records[*index].complete => self.records[*index].size,
+            Ty::Array(_, None) | Ty::Record(_) => return Err(invalid("incomplete type".to_string(), line)),
+            Ty::Function(_) => return Err(invalid("size of a function".to_string(), line)),
+            // `sizeof` computes it at run time; see `size_value`
+            Ty::VariableArray(..) => return Err(unsupported("variable length arrays outside sizeof and subscripts", line)),
+        })
+    }
+
+    fn align(&self, ty: &Ty, line: u32) -> Result<u32, BytecodeError> {
+        Ok(match ty {
+            Ty::Array(element, _) | Ty::VariableArray(element, _) => self.align(element, line)?,
+            Ty::Record(index) => self.records[*index].align,
+            ty => self.size(ty, line)?,
+        })
+    }
+
+    fn field(&self, record: &Ty, name: &str, line: u32) -> Result<Field, BytecodeError> {
+        let Ty::Record(index) = record else {
+            return Err(invalid(format!("member '{}' of something that isn't a struct or union", name), line));
+        };
+        self.records[*index]
+            .fields
+            .iter()
+            .find(|field| field.name == name)
+            .cloned()
+            .ok_or_else(|| invalid(format!("no member named '{}'", name), line))
+    }
+
+    fn signature(&self, ty: &FunctionType, line: u32) -> Result<Signature, BytecodeError> {
+        let kind = |ty: &Ty| ty.kind().ok_or_else(|| unsupported("structs passed or returned by value", line));
+        Ok(Signature {
+            parameters: ty.parameters.iter().map(kind).collect::<Result<_, _>>()?,
+            variadic: (ty.variadic || !ty.prototyped).then_some(ty.parameters.len() as u16),
+            result: match &ty.result {
+                Ty::Void => None,
+                result => Some(kind(result)?),
+            },
+        })
+    }
+
+    // File scope
+
+    fn file_declaration(&mut self, declaration: &Declaration) -> Result<(), BytecodeError> {
+        let line = declaration.span.line;
+        if declaration.declarators.is_empty() {
+            self.resolve(&declaration.ty, line)?;
+            return Ok(());
+        }
+        for declarator in &declaration.declarators {
+            let line = declarator.span.line;
+            let ty = self.declared_type(declarator, line)?;
+            match (declaration.storage, ty) {
+                (Some(StorageClass::Typedef), ty) => self.bind(&declarator.name, Binding::Typedef(ty)),
+                (_, Ty::Function(ty)) => {
+                    let internal = self.defined_functions.get(&declarator.name).copied();
+                    self.bind(&declarator.name, Binding::Function { name: declarator.name.clone(), ty, internal });
+                }
+                (Some(StorageClass::ThreadLocal), _) => return Err(unsupported("_Thread_local", line)),
+                (_, ty) if self.defined_objects.contains(&declarator.name) => {
+                    let ty = self.complete_array(ty, declarator.initializer.as_ref(), line)?;
+                    let offset = match self.globals.get(&declarator.name) {
+                        Some((offset, _)) => *offset,
+                        None => {
+                            let offset = self.allocate_data(&ty, line)?;
+                            self.globals.insert(declarator.name.clone(), (offset, ty.clone()));
+                            offset
+                        }
+                    };
+                    self.bind(&declarator.name, Binding::Global(offset, ty.clone()));
+                    if let Some(initializer) = &declarator.initializer {
+                        self.initialize_data(offset, &ty, initializer, line)?;
+                    }
+                }
+                (_, ty) => {
+                    let external = self.external(&declarator.name, Signature { parameters: Vec::new(), variadic: None, result: None });
+                    self.bind(&declarator.name, Binding::ExternalObject(external, ty));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn external(&mut self, name: &str, signature: Signature) -> u32 {
+        if let Some(&index) = self.externals.get(name) {
+            return index;
+        }
+        self.program.externals.push(External { name: name.to_string(), signature });
+        let index = self.program.externals.len() as u32 - 1;
+        self.externals.insert(name.to_string(), index);
+        index
+    }
+
+    fn allocate_data(&mut self, ty: &Ty, line: u32) -> Result<u32, BytecodeError> {
+        let (size, align) = (self.size(ty, line)?, self.align(ty, line)?);
+        let offset = (self.program.data.len() as u32).next_multiple_of(align.max(1));
+        self.program.data.resize((offset + size) as usize, 0);
+        Ok(offset)
+    }
+
+    fn string(&mut self, bytes: &[u8]) -> u32 {
+        if let Some(&offset) = self.strings.get(bytes) {
+            return offset;
+        }
+        let offset = self.program.data.len() as u32;
+        self.program.data.extend_from_slice(bytes);
+        self.program.data.push(0);
+        self.strings.insert(bytes.to_vec(), offset);
+        offset
+    }
+
+    /// `int a[] = {1, 2, 3}` is an `int[3]`
+    fn complete_array(&mut self, ty: Ty, initializer: Option<&Initializer>, line: u32) -> Result<Ty, BytecodeError> {
+        let Ty::Array(element, None) = &ty else { return Ok(ty) };
+        let length = match initializer {
+            Some(Initializer::Expression(Expression { kind: ExpressionKind::String(bytes), .. })) => bytes.len() as u32 + 1,
+            Some(Initializer::List(items)) => {
+                let (mut index, mut length) = (0u32, 0u32);
+                for (designator, _) in items {
+                    if let Some(Designator::Index(position)) = designator {
+                        index = self.integer_constant(position)? as u32;
+                    }
+                    index += 1;
+                    length = length.max(index);
+                }
+                length
+            }
+            _ => return Err(invalid("array of unknown size".to_string(), line)),
+        };
+        Ok(Ty::Array(element.clone(), Some(length)))
+    }
+
+    /// Write a static initializer into the data at `offset`
+    fn initialize_data(&mut self, offset: u32, ty: &Ty, initializer: &Initializer, line: u32) -> Result<(), BytecodeError> {
+        match (ty, initializer) {
+            (Ty::Array(element, Some(length)), Initializer::Expression(Expression { kind: ExpressionKind::String(bytes), .. }))
+                if matches!(**element, Ty::Int { bytes: 1, .. }) =>
+            {
+                let count = (bytes.len() as u32).min(*length) as usize;
+                let start = offset as usize;
+                self.program.data[start..start + count].copy_from_slice(&bytes[..count]);
+                Ok(())
+            }
+            (Ty::Array(..) | Ty::Record(_), Initializer::List(items)) => {
+                for (member_offset, member_ty, initializer) in self.list_members(ty, items, line)? {
+                    self.initialize_data(offset + member_offset, &member_ty, initializer, line)?;
+                }
+                Ok(())
+            }
+            (ty, Initializer::Expression(expression)) if ty.is_scalar() => {
+                let constant = self.constant(expression)?;
+                let kind = ty.kind().expect("scalar");
+                let bytes = match (constant, ty.is_float()) {
+                    (Constant::Address { target, addend }, false) => {
+                        self.program.relocations.push(Relocation { offset, target, addend });
+                        return Ok(());
+                    }
+                    (Constant::Int(value, from), true) => encode_float(if from.is_signed() { value as f64 } else { value as u64 as f64 }, kind),
+                    (Constant::Float(value, _), true) => encode_float(value, kind),
+                    (Constant::Int(value, _), false) if *ty == Ty::Bool => vec![(value != 0) as u8],
+                    (Constant::Int(value, _), false) => value.to_le_bytes()[..kind.size()].to_vec(),
+                    (Constant::Float(value, _), false) if *ty == Ty::Bool => vec![(value != 0.0) as u8],
+                    (Constant::Float(value, _), false) => {
+                        let value = if ty.is_signed() { value as i64 } else { value as u64 as i64 };
+                        value.to_le_bytes()[..kind.size()].to_vec()
+                    }
+                    (Constant::Address { .. }, true) => return Err(invalid("pointer used as a floating-point initializer".to_string(), line)),
+                };
+                let start = offset as usize;
+                self.program.data[start..start + bytes.len()].copy_from_slice(&bytes);
+                Ok(())
+            }
+            (Ty::Record(_), Initializer::Expression(_)) | (Ty::Array(..), Initializer::Expression(_)) => {
+                Err(unsupported("aggregate initializers without braces", line))
+            }
+            (_, Initializer::List(items)) => match items.as_slice() {
+                // `int x = { 1 };`
+                [(None, inner)] => self.initialize_data(offset, ty, inner, line),
+                _ => Err(invalid("too many initializers for a scalar".to_string(), line)),
+            },
+            _ => Err(invalid("invalid initializer".to_string(), line)),
+        }
+    }
+
+    /// Offset, type and initializer of each member a braced list sets
+    fn list_members<'i>(
+        &mut self,
+        ty: &Ty,
+        items: &'i [(Option<Designator>, Initializer)],
+        line: u32,
+    ) -> Result<Vec<(u32, Ty, &'i Initializer)>, BytecodeError> {
+        let mut members = Vec::new();
+        match ty {
+            Ty::Array(element, length) => {
+                let element_size = self.size(element, line)?;
+                let mut index = 0u32;
+                for (designator, initializer) in items {
+                    match designator {
+                        Some(Designator::Index(position)) => index = self.integer_constant(position)? as u32,
+                        Some(Designator::Member(_)) => return Err(invalid("member designator in an array initializer".to_string(), line)),
+                        None => {}
+                    }
+                    if length.is_some_and(|length| index >= length) {
+                        return Err(invalid("excess elements in array initializer".to_string(), line));
+                    }
+                    members.push((index * element_size, (**element).clone(), initializer));
+                    index += 1;
+                }
+            }
+            Ty::Record(record) => {
+                let fields = self.records[*record].fields.clone();
+                let union = self.records[*record].union;
+                let mut next = 0usize;
+                for (designator, initializer) in items {
+                    match designator {
+                        Some(Designator::Member(name)) => {
+                            next = fields
+                                .iter()
+                                .position(|field| &field.name == name)
+                                .ok_or_else(|| invalid(format!("no member named '{}'", name), line))?;
+                        }
+                        Some(Designator::Index(_)) => return Err(invalid("index designator in a struct initializer".to_string(), line)),
+                        None => {}
+                    }
+                    let field = fields.get(next).ok_or_else(|| invalid("excess elements in struct initializer".to_string(), line))?;
+                    members.push((field.offset, field.ty.clone(), initializer));
+                    next = if union { fields.len() } else { next + 1 };
+                }
+            }
+            _ => return Err(invalid("braced initializer for a scalar".to_string(), line)),
+        }
+        Ok(members)
+    }
+
+    // Constant expressions
+
+    fn integer_constant(&mut self, expression: &Expression) -> Result<i64, BytecodeError> {
+        match self.constant(expression)? {
+            Constant::Int(value, _) => Ok(value),
+            _ => Err(invalid("expected an integer constant".to_string(), expression.span.line)),
+        }
+    }
+
+    fn constant(&mut self, expression: &Expression) -> Result<Constant, BytecodeError> {
+        let line = expression.span.line;
+        let not_constant = || invalid("not a constant expression".to_string(), line);
+        Ok(match &expression.kind {
+            ExpressionKind::Integer { value, suffix } => {
+                let ty = integer_literal_type(*value, *suffix);
+                Constant::Int(*value as i64, ty)
+            }
+            ExpressionKind::Character(value) => Constant::Int(*value, INT),
+            ExpressionKind::Float { value, single } => Constant::Float(*value, if *single { Ty::Float } else { Ty::Double }),
+            ExpressionKind::String(bytes) => Constant::Address { target: RelocationTarget::Data(self.string(bytes)), addend: 0 },
+            ExpressionKind::Identifier(name) => match self.lookup(name).cloned() {
+                Some(Binding::Constant(value)) => Constant::Int(value, INT),
+                Some(Binding::Function { name, ty, internal }) => self.function_constant(&name, &ty, internal, line)?,
+                // An array designator is its address
+                Some(Binding::Global(offset, Ty::Array(..))) => Constant::Address { target: RelocationTarget::Data(offset), addend: 0 },
+                Some(Binding::ExternalObject(external, Ty::Array(..))) => {
+                    Constant::Address { target: RelocationTarget::External(external), addend: 0 }
+                }
+                None => return Err(BytecodeError::Undeclared { name: name.clone(), line }),
+                _ => return Err(not_constant()),
+            },
+            ExpressionKind::Unary { op: UnaryOp::AddressOf, operand } => self.address_constant(operand)?,
+            ExpressionKind::Unary { op, operand } => {
+                let value = self.constant(operand)?;
+                match (op, value) {
+                    (UnaryOp::Plus, value) => value,
+                    (UnaryOp::Minus, Constant::Int(value, ty)) => Constant::Int(wrap_to(value.wrapping_neg(), &ty.promoted()), ty.promoted()),
+                    (UnaryOp::Minus, Constant::Float(value, ty)) => Constant::Float(-value, ty),
+                    (UnaryOp::BitNot, Constant::Int(value, ty)) => Constant::Int(wrap_to(!value, &ty.promoted()), ty.promoted()),
+                    (UnaryOp::Not, Constant::Int(value, _)) => Constant::Int((value == 0) as i64, INT),
+                    (UnaryOp::Not, Constant::Float(value, _)) => Constant::Int((value == 0.0) as i64, INT),
+                    _ => return Err(not_constant()),
+                }
+            }
+            ExpressionKind::Binary { op, left, right } => {
+                let (left, right) = (self.constant(left)?, self.constant(right)?);
+                constant_binary(*op, left, right).ok_or_else(not_constant)?
+            }
+            ExpressionKind::Conditional { condition, then, otherwise } => {
+                let taken = match self.constant(condition)? {
+                    Constant::Int(value, _) => value != 0,
+                    Constant::Float(value, _) => value != 0.0,
+                    Constant::Address { .. } => true,
+                };
+                self.constant(if taken { then } else { otherwise })?
+            }
+            ExpressionKind::Cast { ty, operand } => {
+                let ty = self.resolve(ty, line)?;
+                match (self.constant(operand)?, &ty) {
+                    (Constant::Int(value, from), Ty::Float | Ty::Double) => {
+                        Constant::Float(if from.is_signed() { value as f64 } else { value as u64 as f64 }, ty)
+                    }
+                    (Constant::Float(value, _), Ty::Float | Ty::Double) => Constant::Float(value, ty),
+                    (Constant::Float(value, _), ty) if ty.is_integer() => Constant::Int(wrap_to(value as i64, ty), ty.clone()),
+                    (Constant::Int(value, _), Ty::Bool) => Constant::Int((value != 0) as i64, Ty::Bool),
+                    (Constant::Int(value, _), ty) if ty.is_integer() || ty.is_pointer() => Constant::Int(wrap_to(value, ty), ty.clone()),
+                    (address @ Constant::Address { .. }, ty) if ty.is_pointer() || *ty == UNSIGNED_LONG || *ty == LONG => address,
+                    _ => return Err(not_constant()),
+                }
+            }
+            ExpressionKind::SizeofType(ty) => {
+                let ty = self.resolve(ty, line)?;
+                Constant::Int(self.size(&ty, line)? as i64, UNSIGNED_LONG)
+            }
+            ExpressionKind::AlignofType(ty) => {
+                let ty = self.resolve(ty, line)?;
+                Constant::Int(self.align(&ty, line)? as i64, UNSIGNED_LONG)
+            }
+            ExpressionKind::SizeofExpression(operand) => {
+                let ty = self.type_of(operand)?;
+                Constant::Int(self.size(&ty, line)? as i64, UNSIGNED_LONG)
+            }
+            ExpressionKind::Generic { control, associations } => {
+                let selected = self.generic(control, associations, line)?;
+                self.constant(&associations[selected].1)?
+            }
+            _ => return Err(not_constant()),
+        })
+    }
+
+    fn function_constant(&mut self, name: &str, ty: &FunctionType, internal: Option<u32>, line: u32) -> Result<Constant, BytecodeError> {
+        let target = match internal {
+            Some(index) => RelocationTarget::Function(index),
+            None => {
+                let signature = self.signature(ty, line)?;
+                RelocationTarget::External(self.external(name, signature))
+            }
+        };
+        Ok(Constant::Address { target, addend: 0 })
+    }
+
+    /// `&object`, `&array[n]`, `&s.member` of a static object
+    fn address_constant(&mut self, operand: &Expression) -> Result<Constant, BytecodeError> {
+        let line = operand.span.line;
+        let not_constant = || invalid("not a constant address".to_string(), line);
+        let (target, addend, _) = self.static_place(operand).ok_or_else(not_constant)??;
+        Ok(Constant::Address { target, addend })
+    }
+
+    /// Where a static lvalue is, and its type; `None` if it isn't static
+    fn static_place(&mut self, expression: &Expression) -> Option<Result<(RelocationTarget, i64, Ty), BytecodeError>> {
+        let line = expression.span.line;
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => match self.lookup(name).cloned()? {
+                Binding::Global(offset, ty) => Some(Ok((RelocationTarget::Data(offset), 0, ty))),
+                Binding::ExternalObject(external, ty) => Some(Ok((RelocationTarget::External(external), 0, ty))),
+                Binding::Function { name, ty, internal } => Some(self.function_constant(&name, &ty, internal, line).map(|constant| match constant {
+                    Constant::Address { target, .. } => (target, 0, Ty::Function(ty)),
+                    _ => unreachable!("function constants are addresses"),
+                })),
+                _ => None,
+            },
+            ExpressionKind::String(bytes) => {
+                let length = bytes.len() as u32 + 1;
+                Some(Ok((RelocationTarget::Data(self.string(bytes)), 0, Ty::Array(Box::new(CHAR), Some(length)))))
+            }
+            ExpressionKind::Index { array, index } => {
+                let (target, addend, ty) = match self.static_place(array)? {
+                    Ok(place) => place,
+                    Err(e) => return Some(Err(e)),
+                };
+                let Ty::Array(element, _) = ty else { return None };
+                let index = self.integer_constant(index).ok()?;
+                let size = self.size(&element, line).ok()? as i64;
+                Some(Ok((target, addend + index * size, *element)))
+            }
+            ExpressionKind::Member { base, member, arrow: false } => {
+                let (target, addend, ty) = match self.static_place(base)? {
+                    Ok(place) => place,
+                    Err(e) => return Some(Err(e)),
+                };
+                Some(self.field(&ty, member, line).map(|field| (target, addend + field.offset as i64, field.ty)))
+            }
+            _ => None,
+        }
+    }
+
+    // Functions
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.function.as_mut().expect("inside a function body")
+    }
+
+    fn function_body(&mut self, definition: &FunctionDefinition) -> Result<Function, BytecodeError> {
+        let line = definition.span.line;
+        if definition.variadic {
+            return Err(unsupported("variadic function definitions", line));
+        }
+        let ty = self.function_type(definition)?;
+        if ty.result.kind().is_none() && ty.result != Ty::Void {
+            return Err(unsupported("structs returned by value", line));
+        }
+        let mut address_taken = HashSet::new();
+        collect_address_taken_block(&definition.body, &mut address_taken);
+        self.function = Some(FunctionState {
+            name: definition.name.clone(),
+            result: ty.result.clone(),
+            code: Vec::new(),
+            next: ty.parameters.len() as u32,
+            max: ty.parameters.len() as u32,
+            frame: 0,
+            address_taken,
+            heap: escape::analyze(definition),
+            breakables: Vec::new(),
+            switches: Vec::new(),
+            labels: HashMap::new(),
+            gotos: Vec::new(),
+            vlas: Vec::new(),
+            vla_scopes: Vec::new(),
+            next_vla: 0,
+        });
+
+        let registers = self.push_scope();
+        for (index, (parameter, ty)) in definition.parameters.iter().zip(&ty.parameters).enumerate() {
+            if ty.kind().is_none() {
+                return Err(unsupported("structs passed by value", line));
+            }
+            let Some(name) = &parameter.name else { continue };
+            let reg = index as Reg;
+            if self.state().address_taken.contains(name) {
+                let offset = self.allocate_frame(ty, line)?;
+                let address = self.temp()?;
+                self.emit(Instruction::LocalAddress { dst: address, offset });
+                self.emit(Instruction::Store { address, src: reg, kind: ty.kind().expect("scalar parameter") });
+                self.state().next -= 1;
+                self.bind(name, Binding::Frame(offset, ty.clone()));
+            } else {
+                self.bind(name, Binding::Register(reg, ty.clone()));
+            }
+        }
+        self.block_items(&definition.body)?;
+        self.pop_scope(registers);
+
+        // Falling off the end: `main` returns 0, anything else an unspecified value
+        if ty.result == Ty::Void {
+            self.emit(Instruction::ReturnVoid);
+        } else {
+            let zero = self.temp()?;
+            self.emit(Instruction::Int { dst: zero, value: 0 });
+            self.emit(Instruction::Return { src: zero });
+        }
+
+        let state = self.function.take().expect("inside a function body");
+        let mut code = state.code;
+        for goto in state.gotos {
+            let line = goto.line;
+            let (target, to) = state.labels.get(&goto.label).ok_or_else(|| invalid(format!("use of undeclared label '{}'", goto.label), line))?;
+            let from: Vec<u32> = goto.vlas.iter().map(|&(slot, _)| slot).collect();
+            vla::check_jump(&from, to).map_err(|_| invalid("jump into the scope of a variable length array".to_string(), line))?;
+            // Leaving VLAs' scope: free them on the way
+            let target = match goto.vlas.iter().find(|(slot, _)| !to.contains(slot)) {
+                Some(&(_, mark)) => {
+                    code.push(Instruction::VlaRelease { mark });
+                    code.push(Instruction::Jump { target: *target });
+                    code.len() as u32 - 2
+                }
+                None => *target,
+            };
+            set_target(&mut code[goto.at], target);
+        }
+        if state.max > Reg::MAX as u32 {
+            return Err(BytecodeError::TooLarge { function: state.name });
+        }
+        // A definition takes exactly its parameters, prototyped or not
+        let signature = Signature { variadic: None, ..self.signature(&ty, line)? };
+        Ok(Function {
+            name: state.name,
+            parameters: ty.parameters.len() as u16,
+            registers: state.max as u16,
+            frame_size: state.frame,
+            code,
+            signature: Some(signature),
+        })
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        let code = &mut self.state().code;
+        code.push(instruction);
+        code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.state().code.len() as u32
+    }
+
+    fn patch(&mut self, at: usize, target: u32) {
+        set_target(&mut self.state().code[at], target);
+    }
+
+    fn temp(&mut self) -> Result<Reg, BytecodeError> {
+        let state = self.state();
+        let reg = state.next;
+        if reg >= Reg::MAX as u32 {
+            return Err(BytecodeError::TooLarge { function: state.name.clone() });
+        }
+        state.next += 1;
+        state.max = state.max.max(state.next);
+        Ok(reg as Reg)
+    }
+
+    /// `count` consecutive registers
+    fn temps(&mut self, count: usize) -> Result<Reg, BytecodeError> {
+        let first = self.state().next as Reg;
+        for _ in 0..count {
+            self.temp()?;
+        }
+        Ok(first)
+    }
+
+    fn allocate_frame(&mut self, ty: &Ty, line: u32) -> Result<u32, BytecodeError> {
+        let (size, align) = (self.size(ty, line)?, self.align(ty, line)?);
+        let state = self.state();
+        let offset = state.frame.next_multiple_of(align.max(1));
+        state.frame = offset.checked_add(size).ok_or_else(|| BytecodeError::TooLarge { function: state.name.clone() })?;
+        Ok(offset)
+    }
+
+    fn constant_index(&mut self, bits: u64) -> u32 {
+        let constants = &mut self.program.constants;
+        match constants.iter().position(|&constant| constant == bits) {
+            Some(index) => index as u32,
+            None => {
+                constants.push(bits);
+                constants.len() as u32 - 1
+            }
+        }
+    }
+
+    fn load_integer(&mut self, dst: Reg, value: i64) {
+        match i32::try_from(value) {
+            Ok(value) => self.emit(Instruction::Int { dst, value }),
+            Err(_) => {
+                let index = self.constant_index(value as u64);
+                self.emit(Instruction::Const { dst, index })
+            }
+        };
+    }
+
+    // Statements
+
+    fn block_items(&mut self, block: &Block) -> Result<(), BytecodeError> {
+        for item in &block.items {
+            match item {
+                BlockItem::Declaration(declaration) => self.local_declaration(declaration)?,
+                BlockItem::Statement(statement) => self.statement(statement)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn local_declaration(&mut self, declaration: &Declaration) -> Result<(), BytecodeError> {
+        let line = declaration.span.line;
+        if declaration.declarators.is_empty() {
+            self.resolve(&declaration.ty, line)?;
+            return Ok(());
+        }
+        for declarator in &declaration.declarators {
+            let line = declarator.span.line;
+            let ty = self.declared_type(declarator, line)?;
+            let ty = self.complete_array(ty, declarator.initializer.as_ref(), line)?;
+            if self.state().heap.holds(&declarator.name) && !ty.pointee().is_some_and(|pointee| self.flat(pointee)) {
+                self.state().heap.reject(&declarator.name);
+            }
+            match (declaration.storage, &ty) {
+                (Some(StorageClass::Typedef), _) => self.bind(&declarator.name, Binding::Typedef(ty)),
+                (_, Ty::Function(function)) => {
+                    let internal = self.defined_functions.get(&declarator.name).copied();
+                    let binding = Binding::Function { name: declarator.name.clone(), ty: function.clone(), internal };
+                    self.bind(&declarator.name, binding);
+                }
+                (Some(_), Ty::VariableArray(..)) => {
+                    return Err(invalid("a variable length array must have automatic storage duration".to_string(), line));
+                }
+                (_, Ty::VariableArray(..)) => self.variable_array(&declarator.name, ty, declarator.initializer.as_ref(), line)?,
+                (Some(StorageClass::Extern), _) => match self.globals.get(&declarator.name).cloned() {
+                    Some((offset, ty)) => self.bind(&declarator.name, Binding::Global(offset, ty)),
+                    None => {
+                        let external = self.external(&declarator.name, Signature { parameters: Vec::new(), variadic: None, result: None });
+                        self.bind(&declarator.name, Binding::ExternalObject(external, ty));
+                    }
+                },
+                (Some(StorageClass::Static), _) => {
+                    let offset = self.allocate_data(&ty, line)?;
+                    if let Some(initializer) = &declarator.initializer {
+                        self.initialize_data(offset, &ty, initializer, line)?;
+                    }
+                    self.bind(&declarator.name, Binding::Global(offset, ty));
+                }
+                (Some(StorageClass::ThreadLocal), _) => return Err(unsupported("_Thread_local", line)),
+                _ if ty.kind().is_some() && !self.state().address_taken.contains(&declarator.name) => {
+                    let reg = self.temp()?;
+                    let mark = self.state().next;
+                    if let Some(initializer) = &declarator.initializer {
+                        let expression = match initializer {
+                            Initializer::Expression(expression) => expression,
+                            Initializer::List(items) => match items.as_slice() {
+                                [(None, Initializer::Expression(expression))] => expression,
+                                _ => return Err(invalid("invalid scalar initializer".to_string(), line)),
+                            },
+                        };
+                        self.statement_marker(line);
+                        let value = self.rvalue(expression)?;
+                        let value = self.convert(value, &ty, line)?;
+                        self.emit(Instruction::Move { dst: reg, src: value.reg });
+                    }
+                    self.state().next = mark;
+                    // Visible from its own initializer on, as in C
+                    self.bind(&declarator.name, Binding::Register(reg, ty));
+                }
+                _ => {
+                    let offset = self.allocate_frame(&ty, line)?;
+                    self.bind(&declarator.name, Binding::Frame(offset, ty.clone()));
+                    if let Some(initializer) = &declarator.initializer {
+                        self.statement_marker(line);
+                        let mark = self.state().next;
+                        let address = self.temp()?;
+                        self.emit(Instruction::LocalAddress { dst: address, offset });
+                        if ty.kind().is_none() {
+                            let size = self.size(&ty, line)?;
+                            self.emit(Instruction::ZeroBytes { dst: address, size });
+                        }
+                        self.initialize_memory(address, 0, &ty, initializer, line)?;
+                        self.state().next = mark;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocate a VLA from the runtime's VLA stack, remembering the stack's
+    /// height so every way out of its scope can free it. Only `= {}`
+    /// initializes one, to zeros.
+    fn variable_array(&mut self, name: &str, ty: Ty, initializer: Option<&Initializer>, line: u32) -> Result<(), BytecodeError> {
+        let Ty::VariableArray(element, length) = &ty else { unreachable!("called for VLAs") };
+        let zero = match initializer {
+            None => false,
+            Some(Initializer::List(items)) if items.is_empty() => true,
+            Some(_) => return Err(invalid("a variable length array may only be initialized with {}".to_string(), line)),
+        };
+        self.statement_marker(line);
+        let mark = self.temp()?;
+        let address = self.temp()?;
+        let temps = self.state().next;
+        let (length, element) = self.element_count(*length, element)?;
+        let size = self.size(element, line)?;
+        self.emit(Instruction::VlaMark { dst: mark });
+        self.emit(Instruction::VlaAllocate { dst: address, length, size, zero });
+        self.state().next = temps;
+        let state = self.state();
+        let slot = state.next_vla;
+        state.next_vla += 1;
+        state.vlas.push((slot, mark));
+        self.bind(name, Binding::VariableArray(address, ty));
+        Ok(())
+    }
+
+    /// How many of its innermost, fixed-size elements a VLA of VLAs holds,
+    /// so they're allocated at once. A length that isn't positive becomes
+    /// the count, for the VLA stack to report.
+    fn element_count<'t>(&mut self, length: Reg, mut element: &'t Ty) -> Result<(Reg, &'t Ty), BytecodeError> {
+        let mut count = length;
+        while let Ty::VariableArray(inner, length) = element {
+            let product = self.temp()?;
+            let positive = self.temp()?;
+            self.emit(Instruction::Move { dst: product, src: *length });
+            self.load_integer(positive, 0);
+            self.emit(Instruction::LtS { dst: positive, a: positive, b: *length });
+            let skip = self.emit(Instruction::JumpIfZero { condition: positive, target: 0 });
+            self.emit(Instruction::Mul { dst: product, a: count, b: *length });
+            let end = self.here();
+            self.patch(skip, end);
+            count = product;
+            element = inner;
+        }
+        Ok((count, element))
+    }
+
+    /// Store an automatic object's initializer at `address + offset`; an
+    /// aggregate is already zeroed
+    fn initialize_memory(&mut self, address: Reg, offset: u32, ty: &Ty, initializer: &Initializer, line: u32) -> Result<(), BytecodeError> {
+        let at = |compiler: &mut Self| -> Result<Reg, BytecodeError> {
+            if offset == 0 {
+                return Ok(address);
+            }
+            let reg = compiler.temp()?;
+            compiler.emit(Instruction::AddImm { dst: reg, a: address, value: offset as i32 });
+            Ok(reg)
+        };
+        match (ty, initializer) {
+            (Ty::Array(element, Some(length)), Initializer::Expression(Expression { kind: ExpressionKind::String(bytes), .. }))
+                if matches!(**element, Ty::Int { bytes: 1, .. }) =>
+            {
+                let literal = self.string(bytes);
+                let source = self.temp()?;
+                self.emit(Instruction::GlobalAddress { dst: source, offset: literal });
+                let destination = at(self)?;
+                let size = (bytes.len() as u32 + 1).min(*length);
+                self.emit(Instruction::CopyBytes { dst: destination, src: source, size });
+                Ok(())
+            }
+            (Ty::Array(..) | Ty::Record(_), Initializer::List(items)) => {
+                for (member_offset, member_ty, initializer) in self.list_members(ty, items, line)? {
+                    let mark = self.state().next;
+                    self.initialize_memory(address, offset + member_offset, &member_ty, initializer, line)?;
+                    self.state().next = mark;
+                }
+                Ok(())
+            }
+            (Ty::Record(_), Initializer::Expression(expression)) => {
+                let value = self.rvalue(expression)?;
+                if value.ty != *ty {
+                    return Err(invalid("initializing a struct from a different type".to_string(), line));
+                }
+                let size = self.size(ty, line)?;
+                let destination = at(self)?;
+                self.emit(Instruction::CopyBytes { dst: destination, src: value.reg, size });
+                Ok(())
+            }
+            (ty, Initializer::Expression(expression)) if ty.is_scalar() => {
+                let value = self.rvalue(expression)?;
+                let value = self.convert(value, ty, line)?;
+                let destination = at(self)?;
+                self.emit(Instruction::Store { address: destination, src: value.reg, kind: ty.kind().expect("scalar") });
+                Ok(())
+            }
+            (_, Initializer::List(items)) if ty.is_scalar() => match items.as_slice() {
+                [(None, inner)] => self.initialize_memory(address, offset, ty, inner, line),
+                _ => Err(invalid("too many initializers for a scalar".to_string(), line)),
+            },
+            _ => Err(unsupported("this initializer", line)),
+        }
+    }
+
+    fn statement_marker(&mut self, line: u32) {
+        self.emit(Instruction::Statement { line });
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), BytecodeError> {
+        let line = statement.span.line;
+        // Loops mark every test of their condition instead
+        let unmarked = matches!(
+            statement.kind,
+            StatementKind::Compound(_)
+                | StatementKind::Labeled { .. }
+                | StatementKind::Case { .. }
+                | StatementKind::Default(_)
+                | StatementKind::While { .. }
+                | StatementKind::For { .. }
+        );
+        if !unmarked {
+            self.statement_marker(line);
+        }
+        let mark = self.state().next;
+        match &statement.kind {
+            StatementKind::Expression(None) => {}
+            StatementKind::Expression(Some(expression)) => {
+                self.operand(expression)?;
+            }
+            StatementKind::Compound(block) => {
+                let registers = self.push_scope();
+                self.block_items(block)?;
+                self.pop_scope(registers);
+            }
+            StatementKind::If { condition, then, otherwise } => {
+                let condition = self.condition(condition)?;
+                let skip = self.emit(Instruction::JumpIfZero { condition, target: 0 });
+                self.state().next = mark;
+                self.statement(then)?;
+                match otherwise {
+                    None => {
+                        let end = self.here();
+                        self.patch(skip, end);
+                    }
+                    Some(otherwise) => {
+                        let over = self.emit(Instruction::Jump { target: 0 });
+                        let start = self.here();
+                        self.patch(skip, start);
+                        self.statement(otherwise)?;
+                        let end = self.here();
+                        self.patch(over, end);
+                    }
+                }
+            }
+            StatementKind::While { condition, body } => {
+                let top = self.here();
+                self.statement_marker(line);
+                let condition = self.condition(condition)?;
+                let exit = self.emit(Instruction::JumpIfZero { condition, target: 0 });
+                self.state().next = mark;
+                let vlas = self.state().vlas.len();
+                self.state().breakables.push(Breakable { breaks: Vec::new(), continues: Some(Vec::new()), vlas });
+                self.statement(body)?;
+                let breakable = self.state().breakables.pop().expect("pushed above");
+                self.emit(Instruction::Jump { target: top });
+                let end = self.here();
+                self.patch(exit, end);
+                self.finish_breakable(breakable, top, end);
+            }
+            StatementKind::DoWhile { body, condition } => {
+                let top = self.here();
+                let vlas = self.state().vlas.len();
+                self.state().breakables.push(Breakable { breaks: Vec::new(), continues: Some(Vec::new()), vlas });
+                self.statement(body)?;
+                let breakable = self.state().breakables.pop().expect("pushed above");
+                let test = self.here();
+                self.statement_marker(line);
+                let condition = self.condition(condition)?;
+                self.emit(Instruction::JumpIfNonZero { condition, target: top });
+                let end = self.here();
+                self.finish_breakable(breakable, test, end);
+            }
+            StatementKind::For { init, condition, step, body } => {
+                let registers = self.push_scope();
+                match init {
+                    Some(ForInit::Declaration(declaration)) => self.local_declaration(declaration)?,
+                    Some(ForInit::Expression(expression)) => {
+                        let mark = self.state().next;
+                        self.operand(expression)?;
+                        self.state().next = mark;
+                    }
+                    None => {}
+                }
+                let inner = self.state().next;
+                let top = self.here();
+                self.statement_marker(line);
+                let exit = match condition {
+                    Some(condition) => {
+                        let condition = self.condition(condition)?;
+                        self.state().next = inner;
+                        Some(self.emit(Instruction::JumpIfZero { condition, target: 0 }))
+                    }
+                    None => None,
+                };
+                let vlas = self.state().vlas.len();
+                self.state().breakables.push(Breakable { breaks: Vec::new(), continues: Some(Vec::new()), vlas });
+                self.statement(body)?;
+                let breakable = self.state().breakables.pop().expect("pushed above");
+                let next = self.here();
+                if let Some(step) = step {
+                    self.operand(step)?;
+                    self.state().next = inner;
+                }
+                self.emit(Instruction::Jump { target: top });
+                let end = self.here();
+                if let Some(exit) = exit {
+                    self.patch(exit, end);
+                }
+                self.finish_breakable(breakable, next, end);
+                self.pop_scope(registers);
+            }
+            StatementKind::Switch { value, body } => {
+                let value = self.rvalue(value)?;
+                if !value.ty.is_integer() {
+                    return Err(invalid("switch on a value that isn't an integer".to_string(), line));
+                }
+                let value = self.convert(value.clone(), &value.ty.promoted(), line)?;
+                let table = self.program.switch_tables.len() as u32;
+                self.program.switch_tables.push(SwitchTable { cases: Vec::new(), default: 0 });
+                self.emit(Instruction::Switch { value: value.reg, table });
+                self.state().next = mark;
+                let vlas = self.vla_slots();
+                let outer = vlas.len();
+                self.state().switches.push(SwitchState { ty: value.ty, cases: Vec::new(), default: None, vlas });
+                self.state().breakables.push(Breakable { breaks: Vec::new(), continues: None, vlas: outer });
+                self.statement(body)?;
+                let breakable = self.state().breakables.pop().expect("pushed above");
+                let switch = self.state().switches.pop().expect("pushed above");
+                let end = self.here();
+                for at in breakable.breaks {
+                    self.patch(at, end);
+                }
+                let mut cases = switch.cases;
+                cases.sort_unstable_by_key(|&(value, _)| value);
+                if cases.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                    return Err(invalid("duplicate case value".to_string(), line));
+                }
+                self.program.switch_tables[table as usize] = SwitchTable { cases, default: switch.default.unwrap_or(end) };
+            }
+            StatementKind::Case { value, body } => {
+                let value = self.integer_constant(value)?;
+                self.check_case(line)?;
+                let here = self.here();
+                let switch = self.state().switches.last_mut().ok_or_else(|| invalid("case outside a switch".to_string(), line))?;
+                let value = wrap_to(value, &switch.ty);
+                switch.cases.push((value, here));
+                self.statement(body)?;
+            }
+            StatementKind::Default(body) => {
+                self.check_case(line)?;
+                let here = self.here();
+                let switch = self.state().switches.last_mut().ok_or_else(|| invalid("default outside a switch".to_string(), line))?;
+                if switch.default.replace(here).is_some() {
+                    return Err(invalid("multiple default labels in one switch".to_string(), line));
+                }
+                self.statement(body)?;
+            }
+            StatementKind::Labeled { label, body } => {
+                let here = self.here();
+                let vlas = self.vla_slots();
+                if self.state().labels.insert(label.clone(), (here, vlas)).is_some() {
+                    return Err(invalid(format!("redefinition of label '{}'", label), line));
+                }
+                self.statement(body)?;
+            }
+            StatementKind::Goto(label) => {
+                let at = self.emit(Instruction::Jump { target: 0 });
+                let vlas = self.state().vlas.clone();
+                self.state().gotos.push(Goto { at, label: label.clone(), line, vlas });
+            }
+            StatementKind::Break => {
+                let outer = self.state().breakables.last().ok_or_else(|| invalid("break outside a loop or switch".to_string(), line))?.vlas;
+                self.release_variable_arrays(outer);
+                let at = self.emit(Instruction::Jump { target: 0 });
+                self.state().breakables.last_mut().expect("checked above").breaks.push(at);
+            }
+            StatementKind::Continue => {
+                let index = self
+                    .state()
+                    .breakables
+                    .iter()
+                    .rposition(|breakable| breakable.continues.is_some())
+                    .ok_or_else(|| invalid("continue outside a loop".to_string(), line))?;
+                let outer = self.state().breakables[index].vlas;
+                self.release_variable_arrays(outer);
+                let at = self.emit(Instruction::Jump { target: 0 });
+                self.state().breakables[index].continues.as_mut().expect("found above").push(at);
+            }
+            StatementKind::Return(None) => {
+                self.release_variable_arrays(0);
+                self.emit(Instruction::ReturnVoid);
+            }
+            StatementKind::Return(Some(expression)) => {
+                let result = self.state().result.clone();
+                let value = self.rvalue(expression)?;
+                self.release_variable_arrays(0);
+                if result == Ty::Void {
+                    self.emit(Instruction::ReturnVoid);
+                } else {
+                    let value = self.convert(value, &result, line)?;
+                    self.emit(Instruction::Return { src: value.reg });
+                }
+            }
+            StatementKind::Asm { statement, outputs, inputs } => self.asm(statement, outputs, inputs, line)?,
+        }
+        self.state().next = mark;
+        Ok(())
+    }
+
+    /// A `case` or `default` label may not be in the scope of a VLA its
+    /// `switch` isn't
+    fn check_case(&mut self, line: u32) -> Result<(), BytecodeError> {
+        let to = self.vla_slots();
+        let from = match self.state().switches.last() {
+            Some(switch) => &switch.vlas,
+            None => return Ok(()),
+        };
+        vla::check_jump(from, &to).map_err(|_| invalid("jump into the scope of a variable length array".to_string(), line))
+    }
+
+    fn finish_breakable(&mut self, breakable: Breakable, next: u32, end: u32) {
+        for at in breakable.breaks {
+            self.patch(at, end);
+        }
+        for at in breakable.continues.into_iter().flatten() {
+            self.patch(at, next);
+        }
+    }
+
+    /// A controlling expression, as a register that is 0 when false
+    fn condition(&mut self, expression: &Expression) -> Result<Reg, BytecodeError> {
+        let value = self.rvalue(expression)?;
+        let line = expression.span.line;
+        match &value.ty {
+            ty if ty.is_integer() || ty.is_pointer() => Ok(value.reg),
+            ty if ty.is_float() => Ok(self.convert(value, &Ty::Bool, line)?.reg),
+            _ => Err(invalid("condition of a type that isn't scalar".to_string(), line)),
+        }
+    }
+
+    // Expressions
+
+    /// The type of `expression` without evaluating it (`sizeof`)
+    fn type_of(&mut self, expression: &Expression) -> Result<Ty, BytecodeError> {
+        if self.function.is_none() {
+            return self.constant_type(expression);
+        }
+        let (code, next, max) = (self.state().code.len(), self.state().next, self.state().max);
+        let (constants, data, relocations, tables) = (
+            self.program.constants.len(),
+            self.program.data.len(),
+            self.program.relocations.len(),
+            self.program.switch_tables.len(),
+        );
+        let strings = self.strings.clone();
+        let ty = self.operand(expression).map(|operand| operand.ty().clone());
+        let state = self.state();
+        state.code.truncate(code);
+        state.next = next;
+        state.max = max;
+        self.program.constants.truncate(constants);
+        self.program.data.truncate(data);
+        self.program.relocations.truncate(relocations);
+        self.program.switch_tables.truncate(tables);
+        self.strings = strings;
+        ty
+    }
+
+    /// `sizeof` at file scope, where only names and literals can appear
+    fn constant_type(&mut self, expression: &Expression) -> Result<Ty, BytecodeError> {
+        let line = expression.span.line;
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => match self.lookup(name) {
+                Some(Binding::Global(_, ty) | Binding::ExternalObject(_, ty)) => Ok(ty.clone()),
+                Some(Binding::Constant(_)) => Ok(INT),
+                _ => Err(unsupported("this sizeof operand at file scope", line)),
+            },
+            ExpressionKind::String(bytes) => Ok(Ty::Array(Box::new(CHAR), Some(bytes.len() as u32 + 1))),
+            _ => match self.constant(expression)? {
+                Constant::Int(_, ty) | Constant::Float(_, ty) => Ok(ty),
+                Constant::Address { .. } => Ok(Ty::Pointer(Box::new(Ty::Void))),
+            },
+        }
+    }
+
+    fn rvalue(&mut self, expression: &Expression) -> Result<Value, BytecodeError> {
+        let operand = self.operand(expression)?;
+        self.load(operand)
+    }
+
+    /// The value of an operand: a register variable as is, memory loaded,
+    /// arrays and functions decayed to their address
+    fn load(&mut self, operand: Operand) -> Result<Value, BytecodeError> {
+        match operand {
+            Operand::Value(value) => Ok(value),
+            Operand::Place(Place::Register(reg, ty)) => Ok(Value { reg, ty }),
+            Operand::Place(Place::Memory(address, ty)) => match ty.kind() {
+                Some(_) if matches!(ty, Ty::Function(_)) => Ok(Value { reg: address, ty: ty.decayed() }),
+                Some(kind) => {
+                    let dst = self.temp()?;
+                    self.emit(Instruction::Load { dst, address, kind });
+                    Ok(Value { reg: dst, ty })
+                }
+                // Structs are handled by address
+                None if matches!(ty, Ty::Record(_)) => Ok(Value { reg: address, ty }),
+                None => Ok(Value { reg: address, ty: ty.decayed() }),
+            },
+        }
+    }
+
+    fn place(&mut self, expression: &Expression) -> Result<Place, BytecodeError> {
+        match self.operand(expression)? {
+            Operand::Place(place) => Ok(place),
+            Operand::Value(_) => Err(invalid("expression is not assignable".to_string(), expression.span.line)),
+        }
+    }
+
+    fn store(&mut self, place: &Place, value: Value, line: u32) -> Result<Value, BytecodeError> {
+        let ty = place.ty().clone();
+        if let Ty::Record(_) = ty {
+            if value.ty != ty {
+                return Err(invalid("assigning between different struct types".to_string(), line));
+            }
+            let Place::Memory(address, _) = place else { unreachable!("structs live in memory") };
+            let size = self.size(&ty, line)?;
+            self.emit(Instruction::CopyBytes { dst: *address, src: value.reg, size });
+            return Ok(Value { reg: *address, ty });
+        }
+        let value = self.convert(value, &ty, line)?;
+        match place {
+            Place::Register(reg, _) => {
+                self.emit(Instruction::Move { dst: *reg, src: value.reg });
+            }
+            Place::Memory(address, _) => {
+                let kind = ty.kind().ok_or_else(|| invalid("assignment to an array".to_string(), line))?;
+                self.emit(Instruction::Store { address: *address, src: value.reg, kind });
+            }
+        }
+        Ok(value)
+    }
+
+    fn operand(&mut self, expression: &Expression) -> Result<Operand, BytecodeError> {
+        let line = expression.span.line;
+        Ok(match &expression.kind {
+            ExpressionKind::Integer { value, suffix } => {
+                let ty = integer_literal_type(*value, *suffix);
+                let dst = self.temp()?;
+                self.load_integer(dst, *value as i64);
+                Operand::Value(Value { reg: dst, ty })
+            }
+            ExpressionKind::Character(value) => {
+                let dst = self.temp()?;
+                self.load_integer(dst, *value);
+                Operand::Value(Value { reg: dst, ty: INT })
+            }
+            ExpressionKind::Float { value, single } => {
+                let dst = self.temp()?;
+                let value = if *single { *value as f32 as f64 } else { *value };
+                let index = self.constant_index(value.to_bits());
+                self.emit(Instruction::Const { dst, index });
+                Operand::Value(Value { reg: dst, ty: if *single { Ty::Float } else { Ty::Double } })
+            }
+            ExpressionKind::String(bytes) => {
+                let offset = self.string(bytes);
+                let dst = self.temp()?;
+                self.emit(Instruction::GlobalAddress { dst, offset });
+                Operand::Place(Place::Memory(dst, Ty::Array(Box::new(CHAR), Some(bytes.len() as u32 + 1))))
+            }
+            ExpressionKind::Identifier(name) => self.identifier(name, line)?,
+            ExpressionKind::Unary { op, operand } => self.unary(*op, operand, line)?,
+            ExpressionKind::Binary { op: op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), left, right } => {
+                let dst = self.temp()?;
+                let left = self.condition(left)?;
+                self.emit(Instruction::Test { dst, src: left });
+                let skip = match op {
+                    BinaryOp::LogicalAnd => self.emit(Instruction::JumpIfZero { condition: dst, target: 0 }),
+                    _ => self.emit(Instruction::JumpIfNonZero { condition: dst, target: 0 }),
+                };
+                let right = self.condition(right)?;
+                self.emit(Instruction::Test { dst, src: right });
+                let end = self.here();
+                self.patch(skip, end);
+                Operand::Value(Value { reg: dst, ty: INT })
+            }
+            ExpressionKind::Binary { op, left, right } => {
+                let left = self.rvalue(left)?;
+                let right = self.rvalue(right)?;
+                Operand::Value(self.binary(*op, left, right, line)?)
+            }
+            ExpressionKind::Assign { op, target, value } => {
+                let place = self.place(target)?;
+                let value = self.rvalue(value)?;
+                let value = match op {
+                    None => value,
+                    Some(op) => {
+                        let current = self.load(Operand::Place(place.clone()))?;
+                        // A register variable's value would change under us
+                        let current = self.copy(current)?;
+                        self.binary(*op, current, value, line)?
+                    }
+                };
+                Operand::Value(self.store(&place, value, line)?)
+            }
+            ExpressionKind::Conditional { condition, then, otherwise } => {
+                let dst = self.temp()?;
+                let condition = self.condition(condition)?;
+                let skip = self.emit(Instruction::JumpIfZero { condition, target: 0 });
+                let then_ty = self.type_of(then)?.decayed();
+                let otherwise_ty = self.type_of(otherwise)?.decayed();
+                let ty = match (&then_ty, &otherwise_ty) {
+                    (a, b) if a.is_arithmetic() && b.is_arithmetic() => arithmetic_type(a, b),
+                    (Ty::Void, _) | (_, Ty::Void) => Ty::Void,
+                    (a, _) if a.is_pointer() => a.clone(),
+                    (_, b) => b.clone(),
+                };
+                let mark = self.state().next;
+                let value = self.rvalue(then)?;
+                self.move_converted(dst, value, &ty, line)?;
+                self.state().next = mark;
+                let over = self.emit(Instruction::Jump { target: 0 });
+                let start = self.here();
+                self.patch(skip, start);
+                let value = self.rvalue(otherwise)?;
+                self.move_converted(dst, value, &ty, line)?;
+                self.state().next = mark;
+                let end = self.here();
+                self.patch(over, end);
+                Operand::Value(Value { reg: dst, ty })
+            }
+            ExpressionKind::Call { function, arguments } => Operand::Value(self.call(function, arguments, line)?),
+            ExpressionKind::Index { array, index } => {
+                let array = self.rvalue(array)?;
+                let index = self.rvalue(index)?;
+                let address = self.binary(BinaryOp::Add, array, index, line)?;
+                let ty = address.ty.pointee().cloned().ok_or_else(|| invalid("subscript of something that isn't an array or pointer".to_string(), line))?;
+                Operand::Place(Place::Memory(address.reg, ty))
+            }
+            ExpressionKind::Member { base, member, arrow } => {
+                let (address, record) = if *arrow {
+                    let pointer = self.rvalue(base)?;
+                    let record = pointer.ty.pointee().cloned().ok_or_else(|| invalid("-> on something that isn't a pointer".to_string(), line))?;
+                    (pointer.reg, record)
+                } else {
+                    match self.operand(base)? {
+                        Operand::Place(Place::Memory(address, ty)) => (address, ty),
+                        Operand::Value(Value { reg, ty: ty @ Ty::Record(_) }) => (reg, ty),
+                        _ => return Err(invalid(format!("member '{}' of something that isn't a struct or union", member), line)),
+                    }
+                };
+                let field = self.field(&record, member, line)?;
+                let dst = if field.offset == 0 {
+                    address
+                } else {
+                    let dst = self.temp()?;
+                    self.emit(Instruction::AddImm { dst, a: address, value: field.offset as i32 });
+                    dst
+                };
+                Operand::Place(Place::Memory(dst, field.ty))
+            }
+            ExpressionKind::Cast { ty, operand } => {
+                let ty = self.resolve(ty, line)?;
+                let value = self.rvalue(operand)?;
+                if ty == Ty::Void {
+                    Operand::Value(Value { reg: value.reg, ty: Ty::Void })
+                } else {
+                    Operand::Value(self.convert(value, &ty, line)?)
+                }
+            }
+            ExpressionKind::SizeofType(ty) => {
+                let ty = self.resolve(ty, line)?;
+                self.size_value(&ty, line)?
+            }
+            ExpressionKind::AlignofType(ty) => {
+                let ty = self.resolve(ty, line)?;
+                let align = self.align(&ty, line)?;
+                self.integer_value(align as i64, UNSIGNED_LONG)?
+            }
+            ExpressionKind::SizeofExpression(operand) => {
+                let ty = self.type_of(operand)?;
+                self.size_value(&ty, line)?
+            }
+            ExpressionKind::Comma(left, right) => {
+                self.operand(left)?;
+                let value = self.rvalue(right)?;
+                Operand::Value(value)
+            }
+            // Only the selected association is compiled
+            ExpressionKind::Generic { control, associations } => {
+                let selected = self.generic(control, associations, line)?;
+                self.operand(&associations[selected].1)?
+            }
+        })
+    }
+
+    /// `sizeof` a type; a VLA's is its element's size times its length
+    fn size_value(&mut self, ty: &Ty, line: u32) -> Result<Operand, BytecodeError> {
+        let reg = self.size_register(ty, line)?;
+        Ok(Operand::Value(Value { reg, ty: UNSIGNED_LONG }))
+    }
+
+    /// A type's size in a fresh register, computed at run time for a VLA
+    fn size_register(&mut self, ty: &Ty, line: u32) -> Result<Reg, BytecodeError> {
+        let Ty::VariableArray(element, length) = ty else {
+            let size = self.size(ty, line)?;
+            let dst = self.temp()?;
+            self.load_integer(dst, size as i64);
+            return Ok(dst);
+        };
+        let factor = self.size_register(element, line)?;
+        let dst = self.temp()?;
+        self.emit(Instruction::Mul { dst, a: *length, b: factor });
+        Ok(dst)
+    }
+
+    fn integer_value(&mut self, value: i64, ty: Ty) -> Result<Operand, BytecodeError> {
+        let dst = self.temp()?;
+        self.load_integer(dst, value);
+        Ok(Operand::Value(Value { reg: dst, ty }))
+    }
+
+    /// `value` in a fresh register
+    fn copy(&mut self, value: Value) -> Result<Value, BytecodeError> {
+        let dst = self.temp()?;
+        self.emit(Instruction::Move { dst, src: value.reg });
+        Ok(Value { reg: dst, ty: value.ty })
+    }
+
+    fn move_converted(&mut self, dst: Reg, value: Value, ty: &Ty, line: u32) -> Result<(), BytecodeError> {
+        let value = if *ty == Ty::Void || matches!(ty, Ty::Record(_)) { value } else { self.convert(value, ty, line)? };
+        self.emit(Instruction::Move { dst, src: value.reg });
+        Ok(())
+    }
+
+    fn identifier(&mut self, name: &str, line: u32) -> Result<Operand, BytecodeError> {
+        let binding = self.lookup(name).cloned().ok_or_else(|| BytecodeError::Undeclared { name: name.to_string(), line })?;
+        Ok(match binding {
+            Binding::Register(reg, ty) => Operand::Place(Place::Register(reg, ty)),
+            Binding::Frame(offset, ty) => {
+                let dst = self.temp()?;
+                self.emit(Instruction::LocalAddress { dst, offset });
+                Operand::Place(Place::Memory(dst, ty))
+            }
+            Binding::VariableArray(address, ty) => {
+                let dst = self.temp()?;
+                self.emit(Instruction::Move { dst, src: address });
+                Operand::Place(Place::Memory(dst, ty))
+            }
+            Binding::Global(offset, ty) => {
+                let dst = self.temp()?;
+                self.emit(Instruction::GlobalAddress { dst, offset });
+                Operand::Place(Place::Memory(dst, ty))
+            }
+            Binding::ExternalObject(external, ty) => {
+                let dst = self.temp()?;
+                self.emit(Instruction::ExternalAddress { dst, external });
+                Operand::Place(Place::Memory(dst, ty))
+            }
+            Binding::Function { name, ty, internal } => {
+                let dst = self.temp()?;
+                match internal {
+                    Some(function) => self.emit(Instruction::FunctionAddress { dst, function }),
+                    None => {
+                        let signature = self.signature(&ty, line)?;
+                        let external = self.external(&name, signature);
+                        self.emit(Instruction::ExternalAddress { dst, external })
+                    }
+                };
+                Operand::Value(Value { reg: dst, ty: Ty::Pointer(Box::new(Ty::Function(ty))) })
+            }
+            Binding::Constant(value) => self.integer_value(value, INT)?,
+            Binding::Typedef(_) => return Err(invalid(format!("'{}' is a type, not a value", name), line)),
+        })
+    }
+
+    fn unary(&mut self, op: UnaryOp, operand: &Expression, line: u32) -> Result<Operand, BytecodeError> {
+        Ok(match op {
+            UnaryOp::AddressOf => match self.operand(operand)? {
+                Operand::Place(Place::Memory(address, ty)) => Operand::Value(Value { reg: address, ty: Ty::Pointer(Box::new(ty)) }),
+                // A function designator already is its address
+                Operand::Value(value) if matches!(value.ty.pointee(), Some(Ty::Function(_))) => Operand::Value(value),
+                _ => return Err(invalid("cannot take the address of this expression".to_string(), line)),
+            },
+            UnaryOp::Deref => {
+                let pointer = self.rvalue(operand)?;
+                match pointer.ty.pointee() {
+                    // `*f` of a function pointer is the function, which decays right back
+                    Some(Ty::Function(_)) => Operand::Value(pointer),
+                    Some(Ty::Void) => return Err(invalid("dereferencing a void pointer".to_string(), line)),
+                    Some(pointee) => Operand::Place(Place::Memory(pointer.reg, pointee.clone())),
+                    None => return Err(invalid("dereferencing something that isn't a pointer".to_string(), line)),
+                }
+            }
+            UnaryOp::Plus => {
+                let value = self.rvalue(operand)?;
+                let ty = value.ty.promoted();
+                Operand::Value(self.convert(value, &ty, line)?)
+            }
+            UnaryOp::Minus => {
+                let value = self.rvalue(operand)?;
+                let ty = value.ty.promoted();
+                let value = self.convert(value, &ty, line)?;
+                let dst = self.temp()?;
+                match &ty {
+                    Ty::Float | Ty::Double => {
+                        self.emit(Instruction::FNeg { dst, src: value.reg });
+                    }
+                    ty if ty.is_signed() => {
+                        self.emit(Instruction::Signed { op: SignedOp::Neg, dst, a: value.reg, b: value.reg, bits: ty.bits() });
+                    }
+                    ty if ty.is_integer() => {
+                        self.emit(Instruction::Neg { dst, src: value.reg });
+                        self.normalize(dst, ty);
+                    }
+                    _ => return Err(invalid("unary minus on something that isn't arithmetic".to_string(), line)),
+                };
+                Operand::Value(Value { reg: dst, ty })
+            }
+            UnaryOp::BitNot => {
+                let value = self.rvalue(operand)?;
+                let ty = value.ty.promoted();
+                if !ty.is_integer() {
+                    return Err(invalid("~ on something that isn't an integer".to_string(), line));
+                }
+                let value = self.convert(value, &ty, line)?;
+                let dst = self.temp()?;
+                self.emit(Instruction::Not { dst, src: value.reg });
+                self.normalize(dst, &ty);
+                Operand::Value(Value { reg: dst, ty })
+            }
+            UnaryOp::Not => {
+                let condition = self.condition(operand)?;
+                let dst = self.temp()?;
+                self.emit(Instruction::LogicalNot { dst, src: condition });
+                Operand::Value(Value { reg: dst, ty: INT })
+            }
+            UnaryOp::PreIncrement | UnaryOp::PreDecrement | UnaryOp::PostIncrement | UnaryOp::PostDecrement => {
+                let place = self.place(operand)?;
+                let current = self.load(Operand::Place(place.clone()))?;
+                let old = self.copy(current)?;
+                let one = self.temp()?;
+                self.emit(Instruction::Int { dst: one, value: 1 });
+                let prefix = matches!(op, UnaryOp::PreIncrement | UnaryOp::PreDecrement);
+                let op = if matches!(op, UnaryOp::PreIncrement | UnaryOp::PostIncrement) { BinaryOp::Add } else { BinaryOp::Sub };
+                let new = self.binary(op, old.clone(), Value { reg: one, ty: INT }, line)?;
+                let new = self.store(&place, new, line)?;
+                Operand::Value(if prefix { new } else { old })
+            }
+        })
+    }
+
+    fn binary(&mut self, op: BinaryOp, left: Value, right: Value, line: u32) -> Result<Value, BytecodeError> {
+        let (left_ty, right_ty) = (left.ty.clone(), right.ty.clone());
+        match (op, left_ty.pointee(), right_ty.pointee()) {
+            (BinaryOp::Add, Some(pointee), None) if right_ty.is_integer() => return self.pointer_offset(left, right, pointee, false, line),
+            (BinaryOp::Add, None, Some(pointee)) if left_ty.is_integer() => return self.pointer_offset(right, left, pointee, false, line),
+            (BinaryOp::Sub, Some(pointee), None) if right_ty.is_integer() => return self.pointer_offset(left, right, pointee, true, line),
+            (BinaryOp::Sub, Some(pointee), Some(_)) => {
+                let stride = self.stride(pointee, line)?;
+                let dst = self.temp()?;
+                self.emit(Instruction::Sub { dst, a: left.reg, b: right.reg });
+                if let Some(divisor) = stride {
+                    self.emit(Instruction::Signed { op: SignedOp::Div, dst, a: dst, b: divisor, bits: 64 });
+                }
+                return Ok(Value { reg: dst, ty: LONG });
+            }
+            // Pointers compare as addresses, with each other or with 0
+            (op, Some(_), _) | (op, _, Some(_)) if op.is_comparison() => {
+                return self.comparison(op, left.reg, right.reg, &UNSIGNED_LONG);
+            }
+            _ => {}
+        }
+        if !left_ty.is_arithmetic() || !right_ty.is_arithmetic() {
+            return Err(invalid(format!("invalid operands to binary {}", op), line));
+        }
+
+        if op.is_comparison() {
+            let ty = arithmetic_type(&left_ty, &right_ty);
+            let (a, b) = (self.convert(left, &ty, line)?, self.convert(right, &ty, line)?);
+            return self.comparison(op, a.reg, b.reg, &ty);
+        }
+
+        if matches!(op, BinaryOp::Shl | BinaryOp::Shr) {
+            let (ty, count_ty) = (left_ty.promoted(), right_ty.promoted());
+            if !ty.is_integer() || !count_ty.is_integer() {
+                return Err(invalid(format!("invalid operands to binary {}", op), line));
+            }
+            let (a, b) = (self.convert(left, &ty, line)?, self.convert(right, &count_ty, line)?);
+            let dst = self.temp()?;
+            match (op, ty.is_signed()) {
+                (BinaryOp::Shl, true) => {
+                    self.emit(Instruction::Signed { op: SignedOp::Shl, dst, a: a.reg, b: b.reg, bits: ty.bits() });
+                }
+                (BinaryOp::Shl, false) => {
+                    self.emit(Instruction::Shl { dst, a: a.reg, b: b.reg, bits: ty.bits() });
+                    self.normalize(dst, &ty);
+                }
+                (_, true) => {
+                    self.emit(Instruction::ShrS { dst, a: a.reg, b: b.reg, bits: ty.bits() });
+                }
+                (_, false) => {
+                    self.emit(Instruction::ShrU { dst, a: a.reg, b: b.reg, bits: ty.bits() });
+                }
+            }
+            return Ok(Value { reg: dst, ty });
+        }
+
+        let ty = arithmetic_type(&left_ty, &right_ty);
+        let (a, b) = (self.convert(left, &ty, line)?.reg, self.convert(right, &ty, line)?.reg);
+        let dst = self.temp()?;
+        if ty.is_float() {
+            self.emit(match op {
+                BinaryOp::Add => Instruction::FAdd { dst, a, b },
+                BinaryOp::Sub => Instruction::FSub { dst, a, b },
+                BinaryOp::Mul => Instruction::FMul { dst, a, b },
+                BinaryOp::Div => Instruction::FDiv { dst, a, b },
+                _ => return Err(invalid(format!("invalid operands to binary {}", op), line)),
+            });
+            if ty == Ty::Float {
+                self.emit(Instruction::RoundF32 { dst, src: dst });
+            }
+            return Ok(Value { reg: dst, ty });
+        }
+        let signed = ty.is_signed();
+        let signed_op = match op {
+            BinaryOp::Add => Some(SignedOp::Add),
+            BinaryOp::Sub => Some(SignedOp::Sub),
+            BinaryOp::Mul => Some(SignedOp::Mul),
+            BinaryOp::Div => Some(SignedOp::Div),
+            BinaryOp::Rem => Some(SignedOp::Rem),
+            _ => None,
+        };
+        match (signed_op, signed) {
+            (Some(op), true) => {
+                self.emit(Instruction::Signed { op, dst, a, b, bits: ty.bits() });
+            }
+            _ => {
+                self.emit(match op {
+                    BinaryOp::Add => Instruction::Add { dst, a, b },
+                    BinaryOp::Sub => Instruction::Sub { dst, a, b },
+                    BinaryOp::Mul => Instruction::Mul { dst, a, b },
+                    BinaryOp::Div => Instruction::DivU { dst, a, b },
+                    BinaryOp::Rem => Instruction::RemU { dst, a, b },
+                    BinaryOp::BitAnd => Instruction::And { dst, a, b },
+                    BinaryOp::BitOr => Instruction::Or { dst, a, b },
+                    BinaryOp::BitXor => Instruction::Xor { dst, a, b },
+                    _ => return Err(invalid(format!("invalid operands to binary {}", op), line)),
+                });
+                // Bitwise results of extended operands are already extended
+                if matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul) {
+                    self.normalize(dst, &ty);
+                }
+            }
+        }
+        Ok(Value { reg: dst, ty })
+    }
+
+    fn comparison(&mut self, op: BinaryOp, a: Reg, b: Reg, ty: &Ty) -> Result<Value, BytecodeError> {
+        let dst = self.temp()?;
+        // `a > b` is `b < a`
+        let (a, b) = match op {
+            BinaryOp::Gt | BinaryOp::Ge => (b, a),
+            _ => (a, b),
+        };
+        let instruction = match (op, ty.is_float(), ty.is_signed()) {
+            (BinaryOp::Eq, true, _) => Instruction::FEq { dst, a, b },
+            (BinaryOp::Ne, true, _) => Instruction::FNe { dst, a, b },
+            (BinaryOp::Lt | BinaryOp::Gt, true, _) => Instruction::FLt { dst, a, b },
+            (_, true, _) => Instruction::FLe { dst, a, b },
+            (BinaryOp::Eq, false, _) => Instruction::Eq { dst, a, b },
+            (BinaryOp::Ne, false, _) => Instruction::Ne { dst, a, b },
+            (BinaryOp::Lt | BinaryOp::Gt, false, true) => Instruction::LtS { dst, a, b },
+            (BinaryOp::Lt | BinaryOp::Gt, false, false) => Instruction::LtU { dst, a, b },
+            (_, false, true) => Instruction::LeS { dst, a, b },
+            (_, false, false) => Instruction::LeU { dst, a, b },
+        };
+        self.emit(instruction);
+        Ok(Value { reg: dst, ty: INT })
+    }
+
+    /// `pointer ± index` elements
+    fn pointer_offset(&mut self, pointer: Value, index: Value, pointee: &Ty, subtract: bool, line: u32) -> Result<Value, BytecodeError> {
+        let stride = self.stride(pointee, line)?;
+        let index = self.convert(index, &LONG, line)?;
+        let scaled = match stride {
+            None => index.reg,
+            Some(factor) => {
+                let scaled = self.temp()?;
+                self.emit(Instruction::Mul { dst: scaled, a: index.reg, b: factor });
+                scaled
+            }
+        };
+        let dst = self.temp()?;
+        self.emit(if subtract {
+            Instruction::Sub { dst, a: pointer.reg, b: scaled }
+        } else {
+            Instruction::Add { dst, a: pointer.reg, b: scaled }
+        });
+        Ok(Value { reg: dst, ty: pointer.ty })
+    }
+
+    /// What a pointer to `pointee` steps by, in a register; `None` for a
+    /// byte. A pointer to a VLA steps by a size computed at run time.
+    fn stride(&mut self, pointee: &Ty, line: u32) -> Result<Option<Reg>, BytecodeError> {
+        if let Ty::VariableArray(..) = pointee {
+            return self.size_register(pointee, line).map(Some);
+        }
+        match self.element_size(pointee, line)? {
+            1 => Ok(None),
+            size => {
+                let factor = self.temp()?;
+                self.load_integer(factor, size as i64);
+                Ok(Some(factor))
+            }
+        }
+    }
+
+    /// Arithmetic on `void *` steps by bytes, as in GNU C
+    fn element_size(&self, pointee: &Ty, line: u32) -> Result<u32, BytecodeError> {
+        match pointee {
+            Ty::Void => Ok(1),
+            Ty::Function(_) => Err(invalid("arithmetic on a function pointer".to_string(), line)),
+            pointee => self.size(pointee, line),
+        }
+    }
+
+    /// Re-extend an integer register from its type's width
+    fn normalize(&mut self, reg: Reg, ty: &Ty) {
+        if ty.is_integer() && ty.bits() < 64 {
+            self.emit(Instruction::Extend { dst: reg, src: reg, bits: ty.bits(), signed: ty.is_signed() });
+        }
+    }
+
+    fn convert(&mut self, value: Value, to: &Ty, line: u32) -> Result<Value, BytecodeError> {
+        let from = value.ty.clone();
+        if from == *to {
+            return Ok(value);
+        }
+        let same = |ty: &Ty| Ok(Value { reg: value.reg, ty: ty.clone() });
+        match (&from, to) {
+            (_, Ty::Void) => same(to),
+            (from, Ty::Bool) if from.is_float() => {
+                let zero = self.temp()?;
+                let index = self.constant_index(0f64.to_bits());
+                self.emit(Instruction::Const { dst: zero, index });
+                let dst = self.temp()?;
+                self.emit(Instruction::FNe { dst, a: value.reg, b: zero });
+                Ok(Value { reg: dst, ty: Ty::Bool })
+            }
+            (from, Ty::Bool) if from.is_scalar() => {
+                let dst = self.temp()?;
+                self.emit(Instruction::Test { dst, src: value.reg });
+                Ok(Value { reg: dst, ty: Ty::Bool })
+            }
+            (Ty::Float, Ty::Double) => same(to),
+            (Ty::Double, Ty::Float) => {
+                let dst = self.temp()?;
+                self.emit(Instruction::RoundF32 { dst, src: value.reg });
+                Ok(Value { reg: dst, ty: Ty::Float })
+            }
+            (from, Ty::Float | Ty::Double) if from.is_integer() => {
+                let dst = self.temp()?;
+                self.emit(Instruction::IntToFloat { dst, src: value.reg, signed: from.is_signed() });
+                if *to == Ty::Float {
+                    self.emit(Instruction::RoundF32 { dst, src: dst });
+                }
+                Ok(Value { reg: dst, ty: to.clone() })
+            }
+            (Ty::Float | Ty::Double, to) if to.is_integer() || to.is_pointer() => {
+                let dst = self.temp()?;
+                self.emit(Instruction::FloatToInt { dst, src: value.reg, signed: to.is_signed() });
+                self.normalize(dst, to);
+                Ok(Value { reg: dst, ty: to.clone() })
+            }
+            (from, to) if (from.is_integer() || from.is_pointer()) && (to.is_integer() || to.is_pointer()) => {
+                // Already extended right for the wider type?
+                let (from_bits, to_bits) = (from.bits(), to.bits());
+                let fits = to_bits == 64
+                    || (to_bits > from_bits && (!from.is_signed() || to.is_signed()))
+                    || (to_bits == from_bits && from.is_signed() == to.is_signed());
+                if fits {
+                    return same(to);
+                }
+                let dst = self.temp()?;
+                self.emit(Instruction::Extend { dst, src: value.reg, bits: to_bits, signed: to.is_signed() });
+                Ok(Value { reg: dst, ty: to.clone() })
+            }
+            _ => Err(invalid("incompatible types in conversion".to_string(), line)),
+        }
+    }
+
+    fn call(&mut self, function: &Expression, arguments: &[Expression], line: u32) -> Result<Value, BytecodeError> {
+        if let Some(value) = self.promoted_call(function, arguments)? {
+            return Ok(value);
+        }
+        let direct = match &function.kind {
+            ExpressionKind::Identifier(name) if name == PROBE_BUILTIN => return self.probe(arguments, line),
+            ExpressionKind::Identifier(name) if UNSUPPORTED_CALLS.contains(&name.as_str()) => {
+                return Err(unsupported(&format!("calls to {}", name), line));
+            }
+            ExpressionKind::Identifier(name) => match self.lookup(name).cloned() {
+                Some(Binding::Function { name, ty, internal }) => Some((name, ty, internal)),
+                Some(_) => None,
+                None => return Err(BytecodeError::Undeclared { name: name.clone(), line }),
+            },
+            _ => None,
+        };
+        let (callee, ty) = match &direct {
+            Some((_, ty, _)) => (None, ty.clone()),
+            None => {
+                let callee = self.rvalue(function)?;
+                match callee.ty.pointee() {
+                    Some(Ty::Function(ty)) => (Some(callee.reg), ty.clone()),
+                    _ => return Err(invalid("called object is not a function".to_string(), line)),
+                }
+            }
+        };
+        if ty.prototyped && (arguments.len() < ty.parameters.len() || (!ty.variadic && arguments.len() > ty.parameters.len())) {
+            return Err(invalid(format!("expected {} argument(s), got {}", ty.parameters.len(), arguments.len()), line));
+        }
+        let count = u8::try_from(arguments.len()).map_err(|_| invalid("too many arguments".to_string(), line))?;
+
+        let mut values = Vec::with_capacity(arguments.len());
+        for (index, argument) in arguments.iter().enumerate() {
+            let value = self.rvalue(argument)?;
+            let target = match ty.parameters.get(index) {
+                Some(parameter) if ty.prototyped => parameter.clone(),
+                // Default argument promotions
+                _ if value.ty == Ty::Float => Ty::Double,
+                _ => value.ty.promoted(),
+            };
+            if target.kind().is_none() {
+                return Err(unsupported("structs passed by value", line));
+            }
+            values.push(self.convert(value, &target, line)?);
+        }
+        let first = self.temps(values.len())?;
+        for (index, value) in values.iter().enumerate() {
+            self.emit(Instruction::Move { dst: first + index as Reg, src: value.reg });
+        }
+        let dst = self.temp()?;
+        if ty.result != Ty::Void && ty.result.kind().is_none() {
+            return Err(unsupported("structs returned by value", line));
+        }
+
+        // Native callees need each argument's kind
+        let call_signature = |compiler: &mut Self| {
+            let signature = Signature {
+                parameters: values.iter().map(|value| value.ty.kind().expect("scalar argument")).collect(),
+                variadic: (ty.variadic || !ty.prototyped).then_some(ty.parameters.len() as u16),
+                result: ty.result.kind(),
+            };
+            let signatures = &mut compiler.program.signatures;
+            match signatures.iter().position(|existing| *existing == signature) {
+                Some(index) => index as u32,
+                None => {
+                    signatures.push(signature);
+                    signatures.len() as u32 - 1
+                }
+            }
+        };
+        match (direct, callee) {
+            (Some((_, _, Some(function))), _) => {
+                self.emit(Instruction::Call { dst, function, arguments: first, count });
+            }
+            (Some((name, ty, None)), _) => {
+                let declared = self.signature(&ty, line)?;
+                let external = self.external(&name, declared);
+                let signature = call_signature(self);
+                self.emit(Instruction::CallExternal { dst, external, arguments: first, count, signature });
+            }
+            (None, Some(callee)) => {
+                let signature = call_signature(self);
+                self.emit(Instruction::CallIndirect { dst, callee, arguments: first, count, signature });
+            }
+            (None, None) => unreachable!("either a named function or a callee value"),
+        }
+        Ok(Value { reg: dst, ty: ty.result.clone() })
+    }
+
+    /// A `malloc` or `calloc` that `escape` promoted, as frame memory, and
+    /// a `free` of one, as nothing; `None` for every other call
+    fn promoted_call(&mut self, function: &Expression, arguments: &[Expression]) -> Result<Option<Value>, BytecodeError> {
+        let call = function as *const Expression;
+        if self.state().heap.elided.contains(&call) {
+            let dst = self.temp()?;
+            return Ok(Some(Value { reg: dst, ty: Ty::Void }));
+        }
+        if !self.state().heap.allocations.contains_key(&call) {
+            return Ok(None);
+        }
+        // The C library's allocator, not one the program defines
+        if ["malloc", "calloc", "free"].iter().any(|name| self.defined_functions.contains_key(*name)) {
+            return Ok(None);
+        }
+        let size = arguments.iter().try_fold(1u64, |size, argument| {
+            let count = u64::try_from(self.integer_constant(argument).ok()?).ok()?;
+            size.checked_mul(count)
+        });
+        let Some(size) = size.filter(|&size| self.state().heap.fits(size)) else { return Ok(None) };
+        self.state().heap.promote(call, size);
+
+        // Aligned as malloc's memory is, for any type
+        let state = self.state();
+        let offset = state.frame.next_multiple_of(16);
+        state.frame = offset.checked_add(size as u32).ok_or_else(|| BytecodeError::TooLarge { function: state.name.clone() })?;
+        let dst = self.temp()?;
+        self.emit(Instruction::LocalAddress { dst, offset });
+        if arguments.len() == 2 {
+            self.emit(Instruction::ZeroBytes { dst, size: size as u32 });
+        }
+        Ok(Some(Value { reg: dst, ty: Ty::Pointer(Box::new(Ty::Void)) }))
+    }
+
+    /// Holds no array, so no part of it decays to a pointer into it
+    fn flat(&self, ty: &Ty) -> bool {
+        match ty {
+            Ty::Array(..) | Ty::VariableArray(..) | Ty::Function(_) => false,
+            Ty::Record(index) => {
+                let record = &self.records[*index];
+                record.complete && record.fields.iter().all(|field| self.flat(&field.ty))
+            }
+            _ => true,
+        }
+    }
+
+    /// `__builtin_probe(site, arguments...)`, from `frontend::usdt`
+    fn probe(&mut self, arguments: &[Expression], line: u32) -> Result<Value, BytecodeError> {
+        let Some((site, arguments)) = arguments.split_first() else {
+            return Err(invalid(format!("{} needs a site number", PROBE_BUILTIN), line));
+        };
+        let site = self.integer_constant(site)? as u32;
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            let value = self.rvalue(argument)?;
+            // Probe arguments are 64-bit integers; doubles as their bits
+            let value = if value.ty.is_float() { self.convert(value, &Ty::Double, line)? } else { self.convert(value, &LONG, line)? };
+            values.push(value);
+        }
+        let first = self.temps(values.len())?;
+        for (index, value) in values.iter().enumerate() {
+            self.emit(Instruction::Move { dst: first + index as Reg, src: value.reg });
+        }
+        self.emit(Instruction::Probe { site, arguments: first, count: values.len() as u8 });
+        Ok(Value { reg: first, ty: Ty::Void })
+    }
+
+    /// A GNU `asm` statement, for the native tier: the register operands in
+    /// consecutive registers around an `Asm` instruction, outputs first
+    fn asm(&mut self, statement: &AsmStatement, outputs: &[Expression], inputs: &[Expression], line: u32) -> Result<(), BytecodeError> {
+        let resolved = arch_asm::resolve(statement, &x86_64::create_support()).map_err(|error| invalid(error.to_string(), line))?;
+
+        let mut places = Vec::with_capacity(outputs.len());
+        let mut operands = Vec::with_capacity(outputs.len() + inputs.len());
+        for output in outputs {
+            let place = self.place(output)?;
+            let ty = place.ty().clone();
+            if !ty.is_integer() && !ty.is_pointer() {
+                return Err(unsupported("asm outputs that aren't integers or pointers", line));
+            }
+            operands.push(inline_asm::Operand::Register { bytes: self.size(&ty, line)? });
+            places.push(place);
+        }
+        let mut values = Vec::with_capacity(inputs.len());
+        for (input, resolved) in inputs.iter().zip(&resolved.inputs) {
+            if resolved.kind == ConstraintKind::Immediate {
+                operands.push(inline_asm::Operand::Immediate(self.integer_constant(input)?));
+                values.push(None);
+                continue;
+            }
+            let value = self.rvalue(input)?;
+            if !value.ty.is_integer() && !value.ty.is_pointer() {
+                return Err(unsupported("asm inputs that aren't integers or pointers", line));
+            }
+            operands.push(inline_asm::Operand::Register { bytes: self.size(&value.ty, line)? });
+            values.push(Some(value));
+        }
+
+        let index = self.program.asm.len() as u32;
+        let block = inline_asm::lower(statement, &resolved, &operands, index).map_err(|error| match error {
+            LowerError::Unsupported(what) => unsupported(&what, line),
+            LowerError::Invalid(message) => invalid(message, line),
+        })?;
+        let count = block.registers.len();
+        let first = self.temps(count)?;
+
+        // An output starts with its old value (`+`) or the input tied to it
+        for (i, place) in places.iter().enumerate() {
+            let tied = resolved.inputs.iter().position(|input| input.kind == ConstraintKind::Tied(i));
+            let value = match tied {
+                Some(input) => values[input].clone(),
+                None if resolved.outputs[i].read_write => Some(self.load(Operand::Place(place.clone()))?),
+                None => None,
+            };
+            if let Some(value) = value {
+                self.emit(Instruction::Move { dst: first + i as Reg, src: value.reg });
+            }
+        }
+        let untied = resolved.inputs.iter().zip(&values).filter(|(input, _)| !matches!(input.kind, ConstraintKind::Tied(_)));
+        for (slot, value) in untied.filter_map(|(_, value)| value.as_ref()).enumerate() {
+            self.emit(Instruction::Move { dst: first + (places.len() + slot) as Reg, src: value.reg });
+        }
+
+        self.program.asm.push(block);
+        self.emit(Instruction::Asm { index, operands: first, count: count as u8 });
+        for (i, place) in places.iter().enumerate() {
+            let ty = place.ty().clone();
+            let reg = first + i as Reg;
+            self.normalize(reg, &ty);
+            self.store(place, Value { reg, ty }, line)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: String, line: u32) -> BytecodeError {
+    BytecodeError::Invalid { message, line }
+}
+
+fn type_error(error: TypeError, line: u32) -> BytecodeError {
+    invalid(error.to_string(), line)
+}
+
+fn unsupported(what: &str, line: u32) -> BytecodeError {
+    BytecodeError::Unsupported { what: what.to_string(), line }
+}
+
+fn set_target(instruction: &mut Instruction, to: u32) {
+    match instruction {
+        Instruction::Jump { target } | Instruction::JumpIfZero { target, .. } | Instruction::JumpIfNonZero { target, .. } => *target = to,
+        other => unreachable!("patching {:?}, which doesn't jump", other),
+    }
+}
+
+/// The type of an integer literal: the first of its suffix's candidates
+/// that holds the value
+fn integer_literal_type(value: u64, suffix: IntegerSuffix) -> Ty {
+    let unsigned = matches!(suffix, IntegerSuffix::Unsigned | IntegerSuffix::UnsignedLong | IntegerSuffix::UnsignedLongLong);
+    let at_least_long = !matches!(suffix, IntegerSuffix::None | IntegerSuffix::Unsigned);
+    match (unsigned, at_least_long) {
+        (false, false) if value <= i32::MAX as u64 => INT,
+        (true, false) if value <= u32::MAX as u64 => Ty::Int { bytes: 4, signed: false },
+        (false, _) if value <= i64::MAX as u64 => LONG,
+        _ => UNSIGNED_LONG,
+    }
+}
+
+/// `value` converted to the integer type `ty`
+fn wrap_to(value: i64, ty: &Ty) -> i64 {
+    match ty {
+        Ty::Bool => (value != 0) as i64,
+        Ty::Int { bytes, signed } if *bytes < 8 => {
+            let shift = 64 - *bytes as u32 * 8;
+            if *signed { (value << shift) >> shift } else { ((value as u64) << shift >> shift) as i64 }
+        }
+        _ => value,
+    }
+}
+
+fn encode_float(value: f64, kind: Kind) -> Vec<u8> {
+    match kind {
+        Kind::F32 => (value as f32).to_le_bytes().to_vec(),
+        _ => value.to_le_bytes().to_vec(),
+    }
+}
+
+/// The usual arithmetic conversions
+fn arithmetic_type(a: &Ty, b: &Ty) -> Ty {
+    if *a == Ty::Double || *b == Ty::Double {
+        return Ty::Double;
+    }
+    if *a == Ty::Float || *b == Ty::Float {
+        return Ty::Float;
+    }
+    let (a, b) = (a.promoted(), b.promoted());
+    match (&a, &b) {
+        (Ty::Int { bytes: a_bytes, signed: a_signed }, Ty::Int { bytes: b_bytes, signed: b_signed }) => {
+            if a_signed == b_signed {
+                if a_bytes >= b_bytes { a } else { b }
+            } else {
+                let (signed, unsigned) = if *a_signed { (&a, &b) } else { (&b, &a) };
+                // LP64: a wider signed type holds every value of the unsigned one
+                if signed.bits() > unsigned.bits() { signed.clone() } else { unsigned.clone() }
+            }
+        }
+        _ => a,
+    }
+}
+
+fn constant_binary(op: BinaryOp, left: Constant, right: Constant) -> Option<Constant> {
+    let truth = |constant: &Constant| match constant {
+        Constant::Int(value, _) => *value != 0,
+        Constant::Float(value, _) => *value != 0.0,
+        Constant::Address { .. } => true,
+    };
+    match op {
+        BinaryOp::LogicalAnd => return Some(Constant::Int((truth(&left) && truth(&right)) as i64, INT)),
+        BinaryOp::LogicalOr => return Some(Constant::Int((truth(&left) || truth(&right)) as i64, INT)),
+        _ => {}
+    }
+    match (left, right) {
+        (Constant::Int(a, a_ty), Constant::Int(b, b_ty)) => {
+            let ty = if matches!(op, BinaryOp::Shl | BinaryOp::Shr) { a_ty.promoted() } else { arithmetic_type(&a_ty, &b_ty) };
+            let (a, b) = (wrap_to(a, &ty), wrap_to(b, &ty));
+            let signed = ty.is_signed();
+            let compare = |ordering: std::cmp::Ordering| {
+                Some(Constant::Int(
+                    match op {
+                        BinaryOp::Eq => ordering.is_eq(),
+                        BinaryOp::Ne => ordering.is_ne(),
+                        BinaryOp::Lt => ordering.is_lt(),
+                        BinaryOp::Le => ordering.is_le(),
+                        BinaryOp::Gt => ordering.is_gt(),
+                        _ => ordering.is_ge(),
+                    } as i64,
+                    INT,
+                ))
+            };
+            if op.is_comparison() {
+                return compare(if signed { a.cmp(&b) } else { (a as u64).cmp(&(b as u64)) });
+            }
+            let value = match op {
+                BinaryOp::Add => a.wrapping_add(b),
+                BinaryOp::Sub => a.wrapping_sub(b),
+                BinaryOp::Mul => a.wrapping_mul(b),
+                BinaryOp::Div if b == 0 => return None,
+                BinaryOp::Div if signed => a.wrapping_div(b),
+                BinaryOp::Div => ((a as u64) / (b as u64)) as i64,
+                BinaryOp::Rem if b == 0 => return None,
+                BinaryOp::Rem if signed => a.wrapping_rem(b),
+                BinaryOp::Rem => ((a as u64) % (b as u64)) as i64,
+                BinaryOp::Shl => a.wrapping_shl(b as u32),
+                BinaryOp::Shr if signed => a.wrapping_shr(b as u32),
+                BinaryOp::Shr => (a as u64).wrapping_shr(b as u32) as i64,
+                BinaryOp::BitAnd => a & b,
+                BinaryOp::BitOr => a | b,
+                BinaryOp::BitXor => a ^ b,
+                _ => return None,
+            };
+            Some(Constant::Int(wrap_to(value, &ty), ty))
+        }
+        (Constant::Address { .. }, _) | (_, Constant::Address { .. }) => None,
+        (left, right) => {
+            let float = |constant: Constant| match constant {
+                Constant::Int(value, ty) if ty.is_signed() => (value as f64, ty),
+                Constant::Int(value, ty) => (value as u64 as f64, ty),
+                Constant::Float(value, ty) => (value, ty),
+                Constant::Address { .. } => unreachable!("handled above"),
+            };
+            let ((a, a_ty), (b, b_ty)) = (float(left), float(right));
+            let ty = arithmetic_type(&a_ty, &b_ty);
+            let value = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Eq => return Some(Constant::Int((a == b) as i64, INT)),
+                BinaryOp::Ne => return Some(Constant::Int((a != b) as i64, INT)),
+                BinaryOp::Lt => return Some(Constant::Int((a < b) as i64, INT)),
+                BinaryOp::Le => return Some(Constant::Int((a <= b) as i64, INT)),
+                BinaryOp::Gt => return Some(Constant::Int((a > b) as i64, INT)),
+                BinaryOp::Ge => return Some(Constant::Int((a >= b) as i64, INT)),
+                _ => return None,
+            };
+            Some(Constant::Float(if ty == Ty::Float { value as f32 as f64 } else { value }, ty))
+        }
+    }
+}
+
+// Which locals need frame memory: every name whose address is taken. A
+// shadowing variable of the same name gets memory too, which is harmless.
+
+fn collect_address_taken_block(block: &Block, names: &mut HashSet<String>) {
+    for item in &block.items {
+        match item {
+            BlockItem::Declaration(declaration) => collect_address_taken_declaration(declaration, names),
+            BlockItem::Statement(statement) => collect_address_taken_statement(statement, names),
+        }
+    }
+}
+
+fn collect_address_taken_declaration(declaration: &Declaration, names: &mut HashSet<String>) {
+    fn initializer(init: &Initializer, names: &mut HashSet<String>) {
+        match init {
+            Initializer::Expression(expression) => collect_address_taken_expression(expression, names),
+            Initializer::List(items) => items.iter().for_each(|(_, item)| initializer(item, names)),
+        }
+    }
+    for declarator in &declaration.declarators {
+        if let Some(init) = &declarator.initializer {
+            initializer(init, names);
+        }
+    }
+}
+
+fn collect_address_taken_statement(statement: &Statement, names: &mut HashSet<String>) {
+    let expression = |expression: &Expression, names: &mut HashSet<String>| collect_address_taken_expression(expression, names);
+    match &statement.kind {
+        StatementKind::Expression(Some(e)) | StatementKind::Return(Some(e)) => expression(e, names),
+        StatementKind::Expression(None) | StatementKind::Return(None) => {}
+        StatementKind::Compound(block) => collect_address_taken_block(block, names),
+        StatementKind::If { condition, then, otherwise } => {
+            expression(condition, names);
+            collect_address_taken_statement(then, names);
+            if let Some(otherwise) = otherwise {
+                collect_address_taken_statement(otherwise, names);
+            }
+        }
+        StatementKind::While { condition, body } | StatementKind::DoWhile { body, condition } => {
+            expression(condition, names);
+            collect_address_taken_statement(body, names);
+        }
+        StatementKind::For { init, condition, step, body } => {
+            match init {
+                Some(ForInit::Declaration(declaration)) => collect_address_taken_declaration(declaration, names),
+                Some(ForInit::Expression(e)) => expression(e, names),
+                None => {}
+            }
+            for e in condition.iter().chain(step) {
+                expression(e, names);
+            }
+            collect_address_taken_statement(body, names);
+        }
+        StatementKind::Switch { value, body } | StatementKind::Case { value, body } => {
+            expression(value, names);
+            collect_address_taken_statement(body, names);
+        }
+        StatementKind::Default(body) | StatementKind::Labeled { body, .. } => collect_address_taken_statement(body, names),
+        StatementKind::Goto(_) | StatementKind::Break | StatementKind::Continue => {}
+        StatementKind::Asm { outputs, inputs, .. } => outputs.iter().chain(inputs).for_each(|e| expression(e, names)),
+    }
+}
+
+fn collect_address_taken_expression(expression: &Expression, names: &mut HashSet<String>) {
+    match &expression.kind {
+        ExpressionKind::Unary { op: UnaryOp::AddressOf, operand } => {
+            if let ExpressionKind::Identifier(name) = &operand.kind {
+                names.insert(name.clone());
+            }
+            collect_address_taken_expression(operand, names);
+        }
+        ExpressionKind::Unary { operand, .. }
+        | ExpressionKind::Cast { operand, .. }
+        | ExpressionKind::SizeofExpression(operand) => collect_address_taken_expression(operand, names),
+        ExpressionKind::Binary { left, right, .. }
+        | ExpressionKind::Assign { target: left, value: right, .. }
+        | ExpressionKind::Index { array: left, index: right }
+        | ExpressionKind::Comma(left, right) => {
+            collect_address_taken_expression(left, names);
+            collect_address_taken_expression(right, names);
+        }
+        ExpressionKind::Conditional { condition, then, otherwise } => {
+            for e in [condition, then, otherwise] {
+                collect_address_taken_expression(e, names);
+            }
+        }
+        ExpressionKind::Call { function, arguments } => {
+            collect_address_taken_expression(function, names);
+            arguments.iter().for_each(|argument| collect_address_taken_expression(argument, names));
+        }
+        ExpressionKind::Member { base, .. } => collect_address_taken_expression(base, names),
+        ExpressionKind::Generic { associations, .. } => {
+            associations.iter().for_each(|(_, value)| collect_address_taken_expression(value, names));
+        }
+        ExpressionKind::Integer { .. }
+        | ExpressionKind::Float { .. }
+        | ExpressionKind::Character(_)
+        | ExpressionKind::String(_)
+        | ExpressionKind::Identifier(_)
+        | ExpressionKind::SizeofType(_)
+        | ExpressionKind::AlignofType(_) => {}
+    }
+}
+
+// Example usage:
+/*
+fn disassemble(unit: &TranslationUnit) -> Result<String, BytecodeError> {
+    let program = compile(unit)?;
+    Ok(program.disassemble())
+}
+*/
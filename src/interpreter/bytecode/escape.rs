@@ -0,0 +1,419 @@
+// src/interpreter/bytecode/escape.rs
+//! Escape analysis for the bytecode compiler
+//! Finds the `malloc` and `calloc` calls whose object never outlives the
+//! function that allocates it, so `compiler` can hand out frame memory
+//! instead and drop the `free`s. The analysis runs on the syntax tree,
+//! before lowering, because registers are reused once code exists.
+//!
+//! An allocation qualifies when its result goes straight into a local
+//! pointer variable, either as the variable's initializer or as the one
+//! assignment to it (an initializer of 0 aside), outside any loop, and
+//! everything else the function does with the variable keeps the pointer
+//! to itself: `*p`, `p[i]`, `p->f`, `free(p)`, a truth test or a
+//! comparison, and `memset`, `memcpy` or `memmove` of its bytes in a
+//! statement of their own. Passing it anywhere else, storing or returning
+//! it, taking its address, stepping it or copying it into another variable
+//! lets it escape. Functions with `goto` are left alone, since a jump back
+//! could run the allocation twice in one frame.
+//!
+//! The rest is checked by `compiler`, where types and enumerators are
+//! resolved: the variable must point to a scalar or a struct without
+//! arrays, since `p->a` or `p[i]` of an array is itself a pointer into the
+//! object, and when the call is reached its size must be a constant within
+//! `MAX_PROMOTED`, and all the function's promotions within
+//! `MAX_PROMOTED_FRAME`. Promoted objects don't count towards the
+//! interpreter's heap limit.
+
+use std::collections::{HashMap, HashSet};
+use crate::frontend::ast::{
+    BinaryOp, Block, BlockItem, Declaration, Designator, Expression, ExpressionKind, ForInit, FunctionDefinition, Initializer,
+    Statement, StatementKind, StorageClass, UnaryOp,
+};
+
+/// Largest object moved to the frame, in bytes
+pub const MAX_PROMOTED: u64 = 1024;
+/// Bytes of promoted objects per function
+pub const MAX_PROMOTED_FRAME: u64 = 4096;
+
+/// What `analyze` found, keyed by the callee expression of each call
+#[derive(Debug, Default)]
+pub struct HeapPromotions {
+    /// Each allocation that may be promoted
+    pub allocations: HashMap<*const Expression, Candidate>,
+    /// `free`s of objects that were promoted, filled in by `compiler`
+    pub elided: HashSet<*const Expression>,
+    /// Bytes promoted so far
+    pub bytes: u64,
+}
+
+/// An allocation whose object doesn't escape
+#[derive(Debug)]
+pub struct Candidate {
+    /// The variable holding it
+    pub variable: String,
+    /// The `free`s of the object
+    pub frees: Vec<*const Expression>,
+}
+
+impl HeapPromotions {
+    /// Whether an allocation stored in `variable` is a candidate
+    pub fn holds(&self, variable: &str) -> bool {
+        self.allocations.values().any(|candidate| candidate.variable == variable)
+    }
+
+    /// Leave `variable`'s allocation on the heap
+    pub fn reject(&mut self, variable: &str) {
+        self.allocations.retain(|_, candidate| candidate.variable != variable);
+    }
+
+    /// Whether `size` more bytes fit in the frame
+    pub fn fits(&self, size: u64) -> bool {
+        size > 0 && size <= MAX_PROMOTED && self.bytes + size <= MAX_PROMOTED_FRAME
+    }
+
+    /// Promote the allocation `call` of `size` bytes; its `free`s are
+    /// elided from now on
+    pub fn promote(&mut self, call: *const Expression, size: u64) {
+        if let Some(candidate) = self.allocations.remove(&call) {
+            self.elided.extend(candidate.frees);
+            self.bytes += size;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Variable {
+    declarations: usize,
+    /// The allocation's callee, once assigned
+    allocation: Option<*const Expression>,
+    /// Plain assignments other than the allocation and a null initializer
+    assignments: usize,
+    frees: Vec<*const Expression>,
+    escapes: bool,
+}
+
+#[derive(Default)]
+struct Analysis {
+    variables: HashMap<String, Variable>,
+    scopes: Vec<HashSet<String>>,
+    loop_depth: usize,
+    jumps: bool,
+}
+
+/// The allocations of `definition` that never escape it
+pub fn analyze(definition: &FunctionDefinition) -> HeapPromotions {
+    let mut analysis = Analysis::default();
+    let parameters: HashSet<String> = definition.parameters.iter().filter_map(|parameter| parameter.name.clone()).collect();
+    for name in &parameters {
+        analysis.variables.entry(name.clone()).or_default().escapes = true;
+    }
+    analysis.scopes.push(parameters);
+    analysis.block(&definition.body);
+
+    let mut promotions = HeapPromotions::default();
+    if analysis.jumps {
+        return promotions;
+    }
+    // A local named like the allocator shadows it
+    if ["malloc", "calloc", "free"].iter().any(|name| analysis.variables.get(*name).is_some_and(|variable| variable.declarations > 0)) {
+        return promotions;
+    }
+    for (name, variable) in analysis.variables {
+        if let Some(allocation) = variable.allocation {
+            if variable.declarations == 1 && variable.assignments == 0 && !variable.escapes {
+                promotions.allocations.insert(allocation, Candidate { variable: name, frees: variable.frees });
+            }
+        }
+    }
+    promotions
+}
+
+/// `malloc(n)` or `calloc(n, size)`, under any casts; the callee expression
+fn allocation(expression: &Expression) -> Option<*const Expression> {
+    match &expression.kind {
+        ExpressionKind::Cast { operand, .. } => allocation(operand),
+        ExpressionKind::Call { function, arguments } => match (&function.kind, arguments.len()) {
+            (ExpressionKind::Identifier(name), 1) if name == "malloc" => Some(&**function as *const Expression),
+            (ExpressionKind::Identifier(name), 2) if name == "calloc" => Some(&**function as *const Expression),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_null(expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Integer { value, .. } => *value == 0,
+        ExpressionKind::Cast { operand, .. } => is_null(operand),
+        _ => false,
+    }
+}
+
+fn identifier(expression: &Expression) -> Option<&str> {
+    match &expression.kind {
+        ExpressionKind::Identifier(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// The variable `&` of `expression` points into: `&p[i]`, `&p->f`, `&*p`
+fn address_base(expression: &Expression) -> Option<&str> {
+    match &expression.kind {
+        ExpressionKind::Identifier(name) => Some(name),
+        ExpressionKind::Unary { op: UnaryOp::Deref, operand } => address_base(operand),
+        ExpressionKind::Index { array: base, .. } | ExpressionKind::Member { base, .. } => address_base(base),
+        _ => None,
+    }
+}
+
+impl Analysis {
+    /// A use of `name`; outside the scope of its declaration, the name is
+    /// another variable's
+    fn variable(&mut self, name: &str) -> &mut Variable {
+        let visible = self.scopes.iter().any(|scope| scope.contains(name));
+        let variable = self.variables.entry(name.to_string()).or_default();
+        if !visible {
+            variable.escapes = true;
+        }
+        variable
+    }
+
+    /// An allocation stored into `name`; only the first one outside a loop
+    /// can be promoted
+    fn define(&mut self, name: &str, call: *const Expression) {
+        let in_loop = self.loop_depth > 0;
+        let variable = self.variable(name);
+        if in_loop || variable.allocation.is_some() {
+            variable.escapes = true;
+        } else {
+            variable.allocation = Some(call);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(HashSet::new());
+        for item in &block.items {
+            match item {
+                BlockItem::Declaration(declaration) => self.declaration(declaration),
+                BlockItem::Statement(statement) => self.statement(statement),
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        for declarator in &declaration.declarators {
+            self.scopes.last_mut().expect("function scope").insert(declarator.name.clone());
+            let variable = self.variables.entry(declarator.name.clone()).or_default();
+            variable.declarations += 1;
+            // Only automatic variables go away with the frame
+            if !matches!(declaration.storage, None | Some(StorageClass::Auto | StorageClass::Register)) {
+                variable.escapes = true;
+            }
+            match &declarator.initializer {
+                Some(Initializer::Expression(expression)) => match allocation(expression) {
+                    Some(call) => {
+                        self.define(&declarator.name, call);
+                        self.arguments(expression);
+                    }
+                    None if is_null(expression) => {}
+                    None => {
+                        self.variable(&declarator.name).assignments += 1;
+                        self.expression(expression, false);
+                    }
+                },
+                Some(Initializer::List(items)) => {
+                    self.variable(&declarator.name).escapes = true;
+                    self.initializer_list(items);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn initializer_list(&mut self, items: &[(Option<Designator>, Initializer)]) {
+        for (_, item) in items {
+            match item {
+                Initializer::Expression(expression) => self.expression(expression, false),
+                Initializer::List(items) => self.initializer_list(items),
+            }
+        }
+    }
+
+    /// The size arguments of an allocation
+    fn arguments(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Cast { operand, .. } => self.arguments(operand),
+            ExpressionKind::Call { arguments, .. } => arguments.iter().for_each(|argument| self.expression(argument, false)),
+            _ => {}
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Expression(Some(e)) => self.expression(e, true),
+            StatementKind::Return(Some(e)) => self.expression(e, false),
+            StatementKind::Expression(None) | StatementKind::Return(None) => {}
+            StatementKind::Compound(block) => self.block(block),
+            StatementKind::If { condition, then, otherwise } => {
+                self.condition(condition);
+                self.statement(then);
+                if let Some(otherwise) = otherwise {
+                    self.statement(otherwise);
+                }
+            }
+            StatementKind::While { condition, body } | StatementKind::DoWhile { body, condition } => {
+                self.loop_depth += 1;
+                self.condition(condition);
+                self.statement(body);
+                self.loop_depth -= 1;
+            }
+            StatementKind::For { init, condition, step, body } => {
+                self.scopes.push(HashSet::new());
+                match init {
+                    Some(ForInit::Declaration(declaration)) => self.declaration(declaration),
+                    Some(ForInit::Expression(e)) => self.expression(e, true),
+                    None => {}
+                }
+                self.loop_depth += 1;
+                if let Some(condition) = condition {
+                    self.condition(condition);
+                }
+                if let Some(step) = step {
+                    self.expression(step, true);
+                }
+                self.statement(body);
+                self.loop_depth -= 1;
+                self.scopes.pop();
+            }
+            StatementKind::Switch { value, body } | StatementKind::Case { value, body } => {
+                self.expression(value, false);
+                self.statement(body);
+            }
+            StatementKind::Default(body) | StatementKind::Labeled { body, .. } => self.statement(body),
+            StatementKind::Goto(_) => self.jumps = true,
+            StatementKind::Break | StatementKind::Continue => {}
+            // Whatever the template does with them, the operands escape
+            StatementKind::Asm { statement, outputs, inputs } => {
+                self.jumps |= statement.qualifiers.goto;
+                for operand in outputs.iter().chain(inputs) {
+                    self.expression(operand, false);
+                }
+            }
+        }
+    }
+
+    /// An expression only tested for truth: a bare pointer is fine
+    fn condition(&mut self, expression: &Expression) {
+        if identifier(expression).is_none() {
+            self.expression(expression, true);
+        }
+    }
+
+    /// Walk `expression`, whose value is only tested or thrown away when
+    /// `discarded`; the candidate variables it lets escape are marked
+    fn expression(&mut self, expression: &Expression, discarded: bool) {
+        match &expression.kind {
+            // The uses that keep the pointer to themselves
+            ExpressionKind::Unary { op: UnaryOp::Deref | UnaryOp::Not, operand } if identifier(operand).is_some() => {}
+            ExpressionKind::Index { array, index } if identifier(array).is_some() => self.expression(index, false),
+            ExpressionKind::Member { base, arrow: true, .. } if identifier(base).is_some() => {}
+            ExpressionKind::Unary { op: UnaryOp::AddressOf, operand } => {
+                if let Some(name) = address_base(operand) {
+                    self.variable(name).escapes = true;
+                }
+                self.expression(operand, false);
+            }
+            ExpressionKind::Unary { op: UnaryOp::Not, operand } => self.condition(operand),
+            ExpressionKind::Binary { op: BinaryOp::Eq | BinaryOp::Ne, left, right } => {
+                self.condition(left);
+                self.condition(right);
+            }
+            ExpressionKind::Binary { op: BinaryOp::LogicalAnd | BinaryOp::LogicalOr, left, right } => {
+                self.condition(left);
+                self.condition(right);
+            }
+            ExpressionKind::Conditional { condition, then, otherwise } => {
+                self.condition(condition);
+                self.expression(then, discarded);
+                self.expression(otherwise, discarded);
+            }
+            ExpressionKind::Comma(left, right) => {
+                self.expression(left, true);
+                self.expression(right, discarded);
+            }
+            ExpressionKind::Assign { op, target, value } => {
+                match (identifier(target), op) {
+                    (Some(name), None) => match allocation(value) {
+                        // `q = p = malloc(n)` hands the pointer on
+                        Some(call) if discarded => {
+                            self.define(name, call);
+                            self.arguments(value);
+                            return;
+                        }
+                        _ => self.variable(name).assignments += 1,
+                    },
+                    (Some(name), Some(_)) => self.variable(name).escapes = true,
+                    (None, _) => self.expression(target, false),
+                }
+                self.expression(value, false);
+            }
+            ExpressionKind::Call { function, arguments } => {
+                let callee = identifier(function);
+                if let (Some("free"), [argument]) = (callee, arguments.as_slice()) {
+                    if let Some(name) = identifier(argument) {
+                        self.variable(name).frees.push(&**function as *const Expression);
+                        return;
+                    }
+                }
+                // These return their destination, so only when it's thrown away
+                let pointers = match callee {
+                    Some("memset") if discarded => 1,
+                    Some("memcpy" | "memmove") if discarded => 2,
+                    _ => 0,
+                };
+                for (index, argument) in arguments.iter().enumerate() {
+                    if index < pointers && identifier(argument).is_some() {
+                        continue;
+                    }
+                    self.expression(argument, false);
+                }
+                self.expression(function, false);
+            }
+            ExpressionKind::Identifier(name) => self.variable(name).escapes = true,
+            ExpressionKind::Unary { operand, .. } | ExpressionKind::Cast { operand, .. } => self.expression(operand, false),
+            ExpressionKind::Member { base, .. } => self.expression(base, false),
+            ExpressionKind::Binary { left, right, .. } | ExpressionKind::Index { array: left, index: right } => {
+                self.expression(left, false);
+                self.expression(right, false);
+            }
+            // Only the selected association is evaluated, but any might be
+            ExpressionKind::Generic { associations, .. } => {
+                for (_, value) in associations {
+                    self.expression(value, discarded);
+                }
+            }
+            // Not evaluated
+            ExpressionKind::SizeofExpression(_) => {}
+            ExpressionKind::Integer { .. }
+            | ExpressionKind::Float { .. }
+            | ExpressionKind::Character(_)
+            | ExpressionKind::String(_)
+            | ExpressionKind::SizeofType(_)
+            | ExpressionKind::AlignofType(_) => {}
+        }
+    }
+}
+
+// Example usage:
+/*
+fn promote_all(definition: &FunctionDefinition, sizes: &HashMap<*const Expression, u64>) -> HeapPromotions {
+    let mut promotions = analyze(definition);
+    for (&call, &size) in sizes {
+        if promotions.allocations.contains_key(&call) && promotions.fits(size) {
+            promotions.promote(call, size);
+        }
+    }
+    promotions
+}
+*/
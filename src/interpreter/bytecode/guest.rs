@@ -0,0 +1,57 @@
+// src/interpreter/bytecode/guest.rs
+//! Bytecode programs as `runtime::async_host` guests
+//! A `BytecodeGuest` owns its runtime and a VM running `main` over a
+//! shared `Program`, so thousands of guests can be spawned from one
+//! compiled program. Each step runs up to the driver's budget in
+//! statements; `read`, `write`, `accept`, `close` and the sleeps suspend
+//! the guest until the driver has made them on the Tokio runtime.
+
+use std::sync::Arc;
+use crate::interpreter::c_runtime::{CRuntimeEnvironment, RuntimeError};
+use crate::runtime::async_host::{ResumableProgram, StepResult, SyscallReply};
+use crate::runtime::RuntimeError as HostError;
+use super::{Program, Vm};
+
+pub struct BytecodeGuest {
+    // Declared first so it's dropped before what it borrows
+    vm: Vm<'static, 'static>,
+    _runtime: Box<CRuntimeEnvironment>,
+    _program: Arc<Program>,
+}
+
+// SAFETY: the driver only moves a guest between worker threads between
+// steps, and a step leaves nothing behind tied to the thread it ran on
+unsafe impl Send for BytecodeGuest {}
+
+impl BytecodeGuest {
+    /// A guest running `program`'s `main` with `args` against `runtime`
+    pub fn new(program: Arc<Program>, runtime: CRuntimeEnvironment, args: &[String]) -> Result<Self, RuntimeError> {
+        let mut runtime = Box::new(runtime);
+        // SAFETY: both live on the heap, at addresses that don't change
+        // when the guest moves, and outlive `vm`, which is dropped first
+        let (program_ref, runtime_ref) = unsafe { (&*Arc::as_ptr(&program), &mut *(runtime.as_mut() as *mut CRuntimeEnvironment)) };
+        let mut vm = Vm::new(program_ref, runtime_ref)?;
+        vm.start_main(args)?;
+        Ok(BytecodeGuest { vm, _runtime: runtime, _program: program })
+    }
+}
+
+impl ResumableProgram for BytecodeGuest {
+    fn step(&mut self, budget: u64) -> Result<StepResult, HostError> {
+        self.vm.step(budget).map_err(|e| HostError::ExceptionError(e.to_string()))
+    }
+
+    fn resume_with(&mut self, reply: SyscallReply) {
+        self.vm.resume_with(reply);
+    }
+}
+
+// Example usage:
+/*
+async fn serve(program: Arc<Program>, listener: TcpListener) -> Result<i32, RuntimeError> {
+    let engine = AsyncEngine::new(AsyncEngineConfig::default());
+    let guest = BytecodeGuest::new(program, CRuntimeEnvironment::new()?, &["echo".to_string()])?;
+    // The program accepts on descriptor 3
+    engine.spawn(guest, vec![(3, listener)]).await.unwrap()
+}
+*/
@@ -0,0 +1,337 @@
+// src/interpreter/bytecode/inline_asm.rs
+//! GNU `asm` statements for the native tier
+//! The compiler checks a statement's constraints with `arch::inline_asm`
+//! and evaluates its operands; `lower` then gives each register operand a
+//! machine register, substitutes the operands into the template and turns
+//! it from AT&T syntax, GCC's default, into the Intel syntax `native`
+//! assembles. The native tier loads the register operands, runs the code
+//! and stores the outputs back. The VM can't run it.
+//!
+//! Operands are x86_64 general registers (`r`, `q`, the single-register
+//! letters, and `g` or `rm` as a register), ties to outputs and constants.
+//! Memory and SSE operands, `asm goto`, directives and numeric local labels
+//! are unsupported. The translation reverses operands and rewrites `%reg`,
+//! `$imm`, `disp(base, index, scale)` and the `b`/`w`/`l`/`q` size
+//! suffixes; other mnemonics must be spelled as the encoding tables have
+//! them.
+
+use crate::arch::inline_asm::{ConstraintKind, ResolvedAsm};
+use crate::arch::x86_64_encoding::TABLES;
+use crate::arch::RegisterClass;
+use crate::frontend::inline_asm::AsmStatement;
+use super::AsmBlock;
+
+/// The general registers by number: 64, 32, 16 and 8-bit names
+const REGISTERS: [[&str; 4]; 16] = [
+    ["rax", "eax", "ax", "al"], ["rcx", "ecx", "cx", "cl"], ["rdx", "edx", "dx", "dl"], ["rbx", "ebx", "bx", "bl"],
+    ["rsp", "esp", "sp", "spl"], ["rbp", "ebp", "bp", "bpl"], ["rsi", "esi", "si", "sil"], ["rdi", "edi", "di", "dil"],
+    ["r8", "r8d", "r8w", "r8b"], ["r9", "r9d", "r9w", "r9b"], ["r10", "r10d", "r10w", "r10b"], ["r11", "r11d", "r11w", "r11b"],
+    ["r12", "r12d", "r12w", "r12b"], ["r13", "r13d", "r13w", "r13b"], ["r14", "r14d", "r14w", "r14b"], ["r15", "r15d", "r15w", "r15b"],
+];
+/// The stack and frame pointers, which no operand gets
+const RESERVED: [usize; 2] = [4, 5];
+/// Caller-saved registers first: native code keeps nothing in them
+/// between instructions, so they need no saving
+const ALLOCATION_ORDER: [usize; 14] = [0, 1, 2, 6, 7, 8, 9, 10, 11, 3, 12, 13, 14, 15];
+const CALLEE_SAVED: [usize; 5] = [3, 12, 13, 14, 15];
+const PREFIXES: &[&str] = &["lock", "rep", "repe", "repz", "repne", "repnz"];
+
+/// An operand as the compiler has it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// A value of this many bytes, in a register
+    Register { bytes: u32 },
+    /// The value of an immediate constraint
+    Immediate(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LowerError {
+    /// Valid asm the native tier doesn't run
+    Unsupported(String),
+    Invalid(String),
+}
+
+/// Registers and Intel-syntax code for `statement`, whose constraints
+/// `resolved` has checked, with `operands` in operand order. The register
+/// operands are the outputs, then the inputs in a register that aren't tied
+/// to an output. `%=` expands to `unique`.
+pub fn lower(statement: &AsmStatement, resolved: &ResolvedAsm, operands: &[Operand], unique: u32) -> Result<AsmBlock, LowerError> {
+    if statement.qualifiers.goto {
+        return Err(unsupported("asm goto"));
+    }
+    let kinds: Vec<&ConstraintKind> = resolved.outputs.iter().chain(&resolved.inputs).map(|operand| &operand.kind).collect();
+
+    // Pinned registers and clobbers first; `resolve` has checked they
+    // don't collide
+    let mut taken = [false; 16];
+    RESERVED.iter().for_each(|&number| taken[number] = true);
+    let mut machine: Vec<Option<usize>> = vec![None; kinds.len()];
+    for (index, kind) in kinds.iter().enumerate() {
+        if let ConstraintKind::Register(register) = kind {
+            let number = number(&register.name).ok_or_else(|| unsupported(&format!("{} as an asm operand", register.name)))?;
+            machine[index] = Some(number);
+            taken[number] = true;
+        }
+    }
+    // Other classes of clobbers are fine: native code keeps nothing there
+    for number in resolved.clobbered.iter().filter_map(|register| number(&register.name)) {
+        taken[number] = true;
+    }
+    for (index, kind) in kinds.iter().enumerate() {
+        match (kind, operands[index]) {
+            (ConstraintKind::Register(_), _) => {}
+            (ConstraintKind::Tied(output), _) => machine[index] = machine[*output],
+            (ConstraintKind::Memory, _) => return Err(unsupported("memory operands of asm")),
+            (ConstraintKind::Class(class, _), _) if *class != RegisterClass::General => {
+                return Err(unsupported("asm operands outside the general registers"))
+            }
+            (_, Operand::Immediate(_)) => {}
+            (ConstraintKind::Immediate, Operand::Register { .. }) => {
+                return Err(LowerError::Invalid(format!("asm operand {} needs a constant", index)))
+            }
+            (ConstraintKind::Class(..) | ConstraintKind::Any, Operand::Register { .. }) => {
+                let number = ALLOCATION_ORDER
+                    .into_iter()
+                    .find(|&number| !taken[number])
+                    .ok_or_else(|| unsupported("more asm operands than free registers"))?;
+                machine[index] = Some(number);
+                taken[number] = true;
+            }
+        }
+    }
+
+    let template = if statement.basic { statement.template.clone() } else { substitute(statement, &machine, operands, unique)? };
+    let code = intel(&template)?;
+
+    let registers = kinds
+        .iter()
+        .zip(&machine)
+        .filter(|(kind, _)| !matches!(kind, ConstraintKind::Tied(_)))
+        .filter_map(|(_, number)| number.map(|number| REGISTERS[number][0].to_string()))
+        .collect();
+    let mut saved: Vec<String> = Vec::new();
+    let changed = machine.iter().flatten().copied().chain(resolved.clobbered.iter().filter_map(|register| number(&register.name)));
+    for number in changed.filter(|number| CALLEE_SAVED.contains(number)) {
+        if !saved.iter().any(|name| name == REGISTERS[number][0]) {
+            saved.push(REGISTERS[number][0].to_string());
+        }
+    }
+    Ok(AsmBlock { code, registers, outputs: resolved.outputs.len() as u8, saved })
+}
+
+fn unsupported(what: &str) -> LowerError {
+    LowerError::Unsupported(what.to_string())
+}
+
+/// Number of a general register, by any of its names
+fn number(name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    REGISTERS.iter().position(|names| names.contains(&name.as_str()))
+}
+
+/// The template with `%N`, `%[name]`, `%%` and `%=` replaced
+fn substitute(statement: &AsmStatement, machine: &[Option<usize>], operands: &[Operand], unique: u32) -> Result<String, LowerError> {
+    let bad = || LowerError::Invalid(format!("bad operand reference in `{}`", statement.template));
+    let mut out = String::with_capacity(statement.template.len());
+    let mut chars = statement.template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            out.push('%');
+            continue;
+        }
+        if chars.next_if_eq(&'=').is_some() {
+            out.push_str(&unique.to_string());
+            continue;
+        }
+        let modifier = chars.next_if(char::is_ascii_alphabetic);
+        let index = if chars.next_if_eq(&'[').is_some() {
+            let name: String = chars.by_ref().take_while(|&c| c != ']').collect();
+            statement.operand_index(name.trim()).ok_or_else(bad)?
+        } else {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            digits.parse().map_err(|_| bad())?
+        };
+        let operand = *operands.get(index).ok_or_else(|| LowerError::Invalid(format!("asm operand %{} doesn't exist", index)))?;
+        out.push_str(&operand_text(machine[index], operand, modifier)?);
+    }
+    Ok(out)
+}
+
+/// An operand in AT&T syntax: the register of its size, unless a modifier
+/// picks another (`%k0`), or `$value`, or the bare value with `c`
+fn operand_text(register: Option<usize>, operand: Operand, modifier: Option<char>) -> Result<String, LowerError> {
+    match (operand, register) {
+        (Operand::Immediate(value), _) => match modifier {
+            None => Ok(format!("${}", value)),
+            Some('c' | 'P') => Ok(value.to_string()),
+            Some('n') => Ok(value.wrapping_neg().to_string()),
+            Some(other) => Err(unsupported(&format!("the `%{}` modifier on a constant", other))),
+        },
+        (Operand::Register { bytes }, Some(number)) => {
+            let width = match (modifier, bytes) {
+                (Some('q'), _) | (None, 8) => 0,
+                (Some('k'), _) | (None, 4) => 1,
+                (Some('w'), _) | (None, 2) => 2,
+                (Some('b'), _) | (None, 1) => 3,
+                (None, _) => 0,
+                (Some(other), _) => return Err(unsupported(&format!("the `%{}` operand modifier", other))),
+            };
+            Ok(format!("%{}", REGISTERS[number][width]))
+        }
+        (Operand::Register { .. }, None) => Err(LowerError::Invalid("asm operand without a register".to_string())),
+    }
+}
+
+/// AT&T `template` in Intel syntax, one instruction or label per line
+fn intel(template: &str) -> Result<String, LowerError> {
+    let mut lines = Vec::new();
+    for statement in template.split(['\n', ';']) {
+        let mut statement = statement.split('#').next().unwrap_or_default().trim();
+        while let Some((label, rest)) = statement.split_once(':').filter(|(label, _)| is_label(label)) {
+            if label.starts_with(|c: char| c.is_ascii_digit()) {
+                return Err(unsupported("numeric local labels in asm"));
+            }
+            lines.push(format!("{}:", label));
+            statement = rest.trim();
+        }
+        if statement.is_empty() {
+            continue;
+        }
+        if statement.starts_with('.') {
+            return Err(unsupported("assembler directives in asm"));
+        }
+        lines.push(instruction(statement)?);
+    }
+    Ok(lines.join("\n"))
+}
+
+fn is_label(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$'))
+}
+
+fn instruction(text: &str) -> Result<String, LowerError> {
+    let mut words = Vec::new();
+    let mut rest = text;
+    let mnemonic = loop {
+        let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        rest = after.trim();
+        if PREFIXES.contains(&word) {
+            words.push(word.to_string());
+        } else {
+            break word.to_lowercase();
+        }
+    };
+
+    // `addl` is `add` on 32-bit operands
+    let size = match mnemonic.chars().last() {
+        Some('b') => Some("byte"),
+        Some('w') => Some("word"),
+        Some('l') => Some("dword"),
+        Some('q') => Some("qword"),
+        _ => None,
+    };
+    let base = &mnemonic[..mnemonic.len().saturating_sub(1)];
+    let (mnemonic, size) = match size {
+        Some(size) if !TABLES.supports(&mnemonic) && TABLES.supports(base) => (base.to_string(), Some(size)),
+        _ => (mnemonic.clone(), None),
+    };
+    words.push(mnemonic);
+
+    let mut operands = split_operands(rest).into_iter().map(|text| operand(text, size)).collect::<Result<Vec<_>, _>>()?;
+    operands.reverse();
+    let mut line = words.join(" ");
+    if !operands.is_empty() {
+        line.push(' ');
+        line.push_str(&operands.join(", "));
+    }
+    Ok(line)
+}
+
+/// Split on the commas outside parentheses
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !text[start..].trim().is_empty() {
+        operands.push(text[start..].trim());
+    }
+    operands
+}
+
+/// One AT&T operand in Intel syntax; a memory operand is given `size`,
+/// from the mnemonic's suffix, if it has one
+fn operand(text: &str, size: Option<&str>) -> Result<String, LowerError> {
+    // `jmp *%rax`
+    let text = text.trim_start_matches('*');
+    if let Some(register) = text.strip_prefix('%') {
+        return Ok(register.to_lowercase());
+    }
+    if let Some(immediate) = text.strip_prefix('$') {
+        return Ok(immediate.to_string());
+    }
+    let Some((displacement, rest)) = text.split_once('(') else {
+        // A jump target or an absolute address
+        if text.starts_with(|c: char| c.is_ascii_digit()) && text.ends_with(['f', 'b']) {
+            return Err(unsupported("numeric local labels in asm"));
+        }
+        return Ok(text.to_string());
+    };
+    if displacement.contains(':') {
+        return Err(unsupported("segment overrides in asm"));
+    }
+    let inner = rest.strip_suffix(')').ok_or_else(|| LowerError::Invalid(format!("bad memory operand `{}`", text)))?;
+    let mut parts = inner.split(',').map(|part| part.trim().trim_start_matches('%').to_lowercase());
+    let (base, index, scale) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default(), parts.next());
+
+    let mut address = Vec::new();
+    if !base.is_empty() {
+        address.push(base);
+    }
+    if !index.is_empty() {
+        address.push(format!("{}*{}", index, scale.as_deref().unwrap_or("1")));
+    }
+    let mut address = address.join(" + ");
+    let displacement = displacement.trim();
+    if address.is_empty() {
+        address = displacement.to_string();
+    } else if let Some(magnitude) = displacement.strip_prefix('-') {
+        address.push_str(&format!(" - {}", magnitude));
+    } else if !displacement.is_empty() {
+        address.push_str(&format!(" + {}", displacement));
+    }
+    Ok(match size {
+        Some(size) => format!("{} ptr [{}]", size, address),
+        None => format!("[{}]", address),
+    })
+}
+
+// Example usage:
+/*
+fn add(resolved: &ResolvedAsm) -> Result<AsmBlock, LowerError> {
+    // int r; asm("addl %2, %0" : "=r"(r) : "0"(a), "r"(b));
+    let statement = parse_asm_statement(r#"asm("addl %2, %0" : "=r"(r) : "0"(a), "r"(b));"#).unwrap();
+    let operands = [Operand::Register { bytes: 4 }; 3];
+    let block = lower(&statement, resolved, &operands, 0)?;
+    assert_eq!(block.code, "add eax, ecx");
+    assert_eq!(block.registers, ["rax", "rcx"]);
+    Ok(block)
+}
+*/
@@ -0,0 +1,670 @@
+// src/interpreter/bytecode/mod.rs
+//! Register-based bytecode for interpreted programs
+//! A middle tier between walking the syntax tree and JIT-compiling with
+//! LLVM: `compile` lowers a `TranslationUnit` to compact instructions once,
+//! and `Vm` runs them in a dispatch loop, without re-resolving names, types
+//! and conversions on every execution the way the tree walker does.
+//!
+//! Every function has its own register window of 64-bit registers. An
+//! integer is kept sign- or zero-extended from its C width, a `float` or
+//! `double` as the bits of an `f64` (a `float` rounded after every
+//! operation), a pointer as the host address. Variables whose address is
+//! taken, arrays and structs live in the function's frame memory instead.
+//!
+//! The compiler rejects what the tier doesn't implement (`setjmp`,
+//! interpreted signal handlers and thread start routines, variadic
+//! definitions, bit-fields, `long double`, structs passed by value)
+//! with `BytecodeError::Unsupported`, so the caller can fall back to the
+//! tree walker for that program. `asm` statements compile, but only the
+//! native tier runs them.
+
+use std::fmt;
+use std::str::FromStr;
+use crate::abi::aggregate::Scalar;
+use crate::optimizer::overflow::SignedOp;
+
+pub mod compiler;
+pub mod escape;
+pub mod guest;
+pub mod inline_asm;
+pub mod native;
+pub mod superinstructions;
+pub mod vm;
+
+pub use compiler::compile;
+pub use guest::BytecodeGuest;
+pub use superinstructions::fuse;
+pub use vm::{Dispatch, Vm};
+
+/// A register in the current function's window
+pub type Reg = u16;
+
+/// Which interpreter `-i` runs programs with (`--engine`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InterpreterEngine {
+    /// Walk the syntax tree
+    #[default]
+    Tree,
+    /// Compile to bytecode first
+    Bytecode,
+    /// Bytecode, with the functions `native` supports run as machine code
+    Native,
+}
+
+impl FromStr for InterpreterEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(InterpreterEngine::Tree),
+            "bytecode" => Ok(InterpreterEngine::Bytecode),
+            "native" => Ok(InterpreterEngine::Native),
+            other => Err(format!("unknown engine '{}': use tree, bytecode or native", other)),
+        }
+    }
+}
+
+impl fmt::Display for InterpreterEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InterpreterEngine::Tree => "tree",
+            InterpreterEngine::Bytecode => "bytecode",
+            InterpreterEngine::Native => "native",
+        })
+    }
+}
+
+/// How a value is laid out in memory, and passed to native functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+}
+
+impl Kind {
+    pub fn size(self) -> usize {
+        self.scalar().size()
+    }
+
+    pub fn scalar(self) -> Scalar {
+        match self {
+            Kind::I8 | Kind::U8 => Scalar::I8,
+            Kind::I16 | Kind::U16 => Scalar::I16,
+            Kind::I32 | Kind::U32 => Scalar::I32,
+            Kind::I64 | Kind::U64 => Scalar::I64,
+            Kind::F32 => Scalar::F32,
+            Kind::F64 => Scalar::F64,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Kind::F32 | Kind::F64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // Registers
+    Move { dst: Reg, src: Reg },
+    /// A small integer, sign-extended
+    Int { dst: Reg, value: i32 },
+    /// `Program::constants[index]`
+    Const { dst: Reg, index: u32 },
+
+    // Integer arithmetic, on all 64 bits; `Extend` narrows the result
+    Add { dst: Reg, a: Reg, b: Reg },
+    Sub { dst: Reg, a: Reg, b: Reg },
+    Mul { dst: Reg, a: Reg, b: Reg },
+    AddImm { dst: Reg, a: Reg, value: i32 },
+    DivU { dst: Reg, a: Reg, b: Reg },
+    RemU { dst: Reg, a: Reg, b: Reg },
+    And { dst: Reg, a: Reg, b: Reg },
+    Or { dst: Reg, a: Reg, b: Reg },
+    Xor { dst: Reg, a: Reg, b: Reg },
+    /// Shifts of `bits`-wide operands; a count of `bits` or more is only
+    /// checked with `--sanitize=undefined`
+    Shl { dst: Reg, a: Reg, b: Reg, bits: u8 },
+    ShrU { dst: Reg, a: Reg, b: Reg, bits: u8 },
+    ShrS { dst: Reg, a: Reg, b: Reg, bits: u8 },
+    /// Signed `+ - * / % <<` on `bits`-wide operands, whose overflow
+    /// follows the runtime's mode (wrap, trap or diagnose)
+    Signed { op: SignedOp, dst: Reg, a: Reg, b: Reg, bits: u8 },
+    Neg { dst: Reg, src: Reg },
+    Not { dst: Reg, src: Reg },
+    /// `dst = src == 0`
+    LogicalNot { dst: Reg, src: Reg },
+    /// `dst = src != 0`
+    Test { dst: Reg, src: Reg },
+    /// Keep the low `bits` of `src`, sign- or zero-extended
+    Extend { dst: Reg, src: Reg, bits: u8, signed: bool },
+
+    // Integer comparisons, producing 0 or 1
+    Eq { dst: Reg, a: Reg, b: Reg },
+    Ne { dst: Reg, a: Reg, b: Reg },
+    LtS { dst: Reg, a: Reg, b: Reg },
+    LeS { dst: Reg, a: Reg, b: Reg },
+    LtU { dst: Reg, a: Reg, b: Reg },
+    LeU { dst: Reg, a: Reg, b: Reg },
+
+    // Floating point, on `f64` bits
+    FAdd { dst: Reg, a: Reg, b: Reg },
+    FSub { dst: Reg, a: Reg, b: Reg },
+    FMul { dst: Reg, a: Reg, b: Reg },
+    FDiv { dst: Reg, a: Reg, b: Reg },
+    FNeg { dst: Reg, src: Reg },
+    FEq { dst: Reg, a: Reg, b: Reg },
+    FNe { dst: Reg, a: Reg, b: Reg },
+    FLt { dst: Reg, a: Reg, b: Reg },
+    FLe { dst: Reg, a: Reg, b: Reg },
+    /// Round to `float` precision
+    RoundF32 { dst: Reg, src: Reg },
+    IntToFloat { dst: Reg, src: Reg, signed: bool },
+    /// Truncate toward zero, to 64 bits
+    FloatToInt { dst: Reg, src: Reg, signed: bool },
+
+    // Memory
+    Load { dst: Reg, address: Reg, kind: Kind },
+    Store { address: Reg, src: Reg, kind: Kind },
+    /// Address `offset` bytes into the frame memory
+    LocalAddress { dst: Reg, offset: u32 },
+    /// Address `offset` bytes into the program's data
+    GlobalAddress { dst: Reg, offset: u32 },
+    /// A pointer to interpreted function `function`
+    FunctionAddress { dst: Reg, function: u32 },
+    /// Native address of external function `external`
+    ExternalAddress { dst: Reg, external: u32 },
+    CopyBytes { dst: Reg, src: Reg, size: u32 },
+    ZeroBytes { dst: Reg, size: u32 },
+    /// The height of the runtime's VLA stack, to free back to
+    VlaMark { dst: Reg },
+    /// `length` elements of `size` bytes from the VLA stack, zeroed if `zero`
+    VlaAllocate { dst: Reg, length: Reg, size: u32, zero: bool },
+    /// Free the VLA stack back to the height `VlaMark` saved in `mark`
+    VlaRelease { mark: Reg },
+
+    // Control
+    Jump { target: u32 },
+    JumpIfZero { condition: Reg, target: u32 },
+    JumpIfNonZero { condition: Reg, target: u32 },
+    /// Jump by `Program::switch_tables[table]`
+    Switch { value: Reg, table: u32 },
+    /// Arguments are in `count` consecutive registers from `arguments`;
+    /// they become the callee's first registers
+    Call { dst: Reg, function: u32, arguments: Reg, count: u8 },
+    /// Call through a function pointer, interpreted or native; `signature`
+    /// indexes `Program::signatures`, for native callees
+    CallIndirect { dst: Reg, callee: Reg, arguments: Reg, count: u8, signature: u32 },
+    /// `signature` has this call's argument kinds, variadic ones included
+    CallExternal { dst: Reg, external: u32, arguments: Reg, count: u8, signature: u32 },
+    Return { src: Reg },
+    ReturnVoid,
+
+    // Runtime hooks
+    /// Start of a statement: limits, record/replay, stack capture
+    Statement { line: u32 },
+    /// `__builtin_probe(site, arguments...)`
+    Probe { site: u32, arguments: Reg, count: u8 },
+    /// Inline assembly `Program::asm[index]`, on its register operands in
+    /// `count` consecutive registers from `operands`, outputs first. Only
+    /// the native tier runs it.
+    Asm { index: u32, operands: Reg, count: u8 },
+
+    // Superinstructions, written by `fuse` over the first of the sequence
+    // they replace: each writes every register the sequence would, then
+    // continues after its last instruction
+    /// `Int { dst: temp, value }`, then `Signed { op, dst, a, b: temp, bits }`
+    SignedImm { op: SignedOp, dst: Reg, a: Reg, temp: Reg, value: i32, bits: u8 },
+    /// `Signed { op, dst: temp, a, b, bits }`, then `Move { dst, src: temp }`
+    SignedMove { op: SignedOp, dst: Reg, a: Reg, b: Reg, temp: Reg, bits: u8 },
+    /// `Int { dst: temp, value }`, then a comparison of `a` with `temp`
+    CompareImm { comparison: Comparison, dst: Reg, a: Reg, temp: Reg, value: i32 },
+    /// A comparison, then `JumpIfNonZero` (`when: true`) or `JumpIfZero` on it
+    CompareJump { comparison: Comparison, dst: Reg, a: Reg, b: Reg, target: u32, when: bool },
+    /// `CompareImm`, then the jump: the three instructions of a loop test
+    CompareImmJump { comparison: Comparison, dst: Reg, a: Reg, temp: Reg, value: i32, target: u32, when: bool },
+    /// `Move { dst, src }`, then `Jump { target }`
+    MoveJump { dst: Reg, src: Reg, target: u32 },
+
+    /// An opcode from a newer minor version of the IR format, read without
+    /// its fields; running it fails, so the rest of the program still runs
+    Unknown { opcode: u64 },
+}
+
+/// The integer comparisons, for superinstructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    LtS,
+    LeS,
+    LtU,
+    LeU,
+}
+
+impl Comparison {
+    /// The comparison `instruction` makes, with its registers
+    pub fn of(instruction: Instruction) -> Option<(Comparison, Reg, Reg, Reg)> {
+        Some(match instruction {
+            Instruction::Eq { dst, a, b } => (Comparison::Eq, dst, a, b),
+            Instruction::Ne { dst, a, b } => (Comparison::Ne, dst, a, b),
+            Instruction::LtS { dst, a, b } => (Comparison::LtS, dst, a, b),
+            Instruction::LeS { dst, a, b } => (Comparison::LeS, dst, a, b),
+            Instruction::LtU { dst, a, b } => (Comparison::LtU, dst, a, b),
+            Instruction::LeU { dst, a, b } => (Comparison::LeU, dst, a, b),
+            _ => return None,
+        })
+    }
+
+    #[inline(always)]
+    pub fn holds(self, a: u64, b: u64) -> bool {
+        match self {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::LtS => (a as i64) < (b as i64),
+            Comparison::LeS => (a as i64) <= (b as i64),
+            Comparison::LtU => a < b,
+            Comparison::LeU => a <= b,
+        }
+    }
+}
+
+impl Instruction {
+    /// How many instructions it stands for: more than one for superinstructions
+    pub fn width(&self) -> usize {
+        match self {
+            Instruction::CompareImmJump { .. } => 3,
+            Instruction::SignedImm { .. }
+            | Instruction::SignedMove { .. }
+            | Instruction::CompareImm { .. }
+            | Instruction::CompareJump { .. }
+            | Instruction::MoveJump { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    /// Whether execution can continue after it (after all `width` of it)
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Instruction::Jump { .. }
+                | Instruction::Switch { .. }
+                | Instruction::Return { .. }
+                | Instruction::ReturnVoid
+                | Instruction::MoveJump { .. }
+                | Instruction::Unknown { .. }
+        )
+    }
+
+    /// Where it jumps, other than through a switch table
+    pub fn target(&self) -> Option<u32> {
+        match self {
+            Instruction::Jump { target }
+            | Instruction::JumpIfZero { target, .. }
+            | Instruction::JumpIfNonZero { target, .. }
+            | Instruction::CompareJump { target, .. }
+            | Instruction::CompareImmJump { target, .. }
+            | Instruction::MoveJump { target, .. } => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// One past the highest register it reads or writes
+    pub fn register_end(&self) -> usize {
+        let end = |registers: &[Reg]| registers.iter().map(|&register| register as usize + 1).max().unwrap_or(0);
+        let range = |start: Reg, count: u8| start as usize + count as usize;
+        match *self {
+            Instruction::Int { dst, .. }
+            | Instruction::Const { dst, .. }
+            | Instruction::LocalAddress { dst, .. }
+            | Instruction::GlobalAddress { dst, .. }
+            | Instruction::FunctionAddress { dst, .. }
+            | Instruction::ExternalAddress { dst, .. }
+            | Instruction::ZeroBytes { dst, .. }
+            | Instruction::VlaMark { dst } => end(&[dst]),
+            Instruction::VlaAllocate { dst, length, .. } => end(&[dst, length]),
+            Instruction::VlaRelease { mark } => end(&[mark]),
+            Instruction::Move { dst, src }
+            | Instruction::Neg { dst, src }
+            | Instruction::Not { dst, src }
+            | Instruction::LogicalNot { dst, src }
+            | Instruction::Test { dst, src }
+            | Instruction::Extend { dst, src, .. }
+            | Instruction::FNeg { dst, src }
+            | Instruction::RoundF32 { dst, src }
+            | Instruction::IntToFloat { dst, src, .. }
+            | Instruction::FloatToInt { dst, src, .. }
+            | Instruction::CopyBytes { dst, src, .. }
+            | Instruction::MoveJump { dst, src, .. }
+            | Instruction::AddImm { dst, a: src, .. }
+            | Instruction::Load { dst, address: src, .. }
+            | Instruction::Store { address: dst, src, .. } => end(&[dst, src]),
+            Instruction::Add { dst, a, b }
+            | Instruction::Sub { dst, a, b }
+            | Instruction::Mul { dst, a, b }
+            | Instruction::DivU { dst, a, b }
+            | Instruction::RemU { dst, a, b }
+            | Instruction::And { dst, a, b }
+            | Instruction::Or { dst, a, b }
+            | Instruction::Xor { dst, a, b }
+            | Instruction::Shl { dst, a, b, .. }
+            | Instruction::ShrU { dst, a, b, .. }
+            | Instruction::ShrS { dst, a, b, .. }
+            | Instruction::Signed { dst, a, b, .. }
+            | Instruction::Eq { dst, a, b }
+            | Instruction::Ne { dst, a, b }
+            | Instruction::LtS { dst, a, b }
+            | Instruction::LeS { dst, a, b }
+            | Instruction::LtU { dst, a, b }
+            | Instruction::LeU { dst, a, b }
+            | Instruction::FAdd { dst, a, b }
+            | Instruction::FSub { dst, a, b }
+            | Instruction::FMul { dst, a, b }
+            | Instruction::FDiv { dst, a, b }
+            | Instruction::FEq { dst, a, b }
+            | Instruction::FNe { dst, a, b }
+            | Instruction::FLt { dst, a, b }
+            | Instruction::FLe { dst, a, b }
+            | Instruction::CompareJump { dst, a, b, .. }
+            | Instruction::SignedImm { dst, a, temp: b, .. }
+            | Instruction::CompareImm { dst, a, temp: b, .. }
+            | Instruction::CompareImmJump { dst, a, temp: b, .. } => end(&[dst, a, b]),
+            Instruction::SignedMove { dst, a, b, temp, .. } => end(&[dst, a, b, temp]),
+            Instruction::JumpIfZero { condition, .. } | Instruction::JumpIfNonZero { condition, .. } => end(&[condition]),
+            Instruction::Switch { value, .. } => end(&[value]),
+            Instruction::Return { src } => end(&[src]),
+            Instruction::Call { dst, arguments, count, .. } | Instruction::CallExternal { dst, arguments, count, .. } => {
+                end(&[dst]).max(range(arguments, count))
+            }
+            Instruction::CallIndirect { dst, callee, arguments, count, .. } => end(&[dst, callee]).max(range(arguments, count)),
+            Instruction::Probe { arguments, count, .. } => range(arguments, count),
+            Instruction::Asm { operands, count, .. } => range(operands, count),
+            Instruction::Jump { .. } | Instruction::ReturnVoid | Instruction::Statement { .. } | Instruction::Unknown { .. } => 0,
+        }
+    }
+
+    /// Name for `--vm-stats` and disassembly
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Move { .. } => "move",
+            Instruction::Int { .. } => "int",
+            Instruction::Const { .. } => "const",
+            Instruction::Add { .. } => "add",
+            Instruction::Sub { .. } => "sub",
+            Instruction::Mul { .. } => "mul",
+            Instruction::AddImm { .. } => "add_imm",
+            Instruction::DivU { .. } => "div_u",
+            Instruction::RemU { .. } => "rem_u",
+            Instruction::And { .. } => "and",
+            Instruction::Or { .. } => "or",
+            Instruction::Xor { .. } => "xor",
+            Instruction::Shl { .. } => "shl",
+            Instruction::ShrU { .. } => "shr_u",
+            Instruction::ShrS { .. } => "shr_s",
+            Instruction::Signed { .. } => "signed",
+            Instruction::Neg { .. } => "neg",
+            Instruction::Not { .. } => "not",
+            Instruction::LogicalNot { .. } => "logical_not",
+            Instruction::Test { .. } => "test",
+            Instruction::Extend { .. } => "extend",
+            Instruction::Eq { .. } => "eq",
+            Instruction::Ne { .. } => "ne",
+            Instruction::LtS { .. } => "lt_s",
+            Instruction::LeS { .. } => "le_s",
+            Instruction::LtU { .. } => "lt_u",
+            Instruction::LeU { .. } => "le_u",
+            Instruction::FAdd { .. } => "f_add",
+            Instruction::FSub { .. } => "f_sub",
+            Instruction::FMul { .. } => "f_mul",
+            Instruction::FDiv { .. } => "f_div",
+            Instruction::FNeg { .. } => "f_neg",
+            Instruction::FEq { .. } => "f_eq",
+            Instruction::FNe { .. } => "f_ne",
+            Instruction::FLt { .. } => "f_lt",
+            Instruction::FLe { .. } => "f_le",
+            Instruction::RoundF32 { .. } => "round_f32",
+            Instruction::IntToFloat { .. } => "int_to_float",
+            Instruction::FloatToInt { .. } => "float_to_int",
+            Instruction::Load { .. } => "load",
+            Instruction::Store { .. } => "store",
+            Instruction::LocalAddress { .. } => "local_address",
+            Instruction::GlobalAddress { .. } => "global_address",
+            Instruction::FunctionAddress { .. } => "function_address",
+            Instruction::ExternalAddress { .. } => "external_address",
+            Instruction::CopyBytes { .. } => "copy_bytes",
+            Instruction::ZeroBytes { .. } => "zero_bytes",
+            Instruction::VlaMark { .. } => "vla_mark",
+            Instruction::VlaAllocate { .. } => "vla_allocate",
+            Instruction::VlaRelease { .. } => "vla_release",
+            Instruction::Jump { .. } => "jump",
+            Instruction::JumpIfZero { .. } => "jump_if_zero",
+            Instruction::JumpIfNonZero { .. } => "jump_if_non_zero",
+            Instruction::Switch { .. } => "switch",
+            Instruction::Call { .. } => "call",
+            Instruction::CallIndirect { .. } => "call_indirect",
+            Instruction::CallExternal { .. } => "call_external",
+            Instruction::Return { .. } => "return",
+            Instruction::ReturnVoid => "return_void",
+            Instruction::Statement { .. } => "statement",
+            Instruction::Probe { .. } => "probe",
+            Instruction::Asm { .. } => "asm",
+            Instruction::SignedImm { .. } => "signed_imm",
+            Instruction::SignedMove { .. } => "signed_move",
+            Instruction::CompareImm { .. } => "compare_imm",
+            Instruction::CompareJump { .. } => "compare_jump",
+            Instruction::CompareImmJump { .. } => "compare_imm_jump",
+            Instruction::MoveJump { .. } => "move_jump",
+            Instruction::Unknown { .. } => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub parameters: u16,
+    /// Size of the register window
+    pub registers: u16,
+    pub frame_size: u32,
+    pub code: Vec<Instruction>,
+    /// How native code calls it; `None` from IR before 1.2
+    pub signature: Option<Signature>,
+}
+
+/// Parameter and result kinds of a function the VM calls natively;
+/// `None` is `void`. At a call site, `parameters` are the arguments'
+/// kinds, variadic ones included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub parameters: Vec<Kind>,
+    /// How many parameters come before the `...`, if there is one
+    pub variadic: Option<u16>,
+    pub result: Option<Kind>,
+}
+
+/// A function or object the program declares but doesn't define, resolved
+/// in the host process when first used
+#[derive(Debug, Clone)]
+pub struct External {
+    pub name: String,
+    pub signature: Signature,
+}
+
+#[derive(Debug, Clone)]
+pub struct SwitchTable {
+    /// Sorted by value
+    pub cases: Vec<(i64, u32)>,
+    pub default: u32,
+}
+
+/// A pointer in the initial data, filled in when the program is loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: u32,
+    pub target: RelocationTarget,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationTarget {
+    Data(u32),
+    Function(u32),
+    External(u32),
+}
+
+/// An `asm` statement as the native tier runs it: each register operand
+/// loaded into its machine register, `code`, then the outputs stored back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmBlock {
+    /// Intel syntax, one instruction or label per line
+    pub code: String,
+    /// The machine register of each register operand, by its 64-bit name
+    pub registers: Vec<String>,
+    /// How many of `registers` are outputs
+    pub outputs: u8,
+    /// Callee-saved registers `code` changes, to save around it
+    pub saved: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub file: String,
+    pub functions: Vec<Function>,
+    pub externals: Vec<External>,
+    pub signatures: Vec<Signature>,
+    pub constants: Vec<u64>,
+    pub switch_tables: Vec<SwitchTable>,
+    pub asm: Vec<AsmBlock>,
+
+    // Globals, statics and string literals
+    pub data: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Program {
+    pub fn function(&self, name: &str) -> Option<u32> {
+        self.functions.iter().position(|function| function.name == name).map(|index| index as u32)
+    }
+
+    /// Check what `Dispatch::Threaded` relies on without checking at run
+    /// time: registers inside each function's window, and jumps and
+    /// fall-through inside its code
+    pub fn verify(&self) -> Result<(), BytecodeError> {
+        for function in &self.functions {
+            let invalid = |pc: usize, what: &str| BytecodeError::Invalid {
+                message: format!("{} at {}+{}", what, function.name, pc),
+                line: 0,
+            };
+            if function.signature.as_ref().is_some_and(|signature| signature.parameters.len() != function.parameters as usize) {
+                return Err(invalid(0, "signature of a different arity"));
+            }
+            let length = function.code.len();
+            let inside = |target: u32| (target as usize) < length;
+            for (pc, instruction) in function.code.iter().enumerate() {
+                if instruction.register_end() > function.registers as usize {
+                    return Err(invalid(pc, "register outside the window"));
+                }
+                let next = pc + instruction.width();
+                if next > length || (instruction.falls_through() && next == length) {
+                    return Err(invalid(pc, "execution past the end"));
+                }
+                if instruction.target().is_some_and(|target| !inside(target)) {
+                    return Err(invalid(pc, "jump past the end"));
+                }
+                if let Instruction::Asm { index, count, .. } = instruction {
+                    let block = self.asm.get(*index as usize).ok_or_else(|| invalid(pc, "no such asm block"))?;
+                    if block.registers.len() != *count as usize || block.outputs > *count {
+                        return Err(invalid(pc, "asm operands that don't match its block"));
+                    }
+                }
+                if let Instruction::Switch { table, .. } = instruction {
+                    let table = self.switch_tables.get(*table as usize).ok_or_else(|| invalid(pc, "no such switch table"))?;
+                    if !inside(table.default) || table.cases.iter().any(|&(_, target)| !inside(target)) {
+                        return Err(invalid(pc, "jump past the end"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Instructions executed and compiled, for debugging the compiler
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        for function in &self.functions {
+            out.push_str(&format!(
+                "{}: {} parameter(s), {} register(s), {} byte frame\n",
+                function.name, function.parameters, function.registers, function.frame_size
+            ));
+            for (pc, instruction) in function.code.iter().enumerate() {
+                out.push_str(&format!("  {:5}  {:?}\n", pc, instruction));
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// Valid C the bytecode tier doesn't run; use the tree walker
+    Unsupported { what: String, line: u32 },
+    Undeclared { name: String, line: u32 },
+    Invalid { message: String, line: u32 },
+    /// More registers, frame memory or data than the encoding allows
+    TooLarge { function: String },
+    /// The program has no `main`
+    NoMain,
+    UndefinedSymbol(String),
+    /// An interpreted function pointer passed to native code, which can't
+    /// call it
+    NativeCallback { external: String },
+    /// A call through a pointer that isn't a function
+    BadFunctionPointer(u64),
+    /// An instruction of a newer IR format than this reader's
+    UnknownOpcode { opcode: u64, function: String },
+    /// Inline assembly reached in the VM, in a function the native tier
+    /// didn't compile
+    InterpretedAsm { function: String },
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::Unsupported { what, line } => write!(f, "line {}: the bytecode engine doesn't support {}", line, what),
+            BytecodeError::Undeclared { name, line } => write!(f, "line {}: '{}' is not declared", line, name),
+            BytecodeError::Invalid { message, line } => write!(f, "line {}: {}", line, message),
+            BytecodeError::TooLarge { function } => write!(f, "'{}' is too large for the bytecode engine", function),
+            BytecodeError::NoMain => write!(f, "the program has no main function"),
+            BytecodeError::UndefinedSymbol(name) => write!(f, "undefined symbol '{}'", name),
+            BytecodeError::NativeCallback { external } => {
+                write!(f, "an interpreted function was passed to native '{}', which can't call it", external)
+            }
+            BytecodeError::BadFunctionPointer(value) => write!(f, "call through 0x{:x}, which is not a function", value),
+            BytecodeError::UnknownOpcode { opcode, function } => {
+                write!(f, "'{}' runs opcode {}, from a newer IR format than this release reads", function, opcode)
+            }
+            BytecodeError::InterpretedAsm { function } => {
+                write!(f, "'{}' runs inline assembly, which only native code can (--engine native)", function)
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn run(runtime: &mut CRuntimeEnvironment, unit: &TranslationUnit) -> Result<i32, RuntimeError> {
+    match bytecode::compile(unit) {
+        Ok(program) => Vm::new(&program, runtime)?.run_main(&["prog".to_string()]),
+        // Something only the tree walker runs
+        Err(BytecodeError::Unsupported { .. }) => runtime.execute(unit).map(|result| result.return_value as i32),
+        Err(e) => Err(RuntimeError::Bytecode(e)),
+    }
+}
+*/
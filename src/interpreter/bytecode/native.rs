@@ -0,0 +1,495 @@
+// src/interpreter/bytecode/native.rs
+//! Baseline native code for bytecode functions (`--engine native`)
+//! The -O0 tier for builds without LLVM: each function the tier supports
+//! is translated instruction by instruction to x86_64 assembly, which
+//! `arch::x86_64` parses and `arch::assembler` encodes, the same path
+//! inline assembly takes. There is no register allocation; every bytecode
+//! register is a stack slot, `qword ptr [rsp + 8 * register]`.
+//!
+//! A compiled function is `extern "C" fn(*const u64) -> u64`, taking its
+//! arguments as the VM would pass them and returning the register value.
+//! Calls between compiled functions are native calls; everything else
+//! stays in the VM, which calls in when it reaches a compiled function.
+//!
+//! Supported are the functions without frame memory whose instructions
+//! are integer and `double` arithmetic, loads and stores, jumps, calls to
+//! other supported functions and inline assembly, which only runs here
+//! (see `inline_asm`). Signed arithmetic is only compiled when it wraps
+//! (`-fwrapv`), as reporting overflow needs the runtime. Statements
+//! aren't traced, a bad pointer faults the host process and recursion runs
+//! on its stack: the VM only uses this tier when nothing observes the
+//! difference.
+
+use std::collections::HashSet;
+use crate::arch::assembler;
+use crate::arch::x86_64::{X86_64AssemblyParser, X86_64InstructionEncoder};
+use crate::arch::AssemblyParser;
+use crate::optimizer::overflow::SignedOp;
+use super::{Comparison, Function, Instruction, Kind, Program, Reg};
+
+/// A compiled function: its arguments, in order, to its result
+type Entry = unsafe extern "C" fn(*const u64) -> u64;
+
+pub struct NativeCode {
+    memory: *mut u8,
+    size: usize,
+    /// Offset and parameter count of each compiled function
+    entries: Vec<Option<(usize, u16)>>,
+}
+
+impl NativeCode {
+    /// Compile the functions of `program` the tier supports, for a VM that
+    /// loaded the program's data at `data` and whose signed overflow
+    /// `wrap`s. `None` on hosts other than x86_64 and when no function is
+    /// supported.
+    pub fn compile(program: &Program, data: u64, wrap: bool) -> Option<NativeCode> {
+        if !cfg!(all(target_arch = "x86_64", unix)) {
+            return None;
+        }
+
+        // A call makes its caller unsupported when the callee is
+        let mut supported: HashSet<u32> = (0..program.functions.len() as u32)
+            .filter(|&index| program.functions[index as usize].frame_size == 0)
+            .collect();
+        let mut source = String::new();
+        loop {
+            source.clear();
+            let mut dropped = false;
+            for index in 0..program.functions.len() as u32 {
+                if !supported.contains(&index) {
+                    continue;
+                }
+                match translate(program, index, data, wrap, &supported) {
+                    Some(text) => source.push_str(&text),
+                    None => {
+                        supported.remove(&index);
+                        dropped = true;
+                    }
+                }
+            }
+            if !dropped {
+                break;
+            }
+        }
+        if supported.is_empty() {
+            return None;
+        }
+
+        let ast = X86_64AssemblyParser::new()
+            .parse(&source)
+            .map_err(|e| log::warn!("native tier: {:?}", e))
+            .ok()?;
+        let assembled = assembler::assemble(&X86_64InstructionEncoder::new(), &ast.blocks)
+            .map_err(|e| log::warn!("native tier: {:?}", e))
+            .ok()?;
+        let entries = program
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(index, function)| {
+                let offset = *assembled.labels.get(&entry_label(index as u32))?;
+                Some((offset, function.parameters))
+            })
+            .collect();
+        log::debug!("native tier: {} of {} functions, {} bytes", supported.len(), program.functions.len(), assembled.code.len());
+
+        // SAFETY: a fresh private mapping, written before it's made executable
+        unsafe {
+            let page = libc::sysconf(libc::_SC_PAGESIZE).max(4096) as usize;
+            let size = assembled.code.len().next_multiple_of(page);
+            let memory = libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if memory == libc::MAP_FAILED {
+                return None;
+            }
+            std::ptr::copy_nonoverlapping(assembled.code.as_ptr(), memory as *mut u8, assembled.code.len());
+            if libc::mprotect(memory, size, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                libc::munmap(memory, size);
+                return None;
+            }
+            Some(NativeCode { memory: memory as *mut u8, size, entries })
+        }
+    }
+
+    pub fn compiled(&self, function: u32) -> bool {
+        self.entries.get(function as usize).is_some_and(Option::is_some)
+    }
+
+    /// Run compiled `function`; missing arguments are zero, as in the VM
+    ///
+    /// # Safety
+    ///
+    /// `function` must be compiled, and the program's data still loaded
+    /// where `compile` was told it is.
+    pub unsafe fn call(&self, function: u32, arguments: &[u64]) -> u64 {
+        let (offset, parameters) = self.entries[function as usize].expect("a compiled function");
+        let entry = std::mem::transmute::<*mut u8, Entry>(self.memory.add(offset));
+        if arguments.len() >= parameters as usize {
+            entry(arguments.as_ptr())
+        } else {
+            let mut padded = arguments.to_vec();
+            padded.resize(parameters as usize, 0);
+            entry(padded.as_ptr())
+        }
+    }
+}
+
+impl Drop for NativeCode {
+    fn drop(&mut self) {
+        // SAFETY: mapped by `compile`, and no compiled code is running
+        unsafe { libc::munmap(self.memory as *mut libc::c_void, self.size) };
+    }
+}
+
+/// Whether an asm block's code assembles on its own, so a template the
+/// tables can't encode only keeps its function out of the tier
+fn assembles(code: &str) -> bool {
+    X86_64AssemblyParser::new()
+        .parse(code)
+        .is_ok_and(|ast| assembler::assemble(&X86_64InstructionEncoder::new(), &ast.blocks).is_ok())
+}
+
+fn entry_label(function: u32) -> String {
+    format!("f{}", function)
+}
+
+fn slot(register: Reg) -> String {
+    format!("qword ptr [rsp + {}]", register as usize * 8)
+}
+
+/// Condition code of `comparison`, or of its negation
+fn condition(comparison: Comparison, holds: bool) -> &'static str {
+    match (comparison, holds) {
+        (Comparison::Eq, true) | (Comparison::Ne, false) => "e",
+        (Comparison::Ne, true) | (Comparison::Eq, false) => "ne",
+        (Comparison::LtS, true) => "l",
+        (Comparison::LtS, false) => "ge",
+        (Comparison::LeS, true) => "le",
+        (Comparison::LeS, false) => "g",
+        (Comparison::LtU, true) => "b",
+        (Comparison::LtU, false) => "ae",
+        (Comparison::LeU, true) => "be",
+        (Comparison::LeU, false) => "a",
+    }
+}
+
+/// Assembly for function `index`, or `None` if it does something the tier
+/// doesn't support
+fn translate(program: &Program, index: u32, data: u64, wrap: bool, supported: &HashSet<u32>) -> Option<String> {
+    let function: &Function = &program.functions[index as usize];
+    let label = |pc: u32| format!("f{}_{}", index, pc);
+    let mut out = String::new();
+    macro_rules! emit {
+        ($($arg:tt)*) => {{
+            out.push_str("    ");
+            out.push_str(&format!($($arg)*));
+            out.push('\n');
+        }};
+    }
+
+    // Only instructions execution can reach may be jumped to: not the
+    // ones inside a superinstruction
+    let mut starts = HashSet::new();
+    let mut pc = 0;
+    while pc < function.code.len() {
+        starts.insert(pc as u32);
+        pc += function.code[pc].width();
+    }
+    let targets: HashSet<u32> = function.code.iter().filter_map(Instruction::target).collect();
+    if !targets.is_subset(&starts) {
+        return None;
+    }
+
+    out.push_str(&format!("{}:\n", entry_label(index)));
+    emit!("push rbp");
+    emit!("mov rbp, rsp");
+    emit!("sub rsp, {}", (function.registers as usize * 8).next_multiple_of(16));
+    for register in 0..function.registers {
+        if register < function.parameters {
+            emit!("mov rax, qword ptr [rdi + {}]", register as usize * 8);
+        } else if register == function.parameters {
+            emit!("xor eax, eax");
+        }
+        emit!("mov {}, rax", slot(register));
+    }
+
+    macro_rules! signed {
+        ($op:expr, $dst:expr, $a:expr, $b:expr, $bits:expr) => {{
+            if !wrap {
+                return None;
+            }
+            emit!("mov rax, {}", slot($a));
+            match $op {
+                SignedOp::Add => emit!("add rax, {}", slot($b)),
+                SignedOp::Sub => emit!("sub rax, {}", slot($b)),
+                SignedOp::Mul => emit!("imul rax, {}", slot($b)),
+                SignedOp::Neg => emit!("neg rax"),
+                // Division faults and out-of-range shifts go to the runtime
+                SignedOp::Div | SignedOp::Rem | SignedOp::Shl => return None,
+            }
+            if $bits < 64 {
+                emit!("shl rax, {}", 64 - $bits as u32);
+                emit!("sar rax, {}", 64 - $bits as u32);
+            }
+            emit!("mov {}, rax", slot($dst));
+        }};
+    }
+    macro_rules! compare {
+        ($comparison:expr, $dst:expr, $a:expr, $b:expr) => {{
+            emit!("mov rax, {}", slot($a));
+            emit!("cmp rax, {}", $b);
+            emit!("set{} cl", condition($comparison, true));
+            emit!("movzx ecx, cl");
+            emit!("mov {}, rcx", slot($dst));
+        }};
+    }
+
+    let mut pc = 0;
+    while pc < function.code.len() {
+        let instruction = function.code[pc];
+        if targets.contains(&(pc as u32)) {
+            out.push_str(&format!("{}:\n", label(pc as u32)));
+        }
+        pc += instruction.width();
+
+        if let Some((comparison, dst, a, b)) = Comparison::of(instruction) {
+            compare!(comparison, dst, a, slot(b));
+            continue;
+        }
+        match instruction {
+            Instruction::Move { dst, src } => {
+                emit!("mov rax, {}", slot(src));
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::Int { dst, value } => emit!("mov {}, {}", slot(dst), value),
+            Instruction::Const { dst, index } => {
+                emit!("mov rax, {}", program.constants[index as usize] as i64);
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::Add { dst, a, b }
+            | Instruction::Sub { dst, a, b }
+            | Instruction::Mul { dst, a, b }
+            | Instruction::And { dst, a, b }
+            | Instruction::Or { dst, a, b }
+            | Instruction::Xor { dst, a, b } => {
+                let mnemonic = match instruction {
+                    Instruction::Add { .. } => "add",
+                    Instruction::Sub { .. } => "sub",
+                    Instruction::Mul { .. } => "imul",
+                    Instruction::And { .. } => "and",
+                    Instruction::Or { .. } => "or",
+                    _ => "xor",
+                };
+                emit!("mov rax, {}", slot(a));
+                emit!("{} rax, {}", mnemonic, slot(b));
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::AddImm { dst, a, value } => {
+                emit!("mov rax, {}", slot(a));
+                emit!("add rax, {}", value);
+                emit!("mov {}, rax", slot(dst));
+            }
+            // Counts are taken mod 64, as `wrapping_shl` does
+            Instruction::Shl { dst, a, b, .. } | Instruction::ShrU { dst, a, b, .. } | Instruction::ShrS { dst, a, b, .. } => {
+                let mnemonic = match instruction {
+                    Instruction::Shl { .. } => "shl",
+                    Instruction::ShrU { .. } => "shr",
+                    _ => "sar",
+                };
+                emit!("mov rax, {}", slot(a));
+                emit!("mov rcx, {}", slot(b));
+                emit!("{} rax, cl", mnemonic);
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::Signed { op, dst, a, b, bits } => signed!(op, dst, a, b, bits),
+            Instruction::Neg { dst, src } | Instruction::Not { dst, src } => {
+                emit!("mov rax, {}", slot(src));
+                emit!("{} rax", if matches!(instruction, Instruction::Neg { .. }) { "neg" } else { "not" });
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::LogicalNot { dst, src } | Instruction::Test { dst, src } => {
+                emit!("cmp {}, 0", slot(src));
+                emit!("set{} cl", if matches!(instruction, Instruction::LogicalNot { .. }) { "e" } else { "ne" });
+                emit!("movzx ecx, cl");
+                emit!("mov {}, rcx", slot(dst));
+            }
+            Instruction::Extend { dst, src, bits, signed } => {
+                emit!("mov rax, {}", slot(src));
+                if bits < 64 {
+                    emit!("shl rax, {}", 64 - bits as u32);
+                    emit!("{} rax, {}", if signed { "sar" } else { "shr" }, 64 - bits as u32);
+                }
+                emit!("mov {}, rax", slot(dst));
+            }
+
+            Instruction::FAdd { dst, a, b }
+            | Instruction::FSub { dst, a, b }
+            | Instruction::FMul { dst, a, b }
+            | Instruction::FDiv { dst, a, b } => {
+                let mnemonic = match instruction {
+                    Instruction::FAdd { .. } => "addsd",
+                    Instruction::FSub { .. } => "subsd",
+                    Instruction::FMul { .. } => "mulsd",
+                    _ => "divsd",
+                };
+                emit!("movsd xmm0, {}", slot(a));
+                emit!("{} xmm0, {}", mnemonic, slot(b));
+                emit!("movsd {}, xmm0", slot(dst));
+            }
+            Instruction::FNeg { dst, src } => {
+                emit!("mov rax, {}", slot(src));
+                emit!("mov rcx, 0x8000000000000000");
+                emit!("xor rax, rcx");
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::RoundF32 { dst, src } => {
+                emit!("movsd xmm0, {}", slot(src));
+                emit!("cvtsd2ss xmm0, xmm0");
+                emit!("cvtss2sd xmm0, xmm0");
+                emit!("movsd {}, xmm0", slot(dst));
+            }
+            Instruction::IntToFloat { dst, src, signed: true } => {
+                emit!("cvtsi2sd xmm0, {}", slot(src));
+                emit!("movsd {}, xmm0", slot(dst));
+            }
+
+            Instruction::Load { dst, address, kind } => {
+                emit!("mov rax, {}", slot(address));
+                match kind {
+                    Kind::I8 => emit!("movsx rax, byte ptr [rax]"),
+                    Kind::U8 => emit!("movzx eax, byte ptr [rax]"),
+                    Kind::I16 => emit!("movsx rax, word ptr [rax]"),
+                    Kind::U16 => emit!("movzx eax, word ptr [rax]"),
+                    Kind::I32 => emit!("movsxd rax, dword ptr [rax]"),
+                    Kind::U32 => emit!("mov eax, dword ptr [rax]"),
+                    Kind::I64 | Kind::U64 | Kind::F64 => emit!("mov rax, qword ptr [rax]"),
+                    Kind::F32 => {
+                        emit!("cvtss2sd xmm0, dword ptr [rax]");
+                        emit!("movq rax, xmm0");
+                    }
+                }
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::Store { address, src, kind } => {
+                emit!("mov rax, {}", slot(address));
+                emit!("mov rcx, {}", slot(src));
+                match kind {
+                    Kind::I8 | Kind::U8 => emit!("mov byte ptr [rax], cl"),
+                    Kind::I16 | Kind::U16 => emit!("mov word ptr [rax], cx"),
+                    Kind::I32 | Kind::U32 => emit!("mov dword ptr [rax], ecx"),
+                    Kind::I64 | Kind::U64 | Kind::F64 => emit!("mov qword ptr [rax], rcx"),
+                    Kind::F32 => {
+                        emit!("movq xmm0, rcx");
+                        emit!("cvtsd2ss xmm0, xmm0");
+                        emit!("movss dword ptr [rax], xmm0");
+                    }
+                }
+            }
+            Instruction::GlobalAddress { dst, offset } => {
+                emit!("mov rax, {}", data.wrapping_add(offset as u64) as i64);
+                emit!("mov {}, rax", slot(dst));
+            }
+
+            Instruction::Jump { target } => emit!("jmp {}", label(target)),
+            Instruction::JumpIfZero { condition, target } | Instruction::JumpIfNonZero { condition, target } => {
+                emit!("cmp {}, 0", slot(condition));
+                emit!("j{} {}", if matches!(instruction, Instruction::JumpIfZero { .. }) { "e" } else { "ne" }, label(target));
+            }
+            // The callee reads exactly its parameters from the arguments
+            Instruction::Call { dst, function: callee, arguments, count } => {
+                if !supported.contains(&callee) || count as u16 != program.functions[callee as usize].parameters {
+                    return None;
+                }
+                emit!("lea rdi, [rsp + {}]", arguments as usize * 8);
+                emit!("call {}", entry_label(callee));
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::Return { src } => {
+                emit!("mov rax, {}", slot(src));
+                emit!("leave");
+                emit!("ret");
+            }
+            Instruction::ReturnVoid => {
+                emit!("xor eax, eax");
+                emit!("leave");
+                emit!("ret");
+            }
+            Instruction::Statement { .. } => {}
+            // Operand slots are addressed past the saved registers' pushes
+            Instruction::Asm { index, operands, count } => {
+                let block = &program.asm[index as usize];
+                if !assembles(&block.code) {
+                    return None;
+                }
+                let pushed = block.saved.len() * 8;
+                let operand = |i: usize| format!("qword ptr [rsp + {}]", (operands as usize + i) * 8 + pushed);
+                for register in &block.saved {
+                    emit!("push {}", register);
+                }
+                for (i, register) in block.registers.iter().enumerate().take(count as usize) {
+                    emit!("mov {}, {}", register, operand(i));
+                }
+                for line in block.code.lines() {
+                    if line.ends_with(':') {
+                        out.push_str(&format!("{}\n", line));
+                    } else {
+                        emit!("{}", line);
+                    }
+                }
+                for (i, register) in block.registers.iter().enumerate().take(block.outputs as usize) {
+                    emit!("mov {}, {}", operand(i), register);
+                }
+                for register in block.saved.iter().rev() {
+                    emit!("pop {}", register);
+                }
+            }
+
+            Instruction::SignedImm { op, dst, a, temp, value, bits } => {
+                emit!("mov {}, {}", slot(temp), value);
+                signed!(op, dst, a, temp, bits);
+            }
+            Instruction::SignedMove { op, dst, a, b, temp, bits } => {
+                signed!(op, temp, a, b, bits);
+                emit!("mov {}, rax", slot(dst));
+            }
+            Instruction::CompareImm { comparison, dst, a, temp, value } => {
+                emit!("mov {}, {}", slot(temp), value);
+                compare!(comparison, dst, a, value);
+            }
+            // `mov` and `movzx` leave the flags for the jump
+            Instruction::CompareJump { comparison, dst, a, b, target, when } => {
+                compare!(comparison, dst, a, slot(b));
+                emit!("j{} {}", condition(comparison, when), label(target));
+            }
+            Instruction::CompareImmJump { comparison, dst, a, temp, value, target, when } => {
+                emit!("mov {}, {}", slot(temp), value);
+                compare!(comparison, dst, a, value);
+                emit!("j{} {}", condition(comparison, when), label(target));
+            }
+            Instruction::MoveJump { dst, src, target } => {
+                emit!("mov rax, {}", slot(src));
+                emit!("mov {}, rax", slot(dst));
+                emit!("jmp {}", label(target));
+            }
+
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+// Example usage:
+/*
+fn fib(program: &Program, data: u64) -> Option<u64> {
+    let native = NativeCode::compile(program, data, true)?;
+    let fib = program.function("fib")?;
+    // SAFETY: the program's data is loaded at `data`
+    native.compiled(fib).then(|| unsafe { native.call(fib, &[30]) })
+}
+*/
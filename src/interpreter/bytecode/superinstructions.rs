@@ -0,0 +1,87 @@
+// src/interpreter/bytecode/superinstructions.rs
+//! Superinstruction fusion
+//! Replaces common short sequences with one instruction that does the work
+//! of all of them, so the dispatch loop runs once instead of two or three
+//! times: a small constant and the signed operation or comparison that uses
+//! it, a comparison and the branch on its result, a signed operation and
+//! the move of its result into a variable, a move and a jump.
+//!
+//! The superinstruction is written over the first of the sequence and the
+//! rest stays where it was, so jump targets keep their meaning and a jump
+//! into the middle still runs the original instructions. Every register the
+//! sequence wrote is still written, which keeps the pass free of liveness
+//! analysis.
+
+use super::{Comparison, Instruction, Program};
+
+/// Fuse the sequences in every function of `program`; returns how many
+pub fn fuse(program: &mut Program) -> usize {
+    let mut fused = 0;
+    for function in &mut program.functions {
+        // Sequences are matched against the original code, not against
+        // what an earlier slot was fused into
+        let original = function.code.clone();
+        for pc in 0..original.len() {
+            let superinstruction = original
+                .get(pc..pc + 3)
+                .and_then(|sequence| triple(sequence[0], sequence[1], sequence[2]))
+                .or_else(|| original.get(pc..pc + 2).and_then(|sequence| pair(sequence[0], sequence[1])));
+            if let Some(superinstruction) = superinstruction {
+                function.code[pc] = superinstruction;
+                fused += 1;
+            }
+        }
+    }
+    fused
+}
+
+fn triple(first: Instruction, second: Instruction, third: Instruction) -> Option<Instruction> {
+    let Instruction::CompareImm { comparison, dst, a, temp, value } = pair(first, second)? else { return None };
+    match third {
+        Instruction::JumpIfZero { condition, target } if condition == dst => {
+            Some(Instruction::CompareImmJump { comparison, dst, a, temp, value, target, when: false })
+        }
+        Instruction::JumpIfNonZero { condition, target } if condition == dst => {
+            Some(Instruction::CompareImmJump { comparison, dst, a, temp, value, target, when: true })
+        }
+        _ => None,
+    }
+}
+
+fn pair(first: Instruction, second: Instruction) -> Option<Instruction> {
+    match (first, second) {
+        (Instruction::Int { dst: temp, value }, Instruction::Signed { op, dst, a, b, bits }) if b == temp && a != temp => {
+            Some(Instruction::SignedImm { op, dst, a, temp, value, bits })
+        }
+        (Instruction::Signed { op, dst: temp, a, b, bits }, Instruction::Move { dst, src }) if src == temp => {
+            Some(Instruction::SignedMove { op, dst, a, b, temp, bits })
+        }
+        (Instruction::Move { dst, src }, Instruction::Jump { target }) => Some(Instruction::MoveJump { dst, src, target }),
+        (Instruction::Int { dst: temp, value }, comparison) => match Comparison::of(comparison)? {
+            (comparison, dst, a, b) if b == temp && a != temp => {
+                Some(Instruction::CompareImm { comparison, dst, a, temp, value })
+            }
+            _ => None,
+        },
+        (comparison, Instruction::JumpIfZero { condition, target }) => match Comparison::of(comparison)? {
+            (comparison, dst, a, b) if condition == dst => {
+                Some(Instruction::CompareJump { comparison, dst, a, b, target, when: false })
+            }
+            _ => None,
+        },
+        (comparison, Instruction::JumpIfNonZero { condition, target }) => match Comparison::of(comparison)? {
+            (comparison, dst, a, b) if condition == dst => {
+                Some(Instruction::CompareJump { comparison, dst, a, b, target, when: true })
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Example usage:
+/*
+let mut program = bytecode::compile(&unit)?;
+let fused = bytecode::fuse(&mut program);
+log::debug!("{} superinstructions", fused);
+*/
@@ -0,0 +1,1409 @@
+// src/interpreter/bytecode/vm.rs
+//! The bytecode dispatch loop
+//! One `Vm` runs one `Program` against the runtime the tree walker would
+//! use, with the same hooks: statements go through `trace_statement`
+//! (limits, scheduling, stack capture), signed overflow through
+//! `signed_arithmetic`, calls through `enter_function`/`leave_function`,
+//! and `--sanitize=undefined` failures through `undefined_behaviour`.
+//!
+//! Interpreted calls don't recurse on the host stack: every frame's
+//! registers live in one vector and its frame memory in one block, so
+//! addresses of locals stay valid while the frame is active. A function
+//! pointer to an interpreted function is `FUNCTION_TAG | index`, which is
+//! never a host address; native code can't call it.
+//!
+//! Declared-only functions are called natively, found in the runtime's
+//! library (`set_library`) or else with `dlsym`. A few
+//! are intercepted: `exit` ends the run, `fe*` act on the host FPU through
+//! `runtime::fenv`, and while resource limits are set
+//! the allocation and stdout functions are charged to them first. With
+//! `with_leak_detection` the allocation functions are recorded in
+//! `memory::leaks` too.
+//! `ic_coroutine_*` never leaves the VM: each coroutine has its own frames,
+//! registers and frame memory, swapped in when it's resumed. VLAs come
+//! from the runtime's VLA stack, which only the main coroutine uses.
+//!
+//! The loop is compiled twice: bounds-checked (`Dispatch::Switch`), and
+//! unchecked over code `Program::verify` accepted (`Dispatch::Threaded`).
+//! With `with_native`, calls to the functions `native` compiled run as
+//! machine code instead; inline assembly only runs there, and reaching it
+//! in the loop is an error. With `attach_tiers`, calls and loop back-edges
+//! are counted by a `jit::tiered::TieredEngine`, and calls to the functions
+//! it has compiled are native calls into its code.
+//!
+//! Started with `start_main`, the VM is a guest of `runtime::async_host`
+//! (see `guest`): `step` runs until a budget of statements is spent, and
+//! `read`, `write`, `accept`, `close` and the sleeps suspend it with the
+//! call for the driver to make, saving the frame as a call does. The
+//! descriptors they take are the driver's.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use crate::abi::aggregate::{marshal, Abi, Argument, CType};
+use crate::abi::call;
+use crate::abi::varargs::marshal_variadic;
+use crate::interpreter::c_runtime::{CRuntimeEnvironment, RuntimeError};
+use crate::interpreter::record::SourcePosition;
+use crate::interpreter::vm_stats::VmStats;
+use crate::jit::tiered::{TieredEngine, TieredFunction};
+use crate::jit::JITValue;
+use crate::memory::leaks::{self, Allocator};
+use crate::optimizer::overflow::{OverflowMode, SignedOp};
+use crate::optimizer::sanitize::CheckKind;
+use crate::runtime::async_host::{GuestSyscall, StepResult, SyscallReply};
+use crate::runtime::coroutine::{self, CoroutineCall, CoroutineError, CoroutineId, Scheduler, MAIN};
+use crate::runtime::fenv::{FenvCall, FE_DFL_ENV};
+use crate::runtime::limits::LimitExceeded;
+use crate::runtime::setjmp::Activation;
+use crate::runtime::vla::VlaMark;
+use super::native::NativeCode;
+use super::{BytecodeError, Function, Instruction, Kind, Program, RelocationTarget, Signature};
+
+/// High bits of an interpreted function pointer
+const FUNCTION_TAG: u64 = 0xFFFE << 48;
+const TAG_MASK: u64 = 0xFFFF << 48;
+
+/// Frame memory and registers, without `--max-stack`
+const DEFAULT_STACK: usize = 8 << 20;
+
+/// Addresses below this are null pointer dereferences
+const NULL_PAGE: u64 = 4096;
+
+/// How the dispatch loop reaches registers and instructions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Bounds-check every register access and instruction fetch; the
+    /// reference the threaded loop is measured and debugged against
+    Switch,
+    /// The same `match`, compiled to a jump table, over a raw pointer to
+    /// the register window, with fetches from verified code unchecked.
+    /// Rust has no computed goto, and tables of handlers per opcode, called
+    /// or tail-called in turn, measured slower than this jump table
+    #[default]
+    Threaded,
+}
+
+struct Frame {
+    function: u32,
+    /// Where the caller resumes, while this frame's callee runs
+    pc: usize,
+    base: usize,
+    /// Start of the frame memory
+    memory: usize,
+    /// Absolute register of the caller's result
+    dst: usize,
+    activation: Activation,
+}
+
+/// What an interpreted coroutine, or the code outside all of them, leaves
+/// behind while another runs
+#[derive(Default)]
+struct Context {
+    registers: Vec<u64>,
+    frames: Vec<Frame>,
+    stack: Box<[u128]>,
+    stack_size: usize,
+    stack_top: usize,
+    /// Absolute register of the value passed back when it runs again
+    pending: usize,
+    /// Function and argument, until the first resume starts it
+    entry: Option<(u32, u64)>,
+}
+
+/// Why execution stopped early
+enum Stop {
+    Exit(i32),
+    Error(RuntimeError),
+    /// A guest spent its budget
+    Yield,
+    /// A guest made a call the driver makes
+    Blocked(GuestSyscall),
+}
+
+impl From<RuntimeError> for Stop {
+    fn from(error: RuntimeError) -> Self {
+        Stop::Error(error)
+    }
+}
+
+/// The engine a VM counts calls for, and its functions by index: `None`
+/// for those it doesn't compile
+struct Tiers {
+    engine: Weak<TieredEngine>,
+    functions: Vec<Option<Arc<TieredFunction>>>,
+}
+
+/// Native functions the VM handles itself, or charges to the limits first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Intercept {
+    Native,
+    Exit,
+    Malloc,
+    Calloc,
+    Realloc,
+    Strdup,
+    Strndup,
+    Free,
+    Puts,
+    Putchar,
+    Printf,
+    Write,
+    Fputs,
+    Fputc,
+    Fwrite,
+    Fprintf,
+    Coroutine(CoroutineCall),
+    Fenv(FenvCall),
+}
+
+impl Intercept {
+    fn of(name: &str) -> Self {
+        match name {
+            "exit" | "_exit" | "_Exit" => Intercept::Exit,
+            "malloc" => Intercept::Malloc,
+            "calloc" => Intercept::Calloc,
+            "realloc" => Intercept::Realloc,
+            "strdup" => Intercept::Strdup,
+            "strndup" => Intercept::Strndup,
+            "free" => Intercept::Free,
+            "puts" => Intercept::Puts,
+            "putchar" => Intercept::Putchar,
+            "printf" => Intercept::Printf,
+            "write" => Intercept::Write,
+            "fputs" => Intercept::Fputs,
+            "fputc" | "putc" => Intercept::Fputc,
+            "fwrite" => Intercept::Fwrite,
+            "fprintf" => Intercept::Fprintf,
+            _ => CoroutineCall::of(name)
+                .map(Intercept::Coroutine)
+                .or_else(|| FenvCall::of(name).map(Intercept::Fenv))
+                .unwrap_or(Intercept::Native),
+        }
+    }
+}
+
+/// Calls a guest suspends at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GuestCall {
+    Read,
+    Write,
+    Accept,
+    Close,
+    Sleep,
+    Usleep,
+    Nanosleep,
+}
+
+impl GuestCall {
+    fn of(name: &str) -> Option<Self> {
+        Some(match name {
+            "read" => GuestCall::Read,
+            "write" => GuestCall::Write,
+            "accept" => GuestCall::Accept,
+            "close" => GuestCall::Close,
+            "sleep" => GuestCall::Sleep,
+            "usleep" => GuestCall::Usleep,
+            "nanosleep" => GuestCall::Nanosleep,
+            _ => return None,
+        })
+    }
+}
+
+/// A VM run by `runtime::async_host`
+struct Guest {
+    /// By external index
+    calls: Vec<Option<GuestCall>>,
+    /// Statements left before yielding
+    budget: u64,
+    /// The call the guest is blocked in: the absolute register of its
+    /// result, and where `read` puts the data
+    blocked: Option<(usize, u64)>,
+    /// Its reply, delivered at the next step
+    reply: Option<SyscallReply>,
+    _arguments: MainArguments,
+}
+
+/// `main`'s `argc`, `argv` and `envp`, and the strings they point to
+struct MainArguments {
+    strings: Vec<CString>,
+    argv: Vec<*const c_char>,
+    _environment: Vec<CString>,
+    envp: Vec<*const c_char>,
+}
+
+impl MainArguments {
+    fn new(args: &[String]) -> Self {
+        let strings: Vec<CString> = args.iter().map(|arg| CString::new(arg.as_str()).unwrap_or_default()).collect();
+        let mut argv: Vec<*const c_char> = strings.iter().map(|arg| arg.as_ptr()).collect();
+        argv.push(std::ptr::null());
+        let environment: Vec<CString> = std::env::vars_os()
+            .filter_map(|(key, value)| {
+                let mut entry = key.into_encoded_bytes();
+                entry.push(b'=');
+                entry.extend(value.into_encoded_bytes());
+                CString::new(entry).ok()
+            })
+            .collect();
+        let mut envp: Vec<*const c_char> = environment.iter().map(|entry| entry.as_ptr()).collect();
+        envp.push(std::ptr::null());
+        MainArguments { strings, argv, _environment: environment, envp }
+    }
+
+    fn values(&self) -> [u64; 3] {
+        [self.strings.len() as u64, self.argv.as_ptr() as u64, self.envp.as_ptr() as u64]
+    }
+}
+
+pub struct Vm<'p, 'r> {
+    program: &'p Program,
+    runtime: &'r mut CRuntimeEnvironment,
+
+    // Memory
+    data: Box<[u128]>,
+    stack: Box<[u128]>,
+    stack_size: usize,
+    stack_limit: Option<usize>,
+
+    // Active calls
+    registers: Vec<u64>,
+    frames: Vec<Frame>,
+    stack_top: usize,
+    coroutines: Scheduler<Context>,
+
+    // Native calls
+    abi: Abi,
+    /// Resolved addresses; 0 until first used
+    externals: Vec<u64>,
+    intercepts: Vec<Intercept>,
+    stdout: Option<u64>,
+
+    // Runtime hooks
+    dispatch: Dispatch,
+    native: Option<NativeCode>,
+    tiers: Option<Tiers>,
+    wrap: bool,
+    /// `--sanitize=undefined`
+    sanitize: bool,
+    /// Heap and output must be charged to the resource limits
+    limited: bool,
+    /// `--detect-leaks`: each function's allocation site
+    leak_sites: Option<Vec<u64>>,
+    guest: Option<Guest>,
+}
+
+impl<'p, 'r> Vm<'p, 'r> {
+    /// Load `program`'s data and resolve its relocations
+    pub fn new(program: &'p Program, runtime: &'r mut CRuntimeEnvironment) -> Result<Self, RuntimeError> {
+        program.verify().map_err(RuntimeError::Bytecode)?;
+        let abi = Abi::host().ok_or_else(|| RuntimeError::ABIError("unsupported host".to_string()))?;
+        let mut data = vec![0u128; program.data.len().div_ceil(16).max(1)].into_boxed_slice();
+        // SAFETY: `data` holds at least `program.data.len()` bytes
+        unsafe { std::ptr::copy_nonoverlapping(program.data.as_ptr(), data.as_mut_ptr() as *mut u8, program.data.len()) };
+
+        let limits = runtime.limits().copied();
+        let stack_limit = limits.and_then(|limits| limits.stack);
+        let stack_size = stack_limit.unwrap_or(DEFAULT_STACK);
+        let limited = limits.is_some_and(|limits| limits.heap.is_some() || limits.output.is_some());
+        let wrap = runtime.overflow_mode() == OverflowMode::Wrap;
+        let sanitize = runtime.undefined_checks();
+
+        let mut vm = Vm {
+            program,
+            runtime,
+            data,
+            // Zeroed pages are only committed when touched
+            stack: vec![0u128; stack_size.div_ceil(16)].into_boxed_slice(),
+            stack_size,
+            stack_limit,
+            registers: Vec::with_capacity(1024),
+            frames: Vec::with_capacity(64),
+            stack_top: 0,
+            coroutines: Scheduler::new(Context::default()),
+            abi,
+            externals: vec![0; program.externals.len()],
+            intercepts: program.externals.iter().map(|external| Intercept::of(&external.name)).collect(),
+            stdout: None,
+            dispatch: Dispatch::default(),
+            native: None,
+            tiers: None,
+            wrap,
+            sanitize,
+            limited,
+            leak_sites: None,
+            guest: None,
+        };
+        if limited {
+            // SAFETY: `stdout` is a `FILE *` object
+            vm.stdout = vm.symbol("stdout").map(|address| unsafe { *(address as *const u64) });
+        }
+
+        let data_base = vm.data.as_ptr() as u64;
+        for relocation in &program.relocations {
+            let target = match relocation.target {
+                RelocationTarget::Data(offset) => data_base + offset as u64,
+                RelocationTarget::Function(index) => FUNCTION_TAG | index as u64,
+                RelocationTarget::External(index) => vm.resolve(index)?,
+            };
+            let value = target.wrapping_add(relocation.addend as u64);
+            // SAFETY: the compiler placed the relocation inside the data
+            unsafe { ((data_base + relocation.offset as u64) as *mut u64).write_unaligned(value) };
+        }
+        Ok(vm)
+    }
+
+    pub fn with_dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.dispatch = dispatch;
+        self
+    }
+
+    /// Record the program's allocations for `leaks::report`. Its data is
+    /// where pointers to them may be kept, so the report has to be made
+    /// while the VM is alive.
+    pub fn with_leak_detection(mut self) -> Self {
+        leaks::add_root(self.data.as_ptr() as usize, self.program.data.len());
+        self.leak_sites = Some(self.program.functions.iter().map(|function| leaks::register_site(&function.name, "<bytecode>")).collect());
+        self
+    }
+
+    /// Run what `native` can compile as machine code. Compiled code skips
+    /// the runtime's hooks, so this does nothing while limits, tracing,
+    /// leak detection or `--vm-stats` are on.
+    pub fn with_native(mut self) -> Self {
+        if !self.runtime.traces_execution() && !VmStats::enabled() && self.leak_sites.is_none() {
+            self.native = NativeCode::compile(self.program, self.data.as_ptr() as u64, self.wrap);
+        }
+        self
+    }
+
+    /// Count calls and back-edges of the functions registered with `engine`
+    /// under their names, and call the code it compiles for them. Compiled
+    /// code skips the runtime's hooks, so this does nothing while limits,
+    /// tracing, `--vm-stats` or `--sanitize` are on.
+    pub fn attach_tiers(&mut self, engine: &Arc<TieredEngine>) {
+        if self.runtime.traces_execution() || VmStats::enabled() || self.sanitize {
+            return;
+        }
+        let functions = self.program.functions.iter().map(|function| engine.function(&function.name)).collect();
+        self.tiers = Some(Tiers { engine: Arc::downgrade(engine), functions });
+    }
+
+    /// Call the function `name` with `args`, converted to its parameter
+    /// types. `exit` ends the process, as it would in compiled code.
+    pub fn call(&mut self, name: &str, args: &[JITValue]) -> Result<JITValue, RuntimeError> {
+        let program = self.program;
+        let index = program.function(name).ok_or_else(|| RuntimeError::Bytecode(BytecodeError::UndefinedSymbol(name.to_string())))?;
+        let signature = program.functions[index as usize].signature.as_ref();
+        let arguments: Vec<u64> = match signature {
+            Some(signature) => signature.parameters.iter().zip(args).map(|(&kind, arg)| canonical(arg.to_raw(), kind)).collect(),
+            None => args.iter().map(JITValue::to_raw).collect(),
+        };
+        match self.execute(index, &arguments) {
+            Ok(value) => Ok(signature.and_then(|signature| signature.result).map_or(JITValue::Void, |kind| argument(kind, value))),
+            Err(Stop::Exit(code)) => {
+                // SAFETY: flushing every stream is what `exit` does
+                unsafe { libc::fflush(std::ptr::null_mut()) };
+                std::process::exit(code)
+            }
+            Err(Stop::Error(e)) => Err(e),
+            Err(Stop::Yield | Stop::Blocked(_)) => unreachable!("only a guest suspends"),
+        }
+    }
+
+    /// Run `main(argc, argv, envp)` and return its exit status; `exit`
+    /// ends the run the same way
+    pub fn run_main(&mut self, args: &[String]) -> Result<i32, RuntimeError> {
+        let main = self.program.function("main").ok_or(RuntimeError::Bytecode(BytecodeError::NoMain))?;
+        let arguments = MainArguments::new(args).values();
+        let count = (self.program.functions[main as usize].parameters as usize).min(arguments.len());
+        let result = self.execute(main, &arguments[..count]);
+        self.finish(result)
+    }
+
+    /// Set up `main(argc, argv, envp)` to be run by `step`, as a guest of
+    /// `runtime::async_host`
+    pub fn start_main(&mut self, args: &[String]) -> Result<(), RuntimeError> {
+        let main = self.program.function("main").ok_or(RuntimeError::Bytecode(BytecodeError::NoMain))?;
+        let main_arguments = MainArguments::new(args);
+        let arguments = main_arguments.values();
+        let count = (self.program.functions[main as usize].parameters as usize).min(arguments.len());
+        self.guest = Some(Guest {
+            calls: self.program.externals.iter().map(|external| GuestCall::of(&external.name)).collect(),
+            budget: 0,
+            blocked: None,
+            reply: None,
+            _arguments: main_arguments,
+        });
+        self.registers.extend_from_slice(&arguments[..count]);
+        match self.push_frame(main, 0, count, 0) {
+            Ok(()) => Ok(()),
+            Err(Stop::Error(e)) => Err(e),
+            Err(_) => unreachable!("pushing a frame only fails"),
+        }
+    }
+
+    /// Run the guest `start_main` set up until it has run `budget`
+    /// statements, blocks in a call or ends
+    pub fn step(&mut self, budget: u64) -> Result<StepResult, RuntimeError> {
+        let guest = self.guest.as_mut().expect("a guest started with start_main");
+        guest.budget = budget.max(1);
+        if let Some(reply) = guest.reply.take() {
+            self.deliver(reply);
+        }
+        match self.run() {
+            Err(Stop::Yield) => Ok(StepResult::Yield),
+            Err(Stop::Blocked(call)) => Ok(StepResult::Blocked(call)),
+            result => {
+                self.unwind(0);
+                self.finish(result).map(StepResult::Exited)
+            }
+        }
+    }
+
+    /// The result of the call the guest is blocked in, for the next `step`
+    pub fn resume_with(&mut self, reply: SyscallReply) {
+        if let Some(guest) = &mut self.guest {
+            guest.reply = Some(reply);
+        }
+    }
+
+    /// A finished run's exit status
+    fn finish(&mut self, result: Result<u64, Stop>) -> Result<i32, RuntimeError> {
+        // SAFETY: flushing every stream is what `exit` does
+        unsafe { libc::fflush(std::ptr::null_mut()) };
+        match result {
+            Ok(value) => Ok(value as i32),
+            Err(Stop::Exit(code)) => Ok(code),
+            Err(Stop::Error(e)) => Err(e),
+            Err(Stop::Yield | Stop::Blocked(_)) => unreachable!("a suspended guest hasn't finished"),
+        }
+    }
+
+    fn execute(&mut self, function: u32, arguments: &[u64]) -> Result<u64, Stop> {
+        let start = self.registers.len();
+        self.registers.extend_from_slice(arguments);
+        let result = self.push_frame(function, start, arguments.len(), 0).and_then(|()| self.run());
+        self.unwind(start);
+        result
+    }
+
+    /// Unwind what an error left behind, down to register `start`
+    fn unwind(&mut self, start: usize) {
+        while let Some(frame) = self.frames.pop() {
+            self.runtime.leave_function(frame.activation);
+            self.runtime.vm_stats_mut().exit_function();
+        }
+        self.registers.truncate(start);
+        self.stack_top = 0;
+    }
+
+    fn push_frame(&mut self, function: u32, arguments: usize, count: usize, dst: usize) -> Result<(), Stop> {
+        let callee = &self.program.functions[function as usize];
+        let base = self.registers.len();
+        let memory = self.stack_top.next_multiple_of(16);
+        let top = memory + callee.frame_size as usize;
+        if top + (base + callee.registers as usize) * 8 > self.stack_size {
+            return Err(self.stack_overflow().into());
+        }
+        self.registers.resize(base + callee.registers as usize, 0);
+        let count = count.min(callee.parameters as usize);
+        self.registers.copy_within(arguments..arguments + count, base);
+        // Uninitialized locals read as zero, every run
+        // SAFETY: `top` is within the stack, checked above
+        unsafe { std::ptr::write_bytes((self.stack.as_mut_ptr() as *mut u8).add(memory), 0, callee.frame_size as usize) };
+        self.stack_top = top;
+
+        let activation = self.runtime.enter_function();
+        self.runtime.vm_stats_mut().enter_function(&callee.name);
+        self.frames.push(Frame { function, pc: 0, base, memory, dst, activation });
+        Ok(())
+    }
+
+    /// Pop the current frame; the caller's, if any, is current again
+    fn pop_frame(&mut self) -> Frame {
+        let frame = self.frames.pop().expect("a frame to return from");
+        self.runtime.leave_function(frame.activation);
+        self.runtime.vm_stats_mut().exit_function();
+        self.registers.truncate(frame.base);
+        self.stack_top = frame.memory;
+        frame
+    }
+
+    fn stack_overflow(&self) -> RuntimeError {
+        match self.stack_limit {
+            // A coroutine's stack is the size it was created with
+            Some(limit) if self.coroutines.current() == MAIN => RuntimeError::Limit(LimitExceeded::Stack(limit)),
+            _ => RuntimeError::FatalSignal(libc::SIGSEGV),
+        }
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        self.runtime.library_symbol(name).or_else(|| host_symbol(name))
+    }
+
+    fn resolve(&mut self, index: u32) -> Result<u64, RuntimeError> {
+        let cached = self.externals[index as usize];
+        if cached != 0 {
+            return Ok(cached);
+        }
+        let name = &self.program.externals[index as usize].name;
+        let address = self.symbol(name).ok_or_else(|| RuntimeError::Bytecode(BytecodeError::UndefinedSymbol(name.clone())))?;
+        self.externals[index as usize] = address;
+        Ok(address)
+    }
+
+    fn run(&mut self) -> Result<u64, Stop> {
+        match self.dispatch {
+            Dispatch::Switch => self.dispatch_loop::<true>(),
+            Dispatch::Threaded => self.dispatch_loop::<false>(),
+        }
+    }
+
+    /// The interpreter proper; `CHECKED` selects `Dispatch::Switch`
+    fn dispatch_loop<const CHECKED: bool>(&mut self) -> Result<u64, Stop> {
+        let program = self.program;
+        let frame = self.frames.last().expect("an entry frame");
+        let mut function: &'p Function = &program.functions[frame.function as usize];
+        let mut code: &'p [Instruction] = &function.code;
+        let mut base = frame.base;
+        let mut memory = frame.memory;
+        // Zero for a new frame; a guest continues where it was suspended
+        let mut pc = frame.pc;
+        let mut line = 0u32;
+        // Moves when the register vector grows: reset on every call and return
+        let mut window = self.registers[base..].as_mut_ptr();
+
+        macro_rules! reg {
+            ($r:expr) => {
+                *if CHECKED {
+                    &mut self.registers[base + $r as usize]
+                } else {
+                    // SAFETY: `Program::verify` keeps every register inside
+                    // the window, which `push_frame` sized and nothing
+                    // resizes until the next call or return
+                    unsafe { &mut *window.add($r as usize) }
+                }
+            };
+        }
+        macro_rules! float {
+            ($r:expr) => {
+                f64::from_bits(reg!($r))
+            };
+        }
+        macro_rules! binary {
+            ($dst:expr, $a:expr, $b:expr, |$x:ident, $y:ident| $value:expr) => {{
+                let ($x, $y) = (reg!($a), reg!($b));
+                reg!($dst) = $value;
+            }};
+        }
+        // A count of `bits` or more (or a negative one) is undefined
+        macro_rules! shift {
+            ($dst:expr, $a:expr, $b:expr, $bits:expr, |$x:ident, $y:ident| $value:expr) => {{
+                let ($x, $y) = (reg!($a), reg!($b));
+                if self.sanitize && $y >= $bits as u64 {
+                    return Err(self.undefined(CheckKind::ShiftOutOfBounds, line, function));
+                }
+                reg!($dst) = $value;
+            }};
+        }
+        macro_rules! enter {
+            () => {{
+                let frame = self.frames.last().expect("the frame just pushed");
+                function = &program.functions[frame.function as usize];
+                code = &function.code;
+                base = frame.base;
+                memory = frame.memory;
+                pc = 0;
+                window = self.registers[base..].as_mut_ptr();
+            }};
+        }
+        // Continue the current frame where it called out
+        macro_rules! resume {
+            () => {{
+                let frame = self.frames.last().expect("a frame to continue");
+                function = &program.functions[frame.function as usize];
+                code = &function.code;
+                base = frame.base;
+                memory = frame.memory;
+                pc = frame.pc;
+                window = self.registers[base..].as_mut_ptr();
+            }};
+        }
+
+        loop {
+            let instruction = if CHECKED {
+                code[pc]
+            } else {
+                // SAFETY: `Program::verify` keeps jumps and fall-through inside `code`
+                unsafe { *code.get_unchecked(pc) }
+            };
+            pc += 1;
+            if VmStats::enabled() {
+                self.runtime.vm_stats_mut().record_opcode(instruction.name());
+            }
+            match instruction {
+                Instruction::Move { dst, src } => reg!(dst) = reg!(src),
+                Instruction::Int { dst, value } => reg!(dst) = value as i64 as u64,
+                Instruction::Const { dst, index } => reg!(dst) = program.constants[index as usize],
+
+                Instruction::Add { dst, a, b } => binary!(dst, a, b, |x, y| x.wrapping_add(y)),
+                Instruction::Sub { dst, a, b } => binary!(dst, a, b, |x, y| x.wrapping_sub(y)),
+                Instruction::Mul { dst, a, b } => binary!(dst, a, b, |x, y| x.wrapping_mul(y)),
+                Instruction::AddImm { dst, a, value } => reg!(dst) = reg!(a).wrapping_add(value as i64 as u64),
+                Instruction::DivU { dst, a, b } | Instruction::RemU { dst, a, b } => {
+                    let (x, y) = (reg!(a), reg!(b));
+                    if y == 0 {
+                        return Err(self.division_by_zero(line, function));
+                    }
+                    reg!(dst) = if matches!(instruction, Instruction::DivU { .. }) { x / y } else { x % y };
+                }
+                Instruction::And { dst, a, b } => binary!(dst, a, b, |x, y| x & y),
+                Instruction::Or { dst, a, b } => binary!(dst, a, b, |x, y| x | y),
+                Instruction::Xor { dst, a, b } => binary!(dst, a, b, |x, y| x ^ y),
+                Instruction::Shl { dst, a, b, bits } => shift!(dst, a, b, bits, |x, y| x.wrapping_shl(y as u32)),
+                Instruction::ShrU { dst, a, b, bits } => shift!(dst, a, b, bits, |x, y| x.wrapping_shr(y as u32)),
+                Instruction::ShrS { dst, a, b, bits } => shift!(dst, a, b, bits, |x, y| (x as i64).wrapping_shr(y as u32) as u64),
+                Instruction::Signed { op, dst, a, b, bits } => {
+                    let (x, y) = (reg!(a), reg!(b));
+                    reg!(dst) = self.signed(op, x, y, bits, line, function)? as u64;
+                }
+                Instruction::Neg { dst, src } => reg!(dst) = reg!(src).wrapping_neg(),
+                Instruction::Not { dst, src } => reg!(dst) = !reg!(src),
+                Instruction::LogicalNot { dst, src } => reg!(dst) = (reg!(src) == 0) as u64,
+                Instruction::Test { dst, src } => reg!(dst) = (reg!(src) != 0) as u64,
+                Instruction::Extend { dst, src, bits, signed } => reg!(dst) = extend(reg!(src), bits, signed),
+
+                Instruction::Eq { dst, a, b } => binary!(dst, a, b, |x, y| (x == y) as u64),
+                Instruction::Ne { dst, a, b } => binary!(dst, a, b, |x, y| (x != y) as u64),
+                Instruction::LtS { dst, a, b } => binary!(dst, a, b, |x, y| ((x as i64) < (y as i64)) as u64),
+                Instruction::LeS { dst, a, b } => binary!(dst, a, b, |x, y| ((x as i64) <= (y as i64)) as u64),
+                Instruction::LtU { dst, a, b } => binary!(dst, a, b, |x, y| (x < y) as u64),
+                Instruction::LeU { dst, a, b } => binary!(dst, a, b, |x, y| (x <= y) as u64),
+
+                Instruction::FAdd { dst, a, b } => reg!(dst) = (float!(a) + float!(b)).to_bits(),
+                Instruction::FSub { dst, a, b } => reg!(dst) = (float!(a) - float!(b)).to_bits(),
+                Instruction::FMul { dst, a, b } => reg!(dst) = (float!(a) * float!(b)).to_bits(),
+                Instruction::FDiv { dst, a, b } => reg!(dst) = (float!(a) / float!(b)).to_bits(),
+                Instruction::FNeg { dst, src } => reg!(dst) = (-float!(src)).to_bits(),
+                Instruction::FEq { dst, a, b } => reg!(dst) = (float!(a) == float!(b)) as u64,
+                Instruction::FNe { dst, a, b } => reg!(dst) = (float!(a) != float!(b)) as u64,
+                Instruction::FLt { dst, a, b } => reg!(dst) = (float!(a) < float!(b)) as u64,
+                Instruction::FLe { dst, a, b } => reg!(dst) = (float!(a) <= float!(b)) as u64,
+                Instruction::RoundF32 { dst, src } => reg!(dst) = (float!(src) as f32 as f64).to_bits(),
+                Instruction::IntToFloat { dst, src, signed } => {
+                    let value = reg!(src);
+                    reg!(dst) = if signed { value as i64 as f64 } else { value as f64 }.to_bits();
+                }
+                Instruction::FloatToInt { dst, src, signed } => {
+                    let value = float!(src);
+                    reg!(dst) = if signed { value as i64 as u64 } else { value as u64 };
+                }
+
+                Instruction::Load { dst, address, kind } => {
+                    let address = reg!(address);
+                    let address = self.access(address, kind.size(), line, function)?;
+                    // SAFETY: the program's own pointer; a wild one faults as it would natively
+                    reg!(dst) = unsafe { load(address, kind) };
+                }
+                Instruction::Store { address, src, kind } => {
+                    let (address, value) = (reg!(address), reg!(src));
+                    let address = self.access(address, kind.size(), line, function)?;
+                    // SAFETY: as for `Load`
+                    unsafe { store(address, value, kind) };
+                }
+                Instruction::LocalAddress { dst, offset } => {
+                    reg!(dst) = self.stack.as_ptr() as u64 + (memory + offset as usize) as u64;
+                }
+                Instruction::GlobalAddress { dst, offset } => reg!(dst) = self.data.as_ptr() as u64 + offset as u64,
+                Instruction::FunctionAddress { dst, function } => reg!(dst) = FUNCTION_TAG | function as u64,
+                Instruction::ExternalAddress { dst, external } => reg!(dst) = self.resolve(external)?,
+                Instruction::CopyBytes { dst, src, size } => {
+                    let (to, from) = (reg!(dst), reg!(src));
+                    let (to, from) = (self.access(to, 1, line, function)?, self.access(from, 1, line, function)?);
+                    // SAFETY: as for `Load`; struct assignment may overlap
+                    unsafe { std::ptr::copy(from as *const u8, to as *mut u8, size as usize) };
+                }
+                Instruction::ZeroBytes { dst, size } => {
+                    let to = reg!(dst);
+                    let to = self.access(to, 1, line, function)?;
+                    // SAFETY: as for `Load`
+                    unsafe { std::ptr::write_bytes(to as *mut u8, 0, size as usize) };
+                }
+                Instruction::VlaMark { dst } => reg!(dst) = self.runtime.enter_vla_scope().height() as u64,
+                Instruction::VlaAllocate { dst, length, size, zero } => {
+                    // One stack for every coroutine couldn't free in order
+                    if self.coroutines.current() != MAIN {
+                        let what = "variable length arrays in coroutines".to_string();
+                        return Err(RuntimeError::Bytecode(BytecodeError::Unsupported { what, line }).into());
+                    }
+                    let address = self.runtime.allocate_vla(reg!(length) as i64, size as usize)?;
+                    if zero {
+                        // SAFETY: just allocated, `length * size` bytes
+                        unsafe { std::ptr::write_bytes(address, 0, reg!(length) as usize * size as usize) };
+                    }
+                    reg!(dst) = address as u64;
+                }
+                Instruction::VlaRelease { mark } => self.runtime.leave_vla_scope(VlaMark::at(reg!(mark) as usize)),
+
+                Instruction::Jump { target } => {
+                    if self.tiers.is_some() && (target as usize) < pc {
+                        self.backedge();
+                    }
+                    pc = target as usize;
+                }
+                Instruction::JumpIfZero { condition, target } => {
+                    if reg!(condition) == 0 {
+                        pc = target as usize;
+                    }
+                }
+                Instruction::JumpIfNonZero { condition, target } => {
+                    if reg!(condition) != 0 {
+                        pc = target as usize;
+                    }
+                }
+                Instruction::Switch { value, table } => {
+                    let table = &program.switch_tables[table as usize];
+                    let value = reg!(value) as i64;
+                    pc = match table.cases.binary_search_by_key(&value, |&(case, _)| case) {
+                        Ok(index) => table.cases[index].1 as usize,
+                        Err(_) => table.default as usize,
+                    };
+                }
+                Instruction::Call { dst, function: callee, arguments, count } => {
+                    if let Some(native) = self.native.as_ref().filter(|native| native.compiled(callee)) {
+                        let start = base + arguments as usize;
+                        // SAFETY: `data` stays where `with_native` compiled against
+                        let value = unsafe { native.call(callee, &self.registers[start..start + count as usize]) };
+                        reg!(dst) = value;
+                    } else if let Some(value) = self.call_tiered(callee, base + arguments as usize, count as usize)? {
+                        reg!(dst) = value;
+                    } else {
+                        self.frames.last_mut().expect("the current frame").pc = pc;
+                        self.push_frame(callee, base + arguments as usize, count as usize, base + dst as usize)?;
+                        enter!();
+                    }
+                }
+                Instruction::CallIndirect { dst, callee, arguments, count, signature } => {
+                    let target = reg!(callee);
+                    if target & TAG_MASK == FUNCTION_TAG {
+                        let index = target & !TAG_MASK;
+                        if index >= program.functions.len() as u64 {
+                            return Err(RuntimeError::Bytecode(BytecodeError::BadFunctionPointer(target)).into());
+                        }
+                        self.frames.last_mut().expect("the current frame").pc = pc;
+                        self.push_frame(index as u32, base + arguments as usize, count as usize, base + dst as usize)?;
+                        enter!();
+                    } else {
+                        if target < NULL_PAGE {
+                            return Err(RuntimeError::FatalSignal(libc::SIGSEGV).into());
+                        }
+                        let start = base + arguments as usize;
+                        let values = self.registers[start..start + count as usize].to_vec();
+                        let signature = &program.signatures[signature as usize];
+                        reg!(dst) = self.call_native(target, signature, &values, "a function pointer")?;
+                    }
+                }
+                Instruction::CallExternal { dst, external, arguments, count, signature } => {
+                    let start = base + arguments as usize;
+                    let values = self.registers[start..start + count as usize].to_vec();
+                    if let Intercept::Coroutine(call) = self.intercepts[external as usize] {
+                        self.frames.last_mut().expect("the current frame").pc = pc;
+                        match self.coroutine(call, &values, base + dst as usize)? {
+                            Some(value) => reg!(dst) = value,
+                            None => resume!(),
+                        }
+                    } else if let Some(call) = self.guest.as_ref().and_then(|guest| guest.calls[external as usize]) {
+                        if self.limited && call == GuestCall::Write {
+                            self.charge(Intercept::Write, &program.signatures[signature as usize], &values)?;
+                        }
+                        self.frames.last_mut().expect("the current frame").pc = pc;
+                        let syscall = self.block(call, &values, base + dst as usize)?;
+                        return Err(Stop::Blocked(syscall));
+                    } else {
+                        reg!(dst) = self.call_external(external, &program.signatures[signature as usize], &values)?;
+                    }
+                }
+                Instruction::Return { .. } | Instruction::ReturnVoid => {
+                    let value = match instruction {
+                        Instruction::Return { src } => reg!(src),
+                        _ => 0,
+                    };
+                    let finished = self.pop_frame();
+                    if !self.frames.is_empty() {
+                        self.registers[finished.dst] = value;
+                    } else if self.coroutines.current() == MAIN {
+                        return Ok(value);
+                    } else {
+                        self.finish_coroutine(value)?;
+                    }
+                    resume!();
+                }
+
+                Instruction::Statement { line: at } => {
+                    line = at;
+                    let position = SourcePosition { file: &program.file, line, column: 0, function: &function.name };
+                    self.runtime.trace_statement(&position)?;
+                    if let Some(guest) = &mut self.guest {
+                        if guest.budget == 0 {
+                            self.frames.last_mut().expect("the current frame").pc = pc;
+                            return Err(Stop::Yield);
+                        }
+                        guest.budget -= 1;
+                    }
+                }
+                Instruction::Probe { site, arguments, count } => {
+                    let start = base + arguments as usize;
+                    let values: Vec<i64> = self.registers[start..start + count as usize].iter().map(|&value| value as i64).collect();
+                    self.runtime.fire_probe(site as usize, &values);
+                }
+                Instruction::Asm { .. } => {
+                    let function = function.name.clone();
+                    return Err(RuntimeError::Bytecode(BytecodeError::InterpretedAsm { function }).into());
+                }
+
+                Instruction::SignedImm { op, dst, a, temp, value, bits } => {
+                    reg!(temp) = value as i64 as u64;
+                    let x = reg!(a);
+                    reg!(dst) = self.signed(op, x, value as i64 as u64, bits, line, function)? as u64;
+                    pc += 1;
+                }
+                Instruction::SignedMove { op, dst, a, b, temp, bits } => {
+                    let (x, y) = (reg!(a), reg!(b));
+                    let value = self.signed(op, x, y, bits, line, function)? as u64;
+                    reg!(temp) = value;
+                    reg!(dst) = value;
+                    pc += 1;
+                }
+                Instruction::CompareImm { comparison, dst, a, temp, value } => {
+                    reg!(temp) = value as i64 as u64;
+                    reg!(dst) = comparison.holds(reg!(a), value as i64 as u64) as u64;
+                    pc += 1;
+                }
+                Instruction::CompareJump { comparison, dst, a, b, target, when } => {
+                    let holds = comparison.holds(reg!(a), reg!(b));
+                    reg!(dst) = holds as u64;
+                    pc = if holds == when { target as usize } else { pc + 1 };
+                }
+                Instruction::CompareImmJump { comparison, dst, a, temp, value, target, when } => {
+                    reg!(temp) = value as i64 as u64;
+                    let holds = comparison.holds(reg!(a), value as i64 as u64);
+                    reg!(dst) = holds as u64;
+                    pc = if holds == when { target as usize } else { pc + 2 };
+                }
+                Instruction::MoveJump { dst, src, target } => {
+                    reg!(dst) = reg!(src);
+                    if self.tiers.is_some() && (target as usize) < pc {
+                        self.backedge();
+                    }
+                    pc = target as usize;
+                }
+
+                Instruction::Unknown { opcode } => {
+                    let function = function.name.clone();
+                    return Err(RuntimeError::Bytecode(BytecodeError::UnknownOpcode { opcode, function }).into());
+                }
+            }
+        }
+    }
+
+    /// A signed operation on `bits`-wide operands; the runtime only sees
+    /// the ones that overflow, to report them
+    #[inline(always)]
+    fn signed(&mut self, op: SignedOp, a: u64, b: u64, bits: u8, line: u32, function: &Function) -> Result<i64, Stop> {
+        let (x, y) = (a as i64, b as i64);
+        if matches!(op, SignedOp::Div | SignedOp::Rem) && y == 0 {
+            return Err(self.division_by_zero(line, function));
+        }
+        match exact(op, x, y, bits) {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(wrapped)) if self.wrap => Ok(wrapped),
+            None if self.sanitize => Err(self.undefined(CheckKind::ShiftOutOfBounds, line, function)),
+            _ => {
+                let location = format!("{}:{}", self.program.file, line);
+                Ok(self.runtime.signed_arithmetic(op, x, y, bits as u32, &location, &function.name)?)
+            }
+        }
+    }
+
+    /// `address` for an access of `size` bytes; with `--sanitize=undefined`
+    /// a null or misaligned one is reported first
+    #[inline(always)]
+    fn access(&mut self, address: u64, size: usize, line: u32, function: &Function) -> Result<u64, Stop> {
+        if self.sanitize {
+            if address < NULL_PAGE {
+                return Err(self.undefined(CheckKind::NullDereference, line, function));
+            }
+            if address % size as u64 != 0 {
+                return Err(self.undefined(CheckKind::MisalignedAccess, line, function));
+            }
+        }
+        Ok(checked(address)?)
+    }
+
+    #[cold]
+    fn division_by_zero(&mut self, line: u32, function: &Function) -> Stop {
+        if self.sanitize {
+            return self.undefined(CheckKind::DivisionByZero, line, function);
+        }
+        RuntimeError::FatalSignal(libc::SIGFPE).into()
+    }
+
+    /// A failed `--sanitize=undefined` check at `line`
+    #[cold]
+    fn undefined(&mut self, kind: CheckKind, line: u32, function: &Function) -> Stop {
+        let location = format!("{}:{}", self.program.file, line);
+        self.runtime.undefined_behaviour(kind, &location, &function.name).into()
+    }
+
+    /// `ic_coroutine_*`: the call's value, or `None` when another
+    /// coroutine is current now and has been passed its value
+    fn coroutine(&mut self, call: CoroutineCall, arguments: &[u64], dst: usize) -> Result<Option<u64>, Stop> {
+        let argument = |index: usize| arguments.get(index).copied().unwrap_or(0);
+        match call {
+            CoroutineCall::Create => {
+                let id = match self.create_coroutine(argument(1), argument(2), argument(3) as usize) {
+                    Ok(id) => id,
+                    Err(e) => return Ok(Some(e.errno() as u64)),
+                };
+                if argument(0) != 0 {
+                    // SAFETY: the program's `ic_coroutine_t *`
+                    unsafe { store(checked(argument(0))?, id, Kind::U64) };
+                }
+                Ok(Some(0))
+            }
+            CoroutineCall::Resume => self.switch(|coroutines| coroutines.resume(argument(0)), argument(1), dst),
+            CoroutineCall::Yield => self.switch(Scheduler::suspend, argument(0), dst),
+            CoroutineCall::Status => Ok(Some(match self.coroutines.status(argument(0)) {
+                Ok(status) => status as u64,
+                Err(e) => {
+                    coroutine::set_errno(e.errno());
+                    -1i64 as u64
+                }
+            })),
+            CoroutineCall::Current => Ok(Some(self.coroutines.current())),
+            CoroutineCall::Destroy => Ok(Some(self.coroutines.remove(argument(0)).map_or_else(|e| e.errno() as u64, |_| 0))),
+        }
+    }
+
+    fn create_coroutine(&mut self, entry: u64, arg: u64, stack_size: usize) -> Result<CoroutineId, CoroutineError> {
+        let index = entry & !TAG_MASK;
+        if entry & TAG_MASK != FUNCTION_TAG || index >= self.program.functions.len() as u64 {
+            return Err(CoroutineError::BadEntry(entry));
+        }
+        let size = match stack_size {
+            0 => coroutine::DEFAULT_STACK_SIZE,
+            size => size.max(coroutine::MIN_STACK_SIZE),
+        };
+        Ok(self.coroutines.insert(Context {
+            // Zeroed pages are only committed when touched
+            stack: vec![0u128; size.div_ceil(16)].into_boxed_slice(),
+            stack_size: size,
+            entry: Some((index as u32, arg)),
+            ..Context::default()
+        }))
+    }
+
+    /// Move to the coroutine `step` picks, passing it `value`; `dst` gets
+    /// whatever is passed back
+    fn switch(
+        &mut self,
+        step: impl FnOnce(&mut Scheduler<Context>) -> Result<(CoroutineId, CoroutineId), CoroutineError>,
+        value: u64,
+        dst: usize,
+    ) -> Result<Option<u64>, Stop> {
+        match step(&mut self.coroutines) {
+            Ok((from, to)) => {
+                self.swap(from, to, value, dst)?;
+                Ok(None)
+            }
+            Err(e) => {
+                coroutine::set_errno(e.errno());
+                Ok(Some(0))
+            }
+        }
+    }
+
+    /// The running coroutine's entry function returned `value`
+    fn finish_coroutine(&mut self, value: u64) -> Result<(), Stop> {
+        let (from, to) = self.coroutines.finish();
+        self.swap(from, to, value, 0)?;
+        // Its frame memory isn't needed any more, only its status
+        *self.coroutines.context_mut(from) = Context::default();
+        Ok(())
+    }
+
+    /// Save the running state as `from`'s, to continue with `value` in
+    /// register `pending`, and make `to`'s current
+    fn swap(&mut self, from: CoroutineId, to: CoroutineId, value: u64, pending: usize) -> Result<(), Stop> {
+        let next = std::mem::take(self.coroutines.context_mut(to));
+        let previous = Context {
+            registers: std::mem::replace(&mut self.registers, next.registers),
+            frames: std::mem::replace(&mut self.frames, next.frames),
+            stack: std::mem::replace(&mut self.stack, next.stack),
+            stack_size: std::mem::replace(&mut self.stack_size, next.stack_size),
+            stack_top: std::mem::replace(&mut self.stack_top, next.stack_top),
+            pending,
+            entry: None,
+        };
+        *self.coroutines.context_mut(from) = previous;
+        match next.entry {
+            // The first resume: start `entry(arg)`, whose frame has nothing to return to
+            Some((entry, arg)) => {
+                self.registers.push(arg);
+                self.push_frame(entry, 0, 1, 0)
+            }
+            None => {
+                self.registers[next.pending] = value;
+                Ok(())
+            }
+        }
+    }
+
+    /// The driver's version of a guest's `call`, whose result goes to
+    /// register `dst` when the guest resumes
+    fn block(&mut self, call: GuestCall, arguments: &[u64], dst: usize) -> Result<GuestSyscall, Stop> {
+        let argument = |index: usize| arguments.get(index).copied().unwrap_or(0);
+        let fd = argument(0) as i32;
+        let mut buffer = 0;
+        let syscall = match call {
+            GuestCall::Read => {
+                buffer = checked(argument(1))?;
+                GuestSyscall::Read { fd, len: argument(2) as usize }
+            }
+            GuestCall::Write => {
+                let length = argument(2) as usize;
+                // SAFETY: the program passes `length` bytes to write
+                let data = unsafe { std::slice::from_raw_parts(checked(argument(1))? as *const u8, length) }.to_vec();
+                // What the program printed through stdio comes first
+                // SAFETY: flushing every stream
+                unsafe { libc::fflush(std::ptr::null_mut()) };
+                GuestSyscall::Write { fd, data }
+            }
+            GuestCall::Accept => {
+                // The peer's address isn't reported
+                if argument(2) != 0 {
+                    // SAFETY: the program's `socklen_t *`
+                    unsafe { store(argument(2), 0, Kind::U32) };
+                }
+                GuestSyscall::Accept { fd }
+            }
+            GuestCall::Close => GuestSyscall::Close { fd },
+            GuestCall::Sleep => GuestSyscall::Sleep(Duration::from_secs(argument(0) as u32 as u64)),
+            GuestCall::Usleep => GuestSyscall::Sleep(Duration::from_micros(argument(0) as u32 as u64)),
+            GuestCall::Nanosleep => {
+                // SAFETY: the program's `const struct timespec *`
+                let (seconds, nanoseconds) = unsafe {
+                    let request = checked(argument(0))?;
+                    (load(request, Kind::I64), load(request + 8, Kind::I64))
+                };
+                let (seconds, nanoseconds) = ((seconds as i64).max(0) as u64, (nanoseconds as i64).max(0) as u64);
+                GuestSyscall::Sleep(Duration::from_secs(seconds) + Duration::from_nanos(nanoseconds))
+            }
+        };
+        self.guest.as_mut().expect("a guest").blocked = Some((dst, buffer));
+        Ok(syscall)
+    }
+
+    /// Finish the call the guest blocked in with the driver's `reply`
+    fn deliver(&mut self, reply: SyscallReply) {
+        let Some((dst, buffer)) = self.guest.as_mut().and_then(|guest| guest.blocked.take()) else { return };
+        self.registers[dst] = match reply {
+            SyscallReply::Data(data) => {
+                // SAFETY: `read`'s buffer, which holds as many bytes as were asked for
+                unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buffer as *mut u8, data.len()) };
+                data.len() as u64
+            }
+            SyscallReply::Value(value) => value as u64,
+            SyscallReply::Errno(errno) => {
+                coroutine::set_errno(errno);
+                -1i64 as u64
+            }
+        };
+    }
+
+    fn call_external(&mut self, external: u32, signature: &Signature, arguments: &[u64]) -> Result<u64, Stop> {
+        let intercept = self.intercepts[external as usize];
+        if intercept == Intercept::Exit {
+            return Err(Stop::Exit(arguments.first().copied().unwrap_or(0) as i32));
+        }
+        if let Intercept::Fenv(call) = intercept {
+            return self.fenv(call, arguments);
+        }
+        if self.limited && intercept != Intercept::Native {
+            self.charge(intercept, signature, arguments)?;
+        }
+        let address = self.resolve(external)?;
+        let name = &self.program.externals[external as usize].name;
+        // The address is only reusable once its record is gone
+        if intercept == Intercept::Free && self.leak_sites.is_some() {
+            leaks::untrack(arguments.first().copied().unwrap_or(0) as *mut c_void);
+        }
+        let result = self.call_native(address, signature, arguments, name)?;
+        self.track_allocation(intercept, arguments, result);
+        Ok(result)
+    }
+
+    /// `--detect-leaks`: record the block an allocation call returned
+    fn track_allocation(&self, intercept: Intercept, arguments: &[u64], result: u64) {
+        let Some(sites) = &self.leak_sites else { return };
+        let site = self.frames.last().map_or(leaks::UNKNOWN_SITE, |frame| sites[frame.function as usize]);
+        let argument = |index: usize| arguments.get(index).copied().unwrap_or(0);
+        let block = result as *mut c_void;
+        match intercept {
+            Intercept::Malloc => leaks::track(block, argument(0) as usize, Allocator::Malloc, site),
+            Intercept::Calloc => leaks::track(block, argument(0).saturating_mul(argument(1)) as usize, Allocator::Calloc, site),
+            Intercept::Realloc => {
+                // On failure the old block is untouched and stays live
+                if result != 0 || argument(1) == 0 {
+                    leaks::untrack(argument(0) as *mut c_void);
+                }
+                leaks::track(block, argument(1) as usize, Allocator::Realloc, site);
+            }
+            Intercept::Strdup | Intercept::Strndup if result != 0 => {
+                let allocator = if intercept == Intercept::Strdup { Allocator::Strdup } else { Allocator::Strndup };
+                // SAFETY: the C library returned a NUL-terminated copy
+                let size = unsafe { libc::strlen(block as *const c_char) } + 1;
+                leaks::track(block, size, allocator, site);
+            }
+            _ => {}
+        }
+    }
+
+    /// `fe*`: the program's FP environment is the host FPU the VM computes with
+    fn fenv(&mut self, call: FenvCall, arguments: &[u64]) -> Result<u64, Stop> {
+        let pointer = match call {
+            FenvCall::GetExceptFlag | FenvCall::SetExceptFlag | FenvCall::GetEnv | FenvCall::HoldExcept => arguments.first(),
+            FenvCall::SetEnv | FenvCall::UpdateEnv => arguments.first().filter(|&&env| env != FE_DFL_ENV),
+            _ => None,
+        };
+        if let Some(&pointer) = pointer {
+            checked(pointer)?;
+        }
+        // SAFETY: the program passes its `fenv_t *` or `fexcept_t *`
+        Ok(unsafe { call.call(arguments) } as i64 as u64)
+    }
+
+    /// Charge a call to the heap and output limits before it's made
+    fn charge(&mut self, intercept: Intercept, signature: &Signature, arguments: &[u64]) -> Result<(), Stop> {
+        let argument = |index: usize| arguments.get(index).copied().unwrap_or(0);
+        let stream = self.stdout;
+        let to_stdout = |index: usize| stream.is_some_and(|stdout| stdout == argument(index));
+        let stdout = libc::STDOUT_FILENO;
+        match intercept {
+            Intercept::Malloc => self.runtime.heap_allocate(argument(0) as usize)?,
+            Intercept::Calloc => self.runtime.heap_allocate(argument(0).saturating_mul(argument(1)) as usize)?,
+            Intercept::Realloc => {
+                let (old, new) = (usable_size(argument(0)), argument(1) as usize);
+                if new > old {
+                    self.runtime.heap_allocate(new - old)?;
+                } else {
+                    self.runtime.heap_free(old - new);
+                }
+            }
+            Intercept::Free => self.runtime.heap_free(usable_size(argument(0))),
+            Intercept::Puts => self.runtime.write_output(stdout, c_strlen(argument(0))? + 1)?,
+            Intercept::Putchar => self.runtime.write_output(stdout, 1)?,
+            Intercept::Write => self.runtime.write_output(argument(0) as i32, argument(2) as usize)?,
+            Intercept::Printf => {
+                let length = self.formatted_length(signature, arguments)?;
+                self.runtime.write_output(stdout, length)?;
+            }
+            Intercept::Fputs if to_stdout(1) => self.runtime.write_output(stdout, c_strlen(argument(0))?)?,
+            Intercept::Fputc if to_stdout(1) => self.runtime.write_output(stdout, 1)?,
+            Intercept::Fwrite if to_stdout(3) => {
+                self.runtime.write_output(stdout, argument(1).saturating_mul(argument(2)) as usize)?;
+            }
+            Intercept::Fprintf if to_stdout(0) => {
+                let rest = Signature {
+                    parameters: signature.parameters[1..].to_vec(),
+                    variadic: signature.variadic.map(|fixed| fixed.saturating_sub(1)),
+                    result: signature.result,
+                };
+                let length = self.formatted_length(&rest, &arguments[1..])?;
+                self.runtime.write_output(stdout, length)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// What `printf(arguments...)` would print: `snprintf(NULL, 0, ...)`
+    fn formatted_length(&mut self, signature: &Signature, arguments: &[u64]) -> Result<usize, Stop> {
+        let snprintf = self.symbol("snprintf").ok_or_else(|| RuntimeError::Bytecode(BytecodeError::UndefinedSymbol("snprintf".to_string())))?;
+        let mut parameters = vec![Kind::U64, Kind::U64];
+        parameters.extend_from_slice(&signature.parameters);
+        let call = Signature { parameters, variadic: Some(3), result: Some(Kind::I32) };
+        let mut values = vec![0, 0];
+        values.extend_from_slice(arguments);
+        let length = self.call_native(snprintf, &call, &values, "snprintf")? as i64;
+        Ok(length.max(0) as usize)
+    }
+
+    /// Call `function` natively if the attached engine has compiled it,
+    /// counting the call either way; `None` when it's interpreted
+    fn call_tiered(&mut self, function: u32, arguments: usize, count: usize) -> Result<Option<u64>, Stop> {
+        let Some(tiers) = &self.tiers else { return Ok(None) };
+        let (Some(engine), Some(tiered)) = (tiers.engine.upgrade(), tiers.functions[function as usize].clone()) else {
+            return Ok(None);
+        };
+        let Some((_running, entry)) = engine.enter(&tiered) else { return Ok(None) };
+        let program = self.program;
+        let callee = &program.functions[function as usize];
+        let Some(signature) = &callee.signature else { return Ok(None) };
+        // Missing arguments are zero, as for an interpreted call
+        let mut values = self.registers[arguments..arguments + count.min(signature.parameters.len())].to_vec();
+        values.resize(signature.parameters.len(), 0);
+        self.call_native(entry as u64, signature, &values, &callee.name).map(Some)
+    }
+
+    /// A loop in the current function went round once more
+    fn backedge(&self) {
+        let frame = self.frames.last().expect("the current frame");
+        if let Some(Some(function)) = self.tiers.as_ref().map(|tiers| &tiers.functions[frame.function as usize]) {
+            function.record_backedges(1);
+        }
+    }
+
+    fn call_native(&mut self, address: u64, signature: &Signature, arguments: &[u64], name: &str) -> Result<u64, Stop> {
+        let mut values = Vec::with_capacity(arguments.len());
+        for (&kind, &bits) in signature.parameters.iter().zip(arguments) {
+            if kind == Kind::U64 && bits & TAG_MASK == FUNCTION_TAG {
+                return Err(RuntimeError::Bytecode(BytecodeError::NativeCallback { external: name.to_string() }).into());
+            }
+            values.push(Argument::Value(argument(kind, bits)));
+        }
+        let result = signature.result.map(|kind| CType::Scalar(kind.scalar()));
+        let call = match signature.variadic {
+            Some(fixed) => marshal_variadic(self.abi, &values, fixed as usize, result.as_ref()),
+            None => marshal(self.abi, &values, result.as_ref()),
+        }
+        .map_err(|e| RuntimeError::ABIError(format!("{:?}", e)))?;
+        // SAFETY: the signature is the one the program declared the function with
+        let bytes = unsafe { call::invoke(address as *const u8, &call) }.map_err(|e| RuntimeError::ABIError(format!("{:?}", e)))?;
+        Ok(match signature.result {
+            None => 0,
+            Some(kind) => {
+                let mut word = [0u8; 8];
+                let size = kind.size().min(bytes.len());
+                word[..size].copy_from_slice(&bytes[..size]);
+                canonical(u64::from_le_bytes(word), kind)
+            }
+        })
+    }
+}
+
+fn host_symbol(name: &str) -> Option<u64> {
+    let name = CString::new(name).ok()?;
+    // SAFETY: `name` is NUL-terminated
+    let address = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    (!address.is_null()).then_some(address as u64)
+}
+
+fn checked(address: u64) -> Result<u64, RuntimeError> {
+    if address < NULL_PAGE {
+        return Err(RuntimeError::FatalSignal(libc::SIGSEGV));
+    }
+    Ok(address)
+}
+
+fn c_strlen(address: u64) -> Result<usize, RuntimeError> {
+    let address = checked(address)?;
+    // SAFETY: the program passes it to a function that reads a C string
+    Ok(unsafe { CStr::from_ptr(address as *const c_char) }.to_bytes().len())
+}
+
+fn usable_size(address: u64) -> usize {
+    if address == 0 {
+        return 0;
+    }
+    #[cfg(target_os = "linux")]
+    // SAFETY: a pointer from `malloc` the program is about to free or resize
+    return unsafe { libc::malloc_usable_size(address as *mut libc::c_void) };
+    #[cfg(target_os = "macos")]
+    // SAFETY: as above
+    return unsafe { libc::malloc_size(address as *const libc::c_void) };
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    0
+}
+
+/// `lhs op rhs` exactly: `Ok` if it fits in `bits`, `Err` with the wrapped
+/// result if it doesn't, `None` for a shift out of range
+fn exact(op: SignedOp, lhs: i64, rhs: i64, bits: u8) -> Option<Result<i64, i64>> {
+    let (a, b) = (lhs as i128, rhs as i128);
+    let exact = match op {
+        SignedOp::Add => a + b,
+        SignedOp::Sub => a - b,
+        SignedOp::Mul => a * b,
+        SignedOp::Neg => -a,
+        SignedOp::Div => a / b,
+        SignedOp::Rem => {
+            let wrapped = extend((a % b) as u64, bits, true) as i64;
+            return Some(if extend((a / b) as u64, bits, true) as i64 as i128 == a / b { Ok(wrapped) } else { Err(wrapped) });
+        }
+        SignedOp::Shl if rhs < 0 || rhs >= bits as i64 => return None,
+        SignedOp::Shl => a << rhs,
+    };
+    let wrapped = extend(exact as u64, bits, true) as i64;
+    Some(if wrapped as i128 == exact { Ok(wrapped) } else { Err(wrapped) })
+}
+
+fn extend(value: u64, bits: u8, signed: bool) -> u64 {
+    if bits >= 64 {
+        return value;
+    }
+    let shift = 64 - bits as u32;
+    if signed { (((value << shift) as i64) >> shift) as u64 } else { (value << shift) >> shift }
+}
+
+/// `raw`'s low bytes as a register value of `kind`
+fn canonical(raw: u64, kind: Kind) -> u64 {
+    match kind {
+        Kind::I8 => raw as i8 as i64 as u64,
+        Kind::U8 => raw as u8 as u64,
+        Kind::I16 => raw as i16 as i64 as u64,
+        Kind::U16 => raw as u16 as u64,
+        Kind::I32 => raw as i32 as i64 as u64,
+        Kind::U32 => raw as u32 as u64,
+        Kind::I64 | Kind::U64 | Kind::F64 => raw,
+        Kind::F32 => (f32::from_bits(raw as u32) as f64).to_bits(),
+    }
+}
+
+unsafe fn load(address: u64, kind: Kind) -> u64 {
+    let raw = match kind.size() {
+        1 => *(address as *const u8) as u64,
+        2 => (address as *const u16).read_unaligned() as u64,
+        4 => (address as *const u32).read_unaligned() as u64,
+        _ => (address as *const u64).read_unaligned(),
+    };
+    canonical(raw, kind)
+}
+
+unsafe fn store(address: u64, value: u64, kind: Kind) {
+    match kind {
+        Kind::F32 => (address as *mut f32).write_unaligned(f64::from_bits(value) as f32),
+        Kind::I8 | Kind::U8 => *(address as *mut u8) = value as u8,
+        Kind::I16 | Kind::U16 => (address as *mut u16).write_unaligned(value as u16),
+        Kind::I32 | Kind::U32 => (address as *mut u32).write_unaligned(value as u32),
+        Kind::I64 | Kind::U64 | Kind::F64 => (address as *mut u64).write_unaligned(value),
+    }
+}
+
+fn argument(kind: Kind, bits: u64) -> JITValue {
+    match kind {
+        Kind::I8 | Kind::U8 => JITValue::Int8(bits as i8),
+        Kind::I16 | Kind::U16 => JITValue::Int16(bits as i16),
+        Kind::I32 | Kind::U32 => JITValue::Int32(bits as i32),
+        Kind::I64 | Kind::U64 => JITValue::Int64(bits as i64),
+        Kind::F32 => JITValue::Float(f64::from_bits(bits) as f32),
+        Kind::F64 => JITValue::Double(f64::from_bits(bits)),
+    }
+}
+
+// Example usage:
+/*
+fn run(program: &Program, runtime: &mut CRuntimeEnvironment) -> Result<i32, RuntimeError> {
+    let mut vm = Vm::new(program, runtime)?;
+    vm.run_main(&["prog".to_string(), "input.txt".to_string()])
+}
+*/
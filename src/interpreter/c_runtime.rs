@@ -1,5 +1,31 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use std::ffi::c_void;
+use std::path::Path;
+use std::time::Duration;
+use super::probe_points::ProbePoints;
+use super::provenance::ProvenanceTracker;
+use super::record::{
+    ExecutionRecorder, InputKind, MemoryWrite, RecordError, Replayer, SourcePosition, SyscallRecord, Trace, TraceEnd,
+};
+use crate::optimizer::overflow::{self, OverflowMode, Outcome, SignedOp};
+use crate::optimizer::sanitize::{self, CheckKind};
+use super::vm_stats::VmStats;
+use crate::runtime::deterministic::Determinism;
+use crate::runtime::fenv::FenvSession;
+use crate::runtime::posix;
+use crate::runtime::limits::{ResourceLimiter, ResourceLimits, ResourceUsage};
+use crate::debug::stack_capture::{ShadowStack, ShadowWriter};
+use crate::abi::aggregate::{Argument, CType, Scalar};
+use crate::jit::JITValue;
+use crate::runtime::setjmp::{Activation, LongJump, SetjmpTable};
+use crate::runtime::setjmp::Interrupted;
+use crate::runtime::signal_safety::{CallMark, SignalAudit};
+use crate::runtime::signals::{Action, Delivery, Handler, SignalBridge};
+use crate::runtime::varargs::{VarargsMark, VarargsStack};
+use crate::runtime::virtual_time::VirtualTime;
+use crate::runtime::vla::{VlaMark, VlaStack};
+use crate::runtime::wasi::WasiHost;
 
 pub struct CRuntimeEnvironment {
     // Core runtime components
@@ -15,10 +41,671 @@ pub struct CRuntimeEnvironment {
     
     // Runtime state
     runtime_state: Arc<RwLock<RuntimeState>>,
+
+    // Variable length arrays, freed block by block
+    vla_stack: VlaStack,
+
+    // Register save areas of the variadic calls in progress
+    varargs: VarargsStack,
+
+    // Checkpoints of the interpreted `setjmp` calls whose functions are running
+    setjmp: SetjmpTable,
+
+    // Handlers installed with signal() and sigaction(), and the signals
+    // waiting for the next statement boundary
+    signals: SignalBridge,
+    
+    // Execution counters (no-ops unless built with `vm-stats`)
+    vm_stats: VmStats,
+
+    // Allocation of origin for every pointer, when `--provenance` is on
+    provenance: Option<ProvenanceTracker>,
+
+    // What signed overflow does: -fwrapv, -ftrapv or diagnose (default)
+    overflow: OverflowMode,
+
+    // `--sanitize=undefined`: the checks the JIT's instrumentation makes
+    sanitize_undefined: bool,
+
+    // `--record` writes non-deterministic behavior out, `--replay` reads it back
+    trace: TraceSession,
+
+    // `--dir`: file access goes through WASI preopens instead of the host
+    sandbox: Option<WasiHost>,
+
+    // `--max-time`, `--max-memory` and friends
+    limits: Option<ResourceLimiter>,
+
+    // `--deterministic`: virtual clock, fixed seed and ordered threads
+    determinism: Option<Arc<Determinism>>,
+
+    // A clock the embedder pauses, scales and advances
+    virtual_time: Option<Arc<VirtualTime>>,
+
+    // USDT sites of the program, which tools can enable while it runs
+    probe_points: Option<Arc<ProbePoints>>,
+
+    // Calls in progress, for stack captures from other threads
+    shadow_stack: Option<ShadowWriter>,
+
+    // `--audit-signals`: library calls made from interpreted handlers
+    signal_audit: Option<SignalAudit>,
+
+    // `--libc=bundled`: where library functions are looked up before the host
+    library: Option<SymbolResolver>,
+}
+
+/// Finds a library function or object by name, for `set_library`
+pub type SymbolResolver = Arc<dyn Fn(&str) -> Option<u64> + Send + Sync>;
+
+enum TraceSession {
+    Off,
+    Recording(ExecutionRecorder),
+    Replaying(Replayer),
 }
 
 impl CRuntimeEnvironment {
+    pub fn vm_stats(&self) -> &VmStats {
+        &self.vm_stats
+    }
+
+    /// For engines that keep their own dispatch loop (`interpreter::bytecode`)
+    pub fn vm_stats_mut(&mut self) -> &mut VmStats {
+        &mut self.vm_stats
+    }
+
+    /// Check every pointer operation against the allocation the pointer came
+    /// from. Must be called before execution starts, so every object is seen.
+    pub fn enable_provenance_checks(&mut self) {
+        self.provenance = Some(ProvenanceTracker::new());
+    }
+
+    /// The tracker pointer operations go through, if checks are on
+    pub fn provenance(&mut self) -> Option<&mut ProvenanceTracker> {
+        self.provenance.as_mut()
+    }
+
+    /// Must agree with the mode the JIT was given, so both engines print the
+    /// same report and produce the same value for a program
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {
+        self.overflow = mode;
+    }
+
+    pub fn overflow_mode(&self) -> OverflowMode {
+        self.overflow
+    }
+
+    /// Check division, shifts and memory accesses for undefined behaviour,
+    /// as `--sanitize=undefined` instruments compiled code
+    pub fn enable_undefined_checks(&mut self) {
+        self.sanitize_undefined = true;
+    }
+
+    pub fn undefined_checks(&self) -> bool {
+        self.sanitize_undefined
+    }
+
+    /// A failed check: print the JIT handler's message and end the program
+    /// the way its abort() does
+    pub fn undefined_behaviour(&mut self, kind: CheckKind, location: &str, function: &str) -> RuntimeError {
+        eprint!("{}", sanitize::runtime_message(location, kind, function));
+        RuntimeError::FatalSignal(libc::SIGABRT)
+    }
+
+    /// Signed `+ - * unary- / % <<` on a `bits`-wide operand, with the
+    /// overflow behavior of the selected mode. Evaluation shares its rules
+    /// and message with the JIT's instrumentation.
+    pub fn signed_arithmetic(
+        &mut self,
+        op: SignedOp,
+        lhs: i64,
+        rhs: i64,
+        bits: u32,
+        location: &str,
+        function: &str,
+    ) -> Result<i64, RuntimeError> {
+        let result = overflow::evaluate(op, lhs, rhs, bits).map_err(RuntimeError::Overflow)?;
+        match self.overflow.apply(result) {
+            Outcome::Value(value) => Ok(value),
+            Outcome::Diagnosed(value) => {
+                eprint!("{}", overflow::runtime_message(location, op, function));
+                Ok(value)
+            }
+            Outcome::Trapped => {
+                eprint!("{}", overflow::runtime_message(location, op, function));
+                Err(RuntimeError::OverflowTrap)
+            }
+        }
+    }
+
+    /// Confine the program's files to the preopens of `host`; `open` and
+    /// friends become WASI calls, as they would in a wasm32-wasi build.
+    /// Must be called before execution starts.
+    pub fn enable_sandbox(&mut self, host: WasiHost) {
+        self.sandbox = Some(host);
+    }
+
+    /// The sandbox file and clock calls go through, if `--dir` was given
+    pub fn sandbox(&mut self) -> Option<&mut WasiHost> {
+        self.sandbox.as_mut()
+    }
+
+    /// Stop the program with `RuntimeError::Limit` once it uses more than
+    /// `limits` allow. Must be called right before execution starts: the
+    /// clocks and the stack are measured from here.
+    pub fn set_limits(&mut self, limits: ResourceLimits) {
+        let mut limiter = ResourceLimiter::new(limits);
+        limiter.start();
+        self.limits = Some(limiter);
+    }
+
+    /// The limits set with `set_limits`, if any
+    pub fn limits(&self) -> Option<&ResourceLimits> {
+        self.limits.as_ref().map(ResourceLimiter::limits)
+    }
+
+    /// Run the same way every time. The program's `POSIXModule` must be
+    /// given the same `determinism` for its threads to take turns.
+    pub fn set_deterministic(&mut self, determinism: Arc<Determinism>) {
+        self.determinism = Some(determinism);
+    }
+
+    /// Read the clocks from `time` and sleep on it, instead of the host's
+    /// or the deterministic clock; see `runtime::virtual_time`
+    pub fn set_virtual_time(&mut self, time: Arc<VirtualTime>) {
+        self.virtual_time = Some(time);
+    }
+
+    /// `clock_gettime(clock)`: the embedder's or the deterministic virtual
+    /// clock if there is one, otherwise the host's, recorded and replayed
+    /// like any clock reading
+    pub fn clock_time(&mut self, clock: libc::clockid_t) -> Result<Duration, RuntimeError> {
+        if let Some(time) = &self.virtual_time {
+            return Ok(time.read(clock));
+        }
+        if let Some(determinism) = &self.determinism {
+            return Ok(determinism.clock().read(clock));
+        }
+        let bytes = self.nondeterministic_input(InputKind::Clock, || {
+            let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+            unsafe { libc::clock_gettime(clock, &mut time) };
+            let mut bytes = (time.tv_sec as u64).to_le_bytes().to_vec();
+            bytes.extend_from_slice(&(time.tv_nsec as u32).to_le_bytes());
+            bytes
+        })?;
+        if bytes.len() != 12 {
+            return Err(RuntimeError::Recording(RecordError::Corrupt(format!("{}-byte clock reading", bytes.len()))));
+        }
+        let seconds = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let nanos = u32::from_le_bytes(bytes[8..].try_into().unwrap());
+        Ok(Duration::new(seconds, nanos))
+    }
+
+    /// `nanosleep` and the other sleeps. Returns the time left if a signal
+    /// cut the sleep short.
+    pub fn sleep(&mut self, duration: Duration) -> Duration {
+        match &self.virtual_time {
+            Some(time) => time.sleep(duration),
+            None => {
+                let request = libc::timespec { tv_sec: duration.as_secs() as libc::time_t, tv_nsec: duration.subsec_nanos() as libc::c_long };
+                let mut left = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+                if unsafe { libc::nanosleep(&request, &mut left) } == 0 {
+                    return Duration::ZERO;
+                }
+                Duration::new(left.tv_sec as u64, left.tv_nsec as u32)
+            }
+        }
+    }
+
+    /// `setitimer(ITIMER_REAL, value, interval)`, and `alarm` with a zero
+    /// interval. Returns the time left on the previous timer and its
+    /// interval.
+    pub fn set_alarm_timer(&mut self, value: Duration, interval: Duration) -> Result<(Duration, Duration), RuntimeError> {
+        if let Some(time) = &self.virtual_time {
+            return Ok(time.set_timer(value, interval));
+        }
+        let timeval = |time: Duration| libc::timeval { tv_sec: time.as_secs() as libc::time_t, tv_usec: time.subsec_micros() as libc::suseconds_t };
+        let new = libc::itimerval { it_interval: timeval(interval), it_value: timeval(value) };
+        let mut old: libc::itimerval = unsafe { std::mem::zeroed() };
+        if unsafe { libc::setitimer(libc::ITIMER_REAL, &new, &mut old) } != 0 {
+            return Err(RuntimeError::InvalidArgument("setitimer(ITIMER_REAL)".to_string()));
+        }
+        let duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+        Ok((duration(old.it_value), duration(old.it_interval)))
+    }
+
+    /// The seed `srand(requested)` uses
+    pub fn srand_seed(&self, requested: u32) -> u32 {
+        self.determinism.as_ref().map_or(requested, |determinism| determinism.seed(requested))
+    }
+
+    /// The sites `frontend::usdt` found in the program, numbered as its
+    /// `__builtin_probe` calls number them
+    pub fn set_probe_points(&mut self, points: Arc<ProbePoints>) {
+        self.probe_points = Some(points);
+    }
+
+    pub fn probe_points(&self) -> Option<&Arc<ProbePoints>> {
+        self.probe_points.as_ref()
+    }
+
+    /// Publish the interpreter's calls in `stack`, for
+    /// `debug::stack_capture::ThreadHandle::capture` on another thread
+    pub fn set_shadow_stack(&mut self, stack: Arc<ShadowStack>) {
+        self.shadow_stack = Some(ShadowWriter::new(stack));
+    }
+
+    /// Check the library calls interpreted signal handlers make; see
+    /// `runtime::signal_safety`. Reports carry the interpreted stack, so
+    /// this publishes one if `set_shadow_stack` wasn't called.
+    pub fn enable_signal_audit(&mut self) {
+        self.signal_audit = Some(SignalAudit::new());
+        if self.shadow_stack.is_none() {
+            self.set_shadow_stack(Arc::new(ShadowStack::new()));
+        }
+    }
+
+    /// Resolve the program's external functions and objects with `library`
+    /// first, and the host process only for what it doesn't define. Must be
+    /// called before execution starts.
+    pub fn set_library(&mut self, library: SymbolResolver) {
+        self.library = Some(library);
+    }
+
+    /// The address of `name` in the library given to `set_library`
+    pub fn library_symbol(&self, name: &str) -> Option<u64> {
+        self.library.as_ref().and_then(|library| library(name))
+    }
+
+    /// Called before every call to a function the program doesn't define,
+    /// and to the runtime's own `signal`, `raise` and friends, with the
+    /// arguments evaluated. Returns the mark for `leave_library_call` while
+    /// the audit is on; a violation ends the program.
+    pub fn enter_library_call(&mut self, function: &str) -> Result<Option<CallMark>, RuntimeError> {
+        let Some(audit) = &mut self.signal_audit else {
+            return Ok(None);
+        };
+        let shadow = &self.shadow_stack;
+        let stack = || shadow.as_ref().map_or_else(Vec::new, |shadow| shadow.stack().snapshot());
+        audit
+            .enter_call(function, self.setjmp.depth(), stack)
+            .map(Some)
+            .map_err(RuntimeError::SignalSafety)
+    }
+
+    /// Called when the library call returns, however it returns
+    pub fn leave_library_call(&mut self, mark: Option<CallMark>) {
+        if let (Some(audit), Some(mark)) = (&mut self.signal_audit, mark) {
+            audit.leave_call(mark);
+        }
+    }
+
+    /// Called for `__builtin_probe(site, arguments...)` once the arguments
+    /// are evaluated; a no-op while the site is disabled
+    pub fn fire_probe(&self, site: usize, arguments: &[i64]) {
+        if let Some(points) = &self.probe_points {
+            points.fire(site, arguments);
+        }
+    }
+
+    /// What the program used, as far as limits are tracked
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.limits.as_ref().map(ResourceLimiter::usage)
+    }
+
+    /// Called on entry to every interpreted function, after `enter_function`
+    pub fn check_stack(&mut self) -> Result<(), RuntimeError> {
+        match &mut self.limits {
+            Some(limiter) => limiter.enter_function().map_err(RuntimeError::Limit),
+            None => Ok(()),
+        }
+    }
+
+    /// Called by `malloc`, `calloc` and a growing `realloc` before they
+    /// allocate `bytes`; an error ends the program
+    pub fn heap_allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        match &mut self.limits {
+            Some(limiter) => limiter.allocate(bytes).map_err(RuntimeError::Limit),
+            None => Ok(()),
+        }
+    }
+
+    /// Called by `free` and a shrinking `realloc`
+    pub fn heap_free(&mut self, bytes: usize) {
+        if let Some(limiter) = &mut self.limits {
+            limiter.free(bytes);
+        }
+    }
+
+    /// Called before `bytes` are written to `fd`; stdout is counted
+    pub fn write_output(&mut self, fd: i32, bytes: usize) -> Result<(), RuntimeError> {
+        match &mut self.limits {
+            Some(limiter) if fd == libc::STDOUT_FILENO => limiter.output(bytes).map_err(RuntimeError::Limit),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record this run to `path`. Must be called before execution starts.
+    pub fn record_to(&mut self, path: &Path, source: &str) -> Result<(), RecordError> {
+        self.trace = TraceSession::Recording(ExecutionRecorder::create(path, source)?);
+        Ok(())
+    }
+
+    /// Run with the syscall results and inputs of a recording of this
+    /// program. Must be called before execution starts.
+    pub fn replay(&mut self, trace: Trace) {
+        self.trace = TraceSession::Replaying(Replayer::new(trace));
+    }
+
+    /// Called before every statement
+    pub fn trace_statement(&mut self, position: &SourcePosition<'_>) -> Result<(), RuntimeError> {
+        if let Some(limiter) = &mut self.limits {
+            limiter.statement().map_err(RuntimeError::Limit)?;
+        }
+        if let Some(determinism) = &self.determinism {
+            determinism.scheduler().statement(posix::current_thread());
+        }
+        if let Some(shadow) = &mut self.shadow_stack {
+            shadow.statement(position.function, position.line);
+        }
+        match &mut self.trace {
+            TraceSession::Off => Ok(()),
+            TraceSession::Recording(recorder) => {
+                recorder.step(position);
+                Ok(())
+            }
+            TraceSession::Replaying(replayer) => replayer.step(position).map_err(RuntimeError::Recording),
+        }
+    }
+
+    /// Whether `trace_statement` does anything: limits, `--deterministic`,
+    /// stack capture or record/replay. Engines only skip it when it doesn't.
+    pub fn traces_execution(&self) -> bool {
+        self.limits.is_some() || self.determinism.is_some() || self.shadow_stack.is_some() || !matches!(self.trace, TraceSession::Off)
+    }
+
+    /// Called for every store into program memory, with the bytes replaced
+    pub fn trace_write(&mut self, address: u64, old: &[u8], new: &[u8]) {
+        if let TraceSession::Recording(recorder) = &mut self.trace {
+            recorder.write(address, old, new);
+        }
+    }
+
+    /// A syscall, made by `live` unless replaying. On replay the recorded
+    /// result is returned and `apply` stores what the kernel wrote.
+    pub fn traced_syscall(
+        &mut self,
+        number: i64,
+        live: impl FnOnce() -> SyscallRecord,
+        mut apply: impl FnMut(&MemoryWrite),
+    ) -> Result<(i64, i32), RuntimeError> {
+        match &mut self.trace {
+            TraceSession::Replaying(replayer) => {
+                let recorded = replayer.syscall(number).map_err(RuntimeError::Recording)?;
+                recorded.writes.iter().for_each(&mut apply);
+                Ok((recorded.result, recorded.errno))
+            }
+            session => {
+                let outcome = live();
+                if let TraceSession::Recording(recorder) = session {
+                    recorder.syscall(number, outcome.result, outcome.errno, &outcome.writes);
+                }
+                Ok((outcome.result, outcome.errno))
+            }
+        }
+    }
+
+    /// A clock reading, random seed, environment lookup or process id:
+    /// from `live`, or from the recording when replaying
+    pub fn nondeterministic_input(
+        &mut self,
+        kind: InputKind,
+        live: impl FnOnce() -> Vec<u8>,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        match &mut self.trace {
+            TraceSession::Off => Ok(live()),
+            TraceSession::Recording(recorder) => {
+                let bytes = live();
+                recorder.input(kind, &bytes);
+                Ok(bytes)
+            }
+            TraceSession::Replaying(replayer) => replayer.input(kind).map_err(RuntimeError::Recording),
+        }
+    }
+
+    /// Close the recording, or check that the replayed run ended where the
+    /// recorded one did
+    pub fn finish_trace(&mut self, end: TraceEnd) -> Result<(), RecordError> {
+        match std::mem::replace(&mut self.trace, TraceSession::Off) {
+            TraceSession::Off => Ok(()),
+            TraceSession::Recording(recorder) => recorder.finish(end).map(|_| ()),
+            TraceSession::Replaying(mut replayer) => replayer.finish(),
+        }
+    }
+
+    /// Called on entry to a block (or loop body) that declares a VLA, and at
+    /// function entry so `return` can free the function's VLAs at once
+    pub fn enter_vla_scope(&self) -> VlaMark {
+        self.vla_stack.mark()
+    }
+
+    /// Evaluate-once storage for `T buf[length]`, reached in program order
+    pub fn allocate_vla(&mut self, length: i64, element_size: usize) -> Result<*mut u8, RuntimeError> {
+        self.vla_stack
+            .allocate(length, element_size)
+            .map_err(RuntimeError::Vla)
+    }
+
+    /// Called on every exit from the scope: fall-through, `break`,
+    /// `continue`, `return`, and `goto` to a label outside it. A backward
+    /// `goto` to before the declaration also leaves its scope.
+    pub fn leave_vla_scope(&mut self, mark: VlaMark) {
+        self.vla_stack.release(mark);
+    }
+
+    /// Called on entry to a variadic function, with every argument of the
+    /// call; `fixed` of them are named parameters
+    pub fn enter_variadic_call(
+        &mut self,
+        args: &[Argument],
+        fixed: usize,
+        result: Option<&CType>,
+    ) -> Result<VarargsMark, RuntimeError> {
+        self.varargs.enter(args, fixed, result).map_err(RuntimeError::Varargs)
+    }
+
+    /// `va_start(ap, last)`; `ap` is the address of the program's `va_list`
+    pub unsafe fn va_start(&mut self, ap: *mut u8) -> Result<(), RuntimeError> {
+        self.varargs.start(ap).map_err(RuntimeError::Varargs)
+    }
+
+    /// `va_arg(ap, T)` for a scalar `T`
+    pub unsafe fn va_arg(&self, ap: *mut u8, scalar: Scalar) -> Result<JITValue, RuntimeError> {
+        self.varargs.arg_value(ap, scalar).map_err(RuntimeError::Varargs)
+    }
+
+    /// `va_arg(ap, T)` for a struct or union `T`
+    pub unsafe fn va_arg_aggregate(&self, ap: *mut u8, ty: &CType) -> Result<Vec<u8>, RuntimeError> {
+        self.varargs.arg(ap, ty).map_err(RuntimeError::Varargs)
+    }
+
+    pub unsafe fn va_copy(&self, dest: *mut u8, src: *mut u8) {
+        self.varargs.copy(dest, src);
+    }
+
+    /// Called when the variadic function returns, however it returns.
+    /// `va_end` itself does nothing on either ABI.
+    pub fn leave_variadic_call(&mut self, mark: VarargsMark) {
+        self.varargs.leave(mark);
+    }
+
+    /// Called on entry to every interpreted function
+    pub fn enter_function(&mut self) -> Activation {
+        let activation = self.setjmp.enter();
+        if let Some(shadow) = &mut self.shadow_stack {
+            shadow.enter(activation.depth());
+        }
+        activation
+    }
+
+    /// Called when the function returns normally or with an error; after a
+    /// `longjmp` past it this does nothing
+    pub fn leave_function(&mut self, activation: Activation) {
+        self.setjmp.leave(activation);
+        if let Some(shadow) = &mut self.shadow_stack {
+            shadow.truncate(activation.depth());
+        }
+    }
+
+    /// `setjmp(buf)`, or `sigsetjmp(buf, save_mask)`, at the interpreter's
+    /// position `resume` in `activation`. Evaluates to 0; a `longjmp` to
+    /// `buf` comes back to `resume` through `catch_longjmp`.
+    pub unsafe fn setjmp(
+        &mut self,
+        buf: *mut u8,
+        activation: Activation,
+        resume: usize,
+        save_mask: bool,
+    ) -> Result<i32, RuntimeError> {
+        let vla = self.vla_stack.mark();
+        let varargs = self.varargs.mark();
+        self.setjmp
+            .setjmp(buf, activation, resume, vla, varargs, save_mask)
+            .map_err(RuntimeError::Setjmp)?;
+        Ok(0)
+    }
+
+    /// `longjmp(buf, value)`. Never returns `Ok`: the error unwinds the
+    /// interpreter to the function that called `setjmp`.
+    pub unsafe fn longjmp(&mut self, buf: *const u8, value: i32) -> Result<(), RuntimeError> {
+        let jump = self.setjmp.longjmp(buf, value).map_err(RuntimeError::Setjmp)?;
+        Err(RuntimeError::LongJump(jump))
+    }
+
+    /// Called by every function that contains a `setjmp` call with each
+    /// error on its way out. A `longjmp` to this activation is caught: the
+    /// VLAs and variadic frames allocated since the `setjmp` are released
+    /// and execution resumes at `jump.resume`, the `setjmp` call evaluating
+    /// to `jump.value`. Anything else is passed on.
+    pub fn catch_longjmp(&mut self, activation: Activation, error: RuntimeError) -> Result<LongJump, RuntimeError> {
+        let jump = match error {
+            RuntimeError::LongJump(jump) => jump,
+            // Compiled code called from here jumped to an interpreted `jmp_buf`
+            RuntimeError::NativeLongJump(pending) => unsafe {
+                self.setjmp.longjmp(pending.buf, pending.value).map_err(RuntimeError::Setjmp)?
+            },
+            other => return Err(other),
+        };
+        if jump.activation != activation {
+            return Err(RuntimeError::LongJump(jump));
+        }
+        self.setjmp.land(&jump);
+        if let Some(shadow) = &mut self.shadow_stack {
+            shadow.truncate(activation.depth() + 1);
+        }
+        if let Some(audit) = &mut self.signal_audit {
+            audit.unwind(activation.depth());
+        }
+        self.vla_stack.release(jump.vla);
+        self.varargs.leave(jump.varargs);
+        Ok(jump)
+    }
+
+    /// `pthread_exit(value)` in interpreted code. Never returns `Ok`: the
+    /// error unwinds the thread's interpreter to its start routine, which
+    /// returns `value` to `pthread_join`.
+    pub fn pthread_exit(&mut self, value: u64) -> Result<(), RuntimeError> {
+        Err(RuntimeError::ThreadExit(value))
+    }
+
+    /// `sigaction(signal, new, &old)`. `new: None` only reads the action.
+    pub fn sigaction(&mut self, signal: i32, new: Option<Action>) -> Result<Action, RuntimeError> {
+        self.signals.sigaction(signal, new).map_err(RuntimeError::Signal)
+    }
+
+    /// `signal(signal, handler)`, returning the previous handler
+    pub fn signal(&mut self, signal: i32, handler: Handler) -> Result<Handler, RuntimeError> {
+        let old = self.sigaction(signal, Some(Action::bsd(handler)))?;
+        Ok(old.handler)
+    }
+
+    /// `sigprocmask(how, set, &old)` with the sets as bit masks, signal N
+    /// being bit N-1. Unblocking a pending signal delivers it at the next
+    /// `check_signals`.
+    pub fn sigprocmask(&mut self, how: i32, set: Option<u64>) -> Result<u64, RuntimeError> {
+        self.signals.sigprocmask(how, set).map_err(RuntimeError::Signal)
+    }
+
+    /// `raise(signal)`. The handler runs at the next `check_signals`, which
+    /// the interpreter does right after the call, so `raise` still returns
+    /// after it.
+    pub fn raise(&mut self, signal: i32) -> Result<i32, RuntimeError> {
+        if unsafe { libc::raise(signal) } != 0 {
+            return Err(RuntimeError::InvalidArgument(format!("raise({})", signal)));
+        }
+        Ok(0)
+    }
+
+    /// Called at every statement boundary. Runs the compiled handlers of the
+    /// signals that arrived since, and returns the next one whose handler
+    /// is interpreted: the interpreter calls it (with `delivery.info` for
+    /// `SA_SIGINFO`) and then `finish_signal`. A `longjmp` out of the
+    /// handler skips `finish_signal`; `siglongjmp` restores the mask itself.
+    pub fn check_signals(&mut self) -> Result<Option<Delivery>, RuntimeError> {
+        while let Some(delivery) = self.signals.poll() {
+            let Handler::Compiled(address) = delivery.action.handler else {
+                if let Some(audit) = &mut self.signal_audit {
+                    audit.enter_handler(delivery.signal, self.setjmp.depth());
+                }
+                return Ok(Some(delivery));
+            };
+            let result = unsafe {
+                crate::runtime::setjmp::call_native(|| {
+                    if delivery.action.flags & libc::SA_SIGINFO != 0 {
+                        let mut info: libc::siginfo_t = std::mem::zeroed();
+                        info.si_signo = delivery.signal;
+                        info.si_code = delivery.info.code;
+                        let handler: extern "C" fn(i32, *mut libc::siginfo_t, *mut c_void) = std::mem::transmute(address);
+                        handler(delivery.signal, &mut info, std::ptr::null_mut())
+                    } else {
+                        let handler: extern "C" fn(i32) = std::mem::transmute(address);
+                        handler(delivery.signal)
+                    }
+                })
+            };
+            self.signals.finish(&delivery);
+            match result {
+                Ok(()) => {}
+                Err(Interrupted::LongJump(pending)) => return Err(RuntimeError::NativeLongJump(pending)),
+                // Pending now, for the interpreted handler the next poll returns
+                Err(Interrupted::Fault(_)) => {}
+                Err(Interrupted::StackOverflow(_)) => return Err(RuntimeError::FatalSignal(libc::SIGSEGV)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// The interpreted handler for `delivery` returned. A fault's handler
+    /// must not return: the instruction would only fault again, so the
+    /// program ends with the signal instead.
+    pub fn finish_signal(&mut self, delivery: Delivery) -> Result<(), RuntimeError> {
+        self.signals.finish(&delivery);
+        if let Some(audit) = &mut self.signal_audit {
+            audit.leave_handler();
+        }
+        if delivery.fault {
+            return Err(RuntimeError::FatalSignal(delivery.signal));
+        }
+        Ok(())
+    }
+
     pub async fn execute_project(&mut self, project: CProject) -> Result<ExecutionResult, RuntimeError> {
+        // <fenv.h> calls act on the host FPU: start from the default
+        // environment and give the host its own back afterwards. The FPU
+        // state is per thread, so this future must not migrate between
+        // worker threads (drive it on a current-thread runtime).
+        let _fenv = FenvSession::begin();
+
         // Initialize runtime
         self.initialize_runtime(&project).await?;
         
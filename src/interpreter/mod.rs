@@ -0,0 +1,9 @@
+// src/interpreter/mod.rs
+//! Interpreted execution of C programs
+
+pub mod bytecode;
+pub mod c_runtime;
+pub mod probe_points;
+pub mod provenance;
+pub mod record;
+pub mod vm_stats;
@@ -0,0 +1,259 @@
+// src/interpreter/probe_points.rs
+//! Probe points of an interpreted program
+//! The sites `frontend::usdt` found, each with an enabled flag. A disabled
+//! site costs the interpreter one relaxed load; an enabled one records its
+//! arguments as a `ProbeHit`, kept in a bounded buffer of recent hits and
+//! passed to every subscriber.
+//!
+//! The table is shared (`Arc`), so a monitoring thread, the gdbstub or an
+//! embedder can enable and disable sites while the program runs, by pattern:
+//! `provider:name`, where either part may contain `*`, or just `provider`.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use crate::frontend::usdt::{ProbeSite, MAX_ARGUMENTS};
+use crate::runtime::posix::{self, ThreadId};
+
+/// Hits kept for `drain` by default
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// One execution of an enabled site
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeHit {
+    pub site: usize,
+    pub thread: ThreadId,
+    /// Hits of any site before this one
+    pub sequence: u64,
+    values: [i64; MAX_ARGUMENTS],
+    count: u8,
+}
+
+impl ProbeHit {
+    pub fn arguments(&self) -> &[i64] {
+        &self.values[..self.count as usize]
+    }
+
+    /// `provider:name(arg, ...) [thread N]`, as `--usdt` prints hits
+    pub fn describe(&self, site: &ProbeSite) -> String {
+        let arguments: Vec<String> = self.arguments().iter().map(i64::to_string).collect();
+        format!("{}:{}({}) [thread {}]", site.provider, site.name, arguments.join(", "), self.thread)
+    }
+}
+
+pub type ProbeListener = dyn Fn(&ProbeSite, &ProbeHit) + Send + Sync;
+
+struct SiteState {
+    site: ProbeSite,
+    enabled: AtomicBool,
+    hits: AtomicU64,
+}
+
+pub struct ProbePoints {
+    sites: Vec<SiteState>,
+
+    // Recording
+    sequence: AtomicU64,
+    recent: Mutex<VecDeque<ProbeHit>>,
+    capacity: usize,
+    listeners: RwLock<Vec<Arc<ProbeListener>>>,
+}
+
+impl ProbePoints {
+    /// All sites start disabled
+    pub fn new(sites: Vec<ProbeSite>) -> Self {
+        Self::with_capacity(sites, DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(sites: Vec<ProbeSite>, capacity: usize) -> Self {
+        ProbePoints {
+            sites: sites
+                .into_iter()
+                .map(|site| SiteState { site, enabled: AtomicBool::new(false), hits: AtomicU64::new(0) })
+                .collect(),
+            sequence: AtomicU64::new(0),
+            recent: Mutex::new(VecDeque::new()),
+            capacity,
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn sites(&self) -> impl Iterator<Item = &ProbeSite> {
+        self.sites.iter().map(|state| &state.site)
+    }
+
+    pub fn is_enabled(&self, site: usize) -> bool {
+        self.sites.get(site).is_some_and(|state| state.enabled.load(Ordering::Relaxed))
+    }
+
+    /// Enable the sites matching `pattern`; returns how many matched
+    pub fn enable(&self, pattern: &str) -> usize {
+        self.set_enabled(pattern, true)
+    }
+
+    /// Disable the sites matching `pattern`; returns how many matched
+    pub fn disable(&self, pattern: &str) -> usize {
+        self.set_enabled(pattern, false)
+    }
+
+    fn set_enabled(&self, pattern: &str, enabled: bool) -> usize {
+        let (provider, name) = pattern.split_once(':').unwrap_or((pattern, "*"));
+        let matching = self
+            .sites
+            .iter()
+            .filter(|state| glob(provider, &state.site.provider) && glob(name, &state.site.name));
+        let mut count = 0;
+        for state in matching {
+            state.enabled.store(enabled, Ordering::Relaxed);
+            count += 1;
+        }
+        count
+    }
+
+    /// Called for every execution of `__builtin_probe(site, arguments...)`,
+    /// after its arguments were evaluated
+    #[inline]
+    pub fn fire(&self, site: usize, arguments: &[i64]) {
+        let Some(state) = self.sites.get(site) else { return };
+        if !state.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        self.record(state, site, arguments);
+    }
+
+    #[cold]
+    fn record(&self, state: &SiteState, site: usize, arguments: &[i64]) {
+        state.hits.fetch_add(1, Ordering::Relaxed);
+        let count = arguments.len().min(MAX_ARGUMENTS);
+        let mut values = [0; MAX_ARGUMENTS];
+        values[..count].copy_from_slice(&arguments[..count]);
+        let hit = ProbeHit {
+            site,
+            thread: posix::current_thread(),
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+            values,
+            count: count as u8,
+        };
+
+        if self.capacity > 0 {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() == self.capacity {
+                recent.pop_front();
+            }
+            recent.push_back(hit);
+        }
+        for listener in self.listeners.read().unwrap().iter() {
+            listener(&state.site, &hit);
+        }
+    }
+
+    /// Call `listener` on every hit of an enabled site, on the thread that
+    /// hit it
+    pub fn subscribe(&self, listener: impl Fn(&ProbeSite, &ProbeHit) + Send + Sync + 'static) {
+        self.listeners.write().unwrap().push(Arc::new(listener));
+    }
+
+    /// Take the hits recorded since the last call, oldest first. Only the
+    /// last `capacity` are kept.
+    pub fn drain(&self) -> Vec<ProbeHit> {
+        self.recent.lock().unwrap().drain(..).collect()
+    }
+
+    /// Times `site` fired while enabled
+    pub fn hits(&self, site: usize) -> u64 {
+        self.sites.get(site).map_or(0, |state| state.hits.load(Ordering::Relaxed))
+    }
+
+    /// Hit counts of the sites that fired, most hit first
+    pub fn summary(&self) -> String {
+        let mut fired: Vec<&SiteState> = self.sites.iter().filter(|state| state.hits.load(Ordering::Relaxed) > 0).collect();
+        fired.sort_by_key(|state| std::cmp::Reverse(state.hits.load(Ordering::Relaxed)));
+        let mut out = String::new();
+        if fired.is_empty() {
+            return out;
+        }
+        let _ = writeln!(out, "Probe points:");
+        for state in fired {
+            let _ = writeln!(
+                out,
+                "  {:>10}  {}:{}  {}:{}",
+                state.hits.load(Ordering::Relaxed),
+                state.site.provider,
+                state.site.name,
+                state.site.file,
+                state.site.line
+            );
+        }
+        out
+    }
+
+    /// A text command, for monitor interfaces:
+    /// `usdt list`, `usdt enable PATTERN`, `usdt disable PATTERN`, `usdt stats`
+    pub fn command(&self, line: &str) -> Result<String, ProbePointError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["usdt", "list"] => {
+                let mut out = String::new();
+                for (index, state) in self.sites.iter().enumerate() {
+                    let enabled = if state.enabled.load(Ordering::Relaxed) { "on " } else { "off" };
+                    let _ = writeln!(out, "{:>4} {} {}", index, enabled, state.site);
+                }
+                Ok(out)
+            }
+            ["usdt", action @ ("enable" | "disable"), pattern] => {
+                let count = if *action == "enable" { self.enable(pattern) } else { self.disable(pattern) };
+                match count {
+                    0 => Err(ProbePointError::NoMatch(pattern.to_string())),
+                    count => Ok(format!("{}d {} site(s)\n", action, count)),
+                }
+            }
+            ["usdt", "stats"] => Ok(self.summary()),
+            _ => Err(ProbePointError::UnknownCommand(line.to_string())),
+        }
+    }
+}
+
+/// `*` matches any run of characters
+fn glob(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else { return false };
+            (0..=text.len()).any(|skip| text.is_char_boundary(skip) && glob(rest, &text[skip..]))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbePointError {
+    NoMatch(String),
+    UnknownCommand(String),
+}
+
+impl std::fmt::Display for ProbePointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbePointError::NoMatch(pattern) => write!(f, "no probe site matches '{}'", pattern),
+            ProbePointError::UnknownCommand(line) => {
+                write!(f, "unknown command '{}': use usdt list, enable PATTERN, disable PATTERN or stats", line)
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn example(runtime: &mut CRuntimeEnvironment, source: &str) -> Result<(), UsdtError> {
+    let rewritten = usdt::rewrite(source, "server.c", Lowering::Interpreted)?;
+    let points = Arc::new(ProbePoints::new(rewritten.sites));
+    points.enable("server:request*");
+    points.subscribe(|site, hit| eprintln!("{}:{} {:?}", site.provider, site.name, hit.arguments()));
+    runtime.set_probe_points(Arc::clone(&points));
+
+    // From a monitoring thread, while the program runs
+    let monitor = Arc::clone(&points);
+    std::thread::spawn(move || monitor.disable("server"));
+    Ok(())
+}
+*/
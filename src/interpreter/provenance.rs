@@ -0,0 +1,490 @@
+// src/interpreter/provenance.rs
+//! Strict pointer provenance for interpreted programs (`--provenance`)
+//! Every pointer the interpreter creates remembers the allocation it was
+//! derived from: a global, a stack variable, a VLA, a string literal or a
+//! heap block. Address arithmetic alone can't tell `&a[4]` from `&b[0]` when
+//! `b` happens to follow `a` in memory; provenance can. With the tracker on,
+//! the interpreter routes pointer operations through it and reports:
+//!
+//! - arithmetic that leaves its allocation (one past the end is allowed),
+//!   naming the other object it landed in, if any
+//! - relational comparison or subtraction of pointers into different objects
+//! - `==` between one-past-the-end of one object and the start of the next,
+//!   whose result C leaves unspecified
+//! - any use of a pointer after `free`, after its block's scope ends, or
+//!   after `realloc`, even when `realloc` returned the same address
+//! - `free` of a non-heap or interior pointer, and double `free`
+//! - writes to string literals
+//!
+//! This is stricter than AddressSanitizer, which only notices when an
+//! access lands outside every valid object. Integer-to-pointer casts follow
+//! the PNVI-ae-udi model proposed for C2y: casting a pointer to an integer
+//! exposes its allocation, and an integer cast back to a pointer regains
+//! the provenance of the exposed allocation it points into. Integers that
+//! were never a pointer to a live exposed object give a pointer that can't
+//! be dereferenced.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// One allocation, numbered in creation order; ids are never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AllocId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationKind {
+    Global,
+    Stack,
+    Vla,
+    /// A string literal; read-only
+    Literal,
+    Heap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifetime {
+    Live,
+    Freed,
+    /// Replaced by `realloc`; the new block's id
+    Reallocated(AllocId),
+    /// Its block or function was left
+    OutOfScope,
+}
+
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub id: AllocId,
+    pub kind: AllocationKind,
+    pub base: usize,
+    pub size: usize,
+    pub lifetime: Lifetime,
+    /// Variable name, or where a heap block was allocated
+    pub label: String,
+}
+
+impl Allocation {
+    /// `address` is inside, or one past the end
+    fn spans(&self, address: usize) -> bool {
+        address >= self.base && address - self.base <= self.size
+    }
+
+    fn contains(&self, address: usize, len: usize) -> bool {
+        address >= self.base && address - self.base <= self.size && self.size - (address - self.base) >= len
+    }
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            AllocationKind::Global => "global",
+            AllocationKind::Stack => "local",
+            AllocationKind::Vla => "VLA",
+            AllocationKind::Literal => "string literal",
+            AllocationKind::Heap => "heap block",
+        };
+        write!(f, "{} '{}' ({} bytes, #{})", kind, self.label, self.size, self.id.0)
+    }
+}
+
+/// A pointer value in provenance mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaggedPointer {
+    pub address: usize,
+    /// `None` for null and for integers that didn't recover a provenance
+    pub provenance: Option<AllocId>,
+}
+
+impl TaggedPointer {
+    pub const NULL: TaggedPointer = TaggedPointer { address: 0, provenance: None };
+
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Default)]
+pub struct ProvenanceTracker {
+    allocations: HashMap<AllocId, Allocation>,
+    // Live allocations by base address
+    live: BTreeMap<usize, AllocId>,
+    // Allocations whose address has been cast to an integer
+    exposed: HashSet<AllocId>,
+    next_id: u64,
+
+    // Statistics
+    checks: u64,
+}
+
+impl ProvenanceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a new object and return a pointer to its start
+    pub fn allocate(&mut self, kind: AllocationKind, base: usize, size: usize, label: impl Into<String>) -> TaggedPointer {
+        self.next_id += 1;
+        let id = AllocId(self.next_id);
+        self.allocations.insert(
+            id,
+            Allocation { id, kind, base, size, lifetime: Lifetime::Live, label: label.into() },
+        );
+        // Zero-sized objects can share an address with their neighbour
+        if size > 0 {
+            self.live.insert(base, id);
+        }
+        TaggedPointer { address: base, provenance: Some(id) }
+    }
+
+    /// `free(ptr)`. Freeing null does nothing, as in C.
+    pub fn free(&mut self, ptr: TaggedPointer) -> Result<(), ProvenanceError> {
+        if ptr.is_null() {
+            return Ok(());
+        }
+        let id = self.heap_block(ptr, "free")?;
+        self.end(id, Lifetime::Freed);
+        Ok(())
+    }
+
+    /// `realloc(ptr, size)` returned `new_base`. The old pointer, and every
+    /// pointer derived from it, is dead from here on even if the block
+    /// didn't move.
+    pub fn reallocate(&mut self, ptr: TaggedPointer, new_base: usize, new_size: usize) -> Result<TaggedPointer, ProvenanceError> {
+        if ptr.is_null() {
+            return Ok(self.allocate(AllocationKind::Heap, new_base, new_size, "realloc(NULL)"));
+        }
+        let old = self.heap_block(ptr, "realloc")?;
+        let label = self.allocations[&old].label.clone();
+        let new = self.allocate(AllocationKind::Heap, new_base, new_size, label);
+        // If the block didn't move, `end` leaves the new entry in `live` alone
+        self.end(old, Lifetime::Reallocated(new.provenance.unwrap()));
+        Ok(new)
+    }
+
+    /// A local, VLA or compound literal's scope ended
+    pub fn end_lifetime(&mut self, ptr: TaggedPointer) {
+        if let Some(id) = ptr.provenance {
+            self.end(id, Lifetime::OutOfScope);
+        }
+    }
+
+    /// `ptr + delta` in bytes. The result may point one past the end of the
+    /// allocation but no further, in either direction.
+    pub fn offset(&mut self, ptr: TaggedPointer, delta: isize) -> Result<TaggedPointer, ProvenanceError> {
+        self.checks += 1;
+        if delta == 0 {
+            return Ok(ptr);
+        }
+        let Some(id) = ptr.provenance else {
+            return Err(if ptr.is_null() {
+                ProvenanceError::NullArithmetic { delta }
+            } else {
+                ProvenanceError::NoProvenance { address: ptr.address, operation: "arithmetic" }
+            });
+        };
+        let allocation = self.live_allocation(id, "arithmetic")?;
+
+        let target = ptr.address.checked_add_signed(delta);
+        match target {
+            Some(address) if allocation.spans(address) => Ok(TaggedPointer { address, provenance: Some(id) }),
+            _ => {
+                let address = target.unwrap_or(0);
+                Err(ProvenanceError::OutOfBounds {
+                    allocation: Box::new(allocation.clone()),
+                    offset: ptr.address as isize - allocation.base as isize + delta,
+                    lands_in: self.live_containing(address).filter(|other| other.id != id).cloned().map(Box::new),
+                })
+            }
+        }
+    }
+
+    /// `a - b` in bytes; both must point into the same object
+    pub fn difference(&mut self, a: TaggedPointer, b: TaggedPointer) -> Result<isize, ProvenanceError> {
+        self.checks += 1;
+        self.same_object(a, b, "subtraction")?;
+        Ok(a.address as isize - b.address as isize)
+    }
+
+    pub fn compare(&mut self, a: TaggedPointer, b: TaggedPointer, op: Comparison) -> Result<bool, ProvenanceError> {
+        self.checks += 1;
+        let equality = matches!(op, Comparison::Equal | Comparison::NotEqual);
+        if !equality {
+            self.same_object(a, b, "relational comparison")?;
+        } else {
+            // Comparing against null is always fine; a dead pointer's value
+            // is indeterminate, so even `==` on it is an error
+            for ptr in [a, b] {
+                if let Some(id) = ptr.provenance {
+                    self.live_allocation(id, "comparison")?;
+                }
+            }
+            if let (Some(x), Some(y)) = (a.provenance, b.provenance) {
+                if x != y && a.address == b.address {
+                    return Err(ProvenanceError::AmbiguousEquality {
+                        left: Box::new(self.allocations[&x].clone()),
+                        right: Box::new(self.allocations[&y].clone()),
+                    });
+                }
+            }
+        }
+
+        Ok(match op {
+            Comparison::Equal => a.address == b.address,
+            Comparison::NotEqual => a.address != b.address,
+            Comparison::Less => a.address < b.address,
+            Comparison::LessEqual => a.address <= b.address,
+            Comparison::Greater => a.address > b.address,
+            Comparison::GreaterEqual => a.address >= b.address,
+        })
+    }
+
+    /// A load or store of `len` bytes through `ptr`
+    pub fn check_access(&mut self, ptr: TaggedPointer, len: usize, access: Access) -> Result<(), ProvenanceError> {
+        self.checks += 1;
+        let Some(id) = ptr.provenance else {
+            return Err(if ptr.is_null() {
+                ProvenanceError::NullDereference
+            } else {
+                ProvenanceError::NoProvenance { address: ptr.address, operation: "dereference" }
+            });
+        };
+        let allocation = self.live_allocation(id, "dereference")?;
+        if !allocation.contains(ptr.address, len) {
+            return Err(ProvenanceError::OutOfBoundsAccess {
+                allocation: Box::new(allocation.clone()),
+                offset: ptr.address as isize - allocation.base as isize,
+                len,
+            });
+        }
+        if access == Access::Write && allocation.kind == AllocationKind::Literal {
+            return Err(ProvenanceError::WriteToLiteral(Box::new(allocation.clone())));
+        }
+        Ok(())
+    }
+
+    /// `(uintptr_t)ptr`: the allocation becomes reachable from integers
+    pub fn expose(&mut self, ptr: TaggedPointer) -> usize {
+        if let Some(id) = ptr.provenance {
+            self.exposed.insert(id);
+        }
+        ptr.address
+    }
+
+    /// `(T *)address`. An address inside one exposed object and one past
+    /// the end of another resolves to the one it is inside.
+    pub fn from_integer(&self, address: usize) -> TaggedPointer {
+        let candidates = self
+            .live
+            .range(..=address)
+            .rev()
+            .take(2)
+            .map(|(_, id)| &self.allocations[id])
+            .filter(|allocation| self.exposed.contains(&allocation.id) && allocation.spans(address));
+        let provenance = candidates
+            .min_by_key(|allocation| allocation.base + allocation.size == address)
+            .map(|allocation| allocation.id);
+        TaggedPointer { address, provenance }
+    }
+
+    pub fn allocation(&self, id: AllocId) -> Option<&Allocation> {
+        self.allocations.get(&id)
+    }
+
+    /// Heap blocks never freed, for an exit-time report
+    pub fn live_heap_blocks(&self) -> impl Iterator<Item = &Allocation> {
+        self.live
+            .values()
+            .map(|id| &self.allocations[id])
+            .filter(|allocation| allocation.kind == AllocationKind::Heap)
+    }
+
+    /// (allocations recorded, pointer operations checked)
+    pub fn stats(&self) -> (u64, u64) {
+        (self.next_id, self.checks)
+    }
+
+    fn end(&mut self, id: AllocId, lifetime: Lifetime) {
+        if let Some(allocation) = self.allocations.get_mut(&id) {
+            allocation.lifetime = lifetime;
+            if self.live.get(&allocation.base) == Some(&id) {
+                self.live.remove(&allocation.base);
+            }
+        }
+    }
+
+    fn live_allocation(&self, id: AllocId, operation: &'static str) -> Result<&Allocation, ProvenanceError> {
+        let allocation = &self.allocations[&id];
+        match allocation.lifetime {
+            Lifetime::Live => Ok(allocation),
+            Lifetime::Reallocated(new) => Err(ProvenanceError::UseAfterRealloc {
+                allocation: Box::new(allocation.clone()),
+                replacement: Box::new(self.allocations[&new].clone()),
+                operation,
+            }),
+            _ => Err(ProvenanceError::UseAfterLifetime { allocation: Box::new(allocation.clone()), operation }),
+        }
+    }
+
+    /// The live heap block `ptr` is the start of
+    fn heap_block(&self, ptr: TaggedPointer, operation: &'static str) -> Result<AllocId, ProvenanceError> {
+        let id = ptr
+            .provenance
+            .ok_or(ProvenanceError::NoProvenance { address: ptr.address, operation })?;
+        let allocation = &self.allocations[&id];
+        if allocation.kind != AllocationKind::Heap {
+            return Err(ProvenanceError::FreeOfNonHeap(Box::new(allocation.clone())));
+        }
+        if allocation.lifetime == Lifetime::Freed {
+            return Err(ProvenanceError::DoubleFree(Box::new(allocation.clone())));
+        }
+        let allocation = self.live_allocation(id, operation)?;
+        if ptr.address != allocation.base {
+            return Err(ProvenanceError::InvalidFree {
+                allocation: Box::new(allocation.clone()),
+                offset: ptr.address as isize - allocation.base as isize,
+            });
+        }
+        Ok(id)
+    }
+
+    fn same_object(&self, a: TaggedPointer, b: TaggedPointer, operation: &'static str) -> Result<(), ProvenanceError> {
+        let (Some(x), Some(y)) = (a.provenance, b.provenance) else {
+            let address = if a.provenance.is_none() { a.address } else { b.address };
+            return Err(ProvenanceError::NoProvenance { address, operation });
+        };
+        self.live_allocation(x, operation)?;
+        self.live_allocation(y, operation)?;
+        if x != y {
+            return Err(ProvenanceError::DifferentObjects {
+                left: Box::new(self.allocations[&x].clone()),
+                right: Box::new(self.allocations[&y].clone()),
+                operation,
+            });
+        }
+        Ok(())
+    }
+
+    /// The live object whose bytes include `address`
+    fn live_containing(&self, address: usize) -> Option<&Allocation> {
+        let (_, id) = self.live.range(..=address).next_back()?;
+        let allocation = &self.allocations[id];
+        (address - allocation.base < allocation.size).then_some(allocation)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProvenanceError {
+    NullDereference,
+    NullArithmetic { delta: isize },
+    /// An integer cast to a pointer that matched no exposed object
+    NoProvenance { address: usize, operation: &'static str },
+    /// Arithmetic beyond one past the end, or before the start
+    OutOfBounds { allocation: Box<Allocation>, offset: isize, lands_in: Option<Box<Allocation>> },
+    OutOfBoundsAccess { allocation: Box<Allocation>, offset: isize, len: usize },
+    DifferentObjects { left: Box<Allocation>, right: Box<Allocation>, operation: &'static str },
+    /// One past the end of `left` has the same address as `right`
+    AmbiguousEquality { left: Box<Allocation>, right: Box<Allocation> },
+    UseAfterLifetime { allocation: Box<Allocation>, operation: &'static str },
+    UseAfterRealloc { allocation: Box<Allocation>, replacement: Box<Allocation>, operation: &'static str },
+    /// `free` of an interior pointer
+    InvalidFree { allocation: Box<Allocation>, offset: isize },
+    FreeOfNonHeap(Box<Allocation>),
+    DoubleFree(Box<Allocation>),
+    WriteToLiteral(Box<Allocation>),
+}
+
+impl fmt::Display for ProvenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvenanceError::NullDereference => write!(f, "dereference of a null pointer"),
+            ProvenanceError::NullArithmetic { delta } => write!(f, "arithmetic on a null pointer ({:+} bytes)", delta),
+            ProvenanceError::NoProvenance { address, operation } => {
+                write!(f, "{} on {:#x}, which doesn't point to any object exposed by a pointer-to-integer cast", operation, address)
+            }
+            ProvenanceError::OutOfBounds { allocation, offset, lands_in } => {
+                write!(f, "pointer arithmetic leaves {} at offset {}", allocation, offset)?;
+                match lands_in {
+                    Some(other) => write!(f, ", landing in {} (a different object)", other),
+                    None => Ok(()),
+                }
+            }
+            ProvenanceError::OutOfBoundsAccess { allocation, offset, len } => {
+                write!(f, "{}-byte access at offset {} of {}", len, offset, allocation)
+            }
+            ProvenanceError::DifferentObjects { left, right, operation } => {
+                write!(f, "{} of pointers into different objects: {} and {}", operation, left, right)
+            }
+            ProvenanceError::AmbiguousEquality { left, right } => write!(
+                f,
+                "== between one past the end of {} and the start of {} has an unspecified result",
+                left, right
+            ),
+            ProvenanceError::UseAfterLifetime { allocation, operation } => {
+                let how = if allocation.lifetime == Lifetime::Freed { "freed" } else { "out of scope" };
+                write!(f, "{} through a pointer to {}, which is {}", operation, allocation, how)
+            }
+            ProvenanceError::UseAfterRealloc { allocation, replacement, operation } => write!(
+                f,
+                "{} through a pointer to {}, which realloc replaced with {}{}",
+                operation,
+                allocation,
+                replacement,
+                if allocation.base == replacement.base { " at the same address" } else { "" }
+            ),
+            ProvenanceError::InvalidFree { allocation, offset } => {
+                write!(f, "free of offset {} into {}; only the start of a heap block can be freed", offset, allocation)
+            }
+            ProvenanceError::FreeOfNonHeap(allocation) => write!(f, "free of {}, which isn't a heap block", allocation),
+            ProvenanceError::DoubleFree(allocation) => write!(f, "{} freed twice", allocation),
+            ProvenanceError::WriteToLiteral(allocation) => write!(f, "write to {}", allocation),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() {
+    let mut tracker = ProvenanceTracker::new();
+
+    // int a[4], b[4]; laid out back to back
+    let a = tracker.allocate(AllocationKind::Stack, 0x1000, 16, "a");
+    let b = tracker.allocate(AllocationKind::Stack, 0x1010, 16, "b");
+
+    // a + 5 is &b[1] by address, but still derived from a
+    let err = tracker.offset(a, 20).unwrap_err();
+    println!("{}", err);
+    // pointer arithmetic leaves local 'a' (16 bytes, #1) at offset 20,
+    // landing in local 'b' (16 bytes, #2) (a different object)
+
+    // p = malloc(8); q = realloc(p, 16) in place; *p = 1;
+    let p = tracker.allocate(AllocationKind::Heap, 0x8000, 8, "malloc at line 12");
+    let q = tracker.reallocate(p, 0x8000, 16).unwrap();
+    assert!(tracker.check_access(q, 4, Access::Write).is_ok());
+    assert!(matches!(
+        tracker.check_access(p, 4, Access::Write),
+        Err(ProvenanceError::UseAfterRealloc { .. })
+    ));
+
+    // (int *)(uintptr_t)&b[0] keeps b's provenance
+    let address = tracker.expose(b);
+    assert_eq!(tracker.from_integer(address).provenance, b.provenance);
+
+    assert!(tracker.compare(a, b, Comparison::Less).is_err());
+    println!("{:?}", tracker.stats()); // (4, 4)
+}
+*/
@@ -0,0 +1,656 @@
+// src/interpreter/record.rs
+//! Record and replay of interpreted execution (`--record`, `--replay`)
+//! A recording is everything needed to run a program again exactly as it
+//! ran, and to move backwards through that run in the debugger:
+//!
+//! - a step per executed statement, with its source location
+//! - every memory write the program makes, with the bytes it overwrote
+//! - every syscall's result and errno, and the bytes the kernel wrote into
+//!   the program's buffers (`read`, `stat`, ...)
+//! - every other non-deterministic input: clocks, random numbers, the
+//!   environment and the process id
+//!
+//! Replaying feeds the recorded syscall results and inputs back instead of
+//! asking the host, so the interpreter takes the same path; a run that asks
+//! for something else stops with `RecordError::Divergence`. Program writes
+//! are recomputed on replay and only used for reverse execution.
+//!
+//! The file is a header followed by tagged little-endian records, written
+//! as execution proceeds. A recording cut short by a crash of the
+//! interpreter itself is still readable up to its last complete record.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"CITRACE\0";
+const VERSION: u32 = 1;
+
+// Record tags
+const TAG_STRING: u8 = 1;
+const TAG_STEP: u8 = 2;
+const TAG_WRITE: u8 = 3;
+const TAG_SYSCALL: u8 = 4;
+const TAG_KERNEL_WRITE: u8 = 5;
+const TAG_INPUT: u8 = 6;
+const TAG_END: u8 = 7;
+
+/// Whether the interpreter records, replays, or does neither
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TraceMode {
+    #[default]
+    Off,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// A non-deterministic value the program obtained other than by a syscall
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputKind {
+    /// `time`, `clock`, `clock_gettime`, `gettimeofday`
+    Clock,
+    /// `rand` seeds from the host, `getrandom`, `/dev/urandom`
+    Random,
+    /// `getenv`
+    Environment,
+    /// `getpid`, `getppid`
+    ProcessId,
+}
+
+impl InputKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            InputKind::Clock => 0,
+            InputKind::Random => 1,
+            InputKind::Environment => 2,
+            InputKind::ProcessId => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0 => InputKind::Clock,
+            1 => InputKind::Random,
+            2 => InputKind::Environment,
+            3 => InputKind::ProcessId,
+            _ => return None,
+        })
+    }
+}
+
+/// Where a step is in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePosition<'a> {
+    pub file: &'a str,
+    pub line: u32,
+    pub column: u32,
+    pub function: &'a str,
+}
+
+/// A step's position with the strings resolved against the trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+    pub function: String,
+}
+
+impl fmt::Display for StepLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{} in '{}'", self.file, self.line, self.column, self.function)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u64,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl MemoryWrite {
+    pub fn overlaps(&self, address: u64, len: usize) -> bool {
+        self.address < address + len as u64 && address < self.address + self.new.len() as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallRecord {
+    pub number: i64,
+    pub result: i64,
+    pub errno: i32,
+    /// What the kernel stored into the program's memory
+    pub writes: Vec<MemoryWrite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Write(MemoryWrite),
+    Syscall(SyscallRecord),
+    Input { kind: InputKind, bytes: Vec<u8> },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Step {
+    /// `None` for work done before the first statement, such as
+    /// initializing globals
+    pub location: Option<StepLocation>,
+    pub events: Vec<Event>,
+}
+
+impl Step {
+    /// Program and kernel writes, in the order they happened
+    pub fn writes(&self) -> impl Iterator<Item = &MemoryWrite> {
+        self.events.iter().flat_map(|event| match event {
+            Event::Write(write) => std::slice::from_ref(write),
+            Event::Syscall(syscall) => syscall.writes.as_slice(),
+            Event::Input { .. } => &[],
+        })
+    }
+}
+
+/// How the recorded run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEnd {
+    Exited(i32),
+    Signaled(i32),
+    /// The interpreter stopped with a runtime error
+    RuntimeError,
+}
+
+impl TraceEnd {
+    fn encode(self) -> (u8, i32) {
+        match self {
+            TraceEnd::Exited(code) => (0, code),
+            TraceEnd::Signaled(signal) => (1, signal),
+            TraceEnd::RuntimeError => (2, 0),
+        }
+    }
+
+    fn decode(kind: u8, value: i32) -> Option<Self> {
+        Some(match kind {
+            0 => TraceEnd::Exited(value),
+            1 => TraceEnd::Signaled(value),
+            2 => TraceEnd::RuntimeError,
+            _ => return None,
+        })
+    }
+}
+
+/// A recording, fully loaded
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub source_hash: u64,
+    pub steps: Vec<Step>,
+    /// `None` if the recording was cut short
+    pub end: Option<TraceEnd>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Self, RecordError> {
+        let data = fs::read(path).map_err(RecordError::Io)?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, RecordError> {
+        let mut reader = Reader { data, offset: 0 };
+        if reader.bytes(MAGIC.len()) != Some(&MAGIC[..]) {
+            return Err(RecordError::NotATrace);
+        }
+        match reader.u32() {
+            Some(VERSION) => {}
+            Some(version) => return Err(RecordError::UnsupportedVersion(version)),
+            None => return Err(RecordError::NotATrace),
+        }
+        let source_hash = reader.u64().ok_or(RecordError::NotATrace)?;
+
+        let mut trace = Trace { source_hash, steps: vec![Step::default()], end: None };
+        let mut strings: Vec<String> = Vec::new();
+        while reader.offset < data.len() {
+            // A partial record at the end means the recorder died mid-write
+            let start = reader.offset;
+            if reader.record(&mut trace, &mut strings)?.is_none() {
+                log::warn!("trace is truncated after {} bytes", start);
+                break;
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Fail unless the trace was recorded from `source`
+    pub fn check_source(&self, source: &str) -> Result<(), RecordError> {
+        if self.source_hash != source_hash(source) {
+            return Err(RecordError::SourceMismatch);
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8).map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn string(&mut self, strings: &[String]) -> Result<Option<String>, RecordError> {
+        let Some(id) = self.u32() else { return Ok(None) };
+        match strings.get(id as usize) {
+            Some(s) => Ok(Some(s.clone())),
+            None => Err(RecordError::Corrupt(format!("undefined string #{}", id))),
+        }
+    }
+
+    fn write(&mut self) -> Option<MemoryWrite> {
+        let address = self.u64()?;
+        let len = self.u32()? as usize;
+        let old = self.bytes(len)?.to_vec();
+        let new = self.bytes(len)?.to_vec();
+        Some(MemoryWrite { address, old, new })
+    }
+
+    /// One record into `trace`; `None` if the data ends inside it
+    fn record(&mut self, trace: &mut Trace, strings: &mut Vec<String>) -> Result<Option<()>, RecordError> {
+        let Some(tag) = self.u8() else { return Ok(None) };
+        let step = trace.steps.last_mut().unwrap();
+        match tag {
+            TAG_STRING => {
+                let Some(len) = self.u32() else { return Ok(None) };
+                let Some(bytes) = self.bytes(len as usize) else { return Ok(None) };
+                strings.push(String::from_utf8_lossy(bytes).into_owned());
+            }
+            TAG_STEP => {
+                let Some(file) = self.string(strings)? else { return Ok(None) };
+                let (Some(line), Some(column)) = (self.u32(), self.u32()) else { return Ok(None) };
+                let Some(function) = self.string(strings)? else { return Ok(None) };
+                trace.steps.push(Step {
+                    location: Some(StepLocation { file, line, column, function }),
+                    events: Vec::new(),
+                });
+            }
+            TAG_WRITE => {
+                let Some(write) = self.write() else { return Ok(None) };
+                step.events.push(Event::Write(write));
+            }
+            TAG_SYSCALL => {
+                let (Some(number), Some(result), Some(errno)) = (self.u64(), self.u64(), self.u32()) else {
+                    return Ok(None);
+                };
+                step.events.push(Event::Syscall(SyscallRecord {
+                    number: number as i64,
+                    result: result as i64,
+                    errno: errno as i32,
+                    writes: Vec::new(),
+                }));
+            }
+            TAG_KERNEL_WRITE => {
+                let Some(write) = self.write() else { return Ok(None) };
+                match step.events.last_mut() {
+                    Some(Event::Syscall(syscall)) => syscall.writes.push(write),
+                    _ => return Err(RecordError::Corrupt("kernel write outside a syscall".to_string())),
+                }
+            }
+            TAG_INPUT => {
+                let Some(kind) = self.u8() else { return Ok(None) };
+                let kind = InputKind::from_byte(kind)
+                    .ok_or_else(|| RecordError::Corrupt(format!("unknown input kind {}", kind)))?;
+                let Some(len) = self.u32() else { return Ok(None) };
+                let Some(bytes) = self.bytes(len as usize) else { return Ok(None) };
+                step.events.push(Event::Input { kind, bytes: bytes.to_vec() });
+            }
+            TAG_END => {
+                let (Some(kind), Some(value)) = (self.u8(), self.u32()) else { return Ok(None) };
+                trace.end = TraceEnd::decode(kind, value as i32);
+            }
+            other => return Err(RecordError::Corrupt(format!("unknown record tag {}", other))),
+        }
+        Ok(Some(()))
+    }
+}
+
+/// Writes a trace as the program runs. The hooks are called on the
+/// interpreter's hot path, so they don't return errors: the first I/O error
+/// stops recording and is reported by `finish`.
+pub struct ExecutionRecorder {
+    out: BufWriter<File>,
+    path: PathBuf,
+
+    // File, function names already written, by id
+    strings: HashMap<String, u32>,
+
+    // First write failure; nothing is recorded after it
+    error: Option<io::Error>,
+
+    // Statistics
+    steps: u64,
+    writes: u64,
+    syscalls: u64,
+    inputs: u64,
+}
+
+impl ExecutionRecorder {
+    /// Start a recording of `source` at `path`, replacing any file there
+    pub fn create(path: &Path, source: &str) -> Result<Self, RecordError> {
+        let file = File::create(path).map_err(RecordError::Io)?;
+        let mut recorder = ExecutionRecorder {
+            out: BufWriter::new(file),
+            path: path.to_path_buf(),
+            strings: HashMap::new(),
+            error: None,
+            steps: 0,
+            writes: 0,
+            syscalls: 0,
+            inputs: 0,
+        };
+        recorder.emit(|out| {
+            out.write_all(MAGIC)?;
+            out.write_all(&VERSION.to_le_bytes())?;
+            out.write_all(&source_hash(source).to_le_bytes())
+        });
+        recorder.error.take().map_or(Ok(recorder), |e| Err(RecordError::Io(e)))
+    }
+
+    /// A statement is about to execute
+    pub fn step(&mut self, position: &SourcePosition<'_>) {
+        let file = self.intern(position.file);
+        let function = self.intern(position.function);
+        self.steps += 1;
+        self.emit(|out| {
+            out.write_all(&[TAG_STEP])?;
+            out.write_all(&file.to_le_bytes())?;
+            out.write_all(&position.line.to_le_bytes())?;
+            out.write_all(&position.column.to_le_bytes())?;
+            out.write_all(&function.to_le_bytes())
+        });
+    }
+
+    /// The program stored `new` at `address`, replacing `old`
+    pub fn write(&mut self, address: u64, old: &[u8], new: &[u8]) {
+        self.writes += 1;
+        self.emit_write(TAG_WRITE, address, old, new);
+    }
+
+    /// A syscall returned; `writes` are the (address, old, new) bytes the
+    /// kernel stored into program memory
+    pub fn syscall(&mut self, number: i64, result: i64, errno: i32, writes: &[MemoryWrite]) {
+        self.syscalls += 1;
+        self.emit(|out| {
+            out.write_all(&[TAG_SYSCALL])?;
+            out.write_all(&number.to_le_bytes())?;
+            out.write_all(&result.to_le_bytes())?;
+            out.write_all(&errno.to_le_bytes())
+        });
+        for write in writes {
+            self.emit_write(TAG_KERNEL_WRITE, write.address, &write.old, &write.new);
+        }
+    }
+
+    pub fn input(&mut self, kind: InputKind, bytes: &[u8]) {
+        self.inputs += 1;
+        self.emit(|out| {
+            out.write_all(&[TAG_INPUT, kind.to_byte()])?;
+            out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+            out.write_all(bytes)
+        });
+    }
+
+    /// Record how the run ended and flush the file
+    pub fn finish(mut self, end: TraceEnd) -> Result<PathBuf, RecordError> {
+        let (kind, value) = end.encode();
+        self.emit(|out| {
+            out.write_all(&[TAG_END, kind])?;
+            out.write_all(&value.to_le_bytes())?;
+            out.flush()
+        });
+        match self.error {
+            Some(e) => Err(RecordError::Io(e)),
+            None => Ok(self.path),
+        }
+    }
+
+    /// (steps, memory writes, syscalls, inputs) recorded so far
+    pub fn stats(&self) -> (u64, u64, u64, u64) {
+        (self.steps, self.writes, self.syscalls, self.inputs)
+    }
+
+    fn intern(&mut self, s: &str) -> u32 {
+        if let Some(&id) = self.strings.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.insert(s.to_string(), id);
+        self.emit(|out| {
+            out.write_all(&[TAG_STRING])?;
+            out.write_all(&(s.len() as u32).to_le_bytes())?;
+            out.write_all(s.as_bytes())
+        });
+        id
+    }
+
+    fn emit_write(&mut self, tag: u8, address: u64, old: &[u8], new: &[u8]) {
+        debug_assert_eq!(old.len(), new.len());
+        self.emit(|out| {
+            out.write_all(&[tag])?;
+            out.write_all(&address.to_le_bytes())?;
+            out.write_all(&(new.len() as u32).to_le_bytes())?;
+            out.write_all(old)?;
+            out.write_all(new)
+        });
+    }
+
+    fn emit(&mut self, record: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) {
+        if self.error.is_none() {
+            if let Err(e) = record(&mut self.out) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Hands a trace's syscall results and inputs back to a second run, in
+/// order, and checks that the run follows the recorded steps
+pub struct Replayer {
+    trace: Trace,
+
+    // Next step, and next event within the current one
+    step: usize,
+    event: usize,
+}
+
+impl Replayer {
+    pub fn new(trace: Trace) -> Self {
+        Replayer { trace, step: 0, event: 0 }
+    }
+
+    /// A statement is about to execute; it must be the one recorded next
+    pub fn step(&mut self, position: &SourcePosition<'_>) -> Result<(), RecordError> {
+        self.skip_writes();
+        if let Some(pending) = self.current().events.get(self.event) {
+            return Err(self.diverged(format!("a statement at {}:{}", position.file, position.line), pending));
+        }
+        let Some(next) = self.trace.steps.get(self.step + 1) else {
+            return Err(RecordError::Divergence {
+                step: self.step,
+                expected: "the end of the program".to_string(),
+                found: format!("a statement at {}:{}", position.file, position.line),
+            });
+        };
+        let location = next.location.as_ref().unwrap();
+        if location.file != position.file || location.line != position.line || location.column != position.column {
+            return Err(RecordError::Divergence {
+                step: self.step + 1,
+                expected: format!("a statement at {}", location),
+                found: format!("{}:{}:{}", position.file, position.line, position.column),
+            });
+        }
+        self.step += 1;
+        self.event = 0;
+        Ok(())
+    }
+
+    /// The recorded outcome of the syscall the program makes now. The
+    /// caller applies `writes` to memory instead of entering the kernel.
+    pub fn syscall(&mut self, number: i64) -> Result<&SyscallRecord, RecordError> {
+        self.skip_writes();
+        let index = self.event;
+        match self.current().events.get(index) {
+            Some(Event::Syscall(syscall)) if syscall.number == number => {}
+            other => return Err(self.diverged_at(format!("syscall {}", number), other)),
+        }
+        self.event += 1;
+        match &self.current().events[index] {
+            Event::Syscall(syscall) => Ok(syscall),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn input(&mut self, kind: InputKind) -> Result<Vec<u8>, RecordError> {
+        self.skip_writes();
+        match self.current().events.get(self.event) {
+            Some(Event::Input { kind: recorded, bytes }) if *recorded == kind => {
+                let bytes = bytes.clone();
+                self.event += 1;
+                Ok(bytes)
+            }
+            other => Err(self.diverged_at(format!("{:?} input", kind), other)),
+        }
+    }
+
+    /// The program ended; so must the recording
+    pub fn finish(&mut self) -> Result<(), RecordError> {
+        self.skip_writes();
+        if let Some(pending) = self.current().events.get(self.event) {
+            return Err(self.diverged("the end of the program".to_string(), pending));
+        }
+        if let Some(next) = self.trace.steps.get(self.step + 1) {
+            return Err(RecordError::Divergence {
+                step: self.step + 1,
+                expected: format!("a statement at {}", next.location.as_ref().unwrap()),
+                found: "the end of the program".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Steps replayed so far
+    pub fn position(&self) -> usize {
+        self.step
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    fn current(&self) -> &Step {
+        &self.trace.steps[self.step]
+    }
+
+    /// Program writes are recomputed by the interpreter, not replayed
+    fn skip_writes(&mut self) {
+        while matches!(self.current().events.get(self.event), Some(Event::Write(_))) {
+            self.event += 1;
+        }
+    }
+
+    fn diverged_at(&self, found: String, expected: Option<&Event>) -> RecordError {
+        match expected {
+            Some(event) => self.diverged(found, event),
+            None => RecordError::Divergence {
+                step: self.step,
+                expected: "the next statement".to_string(),
+                found,
+            },
+        }
+    }
+
+    fn diverged(&self, found: String, expected: &Event) -> RecordError {
+        let expected = match expected {
+            Event::Syscall(syscall) => format!("syscall {}", syscall.number),
+            Event::Input { kind, .. } => format!("{:?} input", kind),
+            Event::Write(_) => "a memory write".to_string(),
+        };
+        RecordError::Divergence { step: self.step, expected, found }
+    }
+}
+
+/// FNV-1a of the program text, to refuse replaying a different program
+pub fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(io::Error),
+    NotATrace,
+    UnsupportedVersion(u32),
+    Corrupt(String),
+    /// The trace was recorded from a different program
+    SourceMismatch,
+    /// The replayed run asked for something other than what was recorded
+    Divergence {
+        step: usize,
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Io(e) => write!(f, "{}", e),
+            RecordError::NotATrace => write!(f, "not an execution trace"),
+            RecordError::UnsupportedVersion(version) => write!(f, "unsupported trace version {}", version),
+            RecordError::Corrupt(what) => write!(f, "corrupt trace: {}", what),
+            RecordError::SourceMismatch => write!(f, "the trace was recorded from a different program"),
+            RecordError::Divergence { step, expected, found } => {
+                write!(f, "replay diverged at step {}: expected {}, got {}", step, expected, found)
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), RecordError> {
+    let source = std::fs::read_to_string("prog.c").unwrap();
+    let position = SourcePosition { file: "prog.c", line: 4, column: 5, function: "main" };
+
+    // Recording run
+    let mut recorder = ExecutionRecorder::create(Path::new("prog.trace"), &source)?;
+    recorder.step(&position);
+    recorder.input(InputKind::Clock, &1_700_000_000i64.to_le_bytes());
+    recorder.write(0x7000, &[0; 8], &1_700_000_000i64.to_le_bytes());
+    recorder.finish(TraceEnd::Exited(0))?;
+
+    // Replaying run: time() returns what it returned the first time
+    let trace = Trace::load(Path::new("prog.trace"))?;
+    trace.check_source(&source)?;
+    let mut replayer = Replayer::new(trace);
+    replayer.step(&position)?;
+    let now = replayer.input(InputKind::Clock)?;
+    assert_eq!(now, 1_700_000_000i64.to_le_bytes());
+    Ok(())
+}
+*/
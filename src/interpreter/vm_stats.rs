@@ -0,0 +1,107 @@
+// src/interpreter/vm_stats.rs
+//! Opcode- and function-level execution counters
+//! Only compiled in with the `vm-stats` feature; without it every recording
+//! call is an empty inline function so the dispatch loop pays nothing.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone)]
+pub struct FunctionStats {
+    pub calls: u64,
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct VmStats {
+    // Executed instructions per opcode
+    opcodes: HashMap<&'static str, u64>,
+
+    // Per-function call and instruction counts
+    functions: HashMap<String, FunctionStats>,
+
+    // Active call stack for attributing instructions and time
+    call_stack: Vec<(String, Instant)>,
+}
+
+impl VmStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether counters are compiled in
+    pub const fn enabled() -> bool {
+        cfg!(feature = "vm-stats")
+    }
+
+    #[inline(always)]
+    pub fn record_opcode(&mut self, opcode: &'static str) {
+        #[cfg(feature = "vm-stats")]
+        {
+            *self.opcodes.entry(opcode).or_insert(0) += 1;
+            if let Some((function, _)) = self.call_stack.last() {
+                if let Some(stats) = self.functions.get_mut(function) {
+                    stats.instructions += 1;
+                }
+            }
+        }
+        #[cfg(not(feature = "vm-stats"))]
+        let _ = opcode;
+    }
+
+    #[inline(always)]
+    pub fn enter_function(&mut self, name: &str) {
+        #[cfg(feature = "vm-stats")]
+        {
+            self.functions.entry(name.to_string()).or_default().calls += 1;
+            self.call_stack.push((name.to_string(), Instant::now()));
+        }
+        #[cfg(not(feature = "vm-stats"))]
+        let _ = name;
+    }
+
+    #[inline(always)]
+    pub fn exit_function(&mut self) {
+        #[cfg(feature = "vm-stats")]
+        {
+            if let Some((name, start)) = self.call_stack.pop() {
+                if let Some(stats) = self.functions.get_mut(&name) {
+                    stats.time += start.elapsed();
+                }
+            }
+        }
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.opcodes.values().sum()
+    }
+
+    /// Human-readable summary of the `top` hottest opcodes and functions
+    pub fn summary(&self, top: usize) -> String {
+        let mut out = String::new();
+        let total = self.total_instructions().max(1);
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let _ = writeln!(out, "VM statistics: {} instructions", self.total_instructions());
+        let _ = writeln!(out, "  {:<24} {:>14} {:>7}", "opcode", "count", "share");
+        for (opcode, count) in opcodes.into_iter().take(top) {
+            let _ = writeln!(out, "  {:<24} {:>14} {:>6.2}%",
+                opcode, count, *count as f64 * 100.0 / total as f64);
+        }
+
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.instructions.cmp(&a.1.instructions).then(a.0.cmp(b.0)));
+
+        let _ = writeln!(out, "  {:<24} {:>10} {:>14} {:>12}", "function", "calls", "instructions", "time");
+        for (name, stats) in functions.into_iter().take(top) {
+            let _ = writeln!(out, "  {:<24} {:>10} {:>14} {:>12.3?}",
+                name, stats.calls, stats.instructions, stats.time);
+        }
+
+        out
+    }
+}
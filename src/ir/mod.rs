@@ -0,0 +1,384 @@
+// src/ir/mod.rs
+//! Versioned binary serialization of the intermediate representation
+//! The bytecode engine's programs (`program`) and the ThinLTO module
+//! summaries are written in one container format, so the compilation cache,
+//! `--emit-ir` and tools outside this crate can keep and exchange them
+//! across releases. Like `syntax`, this module's API follows semver, and so
+//! does the format: a reader of version 1.n reads every 1.x file.
+//!
+//! A file is a header and a sequence of sections:
+//!
+//! ```text
+//! header   "ICIR"  major: u16 LE  minor: u16 LE  kind: u8
+//! section  tag: u8  length: uleb128  payload
+//! ```
+//!
+//! `kind` is an `ArtifactKind`. A section tag with the high bit set is
+//! optional: readers that don't know it skip it. Without that bit, a reader
+//! that doesn't know the section has to refuse the file, because what it
+//! would build without it would be wrong. Inside sections, integers are
+//! ULEB128 (signed ones zigzag-encoded first), strings and byte strings
+//! are length-prefixed, and every record (a function, a symbol, an
+//! instruction...) is length-prefixed too, so a reader skips whatever
+//! fields a newer writer appended to it.
+//!
+//! What a minor version may add: optional sections, fields at the end of
+//! records, new instruction opcodes and new values of enumerations. An older
+//! reader still reads a program with instructions it can't decode: each
+//! becomes `Instruction::Unknown`, and only running one fails, so a program
+//! whose new instructions sit in code it never reaches still runs. A new
+//! value anywhere else, such as a relocation's target, is rejected with
+//! `IrError::Unknown`. Anything else, such as renumbering, removing or
+//! retyping a field, bumps the major version.
+
+use std::fmt;
+
+pub mod program;
+
+pub use program::{read_program, write_program};
+
+/// First bytes of every file
+pub const MAGIC: [u8; 4] = *b"ICIR";
+
+/// Bumped when existing bytes change meaning; readers refuse other majors
+pub const FORMAT_MAJOR: u16 = 1;
+/// Bumped when something is added; readers take any minor of their major.
+/// 1.1: shifts carry their operand width (opcodes 65-67)
+/// 1.2: functions carry their signature
+/// 1.3: variable length arrays (opcodes 68-70)
+/// 1.4: inline assembly (opcode 71, the optional asm section)
+pub const FORMAT_MINOR: u16 = 4;
+
+/// Optional sections have this bit set in their tag
+pub const OPTIONAL: u8 = 0x80;
+
+/// What a file holds, from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArtifactKind {
+    /// A bytecode program, from `write_program`
+    Program,
+    /// The summary of one LLVM module, for ThinLTO's index
+    LtoSummary,
+}
+
+impl ArtifactKind {
+    fn code(self) -> u8 {
+        match self {
+            ArtifactKind::Program => 1,
+            ArtifactKind::LtoSummary => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(ArtifactKind::Program),
+            2 => Some(ArtifactKind::LtoSummary),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ArtifactKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ArtifactKind::Program => "bytecode program",
+            ArtifactKind::LtoSummary => "LTO summary",
+        })
+    }
+}
+
+/// Builds a file section by section
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    /// A file of `kind`, at the current format version
+    pub fn new(kind: ArtifactKind) -> Self {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_MAJOR.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_MINOR.to_le_bytes());
+        bytes.push(kind.code());
+        Writer { bytes }
+    }
+
+    pub fn section(&mut self, tag: u8, payload: Encoder) {
+        self.bytes.push(tag);
+        let mut length = Encoder::new();
+        length.uint(payload.bytes.len() as u64);
+        self.bytes.extend_from_slice(&length.bytes);
+        self.bytes.extend_from_slice(&payload.bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// A section's payload, from `Reader`
+#[derive(Debug, Clone, Copy)]
+pub struct Section<'a> {
+    pub tag: u8,
+    pub payload: &'a [u8],
+}
+
+impl Section<'_> {
+    pub fn is_optional(&self) -> bool {
+        self.tag & OPTIONAL != 0
+    }
+}
+
+/// A file's header and sections
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    pub major: u16,
+    pub minor: u16,
+    pub kind: ArtifactKind,
+    sections: Vec<Section<'a>>,
+}
+
+impl<'a> Reader<'a> {
+    /// Check the header and split `data` into sections, without looking
+    /// inside them
+    pub fn new(data: &'a [u8]) -> Result<Self, IrError> {
+        if data.len() < 9 || data[..4] != MAGIC {
+            return Err(IrError::NotIr);
+        }
+        let major = u16::from_le_bytes([data[4], data[5]]);
+        let minor = u16::from_le_bytes([data[6], data[7]]);
+        if major != FORMAT_MAJOR {
+            return Err(IrError::Version { major, minor });
+        }
+        let kind = ArtifactKind::from_code(data[8]).ok_or(IrError::Unknown { what: "artifact kind", value: data[8] as u64 })?;
+
+        let mut decoder = Decoder::new(&data[9..]);
+        let mut sections = Vec::new();
+        while !decoder.is_empty() {
+            let tag = decoder.u8()?;
+            let payload = decoder.bytes()?;
+            sections.push(Section { tag, payload });
+        }
+        Ok(Reader { major, minor, kind, sections })
+    }
+
+    /// Like `new`, for a file that has to be of `kind` and whose required
+    /// sections the caller all knows, as listed in `known`
+    pub fn expect(data: &'a [u8], kind: ArtifactKind, known: &[u8]) -> Result<Self, IrError> {
+        let reader = Reader::new(data)?;
+        if reader.kind != kind {
+            return Err(IrError::WrongKind { expected: kind, found: reader.kind });
+        }
+        if let Some(section) = reader.sections.iter().find(|section| !section.is_optional() && !known.contains(&section.tag)) {
+            return Err(IrError::Unknown { what: "required section", value: section.tag as u64 });
+        }
+        Ok(reader)
+    }
+
+    pub fn sections(&self) -> &[Section<'a>] {
+        &self.sections
+    }
+
+    /// The first section tagged `tag`
+    pub fn section(&self, tag: u8) -> Option<Decoder<'a>> {
+        self.sections.iter().find(|section| section.tag == tag).map(|section| Decoder::new(section.payload))
+    }
+}
+
+/// Writes the contents of a section or record
+#[derive(Debug, Clone, Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    /// ULEB128
+    pub fn uint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    /// Zigzag, then ULEB128, so small negative numbers stay short
+    pub fn int(&mut self, value: i64) {
+        self.uint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.uint(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    /// A length-prefixed record, whose fields `fields` writes
+    pub fn record(&mut self, fields: impl FnOnce(&mut Encoder)) {
+        let mut record = Encoder::new();
+        fields(&mut record);
+        self.bytes(&record.bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+/// Reads the contents of a section or record
+#[derive(Debug, Clone)]
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Decoder { data, position: 0 }
+    }
+
+    /// Whether everything has been read
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    pub fn u8(&mut self) -> Result<u8, IrError> {
+        let byte = *self.data.get(self.position).ok_or(IrError::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, IrError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(IrError::Invalid(format!("{} is not a boolean", other))),
+        }
+    }
+
+    pub fn uint(&mut self) -> Result<u64, IrError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(IrError::Invalid("integer longer than 64 bits".to_string()))
+    }
+
+    pub fn int(&mut self) -> Result<i64, IrError> {
+        let value = self.uint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// An unsigned integer that has to fit in `T`
+    pub fn uint_as<T: TryFrom<u64>>(&mut self) -> Result<T, IrError> {
+        let value = self.uint()?;
+        T::try_from(value).map_err(|_| IrError::Invalid(format!("{} is out of range", value)))
+    }
+
+    /// A signed integer that has to fit in `T`
+    pub fn int_as<T: TryFrom<i64>>(&mut self) -> Result<T, IrError> {
+        let value = self.int()?;
+        T::try_from(value).map_err(|_| IrError::Invalid(format!("{} is out of range", value)))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], IrError> {
+        let length = self.uint_as::<usize>()?;
+        let end = self.position.checked_add(length).filter(|&end| end <= self.data.len()).ok_or(IrError::Truncated)?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn str(&mut self) -> Result<&'a str, IrError> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| IrError::Invalid("string is not UTF-8".to_string()))
+    }
+
+    /// The next record; fields left unread in it are skipped
+    pub fn record(&mut self) -> Result<Decoder<'a>, IrError> {
+        self.bytes().map(Decoder::new)
+    }
+
+    /// A count of items to come, each taking at least a byte; a count that
+    /// can't be right fails here rather than in a huge allocation
+    pub fn count(&mut self) -> Result<usize, IrError> {
+        let count = self.uint_as::<usize>()?;
+        if count > self.data.len() - self.position.min(self.data.len()) {
+            return Err(IrError::Truncated);
+        }
+        Ok(count)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IrError {
+    /// Doesn't start with `MAGIC`
+    NotIr,
+    /// Written with a major version this reader doesn't read
+    Version { major: u16, minor: u16 },
+    WrongKind { expected: ArtifactKind, found: ArtifactKind },
+    /// Ends in the middle of something
+    Truncated,
+    /// A newer writer's addition this reader can't do without: a required
+    /// section, an opcode, an enumeration value
+    Unknown { what: &'static str, value: u64 },
+    Invalid(String),
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrError::NotIr => write!(f, "not an IR file"),
+            IrError::Version { major, minor } => write!(
+                f,
+                "IR format {}.{} is not supported; this build reads {}.x",
+                major, minor, FORMAT_MAJOR
+            ),
+            IrError::WrongKind { expected, found } => write!(f, "expected a {}, found a {}", expected, found),
+            IrError::Truncated => write!(f, "IR file is truncated"),
+            IrError::Unknown { what, value } => {
+                write!(f, "unknown {} {}; the file needs a newer version of this tool", what, value)
+            }
+            IrError::Invalid(message) => write!(f, "invalid IR: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for IrError {}
+
+// Example usage:
+/*
+fn cache_program(program: &Program, path: &Path) -> io::Result<()> {
+    fs::write(path, ir::write_program(program))
+}
+
+fn load_program(path: &Path) -> Result<Program, Box<dyn std::error::Error>> {
+    // A file from an older or newer 1.x release reads the same way
+    Ok(ir::read_program(&fs::read(path)?)?)
+}
+*/
@@ -0,0 +1,725 @@
+// src/ir/program.rs
+//! Bytecode programs in the IR format
+//! Each table of `Program` is a required section; a reader that finds none
+//! takes the table as empty. Instructions are records of an opcode and
+//! the instruction's fields in declaration order: registers, indices and
+//! sizes unsigned, immediates and addends signed, `Kind`, `SignedOp` and
+//! `Comparison` as their codes below. Opcodes and codes are never reused;
+//! a retired one stays reserved. An instruction with an opcode or a code
+//! this reader doesn't know reads as `Instruction::Unknown`, which fails
+//! only when the VM reaches it.
+
+use crate::interpreter::bytecode::{
+    AsmBlock, Comparison, External, Function, Instruction, Kind, Program, Reg, Relocation, RelocationTarget, Signature, SwitchTable,
+};
+use crate::optimizer::overflow::SignedOp;
+use super::{ArtifactKind, Decoder, Encoder, IrError, Reader, Writer, OPTIONAL};
+
+// Sections
+const FILE: u8 = 0x01;
+const FUNCTIONS: u8 = 0x02;
+const EXTERNALS: u8 = 0x03;
+const SIGNATURES: u8 = 0x04;
+const CONSTANTS: u8 = 0x05;
+const SWITCH_TABLES: u8 = 0x06;
+const DATA: u8 = 0x07;
+const RELOCATIONS: u8 = 0x08;
+/// Name and version of the tool that wrote the file
+const PRODUCER: u8 = OPTIONAL | 0x01;
+/// `Program::asm`, since 1.4; only written when there is inline assembly.
+/// A reader without it reads the `Asm` instructions as unknown.
+const ASM: u8 = OPTIONAL | 0x02;
+
+const REQUIRED: &[u8] = &[FILE, FUNCTIONS, EXTERNALS, SIGNATURES, CONSTANTS, SWITCH_TABLES, DATA, RELOCATIONS];
+
+/// Codes of `Kind`, `SignedOp` and `Comparison`: their index
+const KINDS: [Kind; 10] = [Kind::I8, Kind::U8, Kind::I16, Kind::U16, Kind::I32, Kind::U32, Kind::I64, Kind::U64, Kind::F32, Kind::F64];
+const SIGNED_OPS: [SignedOp; 7] = [SignedOp::Add, SignedOp::Sub, SignedOp::Mul, SignedOp::Neg, SignedOp::Div, SignedOp::Rem, SignedOp::Shl];
+const COMPARISONS: [Comparison; 6] = [Comparison::Eq, Comparison::Ne, Comparison::LtS, Comparison::LeS, Comparison::LtU, Comparison::LeU];
+
+/// `program` in the current format version
+pub fn write_program(program: &Program) -> Vec<u8> {
+    let mut writer = Writer::new(ArtifactKind::Program);
+
+    let mut producer = Encoder::new();
+    producer.str(env!("CARGO_PKG_NAME"));
+    producer.str(env!("CARGO_PKG_VERSION"));
+    writer.section(PRODUCER, producer);
+
+    let mut file = Encoder::new();
+    file.str(&program.file);
+    writer.section(FILE, file);
+
+    writer.section(FUNCTIONS, table(&program.functions, |e, function| {
+        e.str(&function.name);
+        e.uint(function.parameters as u64);
+        e.uint(function.registers as u64);
+        e.uint(function.frame_size as u64);
+        e.uint(function.code.len() as u64);
+        for instruction in &function.code {
+            e.record(|e| write_instruction(e, instruction));
+        }
+        // Since 1.2
+        e.bool(function.signature.is_some());
+        if let Some(signature) = &function.signature {
+            e.record(|e| write_signature(e, signature));
+        }
+    }));
+    writer.section(EXTERNALS, table(&program.externals, |e, external| {
+        e.str(&external.name);
+        e.record(|e| write_signature(e, &external.signature));
+    }));
+    writer.section(SIGNATURES, table(&program.signatures, write_signature));
+
+    let mut constants = Encoder::new();
+    constants.uint(program.constants.len() as u64);
+    for &constant in &program.constants {
+        constants.uint(constant);
+    }
+    writer.section(CONSTANTS, constants);
+
+    writer.section(SWITCH_TABLES, table(&program.switch_tables, |e, switch| {
+        e.uint(switch.default as u64);
+        e.uint(switch.cases.len() as u64);
+        for &(value, target) in &switch.cases {
+            e.int(value);
+            e.uint(target as u64);
+        }
+    }));
+
+    if !program.asm.is_empty() {
+        writer.section(ASM, table(&program.asm, |e, block| {
+            e.str(&block.code);
+            strings(e, &block.registers);
+            e.u8(block.outputs);
+            strings(e, &block.saved);
+        }));
+    }
+
+    let mut data = Encoder::new();
+    data.bytes(&program.data);
+    writer.section(DATA, data);
+
+    writer.section(RELOCATIONS, table(&program.relocations, |e, relocation| {
+        e.uint(relocation.offset as u64);
+        let (kind, index) = match relocation.target {
+            RelocationTarget::Data(offset) => (0, offset),
+            RelocationTarget::Function(function) => (1, function),
+            RelocationTarget::External(external) => (2, external),
+        };
+        e.u8(kind);
+        e.uint(index as u64);
+        e.int(relocation.addend);
+    }));
+
+    writer.finish()
+}
+
+/// A program written by `write_program` of any 1.x release. Check it with
+/// `Program::verify` before running it, as for any program from outside.
+pub fn read_program(data: &[u8]) -> Result<Program, IrError> {
+    let reader = Reader::expect(data, ArtifactKind::Program, REQUIRED)?;
+    let mut program = Program::default();
+
+    if let Some(mut file) = reader.section(FILE) {
+        program.file = file.str()?.to_string();
+    }
+    if let Some(mut functions) = reader.section(FUNCTIONS) {
+        program.functions = read_table(&mut functions, |d| {
+            let name = d.str()?.to_string();
+            let parameters = d.uint_as()?;
+            let registers = d.uint_as()?;
+            let frame_size = d.uint_as()?;
+            let length = d.count()?;
+            let code = (0..length).map(|_| read_instruction(&mut d.record()?)).collect::<Result<_, _>>()?;
+            let signature = if !d.is_empty() && d.bool()? { Some(read_signature(&mut d.record()?)?) } else { None };
+            Ok(Function { name, parameters, registers, frame_size, code, signature })
+        })?;
+    }
+    if let Some(mut externals) = reader.section(EXTERNALS) {
+        program.externals = read_table(&mut externals, |d| {
+            Ok(External {
+                name: d.str()?.to_string(),
+                signature: read_signature(&mut d.record()?)?,
+            })
+        })?;
+    }
+    if let Some(mut signatures) = reader.section(SIGNATURES) {
+        program.signatures = read_table(&mut signatures, read_signature)?;
+    }
+    if let Some(mut constants) = reader.section(CONSTANTS) {
+        let count = constants.count()?;
+        program.constants = (0..count).map(|_| constants.uint()).collect::<Result<_, _>>()?;
+    }
+    if let Some(mut tables) = reader.section(SWITCH_TABLES) {
+        program.switch_tables = read_table(&mut tables, |d| {
+            let default = d.uint_as()?;
+            let count = d.count()?;
+            let cases = (0..count)
+                .map(|_| -> Result<(i64, u32), IrError> { Ok((d.int()?, d.uint_as()?)) })
+                .collect::<Result<_, _>>()?;
+            Ok(SwitchTable { cases, default })
+        })?;
+    }
+    if let Some(mut blocks) = reader.section(ASM) {
+        program.asm = read_table(&mut blocks, |d| {
+            Ok(AsmBlock {
+                code: d.str()?.to_string(),
+                registers: read_strings(d)?,
+                outputs: d.u8()?,
+                saved: read_strings(d)?,
+            })
+        })?;
+    }
+    if let Some(mut data) = reader.section(DATA) {
+        program.data = data.bytes()?.to_vec();
+    }
+    if let Some(mut relocations) = reader.section(RELOCATIONS) {
+        program.relocations = read_table(&mut relocations, |d| {
+            let offset = d.uint_as()?;
+            let kind = d.u8()?;
+            let index = d.uint_as()?;
+            let target = match kind {
+                0 => RelocationTarget::Data(index),
+                1 => RelocationTarget::Function(index),
+                2 => RelocationTarget::External(index),
+                other => return Err(IrError::Unknown { what: "relocation target", value: other as u64 }),
+            };
+            Ok(Relocation { offset, target, addend: d.int()? })
+        })?;
+    }
+    Ok(program)
+}
+
+/// The tool and version that wrote a program, if it says
+pub fn producer(data: &[u8]) -> Result<Option<(String, String)>, IrError> {
+    let reader = Reader::new(data)?;
+    match reader.section(PRODUCER) {
+        Some(mut producer) => Ok(Some((producer.str()?.to_string(), producer.str()?.to_string()))),
+        None => Ok(None),
+    }
+}
+
+/// A count, then one record per item
+fn table<T>(items: &[T], mut write: impl FnMut(&mut Encoder, &T)) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder.uint(items.len() as u64);
+    for item in items {
+        encoder.record(|e| write(e, item));
+    }
+    encoder
+}
+
+fn read_table<'a, T>(decoder: &mut Decoder<'a>, mut read: impl FnMut(&mut Decoder<'a>) -> Result<T, IrError>) -> Result<Vec<T>, IrError> {
+    let count = decoder.count()?;
+    (0..count).map(|_| read(&mut decoder.record()?)).collect()
+}
+
+/// A count, then each string
+fn strings(e: &mut Encoder, items: &[String]) {
+    e.uint(items.len() as u64);
+    for item in items {
+        e.str(item);
+    }
+}
+
+fn read_strings(d: &mut Decoder<'_>) -> Result<Vec<String>, IrError> {
+    let count = d.count()?;
+    (0..count).map(|_| d.str().map(str::to_string)).collect()
+}
+
+fn write_signature(e: &mut Encoder, signature: &Signature) {
+    e.uint(signature.parameters.len() as u64);
+    for &kind in &signature.parameters {
+        e.u8(kind_code(kind));
+    }
+    // 0 for none, else one more than the value
+    e.uint(signature.variadic.map_or(0, |fixed| fixed as u64 + 1));
+    e.u8(signature.result.map_or(0, |kind| kind_code(kind) + 1));
+}
+
+fn read_signature(d: &mut Decoder<'_>) -> Result<Signature, IrError> {
+    let count = d.count()?;
+    let parameters = (0..count).map(|_| read_kind(d)).collect::<Result<_, _>>()?;
+    let variadic = match d.uint()? {
+        0 => None,
+        fixed => Some(u16::try_from(fixed - 1).map_err(|_| IrError::Invalid(format!("{} fixed parameters", fixed - 1)))?),
+    };
+    let result = match d.u8()? {
+        0 => None,
+        code => Some(kind_from(code - 1)?),
+    };
+    Ok(Signature { parameters, variadic, result })
+}
+
+fn kind_code(kind: Kind) -> u8 {
+    KINDS.iter().position(|&k| k == kind).unwrap() as u8
+}
+
+fn kind_from(code: u8) -> Result<Kind, IrError> {
+    KINDS.get(code as usize).copied().ok_or(IrError::Unknown { what: "value kind", value: code as u64 })
+}
+
+fn read_kind(d: &mut Decoder<'_>) -> Result<Kind, IrError> {
+    kind_from(d.u8()?)
+}
+
+fn read_signed_op(d: &mut Decoder<'_>) -> Result<SignedOp, IrError> {
+    let code = d.u8()?;
+    SIGNED_OPS.get(code as usize).copied().ok_or(IrError::Unknown { what: "signed operation", value: code as u64 })
+}
+
+fn read_comparison(d: &mut Decoder<'_>) -> Result<Comparison, IrError> {
+    let code = d.u8()?;
+    COMPARISONS.get(code as usize).copied().ok_or(IrError::Unknown { what: "comparison", value: code as u64 })
+}
+
+fn reg(d: &mut Decoder<'_>) -> Result<Reg, IrError> {
+    d.uint_as()
+}
+
+fn index(d: &mut Decoder<'_>) -> Result<u32, IrError> {
+    d.uint_as()
+}
+
+fn write_instruction(e: &mut Encoder, instruction: &Instruction) {
+    let regs = |e: &mut Encoder, registers: &[Reg]| registers.iter().for_each(|&register| e.uint(register as u64));
+    let signed_op = |op: SignedOp| SIGNED_OPS.iter().position(|&o| o == op).unwrap() as u8;
+    let comparison = |c: Comparison| COMPARISONS.iter().position(|&k| k == c).unwrap() as u8;
+    match *instruction {
+        Instruction::Move { dst, src } => {
+            e.uint(1);
+            regs(e, &[dst, src]);
+        }
+        Instruction::Int { dst, value } => {
+            e.uint(2);
+            regs(e, &[dst]);
+            e.int(value as i64);
+        }
+        Instruction::Const { dst, index } => {
+            e.uint(3);
+            regs(e, &[dst]);
+            e.uint(index as u64);
+        }
+        Instruction::Add { dst, a, b } => {
+            e.uint(4);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::Sub { dst, a, b } => {
+            e.uint(5);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::Mul { dst, a, b } => {
+            e.uint(6);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::AddImm { dst, a, value } => {
+            e.uint(7);
+            regs(e, &[dst, a]);
+            e.int(value as i64);
+        }
+        Instruction::DivU { dst, a, b } => {
+            e.uint(8);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::RemU { dst, a, b } => {
+            e.uint(9);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::And { dst, a, b } => {
+            e.uint(10);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::Or { dst, a, b } => {
+            e.uint(11);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::Xor { dst, a, b } => {
+            e.uint(12);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::Shl { dst, a, b, bits } => {
+            e.uint(65);
+            regs(e, &[dst, a, b]);
+            e.u8(bits);
+        }
+        Instruction::ShrU { dst, a, b, bits } => {
+            e.uint(66);
+            regs(e, &[dst, a, b]);
+            e.u8(bits);
+        }
+        Instruction::ShrS { dst, a, b, bits } => {
+            e.uint(67);
+            regs(e, &[dst, a, b]);
+            e.u8(bits);
+        }
+        Instruction::Signed { op, dst, a, b, bits } => {
+            e.uint(16);
+            e.u8(signed_op(op));
+            regs(e, &[dst, a, b]);
+            e.u8(bits);
+        }
+        Instruction::Neg { dst, src } => {
+            e.uint(17);
+            regs(e, &[dst, src]);
+        }
+        Instruction::Not { dst, src } => {
+            e.uint(18);
+            regs(e, &[dst, src]);
+        }
+        Instruction::LogicalNot { dst, src } => {
+            e.uint(19);
+            regs(e, &[dst, src]);
+        }
+        Instruction::Test { dst, src } => {
+            e.uint(20);
+            regs(e, &[dst, src]);
+        }
+        Instruction::Extend { dst, src, bits, signed } => {
+            e.uint(21);
+            regs(e, &[dst, src]);
+            e.u8(bits);
+            e.bool(signed);
+        }
+        Instruction::Eq { dst, a, b } => {
+            e.uint(22);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::Ne { dst, a, b } => {
+            e.uint(23);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::LtS { dst, a, b } => {
+            e.uint(24);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::LeS { dst, a, b } => {
+            e.uint(25);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::LtU { dst, a, b } => {
+            e.uint(26);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::LeU { dst, a, b } => {
+            e.uint(27);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FAdd { dst, a, b } => {
+            e.uint(28);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FSub { dst, a, b } => {
+            e.uint(29);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FMul { dst, a, b } => {
+            e.uint(30);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FDiv { dst, a, b } => {
+            e.uint(31);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FNeg { dst, src } => {
+            e.uint(32);
+            regs(e, &[dst, src]);
+        }
+        Instruction::FEq { dst, a, b } => {
+            e.uint(33);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FNe { dst, a, b } => {
+            e.uint(34);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FLt { dst, a, b } => {
+            e.uint(35);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::FLe { dst, a, b } => {
+            e.uint(36);
+            regs(e, &[dst, a, b]);
+        }
+        Instruction::RoundF32 { dst, src } => {
+            e.uint(37);
+            regs(e, &[dst, src]);
+        }
+        Instruction::IntToFloat { dst, src, signed } => {
+            e.uint(38);
+            regs(e, &[dst, src]);
+            e.bool(signed);
+        }
+        Instruction::FloatToInt { dst, src, signed } => {
+            e.uint(39);
+            regs(e, &[dst, src]);
+            e.bool(signed);
+        }
+        Instruction::Load { dst, address, kind } => {
+            e.uint(40);
+            regs(e, &[dst, address]);
+            e.u8(kind_code(kind));
+        }
+        Instruction::Store { address, src, kind } => {
+            e.uint(41);
+            regs(e, &[address, src]);
+            e.u8(kind_code(kind));
+        }
+        Instruction::LocalAddress { dst, offset } => {
+            e.uint(42);
+            regs(e, &[dst]);
+            e.uint(offset as u64);
+        }
+        Instruction::GlobalAddress { dst, offset } => {
+            e.uint(43);
+            regs(e, &[dst]);
+            e.uint(offset as u64);
+        }
+        Instruction::FunctionAddress { dst, function } => {
+            e.uint(44);
+            regs(e, &[dst]);
+            e.uint(function as u64);
+        }
+        Instruction::ExternalAddress { dst, external } => {
+            e.uint(45);
+            regs(e, &[dst]);
+            e.uint(external as u64);
+        }
+        Instruction::CopyBytes { dst, src, size } => {
+            e.uint(46);
+            regs(e, &[dst, src]);
+            e.uint(size as u64);
+        }
+        Instruction::ZeroBytes { dst, size } => {
+            e.uint(47);
+            regs(e, &[dst]);
+            e.uint(size as u64);
+        }
+        Instruction::Jump { target } => {
+            e.uint(48);
+            e.uint(target as u64);
+        }
+        Instruction::JumpIfZero { condition, target } => {
+            e.uint(49);
+            regs(e, &[condition]);
+            e.uint(target as u64);
+        }
+        Instruction::JumpIfNonZero { condition, target } => {
+            e.uint(50);
+            regs(e, &[condition]);
+            e.uint(target as u64);
+        }
+        Instruction::Switch { value, table } => {
+            e.uint(51);
+            regs(e, &[value]);
+            e.uint(table as u64);
+        }
+        Instruction::Call { dst, function, arguments, count } => {
+            e.uint(52);
+            regs(e, &[dst]);
+            e.uint(function as u64);
+            regs(e, &[arguments]);
+            e.u8(count);
+        }
+        Instruction::CallIndirect { dst, callee, arguments, count, signature } => {
+            e.uint(53);
+            regs(e, &[dst, callee, arguments]);
+            e.u8(count);
+            e.uint(signature as u64);
+        }
+        Instruction::CallExternal { dst, external, arguments, count, signature } => {
+            e.uint(54);
+            regs(e, &[dst]);
+            e.uint(external as u64);
+            regs(e, &[arguments]);
+            e.u8(count);
+            e.uint(signature as u64);
+        }
+        Instruction::Return { src } => {
+            e.uint(55);
+            regs(e, &[src]);
+        }
+        Instruction::ReturnVoid => e.uint(56),
+        Instruction::Statement { line } => {
+            e.uint(57);
+            e.uint(line as u64);
+        }
+        Instruction::Probe { site, arguments, count } => {
+            e.uint(58);
+            e.uint(site as u64);
+            regs(e, &[arguments]);
+            e.u8(count);
+        }
+        Instruction::SignedImm { op, dst, a, temp, value, bits } => {
+            e.uint(59);
+            e.u8(signed_op(op));
+            regs(e, &[dst, a, temp]);
+            e.int(value as i64);
+            e.u8(bits);
+        }
+        Instruction::SignedMove { op, dst, a, b, temp, bits } => {
+            e.uint(60);
+            e.u8(signed_op(op));
+            regs(e, &[dst, a, b, temp]);
+            e.u8(bits);
+        }
+        Instruction::CompareImm { comparison: c, dst, a, temp, value } => {
+            e.uint(61);
+            e.u8(comparison(c));
+            regs(e, &[dst, a, temp]);
+            e.int(value as i64);
+        }
+        Instruction::CompareJump { comparison: c, dst, a, b, target, when } => {
+            e.uint(62);
+            e.u8(comparison(c));
+            regs(e, &[dst, a, b]);
+            e.uint(target as u64);
+            e.bool(when);
+        }
+        Instruction::CompareImmJump { comparison: c, dst, a, temp, value, target, when } => {
+            e.uint(63);
+            e.u8(comparison(c));
+            regs(e, &[dst, a, temp]);
+            e.int(value as i64);
+            e.uint(target as u64);
+            e.bool(when);
+        }
+        Instruction::MoveJump { dst, src, target } => {
+            e.uint(64);
+            regs(e, &[dst, src]);
+            e.uint(target as u64);
+        }
+        // Its fields weren't read; it still fails where it runs
+        Instruction::Unknown { opcode } => e.uint(opcode),
+        Instruction::VlaMark { dst } => {
+            e.uint(68);
+            regs(e, &[dst]);
+        }
+        Instruction::VlaAllocate { dst, length, size, zero } => {
+            e.uint(69);
+            regs(e, &[dst, length]);
+            e.uint(size as u64);
+            e.bool(zero);
+        }
+        Instruction::VlaRelease { mark } => {
+            e.uint(70);
+            regs(e, &[mark]);
+        }
+        Instruction::Asm { index, operands, count } => {
+            e.uint(71);
+            e.uint(index as u64);
+            regs(e, &[operands]);
+            e.u8(count);
+        }
+    }
+}
+
+fn read_instruction(d: &mut Decoder<'_>) -> Result<Instruction, IrError> {
+    let opcode = d.uint()?;
+    match decode_instruction(opcode, d) {
+        // From a newer minor version: an opcode, or a comparison or
+        // operation of a known one; the record's length skips its fields
+        Err(IrError::Unknown { .. }) => Ok(Instruction::Unknown { opcode }),
+        decoded => decoded,
+    }
+}
+
+fn decode_instruction(opcode: u64, d: &mut Decoder<'_>) -> Result<Instruction, IrError> {
+    Ok(match opcode {
+        1 => Instruction::Move { dst: reg(d)?, src: reg(d)? },
+        2 => Instruction::Int { dst: reg(d)?, value: d.int_as()? },
+        3 => Instruction::Const { dst: reg(d)?, index: index(d)? },
+        4 => Instruction::Add { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        5 => Instruction::Sub { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        6 => Instruction::Mul { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        7 => Instruction::AddImm { dst: reg(d)?, a: reg(d)?, value: d.int_as()? },
+        8 => Instruction::DivU { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        9 => Instruction::RemU { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        10 => Instruction::And { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        11 => Instruction::Or { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        12 => Instruction::Xor { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        // Retired: shifts from before they carried their width
+        13 => Instruction::Shl { dst: reg(d)?, a: reg(d)?, b: reg(d)?, bits: 64 },
+        14 => Instruction::ShrU { dst: reg(d)?, a: reg(d)?, b: reg(d)?, bits: 64 },
+        15 => Instruction::ShrS { dst: reg(d)?, a: reg(d)?, b: reg(d)?, bits: 64 },
+        16 => Instruction::Signed { op: read_signed_op(d)?, dst: reg(d)?, a: reg(d)?, b: reg(d)?, bits: d.u8()? },
+        17 => Instruction::Neg { dst: reg(d)?, src: reg(d)? },
+        18 => Instruction::Not { dst: reg(d)?, src: reg(d)? },
+        19 => Instruction::LogicalNot { dst: reg(d)?, src: reg(d)? },
+        20 => Instruction::Test { dst: reg(d)?, src: reg(d)? },
+        21 => Instruction::Extend { dst: reg(d)?, src: reg(d)?, bits: d.u8()?, signed: d.bool()? },
+        22 => Instruction::Eq { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        23 => Instruction::Ne { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        24 => Instruction::LtS { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        25 => Instruction::LeS { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        26 => Instruction::LtU { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        27 => Instruction::LeU { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        28 => Instruction::FAdd { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        29 => Instruction::FSub { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        30 => Instruction::FMul { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        31 => Instruction::FDiv { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        32 => Instruction::FNeg { dst: reg(d)?, src: reg(d)? },
+        33 => Instruction::FEq { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        34 => Instruction::FNe { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        35 => Instruction::FLt { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        36 => Instruction::FLe { dst: reg(d)?, a: reg(d)?, b: reg(d)? },
+        37 => Instruction::RoundF32 { dst: reg(d)?, src: reg(d)? },
+        38 => Instruction::IntToFloat { dst: reg(d)?, src: reg(d)?, signed: d.bool()? },
+        39 => Instruction::FloatToInt { dst: reg(d)?, src: reg(d)?, signed: d.bool()? },
+        40 => Instruction::Load { dst: reg(d)?, address: reg(d)?, kind: read_kind(d)? },
+        41 => Instruction::Store { address: reg(d)?, src: reg(d)?, kind: read_kind(d)? },
+        42 => Instruction::LocalAddress { dst: reg(d)?, offset: index(d)? },
+        43 => Instruction::GlobalAddress { dst: reg(d)?, offset: index(d)? },
+        44 => Instruction::FunctionAddress { dst: reg(d)?, function: index(d)? },
+        45 => Instruction::ExternalAddress { dst: reg(d)?, external: index(d)? },
+        46 => Instruction::CopyBytes { dst: reg(d)?, src: reg(d)?, size: index(d)? },
+        47 => Instruction::ZeroBytes { dst: reg(d)?, size: index(d)? },
+        48 => Instruction::Jump { target: index(d)? },
+        49 => Instruction::JumpIfZero { condition: reg(d)?, target: index(d)? },
+        50 => Instruction::JumpIfNonZero { condition: reg(d)?, target: index(d)? },
+        51 => Instruction::Switch { value: reg(d)?, table: index(d)? },
+        52 => Instruction::Call { dst: reg(d)?, function: index(d)?, arguments: reg(d)?, count: d.u8()? },
+        53 => Instruction::CallIndirect { dst: reg(d)?, callee: reg(d)?, arguments: reg(d)?, count: d.u8()?, signature: index(d)? },
+        54 => Instruction::CallExternal { dst: reg(d)?, external: index(d)?, arguments: reg(d)?, count: d.u8()?, signature: index(d)? },
+        55 => Instruction::Return { src: reg(d)? },
+        56 => Instruction::ReturnVoid,
+        57 => Instruction::Statement { line: index(d)? },
+        58 => Instruction::Probe { site: index(d)?, arguments: reg(d)?, count: d.u8()? },
+        59 => Instruction::SignedImm {
+            op: read_signed_op(d)?,
+            dst: reg(d)?,
+            a: reg(d)?,
+            temp: reg(d)?,
+            value: d.int_as()?,
+            bits: d.u8()?,
+        },
+        60 => Instruction::SignedMove { op: read_signed_op(d)?, dst: reg(d)?, a: reg(d)?, b: reg(d)?, temp: reg(d)?, bits: d.u8()? },
+        61 => Instruction::CompareImm { comparison: read_comparison(d)?, dst: reg(d)?, a: reg(d)?, temp: reg(d)?, value: d.int_as()? },
+        62 => Instruction::CompareJump {
+            comparison: read_comparison(d)?,
+            dst: reg(d)?,
+            a: reg(d)?,
+            b: reg(d)?,
+            target: index(d)?,
+            when: d.bool()?,
+        },
+        63 => Instruction::CompareImmJump {
+            comparison: read_comparison(d)?,
+            dst: reg(d)?,
+            a: reg(d)?,
+            temp: reg(d)?,
+            value: d.int_as()?,
+            target: index(d)?,
+            when: d.bool()?,
+        },
+        64 => Instruction::MoveJump { dst: reg(d)?, src: reg(d)?, target: index(d)? },
+        65 => Instruction::Shl { dst: reg(d)?, a: reg(d)?, b: reg(d)?, bits: d.u8()? },
+        66 => Instruction::ShrU { dst: reg(d)?, a: reg(d)?, b: reg(d)?, bits: d.u8()? },
+        67 => Instruction::ShrS { dst: reg(d)?, a: reg(d)?, b: reg(d)?, bits: d.u8()? },
+        68 => Instruction::VlaMark { dst: reg(d)? },
+        69 => Instruction::VlaAllocate { dst: reg(d)?, length: reg(d)?, size: index(d)?, zero: d.bool()? },
+        70 => Instruction::VlaRelease { mark: reg(d)? },
+        71 => Instruction::Asm { index: index(d)?, operands: reg(d)?, count: d.u8()? },
+        other => return Err(IrError::Unknown { what: "opcode", value: other }),
+    })
+}
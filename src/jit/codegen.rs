@@ -2,12 +2,17 @@
 use std::sync::Arc;
 use std::collections::HashMap;
 use parking_lot::RwLock;
+use crate::arch::{ABIHandler, Architecture, CallingConvention};
+use crate::arch::frame::{FrameBuilder, FrameLayout, FramePointerPolicy, FrameStep, FunctionFrame, SlotId};
+use crate::arch::x86_64::X86_64ABIHandler;
+use crate::jit::registers::PhysicalReg;
 
 pub struct CodeGenerator {
     // Core components
     memory_manager: Arc<MemoryManager>,
     register_allocator: RegisterAllocator,
     instruction_encoder: InstructionEncoder,
+    calling_convention: CallingConvention,
     
     // State tracking
     functions: RwLock<HashMap<String, FunctionInfo>>,
@@ -23,6 +28,7 @@ impl CodeGenerator {
             memory_manager,
             register_allocator: RegisterAllocator::new(),
             instruction_encoder: InstructionEncoder::new(),
+            calling_convention: X86_64ABIHandler::new().calling_convention().clone(),
             functions: RwLock::new(HashMap::new()),
             code_buffer: CodeBuffer::new(),
             relocation_table: RelocationTable::new(),
@@ -39,8 +45,24 @@ impl CodeGenerator {
         self.register_allocator.reset();
         self.relocation_table.clear();
 
-        // Function prologue
-        self.emit_prologue()?;
+        // Registers `asm` statements need, so the prologue saves the
+        // callee-saved ones among them; and whether this is a leaf
+        let mut makes_calls = false;
+        for block in ir.basic_blocks() {
+            for inst in block.instructions() {
+                match inst {
+                    Instruction::InlineAsm(asm) => {
+                        self.register_allocator.reserve_asm(&asm.resolved).map_err(JITError::RegisterAllocation)?;
+                    }
+                    Instruction::Call(_) => makes_calls = true,
+                    _ => {}
+                }
+            }
+        }
+
+        // Function prologue, from the layout the unwinder and debug info see
+        let layout = self.layout_frame(makes_calls)?;
+        let step_ends = self.emit_prologue(&layout)?;
 
         // Generate code for each basic block
         for block in ir.basic_blocks() {
@@ -48,36 +70,49 @@ impl CodeGenerator {
         }
 
         // Function epilogue
-        self.emit_epilogue()?;
+        self.emit_epilogue(&layout)?;
 
-        // Allocate executable memory
+        // Allocate code memory; it's written at one address and runs at another
         let code_size = self.code_buffer.size();
-        let code_ptr = self.memory_manager.allocate_executable(code_size)?;
+        let code = self.memory_manager.allocate_code(code_size)?;
 
         // Copy generated code
         std::ptr::copy_nonoverlapping(
             self.code_buffer.data(),
-            code_ptr,
+            code.writable,
             code_size
         );
 
-        // Apply relocations
-        self.apply_relocations(code_ptr)?;
+        // Apply relocations, relative to where the code runs
+        self.apply_relocations(code.writable, code.executable)?;
 
         // Make memory executable
-        self.memory_manager.make_executable(code_ptr)?;
+        self.memory_manager.finalize_code(&code)?;
+        let code_ptr = code.executable as *mut u8;
 
         // Track function
         let info = FunctionInfo {
             address: code_ptr,
             size: code_size,
             name: name.to_string(),
+            frame: FunctionFrame {
+                address: code_ptr as u64,
+                size: code_size as u64,
+                layout,
+                step_ends,
+            },
         };
         self.functions.write().insert(name.to_string(), info);
 
         Ok(code_ptr)
     }
 
+    /// Frames of the functions generated so far, for `.debug_frame` and
+    /// the unwinder
+    pub fn frames(&self) -> Vec<FunctionFrame> {
+        self.functions.read().values().map(|info| info.frame.clone()).collect()
+    }
+
     unsafe fn generate_block(&mut self, block: &BasicBlock) -> Result<(), JITError> {
         // Align block
         self.code_buffer.align(16);
@@ -153,39 +188,36 @@ impl CodeGenerator {
         Ok(())
     }
 
-    unsafe fn emit_prologue(&mut self) -> Result<(), JITError> {
-        // Save callee-saved registers
+    /// Where this function's saved registers and spill slots go. Every JIT
+    /// frame keeps `rbp`, since the stack map walker follows the chain.
+    fn layout_frame(&self, makes_calls: bool) -> Result<FrameLayout, JITError> {
+        let mut builder = FrameBuilder::new(Architecture::X86_64, &self.calling_convention)
+            .frame_pointer(FramePointerPolicy::Always)
+            .makes_calls(makes_calls);
         for reg in self.register_allocator.callee_saved() {
-            self.instruction_encoder.encode_push(*reg, &mut self.code_buffer)?;
+            builder = builder.save(&reg.name());
         }
-
-        // Setup frame pointer
-        self.instruction_encoder.encode_push(Register::RBP, &mut self.code_buffer)?;
-        self.instruction_encoder.encode_mov(Register::RBP, Register::RSP, &mut self.code_buffer)?;
-
-        // Allocate stack space
-        let frame_size = self.calculate_frame_size();
-        if frame_size > 0 {
-            self.instruction_encoder.encode_sub(
-                Register::RSP,
-                Immediate(frame_size as i32),
-                &mut self.code_buffer
-            )?;
+        let spill_area = self.register_allocator.get_frame_size() as usize;
+        if spill_area > 0 {
+            builder = builder.spill(SPILL_AREA, spill_area, 16);
         }
-
-        Ok(())
+        builder.build().map_err(|e| JITError::Compilation(e.to_string()))
     }
 
-    unsafe fn emit_epilogue(&mut self) -> Result<(), JITError> {
-        // Restore stack pointer
-        self.instruction_encoder.encode_mov(Register::RSP, Register::RBP, &mut self.code_buffer)?;
-
-        // Restore frame pointer
-        self.instruction_encoder.encode_pop(Register::RBP, &mut self.code_buffer)?;
+    /// Returns the code offset after each step, for the CFI
+    unsafe fn emit_prologue(&mut self, layout: &FrameLayout) -> Result<Vec<usize>, JITError> {
+        let mut step_ends = Vec::with_capacity(layout.prologue.len());
+        for step in &layout.prologue {
+            self.emit_frame_step(step)?;
+            step_ends.push(self.code_buffer.position());
+        }
+        Ok(step_ends)
+    }
 
-        // Restore callee-saved registers in reverse order
-        for reg in self.register_allocator.callee_saved().iter().rev() {
-            self.instruction_encoder.encode_pop(*reg, &mut self.code_buffer)?;
+    unsafe fn emit_epilogue(&mut self, layout: &FrameLayout) -> Result<(), JITError> {
+        // Restore stack pointer and callee-saved registers
+        for step in &layout.epilogue {
+            self.emit_frame_step(step)?;
         }
 
         // Return
@@ -194,14 +226,52 @@ impl CodeGenerator {
         Ok(())
     }
 
-    unsafe fn apply_relocations(&self, code_ptr: *mut u8) -> Result<(), JITError> {
+    unsafe fn emit_frame_step(&mut self, step: &FrameStep) -> Result<(), JITError> {
+        let physical = |name: &str| {
+            PhysicalReg::from_name(name).ok_or_else(|| JITError::Compilation(format!("no register {}", name)))
+        };
+        match step {
+            FrameStep::Push { register, .. } => {
+                self.instruction_encoder.encode_push(physical(register)?, &mut self.code_buffer)?;
+            }
+            FrameStep::Pop { register, .. } => {
+                self.instruction_encoder.encode_pop(physical(register)?, &mut self.code_buffer)?;
+            }
+            FrameStep::SetFramePointer => {
+                self.instruction_encoder.encode_mov(Register::RBP, Register::RSP, &mut self.code_buffer)?;
+            }
+            FrameStep::Allocate(size) => {
+                self.instruction_encoder.encode_sub(Register::RSP, Immediate(*size as i32), &mut self.code_buffer)?;
+            }
+            FrameStep::Deallocate(size) => {
+                self.instruction_encoder.encode_add(Register::RSP, Immediate(*size as i32), &mut self.code_buffer)?;
+            }
+            FrameStep::RestoreStackPointer { below_frame_pointer } => {
+                self.instruction_encoder.encode_mov(Register::RSP, Register::RBP, &mut self.code_buffer)?;
+                if *below_frame_pointer > 0 {
+                    self.instruction_encoder.encode_sub(
+                        Register::RSP,
+                        Immediate(*below_frame_pointer as i32),
+                        &mut self.code_buffer
+                    )?;
+                }
+            }
+            // x86-64 layouts push one register at a time
+            FrameStep::PushPair { .. } | FrameStep::PopPair { .. } => {
+                return Err(JITError::Compilation(format!("{:?} on x86-64", step)));
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn apply_relocations(&self, code_ptr: *mut u8, runs_at: *const u8) -> Result<(), JITError> {
         for relocation in self.relocation_table.relocations() {
             match relocation.kind {
                 RelocationType::Direct32 => {
                     let target = self.relocation_table.get_label(&relocation.target)
                         .ok_or(JITError::UnresolvedLabel(relocation.target.clone()))?;
 
-                    let offset = target - (code_ptr as usize + relocation.offset + 4);
+                    let offset = target - (runs_at as usize + relocation.offset + 4);
                     *(code_ptr.add(relocation.offset) as *mut i32) = offset as i32;
                 },
                 RelocationType::Absolute64 => {
@@ -265,11 +335,15 @@ enum RelocationType {
     Absolute64, // Absolute address
 }
 
+/// The frame slot holding all of the register allocator's spill slots
+const SPILL_AREA: SlotId = 0;
+
 #[derive(Debug)]
 struct FunctionInfo {
     address: *mut u8,
     size: usize,
     name: String,
+    frame: FunctionFrame,
 }
 
 // Example usage:
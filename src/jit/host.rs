@@ -0,0 +1,448 @@
+// src/jit/host.rs
+//! Host functions callable from JIT-compiled C
+//! An embedding application registers Rust functions by name; C code
+//! declares a matching prototype and calls them like any other function.
+//!
+//! - `HostFunction::Pointer` is an `extern "C"` function with exactly the C
+//!   signature. The declaration is simply mapped to its address.
+//! - `HostFunction::Closure` is any Rust closure over `JITValue`s. The
+//!   declaration gets a generated body that spills the arguments into
+//!   64-bit slots, the same raw convention `RuntimeSupport::execute_function`
+//!   and the ABI handler use, and calls a single dispatcher. The dispatcher
+//!   unpacks the slots, runs the closure, and packs the result. The
+//!   trampoline also passes its frame, so a closure can enumerate the
+//!   managed pointers of the C frames below it (`stackmap::for_each_root`).
+//!
+//! `#[c_export]` (the `macros` feature) generates the `Pointer` form from an
+//! ordinary Rust function: each parameter and return type names its
+//! `JITType` through `HostType`, and an `extern "C"` wrapper calls the
+//! function. `c_exports![...]` collects the resulting `HostExport`s.
+//!
+//! A variadic host function must be a `Pointer` declared with
+//! `HostSignature::variadic`, and takes the variable arguments it reads as
+//! further fixed parameters: on x86-64 and AArch64 Linux an integer or
+//! pointer vararg is passed exactly like a named one. A closure that panics, or
+//! returns a value of the wrong type, aborts the process: there is no way
+//! to unwind through C frames.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::execution_engine::{LLVMAddGlobalMapping, LLVMExecutionEngineRef};
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::LLVMLinkage;
+#[cfg(feature = "llvm")]
+use super::stackmap::{keep_frame_pointer, ExitFrame};
+use super::{JITError, JITType, JITValue};
+#[cfg(feature = "llvm")]
+use crate::runtime::signals;
+
+/// Symbol of the dispatcher that closure trampolines call
+const DISPATCH_SYMBOL: &str = "__c_interpreter_host_dispatch";
+
+pub type HostClosure = dyn Fn(&[JITValue]) -> JITValue + Send + Sync;
+
+/// C-visible signature of a host function
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostSignature {
+    pub params: Vec<JITType>,
+    pub return_type: JITType,
+    /// `params` are the named ones of a `...` prototype
+    pub variadic: bool,
+}
+
+impl HostSignature {
+    pub fn new(params: Vec<JITType>, return_type: JITType) -> Self {
+        HostSignature { params, return_type, variadic: false }
+    }
+
+    /// For C's `open(const char *, int, ...)` and the like
+    pub fn variadic(params: Vec<JITType>, return_type: JITType) -> Self {
+        HostSignature { params, return_type, variadic: true }
+    }
+}
+
+pub enum HostFunction {
+    /// An `extern "C"` function whose signature matches the descriptor
+    Pointer(*const c_void),
+    Closure(Box<HostClosure>),
+}
+
+/// A Rust type that crosses the C boundary unchanged. Unsigned integers
+/// share the `JITType` of their width; every pointer is `Pointer(Void)`,
+/// since the pointee isn't checked against the C prototype.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be passed to or returned from C",
+    note = "host functions take and return integers, `f32`, `f64` and raw pointers"
+)]
+pub trait HostType {
+    fn jit_type() -> JITType;
+}
+
+macro_rules! host_type {
+    ($($ty:ty => $jit:expr),* $(,)?) => {
+        $(impl HostType for $ty {
+            fn jit_type() -> JITType {
+                $jit
+            }
+        })*
+    };
+}
+
+host_type! {
+    () => JITType::Void,
+    i8 => JITType::Int8,
+    u8 => JITType::Int8,
+    i16 => JITType::Int16,
+    u16 => JITType::Int16,
+    i32 => JITType::Int32,
+    u32 => JITType::Int32,
+    i64 => JITType::Int64,
+    u64 => JITType::Int64,
+    f32 => JITType::Float,
+    f64 => JITType::Double,
+}
+
+#[cfg(target_pointer_width = "64")]
+host_type! {
+    isize => JITType::Int64,
+    usize => JITType::Int64,
+}
+
+impl<T: ?Sized> HostType for *const T {
+    fn jit_type() -> JITType {
+        JITType::Pointer(Box::new(JITType::Void))
+    }
+}
+
+impl<T: ?Sized> HostType for *mut T {
+    fn jit_type() -> JITType {
+        JITType::Pointer(Box::new(JITType::Void))
+    }
+}
+
+/// A host function ready to register, as generated by `#[c_export]`
+pub struct HostExport {
+    pub name: &'static str,
+    pub signature: HostSignature,
+    pub function: HostFunction,
+}
+
+impl HostExport {
+    /// Run an exported function's body from its `extern "C"` wrapper. A
+    /// panic can't unwind into C, so it is reported and the process aborts,
+    /// the same as for a closure.
+    #[doc(hidden)]
+    pub fn guard<R>(name: &str, body: impl FnOnce() -> R) -> R {
+        match catch_unwind(AssertUnwindSafe(body)) {
+            Ok(result) => result,
+            Err(_) => {
+                eprintln!("fatal: host function '{}' panicked", name);
+                std::process::abort()
+            }
+        }
+    }
+}
+
+pub struct HostEntry {
+    name: String,
+    signature: HostSignature,
+    function: HostFunction,
+}
+
+impl HostEntry {
+    /// Run a closure on raw argument slots and return the raw result
+    #[cfg(feature = "llvm")]
+    fn call(&self, raw: &[u64]) -> Result<u64, String> {
+        let HostFunction::Closure(closure) = &self.function else {
+            return Err("pointer host functions are called directly".to_string());
+        };
+        let args: Vec<JITValue> = self
+            .signature
+            .params
+            .iter()
+            .zip(raw)
+            .map(|(ty, &slot)| JITValue::from_raw(slot, ty))
+            .collect();
+
+        let result = closure(&args);
+        if !self.signature.return_type.accepts(&result) {
+            return Err(format!("returned {:?}, declared {:?}", result, self.signature.return_type));
+        }
+        Ok(result.to_raw())
+    }
+}
+
+/// Host functions by C name. Entries are never removed, so the addresses
+/// baked into generated trampolines stay valid.
+#[derive(Default)]
+pub struct HostFunctions {
+    entries: HashMap<String, Arc<HostEntry>>,
+
+    // Statistics
+    bound: usize,
+}
+
+impl HostFunctions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: &str, signature: HostSignature, function: HostFunction) -> Result<(), JITError> {
+        if self.entries.contains_key(name) {
+            return Err(JITError::HostFunction(format!("'{}' is already registered", name)));
+        }
+        if signature.params.contains(&JITType::Void) {
+            return Err(JITError::HostFunction(format!("'{}' has a void parameter", name)));
+        }
+        if signature.variadic && matches!(function, HostFunction::Closure(_)) {
+            return Err(JITError::HostFunction(format!("'{}' is variadic, which only pointer host functions can be", name)));
+        }
+        self.entries.insert(
+            name.to_string(),
+            Arc::new(HostEntry { name: name.to_string(), signature, function }),
+        );
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// Bind every registered function that `module` declares but doesn't
+    /// define. Must run before the engine compiles the module.
+    #[cfg(feature = "llvm")]
+    pub unsafe fn bind(
+        &mut self,
+        context: LLVMContextRef,
+        module: LLVMModuleRef,
+        engine: LLVMExecutionEngineRef,
+    ) -> Result<usize, JITError> {
+        let mut bound = 0;
+        for (name, entry) in &self.entries {
+            let Ok(c_name) = std::ffi::CString::new(name.as_str()) else { continue };
+            let declaration = LLVMGetNamedFunction(module, c_name.as_ptr());
+            if declaration.is_null() || LLVMIsDeclaration(declaration) == 0 {
+                continue;
+            }
+            check_prototype(context, declaration, entry)?;
+
+            match &entry.function {
+                HostFunction::Pointer(address) => {
+                    LLVMAddGlobalMapping(engine, declaration, *address as *mut c_void)
+                }
+                HostFunction::Closure(_) => emit_trampoline(context, module, engine, declaration, entry),
+            }
+            bound += 1;
+        }
+        self.bound += bound;
+        Ok(bound)
+    }
+
+    /// (registered, bound into modules so far)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.entries.len(), self.bound)
+    }
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn llvm_type(context: LLVMContextRef, ty: &JITType) -> LLVMTypeRef {
+    match ty {
+        JITType::Void => LLVMVoidTypeInContext(context),
+        JITType::Int8 => LLVMInt8TypeInContext(context),
+        JITType::Int16 => LLVMInt16TypeInContext(context),
+        JITType::Int32 => LLVMInt32TypeInContext(context),
+        JITType::Int64 => LLVMInt64TypeInContext(context),
+        JITType::Float => LLVMFloatTypeInContext(context),
+        JITType::Double => LLVMDoubleTypeInContext(context),
+        JITType::Pointer(_) => LLVMPointerTypeInContext(context, 0),
+    }
+}
+
+/// The C prototype must agree with the registered signature
+#[cfg(feature = "llvm")]
+unsafe fn check_prototype(context: LLVMContextRef, declaration: LLVMValueRef, entry: &HostEntry) -> Result<(), JITError> {
+    let function_type = LLVMGlobalGetValueType(declaration);
+    let count = LLVMCountParamTypes(function_type) as usize;
+    let mut params = vec![std::ptr::null_mut(); count];
+    LLVMGetParamTypes(function_type, params.as_mut_ptr());
+
+    let expected: Vec<LLVMTypeRef> = entry.signature.params.iter().map(|ty| llvm_type(context, ty)).collect();
+    let matches = (LLVMIsFunctionVarArg(function_type) != 0) == entry.signature.variadic
+        && params == expected
+        && LLVMGetReturnType(function_type) == llvm_type(context, &entry.signature.return_type);
+    if !matches {
+        return Err(JITError::HostFunction(format!(
+            "C declaration of '{}' doesn't match the registered signature {:?}",
+            entry.name, entry.signature
+        )));
+    }
+    Ok(())
+}
+
+/// Give the declaration a body that forwards to `host_dispatch`
+#[cfg(feature = "llvm")]
+unsafe fn emit_trampoline(
+    context: LLVMContextRef,
+    module: LLVMModuleRef,
+    engine: LLVMExecutionEngineRef,
+    declaration: LLVMValueRef,
+    entry: &Arc<HostEntry>,
+) {
+    let i32_ty = LLVMInt32TypeInContext(context);
+    let i64_ty = LLVMInt64TypeInContext(context);
+    let ptr_ty = LLVMPointerTypeInContext(context, 0);
+
+    let mut dispatch_params = [ptr_ty, ptr_ty, i32_ty, ptr_ty, ptr_ty];
+    let dispatch_ty = LLVMFunctionType(i64_ty, dispatch_params.as_mut_ptr(), 5, 0);
+    let dispatch_name = std::ffi::CString::new(DISPATCH_SYMBOL).unwrap();
+    let mut dispatch = LLVMGetNamedFunction(module, dispatch_name.as_ptr());
+    if dispatch.is_null() {
+        dispatch = LLVMAddFunction(module, dispatch_name.as_ptr(), dispatch_ty);
+        LLVMAddGlobalMapping(engine, dispatch, host_dispatch as *mut c_void);
+    }
+
+    // Every module gets its own trampoline; keep it out of the engine's
+    // symbols, which outlive the module
+    LLVMSetLinkage(declaration, LLVMLinkage::LLVMInternalLinkage);
+
+    let builder = LLVMCreateBuilderInContext(context);
+    let block = LLVMAppendBasicBlockInContext(context, declaration, c"entry".as_ptr());
+    LLVMPositionBuilderAtEnd(builder, block);
+
+    // Where the C stack stops, for a collector walking it from the closure
+    keep_frame_pointer(context, declaration);
+    let frame = call_intrinsic(module, builder, "llvm.frameaddress", &[ptr_ty], &mut [LLVMConstInt(i32_ty, 0, 0)]);
+    // The caller's stack pointer: above the saved frame pointer and return
+    // address on x86-64, where this function's stack began on AArch64
+    let stack = if cfg!(target_arch = "aarch64") {
+        call_intrinsic(module, builder, "llvm.sponentry", &[ptr_ty], &mut [])
+    } else {
+        let mut offset = [LLVMConstInt(i64_ty, 16, 0)];
+        LLVMBuildGEP2(builder, LLVMInt8TypeInContext(context), frame, offset.as_mut_ptr(), 1, c"".as_ptr())
+    };
+
+    let count = entry.signature.params.len();
+    let slots_ty = LLVMArrayType2(i64_ty, count.max(1) as u64);
+    let slots = LLVMBuildAlloca(builder, slots_ty, c"host.args".as_ptr());
+    for (index, ty) in entry.signature.params.iter().enumerate() {
+        let param = LLVMGetParam(declaration, index as u32);
+        let raw = match ty {
+            JITType::Int64 => param,
+            JITType::Float => {
+                let bits = LLVMBuildBitCast(builder, param, i32_ty, c"".as_ptr());
+                LLVMBuildZExt(builder, bits, i64_ty, c"".as_ptr())
+            }
+            JITType::Double => LLVMBuildBitCast(builder, param, i64_ty, c"".as_ptr()),
+            JITType::Pointer(_) => LLVMBuildPtrToInt(builder, param, i64_ty, c"".as_ptr()),
+            _ => LLVMBuildSExt(builder, param, i64_ty, c"".as_ptr()),
+        };
+        let mut indices = [LLVMConstInt(i32_ty, 0, 0), LLVMConstInt(i32_ty, index as u64, 0)];
+        let slot = LLVMBuildInBoundsGEP2(builder, slots_ty, slots, indices.as_mut_ptr(), 2, c"".as_ptr());
+        LLVMBuildStore(builder, raw, slot);
+    }
+
+    // The entry outlives the code: the registry never drops it
+    let context_ptr = LLVMConstIntToPtr(LLVMConstInt(i64_ty, Arc::as_ptr(entry) as u64, 0), ptr_ty);
+    let mut args = [context_ptr, slots, LLVMConstInt(i32_ty, count as u64, 0), frame, stack];
+    let raw = LLVMBuildCall2(builder, dispatch_ty, dispatch, args.as_mut_ptr(), 5, c"host.ret".as_ptr());
+
+    let return_type = llvm_type(context, &entry.signature.return_type);
+    match entry.signature.return_type {
+        JITType::Void => LLVMBuildRetVoid(builder),
+        JITType::Int64 => LLVMBuildRet(builder, raw),
+        JITType::Float => {
+            let bits = LLVMBuildTrunc(builder, raw, i32_ty, c"".as_ptr());
+            LLVMBuildRet(builder, LLVMBuildBitCast(builder, bits, return_type, c"".as_ptr()))
+        }
+        JITType::Double => LLVMBuildRet(builder, LLVMBuildBitCast(builder, raw, return_type, c"".as_ptr())),
+        JITType::Pointer(_) => LLVMBuildRet(builder, LLVMBuildIntToPtr(builder, raw, return_type, c"".as_ptr())),
+        _ => LLVMBuildRet(builder, LLVMBuildTrunc(builder, raw, return_type, c"".as_ptr())),
+    };
+
+    // Nothing outside the module should call the trampoline
+    LLVMSetLinkage(declaration, LLVMLinkage::LLVMInternalLinkage);
+    LLVMDisposeBuilder(builder);
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn call_intrinsic(
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    name: &str,
+    overloads: &[LLVMTypeRef],
+    args: &mut [LLVMValueRef],
+) -> LLVMValueRef {
+    let id = LLVMLookupIntrinsicID(name.as_ptr() as *const _, name.len());
+    let mut overloads = overloads.to_vec();
+    let function = LLVMGetIntrinsicDeclaration(module, id, overloads.as_mut_ptr(), overloads.len());
+    let function_ty = LLVMIntrinsicGetType(LLVMGetModuleContext(module), id, overloads.as_mut_ptr(), overloads.len());
+    LLVMBuildCall2(builder, function_ty, function, args.as_mut_ptr(), args.len() as u32, c"".as_ptr())
+}
+
+/// Called from every closure trampoline
+#[cfg(feature = "llvm")]
+extern "C" fn host_dispatch(entry: *const HostEntry, args: *const u64, count: u32, frame: *const u8, stack: *const u8) -> u64 {
+    let entry = unsafe { &*entry };
+    let raw = unsafe { std::slice::from_raw_parts(args, count as usize) };
+    let _exit = ExitFrame { frame_pointer: frame as usize, stack_pointer: stack as usize }.enter();
+    // A compiled signal handler mustn't run in the middle of Rust code
+    let _signals = signals::critical();
+
+    match catch_unwind(AssertUnwindSafe(|| entry.call(raw))) {
+        Ok(Ok(result)) => result,
+        Ok(Err(message)) => {
+            eprintln!("fatal: host function '{}' {}", entry.name, message);
+            std::process::abort()
+        }
+        Err(_) => {
+            eprintln!("fatal: host function '{}' panicked", entry.name);
+            std::process::abort()
+        }
+    }
+}
+
+// Example usage:
+/*
+extern "C" fn host_log2(x: f64) -> f64 {
+    x.log2()
+}
+
+fn main() -> Result<(), JITError> {
+    unsafe {
+        let jit = JITCompiler::new()?;
+
+        jit.register_host_function(
+            "host_log2",
+            HostSignature::new(vec![JITType::Double], JITType::Double),
+            HostFunction::Pointer(host_log2 as *const c_void),
+        )?;
+
+        let counter = Arc::new(AtomicI64::new(0));
+        let seen = counter.clone();
+        jit.register_host_closure(
+            "host_count",
+            HostSignature::new(vec![JITType::Int32], JITType::Int64),
+            move |args| {
+                let JITValue::Int32(n) = args[0] else { unreachable!() };
+                JITValue::Int64(seen.fetch_add(n as i64, Ordering::Relaxed) + n as i64)
+            },
+        )?;
+
+        let source = r#"
+            double host_log2(double);
+            long host_count(int);
+            long run(void) { host_count(3); return host_count((int)host_log2(16.0)); }
+        "#;
+        let total: i64 = jit.compile_and_run(source, "run", &[])?;
+        println!("{}", total); // 7
+        Ok(())
+    }
+}
+*/
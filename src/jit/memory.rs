@@ -1,8 +1,18 @@
 // src/jit/memory.rs
+//! Memory for generated code and its data
+//! Generated code is written through one address and run through another
+//! that is never writable at the same time (W^X), in one of three ways,
+//! see `WxPolicy`. `allocate_code` hands out a `CodeBuffer` with both
+//! addresses; `finalize_code` makes what was written runnable, flushing the
+//! instruction cache where the hardware doesn't keep it coherent (aarch64),
+//! and `reopen_code` makes it writable again for patching.
+
 use std::collections::HashMap;
 use std::sync::Arc;
-use nix::sys::mman::{mmap, mprotect, munmap, ProtFlags, MapFlags};
+use nix::sys::mman::{mprotect, munmap, ProtFlags};
 use parking_lot::RwLock;
+use crate::memory::hugepages::{self, HugePageMode, HugePageRegion, PageBacking, HUGE_PAGE_SIZE, DEFAULT_HUGE_PAGE_THRESHOLD};
+use crate::memory::numa::{NumaAllocator, NumaPolicy, PlacementKind};
 
 pub struct MemoryManager {
     // System information
@@ -12,11 +22,27 @@ pub struct MemoryManager {
     allocations: RwLock<HashMap<*mut u8, AllocationInfo>>,
     executable_regions: RwLock<HashMap<*mut u8, ExecutableRegion>>,
     
-    // Memory pools
-    code_pool: CodePool,
-    data_pool: DataPool,
+    // Memory pools, whose slabs are mapped and placed like direct allocations
+    code_pool: Pool,
+    data_pool: Pool,
+    
+    // NUMA placement (None leaves placement to the kernel), and the node
+    // each placed region is accounted to until it is unmapped
+    numa: Option<Arc<NumaAllocator>>,
+    placements: RwLock<HashMap<*mut u8, (usize, usize, PlacementKind)>>,
+    
+    // Huge-page backing for large direct allocations and pool slabs
+    huge_pages: HugePageMode,
+
+    // How code buffers keep writable and executable apart
+    wx_policy: WxPolicy,
 }
 
+// The pointers are mappings it owns, and the maps that hold them are
+// behind locks: C threads allocate their stacks through a shared one
+unsafe impl Send for MemoryManager {}
+unsafe impl Sync for MemoryManager {}
+
 impl MemoryManager {
     pub unsafe fn new() -> Result<Self, JITError> {
         let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
@@ -25,94 +51,191 @@ impl MemoryManager {
             page_size,
             allocations: RwLock::new(HashMap::new()),
             executable_regions: RwLock::new(HashMap::new()),
-            code_pool: CodePool::new(page_size)?,
-            data_pool: DataPool::new(page_size)?,
+            code_pool: Pool::new(page_size),
+            data_pool: Pool::new(page_size),
+            numa: None,
+            placements: RwLock::new(HashMap::new()),
+            huge_pages: HugePageMode::Disabled,
+            wx_policy: WxPolicy::detect(page_size),
         })
     }
 
-    /// Allocate executable memory for JIT code
+    /// A manager placing what it maps as `placement` says
+    pub unsafe fn with_placement(placement: &MemoryPlacement) -> Result<Self, JITError> {
+        let mut manager = Self::new()?;
+        if let Some(policy) = &placement.numa {
+            let numa = NumaAllocator::new(policy.clone())
+                .map_err(|e| JITError::MemoryError(format!("NUMA policy {:?}: {:?}", policy, e)))?;
+            manager.set_numa_allocator(Arc::new(numa));
+        }
+        manager.set_huge_page_mode(placement.huge_pages);
+        Ok(manager)
+    }
+
+    /// Back large direct allocations and pool slabs with huge pages where
+    /// the host allows it
+    pub fn set_huge_page_mode(&mut self, mode: HugePageMode) {
+        self.huge_pages = mode;
+    }
+
+    /// Place subsequent direct allocations according to a NUMA policy
+    pub fn set_numa_allocator(&mut self, numa: Arc<NumaAllocator>) {
+        self.numa = Some(numa);
+    }
+
+    /// Keep code buffers W^X with `policy` instead of the detected one
+    pub fn set_wx_policy(&mut self, policy: WxPolicy) {
+        self.wx_policy = policy;
+    }
+
+    pub fn wx_policy(&self) -> WxPolicy {
+        self.wx_policy
+    }
+
+    /// Allocate a code buffer, writable through `CodeBuffer::writable`
+    /// until `finalize_code`
+    pub unsafe fn allocate_code(&self, size: usize) -> Result<CodeBuffer, JITError> {
+        let size = self.align_to_page_size(size.max(1));
+        let buffer = match self.wx_policy {
+            WxPolicy::DualMapping => map_dual(size)?,
+            WxPolicy::Flip => {
+                let region = self.map_region(size)?;
+                CodeBuffer { writable: region.ptr, executable: region.ptr, size: region.size }
+            }
+            WxPolicy::MapJit => {
+                let ptr = map_jit(size)?;
+                jit_write_protect(false);
+                CodeBuffer { writable: ptr, executable: ptr, size }
+            }
+        };
+        self.place(buffer.writable, buffer.size, PlacementKind::Code)?;
+        Ok(buffer)
+    }
+
+    /// Make what was written to `buffer` runnable through its executable
+    /// address
+    pub unsafe fn finalize_code(&self, buffer: &CodeBuffer) -> Result<(), JITError> {
+        match self.wx_policy {
+            WxPolicy::DualMapping => {}
+            WxPolicy::Flip => {
+                mprotect(buffer.writable as *mut _, buffer.size, ProtFlags::PROT_READ | ProtFlags::PROT_EXEC)
+                    .map_err(|e| JITError::MemoryError(format!("mprotect failed: {}", e)))?;
+            }
+            WxPolicy::MapJit => jit_write_protect(true),
+        }
+        flush_instruction_cache(buffer.executable, buffer.size);
+        Ok(())
+    }
+
+    /// Make `buffer` writable again, to patch it. With `WxPolicy::Flip` it
+    /// can't run until the next `finalize_code`, and with `MapJit` this
+    /// thread can't run any JIT code until then.
+    pub unsafe fn reopen_code(&self, buffer: &CodeBuffer) -> Result<(), JITError> {
+        match self.wx_policy {
+            WxPolicy::DualMapping => {}
+            WxPolicy::Flip => {
+                mprotect(buffer.writable as *mut _, buffer.size, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)
+                    .map_err(|e| JITError::MemoryError(format!("mprotect failed: {}", e)))?;
+            }
+            WxPolicy::MapJit => jit_write_protect(false),
+        }
+        Ok(())
+    }
+
+    /// Unmap a code buffer; nothing may be running it
+    pub unsafe fn free_code(&self, buffer: CodeBuffer) -> Result<(), JITError> {
+        self.unplace(buffer.writable);
+        munmap(buffer.writable as *mut _, buffer.size)
+            .map_err(|e| JITError::MemoryError(format!("munmap failed: {}", e)))?;
+        if buffer.is_dual() {
+            munmap(buffer.executable as *mut _, buffer.size)
+                .map_err(|e| JITError::MemoryError(format!("munmap failed: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Allocate executable memory for JIT code at a single address, made
+    /// executable with `make_executable`; `allocate_code` is for hosts that
+    /// don't allow flipping
     pub unsafe fn allocate_executable(&self, size: usize) -> Result<*mut u8, JITError> {
-        // Align to page size
-        let aligned_size = self.align_to_page_size(size);
+        // Align to page size (or huge page size for large regions)
+        let aligned_size = self.align_allocation(size);
         
         // First try code pool
-        if let Ok(ptr) = self.code_pool.allocate(aligned_size) {
+        if let Ok(ptr) = self.code_pool.allocate(aligned_size, |size| self.map_slab(size, PlacementKind::Code)) {
             return Ok(ptr);
         }
         
         // Fall back to direct allocation
-        let ptr = self.allocate_raw_executable(aligned_size)?;
-        
-        // Track allocation
-        let info = AllocationInfo {
-            base: ptr,
-            size: aligned_size,
-            executable: true,
-            permissions: Permissions::READ | Permissions::EXECUTE,
-        };
-        self.allocations.write().insert(ptr, info);
-        
-        Ok(ptr)
+        self.map_section(aligned_size, true).map(|(ptr, _)| ptr)
     }
 
     /// Allocate memory for data
     pub unsafe fn allocate_data(&self, size: usize) -> Result<*mut u8, JITError> {
-        // Align to page size
-        let aligned_size = self.align_to_page_size(size);
+        // Align to page size (or huge page size for large regions)
+        let aligned_size = self.align_allocation(size);
         
         // Try data pool first
-        if let Ok(ptr) = self.data_pool.allocate(aligned_size) {
+        if let Ok(ptr) = self.data_pool.allocate(aligned_size, |size| self.map_slab(size, PlacementKind::Heap)) {
             return Ok(ptr);
         }
         
         // Fall back to direct allocation
-        let ptr = self.allocate_raw_data(aligned_size)?;
-        
+        self.map_section(aligned_size, false).map(|(ptr, _)| ptr)
+    }
+
+    /// Memory mapped on its own rather than carved from a pool, so `free`
+    /// unmaps it: what MCJIT loads a module's sections into. Code starts
+    /// writable until `make_executable`. Returns the address and the bytes
+    /// mapped.
+    pub unsafe fn map_section(&self, size: usize, executable: bool) -> Result<(*mut u8, usize), JITError> {
+        let aligned_size = self.align_allocation(size);
+        let region = if executable {
+            self.allocate_raw_executable(aligned_size)?
+        } else {
+            self.allocate_raw_data(aligned_size)?
+        };
+
         // Track allocation
         let info = AllocationInfo {
-            base: ptr,
-            size: aligned_size,
-            executable: false,
-            permissions: Permissions::READ | Permissions::WRITE,
+            base: region.ptr,
+            size: region.size,
+            backing: region.backing,
+            executable,
+            permissions: if executable {
+                Permissions::READ | Permissions::EXECUTE
+            } else {
+                Permissions::READ | Permissions::WRITE
+            },
         };
-        self.allocations.write().insert(ptr, info);
-        
-        Ok(ptr)
+        self.allocations.write().insert(region.ptr, info);
+
+        Ok((region.ptr, region.size))
     }
 
-    unsafe fn allocate_raw_executable(&self, size: usize) -> Result<*mut u8, JITError> {
+    unsafe fn allocate_raw_executable(&self, size: usize) -> Result<HugePageRegion, JITError> {
         // First allocate RW memory
-        let ptr = mmap(
-            None,
-            size,
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-            MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
-            -1,
-            0
-        ).map_err(|e| JITError::MemoryError(format!("mmap failed: {}", e)))?;
+        let region = self.map_region(size)?;
+
+        // Bind to the configured node before the pages are first touched
+        self.place(region.ptr, region.size, PlacementKind::Code)?;
 
         // Track executable region
-        let region = ExecutableRegion {
-            base: ptr as *mut u8,
-            size,
+        let executable = ExecutableRegion {
+            base: region.ptr,
+            size: region.size,
             writable: true,
         };
-        self.executable_regions.write().insert(ptr as *mut u8, region);
+        self.executable_regions.write().insert(region.ptr, executable);
 
-        Ok(ptr as *mut u8)
+        Ok(region)
     }
 
-    unsafe fn allocate_raw_data(&self, size: usize) -> Result<*mut u8, JITError> {
-        let ptr = mmap(
-            None,
-            size,
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-            MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
-            -1,
-            0
-        ).map_err(|e| JITError::MemoryError(format!("mmap failed: {}", e)))?;
+    unsafe fn allocate_raw_data(&self, size: usize) -> Result<HugePageRegion, JITError> {
+        let region = self.map_region(size)?;
+        self.place(region.ptr, region.size, PlacementKind::Heap)?;
 
-        Ok(ptr as *mut u8)
+        Ok(region)
     }
 
     /// Make memory executable
@@ -165,8 +288,9 @@ impl MemoryManager {
         let mut allocations = self.allocations.write();
         
         if let Some(info) = allocations.remove(&ptr) {
-            munmap(ptr as *mut _, info.size)
+            munmap(ptr as *mut _, info.mapped_length())
                 .map_err(|e| JITError::MemoryError(format!("munmap failed: {}", e)))?;
+            self.unplace(ptr);
                 
             if info.executable {
                 self.executable_regions.write().remove(&ptr);
@@ -176,11 +300,209 @@ impl MemoryManager {
         Ok(())
     }
 
+    /// A new pool slab, bound to its node before the pool hands out any of
+    /// it. With huge pages on, slabs are at least a huge page so they can
+    /// be backed by one.
+    unsafe fn map_slab(&self, size: usize, kind: PlacementKind) -> Result<HugePageRegion, JITError> {
+        let size = if self.huge_pages != HugePageMode::Disabled { size.max(HUGE_PAGE_SIZE) } else { size };
+        let region = self.map_region(size)?;
+        if let Err(e) = self.place(region.ptr, region.size, kind) {
+            let _ = munmap(region.ptr as *mut _, region.size);
+            return Err(e);
+        }
+        Ok(region)
+    }
+
+    /// Apply the NUMA policy to a fresh region, remembering its node
+    unsafe fn place(&self, ptr: *mut u8, size: usize, kind: PlacementKind) -> Result<(), JITError> {
+        if let Some(numa) = &self.numa {
+            let node = numa.place(ptr, size, kind)
+                .map_err(|e| JITError::MemoryError(format!("NUMA placement failed: {:?}", e)))?;
+            self.placements.write().insert(ptr, (node, size, kind));
+        }
+        Ok(())
+    }
+
+    /// Take an unmapped region out of the per-node usage
+    fn unplace(&self, ptr: *mut u8) {
+        if let (Some(numa), Some((node, size, kind))) = (&self.numa, self.placements.write().remove(&ptr)) {
+            numa.release(node, size, kind);
+        }
+    }
+
+    /// Map a fresh RW region, huge-page backed when enabled and large
+    /// enough. The region's size is what was mapped, which is what it must
+    /// be unmapped and protected with.
+    unsafe fn map_region(&self, size: usize) -> Result<HugePageRegion, JITError> {
+        hugepages::map_anonymous(size, self.huge_pages, DEFAULT_HUGE_PAGE_THRESHOLD)
+            .map_err(|e| JITError::MemoryError(format!("mmap failed: {:?}", e)))
+    }
+
+    fn align_allocation(&self, size: usize) -> usize {
+        if self.huge_pages != HugePageMode::Disabled && size >= DEFAULT_HUGE_PAGE_THRESHOLD {
+            (size + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1)
+        } else {
+            self.align_to_page_size(size)
+        }
+    }
+
     fn align_to_page_size(&self, size: usize) -> usize {
         (size + self.page_size - 1) & !(self.page_size - 1)
     }
 }
 
+/// Where a `MemoryManager` puts what it maps
+#[derive(Debug, Clone, Default)]
+pub struct MemoryPlacement {
+    /// NUMA policy for code and data; `None` leaves it to the kernel
+    pub numa: Option<NumaPolicy>,
+    /// Huge pages for large direct allocations (the code region, big heaps)
+    pub huge_pages: HugePageMode,
+}
+
+/// How a code buffer is kept from being writable and executable at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WxPolicy {
+    /// The same pages mapped twice from a memfd, read/write at one address
+    /// and read/execute at another. Works where the kernel refuses to make
+    /// anonymous memory executable after it was written (PaX MPROTECT).
+    DualMapping,
+    /// One mapping, read/write while the code is written and then switched
+    /// to read/execute with mprotect
+    Flip,
+    /// macOS: one MAP_JIT mapping whose write protection is toggled per
+    /// thread with pthread_jit_write_protect_np, as the hardened runtime
+    /// requires
+    MapJit,
+}
+
+impl WxPolicy {
+    /// MAP_JIT on macOS; elsewhere dual mapping if the kernel allows it,
+    /// else flipping
+    pub fn detect(page_size: usize) -> WxPolicy {
+        if cfg!(target_os = "macos") {
+            return WxPolicy::MapJit;
+        }
+        // SAFETY: a fresh mapping, unmapped again
+        match unsafe { map_dual(page_size) } {
+            Ok(buffer) => unsafe {
+                let _ = munmap(buffer.writable as *mut _, buffer.size);
+                let _ = munmap(buffer.executable as *mut _, buffer.size);
+                WxPolicy::DualMapping
+            },
+            Err(e) => {
+                log::debug!("no dual-mapped JIT code ({:?}), flipping protections instead", e);
+                WxPolicy::Flip
+            }
+        }
+    }
+}
+
+/// Generated code, written through `writable` and run through `executable`.
+/// The two are the same address unless the policy is dual mapping.
+#[derive(Debug)]
+pub struct CodeBuffer {
+    pub writable: *mut u8,
+    pub executable: *const u8,
+    /// Mapped size, a multiple of the page size
+    pub size: usize,
+}
+
+impl CodeBuffer {
+    pub fn is_dual(&self) -> bool {
+        self.writable as *const u8 != self.executable
+    }
+}
+
+/// Map `size` bytes of a memfd twice, read/write and read/execute
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn map_dual(size: usize) -> Result<CodeBuffer, JITError> {
+    let error = |what: &str| JITError::MemoryError(format!("{} failed: {}", what, std::io::Error::last_os_error()));
+    let fd = libc::memfd_create(c"jit-code".as_ptr(), libc::MFD_CLOEXEC);
+    if fd < 0 {
+        return Err(error("memfd_create"));
+    }
+    if libc::ftruncate(fd, size as libc::off_t) != 0 {
+        let e = error("ftruncate");
+        libc::close(fd);
+        return Err(e);
+    }
+    let map = |protection| libc::mmap(std::ptr::null_mut(), size, protection, libc::MAP_SHARED, fd, 0);
+    let writable = map(libc::PROT_READ | libc::PROT_WRITE);
+    if writable == libc::MAP_FAILED {
+        let e = error("mmap");
+        libc::close(fd);
+        return Err(e);
+    }
+    let executable = map(libc::PROT_READ | libc::PROT_EXEC);
+    let failed = (executable == libc::MAP_FAILED).then(|| error("mmap"));
+    // The mappings keep the memory alive
+    libc::close(fd);
+    if let Some(e) = failed {
+        libc::munmap(writable, size);
+        return Err(e);
+    }
+    Ok(CodeBuffer { writable: writable as *mut u8, executable: executable as *const u8, size })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn map_dual(_size: usize) -> Result<CodeBuffer, JITError> {
+    Err(JITError::MemoryError("dual mapping needs memfd_create".to_string()))
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn map_jit(size: usize) -> Result<*mut u8, JITError> {
+    let ptr = libc::mmap(
+        std::ptr::null_mut(),
+        size,
+        libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+        libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_JIT,
+        -1,
+        0,
+    );
+    if ptr == libc::MAP_FAILED {
+        return Err(JITError::MemoryError(format!("mmap(MAP_JIT) failed: {}", std::io::Error::last_os_error())));
+    }
+    Ok(ptr as *mut u8)
+}
+
+#[cfg(not(target_os = "macos"))]
+unsafe fn map_jit(_size: usize) -> Result<*mut u8, JITError> {
+    Err(JITError::MemoryError("MAP_JIT is macOS only".to_string()))
+}
+
+/// Make MAP_JIT memory executable (`true`) or writable (`false`) for the
+/// calling thread
+#[cfg(target_os = "macos")]
+unsafe fn jit_write_protect(enabled: bool) {
+    libc::pthread_jit_write_protect_np(enabled as libc::c_int);
+}
+
+#[cfg(not(target_os = "macos"))]
+unsafe fn jit_write_protect(_enabled: bool) {}
+
+/// Make instructions written at `start` visible to instruction fetch. x86
+/// keeps the caches coherent; aarch64 needs the lines cleaned and
+/// invalidated.
+pub unsafe fn flush_instruction_cache(start: *const u8, size: usize) {
+    #[cfg(all(target_arch = "aarch64", target_os = "macos"))]
+    {
+        extern "C" {
+            fn sys_icache_invalidate(start: *mut libc::c_void, len: libc::size_t);
+        }
+        sys_icache_invalidate(start as *mut libc::c_void, size);
+    }
+    #[cfg(all(target_arch = "aarch64", not(target_os = "macos")))]
+    {
+        extern "C" {
+            fn __clear_cache(start: *mut libc::c_char, end: *mut libc::c_char);
+        }
+        __clear_cache(start as *mut libc::c_char, start.add(size) as *mut libc::c_char);
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = (start, size);
+}
+
 bitflags! {
     pub struct Permissions: u32 {
         const READ = 0b001;
@@ -191,25 +513,32 @@ bitflags! {
 
 struct AllocationInfo {
     base: *mut u8,
+    /// Mapped length, rounded up to the pages backing it
     size: usize,
+    backing: PageBacking,
     executable: bool,
     permissions: Permissions,
 }
 
+impl AllocationInfo {
+    /// What munmap takes: hugetlb mappings only unmap in whole huge pages
+    fn mapped_length(&self) -> usize {
+        match self.backing {
+            PageBacking::ExplicitHugePages => (self.size + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1),
+            PageBacking::BasePages | PageBacking::TransparentHugePages => self.size,
+        }
+    }
+}
+
 struct ExecutableRegion {
     base: *mut u8,
     size: usize,
     writable: bool,
 }
 
-/// Memory pool for code
-struct CodePool {
-    page_size: usize,
-    chunks: RwLock<Vec<PoolChunk>>,
-}
-
-/// Memory pool for data
-struct DataPool {
+/// Memory pool for code or data: bump allocation from slabs that are
+/// never returned
+struct Pool {
     page_size: usize,
     chunks: RwLock<Vec<PoolChunk>>,
 }
@@ -220,15 +549,21 @@ struct PoolChunk {
     used: usize,
 }
 
-impl CodePool {
-    unsafe fn new(page_size: usize) -> Result<Self, JITError> {
-        Ok(CodePool {
+impl Pool {
+    fn new(page_size: usize) -> Self {
+        Pool {
             page_size,
             chunks: RwLock::new(Vec::new()),
-        })
+        }
     }
 
-    unsafe fn allocate(&self, size: usize) -> Result<*mut u8, JITError> {
+    /// `size` bytes from a slab, mapping a new one of at least the size
+    /// asked with `map` when none has room
+    unsafe fn allocate(
+        &self,
+        size: usize,
+        map: impl FnOnce(usize) -> Result<HugePageRegion, JITError>,
+    ) -> Result<*mut u8, JITError> {
         let mut chunks = self.chunks.write();
         
         // Try to find space in existing chunks
@@ -241,28 +576,20 @@ impl CodePool {
         }
         
         // Allocate new chunk
-        let chunk_size = size.max(64 * self.page_size);
-        let ptr = mmap(
-            None,
-            chunk_size,
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-            MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
-            -1,
-            0
-        ).map_err(|e| JITError::MemoryError(format!("mmap failed: {}", e)))?;
+        let region = map(size.max(64 * self.page_size))?;
         
         let chunk = PoolChunk {
-            base: ptr as *mut u8,
-            size: chunk_size,
+            base: region.ptr,
+            size: region.size,
             used: size,
         };
         chunks.push(chunk);
         
-        Ok(ptr as *mut u8)
+        Ok(region.ptr)
     }
 
     unsafe fn free(&self, ptr: *mut u8) -> Result<bool, JITError> {
-        let mut chunks = self.chunks.write();
+        let chunks = self.chunks.read();
         
         // Check if ptr belongs to any chunk
         for chunk in chunks.iter() {
@@ -276,11 +603,6 @@ impl CodePool {
     }
 }
 
-impl DataPool {
-    // Similar implementation to CodePool but for data allocations
-    // ...
-}
-
 #[derive(Debug)]
 pub enum JITError {
     MemoryError(String),
@@ -293,25 +615,25 @@ pub enum JITError {
 unsafe fn example() -> Result<(), JITError> {
     let mm = MemoryManager::new()?;
     
-    // Allocate executable memory
-    let code = mm.allocate_executable(1024)?;
+    // Allocate a code buffer
+    let code = mm.allocate_code(1024)?;
     
-    // Write code
+    // Write code through the writable address
     std::ptr::copy_nonoverlapping(
         some_machine_code.as_ptr(),
-        code,
+        code.writable,
         some_machine_code.len()
     );
     
     // Make it executable
-    mm.make_executable(code)?;
+    mm.finalize_code(&code)?;
     
-    // Execute
-    let f: extern "C" fn() = std::mem::transmute(code);
+    // Execute through the executable address
+    let f: extern "C" fn() = std::mem::transmute(code.executable);
     f();
     
     // Clean up
-    mm.free(code)?;
+    mm.free_code(code)?;
     
     Ok(())
 }
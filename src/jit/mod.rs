@@ -1,30 +1,90 @@
 // src/jit/mod.rs
+#[cfg(feature = "llvm")]
+use std::ffi::CString;
+#[cfg(feature = "llvm")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "llvm")]
 use std::sync::Arc;
-use std::collections::HashMap;
-use parking_lot::RwLock;
+#[cfg(feature = "llvm")]
+use parking_lot::{Mutex, RwLock};
+#[cfg(feature = "llvm")]
 use llvm_sys::*;
+#[cfg(feature = "llvm")]
 use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
 use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
 use llvm_sys::execution_engine::*;
-
+#[cfg(feature = "llvm")]
+use crate::compiler::setjmp;
+#[cfg(feature = "llvm")]
+use crate::debug::jit_interface::{JitRegistration, SymfileBuilder};
+#[cfg(feature = "llvm")]
+use crate::memory::code_cache::{CodeCache, CodeCacheConfig, CodeCacheStats};
+
+// Without the `llvm` feature only the value types, the host function
+// registry, probes, stack maps and the tiered engine are built; the
+// interpreter and `Engine`'s embedders use them without compiling anything
+pub mod host;
+pub mod memory;
+pub mod probes;
+pub mod stackmap;
+pub mod tiered;
+pub mod tiers;
+#[cfg(feature = "llvm")]
+mod sections;
+
+#[cfg(feature = "llvm")]
+use host::{HostFunction, HostFunctions, HostSignature};
+#[cfg(feature = "llvm")]
+use memory::{MemoryManager, MemoryPlacement};
+#[cfg(feature = "llvm")]
+use sections::{Section, SectionAllocator};
+
+#[cfg(feature = "llvm")]
 pub struct JITCompiler {
-    // Core JIT components
+    // Core JIT components; each compiled function gets a module of its own
+    // next to the engine's first, empty one
     context: LLVMContextRef,
     module: LLVMModuleRef,
     execution_engine: LLVMExecutionEngineRef,
     
-    // Memory management
+    // Memory management: MCJIT loads modules into sections mapped here
     memory_manager: Arc<MemoryManager>,
+    sections: Arc<SectionAllocator>,
     
-    // Function cache
-    function_cache: RwLock<HashMap<String, JITFunction>>,
+    // Compiled functions, evicted least recently used first
+    code_cache: CodeCache<JITFunction>,
+
+    // Modules are compiled one at a time; the count makes symbols unique
+    compiling: Mutex<()>,
+    compiled: AtomicU64,
     
     // Runtime support
     runtime: RuntimeSupport,
+
+    // Symbol files registered with an attached debugger
+    debug_registrations: RwLock<Vec<JitRegistration>>,
+
+    // Rust functions callable from the compiled C
+    host_functions: RwLock<HostFunctions>,
 }
 
+#[cfg(feature = "llvm")]
 impl JITCompiler {
     pub unsafe fn new() -> Result<Self, JITError> {
+        Self::with_code_cache(CodeCacheConfig::default())
+    }
+
+    /// A compiler keeping compiled functions within `code_cache`'s limits;
+    /// an evicted function is compiled again when next called
+    pub unsafe fn with_code_cache(code_cache: CodeCacheConfig) -> Result<Self, JITError> {
+        Self::with_memory(code_cache, &MemoryPlacement::default())
+    }
+
+    /// `with_code_cache`, with code and data placed as `placement` says
+    /// (e.g. on the NUMA node the execution threads are pinned to)
+    pub unsafe fn with_memory(code_cache: CodeCacheConfig, placement: &MemoryPlacement) -> Result<Self, JITError> {
         // Initialize LLVM for JIT
         LLVM_InitializeNativeTarget();
         LLVM_InitializeNativeAsmPrinter();
@@ -34,14 +94,22 @@ impl JITCompiler {
         let module_name = std::ffi::CString::new("jit_module").unwrap();
         let module = LLVMModuleCreateWithNameInContext(module_name.as_ptr(), context);
 
-        // Create execution engine
+        // Create execution engine, loading code into our own memory
+        let memory_manager = Arc::new(MemoryManager::with_placement(placement)?);
+        let sections = SectionAllocator::new(memory_manager.clone());
+        let mut options: LLVMMCJITCompilerOptions = std::mem::zeroed();
+        LLVMInitializeMCJITCompilerOptions(&mut options, std::mem::size_of::<LLVMMCJITCompilerOptions>());
+        options.OptLevel = 0;
+        options.MCJMM = SectionAllocator::mcjit_memory_manager(&sections);
+
         let mut ee = std::ptr::null_mut();
         let mut error = std::ptr::null_mut();
         
-        if LLVMCreateJITCompilerForModule(
+        if LLVMCreateMCJITCompilerForModule(
             &mut ee,
             module,
-            0, // Optimization level
+            &mut options,
+            std::mem::size_of::<LLVMMCJITCompilerOptions>(),
             &mut error
         ) != 0 {
             let error_str = std::ffi::CStr::from_ptr(error)
@@ -55,34 +123,128 @@ impl JITCompiler {
             context,
             module,
             execution_engine: ee,
-            memory_manager: Arc::new(MemoryManager::new()?),
-            function_cache: RwLock::new(HashMap::new()),
+            memory_manager,
+            sections,
+            code_cache: CodeCache::new(code_cache),
+            compiling: Mutex::new(()),
+            compiled: AtomicU64::new(0),
             runtime: RuntimeSupport::new()?,
+            debug_registrations: RwLock::new(Vec::new()),
+            host_functions: RwLock::new(HostFunctions::new()),
         })
     }
 
+    /// Let C code call `function` as `name`. The C side declares a prototype
+    /// matching `signature`; it is checked when the calling code is compiled.
+    pub fn register_host_function(
+        &self,
+        name: &str,
+        signature: HostSignature,
+        function: HostFunction,
+    ) -> Result<(), JITError> {
+        self.host_functions.write().register(name, signature, function)
+    }
+
+    /// `register_host_function` for a closure; arguments and the result are
+    /// converted to and from `JITValue`s according to `signature`
+    pub fn register_host_closure(
+        &self,
+        name: &str,
+        signature: HostSignature,
+        closure: impl Fn(&[JITValue]) -> JITValue + Send + Sync + 'static,
+    ) -> Result<(), JITError> {
+        self.register_host_function(name, signature, HostFunction::Closure(Box::new(closure)))
+    }
+
+    /// Make a compiled function visible to GDB/LLDB through the JIT
+    /// interface, with the DWARF sections produced by DebugInfoGenerator
+    pub fn register_debug_info(
+        &self,
+        name: &str,
+        address: *const u8,
+        size: usize,
+        debug_sections: Vec<(String, Vec<u8>)>,
+    ) -> Result<(), JITError> {
+        let registration = self.debug_registration(name, address, size, debug_sections)?;
+        self.debug_registrations.write().push(registration);
+        Ok(())
+    }
+
+    /// A symbol file registration, withdrawn when dropped
+    fn debug_registration(
+        &self,
+        name: &str,
+        address: *const u8,
+        size: usize,
+        debug_sections: Vec<(String, Vec<u8>)>,
+    ) -> Result<JitRegistration, JITError> {
+        let mut builder = SymfileBuilder::new().function(name, address as u64, size as u64);
+        for (section, data) in debug_sections {
+            builder = builder.debug_section(&section, data);
+        }
+        let symfile = builder
+            .build()
+            .map_err(|e| JITError::Compilation(format!("debug symfile: {:?}", e)))?;
+
+        Ok(JitRegistration::register(symfile))
+    }
+
     pub unsafe fn compile_and_run<T>(
         &self,
         source: &str,
         function_name: &str,
         args: &[JITValue],
     ) -> Result<T, JITError> {
+        // Evicted code isn't freed while this call may be running it
+        let _running = self.code_cache.enter();
+
         // Check cache first
-        if let Some(cached_fn) = self.function_cache.read().get(function_name) {
-            return self.execute_function(cached_fn, args);
+        if let Some(cached_fn) = self.code_cache.get(function_name) {
+            return self.execute_function(&cached_fn, args);
         }
 
         // Parse C code
         let ast = self.parse_c_code(source)?;
 
+        // A module of its own, taken out of the engine again if compiling
+        // fails or once the cache frees it
+        let compiling = self.compiling.lock();
+        let mut code = self.add_module(function_name);
+
         // Generate LLVM IR
-        let function = self.generate_ir(&ast)?;
+        let function = self.generate_ir(code.module, &ast)?;
+        setjmp::mark_returns_twice(code.module);
 
-        // Optimize
-        self.optimize_function(&function)?;
+        // A fresh symbol per compile: MCJIT looks names up among everything
+        // it has loaded before compiling anything, and would find a copy
+        // of this function that was evicted
+        let symbol = CString::new(format!("{}.{}", function_name, self.compiled.fetch_add(1, Ordering::Relaxed)))
+            .map_err(|_| JITError::IRGeneration(format!("'{}' isn't a symbol name", function_name)))?;
+        LLVMSetValueName2(function, symbol.as_ptr(), symbol.as_bytes().len());
 
-        // JIT compile
-        let function_ptr = self.compile_function(&function)?;
+        // Optimize
+        self.optimize_function(code.module, &function)?;
+
+        // Calls to registered host functions
+        self.host_functions
+            .write()
+            .bind(self.context, code.module, self.execution_engine)?;
+        setjmp::bind_longjmp(code.module, self.execution_engine);
+
+        // JIT compile; what MCJIT mapped for the module is what the cache
+        // is charged
+        let compiled = self.compile_function(&function);
+        code.sections = self.sections.take();
+        let function_ptr = compiled?;
+        let size = code.sections.iter().map(|section| section.size).sum();
+
+        // Symbols only: this path has no source map to build DWARF from
+        let extent = code
+            .sections
+            .iter()
+            .find(|section| section.contains(function_ptr))
+            .map_or(0, |section| section.address as usize + section.size - function_ptr as usize);
+        code.debug = Some(self.debug_registration(function_name, function_ptr as *const u8, extent, Vec::new())?);
 
         // Create JIT function
         let jit_function = JITFunction {
@@ -91,16 +253,19 @@ impl JITCompiler {
             name: function_name.to_string(),
         };
 
-        // Cache the function
-        self.function_cache.write().insert(
-            function_name.to_string(),
-            jit_function.clone()
-        );
+        // Cache the function; the ones it evicts are recompiled when called
+        self.code_cache.insert(function_name, jit_function.clone(), size, Box::new(code));
+        drop(compiling);
 
         // Execute
         self.execute_function(&jit_function, args)
     }
 
+    /// Size, hits, misses and evictions of the compiled-function cache
+    pub fn code_cache_stats(&self) -> CodeCacheStats {
+        self.code_cache.stats()
+    }
+
     unsafe fn parse_c_code(&self, source: &str) -> Result<AST, JITError> {
         // Use minimal C parser focused on function definitions
         let mut parser = CParser::new(source);
@@ -108,8 +273,22 @@ impl JITCompiler {
             .map_err(|e| JITError::ParseError(e))
     }
 
-    unsafe fn generate_ir(&self, ast: &AST) -> Result<LLVMValueRef, JITError> {
-        let mut builder = IRBuilder::new(self.context);
+    /// A new module for `function_name`, added to the execution engine
+    unsafe fn add_module(&self, function_name: &str) -> MachineCode {
+        let name = CString::new(function_name.replace('\0', "")).unwrap();
+        let module = LLVMModuleCreateWithNameInContext(name.as_ptr(), self.context);
+        LLVMAddModule(self.execution_engine, module);
+        MachineCode {
+            execution_engine: self.execution_engine,
+            module,
+            sections: Vec::new(),
+            allocator: self.sections.clone(),
+            debug: None,
+        }
+    }
+
+    unsafe fn generate_ir(&self, module: LLVMModuleRef, ast: &AST) -> Result<LLVMValueRef, JITError> {
+        let mut builder = IRBuilder::new(self.context, module);
         
         // Convert AST to LLVM IR
         let function = builder.generate_function(ast)?;
@@ -133,10 +312,11 @@ impl JITCompiler {
 
     unsafe fn optimize_function(
         &self,
+        module: LLVMModuleRef,
         function: &LLVMValueRef
     ) -> Result<(), JITError> {
         // Create function pass manager
-        let pass_manager = LLVMCreateFunctionPassManagerForModule(self.module);
+        let pass_manager = LLVMCreateFunctionPassManagerForModule(module);
 
         // Add optimization passes
         LLVMAddInstructionCombiningPass(pass_manager);
@@ -186,7 +366,7 @@ impl JITCompiler {
         // Prepare arguments
         let mut raw_args: Vec<u64> = Vec::with_capacity(args.len());
         for (arg, expected_type) in args.iter().zip(function.signature.args.iter()) {
-            if arg.get_type() != *expected_type {
+            if !expected_type.accepts(arg) {
                 return Err(JITError::TypeMismatch);
             }
             raw_args.push(arg.to_raw());
@@ -204,6 +384,41 @@ impl JITCompiler {
     }
 }
 
+/// A compiled function's module and the sections MCJIT loaded it into,
+/// released when the code cache frees the function
+#[cfg(feature = "llvm")]
+struct MachineCode {
+    execution_engine: LLVMExecutionEngineRef,
+    module: LLVMModuleRef,
+    sections: Vec<Section>,
+    allocator: Arc<SectionAllocator>,
+    debug: Option<JitRegistration>,
+}
+
+// Only dropped by the code cache, once no thread runs compiled code
+#[cfg(feature = "llvm")]
+unsafe impl Send for MachineCode {}
+
+#[cfg(feature = "llvm")]
+impl Drop for MachineCode {
+    fn drop(&mut self) {
+        // The debugger stops looking at the code before it goes
+        self.debug = None;
+        unsafe {
+            let mut module = std::ptr::null_mut();
+            let mut error = std::ptr::null_mut();
+            if LLVMRemoveModule(self.execution_engine, self.module, &mut module, &mut error) != 0 {
+                // Still the engine's: leave its memory mapped
+                LLVMDisposeMessage(error);
+                return;
+            }
+            LLVMDisposeModule(module);
+            self.allocator.free(std::mem::take(&mut self.sections));
+        }
+    }
+}
+
+#[cfg(feature = "llvm")]
 #[derive(Clone)]
 pub struct JITFunction {
     ptr: *mut u8,
@@ -211,6 +426,7 @@ pub struct JITFunction {
     name: String,
 }
 
+#[cfg(feature = "llvm")]
 #[derive(Clone)]
 pub struct FunctionSignature {
     args: Vec<JITType>,
@@ -230,6 +446,79 @@ pub enum JITType {
     Pointer(Box<JITType>),
 }
 
+impl JITType {
+    /// Whether `value` can be passed where this type is expected. Pointers
+    /// match any pointer; the pointee isn't tracked at runtime.
+    pub fn accepts(&self, value: &JITValue) -> bool {
+        matches!(
+            (self, value),
+            (JITType::Void, JITValue::Void)
+                | (JITType::Int8, JITValue::Int8(_))
+                | (JITType::Int16, JITValue::Int16(_))
+                | (JITType::Int32, JITValue::Int32(_))
+                | (JITType::Int64, JITValue::Int64(_))
+                | (JITType::Float, JITValue::Float(_))
+                | (JITType::Double, JITValue::Double(_))
+                | (JITType::Pointer(_), JITValue::Pointer(_))
+        )
+    }
+}
+
+/// A value crossing between Rust and compiled C
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JITValue {
+    Void,
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Float(f32),
+    Double(f64),
+    Pointer(*mut u8),
+}
+
+impl JITValue {
+    /// The raw 64-bit argument slot: integers sign-extended, floats as
+    /// their bit pattern in the low bits
+    pub fn to_raw(&self) -> u64 {
+        match *self {
+            JITValue::Void => 0,
+            JITValue::Int8(v) => v as i64 as u64,
+            JITValue::Int16(v) => v as i64 as u64,
+            JITValue::Int32(v) => v as i64 as u64,
+            JITValue::Int64(v) => v as u64,
+            JITValue::Float(v) => v.to_bits() as u64,
+            JITValue::Double(v) => v.to_bits(),
+            JITValue::Pointer(p) => p as u64,
+        }
+    }
+
+    pub fn from_raw(raw: u64, ty: &JITType) -> Self {
+        match ty {
+            JITType::Void => JITValue::Void,
+            JITType::Int8 => JITValue::Int8(raw as i8),
+            JITType::Int16 => JITValue::Int16(raw as i16),
+            JITType::Int32 => JITValue::Int32(raw as i32),
+            JITType::Int64 => JITValue::Int64(raw as i64),
+            JITType::Float => JITValue::Float(f32::from_bits(raw as u32)),
+            JITType::Double => JITValue::Double(f64::from_bits(raw)),
+            JITType::Pointer(_) => JITValue::Pointer(raw as *mut u8),
+        }
+    }
+}
+
+macro_rules! jit_value_from {
+    ($($ty:ty => $variant:ident),*) => {
+        $(impl From<$ty> for JITValue {
+            fn from(value: $ty) -> Self {
+                JITValue::$variant(value)
+            }
+        })*
+    };
+}
+
+jit_value_from!(i8 => Int8, i16 => Int16, i32 => Int32, i64 => Int64, f32 => Float, f64 => Double, *mut u8 => Pointer);
+
 #[derive(Debug)]
 pub enum JITError {
     EngineCreation(String),
@@ -240,6 +529,8 @@ pub enum JITError {
     ArgumentMismatch,
     TypeMismatch,
     MemoryError(String),
+    /// Registering or binding a host function
+    HostFunction(String),
 }
 
 // Example usage:
@@ -0,0 +1,574 @@
+// src/jit/probes.rs
+//! Hot-patchable prologues and live entry/exit probes
+//! With `JITOptions::patchable_prologues` every function of the program
+//! starts with a `PATCH_SIZE`-byte nop. Attaching a probe swaps that nop for
+//! a call to a small stub near the code, which jumps to `probe_entry` in
+//! the runtime; detaching swaps the nop back. Nothing is recompiled, and a
+//! function without a probe pays for one nop.
+//!
+//! An entry probe counts calls. An exit probe also redirects the caller's
+//! return address through `probe_return`, which counts the return and the
+//! time spent in the function before going back to the caller; returns
+//! skipped by `longjmp` are dropped when an outer probed function returns.
+//!
+//! Probe state lives in `SLOTS`, a fixed table at the same address in a
+//! process forked after compiling, so the gdbstub attaches probes to the
+//! program it debugs by writing the table and the patch through
+//! `/proc/PID/mem`, with `monitor probe ...` (see `ProbeTable::command`).
+//! Only x86_64 hosts are supported.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+#[cfg(feature = "llvm")]
+use std::ffi::CStr;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::LLVMAttributeFunctionIndex;
+
+/// Functions that may have a probe attached at once
+pub const MAX_PROBES: usize = 64;
+
+/// Bytes of nop at each function's entry: room for a `call rel32`
+pub const PATCH_SIZE: usize = 5;
+
+/// The single instruction the pad is rewritten to, so a patch replaces
+/// one instruction and never one a thread is part way through
+const NOP5: [u8; PATCH_SIZE] = [0x0f, 0x1f, 0x44, 0x00, 0x00];
+
+const ENTRY: u64 = 1;
+const EXIT: u64 = 2;
+
+/// Probe state of one function, read and written as plain words by a
+/// debugger in another process
+#[repr(C)]
+struct Slot {
+    function: AtomicU64,
+    kinds: AtomicU64,
+    entries: AtomicU64,
+    exits: AtomicU64,
+    nanos: AtomicU64,
+}
+
+const SLOT_SIZE: usize = std::mem::size_of::<Slot>();
+
+impl Slot {
+    const fn new() -> Self {
+        Slot {
+            function: AtomicU64::new(0),
+            kinds: AtomicU64::new(0),
+            entries: AtomicU64::new(0),
+            exits: AtomicU64::new(0),
+            nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+static SLOTS: [Slot; MAX_PROBES] = [const { Slot::new() }; MAX_PROBES];
+
+fn slot_address(slot: usize) -> u64 {
+    &SLOTS[slot] as *const Slot as u64
+}
+
+/// Which events of a function are probed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeKind {
+    Entry,
+    Exit,
+    Both,
+}
+
+impl ProbeKind {
+    fn bits(self) -> u64 {
+        match self {
+            ProbeKind::Entry => ENTRY,
+            ProbeKind::Exit => EXIT,
+            ProbeKind::Both => ENTRY | EXIT,
+        }
+    }
+}
+
+impl std::str::FromStr for ProbeKind {
+    type Err = ProbeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "entry" => Ok(ProbeKind::Entry),
+            "exit" => Ok(ProbeKind::Exit),
+            "both" => Ok(ProbeKind::Both),
+            _ => Err(ProbeError::Usage(format!("unknown probe kind '{}'", s))),
+        }
+    }
+}
+
+/// What the probes of one function counted since they were attached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProbeStats {
+    pub entries: u64,
+    pub exits: u64,
+    /// Time between entry and return, summed over `exits` calls
+    pub total_time: Duration,
+}
+
+/// Access to the memory of the process running the probed code
+pub trait ProbeMemory {
+    fn read(&self, address: u64, buf: &mut [u8]) -> Result<(), ProbeError>;
+    /// Store to `SLOTS`, in whole aligned words
+    fn write_data(&self, address: u64, data: &[u8]) -> Result<(), ProbeError>;
+    /// Replace the instruction at a function's entry
+    fn write_code(&self, address: u64, data: &[u8]) -> Result<(), ProbeError>;
+}
+
+/// The memory of this process, for probes on code it runs itself
+pub struct LocalMemory;
+
+impl ProbeMemory for LocalMemory {
+    fn read(&self, address: u64, buf: &mut [u8]) -> Result<(), ProbeError> {
+        unsafe { std::ptr::copy_nonoverlapping(address as *const u8, buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+
+    fn write_data(&self, address: u64, data: &[u8]) -> Result<(), ProbeError> {
+        if !address.is_multiple_of(8) || !data.len().is_multiple_of(8) {
+            return Err(ProbeError::Unaligned(address));
+        }
+        for (index, word) in data.chunks_exact(8).enumerate() {
+            let target = unsafe { &*((address as usize + index * 8) as *const AtomicU64) };
+            target.store(u64::from_le_bytes(word.try_into().expect("an 8-byte chunk")), Ordering::Release);
+        }
+        Ok(())
+    }
+
+    fn write_code(&self, address: u64, data: &[u8]) -> Result<(), ProbeError> {
+        // One aligned store, so other threads run either the old instruction or the new one
+        let word = address & !7;
+        let shift = (address - word) as usize;
+        if shift + data.len() > 8 {
+            return Err(ProbeError::Unaligned(address));
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let page = (word & !(page_size - 1)) as *mut libc::c_void;
+        unsafe {
+            if libc::mprotect(page, page_size as usize, libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC) != 0 {
+                return Err(ProbeError::Memory(std::io::Error::last_os_error().to_string()));
+            }
+            let target = &*(word as *const AtomicU64);
+            let mut bytes = target.load(Ordering::Acquire).to_le_bytes();
+            bytes[shift..shift + data.len()].copy_from_slice(data);
+            target.store(u64::from_le_bytes(bytes), Ordering::Release);
+            libc::mprotect(page, page_size as usize, libc::PROT_READ | libc::PROT_EXEC);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PatchSite {
+    address: u64,
+    stub: u64,
+    slot: Option<usize>,
+}
+
+/// The patchable functions of the JIT-compiled program and the probes
+/// attached to them
+#[derive(Debug, Clone, Default)]
+pub struct ProbeTable {
+    functions: HashMap<String, PatchSite>,
+    stubs: Vec<u64>,
+}
+
+impl ProbeTable {
+    pub fn new() -> Self {
+        ProbeTable::default()
+    }
+
+    /// Register the freshly compiled function `name` at `address`, before
+    /// any thread runs it: its pad becomes one nop, and a stub it can reach
+    /// with a `call rel32` is set up.
+    ///
+    /// # Safety
+    /// `address` must be the entry of a function compiled with
+    /// `mark_patchable`, in this process.
+    pub unsafe fn add_function(&mut self, name: &str, address: *const u8) -> Result<(), ProbeError> {
+        let address = address as u64;
+        let pad = std::slice::from_raw_parts(address as *const u8, PATCH_SIZE);
+        if pad != NOP5 {
+            if pad.iter().any(|&byte| byte != 0x90) {
+                return Err(ProbeError::NotPatchable(name.to_string()));
+            }
+            LocalMemory.write_code(address, &NOP5)?;
+        }
+        let stub = self.near_stub(address)?;
+        self.functions.insert(name.to_string(), PatchSite { address, stub, slot: None });
+        Ok(())
+    }
+
+    /// Names of the functions probes can be attached to
+    pub fn functions(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+
+    pub fn attach(&mut self, memory: &dyn ProbeMemory, name: &str, kind: ProbeKind) -> Result<(), ProbeError> {
+        let in_use: Vec<usize> = self.functions.values().filter_map(|site| site.slot).collect();
+        let site = self.functions.get_mut(name).ok_or_else(|| ProbeError::UnknownFunction(name.to_string()))?;
+        if let Some(slot) = site.slot {
+            return memory.write_data(slot_address(slot) + 8, &kind.bits().to_le_bytes());
+        }
+        let slot = (0..MAX_PROBES).find(|slot| !in_use.contains(slot)).ok_or(ProbeError::TooManyProbes)?;
+
+        // The slot is ready before the first call can reach it
+        let mut state = [0u8; SLOT_SIZE];
+        state[..8].copy_from_slice(&site.address.to_le_bytes());
+        state[8..16].copy_from_slice(&kind.bits().to_le_bytes());
+        memory.write_data(slot_address(slot), &state)?;
+
+        let mut call = [0u8; PATCH_SIZE];
+        call[0] = 0xe8;
+        let displacement = site.stub as i64 - (site.address + PATCH_SIZE as u64) as i64;
+        call[1..].copy_from_slice(&(displacement as i32).to_le_bytes());
+        memory.write_code(site.address, &call)?;
+        site.slot = Some(slot);
+        Ok(())
+    }
+
+    pub fn detach(&mut self, memory: &dyn ProbeMemory, name: &str) -> Result<(), ProbeError> {
+        let site = self.functions.get_mut(name).ok_or_else(|| ProbeError::UnknownFunction(name.to_string()))?;
+        let Some(slot) = site.slot.take() else { return Ok(()) };
+        memory.write_code(site.address, &NOP5)?;
+        // Calls already past the pad still return through `probe_return`
+        memory.write_data(slot_address(slot) + 8, &0u64.to_le_bytes())
+    }
+
+    /// Counts of the probes attached to `name`
+    pub fn stats(&self, memory: &dyn ProbeMemory, name: &str) -> Result<ProbeStats, ProbeError> {
+        let site = self.functions.get(name).ok_or_else(|| ProbeError::UnknownFunction(name.to_string()))?;
+        let slot = site.slot.ok_or_else(|| ProbeError::NotAttached(name.to_string()))?;
+        let mut state = [0u8; SLOT_SIZE];
+        memory.read(slot_address(slot), &mut state)?;
+        let word = |index: usize| u64::from_le_bytes(state[index * 8..index * 8 + 8].try_into().expect("an 8-byte field"));
+        Ok(ProbeStats { entries: word(2), exits: word(3), total_time: Duration::from_nanos(word(4)) })
+    }
+
+    /// Functions with probes attached
+    pub fn attached(&self) -> impl Iterator<Item = &str> {
+        self.functions.iter().filter(|(_, site)| site.slot.is_some()).map(|(name, _)| name.as_str())
+    }
+
+    /// Run one line of the text interface remote frontends offer:
+    /// `probe list`, `probe attach NAME [entry|exit|both]`,
+    /// `probe detach NAME` and `probe stats [NAME]`. Returns the output.
+    pub fn command(&mut self, memory: &dyn ProbeMemory, line: &str) -> Result<String, ProbeError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["probe", "list"] => {
+                let mut names: Vec<&str> = self.functions().collect();
+                names.sort_unstable();
+                Ok(names
+                    .iter()
+                    .map(|&name| {
+                        let attached = if self.functions[name].slot.is_some() { " (probed)" } else { "" };
+                        format!("{}{}\n", name, attached)
+                    })
+                    .collect())
+            }
+            ["probe", "attach", name, rest @ ..] if rest.len() <= 1 => {
+                let kind = rest.first().map_or(Ok(ProbeKind::Both), |kind| kind.parse())?;
+                self.attach(memory, name, kind)?;
+                Ok(format!("probe attached to {}\n", name))
+            }
+            ["probe", "detach", name] => {
+                self.detach(memory, name)?;
+                Ok(format!("probe detached from {}\n", name))
+            }
+            ["probe", "stats", names @ ..] if names.len() <= 1 => {
+                let mut listed: Vec<&str> = if names.is_empty() { self.attached().collect() } else { names.to_vec() };
+                listed.sort_unstable();
+                let mut output = String::new();
+                for name in listed {
+                    let stats = self.stats(memory, name)?;
+                    output.push_str(&format!(
+                        "{}: {} entries, {} exits, {:?} inside\n",
+                        name, stats.entries, stats.exits, stats.total_time
+                    ));
+                }
+                Ok(output)
+            }
+            _ => Err(ProbeError::Usage(
+                "probe list | probe attach NAME [entry|exit|both] | probe detach NAME | probe stats [NAME]".to_string(),
+            )),
+        }
+    }
+
+    /// A stub within `call rel32` range of `address` that jumps to
+    /// `probe_entry_trampoline`, mapping a page for one if none is close enough
+    unsafe fn near_stub(&mut self, address: u64) -> Result<u64, ProbeError> {
+        let reachable = |stub: u64| i32::try_from(stub as i64 - (address + PATCH_SIZE as u64) as i64).is_ok();
+        if let Some(&stub) = self.stubs.iter().find(|&&stub| reachable(stub)) {
+            return Ok(stub);
+        }
+
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as u64;
+        for distance in (1..=16u64).map(|step| step << 26) {
+            let Some(hint) = address.checked_sub(distance) else { break };
+            let page = libc::mmap(
+                (hint & !(page_size - 1)) as *mut libc::c_void,
+                page_size as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            );
+            if page == libc::MAP_FAILED {
+                continue;
+            }
+            let stub = page as u64;
+            if !reachable(stub) {
+                libc::munmap(page, page_size as usize);
+                continue;
+            }
+
+            // jmp [rip + 0] followed by the target
+            let code = page as *mut u8;
+            std::ptr::copy_nonoverlapping([0xff, 0x25, 0, 0, 0, 0].as_ptr(), code, 6);
+            std::ptr::write_unaligned(code.add(6) as *mut u64, probe_entry_trampoline as *const () as u64);
+            libc::mprotect(page, page_size as usize, libc::PROT_READ | libc::PROT_EXEC);
+            self.stubs.push(stub);
+            return Ok(stub);
+        }
+        Err(ProbeError::NoStubSpace(address))
+    }
+}
+
+/// Give every function defined in `module` a `PATCH_SIZE`-byte pad at its
+/// entry, 16-byte aligned so a patch is one aligned store. Returns their names.
+#[cfg(feature = "llvm")]
+pub unsafe fn mark_patchable(module: LLVMModuleRef) -> Result<Vec<String>, ProbeError> {
+    if !cfg!(target_arch = "x86_64") {
+        return Err(ProbeError::UnsupportedArchitecture);
+    }
+    let context = LLVMGetModuleContext(module);
+    let (key, value) = ("patchable-function-entry", PATCH_SIZE.to_string());
+    let attribute = LLVMCreateStringAttribute(
+        context,
+        key.as_ptr() as *const _,
+        key.len() as u32,
+        value.as_ptr() as *const _,
+        value.len() as u32,
+    );
+
+    let mut names = Vec::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMCountBasicBlocks(function) > 0 {
+            LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute);
+            LLVMSetAlignment(function, 16);
+            let mut len = 0;
+            let name = LLVMGetValueName2(function, &mut len);
+            names.push(CStr::from_ptr(name).to_string_lossy().into_owned());
+        }
+        function = LLVMGetNextFunction(function);
+    }
+    Ok(names)
+}
+
+/// A call whose return is redirected through `probe_return`
+struct Pending {
+    slot: usize,
+    return_slot: usize,
+    return_address: usize,
+    start: u64,
+}
+
+thread_local! {
+    static PENDING: RefCell<Vec<Pending>> = const { RefCell::new(Vec::new()) };
+}
+
+fn now() -> u64 {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/// Called from the pad of a probed function; `patch_end` is the address
+/// after the pad, `return_slot` holds the function's return address
+extern "C" fn probe_entry(patch_end: usize, return_slot: *mut usize) {
+    let function = (patch_end - PATCH_SIZE) as u64;
+    let Some(slot) = SLOTS
+        .iter()
+        .position(|slot| slot.function.load(Ordering::Acquire) == function && slot.kinds.load(Ordering::Acquire) != 0)
+    else {
+        return;
+    };
+    let kinds = SLOTS[slot].kinds.load(Ordering::Acquire);
+    if kinds & ENTRY != 0 {
+        SLOTS[slot].entries.fetch_add(1, Ordering::Relaxed);
+    }
+    if kinds & EXIT != 0 {
+        let return_address = unsafe { *return_slot };
+        PENDING.with(|pending| {
+            pending.borrow_mut().push(Pending { slot, return_slot: return_slot as usize, return_address, start: now() })
+        });
+        unsafe { *return_slot = probe_return_trampoline as *const () as usize };
+    }
+}
+
+/// Called when a function with an exit probe returns, with the stack
+/// pointer after its `ret`; returns where it should have returned to
+extern "C" fn probe_return(stack: usize) -> usize {
+    let end = now();
+    let found = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        // Anything deeper was left by a `longjmp`
+        while let Some(call) = pending.pop() {
+            if call.return_slot == stack - 8 {
+                return Some(call);
+            }
+        }
+        None
+    });
+    let Some(call) = found else {
+        eprintln!("probe: return through a probe with no record of the call");
+        std::process::abort();
+    };
+    let slot = &SLOTS[call.slot];
+    slot.exits.fetch_add(1, Ordering::Relaxed);
+    slot.nanos.fetch_add(end.saturating_sub(call.start), Ordering::Relaxed);
+    call.return_address
+}
+
+/// Reached by `call` from a function's pad, via its stub: saves the
+/// argument registers around `probe_entry`
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn probe_entry_trampoline() {
+    core::arch::naked_asm!(
+        // rax holds the vector register count of a variadic call, r10 a static chain
+        "push rax",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push rcx",
+        "push r8",
+        "push r9",
+        "push r10",
+        "sub rsp, 128",
+        "movdqu [rsp], xmm0",
+        "movdqu [rsp + 16], xmm1",
+        "movdqu [rsp + 32], xmm2",
+        "movdqu [rsp + 48], xmm3",
+        "movdqu [rsp + 64], xmm4",
+        "movdqu [rsp + 80], xmm5",
+        "movdqu [rsp + 96], xmm6",
+        "movdqu [rsp + 112], xmm7",
+        // The end of the pad, and the slot of the function's own return address
+        "mov rdi, [rsp + 192]",
+        "lea rsi, [rsp + 200]",
+        "call {entry}",
+        "movdqu xmm0, [rsp]",
+        "movdqu xmm1, [rsp + 16]",
+        "movdqu xmm2, [rsp + 32]",
+        "movdqu xmm3, [rsp + 48]",
+        "movdqu xmm4, [rsp + 64]",
+        "movdqu xmm5, [rsp + 80]",
+        "movdqu xmm6, [rsp + 96]",
+        "movdqu xmm7, [rsp + 112]",
+        "add rsp, 128",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rcx",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop rax",
+        "ret",
+        entry = sym probe_entry,
+    )
+}
+
+/// Where a function with an exit probe returns to: keeps the return value
+/// registers around `probe_return`, then goes on to the real caller
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn probe_return_trampoline() {
+    core::arch::naked_asm!(
+        "push rax",
+        "push rdx",
+        "sub rsp, 32",
+        "movdqu [rsp], xmm0",
+        "movdqu [rsp + 16], xmm1",
+        "lea rdi, [rsp + 48]",
+        "call {exit}",
+        "mov r11, rax",
+        "movdqu xmm0, [rsp]",
+        "movdqu xmm1, [rsp + 16]",
+        "add rsp, 32",
+        "pop rdx",
+        "pop rax",
+        "jmp r11",
+        exit = sym probe_return,
+    )
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+unsafe extern "C" fn probe_entry_trampoline() {}
+
+#[cfg(not(target_arch = "x86_64"))]
+unsafe extern "C" fn probe_return_trampoline() {}
+
+#[derive(Debug)]
+pub enum ProbeError {
+    UnknownFunction(String),
+    /// The function was compiled without a patchable prologue
+    NotPatchable(String),
+    NotAttached(String),
+    TooManyProbes,
+    /// No page within `call rel32` range of the function could be mapped
+    NoStubSpace(u64),
+    Unaligned(u64),
+    Memory(String),
+    Usage(String),
+    UnsupportedArchitecture,
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::UnknownFunction(name) => write!(f, "no patchable function named {}", name),
+            ProbeError::NotPatchable(name) => write!(f, "{} has no patchable prologue", name),
+            ProbeError::NotAttached(name) => write!(f, "no probe is attached to {}", name),
+            ProbeError::TooManyProbes => write!(f, "at most {} functions can be probed at once", MAX_PROBES),
+            ProbeError::NoStubSpace(address) => write!(f, "no room for a probe stub near {:#x}", address),
+            ProbeError::Unaligned(address) => write!(f, "patch at {:#x} isn't within one aligned word", address),
+            ProbeError::Memory(message) => write!(f, "patching failed: {}", message),
+            ProbeError::Usage(message) => write!(f, "{}", message),
+            ProbeError::UnsupportedArchitecture => write!(f, "probes are only supported on x86_64 hosts"),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn example(engine: &mut Engine) -> Result<(), ProbeError> {
+    // EngineOptions { patchable_prologues: true, .. }
+    engine.eval_string("int square(int x) { return x * x; }").unwrap();
+
+    let probes = engine.probes();
+    probes.write().attach(&LocalMemory, "square", ProbeKind::Both)?;
+    let nine: i64 = unsafe { engine.call_function("square", &[JITValue::Int(3)]).unwrap() };
+
+    let stats = probes.read().stats(&LocalMemory, "square")?;
+    println!("square: {} calls, {:?}", stats.entries, stats.total_time);
+    probes.write().detach(&LocalMemory, "square")?;
+
+    // The same from GDB attached with `interp debug --gdb-port`:
+    // (gdb) monitor probe attach square
+    // (gdb) monitor probe stats
+    Ok(())
+}
+*/
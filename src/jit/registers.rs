@@ -1,6 +1,7 @@
 // src/jit/registers.rs
 use std::collections::{HashMap, HashSet, VecDeque};
 use bitflags::bitflags;
+use crate::arch::inline_asm::{ConstraintKind, ResolvedAsm};
 
 bitflags! {
     pub struct RegisterClass: u32 {
@@ -26,6 +27,9 @@ pub struct RegisterAllocator {
     // ABI handling
     abi_reserved: HashSet<PhysicalReg>,
     callee_saved: HashSet<PhysicalReg>,
+
+    // Callee-saved registers the function's `asm` statements change
+    asm_saved: HashSet<PhysicalReg>,
 }
 
 impl RegisterAllocator {
@@ -38,6 +42,7 @@ impl RegisterAllocator {
             interference_graph: InterferenceGraph::new(),
             abi_reserved: HashSet::new(),
             callee_saved: HashSet::new(),
+            asm_saved: HashSet::new(),
         };
 
         // Initialize register pools
@@ -252,9 +257,49 @@ impl RegisterAllocator {
     pub fn get_callee_saved(&self) -> &HashSet<PhysicalReg> {
         &self.callee_saved
     }
+
+    /// Keep the registers an `asm` statement clobbers or pins operands to
+    /// out of the pools for the rest of the function, so no value lives in
+    /// one across the statement. Must run for every statement before the
+    /// prologue is emitted, which then saves the callee-saved ones.
+    pub fn reserve_asm(&mut self, asm: &ResolvedAsm) -> Result<(), AllocError> {
+        let pinned = asm.outputs.iter().chain(&asm.inputs).filter_map(|operand| match &operand.kind {
+            ConstraintKind::Register(register) => Some(register),
+            _ => None,
+        });
+        for register in asm.clobbered.iter().chain(pinned) {
+            let preg = PhysicalReg::from_name(&register.name).ok_or(AllocError::InvalidRegister)?;
+            if self.allocated.values().any(|&allocated| allocated == preg) {
+                return Err(AllocError::AsmConflict(preg));
+            }
+            for pool in self.available.values_mut() {
+                pool.retain(|&candidate| candidate != preg);
+            }
+        }
+        for register in &asm.saved {
+            let preg = PhysicalReg::from_name(&register.name).ok_or(AllocError::InvalidRegister)?;
+            self.asm_saved.insert(preg);
+        }
+        Ok(())
+    }
+
+    /// Callee-saved registers the prologue must save, in push order: those
+    /// the allocator may still hand out and those `asm` statements change.
+    /// The frame pointer is saved separately.
+    pub fn callee_saved(&self) -> Vec<PhysicalReg> {
+        let mut used: Vec<PhysicalReg> = self
+            .callee_saved
+            .iter()
+            .copied()
+            .filter(|preg| !self.abi_reserved.contains(preg))
+            .filter(|preg| self.asm_saved.contains(preg) || self.available.values().any(|pool| pool.contains(preg)))
+            .collect();
+        used.sort();
+        used
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum PhysicalReg {
     // General purpose
     RAX, RBX, RCX, RDX,
@@ -270,6 +315,37 @@ pub enum PhysicalReg {
 }
 
 impl PhysicalReg {
+    /// The register `name` (`rax`, `eax`, `al`, `r8d`, `xmm3`) is part of
+    pub fn from_name(name: &str) -> Option<PhysicalReg> {
+        use PhysicalReg::*;
+        const GENERAL: [PhysicalReg; 16] = [RAX, RCX, RDX, RBX, RSP, RBP, RSI, RDI, R8, R9, R10, R11, R12, R13, R14, R15];
+        const VECTOR: [PhysicalReg; 16] = [
+            XMM0, XMM1, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7, XMM8, XMM9, XMM10, XMM11, XMM12, XMM13, XMM14, XMM15,
+        ];
+        let name = name.to_lowercase();
+        if let Some(index) = name.strip_prefix("xmm").and_then(|n| n.parse::<usize>().ok()) {
+            return VECTOR.get(index).copied();
+        }
+        if let Some(rest) = name.strip_prefix('r').filter(|rest| rest.starts_with(|c: char| c.is_ascii_digit())) {
+            let digits = rest.trim_end_matches(['d', 'w', 'b']);
+            return digits.parse::<usize>().ok().filter(|&n| (8..16).contains(&n)).map(|n| GENERAL[n]);
+        }
+        let legacy = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+        let low = ["al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil"];
+        let base = name.strip_prefix(['r', 'e']).filter(|base| base.len() == 2).unwrap_or(&name);
+        legacy
+            .iter()
+            .position(|&r| r == base)
+            .or_else(|| low.iter().position(|&r| r == name))
+            .or_else(|| ["ah", "ch", "dh", "bh"].iter().position(|&r| r == name))
+            .map(|index| GENERAL[index])
+    }
+
+    /// The 64-bit name, `rbx` or `xmm3`
+    pub fn name(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
     fn register_class(&self) -> RegisterClass {
         match self {
             PhysicalReg::RAX..=PhysicalReg::R15 => RegisterClass::GENERAL,
@@ -330,6 +406,8 @@ pub enum AllocError {
     NoSpillCandidate,
     InvalidRegister,
     SpillFailed,
+    /// An `asm` statement clobbers a register already holding a value
+    AsmConflict(PhysicalReg),
 }
 
 // Example usage:
@@ -0,0 +1,130 @@
+// src/jit/sections.rs
+//! MCJIT's memory manager
+//! MCJIT asks for every section of a module it loads. Each is mapped on its
+//! own by the compiler's `MemoryManager`, so freeing a module's sections
+//! gives the memory back, and is recorded until whoever compiled the module
+//! takes them: that is how the code cache learns a function's real size.
+//! Modules are loaded one at a time (the compiler holds its compile lock),
+//! so everything recorded between two `take`s belongs to one module.
+
+use std::ffi::{c_char, c_uint, c_void, CString};
+use std::sync::Arc;
+use llvm_sys::execution_engine::*;
+use llvm_sys::prelude::LLVMBool;
+use parking_lot::Mutex;
+use super::memory::{flush_instruction_cache, MemoryManager};
+
+/// A section MCJIT loaded a module into
+#[derive(Debug)]
+pub struct Section {
+    pub address: *mut u8,
+    /// Bytes mapped for it, whole pages
+    pub size: usize,
+    pub executable: bool,
+}
+
+// Only freed by the module owning it, once no thread runs its code
+unsafe impl Send for Section {}
+
+impl Section {
+    pub fn contains(&self, address: *const u8) -> bool {
+        (self.address as usize..self.address as usize + self.size).contains(&(address as usize))
+    }
+}
+
+pub struct SectionAllocator {
+    memory: Arc<MemoryManager>,
+    // Sections of the module being loaded
+    loading: Mutex<Vec<Section>>,
+}
+
+impl SectionAllocator {
+    pub fn new(memory: Arc<MemoryManager>) -> Arc<Self> {
+        Arc::new(SectionAllocator { memory, loading: Mutex::new(Vec::new()) })
+    }
+
+    /// An MCJIT memory manager allocating through `allocator`; the engine
+    /// holds a reference until it's disposed
+    pub unsafe fn mcjit_memory_manager(allocator: &Arc<Self>) -> LLVMMCJITMemoryManagerRef {
+        LLVMCreateSimpleMCJITMemoryManager(
+            Arc::into_raw(allocator.clone()) as *mut c_void,
+            allocate_code_section,
+            allocate_data_section,
+            finalize_memory,
+            Some(destroy),
+        )
+    }
+
+    /// The sections loaded since the last call
+    pub fn take(&self) -> Vec<Section> {
+        std::mem::take(&mut *self.loading.lock())
+    }
+
+    /// Unmap sections taken from `take`
+    pub unsafe fn free(&self, sections: Vec<Section>) {
+        for section in sections {
+            let _ = self.memory.free(section.address);
+        }
+    }
+
+    unsafe fn allocate(&self, size: usize, alignment: c_uint, executable: bool) -> *mut u8 {
+        // Mappings are page aligned; MCJIT never asks for more
+        debug_assert!((alignment as usize) <= 4096);
+        match self.memory.map_section(size.max(1), executable) {
+            Ok((address, size)) => {
+                self.loading.lock().push(Section { address, size, executable });
+                address
+            }
+            Err(_) => std::ptr::null_mut(),
+        }
+    }
+
+    /// Make the loaded code executable; MCJIT calls this once it has applied
+    /// the relocations
+    unsafe fn finalize(&self) -> Result<(), String> {
+        for section in self.loading.lock().iter().filter(|section| section.executable) {
+            self.memory
+                .make_executable(section.address)
+                .map_err(|e| format!("{:?}", e))?;
+            flush_instruction_cache(section.address, section.size);
+        }
+        Ok(())
+    }
+}
+
+extern "C" fn allocate_code_section(
+    opaque: *mut c_void,
+    size: usize,
+    alignment: c_uint,
+    _section_id: c_uint,
+    _section_name: *const c_char,
+) -> *mut u8 {
+    unsafe { (*(opaque as *const SectionAllocator)).allocate(size, alignment, true) }
+}
+
+extern "C" fn allocate_data_section(
+    opaque: *mut c_void,
+    size: usize,
+    alignment: c_uint,
+    _section_id: c_uint,
+    _section_name: *const c_char,
+    _read_only: LLVMBool,
+) -> *mut u8 {
+    unsafe { (*(opaque as *const SectionAllocator)).allocate(size, alignment, false) }
+}
+
+extern "C" fn finalize_memory(opaque: *mut c_void, error: *mut *mut c_char) -> LLVMBool {
+    match unsafe { (*(opaque as *const SectionAllocator)).finalize() } {
+        Ok(()) => 0,
+        Err(message) => {
+            // LLVM copies the message and free()s ours
+            let message = CString::new(message).unwrap_or_default();
+            unsafe { *error = libc::strdup(message.as_ptr()) };
+            1
+        }
+    }
+}
+
+extern "C" fn destroy(opaque: *mut c_void) {
+    unsafe { drop(Arc::from_raw(opaque as *const SectionAllocator)) }
+}
@@ -0,0 +1,496 @@
+// src/jit/stackmap.rs
+//! Stack maps for embedders with precise garbage collectors
+//! C code marks pointers into an embedder-managed heap by putting them in
+//! address space 1 (`__attribute__((address_space(1)))`). Every function
+//! that touches such a pointer gets LLVM's `statepoint-example` GC strategy
+//! and frame pointers, and `rewrite-statepoints-for-gc` turns each of its
+//! calls into a safepoint: the live managed pointers are spilled to stack
+//! slots across the call and reloaded afterwards, so a moving collector
+//! may update them. LLVM describes the slots in the `.llvm_stackmaps`
+//! section (format version 3), which `StackMaps` parses.
+//!
+//! A collection can only happen while C has called back into Rust. Closure
+//! host functions record where the C stack stops on entry (`ExitFrame`);
+//! `for_each_root` starts there and follows the frame-pointer chain through
+//! every C frame with a safepoint at its return address, then continues
+//! from the next host call further out. A C frame without managed
+//! pointers, or a native frame (libc's `qsort` calling back into C), ends
+//! the walk of that segment: managed pointers held below it aren't found.
+//!
+//! Each root is a (base, derived) pair: `derived` may point into the
+//! middle of the object `base` points to, and must move with it. Within a
+//! function LLVM tracks which object an interior pointer came from, but
+//! across calls it can't: a managed pointer passed as an argument or
+//! returned must point to the start of its object.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+#[cfg(feature = "llvm")]
+use std::ffi::CStr;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::target_machine::LLVMTargetMachineRef;
+#[cfg(feature = "llvm")]
+use llvm_sys::transforms::pass_builder::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMTypeKind};
+use crate::arch::frame::FrameRecord;
+use crate::runtime::signals;
+
+/// Address space of pointers into the embedder's heap
+pub const MANAGED_ADDRESS_SPACE: u32 = 1;
+/// GC strategy LLVM lowers to statepoints with stack maps
+#[cfg(feature = "llvm")]
+const GC_STRATEGY: &CStr = c"statepoint-example";
+/// The only stack map format LLVM has emitted since LLVM 4
+const STACK_MAP_VERSION: u8 = 3;
+/// A statepoint's first locations: calling convention, flags and the
+/// number of deoptimization arguments, which follow them
+const STATEPOINT_HEADER: usize = 3;
+
+/// DWARF numbers of the registers locations are relative to
+#[cfg(target_arch = "x86_64")]
+const DWARF_SP: u16 = 7;
+#[cfg(target_arch = "x86_64")]
+const DWARF_FP: u16 = 6;
+#[cfg(target_arch = "aarch64")]
+const DWARF_SP: u16 = 31;
+#[cfg(target_arch = "aarch64")]
+const DWARF_FP: u16 = 29;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const DWARF_SP: u16 = u16::MAX;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const DWARF_FP: u16 = u16::MAX - 1;
+
+/// Mark the functions of `module` that use managed pointers for the
+/// statepoint GC and insert their safepoints. Run after optimization, which
+/// can't see through statepoints. Returns the number of functions marked.
+#[cfg(feature = "llvm")]
+pub unsafe fn rewrite_statepoints(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) -> Result<usize, StackMapError> {
+    let context = LLVMGetModuleContext(module);
+    let mut marked = 0;
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 && uses_managed_pointers(function) {
+            LLVMSetGC(function, GC_STRATEGY.as_ptr());
+            keep_frame_pointer(context, function);
+            marked += 1;
+        }
+        function = LLVMGetNextFunction(function);
+    }
+    if marked == 0 {
+        return Ok(0);
+    }
+
+    let options = LLVMCreatePassBuilderOptions();
+    let error = LLVMRunPasses(module, c"rewrite-statepoints-for-gc".as_ptr(), target_machine, options);
+    LLVMDisposePassBuilderOptions(options);
+    if !error.is_null() {
+        let message = llvm_sys::error::LLVMGetErrorMessage(error);
+        let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+        llvm_sys::error::LLVMDisposeErrorMessage(message);
+        return Err(StackMapError::Rewrite(text));
+    }
+    Ok(marked)
+}
+
+/// The walk follows the frame-pointer chain, so every frame on it needs one
+#[cfg(feature = "llvm")]
+pub(crate) unsafe fn keep_frame_pointer(context: LLVMContextRef, function: LLVMValueRef) {
+    let (key, value) = ("frame-pointer", "all");
+    let attribute = LLVMCreateStringAttribute(
+        context,
+        key.as_ptr() as *const _,
+        key.len() as u32,
+        value.as_ptr() as *const _,
+        value.len() as u32,
+    );
+    LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute);
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn uses_managed_pointers(function: LLVMValueRef) -> bool {
+    let is_managed = |ty: LLVMTypeRef| {
+        LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMPointerTypeKind && LLVMGetPointerAddressSpace(ty) == MANAGED_ADDRESS_SPACE
+    };
+    let function_type = LLVMGlobalGetValueType(function);
+    if is_managed(LLVMGetReturnType(function_type)) {
+        return true;
+    }
+    for index in 0..LLVMCountParams(function) {
+        if is_managed(LLVMTypeOf(LLVMGetParam(function, index))) {
+            return true;
+        }
+    }
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        let mut instruction = LLVMGetFirstInstruction(block);
+        while !instruction.is_null() {
+            if is_managed(LLVMTypeOf(instruction)) {
+                return true;
+            }
+            for operand in 0..LLVMGetNumOperands(instruction).max(0) as u32 {
+                if is_managed(LLVMTypeOf(LLVMGetOperand(instruction, operand))) {
+                    return true;
+                }
+            }
+            instruction = LLVMGetNextInstruction(instruction);
+        }
+        block = LLVMGetNextBasicBlock(block);
+    }
+    false
+}
+
+/// Where a stack map says a value is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// In a register, by DWARF number
+    Register(u16),
+    /// The address `register + offset` itself, for stack allocations
+    Direct { register: u16, offset: i32 },
+    /// In memory at `register + offset`
+    Indirect { register: u16, offset: i32 },
+    Constant(i64),
+}
+
+/// A managed pointer's slots at one safepoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootLocation {
+    pub base: Location,
+    pub derived: Location,
+}
+
+/// The live managed pointers of a call site, keyed by its return address
+#[derive(Debug, Clone)]
+pub struct Safepoint {
+    /// Address of the function containing the call
+    pub function: u64,
+    /// The function's fixed frame size; `u64::MAX` with dynamic allocas
+    pub frame_size: u64,
+    pub roots: Vec<RootLocation>,
+}
+
+/// Parsed `.llvm_stackmaps` sections of every unit compiled so far
+#[derive(Debug, Default)]
+pub struct StackMaps {
+    // Safepoints by return address
+    safepoints: HashMap<u64, Safepoint>,
+
+    // Statistics
+    functions: usize,
+    roots: usize,
+}
+
+impl StackMaps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse one `.llvm_stackmaps` section as loaded in memory, with its
+    /// function addresses relocated
+    pub fn parse(section: &[u8]) -> Result<Self, StackMapError> {
+        let mut reader = Reader { bytes: section, position: 0 };
+        let version = reader.u8()?;
+        if version != STACK_MAP_VERSION {
+            return Err(StackMapError::UnsupportedVersion(version));
+        }
+        reader.skip(3)?;
+        let function_count = reader.u32()? as usize;
+        let constant_count = reader.u32()? as usize;
+        let record_count = reader.u32()? as usize;
+
+        let mut functions = Vec::with_capacity(function_count);
+        for _ in 0..function_count {
+            let address = reader.u64()?;
+            let frame_size = reader.u64()?;
+            let records = reader.u64()?;
+            functions.push((address, frame_size, records));
+        }
+        let mut constants = Vec::with_capacity(constant_count);
+        for _ in 0..constant_count {
+            constants.push(reader.u64()? as i64);
+        }
+
+        let mut maps = StackMaps::new();
+        maps.functions = function_count;
+        let mut records = functions
+            .iter()
+            .flat_map(|&(address, frame_size, records)| (0..records).map(move |_| (address, frame_size)));
+        for _ in 0..record_count {
+            let (function, frame_size) = records.next().ok_or(StackMapError::Truncated)?;
+            let _id = reader.u64()?;
+            let offset = reader.u32()?;
+            reader.skip(2)?;
+            let location_count = reader.u16()? as usize;
+            let mut locations = Vec::with_capacity(location_count);
+            for _ in 0..location_count {
+                locations.push(reader.location(&constants)?);
+            }
+            reader.align(8)?;
+            reader.skip(2)?;
+            let live_outs = reader.u16()? as usize;
+            reader.skip(live_outs * 4)?;
+            reader.align(8)?;
+
+            // Skip the header constants and the deoptimization arguments
+            let deopt = match locations.get(STATEPOINT_HEADER - 1) {
+                Some(Location::Constant(count)) => *count as usize,
+                _ => return Err(StackMapError::NotAStatepoint(function + offset as u64)),
+            };
+            let pairs = locations.get(STATEPOINT_HEADER + deopt..).unwrap_or_default();
+            let roots: Vec<RootLocation> = pairs
+                .chunks_exact(2)
+                .map(|pair| RootLocation { base: pair[0], derived: pair[1] })
+                .collect();
+            maps.roots += roots.len();
+            maps.safepoints.insert(function + offset as u64, Safepoint { function, frame_size, roots });
+        }
+        Ok(maps)
+    }
+
+    /// Add the safepoints of another unit
+    pub fn extend(&mut self, other: StackMaps) {
+        self.functions += other.functions;
+        self.roots += other.roots;
+        self.safepoints.extend(other.safepoints);
+    }
+
+    pub fn safepoint(&self, return_address: u64) -> Option<&Safepoint> {
+        self.safepoints.get(&return_address)
+    }
+
+    /// (functions, safepoints, root pairs)
+    pub fn stats(&self) -> (usize, usize, usize) {
+        (self.functions, self.safepoints.len(), self.roots)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], StackMapError> {
+        let bytes = self.bytes.get(self.position..self.position + N).ok_or(StackMapError::Truncated)?;
+        self.position += N;
+        Ok(bytes.try_into().expect("slice of N bytes"))
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), StackMapError> {
+        if self.position + count > self.bytes.len() {
+            return Err(StackMapError::Truncated);
+        }
+        self.position += count;
+        Ok(())
+    }
+
+    fn align(&mut self, alignment: usize) -> Result<(), StackMapError> {
+        self.skip(self.position.next_multiple_of(alignment) - self.position)
+    }
+
+    fn u8(&mut self) -> Result<u8, StackMapError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StackMapError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, StackMapError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, StackMapError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn location(&mut self, constants: &[i64]) -> Result<Location, StackMapError> {
+        let kind = self.u8()?;
+        self.skip(3)?;
+        let register = self.u16()?;
+        self.skip(2)?;
+        let offset = i32::from_le_bytes(self.take()?);
+        match kind {
+            1 => Ok(Location::Register(register)),
+            2 => Ok(Location::Direct { register, offset }),
+            3 => Ok(Location::Indirect { register, offset }),
+            4 => Ok(Location::Constant(offset as i64)),
+            5 => constants.get(offset as usize).map(|&c| Location::Constant(c)).ok_or(StackMapError::Truncated),
+            kind => Err(StackMapError::UnknownLocation(kind)),
+        }
+    }
+}
+
+/// Where C stopped and Rust took over: the frame pointer of a host
+/// function's trampoline and the stack pointer of the C function calling it
+#[derive(Debug, Clone, Copy)]
+pub struct ExitFrame {
+    pub frame_pointer: usize,
+    pub stack_pointer: usize,
+}
+
+thread_local! {
+    // Host calls in progress on this thread, innermost last
+    static EXIT_FRAMES: RefCell<Vec<ExitFrame>> = const { RefCell::new(Vec::new()) };
+}
+
+impl ExitFrame {
+    /// Record a host call for the lifetime of the returned guard
+    pub fn enter(self) -> ExitGuard {
+        EXIT_FRAMES.with(|frames| frames.borrow_mut().push(self));
+        ExitGuard(())
+    }
+}
+
+pub struct ExitGuard(());
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        EXIT_FRAMES.with(|frames| frames.borrow_mut().pop());
+    }
+}
+
+/// A managed pointer live in a C frame. `base` and `derived` are the
+/// values when the walk started; `relocate` rewrites both slots.
+#[derive(Debug)]
+pub struct GcRoot {
+    base_slot: *mut usize,
+    derived_slot: *mut usize,
+    base: usize,
+    derived: usize,
+}
+
+impl GcRoot {
+    /// Start of the object, as a collector sees it
+    pub fn base(&self) -> *mut u8 {
+        self.base as *mut u8
+    }
+
+    pub fn derived(&self) -> *mut u8 {
+        self.derived as *mut u8
+    }
+
+    /// The object moved to `new_base`: point both slots at the new copy.
+    /// Relocating the same old object twice is harmless.
+    ///
+    /// # Safety
+    /// The C frames the root came from must not have resumed.
+    pub unsafe fn relocate(&self, new_base: *mut u8) {
+        let new_base = new_base as usize;
+        *self.base_slot = new_base;
+        *self.derived_slot = new_base.wrapping_add(self.derived.wrapping_sub(self.base));
+    }
+}
+
+/// Call `visitor` once for each managed pointer held by C frames on this
+/// thread, innermost first, and return how many there were. Only valid
+/// inside a host function called from C.
+///
+/// # Safety
+/// Every frame on the stack between the recorded host calls must still be
+/// live, which holds as long as this is called from the host function.
+pub unsafe fn for_each_root(maps: &StackMaps, mut visitor: impl FnMut(&GcRoot)) -> Result<usize, StackMapError> {
+    let exits = EXIT_FRAMES.with(|frames| frames.borrow().clone());
+    if exits.is_empty() {
+        return Err(StackMapError::NotInHostCall);
+    }
+    let record = FrameRecord::host().ok_or(StackMapError::UnsupportedHost)?;
+    // Nor may a compiled signal handler move pointers during the walk
+    let _signals = signals::critical();
+
+    // Read every slot before the visitor can rewrite any of them
+    let mut roots: Vec<GcRoot> = Vec::new();
+    for exit in exits.iter().rev() {
+        let (mut frame_pointer, mut stack_pointer) = (exit.frame_pointer, exit.stack_pointer);
+        loop {
+            let return_address = *((frame_pointer + record.return_address) as *const u64);
+            let Some(safepoint) = maps.safepoint(return_address) else { break };
+            // The frame pointer of the function the safepoint is in
+            let caller_frame = *((frame_pointer + record.caller_frame) as *const usize);
+            let slot = |location: Location| -> Result<Option<*mut usize>, StackMapError> {
+                match location {
+                    Location::Indirect { register, offset } => {
+                        let base = match register {
+                            DWARF_SP => stack_pointer,
+                            DWARF_FP => caller_frame,
+                            register => return Err(StackMapError::UnsupportedLocation(Location::Register(register))),
+                        };
+                        Ok(Some(base.wrapping_add_signed(offset as isize) as *mut usize))
+                    }
+                    // Null or a constant: nothing to relocate
+                    Location::Constant(_) => Ok(None),
+                    location => Err(StackMapError::UnsupportedLocation(location)),
+                }
+            };
+            for root in &safepoint.roots {
+                if let (Some(base_slot), Some(derived_slot)) = (slot(root.base)?, slot(root.derived)?) {
+                    roots.push(GcRoot { base_slot, derived_slot, base: *base_slot, derived: *derived_slot });
+                }
+            }
+
+            // Up one frame: the caller's CFA where the frame record is at
+            // the top of the frame; on AArch64 it isn't, so use the frame size
+            stack_pointer = if let Some(cfa) = record.cfa {
+                caller_frame + cfa
+            } else if safepoint.frame_size != u64::MAX {
+                stack_pointer + safepoint.frame_size as usize
+            } else {
+                break;
+            };
+            frame_pointer = caller_frame;
+        }
+    }
+
+    for root in &roots {
+        visitor(root);
+    }
+    Ok(roots.len())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackMapError {
+    /// `rewrite-statepoints-for-gc` failed
+    Rewrite(String),
+    UnsupportedVersion(u8),
+    Truncated,
+    UnknownLocation(u8),
+    /// A record at this return address isn't a statepoint
+    NotAStatepoint(u64),
+    /// A root in a register or stack allocation; statepoints spill roots
+    /// to the stack, so this means a record from some other source
+    UnsupportedLocation(Location),
+    /// `for_each_root` outside a host function
+    NotInHostCall,
+    /// No frame record layout for the machine this runs on
+    UnsupportedHost,
+}
+
+// Example usage:
+/*
+fn embed(heap: Arc<Heap>) -> Result<(), EngineError> {
+    let mut engine = Engine::new()?;
+    let maps = engine.stack_maps();
+    engine.register_host_function("collect", HostSignature::new(vec![], JITType::Void), HostFunction::Closure(Box::new(move |_| {
+        let found = unsafe {
+            for_each_root(&maps.read(), |root| {
+                let copy = heap.evacuate(root.base());
+                unsafe { root.relocate(copy) };
+            })
+        };
+        println!("{:?} roots", found);
+        JITValue::Void
+    })))?;
+
+    engine.eval_string(r#"
+        struct node { long value; };
+        typedef struct node __attribute__((address_space(1))) *node_ref;
+        void collect(void);
+        long sum(node_ref a, node_ref b) { collect(); return a->value + b->value; }
+    "#)?;
+    Ok(())
+}
+*/
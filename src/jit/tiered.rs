@@ -0,0 +1,417 @@
+// src/jit/tiered.rs
+//! Tiered execution
+//! Every function starts in the interpreter (tier 0). Calls and loop
+//! back-edges are counted with the PGO counters; once a function is hot it is
+//! queued for a background thread that compiles it at -O1 (baseline) and,
+//! hotter still, at -O3 (optimized). `jit::tiers` has the tiers `--tiered`
+//! runs with: the bytecode VM, and LLVM compiling the same source.
+//!
+//! Each function has a dispatch slot that holds its current entry point.
+//! `call`, and tier 0 through `enter` for the calls it makes itself, load
+//! the slot on every call, so installing a new tier is a single atomic
+//! store. Compiled code calls its callees' compiled copies directly.
+//! Arguments and results keep their C types: compiled code is called
+//! through `abi::aggregate` with the signature the function was registered
+//! with.
+//!
+//! Compiled code lives in a `memory::code_cache::CodeCache` with a size
+//! limit. Code from earlier tiers, and the code of functions evicted as
+//! least recently used, stays mapped while any thread is running compiled
+//! code, then is freed. An evicted function deoptimizes: its slot points
+//! back at the interpreter and its counters start over, so it is compiled
+//! again once it is hot again.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use parking_lot::RwLock;
+use crate::abi::aggregate::{marshal, Abi, Argument, CType, Scalar};
+use crate::abi::call;
+use crate::memory::code_cache::{CodeCache, CodeCacheConfig, CodeCacheStats, ExecutionGuard};
+use crate::pgo::{Counter, CounterType};
+use super::{JITError, JITType, JITValue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Tier {
+    Interpreted = 0,
+    Baseline = 1,
+    Optimized = 2,
+}
+
+impl Tier {
+    fn from_u8(value: u8) -> Tier {
+        match value {
+            0 => Tier::Interpreted,
+            1 => Tier::Baseline,
+            _ => Tier::Optimized,
+        }
+    }
+
+    /// LLVM optimization level used to compile this tier
+    pub fn opt_level(self) -> u32 {
+        match self {
+            Tier::Interpreted => 0,
+            Tier::Baseline => 1,
+            Tier::Optimized => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TierThresholds {
+    /// Hotness at which a function is compiled at -O1
+    pub baseline: u64,
+    /// Hotness at which a function is recompiled at -O3
+    pub optimized: u64,
+    /// Loop back-edges that count as one call
+    pub backedge_weight: u64,
+}
+
+impl Default for TierThresholds {
+    fn default() -> Self {
+        TierThresholds {
+            baseline: 100,
+            optimized: 10_000,
+            backedge_weight: 16,
+        }
+    }
+}
+
+/// Machine code for one function at one tier
+pub struct CompiledCode {
+    pub entry: *const u8,
+    pub size: usize,
+    /// Whatever keeps the code mapped, e.g. the execution engine that owns it
+    pub owner: Box<dyn Any + Send>,
+}
+
+unsafe impl Send for CompiledCode {}
+
+/// What the background compiler is asked to build
+pub struct TierRequest<'a> {
+    pub name: &'a str,
+    pub tier: Tier,
+    /// Baseline code should bump these so it can be promoted again
+    pub call_counter: *const AtomicU64,
+    pub backedge_counter: *const AtomicU64,
+}
+
+/// Compiles single functions; owned by the background thread, so LLVM state
+/// never crosses threads
+pub trait TierCompiler: Send {
+    fn compile(&mut self, request: &TierRequest) -> Result<CompiledCode, JITError>;
+}
+
+/// Tier 0. Should call `record_backedges` on the function as loops iterate.
+pub trait TierZero: Send + Sync {
+    fn call(&self, function: &TieredFunction, args: &[JITValue]) -> Result<JITValue, JITError>;
+}
+
+pub struct TieredFunction {
+    // Identity
+    name: String,
+    parameters: Vec<JITType>,
+    result: JITType,
+
+    // Current tier and its entry point; null while interpreted
+    tier: AtomicU8,
+    entry: AtomicPtr<u8>,
+
+    // Profile
+    calls: Counter,
+    backedges: Counter,
+
+    // Set while a tier-up is queued, and left set if it failed
+    pending: AtomicBool,
+}
+
+impl TieredFunction {
+    fn new(name: &str, parameters: &[JITType], result: JITType) -> Self {
+        TieredFunction {
+            name: name.to_string(),
+            parameters: parameters.to_vec(),
+            result,
+            tier: AtomicU8::new(Tier::Interpreted as u8),
+            entry: AtomicPtr::new(std::ptr::null_mut()),
+            calls: Counter::new(CounterType::Function),
+            backedges: Counter::new(CounterType::Loop),
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parameters(&self) -> &[JITType] {
+        &self.parameters
+    }
+
+    pub fn result(&self) -> &JITType {
+        &self.result
+    }
+
+    pub fn tier(&self) -> Tier {
+        Tier::from_u8(self.tier.load(Ordering::Acquire))
+    }
+
+    pub fn calls(&self) -> u64 {
+        self.calls.get()
+    }
+
+    pub fn backedges(&self) -> u64 {
+        self.backedges.get()
+    }
+
+    pub fn record_backedges(&self, count: u64) {
+        self.backedges.add(count);
+    }
+
+    fn hotness(&self, backedge_weight: u64) -> u64 {
+        self.calls.get() + self.backedges.get() / backedge_weight.max(1)
+    }
+
+    fn install(self: &Arc<Self>, tier: Tier, code: CompiledCode, cache: &CodeCache<Arc<TieredFunction>>) {
+        let (entry, size) = (code.entry as *mut u8, code.size);
+        // Switch the slot before the cache retires the previous tier's code,
+        // so that whoever enters after the retirement can't reach it
+        self.entry.store(entry, Ordering::SeqCst);
+        self.tier.store(tier as u8, Ordering::Release);
+        cache.insert(&self.name, self.clone(), size, Box::new(code));
+    }
+
+    /// Back to the interpreter, starting the profile over
+    fn deoptimize(&self) {
+        self.entry.store(std::ptr::null_mut(), Ordering::SeqCst);
+        self.tier.store(Tier::Interpreted as u8, Ordering::Release);
+        self.calls.reset();
+        self.backedges.reset();
+        self.pending.store(false, Ordering::Release);
+    }
+}
+
+pub struct TieredEngine {
+    // Functions by name
+    functions: RwLock<HashMap<String, Arc<TieredFunction>>>,
+
+    // Policy
+    thresholds: TierThresholds,
+
+    // Tier 0
+    interpreter: Arc<dyn TierZero>,
+
+    // Compiled code of every function
+    code_cache: Arc<CodeCache<Arc<TieredFunction>>>,
+
+    // Background compilation
+    requests: Option<Sender<(Arc<TieredFunction>, Tier)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl TieredEngine {
+    pub fn new(
+        interpreter: Arc<dyn TierZero>,
+        compiler: Box<dyn TierCompiler>,
+        thresholds: TierThresholds,
+    ) -> Result<Self, JITError> {
+        Self::with_code_cache(interpreter, compiler, thresholds, CodeCacheConfig::default())
+    }
+
+    /// An engine keeping compiled code within `code_cache`'s limits
+    pub fn with_code_cache(
+        interpreter: Arc<dyn TierZero>,
+        mut compiler: Box<dyn TierCompiler>,
+        thresholds: TierThresholds,
+        code_cache: CodeCacheConfig,
+    ) -> Result<Self, JITError> {
+        let code_cache = Arc::new(CodeCache::new(code_cache).on_evict(|name, function: &Arc<TieredFunction>| {
+            log::debug!("{} evicted from the code cache, back to the interpreter", name);
+            function.deoptimize();
+        }));
+        let cache = code_cache.clone();
+        let (sender, receiver) = mpsc::channel::<(Arc<TieredFunction>, Tier)>();
+
+        let worker = std::thread::Builder::new()
+            .name("jit-tier-up".to_string())
+            .spawn(move || {
+                for (function, tier) in receiver {
+                    let request = TierRequest {
+                        name: &function.name,
+                        tier,
+                        call_counter: function.calls.as_ptr(),
+                        backedge_counter: function.backedges.as_ptr(),
+                    };
+                    match compiler.compile(&request) {
+                        Ok(code) => {
+                            log::debug!("{} promoted to {:?} ({} bytes)", function.name, tier, code.size);
+                            function.install(tier, code, &cache);
+                            function.pending.store(false, Ordering::Release);
+                        }
+                        Err(e) => {
+                            // Stay on the current tier; `pending` stays set so we don't retry
+                            log::warn!("tier-up of {} to {:?} failed: {:?}", function.name, tier, e);
+                        }
+                    }
+                }
+            })
+            .map_err(|e| JITError::EngineCreation(format!("tier-up thread: {}", e)))?;
+
+        Ok(TieredEngine {
+            functions: RwLock::new(HashMap::new()),
+            thresholds,
+            interpreter,
+            code_cache,
+            requests: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// Make a function with the C prototype `result name(parameters)`
+    /// callable; it starts interpreted
+    pub fn register(&self, name: &str, parameters: &[JITType], result: JITType) -> Arc<TieredFunction> {
+        self.functions
+            .write()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(TieredFunction::new(name, parameters, result)))
+            .clone()
+    }
+
+    pub fn function(&self, name: &str) -> Option<Arc<TieredFunction>> {
+        self.functions.read().get(name).cloned()
+    }
+
+    /// Call through the dispatch slot: compiled code if there is any, else the interpreter
+    pub fn call(&self, name: &str, args: &[JITValue]) -> Result<JITValue, JITError> {
+        let function = self
+            .function(name)
+            .ok_or_else(|| JITError::Execution(format!("unknown function '{}'", name)))?;
+        if args.len() != function.parameters.len() {
+            return Err(JITError::ArgumentMismatch);
+        }
+        if !function.parameters.iter().zip(args).all(|(ty, arg)| ty.accepts(arg)) {
+            return Err(JITError::TypeMismatch);
+        }
+
+        match self.enter(&function) {
+            // SAFETY: the arguments match the signature the code was compiled for
+            Some((_running, entry)) => unsafe { invoke(entry, &function, args) },
+            None => self.interpreter.call(&function, args),
+        }
+    }
+
+    /// Count a call to `function` and return its compiled code, if any,
+    /// with the guard that keeps the code mapped until the call returns.
+    /// Tier 0 calls this for the calls it makes itself.
+    pub fn enter(&self, function: &Arc<TieredFunction>) -> Option<(ExecutionGuard<'_, Arc<TieredFunction>>, *const u8)> {
+        function.calls.increment();
+        self.maybe_tier_up(function);
+
+        // Entered before loading the slot, so the code can't be freed under the call
+        let running = self.code_cache.enter();
+        let entry = function.entry.load(Ordering::SeqCst);
+        (!entry.is_null()).then_some((running, entry as *const u8))
+    }
+
+    fn maybe_tier_up(&self, function: &Arc<TieredFunction>) {
+        let hotness = function.hotness(self.thresholds.backedge_weight);
+        let target = if hotness >= self.thresholds.optimized {
+            Tier::Optimized
+        } else if hotness >= self.thresholds.baseline {
+            Tier::Baseline
+        } else {
+            return;
+        };
+
+        if target <= function.tier() {
+            return;
+        }
+        if function
+            .pending
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        if let Some(requests) = &self.requests {
+            let _ = requests.send((function.clone(), target));
+        }
+    }
+
+    /// (name, tier, calls, back-edges) for every function, sorted by name
+    pub fn stats(&self) -> Vec<(String, Tier, u64, u64)> {
+        let mut stats: Vec<_> = self
+            .functions
+            .read()
+            .values()
+            .map(|f| (f.name.clone(), f.tier(), f.calls(), f.backedges()))
+            .collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
+    /// Size, hits, misses and evictions of the code cache
+    pub fn code_cache_stats(&self) -> CodeCacheStats {
+        self.code_cache.stats()
+    }
+}
+
+impl Drop for TieredEngine {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker finish its queue and exit
+        self.requests.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Call compiled code the way the C ABI passes `function`'s arguments
+unsafe fn invoke(entry: *const u8, function: &TieredFunction, args: &[JITValue]) -> Result<JITValue, JITError> {
+    let abi = Abi::host().ok_or_else(|| JITError::Execution(format!("no C ABI for {}", std::env::consts::ARCH)))?;
+    let arguments: Vec<Argument> = args.iter().map(|&arg| Argument::Value(arg)).collect();
+    let result = scalar(&function.result).map(CType::Scalar);
+    let call = marshal(abi, &arguments, result.as_ref()).map_err(|e| JITError::Execution(format!("{:?}", e)))?;
+    let bytes = call::invoke(entry, &call).map_err(|e| JITError::Execution(format!("{:?}", e)))?;
+    // Registers come back whole; the low bytes are the result
+    let mut word = [0u8; 8];
+    let size = bytes.len().min(word.len());
+    word[..size].copy_from_slice(&bytes[..size]);
+    Ok(JITValue::from_raw(u64::from_le_bytes(word), &function.result))
+}
+
+fn scalar(ty: &JITType) -> Option<Scalar> {
+    match ty {
+        JITType::Void => None,
+        JITType::Int8 => Some(Scalar::I8),
+        JITType::Int16 => Some(Scalar::I16),
+        JITType::Int32 => Some(Scalar::I32),
+        JITType::Int64 => Some(Scalar::I64),
+        JITType::Float => Some(Scalar::F32),
+        JITType::Double => Some(Scalar::F64),
+        JITType::Pointer(_) => Some(Scalar::Pointer),
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), JITError> {
+    let interpreter = Arc::new(BytecodeTier::new(&program, runtime)?);
+    let compiler = Box::new(LlvmTier::new(source, jit_options, &program));
+    let engine = Arc::new(TieredEngine::new(interpreter.clone(), compiler, TierThresholds::default())?);
+    interpreter.attach(&engine);
+
+    engine.register("fib", &[JITType::Int32], JITType::Int32);
+    for _ in 0..20_000 {
+        engine.call("fib", &[JITValue::Int32(20)])?;
+    }
+
+    // [("fib", Optimized, 20000, 0)] once the background compile has landed
+    println!("{:?}", engine.stats());
+    println!("{:?}", engine.code_cache_stats());
+    Ok(())
+}
+*/
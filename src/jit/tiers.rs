@@ -0,0 +1,212 @@
+// src/jit/tiers.rs
+//! The tiers `--tiered` runs with
+//! `BytecodeTier` is tier 0: the bytecode VM, attached to the engine so
+//! that the calls and back-edges it runs are counted and its calls to
+//! compiled functions are native calls. `LlvmTier` compiles the program's
+//! source with LLVM at the tier's optimization level, once per tier, and
+//! hands out the addresses of the functions in that copy.
+//!
+//! The compiled copy has globals of its own, so only functions that reach
+//! no global state can run there without the two copies drifting apart;
+//! `tiered_functions` picks them. Functions that take the address of a
+//! global or a function, call through a pointer, call a function the
+//! program only declares, fire a probe, run inline assembly or declare a
+//! VLA (whose storage is the runtime's) stay interpreted, as do the ones
+//! calling them. So does signed arithmetic unless it wraps (`-fwrapv`):
+//! reporting overflow needs the runtime, as in the native tier.
+
+use std::sync::Arc;
+use parking_lot::Mutex;
+use crate::interpreter::bytecode::{Instruction, Kind, Program, Vm};
+use crate::interpreter::c_runtime::{CRuntimeEnvironment, RuntimeError};
+use super::tiered::{TierZero, TieredEngine, TieredFunction};
+use super::{JITError, JITType, JITValue};
+#[cfg(feature = "llvm")]
+use std::collections::HashMap;
+#[cfg(feature = "llvm")]
+use crate::compiler::{CompilerSystem, JITOptions};
+#[cfg(feature = "llvm")]
+use super::tiered::{CompiledCode, Tier, TierCompiler, TierRequest};
+
+/// Tier 0: runs functions on one bytecode VM
+pub struct BytecodeTier {
+    vm: Mutex<Vm<'static, 'static>>,
+}
+
+// SAFETY: the VM is only reached through the mutex, and its raw pointers
+// are into memory it owns
+unsafe impl Send for BytecodeTier {}
+unsafe impl Sync for BytecodeTier {}
+
+impl BytecodeTier {
+    /// Run `program` against `runtime`. An `Arc<dyn TierZero>` may be
+    /// called for as long as the process runs, so both are `'static`:
+    /// leaked, as `--tiered` does for its one run.
+    pub fn new(program: &'static Program, runtime: &'static mut CRuntimeEnvironment) -> Result<Self, RuntimeError> {
+        Ok(BytecodeTier { vm: Mutex::new(Vm::new(program, runtime)?) })
+    }
+
+    /// Count calls for `engine` and run the code it compiles; see `Vm::attach_tiers`
+    pub fn attach(&self, engine: &Arc<TieredEngine>) {
+        self.vm.lock().attach_tiers(engine);
+    }
+
+    /// `Vm::run_main`
+    pub fn run_main(&self, args: &[String]) -> Result<i32, RuntimeError> {
+        self.vm.lock().run_main(args)
+    }
+}
+
+impl TierZero for BytecodeTier {
+    fn call(&self, function: &TieredFunction, args: &[JITValue]) -> Result<JITValue, JITError> {
+        self.vm.lock().call(function.name(), args).map_err(|e| JITError::Execution(format!("{:?}", e)))
+    }
+}
+
+/// The functions of `program` that can be compiled separately, as
+/// described above, for a VM whose signed overflow `wrap`s
+pub fn tiered_functions(program: &Program, wrap: bool) -> Vec<u32> {
+    let mut eligible: Vec<bool> = program
+        .functions
+        .iter()
+        .map(|function| {
+            function.signature.is_some()
+                && function.code.iter().all(|instruction| match instruction {
+                    Instruction::GlobalAddress { .. }
+                    | Instruction::FunctionAddress { .. }
+                    | Instruction::ExternalAddress { .. }
+                    | Instruction::CallIndirect { .. }
+                    | Instruction::CallExternal { .. }
+                    | Instruction::Probe { .. }
+                    | Instruction::Asm { .. }
+                    | Instruction::VlaMark { .. }
+                    | Instruction::VlaAllocate { .. }
+                    | Instruction::VlaRelease { .. }
+                    | Instruction::Unknown { .. } => false,
+                    Instruction::Signed { .. } | Instruction::SignedImm { .. } | Instruction::SignedMove { .. } => wrap,
+                    _ => true,
+                })
+        })
+        .collect();
+
+    // A call makes its caller ineligible when the callee is
+    loop {
+        let mut dropped = false;
+        for (index, function) in program.functions.iter().enumerate() {
+            let calls_ineligible = function.code.iter().any(|instruction| {
+                matches!(instruction, Instruction::Call { function: callee, .. } if !eligible[*callee as usize])
+            });
+            if eligible[index] && calls_ineligible {
+                eligible[index] = false;
+                dropped = true;
+            }
+        }
+        if !dropped {
+            break;
+        }
+    }
+    (0..program.functions.len() as u32).filter(|&index| eligible[index as usize]).collect()
+}
+
+/// The type `TieredEngine::register` takes for a VM value of `kind`;
+/// pointers are `U64` in the VM, so they are registered as `Int64`
+pub fn jit_type(kind: Option<Kind>) -> JITType {
+    match kind {
+        None => JITType::Void,
+        Some(Kind::I8 | Kind::U8) => JITType::Int8,
+        Some(Kind::I16 | Kind::U16) => JITType::Int16,
+        Some(Kind::I32 | Kind::U32) => JITType::Int32,
+        Some(Kind::I64 | Kind::U64) => JITType::Int64,
+        Some(Kind::F32) => JITType::Float,
+        Some(Kind::F64) => JITType::Double,
+    }
+}
+
+/// Compiles tiers with LLVM, from the source the bytecode came from
+#[cfg(feature = "llvm")]
+pub struct LlvmTier {
+    source: String,
+    options: JITOptions,
+
+    /// The copy compiled for each tier so far
+    modules: HashMap<Tier, Arc<Module>>,
+    /// What each function is charged in the code cache
+    sizes: HashMap<String, usize>,
+}
+
+/// One compiled copy of the program, freed with its last function
+#[cfg(feature = "llvm")]
+struct Module(CompilerSystem);
+
+// SAFETY: once compiled, a module is only looked up on the tier-up thread;
+// other threads just run its code and drop it
+#[cfg(feature = "llvm")]
+unsafe impl Send for Module {}
+#[cfg(feature = "llvm")]
+unsafe impl Sync for Module {}
+
+#[cfg(feature = "llvm")]
+impl LlvmTier {
+    /// Bytes of machine code per bytecode instruction, to charge the code
+    /// cache for functions that share one module
+    const BYTES_PER_INSTRUCTION: usize = 8;
+
+    /// Compile `source`, which `program` was compiled from, with `options`
+    /// at each tier's level
+    pub fn new(source: &str, options: JITOptions, program: &Program) -> Self {
+        let sizes = program
+            .functions
+            .iter()
+            .map(|function| (function.name.clone(), function.code.len().max(1) * Self::BYTES_PER_INSTRUCTION))
+            .collect();
+        LlvmTier { source: source.to_string(), options, modules: HashMap::new(), sizes }
+    }
+
+    fn module(&mut self, tier: Tier) -> Result<Arc<Module>, JITError> {
+        if let Some(module) = self.modules.get(&tier) {
+            return Ok(module.clone());
+        }
+        self.options.optimization_level = tier.opt_level();
+        let triple = crate::options::target_triple(std::env::consts::ARCH);
+        // SAFETY: the compiler is only used from this thread until it is shared
+        let compiler = unsafe { CompilerSystem::new(triple) }.map_err(|e| JITError::EngineCreation(format!("{:?}", e)))?;
+        unsafe { compiler.jit_compile(&self.source, &self.options) }.map_err(|e| JITError::Compilation(format!("{:?}", e)))?;
+        let module = Arc::new(Module(compiler));
+        self.modules.insert(tier, module.clone());
+        Ok(module)
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl TierCompiler for LlvmTier {
+    fn compile(&mut self, request: &TierRequest) -> Result<CompiledCode, JITError> {
+        let module = self.module(request.tier)?;
+        // SAFETY: the module is compiled
+        let entry = unsafe { module.0.jit_symbol_address(request.name) }
+            .ok_or_else(|| JITError::Compilation(format!("'{}' was inlined or removed at {:?}", request.name, request.tier)))?;
+        Ok(CompiledCode {
+            entry,
+            size: self.sizes.get(request.name).copied().unwrap_or(Self::BYTES_PER_INSTRUCTION),
+            owner: Box::new(module),
+        })
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let program: &'static Program = Box::leak(Box::new(bytecode::compile(&ast)?));
+    let runtime = Box::leak(Box::new(CRuntimeEnvironment::new()?));
+    let interpreter = Arc::new(BytecodeTier::new(program, runtime)?);
+    let compiler = Box::new(LlvmTier::new(source, options.jit_options(), program));
+    let engine = Arc::new(TieredEngine::new(interpreter.clone(), compiler, TierThresholds::default())?);
+    for index in tiered_functions(program, false) {
+        let function = &program.functions[index as usize];
+        let signature = function.signature.as_ref().expect("tiered functions have one");
+        let parameters: Vec<JITType> = signature.parameters.iter().map(|&kind| jit_type(Some(kind))).collect();
+        engine.register(&function.name, &parameters, jit_type(signature.result));
+    }
+    interpreter.attach(&engine);
+    std::process::exit(interpreter.run_main(&["prog".to_string()])?);
+}
+*/
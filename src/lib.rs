@@ -0,0 +1,135 @@
+// src/lib.rs
+//! Interpreter-C as a library
+//! `Engine` is the supported way to embed the interpreter: it JIT-compiles C
+//! source into the current process, runs `main`, calls functions and reads
+//! and writes globals, without spawning the `c-interpreter` binary. Its API
+//! follows semver.
+//!
+//! `syntax` exposes the C syntax tree, a visitor over it and a rewriter
+//! that edits source along it, for source-to-source tools; it follows
+//! semver too.
+//!
+//! `build_script` compiles C sources into a static library from a Rust
+//! crate's `build.rs`, as the `cc` crate does; it follows semver too.
+//!
+//! `ir` reads and writes the bytecode engine's programs and the ThinLTO
+//! summaries in a versioned binary format, for plugins and external tools;
+//! it follows semver, and readers take any file of their major version.
+//!
+//! `Engine` and `build_script` need the `llvm` feature, on by default.
+//! Without it the crate builds with no LLVM libraries installed, and the
+//! command-line driver runs programs on the bytecode engine instead.
+//!
+//! The remaining modules are public so the command-line driver can be
+//! built on top of this crate; they are implementation details and may
+//! change in any release.
+
+#[cfg(feature = "llvm")]
+pub mod build_script;
+#[cfg(feature = "llvm")]
+pub mod engine;
+pub mod ir;
+pub mod syntax;
+
+pub use abi::aggregate::{Argument, CType, Scalar};
+#[cfg(feature = "llvm")]
+pub use engine::{Engine, EngineError, EngineOptions, ReturnValue};
+pub use jit::host::{HostExport, HostFunction, HostSignature, HostType};
+pub use jit::stackmap::{for_each_root, GcRoot, StackMapError, StackMaps};
+pub use jit::{JITType as Type, JITValue as Value};
+pub use runtime::virtual_time::{VirtualTime, VirtualTimeError};
+
+/// `#[c_export]` and `c_exports![...]`, from the `interpreter_c_macros` crate
+#[cfg(feature = "macros")]
+pub use interpreter_c_macros::{c_export, c_exports};
+
+/// C interface to `Engine`, declared in `include/interpreter_c.h`
+#[cfg(feature = "capi")]
+pub mod capi;
+
+#[doc(hidden)]
+pub mod abi;
+#[doc(hidden)]
+pub mod analysis;
+#[doc(hidden)]
+pub mod arch;
+#[doc(hidden)]
+pub mod build;
+#[doc(hidden)]
+#[cfg(feature = "llvm")]
+pub mod compiler;
+#[doc(hidden)]
+pub mod cpu;
+#[doc(hidden)]
+pub mod debug;
+#[doc(hidden)]
+pub mod diagnostics;
+#[doc(hidden)]
+pub mod docs;
+#[doc(hidden)]
+pub mod driver;
+#[doc(hidden)]
+pub mod frontend;
+#[doc(hidden)]
+pub mod gui;
+#[doc(hidden)]
+pub mod ide;
+#[doc(hidden)]
+pub mod interpreter;
+#[doc(hidden)]
+pub mod jit;
+#[doc(hidden)]
+pub mod kernel;
+#[doc(hidden)]
+pub mod linker;
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
+pub mod lto;
+#[doc(hidden)]
+pub mod memory;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod monitoring;
+#[doc(hidden)]
+pub mod optimizer;
+#[doc(hidden)]
+pub mod options;
+#[doc(hidden)]
+pub mod orchestrator;
+#[doc(hidden)]
+pub mod pgo;
+#[doc(hidden)]
+pub mod pipeline;
+#[doc(hidden)]
+pub mod project;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod runtime;
+#[doc(hidden)]
+pub mod stdlib;
+#[doc(hidden)]
+pub mod syscall;
+#[doc(hidden)]
+pub mod testing;
+#[doc(hidden)]
+pub mod types;
+
+// Example usage:
+/*
+use interpreter_c::{Engine, Value};
+
+fn main() -> Result<(), interpreter_c::EngineError> {
+    let mut engine = Engine::new()?;
+    engine.eval_string("int scale = 3; int mul(int x) { return x * scale; }")?;
+
+    unsafe {
+        engine.set_global("scale", 7i32)?;
+        let product: i32 = engine.call_function("mul", &[Value::Int32(6)])?;
+        println!("{}", product); // 42
+    }
+    Ok(())
+}
+*/
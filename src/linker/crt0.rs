@@ -0,0 +1,365 @@
+// src/linker/crt0.rs
+//! Built-in startup objects for -nostdlib executables
+//! Without the system toolchain's crt1.o/crti.o nothing sets up the process
+//! before main. Each target gets a small hand-assembled `_start` that:
+//!  - clears the frame pointer so unwinders stop here
+//!  - reads argc/argv/envp from the initial stack laid out by the kernel
+//!  - aligns the stack to the ABI requirement
+//!  - runs every constructor in `.init_array` with (argc, argv, envp)
+//!  - calls main(argc, argv, envp) and passes its result to exit_group
+//!
+//! `__init_array_start`/`__init_array_end` come from the linker's default
+//! script, so no libc symbols are referenced.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use object::elf;
+use object::write::{Object, Relocation, StandardSection, Symbol, SymbolSection};
+use object::{
+    Architecture as ObjectArchitecture, BinaryFormat, Endianness, FileFlags, RelocationEncoding,
+    RelocationKind, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+};
+use crate::arch::Architecture;
+
+const INIT_ARRAY_START: &str = "__init_array_start";
+const INIT_ARRAY_END: &str = "__init_array_end";
+const MAIN: &str = "main";
+
+/// A relocation against one of the external symbols above
+struct Fixup {
+    offset: u64,
+    symbol: &'static str,
+    kind: RelocationKind,
+    size: u8,
+    addend: i64,
+}
+
+/// Minimal little-endian code buffer with label patching
+struct CodeBuffer {
+    code: Vec<u8>,
+    fixups: Vec<Fixup>,
+}
+
+impl CodeBuffer {
+    fn new() -> Self {
+        CodeBuffer { code: Vec::new(), fixups: Vec::new() }
+    }
+
+    fn here(&self) -> usize {
+        self.code.len()
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn word(&mut self, word: u32) {
+        self.code.extend_from_slice(&word.to_le_bytes());
+    }
+
+    fn read_word(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.code[at..at + 4].try_into().unwrap())
+    }
+
+    fn patch_word(&mut self, at: usize, word: u32) {
+        self.code[at..at + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    fn fixup(&mut self, at: usize, symbol: &'static str, kind: RelocationKind, size: u8, addend: i64) {
+        self.fixups.push(Fixup { offset: at as u64, symbol, kind, size, addend });
+    }
+}
+
+/// Startup code for one target
+pub struct Crt0 {
+    architecture: Architecture,
+    buffer: CodeBuffer,
+}
+
+impl Crt0 {
+    pub fn for_architecture(architecture: Architecture) -> Self {
+        let buffer = match architecture {
+            Architecture::X86_64 => x86_64_start(),
+            Architecture::AArch64 => aarch64_start(),
+            Architecture::Arm => arm_start(),
+        };
+        Crt0 { architecture, buffer }
+    }
+
+    /// Size of the `_start` routine (including any literal pool)
+    pub fn code_size(&self) -> usize {
+        self.buffer.code.len()
+    }
+
+    /// Relocatable ELF object defining `_start`
+    pub fn build_object(&self) -> Result<Vec<u8>, Crt0Error> {
+        let arch = match self.architecture {
+            Architecture::X86_64 => ObjectArchitecture::X86_64,
+            Architecture::AArch64 => ObjectArchitecture::Aarch64,
+            Architecture::Arm => ObjectArchitecture::Arm,
+        };
+        let mut object = Object::new(BinaryFormat::Elf, arch, Endianness::Little);
+
+        // ld refuses to mix EABI versions, and LLVM emits EABI5 objects
+        if self.architecture == Architecture::Arm {
+            object.flags = FileFlags::Elf {
+                os_abi: elf::ELFOSABI_NONE,
+                abi_version: 0,
+                e_flags: elf::EF_ARM_EABI_VER5,
+            };
+        }
+
+        let text = object.section_id(StandardSection::Text);
+        let offset = object.append_section_data(text, &self.buffer.code, 16);
+
+        object.add_symbol(Symbol {
+            name: b"_start".to_vec(),
+            value: offset,
+            size: self.buffer.code.len() as u64,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+
+        for fixup in &self.buffer.fixups {
+            let symbol = match object.symbol_id(fixup.symbol.as_bytes()) {
+                Some(id) => id,
+                None => object.add_symbol(Symbol {
+                    name: fixup.symbol.as_bytes().to_vec(),
+                    value: 0,
+                    size: 0,
+                    kind: if fixup.symbol == MAIN { SymbolKind::Text } else { SymbolKind::Unknown },
+                    scope: SymbolScope::Dynamic,
+                    weak: false,
+                    section: SymbolSection::Undefined,
+                    flags: SymbolFlags::None,
+                }),
+            };
+
+            object
+                .add_relocation(text, Relocation {
+                    offset: offset + fixup.offset,
+                    size: fixup.size,
+                    kind: fixup.kind,
+                    encoding: RelocationEncoding::Generic,
+                    symbol,
+                    addend: fixup.addend,
+                })
+                .map_err(|e| Crt0Error::ObjectWrite(e.to_string()))?;
+        }
+
+        // Don't request an executable stack
+        object.add_section(
+            Vec::new(),
+            b".note.GNU-stack".to_vec(),
+            SectionKind::Elf(elf::SHT_PROGBITS),
+        );
+
+        object.write().map_err(|e| Crt0Error::ObjectWrite(e.to_string()))
+    }
+
+    /// Write `crt0-<arch>.o` into `dir` and return its path
+    pub fn write_object(&self, dir: &Path) -> Result<PathBuf, Crt0Error> {
+        fs::create_dir_all(dir).map_err(Crt0Error::Io)?;
+        let path = dir.join(format!("crt0-{}.o", self.architecture));
+        fs::write(&path, self.build_object()?).map_err(Crt0Error::Io)?;
+        Ok(path)
+    }
+
+    /// Write the object to a file of its own in the temporary directory, so
+    /// concurrent builds never share one; removed when the result is dropped
+    pub fn write_temporary(&self) -> Result<TemporaryObject, Crt0Error> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "c-interpreter-crt0-{}-{}-{}.o",
+            self.architecture,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let object = self.build_object()?;
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&path).map_err(Crt0Error::Io)?;
+        let temporary = TemporaryObject { path };
+        file.write_all(&object).map_err(Crt0Error::Io)?;
+        Ok(temporary)
+    }
+}
+
+/// A startup object written by `write_temporary`, deleted on drop
+pub struct TemporaryObject {
+    path: PathBuf,
+}
+
+impl TemporaryObject {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TemporaryObject {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn x86_64_start() -> CodeBuffer {
+    let mut asm = CodeBuffer::new();
+    const PC32: RelocationKind = RelocationKind::Relative;
+
+    asm.bytes(&[0x31, 0xed]);                         // xor %ebp, %ebp
+    asm.bytes(&[0x48, 0x8b, 0x3c, 0x24]);             // mov (%rsp), %rdi          argc
+    asm.bytes(&[0x48, 0x8d, 0x74, 0x24, 0x08]);       // lea 8(%rsp), %rsi         argv
+    asm.bytes(&[0x48, 0x8d, 0x54, 0xfc, 0x10]);       // lea 16(%rsp,%rdi,8), %rdx envp
+    asm.bytes(&[0x48, 0x83, 0xe4, 0xf0]);             // and $-16, %rsp
+    asm.bytes(&[0x49, 0x89, 0xfc]);                   // mov %rdi, %r12
+    asm.bytes(&[0x49, 0x89, 0xf5]);                   // mov %rsi, %r13
+    asm.bytes(&[0x49, 0x89, 0xd6]);                   // mov %rdx, %r14
+
+    asm.bytes(&[0x48, 0x8d, 0x1d]);                   // lea __init_array_start(%rip), %rbx
+    asm.fixup(asm.here(), INIT_ARRAY_START, PC32, 32, -4);
+    asm.bytes(&[0; 4]);
+
+    let loop_top = asm.here();
+    asm.bytes(&[0x48, 0x8d, 0x05]);                   // lea __init_array_end(%rip), %rax
+    asm.fixup(asm.here(), INIT_ARRAY_END, PC32, 32, -4);
+    asm.bytes(&[0; 4]);
+    asm.bytes(&[0x48, 0x39, 0xc3]);                   // cmp %rax, %rbx
+    let exit_loop = asm.here();
+    asm.bytes(&[0x73, 0x00]);                         // jae 2f
+    asm.bytes(&[0x4c, 0x89, 0xe7, 0x4c, 0x89, 0xee, 0x4c, 0x89, 0xf2]); // argc, argv, envp
+    asm.bytes(&[0xff, 0x13]);                         // call *(%rbx)
+    asm.bytes(&[0x48, 0x83, 0xc3, 0x08]);             // add $8, %rbx
+    let back = asm.here();
+    asm.bytes(&[0xeb, (loop_top as isize - (back as isize + 2)) as u8]); // jmp 1b
+
+    let after_loop = asm.here();
+    asm.code[exit_loop + 1] = (after_loop - (exit_loop + 2)) as u8;
+    asm.bytes(&[0x4c, 0x89, 0xe7, 0x4c, 0x89, 0xee, 0x4c, 0x89, 0xf2]); // argc, argv, envp
+    asm.bytes(&[0xe8]);                               // call main
+    asm.fixup(asm.here(), MAIN, RelocationKind::PltRelative, 32, -4);
+    asm.bytes(&[0; 4]);
+
+    asm.bytes(&[0x89, 0xc7]);                         // mov %eax, %edi
+    asm.bytes(&[0xb8, 0xe7, 0x00, 0x00, 0x00]);       // mov $231, %eax     exit_group
+    asm.bytes(&[0x0f, 0x05]);                         // syscall
+    asm.bytes(&[0xf4]);                               // hlt
+    asm
+}
+
+fn aarch64_start() -> CodeBuffer {
+    let mut asm = CodeBuffer::new();
+
+    asm.word(0xd280001d);                             // mov x29, #0
+    asm.word(0xd280001e);                             // mov x30, #0
+    asm.word(0xf94003f3);                             // ldr x19, [sp]            argc
+    asm.word(0x910023f4);                             // add x20, sp, #8          argv
+    asm.word(0x8b130e95);                             // add x21, x20, x19, lsl #3
+    asm.word(0x910022b5);                             // add x21, x21, #8         envp
+
+    for (symbol, reg) in [(INIT_ARRAY_START, 22), (INIT_ARRAY_END, 23)] {
+        asm.fixup(asm.here(), symbol, RelocationKind::Elf(elf::R_AARCH64_ADR_PREL_PG_HI21), 21, 0);
+        asm.word(0x90000000 | reg);                   // adrp xN, symbol
+        asm.fixup(asm.here(), symbol, RelocationKind::Elf(elf::R_AARCH64_ADD_ABS_LO12_NC), 12, 0);
+        asm.word(0x91000000 | (reg << 5) | reg);      // add xN, xN, :lo12:symbol
+    }
+
+    let loop_top = asm.here();
+    asm.word(0xeb1702df);                             // cmp x22, x23
+    let exit_loop = asm.here();
+    asm.word(0x54000002);                             // b.hs 2f
+    asm.word(0xf84086c8);                             // ldr x8, [x22], #8
+    asm.word(0xaa1303e0);                             // mov x0, x19
+    asm.word(0xaa1403e1);                             // mov x1, x20
+    asm.word(0xaa1503e2);                             // mov x2, x21
+    asm.word(0xd63f0100);                             // blr x8
+    let back = asm.here();
+    let offset = (loop_top as i32 - back as i32) / 4;
+    asm.word(0x14000000 | (offset as u32 & 0x03ff_ffff)); // b 1b
+
+    let after_loop = asm.here();
+    let offset = ((after_loop - exit_loop) / 4) as u32;
+    let patched = asm.read_word(exit_loop) | ((offset & 0x7ffff) << 5);
+    asm.patch_word(exit_loop, patched);
+
+    asm.word(0xaa1303e0);                             // mov x0, x19
+    asm.word(0xaa1403e1);                             // mov x1, x20
+    asm.word(0xaa1503e2);                             // mov x2, x21
+    asm.fixup(asm.here(), MAIN, RelocationKind::Elf(elf::R_AARCH64_CALL26), 26, 0);
+    asm.word(0x94000000);                             // bl main
+    asm.word(0xd2800bc8);                             // mov x8, #94          exit_group
+    asm.word(0xd4000001);                             // svc #0
+    asm
+}
+
+fn arm_start() -> CodeBuffer {
+    let mut asm = CodeBuffer::new();
+
+    asm.word(0xe3a0b000);                             // mov fp, #0
+    asm.word(0xe3a0e000);                             // mov lr, #0
+    asm.word(0xe59d4000);                             // ldr r4, [sp]             argc
+    asm.word(0xe28d5004);                             // add r5, sp, #4           argv
+    asm.word(0xe0856104);                             // add r6, r5, r4, lsl #2
+    asm.word(0xe2866004);                             // add r6, r6, #4           envp
+    asm.word(0xe3cdd007);                             // bic sp, sp, #7           AAPCS 8-byte alignment
+    let load_start = asm.here();
+    asm.word(0xe59f7000);                             // ldr r7, =__init_array_start
+    let load_end = asm.here();
+    asm.word(0xe59f8000);                             // ldr r8, =__init_array_end
+
+    let loop_top = asm.here();
+    asm.word(0xe1570008);                             // cmp r7, r8
+    let exit_loop = asm.here();
+    asm.word(0x2a000000);                             // bhs 2f
+    asm.word(0xe4973004);                             // ldr r3, [r7], #4
+    asm.word(0xe1a00004);                             // mov r0, r4
+    asm.word(0xe1a01005);                             // mov r1, r5
+    asm.word(0xe1a02006);                             // mov r2, r6
+    asm.word(0xe12fff33);                             // blx r3
+    let back = asm.here();
+    let offset = (loop_top as i32 - (back as i32 + 8)) / 4;
+    asm.word(0xea000000 | (offset as u32 & 0x00ff_ffff)); // b 1b
+
+    let after_loop = asm.here();
+    let offset = ((after_loop - (exit_loop + 8)) / 4) as u32;
+    let patched = asm.read_word(exit_loop) | offset;
+    asm.patch_word(exit_loop, patched);
+
+    asm.word(0xe1a00004);                             // mov r0, r4
+    asm.word(0xe1a01005);                             // mov r1, r5
+    asm.word(0xe1a02006);                             // mov r2, r6
+    // REL relocations keep the addend in place: imm24 = -2 is the usual PC bias
+    asm.fixup(asm.here(), MAIN, RelocationKind::Elf(elf::R_ARM_CALL), 24, 0);
+    asm.word(0xebfffffe);                             // bl main
+    asm.word(0xe3a070f8);                             // mov r7, #248         exit_group
+    asm.word(0xef000000);                             // svc #0
+
+    // Literal pool for the two ldr-literal loads above
+    for (load, symbol) in [(load_start, INIT_ARRAY_START), (load_end, INIT_ARRAY_END)] {
+        let literal = asm.here();
+        let patched = asm.read_word(load) | (literal - (load + 8)) as u32;
+        asm.patch_word(load, patched);
+        asm.fixup(literal, symbol, RelocationKind::Absolute, 32, 0);
+        asm.word(0);
+    }
+    asm
+}
+
+#[derive(Debug)]
+pub enum Crt0Error {
+    ObjectWrite(String),
+    Io(std::io::Error),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), Crt0Error> {
+    // c-interpreter compile -a aarch64 --nostdlib prog.c -o prog
+    let crt0 = Crt0::for_architecture(Architecture::AArch64);
+    let object = crt0.write_object(Path::new("target/crt"))?;
+    println!("{} ({} bytes of startup code)", object.display(), crt0.code_size());
+    Ok(())
+}
+*/
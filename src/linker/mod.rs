@@ -2,6 +2,13 @@ use std::path::{Path, PathBuf};
 use std::collections::{HashMap, HashSet};
 use object::{Object, ObjectSection, SectionKind};
 
+pub mod crt0;
+pub mod oformat;
+pub mod pkg_config;
+pub mod script;
+pub mod static_elf;
+pub mod symbols;
+
 pub struct LinkerSystem {
     // File management
     file_manager: FileManager,
@@ -113,35 +120,67 @@ impl SymbolTable {
         name: String,
         symbol: Symbol
     ) -> Result<(), SymbolError> {
-        // Check for conflicts
+        // Record the version, and make the default one reachable by the
+        // plain name
+        if let Some(versioned) = symbols::Versioned::parse(&name) {
+            self.symbol_versions.entry(versioned.base.to_string()).or_default().push(SymbolVersion {
+                version: versioned.version.to_string(),
+                default: versioned.default,
+            });
+            if versioned.default {
+                self.add_symbol(versioned.base.to_string(), symbol.clone())?;
+            }
+        }
+
+        // Check for conflicts; the existing symbol may stay
         if let Some(existing) = self.symbols.get(&name) {
-            self.handle_symbol_conflict(&name, existing, &symbol)?;
+            if !self.handle_symbol_conflict(&name, existing, &symbol)? {
+                return Ok(());
+            }
         }
-        
+
         // Add symbol
-        self.symbols.insert(name.clone(), symbol);
-        
+        if symbol.binding == SymbolBinding::Weak {
+            self.weak_symbols.insert(name.clone());
+        } else {
+            self.weak_symbols.remove(&name);
+        }
+        self.symbols.insert(name, symbol);
+
         Ok(())
     }
 
+    /// Whether `new` replaces `existing`: strong over common over weak, and
+    /// two commons merge into the larger
     fn handle_symbol_conflict(
-        &mut self,
+        &self,
         name: &str,
         existing: &Symbol,
         new: &Symbol
-    ) -> Result<(), SymbolError> {
-        match (existing.binding, new.binding) {
-            (SymbolBinding::Weak, SymbolBinding::Strong) => {
-                // Replace weak with strong
-                self.symbols.insert(name.to_string(), new.clone());
-                Ok(())
-            }
-            (SymbolBinding::Strong, SymbolBinding::Strong) => {
-                Err(SymbolError::Conflict(name.to_string()))
-            }
-            _ => Ok(()),
+    ) -> Result<bool, SymbolError> {
+        match symbols::resolve(strength(existing.binding), strength(new.binding)) {
+            symbols::Resolution::Duplicate => Err(SymbolError::Conflict(name.to_string())),
+            symbols::Resolution::Replace => Ok(true),
+            symbols::Resolution::Merge => Ok(new.size > existing.size),
+            symbols::Resolution::Keep => Ok(false),
         }
     }
+
+    /// The symbol a reference to `name` binds to; `name@VERSION` also
+    /// binds to `name@@VERSION`
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.symbols
+            .get(name)
+            .or_else(|| symbols::default_version(name).and_then(|name| self.symbols.get(&name)))
+    }
+}
+
+fn strength(binding: SymbolBinding) -> symbols::Strength {
+    match binding {
+        SymbolBinding::Weak => symbols::Strength::Weak,
+        SymbolBinding::Common => symbols::Strength::Common,
+        SymbolBinding::Strong => symbols::Strength::Strong,
+    }
 }
 
 // Section management
@@ -0,0 +1,384 @@
+// src/linker/oformat.rs
+//! Output format conversion (objcopy -O equivalent)
+//! Turns a linked ELF image into a flat binary, Intel HEX or Motorola
+//! S-record file so bare-metal targets can be flashed without binutils.
+//! Loadable segments are taken from the program headers, at their load
+//! (physical) addresses as objcopy does, so data a linker script placed in
+//! RAM but loads from flash lands in flash. Relocatable objects have no
+//! program headers and every section at address 0, so their allocated
+//! sections are laid out one after another at their alignment; their
+//! relocations are not applied, so link first for a runnable image.
+
+use std::collections::HashMap;
+use object::elf::{FileHeader32, FileHeader64};
+use object::read::elf::{ElfFile, FileHeader, ProgramHeader};
+use object::{Endianness, Object, ObjectKind, ObjectSection, ObjectSegment, SectionKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Elf,
+    Binary,
+    IntelHex,
+    Srec,
+}
+
+impl OutputFormat {
+    pub fn from_str(s: &str) -> Result<Self, OutputFormatError> {
+        match s {
+            "elf" => Ok(OutputFormat::Elf),
+            "binary" | "bin" => Ok(OutputFormat::Binary),
+            "ihex" | "hex" => Ok(OutputFormat::IntelHex),
+            "srec" | "s19" | "s28" | "s37" => Ok(OutputFormat::Srec),
+            other => Err(OutputFormatError::UnknownFormat(other.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversionOptions {
+    /// Move the image so its lowest address lands here (e.g. flash base)
+    pub load_address: Option<u64>,
+    /// Entry point for the start record; defaults to the ELF entry
+    pub entry: Option<u64>,
+    /// Byte used to fill holes between segments in binary output
+    pub gap_fill: u8,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            load_address: None,
+            entry: None,
+            gap_fill: 0x00,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadSegment {
+    pub address: u64,
+    pub data: Vec<u8>,
+}
+
+/// Convert an ELF image to `format`
+pub fn convert(
+    elf: &[u8],
+    format: OutputFormat,
+    options: &ConversionOptions
+) -> Result<Vec<u8>, OutputFormatError> {
+    if format == OutputFormat::Elf {
+        return Ok(elf.to_vec());
+    }
+
+    let file = object::File::parse(elf).map_err(|e| OutputFormatError::Parse(e.to_string()))?;
+    let mut segments = load_segments(&file, &physical_addresses(elf))?;
+    let mut entry = options.entry.unwrap_or(file.entry());
+
+    // Rebase the whole image, keeping relative layout and the entry offset
+    if let Some(base) = options.load_address {
+        let lowest = segments.iter().map(|s| s.address).min().unwrap_or(0);
+        for segment in &mut segments {
+            segment.address = segment.address - lowest + base;
+        }
+        if options.entry.is_none() && entry >= lowest {
+            entry = entry - lowest + base;
+        }
+    }
+
+    match format {
+        OutputFormat::Binary => Ok(to_binary(&segments, options.gap_fill)),
+        OutputFormat::IntelHex => to_ihex(&segments, entry),
+        OutputFormat::Srec => Ok(to_srec(&segments, entry)),
+        OutputFormat::Elf => unreachable!(),
+    }
+}
+
+fn load_segments(file: &object::File, physical: &HashMap<u64, u64>) -> Result<Vec<LoadSegment>, OutputFormatError> {
+    let mut segments = Vec::new();
+
+    for segment in file.segments() {
+        let data = segment.data().map_err(|e| OutputFormatError::Parse(e.to_string()))?;
+        if !data.is_empty() {
+            segments.push(LoadSegment {
+                address: physical.get(&segment.address()).copied().unwrap_or(segment.address()),
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    if segments.is_empty() {
+        let relocatable = file.kind() == ObjectKind::Relocatable;
+        if relocatable {
+            log::warn!("converting a relocatable object: sections are laid out in order and relocations are not applied");
+        }
+        let mut next = 0u64;
+        for section in file.sections() {
+            let loadable = matches!(
+                section.kind(),
+                SectionKind::Text | SectionKind::Data | SectionKind::ReadOnlyData | SectionKind::ReadOnlyString
+            );
+            if loadable {
+                let data = section.data().map_err(|e| OutputFormatError::Parse(e.to_string()))?;
+                if !data.is_empty() {
+                    let address = if relocatable {
+                        let align = section.align().max(1);
+                        let address = next.div_ceil(align) * align;
+                        next = address + data.len() as u64;
+                        address
+                    } else {
+                        section.address()
+                    };
+                    segments.push(LoadSegment {
+                        address,
+                        data: data.to_vec(),
+                    });
+                }
+            }
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(OutputFormatError::NoLoadableData);
+    }
+
+    segments.sort_by_key(|s| s.address);
+    for pair in segments.windows(2) {
+        if pair[0].address + pair[0].data.len() as u64 > pair[1].address {
+            return Err(OutputFormatError::OverlappingSegments(pair[1].address));
+        }
+    }
+    Ok(segments)
+}
+
+/// p_paddr by p_vaddr, for ELF files
+fn physical_addresses(elf: &[u8]) -> HashMap<u64, u64> {
+    fn collect<Elf: FileHeader<Endian = Endianness>>(elf: &[u8]) -> Option<HashMap<u64, u64>> {
+        let file = ElfFile::<Elf>::parse(elf).ok()?;
+        let endian = file.endian();
+        let segments = file.raw_segments().iter();
+        Some(segments.map(|segment| (segment.p_vaddr(endian).into(), segment.p_paddr(endian).into())).collect())
+    }
+    collect::<FileHeader64<Endianness>>(elf)
+        .or_else(|| collect::<FileHeader32<Endianness>>(elf))
+        .unwrap_or_default()
+}
+
+/// Flat image from the lowest to the highest loaded byte
+fn to_binary(segments: &[LoadSegment], gap_fill: u8) -> Vec<u8> {
+    let base = segments[0].address;
+    let end = segments
+        .iter()
+        .map(|s| s.address + s.data.len() as u64)
+        .max()
+        .unwrap_or(base);
+
+    let mut image = vec![gap_fill; (end - base) as usize];
+    for segment in segments {
+        let offset = (segment.address - base) as usize;
+        image[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+    }
+    image
+}
+
+fn to_ihex(segments: &[LoadSegment], entry: u64) -> Result<Vec<u8>, OutputFormatError> {
+    let mut out = String::new();
+    let mut upper: Option<u16> = None;
+
+    for segment in segments {
+        if segment.address + segment.data.len() as u64 > 0x1_0000_0000 {
+            return Err(OutputFormatError::AddressTooLarge(segment.address));
+        }
+
+        for (i, chunk) in segment.data.chunks(16).enumerate() {
+            let address = segment.address + (i * 16) as u64;
+
+            // Extended linear address record when crossing a 64K boundary
+            let high = (address >> 16) as u16;
+            if upper != Some(high) {
+                ihex_record(&mut out, 0, 0x04, &high.to_be_bytes());
+                upper = Some(high);
+            }
+
+            // A record must not wrap within its 64K window
+            let low = (address & 0xffff) as u16;
+            let fits = (0x1_0000 - low as usize).min(chunk.len());
+            ihex_record(&mut out, low, 0x00, &chunk[..fits]);
+            if fits < chunk.len() {
+                let next_high = high.wrapping_add(1);
+                ihex_record(&mut out, 0, 0x04, &next_high.to_be_bytes());
+                upper = Some(next_high);
+                ihex_record(&mut out, 0, 0x00, &chunk[fits..]);
+            }
+        }
+    }
+
+    // Start linear address, then end of file
+    ihex_record(&mut out, 0, 0x05, &(entry as u32).to_be_bytes());
+    ihex_record(&mut out, 0, 0x01, &[]);
+    Ok(out.into_bytes())
+}
+
+fn ihex_record(out: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    bytes.extend_from_slice(data);
+    let checksum = (!bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))).wrapping_add(1);
+
+    out.push(':');
+    for b in &bytes {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push_str(&format!("{:02X}\n", checksum));
+}
+
+fn to_srec(segments: &[LoadSegment], entry: u64) -> Vec<u8> {
+    let end = segments
+        .iter()
+        .map(|s| s.address + s.data.len() as u64)
+        .max()
+        .unwrap_or(0)
+        .max(entry);
+
+    // Smallest address width that covers the image: S1/S9, S2/S8 or S3/S7
+    let (data_kind, end_kind, width) = if end <= 0x1_0000 {
+        (1, 9, 2)
+    } else if end <= 0x100_0000 {
+        (2, 8, 3)
+    } else {
+        (3, 7, 4)
+    };
+
+    let mut out = String::new();
+    srec_record(&mut out, 0, 0, 2, b"c-interpreter");
+
+    let mut count = 0u32;
+    for segment in segments {
+        for (i, chunk) in segment.data.chunks(32).enumerate() {
+            let address = segment.address + (i * 32) as u64;
+            srec_record(&mut out, data_kind, address, width, chunk);
+            count += 1;
+        }
+    }
+
+    // Record count (S5 for 16-bit counts, S6 for 24-bit)
+    if count <= 0xffff {
+        srec_record(&mut out, 5, count as u64, 2, &[]);
+    } else if count <= 0xff_ffff {
+        srec_record(&mut out, 6, count as u64, 3, &[]);
+    }
+
+    srec_record(&mut out, end_kind, entry, width, &[]);
+    out.into_bytes()
+}
+
+fn srec_record(out: &mut String, kind: u8, address: u64, width: usize, data: &[u8]) {
+    let address_bytes = &address.to_be_bytes()[8 - width..];
+    let length = (width + data.len() + 1) as u8;
+
+    let mut sum = length;
+    out.push_str(&format!("S{}{:02X}", kind, length));
+    for b in address_bytes.iter().chain(data) {
+        sum = sum.wrapping_add(*b);
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push_str(&format!("{:02X}\n", !sum));
+}
+
+/// Parse a fill byte given as hex (`0xff`) or decimal
+pub fn parse_byte(s: &str) -> Result<u8, OutputFormatError> {
+    let value = parse_address(s).map_err(|_| OutputFormatError::InvalidByte(s.to_string()))?;
+    u8::try_from(value).map_err(|_| OutputFormatError::InvalidByte(s.to_string()))
+}
+
+/// Parse a load address given as hex (`0x08000000`) or decimal
+pub fn parse_address(s: &str) -> Result<u64, OutputFormatError> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| OutputFormatError::InvalidAddress(s.to_string()))
+}
+
+#[derive(Debug)]
+pub enum OutputFormatError {
+    UnknownFormat(String),
+    InvalidAddress(String),
+    InvalidByte(String),
+    Parse(String),
+    NoLoadableData,
+    OverlappingSegments(u64),
+    AddressTooLarge(u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every byte of a record, checksum included, sums to zero
+    fn ihex_sums_to_zero(line: &str) -> bool {
+        let bytes = (1..line.len()).step_by(2).map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap());
+        bytes.fold(0u8, |sum, b| sum.wrapping_add(b)) == 0
+    }
+
+    /// The count, address and data bytes plus the checksum sum to 0xff
+    fn srec_sums_to_ff(line: &str) -> bool {
+        let bytes = (2..line.len()).step_by(2).map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap());
+        bytes.fold(0u8, |sum, b| sum.wrapping_add(b)) == 0xff
+    }
+
+    #[test]
+    fn ihex_record_checksums() {
+        let mut out = String::new();
+        ihex_record(&mut out, 0x0010, 0x00, b"address gap");
+        ihex_record(&mut out, 0, 0x01, &[]);
+        assert_eq!(out, ":0B0010006164647265737320676170A7\n:00000001FF\n");
+    }
+
+    #[test]
+    fn ihex_image_across_64k() {
+        let segments = [LoadSegment { address: 0x0800_fff8, data: (0..=255).collect() }];
+        let hex = String::from_utf8(to_ihex(&segments, 0x0800_0000).unwrap()).unwrap();
+        assert!(hex.lines().all(ihex_sums_to_zero));
+        assert!(hex.contains(":020000040801F1\n"));
+        assert!(hex.ends_with(":0400000508000000EF\n:00000001FF\n"));
+    }
+
+    #[test]
+    fn srec_record_checksums() {
+        let mut data = vec![0x0A, 0x0A, 0x0D];
+        data.resize(16, 0);
+        let mut out = String::new();
+        srec_record(&mut out, 1, 0x7AF0, 2, &data);
+        srec_record(&mut out, 5, 3, 2, &[]);
+        srec_record(&mut out, 9, 0, 2, &[]);
+        assert_eq!(out, "S1137AF00A0A0D0000000000000000000000000061\nS5030003F9\nS9030000FC\n");
+    }
+
+    #[test]
+    fn srec_image_widths() {
+        let segments = [LoadSegment { address: 0x0800_0000, data: vec![0xA5; 100] }];
+        let srec = String::from_utf8(to_srec(&segments, 0x0800_0000)).unwrap();
+        assert!(srec.lines().all(srec_sums_to_ff));
+        assert!(srec.lines().skip(1).take(4).all(|line| line.starts_with("S3")));
+        assert!(srec.ends_with("S70508000000F2\n"));
+    }
+
+    #[test]
+    fn gap_fill_is_a_byte() {
+        assert_eq!(parse_byte("0xff").unwrap(), 0xff);
+        assert_eq!(parse_byte("17").unwrap(), 17);
+        assert!(matches!(parse_byte("0x100"), Err(OutputFormatError::InvalidByte(_))));
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), OutputFormatError> {
+    let elf = std::fs::read("firmware.elf").unwrap();
+    let hex = convert(&elf, OutputFormat::IntelHex, &ConversionOptions {
+        load_address: Some(0x0800_0000),
+        ..ConversionOptions::default()
+    })?;
+    std::fs::write("firmware.hex", hex).unwrap();
+    Ok(())
+}
+*/
@@ -0,0 +1,238 @@
+// src/linker/pkg_config.rs
+//! System library discovery
+//! `-lsqlite3` alone only works when sqlite's header and library sit in
+//! the default search directories. Every library a program asks for, with
+//! `-l` or with MSVC's `#pragma comment(lib, "...")` in the source, is
+//! looked up with pkg-config first (vcpkg on Windows): a package that
+//! answers contributes its include directories, its library directories
+//! and the libraries it links, dependencies included, and the same list
+//! feeds the linker in compile mode and `runtime::dynamic_loader` in JIT
+//! mode. Libraries no package describes are kept as plain `-l` names.
+//!
+//! A library is tried as its own package name, as `lib<name>`, and under
+//! the package names of `ALIASES` (`-lz` is the `zlib` package). The
+//! pkg-config binary can be chosen with `PKG_CONFIG`, and `PKG_CONFIG_PATH`
+//! and friends work as usual. pkg-config describes the host, so when
+//! cross-compiling it's only asked when `PKG_CONFIG_LIBDIR` or
+//! `PKG_CONFIG_SYSROOT_DIR` points it at the target. vcpkg is found
+//! through `VCPKG_ROOT`, with the triplet from `VCPKGRS_TRIPLET` (default
+//! `x64-windows`).
+
+use std::path::PathBuf;
+use std::process::Command;
+use serde::Serialize;
+use crate::optimizer::pragma::pragma_words;
+use crate::runtime::dynamic_loader::LibrarySearch;
+
+/// `-l` names whose package is called something else
+pub const ALIASES: &[(&str, &str)] = &[
+    ("z", "zlib"),
+    ("bz2", "bzip2"),
+    ("ssl", "openssl"),
+    ("crypto", "libcrypto"),
+    ("curl", "libcurl"),
+    ("png", "libpng"),
+    ("png16", "libpng16"),
+    ("jpeg", "libjpeg"),
+    ("xml2", "libxml-2.0"),
+    ("ffi", "libffi"),
+    ("freetype", "freetype2"),
+    ("pcre2-8", "libpcre2-8"),
+    ("lzma", "liblzma"),
+    ("zstd", "libzstd"),
+    ("event", "libevent"),
+    ("uv", "libuv"),
+    ("pq", "libpq"),
+    ("magic", "libmagic"),
+    ("systemd", "libsystemd"),
+    ("udev", "libudev"),
+];
+
+/// What a package says to compile and link with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Package {
+    /// As pkg-config or vcpkg knows it
+    pub name: String,
+    pub include_dirs: Vec<PathBuf>,
+    pub library_paths: Vec<String>,
+    /// Its own libraries and those of its dependencies, in link order
+    pub libraries: Vec<String>,
+}
+
+/// The libraries of a program after discovery
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SystemLibraries {
+    /// Include directories of the packages found, in request order
+    pub include_dirs: Vec<PathBuf>,
+    /// The `-L` directories given, then the packages'; the packages'
+    /// libraries in place of the names that found them
+    pub search: LibrarySearch,
+    /// (name requested, package that answered)
+    pub packages: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Discovery {
+    /// Ask for the libraries of a static link (`pkg-config --static`)
+    pub static_link: bool,
+    /// The code runs on another machine than the compiler
+    pub cross_compiling: bool,
+}
+
+impl Discovery {
+    /// Look up every library of `requested`, in order
+    pub fn run(&self, requested: &LibrarySearch) -> SystemLibraries {
+        let mut found = SystemLibraries {
+            search: LibrarySearch { libraries: Vec::new(), library_paths: requested.library_paths.clone() },
+            ..SystemLibraries::default()
+        };
+        let use_pkg_config = !self.cross_compiling
+            || std::env::var_os("PKG_CONFIG_LIBDIR").is_some()
+            || std::env::var_os("PKG_CONFIG_SYSROOT_DIR").is_some();
+        if !use_pkg_config {
+            log::debug!("cross-compiling: pkg-config not consulted (set PKG_CONFIG_LIBDIR for the target)");
+        }
+
+        for name in &requested.libraries {
+            // -l:file and paths name a file, not a package
+            let package = if name.starts_with(':') || name.contains('/') {
+                None
+            } else {
+                candidates(name).into_iter().find_map(|candidate| {
+                    if use_pkg_config {
+                        if let Some(package) = self.pkg_config(&candidate) {
+                            return Some(package);
+                        }
+                    }
+                    vcpkg(&candidate)
+                })
+            };
+            let Some(package) = package else {
+                push_unique(&mut found.search.libraries, name.clone());
+                continue;
+            };
+            log::debug!("-l{}: package {} ({})", name, package.name, package.libraries.join(" "));
+            for dir in package.include_dirs {
+                push_unique(&mut found.include_dirs, dir);
+            }
+            for dir in package.library_paths {
+                push_unique(&mut found.search.library_paths, dir);
+            }
+            for library in package.libraries {
+                push_unique(&mut found.search.libraries, library);
+            }
+            found.packages.push((name.clone(), package.name));
+        }
+        found
+    }
+
+    fn pkg_config(&self, package: &str) -> Option<Package> {
+        let program = std::env::var_os("PKG_CONFIG").unwrap_or_else(|| "pkg-config".into());
+        let mut command = Command::new(&program);
+        command.args(["--cflags-only-I", "--libs"]);
+        if self.static_link {
+            command.arg("--static");
+        }
+        let output = match command.arg(package).output() {
+            Ok(output) => output,
+            Err(e) => {
+                log::debug!("{}: {}", program.to_string_lossy(), e);
+                return None;
+            }
+        };
+        if !output.status.success() {
+            return None;
+        }
+
+        let mut found = Package { name: package.to_string(), ..Package::default() };
+        for flag in String::from_utf8_lossy(&output.stdout).split_whitespace() {
+            if let Some(dir) = flag.strip_prefix("-I") {
+                push_unique(&mut found.include_dirs, PathBuf::from(dir));
+            } else if let Some(dir) = flag.strip_prefix("-L") {
+                push_unique(&mut found.library_paths, dir.to_string());
+            } else if let Some(library) = flag.strip_prefix("-l") {
+                push_unique(&mut found.libraries, library.to_string());
+            } else if flag == "-pthread" {
+                push_unique(&mut found.libraries, "pthread".to_string());
+            } else {
+                log::debug!("pkg-config {}: ignoring '{}'", package, flag);
+            }
+        }
+        Some(found)
+    }
+}
+
+/// Package names to try for `-l<name>`
+fn candidates(name: &str) -> Vec<String> {
+    let mut candidates = vec![name.to_string()];
+    if !name.starts_with("lib") {
+        candidates.push(format!("lib{}", name));
+    }
+    candidates.extend(ALIASES.iter().filter(|(library, _)| *library == name).map(|(_, package)| package.to_string()));
+    candidates
+}
+
+#[cfg(windows)]
+fn vcpkg(package: &str) -> Option<Package> {
+    let root = PathBuf::from(std::env::var_os("VCPKG_ROOT")?);
+    let triplet = std::env::var("VCPKGRS_TRIPLET").unwrap_or_else(|_| "x64-windows".to_string());
+    let installed = root.join("installed").join(triplet);
+    let lib_dir = installed.join("lib");
+    if !lib_dir.join(format!("{}.lib", package)).is_file() {
+        return None;
+    }
+    Some(Package {
+        name: package.to_string(),
+        include_dirs: vec![installed.join("include")],
+        // The DLLs live in bin
+        library_paths: vec![lib_dir.to_string_lossy().into_owned(), installed.join("bin").to_string_lossy().into_owned()],
+        libraries: vec![package.to_string()],
+    })
+}
+
+#[cfg(not(windows))]
+fn vcpkg(_package: &str) -> Option<Package> {
+    None
+}
+
+/// Libraries named by `#pragma comment(lib, "name")` in `source`, as `-l`
+/// names: `"sqlite3.lib"` is `sqlite3`, `"libz.a"` is `z`
+pub fn pragma_libraries(source: &str) -> Vec<String> {
+    let mut libraries = Vec::new();
+    for line in source.lines() {
+        let Some(words) = pragma_words(line.trim_start()) else { continue };
+        let words: Vec<&str> = words.split_whitespace().collect();
+        let ["comment", "lib", name] = words[..] else { continue };
+        let name = name.trim_matches('"');
+        let stem = [".lib", ".a", ".so", ".dylib"].iter().find_map(|extension| name.strip_suffix(extension));
+        let library = match stem {
+            Some(stem) if !cfg!(windows) => stem.strip_prefix("lib").filter(|rest| !rest.is_empty()).unwrap_or(stem),
+            Some(stem) => stem,
+            None => name,
+        };
+        if !library.is_empty() {
+            push_unique(&mut libraries, library.to_string());
+        }
+    }
+    libraries
+}
+
+fn push_unique<T: PartialEq>(list: &mut Vec<T>, item: T) {
+    if !list.contains(&item) {
+        list.push(item);
+    }
+}
+
+// Example usage:
+/*
+fn libraries_for(source: &str) -> SystemLibraries {
+    // #pragma comment(lib, "sqlite3.lib") in the source, -lz on the command line
+    let mut requested = LibrarySearch { libraries: vec!["z".to_string()], library_paths: vec![] };
+    requested.libraries.extend(pragma_libraries(source));
+    let found = Discovery { static_link: false, cross_compiling: false }.run(&requested);
+    for (name, package) in &found.packages {
+        println!("-l{} -> {}", name, package);
+    }
+    found
+}
+*/
@@ -0,0 +1,1027 @@
+// src/linker/script.rs
+//! GNU ld linker scripts
+//! The subset of the ld script language embedded projects use to place
+//! code and data in flash and RAM: `ENTRY`, `MEMORY` regions, and `SECTIONS`
+//! with output section addresses, `ALIGN`, `(NOLOAD)`, `> REGION`,
+//! `AT > REGION` and `AT(...)` load addresses, input section patterns with
+//! `KEEP`, `SORT` and `EXCLUDE_FILE`, `/DISCARD/`, symbol assignments,
+//! `PROVIDE`, `PROVIDE_HIDDEN` and `ASSERT`. `OUTPUT_FORMAT`, `OUTPUT_ARCH`
+//! and `SEARCH_DIR` are accepted and ignored; anything else, such as
+//! `PHDRS`, `INCLUDE` or the `BYTE`/`LONG` data commands, is an error
+//! rather than being silently dropped.
+//!
+//! `static_elf::StaticLinker::with_script` does the placing; this module
+//! only parses and evaluates expressions against what the linker knows.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the output section whose inputs are thrown away
+pub const DISCARD: &str = "/DISCARD/";
+
+#[derive(Debug, Clone, Default)]
+pub struct LinkerScript {
+    pub entry: Option<String>,
+    pub regions: Vec<MemoryRegion>,
+    /// Top-level statements and the contents of `SECTIONS`, in order
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    pub name: String,
+    /// As written, e.g. `rx` or `rwx`
+    pub attributes: String,
+    pub origin: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum Item {
+    Output(OutputDescription),
+    Assign(Assignment),
+    Assert { condition: Expr, message: String },
+}
+
+/// One output section of `SECTIONS`
+#[derive(Debug, Clone)]
+pub struct OutputDescription {
+    pub name: String,
+    /// `.text 0x1000 : { ... }`
+    pub address: Option<Expr>,
+    /// `: ALIGN(8)`
+    pub align: Option<Expr>,
+    /// `AT(expr)`
+    pub load_address: Option<Expr>,
+    /// `(NOLOAD)`: takes memory but nothing is loaded there
+    pub noload: bool,
+    pub commands: Vec<SectionCommand>,
+    /// `> REGION`
+    pub region: Option<String>,
+    /// `AT > REGION`
+    pub load_region: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SectionCommand {
+    Input(InputPattern),
+    Assign(Assignment),
+}
+
+/// `KEEP(*crt0.o(.text .text.*))`
+#[derive(Debug, Clone)]
+pub struct InputPattern {
+    pub file: String,
+    pub exclude_files: Vec<String>,
+    pub sections: Vec<String>,
+    /// Kept by section garbage collection
+    pub keep: bool,
+    /// `SORT`/`SORT_BY_NAME`: matching sections in name order rather than
+    /// input order
+    pub sort: bool,
+}
+
+impl InputPattern {
+    pub fn matches(&self, file: &str, section: &str) -> bool {
+        let file_matches = |pattern: &str| {
+            // `libc.a(memcpy.o)` is matched as the archive and as the member
+            glob(pattern, file)
+                || file.rsplit('/').next().is_some_and(|name| glob(pattern, name))
+                || file.strip_suffix(')').and_then(|f| f.rsplit_once('(')).is_some_and(|(_, member)| glob(pattern, member))
+        };
+        file_matches(&self.file)
+            && !self.exclude_files.iter().any(|pattern| file_matches(pattern))
+            && self.sections.iter().any(|pattern| glob(pattern, section))
+    }
+}
+
+/// `symbol = expr`, `. += expr`, `PROVIDE(symbol = expr)`
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    /// `.` for the location counter
+    pub symbol: String,
+    /// The operator of a compound assignment such as `+=`
+    pub op: Option<BinaryOp>,
+    pub expr: Expr,
+    /// Only defines the symbol if no input does
+    pub provide: bool,
+}
+
+impl Assignment {
+    pub fn is_dot(&self) -> bool {
+        self.symbol == "."
+    }
+
+    /// The value assigned, given the symbol's current value for compound
+    /// assignments
+    pub fn evaluate(&self, script: &LinkerScript, environment: &dyn Environment) -> Result<u64, ScriptError> {
+        let value = script.evaluate(&self.expr, environment)?;
+        match self.op {
+            None => Ok(value),
+            Some(op) => {
+                let current = if self.is_dot() { environment.dot() } else { environment.symbol(&self.symbol) };
+                let current = current.ok_or_else(|| ScriptError::Undefined(self.symbol.clone()))?;
+                op.apply(current, value)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(u64),
+    Symbol(String),
+    /// The location counter
+    Dot,
+    Call(Function, Vec<Expr>),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Complement(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Whether the value depends on the location counter
+    pub fn uses_dot(&self) -> bool {
+        let mut found = false;
+        self.visit(&mut |expr| found |= *expr == Expr::Dot || matches!(expr, Expr::Call(Function::Align, args) if args.len() == 1));
+        found
+    }
+
+    /// The alignment operands of the `ALIGN`s in this expression
+    pub fn alignments(&self) -> Vec<&Expr> {
+        let mut alignments = Vec::new();
+        self.visit(&mut |expr| {
+            if let Expr::Call(Function::Align, args) = expr {
+                alignments.extend(args.last());
+            }
+        });
+        alignments
+    }
+
+    fn visit<'a>(&'a self, f: &mut dyn FnMut(&'a Expr)) {
+        f(self);
+        match self {
+            Expr::Call(_, args) => args.iter().for_each(|arg| arg.visit(f)),
+            Expr::Negate(e) | Expr::Not(e) | Expr::Complement(e) => e.visit(f),
+            Expr::Binary(_, a, b) => {
+                a.visit(f);
+                b.visit(f);
+            }
+            Expr::Conditional(condition, a, b) => {
+                condition.visit(f);
+                a.visit(f);
+                b.visit(f);
+            }
+            Expr::Number(_) | Expr::Symbol(_) | Expr::Dot => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    /// `ALIGN(n)` aligns `.`; `ALIGN(x, n)` aligns `x`
+    Align,
+    Origin,
+    Length,
+    Addr,
+    LoadAddr,
+    SizeOf,
+    Defined,
+    Absolute,
+    Max,
+    Min,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+impl BinaryOp {
+    fn apply(self, a: u64, b: u64) -> Result<u64, ScriptError> {
+        Ok(match self {
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div => a.checked_div(b).ok_or(ScriptError::DivisionByZero)?,
+            BinaryOp::Rem => a.checked_rem(b).ok_or(ScriptError::DivisionByZero)?,
+            BinaryOp::Shl => a.wrapping_shl(b as u32),
+            BinaryOp::Shr => a.wrapping_shr(b as u32),
+            BinaryOp::And => a & b,
+            BinaryOp::Or => a | b,
+            BinaryOp::Xor => a ^ b,
+            BinaryOp::Eq => (a == b) as u64,
+            BinaryOp::Ne => (a != b) as u64,
+            BinaryOp::Lt => (a < b) as u64,
+            BinaryOp::Le => (a <= b) as u64,
+            BinaryOp::Gt => (a > b) as u64,
+            BinaryOp::Ge => (a >= b) as u64,
+            BinaryOp::LogicalAnd => (a != 0 && b != 0) as u64,
+            BinaryOp::LogicalOr => (a != 0 || b != 0) as u64,
+        })
+    }
+
+    /// Binding strength, higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::LogicalOr => 1,
+            BinaryOp::LogicalAnd => 2,
+            BinaryOp::Or => 3,
+            BinaryOp::Xor => 4,
+            BinaryOp::And => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+        }
+    }
+}
+
+/// Operators, longest first
+const OPERATORS: &[(&str, BinaryOp)] = &[
+    ("<<", BinaryOp::Shl),
+    (">>", BinaryOp::Shr),
+    ("==", BinaryOp::Eq),
+    ("!=", BinaryOp::Ne),
+    ("<=", BinaryOp::Le),
+    (">=", BinaryOp::Ge),
+    ("&&", BinaryOp::LogicalAnd),
+    ("||", BinaryOp::LogicalOr),
+    ("+", BinaryOp::Add),
+    ("-", BinaryOp::Sub),
+    ("*", BinaryOp::Mul),
+    ("/", BinaryOp::Div),
+    ("%", BinaryOp::Rem),
+    ("&", BinaryOp::And),
+    ("|", BinaryOp::Or),
+    ("^", BinaryOp::Xor),
+    ("<", BinaryOp::Lt),
+    (">", BinaryOp::Gt),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionProperty {
+    Address,
+    LoadAddress,
+    Size,
+}
+
+/// What expressions refer to, as far as the linker knows it so far; `None`
+/// for what isn't known (yet)
+pub trait Environment {
+    fn dot(&self) -> Option<u64>;
+    fn symbol(&self, name: &str) -> Option<u64>;
+    fn section(&self, name: &str, property: SectionProperty) -> Option<u64>;
+}
+
+impl LinkerScript {
+    pub fn load(path: &Path) -> Result<Self, ScriptError> {
+        let text = fs::read_to_string(path).map_err(|e| ScriptError::Io(path.to_path_buf(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut parser = Parser { text, position: 0 };
+        let mut script = LinkerScript::default();
+        loop {
+            parser.skip_blank();
+            if parser.at_end() {
+                return Ok(script);
+            }
+            parser.command(&mut script)?;
+        }
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &OutputDescription> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Output(output) => Some(output),
+            _ => None,
+        })
+    }
+
+    pub fn region(&self, name: &str) -> Result<&MemoryRegion, ScriptError> {
+        self.regions
+            .iter()
+            .find(|region| region.name == name)
+            .ok_or_else(|| ScriptError::UndefinedRegion(name.to_string()))
+    }
+
+    /// The first output section and command of that section whose pattern
+    /// takes `section` of `file`, as ld picks them
+    pub fn place(&self, file: &str, section: &str) -> Option<(usize, usize, &InputPattern)> {
+        self.outputs().enumerate().find_map(|(index, output)| {
+            output.commands.iter().enumerate().find_map(|(command, c)| match c {
+                SectionCommand::Input(pattern) if pattern.matches(file, section) => Some((index, command, pattern)),
+                _ => None,
+            })
+        })
+    }
+
+    pub fn evaluate(&self, expr: &Expr, environment: &dyn Environment) -> Result<u64, ScriptError> {
+        let eval = |expr: &Expr| self.evaluate(expr, environment);
+        // The parser checked the argument counts
+        let name = |args: &[Expr]| match &args[0] {
+            Expr::Symbol(name) => name.clone(),
+            other => format!("{:?}", other),
+        };
+        let dot = || environment.dot().ok_or_else(|| ScriptError::Undefined(".".to_string()));
+        match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::Dot => dot(),
+            Expr::Symbol(symbol) => environment.symbol(symbol).ok_or_else(|| ScriptError::Undefined(symbol.clone())),
+            Expr::Negate(e) => Ok(eval(e)?.wrapping_neg()),
+            Expr::Not(e) => Ok((eval(e)? == 0) as u64),
+            Expr::Complement(e) => Ok(!eval(e)?),
+            Expr::Binary(op, a, b) => op.apply(eval(a)?, eval(b)?),
+            Expr::Conditional(condition, a, b) => {
+                if eval(condition)? != 0 {
+                    eval(a)
+                } else {
+                    eval(b)
+                }
+            }
+            Expr::Call(function, args) => match function {
+                Function::Align => {
+                    let (value, align) = match args.as_slice() {
+                        [align] => (dot()?, eval(align)?),
+                        [value, align, ..] => (eval(value)?, eval(align)?),
+                        [] => unreachable!(),
+                    };
+                    Ok(value.div_ceil(align.max(1)) * align.max(1))
+                }
+                Function::Origin => Ok(self.region(&name(args))?.origin),
+                Function::Length => Ok(self.region(&name(args))?.length),
+                Function::Addr | Function::LoadAddr | Function::SizeOf => {
+                    let section = name(args);
+                    let property = match function {
+                        Function::Addr => SectionProperty::Address,
+                        Function::LoadAddr => SectionProperty::LoadAddress,
+                        _ => SectionProperty::Size,
+                    };
+                    environment.section(&section, property).ok_or(ScriptError::Undefined(section))
+                }
+                Function::Defined => Ok(environment.symbol(&name(args)).is_some() as u64),
+                Function::Absolute => eval(&args[0]),
+                Function::Max => Ok(eval(&args[0])?.max(eval(&args[1])?)),
+                Function::Min => Ok(eval(&args[0])?.min(eval(&args[1])?)),
+            },
+        }
+    }
+}
+
+/// `*` matches any run of characters and `?` any one
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.text.len()
+    }
+
+    fn line(&self) -> usize {
+        self.text[..self.position].matches('\n').count() + 1
+    }
+
+    fn error(&self, message: impl Into<String>) -> ScriptError {
+        ScriptError::Syntax { line: self.line(), message: message.into() }
+    }
+
+    /// Whitespace and `/* */` comments
+    fn skip_blank(&mut self) {
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.position = self.text.len() - trimmed.len();
+            match trimmed.strip_prefix("/*") {
+                Some(comment) => match comment.find("*/") {
+                    Some(end) => self.position += 2 + end + 2,
+                    None => self.position = self.text.len(),
+                },
+                None => return,
+            }
+        }
+    }
+
+    fn peek(&mut self, token: &str) -> bool {
+        self.skip_blank();
+        self.rest().starts_with(token)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.peek(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ScriptError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            let found: String = self.rest().chars().take(16).collect();
+            Err(self.error(format!("expected '{}' before '{}'", token, found)))
+        }
+    }
+
+    /// A section name, file pattern or symbol: anything up to a delimiter
+    fn name(&mut self) -> Result<String, ScriptError> {
+        self.skip_blank();
+        if let Some(quoted) = self.rest().strip_prefix('"') {
+            let end = quoted.find('"').ok_or_else(|| self.error("unterminated string"))?;
+            let name = quoted[..end].to_string();
+            self.position += end + 2;
+            return Ok(name);
+        }
+        let length = self
+            .rest()
+            .find(|c: char| c.is_whitespace() || "(){};,=:\"".contains(c))
+            .unwrap_or(self.rest().len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = self.rest()[..length].to_string();
+        self.position += length;
+        Ok(name)
+    }
+
+    /// A keyword followed by `(`
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip_blank();
+        let rest = self.rest();
+        let after = &rest[keyword.len().min(rest.len())..];
+        if rest.starts_with(keyword) && after.trim_start().starts_with('(') {
+            self.position += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skip a parenthesized argument list
+    fn skip_arguments(&mut self) -> Result<(), ScriptError> {
+        self.expect("(")?;
+        let close = self.rest().find(')').ok_or_else(|| self.error("unterminated argument list"))?;
+        self.position += close + 1;
+        Ok(())
+    }
+
+    fn command(&mut self, script: &mut LinkerScript) -> Result<(), ScriptError> {
+        if self.eat(";") {
+            return Ok(());
+        }
+        if self.keyword("ENTRY") {
+            self.expect("(")?;
+            script.entry = Some(self.name()?);
+            return self.expect(")");
+        }
+        for ignored in ["OUTPUT_FORMAT", "OUTPUT_ARCH", "SEARCH_DIR", "TARGET"] {
+            if self.keyword(ignored) {
+                return self.skip_arguments();
+            }
+        }
+        if self.peek("MEMORY") {
+            self.position += "MEMORY".len();
+            return self.memory(script);
+        }
+        if self.peek("SECTIONS") {
+            self.position += "SECTIONS".len();
+            self.expect("{")?;
+            while !self.eat("}") {
+                if self.at_end() {
+                    return Err(self.error("unterminated SECTIONS"));
+                }
+                self.sections_item(script)?;
+            }
+            return Ok(());
+        }
+        if let Some(item) = self.statement()? {
+            script.items.push(item);
+            return Ok(());
+        }
+        Err(self.unsupported())
+    }
+
+    fn unsupported(&mut self) -> ScriptError {
+        let line = self.line();
+        let command = self.name().unwrap_or_default();
+        ScriptError::Unsupported { line, command }
+    }
+
+    fn memory(&mut self, script: &mut LinkerScript) -> Result<(), ScriptError> {
+        self.expect("{")?;
+        while !self.eat("}") {
+            if self.at_end() {
+                return Err(self.error("unterminated MEMORY"));
+            }
+            let name = self.name()?;
+            let attributes = if self.eat("(") {
+                let close = self.rest().find(')').ok_or_else(|| self.error("unterminated attributes"))?;
+                let attributes = self.rest()[..close].trim().to_string();
+                self.position += close + 1;
+                attributes
+            } else {
+                String::new()
+            };
+            self.expect(":")?;
+            let mut origin = None;
+            let mut length = None;
+            for _ in 0..2 {
+                let key = self.name()?;
+                self.expect("=")?;
+                let value = self.expression()?;
+                let value = self.constant(&value, script)?;
+                match key.as_str() {
+                    "ORIGIN" | "org" | "o" => origin = Some(value),
+                    "LENGTH" | "len" | "l" => length = Some(value),
+                    _ => return Err(self.error(format!("unknown MEMORY attribute '{}'", key))),
+                }
+                self.eat(",");
+            }
+            match (origin, length) {
+                (Some(origin), Some(length)) => script.regions.push(MemoryRegion { name, attributes, origin, length }),
+                _ => return Err(self.error(format!("region {} needs ORIGIN and LENGTH", name))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Region bounds can only use numbers and earlier regions
+    fn constant(&self, expr: &Expr, script: &LinkerScript) -> Result<u64, ScriptError> {
+        struct Nothing;
+        impl Environment for Nothing {
+            fn dot(&self) -> Option<u64> {
+                None
+            }
+            fn symbol(&self, _: &str) -> Option<u64> {
+                None
+            }
+            fn section(&self, _: &str, _: SectionProperty) -> Option<u64> {
+                None
+            }
+        }
+        script.evaluate(expr, &Nothing).map_err(|e| self.error(e.to_string()))
+    }
+
+    /// An assignment, `PROVIDE` or `ASSERT`; `None` if it's something else
+    fn statement(&mut self) -> Result<Option<Item>, ScriptError> {
+        for (keyword, provide) in [("PROVIDE_HIDDEN", true), ("PROVIDE", true), ("HIDDEN", false)] {
+            if self.keyword(keyword) {
+                self.expect("(")?;
+                let symbol = self.name()?;
+                let mut assignment = self.assignment(symbol)?.ok_or_else(|| self.error("expected an assignment"))?;
+                assignment.provide = provide;
+                self.expect(")")?;
+                self.eat(";");
+                return Ok(Some(Item::Assign(assignment)));
+            }
+        }
+        if self.keyword("ASSERT") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(",")?;
+            let message = self.name()?;
+            self.expect(")")?;
+            self.eat(";");
+            return Ok(Some(Item::Assert { condition, message }));
+        }
+
+        let start = self.position;
+        let Ok(symbol) = self.name() else { return Ok(None) };
+        match self.assignment(symbol)? {
+            Some(assignment) => {
+                self.expect(";")?;
+                Ok(Some(Item::Assign(assignment)))
+            }
+            None => {
+                self.position = start;
+                Ok(None)
+            }
+        }
+    }
+
+    /// The rest of `symbol = expr`, if an assignment operator follows
+    fn assignment(&mut self, symbol: String) -> Result<Option<Assignment>, ScriptError> {
+        self.skip_blank();
+        let compound_ops = OPERATORS.iter().filter(|(_, op)| {
+            matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Shl | BinaryOp::Shr | BinaryOp::And | BinaryOp::Or)
+        });
+        for (text, op) in compound_ops {
+            let compound = format!("{}=", text);
+            if self.rest().starts_with(&compound) {
+                self.position += compound.len();
+                let expr = self.expression()?;
+                return Ok(Some(Assignment { symbol, op: Some(*op), expr, provide: false }));
+            }
+        }
+        if self.rest().starts_with('=') && !self.rest().starts_with("==") {
+            self.position += 1;
+            let expr = self.expression()?;
+            return Ok(Some(Assignment { symbol, op: None, expr, provide: false }));
+        }
+        Ok(None)
+    }
+
+    fn sections_item(&mut self, script: &mut LinkerScript) -> Result<(), ScriptError> {
+        if self.eat(";") {
+            return Ok(());
+        }
+        if self.keyword("ENTRY") {
+            self.expect("(")?;
+            script.entry = Some(self.name()?);
+            return self.expect(")");
+        }
+        if let Some(item) = self.statement()? {
+            script.items.push(item);
+            return Ok(());
+        }
+        let output = self.output_description()?;
+        script.items.push(Item::Output(output));
+        Ok(())
+    }
+
+    fn output_description(&mut self) -> Result<OutputDescription, ScriptError> {
+        let name = self.name()?;
+        let mut output = OutputDescription {
+            name,
+            address: None,
+            align: None,
+            load_address: None,
+            noload: false,
+            commands: Vec::new(),
+            region: None,
+            load_region: None,
+        };
+
+        // Address and type, before the colon
+        while !self.eat(":") {
+            let start = self.position;
+            if self.eat("(") {
+                let kind = self.name()?;
+                match kind.as_str() {
+                    "NOLOAD" => output.noload = true,
+                    "COPY" | "INFO" | "OVERLAY" => return Err(self.error(format!("({}) sections are not supported", kind))),
+                    _ if output.address.is_none() => {
+                        // A parenthesized address
+                        self.position = start;
+                        output.address = Some(self.expression()?);
+                        continue;
+                    }
+                    _ => return Err(self.error(format!("unknown section type {}", kind))),
+                }
+                self.expect(")")?;
+            } else if output.address.is_none() {
+                output.address = Some(self.expression()?);
+            } else {
+                return Err(self.error(format!("expected ':' after output section {}", output.name)));
+            }
+        }
+
+        // Load address and alignment, before the brace
+        while !self.eat("{") {
+            if self.keyword("AT") {
+                self.expect("(")?;
+                output.load_address = Some(self.expression()?);
+                self.expect(")")?;
+            } else if self.keyword("ALIGN") {
+                self.expect("(")?;
+                output.align = Some(self.expression()?);
+                self.expect(")")?;
+            } else if self.keyword("SUBALIGN") {
+                return Err(self.error("SUBALIGN is not supported"));
+            } else {
+                return Err(self.error(format!("expected '{{' in output section {}", output.name)));
+            }
+        }
+
+        while !self.eat("}") {
+            if self.at_end() {
+                return Err(self.error(format!("unterminated output section {}", output.name)));
+            }
+            if self.eat(";") {
+                continue;
+            }
+            if let Some(item) = self.statement()? {
+                match item {
+                    Item::Assign(assignment) => output.commands.push(SectionCommand::Assign(assignment)),
+                    _ => return Err(self.error("ASSERT inside an output section is not supported")),
+                }
+                continue;
+            }
+            for data in ["BYTE", "SHORT", "LONG", "QUAD", "FILL", "CONSTRUCTORS", "INCLUDE"] {
+                if self.peek(data) {
+                    return Err(self.unsupported());
+                }
+            }
+            let keep = self.keyword("KEEP");
+            if keep {
+                self.expect("(")?;
+            }
+            let mut pattern = self.input_pattern()?;
+            pattern.keep = keep;
+            if keep {
+                self.expect(")")?;
+            }
+            output.commands.push(SectionCommand::Input(pattern));
+        }
+
+        // Regions and the rest, after the brace
+        loop {
+            if self.eat(">") {
+                output.region = Some(self.name()?);
+            } else if self.peek("AT") && self.rest()[2..].trim_start().starts_with('>') {
+                self.position += 2;
+                self.expect(">")?;
+                output.load_region = Some(self.name()?);
+            } else if self.peek(":") {
+                return Err(self.error("program headers (PHDRS) are not supported"));
+            } else if self.peek("=") && !self.peek("==") {
+                return Err(self.error("fill patterns are not supported"));
+            } else {
+                self.eat(",");
+                return Ok(output);
+            }
+        }
+    }
+
+    /// `file(section section ...)`, where the file pattern and each section
+    /// pattern may be wrapped in `SORT(...)`
+    fn input_pattern(&mut self) -> Result<InputPattern, ScriptError> {
+        let mut sort = false;
+        let file = if self.keyword("SORT") || self.keyword("SORT_BY_NAME") {
+            self.expect("(")?;
+            let file = self.name()?;
+            self.expect(")")?;
+            file
+        } else {
+            self.name()?
+        };
+        let mut pattern = InputPattern { file, exclude_files: Vec::new(), sections: Vec::new(), keep: false, sort: false };
+        self.expect("(")?;
+        while !self.eat(")") {
+            if self.at_end() {
+                return Err(self.error("unterminated input section list"));
+            }
+            if self.keyword("EXCLUDE_FILE") {
+                self.expect("(")?;
+                while !self.eat(")") {
+                    pattern.exclude_files.push(self.name()?);
+                }
+                continue;
+            }
+            let sorted = ["SORT_BY_INIT_PRIORITY", "SORT_BY_NAME", "SORT_BY_ALIGNMENT", "SORT"]
+                .into_iter()
+                .any(|keyword| self.keyword(keyword));
+            if sorted {
+                sort = true;
+                self.expect("(")?;
+                while !self.eat(")") {
+                    pattern.sections.push(self.name()?);
+                }
+                continue;
+            }
+            pattern.sections.push(self.name()?);
+        }
+        pattern.sort = sort;
+        Ok(pattern)
+    }
+
+    fn expression(&mut self) -> Result<Expr, ScriptError> {
+        let condition = self.binary(0)?;
+        if self.eat("?") {
+            let a = self.expression()?;
+            self.expect(":")?;
+            let b = self.expression()?;
+            return Ok(Expr::Conditional(Box::new(condition), Box::new(a), Box::new(b)));
+        }
+        Ok(condition)
+    }
+
+    /// Operators binding tighter than `min`
+    fn binary(&mut self, min: u8) -> Result<Expr, ScriptError> {
+        let mut left = self.unary()?;
+        loop {
+            self.skip_blank();
+            let rest = self.rest();
+            let Some(&(text, op)) = OPERATORS.iter().find(|(text, _)| rest.starts_with(text)) else { return Ok(left) };
+            // `x += 1` ends the expression before it
+            let assignment = rest[text.len()..].starts_with('=') && !matches!(op, BinaryOp::Lt | BinaryOp::Gt);
+            if op.precedence() <= min || assignment {
+                return Ok(left);
+            }
+            self.position += text.len();
+            let right = self.binary(op.precedence())?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ScriptError> {
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Expr::Complement(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.expression()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ScriptError> {
+        self.skip_blank();
+        let rest = self.rest();
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let length = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let literal = &rest[..length];
+            self.position += length;
+            return parse_number(literal).map(Expr::Number).ok_or_else(|| self.error(format!("bad number '{}'", literal)));
+        }
+
+        let length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_.$".contains(c)))
+            .unwrap_or(rest.len());
+        let word = &rest[..length];
+        if word.is_empty() {
+            return Err(self.error("expected an expression"));
+        }
+        self.position += length;
+        if word == "." {
+            return Ok(Expr::Dot);
+        }
+        let function = match word {
+            "ALIGN" => Some(Function::Align),
+            "ORIGIN" | "org" => Some(Function::Origin),
+            "LENGTH" | "len" => Some(Function::Length),
+            "ADDR" => Some(Function::Addr),
+            "LOADADDR" => Some(Function::LoadAddr),
+            "SIZEOF" => Some(Function::SizeOf),
+            "DEFINED" => Some(Function::Defined),
+            "ABSOLUTE" => Some(Function::Absolute),
+            "MAX" => Some(Function::Max),
+            "MIN" => Some(Function::Min),
+            _ => None,
+        };
+        match function {
+            Some(function) if self.peek("(") => {
+                self.expect("(")?;
+                let mut args = Vec::new();
+                while !self.eat(")") {
+                    args.push(match function {
+                        // Names that aren't expressions
+                        Function::Origin | Function::Length | Function::Addr | Function::LoadAddr | Function::SizeOf | Function::Defined => {
+                            Expr::Symbol(self.name()?)
+                        }
+                        _ => self.expression()?,
+                    });
+                    self.eat(",");
+                }
+                let arity = match function {
+                    Function::Align => 1..=2,
+                    Function::Max | Function::Min => 2..=2,
+                    _ => 1..=1,
+                };
+                if !arity.contains(&args.len()) {
+                    return Err(self.error(format!("wrong number of arguments to {}", word)));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            _ => Ok(Expr::Symbol(word.to_string())),
+        }
+    }
+}
+
+/// `0x1000`, `4096`, `0777`, with an optional `K` or `M` multiplier
+fn parse_number(literal: &str) -> Option<u64> {
+    let (digits, multiplier) = match literal.as_bytes().last()? {
+        b'K' | b'k' => (&literal[..literal.len() - 1], 1024),
+        b'M' | b'm' => (&literal[..literal.len() - 1], 1024 * 1024),
+        _ => (literal, 1),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if let Some(hex) = digits.strip_suffix('h').or_else(|| digits.strip_suffix('H')) {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    value.checked_mul(multiplier)
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(PathBuf, io::Error),
+    Syntax { line: usize, message: String },
+    /// A command outside the supported subset
+    Unsupported { line: usize, command: String },
+    UndefinedRegion(String),
+    /// A symbol or section an expression needs that isn't known
+    Undefined(String),
+    DivisionByZero,
+    /// An `ASSERT` that failed, with its message
+    Assertion(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ScriptError::Syntax { line, message } => write!(f, "linker script line {}: {}", line, message),
+            ScriptError::Unsupported { line, command } => {
+                write!(f, "linker script line {}: '{}' is not supported", line, command)
+            }
+            ScriptError::UndefinedRegion(name) => write!(f, "linker script: no memory region {}", name),
+            ScriptError::Undefined(name) => write!(f, "linker script: '{}' is not defined here", name),
+            ScriptError::DivisionByZero => write!(f, "linker script: division by zero"),
+            ScriptError::Assertion(message) => write!(f, "linker script assertion failed: {}", message),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ScriptError> {
+    let script = LinkerScript::parse(r#"
+        ENTRY(reset_handler)
+        MEMORY {
+            FLASH (rx)  : ORIGIN = 0x08000000, LENGTH = 512K
+            RAM   (rwx) : ORIGIN = 0x20000000, LENGTH = 128K
+        }
+        SECTIONS {
+            .vectors : { KEEP(*(.vectors)) } > FLASH
+            .text : { *(.text .text.*) *(.rodata*) . = ALIGN(4); _etext = .; } > FLASH
+            .data : { _sdata = .; *(.data*) _edata = .; } > RAM AT > FLASH
+            _sidata = LOADADDR(.data);
+            .bss (NOLOAD) : { _sbss = .; *(.bss*) *(COMMON) _ebss = .; } > RAM
+            /DISCARD/ : { *(.comment) }
+        }
+        _estack = ORIGIN(RAM) + LENGTH(RAM);
+    "#)?;
+    assert_eq!(script.region("FLASH")?.length, 512 * 1024);
+    let (output, _, pattern) = script.place("startup.o", ".vectors").unwrap();
+    assert!(output == 0 && pattern.keep);
+    Ok(())
+}
+*/
@@ -0,0 +1,2023 @@
+// src/linker/static_elf.rs
+//! Built-in static ELF linker
+//! Links relocatable objects and archives into a static, non-PIE executable
+//! without the system linker, so compiled mode can target x86_64 and aarch64
+//! Linux from any host. It is meant for -nostdlib and bundled-libc programs,
+//! which need nothing from a dynamic loader: `_start` comes from `Crt0` or
+//! the bundled crt1 and calls main.
+//!
+//! The image has three PT_LOAD segments: the headers and .rodata (R), .text
+//! (RX), then .init_array, .fini_array, the GOT, .data and .bss (RW). Each
+//! segment starts on a fresh page of address space while the file stays
+//! dense. Every address is known at link time, so calls go straight to their
+//! target without a PLT, `mov foo@GOTPCREL(%rip)` is relaxed to `lea`, and
+//! the remaining GOT loads read a GOT filled in here. Archive members are
+//! pulled in while they define a symbol that is still undefined, across all
+//! archives as with --start-group. .eh_frame, notes and non-allocated
+//! sections are dropped, except the provenance notes of
+//! `report::provenance`, which are concatenated into an unloaded section of
+//! the same name; thread-local storage isn't supported.
+//!
+//! With `with_gc_sections`, as with ld's --gc-sections, only the input
+//! sections reachable through relocations from the entry point, the kept
+//! symbols, .init_array, .fini_array and `SHF_GNU_RETAIN` sections are
+//! linked. Objects compiled with a section per function and variable lose
+//! every unused one; `LinkSummary` reports what went.
+//!
+//! With `with_script`, a `LinkerScript` replaces the fixed layout: output
+//! sections are the script's, in its order, each filled by the first rule
+//! whose pattern takes an input section, at the address, alignment and
+//! memory region the script gives and loaded at its `AT` address. Each
+//! output section gets its own PT_LOAD with the load address as p_paddr,
+//! which is what `objcopy -O binary` and flashers go by. `KEEP` sections are
+//! roots for garbage collection, and a section that outgrows its region is
+//! an error rather than a silently broken image.
+//!
+//! Global symbols resolve by the rules in `symbols`: strong over common over
+//! weak, with GNU versions, so `foo@@V2` answers to `foo` and `foo@V2` while
+//! `foo@V1` only to itself. Of the COMDAT groups with one signature, such as
+//! the inline functions and template instances each object emits, the
+//! first is linked and the rest are folded away.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use object::elf;
+use object::read::archive::ArchiveFile;
+use object::write::elf::{FileHeader, ProgramHeader, SectionHeader, Sym, Writer};
+use object::{
+    Architecture as ObjectArchitecture, BinaryFormat, Endianness, Object, ObjectComdat, ObjectKind, ObjectSection, ObjectSymbol,
+    Relocation, RelocationEncoding, RelocationKind, RelocationTarget, SectionFlags, SectionIndex, SectionKind,
+    SymbolFlags, SymbolSection,
+};
+use crate::arch::Architecture;
+use crate::linker::symbols::{self, Resolution, Strength, Versioned};
+use crate::report::provenance;
+use crate::linker::script::{
+    Assignment, Environment, Item, LinkerScript, MemoryRegion, OutputDescription, ScriptError, SectionCommand, SectionProperty,
+    DISCARD,
+};
+
+/// Where the image is loaded unless told otherwise, as with ld
+pub const DEFAULT_BASE_ADDRESS: u64 = 0x400000;
+
+/// Symbol the executable starts at unless told otherwise
+pub const DEFAULT_ENTRY: &str = "_start";
+
+const GOT_ENTRY_SIZE: u64 = 8;
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const AARCH64_NOP: u32 = 0xd503201f;
+/// Not in `object::elf` yet
+const SHF_GNU_RETAIN: u64 = 0x200000;
+
+/// Output sections, in address order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Output {
+    Rodata,
+    Text,
+    InitArray,
+    FiniArray,
+    Got,
+    Data,
+    Bss,
+}
+
+impl Output {
+    const ALL: [Output; 7] = [
+        Output::Rodata,
+        Output::Text,
+        Output::InitArray,
+        Output::FiniArray,
+        Output::Got,
+        Output::Data,
+        Output::Bss,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Output::Rodata => ".rodata",
+            Output::Text => ".text",
+            Output::InitArray => ".init_array",
+            Output::FiniArray => ".fini_array",
+            Output::Got => ".got",
+            Output::Data => ".data",
+            Output::Bss => ".bss",
+        }
+    }
+
+    /// Index into `SEGMENT_FLAGS`
+    fn segment(self) -> usize {
+        match self {
+            Output::Rodata => 0,
+            Output::Text => 1,
+            _ => 2,
+        }
+    }
+
+    fn sh_type(self) -> u32 {
+        match self {
+            Output::InitArray => elf::SHT_INIT_ARRAY,
+            Output::FiniArray => elf::SHT_FINI_ARRAY,
+            Output::Bss => elf::SHT_NOBITS,
+            _ => elf::SHT_PROGBITS,
+        }
+    }
+
+    fn sh_flags(self) -> u64 {
+        u64::from(match self {
+            Output::Rodata => elf::SHF_ALLOC,
+            Output::Text => elf::SHF_ALLOC | elf::SHF_EXECINSTR,
+            _ => elf::SHF_ALLOC | elf::SHF_WRITE,
+        })
+    }
+}
+
+const SEGMENT_FLAGS: [u32; 3] = [elf::PF_R, elf::PF_R | elf::PF_X, elf::PF_R | elf::PF_W];
+
+/// Index of an output section; without a script, `Output as usize`
+type OutputId = usize;
+
+#[derive(Default)]
+struct OutputSection {
+    name: String,
+    sh_type: u32,
+    sh_flags: u64,
+    entsize: u64,
+    /// A script's `(NOLOAD)`: no PT_LOAD
+    noload: bool,
+
+    // Contents; empty for SHT_NOBITS
+    data: Vec<u8>,
+    size: u64,
+    align: u64,
+
+    // Assigned by `layout`; the load address is the address unless a
+    // script says otherwise
+    offset: u64,
+    address: u64,
+    load_address: u64,
+}
+
+impl OutputSection {
+    fn new(name: &str, sh_type: u32, sh_flags: u64) -> Self {
+        let entsize = if matches!(sh_type, elf::SHT_INIT_ARRAY | elf::SHT_FINI_ARRAY) { 8 } else { 0 };
+        OutputSection { name: name.to_string(), sh_type, sh_flags, entsize, ..Default::default() }
+    }
+
+    fn builtin(output: Output) -> Self {
+        let mut section = OutputSection::new(output.name(), output.sh_type(), output.sh_flags());
+        if output == Output::Got {
+            section.entsize = GOT_ENTRY_SIZE;
+        }
+        section
+    }
+
+    fn has_data(&self) -> bool {
+        self.sh_type != elf::SHT_NOBITS
+    }
+
+    /// Add an input section's contents at the next `align`ed offset, which
+    /// is returned; `data` is empty for an input .bss, which is zeros here
+    fn append(&mut self, data: &[u8], size: u64, align: u64) -> u64 {
+        let align = align.max(1);
+        let offset = align_up(self.size, align);
+        self.align = self.align.max(align);
+        self.size = offset + size;
+        if self.has_data() {
+            self.data.resize(offset as usize, 0);
+            self.data.extend_from_slice(data);
+            self.data.resize(self.size as usize, 0);
+        }
+        offset
+    }
+
+    fn segment_flags(&self) -> u32 {
+        let mut flags = elf::PF_R;
+        if self.sh_flags & u64::from(elf::SHF_EXECINSTR) != 0 {
+            flags |= elf::PF_X;
+        }
+        if self.sh_flags & u64::from(elf::SHF_WRITE) != 0 {
+            flags |= elf::PF_W;
+        }
+        flags
+    }
+}
+
+struct Segment {
+    flags: u32,
+    offset: u64,
+    address: u64,
+    physical_address: u64,
+    file_size: u64,
+    memory_size: u64,
+}
+
+/// Where an input section went
+#[derive(Clone, Copy)]
+struct Placement {
+    output: OutputId,
+    offset: u64,
+}
+
+/// A symbol's address, before addresses are assigned
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    At(OutputId, u64),
+    /// Just past the end of the output section
+    End(OutputId),
+    Absolute(u64),
+}
+
+#[derive(Clone, Copy)]
+struct Definition {
+    value: Value,
+    strength: Strength,
+    st_info: u8,
+    size: u64,
+    /// Defining input; `None` for common and linker-provided symbols
+    file: Option<usize>,
+}
+
+/// A symbol a linker script assigns. Those relative to `.` inside an
+/// output section are known once sections are placed, the rest once they
+/// are laid out.
+struct ScriptSymbol<'data> {
+    assignment: &'data Assignment,
+    value: Option<Value>,
+    /// Outside any output section
+    top_level: bool,
+    /// Not a `PROVIDE` of something an input defines
+    applies: bool,
+}
+
+/// A GOT slot is shared by every access to the same symbol
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum GotKey<'data> {
+    Global(&'data str),
+    Local(usize, usize),
+    Absolute,
+}
+
+/// The symbol a relocation refers to
+struct Resolved<'data> {
+    name: &'data str,
+    /// `None` for an undefined weak symbol, which is zero
+    value: Option<Value>,
+    got: GotKey<'data>,
+}
+
+/// One relocation, ready to apply: symbol address `s`, addend `a`, place
+/// `p`, and the address of the symbol's GOT slot if it has one
+struct Fixup {
+    r_type: u32,
+    s: u64,
+    a: i64,
+    p: u64,
+    got: u64,
+    /// Not an undefined weak symbol
+    defined: bool,
+}
+
+/// Why a relocation couldn't be applied
+enum Apply {
+    Overflow,
+    Unsupported,
+    OutOfBounds,
+}
+
+struct Input<'data> {
+    name: String,
+    file: object::File<'data>,
+}
+
+/// What differs between the supported targets
+#[derive(Clone, Copy)]
+struct Machine {
+    architecture: Architecture,
+    e_machine: u16,
+    page_size: u64,
+}
+
+impl Machine {
+    fn for_architecture(architecture: Architecture) -> Result<Self, StaticLinkError> {
+        match architecture {
+            Architecture::X86_64 => Ok(Machine { architecture, e_machine: elf::EM_X86_64, page_size: 0x1000 }),
+            // Kernels may use 64K pages
+            Architecture::AArch64 => Ok(Machine { architecture, e_machine: elf::EM_AARCH64, page_size: 0x10000 }),
+            Architecture::Arm => Err(StaticLinkError::Unsupported(format!("the built-in linker does not support {}", architecture))),
+        }
+    }
+
+    fn object_architecture(&self) -> ObjectArchitecture {
+        match self.architecture {
+            Architecture::AArch64 => ObjectArchitecture::Aarch64,
+            _ => ObjectArchitecture::X86_64,
+        }
+    }
+
+    /// The ELF relocation type `object` decoded `relocation` from
+    fn relocation_type(&self, relocation: &Relocation) -> Option<u32> {
+        use RelocationKind::*;
+        let r_type = match (self.architecture, relocation.kind(), relocation.size()) {
+            (_, Elf(r_type), _) => r_type,
+            (Architecture::X86_64, Absolute, 64) => elf::R_X86_64_64,
+            (Architecture::X86_64, Absolute, 32) if relocation.encoding() == RelocationEncoding::X86Signed => {
+                elf::R_X86_64_32S
+            }
+            (Architecture::X86_64, Absolute, 32) => elf::R_X86_64_32,
+            (Architecture::X86_64, Absolute, 16) => elf::R_X86_64_16,
+            (Architecture::X86_64, Absolute, 8) => elf::R_X86_64_8,
+            (Architecture::X86_64, Relative, 32) => elf::R_X86_64_PC32,
+            (Architecture::X86_64, Relative, 16) => elf::R_X86_64_PC16,
+            (Architecture::X86_64, Relative, 8) => elf::R_X86_64_PC8,
+            (Architecture::X86_64, PltRelative, 32) => elf::R_X86_64_PLT32,
+            (Architecture::X86_64, GotRelative, 32) => elf::R_X86_64_GOTPCREL,
+            (Architecture::AArch64, Absolute, 64) => elf::R_AARCH64_ABS64,
+            (Architecture::AArch64, Absolute, 32) => elf::R_AARCH64_ABS32,
+            (Architecture::AArch64, Absolute, 16) => elf::R_AARCH64_ABS16,
+            (Architecture::AArch64, Relative, 64) => elf::R_AARCH64_PREL64,
+            (Architecture::AArch64, Relative, 32) => elf::R_AARCH64_PREL32,
+            (Architecture::AArch64, Relative, 16) => elf::R_AARCH64_PREL16,
+            (Architecture::AArch64, PltRelative, 26) => elf::R_AARCH64_CALL26,
+            _ => return None,
+        };
+        Some(r_type)
+    }
+
+    /// Whether the relocation at `at` in `data` reads the symbol's GOT slot
+    /// rather than being relaxed to a direct reference
+    fn uses_got(&self, r_type: u32, data: &[u8], at: usize, defined: bool) -> bool {
+        match (self.architecture, r_type) {
+            (Architecture::X86_64, elf::R_X86_64_GOTPCREL) => true,
+            (Architecture::X86_64, elf::R_X86_64_GOTPCRELX | elf::R_X86_64_REX_GOTPCRELX) => {
+                !(defined && relaxable_mov(data, at))
+            }
+            (Architecture::AArch64, elf::R_AARCH64_ADR_GOT_PAGE | elf::R_AARCH64_LD64_GOT_LO12_NC) => true,
+            _ => false,
+        }
+    }
+
+    /// Patch the field at `at`
+    fn apply(&self, data: &mut [u8], at: usize, fixup: &Fixup) -> Result<(), Apply> {
+        let Fixup { r_type, a, defined, .. } = *fixup;
+        let (s, p, got) = (fixup.s as i64, fixup.p as i64, fixup.got as i64);
+        let sa = s.wrapping_add(a);
+        match self.architecture {
+            Architecture::X86_64 => match r_type {
+                elf::R_X86_64_NONE => Ok(()),
+                elf::R_X86_64_64 => store(data, at, sa, 8, i64::MIN, i64::MAX),
+                elf::R_X86_64_PC64 => store(data, at, sa.wrapping_sub(p), 8, i64::MIN, i64::MAX),
+                elf::R_X86_64_32 => store(data, at, sa, 4, 0, u32::MAX as i64),
+                elf::R_X86_64_32S => store(data, at, sa, 4, i32::MIN as i64, i32::MAX as i64),
+                elf::R_X86_64_PC32 | elf::R_X86_64_PLT32 => store(data, at, sa - p, 4, i32::MIN as i64, i32::MAX as i64),
+                elf::R_X86_64_16 => store(data, at, sa, 2, i16::MIN as i64, u16::MAX as i64),
+                elf::R_X86_64_PC16 => store(data, at, sa - p, 2, i16::MIN as i64, i16::MAX as i64),
+                elf::R_X86_64_8 => store(data, at, sa, 1, i8::MIN as i64, u8::MAX as i64),
+                elf::R_X86_64_PC8 => store(data, at, sa - p, 1, i8::MIN as i64, i8::MAX as i64),
+                elf::R_X86_64_GOTPCREL | elf::R_X86_64_GOTPCRELX | elf::R_X86_64_REX_GOTPCRELX => {
+                    if self.uses_got(r_type, data, at, defined) {
+                        store(data, at, got + a - p, 4, i32::MIN as i64, i32::MAX as i64)
+                    } else {
+                        // mov foo@GOTPCREL(%rip), %reg -> lea foo(%rip), %reg
+                        data[at - 2] = 0x8d;
+                        store(data, at, sa - p, 4, i32::MIN as i64, i32::MAX as i64)
+                    }
+                }
+                _ => Err(Apply::Unsupported),
+            },
+            Architecture::AArch64 => match r_type {
+                elf::R_AARCH64_NONE => Ok(()),
+                elf::R_AARCH64_ABS64 => store(data, at, sa, 8, i64::MIN, i64::MAX),
+                elf::R_AARCH64_ABS32 => store(data, at, sa, 4, i32::MIN as i64, u32::MAX as i64),
+                elf::R_AARCH64_ABS16 => store(data, at, sa, 2, i16::MIN as i64, u16::MAX as i64),
+                elf::R_AARCH64_PREL64 => store(data, at, sa.wrapping_sub(p), 8, i64::MIN, i64::MAX),
+                elf::R_AARCH64_PREL32 => store(data, at, sa - p, 4, i32::MIN as i64, u32::MAX as i64),
+                elf::R_AARCH64_PREL16 => store(data, at, sa - p, 2, i16::MIN as i64, u16::MAX as i64),
+                elf::R_AARCH64_MOVW_UABS_G0
+                | elf::R_AARCH64_MOVW_UABS_G0_NC
+                | elf::R_AARCH64_MOVW_UABS_G1
+                | elf::R_AARCH64_MOVW_UABS_G1_NC
+                | elf::R_AARCH64_MOVW_UABS_G2
+                | elf::R_AARCH64_MOVW_UABS_G2_NC
+                | elf::R_AARCH64_MOVW_UABS_G3 => {
+                    let group = (r_type - elf::R_AARCH64_MOVW_UABS_G0) / 2;
+                    let checked = r_type != elf::R_AARCH64_MOVW_UABS_G3 && (r_type - elf::R_AARCH64_MOVW_UABS_G0).is_multiple_of(2);
+                    if checked && (sa as u64) >> (16 * (group + 1)) != 0 {
+                        return Err(Apply::Overflow);
+                    }
+                    let imm = ((sa as u64) >> (16 * group)) & 0xffff;
+                    patch(data, at, |insn| (insn & !(0xffff << 5)) | ((imm as u32) << 5))
+                }
+                elf::R_AARCH64_ADR_PREL_LO21 => {
+                    let offset = sa - p;
+                    check_signed(offset, 21)?;
+                    patch(data, at, |insn| encode_adr(insn, offset))
+                }
+                elf::R_AARCH64_ADR_PREL_PG_HI21 | elf::R_AARCH64_ADR_PREL_PG_HI21_NC => {
+                    let pages = (page(sa) - page(p)) >> 12;
+                    if r_type == elf::R_AARCH64_ADR_PREL_PG_HI21 {
+                        check_signed(pages, 21)?;
+                    }
+                    patch(data, at, |insn| encode_adr(insn, pages))
+                }
+                elf::R_AARCH64_ADD_ABS_LO12_NC | elf::R_AARCH64_LDST8_ABS_LO12_NC => {
+                    patch(data, at, |insn| encode_imm12(insn, sa & 0xfff))
+                }
+                elf::R_AARCH64_LDST16_ABS_LO12_NC => patch(data, at, |insn| encode_imm12(insn, (sa & 0xfff) >> 1)),
+                elf::R_AARCH64_LDST32_ABS_LO12_NC => patch(data, at, |insn| encode_imm12(insn, (sa & 0xfff) >> 2)),
+                elf::R_AARCH64_LDST64_ABS_LO12_NC => patch(data, at, |insn| encode_imm12(insn, (sa & 0xfff) >> 3)),
+                elf::R_AARCH64_LDST128_ABS_LO12_NC => patch(data, at, |insn| encode_imm12(insn, (sa & 0xfff) >> 4)),
+                elf::R_AARCH64_TSTBR14 => {
+                    let offset = sa - p;
+                    check_signed(offset, 16)?;
+                    patch(data, at, |insn| (insn & !(0x3fff << 5)) | ((((offset >> 2) & 0x3fff) as u32) << 5))
+                }
+                elf::R_AARCH64_CONDBR19 => {
+                    let offset = sa - p;
+                    check_signed(offset, 21)?;
+                    patch(data, at, |insn| (insn & !(0x7ffff << 5)) | ((((offset >> 2) & 0x7ffff) as u32) << 5))
+                }
+                elf::R_AARCH64_JUMP26 | elf::R_AARCH64_CALL26 => {
+                    // A call to an undefined weak function does nothing
+                    if !defined {
+                        return patch(data, at, |_| AARCH64_NOP);
+                    }
+                    let offset = sa - p;
+                    check_signed(offset, 28)?;
+                    patch(data, at, |insn| (insn & !0x3ffffff) | ((offset >> 2) & 0x3ffffff) as u32)
+                }
+                elf::R_AARCH64_ADR_GOT_PAGE => {
+                    let pages = (page(got + a) - page(p)) >> 12;
+                    check_signed(pages, 21)?;
+                    patch(data, at, |insn| encode_adr(insn, pages))
+                }
+                elf::R_AARCH64_LD64_GOT_LO12_NC => patch(data, at, |insn| encode_imm12(insn, ((got + a) & 0xfff) >> 3)),
+                _ => Err(Apply::Unsupported),
+            },
+            Architecture::Arm => Err(Apply::Unsupported),
+        }
+    }
+}
+
+/// `mov foo@GOTPCREL(%rip), %reg`, which can become a `lea`
+fn relaxable_mov(data: &[u8], at: usize) -> bool {
+    at >= 2 && data.get(at - 2) == Some(&0x8b)
+}
+
+/// Store the low `bytes` of `value` at `at`, if it's within `min..=max`
+fn store(data: &mut [u8], at: usize, value: i64, bytes: usize, min: i64, max: i64) -> Result<(), Apply> {
+    if value < min || value > max {
+        return Err(Apply::Overflow);
+    }
+    let field = data.get_mut(at..at + bytes).ok_or(Apply::OutOfBounds)?;
+    field.copy_from_slice(&value.to_le_bytes()[..bytes]);
+    Ok(())
+}
+
+/// Rewrite the instruction at `at`
+fn patch(data: &mut [u8], at: usize, f: impl FnOnce(u32) -> u32) -> Result<(), Apply> {
+    let field = data.get_mut(at..at + 4).ok_or(Apply::OutOfBounds)?;
+    let insn = u32::from_le_bytes([field[0], field[1], field[2], field[3]]);
+    field.copy_from_slice(&f(insn).to_le_bytes());
+    Ok(())
+}
+
+fn check_signed(value: i64, bits: u32) -> Result<(), Apply> {
+    let limit = 1i64 << (bits - 1);
+    if value < -limit || value >= limit {
+        return Err(Apply::Overflow);
+    }
+    Ok(())
+}
+
+fn page(address: i64) -> i64 {
+    address & !0xfff
+}
+
+/// ADR/ADRP immediate: low two bits in 29-30, the rest in 5-23
+fn encode_adr(insn: u32, imm: i64) -> u32 {
+    let imm = imm as u32;
+    (insn & !((0x3 << 29) | (0x7ffff << 5))) | ((imm & 0x3) << 29) | (((imm >> 2) & 0x7ffff) << 5)
+}
+
+/// ADD/LDR/STR unsigned 12-bit immediate in bits 10-21
+fn encode_imm12(insn: u32, imm: i64) -> u32 {
+    (insn & !(0xfff << 10)) | (((imm as u32) & 0xfff) << 10)
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    let align = align.max(1);
+    value.div_ceil(align) * align
+}
+
+/// Links objects and archives into one static executable
+pub struct StaticLinker {
+    architecture: Architecture,
+    base_address: u64,
+    /// Overrides the script's `ENTRY`
+    entry: Option<String>,
+    script: Option<LinkerScript>,
+
+    // Section garbage collection, and the symbols it must keep besides
+    // the entry point
+    gc_sections: bool,
+    keep: Vec<String>,
+
+    // Inputs, by name for diagnostics
+    objects: Vec<(String, Vec<u8>)>,
+    archives: Vec<(String, Vec<u8>)>,
+}
+
+impl StaticLinker {
+    pub fn new(architecture: Architecture) -> Self {
+        StaticLinker {
+            architecture,
+            base_address: DEFAULT_BASE_ADDRESS,
+            entry: None,
+            script: None,
+            gc_sections: false,
+            keep: Vec::new(),
+            objects: Vec::new(),
+            archives: Vec::new(),
+        }
+    }
+
+    pub fn with_base_address(mut self, address: u64) -> Self {
+        self.base_address = address;
+        self
+    }
+
+    pub fn with_entry(mut self, symbol: &str) -> Self {
+        self.entry = Some(symbol.to_string());
+        self
+    }
+
+    /// Place sections as `script` says instead of in the built-in layout;
+    /// the base address is then unused
+    pub fn with_script(mut self, script: LinkerScript) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    /// Drop the input sections nothing live refers to
+    pub fn with_gc_sections(mut self, enabled: bool) -> Self {
+        self.gc_sections = enabled;
+        self
+    }
+
+    /// Keep `symbol`'s section, and what it refers to, when collecting
+    /// garbage
+    pub fn keep_symbol(mut self, symbol: &str) -> Self {
+        self.keep.push(symbol.to_string());
+        self
+    }
+
+    /// Link `data` in whole
+    pub fn add_object(&mut self, name: &str, data: Vec<u8>) {
+        self.objects.push((name.to_string(), data));
+    }
+
+    /// Link the members of `data` that define something the rest needs
+    pub fn add_archive(&mut self, name: &str, data: Vec<u8>) {
+        self.archives.push((name.to_string(), data));
+    }
+
+    /// Link `path` in whole
+    pub fn add_object_file(&mut self, path: &Path) -> Result<(), StaticLinkError> {
+        let data = fs::read(path).map_err(|e| StaticLinkError::Io(path.to_path_buf(), e))?;
+        self.add_object(&path.to_string_lossy(), data);
+        Ok(())
+    }
+
+    /// `-l<name>`: the first `lib<name>.a` in `search_dirs`
+    pub fn add_library(&mut self, name: &str, search_dirs: &[PathBuf]) -> Result<(), StaticLinkError> {
+        let file_name = format!("lib{}.a", name);
+        let path = search_dirs
+            .iter()
+            .map(|dir| dir.join(&file_name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| StaticLinkError::LibraryNotFound(name.to_string()))?;
+        let data = fs::read(&path).map_err(|e| StaticLinkError::Io(path.clone(), e))?;
+        self.add_archive(&path.to_string_lossy(), data);
+        Ok(())
+    }
+
+    /// Link and write the executable to `path`
+    pub fn write_executable(&self, path: &Path) -> Result<LinkSummary, StaticLinkError> {
+        let (executable, summary) = self.link_with_summary()?;
+        fs::write(path, executable).map_err(|e| StaticLinkError::Io(path.to_path_buf(), e))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).map_err(|e| StaticLinkError::Io(path.to_path_buf(), e))?;
+        Ok(summary)
+    }
+
+    /// The executable's bytes
+    pub fn link(&self) -> Result<Vec<u8>, StaticLinkError> {
+        self.link_with_summary().map(|(executable, _)| executable)
+    }
+
+    /// The executable's bytes, and what went into them
+    pub fn link_with_summary(&self) -> Result<(Vec<u8>, LinkSummary), StaticLinkError> {
+        let machine = Machine::for_architecture(self.architecture)?;
+        let script = self.script.as_ref();
+        let entry_symbol = self
+            .entry
+            .as_deref()
+            .or_else(|| script.and_then(|script| script.entry.as_deref()))
+            .unwrap_or(DEFAULT_ENTRY);
+        let mut link = Link {
+            machine,
+            inputs: self.load_inputs(&machine)?,
+            script,
+            live: None,
+            folded: HashSet::new(),
+            placements: Vec::new(),
+            removed: Vec::new(),
+            folded_sections: Vec::new(),
+            got_output: Output::Got as usize,
+            common_output: Some(Output::Bss as usize),
+            script_outputs: Vec::new(),
+            script_symbols: Vec::new(),
+            definitions: BTreeMap::new(),
+            got: Vec::new(),
+            got_slots: HashMap::new(),
+        };
+
+        link.fold_comdats()?;
+        if self.gc_sections {
+            let roots: Vec<&str> = std::iter::once(entry_symbol).chain(self.keep.iter().map(String::as_str)).collect();
+            link.live = Some(link.live_sections(&roots)?);
+        }
+        let mut sections = match script {
+            Some(script) => link.place_script(script)?,
+            None => link.place_sections()?,
+        };
+        link.collect_definitions(&mut sections)?;
+        link.scan_relocations()?;
+
+        let got = &mut sections[link.got_output];
+        got.size = link.got.len() as u64 * GOT_ENTRY_SIZE;
+        got.align = GOT_ENTRY_SIZE;
+        got.data = vec![0; got.size as usize];
+
+        let segments = match script {
+            Some(script) => link.layout_script(script, &mut sections)?,
+            None => link.layout(&mut sections, self.base_address),
+        };
+        // Script symbols only got their values in layout
+        for (key, &slot) in &link.got_slots {
+            let value = match key {
+                GotKey::Global(name) => link.definition(name).map(|definition| definition.value),
+                _ => link.got[slot as usize],
+            };
+            let address = value.map_or(0, |value| address(&sections, value));
+            let at = slot as usize * GOT_ENTRY_SIZE as usize;
+            sections[link.got_output].data[at..at + 8].copy_from_slice(&address.to_le_bytes());
+        }
+        link.apply_relocations(&mut sections)?;
+
+        let entry = match link.definition(entry_symbol) {
+            Some(definition) => address(&sections, definition.value),
+            // As with ld, a scripted image without an entry point starts at
+            // its first code; firmware is entered through a vector table
+            None if self.entry.is_none() && script.is_some_and(|script| script.entry.is_none()) => {
+                let code = u64::from(elf::SHF_EXECINSTR);
+                let text = sections.iter().find(|section| section.size > 0 && section.sh_flags & code != 0);
+                let address = text.map_or(0, |text| text.address);
+                log::warn!("entry symbol {} is not defined; starting at {:#x}", entry_symbol, address);
+                address
+            }
+            None => return Err(StaticLinkError::MissingEntry(entry_symbol.to_string())),
+        };
+        let executable = link.write(&sections, &segments, entry)?;
+        let summary = LinkSummary {
+            sections: sections
+                .iter()
+                .filter(|section| section.size > 0)
+                .map(|section| (section.name.clone(), section.size))
+                .collect(),
+            removed: link.removed,
+            folded: link.folded_sections,
+            file_size: executable.len() as u64,
+        };
+        Ok((executable, summary))
+    }
+
+    /// Parse the objects, then add archive members until nothing more is
+    /// needed
+    fn load_inputs(&self, machine: &Machine) -> Result<Vec<Input<'_>>, StaticLinkError> {
+        let mut inputs = Vec::new();
+        for (name, data) in &self.objects {
+            inputs.push(parse_input(name.clone(), data, machine)?);
+        }
+
+        let mut members = Vec::new();
+        for (name, data) in &self.archives {
+            let archive = ArchiveFile::parse(data.as_slice()).map_err(|e| parse_error(name, e))?;
+            for member in archive.members() {
+                let member = member.map_err(|e| parse_error(name, e))?;
+                let bytes = member.data(data.as_slice()).map_err(|e| parse_error(name, e))?;
+                let member_name = format!("{}({})", name, String::from_utf8_lossy(member.name()));
+                members.push(Some(parse_input(member_name, bytes, machine)?));
+            }
+        }
+
+        let mut defined = HashSet::new();
+        let mut undefined = HashSet::new();
+        for input in &inputs {
+            add_symbols(&input.file, &mut defined, &mut undefined);
+        }
+        loop {
+            let mut pulled = false;
+            for slot in members.iter_mut() {
+                let wanted = slot.as_ref().is_some_and(|member| {
+                    global_definitions(&member.file)
+                        .flat_map(symbols::spellings)
+                        .any(|name| undefined.contains(&name) && !defined.contains(&name))
+                });
+                if wanted {
+                    let member = slot.take().unwrap();
+                    add_symbols(&member.file, &mut defined, &mut undefined);
+                    inputs.push(member);
+                    pulled = true;
+                }
+            }
+            if !pulled {
+                return Ok(inputs);
+            }
+        }
+    }
+}
+
+fn parse_input<'data>(name: String, data: &'data [u8], machine: &Machine) -> Result<Input<'data>, StaticLinkError> {
+    let file = object::File::parse(data).map_err(|e| parse_error(&name, e))?;
+    if file.format() != BinaryFormat::Elf || file.kind() != ObjectKind::Relocatable {
+        return Err(StaticLinkError::Parse { file: name, message: "not a relocatable ELF object".to_string() });
+    }
+    if file.architecture() != machine.object_architecture() {
+        return Err(StaticLinkError::Parse {
+            file: name,
+            message: format!("built for {:?}, not {}", file.architecture(), machine.architecture),
+        });
+    }
+    Ok(Input { name, file })
+}
+
+fn parse_error(file: &str, error: object::Error) -> StaticLinkError {
+    StaticLinkError::Parse { file: file.to_string(), message: error.to_string() }
+}
+
+/// Names of the global symbols `file` defines
+fn global_definitions<'data, 'file>(file: &'file object::File<'data>) -> impl Iterator<Item = &'data str> + 'file {
+    file.symbols()
+        .filter(|symbol| symbol.is_global() && !symbol.is_undefined())
+        .filter_map(|symbol| symbol.name().ok())
+}
+
+/// Record what `file` defines, under every name that reaches it, and what
+/// it still needs; weak references don't pull in archive members
+fn add_symbols<'data>(
+    file: &object::File<'data>,
+    defined: &mut HashSet<Cow<'data, str>>,
+    undefined: &mut HashSet<Cow<'data, str>>,
+) {
+    for symbol in file.symbols() {
+        if !symbol.is_global() {
+            continue;
+        }
+        let Ok(name) = symbol.name() else { continue };
+        if !symbol.is_undefined() {
+            defined.extend(symbols::spellings(name));
+        } else if !symbol.is_weak() {
+            undefined.insert(Cow::Borrowed(name));
+        }
+    }
+}
+
+/// How strongly `symbol` defines its name
+fn strength(symbol: &object::Symbol<'_, '_>) -> Strength {
+    if symbol.is_weak() {
+        Strength::Weak
+    } else if symbol.section() == SymbolSection::Common {
+        Strength::Common
+    } else {
+        Strength::Strong
+    }
+}
+
+/// Output section an input section belongs in; `None` drops it
+fn classify(file: &str, section: &object::Section<'_, '_>) -> Result<Option<Output>, StaticLinkError> {
+    let name = section.name().unwrap_or("");
+    // Nothing unwinds through a static -nostdlib image
+    if name == ".eh_frame" {
+        return Ok(None);
+    }
+    let sh_flags = match section.flags() {
+        SectionFlags::Elf { sh_flags } => sh_flags,
+        _ => 0,
+    };
+    Ok(match section.kind() {
+        SectionKind::Text => Some(Output::Text),
+        SectionKind::ReadOnlyData | SectionKind::ReadOnlyDataWithRel | SectionKind::ReadOnlyString => Some(Output::Rodata),
+        SectionKind::Data => Some(Output::Data),
+        SectionKind::UninitializedData => Some(Output::Bss),
+        SectionKind::Tls | SectionKind::UninitializedTls => {
+            return Err(StaticLinkError::Unsupported(format!("{}: thread-local storage ({})", file, name)));
+        }
+        SectionKind::Elf(elf::SHT_INIT_ARRAY) => Some(Output::InitArray),
+        SectionKind::Elf(elf::SHT_FINI_ARRAY) => Some(Output::FiniArray),
+        SectionKind::Elf(elf::SHT_PREINIT_ARRAY) => {
+            return Err(StaticLinkError::Unsupported(format!("{}: .preinit_array", file)));
+        }
+        SectionKind::Elf(_) if sh_flags & u64::from(elf::SHF_ALLOC) != 0 => {
+            if sh_flags & u64::from(elf::SHF_EXECINSTR) != 0 {
+                Some(Output::Text)
+            } else if sh_flags & u64::from(elf::SHF_WRITE) != 0 {
+                Some(Output::Data)
+            } else {
+                Some(Output::Rodata)
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Whether `section` of input `index` is in a folded COMDAT group or, with
+/// garbage collection, dead; dead sections with contents are recorded in
+/// `removed`
+fn is_garbage(
+    live: &Option<HashSet<(usize, SectionIndex)>>,
+    folded: &HashSet<(usize, SectionIndex)>,
+    removed: &mut Vec<RemovedSection>,
+    index: usize,
+    input: &Input<'_>,
+    section: &object::Section<'_, '_>,
+) -> bool {
+    if folded.contains(&(index, section.index())) {
+        return true;
+    }
+    if live.as_ref().is_none_or(|live| live.contains(&(index, section.index()))) {
+        return false;
+    }
+    if section.size() > 0 {
+        removed.push(RemovedSection {
+            input: input.name.clone(),
+            section: section.name().unwrap_or("").to_string(),
+            size: section.size(),
+        });
+    }
+    true
+}
+
+/// Whether `section`, ending at `end`, fits in `region`
+fn check_region(section: &OutputSection, region: &MemoryRegion, end: u64) -> Result<(), StaticLinkError> {
+    let limit = region.origin + region.length;
+    if end > limit {
+        return Err(StaticLinkError::RegionOverflow {
+            section: section.name.clone(),
+            region: region.name.clone(),
+            overflow: end - limit,
+        });
+    }
+    Ok(())
+}
+
+/// Before layout, only the constant script symbols are known; inside an
+/// output section being filled, `.` is the offset into it
+struct Placing<'a> {
+    dot: Option<u64>,
+    constants: &'a HashMap<&'a str, u64>,
+}
+
+impl Environment for Placing<'_> {
+    fn dot(&self) -> Option<u64> {
+        self.dot
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        self.constants.get(name).copied()
+    }
+
+    fn section(&self, _: &str, _: SectionProperty) -> Option<u64> {
+        None
+    }
+}
+
+/// Script expressions during and after layout: symbols and sections as
+/// far as they've been placed
+struct ScriptEnvironment<'a, 'data> {
+    link: &'a Link<'data>,
+    sections: &'a [OutputSection],
+    dot: Option<u64>,
+}
+
+impl Environment for ScriptEnvironment<'_, '_> {
+    fn dot(&self) -> Option<u64> {
+        self.dot
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        self.link.definition(name).map(|definition| address(self.sections, definition.value))
+    }
+
+    fn section(&self, name: &str, property: SectionProperty) -> Option<u64> {
+        let section = self.sections.iter().find(|section| section.name == name)?;
+        Some(match property {
+            SectionProperty::Address => section.address,
+            SectionProperty::LoadAddress => section.load_address,
+            SectionProperty::Size => section.size,
+        })
+    }
+}
+
+fn address(sections: &[OutputSection], value: Value) -> u64 {
+    match value {
+        Value::At(output, offset) => sections[output].address + offset,
+        Value::End(output) => sections[output].address + sections[output].size,
+        Value::Absolute(address) => address,
+    }
+}
+
+/// What a link produced, what section garbage collection dropped and which
+/// duplicate COMDAT sections were folded
+#[derive(Debug, Clone, Default)]
+pub struct LinkSummary {
+    /// Size of each non-empty output section, in address order
+    pub sections: Vec<(String, u64)>,
+    pub removed: Vec<RemovedSection>,
+    pub folded: Vec<RemovedSection>,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RemovedSection {
+    pub input: String,
+    pub section: String,
+    pub size: u64,
+}
+
+impl LinkSummary {
+    /// Bytes of code and data left out of the image
+    pub fn removed_bytes(&self) -> u64 {
+        self.removed.iter().map(|removed| removed.size).sum()
+    }
+
+    /// Bytes of duplicate COMDAT sections left out of the image
+    pub fn folded_bytes(&self) -> u64 {
+        self.folded.iter().map(|folded| folded.size).sum()
+    }
+}
+
+impl fmt::Display for LinkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, size) in &self.sections {
+            writeln!(f, "{:<12} {:>10}", name, size)?;
+        }
+        writeln!(f, "{:<12} {:>10}", "file", self.file_size)?;
+        if !self.removed.is_empty() {
+            writeln!(f, "removed {} unreferenced section(s), {} bytes:", self.removed.len(), self.removed_bytes())?;
+            for removed in &self.removed {
+                writeln!(f, "  {} {} ({} bytes)", removed.input, removed.section, removed.size)?;
+            }
+        }
+        if !self.folded.is_empty() {
+            writeln!(f, "folded {} duplicate COMDAT section(s), {} bytes", self.folded.len(), self.folded_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// State of one `StaticLinker::link`
+struct Link<'data> {
+    machine: Machine,
+    inputs: Vec<Input<'data>>,
+    script: Option<&'data LinkerScript>,
+
+    // With garbage collection, the (input, section) pairs to link, and the
+    // sections of COMDAT groups another input already supplies
+    live: Option<HashSet<(usize, SectionIndex)>>,
+    folded: HashSet<(usize, SectionIndex)>,
+
+    // Per input, where its sections went, and the sections left out as
+    // garbage or folded
+    placements: Vec<HashMap<SectionIndex, Placement>>,
+    removed: Vec<RemovedSection>,
+    folded_sections: Vec<RemovedSection>,
+
+    // Where the GOT and common symbols go
+    got_output: OutputId,
+    common_output: Option<OutputId>,
+
+    // With a script, the output section of each of its output section
+    // descriptions (`None` for /DISCARD/), and the symbols it assigns
+    script_outputs: Vec<Option<OutputId>>,
+    script_symbols: Vec<ScriptSymbol<'data>>,
+
+    // Global symbols
+    definitions: BTreeMap<&'data str, Definition>,
+
+    // GOT contents, and the slot of each symbol in it
+    got: Vec<Option<Value>>,
+    got_slots: HashMap<GotKey<'data>, u64>,
+}
+
+impl<'data> Link<'data> {
+    /// The input sections reachable from the `roots` symbols and from the
+    /// sections that are always kept or a script `KEEP`s, following
+    /// relocations. A global
+    /// symbol leads to the section of the definition that will win.
+    fn live_sections(&self, roots: &[&str]) -> Result<HashSet<(usize, SectionIndex)>, StaticLinkError> {
+        let mut defined: HashMap<Cow<'data, str>, ((usize, SectionIndex), Strength)> = HashMap::new();
+        for (index, input) in self.inputs.iter().enumerate() {
+            for symbol in input.file.symbols() {
+                if symbol.is_local() || symbol.is_undefined() {
+                    continue;
+                }
+                let (Ok(name), SymbolSection::Section(section)) = (symbol.name(), symbol.section()) else { continue };
+                if self.folded.contains(&(index, section)) {
+                    continue;
+                }
+                let strength = strength(&symbol);
+                for name in symbols::spellings(name) {
+                    let replace = defined
+                        .get(&name)
+                        .is_none_or(|&(_, existing)| symbols::resolve(existing, strength) == Resolution::Replace);
+                    if replace {
+                        defined.insert(name, ((index, section), strength));
+                    }
+                }
+            }
+        }
+        let lookup = |name: &str| {
+            defined
+                .get(name)
+                .or_else(|| symbols::default_version(name).and_then(|name| defined.get(name.as_str())))
+                .map(|(at, _)| *at)
+        };
+
+        let mut pending: Vec<(usize, SectionIndex)> = roots.iter().filter_map(|name| lookup(name)).collect();
+        for (index, input) in self.inputs.iter().enumerate() {
+            for section in input.file.sections() {
+                let retained = matches!(section.flags(), SectionFlags::Elf { sh_flags } if sh_flags & SHF_GNU_RETAIN != 0);
+                let output = classify(&input.name, &section)?;
+                let kept = self.script.is_some_and(|script| {
+                    let name = section.name().unwrap_or("");
+                    script.place(&input.name, name).is_some_and(|(_, _, pattern)| pattern.keep)
+                });
+                if retained || kept || matches!(output, Some(Output::InitArray | Output::FiniArray)) {
+                    pending.push((index, section.index()));
+                }
+            }
+        }
+
+        let mut live = HashSet::new();
+        while let Some((index, section)) = pending.pop() {
+            if self.folded.contains(&(index, section)) || !live.insert((index, section)) {
+                continue;
+            }
+            let input = &self.inputs[index];
+            let section = input.file.section_by_index(section).map_err(|e| parse_error(&input.name, e))?;
+            // References from .eh_frame would keep everything
+            if classify(&input.name, &section)?.is_none() {
+                continue;
+            }
+            for (_, relocation) in section.relocations() {
+                let symbol = match relocation.target() {
+                    RelocationTarget::Symbol(symbol) => input.file.symbol_by_index(symbol).map_err(|e| parse_error(&input.name, e))?,
+                    RelocationTarget::Section(section) => {
+                        pending.push((index, section));
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if symbol.is_local() {
+                    if let SymbolSection::Section(section) = symbol.section() {
+                        pending.push((index, section));
+                    }
+                } else if let Some(at) = symbol.name().ok().and_then(lookup) {
+                    pending.push(at);
+                }
+            }
+        }
+        Ok(live)
+    }
+
+    /// Of the COMDAT groups with one signature, keep the first input's and
+    /// fold the rest. As with ld, groups are taken to be interchangeable
+    /// whatever their selection kind; the folded copies' symbols are never
+    /// defined, so references reach the kept copy.
+    fn fold_comdats(&mut self) -> Result<(), StaticLinkError> {
+        let mut signatures = HashSet::new();
+        for (index, input) in self.inputs.iter().enumerate() {
+            for comdat in input.file.comdats() {
+                let signature = comdat.name().map_err(|e| parse_error(&input.name, e))?.to_string();
+                if signatures.insert(signature) {
+                    continue;
+                }
+                for section_index in comdat.sections() {
+                    let section = input.file.section_by_index(section_index).map_err(|e| parse_error(&input.name, e))?;
+                    self.folded.insert((index, section_index));
+                    if classify(&input.name, &section)?.is_some() && section.size() > 0 {
+                        self.folded_sections.push(RemovedSection {
+                            input: input.name.clone(),
+                            section: section.name().unwrap_or("").to_string(),
+                            size: section.size(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Concatenate the input sections into the built-in output sections,
+    /// in input order
+    fn place_sections(&mut self) -> Result<Vec<OutputSection>, StaticLinkError> {
+        let mut sections: Vec<OutputSection> = Output::ALL.into_iter().map(OutputSection::builtin).collect();
+        for (index, input) in self.inputs.iter().enumerate() {
+            let mut placements = HashMap::new();
+            for section in input.file.sections() {
+                let Some(output) = classify(&input.name, &section)? else { continue };
+                if is_garbage(&self.live, &self.folded, &mut self.removed, index, input, &section) {
+                    continue;
+                }
+                let data = section.data().map_err(|e| parse_error(&input.name, e))?;
+                let offset = sections[output as usize].append(data, section.size(), section.align());
+                placements.insert(section.index(), Placement { output: output as usize, offset });
+            }
+            self.placements.push(placements);
+        }
+        Ok(sections)
+    }
+
+    /// Fill the script's output sections: each input section goes to the
+    /// first rule that takes it, and one no rule takes to the output
+    /// section of the name it would have without a script, after that
+    /// section's rules. Assignments to `.` inside an output section move
+    /// it within the section; the GOT follows the last writable section.
+    fn place_script(&mut self, script: &'data LinkerScript) -> Result<Vec<OutputSection>, StaticLinkError> {
+        let descriptions: Vec<&'data OutputDescription> = script.outputs().collect();
+        // Input sections by (description, command), and the kinds of input
+        // in each description
+        let mut taken: HashMap<(usize, usize), Vec<(usize, SectionIndex)>> = HashMap::new();
+        let mut kinds: Vec<Vec<Output>> = vec![Vec::new(); descriptions.len()];
+        for (index, input) in self.inputs.iter().enumerate() {
+            for section in input.file.sections() {
+                let Some(output) = classify(&input.name, &section)? else { continue };
+                if is_garbage(&self.live, &self.folded, &mut self.removed, index, input, &section) {
+                    continue;
+                }
+                let name = section.name().unwrap_or("");
+                let (at, command) = match script.place(&input.name, name) {
+                    Some((at, command, _)) => (at, command),
+                    None => match descriptions.iter().position(|description| description.name == output.name()) {
+                        Some(at) => (at, usize::MAX),
+                        None => {
+                            return Err(StaticLinkError::Unsupported(format!(
+                                "{}: the linker script does not place {}",
+                                input.name, name
+                            )));
+                        }
+                    },
+                };
+                if descriptions[at].name == DISCARD {
+                    continue;
+                }
+                taken.entry((at, command)).or_default().push((index, section.index()));
+                kinds[at].push(output);
+            }
+        }
+
+        let takes_common = |description: &OutputDescription| {
+            description.commands.iter().any(|command| {
+                matches!(command, SectionCommand::Input(pattern) if pattern.sections.iter().any(|section| section == "COMMON"))
+            })
+        };
+        let common = descriptions
+            .iter()
+            .position(|description| takes_common(description))
+            .or_else(|| descriptions.iter().position(|description| description.name == ".bss"));
+
+        let mut sections = Vec::new();
+        for (at, description) in descriptions.iter().enumerate() {
+            if description.name == DISCARD {
+                self.script_outputs.push(None);
+                continue;
+            }
+            let kinds = &kinds[at];
+            let sh_type = match kinds.first() {
+                _ if description.noload => elf::SHT_NOBITS,
+                Some(first) if kinds.iter().all(|kind| kind == first) => first.sh_type(),
+                // Data and .bss together: the .bss part is stored as zeros
+                Some(_) => elf::SHT_PROGBITS,
+                None if common == Some(at) => elf::SHT_NOBITS,
+                None => elf::SHT_PROGBITS,
+            };
+            let mut sh_flags = kinds.iter().fold(u64::from(elf::SHF_ALLOC), |flags, kind| flags | kind.sh_flags());
+            if common == Some(at) {
+                sh_flags |= u64::from(elf::SHF_WRITE);
+            }
+            let mut section = OutputSection::new(&description.name, sh_type, sh_flags);
+            section.noload = description.noload;
+            self.script_outputs.push(Some(sections.len()));
+            sections.push(section);
+        }
+        self.common_output = common.and_then(|at| self.script_outputs[at]);
+
+        // The GOT goes with the writable data, or last
+        let write = u64::from(elf::SHF_WRITE);
+        let anchor = sections
+            .iter()
+            .rposition(|section| section.sh_flags & write != 0 && section.has_data())
+            .or_else(|| sections.iter().rposition(OutputSection::has_data))
+            .map_or(sections.len(), |id| id + 1);
+        sections.insert(anchor, OutputSection::builtin(Output::Got));
+        self.got_output = anchor;
+        for id in self.script_outputs.iter_mut().flatten() {
+            if *id >= anchor {
+                *id += 1;
+            }
+        }
+
+        // Top-level symbols that don't depend on the layout, such as a
+        // stack size, are known before it
+        let mut constants = HashMap::new();
+        for item in &script.items {
+            if let Item::Assign(assignment) = item {
+                if assignment.is_dot() {
+                    continue;
+                }
+                let value = assignment.evaluate(script, &Placing { dot: None, constants: &constants }).ok();
+                if let Some(value) = value {
+                    constants.insert(assignment.symbol.as_str(), value);
+                }
+                let value = value.map(Value::Absolute);
+                self.script_symbols.push(ScriptSymbol { assignment, value, top_level: true, applies: true });
+            }
+        }
+
+        self.placements = vec![HashMap::new(); self.inputs.len()];
+        for (at, description) in descriptions.iter().enumerate() {
+            let Some(id) = self.script_outputs[at] else { continue };
+            // Then the orphans
+            for command in 0..=description.commands.len() {
+                let pattern = match description.commands.get(command) {
+                    Some(SectionCommand::Assign(assignment)) => {
+                        let placing = Placing { dot: Some(sections[id].size), constants: &constants };
+                        self.assign_in_section(script, assignment, &mut sections[id], id, &placing)?;
+                        continue;
+                    }
+                    Some(SectionCommand::Input(pattern)) => Some(pattern),
+                    None => None,
+                };
+                let Some(mut inputs) = taken.remove(&(at, if pattern.is_some() { command } else { usize::MAX })) else { continue };
+                if pattern.is_some_and(|pattern| pattern.sort) {
+                    inputs.sort_by_cached_key(|&(index, section)| {
+                        let section = self.inputs[index].file.section_by_index(section).ok();
+                        section.and_then(|section| section.name().ok().map(str::to_string))
+                    });
+                }
+                for (index, section_index) in inputs {
+                    let input = &self.inputs[index];
+                    let section = input.file.section_by_index(section_index).map_err(|e| parse_error(&input.name, e))?;
+                    let data = section.data().map_err(|e| parse_error(&input.name, e))?;
+                    let offset = sections[id].append(data, section.size(), section.align());
+                    self.placements[index].insert(section_index, Placement { output: id, offset });
+                }
+            }
+        }
+        Ok(sections)
+    }
+
+    /// An assignment inside output section `id`, where `.` is the offset
+    /// into the section. `ALIGN` there aligns the section too, so the
+    /// offset's alignment is the address's.
+    fn assign_in_section(
+        &mut self,
+        script: &'data LinkerScript,
+        assignment: &'data Assignment,
+        section: &mut OutputSection,
+        id: OutputId,
+        placing: &Placing<'_>,
+    ) -> Result<(), StaticLinkError> {
+        for align in assignment.expr.alignments() {
+            section.align = section.align.max(script.evaluate(align, placing).map_err(StaticLinkError::Script)?);
+        }
+        if assignment.is_dot() {
+            let dot = assignment.evaluate(script, placing).map_err(StaticLinkError::Script)?;
+            if dot < section.size {
+                return Err(StaticLinkError::Unsupported(format!("moving . backwards in {}", section.name)));
+            }
+            section.size = dot;
+            if section.has_data() {
+                section.data.resize(dot as usize, 0);
+            }
+        } else {
+            let value = match assignment.expr.uses_dot() {
+                true => Some(Value::At(id, assignment.evaluate(script, placing).map_err(StaticLinkError::Script)?)),
+                false => None,
+            };
+            self.script_symbols.push(ScriptSymbol { assignment, value, top_level: false, applies: true });
+        }
+        Ok(())
+    }
+
+    /// Resolve the global symbols, allocate common symbols in .bss, and
+    /// provide the symbols ld's default script would
+    fn collect_definitions(&mut self, sections: &mut [OutputSection]) -> Result<(), StaticLinkError> {
+        let mut found = Vec::new();
+        let mut commons: BTreeMap<&'data str, (u64, u64)> = BTreeMap::new();
+        for (index, input) in self.inputs.iter().enumerate() {
+            for symbol in input.file.symbols() {
+                if symbol.is_local() || symbol.is_undefined() {
+                    continue;
+                }
+                let name = match symbol.name() {
+                    Ok(name) if !name.is_empty() => name,
+                    _ => continue,
+                };
+                let value = match symbol.section() {
+                    SymbolSection::Section(section) => match self.placements[index].get(&section) {
+                        Some(place) => Value::At(place.output, place.offset + symbol.address()),
+                        None => continue,
+                    },
+                    SymbolSection::Absolute => Value::Absolute(symbol.address()),
+                    SymbolSection::Common => {
+                        // The value of a common symbol is its alignment;
+                        // commons of one name merge
+                        let common = commons.entry(name).or_insert((0, 1));
+                        common.0 = common.0.max(symbol.size());
+                        common.1 = common.1.max(symbol.address());
+                        continue;
+                    }
+                    _ => continue,
+                };
+                let st_info = match symbol.flags() {
+                    SymbolFlags::Elf { st_info, .. } => st_info,
+                    _ => elf::STB_GLOBAL << 4,
+                };
+                let definition = Definition { value, strength: strength(&symbol), st_info, size: symbol.size(), file: Some(index) };
+                found.push((name, definition));
+                // The default version is also what the plain name means
+                if let Some(versioned) = Versioned::parse(name).filter(|versioned| versioned.default) {
+                    found.push((versioned.base, definition));
+                }
+            }
+        }
+        for (name, definition) in found {
+            self.define(name, definition)?;
+        }
+
+        // Script assignments replace definitions from inputs, except for
+        // `PROVIDE`; they're placeholders until layout
+        for symbol in &mut self.script_symbols {
+            let name = symbol.assignment.symbol.as_str();
+            if symbol.assignment.provide && self.definitions.contains_key(name) {
+                symbol.applies = false;
+                continue;
+            }
+            self.definitions.insert(name, Definition {
+                value: symbol.value.unwrap_or(Value::Absolute(0)),
+                strength: Strength::Strong,
+                st_info: (elf::STB_GLOBAL << 4) | elf::STT_NOTYPE,
+                size: 0,
+                file: None,
+            });
+        }
+
+        // A strong definition wins over common ones, which win over weak
+        // ones
+        let bss = match self.common_output {
+            Some(bss) => bss,
+            None if commons.is_empty() => 0,
+            None => {
+                return Err(StaticLinkError::Unsupported(
+                    "common symbols, with no .bss or *(COMMON) in the linker script".to_string(),
+                ));
+            }
+        };
+        for (name, (size, align)) in commons {
+            let replaces = self.definitions.get(name).is_none_or(|existing| {
+                symbols::resolve(existing.strength, Strength::Common) == Resolution::Replace
+            });
+            if !replaces {
+                continue;
+            }
+            let offset = sections[bss].append(&[], size, align);
+            self.definitions.insert(name, Definition {
+                value: Value::At(bss, offset),
+                strength: Strength::Common,
+                st_info: (elf::STB_GLOBAL << 4) | elf::STT_OBJECT,
+                size,
+                file: None,
+            });
+        }
+
+        for (name, output, end) in [
+            ("__init_array_start", Output::InitArray, false),
+            ("__init_array_end", Output::InitArray, true),
+            ("__fini_array_start", Output::FiniArray, false),
+            ("__fini_array_end", Output::FiniArray, true),
+            ("_etext", Output::Text, true),
+            ("_edata", Output::Data, true),
+            ("__bss_start", Output::Bss, false),
+            ("_end", Output::Bss, true),
+        ] {
+            // A script may not have the section
+            let Some(id) = sections.iter().position(|section| section.name == output.name()) else { continue };
+            let value = if end { Value::End(id) } else { Value::At(id, 0) };
+            self.definitions.entry(name).or_insert(Definition {
+                value,
+                strength: Strength::Strong,
+                st_info: (elf::STB_GLOBAL << 4) | elf::STT_NOTYPE,
+                size: 0,
+                file: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// A stronger definition replaces a weaker one; two strong ones clash
+    fn define(&mut self, name: &'data str, definition: Definition) -> Result<(), StaticLinkError> {
+        let Some(existing) = self.definitions.get(name) else {
+            self.definitions.insert(name, definition);
+            return Ok(());
+        };
+        match symbols::resolve(existing.strength, definition.strength) {
+            Resolution::Duplicate => Err(StaticLinkError::DuplicateSymbol {
+                symbol: name.to_string(),
+                first: existing.file.map_or_else(String::new, |file| self.inputs[file].name.clone()),
+                second: definition.file.map_or_else(String::new, |file| self.inputs[file].name.clone()),
+            }),
+            Resolution::Replace => {
+                self.definitions.insert(name, definition);
+                Ok(())
+            }
+            Resolution::Keep | Resolution::Merge => Ok(()),
+        }
+    }
+
+    /// The definition a reference to `name` binds to: `name@VERSION` also
+    /// binds to `name@@VERSION`
+    fn definition(&self, name: &str) -> Option<&Definition> {
+        self.definitions
+            .get(name)
+            .or_else(|| symbols::default_version(name).and_then(|name| self.definitions.get(name.as_str())))
+    }
+
+    /// The symbol a relocation in input `file` refers to
+    fn resolve(&self, file: usize, target: RelocationTarget) -> Result<Resolved<'data>, StaticLinkError> {
+        let input = &self.inputs[file];
+        let index = match target {
+            RelocationTarget::Symbol(index) => index,
+            RelocationTarget::Absolute => return Ok(Resolved { name: "", value: Some(Value::Absolute(0)), got: GotKey::Absolute }),
+            _ => return Err(StaticLinkError::Unsupported(format!("{}: relocation against a section", input.name))),
+        };
+        let symbol = input.file.symbol_by_index(index).map_err(|e| parse_error(&input.name, e))?;
+        let name = symbol.name().unwrap_or("");
+
+        if symbol.is_local() {
+            let value = match symbol.section() {
+                SymbolSection::Section(section) => match self.placements[file].get(&section) {
+                    Some(place) => Value::At(place.output, place.offset + symbol.address()),
+                    None => {
+                        let section = input.file.section_by_index(section).map_err(|e| parse_error(&input.name, e))?;
+                        return Err(StaticLinkError::Unsupported(format!(
+                            "{}: reference to discarded section {}",
+                            input.name,
+                            section.name().unwrap_or("")
+                        )));
+                    }
+                },
+                SymbolSection::Absolute => Value::Absolute(symbol.address()),
+                _ => Value::Absolute(0),
+            };
+            return Ok(Resolved { name, value: Some(value), got: GotKey::Local(file, index.0) });
+        }
+
+        match self.definition(name) {
+            Some(definition) => Ok(Resolved { name, value: Some(definition.value), got: GotKey::Global(name) }),
+            None if symbol.is_weak() => Ok(Resolved { name, value: None, got: GotKey::Global(name) }),
+            None => Err(StaticLinkError::UndefinedSymbols(vec![(name.to_string(), input.name.clone())])),
+        }
+    }
+
+    /// Report every undefined symbol at once, and give each symbol loaded
+    /// through the GOT a slot
+    fn scan_relocations(&mut self) -> Result<(), StaticLinkError> {
+        let mut undefined = BTreeSet::new();
+        let mut got = Vec::new();
+        for (index, input) in self.inputs.iter().enumerate() {
+            for section in input.file.sections() {
+                if !self.placements[index].contains_key(&section.index()) {
+                    continue;
+                }
+                let data = section.data().map_err(|e| parse_error(&input.name, e))?;
+                for (offset, relocation) in section.relocations() {
+                    let resolved = match self.resolve(index, relocation.target()) {
+                        Ok(resolved) => resolved,
+                        Err(StaticLinkError::UndefinedSymbols(missing)) => {
+                            undefined.extend(missing);
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let r_type = self.machine.relocation_type(&relocation).ok_or_else(|| {
+                        StaticLinkError::Unsupported(format!("{}: {:?} relocation against '{}'", input.name, relocation.kind(), resolved.name))
+                    })?;
+                    if self.machine.uses_got(r_type, data, offset as usize, resolved.value.is_some()) {
+                        got.push((resolved.got, resolved.value));
+                    }
+                }
+            }
+        }
+        if !undefined.is_empty() {
+            return Err(StaticLinkError::UndefinedSymbols(undefined.into_iter().collect()));
+        }
+
+        for (key, value) in got {
+            if !self.got_slots.contains_key(&key) {
+                self.got_slots.insert(key, self.got.len() as u64);
+                self.got.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Assign file offsets and addresses. Each segment's addresses are
+    /// offset by another page, so no page is shared between two segments
+    /// with different permissions while the file stays dense.
+    fn layout(&self, sections: &mut [OutputSection], base_address: u64) -> Vec<Segment> {
+        let page_size = self.machine.page_size;
+        let used: Vec<bool> = (0..SEGMENT_FLAGS.len())
+            .map(|segment| segment == 0 || Output::ALL.iter().any(|&output| output.segment() == segment && sections[output as usize].size > 0))
+            .collect();
+        // One PT_LOAD per used segment, and PT_GNU_STACK
+        let program_headers = used.iter().filter(|&&used| used).count() as u64 + 1;
+
+        let mut offset = ELF_HEADER_SIZE + program_headers * PROGRAM_HEADER_SIZE;
+        let mut segments = Vec::new();
+        for (segment, &flags) in SEGMENT_FLAGS.iter().enumerate() {
+            let delta = base_address + segment as u64 * page_size;
+            let mut start = None;
+            let mut end = 0;
+            for output in Output::ALL.into_iter().filter(|output| output.segment() == segment) {
+                let section = &mut sections[output as usize];
+                section.offset = align_up(offset, section.align);
+                section.address = delta + section.offset;
+                section.load_address = section.address;
+                // The first segment also maps the headers
+                start.get_or_insert(if segment == 0 { 0 } else { section.offset });
+                // .bss takes memory but no file space
+                if section.has_data() {
+                    offset = section.offset + section.size;
+                }
+                end = end.max(section.address + section.size);
+            }
+            let start = start.unwrap_or(offset);
+            if used[segment] {
+                segments.push(Segment {
+                    flags,
+                    offset: start,
+                    address: delta + start,
+                    physical_address: delta + start,
+                    file_size: offset - start,
+                    memory_size: end - (delta + start),
+                });
+            }
+        }
+        segments
+    }
+
+    /// Assign addresses as the script says: at an output section's own
+    /// address, else next in its memory region, else at `.`, and loaded at
+    /// its `AT` address or next in its `AT >` region. Each loaded section
+    /// gets a PT_LOAD, at a file offset congruent to its address modulo
+    /// the page size. Symbols that need the layout and `ASSERT`s are
+    /// evaluated last.
+    fn layout_script(&mut self, script: &'data LinkerScript, sections: &mut [OutputSection]) -> Result<Vec<Segment>, StaticLinkError> {
+        let page_size = self.machine.page_size;
+        let loaded = |section: &OutputSection| section.size > 0 && !section.noload;
+        let program_headers = sections.iter().filter(|section| loaded(section)).count() as u64 + 1;
+        let mut offset = ELF_HEADER_SIZE + program_headers * PROGRAM_HEADER_SIZE;
+        let mut segments = Vec::new();
+        let mut dot = 0;
+        let mut cursors: HashMap<&str, u64> = script.regions.iter().map(|region| (region.name.as_str(), region.origin)).collect();
+        let mut next = 0;
+
+        for item in &script.items {
+            let (id, description) = match item {
+                Item::Output(description) => {
+                    next += 1;
+                    match self.script_outputs[next - 1] {
+                        Some(id) => (id, description),
+                        None => continue,
+                    }
+                }
+                Item::Assign(assignment) => {
+                    let environment = ScriptEnvironment { link: self, sections, dot: Some(dot) };
+                    if assignment.is_dot() {
+                        dot = assignment.evaluate(script, &environment).map_err(StaticLinkError::Script)?;
+                    } else if assignment.expr.uses_dot() {
+                        let value = assignment.evaluate(script, &environment).map_err(StaticLinkError::Script)?;
+                        if let Some(at) = self.script_symbols.iter().position(|symbol| std::ptr::eq(symbol.assignment, assignment)) {
+                            self.set_script_symbol(at, Value::Absolute(value));
+                        }
+                    }
+                    continue;
+                }
+                Item::Assert { .. } => continue,
+            };
+
+            // The GOT is laid out with the section it follows
+            let mut outputs = vec![(id, Some(description))];
+            if self.got_output == id + 1 {
+                outputs.push((self.got_output, None));
+            }
+            for (id, own) in outputs {
+                let environment = ScriptEnvironment { link: self, sections, dot: Some(dot) };
+                let evaluate = |expr: &Option<_>| match (own, expr) {
+                    (Some(_), Some(expr)) => script.evaluate(expr, &environment).map(Some),
+                    _ => Ok(None),
+                };
+                let address = evaluate(&description.address).map_err(StaticLinkError::Script)?;
+                let align = evaluate(&description.align).map_err(StaticLinkError::Script)?.unwrap_or(1);
+                let load_address = evaluate(&description.load_address).map_err(StaticLinkError::Script)?;
+
+                let section = &mut sections[id];
+                let region = match &description.region {
+                    Some(region) => Some(script.region(region).map_err(StaticLinkError::Script)?),
+                    // Else the first region that allows the section
+                    None => script.regions.iter().find(|region| {
+                        let attributes = region.attributes.to_ascii_lowercase();
+                        let allows = |flag: u32, attribute: char| section.sh_flags & u64::from(flag) == 0 || attributes.contains(attribute);
+                        attributes.is_empty() || (allows(elf::SHF_EXECINSTR, 'x') && allows(elf::SHF_WRITE, 'w'))
+                    }),
+                };
+                let start = match (address, region) {
+                    (Some(address), _) => address,
+                    (None, Some(region)) => cursors[region.name.as_str()],
+                    (None, None) => dot,
+                };
+                section.align = section.align.max(align);
+                section.address = if address.is_some() || section.size == 0 { start } else { align_up(start, section.align) };
+                dot = section.address + section.size;
+                if let Some(region) = region {
+                    cursors.insert(&region.name, dot);
+                    check_region(section, region, dot)?;
+                }
+
+                let load_region = match &description.load_region {
+                    Some(name) if Some(name) != description.region.as_ref() => Some(script.region(name).map_err(StaticLinkError::Script)?),
+                    _ => None,
+                };
+                section.load_address = match (load_address, load_region) {
+                    (Some(address), _) => address,
+                    (None, Some(region)) => align_up(cursors[region.name.as_str()], section.align),
+                    (None, None) => section.address,
+                };
+                if let Some(region) = load_region {
+                    let end = section.load_address + if section.has_data() { section.size } else { 0 };
+                    cursors.insert(&region.name, end);
+                    check_region(section, region, end)?;
+                }
+
+                if loaded(section) && section.has_data() {
+                    offset += section.address.wrapping_sub(offset) % page_size;
+                }
+                section.offset = offset;
+                if !loaded(section) {
+                    continue;
+                }
+                let file_size = if section.has_data() { section.size } else { 0 };
+                offset += file_size;
+                segments.push(Segment {
+                    flags: section.segment_flags(),
+                    offset: section.offset,
+                    address: section.address,
+                    physical_address: section.load_address,
+                    file_size,
+                    memory_size: section.size,
+                });
+            }
+        }
+
+        for at in 0..self.script_symbols.len() {
+            let symbol = &self.script_symbols[at];
+            if !symbol.applies || symbol.value.is_some() || (symbol.top_level && symbol.assignment.expr.uses_dot()) {
+                continue;
+            }
+            let environment = ScriptEnvironment { link: self, sections, dot: None };
+            let value = symbol.assignment.evaluate(script, &environment).map_err(StaticLinkError::Script)?;
+            self.set_script_symbol(at, Value::Absolute(value));
+        }
+        for item in &script.items {
+            if let Item::Assert { condition, message } = item {
+                let environment = ScriptEnvironment { link: self, sections, dot: None };
+                if script.evaluate(condition, &environment).map_err(StaticLinkError::Script)? == 0 {
+                    return Err(StaticLinkError::Script(ScriptError::Assertion(message.clone())));
+                }
+            }
+        }
+        Ok(segments)
+    }
+
+    fn set_script_symbol(&mut self, at: usize, value: Value) {
+        let symbol = &mut self.script_symbols[at];
+        symbol.value = Some(value);
+        if let Some(definition) = self.definitions.get_mut(symbol.assignment.symbol.as_str()) {
+            definition.value = value;
+        }
+    }
+
+    fn apply_relocations(&self, sections: &mut [OutputSection]) -> Result<(), StaticLinkError> {
+        let got_address = sections[self.got_output].address;
+        for (index, input) in self.inputs.iter().enumerate() {
+            for section in input.file.sections() {
+                let Some(&place) = self.placements[index].get(&section.index()) else { continue };
+                for (offset, relocation) in section.relocations() {
+                    let resolved = self.resolve(index, relocation.target())?;
+                    // Checked by `scan_relocations`
+                    let r_type = self.machine.relocation_type(&relocation).unwrap_or(0);
+                    let at = place.offset + offset;
+                    let fixup = Fixup {
+                        r_type,
+                        s: resolved.value.map_or(0, |value| address(sections, value)),
+                        a: relocation.addend(),
+                        p: sections[place.output].address + at,
+                        got: self.got_slots.get(&resolved.got).map_or(0, |slot| got_address + slot * GOT_ENTRY_SIZE),
+                        defined: resolved.value.is_some(),
+                    };
+                    match self.machine.apply(&mut sections[place.output].data, at as usize, &fixup) {
+                        Ok(()) => {}
+                        Err(Apply::Overflow) => {
+                            return Err(StaticLinkError::RelocationOverflow {
+                                file: input.name.clone(),
+                                symbol: resolved.name.to_string(),
+                                r_type,
+                            });
+                        }
+                        Err(Apply::Unsupported) => {
+                            return Err(StaticLinkError::Unsupported(format!(
+                                "{}: relocation type {} against '{}'",
+                                input.name, r_type, resolved.name
+                            )));
+                        }
+                        Err(Apply::OutOfBounds) => {
+                            return Err(StaticLinkError::Parse {
+                                file: input.name.clone(),
+                                message: format!("relocation at {:#x} is outside its section", offset),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The provenance notes of the inputs, in input order
+    fn provenance_notes(&self) -> Result<Vec<u8>, StaticLinkError> {
+        let mut notes = Vec::new();
+        for input in &self.inputs {
+            for section in input.file.sections().filter(|section| section.name() == Ok(provenance::ELF_SECTION)) {
+                notes.extend_from_slice(&section.data().map_err(|e| parse_error(&input.name, e))?);
+            }
+        }
+        Ok(notes)
+    }
+
+    /// Serialize the laid out image, with section headers and a symbol
+    /// table of the global symbols for debuggers and profilers
+    fn write(&self, sections: &[OutputSection], segments: &[Segment], entry: u64) -> Result<Vec<u8>, StaticLinkError> {
+        let mut buffer = Vec::new();
+        let mut writer = Writer::new(Endianness::Little, true, &mut buffer);
+
+        writer.reserve_file_header();
+        writer.reserve_program_headers(segments.len() as u32 + 1);
+        let present: Vec<OutputId> = (0..sections.len()).filter(|&id| sections[id].size > 0).collect();
+        for &id in &present {
+            let section = &sections[id];
+            if section.has_data() {
+                writer.reserve_until((section.offset + section.size) as usize);
+            }
+        }
+        let headers: Vec<_> = present
+            .iter()
+            .map(|&id| (id, writer.add_section_name(sections[id].name.as_bytes()), writer.reserve_section_index()))
+            .collect();
+        // After everything loaded, so it takes no address space
+        let notes = self.provenance_notes()?;
+        let notes_header = (!notes.is_empty()).then(|| {
+            let name = writer.add_section_name(provenance::ELF_SECTION.as_bytes());
+            writer.reserve_section_index();
+            (name, writer.reserve(notes.len(), 4))
+        });
+        let section_index = |value: Value| match value {
+            Value::At(output, _) | Value::End(output) => {
+                headers.iter().find(|(present, _, _)| *present == output).map(|(_, _, index)| *index)
+            }
+            Value::Absolute(_) => None,
+        };
+
+        writer.reserve_null_symbol_index();
+        let symbols: Vec<_> = self
+            .definitions
+            .iter()
+            .map(|(name, definition)| {
+                let index = section_index(definition.value);
+                writer.reserve_symbol_index(index);
+                (writer.add_string(name.as_bytes()), index, definition)
+            })
+            .collect();
+        writer.reserve_symtab_section_index();
+        writer.reserve_strtab_section_index();
+        writer.reserve_shstrtab_section_index();
+        writer.reserve_symtab();
+        writer.reserve_strtab();
+        writer.reserve_shstrtab();
+        writer.reserve_section_headers();
+
+        writer
+            .write_file_header(&FileHeader {
+                os_abi: elf::ELFOSABI_NONE,
+                abi_version: 0,
+                e_type: elf::ET_EXEC,
+                e_machine: self.machine.e_machine,
+                e_entry: entry,
+                e_flags: 0,
+            })
+            .map_err(|e| StaticLinkError::Write(e.to_string()))?;
+        writer.write_align_program_headers();
+        for segment in segments {
+            writer.write_program_header(&ProgramHeader {
+                p_type: elf::PT_LOAD,
+                p_flags: segment.flags,
+                p_offset: segment.offset,
+                p_vaddr: segment.address,
+                p_paddr: segment.physical_address,
+                p_filesz: segment.file_size,
+                p_memsz: segment.memory_size,
+                p_align: self.machine.page_size,
+            });
+        }
+        writer.write_program_header(&ProgramHeader {
+            p_type: elf::PT_GNU_STACK,
+            p_flags: elf::PF_R | elf::PF_W,
+            p_offset: 0,
+            p_vaddr: 0,
+            p_paddr: 0,
+            p_filesz: 0,
+            p_memsz: 0,
+            p_align: 16,
+        });
+
+        for &id in &present {
+            let section = &sections[id];
+            if section.has_data() {
+                writer.pad_until(section.offset as usize);
+                writer.write(&section.data);
+            }
+        }
+        if notes_header.is_some() {
+            writer.write_align(4);
+            writer.write(&notes);
+        }
+
+        writer.write_null_symbol();
+        for (name, index, definition) in &symbols {
+            writer.write_symbol(&Sym {
+                name: Some(*name),
+                section: *index,
+                st_info: definition.st_info,
+                st_other: elf::STV_DEFAULT,
+                st_shndx: elf::SHN_ABS,
+                st_value: address(sections, definition.value),
+                st_size: definition.size,
+            });
+        }
+        writer.write_strtab();
+        writer.write_shstrtab();
+
+        writer.write_null_section_header();
+        for (id, name, _) in &headers {
+            let section = &sections[*id];
+            writer.write_section_header(&SectionHeader {
+                name: Some(*name),
+                sh_type: section.sh_type,
+                sh_flags: section.sh_flags,
+                sh_addr: section.address,
+                sh_offset: section.offset,
+                sh_size: section.size,
+                sh_link: 0,
+                sh_info: 0,
+                sh_addralign: section.align.max(1),
+                sh_entsize: section.entsize,
+            });
+        }
+        if let Some((name, offset)) = notes_header {
+            writer.write_section_header(&SectionHeader {
+                name: Some(name),
+                sh_type: elf::SHT_NOTE,
+                sh_flags: 0,
+                sh_addr: 0,
+                sh_offset: offset as u64,
+                sh_size: notes.len() as u64,
+                sh_link: 0,
+                sh_info: 0,
+                sh_addralign: 4,
+                sh_entsize: 0,
+            });
+        }
+        // Only the null symbol is local
+        writer.write_symtab_section_header(1);
+        writer.write_strtab_section_header();
+        writer.write_shstrtab_section_header();
+
+        Ok(buffer)
+    }
+}
+
+#[derive(Debug)]
+pub enum StaticLinkError {
+    Io(PathBuf, io::Error),
+    /// No `lib<name>.a` in the search directories; shared libraries can't
+    /// be linked statically
+    LibraryNotFound(String),
+    /// Input that isn't a relocatable ELF object for the target
+    Parse { file: String, message: String },
+    /// (symbol, first input referring to it)
+    UndefinedSymbols(Vec<(String, String)>),
+    DuplicateSymbol { symbol: String, first: String, second: String },
+    MissingEntry(String),
+    Script(ScriptError),
+    /// An output section that doesn't fit its linker script memory region
+    RegionOverflow { section: String, region: String, overflow: u64 },
+    RelocationOverflow { file: String, symbol: String, r_type: u32 },
+    /// A target, section or relocation this linker doesn't handle
+    Unsupported(String),
+    Write(String),
+}
+
+impl fmt::Display for StaticLinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StaticLinkError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            StaticLinkError::LibraryNotFound(name) => write!(f, "no static library for -l{}", name),
+            StaticLinkError::Parse { file, message } => write!(f, "{}: {}", file, message),
+            StaticLinkError::UndefinedSymbols(symbols) => {
+                write!(f, "undefined symbols:")?;
+                for (symbol, file) in symbols {
+                    write!(f, "\n  {} (referenced from {})", symbol, file)?;
+                }
+                Ok(())
+            }
+            StaticLinkError::DuplicateSymbol { symbol, first, second } => {
+                write!(f, "'{}' is defined in both {} and {}", symbol, first, second)
+            }
+            StaticLinkError::MissingEntry(symbol) => write!(f, "entry symbol '{}' is not defined", symbol),
+            StaticLinkError::Script(e) => write!(f, "{}", e),
+            StaticLinkError::RegionOverflow { section, region, overflow } => {
+                write!(f, "section {} overflows region {} by {} bytes", section, region, overflow)
+            }
+            StaticLinkError::RelocationOverflow { file, symbol, r_type } => {
+                write!(f, "{}: relocation type {} against '{}' is out of range", file, r_type, symbol)
+            }
+            StaticLinkError::Unsupported(message) => write!(f, "unsupported: {}", message),
+            StaticLinkError::Write(message) => write!(f, "writing executable: {}", message),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let crt0 = Crt0::for_architecture(Architecture::AArch64).build_object()?;
+
+    let mut linker = StaticLinker::new(Architecture::AArch64);
+    linker.add_object("crt0.o", crt0);
+    linker.add_object_file(Path::new("main.o"))?;
+    linker.add_library("c", &[PathBuf::from("sysroot/lib")])?;
+
+    // Or `linker.link()` for the bytes
+    linker.write_executable(Path::new("a.out"))?;
+
+    // Leave out the functions and variables nothing uses
+    let mut linker = StaticLinker::new(Architecture::X86_64).with_gc_sections(true).keep_symbol("interrupt_table");
+    linker.add_object_file(Path::new("crt0.o"))?;
+    linker.add_object_file(Path::new("firmware.o"))?;
+    let summary = linker.write_executable(Path::new("firmware"))?;
+    println!("{}", summary);
+
+    // Place code in flash and data in RAM, loaded from flash
+    let script = LinkerScript::load(Path::new("board.ld"))?;
+    let mut linker = StaticLinker::new(Architecture::AArch64).with_script(script);
+    linker.add_object_file(Path::new("firmware.o"))?;
+    linker.write_executable(Path::new("firmware.elf"))?;
+    Ok(())
+}
+*/
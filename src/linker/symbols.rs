@@ -0,0 +1,107 @@
+// src/linker/symbols.rs
+//! Symbol resolution rules
+//! Which of several global definitions of a name a link keeps, as ld
+//! decides it, shared by `static_elf` and `SymbolTable`:
+//!
+//! - A strong definition replaces weak and common ones; two strong
+//!   definitions clash.
+//! - A common symbol (an uninitialized tentative definition under
+//!   -fcommon) replaces a weak definition. Commons of one name merge into
+//!   one, as large and as aligned as the largest.
+//! - Of several weak definitions, the first one stays.
+//!
+//! GNU symbol versions, as `.symver` or `__attribute__((symver))` spell
+//! them in object files: `name@VERSION` is a hidden version, reached only by
+//! a reference to that exact name. `name@@VERSION` is the default version,
+//! which a reference to plain `name` or to `name@VERSION` reaches too, so a
+//! default version and an unversioned definition of `name` clash.
+
+use std::borrow::Cow;
+
+/// How strongly an input defines a symbol, weakest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strength {
+    Weak,
+    Common,
+    Strong,
+}
+
+/// What to do with a second definition of a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// The existing definition stays
+    Keep,
+    /// The new definition takes over
+    Replace,
+    /// Both are common: one symbol of the larger size and alignment
+    Merge,
+    /// Two strong definitions
+    Duplicate,
+}
+
+pub fn resolve(existing: Strength, new: Strength) -> Resolution {
+    match (existing, new) {
+        (Strength::Strong, Strength::Strong) => Resolution::Duplicate,
+        (Strength::Common, Strength::Common) => Resolution::Merge,
+        (existing, new) if new > existing => Resolution::Replace,
+        _ => Resolution::Keep,
+    }
+}
+
+/// `name@VERSION` or `name@@VERSION`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Versioned<'a> {
+    pub base: &'a str,
+    pub version: &'a str,
+    /// `@@`: what references to plain `base` get
+    pub default: bool,
+}
+
+impl<'a> Versioned<'a> {
+    pub fn parse(name: &'a str) -> Option<Self> {
+        let (base, rest) = name.split_once('@')?;
+        let (version, default) = match rest.strip_prefix('@') {
+            Some(version) => (version, true),
+            None => (rest, false),
+        };
+        if base.is_empty() || version.is_empty() || version.contains('@') {
+            return None;
+        }
+        Some(Versioned { base, version, default })
+    }
+}
+
+/// Every name a reference can use to reach a definition of `name`
+pub fn spellings(name: &str) -> Vec<Cow<'_, str>> {
+    match Versioned::parse(name) {
+        Some(versioned) if versioned.default => vec![
+            Cow::Borrowed(name),
+            Cow::Borrowed(versioned.base),
+            Cow::Owned(format!("{}@{}", versioned.base, versioned.version)),
+        ],
+        _ => vec![Cow::Borrowed(name)],
+    }
+}
+
+/// The default-version definition a reference to the hidden version
+/// `name@VERSION` may also bind to
+pub fn default_version(reference: &str) -> Option<String> {
+    match Versioned::parse(reference)? {
+        versioned if !versioned.default => Some(format!("{}@@{}", versioned.base, versioned.version)),
+        _ => None,
+    }
+}
+
+// Example usage:
+/*
+fn example() {
+    assert_eq!(resolve(Strength::Weak, Strength::Common), Resolution::Replace);
+    assert_eq!(resolve(Strength::Strong, Strength::Weak), Resolution::Keep);
+
+    // memcpy@@GLIBC_2.14 is what plain memcpy and memcpy@GLIBC_2.14 get;
+    // memcpy@GLIBC_2.2.5 only answers to its own name
+    let names: Vec<_> = spellings("memcpy@@GLIBC_2.14").into_iter().collect();
+    assert_eq!(names, ["memcpy@@GLIBC_2.14", "memcpy", "memcpy@GLIBC_2.14"]);
+    assert_eq!(default_version("memcpy@GLIBC_2.14").as_deref(), Some("memcpy@@GLIBC_2.14"));
+}
+*/
@@ -0,0 +1,192 @@
+// src/logging.rs
+//! Logging backend for the `log` facade
+//! Levels are controlled per module with a directive string such as
+//! `info,jit=debug,linker=warn`, taken from `--log` or `C_INTERPRETER_LOG`.
+//! Output is human-readable text on stderr, or one JSON object per line for
+//! server deployments that ship logs to a collector.
+
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Environment variable consulted when `--log` is not given
+pub const LOG_ENV_VAR: &str = "C_INTERPRETER_LOG";
+
+/// Environment variable selecting the output format (`text` or `json`)
+pub const LOG_FORMAT_ENV_VAR: &str = "C_INTERPRETER_LOG_FORMAT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_str(s: &str) -> Result<Self, LoggingError> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(LoggingError::InvalidFormat(other.to_string())),
+        }
+    }
+}
+
+/// Parsed `module=level` directives plus a default level
+#[derive(Debug, Clone)]
+pub struct LogSpec {
+    default: LevelFilter,
+    // Sorted longest module first so the most specific directive wins
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl LogSpec {
+    /// Parse `level` / `module=level` entries separated by commas
+    pub fn parse(spec: &str, default: LevelFilter) -> Result<Self, LoggingError> {
+        let mut parsed = LogSpec {
+            default,
+            directives: Vec::new(),
+        };
+
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((module, level)) => {
+                    let level = parse_level(level)?;
+                    parsed.directives.push((module.trim().replace('.', "::"), level));
+                }
+                None => parsed.default = parse_level(entry)?,
+            }
+        }
+
+        parsed.directives.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(parsed)
+    }
+
+    /// Level in effect for a log target such as `c_ide::jit::codegen`
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        // Directives are written relative to the crate root
+        let path = target.split_once("::").map_or(target, |(_, rest)| rest);
+
+        for (module, level) in &self.directives {
+            if path == module
+                || path.starts_with(&format!("{}::", module))
+                || target == module
+            {
+                return *level;
+            }
+        }
+        self.default
+    }
+
+    /// Most verbose level any directive enables
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+fn parse_level(s: &str) -> Result<LevelFilter, LoggingError> {
+    s.trim()
+        .parse::<LevelFilter>()
+        .map_err(|_| LoggingError::InvalidLevel(s.to_string()))
+}
+
+struct Logger {
+    spec: LogSpec,
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.spec.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = match self.format {
+            LogFormat::Text => format!(
+                "[{} {}] {}",
+                level_name(record.level()),
+                record.target(),
+                record.args()
+            ),
+            LogFormat::Json => serde_json::json!({
+                "ts": SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs_f64())
+                    .unwrap_or(0.0),
+                "level": level_name(record.level()),
+                "target": record.target(),
+                "message": record.args().to_string(),
+                "file": record.file(),
+                "line": record.line(),
+            })
+            .to_string(),
+        };
+
+        // A single write per record keeps lines intact across threads
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+/// Install the global logger.
+///
+/// `spec` overrides `C_INTERPRETER_LOG`; `format` overrides
+/// `C_INTERPRETER_LOG_FORMAT`. Modules without a directive log at `default`.
+pub fn init(
+    spec: Option<&str>,
+    format: Option<&str>,
+    default: LevelFilter
+) -> Result<(), LoggingError> {
+    let spec = match spec {
+        Some(s) => s.to_string(),
+        None => std::env::var(LOG_ENV_VAR).unwrap_or_default(),
+    };
+    let format = match format {
+        Some(f) => f.to_string(),
+        None => std::env::var(LOG_FORMAT_ENV_VAR).unwrap_or_else(|_| "text".to_string()),
+    };
+
+    let spec = LogSpec::parse(&spec, default)?;
+    let format = LogFormat::from_str(&format)?;
+
+    log::set_max_level(spec.max_level());
+    log::set_boxed_logger(Box::new(Logger { spec, format }))
+        .map_err(|_| LoggingError::AlreadyInitialized)
+}
+
+#[derive(Debug)]
+pub enum LoggingError {
+    InvalidLevel(String),
+    InvalidFormat(String),
+    AlreadyInitialized,
+}
+
+// Example usage:
+/*
+fn main() {
+    // Equivalent to C_INTERPRETER_LOG=warn,jit=debug,linker=warn
+    logging::init(Some("warn,jit=debug,linker=warn"), Some("json"), LevelFilter::Warn).unwrap();
+
+    log::debug!(target: "c_ide::jit", "compiled main in 3ms");  // emitted
+    log::info!(target: "c_ide::linker", "resolved 12 symbols");  // filtered
+}
+*/
@@ -1,22 +1,1193 @@
-pub struct LTOSystem {
-    // Link time optimization
-    lto_pipeline: LTOPipeline,
-    
-    // Module linking
-    module_linker: ModuleLinker,
-    
-    // Symbol resolution
-    symbol_resolver: SymbolResolver,
-    
+// src/lto/mod.rs
+//! Link-time optimization
+//! Each translation unit is compiled to bitcode with the pre-link pipeline
+//! (`pre_link`), and the bitcode of the whole program is handed to
+//! `LTOSystem`, which optimizes across the units before code generation.
+//!
+//! Full LTO links every module into one and optimizes and compiles that on
+//! one thread. ThinLTO keeps the modules apart, so the backends run in
+//! parallel, and gets most of the cross-module inlining from a global index
+//! instead:
+//!
+//! - every module gets a summary: the symbols it defines, their linkage
+//!   and size, and what each of them calls and references;
+//! - the combined index decides, for each module, which small functions of
+//!   other modules to import (their callees too, under a shrinking size
+//!   limit), which local symbols those functions use and must therefore be
+//!   promoted to hidden external names, which definition of each weak
+//!   symbol prevails, and which external symbols nothing outside their
+//!   module references and can be internalized;
+//! - each backend parses its own module in a fresh context, applies the
+//!   index's decisions, links in `available_externally` copies of its
+//!   imports, runs `thinlto<On>` and emits an object.
+//!
+//! Unlike LLVM's own ThinLTO, summaries are computed from the bitcode at
+//! link time rather than stored in it, and read-only variables are never
+//! imported. With a cache directory, they are kept there in the IR format
+//! (`crate::ir`), keyed by the bitcode, so a relink only summarizes the
+//! modules that changed. Modules with aliases, ifuncs or personality functions aren't
+//! imported from, since the C API can't drop those from a copy.
+
+#[cfg(feature = "llvm")]
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+#[cfg(feature = "llvm")]
+use std::collections::VecDeque;
+#[cfg(feature = "llvm")]
+use std::ffi::{CStr, CString};
+use std::fmt;
+#[cfg(feature = "llvm")]
+use std::os::raw::c_void;
+#[cfg(feature = "llvm")]
+use std::path::PathBuf;
+use std::str::FromStr;
+#[cfg(feature = "llvm")]
+use llvm_sys::bit_reader::LLVMParseBitcodeInContext2;
+#[cfg(feature = "llvm")]
+use llvm_sys::bit_writer::LLVMWriteBitcodeToMemoryBuffer;
+#[cfg(feature = "llvm")]
+use llvm_sys::comdat::LLVMSetComdat;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+#[cfg(feature = "llvm")]
+use llvm_sys::linker::LLVMLinkModules2;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::target::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::target_machine::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::transforms::pass_builder::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMDiagnosticSeverity, LLVMLinkage, LLVMOpcode, LLVMVisibility};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "llvm")]
+use crate::driver::parallel::{DiagnosticsSink, ParallelCompiler};
+use crate::ir::{ArtifactKind, Decoder, Encoder, IrError, Reader, Writer};
+#[cfg(feature = "llvm")]
+use crate::optimizer::fenv::instructions;
+#[cfg(feature = "llvm")]
+use crate::pipeline::cache::{CacheKey, CompilationCache};
+
+/// Functions of up to this many instructions are imported
+pub const DEFAULT_IMPORT_LIMIT: usize = 100;
+
+// The limit for the callees of an imported function, relative to its own
+#[cfg(feature = "llvm")]
+const IMPORT_LIMIT_DECAY: f64 = 0.7;
+
+/// `-flto`, `-flto=thin`, `-flto=full`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LtoMode {
+    /// Every unit is optimized and compiled on its own
+    #[default]
+    Off,
+    /// One merged module
+    Full,
+    /// Per-module backends guided by a global index
+    Thin,
+}
+
+impl FromStr for LtoMode {
+    type Err = LTOError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" | "none" => Ok(LtoMode::Off),
+            "full" => Ok(LtoMode::Full),
+            "thin" => Ok(LtoMode::Thin),
+            other => Err(LTOError::UnknownMode(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for LtoMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LtoMode::Off => "off",
+            LtoMode::Full => "full",
+            LtoMode::Thin => "thin",
+        })
+    }
+}
+
+#[cfg(feature = "llvm")]
+#[derive(Debug, Clone)]
+pub struct LtoOptions {
+    pub mode: LtoMode,
+    /// 0-3, as for `-O`
+    pub optimization_level: u32,
+
     // Code generation
-    codegen: LTOCodeGenerator,
+    /// Required; there is no default target
+    pub target_triple: String,
+    pub target_cpu: String,
+    /// Comma-separated, as LLVM takes them (`+sse4.2,-avx`)
+    pub target_features: String,
+    pub relocation_model: LLVMRelocMode,
+    pub code_model: LLVMCodeModel,
+
+    // Symbols the output must keep
+    /// The output is an object or shared library, so every external symbol
+    /// may be used by something LTO doesn't see
+    pub preserve_all: bool,
+    /// Kept besides `main` and whatever `llvm.used` lists
+    pub preserve: Vec<String>,
+
+    // ThinLTO
+    /// Largest function imported into a caller, in instructions
+    pub import_limit: usize,
+    /// Backends run concurrently; 0 = one per CPU
+    pub jobs: usize,
+    /// Compilation cache that keeps module summaries between links; `None`
+    /// summarizes every module every time
+    pub cache_dir: Option<PathBuf>,
 }
 
+#[cfg(feature = "llvm")]
+impl Default for LtoOptions {
+    fn default() -> Self {
+        LtoOptions {
+            mode: LtoMode::Thin,
+            optimization_level: 2,
+            target_triple: String::new(),
+            target_cpu: "generic".to_string(),
+            target_features: String::new(),
+            relocation_model: LLVMRelocMode::LLVMRelocPIC,
+            code_model: LLVMCodeModel::LLVMCodeModelDefault,
+            preserve_all: false,
+            preserve: Vec::new(),
+            import_limit: DEFAULT_IMPORT_LIMIT,
+            jobs: 0,
+            cache_dir: None,
+        }
+    }
+}
+
+/// One object of the LTO output
+#[derive(Debug, Clone)]
+pub struct LtoObject {
+    /// The input module it was compiled from, or `ld-temp.o` for full LTO
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// A translation unit's bitcode
+#[cfg(feature = "llvm")]
+struct InputModule {
+    name: String,
+    bitcode: Vec<u8>,
+}
+
+#[cfg(feature = "llvm")]
+pub struct LTOSystem {
+    options: LtoOptions,
+    // In link order, which decides which weak definition prevails
+    modules: Vec<InputModule>,
+}
+
+/// Run the pre-link pipeline for `mode` on a unit's module before its
+/// bitcode goes to `LTOSystem`: it optimizes the unit but leaves inlining
+/// and dead-symbol removal to the link
+#[cfg(feature = "llvm")]
+pub unsafe fn pre_link(module: LLVMModuleRef, mode: LtoMode, optimization_level: u32) -> Result<(), LTOError> {
+    let level = optimization_level.min(3);
+    let pipeline = match mode {
+        LtoMode::Off => format!("default<O{}>", level),
+        LtoMode::Full => format!("lto-pre-link<O{}>", level),
+        LtoMode::Thin => format!("thinlto-pre-link<O{}>", level),
+    };
+    run_passes(module, &pipeline, std::ptr::null_mut())
+}
+
+#[cfg(feature = "llvm")]
 impl LTOSystem {
-    fn perform_lto(&mut self) -> Result<(), LTOError> {
-        // Perform interprocedural optimization
-        // Resolve all symbols
-        // Generate optimized code
-        // Handle ThinLTO
+    pub fn new(options: LtoOptions) -> Self {
+        LTOSystem {
+            options,
+            modules: Vec::new(),
+        }
+    }
+
+    pub fn add_bitcode(&mut self, name: &str, bitcode: Vec<u8>) {
+        self.modules.push(InputModule {
+            name: name.to_string(),
+            bitcode,
+        });
+    }
+
+    /// Add a copy of `module`; the caller keeps ownership, and may dispose
+    /// of it or its context right away
+    pub unsafe fn add_module(&mut self, name: &str, module: LLVMModuleRef) {
+        self.add_bitcode(name, write_bitcode(module));
+    }
+
+    /// Optimize and compile the program; one object per input module for
+    /// ThinLTO and without LTO, a single one for full LTO
+    pub fn perform_lto(&mut self) -> Result<Vec<LtoObject>, LTOError> {
+        if self.modules.is_empty() {
+            return Ok(Vec::new());
+        }
+        unsafe {
+            LLVM_InitializeAllTargets();
+            LLVM_InitializeAllTargetInfos();
+            LLVM_InitializeAllTargetMCs();
+            LLVM_InitializeAllAsmPrinters();
+        }
+
+        match self.options.mode {
+            // `pre_link` already optimized them
+            LtoMode::Off => self.run_backends(|system, session, machine, m| unsafe {
+                emit_object(session.parse(&system.modules[m])?, machine)
+            }),
+            LtoMode::Full => unsafe { self.full_lto().map(|object| vec![object]) },
+            LtoMode::Thin => {
+                let index = self.index()?;
+                log::debug!(
+                    "thinlto: {} modules, {} functions imported, {} locals promoted",
+                    index.modules.len(),
+                    index.imports.iter().flat_map(|imports| imports.values()).map(BTreeSet::len).sum::<usize>(),
+                    index.promotions.iter().map(BTreeSet::len).sum::<usize>(),
+                );
+                self.run_backends(|system, session, machine, m| unsafe { system.thin_backend(&index, session, machine, m) })
+            }
+        }
+    }
+
+    /// Summarize every module and combine the summaries
+    pub fn index(&self) -> Result<ModuleIndex, LTOError> {
+        let mut cache = self.options.cache_dir.as_ref().and_then(|dir| CompilationCache::open(dir).ok());
+        let key = |input: &InputModule| CacheKey::for_ir(&input.bitcode, "thinlto-summary", &self.options.target_triple);
+
+        // Summaries of unchanged modules come from the cache; the rest are
+        // computed in parallel
+        let mut summaries: Vec<Option<ModuleSummary>> = self
+            .modules
+            .iter()
+            .map(|input| {
+                let ir = cache.as_mut()?.get_ir(&key(input))?;
+                match ModuleSummary::from_ir(&ir) {
+                    Ok(summary) => Some(ModuleSummary { name: input.name.clone(), ..summary }),
+                    Err(e) => {
+                        log::debug!("thinlto: cached summary of {} is unusable: {}", input.name, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let missing: Vec<usize> = (0..self.modules.len()).filter(|&m| summaries[m].is_none()).collect();
+
+        let workers = ParallelCompiler::new(self.options.jobs).map_err(|e| LTOError::Parallel(format!("{:?}", e)))?;
+        let sink = DiagnosticsSink::new();
+        let computed = workers
+            .compile_all(&missing, &sink, || (), |_, _, &m, _| unsafe {
+                let input = &self.modules[m];
+                let session = Session::new();
+                let module = session.parse(input)?;
+                Ok(summarize(&input.name, module))
+            })
+            .into_iter()
+            .collect::<Result<Vec<_>, LTOError>>()?;
+        for (m, summary) in missing.into_iter().zip(computed) {
+            if let Some(cache) = cache.as_mut() {
+                if let Err(e) = cache.put_ir(&key(&self.modules[m]), &summary.to_ir()) {
+                    log::warn!("could not store the summary of {} in the compilation cache: {:?}", summary.name, e);
+                }
+            }
+            summaries[m] = Some(summary);
+        }
+        Ok(ModuleIndex::build(summaries.into_iter().flatten().collect(), &self.options))
+    }
+
+    fn level(&self) -> u32 {
+        self.options.optimization_level.min(3)
+    }
+
+    /// Run `backend` on every module, each worker thread with its own target
+    /// machine, and each module in a fresh context
+    fn run_backends<F>(&self, backend: F) -> Result<Vec<LtoObject>, LTOError>
+    where
+        F: Fn(&Self, &Session, LLVMTargetMachineRef, usize) -> Result<Vec<u8>, LTOError> + Sync + Send,
+    {
+        let workers = ParallelCompiler::new(self.options.jobs).map_err(|e| LTOError::Parallel(format!("{:?}", e)))?;
+        let sink = DiagnosticsSink::new();
+        let indices: Vec<usize> = (0..self.modules.len()).collect();
+        workers
+            .compile_all(
+                &indices,
+                &sink,
+                || unsafe { TargetMachine::new(&self.options) },
+                |machine, _, &m, _| {
+                    let machine = machine.as_ref().map_err(Clone::clone)?;
+                    let session = Session::new();
+                    let bytes = backend(self, &session, machine.0, m).map_err(|e| session.explain(e))?;
+                    Ok(LtoObject {
+                        name: self.modules[m].name.clone(),
+                        bytes,
+                    })
+                },
+            )
+            .into_iter()
+            .collect()
+    }
+
+    /// Module `m`'s backend: resolve its symbols, import, optimize, compile
+    unsafe fn thin_backend(
+        &self,
+        index: &ModuleIndex,
+        session: &Session,
+        machine: LLVMTargetMachineRef,
+        m: usize,
+    ) -> Result<Vec<u8>, LTOError> {
+        let module = session.parse(&self.modules[m])?;
+        index.resolve(module, m);
+
+        for (&source, functions) in &index.imports[m] {
+            let copy = session.parse(&self.modules[source])?;
+            index.promote(copy, source);
+            keep_only(copy, functions);
+            if LLVMLinkModules2(module, copy) != 0 {
+                return Err(LTOError::Link(format!(
+                    "importing from {} into {}",
+                    self.modules[source].name, self.modules[m].name
+                )));
+            }
+        }
+
+        run_passes(module, &format!("thinlto<O{}>", self.level()), machine)?;
+        emit_object(module, machine)
+    }
+
+    unsafe fn full_lto(&self) -> Result<LtoObject, LTOError> {
+        let machine = TargetMachine::new(&self.options)?;
+        let session = Session::new();
+        let result = (|| {
+            let module = session.parse(&self.modules[0])?;
+            let mut preserved: HashSet<String> = self.options.preserve.iter().cloned().collect();
+            preserved.insert("main".to_string());
+            preserved.extend(used_symbols(module));
+            let mut has_asm = has_module_asm(module);
+
+            for input in &self.modules[1..] {
+                let other = session.parse(input)?;
+                preserved.extend(used_symbols(other));
+                has_asm |= has_module_asm(other);
+                if LLVMLinkModules2(module, other) != 0 {
+                    return Err(LTOError::Link(format!("linking {}", input.name)));
+                }
+            }
+
+            // Everything the program uses is in the module now
+            if !self.options.preserve_all && !has_asm {
+                for value in global_values(module) {
+                    let name = value_name(value);
+                    if is_definition(value) && !is_local(value) && !name.starts_with("llvm.") && !preserved.contains(&name) {
+                        internalize(value);
+                    }
+                }
+            }
+
+            run_passes(module, &format!("lto<O{}>", self.level()), machine.0)?;
+            emit_object(module, machine.0)
+        })();
+        result.map_err(|e| session.explain(e)).map(|bytes| LtoObject {
+            name: "ld-temp.o".to_string(),
+            bytes,
+        })
+    }
+}
+
+/// What one module defines and uses
+#[derive(Debug, Clone, Default)]
+pub struct ModuleSummary {
+    pub name: String,
+    /// Defined symbols, local ones included
+    pub symbols: HashMap<String, SymbolSummary>,
+    /// External symbols used here and defined elsewhere, if anywhere
+    pub undefined: HashSet<String>,
+    /// Listed in `llvm.used` or `llvm.compiler.used`
+    pub used: HashSet<String>,
+    /// Top-level `asm` may define or use any symbol
+    pub has_asm: bool,
+    /// Has aliases, ifuncs or functions with a personality, which a copy
+    /// can't be reduced around; nothing is imported from it
+    pub opaque: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Alias,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Linkage {
+    /// `static`: internal or private
+    Local,
+    External,
+    /// Weak or linkonce; `odr` when every definition is the same
+    Weak { odr: bool },
+    /// A tentative definition with `-fcommon`
+    Common,
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolSummary {
+    pub kind: SymbolKind,
+    pub linkage: Linkage,
+    pub instructions: usize,
+    /// Functions called directly
+    pub calls: Vec<String>,
+    /// Everything referenced, callees included
+    pub refs: Vec<String>,
+    /// Uses inline asm or block addresses, which can't be moved to
+    /// another module
+    pub pinned: bool,
+}
+
+// Summary sections
+const SUMMARY_MODULE: u8 = 0x01;
+const SUMMARY_SYMBOLS: u8 = 0x02;
+const SUMMARY_UNDEFINED: u8 = 0x03;
+const SUMMARY_USED: u8 = 0x04;
+
+impl ModuleSummary {
+    /// The summary in the IR format. Names are sorted, so the same module
+    /// always gives the same bytes.
+    pub fn to_ir(&self) -> Vec<u8> {
+        let mut writer = Writer::new(ArtifactKind::LtoSummary);
+
+        let mut module = Encoder::new();
+        module.str(&self.name);
+        module.bool(self.has_asm);
+        module.bool(self.opaque);
+        writer.section(SUMMARY_MODULE, module);
+
+        let mut symbols = Encoder::new();
+        let mut names: Vec<&String> = self.symbols.keys().collect();
+        names.sort();
+        symbols.uint(names.len() as u64);
+        for name in names {
+            let symbol = &self.symbols[name];
+            symbols.record(|e| {
+                e.str(name);
+                e.u8(match symbol.kind {
+                    SymbolKind::Function => 0,
+                    SymbolKind::Variable => 1,
+                    SymbolKind::Alias => 2,
+                });
+                e.u8(match symbol.linkage {
+                    Linkage::Local => 0,
+                    Linkage::External => 1,
+                    Linkage::Weak { odr: false } => 2,
+                    Linkage::Weak { odr: true } => 3,
+                    Linkage::Common => 4,
+                });
+                e.uint(symbol.instructions as u64);
+                e.bool(symbol.pinned);
+                write_names(e, symbol.calls.iter());
+                write_names(e, symbol.refs.iter());
+            });
+        }
+        writer.section(SUMMARY_SYMBOLS, symbols);
+
+        for (tag, set) in [(SUMMARY_UNDEFINED, &self.undefined), (SUMMARY_USED, &self.used)] {
+            let mut names: Vec<&String> = set.iter().collect();
+            names.sort();
+            let mut section = Encoder::new();
+            write_names(&mut section, names.into_iter());
+            writer.section(tag, section);
+        }
+        writer.finish()
+    }
+
+    /// A summary written by `to_ir` of any release with the same IR major
+    /// version
+    pub fn from_ir(data: &[u8]) -> Result<Self, IrError> {
+        let reader = Reader::expect(
+            data,
+            ArtifactKind::LtoSummary,
+            &[SUMMARY_MODULE, SUMMARY_SYMBOLS, SUMMARY_UNDEFINED, SUMMARY_USED],
+        )?;
+        let mut summary = ModuleSummary::default();
+        if let Some(mut module) = reader.section(SUMMARY_MODULE) {
+            summary.name = module.str()?.to_string();
+            summary.has_asm = module.bool()?;
+            summary.opaque = module.bool()?;
+        }
+        if let Some(mut symbols) = reader.section(SUMMARY_SYMBOLS) {
+            for _ in 0..symbols.count()? {
+                let mut d = symbols.record()?;
+                let name = d.str()?.to_string();
+                let kind = match d.u8()? {
+                    0 => SymbolKind::Function,
+                    1 => SymbolKind::Variable,
+                    2 => SymbolKind::Alias,
+                    other => return Err(IrError::Unknown { what: "symbol kind", value: other as u64 }),
+                };
+                let linkage = match d.u8()? {
+                    0 => Linkage::Local,
+                    1 => Linkage::External,
+                    2 => Linkage::Weak { odr: false },
+                    3 => Linkage::Weak { odr: true },
+                    4 => Linkage::Common,
+                    other => return Err(IrError::Unknown { what: "linkage", value: other as u64 }),
+                };
+                let symbol = SymbolSummary {
+                    kind,
+                    linkage,
+                    instructions: d.uint_as()?,
+                    pinned: d.bool()?,
+                    calls: read_names(&mut d)?,
+                    refs: read_names(&mut d)?,
+                };
+                summary.symbols.insert(name, symbol);
+            }
+        }
+        if let Some(mut undefined) = reader.section(SUMMARY_UNDEFINED) {
+            summary.undefined = read_names(&mut undefined)?.into_iter().collect();
+        }
+        if let Some(mut used) = reader.section(SUMMARY_USED) {
+            summary.used = read_names(&mut used)?.into_iter().collect();
+        }
+        Ok(summary)
+    }
+}
+
+fn write_names<'a>(e: &mut Encoder, names: impl ExactSizeIterator<Item = &'a String>) {
+    e.uint(names.len() as u64);
+    for name in names {
+        e.str(name);
+    }
+}
+
+fn read_names(d: &mut Decoder<'_>) -> Result<Vec<String>, IrError> {
+    let count = d.count()?;
+    (0..count).map(|_| d.str().map(str::to_string)).collect()
+}
+
+/// The combined summaries, and the decisions the backends apply
+#[derive(Debug, Clone, Default)]
+pub struct ModuleIndex {
+    pub modules: Vec<ModuleSummary>,
+    /// The module whose definition of each external symbol the link keeps
+    pub prevailing: HashMap<String, usize>,
+    /// Per module: functions to import, by the module they come from
+    pub imports: Vec<BTreeMap<usize, BTreeSet<String>>>,
+    /// Per module: local symbols that get external names
+    pub promotions: Vec<BTreeSet<String>>,
+    /// External symbols used outside the module that defines them
+    pub exported: HashSet<String>,
+    /// Symbols the output must keep
+    pub preserved: HashSet<String>,
+    preserve_all: bool,
+}
+
+#[cfg(feature = "llvm")]
+impl ModuleIndex {
+    pub fn build(modules: Vec<ModuleSummary>, options: &LtoOptions) -> Self {
+        let mut index = ModuleIndex {
+            prevailing: HashMap::new(),
+            imports: vec![BTreeMap::new(); modules.len()],
+            promotions: vec![BTreeSet::new(); modules.len()],
+            exported: HashSet::new(),
+            preserved: options.preserve.iter().cloned().collect(),
+            preserve_all: options.preserve_all || modules.iter().any(|module| module.has_asm),
+            modules,
+        };
+        index.preserved.insert("main".to_string());
+
+        // The first definition in link order prevails, as with a static link
+        for (m, module) in index.modules.iter().enumerate() {
+            index.preserved.extend(module.used.iter().cloned());
+            index.exported.extend(module.undefined.iter().cloned());
+            for (name, symbol) in &module.symbols {
+                if symbol.linkage == Linkage::Local {
+                    continue;
+                }
+                if index.prevailing.contains_key(name) {
+                    // Other copies are replaced by references to it
+                    index.exported.insert(name.clone());
+                } else {
+                    index.prevailing.insert(name.clone(), m);
+                }
+            }
+        }
+
+        for m in 0..index.modules.len() {
+            index.compute_imports(m, options.import_limit);
+        }
+        index
+    }
+
+    /// Import the small functions `m` calls, then the small functions those
+    /// call, each hop with a smaller limit
+    fn compute_imports(&mut self, m: usize, limit: usize) {
+        let mut queue: VecDeque<(String, f64)> = self.modules[m]
+            .symbols
+            .values()
+            .filter(|symbol| symbol.kind == SymbolKind::Function)
+            .flat_map(|symbol| symbol.calls.iter().map(|callee| (callee.clone(), limit as f64)))
+            .collect();
+        let mut seen = HashSet::new();
+
+        while let Some((name, limit)) = queue.pop_front() {
+            if self.modules[m].symbols.contains_key(&name) || !seen.insert(name.clone()) {
+                continue;
+            }
+            let Some(&source) = self.prevailing.get(&name) else { continue };
+            let module = &self.modules[source];
+            let symbol = &module.symbols[&name];
+            let importable = symbol.kind == SymbolKind::Function
+                && matches!(symbol.linkage, Linkage::External | Linkage::Weak { odr: true })
+                && !symbol.pinned
+                && !module.opaque;
+            if !importable || symbol.instructions as f64 > limit {
+                continue;
+            }
+
+            // The copy refers to the source module's symbols by name now
+            for reference in &symbol.refs {
+                match module.symbols.get(reference) {
+                    Some(referenced) if referenced.linkage == Linkage::Local => {
+                        self.promotions[source].insert(reference.clone());
+                        self.exported.insert(promoted_name(reference, source));
+                    }
+                    _ => {
+                        self.exported.insert(reference.clone());
+                    }
+                }
+            }
+            for callee in &symbol.calls {
+                if module.symbols.get(callee).is_none_or(|callee| callee.linkage != Linkage::Local) {
+                    queue.push_back((callee.clone(), limit * IMPORT_LIMIT_DECAY));
+                }
+            }
+            self.imports[m].entry(source).or_default().insert(name);
+        }
+    }
+
+    /// Whether `name` must stay visible outside the module defining it
+    pub fn keeps(&self, name: &str) -> bool {
+        self.preserve_all || self.exported.contains(name) || self.preserved.contains(name)
+    }
+
+    /// Give module `m`'s promoted locals their external names
+    unsafe fn promote(&self, module: LLVMModuleRef, m: usize) {
+        for value in global_values(module) {
+            let name = value_name(value);
+            if is_local(value) && self.promotions[m].contains(&name) {
+                let promoted = promoted_name(&name, m);
+                LLVMSetValueName2(value, promoted.as_ptr() as *const _, promoted.len());
+                LLVMSetLinkage(value, LLVMLinkage::LLVMExternalLinkage);
+                LLVMSetVisibility(value, LLVMVisibility::LLVMHiddenVisibility);
+            }
+        }
     }
-} 
+
+    /// Apply the index to module `m` before its backend runs: promote,
+    /// compile only the prevailing copy of ODR functions, and internalize
+    /// what nothing else uses. Other weak and common definitions are left
+    /// to the system linker.
+    unsafe fn resolve(&self, module: LLVMModuleRef, m: usize) {
+        self.promote(module, m);
+        for value in global_values(module) {
+            let name = value_name(value);
+            let Some(symbol) = self.modules[m].symbols.get(&name) else { continue };
+            match symbol.linkage {
+                Linkage::Local => {}
+                Linkage::Weak { odr: true } if symbol.kind == SymbolKind::Function && self.prevailing.get(&name) != Some(&m) => {
+                    // Still good for inlining
+                    LLVMSetLinkage(value, LLVMLinkage::LLVMAvailableExternallyLinkage);
+                    LLVMSetComdat(value, std::ptr::null_mut());
+                }
+                _ if self.keeps(&name) => {}
+                _ => internalize(value),
+            }
+        }
+    }
+}
+
+/// The external name of local `name` of module `m`
+pub fn promoted_name(name: &str, m: usize) -> String {
+    format!("{}.llvm.{}", name, m)
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn summarize(name: &str, module: LLVMModuleRef) -> ModuleSummary {
+    let mut summary = ModuleSummary {
+        name: name.to_string(),
+        used: used_symbols(module),
+        has_asm: has_module_asm(module),
+        opaque: !LLVMGetFirstGlobalAlias(module).is_null() || !LLVMGetFirstGlobalIFunc(module).is_null(),
+        ..ModuleSummary::default()
+    };
+
+    for value in global_values(module) {
+        let name = value_name(value);
+        if name.starts_with("llvm.") {
+            continue;
+        }
+        let Some(linkage) = linkage_of(value) else {
+            continue;
+        };
+
+        let mut symbol = SymbolSummary {
+            kind: SymbolKind::Variable,
+            linkage,
+            instructions: 0,
+            calls: Vec::new(),
+            refs: Vec::new(),
+            pinned: false,
+        };
+        let mut referenced = Vec::new();
+        let mut seen = HashSet::new();
+        if !LLVMIsAFunction(value).is_null() {
+            symbol.kind = SymbolKind::Function;
+            summary.opaque |= LLVMHasPersonalityFn(value) != 0;
+            for inst in instructions(value) {
+                symbol.instructions += 1;
+                if matches!(LLVMGetInstructionOpcode(inst), LLVMOpcode::LLVMCall | LLVMOpcode::LLVMInvoke | LLVMOpcode::LLVMCallBr) {
+                    let callee = LLVMGetCalledValue(inst);
+                    if !LLVMIsAInlineAsm(callee).is_null() {
+                        symbol.pinned = true;
+                    } else if !LLVMIsAFunction(callee).is_null() {
+                        let callee = value_name(callee);
+                        if !callee.starts_with("llvm.") && !symbol.calls.contains(&callee) {
+                            symbol.calls.push(callee);
+                        }
+                    }
+                }
+                symbol.pinned |= collect_globals(inst, &mut seen, &mut referenced);
+            }
+        } else if !LLVMIsAGlobalAlias(value).is_null() {
+            symbol.kind = SymbolKind::Alias;
+            symbol.pinned = collect_globals(value, &mut seen, &mut referenced);
+        } else {
+            let initializer = LLVMGetInitializer(value);
+            if !LLVMIsAGlobalValue(initializer).is_null() {
+                referenced.push(initializer);
+            } else {
+                symbol.pinned = collect_globals(initializer, &mut seen, &mut referenced);
+            }
+        }
+
+        for global in referenced {
+            let referenced_name = value_name(global);
+            if referenced_name.starts_with("llvm.") {
+                continue;
+            }
+            if linkage_of(global).is_none() {
+                summary.undefined.insert(referenced_name.clone());
+            }
+            if !symbol.refs.contains(&referenced_name) {
+                symbol.refs.push(referenced_name);
+            }
+        }
+        summary.symbols.insert(name, symbol);
+    }
+    summary
+}
+
+/// Add the globals `value`'s operands refer to, through constant
+/// expressions and aggregates, to `out`; true if one is a block address
+#[cfg(feature = "llvm")]
+unsafe fn collect_globals(value: LLVMValueRef, seen: &mut HashSet<LLVMValueRef>, out: &mut Vec<LLVMValueRef>) -> bool {
+    let mut pinned = false;
+    for i in 0..LLVMGetNumOperands(value).max(0) as u32 {
+        let operand = LLVMGetOperand(value, i);
+        if operand.is_null() || !seen.insert(operand) {
+            continue;
+        }
+        if !LLVMIsAGlobalValue(operand).is_null() {
+            out.push(operand);
+        } else if !LLVMIsABlockAddress(operand).is_null() {
+            pinned = true;
+        } else if !LLVMIsAConstant(operand).is_null() {
+            pinned |= collect_globals(operand, seen, out);
+        }
+    }
+    pinned
+}
+
+/// A symbol's linkage class; `None` when `value` isn't a definition the
+/// link sees (declarations, `available_externally`, appending arrays)
+#[cfg(feature = "llvm")]
+unsafe fn linkage_of(value: LLVMValueRef) -> Option<Linkage> {
+    use LLVMLinkage::*;
+    if !is_definition(value) {
+        return None;
+    }
+    match LLVMGetLinkage(value) {
+        LLVMInternalLinkage | LLVMPrivateLinkage => Some(Linkage::Local),
+        LLVMExternalLinkage => Some(Linkage::External),
+        LLVMLinkOnceODRLinkage | LLVMWeakODRLinkage => Some(Linkage::Weak { odr: true }),
+        LLVMLinkOnceAnyLinkage | LLVMWeakAnyLinkage => Some(Linkage::Weak { odr: false }),
+        LLVMCommonLinkage => Some(Linkage::Common),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn is_definition(value: LLVMValueRef) -> bool {
+    if !LLVMIsAGlobalAlias(value).is_null() {
+        return true;
+    }
+    LLVMIsDeclaration(value) == 0 && LLVMGetLinkage(value) != LLVMLinkage::LLVMAvailableExternallyLinkage
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn is_local(value: LLVMValueRef) -> bool {
+    matches!(LLVMGetLinkage(value), LLVMLinkage::LLVMInternalLinkage | LLVMLinkage::LLVMPrivateLinkage)
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn internalize(value: LLVMValueRef) {
+    LLVMSetLinkage(value, LLVMLinkage::LLVMInternalLinkage);
+    LLVMSetVisibility(value, LLVMVisibility::LLVMDefaultVisibility);
+    if !LLVMIsAGlobalObject(value).is_null() {
+        LLVMSetComdat(value, std::ptr::null_mut());
+    }
+}
+
+/// Turn a function or variable definition into a declaration
+#[cfg(feature = "llvm")]
+unsafe fn make_declaration(value: LLVMValueRef) {
+    if !LLVMIsAFunction(value).is_null() {
+        // Nothing outside the body uses its instructions and blocks, so
+        // they can go once they no longer use each other
+        let body = instructions(value);
+        for &inst in &body {
+            if LLVMGetTypeKind(LLVMTypeOf(inst)) != llvm_sys::LLVMTypeKind::LLVMVoidTypeKind {
+                LLVMReplaceAllUsesWith(inst, LLVMGetUndef(LLVMTypeOf(inst)));
+            }
+        }
+        for inst in body {
+            LLVMInstructionEraseFromParent(inst);
+        }
+        let mut block = LLVMGetFirstBasicBlock(value);
+        while !block.is_null() {
+            let next = LLVMGetNextBasicBlock(block);
+            LLVMDeleteBasicBlock(block);
+            block = next;
+        }
+        LLVMGlobalClearMetadata(value);
+    } else {
+        LLVMSetInitializer(value, std::ptr::null_mut());
+    }
+    LLVMSetLinkage(value, LLVMLinkage::LLVMExternalLinkage);
+    LLVMSetComdat(value, std::ptr::null_mut());
+}
+
+/// Reduce a copy of an exporting module to `functions`, as
+/// `available_externally` definitions, and the declarations they need
+#[cfg(feature = "llvm")]
+unsafe fn keep_only(module: LLVMModuleRef, functions: &BTreeSet<String>) {
+    let mut variable = LLVMGetFirstGlobal(module);
+    while !variable.is_null() {
+        let next = LLVMGetNextGlobal(variable);
+        // Constructors and `llvm.used` belong to the exporting module
+        if value_name(variable).starts_with("llvm.") {
+            LLVMDeleteGlobal(variable);
+        } else if LLVMIsDeclaration(variable) == 0 {
+            make_declaration(variable);
+        }
+        variable = next;
+    }
+
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 {
+            if functions.contains(&value_name(function)) {
+                LLVMSetLinkage(function, LLVMLinkage::LLVMAvailableExternallyLinkage);
+                LLVMSetComdat(function, std::ptr::null_mut());
+            } else {
+                make_declaration(function);
+            }
+        }
+        function = LLVMGetNextFunction(function);
+    }
+
+    // Declarations only the dropped bodies used
+    let mut variable = LLVMGetFirstGlobal(module);
+    while !variable.is_null() {
+        let next = LLVMGetNextGlobal(variable);
+        if LLVMGetFirstUse(variable).is_null() {
+            LLVMDeleteGlobal(variable);
+        }
+        variable = next;
+    }
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        let next = LLVMGetNextFunction(function);
+        if LLVMIsDeclaration(function) != 0 && LLVMGetFirstUse(function).is_null() {
+            LLVMDeleteFunction(function);
+        }
+        function = next;
+    }
+}
+
+/// Functions, variables and aliases
+#[cfg(feature = "llvm")]
+unsafe fn global_values(module: LLVMModuleRef) -> Vec<LLVMValueRef> {
+    let mut values = Vec::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        values.push(function);
+        function = LLVMGetNextFunction(function);
+    }
+    let mut variable = LLVMGetFirstGlobal(module);
+    while !variable.is_null() {
+        values.push(variable);
+        variable = LLVMGetNextGlobal(variable);
+    }
+    let mut alias = LLVMGetFirstGlobalAlias(module);
+    while !alias.is_null() {
+        values.push(alias);
+        alias = LLVMGetNextGlobalAlias(alias);
+    }
+    values
+}
+
+/// Names listed in `llvm.used` and `llvm.compiler.used`
+#[cfg(feature = "llvm")]
+unsafe fn used_symbols(module: LLVMModuleRef) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for list in ["llvm.used", "llvm.compiler.used"] {
+        let list = CString::new(list).unwrap();
+        let global = LLVMGetNamedGlobal(module, list.as_ptr());
+        if global.is_null() || LLVMIsDeclaration(global) != 0 {
+            continue;
+        }
+        let mut referenced = Vec::new();
+        collect_globals(LLVMGetInitializer(global), &mut HashSet::new(), &mut referenced);
+        names.extend(referenced.into_iter().map(|value| value_name(value)));
+    }
+    names
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn has_module_asm(module: LLVMModuleRef) -> bool {
+    let mut len = 0;
+    LLVMGetModuleInlineAsm(module, &mut len);
+    len > 0
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(value, &mut len);
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len)).into_owned()
+}
+
+/// `module` as bitcode for `LTOSystem::add_bitcode`
+#[cfg(feature = "llvm")]
+pub unsafe fn write_bitcode(module: LLVMModuleRef) -> Vec<u8> {
+    let buffer = LLVMWriteBitcodeToMemoryBuffer(module);
+    let bytes = std::slice::from_raw_parts(LLVMGetBufferStart(buffer) as *const u8, LLVMGetBufferSize(buffer)).to_vec();
+    LLVMDisposeMemoryBuffer(buffer);
+    bytes
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn run_passes(module: LLVMModuleRef, pipeline: &str, machine: LLVMTargetMachineRef) -> Result<(), LTOError> {
+    let pipeline = CString::new(pipeline).unwrap();
+    let options = LLVMCreatePassBuilderOptions();
+    let error = LLVMRunPasses(module, pipeline.as_ptr(), machine, options);
+    LLVMDisposePassBuilderOptions(options);
+    if error.is_null() {
+        return Ok(());
+    }
+    let message = LLVMGetErrorMessage(error);
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(message);
+    Err(LTOError::Optimization(text))
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn emit_object(module: LLVMModuleRef, machine: LLVMTargetMachineRef) -> Result<Vec<u8>, LTOError> {
+    let mut buffer = std::ptr::null_mut();
+    let mut error = std::ptr::null_mut();
+    let failed = LLVMTargetMachineEmitToMemoryBuffer(
+        machine,
+        module,
+        LLVMCodeGenFileType::LLVMObjectFile,
+        &mut error,
+        &mut buffer,
+    );
+    if failed != 0 {
+        let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+        LLVMDisposeMessage(error);
+        return Err(LTOError::CodeGeneration(message));
+    }
+    let bytes = std::slice::from_raw_parts(LLVMGetBufferStart(buffer) as *const u8, LLVMGetBufferSize(buffer)).to_vec();
+    LLVMDisposeMemoryBuffer(buffer);
+    Ok(bytes)
+}
+
+/// A context of its own, whose error diagnostics are collected instead of
+/// ending the process
+#[cfg(feature = "llvm")]
+struct Session {
+    context: LLVMContextRef,
+    // Boxed so the handler's pointer stays put
+    errors: Box<RefCell<Vec<String>>>,
+}
+
+#[cfg(feature = "llvm")]
+impl Session {
+    fn new() -> Self {
+        unsafe {
+            let context = LLVMContextCreate();
+            let errors = Box::new(RefCell::new(Vec::new()));
+            LLVMContextSetDiagnosticHandler(context, Some(collect_error), &*errors as *const _ as *mut c_void);
+            Session { context, errors }
+        }
+    }
+
+    /// Parse a module into this context; it lives as long as the context
+    unsafe fn parse(&self, input: &InputModule) -> Result<LLVMModuleRef, LTOError> {
+        let name = CString::new(input.name.as_str()).unwrap_or_default();
+        let buffer = LLVMCreateMemoryBufferWithMemoryRangeCopy(input.bitcode.as_ptr() as *const _, input.bitcode.len(), name.as_ptr());
+        let mut module = std::ptr::null_mut();
+        let failed = LLVMParseBitcodeInContext2(self.context, buffer, &mut module);
+        LLVMDisposeMemoryBuffer(buffer);
+        if failed != 0 {
+            return Err(LTOError::Bitcode(input.name.clone()));
+        }
+        Ok(module)
+    }
+
+    /// Add what LLVM reported to a link error
+    fn explain(&self, error: LTOError) -> LTOError {
+        let errors = self.errors.borrow();
+        match error {
+            LTOError::Link(what) if !errors.is_empty() => LTOError::Link(format!("{}: {}", what, errors.join("; "))),
+            other => other,
+        }
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe { LLVMContextDispose(self.context) };
+    }
+}
+
+#[cfg(feature = "llvm")]
+extern "C" fn collect_error(info: LLVMDiagnosticInfoRef, errors: *mut c_void) {
+    unsafe {
+        if LLVMGetDiagInfoSeverity(info) != LLVMDiagnosticSeverity::LLVMDSError {
+            return;
+        }
+        let description = LLVMGetDiagInfoDescription(info);
+        let text = CStr::from_ptr(description).to_string_lossy().into_owned();
+        LLVMDisposeMessage(description);
+        (*(errors as *const RefCell<Vec<String>>)).borrow_mut().push(text);
+    }
+}
+
+/// A worker's own target machine; they can't be shared between threads
+#[cfg(feature = "llvm")]
+struct TargetMachine(LLVMTargetMachineRef);
+
+#[cfg(feature = "llvm")]
+impl TargetMachine {
+    unsafe fn new(options: &LtoOptions) -> Result<Self, LTOError> {
+        let triple = CString::new(options.target_triple.as_str()).map_err(|_| LTOError::Target(options.target_triple.clone()))?;
+        let mut target = std::ptr::null_mut();
+        let mut error = std::ptr::null_mut();
+        if LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut error) != 0 {
+            let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+            LLVMDisposeMessage(error);
+            return Err(LTOError::Target(message));
+        }
+
+        let cpu = CString::new(options.target_cpu.as_str()).unwrap_or_default();
+        let features = CString::new(options.target_features.as_str()).unwrap_or_default();
+        let level = match options.optimization_level {
+            0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            _ => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        };
+        let machine = LLVMCreateTargetMachine(
+            target,
+            triple.as_ptr(),
+            cpu.as_ptr(),
+            features.as_ptr(),
+            level,
+            options.relocation_model,
+            options.code_model,
+        );
+        if machine.is_null() {
+            return Err(LTOError::Target(options.target_triple.clone()));
+        }
+        Ok(TargetMachine(machine))
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl Drop for TargetMachine {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeTargetMachine(self.0) };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LTOError {
+    UnknownMode(String),
+    /// A module's bitcode didn't parse
+    Bitcode(String),
+    /// Linking modules failed, typically on a duplicate definition
+    Link(String),
+    Optimization(String),
+    CodeGeneration(String),
+    Target(String),
+    Parallel(String),
+}
+
+impl fmt::Display for LTOError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LTOError::UnknownMode(mode) => write!(f, "unknown LTO mode '{}' (expected thin or full)", mode),
+            LTOError::Bitcode(module) => write!(f, "lto: {}: invalid bitcode", module),
+            LTOError::Link(message) => write!(f, "lto: {}", message),
+            LTOError::Optimization(message) => write!(f, "lto: optimization failed: {}", message),
+            LTOError::CodeGeneration(message) => write!(f, "lto: code generation failed: {}", message),
+            LTOError::Target(message) => write!(f, "lto: no target machine for {}", message),
+            LTOError::Parallel(message) => write!(f, "lto: {}", message),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn link_program(units: &[(String, LLVMModuleRef)]) -> Result<Vec<LtoObject>, LTOError> {
+    let mut lto = LTOSystem::new(LtoOptions {
+        mode: LtoMode::Thin,
+        optimization_level: 2,
+        target_triple: "x86_64-unknown-linux-gnu".to_string(),
+        jobs: 8,
+        ..LtoOptions::default()
+    });
+    for (name, module) in units {
+        pre_link(*module, LtoMode::Thin, 2)?;
+        lto.add_module(name, *module);
+    }
+
+    // a.c's small helpers are inlined into b.c's callers; functions only
+    // a.c calls are internalized and, if inlined everywhere, dropped
+    let objects = lto.perform_lto()?;
+    for object in &objects {
+        std::fs::write(format!("{}.o", object.name), &object.bytes).unwrap();
+    }
+    Ok(objects)
+}
+*/
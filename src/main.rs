@@ -1,251 +1,1983 @@
-use clap::{Arg, ArgAction, Command};
+use clap::ArgMatches;
+use std::collections::HashSet;
 use std::fs;
 use std::io::{self, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-// Import our interpreter components
-mod abi;
-mod analysis;
-mod arch;
-mod build;
-mod compiler;
-mod cpu;
-mod debug;
-mod diagnostics;
-mod docs;
-mod driver;
-mod frontend;
-mod gui;
-mod ide;
-mod interpreter;
-mod jit;
-mod kernel;
-mod linker;
-mod lto;
-mod memory;
-mod metrics;
-mod monitoring;
-mod optimizer;
-mod orchestrator;
-mod pgo;
-mod pipeline;
-mod project;
-mod runtime;
-mod stdlib;
-mod syscall;
-mod testing;
-mod types;
-
-use compiler::CompilerOptions;
-use jit::JITOptions;
-use interpreter::c_runtime::CRuntimeEnvironment;
+mod cli;
+
+// The interpreter itself lives in the library crate
+use interpreter_c::{
+    analysis, arch, debug, diagnostics, driver, frontend, interpreter, ir, linker,
+    logging, lto, memory, optimizer, options, pgo, pipeline, report, runtime, stdlib, testing,
+};
+#[cfg(feature = "llvm")]
+use interpreter_c::compiler;
+#[cfg(feature = "llvm")]
+use interpreter_c::jit::tiered::{TierThresholds, TieredEngine};
+#[cfg(feature = "llvm")]
+use interpreter_c::jit::tiers::{self, BytecodeTier, LlvmTier};
+
+#[cfg(feature = "llvm")]
+use compiler::{CompilerOptions, EmitStage, JITOptions};
+use interpreter::bytecode::{self, BytecodeError, InterpreterEngine, Vm};
+use interpreter::c_runtime::{CRuntimeEnvironment, RuntimeError, SymbolResolver};
+use interpreter::probe_points::ProbePoints;
+use interpreter::record::{Trace, TraceEnd, TraceMode};
 use frontend::c23::C23Parser;
+use frontend::instrument::{self, InstrumentOptions};
+use frontend::recovery;
+use frontend::usdt::{self, Lowering};
+use report::{CompilationReport, RemarkFilter, ReportOptions, ReportTarget};
+#[cfg(feature = "llvm")]
+use report::OptimizationRemark;
+use report::provenance;
+use debug::environment::{EnvironmentSnapshot, ReproBundle};
+use analysis::semdiff::{self, DataModel, Impact, SemanticDiff};
+#[cfg(feature = "llvm")]
+use analysis::signal_safety::SignalSafetyOptions;
+#[cfg(feature = "llvm")]
+use analysis::stack_depth::StackDepthOptions;
+#[cfg(feature = "llvm")]
+use analysis::wcet::WcetOptions;
+#[cfg(feature = "llvm")]
+use debug::gdbstub::{spawn_stopped, GdbStub};
+use debug::reverse;
+use debug::DebugSystem;
+use testing::reduce::{self, shell_quote, CommandTest, Outcome};
+#[cfg(feature = "llvm")]
+use driver::batch::{BatchFile, BatchRunner, JobStatus, ProgramMain};
+use driver::daemon::{self, Daemon, DaemonConfig, DaemonReply, DaemonRequest};
+use driver::fallback::{MixedBuild, NativeError, ToolchainConfig};
+use driver::usage::{self, UsageStore, UsageSummary};
+#[cfg(feature = "llvm")]
+use linker::crt0::Crt0;
+use memory::leaks::{self, LeakConfig};
+use optimizer::budget::FunctionBudget;
+use optimizer::fastmath::{FpContract, FpOptions};
+use optimizer::loops::LoopTransforms;
+use options::Options;
+use optimizer::overflow::OverflowMode;
+use optimizer::sanitize::SanitizerSet;
+use pgo::profile::{Profile, ProfileFormat};
+#[cfg(feature = "llvm")]
+use pgo::sampling::{self, SampleProfile, SamplingConfig, SamplingProfiler};
+use pipeline::cache::{CacheKey, CompilationCache};
+use stdlib::bundled::{self, BundledLibc, LibcMode};
+use linker::oformat::{self, parse_address, ConversionOptions, OutputFormat};
+use linker::pkg_config::{self, Discovery};
+use runtime::capture::{self, CaptureMode};
+use runtime::deterministic::{self, Determinism, DeterministicConfig};
+use runtime::dynamic_loader::LibrarySearch;
+use runtime::exit_status::ProgramExit;
+#[cfg(feature = "llvm")]
+use runtime::exit_status::run_in_child;
+#[cfg(feature = "llvm")]
+use runtime::setjmp::{self, Interrupted};
+#[cfg(feature = "llvm")]
+use runtime::output_mux::{OutputMultiplexer, PrefixConfig};
+#[cfg(feature = "llvm")]
+use runtime::stack_guard;
+use runtime::limits::{self, ResourceLimits};
+use runtime::stdio::{self, ProgramStdin};
+use runtime::wasi::{DirAccess, WasiHost};
+use diagnostics::catalog::{Locale, MessageId};
+use diagnostics::engine::{Diagnostic, DiagnosticsConfig, DiagnosticsEngine, Severity, DEFAULT_ERROR_LIMIT};
 
 /// The main entry point for the Interpreter-C CLI
 fn main() -> io::Result<()> {
-    let matches = Command::new("c-interpreter")
-        .version("0.1.0")
-        .author("Interpreter-C Team")
-        .about("A high-performance C interpreter with JIT compilation")
-        .arg(
-            Arg::new("file")
-                .help("The C source file to interpret")
-                .index(1),
-        )
-        .arg(
-            Arg::new("jit")
-                .long("jit")
-                .short('j')
-                .help("Use JIT compilation (default)")
-                .action(ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("interpret")
-                .long("interpret")
-                .short('i')
-                .help("Use interpretation only (no JIT)")
-                .action(ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("optimization")
-                .long("opt")
-                .short('O')
-                .help("Optimization level (0-3)")
-                .default_value("2"),
-        )
-        .arg(
-            Arg::new("output")
-                .long("output")
-                .short('o')
-                .help("Output file (for compiled mode)"),
-        )
-        .arg(
-            Arg::new("compile")
-                .long("compile")
-                .short('c')
-                .help("Compile to object file instead of executing")
-                .action(ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("architecture")
-                .long("arch")
-                .short('a')
-                .help("Target architecture (x86_64, aarch64, arm, amdgpu, nvptx)")
-                .value_parser(["x86_64", "aarch64", "arm", "amdgpu", "nvptx"])
-                .default_value(std::env::consts::ARCH),
-        )
-        .arg(
-            Arg::new("include")
-                .long("include")
-                .short('I')
-                .help("Add directory to include search path")
-                .action(ArgAction::Append),
-        )
-        .arg(
-            Arg::new("verbose")
-                .long("verbose")
-                .short('v')
-                .help("Verbose output")
-                .action(ArgAction::SetTrue),
-        )
-        .get_matches();
+    let args: Vec<String> = std::env::args().collect();
+
+    // A warm daemon, when one is configured, does the work instead
+    if let Some(code) = daemon::forward_from_env(&args) {
+        process::exit(code);
+    }
+
+    let result = run_command_line(args);
+    if result.is_err() {
+        usage::note_error(usage::IO);
+    }
+    usage::finish(if result.is_ok() { 0 } else { 1 });
+    result
+}
 
-    // Get source code
-    let source_code = if let Some(filename) = matches.get_one::<String>("file") {
-        fs::read_to_string(filename)?
+/// Everything after startup; also what `daemon` runs for each request
+fn run_command_line(args: Vec<String>) -> io::Result<()> {
+    // Recorded in a crash repro bundle as given, before normalization
+    let command_line = args.clone();
+    let matches = cli::build_cli().get_matches_from(normalize_gcc_style_args(args.into_iter()));
+
+    // Global options are propagated to the selected subcommand
+    let (command, opts) = match matches.subcommand() {
+        Some((name, sub_matches)) => (name, sub_matches),
+        None => ("", &matches),
+    };
+
+    // Workers forked from the daemon install their own logger per request
+    if command == "daemon" {
+        return run_daemon(opts);
+    }
+
+    // Install the logger before anything else can emit
+    let default_level = if command == "debug" {
+        log::LevelFilter::Debug
+    } else if opts.get_flag("verbose") {
+        log::LevelFilter::Info
     } else {
-        // Read from stdin if no file is specified
+        log::LevelFilter::Warn
+    };
+    if let Err(e) = logging::init(
+        opts.get_one::<String>("log").map(|s| s.as_str()),
+        opts.get_one::<String>("log-format").map(|s| s.as_str()),
+        default_level,
+    ) {
+        eprintln!("Error: invalid logging configuration: {:?}", e);
+        process::exit(1);
+    }
+
+    // Opt-in and local only; see `stats`
+    if command != "stats" {
+        usage::begin(command, flags_used(command, opts));
+    }
+
+    // Every compilation setting, from the flags
+    let options = options_from_args(opts);
+    let architecture = options.architecture.clone();
+
+    let mode = match command {
+        "doctor" => return run_doctor(opts),
+        "stats" => return run_stats(opts),
+        "verify-provenance" => return run_verify_provenance(opts),
+        "dump-ir" => return run_dump_ir(opts),
+        "explain" => return run_explain(opts),
+        "instrument" => return run_instrument(opts),
+        "reduce" => return run_reduce(opts),
+        #[cfg(feature = "llvm")]
+        "stack-depth" => return run_stack_depth(opts, &options),
+        #[cfg(feature = "llvm")]
+        "wcet" => return run_wcet(opts, &options),
+        #[cfg(feature = "llvm")]
+        "signal-safety" => return run_signal_safety(opts, &options),
+        "semdiff" => return run_semdiff(opts, &architecture),
+        "abi-check" => return run_abi_check(opts),
+        "merge-profiles" => return run_merge_profiles(opts),
+        "completions" => return run_completions(opts),
+        "man" => return run_man(opts),
+        #[cfg(feature = "llvm")]
+        "repl" => return run_repl(&options),
+        "test" => return run_tests(opts, &options),
+        #[cfg(feature = "llvm")]
+        "batch" => return run_batch(opts, &options),
+        #[cfg(not(feature = "llvm"))]
+        "stack-depth" | "wcet" | "signal-safety" | "repl" | "batch" => without_llvm(command),
+        "build" => return run_build(opts, &options),
+        "run" if opts.get_flag("interpret") => "interpret",
+        "run" => "jit",
+        "compile" => "compile",
+        "debug" => "debug",
+        "analyze" => "analyze",
+        // No subcommand: the original flag-driven interface
+        _ if opts.get_flag("compile") => "compile",
+        _ if opts.get_flag("interpret") => "interpret",
+        _ => "jit",
+    };
+    let engine = opts
+        .get_one::<String>("engine")
+        .and_then(|s| s.parse::<InterpreterEngine>().ok())
+        .unwrap_or_default();
+    // Built without LLVM, the bytecode engine's native tier runs what the
+    // JIT would
+    #[cfg(not(feature = "llvm"))]
+    let (mode, engine) = match mode {
+        "jit" if engine == InterpreterEngine::Tree => ("interpret", InterpreterEngine::Native),
+        "jit" => ("interpret", engine),
+        "compile" => without_llvm("compiling (-c)"),
+        _ => (mode, engine),
+    };
+    usage::set_command(mode);
+    if opts.get_flag("provenance") && !matches!(mode, "interpret" | "debug") {
+        log::warn!("--provenance only applies to the interpreter (-i)");
+    }
+    if opts.get_flag("audit-signals") && !matches!(mode, "interpret" | "debug") {
+        log::warn!("--audit-signals only applies to the interpreter (-i); see the signal-safety subcommand for compiled code");
+    }
+    let trace = match (opts.get_one::<String>("record"), opts.get_one::<String>("replay")) {
+        (Some(path), _) => TraceMode::Record(PathBuf::from(path)),
+        (None, Some(path)) => TraceMode::Replay(PathBuf::from(path)),
+        (None, None) => TraceMode::Off,
+    };
+    if trace != TraceMode::Off && !matches!(mode, "interpret" | "debug") {
+        log::warn!("--record and --replay only apply to the interpreter (-i)");
+    }
+    let sandbox = sandbox_from_args(opts);
+    if sandbox.is_some() && !matches!(mode, "interpret" | "debug") {
+        log::warn!("--dir and --dir-ro only apply to the interpreter (-i)");
+    }
+    let limits = limits_from_args(opts);
+    if !limits.is_unlimited() && !matches!(mode, "interpret" | "debug") {
+        log::warn!("--max-time, --max-memory and the other limits only apply to the interpreter (-i)");
+    }
+    if engine != InterpreterEngine::Tree && mode != "interpret" {
+        log::warn!("--engine only applies to the interpreter (-i)");
+    }
+    let emit_ir = opts.get_one::<String>("emit-ir").map(Path::new);
+    if emit_ir.is_some() && (engine == InterpreterEngine::Tree || mode != "interpret") {
+        log::warn!("--emit-ir only applies to the bytecode engine (-i --engine=bytecode or native)");
+    }
+    let usdt: Vec<String> = opts.get_many::<String>("usdt").map(|patterns| patterns.cloned().collect()).unwrap_or_default();
+    if !usdt.is_empty() && !matches!(mode, "interpret" | "debug") {
+        log::warn!("--usdt only applies to the interpreter (-i); compiled probe sites do nothing");
+    }
+    if options.function_budget.is_some() && mode != "compile" {
+        log::warn!("--opt-time-budget and --opt-memory-budget only apply when compiling (-c)");
+    }
+    if (options.profile_generate.is_some() || options.profile_use.is_some()) && !matches!(mode, "compile" | "jit") {
+        log::warn!("--profile-generate and --profile-use only apply to compiled code (-c or the JIT)");
+    }
+    if options.capture.is_some() && mode != "jit" {
+        log::warn!("--capture-io and --replay-io only apply to the JIT");
+    }
+    if options.detect_leaks.is_some() && !matches!(mode, "jit" | "interpret") {
+        eprintln!("Error: --detect-leaks only applies to the JIT and the interpreter");
+        process::exit(1);
+    }
+    let sample_profile = opts.get_one::<String>("sample-profile").map(String::as_str);
+    if sample_profile.is_some() && mode != "jit" {
+        log::warn!("--sample-profile only applies to the JIT");
+    }
+    let tiered = opts.get_flag("tiered");
+    if tiered && mode != "jit" {
+        log::warn!("--tiered only applies to the JIT");
+    }
+    if options.deterministic.is_some() {
+        // Starts the process over with randomization off, if it was on
+        if let Err(e) = deterministic::disable_aslr() {
+            log::warn!("--deterministic: addresses will vary, cannot disable ASLR: {}", e);
+        }
+    }
+
+    // --libc=bundled: build (or reuse) the embedded libc before compiling
+    // or interpreting against it
+    #[cfg(feature = "llvm")]
+    let bundled_libc = match options.libc {
+        LibcMode::Bundled if mode != "analyze" => Some(prepare_bundled_libc(&architecture, options.cache_dir.as_deref())),
+        _ => None,
+    };
+    // Building it takes the compiler
+    #[cfg(not(feature = "llvm"))]
+    let bundled_libc: Option<BundledLibc> = match options.libc {
+        LibcMode::Bundled if mode != "analyze" => without_llvm("--libc=bundled"),
+        _ => None,
+    };
+
+    // Get source code; `-` reads it from stdin
+    let source_from_stdin = match opts.get_one::<String>("file").map(|s| s.as_str()) {
+        Some("-") => true,
+        Some(_) => false,
+        // Typed in by hand, as before `-` existed; say how to finish
+        None if stdio::stdin_is_tty() => {
+            let eof = if cfg!(windows) { "Ctrl-Z then Enter" } else { "Ctrl-D" };
+            eprintln!("Reading the program from the terminal; end it with {} (or pass a file)", eof);
+            true
+        }
+        None => {
+            log::warn!("reading the program from stdin without '-' is deprecated");
+            true
+        }
+    };
+
+    let source_code = if source_from_stdin {
         let mut buffer = String::new();
         io::stdin().read_to_string(&mut buffer)?;
         buffer
+    } else {
+        fs::read_to_string(opts.get_one::<String>("file").unwrap())?
+    };
+
+    // Give the program a usable stdin now that ours may be consumed
+    let program_stdin = ProgramStdin::select(
+        opts.get_one::<String>("stdin-file").map(PathBuf::from),
+        source_from_stdin,
+    );
+    if let Err(e) = program_stdin.connect() {
+        eprintln!("Error: failed to connect program stdin ({:?}): {}", program_stdin, e);
+        process::exit(1);
+    }
+
+    // Log the configuration (visible with --verbose or --log=info)
+    log::info!("Source length: {} characters", source_code.len());
+    log::info!("Optimization level: {}", options.optimization_level);
+    log::info!("Target architecture: {}", architecture);
+    log::info!("Mode: {}", mode);
+
+    let diagnostics_config = DiagnosticsConfig {
+        error_limit: opts
+            .get_one::<usize>("error-limit")
+            .copied()
+            .unwrap_or(DEFAULT_ERROR_LIMIT),
+        locale: match opts.get_one::<String>("locale") {
+            Some(requested) => Locale::parse(requested).unwrap_or_else(|| {
+                log::warn!("unsupported locale '{}', using English", requested);
+                Locale::En
+            }),
+            None => Locale::from_env(),
+        },
+        ..DiagnosticsConfig::default()
+    };
+
+    // Reject C++ up front instead of failing with a cascade of parse errors
+    let file_name = opts.get_one::<String>("file").map(|s| s.as_str()).unwrap_or("<stdin>");
+    reject_cxx_source(&source_code, file_name, &diagnostics_config);
+
+    // A repro bundle holds the program as written; replaying its command
+    // line instruments it again
+    let bundle_source = opts.get_one::<String>("crash-bundle").map(|_| source_code.clone());
+
+    // --instrument: wrap the selected functions before anything compiles them
+    let source_code = match instrument_options(opts, false) {
+        Some(instrument) => instrument_source(&source_code, file_name, &instrument),
+        None => source_code,
+    };
+
+    // -l and #pragma comment(lib): pkg-config knows where a library's
+    // headers are and what else it needs
+    let options = if matches!(mode, "compile" | "jit") && !opts.get_flag("no-pkg-config") {
+        let static_link = mode == "compile" && (opts.get_flag("nostdlib") || bundled_libc.is_some());
+        discover_system_libraries(options, &source_code, static_link)
+    } else {
+        options
+    };
+
+    // Set up the compilation report if requested; the stages add to it
+    if let Some(path) = opts.get_one::<String>("report") {
+        let started = CompilationReport::new(
+            mode,
+            ReportOptions {
+                optimization_level: options.optimization_level,
+                include_paths: opts
+                    .get_many::<String>("include")
+                    .map(|dirs| dirs.cloned().collect())
+                    .unwrap_or_default(),
+                source: opts.get_one::<String>("file").cloned(),
+            },
+            ReportTarget {
+                architecture: architecture.clone(),
+                triple: options.target_triple().to_string(),
+            },
+        );
+        report::begin(Path::new(path), started);
+    }
+
+    // Execute or compile based on options
+    let start = Instant::now();
+    let exit = match mode {
+        #[cfg(feature = "llvm")]
+        "compile" => {
+            let remarks = compile_code(
+                &source_code,
+                opts.get_one::<String>("output"),
+                &options,
+                opts.get_flag("nostdlib"),
+                bundled_libc.as_ref(),
+                opts.get_one::<String>("linker").map_or(false, |linker| linker == "builtin"),
+                opts.get_flag("gc-sections"),
+                opts.get_one::<String>("linker-script"),
+            )?;
+            print_remarks(&remarks, &options.remarks);
+            convert_output(opts)?;
+            ProgramExit::Exited(0)
+        }
+        "interpret" => interpret_code(
+            &source_code,
+            opts.get_flag("vm-stats"),
+            opts.get_flag("provenance"),
+            opts.get_flag("audit-signals"),
+            options.overflow,
+            options.sanitizers,
+            &trace,
+            sandbox,
+            limits,
+            options.deterministic,
+            &usdt,
+            engine,
+            options.cache_dir.as_deref(),
+            emit_ir,
+            bundled_libc.as_ref(),
+            options.detect_leaks.as_ref(),
+            diagnostics_config,
+        )?,
+        // Tracing comes from the debug log level set above
+        "debug" => match (opts.get_one::<u16>("gdb-port"), &trace) {
+            (_, TraceMode::Replay(path)) => debug_recording(path, &source_code)?,
+            #[cfg(feature = "llvm")]
+            (Some(port), _) => jit_debug(&source_code, &options, bundled_libc.as_ref(), *port)?,
+            #[cfg(not(feature = "llvm"))]
+            (Some(_), _) => without_llvm("--gdb-port"),
+            (None, _) => interpret_code(&source_code, true, opts.get_flag("provenance"), opts.get_flag("audit-signals"), options.overflow, options.sanitizers, &trace, sandbox, limits, options.deterministic, &usdt, InterpreterEngine::Tree, None, None, bundled_libc.as_ref(), None, diagnostics_config)?,
+        },
+        "analyze" => {
+            analyze_code(&source_code, diagnostics_config)?;
+            ProgramExit::Exited(0)
+        }
+        // Default: JIT execution
+        #[cfg(feature = "llvm")]
+        _ => jit_execute(&source_code, &options, bundled_libc.as_ref(), sample_profile, tiered)?,
+        #[cfg(not(feature = "llvm"))]
+        _ => without_llvm("the JIT"),
+    };
+
+    report::update(|report| {
+        report.record_timing("total", start.elapsed());
+
+        if mode == "compile" {
+            let output = opts.get_one::<String>("output").map(|s| s.as_str()).unwrap_or("a.out");
+            if let Err(e) = report.record_artifact(Path::new(output)) {
+                log::warn!("could not hash artifact '{}': {:?}", output, e);
+            }
+        }
+    });
+    if let Err(e) = report::finish() {
+        let report_path = opts.get_one::<String>("report").map(String::as_str).unwrap_or_default();
+        eprintln!("Error: failed to write report to '{}': {:?}", report_path, e);
+        process::exit(1);
+    }
+
+    // Keep stdout clean for the program's own output
+    if opts.get_flag("print-exit-status") && mode != "compile" && mode != "analyze" {
+        eprintln!("Program {}", exit);
+    }
+
+    if let (ProgramExit::Signaled(_), Some(dir), Some(source)) =
+        (&exit, opts.get_one::<String>("crash-bundle"), &bundle_source)
+    {
+        let bundle = ReproBundle { command_line, mode: mode.to_string(), exit: exit.to_string() };
+        match bundle.write(Path::new(dir), source) {
+            Ok(path) => eprintln!("Crash repro bundle written to {}", path.display()),
+            Err(e) => eprintln!("Error: failed to write crash repro bundle under '{}': {:?}", dir, e),
+        }
+    }
+
+    usage::finish(exit.code());
+    process::exit(exit.code());
+}
+
+/// The flags given on the command line, by name and without their values,
+/// for the usage statistics
+fn flags_used(command: &str, opts: &ArgMatches) -> Vec<String> {
+    let mut cli = cli::build_cli();
+    // Propagates the global flags to the subcommands
+    cli.build();
+    let cli = match cli.find_subcommand(command) {
+        Some(subcommand) => subcommand.clone(),
+        None => cli,
+    };
+    cli.get_arguments()
+        .filter(|arg| !arg.is_positional())
+        .filter(|arg| opts.value_source(arg.get_id().as_str()) == Some(clap::parser::ValueSource::CommandLine))
+        .filter_map(|arg| match (arg.get_long(), arg.get_short()) {
+            (Some(long), _) => Some(format!("--{}", long)),
+            (None, Some(short)) => Some(format!("-{}", short)),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+/// Accept GCC/Clang style single-dash `-fname[=value]` and `-Rpass=...`
+/// flags by rewriting them to the `--fname[=value]` form clap understands.
+/// Only names the command line defines are rewritten, and nothing after
+/// `--`, so file names and program arguments starting with `-f` pass through.
+fn normalize_gcc_style_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    let command = cli::build_cli();
+    let known: HashSet<&str> = command
+        .get_arguments()
+        .flat_map(|arg| arg.get_long().into_iter().chain(arg.get_all_aliases().unwrap_or_default()))
+        .filter(|name| name.starts_with('f') || name.starts_with("Rpass"))
+        .collect();
+
+    let mut passthrough = false;
+    args.map(|arg| {
+        passthrough |= arg == "--";
+        let name = arg.strip_prefix('-').unwrap_or("").split('=').next().unwrap_or("");
+        if !passthrough && !arg.starts_with("--") && known.contains(name) {
+            format!("-{}", arg)
+        } else {
+            arg
+        }
+    })
+    .collect()
+}
+
+/// Print a completion script for the requested shell
+fn run_completions(matches: &ArgMatches) -> io::Result<()> {
+    let shell = matches.get_one::<String>("shell").unwrap();
+    if let Err(e) = cli::print_completions(shell) {
+        eprintln!("Error: {:?}", e);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Write man pages for the command and its subcommands
+fn run_man(matches: &ArgMatches) -> io::Result<()> {
+    let dir = matches.get_one::<String>("dir").unwrap();
+    match cli::generate_man_pages(Path::new(dir)) {
+        Ok(pages) => {
+            for page in pages {
+                println!("{}/{}", dir, page);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Error: failed to write man pages to '{}': {:?}", dir, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Interactive session backed by the JIT
+#[cfg(feature = "llvm")]
+fn run_repl(options: &Options) -> io::Result<()> {
+    let options = options.clone();
+    let mut repl = driver::repl::Repl::new(Box::new(move |unit| jit_eval(unit, &options)));
+    repl.run()
+}
+
+/// Run test programs and exit non-zero if any fail
+fn run_tests(matches: &ArgMatches, options: &Options) -> io::Result<()> {
+    if let Some(root) = matches.get_one::<String>("libc-headers") {
+        let history = Path::new(matches.get_one::<String>("compat-history").unwrap());
+        #[cfg(feature = "llvm")]
+        return run_header_compat(root, history);
+        #[cfg(not(feature = "llvm"))]
+        without_llvm("--libc-headers");
+    }
+    if matches.get_flag("encoder") {
+        if !testing::encoder::run().passed() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if matches.get_flag("dispatch") {
+        return run_dispatch_bench(matches);
+    }
+    run_programs(matches, options)
+}
+
+/// Time the bytecode dispatch loops, failing below the speedup gate or when
+/// a kernel got slower than the baseline. The first run on a machine records
+/// the baseline instead, since timings only compare on the same hardware.
+fn run_dispatch_bench(matches: &ArgMatches) -> io::Result<()> {
+    use testing::perf::dispatch::{DispatchBenchSuite, DispatchResults};
+
+    let fail = |e: &dyn std::fmt::Display| -> ! {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    };
+    let results = DispatchBenchSuite::new(5).run().unwrap_or_else(|e| fail(&e));
+    for kernel in &results.kernels {
+        println!(
+            "{:<14} switch {:>8.1} ms  threaded {:>8.1} ms  fused {:>8.1} ms ({} superinstructions)",
+            kernel.kernel, kernel.switch_ms, kernel.threaded_ms, kernel.fused_ms, kernel.superinstructions
+        );
+    }
+    println!("threaded with superinstructions: {:.2}x the switch loop", results.speedup());
+
+    let mut failed = false;
+    if let Err(e) = results.gate(*matches.get_one::<f64>("min-speedup").unwrap()) {
+        eprintln!("Error: {}", e);
+        failed = true;
+    }
+
+    let baseline = Path::new(matches.get_one::<String>("dispatch-baseline").unwrap());
+    if baseline.exists() {
+        let threshold = matches.get_one::<f64>("max-regression").unwrap() / 100.0;
+        let regressions = results.regressions_against(&DispatchResults::load_baseline(baseline).unwrap_or_else(|e| fail(&e)), threshold);
+        for r in &regressions {
+            eprintln!("regression: {} {:.1} ms -> {:.1} ms ({:+.1}%)", r.kernel, r.baseline_ms, r.current_ms, r.slowdown * 100.0);
+        }
+        failed |= !regressions.is_empty();
+    } else {
+        eprintln!("No dispatch baseline found, recording {}", baseline.display());
+        results.save_baseline(baseline).unwrap_or_else(|e| fail(&e));
+    }
+
+    if failed {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Run test programs with the JIT, and check what they emit
+#[cfg(feature = "llvm")]
+fn run_programs(matches: &ArgMatches, options: &Options) -> io::Result<()> {
+    let paths: Vec<PathBuf> = matches
+        .get_many::<String>("paths")
+        .map(|paths| paths.map(PathBuf::from).collect())
+        .unwrap_or_default();
+
+    let tests = match testing::programs::discover(&paths) {
+        Ok(tests) => tests,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut runner = |source: &str| jit_eval(source, options);
+    let mut emitter = |source: &str, stage| emit_for_check(source, stage, options);
+    let jobs = if options.jobs == 0 { driver::parallel::default_jobs() } else { options.jobs };
+    let results = if jobs > 1 {
+        // Tests run side by side; their output comes out a whole line at a
+        // time, tagged with the test
+        let mut mux = OutputMultiplexer::new(PrefixConfig::default());
+        let results = testing::programs::run_parallel(&tests, jobs, &mux, &mut runner, &mut emitter);
+        mux.shutdown();
+        results
+    } else {
+        testing::programs::run_all(&tests, &mut runner, &mut emitter)
+    };
+    if results.iter().any(|r| !r.passed()) {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "llvm"))]
+fn run_programs(_matches: &ArgMatches, _options: &Options) -> io::Result<()> {
+    without_llvm("running test programs")
+}
+
+/// Print the worst-case stack depth of each entry point; with `--limit`,
+/// exit 1 if one may not fit
+#[cfg(feature = "llvm")]
+fn run_stack_depth(matches: &ArgMatches, options: &Options) -> io::Result<()> {
+    let file = matches.get_one::<String>("file").unwrap();
+    let source = if file == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        buffer
+    } else {
+        fs::read_to_string(file)?
+    };
+
+    let mut analysis = StackDepthOptions {
+        entries: matches.get_many::<String>("entry").map_or_else(Vec::new, |entries| entries.cloned().collect()),
+        ..StackDepthOptions::default()
+    };
+    let annotations = &mut analysis.annotations;
+    let specs = |id: &str| matches.get_many::<String>(id).into_iter().flatten();
+    let parsed = specs("indirect")
+        .try_for_each(|spec| annotations.add_indirect(spec))
+        .and_then(|()| specs("recursion").try_for_each(|spec| annotations.add_recursion_bound(spec)))
+        .and_then(|()| specs("frame").try_for_each(|spec| annotations.add_external_frame(spec)));
+    if let Err(e) = parsed {
+        eprintln!("Error: {}", e);
+        process::exit(2);
+    }
+
+    let compiler = unsafe { compiler::Compiler::new() }.unwrap_or_else(|e| {
+        eprintln!("Failed to initialize compiler: {:?}", e);
+        process::exit(2);
+    });
+    let report = unsafe { compiler.stack_depth(&source, &options.compiler_options(None), &analysis) }.unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        process::exit(2);
+    });
+
+    if matches.get_one::<String>("format").map(String::as_str) == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&report).map_err(io::Error::other)?);
+    } else {
+        print!("{}", report);
+    }
+
+    if let Some(&limit) = matches.get_one::<u64>("limit") {
+        let exceeding: Vec<&str> = report.exceeding(limit).map(|entry| entry.entry.as_str()).collect();
+        if !exceeding.is_empty() {
+            eprintln!("may not fit in {} bytes: {}", limit, exceeding.join(", "));
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Print the worst-case execution time of each function; with `--limit`,
+/// exit 1 if one may take longer
+#[cfg(feature = "llvm")]
+fn run_wcet(matches: &ArgMatches, options: &Options) -> io::Result<()> {
+    let file = matches.get_one::<String>("file").unwrap();
+    let source = if file == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        buffer
+    } else {
+        fs::read_to_string(file)?
+    };
+
+    let mut analysis = WcetOptions {
+        functions: matches.get_many::<String>("function").map_or_else(Vec::new, |functions| functions.cloned().collect()),
+        cpu: matches.get_one::<String>("cpu").cloned(),
+        ..WcetOptions::default()
+    };
+    let annotations = &mut analysis.annotations;
+    let specs = |id: &str| matches.get_many::<String>(id).into_iter().flatten();
+    let parsed = specs("loop-bound")
+        .try_for_each(|spec| annotations.add_loop_bound(spec))
+        .and_then(|()| specs("indirect").try_for_each(|spec| annotations.add_indirect(spec)))
+        .and_then(|()| specs("cost").try_for_each(|spec| annotations.add_external_cost(spec)));
+    if let Err(e) = parsed {
+        eprintln!("Error: {}", e);
+        process::exit(2);
+    }
+
+    let compiler = unsafe { compiler::Compiler::new() }.unwrap_or_else(|e| {
+        eprintln!("Failed to initialize compiler: {:?}", e);
+        process::exit(2);
+    });
+    let report = unsafe { compiler.wcet(&source, &options.compiler_options(None), &analysis) }.unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        process::exit(2);
+    });
+
+    if matches.get_one::<String>("format").map(String::as_str) == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&report).map_err(io::Error::other)?);
+    } else {
+        print!("{}", report);
+    }
+
+    if let Some(&limit) = matches.get_one::<u64>("limit") {
+        let exceeding: Vec<&str> = report.exceeding(limit).map(|function| function.function.as_str()).collect();
+        if !exceeding.is_empty() {
+            eprintln!("may take more than {} cycles: {}", limit, exceeding.join(", "));
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Print the calls that aren't async-signal-safe in reach of each signal
+/// handler; exit 1 if there are any
+#[cfg(feature = "llvm")]
+fn run_signal_safety(matches: &ArgMatches, options: &Options) -> io::Result<()> {
+    let file = matches.get_one::<String>("file").unwrap();
+    let source = if file == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        buffer
+    } else {
+        fs::read_to_string(file)?
+    };
+
+    let names = |id: &str| matches.get_many::<String>(id).map_or_else(Vec::new, |names| names.cloned().collect());
+    let analysis = SignalSafetyOptions { handlers: names("handler"), safe: names("safe") };
+
+    let compiler = unsafe { compiler::Compiler::new() }.unwrap_or_else(|e| {
+        eprintln!("Failed to initialize compiler: {:?}", e);
+        process::exit(2);
+    });
+    let report = unsafe { compiler.signal_safety(&source, &options.compiler_options(None), &analysis) }.unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        process::exit(2);
+    });
+
+    if matches.get_one::<String>("format").map(String::as_str) == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&report).map_err(io::Error::other)?);
+    } else {
+        print!("{}", report);
+    }
+    if !report.is_clean() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// The IR or assembly a codegen test's `// CHECK:` lines are matched against
+#[cfg(feature = "llvm")]
+fn emit_for_check(source: &str, stage: EmitStage, options: &Options) -> Result<String, String> {
+    let compiler = unsafe { compiler::Compiler::new() }
+        .map_err(|e| format!("Failed to initialize compiler: {:?}", e))?;
+    let options = CompilerOptions {
+        debug_info: false,
+        ..options.compiler_options(None)
+    };
+    unsafe { compiler.emit(source, &options, stage) }.map_err(|e| format!("{:?}", e))
+}
+
+/// Run every job in a batch file and write the results; exit 1 unless all pass
+#[cfg(feature = "llvm")]
+fn run_batch(opts: &ArgMatches, options: &Options) -> io::Result<()> {
+    let path = Path::new(opts.get_one::<String>("jobs-file").unwrap());
+    let batch = BatchFile::load(path).unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        process::exit(1);
+    });
+
+    // Compile the shared prelude once, before forking, so every job starts warm
+    let warmup = format!("{}\nint main(void) {{ return 0; }}\n", batch.prelude);
+    if let Err(e) = jit_eval(&warmup, options) {
+        eprintln!("Error: the batch prelude does not compile: {}", e);
+        process::exit(1);
+    }
+
+    let compile = |source: &str| -> ProgramMain {
+        let (compiler, main_fn) = jit_compile_main(source, options, None, false);
+        // The job's child exits as soon as main returns
+        std::mem::forget(compiler);
+        main_fn
+    };
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut runner = BatchRunner::new(options.jobs, base_dir, &compile);
+
+    // Whole lines tagged with the job name, never torn between jobs
+    let mut mux = opts.get_flag("live-output").then(|| {
+        OutputMultiplexer::new(PrefixConfig { show_timestamp: opts.get_flag("timestamps"), ..PrefixConfig::default() })
+    });
+    if let Some(mux) = &mux {
+        runner = runner.with_live_output(mux);
+    }
+    let report = runner.run(&batch);
+    if let Some(mux) = &mut mux {
+        mux.shutdown();
+    }
+
+    for result in report.jobs.iter().filter(|r| r.status != JobStatus::Passed) {
+        eprintln!("{}: {:?}", result.name, result.status);
+    }
+    eprintln!("{}/{} jobs passed in {} ms", report.passed, report.total, report.elapsed_ms);
+
+    let results = opts.get_one::<String>("results").unwrap();
+    if let Err(e) = report.write_to(Path::new(results)) {
+        eprintln!("Error: failed to write results to '{}': {:?}", results, e);
+        process::exit(1);
+    }
+    if report.passed != report.total {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Parse every libc header and record the pass rate; exit 1 on regressions
+#[cfg(feature = "llvm")]
+fn run_header_compat(root: &str, history: &Path) -> io::Result<()> {
+    use testing::headers::{self, LibcFlavor, LibcHeaders};
+
+    let installed = if root == "auto" {
+        LibcHeaders::discover()
+    } else {
+        let root = Path::new(root);
+        vec![LibcHeaders::at(root, LibcFlavor::detect(root))]
+    };
+    if installed.is_empty() {
+        eprintln!("Error: {:?}", headers::HeaderCompatError::NoLibcFound);
+        process::exit(1);
+    }
+
+    // The headers are parsed for the host, the way the compiler sees them
+    let triple = options::target_triple(std::env::consts::ARCH);
+    let compiler = unsafe { compiler::CompilerSystem::new(triple) }.unwrap_or_else(|e| {
+        eprintln!("Failed to initialize compiler: {:?}", e);
+        process::exit(1);
+    });
+    let mut regressed = false;
+    for libc in &installed {
+        let report = headers::run(libc, &mut |source, include_dirs| {
+            compiler.parse_only(source, &[], include_dirs).map_err(|e| format!("{:?}", e))
+        });
+
+        match headers::record(history, &report) {
+            Ok(regressions) => {
+                for regression in &regressions {
+                    eprintln!("regression: {:?}", regression);
+                }
+                regressed |= !regressions.is_empty();
+            }
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if regressed {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Exit with E0016 diagnostics if `source` contains C++-only constructs
+fn reject_cxx_source(source: &str, file_name: &str, diagnostics_config: &DiagnosticsConfig) {
+    let found = diagnostics::cxx::detect(source);
+    if found.is_empty() {
+        if diagnostics::cxx::has_cxx_extension(file_name) {
+            log::warn!("'{}' has a C++ file extension; compiling it as C", file_name);
+        }
+        return;
+    }
+
+    let mut diagnostics = DiagnosticsEngine::new(diagnostics_config.clone());
+    for diagnostic in diagnostics::cxx::diagnostics(file_name, &found) {
+        diagnostics.report(diagnostic);
+    }
+    diagnostics.flush_to_stderr();
+    process::exit(1);
+}
+
+/// Compile several files, routing some through the host toolchain, then link
+fn run_build(matches: &ArgMatches, options: &Options) -> io::Result<()> {
+    let config = match ToolchainConfig::discover(
+        matches.get_one::<String>("toolchain-config").map(Path::new),
+    ) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            process::exit(1);
+        }
+    };
+
+    let sources: Vec<PathBuf> = matches
+        .get_many::<String>("files")
+        .map(|files| files.map(PathBuf::from).collect())
+        .unwrap_or_default();
+    let output = PathBuf::from(matches.get_one::<String>("output").unwrap());
+    let libraries: Vec<String> = matches
+        .get_many::<String>("library")
+        .map(|libs| libs.cloned().collect())
+        .unwrap_or_default();
+
+    let object_dir = output.with_extension("objs");
+    let mut build = MixedBuild::new(config.fallback, &options.architecture, &object_dir).with_jobs(options.jobs);
+    let result = build
+        .compile_all(&sources, &|source, object| {
+            compile_object(source, object, options)
+        })
+        .and_then(|_| build.link(&output, &libraries));
+
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        process::exit(1);
+    }
+
+    let delegated = build.units().iter().filter(|u| u.route.is_host()).count();
+    log::info!("Built {} ({} of {} files via the host toolchain)", output.display(), delegated, sources.len());
+    Ok(())
+}
+
+/// Compile one file to an object without linking
+#[cfg(feature = "llvm")]
+fn compile_object(source: &Path, object: &Path, options: &Options) -> Result<(), NativeError> {
+    if options.cpu_architecture().is_none() {
+        return Err(NativeError::Unsupported(format!("no code generator for '{}'", options.architecture)));
+    }
+
+    let compiler = unsafe { compiler::Compiler::new() }
+        .map_err(|e| NativeError::Failed(format!("failed to initialize compiler: {:?}", e)))?;
+
+    let options = options.compiler_options(None);
+    match unsafe { compiler.compile_file(&source.to_string_lossy(), &object.to_string_lossy(), &options) } {
+        Ok(()) => Ok(()),
+        Err(compiler::CompilerError::UnsupportedArchitecture(arch)) => {
+            Err(NativeError::Unsupported(format!("unsupported architecture '{}'", arch)))
+        }
+        Err(compiler::CompilerError::Unsupported(diagnostics)) => {
+            let reasons: Vec<String> = diagnostics.iter().map(|diagnostic| diagnostic.message.clone()).collect();
+            Err(NativeError::Unsupported(reasons.join("; ")))
+        }
+        Err(e) => Err(NativeError::Failed(format!("{:?}", e))),
+    }
+}
+
+/// Without LLVM every file goes to the host toolchain, if the fallback
+/// configuration allows it
+#[cfg(not(feature = "llvm"))]
+fn compile_object(_source: &Path, _object: &Path, _options: &Options) -> Result<(), NativeError> {
+    Err(NativeError::Unsupported("built without the `llvm` feature".to_string()))
+}
+
+/// Print the extended description of a diagnostic code
+fn run_explain(matches: &ArgMatches) -> io::Result<()> {
+    let code = matches.get_one::<String>("code").unwrap();
+    match diagnostics::codes::lookup(code) {
+        Some(info) => {
+            print!("{}", diagnostics::codes::explain(info));
+            Ok(())
+        }
+        None => {
+            eprintln!("Error: '{}' is not a known diagnostic code", code);
+            process::exit(1);
+        }
+    }
+}
+
+/// What `--instrument` and `--instrument-log` ask for; `None` without
+/// patterns, unless `all_by_default`
+fn instrument_options(matches: &ArgMatches, all_by_default: bool) -> Option<InstrumentOptions> {
+    let functions: Vec<String> = match matches.get_many::<String>("instrument") {
+        Some(patterns) => patterns.cloned().collect(),
+        None if all_by_default => vec!["*".to_string()],
+        None => return None,
+    };
+    let list = matches.get_one::<String>("instrument-log").map_or("entry,exit", |s| s.as_str());
+    let actions = InstrumentOptions::parse_actions(list).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    Some(InstrumentOptions { functions, actions })
+}
+
+/// `source` with the functions `options` selects wrapped in logging
+fn instrument_source(source: &str, file_name: &str, options: &InstrumentOptions) -> String {
+    let instrumented = instrument::instrument(source, file_name, options).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    for (function, reason) in &instrumented.skipped {
+        log::warn!("--instrument: not instrumenting '{}': {}", function, reason);
+    }
+    if instrumented.functions.is_empty() {
+        log::warn!("--instrument: no function matches {}", options.functions.join(", "));
+    }
+    log::info!("Instrumented {} function(s)", instrumented.functions.len());
+    instrumented.source
+}
+
+/// Write the instrumented source instead of running it, for inspection or
+/// another compiler
+fn run_instrument(matches: &ArgMatches) -> io::Result<()> {
+    let file = matches.get_one::<String>("file").unwrap();
+    let (source, file_name) = if file == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        (buffer, "<stdin>")
+    } else {
+        (fs::read_to_string(file)?, file.as_str())
+    };
+
+    let options = instrument_options(matches, true).unwrap();
+    let instrumented = instrument_source(&source, file_name, &options);
+    match matches.get_one::<String>("output") {
+        Some(path) => fs::write(path, instrumented),
+        None => {
+            print!("{}", instrumented);
+            Ok(())
+        }
+    }
+}
+
+/// Minimize a file that makes a test fail, writing each smaller version as
+/// it is found
+fn run_reduce(matches: &ArgMatches) -> io::Result<()> {
+    let file = matches.get_one::<String>("file").unwrap();
+    let source = fs::read_to_string(file)?;
+    let output = matches
+        .get_one::<String>("output")
+        .map_or_else(|| Path::new(file).with_extension("reduced.c"), PathBuf::from);
+    let timeout = Duration::from_secs(*matches.get_one::<u64>("timeout").unwrap());
+    let needle = matches.get_one::<String>("match").cloned().unwrap_or_default();
+
+    let mut interesting: Box<dyn FnMut(&str) -> bool> = if matches.get_flag("differential") {
+        let exe = shell_quote(&std::env::current_exe()?.to_string_lossy());
+        let level = matches.get_one::<String>("optimization").map_or("2", |s| s.as_str());
+        let interpreted = CommandTest::new(&format!("{} run -i {{}}", exe), file, timeout)?;
+        let compiled = CommandTest::new(&format!("{} run -O {} {{}}", exe, level), file, timeout)?;
+        Box::new(move |candidate| match (interpreted.run(candidate), compiled.run(candidate)) {
+            (Ok(a), Ok(b)) => {
+                a.outcome != Outcome::TimedOut
+                    && b.outcome != Outcome::TimedOut
+                    && (a.outcome != b.outcome || a.stdout != b.stdout)
+            }
+            _ => false,
+        })
+    } else if let Some(command) = matches.get_one::<String>("test-cmd") {
+        let test = CommandTest::new(command, file, timeout)?;
+        Box::new(move |candidate| {
+            test.run(candidate).is_ok_and(|run| run.outcome == Outcome::Exited(0) && run.printed(&needle))
+        })
+    } else {
+        let command = matches.get_one::<String>("crash-cmd").unwrap();
+        let test = CommandTest::new(command, file, timeout)?;
+        let original = test.run(&source)?;
+        if !original.outcome.failed() || !original.printed(&needle) {
+            eprintln!("Error: '{}' doesn't fail on {} as required", command, file);
+            process::exit(1);
+        }
+        eprintln!("Reducing while the command ends with {}", original.outcome);
+        Box::new(move |candidate| {
+            test.run(candidate).is_ok_and(|run| run.outcome == original.outcome && run.printed(&needle))
+        })
+    };
+
+    let reduction = reduce::reduce(&source, file, &mut *interesting, &mut |best| {
+        let _ = fs::write(&output, best);
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    fs::write(&output, &reduction.source)?;
+    eprintln!(
+        "{}: {} -> {} bytes after {} tests; written to {}",
+        file,
+        reduction.original_len,
+        reduction.source.len(),
+        reduction.tests,
+        output.display()
+    );
+    Ok(())
+}
+
+/// Report how the declarations of `new` differ from `old`. Exits 1 if any
+/// change breaks existing callers and 2 if a file can't be read or parsed.
+fn run_semdiff(matches: &ArgMatches, architecture: &str) -> io::Result<()> {
+    let read = |id: &str| {
+        let path = matches.get_one::<String>(id).unwrap();
+        fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Error: failed to read '{}': {}", path, e);
+            process::exit(2);
+        })
     };
+    let (old, new) = (read("old"), read("new"));
 
-    // Parse optimization level
-    let opt_level = matches
-        .get_one::<String>("optimization")
-        .and_then(|s| s.parse::<u32>().ok())
-        .unwrap_or(2);
+    let diff = semdiff::diff_sources(&old, &new, DataModel::for_architecture(architecture)).unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        process::exit(2);
+    });
+
+    report_changes(matches, &diff)
+}
+
+/// Report how a new build of a library differs from the one existing
+/// binaries link against; exit statuses as for `semdiff`
+fn run_abi_check(matches: &ArgMatches) -> io::Result<()> {
+    let old = Path::new(matches.get_one::<String>("old").unwrap());
+    let new = Path::new(matches.get_one::<String>("new").unwrap());
+
+    let diff = debug::abi_check::compare_files(old, new).unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        process::exit(2);
+    });
+    report_changes(matches, &diff)
+}
+
+/// Add up profiles from several runs into one; counts that can't be
+/// merged are warned about and left out, as `llvm-profdata merge` does
+fn run_merge_profiles(matches: &ArgMatches) -> io::Result<()> {
+    let output = Path::new(matches.get_one::<String>("output").unwrap());
+    let mut merged = Profile::new();
+    for path in matches.get_many::<String>("profiles").unwrap() {
+        let profile = Profile::load(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        });
+        for conflict in merged.merge(profile) {
+            log::warn!("{}: {}", path, conflict);
+        }
+    }
+
+    if let Err(e) = merged.save(output, ProfileFormat::for_path(output)) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+    log::info!("wrote {}\n{}", output.display(), merged.summary());
+    Ok(())
+}
+
+/// Print `diff` in the requested `--format`; exit 1 if anything breaks
+fn report_changes(matches: &ArgMatches, diff: &SemanticDiff) -> io::Result<()> {
+    if matches.get_one::<String>("format").map(String::as_str) == Some("json") {
+        println!("{}", serde_json::to_string_pretty(diff).map_err(io::Error::other)?);
+    } else {
+        for change in &diff.changes {
+            println!("{}", change);
+        }
+        let breaking = diff.changes.iter().filter(|c| c.impact >= Impact::ApiBreak).count();
+        eprintln!("{} changes, {} breaking", diff.changes.len(), breaking);
+    }
+
+    if diff.breaks_compatibility() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Capture the host environment, or compare it against an earlier capture
+fn run_doctor(matches: &ArgMatches) -> io::Result<()> {
+    let current = EnvironmentSnapshot::capture();
+
+    if let Some(captured_path) = matches.get_one::<String>("compare") {
+        let captured = match EnvironmentSnapshot::load(Path::new(captured_path)) {
+            Ok(env) => env,
+            Err(e) => {
+                eprintln!("Error: failed to load environment '{}': {:?}", captured_path, e);
+                process::exit(1);
+            }
+        };
+
+        // A bundle says how to reproduce the crash
+        if let Ok(bundle) = ReproBundle::load(Path::new(captured_path)) {
+            println!("Crashed: {} ({})", bundle.exit, bundle.mode);
+            println!("Replay: {}", bundle.command_line.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" "));
+        }
+
+        let diffs = captured.diff(&current);
+        if diffs.is_empty() {
+            println!("Environment matches {}", captured_path);
+        }
+        for diff in &diffs {
+            let level = if diff.material { "warning" } else { "note" };
+            eprintln!("{}: {} differs (captured: '{}', current: '{}')",
+                level, diff.field, diff.captured, diff.current);
+        }
+        return Ok(());
+    }
+
+    let output = matches.get_one::<String>("output").unwrap();
+    println!("OS: {} ({})", current.os, current.architecture);
+    let missing = if cfg!(feature = "llvm") { "unknown" } else { "none (built without the `llvm` feature)" };
+    println!("LLVM: {}", current.llvm_version.as_deref().unwrap_or(missing));
+    println!("Locale: {}", current.locale.as_deref().unwrap_or("C"));
+    println!("CPU features: {}", current.cpu_features.join(" "));
+
+    if let Err(e) = current.write_to(Path::new(output)) {
+        eprintln!("Error: failed to write environment to '{}': {:?}", output, e);
+        process::exit(1);
+    }
+    println!("Environment written to {}", output);
+    Ok(())
+}
+
+/// Check an artifact's provenance notes against the files at hand; exit 1
+/// on a mismatch
+/// `dump-ir`: what an IR file holds, as far as this build can tell
+fn run_dump_ir(matches: &ArgMatches) -> io::Result<()> {
+    let input = matches.get_one::<String>("input").unwrap();
+    let data = fs::read(input).unwrap_or_else(|e| {
+        eprintln!("Error: failed to read '{}': {}", input, e);
+        process::exit(1);
+    });
+    let fail = |e: ir::IrError| -> ! {
+        eprintln!("Error: {}: {}", input, e);
+        process::exit(1);
+    };
+    let reader = ir::Reader::new(&data).unwrap_or_else(|e| fail(e));
+
+    println!("{}: IR {}.{}, {}", input, reader.major, reader.minor, reader.kind);
+    if let Ok(Some((tool, version))) = ir::program::producer(&data) {
+        println!("  written by {} {}", tool, version);
+    }
+    for section in reader.sections() {
+        let optional = if section.is_optional() { ", optional" } else { "" };
+        println!("  section 0x{:02x}: {} bytes{}", section.tag, section.payload.len(), optional);
+    }
+
+    match reader.kind {
+        ir::ArtifactKind::Program => {
+            let program = ir::read_program(&data).unwrap_or_else(|e| fail(e));
+            if let Err(e) = program.verify() {
+                println!("  does not verify: {}", e);
+            }
+            print!("{}", program.disassemble());
+        }
+        ir::ArtifactKind::LtoSummary => {
+            let summary = lto::ModuleSummary::from_ir(&data).unwrap_or_else(|e| fail(e));
+            println!(
+                "{}: {} symbol(s), {} undefined, {} used",
+                summary.name,
+                summary.symbols.len(),
+                summary.undefined.len(),
+                summary.used.len()
+            );
+            let mut names: Vec<_> = summary.symbols.keys().collect();
+            names.sort();
+            for name in names {
+                let symbol = &summary.symbols[name];
+                println!("  {} {:?} {:?}, {} instruction(s), calls {:?}", name, symbol.kind, symbol.linkage, symbol.instructions, symbol.calls);
+            }
+        }
+        // Kinds from a newer release
+        _ => {}
+    }
+    Ok(())
+}
+
+fn run_verify_provenance(matches: &ArgMatches) -> io::Result<()> {
+    let artifact = matches.get_one::<String>("artifact").unwrap();
+    let data = fs::read(artifact).unwrap_or_else(|e| {
+        eprintln!("Error: failed to read '{}': {}", artifact, e);
+        process::exit(1);
+    });
+    let records = provenance::read_artifact(&data).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", artifact, e);
+        process::exit(1);
+    });
+    if records.is_empty() {
+        eprintln!("Error: {} has no provenance notes; build it with --embed-provenance", artifact);
+        process::exit(1);
+    }
+
+    let sources: Vec<PathBuf> = matches
+        .get_many::<String>("source")
+        .map(|sources| sources.map(PathBuf::from).collect())
+        .unwrap_or_default();
+    let profile = matches.get_one::<String>("expect-profile").map(Path::new);
+    let findings = provenance::verify(&records, &sources, profile).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    if matches.get_flag("attestation") {
+        let statement = provenance::attestation(artifact, &data, &records);
+        println!("{}", serde_json::to_string_pretty(&statement).map_err(io::Error::other)?);
+    } else {
+        println!("{}: {} translation unit(s)", artifact, records.len());
+        for record in &records {
+            println!("  {} ({})", record.source.path, record.source.sha256);
+            println!("    {} {}, target {}", record.tool, record.tool_version, record.target);
+            println!("    options {}", record.options_sha256);
+            println!("    preprocessed {}", record.preprocessed_sha256);
+            if let Some(profile) = &record.profile {
+                println!("    profile {} ({})", profile.path, profile.sha256);
+            }
+        }
+    }
+
+    for record in records.iter().filter(|record| record.tool_version != env!("CARGO_PKG_VERSION")) {
+        eprintln!("note: {} was built by {} {}", record.source.path, record.tool, record.tool_version);
+    }
+    for finding in &findings {
+        eprintln!("error: {}", finding);
+    }
+    if !findings.is_empty() {
+        process::exit(1);
+    }
+    if !sources.is_empty() || profile.is_some() {
+        eprintln!("Provenance verified");
+    }
+    Ok(())
+}
+
+/// Turn the local usage statistics on or off, or show them
+fn run_stats(matches: &ArgMatches) -> io::Result<()> {
+    let store = UsageStore::new(UsageStore::default_root());
+    let updates: [(&str, fn(&UsageStore) -> io::Result<()>, &str); 3] = [
+        ("reset", UsageStore::reset, "Recorded runs deleted"),
+        ("disable", UsageStore::disable, "Usage statistics disabled; the runs so far are kept"),
+        ("enable", UsageStore::enable, "Usage statistics enabled; they stay on this machine"),
+    ];
+    let mut acted = false;
+    for (flag, update, message) in updates {
+        if !matches.get_flag(flag) {
+            continue;
+        }
+        if let Err(e) = update(&store) {
+            eprintln!("Error: failed to update usage statistics in '{}': {}", store.root().display(), e);
+            process::exit(1);
+        }
+        println!("{}", message);
+        acted = true;
+    }
+    if acted {
+        return Ok(());
+    }
+
+    let mut records = match store.records() {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Error: failed to read usage statistics from '{}': {}", store.root().display(), e);
+            process::exit(1);
+        }
+    };
+    if let Some(days) = matches.get_one::<u64>("days") {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let since = now.saturating_sub(days * 86_400);
+        records.retain(|record| record.timestamp >= since);
+    }
+    let summary = UsageSummary::from_records(&records, *matches.get_one::<usize>("top").unwrap());
+
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&summary).map_err(io::Error::other)?);
+        return Ok(());
+    }
+    if !store.is_enabled() {
+        println!("Usage statistics are off; `c-interpreter stats --enable` records them locally");
+    }
+    println!("Recorded in {}\n", store.root().display());
+    println!("{}", summary.to_string().trim_end());
+    Ok(())
+}
+
+/// Serve requests from a warm background process, or query or stop one
+fn run_daemon(opts: &ArgMatches) -> io::Result<()> {
+    let socket = opts
+        .get_one::<String>("socket")
+        .map(PathBuf::from)
+        .unwrap_or_else(daemon::default_socket_path);
+
+    if opts.get_flag("status") || opts.get_flag("stop") {
+        let request = if opts.get_flag("stop") { DaemonRequest::Shutdown } else { DaemonRequest::Status };
+        match daemon::request(&socket, &request) {
+            Ok(DaemonReply::Status(status)) => println!(
+                "Daemon {} on {}: up {}s, {} requests served, {} running",
+                status.pid,
+                socket.display(),
+                status.uptime_secs,
+                status.served,
+                status.active
+            ),
+            Ok(DaemonReply::Stopping) => println!("Daemon on {} stopping", socket.display()),
+            Ok(reply) => {
+                eprintln!("Error: unexpected reply from daemon: {:?}", reply);
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: no daemon answering on '{}': {:?}", socket.display(), e);
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    // The warm-up compile uses the same settings most requests will
+    let options = options_from_args(opts);
+
+    let config = DaemonConfig {
+        idle_timeout: match opts.get_one::<u64>("idle-timeout").copied().unwrap_or(0) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        },
+        ..DaemonConfig::new(socket)
+    };
+
+    let daemon = Daemon::bind(config, run_command_line, || warm_up(&options))
+    .unwrap_or_else(|e| {
+        eprintln!("Error: failed to start daemon: {:?}", e);
+        process::exit(1);
+    });
+
+    eprintln!("Listening on {} (clients: {}={})", daemon.socket().display(), daemon::DAEMON_ENV_VAR, daemon.socket().display());
+    match daemon.serve() {
+        Ok(status) => {
+            eprintln!("Daemon stopped after {} requests", status.served);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Error: daemon failed: {:?}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Compile what most daemon requests will, before the first worker forks
+#[cfg(feature = "llvm")]
+fn warm_up(options: &Options) -> Result<(), String> {
+    if options.libc == LibcMode::Bundled {
+        prepare_bundled_libc(&options.architecture, options.cache_dir.as_deref());
+    }
+    jit_eval(daemon::WARMUP_SOURCE, options).map(|_| ())
+}
+
+/// Without LLVM, requests are interpreted and nothing is worth warming
+#[cfg(not(feature = "llvm"))]
+fn warm_up(_options: &Options) -> Result<(), String> {
+    Ok(())
+}
+
+/// Compile C code to an object file
+#[cfg(feature = "llvm")]
+fn compile_code(
+    source: &str,
+    output_file: Option<&String>,
+    options: &Options,
+    nostdlib: bool,
+    libc: Option<&BundledLibc>,
+    builtin_linker: bool,
+    gc_sections: bool,
+    linker_script: Option<&String>,
+) -> io::Result<Vec<OptimizationRemark>> {
+    log::info!("Compiling to {}", output_file.map(|s| s.as_str()).unwrap_or("a.out"));
+
+    // The built-in linker only makes static executables
+    if builtin_linker && !nostdlib && libc.is_none() {
+        eprintln!("Error: --linker=builtin needs --nostdlib or --libc=bundled");
+        process::exit(1);
+    }
+    if gc_sections && !builtin_linker {
+        eprintln!("Error: --gc-sections needs --linker=builtin");
+        process::exit(1);
+    }
+    if linker_script.is_some() && !builtin_linker {
+        eprintln!("Error: --linker-script needs --linker=builtin");
+        process::exit(1);
+    }
+
+    // Create compiler instance
+    let compiler = unsafe {
+        match compiler::Compiler::new() {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Failed to initialize compiler: {:?}", e);
+                process::exit(1);
+            }
+        }
+    };
+
+    // Without the system CRT files the program boots through our own _start,
+    // or the bundled libc's crt1 which also runs atexit handlers and flushes stdio
+    let mut startup_objects = vec![];
+    let mut libraries = options.libraries.libraries.clone();
+    let mut library_paths = options.libraries.library_paths.clone();
+    let mut system_include_dirs = vec![];
+    // Our crt0 is a temporary file of this build, kept until the link is done
+    let crt0 = match (libc, nostdlib) {
+        (None, true) => {
+            let target = options.cpu_architecture().unwrap_or_else(|| {
+                eprintln!("Error: no built-in startup code for '{}'", options.architecture);
+                process::exit(1);
+            });
+            match Crt0::for_architecture(target).write_temporary() {
+                Ok(object) => Some(object),
+                Err(e) => {
+                    eprintln!("Error: failed to write startup object: {:?}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+    if let Some(libc) = libc {
+        startup_objects.push(libc.crt1().to_string_lossy().into_owned());
+        library_paths.push(libc.library_dir().to_string_lossy().into_owned());
+        libraries.push("c".to_string());
+        system_include_dirs.push(libc.include_dir());
+    } else if let Some(crt0) = &crt0 {
+        startup_objects.push(crt0.path().to_string_lossy().into_owned());
+    }
+
+    // Set up compiler options
+    let output_path = output_file.map(|s| s.as_str()).unwrap_or("a.out");
+    let mut options = options.compiler_options(Some(compiler::LinkOptions {
+        libraries,
+        library_paths,
+        static_link: nostdlib || libc.is_some(),
+        strip_symbols: false,
+        nostdlib: nostdlib || libc.is_some(),
+        startup_objects,
+        builtin_linker,
+        gc_sections,
+        linker_script: linker_script.cloned(),
+    }));
+    options.system_include_dirs.extend(system_include_dirs);
+
+    // Compile the code
+    unsafe {
+        if let Err(e) = compiler.compile_string(source, output_path, &options) {
+            e.diagnostics().iter().for_each(print_error);
+            process::exit(1);
+        }
+    }
+
+    log::info!("Compilation successful");
+    Ok(compiler.take_remarks())
+}
+
+/// Build the bundled libc for `architecture` with this compiler, or reuse the
+/// cached build
+#[cfg(feature = "llvm")]
+fn prepare_bundled_libc(architecture: &str, cache_dir: Option<&Path>) -> BundledLibc {
+    let target = arch::Architecture::from_str(architecture).unwrap_or_else(|_| {
+        eprintln!("Error: the bundled libc does not support '{}'", architecture);
+        process::exit(1);
+    });
+    let compiler = unsafe { compiler::Compiler::new() }.unwrap_or_else(|e| {
+        eprintln!("Failed to initialize compiler: {:?}", e);
+        process::exit(1);
+    });
+
+    // --no-cache still needs somewhere to put the build
+    let cache_root = cache_dir.map(Path::to_path_buf).unwrap_or_else(CompilationCache::default_root);
+    let result = BundledLibc::prepare(&cache_root, target, &mut |source, object, include_dirs| {
+        let text = fs::read_to_string(source).map_err(|e| e.to_string())?;
+        let link_options = compiler::LinkOptions {
+            libraries: vec![],
+            library_paths: vec![],
+            static_link: true,
+            strip_symbols: false,
+            nostdlib: true,
+            startup_objects: vec![],
+            builtin_linker: false,
+            gc_sections: false,
+            linker_script: None,
+        };
+        let result = if source.extension().map_or(false, |ext| ext == "s") {
+            let options = compiler::AssemblyOptions {
+                link: false,
+                link_options,
+                target_architecture: Some(target),
+            };
+            unsafe { compiler.compile_assembly(&text, &object.to_string_lossy(), &options) }
+        } else {
+            let options = Options {
+                optimization_level: 2,
+                architecture: architecture.to_string(),
+                debug_info: false,
+                system_include_dirs: include_dirs.to_vec(),
+                ..Options::default()
+            };
+            let options = CompilerOptions {
+                link_options,
+                ..options.compiler_options(None)
+            };
+            unsafe { compiler.compile_file(&source.to_string_lossy(), &object.to_string_lossy(), &options) }
+        };
+        result.map_err(|e| format!("{:?}", e))
+    });
 
-    // Parse target architecture
-    let architecture = matches
-        .get_one::<String>("architecture")
-        .unwrap_or(&String::from(std::env::consts::ARCH))
-        .clone();
+    result.unwrap_or_else(|e| {
+        eprintln!("Error: failed to build the bundled libc: {:?}", e);
+        process::exit(1);
+    })
+}
 
-    // Validate architecture selection
-    let arch_valid = match architecture.as_str() {
-        "x86_64" | "aarch64" | "arm" | "amdgpu" | "nvptx" => true,
-        _ => false
+/// Rewrite the compiled ELF in place as a flat binary, Intel HEX or S-record
+fn convert_output(opts: &ArgMatches) -> io::Result<()> {
+    let format = match OutputFormat::from_str(opts.get_one::<String>("oformat").unwrap()) {
+        Ok(OutputFormat::Elf) => return Ok(()),
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            process::exit(1);
+        }
     };
 
-    if !arch_valid {
-        eprintln!("Error: Unsupported architecture '{}'. Supported: x86_64, aarch64, arm, amdgpu, nvptx", architecture);
+    let parse = |name: &str| {
+        opts.get_one::<String>(name).map(|s| parse_address(s)).transpose().unwrap_or_else(|e| {
+            eprintln!("Error: invalid --{}: {:?}", name, e);
+            process::exit(1);
+        })
+    };
+    let gap_fill = opts.get_one::<String>("gap-fill").map(|s| oformat::parse_byte(s)).transpose().unwrap_or_else(|e| {
+        eprintln!("Error: invalid --gap-fill (a byte, 0 to 0xff): {:?}", e);
         process::exit(1);
-    }
-
-    // If verbose, print configuration
-    if matches.get_flag("verbose") {
-        println!("Source length: {} characters", source_code.len());
-        println!("Optimization level: {}", opt_level);
-        println!("Target architecture: {}", architecture);
-        println!("Mode: {}", if matches.get_flag("interpret") {
-            "Interpret"
-        } else if matches.get_flag("compile") {
-            "Compile"
-        } else {
-            "JIT"
-        });
-    }
+    });
+    let options = ConversionOptions {
+        load_address: parse("load-address"),
+        gap_fill: gap_fill.unwrap_or(0),
+        ..ConversionOptions::default()
+    };
 
-    // Execute or compile based on options
-    if matches.get_flag("compile") {
-        compile_code(&source_code, matches.get_one::<String>("output"), opt_level, &architecture)?;
-    } else if matches.get_flag("interpret") {
-        interpret_code(&source_code)?;
-    } else {
-        // Default: JIT execution
-        jit_execute(&source_code, opt_level, &architecture)?;
+    let output_path = opts.get_one::<String>("output").map(|s| s.as_str()).unwrap_or("a.out");
+    let elf = fs::read(output_path)?;
+    match oformat::convert(&elf, format, &options) {
+        Ok(converted) => fs::write(output_path, converted)?,
+        Err(e) => {
+            eprintln!("Error: cannot convert '{}' to {:?}: {:?}", output_path, format, e);
+            process::exit(1);
+        }
     }
 
+    log::info!("Converted {} to {:?}", output_path, format);
     Ok(())
 }
 
-/// Get LLVM target triple for the specified architecture
-fn get_target_triple(architecture: &str) -> &'static str {
-    match architecture {
-        "x86_64" => "x86_64-unknown-linux-gnu",
-        "aarch64" => "aarch64-unknown-linux-gnu",
-        "arm" => "arm-unknown-linux-gnueabihf",
-        "amdgpu" => "amdgcn-amd-amdhsa",
-        "nvptx" => "nvptx64-nvidia-cuda",
-        _ => "x86_64-unknown-linux-gnu", // Default fallback
+/// The WASI sandbox for `--dir` and `--dir-ro`, if either was given
+fn sandbox_from_args(opts: &ArgMatches) -> Option<WasiHost> {
+    let dirs = |name| opts.get_many::<String>(name).into_iter().flatten();
+    let specs: Vec<(&String, DirAccess)> = dirs("dir")
+        .map(|spec| (spec, DirAccess::ReadWrite))
+        .chain(dirs("dir-ro").map(|spec| (spec, DirAccess::ReadOnly)))
+        .collect();
+    if specs.is_empty() {
+        return None;
+    }
+    let program = opts.get_one::<String>("file").cloned().unwrap_or_else(|| "-".to_string());
+    let mut sandbox = WasiHost::new().with_args(vec![program]);
+    for (spec, access) in specs {
+        sandbox = match sandbox.with_dir_spec(spec, access) {
+            Ok(sandbox) => sandbox,
+            Err(e) => {
+                eprintln!("Error: --dir {}: {:?}", spec, e);
+                process::exit(1);
+            }
+        };
     }
+    Some(sandbox)
 }
 
-/// Compile C code to an object file
-fn compile_code(source: &str, output_file: Option<&String>, opt_level: u32, architecture: &str) -> io::Result<()> {
-    if let Some(output) = output_file {
-        println!("Compiling to {}", output);
-    } else {
-        println!("Compiling to a.out");
+/// `--max-time` and the other limits of the interpreted program
+fn limits_from_args(opts: &ArgMatches) -> ResourceLimits {
+    ResourceLimits {
+        wall_time: parse_arg(opts, "max-time", limits::parse_duration),
+        cpu_time: parse_arg(opts, "max-cpu-time", limits::parse_duration),
+        heap: parse_arg(opts, "max-memory", limits::parse_size).map(|size| size as usize),
+        stack: parse_arg(opts, "max-stack", limits::parse_size).map(|size| size as usize),
+        output: parse_arg(opts, "max-output", limits::parse_size),
     }
+}
 
-    // Create compiler instance
-    let compiler = unsafe {
-        match compiler::Compiler::new() {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("Failed to initialize compiler: {:?}", e);
-                process::exit(1);
-            }
+/// The compilation settings on the command line; exits if they are invalid
+fn options_from_args(opts: &ArgMatches) -> Options {
+    // Runtime checks inserted into the generated code
+    let sanitizers = match opts.get_one::<String>("sanitize") {
+        Some(list) => SanitizerSet::parse(list).unwrap_or_else(|e| {
+            eprintln!("Error: {:?}", e);
+            process::exit(1);
+        }),
+        None => SanitizerSet::default(),
+    };
+
+    // Strict IEEE unless relaxed; the last of -fX/-fno-X wins
+    let last_index = |id: &str| {
+        if opts.value_source(id) == Some(clap::parser::ValueSource::CommandLine) {
+            opts.indices_of(id).and_then(|i| i.last())
+        } else {
+            None
         }
     };
+    let fast_math = last_index("fast-math") > last_index("no-fast-math");
+    let math_errno = match (last_index("math-errno"), last_index("no-math-errno")) {
+        (None, None) => None,
+        (on, off) => Some(on > off),
+    };
+    let fp_contract = opts.get_one::<String>("fp-contract").map(|mode| {
+        mode.parse::<FpContract>().unwrap_or_else(|e| {
+            eprintln!("Error: {:?}", e);
+            process::exit(1);
+        })
+    });
 
-    // Set up compiler options
-    let output_path = output_file.map(|s| s.as_str()).unwrap_or("a.out");
-    let options = CompilerOptions {
-        optimization_level: opt_level,
-        link: true,
-        link_options: compiler::LinkOptions {
-            libraries: vec![],
-            library_paths: vec![],
-        },
-        debug_info: true,
-        target_features: vec![],
-        target_architecture: arch::Architecture::from_str(architecture).ok(),
-        target_triple: Some(get_target_triple(architecture).to_string()),
+    // Signed overflow is diagnosed unless -fwrapv or -ftrapv picks a behavior
+    let overflow = match (last_index("wrapv"), last_index("trapv")) {
+        (None, None) => OverflowMode::Diagnose,
+        (wrapv, trapv) if wrapv > trapv => OverflowMode::Wrap,
+        _ => OverflowMode::Trap,
     };
 
-    // Compile the code
-    unsafe {
-        if let Err(e) = compiler.compile_string(source, output_path, &options) {
-            eprintln!("Compilation error: {:?}", e);
+    // Loop interchange, fusion and tiling at -O3 unless -fno-X comes last
+    let loop_transforms = LoopTransforms {
+        interchange: last_index("no-loop-interchange") <= last_index("loop-interchange"),
+        fusion: last_index("no-loop-fusion") <= last_index("loop-fusion"),
+        tiling: last_index("no-loop-tiling") <= last_index("loop-tiling"),
+    };
+    let heap_to_stack = last_index("no-heap-to-stack") <= last_index("heap-to-stack");
+
+    // Each -Rpass flag may be repeated; the patterns add up
+    let remark_pattern = |id: &str| {
+        opts.try_get_many::<String>(id)
+            .ok()
+            .flatten()
+            .map(|patterns| patterns.cloned().collect::<Vec<_>>().join("|"))
+    };
+    let remarks = RemarkFilter {
+        applied: remark_pattern("remarks"),
+        missed: remark_pattern("remarks-missed"),
+        analysis: remark_pattern("remarks-analysis"),
+    };
+
+    // Reuse objects from earlier runs unless --no-cache
+    let cache_dir = if opts.get_flag("no-cache") {
+        None
+    } else {
+        Some(
+            opts.get_one::<String>("cache-dir")
+                .map(PathBuf::from)
+                .unwrap_or_else(CompilationCache::default_root),
+        )
+    };
+
+    // -l/-L: dlopened by the JIT, handed to the linker when compiling
+    let collect = |id: &str| -> Vec<String> {
+        opts.try_get_many::<String>(id)
+            .ok()
+            .flatten()
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+
+    // A function over either budget is compiled at -O0
+    let time = parse_arg(opts, "opt-time-budget", limits::parse_duration);
+    let memory = parse_arg(opts, "opt-memory-budget", limits::parse_size).map(|size| size as usize);
+    let function_budget = (time.is_some() || memory.is_some()).then(|| FunctionBudget {
+        time: time.unwrap_or(FunctionBudget::default().time),
+        memory,
+        ..FunctionBudget::default()
+    });
+
+    let optimization_level = opts.get_one::<String>("optimization").map(|level| {
+        level.parse::<u32>().unwrap_or_else(|_| {
+            eprintln!("Error: invalid optimization level '{}'", level);
             process::exit(1);
+        })
+    });
+    let builder = Options::builder()
+        .optimization_level(optimization_level.unwrap_or(2))
+        .architecture(opts.get_one::<String>("architecture").map(String::as_str).unwrap_or(std::env::consts::ARCH))
+        .sanitizers(sanitizers)
+        .fp(FpOptions::from_flags(fast_math, math_errno, fp_contract))
+        .overflow(overflow)
+        .function_budget(function_budget)
+        .loop_transforms(loop_transforms)
+        .heap_to_stack(heap_to_stack)
+        .remarks(remarks)
+        .profile_generate(opts.get_one::<String>("profile-generate").map(PathBuf::from))
+        .profile_use(opts.get_one::<String>("profile-use").map(PathBuf::from))
+        .libc(parse_arg(opts, "libc", |s| s.parse::<LibcMode>().map_err(|_| format!("unknown C library '{}', expected host or bundled", s))).unwrap_or(LibcMode::Host))
+        .libraries(LibrarySearch {
+            libraries: collect("library"),
+            library_paths: collect("library-path"),
+        })
+        .deterministic(opts.get_flag("deterministic").then(|| DeterministicConfig {
+            seed: opts.get_one::<u32>("seed").copied().unwrap_or(1),
+            ..DeterministicConfig::default()
+        }))
+        .capture(match (opts.get_one::<String>("capture-io"), opts.get_one::<String>("replay-io")) {
+            (Some(path), _) => Some(CaptureMode::Record(PathBuf::from(path))),
+            (None, Some(path)) => Some(CaptureMode::Replay(PathBuf::from(path))),
+            (None, None) => None,
+        })
+        .detect_leaks(opts.get_flag("detect-leaks").then(|| LeakConfig {
+            suppressions: collect("leak-suppressions").into_iter().map(PathBuf::from).collect(),
+            show_reachable: opts.get_flag("show-reachable"),
+        }))
+        .cache_dir(cache_dir)
+        .embed_provenance(opts.get_flag("embed-provenance"))
+        // 0 = one job per CPU
+        .jobs(opts.get_one::<usize>("jobs").copied().unwrap_or(0));
+    let builder = collect("include").into_iter().fold(builder, |builder, dir| builder.include_dir(PathBuf::from(dir)));
+
+    builder.build().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    })
+}
+
+/// Look up the libraries of `-l` and `#pragma comment(lib)` with
+/// pkg-config (vcpkg on Windows), adding their include and library
+/// directories and the libraries they depend on
+fn discover_system_libraries(mut options: Options, source: &str, static_link: bool) -> Options {
+    let mut requested = options.libraries.clone();
+    for library in pkg_config::pragma_libraries(source) {
+        if !requested.libraries.contains(&library) {
+            requested.libraries.push(library);
         }
     }
+    if requested.is_empty() {
+        return options;
+    }
+    let discovery = Discovery {
+        static_link,
+        cross_compiling: options.architecture != std::env::consts::ARCH,
+    };
+    let found = discovery.run(&requested);
+    for (name, package) in &found.packages {
+        log::info!("-l{}: using package {}", name, package);
+    }
+    options.include_dirs.extend(found.include_dirs);
+    options.libraries = found.search;
+    options
+}
 
-    println!("Compilation successful");
-    Ok(())
+/// Parse the value of `--name`, exiting with its error
+fn parse_arg<T>(opts: &ArgMatches, name: &str, parse: fn(&str) -> Result<T, String>) -> Option<T> {
+    opts.get_one::<String>(name).map(|text| {
+        parse(text).unwrap_or_else(|e| {
+            eprintln!("Error: --{}: {}", name, e);
+            process::exit(1);
+        })
+    })
 }
 
 /// Interpret C code without JIT compilation
-fn interpret_code(source: &str) -> io::Result<()> {
-    println!("Interpreting code...");
-
-    // Create a parser
-    let mut parser = C23Parser::new();
-    
-    // Parse the source
-    let ast = match parser.parse(source) {
-        Ok(ast) => ast,
+fn interpret_code(
+    source: &str,
+    vm_stats: bool,
+    provenance: bool,
+    audit_signals: bool,
+    overflow: OverflowMode,
+    sanitizers: SanitizerSet,
+    trace: &TraceMode,
+    sandbox: Option<WasiHost>,
+    limits: ResourceLimits,
+    deterministic: Option<DeterministicConfig>,
+    usdt: &[String],
+    engine: InterpreterEngine,
+    cache_dir: Option<&Path>,
+    emit_ir: Option<&Path>,
+    bundled_libc: Option<&BundledLibc>,
+    detect_leaks: Option<&LeakConfig>,
+    diagnostics_config: DiagnosticsConfig,
+) -> io::Result<ProgramExit> {
+    log::info!("Interpreting code...");
+
+    if vm_stats && !interpreter::vm_stats::VmStats::enabled() {
+        log::warn!("--vm-stats requires a build with the `vm-stats` feature");
+    }
+
+    let mut diagnostics = DiagnosticsEngine::new(diagnostics_config);
+
+    // DTRACE_PROBE and friends become numbered probe sites
+    let mut rewritten = match usdt::rewrite(source, "<input>", Lowering::Interpreted) {
+        Ok(rewritten) => rewritten,
         Err(e) => {
-            eprintln!("Parse error: {:?}", e);
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // --libc=bundled: its headers instead of the host's
+    if bundled_libc.is_some() {
+        rewritten.source = bundled::inline_headers(&rewritten.source).unwrap_or_else(|e| {
+            eprintln!("Error: --libc=bundled: {:?}", e);
+            process::exit(1);
+        });
+    }
+
+    // Parse the source, reporting every declaration that fails
+    let parsed = report::timed("parse", || {
+        recovery::parse_reporting(&rewritten.source, "<input>", |text| C23Parser::new().parse(text), &mut diagnostics)
+    });
+    let ast = match parsed {
+        Some(ast) => ast,
+        None => {
+            diagnostics.flush_to_stderr();
             process::exit(1);
         }
     };
 
     // Create runtime environment
+    // What the bytecode engine can't model yet, checked before `sandbox` moves
+    let tree_only = if provenance {
+        Some("--provenance")
+    } else if *trace != TraceMode::Off {
+        Some("--record and --replay")
+    } else if sandbox.is_some() {
+        Some("--dir")
+    } else if deterministic.is_some() {
+        Some("--deterministic")
+    } else if audit_signals {
+        Some("--audit-signals")
+    } else {
+        None
+    };
+
+    // The tree walker only calls the host's libc, and doesn't see the
+    // program's allocations
+    let needs_bytecode = if bundled_libc.is_some() {
+        Some("--libc=bundled")
+    } else if detect_leaks.is_some() {
+        Some("--detect-leaks")
+    } else {
+        None
+    };
+    let engine = match (needs_bytecode, tree_only) {
+        (Some(needed), Some(option)) => {
+            eprintln!("Error: {} needs the bytecode engine, which does not support {}", needed, option);
+            process::exit(1);
+        }
+        (Some(_), None) if engine == InterpreterEngine::Tree => InterpreterEngine::Bytecode,
+        _ => engine,
+    };
+
     let mut runtime = match CRuntimeEnvironment::new() {
         Ok(r) => r,
         Err(e) => {
@@ -253,25 +1985,512 @@ fn interpret_code(source: &str) -> io::Result<()> {
             process::exit(1);
         }
     };
+    if provenance {
+        runtime.enable_provenance_checks();
+    }
+    if audit_signals {
+        runtime.enable_signal_audit();
+    }
+    if let Some(bundled) = bundled_libc {
+        runtime.set_library(bundled_symbols(bundled));
+    }
+    runtime.set_overflow_mode(overflow);
+    if sanitizers.undefined {
+        runtime.enable_undefined_checks();
+    }
+    if let Some(sandbox) = sandbox {
+        runtime.enable_sandbox(sandbox);
+    }
+    match trace {
+        TraceMode::Off => {}
+        TraceMode::Record(path) => {
+            if let Err(e) = runtime.record_to(path, source) {
+                eprintln!("Error: cannot record to {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        TraceMode::Replay(path) => match Trace::load(path).and_then(|t| t.check_source(source).map(|_| t)) {
+            Ok(recording) => runtime.replay(recording),
+            Err(e) => {
+                eprintln!("Error: {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+    }
+
+    if let Some(config) = deterministic {
+        runtime.set_deterministic(Arc::new(Determinism::new(config)));
+    }
+    let probe_points = Arc::new(ProbePoints::new(rewritten.sites));
+    for pattern in usdt {
+        if probe_points.enable(pattern) == 0 {
+            log::warn!("--usdt: no probe site matches '{}'", pattern);
+        }
+    }
+    if !usdt.is_empty() {
+        probe_points.subscribe(|site, hit| eprintln!("probe {}", hit.describe(site)));
+    } else if vm_stats {
+        // Counted for the profile, not printed
+        probe_points.enable("*");
+    }
+    runtime.set_probe_points(Arc::clone(&probe_points));
 
     // Execute the code
-    match runtime.execute(&ast) {
-        Ok(result) => {
-            println!("Program executed successfully");
-            println!("Return value: {}", result.return_value);
-            Ok(())
+    if !limits.is_unlimited() {
+        runtime.set_limits(limits);
+    }
+    let bytecode_result = match engine {
+        InterpreterEngine::Bytecode | InterpreterEngine::Native => {
+            execute_bytecode(&ast, &rewritten.source, &mut runtime, tree_only, engine, cache_dir, emit_ir, detect_leaks)
+        }
+        InterpreterEngine::Tree => None,
+    };
+    if let (Some(needed), None) = (needs_bytecode, &bytecode_result) {
+        eprintln!("Error: {} needs the bytecode engine, which cannot run this program", needed);
+        process::exit(1);
+    }
+    // Only the bytecode engine feeds the opcode and function counters
+    let walked_tree = bytecode_result.is_none();
+    let result = bytecode_result
+        .unwrap_or_else(|| report::timed("run", || runtime.execute(&ast).map(|result| result.return_value as i32)));
+    // The bundled stdio buffers output; `exit` is intercepted, so it's
+    // flushed here (handlers registered with its `atexit` don't run)
+    if let Some(fflush) = runtime.library_symbol("fflush") {
+        // SAFETY: the bundled libc's `int fflush(FILE *)`
+        let fflush: extern "C" fn(*mut libc::FILE) -> libc::c_int = unsafe { std::mem::transmute(fflush as usize) };
+        fflush(std::ptr::null_mut());
+    }
+    let outcome = match result {
+        Ok(code) => {
+            log::info!("Program executed successfully");
+            if vm_stats && interpreter::vm_stats::VmStats::enabled() {
+                if walked_tree {
+                    log::warn!("--vm-stats: the tree-walking interpreter keeps no opcode counters; run with --engine=bytecode");
+                } else {
+                    eprint!("{}", runtime.vm_stats().summary(20));
+                }
+            }
+            if vm_stats {
+                eprint!("{}", probe_points.summary());
+            }
+            Ok(ProgramExit::Exited(code))
+        }
+        // -ftrapv: the report is already printed; end like the JIT's abort()
+        Err(RuntimeError::OverflowTrap) => Ok(ProgramExit::Signaled(libc::SIGABRT)),
+        // A fault whose handler returned: what the host would do on the retry
+        Err(RuntimeError::FatalSignal(signal)) => Ok(ProgramExit::Signaled(signal)),
+        // --audit-signals: an assertion failure, so end like assert()
+        Err(RuntimeError::SignalSafety(violation)) => {
+            eprint!("{}", violation);
+            Ok(ProgramExit::Signaled(libc::SIGABRT))
+        }
+        // As if the kernel enforced the matching rlimit
+        Err(RuntimeError::Limit(exceeded)) => {
+            eprintln!("Error: {}", exceeded);
+            Ok(ProgramExit::Signaled(exceeded.signal()))
+        }
+        Err(e) => Err(e),
+    };
+
+    // A recording must also end when the program fails, to debug the failure
+    let end = match &outcome {
+        Ok(ProgramExit::Exited(code)) => TraceEnd::Exited(*code),
+        Ok(ProgramExit::Signaled(signal)) => TraceEnd::Signaled(*signal),
+        Err(_) => TraceEnd::RuntimeError,
+    };
+    if let Err(e) = runtime.finish_trace(end) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+
+    outcome.or_else(|e| {
+        print_error(&runtime_error(&e));
+        process::exit(1);
+    })
+}
+
+/// Print an error that ends the run, and record it as the frontend's are
+fn print_error(diagnostic: &Diagnostic) {
+    eprintln!("{}", diagnostic);
+    report::note_diagnostic(diagnostic);
+    usage::note_error(diagnostic.code.unwrap_or(usage::UNCODED));
+}
+
+/// E0015, or E0018 for a program the bytecode compiler rejects
+fn runtime_error(error: &RuntimeError) -> Diagnostic {
+    match error {
+        RuntimeError::Bytecode(rejected @ (BytecodeError::Undeclared { .. } | BytecodeError::Invalid { .. })) => {
+            Diagnostic::error(rejected.to_string()).with_code(diagnostics::codes::E0018)
+        }
+        error => Diagnostic::catalogued(Severity::Error, MessageId::RuntimeError, vec![format!("{:?}", error)]),
+    }
+}
+
+/// `--engine=bytecode` or `native`: the exit status, or `None` when the tree
+/// walker has to run the program instead. `source` is what `ast` was parsed
+/// from, the key of the compiled program in the cache at `cache_dir`.
+/// With `detect_leaks`, the leak report is printed before the VM goes away.
+fn execute_bytecode(
+    ast: &frontend::ast::TranslationUnit,
+    source: &str,
+    runtime: &mut CRuntimeEnvironment,
+    tree_only: Option<&str>,
+    engine: InterpreterEngine,
+    cache_dir: Option<&Path>,
+    emit_ir: Option<&Path>,
+    detect_leaks: Option<&LeakConfig>,
+) -> Option<Result<i32, RuntimeError>> {
+    if let Some(option) = tree_only {
+        log::warn!("--engine={} does not support {}; using the tree-walking interpreter", engine, option);
+        return None;
+    }
+
+    // Unchanged programs skip the bytecode compiler, also across upgrades
+    // that keep the IR format
+    let mut cache = cache_dir.and_then(|dir| CompilationCache::open(dir).ok());
+    // The program is laid out for the host's ABI
+    let host = arch::Architecture::from_str(std::env::consts::ARCH)
+        .map_or_else(|_| format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS), |architecture| architecture.host_target_triple().to_string());
+    let key = CacheKey::for_ir(source.as_bytes(), "bytecode", &host);
+    let cached = cache.as_mut().and_then(|cache| cache.get_ir(&key)).and_then(|data| {
+        match ir::read_program(&data).and_then(|program| {
+            program.verify().map_err(|e| ir::IrError::Invalid(e.to_string()))?;
+            Ok(program)
+        }) {
+            Ok(program) => Some(program),
+            Err(e) => {
+                log::debug!("cached bytecode is unusable: {}", e);
+                None
+            }
+        }
+    });
+
+    let program = match cached {
+        Some(program) => program,
+        None => {
+            let mut program = match report::timed("bytecode", || bytecode::compile(ast)) {
+                Ok(program) => program,
+                Err(e @ BytecodeError::Unsupported { .. }) => {
+                    log::warn!("{}; using the tree-walking interpreter", e);
+                    return None;
+                }
+                Err(e) => return Some(Err(RuntimeError::Bytecode(e))),
+            };
+            let fused = bytecode::fuse(&mut program);
+            log::debug!("Bytecode, {} superinstructions:\n{}", fused, program.disassemble());
+            if let Some(cache) = cache.as_mut() {
+                if let Err(e) = cache.put_ir(&key, &ir::write_program(&program)) {
+                    log::warn!("could not store the bytecode in the compilation cache: {:?}", e);
+                }
+            }
+            program
+        }
+    };
+    if let Some(path) = emit_ir {
+        if let Err(e) = fs::write(path, ir::write_program(&program)) {
+            eprintln!("Error: cannot write IR to {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+    // Machine code skips the checks `--sanitize=undefined` asks the VM
+    // for, and calls the allocator without it
+    let native = engine == InterpreterEngine::Native && !runtime.undefined_checks() && detect_leaks.is_none();
+    if engine == InterpreterEngine::Native && !native {
+        log::warn!("--engine=native does not support --sanitize or --detect-leaks; interpreting the bytecode");
+    }
+    // A bad suppression file is reported before the program runs
+    if let Some(config) = detect_leaks {
+        if let Err(e) = leaks::report_at_exit(config, Arc::default()) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
         }
+    }
+    Some(report::timed("run", || {
+        Vm::new(&program, runtime).and_then(|vm| {
+            let vm = if detect_leaks.is_some() { vm.with_leak_detection() } else { vm };
+            let mut vm = if native { vm.with_native() } else { vm };
+            let status = vm.run_main(&["<input>".to_string()]);
+            leaks::flush_at_exit();
+            status
+        })
+    }))
+}
+
+/// Step backwards and forwards through a run recorded with `--record`
+fn debug_recording(path: &Path, source: &str) -> io::Result<ProgramExit> {
+    let mut debugger = DebugSystem::new().unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        process::exit(1);
+    });
+    let session = match debugger.load_recording(path) {
+        Ok(session) => session,
         Err(e) => {
-            eprintln!("Runtime error: {:?}", e);
+            eprintln!("Error: {}: {:?}", path.display(), e);
             process::exit(1);
         }
+    };
+    if session.trace().check_source(source).is_err() {
+        log::warn!("{} was recorded from a different version of the program", path.display());
+    }
+
+    reverse::run_prompt(session)?;
+    Ok(ProgramExit::Exited(0))
+}
+
+/// Parse the program and report diagnostics without executing it
+fn analyze_code(source: &str, diagnostics_config: DiagnosticsConfig) -> io::Result<()> {
+    let mut diagnostics = DiagnosticsEngine::new(diagnostics_config);
+    recovery::parse_reporting(source, "<input>", |text| C23Parser::new().parse(text), &mut diagnostics);
+
+    diagnostics.flush_to_stderr();
+    if diagnostics.has_errors() {
+        process::exit(1);
     }
+    println!("No issues found");
+    Ok(())
 }
 
 /// JIT compile and execute C code
-fn jit_execute(source: &str, opt_level: u32, architecture: &str) -> io::Result<()> {
-    println!("JIT compiling and executing code...");
+/// `sample_profile` is where `--sample-profile` goes, `-` for stderr
+#[cfg(feature = "llvm")]
+fn jit_execute(
+    source: &str,
+    options: &Options,
+    libc: Option<&BundledLibc>,
+    sample_profile: Option<&str>,
+    tiered: bool,
+) -> io::Result<ProgramExit> {
+    if tiered {
+        if let Some(exit) = tiered_execute(source, options, libc, sample_profile)? {
+            return Ok(exit);
+        }
+    }
+    log::info!("JIT compiling and executing code...");
+
+    // The compiler owns the code, so keep it alive until the program is done
+    let (compiler, main_fn) = jit_compile_main(source, options, libc, false);
+    let jit = options.jit_options();
+
+    // Run in a child so crashes and exit() calls surface as our exit status
+    let exit = run_in_child(|| {
+        // Its helper threads have to be in the process the program runs in
+        if let Some(mode) = &options.capture {
+            if let Err(e) = capture::start(mode, source) {
+                eprintln!("Error: {}", e);
+                return 1;
+            }
+        }
+        if let Some(config) = &options.detect_leaks {
+            if let Err(e) = leaks::report_at_exit(config, compiler.jit_symbols()) {
+                eprintln!("Error: {}", e);
+                return 1;
+            }
+        }
+        // Started in the child: perf events follow the thread that opened them
+        if let Some(output) = sample_profile {
+            match SamplingProfiler::start(SamplingConfig::default()) {
+                Ok(profiler) => {
+                    let output = output.to_string();
+                    profiler.report_at_exit(compiler.jit_symbols(), move |profile| write_sample_profile(&profile, &output));
+                }
+                Err(e) => eprintln!("Warning: --sample-profile: {:?}", e),
+            }
+        }
+        let status = if jit.enable_guard_pages {
+            run_main_guarded(main_fn, jit.stack_size, &compiler)
+        } else {
+            let args: Vec<*const i8> = vec![std::ptr::null()];
+            main_fn(0, args.as_ptr())
+        };
+        sampling::flush_at_exit();
+        leaks::flush_at_exit();
+        status
+    })?;
+
+    log::info!("Program {}", exit);
+    Ok(exit)
+}
+
+/// `--tiered`: run the program on the bytecode VM, compiling its hot
+/// functions with LLVM in the background; see `jit::tiers`. `None` when
+/// the program or the options need everything compiled up front.
+#[cfg(feature = "llvm")]
+fn tiered_execute(source: &str, options: &Options, libc: Option<&BundledLibc>, sample_profile: Option<&str>) -> io::Result<Option<ProgramExit>> {
+    let unsupported = if options.capture.is_some() {
+        Some("--capture-io and --replay-io")
+    } else if options.detect_leaks.is_some() {
+        Some("--detect-leaks")
+    } else if options.profile_generate.is_some() {
+        Some("--profile-generate")
+    } else if sample_profile.is_some() {
+        Some("--sample-profile")
+    } else if options.sanitizers.undefined {
+        Some("--sanitize")
+    } else {
+        None
+    };
+    if let Some(option) = unsupported {
+        log::warn!("--tiered does not support {}; compiling everything up front", option);
+        return Ok(None);
+    }
+    // The JIT reports parse errors
+    let Ok(ast) = C23Parser::new().parse(source) else { return Ok(None) };
+    let mut program = match bytecode::compile(&ast) {
+        Ok(program) => program,
+        Err(e) => {
+            log::warn!("--tiered: {}; compiling everything up front", e);
+            return Ok(None);
+        }
+    };
+    bytecode::fuse(&mut program);
+    log::info!("Interpreting, compiling hot functions in the background...");
+
+    let exit = run_in_child(|| {
+        let mut runtime = match CRuntimeEnvironment::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to initialize runtime: {:?}", e);
+                return 1;
+            }
+        };
+        runtime.set_overflow_mode(options.overflow);
+        // Both are needed until the child exits
+        let program: &'static bytecode::Program = Box::leak(Box::new(program));
+        let interpreter = match BytecodeTier::new(program, Box::leak(Box::new(runtime))) {
+            Ok(interpreter) => Arc::new(interpreter),
+            Err(e) => {
+                print_error(&runtime_error(&e));
+                return 1;
+            }
+        };
+        let compiler = Box::new(LlvmTier::new(source, jit_options(options, libc), program));
+        let engine = match TieredEngine::new(interpreter.clone(), compiler, TierThresholds::default()) {
+            Ok(engine) => Arc::new(engine),
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                return 1;
+            }
+        };
+        for index in tiers::tiered_functions(program, options.overflow == OverflowMode::Wrap) {
+            let function = &program.functions[index as usize];
+            let signature = function.signature.as_ref().expect("tiered functions have a signature");
+            let parameters: Vec<_> = signature.parameters.iter().map(|&kind| tiers::jit_type(Some(kind))).collect();
+            engine.register(&function.name, &parameters, tiers::jit_type(signature.result));
+        }
+        interpreter.attach(&engine);
 
+        let signal = match interpreter.run_main(&["<input>".to_string()]) {
+            Ok(status) => {
+                log::debug!("tiers: {:?}", engine.stats());
+                return status;
+            }
+            // -ftrapv: the report is already printed; end like the JIT's abort()
+            Err(RuntimeError::OverflowTrap) => libc::SIGABRT,
+            Err(RuntimeError::FatalSignal(signal)) => signal,
+            Err(e) => {
+                print_error(&runtime_error(&e));
+                return 1;
+            }
+        };
+        // SAFETY: ends the child the way the signal would have
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+        128 + signal
+    })?;
+
+    log::info!("Program {}", exit);
+    Ok(Some(exit))
+}
+
+/// Run `main` on a stack of `stack_size` bytes between guard pages, so
+/// that overflowing it prints where instead of crashing without a word
+#[cfg(feature = "llvm")]
+fn run_main_guarded(main_fn: MainFn, stack_size: usize, compiler: &compiler::Compiler) -> i32 {
+    let args: Vec<*const i8> = vec![std::ptr::null()];
+    let run = || unsafe { setjmp::call_native(|| main_fn(0, args.as_ptr())) };
+    match unsafe { stack_guard::run_guarded(stack_size, run) } {
+        Ok(Ok(status)) => status,
+        Ok(Err(Interrupted::StackOverflow(address))) => {
+            let symbols = compiler.jit_symbols();
+            eprint!("Error: {}", stack_guard::take_overflow(address, Some(&symbols.read())));
+            // What a shell reports for a program killed by SIGSEGV
+            128 + libc::SIGSEGV
+        }
+        // Nothing interpreted for a `longjmp` or a fault to go back to
+        Ok(Err(interrupted)) => {
+            eprintln!("Error: compiled code was interrupted: {:?}", interrupted);
+            1
+        }
+        Err(e) => {
+            eprintln!("Warning: no guarded stack for the program: {:?}", e);
+            main_fn(0, args.as_ptr())
+        }
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn write_sample_profile(profile: &SampleProfile, output: &str) {
+    if output == "-" {
+        eprint!("{}", profile);
+        return;
+    }
+    let written = serde_json::to_string_pretty(profile).map_err(io::Error::other).and_then(|json| fs::write(output, json));
+    if let Err(e) = written {
+        eprintln!("Warning: cannot write the sample profile to {}: {}", output, e);
+    }
+}
+
+/// JIT compile and run the program stopped under a gdbstub on `port`
+#[cfg(feature = "llvm")]
+fn jit_debug(source: &str, options: &Options, libc: Option<&BundledLibc>, port: u16) -> io::Result<ProgramExit> {
+    // Prologues the debugger can patch probes into with `monitor probe`
+    let (compiler, main_fn) = jit_compile_main(source, options, libc, true);
+
+    let pid = match spawn_stopped(|| {
+        let args: Vec<*const i8> = vec![std::ptr::null()];
+        main_fn(0, args.as_ptr())
+    }) {
+        Ok(pid) => pid,
+        Err(e) => {
+            eprintln!("Error: failed to start program under the debugger: {:?}", e);
+            process::exit(1);
+        }
+    };
+
+    eprintln!("Waiting for debugger: target remote :{}", port);
+    let mut stub = GdbStub::new(pid).with_probes(compiler.probes().read().clone());
+    if let Err(e) = stub.serve(&format!("127.0.0.1:{}", port)) {
+        eprintln!("Error: gdbstub: {:?}", e);
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+
+    if let Some(exit) = stub.exit_status() {
+        return Ok(exit);
+    }
+
+    // The debugger detached; let the program finish on its own
+    let mut status = 0;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    Ok(if libc::WIFSIGNALED(status) {
+        ProgramExit::Signaled(libc::WTERMSIG(status))
+    } else {
+        ProgramExit::Exited(libc::WEXITSTATUS(status))
+    })
+}
+
+#[cfg(feature = "llvm")]
+type MainFn = extern "C" fn(i32, *const *const i8) -> i32;
+
+/// JIT compile `source` and return the compiler with the program's `main`
+#[cfg(feature = "llvm")]
+fn jit_compile_main(
+    source: &str,
+    options: &Options,
+    libc: Option<&BundledLibc>,
+    patchable_prologues: bool,
+) -> (compiler::Compiler, MainFn) {
     // Create compiler instance
     let compiler = unsafe {
         match compiler::Compiler::new() {
@@ -283,37 +2502,103 @@ fn jit_execute(source: &str, opt_level: u32, architecture: &str) -> io::Result<(
         }
     };
 
-    // Set up JIT options
-    let jit_options = JITOptions {
-        optimization_level: opt_level,
-        enable_fast_isel: true,
-        enable_guard_pages: true,
-        stack_size: 8 * 1024 * 1024, // 8MB stack
-        target_architecture: arch::Architecture::from_str(architecture).ok(),
-        target_triple: Some(get_target_triple(architecture).to_string()),
+    let mut jit = jit_options(options, libc);
+    jit.patchable_prologues |= patchable_prologues;
+    let func_ptr = match unsafe { compiler.jit_compile(source, &jit) } {
+        Ok(func_ptr) => func_ptr,
+        Err(e) => {
+            e.diagnostics().iter().for_each(print_error);
+            process::exit(1);
+        }
     };
 
+    print_remarks(&compiler.take_remarks(), &options.remarks);
+
+    // Cast function pointer to the appropriate type (main function)
+    let main_fn: MainFn = unsafe { std::mem::transmute(func_ptr) };
+    (compiler, main_fn)
+}
+
+/// Print the remarks `-Rpass`, `-Rpass-missed` and `-Rpass-analysis` ask
+/// for to stderr; `--report` gets all of them
+#[cfg(feature = "llvm")]
+fn print_remarks(remarks: &[OptimizationRemark], filter: &RemarkFilter) {
+    for remark in remarks {
+        report::note_remark(remark);
+        if filter.matches(remark) {
+            eprintln!("{}", remark.to_diagnostic());
+        }
+    }
+}
+
+/// `options` for the JIT, compiling against the bundled `libc` if given
+#[cfg(feature = "llvm")]
+fn jit_options(options: &Options, libc: Option<&BundledLibc>) -> JITOptions {
+    let mut jit = options.jit_options();
+    if let Some(libc) = libc {
+        jit.system_include_dirs.push(libc.include_dir());
+        jit.archives.push(libc.archive());
+    }
+    jit
+}
+
+/// Where the interpreter finds the bundled libc's functions: `libc.a`,
+/// loaded into a JIT alongside a stub `main`
+#[cfg(feature = "llvm")]
+fn bundled_symbols(libc: &BundledLibc) -> SymbolResolver {
+    struct Library(compiler::CompilerSystem);
+    // SAFETY: after loading, the engine is only asked for addresses
+    unsafe impl Send for Library {}
+    unsafe impl Sync for Library {}
+
+    let triple = options::target_triple(std::env::consts::ARCH);
+    let compiler = unsafe { compiler::CompilerSystem::new(triple) }.unwrap_or_else(|e| {
+        eprintln!("Failed to initialize compiler: {:?}", e);
+        process::exit(1);
+    });
+    if let Err(e) = unsafe { compiler.jit_compile("int main(void) { return 0; }\n", &jit_options(&Options::default(), Some(libc))) } {
+        eprintln!("Error: --libc=bundled: cannot load {}: {:?}", libc.archive().display(), e);
+        process::exit(1);
+    }
+    let library = Library(compiler);
+    // SAFETY: the archive is loaded
+    Arc::new(move |name| unsafe { library.0.jit_symbol_address(name) }.map(|address| address as u64))
+}
+
+/// Only reached with `--libc=bundled`, which needs LLVM to build the library
+#[cfg(not(feature = "llvm"))]
+fn bundled_symbols(_libc: &BundledLibc) -> SymbolResolver {
+    without_llvm("--libc=bundled")
+}
+
+/// JIT compile a translation unit and return the result of its `main`
+#[cfg(feature = "llvm")]
+fn jit_eval(source: &str, options: &Options) -> Result<i32, String> {
+    // Create compiler instance
+    let compiler = unsafe { compiler::Compiler::new() }
+        .map_err(|e| format!("Failed to initialize compiler: {:?}", e))?;
+
     // JIT compile and execute
     unsafe {
-        match compiler.jit_compile(source, &jit_options) {
-            Ok(func_ptr) => {
-                // Cast function pointer to the appropriate type (main function)
-                let main_fn: extern "C" fn(i32, *const *const i8) -> i32 = 
-                    std::mem::transmute(func_ptr);
-                
-                // Prepare argc and argv
-                let args: Vec<*const i8> = vec![std::ptr::null()];
-                
-                // Call the function
-                let result = main_fn(0, args.as_ptr());
-                println!("Program executed successfully");
-                println!("Return value: {}", result);
-                Ok(())
-            }
-            Err(e) => {
-                eprintln!("JIT compilation error: {:?}", e);
-                process::exit(1);
-            }
-        }
+        let func_ptr = compiler
+            .jit_compile(source, &jit_options(options, None))
+            .map_err(|e| format!("JIT compilation error: {:?}", e))?;
+
+        // Cast function pointer to the appropriate type (main function)
+        let main_fn: extern "C" fn(i32, *const *const i8) -> i32 = 
+            std::mem::transmute(func_ptr);
+        
+        // Prepare argc and argv
+        let args: Vec<*const i8> = vec![std::ptr::null()];
+        
+        // Call the function
+        Ok(main_fn(0, args.as_ptr()))
     }
-} 
+}
+
+/// Exit with an error for `what`, which this binary can't do
+#[cfg(not(feature = "llvm"))]
+fn without_llvm(what: &str) -> ! {
+    eprintln!("Error: {} needs LLVM, and this binary was built without the `llvm` feature", what);
+    process::exit(1);
+}
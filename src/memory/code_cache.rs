@@ -0,0 +1,259 @@
+// src/memory/code_cache.rs
+//! Managed cache of JIT-compiled code
+//! Compiled functions are kept by name up to a budget of bytes and of
+//! entries. When a new one doesn't fit, the least recently used are
+//! evicted: the cache's eviction hook takes them out of use (the tiered
+//! engine points their dispatch slot back at the interpreter), and their
+//! code is retired rather than unmapped, since another thread may still be
+//! running it. Threads run cached code inside an `ExecutionGuard`; once no
+//! guard is held, nothing can be running retired code and it is freed.
+//! A function that is evicted and called again is compiled again.
+//!
+//! Hits, misses, evictions and the bytes in use are counted in `stats` and
+//! reported through the `metrics` facade as `jit.code_cache.*`.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use metrics::{counter, gauge};
+use parking_lot::Mutex;
+
+/// Size limits of a code cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeCacheConfig {
+    /// Machine code bytes kept before the least recently used are evicted
+    pub max_bytes: usize,
+    /// Functions kept before the least recently used are evicted
+    pub max_entries: usize,
+}
+
+impl Default for CodeCacheConfig {
+    fn default() -> Self {
+        CodeCacheConfig {
+            max_bytes: 64 * 1024 * 1024,
+            max_entries: 16 * 1024,
+        }
+    }
+}
+
+/// Counters of a code cache since it was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodeCacheStats {
+    pub entries: usize,
+    /// Bytes of the cached functions
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Bytes evicted or replaced but not yet freed, as some thread was
+    /// running compiled code
+    pub retired_bytes: usize,
+}
+
+/// Whatever keeps a function's code mapped; dropping it frees the code
+pub type CodeOwner = Box<dyn Any + Send>;
+
+/// Called with each evicted entry, before its code is retired
+pub type EvictionHook<V> = Box<dyn Fn(&str, &V) + Send + Sync>;
+
+struct Entry<V> {
+    value: V,
+    size: usize,
+    last_use: u64,
+    owner: CodeOwner,
+}
+
+struct Inner<V> {
+    entries: HashMap<String, Entry<V>>,
+    bytes: usize,
+    // Logical clock for LRU order
+    clock: u64,
+    // Code no longer reachable through the cache, freed when nothing runs
+    retired: Vec<(usize, CodeOwner)>,
+}
+
+pub struct CodeCache<V> {
+    config: CodeCacheConfig,
+    inner: Mutex<Inner<V>>,
+    on_evict: Option<EvictionHook<V>>,
+
+    // Threads inside an `ExecutionGuard`
+    running: AtomicUsize,
+
+    // Statistics
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<V: Clone> CodeCache<V> {
+    pub fn new(config: CodeCacheConfig) -> Self {
+        CodeCache {
+            config,
+            inner: Mutex::new(Inner { entries: HashMap::new(), bytes: 0, clock: 0, retired: Vec::new() }),
+            on_evict: None,
+            running: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Call `hook` for each entry evicted to make room, while the cache is
+    /// locked and before its code is retired. It must stop new calls from
+    /// reaching the code; threads already in it finish normally.
+    pub fn on_evict(mut self, hook: impl Fn(&str, &V) + Send + Sync + 'static) -> Self {
+        self.on_evict = Some(Box::new(hook));
+        self
+    }
+
+    pub fn config(&self) -> CodeCacheConfig {
+        self.config
+    }
+
+    /// The cached function `name`, now the most recently used
+    pub fn get(&self, name: &str) -> Option<V> {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let clock = inner.clock;
+        match inner.entries.get_mut(name) {
+            Some(entry) => {
+                entry.last_use = clock;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                counter!("jit.code_cache.hits").increment(1);
+                Some(entry.value.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                counter!("jit.code_cache.misses").increment(1);
+                None
+            }
+        }
+    }
+
+    /// Cache `size` bytes of code for `name`, replacing any earlier code
+    /// for it, and evict the least recently used functions until the cache
+    /// is within its limits again. The new entry itself is never evicted,
+    /// even if it alone is over the limit: its caller is about to run it.
+    /// Returns the names evicted.
+    pub fn insert(&self, name: &str, value: V, size: usize, owner: CodeOwner) -> Vec<String> {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let entry = Entry { value, size, last_use: inner.clock, owner };
+        inner.bytes += size;
+        if let Some(old) = inner.entries.insert(name.to_string(), entry) {
+            inner.bytes -= old.size;
+            inner.retired.push((old.size, old.owner));
+        }
+
+        let mut evicted = Vec::new();
+        while inner.entries.len() > 1
+            && (inner.bytes > self.config.max_bytes || inner.entries.len() > self.config.max_entries)
+        {
+            let Some(victim) = inner
+                .entries
+                .iter()
+                .filter(|(key, _)| key.as_str() != name)
+                .min_by_key(|(_, entry)| entry.last_use)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            let entry = inner.entries.remove(&victim).expect("victim is cached");
+            if let Some(hook) = &self.on_evict {
+                hook(&victim, &entry.value);
+            }
+            inner.bytes -= entry.size;
+            inner.retired.push((entry.size, entry.owner));
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+            counter!("jit.code_cache.evictions").increment(1);
+            log::debug!("code cache: evicted {} ({} bytes)", victim, entry.size);
+            evicted.push(victim);
+        }
+
+        self.reclaim(&mut inner);
+        self.report(&inner);
+        evicted
+    }
+
+    /// Drop `name` from the cache without calling the eviction hook
+    pub fn remove(&self, name: &str) -> Option<V> {
+        let mut inner = self.inner.lock();
+        let entry = inner.entries.remove(name)?;
+        inner.bytes -= entry.size;
+        inner.retired.push((entry.size, entry.owner));
+        self.reclaim(&mut inner);
+        self.report(&inner);
+        Some(entry.value)
+    }
+
+    /// Mark this thread as running cached code until the guard is dropped.
+    /// Take it before loading an entry point, so that code evicted after
+    /// the load isn't freed under the call.
+    pub fn enter(&self) -> ExecutionGuard<'_, V> {
+        self.running.fetch_add(1, Ordering::SeqCst);
+        ExecutionGuard { cache: self }
+    }
+
+    pub fn stats(&self) -> CodeCacheStats {
+        let inner = self.inner.lock();
+        CodeCacheStats {
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            retired_bytes: inner.retired.iter().map(|(size, _)| size).sum(),
+        }
+    }
+
+    /// Free the retired code if no thread is running any
+    fn reclaim(&self, inner: &mut Inner<V>) {
+        if !inner.retired.is_empty() && self.running.load(Ordering::SeqCst) == 0 {
+            inner.retired.clear();
+        }
+    }
+
+    fn report(&self, inner: &Inner<V>) {
+        gauge!("jit.code_cache.entries").set(inner.entries.len() as f64);
+        gauge!("jit.code_cache.bytes").set(inner.bytes as f64);
+        gauge!("jit.code_cache.retired_bytes").set(inner.retired.iter().map(|(size, _)| *size).sum::<usize>() as f64);
+    }
+}
+
+/// A thread running code from a `CodeCache`; see `CodeCache::enter`
+pub struct ExecutionGuard<'a, V: Clone> {
+    cache: &'a CodeCache<V>,
+}
+
+impl<V: Clone> Drop for ExecutionGuard<'_, V> {
+    fn drop(&mut self) {
+        if self.cache.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Someone else holding the lock will reclaim once they're done
+            if let Some(mut inner) = self.cache.inner.try_lock() {
+                self.cache.reclaim(&mut inner);
+                self.cache.report(&inner);
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn run(cache: &CodeCache<*const u8>, compile: impl Fn() -> CompiledCode) -> u64 {
+    let _running = cache.enter();
+    let entry = match cache.get("fib") {
+        Some(entry) => entry,
+        None => {
+            let code = compile();
+            let entry = code.entry;
+            for name in cache.insert("fib", entry, code.size, Box::new(code)) {
+                println!("evicted {}", name);
+            }
+            entry
+        }
+    };
+    let fib: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(entry) };
+    fib(30)
+}
+*/
@@ -0,0 +1,140 @@
+// src/memory/hugepages.rs
+//! Huge-page backed mappings for the JIT code region and large heaps
+//! Explicit huge pages (MAP_HUGETLB) are tried first when requested, then
+//! transparent huge pages via madvise(MADV_HUGEPAGE), then plain 4K pages.
+
+use std::ptr;
+
+/// Size of a huge page on x86_64 and aarch64 (with 4K base pages)
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Regions smaller than this never benefit enough to be worth a huge page
+pub const DEFAULT_HUGE_PAGE_THRESHOLD: usize = HUGE_PAGE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HugePageMode {
+    /// Always use base pages
+    #[default]
+    Disabled,
+    /// Ask the kernel to back the region with transparent huge pages
+    Transparent,
+    /// Use MAP_HUGETLB from the reserved pool, falling back to transparent
+    Explicit,
+}
+
+/// How a region actually ended up being backed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageBacking {
+    BasePages,
+    TransparentHugePages,
+    ExplicitHugePages,
+}
+
+#[derive(Debug)]
+pub struct HugePageRegion {
+    pub ptr: *mut u8,
+    /// Mapped size, rounded up to the page size actually used
+    pub size: usize,
+    pub backing: PageBacking,
+}
+
+/// Map an anonymous read/write region, using huge pages where possible.
+///
+/// The returned region is always usable; `backing` reports which fallback
+/// was taken so callers can surface it in metrics.
+pub unsafe fn map_anonymous(
+    size: usize,
+    mode: HugePageMode,
+    threshold: usize
+) -> Result<HugePageRegion, HugePageError> {
+    if mode == HugePageMode::Disabled || size < threshold {
+        let ptr = mmap_raw(size, 0)?;
+        return Ok(HugePageRegion { ptr, size, backing: PageBacking::BasePages });
+    }
+
+    let huge_size = align_up(size, HUGE_PAGE_SIZE);
+
+    if mode == HugePageMode::Explicit {
+        // Fails with ENOMEM when no huge pages are reserved; that is expected
+        if let Ok(ptr) = mmap_raw(huge_size, libc::MAP_HUGETLB) {
+            return Ok(HugePageRegion {
+                ptr,
+                size: huge_size,
+                backing: PageBacking::ExplicitHugePages,
+            });
+        }
+    }
+
+    // Over-allocate so the region can start on a huge page boundary, which
+    // khugepaged needs in order to collapse it
+    let reserve = huge_size + HUGE_PAGE_SIZE;
+    let raw = mmap_raw(reserve, 0)?;
+    let aligned = align_up(raw as usize, HUGE_PAGE_SIZE) as *mut u8;
+
+    let head = aligned as usize - raw as usize;
+    if head > 0 {
+        libc::munmap(raw as *mut libc::c_void, head);
+    }
+    let tail = reserve - head - huge_size;
+    if tail > 0 {
+        libc::munmap(aligned.add(huge_size) as *mut libc::c_void, tail);
+    }
+
+    let backing = if libc::madvise(aligned as *mut libc::c_void, huge_size, libc::MADV_HUGEPAGE) == 0 {
+        PageBacking::TransparentHugePages
+    } else {
+        PageBacking::BasePages
+    };
+
+    Ok(HugePageRegion { ptr: aligned, size: huge_size, backing })
+}
+
+/// Whether transparent huge pages are enabled for madvise'd regions
+pub fn transparent_huge_pages_available() -> bool {
+    std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled")
+        .map(|mode| mode.contains("[always]") || mode.contains("[madvise]"))
+        .unwrap_or(false)
+}
+
+/// Number of free explicit huge pages in the reserved pool
+pub fn free_explicit_huge_pages() -> usize {
+    std::fs::read_to_string("/sys/kernel/mm/hugepages/hugepages-2048kB/free_hugepages")
+        .ok()
+        .and_then(|n| n.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+unsafe fn mmap_raw(size: usize, extra_flags: i32) -> Result<*mut u8, HugePageError> {
+    let ptr = libc::mmap(
+        ptr::null_mut(),
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | extra_flags,
+        -1,
+        0,
+    );
+
+    if ptr == libc::MAP_FAILED {
+        return Err(HugePageError::MapFailed(std::io::Error::last_os_error()));
+    }
+
+    Ok(ptr as *mut u8)
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+#[derive(Debug)]
+pub enum HugePageError {
+    MapFailed(std::io::Error),
+}
+
+// Example usage:
+/*
+unsafe fn example() -> Result<(), HugePageError> {
+    let region = map_anonymous(64 * 1024 * 1024, HugePageMode::Explicit, DEFAULT_HUGE_PAGE_THRESHOLD)?;
+    println!("JIT code region backed by {:?}", region.backing);
+    Ok(())
+}
+*/
@@ -0,0 +1,855 @@
+// src/memory/leaks.rs
+//! Leak detection for the program's heap (`--detect-leaks`)
+//! With `JITOptions::detect_leaks`, `optimizer::leaks` sends the program's
+//! calls to `malloc`, `calloc`, `realloc`, `strdup`, `strndup` and `free`
+//! to the `__ic_leak_*` host functions here, passing each allocating call
+//! its site: the calling function and its `file:line:column`, resolved
+//! through the debug SourceMap at compile time. They call the C library
+//! and keep every live block with its size, site, and the return
+//! addresses of the frames above it, from a frame-pointer walk.
+//!
+//! At exit, `report` sorts the blocks still live the way Valgrind's
+//! memcheck does, by scanning memory conservatively for pointers, starting
+//! from the program's globals (`add_root`):
+//!  - still reachable: a chain of pointers to their start leads to them
+//!  - possibly lost: only pointers into their middle do
+//!  - definitely lost: nothing points to them
+//!  - indirectly lost: only lost blocks point to them; they are counted in
+//!    the record of the definitely lost block that holds the chain
+//!
+//! Lost blocks with the same call stack make one loss record; records are
+//! printed smallest first with their stacks, then the totals. The stack
+//! of a block begins with the allocator and its site, followed by the
+//! compiled functions above it, named through `JitSymbols`.
+//!
+//! Suppressions use Valgrind's format; only `Memcheck:Leak` entries apply:
+//!
+//! ```text
+//! {
+//!    cache-is-freed-by-the-os
+//!    Memcheck:Leak
+//!    match-leak-kinds: definite
+//!    fun:malloc
+//!    fun:cache_*
+//!    ...
+//!    fun:main
+//! }
+//! ```
+//!
+//! `fun:` matches a function name and `obj:` the object it is in
+//! (`<jit>` for compiled code), with `*` and `?` as wildcards; `...`
+//! matches any number of frames. An entry matches a stack it is a prefix
+//! of. Blocks the C library allocates itself (`getline`, `fopen`) aren't
+//! tracked, and freeing them is passed through.
+//!
+//! The bytecode VM makes the same calls itself and records them through
+//! `track` and `untrack`. Its functions have no addresses, so its stacks
+//! are the allocator and the interpreted function that called it, located
+//! at `<bytecode>`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{c_char, c_void};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use crate::debug::stack_capture::{self, JitSymbols, NativeFrame};
+use crate::jit::host::{HostExport, HostFunction, HostSignature};
+use crate::jit::JITType;
+use crate::runtime::stack_guard;
+
+/// Frames kept per allocation, as Valgrind's `--num-callers`
+pub const MAX_CALLERS: usize = 12;
+
+/// Site of an allocation made outside the program's own calls
+pub const UNKNOWN_SITE: u64 = u64::MAX;
+
+/// What `--detect-leaks` was asked for
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeakConfig {
+    /// Valgrind suppression files, `--leak-suppressions`
+    pub suppressions: Vec<PathBuf>,
+    /// List still reachable blocks too, not just count them
+    pub show_reachable: bool,
+}
+
+/// Where an allocating call is in the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Site {
+    pub function: String,
+    /// `file:line:column`
+    pub location: String,
+}
+
+/// The C library function a block came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Allocator {
+    Malloc,
+    Calloc,
+    Realloc,
+    Strdup,
+    Strndup,
+}
+
+impl Allocator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Allocator::Malloc => "malloc",
+            Allocator::Calloc => "calloc",
+            Allocator::Realloc => "realloc",
+            Allocator::Strdup => "strdup",
+            Allocator::Strndup => "strndup",
+        }
+    }
+}
+
+struct Block {
+    size: usize,
+    allocator: Allocator,
+    site: u64,
+    /// Return addresses, innermost first
+    callers: Box<[usize]>,
+}
+
+// Sites by id, registered while compiling
+static SITES: RwLock<Vec<Site>> = RwLock::new(Vec::new());
+// Live blocks by address
+static LIVE: Mutex<BTreeMap<usize, Block>> = Mutex::new(BTreeMap::new());
+// Where pointers to live blocks may be kept: the program's globals
+static ROOTS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+// Totals for the heap summary
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static FREES: AtomicU64 = AtomicU64::new(0);
+static BYTES_ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+/// A site for `optimizer::leaks` to pass to the allocator it calls there
+pub fn register_site(function: &str, location: &str) -> u64 {
+    let mut sites = SITES.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    sites.push(Site { function: function.to_string(), location: location.to_string() });
+    (sites.len() - 1) as u64
+}
+
+/// Scan `size` bytes at `address` for pointers to live blocks at exit
+pub fn add_root(address: usize, size: usize) {
+    if size > 0 {
+        ROOTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((address, size));
+    }
+}
+
+/// The `__ic_leak_*` functions `optimizer::leaks` calls instead of the C
+/// library's
+pub fn host_exports() -> Vec<HostExport> {
+    let pointer = || JITType::Pointer(Box::new(JITType::Void));
+    let export = |name: &'static str, params: Vec<JITType>, return_type: JITType, function: *const ()| HostExport {
+        name,
+        signature: HostSignature::new(params, return_type),
+        function: HostFunction::Pointer(function as *const c_void),
+    };
+    let i64 = || JITType::Int64;
+    vec![
+        export("__ic_leak_malloc", vec![i64(), i64()], pointer(), leak_malloc as *const ()),
+        export("__ic_leak_calloc", vec![i64(), i64(), i64()], pointer(), leak_calloc as *const ()),
+        export("__ic_leak_realloc", vec![pointer(), i64(), i64()], pointer(), leak_realloc as *const ()),
+        export("__ic_leak_strdup", vec![pointer(), i64()], pointer(), leak_strdup as *const ()),
+        export("__ic_leak_strndup", vec![pointer(), i64(), i64()], pointer(), leak_strndup as *const ()),
+        export("__ic_leak_free", vec![pointer()], JITType::Void, leak_free as *const ()),
+    ]
+}
+
+extern "C" fn leak_malloc(size: usize, site: u64) -> *mut c_void {
+    let block = unsafe { libc::malloc(size) };
+    track(block, size, Allocator::Malloc, site);
+    block
+}
+
+extern "C" fn leak_calloc(count: usize, size: usize, site: u64) -> *mut c_void {
+    let block = unsafe { libc::calloc(count, size) };
+    track(block, count.saturating_mul(size), Allocator::Calloc, site);
+    block
+}
+
+extern "C" fn leak_realloc(old: *mut c_void, size: usize, site: u64) -> *mut c_void {
+    // Held across the call: once `old` is freed another thread's malloc
+    // may return it, and its record must not be the one dropped here
+    let callers = callers();
+    let mut live = LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let block = unsafe { libc::realloc(old, size) };
+    // On failure the old block is untouched and stays live
+    if block.is_null() && size != 0 {
+        return block;
+    }
+    if !old.is_null() && live.remove(&(old as usize)).is_some() {
+        FREES.fetch_add(1, Ordering::Relaxed);
+    }
+    if !block.is_null() {
+        insert(&mut live, block, size, Allocator::Realloc, site, callers);
+    }
+    block
+}
+
+extern "C" fn leak_strdup(string: *const c_char, site: u64) -> *mut c_char {
+    let copy = unsafe { libc::strdup(string) };
+    if !copy.is_null() {
+        track(copy as *mut c_void, unsafe { libc::strlen(copy) } + 1, Allocator::Strdup, site);
+    }
+    copy
+}
+
+extern "C" fn leak_strndup(string: *const c_char, length: usize, site: u64) -> *mut c_char {
+    let copy = unsafe { libc::strndup(string, length) };
+    if !copy.is_null() {
+        track(copy as *mut c_void, unsafe { libc::strlen(copy) } + 1, Allocator::Strndup, site);
+    }
+    copy
+}
+
+extern "C" fn leak_free(block: *mut c_void) {
+    if block.is_null() {
+        return;
+    }
+    // As for realloc: the address is only reusable after the record is gone
+    let mut live = LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if live.remove(&(block as usize)).is_some() {
+        FREES.fetch_add(1, Ordering::Relaxed);
+    }
+    unsafe { libc::free(block) };
+}
+
+/// Record `block`, `size` bytes from `allocator`, as live
+pub fn track(block: *mut c_void, size: usize, allocator: Allocator, site: u64) {
+    if block.is_null() {
+        return;
+    }
+    let callers = callers();
+    let mut live = LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    insert(&mut live, block, size, allocator, site, callers);
+}
+
+/// Forget `block` before it is freed
+pub fn untrack(block: *mut c_void) {
+    let mut live = LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if live.remove(&(block as usize)).is_some() {
+        FREES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn insert(live: &mut BTreeMap<usize, Block>, block: *mut c_void, size: usize, allocator: Allocator, site: u64, callers: Box<[usize]>) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    BYTES_ALLOCATED.fetch_add(size as u64, Ordering::Relaxed);
+    live.insert(block as usize, Block { size, allocator, site, callers });
+}
+
+thread_local! {
+    static THREAD_STACK: Option<(usize, usize)> = stack_capture::current_stack().ok();
+}
+
+/// Return addresses above the allocator's caller. The walk goes from this
+/// function's frame, so the first few are the host's own and are dropped
+/// when the stack is named.
+#[inline(never)]
+fn callers() -> Box<[usize]> {
+    let frame_pointer = frame_pointer();
+    // Compiled code may be on a guarded stack or a coroutine's
+    let stack = stack_guard::stack_containing(frame_pointer)
+        .or_else(|| THREAD_STACK.with(|stack| *stack))
+        .filter(|&(low, high)| (low..high).contains(&frame_pointer));
+    let Some(stack) = stack else { return Box::new([]) };
+    // Room for the host frames in front
+    let mut frames = [0; MAX_CALLERS + 4];
+    let count = unsafe { stack_capture::walk_from(frame_pointer, stack, &mut frames) };
+    frames[..count].into()
+}
+
+#[inline(always)]
+fn frame_pointer() -> usize {
+    let frame_pointer: usize;
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack, preserves_flags))
+    };
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("mov {}, x29", out(reg) frame_pointer, options(nomem, nostack, preserves_flags))
+    };
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        frame_pointer = 0;
+    }
+    frame_pointer
+}
+
+/// How a block left live at exit stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LeakKind {
+    Definite,
+    Indirect,
+    Possible,
+    Reachable,
+}
+
+impl LeakKind {
+    /// As Valgrind's `match-leak-kinds` names it
+    pub fn name(&self) -> &'static str {
+        match self {
+            LeakKind::Definite => "definite",
+            LeakKind::Indirect => "indirect",
+            LeakKind::Possible => "possible",
+            LeakKind::Reachable => "reachable",
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            LeakKind::Definite => "definitely lost",
+            LeakKind::Indirect => "indirectly lost",
+            LeakKind::Possible => "possibly lost",
+            LeakKind::Reachable => "still reachable",
+        }
+    }
+}
+
+/// Blocks of one kind with the same call stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LossRecord {
+    pub kind: LeakKind,
+    pub blocks: usize,
+    pub bytes: usize,
+    /// Bytes of the blocks only these hold on to; for definitely lost records
+    pub indirect_bytes: usize,
+    /// The allocator first, then its caller at the site, then the callers
+    /// above it
+    pub stack: Vec<NativeFrame>,
+}
+
+/// Bytes and blocks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Amount {
+    pub bytes: usize,
+    pub blocks: usize,
+}
+
+impl Amount {
+    fn add(&mut self, bytes: usize, blocks: usize) {
+        self.bytes += bytes;
+        self.blocks += blocks;
+    }
+}
+
+/// What `report` found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeakReport {
+    /// Loss records that weren't suppressed, smallest first
+    pub records: Vec<LossRecord>,
+    pub in_use: Amount,
+    pub allocations: u64,
+    pub frees: u64,
+    pub bytes_allocated: u64,
+    pub definitely_lost: Amount,
+    pub indirectly_lost: Amount,
+    pub possibly_lost: Amount,
+    pub still_reachable: Amount,
+    pub suppressed: Amount,
+    /// Hits per suppression name
+    pub suppressions_used: Vec<(String, usize)>,
+    /// Whether still reachable records are in `records`
+    pub show_reachable: bool,
+}
+
+impl LeakReport {
+    /// Definitely or possibly lost bytes, what makes a run fail
+    pub fn has_leaks(&self) -> bool {
+        self.definitely_lost.bytes > 0 || self.possibly_lost.bytes > 0
+    }
+}
+
+/// Classify the blocks still live; see the module documentation.
+/// `symbols` names the compiled frames.
+///
+/// # Safety
+/// The roots and live blocks must still be mapped, as they are at exit.
+pub unsafe fn report(symbols: Option<&JitSymbols>, suppressions: &[Suppression], show_reachable: bool) -> LeakReport {
+    let live = LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let roots = ROOTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    let sites = SITES.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let blocks: Vec<(usize, &Block)> = live.iter().map(|(&address, block)| (address, block)).collect();
+    let kinds = classify(&blocks, &roots);
+
+    // Group by kind and stack; a clique's indirect bytes go to its leader
+    let mut records: HashMap<(LeakKind, Allocator, u64, &[usize]), LossRecord> = HashMap::new();
+    let mut report = LeakReport {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        frees: FREES.load(Ordering::Relaxed),
+        bytes_allocated: BYTES_ALLOCATED.load(Ordering::Relaxed),
+        show_reachable,
+        ..LeakReport::default()
+    };
+    let mut indirect_of = vec![0; blocks.len()];
+    for (index, kind) in kinds.iter().enumerate() {
+        if let Status::Indirect(_) = kind {
+            indirect_of[leader(&kinds, index)] += blocks[index].1.size;
+        }
+    }
+    for (index, &(_, block)) in blocks.iter().enumerate() {
+        let kind = kinds[index].kind();
+        let record = records.entry((kind, block.allocator, block.site, &block.callers)).or_insert_with(|| LossRecord {
+            kind,
+            blocks: 0,
+            bytes: 0,
+            indirect_bytes: 0,
+            stack: name_stack(block, &sites, symbols),
+        });
+        record.blocks += 1;
+        record.bytes += block.size;
+        record.indirect_bytes += indirect_of[index];
+        report.in_use.add(block.size, 1);
+    }
+
+    let mut hits: Vec<usize> = vec![0; suppressions.len()];
+    let mut records: Vec<LossRecord> = records.into_values().collect();
+    records.sort_by_key(|record| (record.bytes + record.indirect_bytes, record.kind, record.blocks));
+    for record in records {
+        if let Some(index) = suppressions.iter().position(|suppression| suppression.matches(record.kind, &record.stack)) {
+            hits[index] += record.blocks;
+            report.suppressed.add(record.bytes, record.blocks);
+            continue;
+        }
+        match record.kind {
+            LeakKind::Definite => report.definitely_lost.add(record.bytes, record.blocks),
+            LeakKind::Indirect => report.indirectly_lost.add(record.bytes, record.blocks),
+            LeakKind::Possible => report.possibly_lost.add(record.bytes, record.blocks),
+            LeakKind::Reachable => report.still_reachable.add(record.bytes, record.blocks),
+        }
+        if record.kind != LeakKind::Reachable || show_reachable {
+            report.records.push(record);
+        }
+    }
+    report.suppressions_used = suppressions
+        .iter()
+        .zip(hits)
+        .filter(|(_, hits)| *hits > 0)
+        .map(|(suppression, hits)| (suppression.name.clone(), hits))
+        .collect();
+    report
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// Not reached from a root, and not yet claimed by a clique
+    Unreached,
+    /// Reached from a root through pointers to the start of every block
+    Reachable,
+    /// Reached from a root, but only through some interior pointer
+    Possible,
+    /// The first lost block of a clique: nothing points to it
+    Definite,
+    /// Lost, held by the clique of this block
+    Indirect(usize),
+}
+
+impl Status {
+    fn kind(&self) -> LeakKind {
+        match self {
+            Status::Reachable => LeakKind::Reachable,
+            Status::Possible => LeakKind::Possible,
+            Status::Indirect(_) => LeakKind::Indirect,
+            Status::Unreached | Status::Definite => LeakKind::Definite,
+        }
+    }
+}
+
+/// The definitely lost block whose clique `index` ended up in
+fn leader(kinds: &[Status], mut index: usize) -> usize {
+    while let Status::Indirect(holder) = kinds[index] {
+        index = holder;
+    }
+    index
+}
+
+/// Which live block `pointer` points into, and whether to its start.
+/// `blocks` is sorted by address.
+fn find(blocks: &[(usize, &Block)], pointer: usize) -> Option<(usize, bool)> {
+    let index = blocks.partition_point(|&(address, _)| address <= pointer).checked_sub(1)?;
+    let (address, block) = blocks[index];
+    if pointer == address {
+        return Some((index, true));
+    }
+    (pointer < address + block.size).then_some((index, false))
+}
+
+/// Every word-aligned pointer into a live block in `address..address + size`
+unsafe fn pointers_in(blocks: &[(usize, &Block)], address: usize, size: usize, mut found: impl FnMut(usize, bool)) {
+    let word = std::mem::size_of::<usize>();
+    let start = address.next_multiple_of(word);
+    let end = address.saturating_add(size);
+    let mut at = start;
+    while at.saturating_add(word) <= end {
+        let value = std::ptr::read_unaligned(at as *const usize);
+        if let Some((index, to_start)) = find(blocks, value) {
+            found(index, to_start);
+        }
+        at += word;
+    }
+}
+
+unsafe fn classify(blocks: &[(usize, &Block)], roots: &[(usize, usize)]) -> Vec<Status> {
+    let mut kinds = vec![Status::Unreached; blocks.len()];
+
+    // Reachability from the roots: a block is as well reached as the best
+    // chain to it, and a chain is only as good as its weakest pointer
+    let mut work: Vec<usize> = Vec::new();
+    let reach = |kinds: &mut Vec<Status>, work: &mut Vec<usize>, index: usize, strong: bool| {
+        let better = matches!((kinds[index], strong), (Status::Unreached, _) | (Status::Possible, true));
+        if better {
+            kinds[index] = if strong { Status::Reachable } else { Status::Possible };
+            work.push(index);
+        }
+    };
+    for &(address, size) in roots {
+        pointers_in(blocks, address, size, |index, to_start| reach(&mut kinds, &mut work, index, to_start));
+    }
+    while let Some(index) = work.pop() {
+        let strong = kinds[index] == Status::Reachable;
+        let (address, block) = blocks[index];
+        pointers_in(blocks, address, block.size, |child, to_start| {
+            if child != index {
+                reach(&mut kinds, &mut work, child, strong && to_start);
+            }
+        });
+    }
+
+    // What's left is lost. Each unclaimed block starts a clique of the
+    // blocks it leads to; a block that led a clique and is found from a
+    // later one joins it, so a cycle ends up with one definite leader.
+    for leader in 0..blocks.len() {
+        if kinds[leader] != Status::Unreached {
+            continue;
+        }
+        kinds[leader] = Status::Definite;
+        let mut work = vec![leader];
+        while let Some(index) = work.pop() {
+            let (address, block) = blocks[index];
+            pointers_in(blocks, address, block.size, |child, _| {
+                if child != leader && matches!(kinds[child], Status::Unreached | Status::Definite) {
+                    kinds[child] = Status::Indirect(leader);
+                    work.push(child);
+                }
+            });
+        }
+    }
+    kinds
+}
+
+/// The allocator, its caller at the site, then the compiled callers above
+fn name_stack(block: &Block, sites: &[Site], symbols: Option<&JitSymbols>) -> Vec<NativeFrame> {
+    let named = stack_capture::symbolize_frames(&block.callers, symbols);
+    let compiled = |frame: &NativeFrame| frame.object.as_deref() == Some("<jit>");
+    // The host's frames: the leak functions below, the runtime above main
+    let first = named.iter().position(compiled).unwrap_or(named.len());
+    let last = named.iter().rposition(compiled).map_or(first, |last| last + 1);
+    let mut frames: Vec<NativeFrame> = named[first..last.max(first)].iter().take(MAX_CALLERS).cloned().collect();
+
+    let mut stack = vec![NativeFrame { address: 0, symbol: Some(block.allocator.name().to_string()), offset: 0, object: None }];
+    if let Some(site) = sites.get(block.site as usize) {
+        // The site names the frame that called the allocator, with its line
+        match frames.first_mut() {
+            Some(frame) if frame.symbol.as_deref() == Some(site.function.as_str()) => {
+                frame.object = Some(site.location.clone());
+            }
+            _ => frames.insert(0, NativeFrame { address: 0, symbol: Some(site.function.clone()), offset: 0, object: Some(site.location.clone()) }),
+        }
+    }
+    stack.extend(frames);
+    stack
+}
+
+/// Valgrind's grouping of thousands with commas
+fn thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+impl fmt::Display for LeakReport {
+    /// Valgrind's text, without the `==pid==` prefix
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amount = |amount: &Amount| format!("{} bytes in {} blocks", thousands(amount.bytes as u64), thousands(amount.blocks as u64));
+        writeln!(f)?;
+        writeln!(f, "HEAP SUMMARY:")?;
+        writeln!(f, "    in use at exit: {}", amount(&self.in_use))?;
+        writeln!(
+            f,
+            "  total heap usage: {} allocs, {} frees, {} bytes allocated",
+            thousands(self.allocations),
+            thousands(self.frees),
+            thousands(self.bytes_allocated)
+        )?;
+        writeln!(f)?;
+
+        let total = self.records.len();
+        for (index, record) in self.records.iter().enumerate() {
+            let bytes = if record.indirect_bytes > 0 {
+                format!(
+                    "{} ({} direct, {} indirect) bytes",
+                    thousands((record.bytes + record.indirect_bytes) as u64),
+                    thousands(record.bytes as u64),
+                    thousands(record.indirect_bytes as u64)
+                )
+            } else {
+                format!("{} bytes", thousands(record.bytes as u64))
+            };
+            writeln!(
+                f,
+                "{} in {} blocks are {} in loss record {} of {}",
+                bytes,
+                thousands(record.blocks as u64),
+                record.kind.describe(),
+                index + 1,
+                total
+            )?;
+            for (depth, frame) in record.stack.iter().enumerate() {
+                write!(f, "   {} ", if depth == 0 { "at" } else { "by" })?;
+                if frame.address != 0 {
+                    write!(f, "0x{:X}: ", frame.address)?;
+                }
+                write!(f, "{}", frame.symbol.as_deref().unwrap_or("???"))?;
+                match &frame.object {
+                    Some(object) => writeln!(f, " ({})", object)?,
+                    None => writeln!(f)?,
+                }
+            }
+            writeln!(f)?;
+        }
+
+        if self.in_use.blocks == 0 {
+            writeln!(f, "All heap blocks were freed -- no leaks are possible")?;
+            return Ok(());
+        }
+        writeln!(f, "LEAK SUMMARY:")?;
+        writeln!(f, "   definitely lost: {}", amount(&self.definitely_lost))?;
+        writeln!(f, "   indirectly lost: {}", amount(&self.indirectly_lost))?;
+        writeln!(f, "     possibly lost: {}", amount(&self.possibly_lost))?;
+        writeln!(f, "   still reachable: {}", amount(&self.still_reachable))?;
+        writeln!(f, "        suppressed: {}", amount(&self.suppressed))?;
+        if self.still_reachable.blocks > 0 && !self.show_reachable {
+            writeln!(f, "Reachable blocks (those to which a pointer was found) are not shown.")?;
+        }
+        for (name, hits) in &self.suppressions_used {
+            writeln!(f, "used_suppression: {} {}", hits, name)?;
+        }
+        Ok(())
+    }
+}
+
+static AT_EXIT: Mutex<Option<Box<dyn FnOnce() + Send>>> = Mutex::new(None);
+
+/// Print the report to stderr when the program exits, or at
+/// `flush_at_exit`. Reads the suppression files now, so that a bad one
+/// is reported before the program runs.
+pub fn report_at_exit(config: &LeakConfig, symbols: std::sync::Arc<parking_lot::RwLock<JitSymbols>>) -> Result<(), LeakError> {
+    let mut suppressions = Vec::new();
+    for path in &config.suppressions {
+        suppressions.extend(Suppression::load(path)?);
+    }
+    let show_reachable = config.show_reachable;
+    *AT_EXIT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(move || {
+        let report = unsafe { report(Some(&symbols.read()), &suppressions, show_reachable) };
+        let prefix = format!("=={}==", std::process::id());
+        let mut stderr = std::io::stderr().lock();
+        for line in report.to_string().lines() {
+            let _ = writeln!(stderr, "{} {}", prefix, line);
+        }
+    }));
+    unsafe { libc::atexit(run_at_exit) };
+    Ok(())
+}
+
+/// Print the `report_at_exit` report now, if it hasn't been yet
+pub fn flush_at_exit() {
+    let report = AT_EXIT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    if let Some(report) = report {
+        report();
+    }
+}
+
+extern "C" fn run_at_exit() {
+    flush_at_exit();
+}
+
+/// One frame pattern of a suppression
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FramePattern {
+    Function(String),
+    Object(String),
+    /// `...`: any number of frames
+    Any,
+}
+
+/// A `Memcheck:Leak` entry of a suppression file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suppression {
+    pub name: String,
+    /// `match-leak-kinds`; empty for all
+    kinds: Vec<LeakKind>,
+    frames: Vec<FramePattern>,
+}
+
+impl Suppression {
+    pub fn load(path: &Path) -> Result<Vec<Self>, LeakError> {
+        let text = std::fs::read_to_string(path).map_err(|e| LeakError::Io(path.to_path_buf(), e))?;
+        Suppression::parse(&text).map_err(|(line, message)| LeakError::Suppressions { path: path.to_path_buf(), line, message })
+    }
+
+    /// The `Memcheck:Leak` entries of a suppression file; the others are
+    /// checked for form and skipped. Errors are `(line, message)`.
+    pub fn parse(text: &str) -> Result<Vec<Self>, (usize, String)> {
+        let mut suppressions = Vec::new();
+        let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line.trim())).filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        while let Some((number, line)) = lines.next() {
+            if line != "{" {
+                return Err((number, format!("expected '{{', found '{}'", line)));
+            }
+            let (_, name) = lines.next().ok_or((number, "missing suppression name".to_string()))?;
+            let (kind_line, kind) = lines.next().ok_or((number, "missing suppression kind".to_string()))?;
+            let mut suppression = Suppression { name: name.to_string(), kinds: Vec::new(), frames: Vec::new() };
+            let mut closed = false;
+            for (number, line) in lines.by_ref() {
+                if line == "}" {
+                    closed = true;
+                    break;
+                }
+                if let Some(kinds) = line.strip_prefix("match-leak-kinds:") {
+                    suppression.kinds = parse_kinds(kinds).map_err(|message| (number, message))?;
+                } else if let Some(function) = line.strip_prefix("fun:") {
+                    suppression.frames.push(FramePattern::Function(function.to_string()));
+                } else if let Some(object) = line.strip_prefix("obj:") {
+                    suppression.frames.push(FramePattern::Object(object.to_string()));
+                } else if line == "..." {
+                    suppression.frames.push(FramePattern::Any);
+                } else if !line.contains(':') {
+                    return Err((number, format!("expected a frame, found '{}'", line)));
+                }
+            }
+            if !closed {
+                return Err((number, format!("suppression '{}' isn't closed with '}}'", name)));
+            }
+            if !kind.contains(':') {
+                return Err((kind_line, format!("expected 'Tool:Kind', found '{}'", kind)));
+            }
+            if kind == "Memcheck:Leak" {
+                suppressions.push(suppression);
+            }
+        }
+        Ok(suppressions)
+    }
+
+    /// Whether a loss record of `kind` with `stack` is suppressed
+    pub fn matches(&self, kind: LeakKind, stack: &[NativeFrame]) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&kind)) && match_frames(&self.frames, stack)
+    }
+}
+
+fn parse_kinds(list: &str) -> Result<Vec<LeakKind>, String> {
+    let mut kinds = Vec::new();
+    for name in list.split(',').map(str::trim) {
+        match name {
+            "definite" => kinds.push(LeakKind::Definite),
+            "indirect" => kinds.push(LeakKind::Indirect),
+            "possible" => kinds.push(LeakKind::Possible),
+            "reachable" => kinds.push(LeakKind::Reachable),
+            "all" => return Ok(Vec::new()),
+            other => return Err(format!("unknown leak kind '{}'", other)),
+        }
+    }
+    Ok(kinds)
+}
+
+/// Whether `patterns` match the start of `stack`
+fn match_frames(patterns: &[FramePattern], stack: &[NativeFrame]) -> bool {
+    let Some((pattern, rest)) = patterns.split_first() else { return true };
+    match pattern {
+        FramePattern::Any => (0..=stack.len()).any(|skip| match_frames(rest, &stack[skip..])),
+        FramePattern::Function(pattern) => stack.first().is_some_and(|frame| {
+            glob(pattern, frame.symbol.as_deref().unwrap_or("???")) && match_frames(rest, &stack[1..])
+        }),
+        FramePattern::Object(pattern) => stack.first().is_some_and(|frame| {
+            glob(pattern, frame.object.as_deref().unwrap_or("???")) && match_frames(rest, &stack[1..])
+        }),
+    }
+}
+
+/// `*` and `?` wildcards, as Valgrind matches names
+fn glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and how much of the text it has taken
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[derive(Debug)]
+pub enum LeakError {
+    Io(PathBuf, std::io::Error),
+    /// A suppression file that isn't in Valgrind's format
+    Suppressions { path: PathBuf, line: usize, message: String },
+}
+
+impl fmt::Display for LeakError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeakError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            LeakError::Suppressions { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for LeakError {}
+
+// Example usage:
+/*
+// struct node *n = malloc(sizeof *n); n->next = malloc(16); n = NULL;
+fn main() -> Result<(), LeakError> {
+    let config = LeakConfig { suppressions: vec!["known.supp".into()], show_reachable: false };
+    let compiler = unsafe { Compiler::new() }?;
+    let mut options = Options::default().jit_options();
+    options.detect_leaks = true;
+    let main = unsafe { compiler.jit_compile(source, &options) }?;
+    leaks::report_at_exit(&config, compiler.jit_symbols())?;
+    run(main);
+    // ==4242== 32 (16 direct, 16 indirect) bytes in 1 blocks are definitely lost in loss record 2 of 2
+    // ==4242==    at malloc
+    // ==4242==    by make_list (list.c:12:21)
+    // ==4242==    by 0x7F3A10002A1B: main (<jit>)
+    leaks::flush_at_exit();
+    Ok(())
+}
+*/
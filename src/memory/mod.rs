@@ -0,0 +1,8 @@
+// src/memory/mod.rs
+//! Memory management shared by the runtime and the JIT
+
+pub mod code_cache;
+pub mod hugepages;
+pub mod leaks;
+pub mod management;
+pub mod numa;
@@ -0,0 +1,319 @@
+// src/memory/numa.rs
+//! NUMA-aware placement for JIT code and tenant heaps
+//! Memory is bound to the node the executing thread runs on (or a configured
+//! node) with mbind(2). On hosts without NUMA support every call degrades to
+//! a no-op so callers never need to special-case single-node machines.
+
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Placement policy for new allocations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// Leave placement to the kernel (first-touch)
+    Default,
+    /// Place on the node of the thread doing the allocation
+    Local,
+    /// Prefer a specific node, falling back to others under pressure
+    Preferred(usize),
+    /// Only allow a specific node
+    Bind(usize),
+    /// Spread pages round-robin over the given nodes
+    Interleave(Vec<usize>),
+}
+
+impl std::str::FromStr for NumaPolicy {
+    type Err = NumaError;
+
+    /// `default`, `local`, `preferred:N`, `bind:N` or `interleave:N,M,...`
+    fn from_str(text: &str) -> Result<Self, NumaError> {
+        let invalid = || NumaError::InvalidPolicy(text.to_string());
+        let node = |n: &str| n.trim().parse::<usize>().map_err(|_| invalid());
+        match text.split_once(':') {
+            None if text == "default" => Ok(NumaPolicy::Default),
+            None if text == "local" => Ok(NumaPolicy::Local),
+            Some(("preferred", n)) => Ok(NumaPolicy::Preferred(node(n)?)),
+            Some(("bind", n)) => Ok(NumaPolicy::Bind(node(n)?)),
+            Some(("interleave", list)) => {
+                let nodes = list.split(',').filter(|n| !n.trim().is_empty()).map(node).collect::<Result<Vec<_>, _>>()?;
+                if nodes.is_empty() {
+                    return Err(NumaError::EmptyInterleave);
+                }
+                Ok(NumaPolicy::Interleave(nodes))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Values from <linux/mempolicy.h>
+const MPOL_PREFERRED: i32 = 1;
+const MPOL_BIND: i32 = 2;
+const MPOL_INTERLEAVE: i32 = 3;
+
+/// Per-node usage counters
+#[derive(Debug, Clone)]
+pub struct NodeUsage {
+    pub node: usize,
+    pub code_bytes: usize,
+    pub heap_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PlacementKind {
+    Code,
+    Heap,
+}
+
+pub struct NumaTopology {
+    // Node id -> CPUs belonging to that node
+    nodes: Vec<(usize, Vec<usize>)>,
+}
+
+impl NumaTopology {
+    /// Read the topology from sysfs. Returns a single node on non-NUMA hosts.
+    pub fn detect() -> Self {
+        let mut nodes = Vec::new();
+
+        if let Ok(entries) = fs::read_dir("/sys/devices/system/node") {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let id = match name.strip_prefix("node").and_then(|n| n.parse::<usize>().ok()) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let cpus = fs::read_to_string(entry.path().join("cpulist"))
+                    .map(|list| parse_cpu_list(list.trim()))
+                    .unwrap_or_default();
+                nodes.push((id, cpus));
+            }
+        }
+
+        if nodes.is_empty() {
+            nodes.push((0, Vec::new()));
+        }
+        nodes.sort_by_key(|(id, _)| *id);
+
+        NumaTopology { nodes }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_numa(&self) -> bool {
+        self.nodes.len() > 1
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes.iter().map(|(id, _)| *id)
+    }
+
+    /// Node that owns the given CPU
+    pub fn node_of_cpu(&self, cpu: usize) -> Option<usize> {
+        self.nodes
+            .iter()
+            .find(|(_, cpus)| cpus.contains(&cpu))
+            .map(|(id, _)| *id)
+    }
+
+    /// Node of the CPU the calling thread is currently running on
+    pub fn current_node(&self) -> Option<usize> {
+        let cpu = unsafe { libc::sched_getcpu() };
+        if cpu < 0 {
+            return None;
+        }
+        self.node_of_cpu(cpu as usize)
+    }
+}
+
+pub struct NumaAllocator {
+    // Host topology
+    topology: NumaTopology,
+
+    // Active policy
+    policy: NumaPolicy,
+
+    // Usage metrics, indexed by node id
+    code_usage: Vec<AtomicUsize>,
+    heap_usage: Vec<AtomicUsize>,
+}
+
+impl NumaAllocator {
+    pub fn new(policy: NumaPolicy) -> Result<Self, NumaError> {
+        let topology = NumaTopology::detect();
+        let slots = topology.node_ids().max().unwrap_or(0) + 1;
+
+        match &policy {
+            NumaPolicy::Preferred(node) | NumaPolicy::Bind(node) => {
+                if !topology.node_ids().any(|id| id == *node) {
+                    return Err(NumaError::UnknownNode(*node));
+                }
+            }
+            NumaPolicy::Interleave(nodes) => {
+                if nodes.is_empty() {
+                    return Err(NumaError::EmptyInterleave);
+                }
+                if let Some(bad) = nodes.iter().find(|n| !topology.node_ids().any(|id| id == **n)) {
+                    return Err(NumaError::UnknownNode(*bad));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(NumaAllocator {
+            topology,
+            policy,
+            code_usage: (0..slots).map(|_| AtomicUsize::new(0)).collect(),
+            heap_usage: (0..slots).map(|_| AtomicUsize::new(0)).collect(),
+        })
+    }
+
+    pub fn policy(&self) -> &NumaPolicy {
+        &self.policy
+    }
+
+    /// Apply the placement policy to a freshly mapped, not yet touched
+    /// region; returns the node it is accounted to, for `release`
+    pub unsafe fn place(
+        &self,
+        ptr: *mut u8,
+        size: usize,
+        kind: PlacementKind
+    ) -> Result<usize, NumaError> {
+        // Nothing to decide on single-node machines
+        if !self.topology.is_numa() {
+            self.record(0, size, kind);
+            return Ok(0);
+        }
+
+        let (mode, nodes, accounted_node) = match &self.policy {
+            NumaPolicy::Default => {
+                let node = self.topology.current_node().unwrap_or(0);
+                self.record(node, size, kind);
+                return Ok(node);
+            }
+            NumaPolicy::Local => {
+                let node = self.topology.current_node().unwrap_or(0);
+                (MPOL_BIND, vec![node], node)
+            }
+            NumaPolicy::Preferred(node) => (MPOL_PREFERRED, vec![*node], *node),
+            NumaPolicy::Bind(node) => (MPOL_BIND, vec![*node], *node),
+            NumaPolicy::Interleave(nodes) => (MPOL_INTERLEAVE, nodes.clone(), nodes[0]),
+        };
+
+        let mask = node_mask(&nodes);
+        let result = libc::syscall(
+            libc::SYS_mbind,
+            ptr as *mut libc::c_void,
+            size,
+            mode,
+            mask.as_ptr(),
+            // The kernel reads one bit fewer than maxnode, as libnuma allows for
+            (mask.len() * 64 + 1) as libc::c_ulong,
+            0u32,
+        );
+
+        if result != 0 {
+            return Err(NumaError::BindFailed(std::io::Error::last_os_error()));
+        }
+
+        self.record(accounted_node, size, kind);
+        Ok(accounted_node)
+    }
+
+    /// Account for memory `place`d on `node` being returned to the system
+    pub fn release(&self, node: usize, size: usize, kind: PlacementKind) {
+        let counters = match kind {
+            PlacementKind::Code => &self.code_usage,
+            PlacementKind::Heap => &self.heap_usage,
+        };
+        if let Some(counter) = counters.get(node) {
+            let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| Some(used.saturating_sub(size)));
+        }
+    }
+
+    /// Current per-node usage
+    pub fn usage(&self) -> Vec<NodeUsage> {
+        self.topology
+            .node_ids()
+            .map(|node| NodeUsage {
+                node,
+                code_bytes: self.code_usage[node].load(Ordering::Relaxed),
+                heap_bytes: self.heap_usage[node].load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    fn record(&self, node: usize, size: usize, kind: PlacementKind) {
+        let counters = match kind {
+            PlacementKind::Code => &self.code_usage,
+            PlacementKind::Heap => &self.heap_usage,
+        };
+        if let Some(counter) = counters.get(node) {
+            counter.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Parse a sysfs cpulist such as "0-3,8,10-11"
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for part in list.split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                if let (Ok(lo), Ok(hi)) = (lo.parse::<usize>(), hi.parse::<usize>()) {
+                    cpus.extend(lo..=hi);
+                }
+            }
+            None => {
+                if let Ok(cpu) = part.parse() {
+                    cpus.push(cpu);
+                }
+            }
+        }
+    }
+    cpus
+}
+
+fn node_mask(nodes: &[usize]) -> Vec<libc::c_ulong> {
+    let words = nodes.iter().max().map(|n| n / 64 + 1).unwrap_or(1);
+    let mut mask = vec![0 as libc::c_ulong; words];
+    for &node in nodes {
+        mask[node / 64] |= 1 << (node % 64);
+    }
+    mask
+}
+
+#[derive(Debug)]
+pub enum NumaError {
+    UnknownNode(usize),
+    /// `Interleave` over no nodes at all
+    EmptyInterleave,
+    /// A policy string `NumaPolicy::from_str` doesn't understand
+    InvalidPolicy(String),
+    BindFailed(std::io::Error),
+}
+
+// Example usage:
+/*
+unsafe fn example() -> Result<(), NumaError> {
+    // Pin JIT code to the node the worker threads run on
+    let numa = NumaAllocator::new(NumaPolicy::Local)?;
+
+    let code = mmap_anonymous(1 << 20);
+    let node = numa.place(code, 1 << 20, PlacementKind::Code)?;
+
+    // Spreading over no nodes is refused up front
+    assert!(matches!("interleave:".parse::<NumaPolicy>(), Err(NumaError::EmptyInterleave)));
+
+    for usage in numa.usage() {
+        println!("node {}: code={} heap={}", usage.node, usage.code_bytes, usage.heap_bytes);
+    }
+
+    munmap(code, 1 << 20);
+    numa.release(node, 1 << 20, PlacementKind::Code);
+
+    Ok(())
+}
+*/
@@ -0,0 +1,118 @@
+// src/metrics/loop_metrics.rs
+//! Loop nest metrics
+//! The shape of a module's loops before and after `optimizer::loops`
+//! tiled, interchanged and fused them, as gauges labelled with the
+//! translation unit and the stage, and counters of what changed. A loop
+//! that was fused away shows up as one loop fewer; an interchange as
+//! strided accesses that became contiguous; a tiled nest as one more loop
+//! per loop it had.
+
+use std::fmt;
+use metrics::{counter, gauge};
+use serde::Serialize;
+
+/// The loops of a function or module at one point in the pipeline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LoopNestMetrics {
+    /// Natural loops
+    pub loops: usize,
+    /// Outermost loops with another loop inside
+    pub nests: usize,
+    pub max_depth: usize,
+    /// Pairs of sibling loops with nothing but branches between them, the
+    /// candidates for fusion
+    pub adjacent: usize,
+    /// Loads and stores in innermost loops that step along the last,
+    /// contiguous index of a row-major array
+    pub contiguous: usize,
+    /// Loads and stores in innermost loops that step along an earlier
+    /// index, a row or more apart each iteration
+    pub strided: usize,
+}
+
+impl LoopNestMetrics {
+    /// Add the loops of another function
+    pub fn combine(&mut self, other: &LoopNestMetrics) {
+        self.loops += other.loops;
+        self.nests += other.nests;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.adjacent += other.adjacent;
+        self.contiguous += other.contiguous;
+        self.strided += other.strided;
+    }
+
+    fn export(&self, unit: &str, stage: &'static str) {
+        let values = [
+            ("optimizer.loops.count", self.loops),
+            ("optimizer.loops.nests", self.nests),
+            ("optimizer.loops.max_depth", self.max_depth),
+            ("optimizer.loops.adjacent", self.adjacent),
+            ("optimizer.loops.contiguous_accesses", self.contiguous),
+            ("optimizer.loops.strided_accesses", self.strided),
+        ];
+        for (name, value) in values {
+            gauge!(name, "unit" => unit.to_string(), "stage" => stage).set(value as f64);
+        }
+    }
+}
+
+impl fmt::Display for LoopNestMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} loop(s), {} nest(s), depth {}, {} adjacent pair(s), {} contiguous / {} strided access(es)",
+            self.loops, self.nests, self.max_depth, self.adjacent, self.contiguous, self.strided
+        )
+    }
+}
+
+/// A module's loops around the tiling, interchange and fusion passes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LoopTransformMetrics {
+    pub before: LoopNestMetrics,
+    pub after: LoopNestMetrics,
+    /// Nests tiled
+    pub tiled: usize,
+    /// Loops tiling added around them
+    pub tile_loops: usize,
+}
+
+impl LoopTransformMetrics {
+    /// Loops that were merged into the loop before them
+    pub fn fused(&self) -> usize {
+        (self.before.loops + self.tile_loops).saturating_sub(self.after.loops)
+    }
+
+    /// Strided accesses that an interchange made contiguous
+    pub fn made_contiguous(&self) -> usize {
+        self.before.strided.saturating_sub(self.after.strided)
+    }
+
+    /// Record both stages for translation unit `unit`
+    pub fn export(&self, unit: &str) {
+        self.before.export(unit, "before");
+        self.after.export(unit, "after");
+        counter!("optimizer.loops.fused", "unit" => unit.to_string()).increment(self.fused() as u64);
+        counter!("optimizer.loops.made_contiguous", "unit" => unit.to_string()).increment(self.made_contiguous() as u64);
+        counter!("optimizer.loops.tiled", "unit" => unit.to_string()).increment(self.tiled as u64);
+    }
+}
+
+impl fmt::Display for LoopTransformMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "before: {}", self.before)?;
+        write!(f, "after:  {}", self.after)?;
+        if self.tiled > 0 {
+            write!(f, "\ntiled:  {} nest(s), {} tile loop(s)", self.tiled, self.tile_loops)?;
+        }
+        Ok(())
+    }
+}
+
+// Example usage:
+/*
+fn report(metrics: &LoopTransformMetrics) {
+    metrics.export("matmul.c");
+    log::debug!("{} loop(s) fused, {} access(es) made contiguous\n{}", metrics.fused(), metrics.made_contiguous(), metrics);
+}
+*/
@@ -0,0 +1,7 @@
+// src/metrics/mod.rs
+//! Metrics
+//! Numbers the compiler reports through the `metrics` facade, for whatever
+//! recorder the embedder installed (Prometheus, statsd, a log).
+
+pub mod loop_metrics;
+pub mod preprocessor_metrics;
@@ -69,9 +69,11 @@ impl PreprocessorMetrics {
         self.optimize_cache_size().await?;
         
         // Report optimizations
-        println!("Scanning optimizations applied:");
-        println!("  New batch size: {}", self.batch_size);
-        println!("  Cache size: {} MB", self.get_cache_size_mb());
+        log::debug!(
+            "Scanning optimizations applied: batch size {}, cache size {} MB",
+            self.batch_size,
+            self.get_cache_size_mb()
+        );
         
         Ok(())
     }
@@ -25,7 +25,7 @@ pub struct IntegratedMonitoringSystem {
 
 impl IntegratedMonitoringSystem {
     pub async fn start_monitoring(&mut self) -> Result<(), MonitorError> {
-        println!("Starting integrated monitoring system...");
+        log::info!("Starting integrated monitoring system...");
         
         // Initialize channels
         let (tx, rx) = broadcast::channel::<MetricEvent>(10000);
@@ -20,7 +20,7 @@ pub struct RealTimeMonitor {
 
 impl RealTimeMonitor {
     pub async fn start_monitoring(&mut self) -> Result<(), MonitorError> {
-        println!("Starting real-time monitoring...");
+        log::info!("Starting real-time monitoring...");
         terminal::enable_raw_mode()?;
         
         loop {
@@ -165,7 +165,7 @@ pub async fn start_monitoring() -> Result<(), MonitorError> {
     // Start monitoring in background task
     tokio::spawn(async move {
         if let Err(e) = monitor.start_monitoring().await {
-            eprintln!("Monitoring error: {}", e);
+            log::error!("Monitoring error: {}", e);
         }
     });
     
@@ -0,0 +1,301 @@
+// src/optimizer/budget.rs
+//! Per-function optimization budgets
+//! A single pathological function (typically machine-generated: a parser
+//! table, an unrolled state machine, an interpreter loop with thousands of
+//! cases) can keep the superlinear passes busy for minutes or exhaust
+//! memory, stalling the whole translation unit.
+//!
+//! Before the module pipeline runs, every function of at least
+//! `min_instructions` instructions is optimized once on its own in a forked
+//! child, on a copy of the module where every other function except its
+//! direct callees is `optnone`. The child runs under the memory budget and
+//! is killed when the time budget runs out. A function whose trial doesn't
+//! finish is marked `optnone noinline` in the real module, so the pipeline
+//! leaves it as generated and the backend emits it with -O0 codegen, and an
+//! optimization remark says so. Functions that fit are optimized by the
+//! pipeline as usual, so with a budget they cost about twice their
+//! optimization time.
+
+#[cfg(feature = "llvm")]
+use std::collections::HashSet;
+#[cfg(feature = "llvm")]
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+#[cfg(feature = "llvm")]
+use std::io::Write;
+use std::time::Duration;
+#[cfg(feature = "llvm")]
+use std::time::Instant;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::target_machine::LLVMTargetMachineRef;
+#[cfg(feature = "llvm")]
+use llvm_sys::transforms::pass_builder::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMOpcode};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "llvm")]
+use crate::report::{OptimizationRemark, RemarkKind};
+#[cfg(feature = "llvm")]
+use crate::runtime::exit_status::signal_name;
+#[cfg(feature = "llvm")]
+use super::fenv::{attribute_kind, instructions};
+
+/// Pass name the remarks are filed under
+pub const REMARK_PASS: &str = "function-budget";
+
+// How often the parent checks on a trial
+#[cfg(feature = "llvm")]
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How much one function's optimization may take
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionBudget {
+    /// Wall-clock time for the function's trial
+    pub time: Duration,
+    /// Bytes the optimizer may allocate on top of what the compiler already
+    /// uses; `None` leaves memory unbounded
+    pub memory: Option<usize>,
+    /// Smaller functions are trusted to optimize quickly and skip the trial
+    pub min_instructions: usize,
+}
+
+impl Default for FunctionBudget {
+    fn default() -> Self {
+        FunctionBudget {
+            time: Duration::from_secs(10),
+            memory: None,
+            min_instructions: 2_000,
+        }
+    }
+}
+
+/// How a function's trial ended
+#[cfg(feature = "llvm")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trial {
+    Finished,
+    OutOfTime,
+    /// Died on its own: out of memory when there is a memory budget,
+    /// otherwise an optimizer crash
+    Died(Option<i32>),
+}
+
+/// Trial-optimize the large functions of `module` at `level` within
+/// `budget`, and fall back to -O0 for those that don't fit. Returns a
+/// remark for each function that fell back.
+#[cfg(feature = "llvm")]
+pub unsafe fn guard_functions(
+    module: LLVMModuleRef,
+    level: u32,
+    target_machine: LLVMTargetMachineRef,
+    budget: &FunctionBudget,
+) -> Result<Vec<OptimizationRemark>, BudgetError> {
+    let pipeline = CString::new(format!("default<O{}>", level.clamp(1, 3))).unwrap();
+    let mut remarks = Vec::new();
+
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        let next = LLVMGetNextFunction(function);
+        let size = instructions(function).len();
+        if LLVMIsDeclaration(function) != 0 || size < budget.min_instructions || has_attribute(function, "optnone") {
+            function = next;
+            continue;
+        }
+
+        let name = function_name(function);
+        let trial = {
+            let copy = trial_module(module, &name);
+            let trial = run_trial(copy, &pipeline, target_machine, budget);
+            LLVMDisposeModule(copy);
+            trial?
+        };
+
+        let message = match trial {
+            Trial::Finished => None,
+            Trial::OutOfTime => Some(format!(
+                "optimizing '{}' ({} instructions) took longer than {:.1}s",
+                name,
+                size,
+                budget.time.as_secs_f64()
+            )),
+            Trial::Died(_) if budget.memory.is_some() => Some(format!(
+                "optimizing '{}' ({} instructions) exceeded its {} MiB memory budget",
+                name,
+                size,
+                budget.memory.unwrap_or(0) >> 20
+            )),
+            Trial::Died(signal) => Some(format!(
+                "the optimizer failed on '{}' ({})",
+                name,
+                signal.map_or("exited with an error".to_string(), |s| format!("signal {}", signal_name(s)))
+            )),
+        };
+        if let Some(message) = message {
+            skip_optimization(function);
+            remarks.push(OptimizationRemark {
+                pass: REMARK_PASS.to_string(),
+                function: name,
+                kind: RemarkKind::Missed,
+                message: format!("{}; compiled at -O0", message),
+            });
+        }
+        function = next;
+    }
+    Ok(remarks)
+}
+
+/// A copy of `module` in which only `name` and the functions it calls
+/// directly are optimized, so inlining into it is part of the trial
+#[cfg(feature = "llvm")]
+unsafe fn trial_module(module: LLVMModuleRef, name: &str) -> LLVMModuleRef {
+    let copy = LLVMCloneModule(module);
+    let c_name = CString::new(name).unwrap();
+    let target = LLVMGetNamedFunction(copy, c_name.as_ptr());
+
+    let mut optimized = HashSet::new();
+    optimized.insert(name.to_string());
+    for inst in instructions(target) {
+        if LLVMGetInstructionOpcode(inst) == LLVMOpcode::LLVMCall {
+            let callee = LLVMGetCalledValue(inst);
+            if !callee.is_null() && !LLVMIsAFunction(callee).is_null() {
+                optimized.insert(function_name(callee));
+            }
+        }
+    }
+
+    let mut function = LLVMGetFirstFunction(copy);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 && !optimized.contains(&function_name(function)) {
+            skip_optimization(function);
+        }
+        function = LLVMGetNextFunction(function);
+    }
+    copy
+}
+
+/// Optimize `module` in a child process under `budget`
+#[cfg(feature = "llvm")]
+unsafe fn run_trial(
+    module: LLVMModuleRef,
+    pipeline: &CStr,
+    target_machine: LLVMTargetMachineRef,
+    budget: &FunctionBudget,
+) -> Result<Trial, BudgetError> {
+    // Anything buffered now would otherwise be written twice
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    let address_space = budget.memory.map(|bytes| address_space_size().saturating_add(bytes));
+
+    let pid = libc::fork();
+    if pid < 0 {
+        return Err(BudgetError::Io(io::Error::last_os_error()));
+    }
+    if pid == 0 {
+        if let Some(bytes) = address_space {
+            let limit = libc::rlimit { rlim_cur: bytes as libc::rlim_t, rlim_max: bytes as libc::rlim_t };
+            libc::setrlimit(libc::RLIMIT_AS, &limit);
+        }
+        let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        libc::setrlimit(libc::RLIMIT_CORE, &limit);
+
+        let options = LLVMCreatePassBuilderOptions();
+        let error = LLVMRunPasses(module, pipeline.as_ptr(), target_machine, options);
+        libc::_exit(if error.is_null() { 0 } else { 1 });
+    }
+
+    let deadline = Instant::now() + budget.time;
+    loop {
+        let mut status = 0;
+        let waited = libc::waitpid(pid, &mut status, libc::WNOHANG);
+        if waited == pid {
+            return Ok(if libc::WIFSIGNALED(status) {
+                Trial::Died(Some(libc::WTERMSIG(status)))
+            } else if libc::WEXITSTATUS(status) != 0 {
+                Trial::Died(None)
+            } else {
+                Trial::Finished
+            });
+        }
+        if waited < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(BudgetError::Io(err));
+            }
+        }
+        if Instant::now() >= deadline {
+            libc::kill(pid, libc::SIGKILL);
+            while libc::waitpid(pid, &mut status, 0) < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {}
+            return Ok(Trial::OutOfTime);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Keep the optimizer, inliner and backend from touching `function`
+#[cfg(feature = "llvm")]
+unsafe fn skip_optimization(function: LLVMValueRef) {
+    let context = LLVMGetModuleContext(LLVMGetGlobalParent(function));
+    // optnone requires noinline, which conflicts with alwaysinline
+    LLVMRemoveEnumAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute_kind("alwaysinline"));
+    for attribute in ["noinline", "optnone"] {
+        LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, LLVMCreateEnumAttribute(context, attribute_kind(attribute), 0));
+    }
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn has_attribute(function: LLVMValueRef, name: &str) -> bool {
+    !LLVMGetEnumAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute_kind(name)).is_null()
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn function_name(function: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(function, &mut len);
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len)).into_owned()
+}
+
+/// Current size of our address space, which RLIMIT_AS counts from
+#[cfg(feature = "llvm")]
+fn address_space_size() -> usize {
+    let pages = std::fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().next()?.parse::<usize>().ok())
+        .unwrap_or(0);
+    pages * unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize
+}
+
+#[derive(Debug)]
+pub enum BudgetError {
+    /// Forking or waiting for a trial failed
+    Io(io::Error),
+}
+
+impl fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetError::Io(e) => write!(f, "optimization trial: {}", e),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn optimize(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) -> Result<(), BudgetError> {
+    let budget = FunctionBudget {
+        time: Duration::from_secs(5),
+        memory: Some(1 << 30),
+        ..FunctionBudget::default()
+    };
+    for remark in guard_functions(module, 2, target_machine, &budget)? {
+        eprintln!("remark: {}: {}", remark.function, remark.message);
+    }
+    // Functions that didn't fit are optnone now; the pipeline skips them
+    run_default_pipeline(module, 2);
+    Ok(())
+}
+*/
@@ -0,0 +1,319 @@
+// src/optimizer/escape.rs
+//! Heap-to-stack promotion (-O2)
+//! A `malloc` or `calloc` of a small constant size whose pointer never
+//! leaves the function that makes it is turned into a stack slot, and the
+//! `free`s of it are deleted. LLVM's own pipeline only deletes allocations
+//! nothing reads; this pass moves the ones that are used, which takes the
+//! allocator off paths like `buf = malloc(64); fill(buf); use(buf);
+//! free(buf)` once `fill` and `use` are inlined.
+//!
+//! Escape analysis follows every use of the pointer and of the GEPs and
+//! casts derived from it. Loads, stores to it, comparisons, `free` of the
+//! pointer itself and the `memset`, `memcpy`, `memmove` and lifetime
+//! intrinsics keep it in the function; storing it anywhere, returning it,
+//! passing it to any other call, turning it into an integer or merging it
+//! with another pointer at a phi or select let it escape, and the
+//! allocation stays on the heap with a missed remark saying why.
+//!
+//! Only allocations outside loops qualify, since a slot in the frame is one
+//! object while a loop would allocate a new one every iteration, and only
+//! up to `MAX_PROMOTED` bytes each and `MAX_PROMOTED_FRAME` per function,
+//! so recursion doesn't turn into stack overflow. The slot is 16-byte
+//! aligned like malloc's memory; for `calloc` it is zeroed where the call
+//! was. An object that is never read is removed entirely, by the cleanup
+//! that runs after the rewrite (SROA deletes write-only slots).
+//!
+//! The pass runs after the module pipeline, once inlining has exposed the
+//! uses; it skips `optnone` functions.
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use llvm_sys::core::*;
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+use llvm_sys::prelude::*;
+use llvm_sys::target_machine::LLVMTargetMachineRef;
+use llvm_sys::transforms::pass_builder::*;
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMOpcode};
+use crate::analysis::stack_depth::{called_function, for_each_call, value_name};
+use crate::report::{OptimizationRemark, RemarkKind};
+use super::fenv::attribute_kind;
+use super::loops::find_loops;
+
+/// Pass name the remarks are filed under
+pub const REMARK_PASS: &str = "heap-to-stack";
+
+/// Largest allocation moved to the stack, in bytes
+pub const MAX_PROMOTED: u64 = 1024;
+/// Stack the promoted allocations of one function may take
+pub const MAX_PROMOTED_FRAME: u64 = 4096;
+
+/// Folds the slots into registers and deletes the ones nothing reads
+const CLEANUP: &str = "function(sroa,early-cse,instcombine,simplifycfg)";
+
+/// An allocation that doesn't escape
+struct Candidate {
+    call: LLVMValueRef,
+    size: u64,
+    zeroed: bool,
+    frees: Vec<LLVMValueRef>,
+    /// Intrinsic calls on the pointer, whose `tail` marker must go
+    intrinsics: Vec<LLVMValueRef>,
+    read: bool,
+}
+
+pub struct HeapToStackPass {
+    target_machine: LLVMTargetMachineRef,
+    remarks: Vec<OptimizationRemark>,
+    // Statistics
+    promoted: usize,
+    removed: usize,
+    frees_removed: usize,
+    rejected: usize,
+}
+
+impl HeapToStackPass {
+    pub fn new(target_machine: LLVMTargetMachineRef) -> Self {
+        HeapToStackPass {
+            target_machine,
+            remarks: Vec::new(),
+            promoted: 0,
+            removed: 0,
+            frees_removed: 0,
+            rejected: 0,
+        }
+    }
+
+    /// Promote the allocations of `module` that don't escape, and return
+    /// how many were
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> Result<usize, EscapeError> {
+        let before = self.promoted + self.removed;
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            let optnone = !LLVMGetEnumAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute_kind("optnone")).is_null();
+            if LLVMIsDeclaration(function) == 0 && !optnone {
+                self.promote_function(function);
+            }
+            function = LLVMGetNextFunction(function);
+        }
+        let changed = self.promoted + self.removed - before;
+        if changed > 0 {
+            run_passes(module, CLEANUP, self.target_machine)?;
+        }
+        Ok(changed)
+    }
+
+    unsafe fn promote_function(&mut self, function: LLVMValueRef) {
+        let name = value_name(function);
+        let mut calls = Vec::new();
+        for_each_call(function, |call| {
+            let callee = called_function(call);
+            if LLVMIsACallInst(call).is_null() || callee.is_null() || LLVMIsDeclaration(callee) == 0 {
+                return;
+            }
+            match (value_name(callee).as_str(), LLVMGetNumArgOperands(call)) {
+                ("malloc", 1) => calls.push((call, false)),
+                ("calloc", 2) => calls.push((call, true)),
+                _ => {}
+            }
+        });
+        if calls.is_empty() {
+            return;
+        }
+        let forest = find_loops(function);
+        let in_loop: Vec<LLVMBasicBlockRef> =
+            forest.loops.iter().flat_map(|l| l.body.iter().map(|&block| forest.blocks[block])).collect();
+
+        let mut frame = 0;
+        for (call, zeroed) in calls {
+            let what = if zeroed { "calloc" } else { "malloc" };
+            let candidate = match self.candidate(call, zeroed, &in_loop) {
+                Ok(candidate) if frame + candidate.size > MAX_PROMOTED_FRAME => Err(format!(
+                    "the function's promoted allocations would take more than {} bytes of stack",
+                    MAX_PROMOTED_FRAME
+                )),
+                candidate => candidate,
+            };
+            match candidate {
+                Ok(candidate) => {
+                    frame += candidate.size;
+                    let message = if candidate.read {
+                        self.promoted += 1;
+                        format!("moved a {}-byte {} to the stack", candidate.size, what)
+                    } else {
+                        self.removed += 1;
+                        format!("removed a {}-byte {} that is never read", candidate.size, what)
+                    };
+                    self.frees_removed += candidate.frees.len();
+                    promote(function, candidate);
+                    self.remark(&name, RemarkKind::Applied, message);
+                }
+                Err(reason) => {
+                    self.rejected += 1;
+                    self.remark(&name, RemarkKind::Missed, format!("{} not moved to the stack: {}", what, reason));
+                }
+            }
+        }
+    }
+
+    /// `call` as a candidate, or why it isn't one
+    unsafe fn candidate(&self, call: LLVMValueRef, zeroed: bool, in_loop: &[LLVMBasicBlockRef]) -> Result<Candidate, String> {
+        let mut size = 1u64;
+        for index in 0..LLVMGetNumArgOperands(call) {
+            let argument = LLVMGetOperand(call, index);
+            if LLVMIsAConstantInt(argument).is_null() {
+                return Err("its size is not a constant".to_string());
+            }
+            size = size.saturating_mul(LLVMConstIntGetZExtValue(argument));
+        }
+        if size == 0 || size > MAX_PROMOTED {
+            return Err(format!("{} bytes is outside 1..={}", size, MAX_PROMOTED));
+        }
+        if in_loop.contains(&LLVMGetInstructionParent(call)) {
+            return Err("it is allocated inside a loop".to_string());
+        }
+
+        let mut candidate = Candidate { call, size, zeroed, frees: Vec::new(), intrinsics: Vec::new(), read: false };
+        let mut pointers = vec![call];
+        let mut seen = vec![call];
+        while let Some(pointer) = pointers.pop() {
+            let mut use_ = LLVMGetFirstUse(pointer);
+            while !use_.is_null() {
+                let user = LLVMGetUser(use_);
+                use_ = LLVMGetNextUse(use_);
+                match LLVMGetInstructionOpcode(user) {
+                    LLVMOpcode::LLVMLoad => candidate.read = true,
+                    LLVMOpcode::LLVMStore if LLVMGetOperand(user, 0) == pointer => {
+                        return Err("its pointer is stored to memory".to_string());
+                    }
+                    LLVMOpcode::LLVMStore | LLVMOpcode::LLVMICmp => {}
+                    LLVMOpcode::LLVMGetElementPtr | LLVMOpcode::LLVMBitCast | LLVMOpcode::LLVMAddrSpaceCast => {
+                        if !seen.contains(&user) {
+                            seen.push(user);
+                            pointers.push(user);
+                        }
+                    }
+                    LLVMOpcode::LLVMCall => {
+                        let callee = called_function(user);
+                        let name = if callee.is_null() { String::new() } else { value_name(callee) };
+                        let operand = (0..LLVMGetNumArgOperands(user)).find(|&index| LLVMGetOperand(user, index) == pointer);
+                        match (name.as_str(), operand) {
+                            ("free", Some(0)) if pointer == call => candidate.frees.push(user),
+                            ("free", _) => return Err("it is freed through a pointer into it".to_string()),
+                            (name, Some(0)) if name.starts_with("llvm.memset.") => candidate.intrinsics.push(user),
+                            (name, Some(index)) if name.starts_with("llvm.memcpy.") || name.starts_with("llvm.memmove.") => {
+                                // Copying out of it reads it; into it, only writes
+                                candidate.read |= index == 1;
+                                candidate.intrinsics.push(user);
+                            }
+                            (name, _) if name.starts_with("llvm.lifetime.") => candidate.intrinsics.push(user),
+                            ("", _) => return Err("its pointer is passed to an indirect call".to_string()),
+                            (name, _) => return Err(format!("its pointer is passed to {}", name)),
+                        }
+                    }
+                    LLVMOpcode::LLVMRet => return Err("its pointer is returned".to_string()),
+                    LLVMOpcode::LLVMPtrToInt => return Err("its pointer is converted to an integer".to_string()),
+                    LLVMOpcode::LLVMPHI | LLVMOpcode::LLVMSelect => {
+                        return Err("its pointer is merged with another at a phi or select".to_string());
+                    }
+                    _ => return Err("its pointer is used by an instruction the analysis doesn't follow".to_string()),
+                }
+            }
+        }
+        Ok(candidate)
+    }
+
+    fn remark(&mut self, function: &str, kind: RemarkKind, message: String) {
+        self.remarks.push(OptimizationRemark {
+            pass: REMARK_PASS.to_string(),
+            function: function.to_string(),
+            kind,
+            message,
+        });
+    }
+
+    pub fn remarks(&self) -> &[OptimizationRemark] {
+        &self.remarks
+    }
+
+    /// (allocations moved to the stack, allocations removed, frees removed,
+    /// allocations left on the heap)
+    pub fn stats(&self) -> (usize, usize, usize, usize) {
+        (self.promoted, self.removed, self.frees_removed, self.rejected)
+    }
+}
+
+/// Replace `candidate`'s allocation with a slot in `function`'s frame
+unsafe fn promote(function: LLVMValueRef, candidate: Candidate) {
+    let context = LLVMGetTypeContext(LLVMTypeOf(function));
+    let builder = LLVMCreateBuilderInContext(context);
+
+    // With the other slots at the top of the entry block
+    let mut first = LLVMGetFirstInstruction(LLVMGetEntryBasicBlock(function));
+    while !LLVMIsAAllocaInst(first).is_null() {
+        first = LLVMGetNextInstruction(first);
+    }
+    LLVMPositionBuilderBefore(builder, first);
+    let ty = LLVMArrayType2(LLVMInt8TypeInContext(context), candidate.size);
+    let slot = LLVMBuildAlloca(builder, ty, c"promoted".as_ptr());
+    LLVMSetAlignment(slot, 16);
+
+    if candidate.zeroed {
+        LLVMPositionBuilderBefore(builder, candidate.call);
+        let zero = LLVMConstInt(LLVMInt8TypeInContext(context), 0, 0);
+        let length = LLVMConstInt(LLVMInt64TypeInContext(context), candidate.size, 0);
+        LLVMBuildMemSet(builder, slot, zero, length, 16);
+    }
+    LLVMDisposeBuilder(builder);
+
+    // A tail call may not touch the caller's stack
+    for intrinsic in &candidate.intrinsics {
+        LLVMSetTailCall(*intrinsic, 0);
+    }
+    for free in &candidate.frees {
+        LLVMInstructionEraseFromParent(*free);
+    }
+    LLVMReplaceAllUsesWith(candidate.call, slot);
+    LLVMInstructionEraseFromParent(candidate.call);
+}
+
+unsafe fn run_passes(module: LLVMModuleRef, pipeline: &str, target_machine: LLVMTargetMachineRef) -> Result<(), EscapeError> {
+    let pipeline = CString::new(pipeline).unwrap();
+    let options = LLVMCreatePassBuilderOptions();
+    let error = LLVMRunPasses(module, pipeline.as_ptr(), target_machine, options);
+    LLVMDisposePassBuilderOptions(options);
+    if error.is_null() {
+        return Ok(());
+    }
+    let message = LLVMGetErrorMessage(error);
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(message);
+    Err(EscapeError::Pipeline(text))
+}
+
+#[derive(Debug)]
+pub enum EscapeError {
+    /// LLVM rejected or failed the cleanup pipeline
+    Pipeline(String),
+}
+
+impl fmt::Display for EscapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EscapeError::Pipeline(message) => write!(f, "heap-to-stack promotion: {}", message),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn promote_heap(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) -> Result<(), EscapeError> {
+    let mut pass = HeapToStackPass::new(target_machine);
+    pass.run(module)?;
+    let (promoted, removed, frees, rejected) = pass.stats();
+    log::debug!("{} promoted, {} removed, {} free(s) deleted, {} left on the heap", promoted, removed, frees, rejected);
+    for remark in pass.remarks() {
+        eprintln!("remark: {}: {}", remark.function, remark.message);
+    }
+    Ok(())
+}
+*/
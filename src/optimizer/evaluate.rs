@@ -0,0 +1,1348 @@
+// src/optimizer/evaluate.rs
+//! Compile-time evaluation of LLVM IR
+//! An interpreter for the IR the frontend generates, used to run code
+//! whose result can't depend on when it runs:
+//!
+//!  - calls to functions with constant arguments that touch nothing but
+//!    their own stack and constant globals are replaced by their result
+//!  - static constructors (`llvm.global_ctors`) that only write globals of
+//!    the module are run, their writes become the globals' initializers
+//!    and they are dropped from the list, so a program that builds its
+//!    tables at startup starts with them built
+//!
+//! Everything is bounded by a `Budget`; running out of it, reaching a call
+//! to an external function, a volatile access, undefined behaviour or
+//! anything else the evaluator doesn't model leaves the code as it was. UB
+//! in particular is never folded, so it still reaches the overflow modes
+//! and `--sanitize=undefined` at run time.
+//!
+//! The arithmetic (`integer_binary`, `integer_compare`, `float_compare`)
+//! is shared with the constant expression evaluation of the frontend, and
+//! `constexpr_value` runs the nullary function the IR generator emits for a
+//! C23 `constexpr` initializer it can't fold itself, so a constant
+//! expression has the same value whichever of them computes it.
+
+#[cfg(feature = "llvm")]
+use std::collections::HashMap;
+#[cfg(feature = "llvm")]
+use std::ffi::CStr;
+use std::fmt;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::target::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMIntPredicate, LLVMLinkage, LLVMOpcode, LLVMRealPredicate, LLVMTypeKind};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "llvm")]
+use super::fenv::instructions;
+
+/// How much work compile-time evaluation may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Budget {
+    /// Instructions one evaluation may execute
+    pub steps: u64,
+    /// Instructions all evaluations of a module may execute together
+    pub total_steps: u64,
+    /// Calls nested inside the evaluated one
+    pub call_depth: u32,
+    /// Bytes of stack and globals one evaluation may have live
+    pub memory: usize,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            steps: 100_000,
+            total_steps: 10_000_000,
+            call_depth: 128,
+            memory: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A value during evaluation
+#[cfg(feature = "llvm")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    /// An integer of `bits` bits, zero-extended
+    Int { bits: u32, value: u64 },
+    F32(f32),
+    F64(f64),
+    /// `offset` bytes into an object of the evaluator's memory
+    Pointer { object: usize, offset: i64 },
+    Null,
+    Function(LLVMValueRef),
+    /// A struct or array value, as `extractvalue` sees it
+    Aggregate(Vec<Constant>),
+}
+
+/// `nsw`, `nuw` and `exact` of an integer instruction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub nsw: bool,
+    pub nuw: bool,
+    pub exact: bool,
+}
+
+#[cfg(feature = "llvm")]
+fn mask(bits: u32) -> u64 {
+    if bits >= 64 { u64::MAX } else { (1u64 << bits) - 1 }
+}
+
+#[cfg(feature = "llvm")]
+fn signed(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+#[cfg(feature = "llvm")]
+fn fits_signed(value: i128, bits: u32) -> bool {
+    let max = (1i128 << (bits - 1)) - 1;
+    (-max - 1..=max).contains(&value)
+}
+
+/// `lhs op rhs` on `bits`-bit integers with LLVM's semantics. Division by
+/// zero, shifts of `bits` or more and results the flags promise can't
+/// happen are poison or UB and come back as `EvalError::Undefined`.
+#[cfg(feature = "llvm")]
+pub fn integer_binary(op: LLVMOpcode, bits: u32, lhs: u64, rhs: u64, flags: Flags) -> Result<u64, EvalError> {
+    if bits == 0 || bits > 64 {
+        return Err(EvalError::Unsupported(format!("i{} arithmetic", bits)));
+    }
+    let (lhs, rhs) = (lhs & mask(bits), rhs & mask(bits));
+    let (slhs, srhs) = (signed(lhs, bits) as i128, signed(rhs, bits) as i128);
+    let undefined = |op: LLVMOpcode| Err(EvalError::Undefined(format!("{:?} on i{}", op, bits)));
+    let result = match op {
+        LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMSub | LLVMOpcode::LLVMMul => {
+            let (unsigned, signed) = match op {
+                LLVMOpcode::LLVMAdd => (lhs as i128 + rhs as i128, slhs + srhs),
+                LLVMOpcode::LLVMSub => (lhs as i128 - rhs as i128, slhs - srhs),
+                _ => (lhs as i128 * rhs as i128, slhs * srhs),
+            };
+            if (flags.nuw && !(0..=mask(bits) as i128).contains(&unsigned)) || (flags.nsw && !fits_signed(signed, bits)) {
+                return undefined(op);
+            }
+            unsigned as u64
+        }
+        LLVMOpcode::LLVMUDiv | LLVMOpcode::LLVMURem => {
+            if rhs == 0 || (flags.exact && lhs % rhs != 0) {
+                return undefined(op);
+            }
+            if op == LLVMOpcode::LLVMUDiv { lhs / rhs } else { lhs % rhs }
+        }
+        LLVMOpcode::LLVMSDiv | LLVMOpcode::LLVMSRem => {
+            if srhs == 0 || !fits_signed(slhs / srhs, bits) || (flags.exact && slhs % srhs != 0) {
+                return undefined(op);
+            }
+            (if op == LLVMOpcode::LLVMSDiv { slhs / srhs } else { slhs % srhs }) as u64
+        }
+        LLVMOpcode::LLVMShl | LLVMOpcode::LLVMLShr | LLVMOpcode::LLVMAShr => {
+            if rhs >= bits as u64 {
+                return undefined(op);
+            }
+            let result = match op {
+                LLVMOpcode::LLVMShl => lhs << rhs,
+                LLVMOpcode::LLVMLShr => lhs >> rhs,
+                _ => (signed(lhs, bits) >> rhs) as u64,
+            };
+            let lost = match op {
+                LLVMOpcode::LLVMShl => {
+                    (flags.nuw && (result & mask(bits)) >> rhs != lhs)
+                        || (flags.nsw && signed(result & mask(bits), bits) >> rhs != signed(lhs, bits))
+                }
+                _ => flags.exact && lhs & mask(rhs as u32) != 0,
+            };
+            if lost {
+                return undefined(op);
+            }
+            result
+        }
+        LLVMOpcode::LLVMAnd => lhs & rhs,
+        LLVMOpcode::LLVMOr => lhs | rhs,
+        LLVMOpcode::LLVMXor => lhs ^ rhs,
+        op => return Err(EvalError::Unsupported(format!("{:?}", op))),
+    };
+    Ok(result & mask(bits))
+}
+
+#[cfg(feature = "llvm")]
+pub fn integer_compare(predicate: LLVMIntPredicate, bits: u32, lhs: u64, rhs: u64) -> bool {
+    let (lhs, rhs) = (lhs & mask(bits), rhs & mask(bits));
+    let (slhs, srhs) = (signed(lhs, bits), signed(rhs, bits));
+    match predicate {
+        LLVMIntPredicate::LLVMIntEQ => lhs == rhs,
+        LLVMIntPredicate::LLVMIntNE => lhs != rhs,
+        LLVMIntPredicate::LLVMIntUGT => lhs > rhs,
+        LLVMIntPredicate::LLVMIntUGE => lhs >= rhs,
+        LLVMIntPredicate::LLVMIntULT => lhs < rhs,
+        LLVMIntPredicate::LLVMIntULE => lhs <= rhs,
+        LLVMIntPredicate::LLVMIntSGT => slhs > srhs,
+        LLVMIntPredicate::LLVMIntSGE => slhs >= srhs,
+        LLVMIntPredicate::LLVMIntSLT => slhs < srhs,
+        LLVMIntPredicate::LLVMIntSLE => slhs <= srhs,
+    }
+}
+
+#[cfg(feature = "llvm")]
+pub fn float_compare(predicate: LLVMRealPredicate, lhs: f64, rhs: f64) -> bool {
+    let unordered = lhs.is_nan() || rhs.is_nan();
+    match predicate {
+        LLVMRealPredicate::LLVMRealPredicateFalse => false,
+        LLVMRealPredicate::LLVMRealPredicateTrue => true,
+        LLVMRealPredicate::LLVMRealOEQ => lhs == rhs,
+        LLVMRealPredicate::LLVMRealOGT => lhs > rhs,
+        LLVMRealPredicate::LLVMRealOGE => lhs >= rhs,
+        LLVMRealPredicate::LLVMRealOLT => lhs < rhs,
+        LLVMRealPredicate::LLVMRealOLE => lhs <= rhs,
+        LLVMRealPredicate::LLVMRealONE => !unordered && lhs != rhs,
+        LLVMRealPredicate::LLVMRealORD => !unordered,
+        LLVMRealPredicate::LLVMRealUNO => unordered,
+        LLVMRealPredicate::LLVMRealUEQ => unordered || lhs == rhs,
+        LLVMRealPredicate::LLVMRealUGT => unordered || lhs > rhs,
+        LLVMRealPredicate::LLVMRealUGE => unordered || lhs >= rhs,
+        LLVMRealPredicate::LLVMRealULT => unordered || lhs < rhs,
+        LLVMRealPredicate::LLVMRealULE => unordered || lhs <= rhs,
+        LLVMRealPredicate::LLVMRealUNE => lhs != rhs,
+    }
+}
+
+/// What evaluated code may touch besides its own stack
+#[cfg(feature = "llvm")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Constant globals, read-only: the result can't depend on when it runs
+    Pure,
+    /// Every global of the module, as a static constructor before `main`
+    Initializer,
+}
+
+#[cfg(feature = "llvm")]
+#[derive(Debug, Clone)]
+struct Object {
+    bytes: Vec<u8>,
+    /// Pointers stored in the object, by offset; their bytes read as zero
+    pointers: HashMap<usize, Constant>,
+    global: Option<LLVMValueRef>,
+    constant: bool,
+    dirty: bool,
+    live: bool,
+}
+
+#[cfg(feature = "llvm")]
+enum Flow {
+    Next,
+    Jump(LLVMBasicBlockRef),
+    Return(Option<Constant>),
+}
+
+#[cfg(feature = "llvm")]
+struct Frame {
+    values: HashMap<LLVMValueRef, Constant>,
+    allocas: Vec<usize>,
+}
+
+#[cfg(feature = "llvm")]
+pub struct Evaluator {
+    target_data: LLVMTargetDataRef,
+    budget: Budget,
+    access: Access,
+
+    // Memory: stack objects of the calls in progress, then globals
+    objects: Vec<Object>,
+    globals: HashMap<LLVMValueRef, usize>,
+    live_bytes: usize,
+
+    // Budget used
+    steps: u64,
+    total_steps: u64,
+}
+
+#[cfg(feature = "llvm")]
+impl Evaluator {
+    /// An evaluator for pure calls into `module`
+    pub unsafe fn new(module: LLVMModuleRef, budget: Budget) -> Result<Self, EvalError> {
+        let target_data = LLVMCreateTargetData(LLVMGetDataLayoutStr(module));
+        if LLVMByteOrder(target_data) != LLVMByteOrdering::LLVMLittleEndian {
+            LLVMDisposeTargetData(target_data);
+            return Err(EvalError::Unsupported("big-endian targets".to_string()));
+        }
+        Ok(Evaluator {
+            target_data,
+            budget,
+            access: Access::Pure,
+            objects: Vec::new(),
+            globals: HashMap::new(),
+            live_bytes: 0,
+            steps: 0,
+            total_steps: 0,
+        })
+    }
+
+    /// Instructions executed by every evaluation so far
+    pub fn steps(&self) -> u64 {
+        self.total_steps
+    }
+
+    /// Call `function` with `arguments`; `None` for `void`
+    pub unsafe fn call(&mut self, function: LLVMValueRef, arguments: &[Constant]) -> Result<Option<Constant>, EvalError> {
+        self.steps = 0;
+        self.run(function, arguments.to_vec(), 0)
+    }
+
+    unsafe fn run(&mut self, function: LLVMValueRef, arguments: Vec<Constant>, depth: u32) -> Result<Option<Constant>, EvalError> {
+        if depth > self.budget.call_depth {
+            return Err(EvalError::Budget);
+        }
+        if LLVMCountBasicBlocks(function) == 0 {
+            return Err(EvalError::External(value_name(function)));
+        }
+        if LLVMIsFunctionVarArg(LLVMGlobalGetValueType(function)) != 0 || arguments.len() != LLVMCountParams(function) as usize {
+            return Err(EvalError::Unsupported(format!("call to {}", value_name(function))));
+        }
+
+        let mut frame = Frame { values: HashMap::new(), allocas: Vec::new() };
+        for (index, argument) in arguments.into_iter().enumerate() {
+            frame.values.insert(LLVMGetParam(function, index as u32), argument);
+        }
+        let result = self.run_frame(&mut frame, function, depth);
+
+        // The frame's allocas die with it; pointers into them now fault
+        for &object in &frame.allocas {
+            let object = &mut self.objects[object];
+            self.live_bytes -= object.bytes.len();
+            object.bytes = Vec::new();
+            object.pointers.clear();
+            object.live = false;
+        }
+        result
+    }
+
+    unsafe fn run_frame(&mut self, frame: &mut Frame, function: LLVMValueRef, depth: u32) -> Result<Option<Constant>, EvalError> {
+        let mut block = LLVMGetEntryBasicBlock(function);
+        let mut previous: LLVMBasicBlockRef = std::ptr::null_mut();
+        loop {
+            // Phis read their incoming values all at once
+            let mut inst = LLVMGetFirstInstruction(block);
+            let mut phis = Vec::new();
+            while !inst.is_null() && LLVMGetInstructionOpcode(inst) == LLVMOpcode::LLVMPHI {
+                let incoming = (0..LLVMCountIncoming(inst))
+                    .find(|&i| LLVMGetIncomingBlock(inst, i) == previous)
+                    .ok_or_else(|| EvalError::Undefined("phi without an incoming value".to_string()))?;
+                phis.push((inst, self.operand(frame, LLVMGetIncomingValue(inst, incoming))?));
+                inst = LLVMGetNextInstruction(inst);
+            }
+            frame.values.extend(phis);
+
+            loop {
+                if inst.is_null() {
+                    return Err(EvalError::Undefined("block without a terminator".to_string()));
+                }
+                self.step()?;
+                match self.execute(frame, inst, depth)? {
+                    Flow::Next => inst = LLVMGetNextInstruction(inst),
+                    Flow::Jump(target) => {
+                        previous = block;
+                        block = target;
+                        break;
+                    }
+                    Flow::Return(value) => return Ok(value),
+                }
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<(), EvalError> {
+        self.steps += 1;
+        self.total_steps += 1;
+        if self.steps > self.budget.steps || self.total_steps > self.budget.total_steps {
+            return Err(EvalError::Budget);
+        }
+        Ok(())
+    }
+
+    unsafe fn execute(&mut self, frame: &mut Frame, inst: LLVMValueRef, depth: u32) -> Result<Flow, EvalError> {
+        let opcode = LLVMGetInstructionOpcode(inst);
+        let ty = LLVMTypeOf(inst);
+        let operand = |evaluator: &mut Self, frame: &Frame, index: u32| evaluator.operand(frame, LLVMGetOperand(inst, index));
+        let value = match opcode {
+            LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMSub | LLVMOpcode::LLVMMul
+            | LLVMOpcode::LLVMUDiv | LLVMOpcode::LLVMSDiv | LLVMOpcode::LLVMURem | LLVMOpcode::LLVMSRem
+            | LLVMOpcode::LLVMShl | LLVMOpcode::LLVMLShr | LLVMOpcode::LLVMAShr
+            | LLVMOpcode::LLVMAnd | LLVMOpcode::LLVMOr | LLVMOpcode::LLVMXor => {
+                let (lhs, rhs) = (operand(self, frame, 0)?, operand(self, frame, 1)?);
+                let (Constant::Int { bits, value: lhs }, Constant::Int { value: rhs, .. }) = (lhs, rhs) else {
+                    return Err(EvalError::Unsupported(format!("{:?} on non-integers", opcode)));
+                };
+                let overflowing = matches!(opcode, LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMSub | LLVMOpcode::LLVMMul | LLVMOpcode::LLVMShl);
+                let exact = matches!(opcode, LLVMOpcode::LLVMUDiv | LLVMOpcode::LLVMSDiv | LLVMOpcode::LLVMLShr | LLVMOpcode::LLVMAShr);
+                let flags = Flags {
+                    nsw: overflowing && LLVMGetNSW(inst) != 0,
+                    nuw: overflowing && LLVMGetNUW(inst) != 0,
+                    exact: exact && LLVMGetExact(inst) != 0,
+                };
+                Constant::Int { bits, value: integer_binary(opcode, bits, lhs, rhs, flags)? }
+            }
+            LLVMOpcode::LLVMFAdd | LLVMOpcode::LLVMFSub | LLVMOpcode::LLVMFMul | LLVMOpcode::LLVMFDiv | LLVMOpcode::LLVMFRem => {
+                let apply = |a: f64, b: f64| match opcode {
+                    LLVMOpcode::LLVMFAdd => a + b,
+                    LLVMOpcode::LLVMFSub => a - b,
+                    LLVMOpcode::LLVMFMul => a * b,
+                    LLVMOpcode::LLVMFDiv => a / b,
+                    _ => a % b,
+                };
+                match (operand(self, frame, 0)?, operand(self, frame, 1)?) {
+                    // f32 arithmetic rounds once, as the hardware would
+                    (Constant::F32(a), Constant::F32(b)) => Constant::F32(match opcode {
+                        LLVMOpcode::LLVMFAdd => a + b,
+                        LLVMOpcode::LLVMFSub => a - b,
+                        LLVMOpcode::LLVMFMul => a * b,
+                        LLVMOpcode::LLVMFDiv => a / b,
+                        _ => a % b,
+                    }),
+                    (Constant::F64(a), Constant::F64(b)) => Constant::F64(apply(a, b)),
+                    _ => return Err(EvalError::Unsupported(format!("{:?} on this type", opcode))),
+                }
+            }
+            LLVMOpcode::LLVMFNeg => match operand(self, frame, 0)? {
+                Constant::F32(a) => Constant::F32(-a),
+                Constant::F64(a) => Constant::F64(-a),
+                _ => return Err(EvalError::Unsupported("fneg on this type".to_string())),
+            },
+            LLVMOpcode::LLVMICmp => {
+                let predicate = LLVMGetICmpPredicate(inst);
+                let result = match (operand(self, frame, 0)?, operand(self, frame, 1)?) {
+                    (Constant::Int { bits, value: lhs }, Constant::Int { value: rhs, .. }) => {
+                        integer_compare(predicate, bits, lhs, rhs)
+                    }
+                    (lhs, rhs) => self.compare_pointers(predicate, &lhs, &rhs)?,
+                };
+                Constant::Int { bits: 1, value: result as u64 }
+            }
+            LLVMOpcode::LLVMFCmp => {
+                let result = match (operand(self, frame, 0)?, operand(self, frame, 1)?) {
+                    (Constant::F32(a), Constant::F32(b)) => float_compare(LLVMGetFCmpPredicate(inst), a as f64, b as f64),
+                    (Constant::F64(a), Constant::F64(b)) => float_compare(LLVMGetFCmpPredicate(inst), a, b),
+                    _ => return Err(EvalError::Unsupported("fcmp on this type".to_string())),
+                };
+                Constant::Int { bits: 1, value: result as u64 }
+            }
+            LLVMOpcode::LLVMSelect => match operand(self, frame, 0)? {
+                Constant::Int { value, .. } => operand(self, frame, if value != 0 { 1 } else { 2 })?,
+                _ => return Err(EvalError::Unsupported("vector select".to_string())),
+            },
+            LLVMOpcode::LLVMTrunc | LLVMOpcode::LLVMZExt | LLVMOpcode::LLVMSExt
+            | LLVMOpcode::LLVMFPTrunc | LLVMOpcode::LLVMFPExt
+            | LLVMOpcode::LLVMFPToSI | LLVMOpcode::LLVMFPToUI | LLVMOpcode::LLVMSIToFP | LLVMOpcode::LLVMUIToFP
+            | LLVMOpcode::LLVMBitCast | LLVMOpcode::LLVMAddrSpaceCast | LLVMOpcode::LLVMFreeze => {
+                cast(opcode, operand(self, frame, 0)?, ty)?
+            }
+            // Addresses don't exist until the program runs
+            LLVMOpcode::LLVMPtrToInt | LLVMOpcode::LLVMIntToPtr => {
+                return Err(EvalError::Unsupported(format!("{:?}", opcode)));
+            }
+            LLVMOpcode::LLVMAlloca => {
+                let count = match operand(self, frame, 0)? {
+                    Constant::Int { value, .. } => value as usize,
+                    _ => 1,
+                };
+                let size = LLVMABISizeOfType(self.target_data, LLVMGetAllocatedType(inst)) as usize;
+                let object = self.allocate(size.saturating_mul(count), None)?;
+                frame.allocas.push(object);
+                Constant::Pointer { object, offset: 0 }
+            }
+            LLVMOpcode::LLVMLoad => {
+                if LLVMGetVolatile(inst) != 0 || LLVMGetOrdering(inst) != llvm_sys::LLVMAtomicOrdering::LLVMAtomicOrderingNotAtomic {
+                    return Err(EvalError::Unsupported("volatile or atomic load".to_string()));
+                }
+                let pointer = operand(self, frame, 0)?;
+                self.load(&pointer, ty)?
+            }
+            LLVMOpcode::LLVMStore => {
+                if LLVMGetVolatile(inst) != 0 || LLVMGetOrdering(inst) != llvm_sys::LLVMAtomicOrdering::LLVMAtomicOrderingNotAtomic {
+                    return Err(EvalError::Unsupported("volatile or atomic store".to_string()));
+                }
+                let stored = LLVMGetOperand(inst, 0);
+                let (value, pointer) = (operand(self, frame, 0)?, operand(self, frame, 1)?);
+                self.store(&pointer, &value, LLVMTypeOf(stored))?;
+                return Ok(Flow::Next);
+            }
+            LLVMOpcode::LLVMGetElementPtr => {
+                let base = operand(self, frame, 0)?;
+                let indices = (1..LLVMGetNumOperands(inst) as u32)
+                    .map(|index| operand(self, frame, index))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.element_pointer(LLVMGetGEPSourceElementType(inst), base, &indices)?
+            }
+            LLVMOpcode::LLVMExtractValue => {
+                let mut value = operand(self, frame, 0)?;
+                let indices = std::slice::from_raw_parts(LLVMGetIndices(inst), LLVMGetNumIndices(inst) as usize);
+                for &index in indices {
+                    value = match value {
+                        Constant::Aggregate(mut elements) if (index as usize) < elements.len() => elements.swap_remove(index as usize),
+                        _ => return Err(EvalError::Undefined("extractvalue out of range".to_string())),
+                    };
+                }
+                value
+            }
+            LLVMOpcode::LLVMInsertValue => {
+                let (mut aggregate, element) = (operand(self, frame, 0)?, operand(self, frame, 1)?);
+                let indices = std::slice::from_raw_parts(LLVMGetIndices(inst), LLVMGetNumIndices(inst) as usize);
+                let mut slot = &mut aggregate;
+                for &index in indices {
+                    slot = match slot {
+                        Constant::Aggregate(elements) if (index as usize) < elements.len() => &mut elements[index as usize],
+                        _ => return Err(EvalError::Undefined("insertvalue out of range".to_string())),
+                    };
+                }
+                *slot = element;
+                aggregate
+            }
+            LLVMOpcode::LLVMCall => match self.call_instruction(frame, inst, depth)? {
+                Some(value) => value,
+                None => return Ok(Flow::Next),
+            },
+            LLVMOpcode::LLVMBr => {
+                let successor = if LLVMIsConditional(inst) != 0 {
+                    match self.operand(frame, LLVMGetCondition(inst))? {
+                        Constant::Int { value, .. } => if value != 0 { 0 } else { 1 },
+                        _ => return Err(EvalError::Unsupported("branch on a vector".to_string())),
+                    }
+                } else {
+                    0
+                };
+                return Ok(Flow::Jump(LLVMGetSuccessor(inst, successor)));
+            }
+            LLVMOpcode::LLVMSwitch => {
+                let Constant::Int { value, .. } = operand(self, frame, 0)? else {
+                    return Err(EvalError::Unsupported("switch on a non-integer".to_string()));
+                };
+                // Operands: condition, default, then (value, destination) pairs
+                let cases = (LLVMGetNumOperands(inst) as u32 - 2) / 2;
+                for case in 0..cases {
+                    if let Constant::Int { value: label, .. } = operand(self, frame, 2 + 2 * case)? {
+                        if label == value {
+                            return Ok(Flow::Jump(LLVMGetSuccessor(inst, case + 1)));
+                        }
+                    }
+                }
+                return Ok(Flow::Jump(LLVMGetSuccessor(inst, 0)));
+            }
+            LLVMOpcode::LLVMRet => {
+                let value = if LLVMGetNumOperands(inst) == 0 { None } else { Some(operand(self, frame, 0)?) };
+                return Ok(Flow::Return(value));
+            }
+            LLVMOpcode::LLVMUnreachable => return Err(EvalError::Undefined("reached unreachable".to_string())),
+            opcode => return Err(EvalError::Unsupported(format!("{:?}", opcode))),
+        };
+        frame.values.insert(inst, value);
+        Ok(Flow::Next)
+    }
+
+    /// The result of a call instruction, `None` for `void`
+    unsafe fn call_instruction(&mut self, frame: &mut Frame, inst: LLVMValueRef, depth: u32) -> Result<Option<Constant>, EvalError> {
+        let callee = LLVMGetCalledValue(inst);
+        if !LLVMIsAInlineAsm(callee).is_null() {
+            return Err(EvalError::Unsupported("inline assembly".to_string()));
+        }
+        let function = match self.operand(frame, callee)? {
+            Constant::Function(function) => function,
+            _ => return Err(EvalError::Undefined("call through a non-function".to_string())),
+        };
+        let arguments = (0..LLVMGetNumArgOperands(inst))
+            .map(|index| self.operand(frame, LLVMGetOperand(inst, index)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if LLVMGetIntrinsicID(function) != 0 {
+            return self.intrinsic(&value_name(function), &arguments, LLVMTypeOf(inst));
+        }
+        self.run(function, arguments, depth + 1)
+    }
+
+    unsafe fn intrinsic(&mut self, name: &str, arguments: &[Constant], ty: LLVMTypeRef) -> Result<Option<Constant>, EvalError> {
+        let int = |index: usize| match arguments.get(index) {
+            Some(&Constant::Int { bits, value }) => Ok((bits, value)),
+            _ => Err(EvalError::Unsupported(format!("{} arguments", name))),
+        };
+        let family = name.trim_start_matches("llvm.").split('.').next().unwrap_or("");
+        let value = match family {
+            // Hints with no effect on the result
+            "lifetime" | "dbg" | "assume" | "experimental" | "invariant" | "donothing" => return Ok(None),
+            "memset" => {
+                let (_, byte) = int(1)?;
+                let (_, length) = int(2)?;
+                self.fill(&arguments[0], byte as u8, length as usize)?;
+                return Ok(None);
+            }
+            "memcpy" | "memmove" => {
+                let (_, length) = int(2)?;
+                self.copy(&arguments[0], &arguments[1], length as usize)?;
+                return Ok(None);
+            }
+            "sadd" | "uadd" | "ssub" | "usub" | "smul" | "umul" if name.contains(".with.overflow.") => {
+                let ((bits, lhs), (_, rhs)) = (int(0)?, int(1)?);
+                let opcode = match &family[1..] {
+                    "add" => LLVMOpcode::LLVMAdd,
+                    "sub" => LLVMOpcode::LLVMSub,
+                    _ => LLVMOpcode::LLVMMul,
+                };
+                let flags = if family.starts_with('s') {
+                    Flags { nsw: true, ..Flags::default() }
+                } else {
+                    Flags { nuw: true, ..Flags::default() }
+                };
+                let wrapped = integer_binary(opcode, bits, lhs, rhs, Flags::default())?;
+                let overflowed = integer_binary(opcode, bits, lhs, rhs, flags).is_err();
+                Constant::Aggregate(vec![
+                    Constant::Int { bits, value: wrapped },
+                    Constant::Int { bits: 1, value: overflowed as u64 },
+                ])
+            }
+            "smax" | "smin" | "umax" | "umin" => {
+                let ((bits, lhs), (_, rhs)) = (int(0)?, int(1)?);
+                let predicate = match family {
+                    "smax" => LLVMIntPredicate::LLVMIntSGT,
+                    "smin" => LLVMIntPredicate::LLVMIntSLT,
+                    "umax" => LLVMIntPredicate::LLVMIntUGT,
+                    _ => LLVMIntPredicate::LLVMIntULT,
+                };
+                Constant::Int { bits, value: if integer_compare(predicate, bits, lhs, rhs) { lhs } else { rhs } }
+            }
+            "ctpop" => {
+                let (bits, value) = int(0)?;
+                Constant::Int { bits, value: (value & mask(bits)).count_ones() as u64 }
+            }
+            "bswap" if int(0)?.0 % 16 == 0 => {
+                let (bits, value) = int(0)?;
+                Constant::Int { bits, value: value.swap_bytes() >> (64 - bits) }
+            }
+            "fabs" => match arguments.first() {
+                Some(Constant::F32(value)) => Constant::F32(value.abs()),
+                Some(Constant::F64(value)) => Constant::F64(value.abs()),
+                _ => return Err(EvalError::Unsupported(name.to_string())),
+            },
+            _ => return Err(EvalError::Unsupported(name.to_string())),
+        };
+        debug_assert!(LLVMGetTypeKind(ty) != LLVMTypeKind::LLVMVoidTypeKind);
+        Ok(Some(value))
+    }
+
+    unsafe fn operand(&mut self, frame: &Frame, value: LLVMValueRef) -> Result<Constant, EvalError> {
+        if let Some(known) = frame.values.get(&value) {
+            return Ok(known.clone());
+        }
+        if LLVMIsConstant(value) == 0 {
+            return Err(EvalError::Undefined(format!("{} used before it is defined", value_name(value))));
+        }
+        self.constant(value)
+    }
+
+    unsafe fn constant(&mut self, value: LLVMValueRef) -> Result<Constant, EvalError> {
+        let ty = LLVMTypeOf(value);
+        if !LLVMIsAPoisonValue(value).is_null() {
+            return Err(EvalError::Undefined("poison".to_string()));
+        }
+        if !LLVMIsAConstantInt(value).is_null() {
+            let bits = LLVMGetIntTypeWidth(ty);
+            if bits > 64 {
+                return Err(EvalError::Unsupported(format!("i{}", bits)));
+            }
+            return Ok(Constant::Int { bits, value: LLVMConstIntGetZExtValue(value) });
+        }
+        if !LLVMIsAConstantFP(value).is_null() {
+            let mut lost = 0;
+            let double = LLVMConstRealGetDouble(value, &mut lost);
+            return match LLVMGetTypeKind(ty) {
+                LLVMTypeKind::LLVMFloatTypeKind => Ok(Constant::F32(double as f32)),
+                LLVMTypeKind::LLVMDoubleTypeKind => Ok(Constant::F64(double)),
+                kind => Err(EvalError::Unsupported(format!("{:?} constants", kind))),
+            };
+        }
+        if !LLVMIsAConstantPointerNull(value).is_null() {
+            return Ok(Constant::Null);
+        }
+        if !LLVMIsAFunction(value).is_null() {
+            return Ok(Constant::Function(value));
+        }
+        if !LLVMIsAGlobalVariable(value).is_null() {
+            return Ok(Constant::Pointer { object: self.global(value)?, offset: 0 });
+        }
+        // Any value is allowed for undef; zero is the one the program can't tell from memory
+        if !LLVMIsAUndefValue(value).is_null() {
+            return self.zero(ty);
+        }
+        if !LLVMIsAConstantAggregateZero(value).is_null() {
+            return self.zero(ty);
+        }
+        if !LLVMIsAConstantStruct(value).is_null()
+            || !LLVMIsAConstantArray(value).is_null()
+            || !LLVMIsAConstantDataSequential(value).is_null()
+        {
+            let count = match LLVMGetTypeKind(ty) {
+                LLVMTypeKind::LLVMStructTypeKind => LLVMCountStructElementTypes(ty) as u64,
+                LLVMTypeKind::LLVMArrayTypeKind => LLVMGetArrayLength2(ty),
+                kind => return Err(EvalError::Unsupported(format!("{:?} constants", kind))),
+            };
+            return (0..count)
+                .map(|index| self.constant(LLVMGetAggregateElement(value, index as u32)))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Constant::Aggregate);
+        }
+        if !LLVMIsAConstantExpr(value).is_null() {
+            return match LLVMGetConstOpcode(value) {
+                LLVMOpcode::LLVMGetElementPtr => {
+                    let base = self.constant(LLVMGetOperand(value, 0))?;
+                    let indices = (1..LLVMGetNumOperands(value) as u32)
+                        .map(|index| self.constant(LLVMGetOperand(value, index)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    self.element_pointer(LLVMGetGEPSourceElementType(value), base, &indices)
+                }
+                LLVMOpcode::LLVMBitCast | LLVMOpcode::LLVMAddrSpaceCast => self.constant(LLVMGetOperand(value, 0)),
+                opcode => Err(EvalError::Unsupported(format!("{:?} constant expressions", opcode))),
+            };
+        }
+        Err(EvalError::Unsupported(format!("constant {}", value_name(value))))
+    }
+
+    unsafe fn zero(&mut self, ty: LLVMTypeRef) -> Result<Constant, EvalError> {
+        Ok(match LLVMGetTypeKind(ty) {
+            LLVMTypeKind::LLVMIntegerTypeKind => Constant::Int { bits: LLVMGetIntTypeWidth(ty), value: 0 },
+            LLVMTypeKind::LLVMFloatTypeKind => Constant::F32(0.0),
+            LLVMTypeKind::LLVMDoubleTypeKind => Constant::F64(0.0),
+            LLVMTypeKind::LLVMPointerTypeKind => Constant::Null,
+            LLVMTypeKind::LLVMStructTypeKind => Constant::Aggregate(
+                (0..LLVMCountStructElementTypes(ty))
+                    .map(|index| self.zero(LLVMStructGetTypeAtIndex(ty, index)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            LLVMTypeKind::LLVMArrayTypeKind => {
+                let element = self.zero(LLVMGetElementType(ty))?;
+                Constant::Aggregate(vec![element; LLVMGetArrayLength2(ty) as usize])
+            }
+            kind => return Err(EvalError::Unsupported(format!("{:?} values", kind))),
+        })
+    }
+
+    unsafe fn compare_pointers(&self, predicate: LLVMIntPredicate, lhs: &Constant, rhs: &Constant) -> Result<bool, EvalError> {
+        let equal = match (lhs, rhs) {
+            (Constant::Null, Constant::Null) => true,
+            (Constant::Function(a), Constant::Function(b)) => a == b,
+            (Constant::Pointer { object: a, offset: x }, Constant::Pointer { object: b, offset: y }) if a == b => {
+                // Offsets within one object order like addresses
+                return Ok(integer_compare(predicate, 64, *x as u64, *y as u64));
+            }
+            // Distinct objects, or an object and null, never share an address
+            _ => false,
+        };
+        match predicate {
+            LLVMIntPredicate::LLVMIntEQ => Ok(equal),
+            LLVMIntPredicate::LLVMIntNE => Ok(!equal),
+            _ => Err(EvalError::Unsupported("ordering pointers into different objects".to_string())),
+        }
+    }
+
+    unsafe fn element_pointer(&mut self, source: LLVMTypeRef, base: Constant, indices: &[Constant]) -> Result<Constant, EvalError> {
+        let index = |constant: &Constant| match *constant {
+            Constant::Int { bits, value } => Ok(signed(value, bits)),
+            _ => Err(EvalError::Unsupported("vector getelementptr".to_string())),
+        };
+        let mut offset = 0i64;
+        let mut ty = source;
+        for (position, constant) in indices.iter().enumerate() {
+            let index = index(constant)?;
+            if position == 0 {
+                offset += index * LLVMABISizeOfType(self.target_data, ty) as i64;
+                continue;
+            }
+            match LLVMGetTypeKind(ty) {
+                LLVMTypeKind::LLVMStructTypeKind => {
+                    offset += LLVMOffsetOfElement(self.target_data, ty, index as u32) as i64;
+                    ty = LLVMStructGetTypeAtIndex(ty, index as u32);
+                }
+                LLVMTypeKind::LLVMArrayTypeKind => {
+                    ty = LLVMGetElementType(ty);
+                    offset += index * LLVMABISizeOfType(self.target_data, ty) as i64;
+                }
+                kind => return Err(EvalError::Unsupported(format!("getelementptr into {:?}", kind))),
+            }
+        }
+        match base {
+            Constant::Pointer { object, offset: base } => Ok(Constant::Pointer { object, offset: base + offset }),
+            Constant::Null if offset == 0 => Ok(Constant::Null),
+            _ => Err(EvalError::Unsupported("getelementptr on a non-object".to_string())),
+        }
+    }
+
+    fn allocate(&mut self, size: usize, global: Option<LLVMValueRef>) -> Result<usize, EvalError> {
+        self.live_bytes += size;
+        if self.live_bytes > self.budget.memory {
+            return Err(EvalError::Budget);
+        }
+        self.objects.push(Object {
+            bytes: vec![0; size],
+            pointers: HashMap::new(),
+            global,
+            constant: false,
+            dirty: false,
+            live: true,
+        });
+        Ok(self.objects.len() - 1)
+    }
+
+    /// The object of a global variable, filled from its initializer the first time
+    unsafe fn global(&mut self, global: LLVMValueRef) -> Result<usize, EvalError> {
+        if let Some(&object) = self.globals.get(&global) {
+            return Ok(object);
+        }
+        if LLVMIsDeclaration(global) != 0 || LLVMIsThreadLocal(global) != 0 {
+            return Err(EvalError::External(value_name(global)));
+        }
+        let ty = LLVMGlobalGetValueType(global);
+        let object = self.allocate(LLVMABISizeOfType(self.target_data, ty) as usize, Some(global))?;
+        self.objects[object].constant = LLVMIsGlobalConstant(global) != 0;
+        // Registered first: an initializer may point at its own global
+        self.globals.insert(global, object);
+        let initializer = self.constant(LLVMGetInitializer(global))?;
+        self.write(object, 0, &initializer, ty)?;
+        Ok(object)
+    }
+
+    /// The object and offset `pointer` addresses, checked for `size` bytes
+    fn resolve(&self, pointer: &Constant, size: usize) -> Result<(usize, usize), EvalError> {
+        let Constant::Pointer { object, offset } = *pointer else {
+            return Err(EvalError::Undefined(format!("access through {:?}", pointer)));
+        };
+        let target = &self.objects[object];
+        if !target.live || offset < 0 || offset as usize + size > target.bytes.len() {
+            return Err(EvalError::Undefined("access outside an object".to_string()));
+        }
+        Ok((object, offset as usize))
+    }
+
+    unsafe fn load(&mut self, pointer: &Constant, ty: LLVMTypeRef) -> Result<Constant, EvalError> {
+        let (object, offset) = self.resolve(pointer, LLVMStoreSizeOfType(self.target_data, ty) as usize)?;
+        let target = &self.objects[object];
+        if self.access == Access::Pure && target.global.is_some() && !target.constant {
+            return Err(EvalError::NotPure(value_name(target.global.unwrap_or(std::ptr::null_mut()))));
+        }
+        self.read(object, offset, ty)
+    }
+
+    unsafe fn read(&self, object: usize, offset: usize, ty: LLVMTypeRef) -> Result<Constant, EvalError> {
+        let target = &self.objects[object];
+        let size = LLVMStoreSizeOfType(self.target_data, ty) as usize;
+        let bytes = &target.bytes[offset..offset + size];
+        let little_endian = || bytes.iter().rev().fold(0u64, |value, &byte| (value << 8) | byte as u64);
+        let kind = LLVMGetTypeKind(ty);
+        if kind != LLVMTypeKind::LLVMPointerTypeKind && kind != LLVMTypeKind::LLVMStructTypeKind && kind != LLVMTypeKind::LLVMArrayTypeKind
+            && target.pointers.keys().any(|&at| at < offset + size && offset < at + 8)
+        {
+            return Err(EvalError::Unsupported("reading a pointer's bytes".to_string()));
+        }
+        Ok(match kind {
+            LLVMTypeKind::LLVMIntegerTypeKind => {
+                let bits = LLVMGetIntTypeWidth(ty);
+                if bits > 64 {
+                    return Err(EvalError::Unsupported(format!("i{}", bits)));
+                }
+                Constant::Int { bits, value: little_endian() & mask(bits) }
+            }
+            LLVMTypeKind::LLVMFloatTypeKind => Constant::F32(f32::from_bits(little_endian() as u32)),
+            LLVMTypeKind::LLVMDoubleTypeKind => Constant::F64(f64::from_bits(little_endian())),
+            LLVMTypeKind::LLVMPointerTypeKind => match target.pointers.get(&offset) {
+                Some(pointer) => pointer.clone(),
+                None if bytes.iter().all(|&byte| byte == 0) => Constant::Null,
+                None => return Err(EvalError::Unsupported("an integer read as a pointer".to_string())),
+            },
+            LLVMTypeKind::LLVMStructTypeKind => Constant::Aggregate(
+                (0..LLVMCountStructElementTypes(ty))
+                    .map(|index| {
+                        let at = offset + LLVMOffsetOfElement(self.target_data, ty, index) as usize;
+                        self.read(object, at, LLVMStructGetTypeAtIndex(ty, index))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            LLVMTypeKind::LLVMArrayTypeKind => {
+                let element = LLVMGetElementType(ty);
+                let stride = LLVMABISizeOfType(self.target_data, element) as usize;
+                Constant::Aggregate(
+                    (0..LLVMGetArrayLength2(ty) as usize)
+                        .map(|index| self.read(object, offset + index * stride, element))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            kind => return Err(EvalError::Unsupported(format!("loading {:?}", kind))),
+        })
+    }
+
+    unsafe fn store(&mut self, pointer: &Constant, value: &Constant, ty: LLVMTypeRef) -> Result<(), EvalError> {
+        let (object, offset) = self.resolve(pointer, LLVMStoreSizeOfType(self.target_data, ty) as usize)?;
+        self.check_writable(object)?;
+        self.write(object, offset, value, ty)
+    }
+
+    fn check_writable(&mut self, object: usize) -> Result<(), EvalError> {
+        let target = &mut self.objects[object];
+        if let Some(global) = target.global {
+            if self.access == Access::Pure {
+                return Err(EvalError::NotPure(unsafe { value_name(global) }));
+            }
+            if target.constant {
+                return Err(EvalError::Undefined("write to a constant".to_string()));
+            }
+            target.dirty = true;
+        }
+        Ok(())
+    }
+
+    unsafe fn write(&mut self, object: usize, offset: usize, value: &Constant, ty: LLVMTypeRef) -> Result<(), EvalError> {
+        let size = LLVMStoreSizeOfType(self.target_data, ty) as usize;
+        match (value, LLVMGetTypeKind(ty)) {
+            (Constant::Aggregate(elements), LLVMTypeKind::LLVMStructTypeKind) => {
+                for (index, element) in elements.iter().enumerate() {
+                    let at = offset + LLVMOffsetOfElement(self.target_data, ty, index as u32) as usize;
+                    self.write(object, at, element, LLVMStructGetTypeAtIndex(ty, index as u32))?;
+                }
+                return Ok(());
+            }
+            (Constant::Aggregate(elements), LLVMTypeKind::LLVMArrayTypeKind) => {
+                let element_ty = LLVMGetElementType(ty);
+                let stride = LLVMABISizeOfType(self.target_data, element_ty) as usize;
+                for (index, element) in elements.iter().enumerate() {
+                    self.write(object, offset + index * stride, element, element_ty)?;
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let target = &mut self.objects[object];
+        target.pointers.retain(|&at, _| at + 8 <= offset || offset + size <= at);
+        let bytes = &mut target.bytes[offset..offset + size];
+        let raw = match *value {
+            Constant::Int { value, .. } => value,
+            Constant::F32(value) => value.to_bits() as u64,
+            Constant::F64(value) => value.to_bits(),
+            Constant::Null => 0,
+            Constant::Pointer { .. } | Constant::Function(_) => {
+                bytes.fill(0);
+                target.pointers.insert(offset, value.clone());
+                return Ok(());
+            }
+            Constant::Aggregate(_) => return Err(EvalError::Unsupported("storing an aggregate as a scalar".to_string())),
+        };
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = raw.checked_shr(8 * index as u32).unwrap_or(0) as u8;
+        }
+        Ok(())
+    }
+
+    fn fill(&mut self, destination: &Constant, byte: u8, length: usize) -> Result<(), EvalError> {
+        let (object, offset) = self.resolve(destination, length)?;
+        self.check_writable(object)?;
+        let target = &mut self.objects[object];
+        target.pointers.retain(|&at, _| at + 8 <= offset || offset + length <= at);
+        target.bytes[offset..offset + length].fill(byte);
+        Ok(())
+    }
+
+    fn copy(&mut self, destination: &Constant, source: &Constant, length: usize) -> Result<(), EvalError> {
+        let (from, start) = self.resolve(source, length)?;
+        let source = &self.objects[from];
+        if self.access == Access::Pure && source.global.is_some() && !source.constant {
+            return Err(EvalError::NotPure(unsafe { value_name(source.global.unwrap_or(std::ptr::null_mut())) }));
+        }
+        let bytes = source.bytes[start..start + length].to_vec();
+        let pointers: Vec<(usize, Constant)> = source
+            .pointers
+            .iter()
+            .filter(|(&at, _)| start <= at && at < start + length)
+            .map(|(&at, pointer)| (at - start, pointer.clone()))
+            .collect();
+
+        let (object, offset) = self.resolve(destination, length)?;
+        self.check_writable(object)?;
+        let target = &mut self.objects[object];
+        target.bytes[offset..offset + length].copy_from_slice(&bytes);
+        target.pointers.retain(|&at, _| at + 8 <= offset || offset + length <= at);
+        target.pointers.extend(pointers.into_iter().map(|(at, pointer)| (offset + at, pointer)));
+        Ok(())
+    }
+
+    /// The current contents of global `object` as an LLVM constant of `ty`
+    unsafe fn to_llvm(&self, object: usize, offset: usize, ty: LLVMTypeRef) -> Result<LLVMValueRef, EvalError> {
+        let context = LLVMGetTypeContext(ty);
+        Ok(match LLVMGetTypeKind(ty) {
+            LLVMTypeKind::LLVMPointerTypeKind => match self.objects[object].pointers.get(&offset) {
+                None => LLVMConstPointerNull(ty),
+                Some(Constant::Function(function)) => *function,
+                Some(&Constant::Pointer { object: pointee, offset: at }) => {
+                    let Some(global) = self.objects[pointee].global else {
+                        return Err(EvalError::Escapes);
+                    };
+                    if at == 0 {
+                        global
+                    } else {
+                        let i8 = LLVMInt8TypeInContext(context);
+                        let mut index = [LLVMConstInt(LLVMInt64TypeInContext(context), at as u64, 1)];
+                        LLVMConstInBoundsGEP2(i8, global, index.as_mut_ptr(), 1)
+                    }
+                }
+                Some(_) => return Err(EvalError::Escapes),
+            },
+            LLVMTypeKind::LLVMStructTypeKind => {
+                let mut fields = (0..LLVMCountStructElementTypes(ty))
+                    .map(|index| {
+                        let at = offset + LLVMOffsetOfElement(self.target_data, ty, index) as usize;
+                        self.to_llvm(object, at, LLVMStructGetTypeAtIndex(ty, index))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if LLVMGetStructName(ty).is_null() {
+                    LLVMConstStructInContext(context, fields.as_mut_ptr(), fields.len() as u32, LLVMIsPackedStruct(ty))
+                } else {
+                    LLVMConstNamedStruct(ty, fields.as_mut_ptr(), fields.len() as u32)
+                }
+            }
+            LLVMTypeKind::LLVMArrayTypeKind => {
+                let element = LLVMGetElementType(ty);
+                let stride = LLVMABISizeOfType(self.target_data, element) as usize;
+                let mut elements = (0..LLVMGetArrayLength2(ty) as usize)
+                    .map(|index| self.to_llvm(object, offset + index * stride, element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                LLVMConstArray2(element, elements.as_mut_ptr(), elements.len() as u64)
+            }
+            _ => match self.read(object, offset, ty)? {
+                Constant::Int { value, .. } => LLVMConstInt(ty, value, 0),
+                Constant::F32(value) => LLVMConstReal(ty, value as f64),
+                Constant::F64(value) => LLVMConstReal(ty, value),
+                _ => return Err(EvalError::Unsupported("global of this type".to_string())),
+            },
+        })
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl Drop for Evaluator {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeTargetData(self.target_data) };
+    }
+}
+
+/// `value` converted by a cast instruction to `ty`
+#[cfg(feature = "llvm")]
+unsafe fn cast(opcode: LLVMOpcode, value: Constant, ty: LLVMTypeRef) -> Result<Constant, EvalError> {
+    let kind = LLVMGetTypeKind(ty);
+    let bits = if kind == LLVMTypeKind::LLVMIntegerTypeKind { LLVMGetIntTypeWidth(ty) } else { 0 };
+    let float = |value: f64| match kind {
+        LLVMTypeKind::LLVMFloatTypeKind => Ok(Constant::F32(value as f32)),
+        LLVMTypeKind::LLVMDoubleTypeKind => Ok(Constant::F64(value)),
+        kind => Err(EvalError::Unsupported(format!("conversion to {:?}", kind))),
+    };
+    Ok(match (opcode, value) {
+        (LLVMOpcode::LLVMFreeze | LLVMOpcode::LLVMAddrSpaceCast, value) => value,
+        (LLVMOpcode::LLVMTrunc | LLVMOpcode::LLVMZExt, Constant::Int { value, .. }) => Constant::Int { bits, value: value & mask(bits) },
+        (LLVMOpcode::LLVMSExt, Constant::Int { bits: from, value }) => Constant::Int { bits, value: signed(value, from) as u64 & mask(bits) },
+        (LLVMOpcode::LLVMSIToFP, Constant::Int { bits: from, value }) => float(signed(value, from) as f64)?,
+        (LLVMOpcode::LLVMUIToFP, Constant::Int { value, .. }) => float(value as f64)?,
+        (LLVMOpcode::LLVMFPTrunc | LLVMOpcode::LLVMFPExt, Constant::F32(value)) => float(value as f64)?,
+        (LLVMOpcode::LLVMFPTrunc | LLVMOpcode::LLVMFPExt, Constant::F64(value)) => float(value)?,
+        (LLVMOpcode::LLVMFPToSI | LLVMOpcode::LLVMFPToUI, Constant::F32(value)) => to_integer(opcode, value as f64, bits)?,
+        (LLVMOpcode::LLVMFPToSI | LLVMOpcode::LLVMFPToUI, Constant::F64(value)) => to_integer(opcode, value, bits)?,
+        (LLVMOpcode::LLVMBitCast, Constant::Int { value, .. }) if kind == LLVMTypeKind::LLVMFloatTypeKind => Constant::F32(f32::from_bits(value as u32)),
+        (LLVMOpcode::LLVMBitCast, Constant::Int { value, .. }) if kind == LLVMTypeKind::LLVMDoubleTypeKind => Constant::F64(f64::from_bits(value)),
+        (LLVMOpcode::LLVMBitCast, Constant::F32(value)) if bits == 32 => Constant::Int { bits, value: value.to_bits() as u64 },
+        (LLVMOpcode::LLVMBitCast, Constant::F64(value)) if bits == 64 => Constant::Int { bits, value: value.to_bits() },
+        (LLVMOpcode::LLVMBitCast, value) if kind == LLVMTypeKind::LLVMPointerTypeKind => value,
+        (opcode, value) => return Err(EvalError::Unsupported(format!("{:?} of {:?}", opcode, value))),
+    })
+}
+
+/// `fptosi`/`fptoui`; out of range is poison
+#[cfg(feature = "llvm")]
+fn to_integer(opcode: LLVMOpcode, value: f64, bits: u32) -> Result<Constant, EvalError> {
+    let truncated = value.trunc();
+    let in_range = if opcode == LLVMOpcode::LLVMFPToSI {
+        truncated >= -(2f64.powi(bits as i32 - 1)) && truncated < 2f64.powi(bits as i32 - 1)
+    } else {
+        truncated >= 0.0 && truncated < 2f64.powi(bits as i32)
+    };
+    if !in_range {
+        return Err(EvalError::Undefined(format!("{} out of range of i{}", value, bits)));
+    }
+    let value = if opcode == LLVMOpcode::LLVMFPToSI { truncated as i64 as u64 } else { truncated as u64 };
+    Ok(Constant::Int { bits, value: value & mask(bits) })
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(value, &mut len);
+    if name.is_null() || len == 0 {
+        return "<unnamed>".to_string();
+    }
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len)).into_owned()
+}
+
+/// Run the nullary `function` the IR generator emitted for a C23
+/// `constexpr` initializer and return its value. Any failure means the
+/// initializer isn't a constant expression.
+#[cfg(feature = "llvm")]
+pub unsafe fn constexpr_value(module: LLVMModuleRef, function: LLVMValueRef, budget: Budget) -> Result<Constant, EvalError> {
+    let mut evaluator = Evaluator::new(module, budget)?;
+    match evaluator.call(function, &[])? {
+        Some(value @ (Constant::Int { .. } | Constant::F32(_) | Constant::F64(_) | Constant::Null)) => Ok(value),
+        Some(_) => Err(EvalError::Escapes),
+        None => Err(EvalError::Unsupported("void initializer".to_string())),
+    }
+}
+
+/// Folds pure calls with constant arguments and runs static constructors
+pub struct CompileTimeEvaluation {
+    budget: Budget,
+
+    // Statistics
+    calls_folded: usize,
+    constructors_run: usize,
+    steps: u64,
+}
+
+#[cfg(feature = "llvm")]
+impl CompileTimeEvaluation {
+    pub fn new(budget: Budget) -> Self {
+        CompileTimeEvaluation {
+            budget,
+            calls_folded: 0,
+            constructors_run: 0,
+            steps: 0,
+        }
+    }
+
+    /// (calls folded, constructors run, instructions evaluated)
+    pub fn stats(&self) -> (usize, usize, u64) {
+        (self.calls_folded, self.constructors_run, self.steps)
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> Result<(), EvalError> {
+        self.run_constructors(module)?;
+        self.fold_calls(module)
+    }
+
+    unsafe fn fold_calls(&mut self, module: LLVMModuleRef) -> Result<(), EvalError> {
+        let mut evaluator = Evaluator::new(module, self.budget)?;
+        let mut results: HashMap<(LLVMValueRef, Vec<u64>), Option<Constant>> = HashMap::new();
+
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            for inst in instructions(function) {
+                let Some((callee, arguments)) = foldable_call(inst) else { continue };
+                let key = (callee, arguments.iter().map(raw_bits).collect());
+                let result = match results.get(&key) {
+                    Some(result) => result.clone(),
+                    None => {
+                        let result = match evaluator.call(callee, &arguments) {
+                            Ok(Some(value @ (Constant::Int { .. } | Constant::F32(_) | Constant::F64(_)))) => Some(value),
+                            Ok(_) => None,
+                            Err(e) => {
+                                log::trace!("not folding call to {}: {}", value_name(callee), e);
+                                None
+                            }
+                        };
+                        results.insert(key, result.clone());
+                        result
+                    }
+                };
+                let Some(value) = result else { continue };
+
+                let ty = LLVMTypeOf(inst);
+                let folded = match value {
+                    Constant::Int { value, .. } => LLVMConstInt(ty, value, 0),
+                    Constant::F32(value) => LLVMConstReal(ty, value as f64),
+                    Constant::F64(value) => LLVMConstReal(ty, value),
+                    _ => continue,
+                };
+                LLVMReplaceAllUsesWith(inst, folded);
+                LLVMInstructionEraseFromParent(inst);
+                self.calls_folded += 1;
+            }
+            function = LLVMGetNextFunction(function);
+        }
+        self.steps += evaluator.steps();
+        Ok(())
+    }
+
+    /// Run constructors in priority order until one can't be, then make
+    /// what they wrote the initial contents of the globals
+    unsafe fn run_constructors(&mut self, module: LLVMModuleRef) -> Result<(), EvalError> {
+        let ctors = LLVMGetNamedGlobal(module, c"llvm.global_ctors".as_ptr());
+        if ctors.is_null() || LLVMIsDeclaration(ctors) != 0 {
+            return Ok(());
+        }
+        let list = LLVMGetInitializer(ctors);
+        let list_ty = LLVMGlobalGetValueType(ctors);
+        let mut entries: Vec<(u64, LLVMValueRef)> = (0..LLVMGetArrayLength2(list_ty))
+            .map(|index| {
+                let entry = LLVMGetAggregateElement(list, index as u32);
+                (LLVMConstIntGetZExtValue(LLVMGetAggregateElement(entry, 0)), entry)
+            })
+            .collect();
+        entries.sort_by_key(|&(priority, _)| priority);
+
+        let mut evaluator = Evaluator::new(module, self.budget)?;
+        evaluator.access = Access::Initializer;
+        let mut run = 0;
+        for &(_, entry) in &entries {
+            let function = LLVMGetAggregateElement(entry, 1);
+            let data = LLVMGetAggregateElement(entry, 2);
+            if LLVMIsAFunction(function).is_null() || (!data.is_null() && LLVMIsNull(data) == 0) {
+                break;
+            }
+            // A constructor that fails part way leaves no trace
+            let (objects, globals, live_bytes) = (evaluator.objects.clone(), evaluator.globals.clone(), evaluator.live_bytes);
+            if let Err(e) = evaluator.call(function, &[]) {
+                log::trace!("not running constructor {} at compile time: {}", value_name(function), e);
+                evaluator.objects = objects;
+                evaluator.globals = globals;
+                evaluator.live_bytes = live_bytes;
+                break;
+            }
+            run += 1;
+        }
+        self.steps += evaluator.steps();
+        if run == 0 {
+            return Ok(());
+        }
+
+        // Every initializer must convert before any is replaced
+        let mut initializers = Vec::new();
+        for (&global, &object) in &evaluator.globals {
+            if evaluator.objects[object].dirty {
+                let value = evaluator.to_llvm(object, 0, LLVMGlobalGetValueType(global));
+                match value {
+                    Ok(value) => initializers.push((global, value)),
+                    Err(e) => {
+                        log::trace!("constructors left {} in a state with no initializer: {}", value_name(global), e);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        for (global, value) in initializers {
+            LLVMSetInitializer(global, value);
+        }
+
+        let mut remaining: Vec<LLVMValueRef> = entries[run..].iter().map(|&(_, entry)| entry).collect();
+        let entry_ty = LLVMGetElementType(list_ty);
+        LLVMDeleteGlobal(ctors);
+        if !remaining.is_empty() {
+            let list = LLVMConstArray2(entry_ty, remaining.as_mut_ptr(), remaining.len() as u64);
+            let ctors = LLVMAddGlobal(module, LLVMTypeOf(list), c"llvm.global_ctors".as_ptr());
+            LLVMSetLinkage(ctors, LLVMLinkage::LLVMAppendingLinkage);
+            LLVMSetInitializer(ctors, list);
+        }
+        self.constructors_run += run;
+        Ok(())
+    }
+}
+
+/// The callee and arguments of a call to a defined function with constant
+/// scalar arguments and a scalar result, whose definition can't be replaced
+#[cfg(feature = "llvm")]
+unsafe fn foldable_call(inst: LLVMValueRef) -> Option<(LLVMValueRef, Vec<Constant>)> {
+    if LLVMGetInstructionOpcode(inst) != LLVMOpcode::LLVMCall {
+        return None;
+    }
+    let callee = LLVMGetCalledValue(inst);
+    if LLVMIsAFunction(callee).is_null() || LLVMCountBasicBlocks(callee) == 0 || LLVMGetIntrinsicID(callee) != 0 {
+        return None;
+    }
+    if !matches!(
+        LLVMGetLinkage(callee),
+        LLVMLinkage::LLVMExternalLinkage | LLVMLinkage::LLVMInternalLinkage | LLVMLinkage::LLVMPrivateLinkage
+    ) {
+        return None;
+    }
+    if !matches!(
+        LLVMGetTypeKind(LLVMTypeOf(inst)),
+        LLVMTypeKind::LLVMIntegerTypeKind | LLVMTypeKind::LLVMFloatTypeKind | LLVMTypeKind::LLVMDoubleTypeKind
+    ) {
+        return None;
+    }
+    let mut arguments = Vec::new();
+    for index in 0..LLVMGetNumArgOperands(inst) {
+        let argument = LLVMGetOperand(inst, index);
+        let ty = LLVMTypeOf(argument);
+        let constant = if !LLVMIsAConstantInt(argument).is_null() && LLVMGetIntTypeWidth(ty) <= 64 {
+            Constant::Int { bits: LLVMGetIntTypeWidth(ty), value: LLVMConstIntGetZExtValue(argument) }
+        } else if !LLVMIsAConstantFP(argument).is_null() {
+            let mut lost = 0;
+            let value = LLVMConstRealGetDouble(argument, &mut lost);
+            match LLVMGetTypeKind(ty) {
+                LLVMTypeKind::LLVMFloatTypeKind => Constant::F32(value as f32),
+                LLVMTypeKind::LLVMDoubleTypeKind => Constant::F64(value),
+                _ => return None,
+            }
+        } else {
+            return None;
+        };
+        arguments.push(constant);
+    }
+    Some((callee, arguments))
+}
+
+#[cfg(feature = "llvm")]
+fn raw_bits(constant: &Constant) -> u64 {
+    match *constant {
+        Constant::Int { value, .. } => value,
+        Constant::F32(value) => value.to_bits() as u64,
+        Constant::F64(value) => value.to_bits(),
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// Ran out of `Budget`
+    Budget,
+    /// Calls or reads something defined outside the module
+    External(String),
+    /// Reads or writes a mutable global where only pure code may run
+    NotPure(String),
+    /// Undefined behaviour or poison; left for run time to diagnose
+    Undefined(String),
+    /// A pointer to the evaluation's stack would outlive it
+    Escapes,
+    Unsupported(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Budget => write!(f, "evaluation budget exhausted"),
+            EvalError::External(name) => write!(f, "uses external {}", name),
+            EvalError::NotPure(name) => write!(f, "accesses mutable global {}", name),
+            EvalError::Undefined(what) => write!(f, "undefined behaviour: {}", what),
+            EvalError::Escapes => write!(f, "a pointer into the evaluation's stack escapes"),
+            EvalError::Unsupported(what) => write!(f, "{} is not evaluated at compile time", what),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn example(module: LLVMModuleRef) -> Result<(), EvalError> {
+    // static int squares[256];
+    // __attribute__((constructor)) static void init(void) {
+    //     for (int i = 0; i < 256; i++) squares[i] = i * i;
+    // }
+    // static int fib(int n) { return n < 2 ? n : fib(n - 1) + fib(n - 2); }
+    // int main(void) { return squares[fib(10) % 256]; }
+    let mut pass = CompileTimeEvaluation::new(Budget::default());
+    pass.run(module)?;
+
+    // `init` is gone and `squares` starts filled in; `fib(10)` is 55
+    let (folded, constructors, steps) = pass.stats();
+    println!("{} call(s) folded, {} constructor(s) run in {} steps", folded, constructors, steps);
+    Ok(())
+}
+*/
@@ -0,0 +1,341 @@
+// src/optimizer/fastmath.rs
+//! Floating-point optimization flags
+//! `-ffast-math`, `-fno-math-errno` and `-ffp-contract=fast|on|off`, plus
+//! pragmas that change them for part of a file. The default is strict IEEE
+//! 754: no reassociation, no contraction into FMA, and libm calls keep
+//! setting errno. That is exactly what the interpreter computes, so
+//! interpreted and compiled programs agree bit for bit unless a flag or
+//! pragma says otherwise.
+//!
+//! Pragmas hold to the next pragma of the same kind or the end of the
+//! enclosing block; a function's attributes follow the state where it
+//! starts, and each instruction's flags the state on its line:
+//!  - `#pragma STDC FP_CONTRACT ON|OFF|DEFAULT`
+//!  - `#pragma clang fp contract(fast|on|off)`
+//!  - `#pragma float_control(precise, on|off)` (`off` enables fast-math)
+//!
+//! Functions under FENV_ACCESS are left alone: they are already strict.
+
+use std::str::FromStr;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMFastMathAll, LLVMFastMathAllowContract, LLVMFastMathFlags, LLVMFastMathNone, LLVMOpcode};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "llvm")]
+use super::fenv::{attribute_kind, debug_file, instructions};
+use super::pragma::PragmaRegions;
+
+/// libm functions that only touch errno; without errno they are pure and
+/// LLVM can turn them into intrinsics (e.g. `sqrt` -> `llvm.sqrt`)
+#[cfg(feature = "llvm")]
+const ERRNO_MATH_FUNCTIONS: &[&str] = &[
+    "acos", "asin", "atan", "atan2", "cos", "sin", "tan", "cosh", "sinh", "tanh",
+    "acosh", "asinh", "atanh", "exp", "exp2", "expm1", "log", "log10", "log1p", "log2",
+    "pow", "sqrt", "cbrt", "hypot", "fmod", "remainder", "ldexp", "erf", "erfc",
+    "lgamma", "tgamma", "lrint", "llrint", "lround", "llround", "ilogb", "logb",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FpContract {
+    /// Never fuse
+    Off,
+    /// Fuse a multiply into the add that consumes it within one expression
+    On,
+    /// Fuse wherever the backend likes
+    Fast,
+}
+
+impl FromStr for FpContract {
+    type Err = FastMathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(FpContract::Off),
+            "on" => Ok(FpContract::On),
+            "fast" => Ok(FpContract::Fast),
+            other => Err(FastMathError::UnknownContractMode(other.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FpOptions {
+    pub fast_math: bool,
+    pub math_errno: bool,
+    pub contract: FpContract,
+}
+
+impl Default for FpOptions {
+    /// Strict IEEE 754, matching the interpreter
+    fn default() -> Self {
+        FpOptions {
+            fast_math: false,
+            math_errno: true,
+            contract: FpContract::Off,
+        }
+    }
+}
+
+impl FpOptions {
+    /// Combine the command-line flags. As with GCC, `-ffast-math` implies
+    /// `-fno-math-errno` and `-ffp-contract=fast` unless those are given.
+    pub fn from_flags(fast_math: bool, math_errno: Option<bool>, contract: Option<FpContract>) -> Self {
+        FpOptions {
+            fast_math,
+            math_errno: math_errno.unwrap_or(!fast_math),
+            contract: contract.unwrap_or(if fast_math { FpContract::Fast } else { FpContract::Off }),
+        }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        *self == FpOptions::default()
+    }
+
+    fn apply(mut self, pragmas: &FpPragmas, file: &str, line: u32) -> Self {
+        if let Some(contract) = pragmas.contract.at(file, line) {
+            // DEFAULT goes back to the command line's setting
+            if let Some(contract) = contract {
+                self.contract = contract;
+            }
+        }
+        if let Some(precise) = pragmas.precise.at(file, line) {
+            self.fast_math = !precise;
+            if !precise {
+                self.math_errno = false;
+            }
+        }
+        self
+    }
+
+    /// String attributes the backend reads per function
+    fn function_attributes(&self) -> [(&'static str, bool); 5] {
+        [
+            ("unsafe-fp-math", self.fast_math),
+            ("no-nans-fp-math", self.fast_math),
+            ("no-infs-fp-math", self.fast_math),
+            ("no-signed-zeros-fp-math", self.fast_math),
+            ("approx-func-fp-math", self.fast_math),
+        ]
+    }
+}
+
+/// The FP_CONTRACT and float_control pragmas in a translation unit
+#[derive(Debug, Clone)]
+pub struct FpPragmas {
+    contract: PragmaRegions<Option<FpContract>>,
+    precise: PragmaRegions<bool>,
+}
+
+impl FpPragmas {
+    pub fn scan(source: &str) -> Self {
+        let contract = PragmaRegions::scan(source, |words| match words {
+            ["STDC", "FP_CONTRACT", "ON", ..] => Some(Some(FpContract::On)),
+            ["STDC", "FP_CONTRACT", "OFF", ..] => Some(Some(FpContract::Off)),
+            ["STDC", "FP_CONTRACT", "DEFAULT", ..] => Some(None),
+            ["clang", "fp", "contract", mode, ..] => mode.parse().ok().map(Some),
+            _ => None,
+        });
+        let precise = PragmaRegions::scan(source, |words| match words {
+            ["float_control", "precise", "on", ..] => Some(true),
+            ["float_control", "precise", "off", ..] => Some(false),
+            _ => None,
+        });
+        FpPragmas { contract, precise }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contract.is_empty() && self.precise.is_empty()
+    }
+}
+
+#[cfg(feature = "llvm")]
+pub struct FastMathPass<'a> {
+    options: FpOptions,
+    pragmas: &'a FpPragmas,
+
+    // Statistics
+    flagged: usize,
+    pure_calls: usize,
+}
+
+#[cfg(feature = "llvm")]
+impl<'a> FastMathPass<'a> {
+    pub fn new(options: FpOptions, pragmas: &'a FpPragmas) -> Self {
+        FastMathPass {
+            options,
+            pragmas,
+            flagged: 0,
+            pure_calls: 0,
+        }
+    }
+
+    /// (instructions given fast-math flags, libm calls marked pure)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.flagged, self.pure_calls)
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) {
+        let context = LLVMGetModuleContext(module);
+        let strictfp = attribute_kind("strictfp");
+
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            let strict = !LLVMGetEnumAttributeAtIndex(function, llvm_sys::LLVMAttributeFunctionIndex, strictfp).is_null();
+            if LLVMCountBasicBlocks(function) > 0 && !strict {
+                self.run_on_function(context, function);
+            }
+            function = LLVMGetNextFunction(function);
+        }
+    }
+
+    unsafe fn run_on_function(&mut self, context: LLVMContextRef, function: LLVMValueRef) {
+        let body = instructions(function);
+
+        // The function as a whole follows the state where it starts
+        let entry = body.iter().find(|&&inst| LLVMGetDebugLocLine(inst) != 0);
+        let function_options = match entry {
+            Some(&inst) => self.options.apply(self.pragmas, &debug_file(inst), LLVMGetDebugLocLine(inst)),
+            None => self.options,
+        };
+        for (name, enabled) in function_options.function_attributes() {
+            add_string_attribute(context, function, name, if enabled { "true" } else { "false" });
+        }
+
+        for &inst in &body {
+            let line = LLVMGetDebugLocLine(inst);
+            let options = if line == 0 {
+                function_options
+            } else {
+                self.options.apply(self.pragmas, &debug_file(inst), line)
+            };
+
+            if LLVMCanValueUseFastMathFlags(inst) != 0 {
+                let flags = self.instruction_flags(inst, &options);
+                LLVMSetFastMathFlags(inst, flags);
+                if flags != LLVMFastMathNone {
+                    self.flagged += 1;
+                }
+            }
+
+            if !options.math_errno && LLVMGetInstructionOpcode(inst) == LLVMOpcode::LLVMCall {
+                let callee = LLVMGetCalledValue(inst);
+                if !callee.is_null() && is_errno_math_function(&value_name(callee)) {
+                    // memory(none): only errno was ever written
+                    let memory = LLVMCreateEnumAttribute(context, attribute_kind("memory"), 0);
+                    LLVMAddCallSiteAttribute(inst, llvm_sys::LLVMAttributeFunctionIndex, memory);
+                    let willreturn = LLVMCreateEnumAttribute(context, attribute_kind("willreturn"), 0);
+                    LLVMAddCallSiteAttribute(inst, llvm_sys::LLVMAttributeFunctionIndex, willreturn);
+                    self.pure_calls += 1;
+                }
+            }
+        }
+    }
+
+    unsafe fn instruction_flags(&self, inst: LLVMValueRef, options: &FpOptions) -> LLVMFastMathFlags {
+        if options.fast_math {
+            return LLVMFastMathAll;
+        }
+        match options.contract {
+            FpContract::Off => LLVMFastMathNone,
+            FpContract::Fast => LLVMFastMathAllowContract,
+            FpContract::On if in_contractible_pair(inst) => LLVMFastMathAllowContract,
+            FpContract::On => LLVMFastMathNone,
+        }
+    }
+}
+
+/// `a * b + c` written as one expression: an fmul whose only use is an
+/// fadd/fsub on the same source line, or that fadd/fsub itself
+#[cfg(feature = "llvm")]
+unsafe fn in_contractible_pair(inst: LLVMValueRef) -> bool {
+    let is_add = |v: LLVMValueRef| {
+        matches!(LLVMGetInstructionOpcode(v), LLVMOpcode::LLVMFAdd | LLVMOpcode::LLVMFSub)
+    };
+    let single_use_mul_on_line = |mul: LLVMValueRef, line: u32| {
+        !LLVMIsAInstruction(mul).is_null()
+            && LLVMGetInstructionOpcode(mul) == LLVMOpcode::LLVMFMul
+            && LLVMGetDebugLocLine(mul) == line
+            && !LLVMGetFirstUse(mul).is_null()
+            && LLVMGetNextUse(LLVMGetFirstUse(mul)).is_null()
+    };
+
+    let line = LLVMGetDebugLocLine(inst);
+    match LLVMGetInstructionOpcode(inst) {
+        LLVMOpcode::LLVMFMul => {
+            let first = LLVMGetFirstUse(inst);
+            if first.is_null() || !single_use_mul_on_line(inst, line) {
+                return false;
+            }
+            let user = LLVMGetUser(first);
+            is_add(user) && LLVMGetDebugLocLine(user) == line
+        }
+        LLVMOpcode::LLVMFAdd | LLVMOpcode::LLVMFSub => {
+            (0..2).any(|i| single_use_mul_on_line(LLVMGetOperand(inst, i), line))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn is_errno_math_function(name: &str) -> bool {
+    // `sqrtf` and `sqrtl` as well as `sqrt`
+    ERRNO_MATH_FUNCTIONS.contains(&name)
+        || [name.strip_suffix('f'), name.strip_suffix('l')]
+            .into_iter()
+            .flatten()
+            .any(|base| ERRNO_MATH_FUNCTIONS.contains(&base))
+}
+
+/// Set `key=value` on `function`, replacing any earlier value
+#[cfg(feature = "llvm")]
+unsafe fn add_string_attribute(context: LLVMContextRef, function: LLVMValueRef, key: &str, value: &str) {
+    let attribute = LLVMCreateStringAttribute(
+        context,
+        key.as_ptr() as *const _,
+        key.len() as u32,
+        value.as_ptr() as *const _,
+        value.len() as u32,
+    );
+    LLVMRemoveStringAttributeAtIndex(
+        function,
+        llvm_sys::LLVMAttributeFunctionIndex,
+        key.as_ptr() as *const _,
+        key.len() as u32,
+    );
+    LLVMAddAttributeAtIndex(function, llvm_sys::LLVMAttributeFunctionIndex, attribute);
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(value, &mut len);
+    if name.is_null() {
+        return String::new();
+    }
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len)).into_owned()
+}
+
+#[derive(Debug)]
+pub enum FastMathError {
+    UnknownContractMode(String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), FastMathError> {
+    // c-interpreter -ffp-contract=fast -fno-math-errno prog.c
+    let options = FpOptions::from_flags(false, Some(false), Some("fast".parse()?));
+    let pragmas = FpPragmas::scan(&preprocessed);
+
+    unsafe {
+        let mut pass = FastMathPass::new(options, &pragmas);
+        pass.run(module);
+        println!("{:?}", pass.stats());
+    }
+    Ok(())
+}
+*/
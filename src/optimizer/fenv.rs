@@ -0,0 +1,258 @@
+// src/optimizer/fenv.rs
+//! `#pragma STDC FENV_ACCESS ON`
+//! LLVM assumes round-to-nearest and no observable exception flags, so by
+//! default it folds `1.0 / 3.0` at compile time and moves or deletes FP
+//! operations freely. That breaks programs that call `fesetround` or test
+//! flags. Where the pragma is on, the affected functions are marked
+//! `strictfp` and every FP operation becomes the matching
+//! `llvm.experimental.constrained.*` intrinsic with dynamic rounding and
+//! strict exception semantics, which the optimizer will neither fold nor
+//! reorder across calls.
+//!
+//! The pragma is found by scanning the preprocessed source (see
+//! `pragma::PragmaRegions`). A function is made strict when any of its
+//! instructions carries a debug location inside an ON region, so the IR
+//! generator must also avoid folding FP constants on those lines (see
+//! `FenvAccessRegions::covers`).
+
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::{LLVMOpcode, LLVMRealPredicate};
+use super::pragma::PragmaRegions;
+
+/// Where FENV_ACCESS is on
+#[derive(Debug, Clone)]
+pub struct FenvAccessRegions {
+    regions: PragmaRegions<bool>,
+}
+
+impl FenvAccessRegions {
+    pub fn scan(source: &str) -> Self {
+        let regions = PragmaRegions::scan(source, |words| match words {
+            ["STDC", "FENV_ACCESS", "ON", ..] => Some(true),
+            ["STDC", "FENV_ACCESS", "OFF", ..] | ["STDC", "FENV_ACCESS", "DEFAULT", ..] => Some(false),
+            _ => None,
+        });
+        FenvAccessRegions { regions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Whether FENV_ACCESS is on at `line` of `file`
+    pub fn covers(&self, file: &str, line: u32) -> bool {
+        self.regions.at(file, line) == Some(true)
+    }
+}
+
+pub struct FenvAccessPass<'a> {
+    regions: &'a FenvAccessRegions,
+
+    // Statistics
+    functions: usize,
+    constrained: usize,
+}
+
+impl<'a> FenvAccessPass<'a> {
+    pub fn new(regions: &'a FenvAccessRegions) -> Self {
+        FenvAccessPass {
+            regions,
+            functions: 0,
+            constrained: 0,
+        }
+    }
+
+    /// (functions made strict, operations replaced)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.functions, self.constrained)
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> Result<(), FenvError> {
+        let context = LLVMGetModuleContext(module);
+        let builder = LLVMCreateBuilderInContext(context);
+
+        let mut result = Ok(());
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() && result.is_ok() {
+            if LLVMCountBasicBlocks(function) > 0 && self.is_strict(function) {
+                result = self.constrain_function(module, context, builder, function);
+                self.functions += 1;
+            }
+            function = LLVMGetNextFunction(function);
+        }
+
+        LLVMDisposeBuilder(builder);
+        result
+    }
+
+    unsafe fn is_strict(&self, function: LLVMValueRef) -> bool {
+        let kind = attribute_kind("strictfp");
+        if !LLVMGetEnumAttributeAtIndex(function, llvm_sys::LLVMAttributeFunctionIndex, kind).is_null() {
+            return true;
+        }
+        instructions(function).into_iter().any(|inst| {
+            let line = LLVMGetDebugLocLine(inst);
+            line != 0 && self.regions.covers(&debug_file(inst), line)
+        })
+    }
+
+    unsafe fn constrain_function(
+        &mut self,
+        module: LLVMModuleRef,
+        context: LLVMContextRef,
+        builder: LLVMBuilderRef,
+        function: LLVMValueRef,
+    ) -> Result<(), FenvError> {
+        let strictfp = LLVMCreateEnumAttribute(context, attribute_kind("strictfp"), 0);
+        LLVMAddAttributeAtIndex(function, llvm_sys::LLVMAttributeFunctionIndex, strictfp);
+
+        let round = metadata(context, "round.dynamic");
+        let except = metadata(context, "fpexcept.strict");
+
+        for inst in instructions(function) {
+            let opcode = LLVMGetInstructionOpcode(inst);
+            let (name, overloads, args): (&str, Vec<LLVMTypeRef>, Vec<LLVMValueRef>) = match opcode {
+                LLVMOpcode::LLVMFAdd | LLVMOpcode::LLVMFSub | LLVMOpcode::LLVMFMul
+                | LLVMOpcode::LLVMFDiv | LLVMOpcode::LLVMFRem => {
+                    let name = match opcode {
+                        LLVMOpcode::LLVMFAdd => "llvm.experimental.constrained.fadd",
+                        LLVMOpcode::LLVMFSub => "llvm.experimental.constrained.fsub",
+                        LLVMOpcode::LLVMFMul => "llvm.experimental.constrained.fmul",
+                        LLVMOpcode::LLVMFDiv => "llvm.experimental.constrained.fdiv",
+                        _ => "llvm.experimental.constrained.frem",
+                    };
+                    (name, vec![LLVMTypeOf(inst)], vec![LLVMGetOperand(inst, 0), LLVMGetOperand(inst, 1), round, except])
+                }
+                LLVMOpcode::LLVMFCmp => match predicate_name(LLVMGetFCmpPredicate(inst)) {
+                    Some(predicate) => (
+                        "llvm.experimental.constrained.fcmp",
+                        vec![LLVMTypeOf(LLVMGetOperand(inst, 0))],
+                        vec![LLVMGetOperand(inst, 0), LLVMGetOperand(inst, 1), metadata(context, predicate), except],
+                    ),
+                    None => continue,
+                },
+                // Conversions that round take the rounding mode; the others only raise
+                LLVMOpcode::LLVMFPTrunc | LLVMOpcode::LLVMSIToFP | LLVMOpcode::LLVMUIToFP => {
+                    let name = match opcode {
+                        LLVMOpcode::LLVMFPTrunc => "llvm.experimental.constrained.fptrunc",
+                        LLVMOpcode::LLVMSIToFP => "llvm.experimental.constrained.sitofp",
+                        _ => "llvm.experimental.constrained.uitofp",
+                    };
+                    let value = LLVMGetOperand(inst, 0);
+                    (name, vec![LLVMTypeOf(inst), LLVMTypeOf(value)], vec![value, round, except])
+                }
+                LLVMOpcode::LLVMFPExt | LLVMOpcode::LLVMFPToSI | LLVMOpcode::LLVMFPToUI => {
+                    let name = match opcode {
+                        LLVMOpcode::LLVMFPExt => "llvm.experimental.constrained.fpext",
+                        LLVMOpcode::LLVMFPToSI => "llvm.experimental.constrained.fptosi",
+                        _ => "llvm.experimental.constrained.fptoui",
+                    };
+                    let value = LLVMGetOperand(inst, 0);
+                    (name, vec![LLVMTypeOf(inst), LLVMTypeOf(value)], vec![value, except])
+                }
+                // Calls into code that may change the mode must not be moved either
+                LLVMOpcode::LLVMCall => {
+                    LLVMAddCallSiteAttribute(inst, llvm_sys::LLVMAttributeFunctionIndex, strictfp);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let mut overloads = overloads;
+            let id = LLVMLookupIntrinsicID(name.as_ptr() as *const _, name.len());
+            if id == 0 {
+                return Err(FenvError::MissingIntrinsic(name.to_string()));
+            }
+            let decl = LLVMGetIntrinsicDeclaration(module, id, overloads.as_mut_ptr(), overloads.len());
+            let fn_ty = LLVMIntrinsicGetType(context, id, overloads.as_mut_ptr(), overloads.len());
+
+            LLVMPositionBuilderBefore(builder, inst);
+            let mut args = args;
+            let call = LLVMBuildCall2(builder, fn_ty, decl, args.as_mut_ptr(), args.len() as u32, c"".as_ptr());
+            LLVMAddCallSiteAttribute(call, llvm_sys::LLVMAttributeFunctionIndex, strictfp);
+            LLVMInstructionSetDebugLoc(call, LLVMInstructionGetDebugLoc(inst));
+
+            LLVMReplaceAllUsesWith(inst, call);
+            LLVMInstructionEraseFromParent(inst);
+            self.constrained += 1;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) unsafe fn instructions(function: LLVMValueRef) -> Vec<LLVMValueRef> {
+    let mut instructions = Vec::new();
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        let mut inst = LLVMGetFirstInstruction(block);
+        while !inst.is_null() {
+            instructions.push(inst);
+            inst = LLVMGetNextInstruction(inst);
+        }
+        block = LLVMGetNextBasicBlock(block);
+    }
+    instructions
+}
+
+pub(super) unsafe fn debug_file(inst: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetDebugLocFilename(inst, &mut len);
+    if name.is_null() {
+        return String::new();
+    }
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len as usize)).into_owned()
+}
+
+pub(super) unsafe fn attribute_kind(name: &str) -> u32 {
+    LLVMGetEnumAttributeKindForName(name.as_ptr() as *const _, name.len())
+}
+
+unsafe fn metadata(context: LLVMContextRef, text: &str) -> LLVMValueRef {
+    let node = LLVMMDStringInContext2(context, text.as_ptr() as *const _, text.len());
+    LLVMMetadataAsValue(context, node)
+}
+
+/// Constant-true/false compares don't touch the FPU and stay as they are
+fn predicate_name(predicate: LLVMRealPredicate) -> Option<&'static str> {
+    use LLVMRealPredicate::*;
+    Some(match predicate {
+        LLVMRealOEQ => "oeq",
+        LLVMRealOGT => "ogt",
+        LLVMRealOGE => "oge",
+        LLVMRealOLT => "olt",
+        LLVMRealOLE => "ole",
+        LLVMRealONE => "one",
+        LLVMRealORD => "ord",
+        LLVMRealUNO => "uno",
+        LLVMRealUEQ => "ueq",
+        LLVMRealUGT => "ugt",
+        LLVMRealUGE => "uge",
+        LLVMRealULT => "ult",
+        LLVMRealULE => "ule",
+        LLVMRealUNE => "une",
+        LLVMRealPredicateFalse | LLVMRealPredicateTrue => return None,
+    })
+}
+
+#[derive(Debug)]
+pub enum FenvError {
+    /// The linked LLVM predates the constrained FP intrinsics
+    MissingIntrinsic(String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), FenvError> {
+    let source = "#pragma STDC FENV_ACCESS ON\n#include <fenv.h>\ndouble third(void) { fesetround(FE_UPWARD); return 1.0 / 3.0; }\n";
+    let regions = FenvAccessRegions::scan(source);
+    assert!(regions.covers("<stdin>", 3));
+
+    unsafe {
+        let mut pass = FenvAccessPass::new(&regions);
+        pass.run(module)?;
+        println!("{:?}", pass.stats()); // (1, 1): `third` is strictfp, its fdiv constrained
+    }
+    Ok(())
+}
+*/
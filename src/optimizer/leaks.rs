@@ -0,0 +1,138 @@
+// src/optimizer/leaks.rs
+//! Allocation tracking for `--detect-leaks`
+//! Rewrites direct calls to the C library's allocator into calls to the
+//! `__ic_leak_*` host functions of `memory::leaks`, which keep the live
+//! blocks. Every allocating call gets a site: its function and source
+//! location, resolved through the debug SourceMap and passed as an extra
+//! `i64`. Runs before the optimizer, so that heap-to-stack promotion and
+//! inlining don't take calls out of sight. Calls through a pointer to
+//! `malloc` or `free` aren't rewritten.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::LLVMOpcode;
+use super::fenv::instructions;
+use super::sanitize::describe_location;
+use crate::debug::SourceMap;
+use crate::memory::leaks;
+
+/// The functions rewritten: name, pointer arguments, the size arguments
+/// after them, and whether it allocates (and so takes a site)
+const ALLOCATORS: &[(&str, usize, usize, bool)] = &[
+    ("malloc", 0, 1, true),
+    ("calloc", 0, 2, true),
+    ("realloc", 1, 1, true),
+    ("strdup", 1, 0, true),
+    ("strndup", 1, 1, true),
+    ("free", 1, 0, false),
+];
+
+pub struct LeakInstrumentation<'a> {
+    // Location lookup
+    source_map: Option<&'a SourceMap>,
+
+    // Statistics
+    calls: HashMap<&'static str, usize>,
+}
+
+impl<'a> LeakInstrumentation<'a> {
+    pub fn new(source_map: Option<&'a SourceMap>) -> Self {
+        LeakInstrumentation { source_map, calls: HashMap::new() }
+    }
+
+    /// Calls rewritten per C library function
+    pub fn stats(&self) -> &HashMap<&'static str, usize> {
+        &self.calls
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) {
+        let context = LLVMGetModuleContext(module);
+        let builder = LLVMCreateBuilderInContext(context);
+        let pointer = LLVMPointerTypeInContext(context, 0);
+        let i64 = LLVMInt64TypeInContext(context);
+
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            if LLVMCountBasicBlocks(function) > 0 {
+                let name = value_name(function);
+                for inst in instructions(function) {
+                    if LLVMGetInstructionOpcode(inst) != LLVMOpcode::LLVMCall {
+                        continue;
+                    }
+                    let callee = LLVMGetCalledValue(inst);
+                    if callee.is_null() || LLVMIsAFunction(callee).is_null() {
+                        continue;
+                    }
+                    let callee_name = value_name(callee);
+                    let Some(&(allocator, pointers, sizes, allocates)) =
+                        ALLOCATORS.iter().find(|(allocator, ..)| *allocator == callee_name)
+                    else {
+                        continue;
+                    };
+                    // A program's own `malloc`, or a prototype that doesn't match
+                    if LLVMCountBasicBlocks(callee) > 0 || LLVMGetNumArgOperands(inst) as usize != pointers + sizes {
+                        continue;
+                    }
+
+                    LLVMPositionBuilderBefore(builder, inst);
+                    let mut args: Vec<LLVMValueRef> = Vec::with_capacity(pointers + sizes + 1);
+                    let mut params: Vec<LLVMTypeRef> = Vec::with_capacity(pointers + sizes + 1);
+                    for index in 0..pointers {
+                        args.push(LLVMGetOperand(inst, index as u32));
+                        params.push(pointer);
+                    }
+                    for index in pointers..pointers + sizes {
+                        args.push(LLVMBuildIntCast2(builder, LLVMGetOperand(inst, index as u32), i64, 0, c"".as_ptr()));
+                        params.push(i64);
+                    }
+                    if allocates {
+                        let location = describe_location(self.source_map, inst);
+                        args.push(LLVMConstInt(i64, leaks::register_site(&name, &location), 0));
+                        params.push(i64);
+                    }
+
+                    let returns = if allocates { pointer } else { LLVMVoidTypeInContext(context) };
+                    let ty = LLVMFunctionType(returns, params.as_mut_ptr(), params.len() as u32, 0);
+                    let tracker = get_or_declare(module, &format!("__ic_leak_{}", allocator), ty);
+                    let call = LLVMBuildCall2(builder, ty, tracker, args.as_mut_ptr(), args.len() as u32, c"".as_ptr());
+                    if allocates {
+                        LLVMReplaceAllUsesWith(inst, call);
+                    }
+                    LLVMInstructionEraseFromParent(inst);
+                    *self.calls.entry(allocator).or_insert(0) += 1;
+                }
+            }
+            function = LLVMGetNextFunction(function);
+        }
+
+        LLVMDisposeBuilder(builder);
+    }
+}
+
+unsafe fn get_or_declare(module: LLVMModuleRef, name: &str, ty: LLVMTypeRef) -> LLVMValueRef {
+    let c_name = CString::new(name).expect("allocator names have no NUL");
+    let existing = LLVMGetNamedFunction(module, c_name.as_ptr());
+    if !existing.is_null() {
+        return existing;
+    }
+    LLVMAddFunction(module, c_name.as_ptr(), ty)
+}
+
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(value, &mut len);
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len)).into_owned()
+}
+
+// Example usage:
+/*
+fn main() {
+    // char *copy = strdup(name);  at list.c:12:18, in make_list
+    let mut pass = LeakInstrumentation::new(Some(&source_map));
+    unsafe { pass.run(module) };
+    println!("{:?}", pass.stats()); // {"strdup": 1, "free": 2}
+    // %copy = call ptr @__ic_leak_strdup(ptr %name, i64 0)
+}
+*/
@@ -0,0 +1,517 @@
+// src/optimizer/linkage.rs
+//! Weak, alias and versioned symbols
+//! The IR generator gives every global external linkage and doesn't know
+//! `__attribute__((weak))`, `__attribute__((alias("target")))`,
+//! `__attribute__((symver("name@VERSION")))`, their `[[gnu::...]]`
+//! spellings or `#pragma weak`, so a weak default in a library would clash
+//! with the program's own definition and an alias wouldn't exist at all.
+//!
+//! The attributes are found by scanning the preprocessed source (see
+//! `SymbolAttributes::scan`) and applied to the module before optimization:
+//! weak definitions get weak linkage and weak declarations extern_weak, an
+//! alias becomes a `GlobalAlias` of its target, and a symbol version a
+//! `.symver` directive. The linkers then resolve them by the rules in
+//! `linker::symbols`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CString;
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::LLVMLinkage;
+
+/// The linkage attributes the source gives each symbol
+#[derive(Debug, Clone, Default)]
+pub struct SymbolAttributes {
+    weak: BTreeSet<String>,
+    /// Alias name to target
+    aliases: BTreeMap<String, String>,
+    /// Symbol to its `name@VERSION` or `name@@VERSION` names
+    versions: BTreeMap<String, Vec<String>>,
+}
+
+impl SymbolAttributes {
+    pub fn scan(source: &str) -> Self {
+        let mut attributes = SymbolAttributes::default();
+        let tokens = tokenize(source, &mut attributes);
+        attributes.scan_declarations(&tokens);
+        attributes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weak.is_empty() && self.aliases.is_empty() && self.versions.is_empty()
+    }
+
+    pub fn is_weak(&self, name: &str) -> bool {
+        self.weak.contains(name)
+    }
+
+    pub fn alias_target(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    /// Walk the file-scope declarations: attributes anywhere in one apply
+    /// to the name it declares, which is the last identifier before its
+    /// parameter list, initializer, array bound or end. Function bodies and
+    /// initializers are skipped.
+    fn scan_declarations(&mut self, tokens: &[Token]) {
+        let mut pending = Pending::default();
+        let mut name: Option<&str> = None;
+        let mut named = false;
+        let mut after_parameters = false;
+        let mut i = 0;
+        while i < tokens.len() {
+            match &tokens[i] {
+                Token::Ident(word) if is_attribute_keyword(word) => {
+                    i = parse_gnu_attribute(tokens, i + 1, &mut pending);
+                    continue;
+                }
+                Token::Ident(word) if matches!(word.as_str(), "asm" | "__asm" | "__asm__") => {
+                    // An assembler name: `int f(void) __asm__("g");`
+                    i = skip_group(tokens, i + 1);
+                    continue;
+                }
+                Token::Punct('[') if tokens.get(i + 1) == Some(&Token::Punct('[')) => {
+                    i = parse_standard_attribute(tokens, i + 2, &mut pending);
+                    continue;
+                }
+                Token::Ident(word) if !named => {
+                    name = Some(word);
+                    after_parameters = false;
+                }
+                Token::Punct('(') if !named || after_parameters => {
+                    // The parameter list; `(*fp)(int)` isn't worth telling apart
+                    named = true;
+                    i = skip_group(tokens, i);
+                    after_parameters = true;
+                    continue;
+                }
+                Token::Punct('=') | Token::Punct('[') => {
+                    named = true;
+                    after_parameters = false;
+                    i = skip_initializer(tokens, i + 1);
+                    continue;
+                }
+                Token::Punct('{') if after_parameters => {
+                    // A function body ends the declaration
+                    i = skip_group(tokens, i);
+                    self.apply(name, &pending);
+                    pending = Pending::default();
+                    (name, named, after_parameters) = (None, false, false);
+                    continue;
+                }
+                Token::Punct('{') => {
+                    // A struct, union or enum body
+                    i = skip_group(tokens, i);
+                    continue;
+                }
+                Token::Punct(',') => {
+                    self.apply(name, &pending);
+                    (name, named, after_parameters) = (None, false, false);
+                }
+                Token::Punct(';') => {
+                    self.apply(name, &pending);
+                    pending = Pending::default();
+                    (name, named, after_parameters) = (None, false, false);
+                }
+                _ => after_parameters = false,
+            }
+            i += 1;
+        }
+    }
+
+    fn apply(&mut self, name: Option<&str>, pending: &Pending) {
+        let Some(name) = name else { return };
+        if pending.weak {
+            self.weak.insert(name.to_string());
+        }
+        if let Some(target) = &pending.alias {
+            self.aliases.insert(name.to_string(), target.clone());
+        }
+        for version in &pending.versions {
+            self.versions.entry(name.to_string()).or_default().push(version.clone());
+        }
+    }
+}
+
+/// Attributes seen so far in one declaration
+#[derive(Default)]
+struct Pending {
+    weak: bool,
+    alias: Option<String>,
+    versions: Vec<String>,
+}
+
+impl Pending {
+    fn add(&mut self, attribute: &str, argument: Option<&str>) {
+        match (attribute.trim_matches('_'), argument) {
+            ("weak", _) => self.weak = true,
+            ("alias", Some(target)) => self.alias = Some(target.to_string()),
+            ("symver", Some(version)) => self.versions.push(version.to_string()),
+            _ => {}
+        }
+    }
+}
+
+fn is_attribute_keyword(word: &str) -> bool {
+    matches!(word, "__attribute__" | "__attribute")
+}
+
+/// `((name, name("arg"), ...))` starting at `i`; returns the index past it
+fn parse_gnu_attribute(tokens: &[Token], i: usize, pending: &mut Pending) -> usize {
+    if tokens.get(i) != Some(&Token::Punct('(')) {
+        return i;
+    }
+    let end = skip_group(tokens, i);
+    parse_attribute_list(&tokens[i + 1..end.saturating_sub(1)], pending);
+    end
+}
+
+/// `gnu::name, gnu::name("arg")]]` starting at `i`; returns the index past it
+fn parse_standard_attribute(tokens: &[Token], i: usize, pending: &mut Pending) -> usize {
+    let mut end = i;
+    let mut depth = 0usize;
+    while end < tokens.len() {
+        match tokens[end] {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') => depth = depth.saturating_sub(1),
+            Token::Punct(']') if depth == 0 => break,
+            _ => {}
+        }
+        end += 1;
+    }
+    // Only the gnu:: attributes mean anything here
+    let list: Vec<Token> = tokens[i..end]
+        .iter()
+        .enumerate()
+        .filter(|&(at, token)| {
+            let namespace = matches!(token, Token::Ident(word) if word == "gnu" || word == "__gnu__")
+                && tokens.get(i + at + 1) == Some(&Token::Punct(':'));
+            !namespace && *token != Token::Punct(':')
+        })
+        .map(|(_, token)| token.clone())
+        .collect();
+    parse_attribute_list(&list, pending);
+    (end + 2).min(tokens.len())
+}
+
+/// The inside of an attribute list: `(weak, alias("x"))` without the outer
+/// parentheses
+fn parse_attribute_list(tokens: &[Token], pending: &mut Pending) {
+    let inner = match tokens {
+        [Token::Punct('('), inner @ .., Token::Punct(')')] => inner,
+        inner => inner,
+    };
+    let mut i = 0;
+    while i < inner.len() {
+        if let Token::Ident(attribute) = &inner[i] {
+            let argument = match (inner.get(i + 1), inner.get(i + 2)) {
+                (Some(Token::Punct('(')), Some(Token::Str(argument))) => Some(argument.as_str()),
+                _ => None,
+            };
+            pending.add(attribute, argument);
+        }
+        i = match inner.get(i + 1) {
+            Some(Token::Punct('(')) => skip_group(inner, i + 1),
+            _ => i + 1,
+        };
+    }
+}
+
+/// Index past the bracketed group opening at `i`
+fn skip_group(tokens: &[Token], i: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = i;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Punct('(' | '[' | '{') => depth += 1,
+            Token::Punct(')' | ']' | '}') => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Index of the `,` or `;` that ends an initializer or array bound
+fn skip_initializer(tokens: &[Token], i: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = i;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Punct('(' | '[' | '{') => depth += 1,
+            Token::Punct(')' | ']' | '}') => depth = depth.saturating_sub(1),
+            Token::Punct(',' | ';') if depth == 0 => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    i
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    /// Contents of a string literal, escapes left as they are
+    Str(String),
+    Punct(char),
+    Other,
+}
+
+/// Tokens of the preprocessed source; `#pragma weak` lines go straight into
+/// `attributes`, other directives and line markers are skipped
+fn tokenize(source: &str, attributes: &mut SymbolAttributes) -> Vec<Token> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line_start = true;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\n' {
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == b'#' && line_start {
+            let end = source[i..].find('\n').map_or(source.len(), |at| i + at);
+            pragma_weak(&source[i + 1..end], attributes);
+            i = end;
+            continue;
+        }
+        line_start = false;
+
+        if c == b'/' && bytes.get(i + 1) == Some(&b'/') {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            i += 2;
+            while i + 1 < bytes.len() && !(bytes[i] == b'*' && bytes[i + 1] == b'/') {
+                i += 1;
+            }
+            i = (i + 2).min(bytes.len());
+            continue;
+        }
+
+        if c == b'"' || c == b'\'' {
+            let start = i + 1;
+            i += 1;
+            while i < bytes.len() && bytes[i] != c && bytes[i] != b'\n' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            let end = i.min(bytes.len());
+            i = (i + 1).min(bytes.len());
+            // Adjacent literals concatenate
+            let text = &source[start..end];
+            match tokens.last_mut() {
+                Some(Token::Str(previous)) if c == b'"' => previous.push_str(text),
+                _ if c == b'"' => tokens.push(Token::Str(text.to_string())),
+                _ => tokens.push(Token::Other),
+            }
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'$') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(source[start..i].to_string()));
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.' || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push(Token::Other);
+        } else if c.is_ascii() {
+            tokens.push(Token::Punct(c as char));
+            i += 1;
+        } else {
+            // Identifiers outside ASCII don't name anything here
+            i += source[i..].chars().next().map_or(1, char::len_utf8);
+            tokens.push(Token::Other);
+        }
+    }
+    tokens
+}
+
+/// `#pragma weak name` or `#pragma weak name = target`
+fn pragma_weak(directive: &str, attributes: &mut SymbolAttributes) {
+    let directive = directive.replace('=', " = ");
+    let words: Vec<&str> = directive.split_whitespace().collect();
+    match words.as_slice() {
+        ["pragma", "weak", name] => {
+            attributes.weak.insert(name.to_string());
+        }
+        ["pragma", "weak", name, "=", target] => {
+            attributes.weak.insert(name.to_string());
+            attributes.aliases.insert(name.to_string(), target.to_string());
+        }
+        _ => {}
+    }
+}
+
+pub struct LinkagePass<'a> {
+    attributes: &'a SymbolAttributes,
+
+    // Statistics
+    weakened: usize,
+    aliased: usize,
+    versioned: usize,
+}
+
+impl<'a> LinkagePass<'a> {
+    pub fn new(attributes: &'a SymbolAttributes) -> Self {
+        LinkagePass {
+            attributes,
+            weakened: 0,
+            aliased: 0,
+            versioned: 0,
+        }
+    }
+
+    /// (symbols made weak, aliases created, versions given)
+    pub fn stats(&self) -> (usize, usize, usize) {
+        (self.weakened, self.aliased, self.versioned)
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> Result<(), LinkageError> {
+        // Aliases first, so that weak applies to them too
+        for (alias, target) in &self.attributes.aliases {
+            self.add_alias(module, alias, target)?;
+        }
+
+        for name in &self.attributes.weak {
+            let Some(global) = named_global(module, name) else { continue };
+            let linkage = if is_definition(global) {
+                LLVMLinkage::LLVMWeakAnyLinkage
+            } else {
+                LLVMLinkage::LLVMExternalWeakLinkage
+            };
+            LLVMSetLinkage(global, linkage);
+            self.weakened += 1;
+        }
+
+        for (name, versions) in &self.attributes.versions {
+            let Some(global) = named_global(module, name) else { continue };
+            if !is_definition(global) {
+                return Err(LinkageError::UndefinedVersionedSymbol(name.clone()));
+            }
+            for version in versions {
+                if crate::linker::symbols::Versioned::parse(version).is_none() {
+                    return Err(LinkageError::InvalidVersion(version.clone()));
+                }
+                let directive = format!(".symver {}, {}", name, version);
+                LLVMAppendModuleInlineAsm(module, directive.as_ptr() as *const _, directive.len());
+                self.versioned += 1;
+            }
+            // A symbol that's only there to be versioned mustn't be dropped
+            // as unused
+            if LLVMGetLinkage(global) == LLVMLinkage::LLVMInternalLinkage {
+                LLVMSetLinkage(global, LLVMLinkage::LLVMExternalLinkage);
+            }
+        }
+        Ok(())
+    }
+
+    /// `alias` as another name for `target`, replacing any declaration of
+    /// `alias` the IR generator made
+    unsafe fn add_alias(&mut self, module: LLVMModuleRef, alias: &str, target: &str) -> Result<(), LinkageError> {
+        let aliasee = match named_global(module, target) {
+            Some(aliasee) if is_definition(aliasee) => aliasee,
+            _ => return Err(LinkageError::UndefinedAliasTarget { alias: alias.to_string(), target: target.to_string() }),
+        };
+        let existing = named_global(module, alias);
+        if let Some(existing) = existing {
+            if is_definition(existing) {
+                return Err(LinkageError::AliasRedefined(alias.to_string()));
+            }
+            // Free the name for the alias
+            LLVMSetValueName2(existing, c"".as_ptr(), 0);
+        }
+
+        let name = CString::new(alias).map_err(|_| LinkageError::AliasRedefined(alias.to_string()))?;
+        let address_space = LLVMGetPointerAddressSpace(LLVMTypeOf(aliasee));
+        let value = LLVMAddAlias2(module, LLVMGlobalGetValueType(aliasee), address_space, aliasee, name.as_ptr());
+
+        if let Some(existing) = existing {
+            LLVMReplaceAllUsesWith(existing, value);
+            if LLVMIsAFunction(existing).is_null() {
+                LLVMDeleteGlobal(existing);
+            } else {
+                LLVMDeleteFunction(existing);
+            }
+        }
+        self.aliased += 1;
+        Ok(())
+    }
+}
+
+unsafe fn named_global(module: LLVMModuleRef, name: &str) -> Option<LLVMValueRef> {
+    let c_name = CString::new(name).ok()?;
+    let function = LLVMGetNamedFunction(module, c_name.as_ptr());
+    if !function.is_null() {
+        return Some(function);
+    }
+    let global = LLVMGetNamedGlobal(module, c_name.as_ptr());
+    if !global.is_null() {
+        return Some(global);
+    }
+    let alias = LLVMGetNamedGlobalAlias(module, name.as_ptr() as *const _, name.len());
+    (!alias.is_null()).then_some(alias)
+}
+
+/// A function with a body, a variable with an initializer, or an alias
+unsafe fn is_definition(global: LLVMValueRef) -> bool {
+    if !LLVMIsAFunction(global).is_null() {
+        LLVMCountBasicBlocks(global) > 0
+    } else if !LLVMIsAGlobalVariable(global).is_null() {
+        !LLVMGetInitializer(global).is_null()
+    } else {
+        !LLVMIsAGlobalAlias(global).is_null()
+    }
+}
+
+#[derive(Debug)]
+pub enum LinkageError {
+    /// `alias("target")` where the translation unit doesn't define target
+    UndefinedAliasTarget { alias: String, target: String },
+    /// An alias with the name of a definition
+    AliasRedefined(String),
+    /// `symver` on a symbol the translation unit doesn't define
+    UndefinedVersionedSymbol(String),
+    /// Not `name@VERSION` or `name@@VERSION`
+    InvalidVersion(String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), LinkageError> {
+    let source = r#"
+        int real_open(const char *path) { return 0; }
+        int open(const char *path) __attribute__((weak, alias("real_open")));
+        [[gnu::weak]] void on_exit_hook(void);
+        #pragma weak malloc_hook
+        int compute_v2(int x) __attribute__((symver("compute@@LIB_2.0"))) { return x; }
+    "#;
+    let attributes = SymbolAttributes::scan(source);
+    assert!(attributes.is_weak("open") && attributes.is_weak("on_exit_hook"));
+    assert_eq!(attributes.alias_target("open"), Some("real_open"));
+
+    unsafe {
+        let module = compile_to_module(source);
+        let mut pass = LinkagePass::new(&attributes);
+        pass.run(module)?;
+        let (weakened, aliased, versioned) = pass.stats();
+        println!("{} weak, {} aliases, {} versions", weakened, aliased, versioned);
+    }
+    Ok(())
+}
+*/
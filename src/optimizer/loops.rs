@@ -0,0 +1,592 @@
+// src/optimizer/loops.rs
+//! Loop interchange and loop fusion (-O3)
+//! Two transformations the default pipeline leaves out, run on the module
+//! before it at -O3. Interchange swaps the loops of a nest so that the
+//! innermost one walks memory contiguously: `for (j) for (i) a[i][j]`
+//! touches a new cache line every iteration, `for (i) for (j) a[i][j]` a
+//! new one every sixteen. Fusion merges adjacent loops with the same trip
+//! count into one, so data the first loop produced is still in cache when
+//! the second consumes it and the loop overhead is paid once.
+//!
+//! The transformations are LLVM's `loop-interchange` and `loop-fusion`,
+//! and so are the legality checks: both query dependence analysis, backed
+//! by the module's alias analysis, and back off unless every dependence
+//! keeps its direction. Interchange needs a dependence matrix that stays
+//! lexicographically positive once its columns are swapped, and decides
+//! profitability with the cache cost model; fusion needs control flow
+//! equivalent loops with equal trip counts and no dependence from a later
+//! iteration of the second loop to an earlier one of the first. Both skip
+//! `optnone` functions, which includes the ones `optimizer::budget` gave up
+//! on.
+//!
+//! In JIT mode the nests are tiled for the host's caches first; see
+//! `optimizer::tiling`.
+//!
+//! The loops are put into the form the passes expect first (SSA induction
+//! variables, preheaders, dedicated exits, LCSSA, rotated), and measured on
+//! either side of the transformations; see `metrics::loop_metrics`. The
+//! measurement reads the CFG directly: natural loops found through the
+//! dominator tree, and array accesses classified by which GEP index the
+//! innermost loop's induction variable reaches.
+
+#[cfg(feature = "llvm")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "llvm")]
+use std::ffi::{CStr, CString};
+use std::fmt;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::target_machine::LLVMTargetMachineRef;
+#[cfg(feature = "llvm")]
+use llvm_sys::transforms::pass_builder::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMOpcode, LLVMTypeKind};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "llvm")]
+use crate::analysis::stack_depth::value_name;
+#[cfg(feature = "llvm")]
+use crate::analysis::wcet::immediate_dominators;
+#[cfg(feature = "llvm")]
+use crate::metrics::loop_metrics::{LoopNestMetrics, LoopTransformMetrics};
+#[cfg(feature = "llvm")]
+use crate::report::{OptimizationRemark, RemarkKind};
+#[cfg(feature = "llvm")]
+use super::tiling::{self, CacheGeometry, LoopTilingPass};
+
+/// Pass name the remarks are filed under
+pub const REMARK_PASS: &str = "loop-transforms";
+
+/// Canonical loop form for both transformations
+#[cfg(feature = "llvm")]
+const CANONICALIZE: &str = "function(sroa,early-cse,simplifycfg,instcombine,loop-simplify,lcssa,loop(loop-rotate))";
+
+/// Blocks between two loops that `adjacent` looks through
+#[cfg(feature = "llvm")]
+const MAX_GAP: usize = 3;
+
+/// Which transformations run at -O3 (`-fno-loop-interchange`,
+/// `-fno-loop-fusion`, `-fno-loop-tiling`); all by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoopTransforms {
+    pub interchange: bool,
+    pub fusion: bool,
+    /// JIT mode only: the tiles are sized for the host's caches
+    pub tiling: bool,
+}
+
+impl Default for LoopTransforms {
+    fn default() -> Self {
+        LoopTransforms {
+            interchange: true,
+            fusion: true,
+            tiling: true,
+        }
+    }
+}
+
+impl LoopTransforms {
+    pub fn is_empty(&self) -> bool {
+        !self.interchange && !self.fusion && !self.tiling
+    }
+
+    /// Interchange first, so that adjacent nests iterate in the same order
+    /// by the time fusion compares them
+    #[cfg(feature = "llvm")]
+    fn pipeline(&self) -> String {
+        let mut passes = Vec::new();
+        if self.interchange {
+            passes.push("loop(indvars,loop-interchange)");
+        }
+        if self.fusion {
+            passes.push("loop-simplify,lcssa,loop-fusion");
+        }
+        format!("function({})", passes.join(","))
+    }
+}
+
+#[cfg(feature = "llvm")]
+pub struct LoopTransformPass {
+    transforms: LoopTransforms,
+    target_machine: LLVMTargetMachineRef,
+    /// Set when tiles can be sized for the machine the code runs on
+    tiling: Option<LoopTilingPass>,
+    /// Function, before, after, tile loops added; functions without loops
+    /// are left out
+    functions: Vec<(String, LoopNestMetrics, LoopNestMetrics, usize)>,
+}
+
+#[cfg(feature = "llvm")]
+impl LoopTransformPass {
+    pub fn new(transforms: LoopTransforms, target_machine: LLVMTargetMachineRef) -> Self {
+        LoopTransformPass {
+            transforms,
+            target_machine,
+            tiling: None,
+            functions: Vec::new(),
+        }
+    }
+
+    /// Tile for `cache` too, if `LoopTransforms::tiling` allows
+    pub fn with_tiling(mut self, cache: CacheGeometry) -> Self {
+        self.tiling = self.transforms.tiling.then(|| LoopTilingPass::new(cache));
+        self
+    }
+
+    /// Tile, interchange and fuse the loops of `module`, returning its loop
+    /// metrics before and after
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> Result<LoopTransformMetrics, LoopTransformError> {
+        let reorder = self.transforms.interchange || self.transforms.fusion;
+        if !reorder && self.tiling.is_none() {
+            return Ok(LoopTransformMetrics::default());
+        }
+        // Tiling matches header-tested loops, so it goes before rotation
+        let before = match &mut self.tiling {
+            Some(tiling) => {
+                run_passes(module, tiling::CANONICALIZE, self.target_machine)?;
+                let before = survey(module);
+                tiling.run(module);
+                run_passes(module, CANONICALIZE, self.target_machine)?;
+                before
+            }
+            None => {
+                run_passes(module, CANONICALIZE, self.target_machine)?;
+                survey(module)
+            }
+        };
+        if reorder {
+            run_passes(module, &self.transforms.pipeline(), self.target_machine)?;
+        }
+        let mut after = survey(module);
+
+        let mut metrics = LoopTransformMetrics::default();
+        self.functions.clear();
+        for (name, before) in before {
+            let after = after.remove(&name).unwrap_or_default();
+            let added = self.tiling.as_ref().map_or(0, |tiling| tiling.added(&name));
+            metrics.before.combine(&before);
+            metrics.after.combine(&after);
+            metrics.tile_loops += added;
+            self.functions.push((name, before, after, added));
+        }
+        metrics.tiled = self.tiling.as_ref().map_or(0, |tiling| tiling.stats().0);
+        self.functions.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(metrics)
+    }
+
+    /// What changed in each function, and the candidates that were left
+    /// alone
+    pub fn remarks(&self) -> Vec<OptimizationRemark> {
+        let mut remarks = Vec::new();
+        let mut remark = |function: &str, kind: RemarkKind, message: String| {
+            remarks.push(OptimizationRemark {
+                pass: REMARK_PASS.to_string(),
+                function: function.to_string(),
+                kind,
+                message,
+            });
+        };
+        for (name, before, after, added) in &self.functions {
+            let fused = (before.loops + added).saturating_sub(after.loops);
+            if fused > 0 {
+                remark(name, RemarkKind::Applied, format!("fused {} loop(s) into the loop before them", fused));
+            }
+            if after.strided < before.strided {
+                remark(
+                    name,
+                    RemarkKind::Applied,
+                    format!("interchanged loops; strided accesses in innermost loops {} -> {}", before.strided, after.strided),
+                );
+            }
+            if self.transforms.fusion && after.adjacent > 0 {
+                remark(
+                    name,
+                    RemarkKind::Missed,
+                    format!("{} pair(s) of adjacent loops not fused: dependences, trip counts or control flow", after.adjacent),
+                );
+            }
+            if self.transforms.interchange && after.strided > 0 && after.nests > 0 {
+                remark(
+                    name,
+                    RemarkKind::Missed,
+                    format!("{} strided access(es) left in innermost loops: not legal or not profitable to interchange", after.strided),
+                );
+            }
+        }
+        if let Some(tiling) = &self.tiling {
+            remarks.extend(tiling.remarks().iter().cloned());
+        }
+        remarks
+    }
+
+    /// (functions with loops, loops fused, strided accesses made contiguous)
+    pub fn stats(&self) -> (usize, usize, usize) {
+        let fused = self.functions.iter().map(|(_, before, after, added)| (before.loops + added).saturating_sub(after.loops)).sum();
+        let contiguous = self.functions.iter().map(|(_, before, after, _)| before.strided.saturating_sub(after.strided)).sum();
+        (self.functions.len(), fused, contiguous)
+    }
+}
+
+/// Loop metrics of every defined function with loops
+#[cfg(feature = "llvm")]
+pub unsafe fn survey(module: LLVMModuleRef) -> HashMap<String, LoopNestMetrics> {
+    let mut functions = HashMap::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMIsDeclaration(function) == 0 {
+            let metrics = survey_function(function);
+            if metrics.loops > 0 {
+                functions.insert(value_name(function), metrics);
+            }
+        }
+        function = LLVMGetNextFunction(function);
+    }
+    functions
+}
+
+/// A natural loop, by block index into `LoopForest::blocks`
+#[cfg(feature = "llvm")]
+pub(crate) struct Loop {
+    pub header: usize,
+    pub body: HashSet<usize>,
+    /// Innermost loop containing this one
+    pub parent: Option<usize>,
+    pub depth: usize,
+    pub innermost: bool,
+}
+
+/// The CFG of a function and its natural loops, outermost first
+#[cfg(feature = "llvm")]
+pub(crate) struct LoopForest {
+    pub blocks: Vec<LLVMBasicBlockRef>,
+    pub successors: Vec<Vec<usize>>,
+    /// Reachable predecessors only
+    pub predecessors: Vec<Vec<usize>>,
+    pub loops: Vec<Loop>,
+}
+
+#[cfg(feature = "llvm")]
+pub(crate) unsafe fn find_loops(function: LLVMValueRef) -> LoopForest {
+    let mut blocks = Vec::new();
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        blocks.push(block);
+        block = LLVMGetNextBasicBlock(block);
+    }
+    let index: HashMap<LLVMBasicBlockRef, usize> = blocks.iter().enumerate().map(|(i, &block)| (block, i)).collect();
+    let successors: Vec<Vec<usize>> = blocks
+        .iter()
+        .map(|&block| {
+            let terminator = LLVMGetBasicBlockTerminator(block);
+            if terminator.is_null() {
+                return Vec::new();
+            }
+            (0..LLVMGetNumSuccessors(terminator)).map(|i| index[&LLVMGetSuccessor(terminator, i)]).collect()
+        })
+        .collect();
+
+    // Reverse postorder from the entry; unreachable blocks stay at MAX
+    let count = blocks.len();
+    let mut rpo = vec![usize::MAX; count];
+    let mut order = Vec::with_capacity(count);
+    let mut visited = vec![false; count];
+    let mut work = vec![(0, 0)];
+    visited[0] = true;
+    while let Some(&(block, edge)) = work.last() {
+        if let Some(&successor) = successors[block].get(edge) {
+            work.last_mut().unwrap().1 += 1;
+            if !visited[successor] {
+                visited[successor] = true;
+                work.push((successor, 0));
+            }
+            continue;
+        }
+        work.pop();
+        order.push(block);
+    }
+    order.reverse();
+    for (position, &block) in order.iter().enumerate() {
+        rpo[block] = position;
+    }
+    let mut predecessors = vec![Vec::new(); count];
+    for &block in &order {
+        for &successor in &successors[block] {
+            predecessors[successor].push(block);
+        }
+    }
+    let dominators = immediate_dominators(&order, &rpo, &predecessors);
+    let dominates = |a: usize, mut b: usize| loop {
+        if a == b {
+            return true;
+        }
+        if b == dominators[b] {
+            return false;
+        }
+        b = dominators[b];
+    };
+
+    // Natural loops, one per header; irreducible cycles aren't loops to
+    // either pass and are left out
+    let mut latches: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &block in &order {
+        for &successor in &successors[block] {
+            if dominates(successor, block) {
+                latches.entry(successor).or_default().push(block);
+            }
+        }
+    }
+    let mut loops: Vec<Loop> = latches
+        .into_iter()
+        .map(|(header, latches)| {
+            let mut body = HashSet::from([header]);
+            let mut work = latches;
+            while let Some(block) = work.pop() {
+                if body.insert(block) {
+                    work.extend(predecessors[block].iter().copied());
+                }
+            }
+            Loop { header, body, parent: None, depth: 1, innermost: true }
+        })
+        .collect();
+    loops.sort_by_key(|l| rpo[l.header]);
+    for i in 0..loops.len() {
+        loops[i].parent = (0..loops.len())
+            .filter(|&j| j != i && loops[j].body.contains(&loops[i].header))
+            .min_by_key(|&j| loops[j].body.len());
+        if let Some(parent) = loops[i].parent {
+            loops[parent].innermost = false;
+        }
+    }
+    for i in 0..loops.len() {
+        let mut parent = loops[i].parent;
+        while let Some(p) = parent {
+            loops[i].depth += 1;
+            parent = loops[p].parent;
+        }
+    }
+    LoopForest { blocks, successors, predecessors, loops }
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn survey_function(function: LLVMValueRef) -> LoopNestMetrics {
+    let LoopForest { blocks, successors, loops, .. } = find_loops(function);
+    let mut metrics = LoopNestMetrics {
+        loops: loops.len(),
+        nests: loops.iter().filter(|l| l.parent.is_none() && !l.innermost).count(),
+        max_depth: loops.iter().map(|l| l.depth).max().unwrap_or(0),
+        ..LoopNestMetrics::default()
+    };
+    for (i, first) in loops.iter().enumerate() {
+        for (j, second) in loops.iter().enumerate() {
+            if i != j && first.parent == second.parent && adjacent(first, second, &loops, &blocks, &successors) {
+                metrics.adjacent += 1;
+            }
+        }
+    }
+    for l in loops.iter().filter(|l| l.innermost) {
+        let induction: Vec<LLVMValueRef> = phis(blocks[l.header])
+            .into_iter()
+            .filter(|&phi| LLVMGetTypeKind(LLVMTypeOf(phi)) == LLVMTypeKind::LLVMIntegerTypeKind)
+            .collect();
+        for &block in &l.body {
+            let mut instruction = LLVMGetFirstInstruction(blocks[block]);
+            while !instruction.is_null() {
+                let pointer = match LLVMGetInstructionOpcode(instruction) {
+                    LLVMOpcode::LLVMLoad => Some(LLVMGetOperand(instruction, 0)),
+                    LLVMOpcode::LLVMStore => Some(LLVMGetOperand(instruction, 1)),
+                    _ => None,
+                };
+                match pointer.and_then(|pointer| access_stride(pointer, &induction)) {
+                    Some(Stride::Contiguous) => metrics.contiguous += 1,
+                    Some(Stride::Strided) => metrics.strided += 1,
+                    None => {}
+                }
+                instruction = LLVMGetNextInstruction(instruction);
+            }
+        }
+    }
+    metrics
+}
+
+/// Whether `second` starts within `MAX_GAP` blocks of `first`'s only exit,
+/// through blocks outside both that only branch and compute
+#[cfg(feature = "llvm")]
+unsafe fn adjacent(first: &Loop, second: &Loop, loops: &[Loop], blocks: &[LLVMBasicBlockRef], successors: &[Vec<usize>]) -> bool {
+    let mut exits: Vec<usize> = first
+        .body
+        .iter()
+        .flat_map(|&block| successors[block].iter().copied())
+        .filter(|block| !first.body.contains(block))
+        .collect();
+    exits.sort_unstable();
+    exits.dedup();
+    let [exit] = exits[..] else { return false };
+
+    let in_other_loop = |block: usize| loops.iter().any(|l| l.body.contains(&block) && !l.body.contains(&first.header));
+    let mut frontier = vec![exit];
+    let mut seen = HashSet::from([exit]);
+    for _ in 0..=MAX_GAP {
+        let mut next = Vec::new();
+        for block in frontier {
+            if block == second.header {
+                return true;
+            }
+            if in_other_loop(block) || has_side_effects(blocks[block]) {
+                continue;
+            }
+            next.extend(successors[block].iter().copied().filter(|&successor| seen.insert(successor)));
+        }
+        frontier = next;
+    }
+    false
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn has_side_effects(block: LLVMBasicBlockRef) -> bool {
+    let mut instruction = LLVMGetFirstInstruction(block);
+    while !instruction.is_null() {
+        if matches!(
+            LLVMGetInstructionOpcode(instruction),
+            LLVMOpcode::LLVMStore
+                | LLVMOpcode::LLVMCall
+                | LLVMOpcode::LLVMInvoke
+                | LLVMOpcode::LLVMAtomicRMW
+                | LLVMOpcode::LLVMAtomicCmpXchg
+                | LLVMOpcode::LLVMFence
+        ) {
+            return true;
+        }
+        instruction = LLVMGetNextInstruction(instruction);
+    }
+    false
+}
+
+#[cfg(feature = "llvm")]
+pub(crate) unsafe fn phis(block: LLVMBasicBlockRef) -> Vec<LLVMValueRef> {
+    let mut phis = Vec::new();
+    let mut instruction = LLVMGetFirstInstruction(block);
+    while !instruction.is_null() && LLVMGetInstructionOpcode(instruction) == LLVMOpcode::LLVMPHI {
+        phis.push(instruction);
+        instruction = LLVMGetNextInstruction(instruction);
+    }
+    phis
+}
+
+#[cfg(feature = "llvm")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stride {
+    Contiguous,
+    Strided,
+}
+
+/// How an access through `pointer` moves as the innermost loop's
+/// induction variables step: row-major, so only the last index of an
+/// array is contiguous. `None` for pointers that aren't array accesses or
+/// don't move.
+#[cfg(feature = "llvm")]
+pub(crate) unsafe fn access_stride(pointer: LLVMValueRef, induction: &[LLVMValueRef]) -> Option<Stride> {
+    let mut gep = LLVMIsAGetElementPtrInst(pointer);
+    if gep.is_null() {
+        return None;
+    }
+    let operands = LLVMGetNumOperands(gep) as u32;
+    match index_stride(LLVMGetOperand(gep, operands - 1), induction, 0) {
+        Some(false) => return Some(Stride::Contiguous),
+        Some(true) => return Some(Stride::Strided),
+        None => {}
+    }
+    // The earlier indices of this GEP and of the GEPs it's based on
+    let mut last = operands - 1;
+    while !gep.is_null() {
+        if (1..last).any(|i| index_stride(LLVMGetOperand(gep, i), induction, 0).is_some()) {
+            return Some(Stride::Strided);
+        }
+        gep = LLVMIsAGetElementPtrInst(LLVMGetOperand(gep, 0));
+        if !gep.is_null() {
+            last = LLVMGetNumOperands(gep) as u32;
+        }
+    }
+    None
+}
+
+/// Whether `index` follows an induction variable: `Some(false)` one step
+/// per iteration, `Some(true)` scaled by a multiply or shift, `None` not at
+/// all (as far as a few instructions back shows)
+#[cfg(feature = "llvm")]
+unsafe fn index_stride(index: LLVMValueRef, induction: &[LLVMValueRef], depth: usize) -> Option<bool> {
+    if induction.contains(&index) {
+        return Some(false);
+    }
+    if depth == 4 || LLVMIsAInstruction(index).is_null() {
+        return None;
+    }
+    let operand = |i: u32| index_stride(LLVMGetOperand(index, i), induction, depth + 1);
+    match LLVMGetInstructionOpcode(index) {
+        LLVMOpcode::LLVMSExt | LLVMOpcode::LLVMZExt | LLVMOpcode::LLVMTrunc => operand(0),
+        LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMSub | LLVMOpcode::LLVMOr => match (operand(0), operand(1)) {
+            (Some(a), Some(b)) => Some(a || b),
+            (a, b) => a.or(b),
+        },
+        LLVMOpcode::LLVMMul | LLVMOpcode::LLVMShl => {
+            let unscaled = |i: u32| {
+                let value = LLVMGetOperand(index, i);
+                let identity = if LLVMGetInstructionOpcode(index) == LLVMOpcode::LLVMMul { 1 } else { 0 };
+                !LLVMIsAConstantInt(value).is_null() && LLVMConstIntGetZExtValue(value) == identity
+            };
+            match (operand(0), operand(1)) {
+                (Some(stride), None) if unscaled(1) => Some(stride),
+                (None, Some(stride)) if unscaled(0) => Some(stride),
+                (None, None) => None,
+                _ => Some(true),
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn run_passes(module: LLVMModuleRef, pipeline: &str, target_machine: LLVMTargetMachineRef) -> Result<(), LoopTransformError> {
+    let pipeline = CString::new(pipeline).unwrap();
+    let options = LLVMCreatePassBuilderOptions();
+    let error = LLVMRunPasses(module, pipeline.as_ptr(), target_machine, options);
+    LLVMDisposePassBuilderOptions(options);
+    if error.is_null() {
+        return Ok(());
+    }
+    let message = LLVMGetErrorMessage(error);
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(message);
+    Err(LoopTransformError::Pipeline(text))
+}
+
+#[derive(Debug)]
+pub enum LoopTransformError {
+    /// LLVM rejected or failed the pass pipeline
+    Pipeline(String),
+}
+
+impl fmt::Display for LoopTransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopTransformError::Pipeline(message) => write!(f, "loop transformations: {}", message),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn optimize_loops(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) -> Result<(), LoopTransformError> {
+    let mut pass = LoopTransformPass::new(LoopTransforms::default(), target_machine);
+    let metrics = pass.run(module)?;
+    metrics.export("matmul.c");
+    for remark in pass.remarks() {
+        eprintln!("remark: {}: {}", remark.function, remark.message);
+    }
+    Ok(())
+}
+*/
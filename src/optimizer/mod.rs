@@ -2,6 +2,29 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+// Passes over LLVM IR. The modules the interpreter and the options also
+// use build without the `llvm` feature, as their settings and runtime
+// helpers only.
+pub mod budget;
+#[cfg(feature = "llvm")]
+pub mod escape;
+pub mod evaluate;
+pub mod fastmath;
+#[cfg(feature = "llvm")]
+pub mod fenv;
+#[cfg(feature = "llvm")]
+pub mod leaks;
+#[cfg(feature = "llvm")]
+pub mod linkage;
+pub mod loops;
+pub mod overflow;
+pub mod pragma;
+pub mod sanitize;
+#[cfg(feature = "llvm")]
+pub mod tiling;
+#[cfg(feature = "llvm")]
+pub mod vectorize;
+
 pub struct Optimizer {
     // Core components
     cpu_info: Arc<CPUInfo>,
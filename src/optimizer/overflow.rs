@@ -0,0 +1,451 @@
+// src/optimizer/overflow.rs
+//! Signed integer overflow modes
+//! C leaves signed overflow undefined. Every execution engine resolves it
+//! the same way, chosen on the command line:
+//!
+//!  - default: the overflow is diagnosed with a `runtime error` line on
+//!    stderr, and execution continues with the two's-complement result
+//!  - `-fwrapv`: two's-complement wrapping, silently; the program is correct
+//!  - `-ftrapv`: the same diagnostic, then the program aborts (SIGABRT)
+//!
+//! Covered: `+`, `-`, `*`, unary `-`, `/` and `%` (INT_MIN / -1) and `<<`
+//! whose result isn't representable. Division by zero and out-of-range
+//! shift counts stay undefined in every mode; `--sanitize=undefined` checks
+//! those.
+//!
+//! `evaluate` is the reference semantics. The interpreter calls it for
+//! every signed operation and the constant folder through `fold`, which
+//! only folds what evaluates identically at runtime. Compiled code gets the
+//! same behaviour from `OverflowPass`, which runs on the IR before the
+//! optimizer so LLVM can never assume `nsw` in a mode that defines or
+//! checks overflow.
+
+#[cfg(feature = "llvm")]
+use std::collections::HashMap;
+#[cfg(feature = "llvm")]
+use std::ffi::CStr;
+use std::fmt;
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMIntPredicate, LLVMLinkage, LLVMOpcode, LLVMTypeKind};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "llvm")]
+use super::fenv::instructions;
+#[cfg(feature = "llvm")]
+use super::sanitize::{add_attribute, describe_location};
+#[cfg(feature = "llvm")]
+use crate::debug::SourceMap;
+
+#[cfg(feature = "llvm")]
+const REPORT_HANDLER: &CStr = c"__cinterp_overflow_report";
+#[cfg(feature = "llvm")]
+const CHECK_HELPER: &CStr = c"__cinterp_overflow_check";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowMode {
+    /// Report and continue with the wrapped result
+    #[default]
+    Diagnose,
+    /// `-fwrapv`
+    Wrap,
+    /// `-ftrapv`
+    Trap,
+}
+
+impl OverflowMode {
+    /// What an evaluated operation produces in this mode
+    pub fn apply(self, result: SignedResult) -> Outcome {
+        match (result.overflowed, self) {
+            (false, _) | (true, OverflowMode::Wrap) => Outcome::Value(result.value),
+            (true, OverflowMode::Diagnose) => Outcome::Diagnosed(result.value),
+            (true, OverflowMode::Trap) => Outcome::Trapped,
+        }
+    }
+
+    /// Constant-fold `lhs op rhs`, or `None` to leave it to runtime: an
+    /// overflow that must be diagnosed or trap has to happen where the
+    /// program executes it, not at compile time
+    pub fn fold(self, op: SignedOp, lhs: i64, rhs: i64, bits: u32) -> Option<i64> {
+        match self.apply(evaluate(op, lhs, rhs, bits).ok()?) {
+            Outcome::Value(value) => Some(value),
+            Outcome::Diagnosed(_) | Outcome::Trapped => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Value(i64),
+    /// Overflowed; report, then continue with this wrapped value
+    Diagnosed(i64),
+    /// Overflowed; report, then abort
+    Trapped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignedOp {
+    Add,
+    Sub,
+    Mul,
+    /// Unary minus; `rhs` is ignored
+    Neg,
+    Div,
+    Rem,
+    Shl,
+}
+
+impl fmt::Display for SignedOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SignedOp::Add => "addition",
+            SignedOp::Sub => "subtraction",
+            SignedOp::Mul => "multiplication",
+            SignedOp::Neg => "negation",
+            SignedOp::Div => "division",
+            SignedOp::Rem => "remainder",
+            SignedOp::Shl => "left shift",
+        })
+    }
+}
+
+/// A signed operation's two's-complement result and whether the exact
+/// result didn't fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedResult {
+    /// Sign-extended from the operand width
+    pub value: i64,
+    pub overflowed: bool,
+}
+
+/// `lhs op rhs` on `bits`-wide signed operands (8, 16, 32 or 64). The
+/// operands are truncated to `bits` first.
+pub fn evaluate(op: SignedOp, lhs: i64, rhs: i64, bits: u32) -> Result<SignedResult, OverflowError> {
+    if !matches!(bits, 8 | 16 | 32 | 64) {
+        return Err(OverflowError::UnsupportedWidth(bits));
+    }
+    let lhs = wrap(lhs as i128, bits) as i128;
+    let rhs = wrap(rhs as i128, bits) as i128;
+
+    // Exact in 128 bits: no 64-bit operation can overflow it
+    let exact = match op {
+        SignedOp::Add => lhs + rhs,
+        SignedOp::Sub => lhs - rhs,
+        SignedOp::Mul => lhs * rhs,
+        SignedOp::Neg => -lhs,
+        SignedOp::Div | SignedOp::Rem if rhs == 0 => return Err(OverflowError::DivisionByZero),
+        SignedOp::Div => lhs / rhs,
+        // INT_MIN % -1 is undefined because INT_MIN / -1 is
+        SignedOp::Rem => {
+            let overflowed = wrap(lhs / rhs, bits) as i128 != lhs / rhs;
+            return Ok(SignedResult { value: (lhs % rhs) as i64, overflowed });
+        }
+        SignedOp::Shl if rhs < 0 || rhs >= bits as i128 => return Err(OverflowError::ShiftOutOfRange(rhs as i64)),
+        SignedOp::Shl => lhs << rhs,
+    };
+    let value = wrap(exact, bits);
+    Ok(SignedResult { value, overflowed: value as i128 != exact })
+}
+
+/// Truncate to `bits` and sign-extend back
+fn wrap(value: i128, bits: u32) -> i64 {
+    let shift = 128 - bits;
+    ((value << shift) >> shift) as i64
+}
+
+/// The line every engine prints for an overflow, so their stderr matches
+pub fn runtime_message(location: &str, op: SignedOp, function: &str) -> String {
+    format!("{}: runtime error: signed integer overflow in {} in '{}'\n", location, op, function)
+}
+
+/// Makes compiled code follow the overflow mode. Must run before any
+/// optimization, while `nsw` still marks exactly the C signed operations.
+#[cfg(feature = "llvm")]
+pub struct OverflowPass<'a> {
+    mode: OverflowMode,
+    source_map: Option<&'a SourceMap>,
+
+    // Statistics
+    rewritten: HashMap<SignedOp, usize>,
+}
+
+#[cfg(feature = "llvm")]
+impl<'a> OverflowPass<'a> {
+    pub fn new(mode: OverflowMode, source_map: Option<&'a SourceMap>) -> Self {
+        OverflowPass {
+            mode,
+            source_map,
+            rewritten: HashMap::new(),
+        }
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) {
+        let context = LLVMGetModuleContext(module);
+        let builder = LLVMCreateBuilderInContext(context);
+        let runtime = (self.mode != OverflowMode::Wrap).then(|| Runtime::define(module, context, builder, self.mode));
+
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            let name = value_name(function);
+            if LLVMCountBasicBlocks(function) > 0 && !name.starts_with("__cinterp_") {
+                for inst in instructions(function) {
+                    self.rewrite(builder, runtime.as_ref(), inst, &name);
+                }
+            }
+            function = LLVMGetNextFunction(function);
+        }
+        LLVMDisposeBuilder(builder);
+    }
+
+    /// Operations rewritten, per kind
+    pub fn stats(&self) -> &HashMap<SignedOp, usize> {
+        &self.rewritten
+    }
+
+    unsafe fn rewrite(&mut self, builder: LLVMBuilderRef, runtime: Option<&Runtime>, inst: LLVMValueRef, function: &str) {
+        let opcode = LLVMGetInstructionOpcode(inst);
+        let arithmetic = matches!(
+            opcode,
+            LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMSub | LLVMOpcode::LLVMMul
+                | LLVMOpcode::LLVMShl | LLVMOpcode::LLVMSDiv | LLVMOpcode::LLVMSRem
+        );
+        // Vector operations only appear after vectorization
+        if !arithmetic || LLVMGetTypeKind(LLVMTypeOf(inst)) != LLVMTypeKind::LLVMIntegerTypeKind {
+            return;
+        }
+        let lhs = LLVMGetOperand(inst, 0);
+        let op = match opcode {
+            LLVMOpcode::LLVMAdd if LLVMGetNSW(inst) != 0 => SignedOp::Add,
+            // The frontend emits unary minus as `sub nsw 0, x`
+            LLVMOpcode::LLVMSub if LLVMGetNSW(inst) != 0 && LLVMIsNull(lhs) != 0 => SignedOp::Neg,
+            LLVMOpcode::LLVMSub if LLVMGetNSW(inst) != 0 => SignedOp::Sub,
+            LLVMOpcode::LLVMMul if LLVMGetNSW(inst) != 0 => SignedOp::Mul,
+            LLVMOpcode::LLVMShl if LLVMGetNSW(inst) != 0 => SignedOp::Shl,
+            LLVMOpcode::LLVMSDiv => SignedOp::Div,
+            LLVMOpcode::LLVMSRem => SignedOp::Rem,
+            _ => return,
+        };
+        *self.rewritten.entry(op).or_insert(0) += 1;
+
+        LLVMPositionBuilderBefore(builder, inst);
+        let (result, overflowed) = match op {
+            SignedOp::Div | SignedOp::Rem => guard_division(builder, inst, op),
+            _ if runtime.is_none() => {
+                LLVMSetNSW(inst, 0);
+                return;
+            }
+            SignedOp::Shl => {
+                // Representable exactly when shifting back recovers the operand
+                LLVMSetNSW(inst, 0);
+                let rhs = LLVMGetOperand(inst, 1);
+                let next = LLVMGetNextInstruction(inst);
+                LLVMPositionBuilderBefore(builder, next);
+                let back = LLVMBuildAShr(builder, inst, rhs, c"".as_ptr());
+                let overflowed = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntNE, back, lhs, c"".as_ptr());
+                (inst, overflowed)
+            }
+            _ => with_overflow(builder, inst, op),
+        };
+
+        if let Some(runtime) = runtime {
+            let text = runtime_message(&describe_location(self.source_map, inst), op, function);
+            runtime.check(builder, overflowed, &text);
+        }
+        if result != inst {
+            LLVMReplaceAllUsesWith(inst, result);
+            LLVMInstructionEraseFromParent(inst);
+        }
+    }
+}
+
+/// `llvm.s{add,sub,mul}.with.overflow` in place of the `nsw` operation
+#[cfg(feature = "llvm")]
+unsafe fn with_overflow(builder: LLVMBuilderRef, inst: LLVMValueRef, op: SignedOp) -> (LLVMValueRef, LLVMValueRef) {
+    let intrinsic = match op {
+        SignedOp::Add => "llvm.sadd.with.overflow",
+        SignedOp::Mul => "llvm.smul.with.overflow",
+        _ => "llvm.ssub.with.overflow",
+    };
+    let module = LLVMGetGlobalParent(LLVMGetBasicBlockParent(LLVMGetInstructionParent(inst)));
+    let context = LLVMGetModuleContext(module);
+    let mut ty = LLVMTypeOf(inst);
+    let id = LLVMLookupIntrinsicID(intrinsic.as_ptr() as *const _, intrinsic.len());
+    let declaration = LLVMGetIntrinsicDeclaration(module, id, &mut ty, 1);
+    let fn_ty = LLVMIntrinsicGetType(context, id, &mut ty, 1);
+
+    let mut args = [LLVMGetOperand(inst, 0), LLVMGetOperand(inst, 1)];
+    let pair = LLVMBuildCall2(builder, fn_ty, declaration, args.as_mut_ptr(), 2, c"".as_ptr());
+    (
+        LLVMBuildExtractValue(builder, pair, 0, c"".as_ptr()),
+        LLVMBuildExtractValue(builder, pair, 1, c"".as_ptr()),
+    )
+}
+
+/// Divide by 1 instead of -1 and negate, so INT_MIN / -1 wraps to INT_MIN
+/// (and INT_MIN % -1 gives 0) instead of faulting in the hardware divide
+#[cfg(feature = "llvm")]
+unsafe fn guard_division(builder: LLVMBuilderRef, inst: LLVMValueRef, op: SignedOp) -> (LLVMValueRef, LLVMValueRef) {
+    let lhs = LLVMGetOperand(inst, 0);
+    let rhs = LLVMGetOperand(inst, 1);
+    let ty = LLVMTypeOf(inst);
+    let minus_one = LLVMConstAllOnes(ty);
+    let min = LLVMConstInt(ty, 1u64 << (LLVMGetIntTypeWidth(ty) - 1), 0);
+
+    let by_minus_one = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntEQ, rhs, minus_one, c"".as_ptr());
+    let divisor = LLVMBuildSelect(builder, by_minus_one, LLVMConstInt(ty, 1, 0), rhs, c"".as_ptr());
+    let divided = if op == SignedOp::Div {
+        let quotient = LLVMBuildSDiv(builder, lhs, divisor, c"".as_ptr());
+        let negated = LLVMBuildSub(builder, LLVMConstNull(ty), lhs, c"".as_ptr());
+        LLVMBuildSelect(builder, by_minus_one, negated, quotient, c"".as_ptr())
+    } else {
+        // x % 1 is already the 0 that x % -1 should give
+        LLVMBuildSRem(builder, lhs, divisor, c"".as_ptr())
+    };
+
+    let lhs_min = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntEQ, lhs, min, c"".as_ptr());
+    (divided, LLVMBuildAnd(builder, lhs_min, by_minus_one, c"".as_ptr()))
+}
+
+/// The report handler and check helper emitted into each module, so JIT and
+/// AOT output behave the same:
+///
+///   void check(i1 failed, ptr msg, i64 len) { if (failed) report(msg, len); }
+///   void report(ptr msg, i64 len) { write(2, msg, len); abort(); }  // -ftrapv
+///   void report(ptr msg, i64 len) { write(2, msg, len); }           // default
+#[cfg(feature = "llvm")]
+struct Runtime {
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    check_fn: LLVMValueRef,
+    check_ty: LLVMTypeRef,
+}
+
+#[cfg(feature = "llvm")]
+impl Runtime {
+    unsafe fn define(module: LLVMModuleRef, context: LLVMContextRef, builder: LLVMBuilderRef, mode: OverflowMode) -> Self {
+        let void = LLVMVoidTypeInContext(context);
+        let i1 = LLVMInt1TypeInContext(context);
+        let i32 = LLVMInt32TypeInContext(context);
+        let i64 = LLVMInt64TypeInContext(context);
+        let ptr = LLVMPointerTypeInContext(context, 0);
+
+        let mut write_params = [i32, ptr, i64];
+        let write_ty = LLVMFunctionType(i64, write_params.as_mut_ptr(), 3, 0);
+        let write_fn = get_or_declare(module, c"write", write_ty);
+        let abort_ty = LLVMFunctionType(void, std::ptr::null_mut(), 0, 0);
+        let abort_fn = get_or_declare(module, c"abort", abort_ty);
+
+        let mut report_params = [ptr, i64];
+        let report_ty = LLVMFunctionType(void, report_params.as_mut_ptr(), 2, 0);
+        let report_fn = get_or_declare(module, REPORT_HANDLER, report_ty);
+        if LLVMCountBasicBlocks(report_fn) == 0 {
+            LLVMSetLinkage(report_fn, LLVMLinkage::LLVMInternalLinkage);
+            add_attribute(context, report_fn, "cold");
+            add_attribute(context, report_fn, "noinline");
+
+            let entry = LLVMAppendBasicBlockInContext(context, report_fn, c"entry".as_ptr());
+            LLVMPositionBuilderAtEnd(builder, entry);
+            let mut args = [LLVMConstInt(i32, 2, 0), LLVMGetParam(report_fn, 0), LLVMGetParam(report_fn, 1)];
+            LLVMBuildCall2(builder, write_ty, write_fn, args.as_mut_ptr(), 3, c"".as_ptr());
+            if mode == OverflowMode::Trap {
+                add_attribute(context, report_fn, "noreturn");
+                LLVMBuildCall2(builder, abort_ty, abort_fn, std::ptr::null_mut(), 0, c"".as_ptr());
+                LLVMBuildUnreachable(builder);
+            } else {
+                LLVMBuildRetVoid(builder);
+            }
+        }
+
+        // Inlining turns each call into a branch to the cold path
+        let mut check_params = [i1, ptr, i64];
+        let check_ty = LLVMFunctionType(void, check_params.as_mut_ptr(), 3, 0);
+        let check_fn = get_or_declare(module, CHECK_HELPER, check_ty);
+        if LLVMCountBasicBlocks(check_fn) == 0 {
+            LLVMSetLinkage(check_fn, LLVMLinkage::LLVMInternalLinkage);
+            add_attribute(context, check_fn, "alwaysinline");
+
+            let entry = LLVMAppendBasicBlockInContext(context, check_fn, c"entry".as_ptr());
+            let fail = LLVMAppendBasicBlockInContext(context, check_fn, c"fail".as_ptr());
+            let done = LLVMAppendBasicBlockInContext(context, check_fn, c"done".as_ptr());
+
+            LLVMPositionBuilderAtEnd(builder, entry);
+            LLVMBuildCondBr(builder, LLVMGetParam(check_fn, 0), fail, done);
+
+            LLVMPositionBuilderAtEnd(builder, fail);
+            let mut args = [LLVMGetParam(check_fn, 1), LLVMGetParam(check_fn, 2)];
+            LLVMBuildCall2(builder, report_ty, report_fn, args.as_mut_ptr(), 2, c"".as_ptr());
+            LLVMBuildBr(builder, done);
+
+            LLVMPositionBuilderAtEnd(builder, done);
+            LLVMBuildRetVoid(builder);
+        }
+
+        Runtime { module, context, check_fn, check_ty }
+    }
+
+    /// Call the check helper at the builder's position
+    unsafe fn check(&self, builder: LLVMBuilderRef, failed: LLVMValueRef, text: &str) {
+        let data = LLVMConstStringInContext(self.context, text.as_ptr() as *const _, text.len() as u32, 1);
+        let message = LLVMAddGlobal(self.module, LLVMTypeOf(data), c"__cinterp_overflow_msg".as_ptr());
+        LLVMSetInitializer(message, data);
+        LLVMSetLinkage(message, LLVMLinkage::LLVMPrivateLinkage);
+        LLVMSetGlobalConstant(message, 1);
+
+        let length = LLVMConstInt(LLVMInt64TypeInContext(self.context), text.len() as u64, 0);
+        let mut args = [failed, message, length];
+        LLVMBuildCall2(builder, self.check_ty, self.check_fn, args.as_mut_ptr(), 3, c"".as_ptr());
+    }
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn get_or_declare(module: LLVMModuleRef, name: &CStr, ty: LLVMTypeRef) -> LLVMValueRef {
+    let existing = LLVMGetNamedFunction(module, name.as_ptr());
+    if !existing.is_null() {
+        return existing;
+    }
+    LLVMAddFunction(module, name.as_ptr(), ty)
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(value, &mut len);
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len)).into_owned()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverflowError {
+    /// Undefined in every mode
+    DivisionByZero,
+    /// Undefined in every mode
+    ShiftOutOfRange(i64),
+    UnsupportedWidth(u32),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), OverflowError> {
+    // INT_MAX + 1 in each mode, as the interpreter sees it
+    let result = evaluate(SignedOp::Add, i32::MAX as i64, 1, 32)?;
+    assert_eq!(OverflowMode::Wrap.apply(result), Outcome::Value(i32::MIN as i64));
+    assert_eq!(OverflowMode::Diagnose.apply(result), Outcome::Diagnosed(i32::MIN as i64));
+    assert_eq!(OverflowMode::Trap.apply(result), Outcome::Trapped);
+
+    // Only -fwrapv lets the folder fold it; otherwise it is reported at runtime
+    assert_eq!(OverflowMode::Wrap.fold(SignedOp::Div, i32::MIN as i64, -1, 32), Some(i32::MIN as i64));
+    assert_eq!(OverflowMode::Diagnose.fold(SignedOp::Add, i32::MAX as i64, 1, 32), None);
+
+    // Compiled code, before optimize_for_jit
+    let mut pass = OverflowPass::new(OverflowMode::Trap, Some(&source_map));
+    unsafe { pass.run(module) };
+    println!("{:?}", pass.stats()); // {Add: 3, Div: 1}
+
+    // int x = INT_MAX; x + 1;  (-ftrapv, in every engine)
+    // prog.c:4:7: runtime error: signed integer overflow in addition in 'main'
+    // terminated by signal SIGABRT (6)
+    Ok(())
+}
+*/
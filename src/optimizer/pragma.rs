@@ -0,0 +1,170 @@
+// src/optimizer/pragma.rs
+//! Scoped `#pragma` state for IR passes
+//! The floating-point pragmas (FENV_ACCESS, FP_CONTRACT, float_control)
+//! hold from where they appear to the next pragma of the same kind or, when
+//! they appear inside a compound statement, to the end of that statement.
+//! Scanning the preprocessed source for them yields line ranges per file
+//! that passes match against instructions' debug locations.
+
+use std::collections::HashMap;
+
+/// Line ranges where a pragma set some state, per file named by the
+/// preprocessor's line markers (`None` for source without markers)
+#[derive(Debug, Clone)]
+pub struct PragmaRegions<T> {
+    spans: HashMap<Option<String>, Vec<(u32, u32, T)>>,
+}
+
+impl<T: Copy + PartialEq> PragmaRegions<T> {
+    /// `parse` gets the words of each pragma, with parentheses and commas
+    /// dropped (`float_control(precise, off)` -> `float_control precise
+    /// off`), and returns the state it sets if it's the pragma of interest.
+    /// `None` from the pragma itself (e.g. `DEFAULT`) is expressed by the
+    /// caller's choice of `T`.
+    pub fn scan(source: &str, parse: impl Fn(&[&str]) -> Option<T>) -> Self {
+        let mut spans: HashMap<Option<String>, Vec<(u32, u32, T)>> = HashMap::new();
+        let mut file: Option<String> = None;
+        let mut line: u32 = 0;
+
+        // (brace depth the pragma appeared at, state); the file-scope entry is never popped
+        let mut stack: Vec<(usize, Option<T>)> = vec![(0, None)];
+        let mut depth = 0usize;
+        let mut open: Option<(Option<String>, u32, T)> = None;
+
+        // Close the running span at `end` and start one for `state`
+        let mut switch = |open: &mut Option<(Option<String>, u32, T)>, file: &Option<String>, end: u32, state: Option<T>| {
+            if let Some((span_file, start, value)) = open.take() {
+                if start < end {
+                    spans.entry(span_file).or_default().push((start, end, value));
+                }
+            }
+            *open = state.map(|value| (file.clone(), end, value));
+        };
+
+        for text in source.lines() {
+            line += 1;
+            let trimmed = text.trim_start();
+            let current = stack.last().and_then(|s| s.1);
+
+            // # 12 "file.c" [flags]
+            if let Some((number, name)) = line_marker(trimmed) {
+                switch(&mut open, &file, line, None);
+                line = number - 1;
+                file = Some(name);
+                switch(&mut open, &file, line + 1, current);
+                continue;
+            }
+
+            if let Some(words) = pragma_words(trimmed) {
+                let words: Vec<&str> = words.split_whitespace().collect();
+                if let Some(state) = parse(&words) {
+                    match stack.last_mut() {
+                        Some(top) if top.0 == depth => top.1 = Some(state),
+                        _ => stack.push((depth, Some(state))),
+                    }
+                    if current != Some(state) {
+                        switch(&mut open, &file, line, Some(state));
+                    }
+                    continue;
+                }
+            }
+
+            for c in strip_literals(text).chars() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth = depth.saturating_sub(1);
+                        // Leaving the block a pragma appeared in restores the outer state
+                        while stack.len() > 1 && stack.last().map_or(false, |s| s.0 > depth) {
+                            let inner = stack.pop().and_then(|s| s.1);
+                            let outer = stack.last().and_then(|s| s.1);
+                            if inner != outer {
+                                switch(&mut open, &file, line + 1, outer);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        switch(&mut open, &file, u32::MAX, None);
+
+        PragmaRegions { spans }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.values().all(|s| s.is_empty())
+    }
+
+    /// The state in effect at `line` of `file`, if a pragma set one
+    pub fn at(&self, file: &str, line: u32) -> Option<T> {
+        let find = |spans: &Vec<(u32, u32, T)>| {
+            spans
+                .iter()
+                .find(|&&(start, end, _)| line >= start && line < end)
+                .map(|&(_, _, value)| value)
+        };
+        self.spans
+            .get(&Some(file.to_string()))
+            .and_then(find)
+            .or_else(|| self.spans.get(&None).and_then(find))
+    }
+}
+
+fn line_marker(line: &str) -> Option<(u32, String)> {
+    let rest = line.strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("line").unwrap_or(rest).trim_start();
+    let (number, rest) = rest.split_once(char::is_whitespace)?;
+    let number = number.parse().ok()?;
+    let name = rest.trim_start().strip_prefix('"')?;
+    let end = name.find('"')?;
+    Some((number, name[..end].to_string()))
+}
+
+/// Body of `#pragma ...` or `_Pragma("...")`, punctuation turned into spaces
+pub(crate) fn pragma_words(line: &str) -> Option<String> {
+    let body = if let Some(rest) = line.strip_prefix('#') {
+        rest.trim_start().strip_prefix("pragma")?.to_string()
+    } else {
+        let start = line.find("_Pragma")?;
+        let open = line[start..].find('"')? + start + 1;
+        let close = line[open..].find('"')? + open;
+        line[open..close].to_string()
+    };
+    Some(body.replace(['(', ')', ','], " "))
+}
+
+/// The line with string/char literals and comments blanked, for brace counting
+fn strip_literals(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                while let Some(d) = chars.next() {
+                    if d == '\\' {
+                        chars.next();
+                    } else if d == c {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => break,
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Example usage:
+/*
+fn main() {
+    let source = "#pragma STDC FP_CONTRACT OFF\ndouble f(double a, double b) { return a * b + 1.0; }\n";
+    let regions = PragmaRegions::scan(source, |words| match words {
+        ["STDC", "FP_CONTRACT", "OFF", ..] => Some(false),
+        ["STDC", "FP_CONTRACT", "ON", ..] => Some(true),
+        _ => None,
+    });
+    assert_eq!(regions.at("<stdin>", 2), Some(false));
+}
+*/
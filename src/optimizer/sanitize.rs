@@ -0,0 +1,503 @@
+// src/optimizer/sanitize.rs
+//! Undefined behaviour sanitizer (--sanitize=undefined)
+//! Instruments LLVM IR before optimization, while the `nsw` flags and
+//! `!range` metadata the frontend emitted still describe the C semantics.
+//! Checked operations:
+//!  - signed `add`/`sub`/`mul` overflow (the `nsw` forms), and INT_MIN / -1,
+//!    unless the overflow pass already resolved them for the overflow mode
+//!  - integer division or remainder by zero
+//!  - shift amounts >= the operand width
+//!  - loads and stores through a null pointer
+//!  - loads and stores through a pointer that violates the access alignment
+//!  - loads of enum values outside the `!range` the frontend attached
+//!
+//! Each failing check calls `__cinterp_ubsan_report` with a message naming
+//! the source line (resolved through the debug SourceMap), which writes it
+//! to stderr and aborts. The handler is emitted into the module itself so
+//! JIT and AOT output behave the same. The bytecode interpreter makes the
+//! same checks itself, except the enum one (its loads carry no range), and
+//! prints the same `runtime_message`.
+
+#[cfg(feature = "llvm")]
+use std::collections::HashMap;
+#[cfg(feature = "llvm")]
+use std::ffi::{CStr, CString};
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::{LLVMIntPredicate, LLVMLinkage, LLVMOpcode, LLVMTypeKind};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "llvm")]
+use crate::debug::{SourceLocation, SourceMap};
+
+#[cfg(feature = "llvm")]
+const REPORT_HANDLER: &str = "__cinterp_ubsan_report";
+#[cfg(feature = "llvm")]
+const CHECK_HELPER: &str = "__cinterp_ubsan_check";
+
+/// Which sanitizers are enabled; parsed from `--sanitize=a,b`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SanitizerSet {
+    pub undefined: bool,
+}
+
+impl SanitizerSet {
+    pub fn parse(list: &str) -> Result<Self, SanitizeError> {
+        let mut set = SanitizerSet::default();
+        for name in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match name {
+                "undefined" => set.undefined = true,
+                other => return Err(SanitizeError::UnknownSanitizer(other.to_string())),
+            }
+        }
+        Ok(set)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CheckKind {
+    SignedOverflow,
+    DivisionByZero,
+    ShiftOutOfBounds,
+    NullDereference,
+    MisalignedAccess,
+    InvalidEnumLoad,
+}
+
+impl CheckKind {
+    fn message(&self) -> &'static str {
+        match self {
+            CheckKind::SignedOverflow => "signed integer overflow",
+            CheckKind::DivisionByZero => "division by zero",
+            CheckKind::ShiftOutOfBounds => "shift exponent is too large for the type",
+            CheckKind::NullDereference => "null pointer dereference",
+            CheckKind::MisalignedAccess => "misaligned memory access",
+            CheckKind::InvalidEnumLoad => "load of a value that is not valid for the enum type",
+        }
+    }
+}
+
+/// The line every engine prints for a failed check, so their stderr matches
+pub fn runtime_message(location: &str, kind: CheckKind, function: &str) -> String {
+    format!("{}: runtime error: {} in '{}'\n", location, kind.message(), function)
+}
+
+#[cfg(feature = "llvm")]
+pub struct UndefinedSanitizer<'a> {
+    // Location lookup
+    source_map: Option<&'a SourceMap>,
+
+    // Statistics
+    checks: HashMap<CheckKind, usize>,
+}
+
+#[cfg(feature = "llvm")]
+impl<'a> UndefinedSanitizer<'a> {
+    pub fn new(source_map: Option<&'a SourceMap>) -> Self {
+        UndefinedSanitizer {
+            source_map,
+            checks: HashMap::new(),
+        }
+    }
+
+    /// Number of checks inserted per kind
+    pub fn checks(&self) -> &HashMap<CheckKind, usize> {
+        &self.checks
+    }
+
+    pub unsafe fn instrument_module(&mut self, module: LLVMModuleRef) -> Result<(), SanitizeError> {
+        let mut ir = IrContext::new(module)?;
+
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            let name = value_name(function);
+            // Skip declarations and our own runtime helpers
+            if LLVMCountBasicBlocks(function) > 0 && !name.starts_with("__cinterp_ubsan") {
+                self.instrument_function(&mut ir, function, &name);
+            }
+            function = LLVMGetNextFunction(function);
+        }
+
+        LLVMDisposeBuilder(ir.builder);
+        Ok(())
+    }
+
+    unsafe fn instrument_function(&mut self, ir: &mut IrContext, function: LLVMValueRef, name: &str) {
+        // Collect first: instrumentation inserts and erases instructions
+        let mut instructions = Vec::new();
+        let mut block = LLVMGetFirstBasicBlock(function);
+        while !block.is_null() {
+            let mut inst = LLVMGetFirstInstruction(block);
+            while !inst.is_null() {
+                instructions.push(inst);
+                inst = LLVMGetNextInstruction(inst);
+            }
+            block = LLVMGetNextBasicBlock(block);
+        }
+
+        for inst in instructions {
+            match LLVMGetInstructionOpcode(inst) {
+                LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMSub | LLVMOpcode::LLVMMul
+                    if LLVMGetNSW(inst) != 0 && is_integer(inst) =>
+                {
+                    self.check_overflow(ir, inst, name);
+                }
+                LLVMOpcode::LLVMSDiv | LLVMOpcode::LLVMSRem => {
+                    self.check_division(ir, inst, name, true);
+                }
+                LLVMOpcode::LLVMUDiv | LLVMOpcode::LLVMURem => {
+                    self.check_division(ir, inst, name, false);
+                }
+                LLVMOpcode::LLVMShl | LLVMOpcode::LLVMLShr | LLVMOpcode::LLVMAShr if is_integer(inst) => {
+                    self.check_shift(ir, inst, name);
+                }
+                LLVMOpcode::LLVMLoad => {
+                    self.check_pointer(ir, inst, LLVMGetOperand(inst, 0), name);
+                    self.check_enum_range(ir, inst, name);
+                }
+                LLVMOpcode::LLVMStore => {
+                    self.check_pointer(ir, inst, LLVMGetOperand(inst, 1), name);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Replace `op nsw a, b` with the overflow intrinsic and check its flag
+    unsafe fn check_overflow(&mut self, ir: &mut IrContext, inst: LLVMValueRef, function: &str) {
+        let intrinsic = match LLVMGetInstructionOpcode(inst) {
+            LLVMOpcode::LLVMAdd => "llvm.sadd.with.overflow",
+            LLVMOpcode::LLVMSub => "llvm.ssub.with.overflow",
+            _ => "llvm.smul.with.overflow",
+        };
+        let lhs = LLVMGetOperand(inst, 0);
+        let rhs = LLVMGetOperand(inst, 1);
+        let mut ty = LLVMTypeOf(lhs);
+
+        let id = LLVMLookupIntrinsicID(intrinsic.as_ptr() as *const _, intrinsic.len());
+        let decl = LLVMGetIntrinsicDeclaration(ir.module, id, &mut ty, 1);
+        let fn_ty = LLVMIntrinsicGetType(ir.context, id, &mut ty, 1);
+
+        LLVMPositionBuilderBefore(ir.builder, inst);
+        let mut args = [lhs, rhs];
+        let pair = LLVMBuildCall2(ir.builder, fn_ty, decl, args.as_mut_ptr(), 2, c"".as_ptr());
+        let result = LLVMBuildExtractValue(ir.builder, pair, 0, c"".as_ptr());
+        let overflow = LLVMBuildExtractValue(ir.builder, pair, 1, c"".as_ptr());
+        self.emit_check(ir, inst, overflow, CheckKind::SignedOverflow, function);
+
+        LLVMReplaceAllUsesWith(inst, result);
+        LLVMInstructionEraseFromParent(inst);
+    }
+
+    unsafe fn check_division(&mut self, ir: &mut IrContext, inst: LLVMValueRef, function: &str, signed: bool) {
+        let lhs = LLVMGetOperand(inst, 0);
+        let rhs = LLVMGetOperand(inst, 1);
+        let ty = LLVMTypeOf(rhs);
+        if LLVMGetTypeKind(ty) != LLVMTypeKind::LLVMIntegerTypeKind {
+            return;
+        }
+
+        LLVMPositionBuilderBefore(ir.builder, inst);
+        let zero = LLVMConstInt(ty, 0, 0);
+        let is_zero = LLVMBuildICmp(ir.builder, LLVMIntPredicate::LLVMIntEQ, rhs, zero, c"".as_ptr());
+        self.emit_check(ir, inst, is_zero, CheckKind::DivisionByZero, function);
+
+        if signed {
+            // INT_MIN / -1 overflows
+            // 1 << (width - 1), in as many 64-bit words as the type needs (i128)
+            let width = LLVMGetIntTypeWidth(ty);
+            let mut words = vec![0u64; width.div_ceil(64) as usize];
+            words[((width - 1) / 64) as usize] = 1u64 << ((width - 1) % 64);
+            let min = LLVMConstIntOfArbitraryPrecision(ty, words.len() as u32, words.as_ptr());
+            let minus_one = LLVMConstAllOnes(ty);
+            let lhs_min = LLVMBuildICmp(ir.builder, LLVMIntPredicate::LLVMIntEQ, lhs, min, c"".as_ptr());
+            let rhs_neg = LLVMBuildICmp(ir.builder, LLVMIntPredicate::LLVMIntEQ, rhs, minus_one, c"".as_ptr());
+            let overflow = LLVMBuildAnd(ir.builder, lhs_min, rhs_neg, c"".as_ptr());
+            self.emit_check(ir, inst, overflow, CheckKind::SignedOverflow, function);
+        }
+    }
+
+    unsafe fn check_shift(&mut self, ir: &mut IrContext, inst: LLVMValueRef, function: &str) {
+        let amount = LLVMGetOperand(inst, 1);
+        let ty = LLVMTypeOf(amount);
+        let width = LLVMConstInt(ty, LLVMGetIntTypeWidth(ty) as u64, 0);
+
+        LLVMPositionBuilderBefore(ir.builder, inst);
+        // Unsigned compare also catches negative shift amounts
+        let too_large = LLVMBuildICmp(ir.builder, LLVMIntPredicate::LLVMIntUGE, amount, width, c"".as_ptr());
+        self.emit_check(ir, inst, too_large, CheckKind::ShiftOutOfBounds, function);
+    }
+
+    unsafe fn check_pointer(&mut self, ir: &mut IrContext, inst: LLVMValueRef, pointer: LLVMValueRef, function: &str) {
+        LLVMPositionBuilderBefore(ir.builder, inst);
+
+        let is_null = LLVMBuildIsNull(ir.builder, pointer, c"".as_ptr());
+        self.emit_check(ir, inst, is_null, CheckKind::NullDereference, function);
+
+        let align = LLVMGetAlignment(inst) as u64;
+        if align > 1 {
+            let address = LLVMBuildPtrToInt(ir.builder, pointer, ir.i64, c"".as_ptr());
+            let mask = LLVMConstInt(ir.i64, align - 1, 0);
+            let low_bits = LLVMBuildAnd(ir.builder, address, mask, c"".as_ptr());
+            let misaligned = LLVMBuildIsNotNull(ir.builder, low_bits, c"".as_ptr());
+            self.emit_check(ir, inst, misaligned, CheckKind::MisalignedAccess, function);
+        }
+    }
+
+    /// The frontend marks enum-typed loads with `!range`; check the loaded value
+    unsafe fn check_enum_range(&mut self, ir: &mut IrContext, inst: LLVMValueRef, function: &str) {
+        let range = LLVMGetMetadata(inst, ir.range_kind);
+        if range.is_null() || !is_integer(inst) {
+            return;
+        }
+
+        let count = LLVMGetMDNodeNumOperands(range) as usize;
+        let mut bounds = vec![std::ptr::null_mut(); count];
+        LLVMGetMDNodeOperands(range, bounds.as_mut_ptr());
+
+        // The optimizer would otherwise fold the check away using the range
+        LLVMSetMetadata(inst, ir.range_kind, std::ptr::null_mut());
+
+        let next = LLVMGetNextInstruction(inst);
+        if next.is_null() {
+            return;
+        }
+        LLVMPositionBuilderBefore(ir.builder, next);
+
+        // Valid if inside any [low, high) pair
+        let mut valid = LLVMConstInt(ir.i1, 0, 0);
+        for pair in bounds.chunks(2) {
+            if pair.len() < 2 {
+                break;
+            }
+            let above = LLVMBuildICmp(ir.builder, LLVMIntPredicate::LLVMIntSGE, inst, pair[0], c"".as_ptr());
+            let below = LLVMBuildICmp(ir.builder, LLVMIntPredicate::LLVMIntSLT, inst, pair[1], c"".as_ptr());
+            let inside = LLVMBuildAnd(ir.builder, above, below, c"".as_ptr());
+            valid = LLVMBuildOr(ir.builder, valid, inside, c"".as_ptr());
+        }
+        let invalid = LLVMBuildNot(ir.builder, valid, c"".as_ptr());
+        self.emit_check(ir, inst, invalid, CheckKind::InvalidEnumLoad, function);
+    }
+
+    /// Call the check helper with the condition and this site's message
+    unsafe fn emit_check(
+        &mut self,
+        ir: &mut IrContext,
+        inst: LLVMValueRef,
+        failed: LLVMValueRef,
+        kind: CheckKind,
+        function: &str
+    ) {
+        let text = runtime_message(&describe_location(self.source_map, inst), kind, function);
+        let message = ir.message(&text);
+        let length = LLVMConstInt(ir.i64, text.len() as u64, 0);
+
+        let mut args = [failed, message, length];
+        LLVMBuildCall2(ir.builder, ir.check_ty, ir.check_fn, args.as_mut_ptr(), 3, c"".as_ptr());
+        *self.checks.entry(kind).or_insert(0) += 1;
+    }
+}
+
+/// `file:line:column`, preferring the SourceMap's file names
+#[cfg(feature = "llvm")]
+pub(super) unsafe fn describe_location(source_map: Option<&SourceMap>, inst: LLVMValueRef) -> String {
+    let line = LLVMGetDebugLocLine(inst);
+    if line == 0 {
+        return "<unknown location>".to_string();
+    }
+    let column = LLVMGetDebugLocColumn(inst);
+
+    let mut len = 0;
+    let name = LLVMGetDebugLocFilename(inst, &mut len);
+    let file = if name.is_null() {
+        String::new()
+    } else {
+        String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len as usize)).into_owned()
+    };
+
+    if let Some(map) = source_map {
+        if let Some(file_id) = map.find_file(&file) {
+            return map.describe(&SourceLocation {
+                file_id,
+                line: line as u64,
+                column: column as u64,
+            });
+        }
+    }
+    format!("{}:{}:{}", file, line, column)
+}
+
+/// Builder and runtime declarations shared by every check in a module
+#[cfg(feature = "llvm")]
+struct IrContext {
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    builder: LLVMBuilderRef,
+    i1: LLVMTypeRef,
+    i64: LLVMTypeRef,
+    range_kind: u32,
+    check_fn: LLVMValueRef,
+    check_ty: LLVMTypeRef,
+    messages: HashMap<String, LLVMValueRef>,
+}
+
+#[cfg(feature = "llvm")]
+impl IrContext {
+    unsafe fn new(module: LLVMModuleRef) -> Result<Self, SanitizeError> {
+        let context = LLVMGetModuleContext(module);
+        let builder = LLVMCreateBuilderInContext(context);
+        let i1 = LLVMInt1TypeInContext(context);
+        let i64 = LLVMInt64TypeInContext(context);
+        let range_kind = LLVMGetMDKindIDInContext(context, c"range".as_ptr(), 5);
+
+        let (check_fn, check_ty) = define_runtime(module, context, builder)?;
+        Ok(IrContext {
+            module,
+            context,
+            builder,
+            i1,
+            i64,
+            range_kind,
+            check_fn,
+            check_ty,
+            messages: HashMap::new(),
+        })
+    }
+
+    /// Private constant string, shared between identical sites
+    unsafe fn message(&mut self, text: &str) -> LLVMValueRef {
+        if let Some(value) = self.messages.get(text) {
+            return *value;
+        }
+        let data = LLVMConstStringInContext(self.context, text.as_ptr() as *const _, text.len() as u32, 1);
+        let global = LLVMAddGlobal(self.module, LLVMTypeOf(data), c"__cinterp_ubsan_msg".as_ptr());
+        LLVMSetInitializer(global, data);
+        LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+        LLVMSetGlobalConstant(global, 1);
+        self.messages.insert(text.to_string(), global);
+        global
+    }
+}
+
+/// Emit the always-inlined check helper and the cold report handler:
+///
+///   void check(i1 failed, ptr msg, i64 len) { if (failed) report(msg, len); }
+///   void report(ptr msg, i64 len) { write(2, msg, len); abort(); }
+#[cfg(feature = "llvm")]
+unsafe fn define_runtime(
+    module: LLVMModuleRef,
+    context: LLVMContextRef,
+    builder: LLVMBuilderRef
+) -> Result<(LLVMValueRef, LLVMTypeRef), SanitizeError> {
+    let void = LLVMVoidTypeInContext(context);
+    let i1 = LLVMInt1TypeInContext(context);
+    let i32 = LLVMInt32TypeInContext(context);
+    let i64 = LLVMInt64TypeInContext(context);
+    let ptr = LLVMPointerTypeInContext(context, 0);
+
+    let mut write_params = [i32, ptr, i64];
+    let write_ty = LLVMFunctionType(i64, write_params.as_mut_ptr(), 3, 0);
+    let write_fn = get_or_declare(module, "write", write_ty)?;
+    let abort_ty = LLVMFunctionType(void, std::ptr::null_mut(), 0, 0);
+    let abort_fn = get_or_declare(module, "abort", abort_ty)?;
+
+    // Report handler
+    let mut report_params = [ptr, i64];
+    let report_ty = LLVMFunctionType(void, report_params.as_mut_ptr(), 2, 0);
+    let report_fn = get_or_declare(module, REPORT_HANDLER, report_ty)?;
+    if LLVMCountBasicBlocks(report_fn) == 0 {
+        LLVMSetLinkage(report_fn, LLVMLinkage::LLVMInternalLinkage);
+        add_attribute(context, report_fn, "cold");
+        add_attribute(context, report_fn, "noinline");
+        add_attribute(context, report_fn, "noreturn");
+
+        let entry = LLVMAppendBasicBlockInContext(context, report_fn, c"entry".as_ptr());
+        LLVMPositionBuilderAtEnd(builder, entry);
+        let mut args = [LLVMConstInt(i32, 2, 0), LLVMGetParam(report_fn, 0), LLVMGetParam(report_fn, 1)];
+        LLVMBuildCall2(builder, write_ty, write_fn, args.as_mut_ptr(), 3, c"".as_ptr());
+        LLVMBuildCall2(builder, abort_ty, abort_fn, std::ptr::null_mut(), 0, c"".as_ptr());
+        LLVMBuildUnreachable(builder);
+    }
+
+    // Check helper; inlining turns each call into a branch to the cold path
+    let mut check_params = [i1, ptr, i64];
+    let check_ty = LLVMFunctionType(void, check_params.as_mut_ptr(), 3, 0);
+    let check_fn = get_or_declare(module, CHECK_HELPER, check_ty)?;
+    if LLVMCountBasicBlocks(check_fn) == 0 {
+        LLVMSetLinkage(check_fn, LLVMLinkage::LLVMInternalLinkage);
+        add_attribute(context, check_fn, "alwaysinline");
+
+        let entry = LLVMAppendBasicBlockInContext(context, check_fn, c"entry".as_ptr());
+        let fail = LLVMAppendBasicBlockInContext(context, check_fn, c"fail".as_ptr());
+        let done = LLVMAppendBasicBlockInContext(context, check_fn, c"done".as_ptr());
+
+        LLVMPositionBuilderAtEnd(builder, entry);
+        LLVMBuildCondBr(builder, LLVMGetParam(check_fn, 0), fail, done);
+
+        LLVMPositionBuilderAtEnd(builder, fail);
+        let mut args = [LLVMGetParam(check_fn, 1), LLVMGetParam(check_fn, 2)];
+        LLVMBuildCall2(builder, report_ty, report_fn, args.as_mut_ptr(), 2, c"".as_ptr());
+        LLVMBuildUnreachable(builder);
+
+        LLVMPositionBuilderAtEnd(builder, done);
+        LLVMBuildRetVoid(builder);
+    }
+
+    Ok((check_fn, check_ty))
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn get_or_declare(module: LLVMModuleRef, name: &str, ty: LLVMTypeRef) -> Result<LLVMValueRef, SanitizeError> {
+    let c_name = CString::new(name).map_err(|_| SanitizeError::InvalidName(name.to_string()))?;
+    let existing = LLVMGetNamedFunction(module, c_name.as_ptr());
+    if !existing.is_null() {
+        return Ok(existing);
+    }
+    Ok(LLVMAddFunction(module, c_name.as_ptr(), ty))
+}
+
+#[cfg(feature = "llvm")]
+pub(super) unsafe fn add_attribute(context: LLVMContextRef, function: LLVMValueRef, name: &str) {
+    let kind = LLVMGetEnumAttributeKindForName(name.as_ptr() as *const _, name.len());
+    let attribute = LLVMCreateEnumAttribute(context, kind, 0);
+    LLVMAddAttributeAtIndex(function, llvm_sys::LLVMAttributeFunctionIndex, attribute);
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn is_integer(value: LLVMValueRef) -> bool {
+    LLVMGetTypeKind(LLVMTypeOf(value)) == LLVMTypeKind::LLVMIntegerTypeKind
+}
+
+#[cfg(feature = "llvm")]
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(value, &mut len);
+    if name.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(name).to_string_lossy().into_owned()
+}
+
+#[derive(Debug)]
+pub enum SanitizeError {
+    UnknownSanitizer(String),
+    InvalidName(String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), SanitizeError> {
+    let sanitizers = SanitizerSet::parse("undefined")?;
+    if sanitizers.undefined {
+        unsafe {
+            let mut ubsan = UndefinedSanitizer::new(Some(&source_map));
+            ubsan.instrument_module(module)?;
+        }
+        println!("{:?}", ubsan.checks());
+    }
+    // int x = INT_MAX; x + 1;  ->  prog.c:4:7: runtime error: signed integer overflow in 'main'
+    Ok(())
+}
+*/
@@ -0,0 +1,805 @@
+// src/optimizer/tiling.rs
+//! Loop tiling (-O3, JIT)
+//! A perfect nest of two or three loops is split into tiles the caches can
+//! hold: `for (i) for (j)` becomes `for (ii) for (jj) for (i in ii..) for
+//! (j in jj..)`, so a matrix kernel reuses the rows and columns it loaded
+//! before they are evicted. LLVM has no tiling pass outside Polly, so this
+//! is a small polyhedral model of its own, limited to what matrix kernels
+//! look like:
+//!
+//! - Every loop counts up by one from a start to a bound that are both
+//!   invariant in the nest (a rectangular iteration space), tests its
+//!   bound in its header and carries no value but its counter.
+//! - Nothing but the innermost loop touches memory or calls a function,
+//!   math intrinsics aside.
+//! - Subscripts are affine in the counters: sums of counters times
+//!   constants, invariant values and constants. `a[i * n + j]` counts as
+//!   `a[i][j]` when `j` runs from 0 to `n`; the subscripts of a
+//!   multi-dimensional array are in bounds, as C requires.
+//! - Tiling is legal when every dependence between two accesses, one of
+//!   them a store, has a distance vector with no negative component once
+//!   made lexicographically positive, or varies in a single loop only.
+//!   Accesses to different arrays are independent when both are locals,
+//!   globals or `restrict` parameters; anything else that may alias a store
+//!   leaves the nest alone.
+//!
+//! Tile sizes come from the target's caches, as its `FeatureDetector`
+//! reports them: the innermost two dimensions are cut so that a tile of
+//! every array fits in half the L1 data cache, in whole cache lines; the
+//! outermost loop of a three-deep nest gets a tile sized for L2. The nest
+//! is rewritten before `optimizer::loops` rotates it, and the point loops
+//! keep their own headers, so interchange can still reorder them.
+
+use std::collections::{HashMap, HashSet};
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::target::{LLVMABISizeOfType, LLVMGetModuleDataLayout, LLVMTargetDataRef};
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMIntPredicate, LLVMOpcode};
+use crate::analysis::stack_depth::value_name;
+use crate::arch::CPUFeatures;
+use crate::report::{OptimizationRemark, RemarkKind};
+use super::fenv::attribute_kind;
+use super::loops::{find_loops, phis, LoopForest, REMARK_PASS};
+
+/// Canonical form the nest is matched in: header-tested loops with
+/// preheaders, dedicated exits and LCSSA, not yet rotated
+pub const CANONICALIZE: &str = "function(sroa,early-cse,simplifycfg,instcombine,loop-simplify,lcssa)";
+
+/// Deepest nest that is tiled
+const MAX_DEPTH: usize = 3;
+
+/// Intrinsics the innermost loop may call: they touch no memory
+const PURE_INTRINSICS: &[&str] = &["llvm.fmuladd", "llvm.fma", "llvm.sqrt", "llvm.fabs", "llvm.minnum", "llvm.maxnum", "llvm.smin", "llvm.smax"];
+
+/// The data caches tiles are sized for, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheGeometry {
+    pub line: usize,
+    pub l1d: usize,
+    pub l2: usize,
+}
+
+impl CacheGeometry {
+    pub fn from_features(features: &CPUFeatures) -> Self {
+        CacheGeometry {
+            line: features.cache_line_size.max(1),
+            l1d: features.l1d_cache_size,
+            l2: features.l2_cache_size,
+        }
+    }
+
+    /// Tile sizes, outermost first, for a nest of `depth` loops over
+    /// `arrays` arrays of `element`-byte elements
+    pub fn tile_sizes(&self, depth: usize, arrays: usize, element: usize) -> Vec<u64> {
+        let element = element.max(1);
+        let per_line = (self.line / element).max(1);
+        let budget = |cache: usize| cache / 2 / (arrays.max(1) * element);
+        let round = |elements: usize| (elements / per_line * per_line).max(per_line);
+        let inner = round((budget(self.l1d) as f64).sqrt() as usize);
+        let mut sizes = vec![inner as u64; depth];
+        if depth == 3 {
+            sizes[0] = round(budget(self.l2) / inner) as u64;
+        }
+        sizes
+    }
+}
+
+pub struct LoopTilingPass {
+    cache: CacheGeometry,
+    remarks: Vec<OptimizationRemark>,
+    /// Tile loops added, per function
+    added: HashMap<String, usize>,
+    // Statistics
+    nests_tiled: usize,
+    nests_rejected: usize,
+}
+
+impl LoopTilingPass {
+    pub fn new(cache: CacheGeometry) -> Self {
+        LoopTilingPass {
+            cache,
+            remarks: Vec::new(),
+            added: HashMap::new(),
+            nests_tiled: 0,
+            nests_rejected: 0,
+        }
+    }
+
+    /// Tile the eligible nests of `module`, which must be in `CANONICALIZE`
+    /// form, and return the number of nests tiled
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> usize {
+        let before = self.nests_tiled;
+        let data_layout = LLVMGetModuleDataLayout(module);
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            let optnone = !LLVMGetEnumAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute_kind("optnone")).is_null();
+            if LLVMIsDeclaration(function) == 0 && !optnone {
+                self.tile_function(function, data_layout);
+            }
+            function = LLVMGetNextFunction(function);
+        }
+        self.nests_tiled - before
+    }
+
+    unsafe fn tile_function(&mut self, function: LLVMValueRef, data_layout: LLVMTargetDataRef) {
+        let name = value_name(function);
+        let forest = find_loops(function);
+        // Top-level nests are disjoint, and tiling one only adds blocks, so
+        // all of them can be matched before any is rewritten
+        let mut nests = Vec::new();
+        for (top, l) in forest.loops.iter().enumerate() {
+            if l.parent.is_some() || l.innermost {
+                continue;
+            }
+            match Nest::find(&forest, top, function, data_layout) {
+                Ok(nest) => nests.push(nest),
+                Err(reason) => {
+                    self.nests_rejected += 1;
+                    self.remark(&name, RemarkKind::Missed, format!("loop nest not tiled: {}", reason));
+                }
+            }
+        }
+
+        for nest in nests {
+            let sizes = self.cache.tile_sizes(nest.loops.len(), nest.arrays, nest.element);
+            if nest.fits(&sizes) {
+                self.remark(&name, RemarkKind::Analysis, "loop nest not tiled: it already fits in a tile".to_string());
+                continue;
+            }
+            nest.tile(function, &sizes);
+            self.nests_tiled += 1;
+            *self.added.entry(name.clone()).or_default() += sizes.len();
+            let shape: Vec<String> = sizes.iter().map(u64::to_string).collect();
+            self.remark(
+                &name,
+                RemarkKind::Applied,
+                format!("tiled a {}-deep loop nest in {} tiles ({} array(s) of {}-byte elements)", sizes.len(), shape.join("x"), nest.arrays, nest.element),
+            );
+        }
+    }
+
+    fn remark(&mut self, function: &str, kind: RemarkKind, message: String) {
+        self.remarks.push(OptimizationRemark {
+            pass: REMARK_PASS.to_string(),
+            function: function.to_string(),
+            kind,
+            message,
+        });
+    }
+
+    pub fn remarks(&self) -> &[OptimizationRemark] {
+        &self.remarks
+    }
+
+    /// Tile loops added to `function`
+    pub fn added(&self, function: &str) -> usize {
+        self.added.get(function).copied().unwrap_or(0)
+    }
+
+    /// (nests tiled, nests rejected)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.nests_tiled, self.nests_rejected)
+    }
+}
+
+/// One loop of the nest, in the form `CANONICALIZE` leaves it
+struct PointLoop {
+    preheader: LLVMBasicBlockRef,
+    header: LLVMBasicBlockRef,
+    /// Index of the successor of the header's branch that leaves the loop
+    exit_edge: u32,
+    induction: LLVMValueRef,
+    start: LLVMValueRef,
+    bound: LLVMValueRef,
+    compare: LLVMValueRef,
+    /// The condition to keep iterating, as `induction <predicate> bound`:
+    /// signed, unsigned or `!=`
+    predicate: LLVMIntPredicate,
+}
+
+struct Nest {
+    /// Outermost first
+    loops: Vec<PointLoop>,
+    /// Distinct arrays accessed
+    arrays: usize,
+    /// Size of the largest element accessed
+    element: usize,
+}
+
+impl Nest {
+    /// Match the perfect nest under top-level loop `top` and check that it
+    /// can be tiled; the error says why not
+    unsafe fn find(
+        forest: &LoopForest,
+        top: usize,
+        function: LLVMValueRef,
+        data_layout: LLVMTargetDataRef,
+    ) -> Result<Nest, String> {
+        let mut chain = vec![top];
+        loop {
+            let last = *chain.last().unwrap();
+            let children: Vec<usize> = (0..forest.loops.len()).filter(|&i| forest.loops[i].parent == Some(last)).collect();
+            match children[..] {
+                [] => break,
+                [child] => chain.push(child),
+                _ => return Err("more than one loop at the same level".to_string()),
+            }
+        }
+        if chain.len() > MAX_DEPTH {
+            return Err(format!("deeper than {} loops", MAX_DEPTH));
+        }
+
+        let outer = &forest.loops[top].body;
+        let mut loops = Vec::with_capacity(chain.len());
+        for &l in &chain {
+            loops.push(point_loop(forest, l, outer)?);
+        }
+
+        // Perfect: the loops around the innermost one only count
+        for pair in chain.windows(2) {
+            let (around, inner) = (&forest.loops[pair[0]], &forest.loops[pair[1]]);
+            for &block in around.body.difference(&inner.body) {
+                let mut instruction = LLVMGetFirstInstruction(forest.blocks[block]);
+                while !instruction.is_null() {
+                    if touches_memory(instruction) {
+                        return Err("statements between the loops".to_string());
+                    }
+                    instruction = LLVMGetNextInstruction(instruction);
+                }
+            }
+        }
+
+        let inductions: Vec<LLVMValueRef> = loops.iter().map(|l| l.induction).collect();
+        let context = Context { inductions: &inductions, outer, forest, loops: &loops };
+        let innermost = &forest.loops[*chain.last().unwrap()];
+        let mut accesses = Vec::new();
+        for &block in &innermost.body {
+            let mut instruction = LLVMGetFirstInstruction(forest.blocks[block]);
+            while !instruction.is_null() {
+                match LLVMGetInstructionOpcode(instruction) {
+                    LLVMOpcode::LLVMLoad | LLVMOpcode::LLVMStore if LLVMGetVolatile(instruction) != 0 => {
+                        return Err("volatile access".to_string());
+                    }
+                    LLVMOpcode::LLVMLoad => accesses.push(Access::new(instruction, false, &context, data_layout)),
+                    LLVMOpcode::LLVMStore => accesses.push(Access::new(instruction, true, &context, data_layout)),
+                    LLVMOpcode::LLVMCall if is_pure_intrinsic(instruction) => {}
+                    _ if touches_memory(instruction) => return Err("call or atomic operation in the loop body".to_string()),
+                    _ => {}
+                }
+                instruction = LLVMGetNextInstruction(instruction);
+            }
+        }
+        if !accesses.iter().any(|a| a.store) {
+            return Err("the loop body stores nothing".to_string());
+        }
+
+        for (i, a) in accesses.iter().enumerate() {
+            for b in &accesses[i..] {
+                if !a.store && !b.store {
+                    continue;
+                }
+                if a.base != b.base {
+                    if identified(a.base, function) && identified(b.base, function) {
+                        continue;
+                    }
+                    return Err(format!("'{}' and '{}' may point to the same memory", value_name(a.base), value_name(b.base)));
+                }
+                let (Some(sa), Some(sb)) = (&a.subscripts, &b.subscripts) else {
+                    return Err(format!("subscript of '{}' isn't affine in the loop counters", value_name(a.base)));
+                };
+                if a.shape != b.shape {
+                    return Err(format!("'{}' is accessed as different types", value_name(a.base)));
+                }
+                match distance(sa, sb, loops.len()) {
+                    Some(Some(vector)) if !permutable(&vector) => {
+                        return Err(format!("a dependence on '{}' would be reversed", value_name(a.base)));
+                    }
+                    Some(_) => {}
+                    None => return Err(format!("can't tell how accesses to '{}' depend on each other", value_name(a.base))),
+                }
+            }
+        }
+
+        let bases: HashSet<LLVMValueRef> = accesses.iter().map(|a| a.base).collect();
+        let element = accesses.iter().map(|a| a.size).max().unwrap_or(1);
+        Ok(Nest { loops, arrays: bases.len(), element })
+    }
+
+    /// Whether every loop with constant bounds runs no more iterations than
+    /// its tile, and at least one loop has constant bounds
+    unsafe fn fits(&self, sizes: &[u64]) -> bool {
+        let trips: Vec<Option<u64>> = self
+            .loops
+            .iter()
+            .map(|l| {
+                if LLVMIsAConstantInt(l.start).is_null() || LLVMIsAConstantInt(l.bound).is_null() {
+                    return None;
+                }
+                Some((LLVMConstIntGetSExtValue(l.bound) - LLVMConstIntGetSExtValue(l.start)).max(0) as u64)
+            })
+            .collect();
+        trips.iter().all(Option::is_some) && trips.iter().zip(sizes).all(|(trip, size)| trip.unwrap() <= *size)
+    }
+
+    /// Wrap the nest in tile loops, one per loop, and narrow each loop to
+    /// its tile:
+    ///
+    /// ```text
+    /// tile.header.k:  tt = phi [start, ...], [end, tile.latch.k]
+    ///                 end = bound - tt > T ? tt + T : bound
+    ///                 br tt <pred> bound, <next tile header or point loops>, <exit>
+    /// tile.latch.k:   br tile.header.k
+    /// ```
+    ///
+    /// The point loop k starts at `tt` and stops at `end`. `end` never
+    /// overflows: `tt + T` is only taken when it's below the bound.
+    unsafe fn tile(&self, function: LLVMValueRef, sizes: &[u64]) {
+        let context = LLVMGetTypeContext(LLVMTypeOf(function));
+        let builder = LLVMCreateBuilderInContext(context);
+        let first = &self.loops[0];
+        let outer_exit = LLVMGetSuccessor(LLVMGetBasicBlockTerminator(first.header), first.exit_edge);
+
+        let mut headers = Vec::with_capacity(sizes.len());
+        let mut latches = Vec::with_capacity(sizes.len());
+        for _ in sizes {
+            headers.push(LLVMInsertBasicBlockInContext(context, first.header, c"tile.header".as_ptr()));
+            latches.push(LLVMInsertBasicBlockInContext(context, first.header, c"tile.latch".as_ptr()));
+        }
+        let body = LLVMInsertBasicBlockInContext(context, first.header, c"tile.body".as_ptr());
+
+        let mut tile_counters = Vec::with_capacity(sizes.len());
+        let mut ends = Vec::with_capacity(sizes.len());
+        for (k, (l, &size)) in self.loops.iter().zip(sizes).enumerate() {
+            let ty = LLVMTypeOf(l.induction);
+            let (entry, next, exit) = match k {
+                0 => (l.preheader, headers.get(1).copied().unwrap_or(body), outer_exit),
+                _ => (headers[k - 1], headers.get(k + 1).copied().unwrap_or(body), latches[k - 1]),
+            };
+            LLVMPositionBuilderAtEnd(builder, headers[k]);
+            let counter = LLVMBuildPhi(builder, ty, c"tile.iv".as_ptr());
+            let tile = LLVMConstInt(ty, size, 0);
+            let remaining = LLVMBuildSub(builder, l.bound, counter, c"tile.rem".as_ptr());
+            let whole = LLVMBuildICmp(builder, LLVMIntPredicate::LLVMIntUGT, remaining, tile, c"tile.whole".as_ptr());
+            let step = LLVMBuildAdd(builder, counter, tile, c"tile.step".as_ptr());
+            let end = LLVMBuildSelect(builder, whole, step, l.bound, c"tile.end".as_ptr());
+            let more = LLVMBuildICmp(builder, l.predicate, counter, l.bound, c"tile.more".as_ptr());
+            LLVMBuildCondBr(builder, more, next, exit);
+            LLVMPositionBuilderAtEnd(builder, latches[k]);
+            LLVMBuildBr(builder, headers[k]);
+
+            let mut values = [l.start, end];
+            let mut blocks = [entry, latches[k]];
+            LLVMAddIncoming(counter, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+            tile_counters.push(counter);
+            ends.push(end);
+        }
+        LLVMPositionBuilderAtEnd(builder, body);
+        LLVMBuildBr(builder, first.header);
+
+        // Into the tiles from the outermost preheader, and back to the
+        // innermost tile latch when the point loops are done
+        let terminator = LLVMGetBasicBlockTerminator(first.preheader);
+        for i in 0..LLVMGetNumSuccessors(terminator) {
+            if LLVMGetSuccessor(terminator, i) == first.header {
+                LLVMSetSuccessor(terminator, i, headers[0]);
+            }
+        }
+        LLVMSetSuccessor(LLVMGetBasicBlockTerminator(first.header), first.exit_edge, *latches.last().unwrap());
+
+        for (k, l) in self.loops.iter().enumerate() {
+            LLVMSetOperand(l.compare, 1, ends[k]);
+            if k > 0 {
+                for i in 0..LLVMCountIncoming(l.induction) {
+                    if LLVMGetIncomingBlock(l.induction, i) == l.preheader {
+                        LLVMSetOperand(l.induction, i, tile_counters[k]);
+                    }
+                }
+                continue;
+            }
+            // The outermost point loop is entered from tile.body now; a phi's
+            // incoming blocks can't be set through the C API, so rebuild it
+            LLVMPositionBuilderBefore(builder, l.induction);
+            let phi = LLVMBuildPhi(builder, LLVMTypeOf(l.induction), c"".as_ptr());
+            for i in 0..LLVMCountIncoming(l.induction) {
+                let mut block = LLVMGetIncomingBlock(l.induction, i);
+                let mut value = LLVMGetIncomingValue(l.induction, i);
+                if block == l.preheader {
+                    block = body;
+                    value = tile_counters[0];
+                }
+                LLVMAddIncoming(phi, &mut value, &mut block, 1);
+            }
+            let mut len = 0;
+            let name = LLVMGetValueName2(l.induction, &mut len);
+            LLVMReplaceAllUsesWith(l.induction, phi);
+            LLVMSetValueName2(phi, name, len);
+            LLVMInstructionEraseFromParent(l.induction);
+        }
+        LLVMDisposeBuilder(builder);
+    }
+}
+
+/// Match loop `index` of `forest` as a header-tested counting loop whose
+/// start and bound don't change inside `outer`
+unsafe fn point_loop(forest: &LoopForest, index: usize, outer: &HashSet<usize>) -> Result<PointLoop, String> {
+    let l = &forest.loops[index];
+    let header = forest.blocks[l.header];
+    let not_counting = || "a loop doesn't count up by one to a bound tested in its header".to_string();
+
+    for &block in &l.body {
+        if block != l.header && forest.successors[block].iter().any(|s| !l.body.contains(s)) {
+            return Err("a loop has more than one exit".to_string());
+        }
+    }
+    let terminator = LLVMGetBasicBlockTerminator(header);
+    if LLVMGetNumSuccessors(terminator) != 2 {
+        return Err(not_counting());
+    }
+    let inside = |edge: u32| l.body.contains(&forest.successors[l.header][edge as usize]);
+    let (continue_on_true, exit_edge) = match (inside(0), inside(1)) {
+        (true, false) => (true, 1),
+        (false, true) => (false, 0),
+        _ => return Err(not_counting()),
+    };
+    let exit = LLVMGetSuccessor(terminator, exit_edge);
+    if !phis(exit).is_empty() {
+        return Err("a value computed in the nest is used after it".to_string());
+    }
+
+    let [induction] = phis(header)[..] else {
+        return Err("a loop carries a value besides its counter".to_string());
+    };
+    if LLVMCountIncoming(induction) != 2 {
+        return Err(not_counting());
+    }
+    let mut preheader = None;
+    let mut next = None;
+    for i in 0..2 {
+        let block = LLVMGetIncomingBlock(induction, i);
+        let value = LLVMGetIncomingValue(induction, i);
+        if l.body.contains(&forest.blocks.iter().position(|&b| b == block).unwrap_or(usize::MAX)) {
+            next = Some(value);
+        } else {
+            preheader = Some((block, value));
+        }
+    }
+    let (Some((preheader, start)), Some(next)) = (preheader, next) else { return Err(not_counting()) };
+    if LLVMGetNumSuccessors(LLVMGetBasicBlockTerminator(preheader)) != 1 {
+        return Err(not_counting());
+    }
+    let steps_by_one = !LLVMIsAInstruction(next).is_null()
+        && LLVMGetInstructionOpcode(next) == LLVMOpcode::LLVMAdd
+        && LLVMGetOperand(next, 0) == induction
+        && !LLVMIsAConstantInt(LLVMGetOperand(next, 1)).is_null()
+        && LLVMConstIntGetZExtValue(LLVMGetOperand(next, 1)) == 1;
+    if !steps_by_one {
+        return Err(not_counting());
+    }
+
+    let compare = LLVMGetCondition(terminator);
+    if LLVMIsAICmpInst(compare).is_null() || LLVMGetOperand(compare, 0) != induction || !LLVMGetNextUse(LLVMGetFirstUse(compare)).is_null() {
+        return Err(not_counting());
+    }
+    use LLVMIntPredicate::*;
+    let predicate = match (LLVMGetICmpPredicate(compare), continue_on_true) {
+        (LLVMIntSLT, true) | (LLVMIntSGE, false) => LLVMIntSLT,
+        (LLVMIntULT, true) | (LLVMIntUGE, false) => LLVMIntULT,
+        (LLVMIntNE, true) | (LLVMIntEQ, false) => LLVMIntNE,
+        _ => return Err(not_counting()),
+    };
+    let bound = LLVMGetOperand(compare, 1);
+    if !invariant(start, forest, outer) || !invariant(bound, forest, outer) {
+        return Err("loop bounds change inside the nest".to_string());
+    }
+    Ok(PointLoop { preheader, header, exit_edge, induction, start, bound, compare, predicate })
+}
+
+/// Whether `value` is computed outside the blocks `outer`
+unsafe fn invariant(value: LLVMValueRef, forest: &LoopForest, outer: &HashSet<usize>) -> bool {
+    if LLVMIsAInstruction(value).is_null() {
+        return true;
+    }
+    let block = LLVMGetInstructionParent(value);
+    forest.blocks.iter().position(|&b| b == block).is_none_or(|b| !outer.contains(&b))
+}
+
+unsafe fn touches_memory(instruction: LLVMValueRef) -> bool {
+    matches!(
+        LLVMGetInstructionOpcode(instruction),
+        LLVMOpcode::LLVMLoad
+            | LLVMOpcode::LLVMStore
+            | LLVMOpcode::LLVMCall
+            | LLVMOpcode::LLVMInvoke
+            | LLVMOpcode::LLVMAtomicRMW
+            | LLVMOpcode::LLVMAtomicCmpXchg
+            | LLVMOpcode::LLVMFence
+            | LLVMOpcode::LLVMVAArg
+    )
+}
+
+unsafe fn is_pure_intrinsic(call: LLVMValueRef) -> bool {
+    let callee = LLVMGetCalledValue(call);
+    if LLVMIsAFunction(callee).is_null() || LLVMGetIntrinsicID(callee) == 0 {
+        return false;
+    }
+    let name = value_name(callee);
+    PURE_INTRINSICS.iter().any(|pure| name == *pure || name.starts_with(&format!("{}.", pure)))
+}
+
+/// An object no other pointer reaches: a local, a global or a `restrict`
+/// parameter of `function`
+unsafe fn identified(base: LLVMValueRef, function: LLVMValueRef) -> bool {
+    if !LLVMIsAAllocaInst(base).is_null() || !LLVMIsAGlobalVariable(base).is_null() {
+        return true;
+    }
+    if LLVMIsAArgument(base).is_null() {
+        return false;
+    }
+    (0..LLVMCountParams(function)).any(|i| {
+        LLVMGetParam(function, i) == base && !LLVMGetEnumAttributeAtIndex(function, i + 1, attribute_kind("noalias")).is_null()
+    })
+}
+
+struct Context<'a> {
+    inductions: &'a [LLVMValueRef],
+    outer: &'a HashSet<usize>,
+    forest: &'a LoopForest,
+    loops: &'a [PointLoop],
+}
+
+struct Access {
+    store: bool,
+    /// The pointer the GEPs start from
+    base: LLVMValueRef,
+    /// One per array dimension, outermost first; `None` if any isn't affine
+    subscripts: Option<Vec<Affine>>,
+    /// Source element type of each GEP from the base, and the type accessed
+    shape: Vec<LLVMTypeRef>,
+    /// Bytes accessed
+    size: usize,
+}
+
+impl Access {
+    unsafe fn new(instruction: LLVMValueRef, store: bool, context: &Context, data_layout: LLVMTargetDataRef) -> Access {
+        let (pointer, ty) = if store {
+            (LLVMGetOperand(instruction, 1), LLVMTypeOf(LLVMGetOperand(instruction, 0)))
+        } else {
+            (LLVMGetOperand(instruction, 0), LLVMTypeOf(instruction))
+        };
+        let mut geps = Vec::new();
+        let mut base = pointer;
+        loop {
+            if !LLVMIsAGetElementPtrInst(base).is_null() {
+                geps.push(base);
+                base = LLVMGetOperand(base, 0);
+            } else if !LLVMIsABitCastInst(base).is_null() {
+                base = LLVMGetOperand(base, 0);
+            } else {
+                break;
+            }
+        }
+        geps.reverse();
+
+        let mut subscripts = Some(Vec::new());
+        let mut shape = Vec::with_capacity(geps.len() + 1);
+        for &gep in &geps {
+            shape.push(LLVMGetGEPSourceElementType(gep));
+            for i in 1..LLVMGetNumOperands(gep) as u32 {
+                let index = affine(LLVMGetOperand(gep, i), context);
+                subscripts = match (subscripts, index.and_then(|index| delinearize(index, context))) {
+                    (Some(mut list), Some(dimensions)) => {
+                        list.extend(dimensions);
+                        Some(list)
+                    }
+                    _ => None,
+                };
+            }
+        }
+        shape.push(ty);
+        Access { store, base, subscripts, shape, size: LLVMABISizeOfType(data_layout, ty) as usize }
+    }
+}
+
+/// A subscript: counters times coefficients, plus invariant values, plus a
+/// constant
+#[derive(Debug, Clone, PartialEq)]
+struct Affine {
+    /// (position of the loop in the nest, coefficient), sorted by loop
+    terms: Vec<(usize, Coefficient)>,
+    /// Sorted by address so equal sums compare equal
+    invariant: Vec<LLVMValueRef>,
+    constant: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Coefficient {
+    Constant(i64),
+    /// A value invariant in the nest, such as a row length
+    Symbol(LLVMValueRef),
+}
+
+impl Affine {
+    fn constant(constant: i64) -> Affine {
+        Affine { terms: Vec::new(), invariant: Vec::new(), constant }
+    }
+
+    fn counter(position: usize) -> Affine {
+        Affine { terms: vec![(position, Coefficient::Constant(1))], ..Affine::constant(0) }
+    }
+
+    fn add(mut self, other: Affine) -> Option<Affine> {
+        for (position, coefficient) in other.terms {
+            match self.terms.iter_mut().find(|(p, _)| *p == position) {
+                Some((_, Coefficient::Constant(a))) => match coefficient {
+                    Coefficient::Constant(b) => *a = a.checked_add(b)?,
+                    Coefficient::Symbol(_) => return None,
+                },
+                Some(_) => return None,
+                None => self.terms.push((position, coefficient)),
+            }
+        }
+        self.terms.retain(|(_, coefficient)| *coefficient != Coefficient::Constant(0));
+        self.terms.sort_by_key(|(position, _)| *position);
+        self.invariant.extend(other.invariant);
+        self.invariant.sort_by_key(|value| *value as usize);
+        self.constant = self.constant.checked_add(other.constant)?;
+        Some(self)
+    }
+
+    fn scale(mut self, factor: i64) -> Option<Affine> {
+        if factor == 0 {
+            return Some(Affine::constant(0));
+        }
+        if factor != 1 && !self.invariant.is_empty() {
+            return None;
+        }
+        for (_, coefficient) in &mut self.terms {
+            *coefficient = match *coefficient {
+                Coefficient::Constant(c) => Coefficient::Constant(c.checked_mul(factor)?),
+                Coefficient::Symbol(_) if factor == 1 => *coefficient,
+                Coefficient::Symbol(_) => return None,
+            };
+        }
+        self.constant = self.constant.checked_mul(factor)?;
+        Some(self)
+    }
+
+    /// `counter * symbol`
+    fn times(self, symbol: LLVMValueRef) -> Option<Affine> {
+        match &self.terms[..] {
+            [(position, Coefficient::Constant(1))] if self.invariant.is_empty() && self.constant == 0 => {
+                Some(Affine { terms: vec![(*position, Coefficient::Symbol(symbol))], ..Affine::constant(0) })
+            }
+            _ => None,
+        }
+    }
+}
+
+unsafe fn affine(value: LLVMValueRef, context: &Context) -> Option<Affine> {
+    if let Some(position) = context.inductions.iter().position(|&induction| induction == value) {
+        return Some(Affine::counter(position));
+    }
+    if !LLVMIsAConstantInt(value).is_null() {
+        return Some(Affine::constant(LLVMConstIntGetSExtValue(value)));
+    }
+    if invariant(value, context.forest, context.outer) {
+        return Some(Affine { invariant: vec![value], ..Affine::constant(0) });
+    }
+    if LLVMIsAInstruction(value).is_null() {
+        return None;
+    }
+    let operand = |i: u32| LLVMGetOperand(value, i);
+    let constant = |i: u32| (!LLVMIsAConstantInt(operand(i)).is_null()).then(|| LLVMConstIntGetSExtValue(operand(i)));
+    let symbol = |i: u32| {
+        let v = operand(i);
+        (LLVMIsAConstantInt(v).is_null() && invariant(v, context.forest, context.outer)).then_some(v)
+    };
+    match LLVMGetInstructionOpcode(value) {
+        LLVMOpcode::LLVMSExt | LLVMOpcode::LLVMZExt | LLVMOpcode::LLVMTrunc => affine(operand(0), context),
+        LLVMOpcode::LLVMAdd => affine(operand(0), context)?.add(affine(operand(1), context)?),
+        LLVMOpcode::LLVMSub => affine(operand(0), context)?.add(affine(operand(1), context)?.scale(-1)?),
+        LLVMOpcode::LLVMMul => match (constant(0), constant(1), symbol(0), symbol(1)) {
+            (_, Some(c), _, _) => affine(operand(0), context)?.scale(c),
+            (Some(c), _, _, _) => affine(operand(1), context)?.scale(c),
+            (_, _, _, Some(s)) => affine(operand(0), context)?.times(s),
+            (_, _, Some(s), _) => affine(operand(1), context)?.times(s),
+            _ => None,
+        },
+        LLVMOpcode::LLVMShl => match constant(1) {
+            Some(c @ 0..=62) => affine(operand(0), context)?.scale(1 << c),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Split `i * n + j` into the dimensions `[i, j]` when loop `j` runs over
+/// `0..n`, so every element lies in exactly one row; other subscripts are
+/// one dimension, or `None` if a coefficient is symbolic
+unsafe fn delinearize(subscript: Affine, context: &Context) -> Option<Vec<Affine>> {
+    let row_length = |coefficient: Coefficient| match coefficient {
+        Coefficient::Symbol(value) => Some(value),
+        Coefficient::Constant(_) => None,
+    };
+    match subscript.terms[..] {
+        [(row, rows), (column, Coefficient::Constant(1))] | [(column, Coefficient::Constant(1)), (row, rows)]
+            if subscript.invariant.is_empty() && subscript.constant == 0 =>
+        {
+            let l = &context.loops[column];
+            let starts_at_zero = !LLVMIsAConstantInt(l.start).is_null() && LLVMConstIntGetSExtValue(l.start) == 0;
+            let spans_row = match rows {
+                Coefficient::Symbol(length) => l.bound == length,
+                Coefficient::Constant(length) => {
+                    !LLVMIsAConstantInt(l.bound).is_null() && LLVMConstIntGetSExtValue(l.bound) == length && length > 1
+                }
+            };
+            if !starts_at_zero || !spans_row {
+                return subscript.terms.iter().all(|(_, c)| row_length(*c).is_none()).then(|| vec![subscript]);
+            }
+            Some(vec![Affine::counter(row), Affine::counter(column)])
+        }
+        _ if subscript.terms.iter().any(|(_, c)| row_length(*c).is_some()) => None,
+        _ => Some(vec![subscript]),
+    }
+}
+
+/// Iteration distance from the first to the second access to the same
+/// element, per loop: `Some(None)` if they never touch the same element,
+/// `None` per loop a subscript doesn't mention, and `None` overall if the
+/// subscripts can't be compared
+fn distance(a: &[Affine], b: &[Affine], depth: usize) -> Option<Option<Vec<Option<i64>>>> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let mut vector = vec![None; depth];
+    for (a, b) in a.iter().zip(b) {
+        if a.terms != b.terms || a.invariant != b.invariant {
+            return None;
+        }
+        // a at I and b at I' touch the same element when I' - I = a.c - b.c
+        let difference = a.constant.checked_sub(b.constant)?;
+        match a.terms[..] {
+            [] if difference != 0 => return Some(None),
+            [] => {}
+            [(position, Coefficient::Constant(coefficient))] => {
+                if difference % coefficient != 0 {
+                    return Some(None);
+                }
+                let d = difference / coefficient;
+                match vector[position] {
+                    Some(other) if other != d => return Some(None),
+                    _ => vector[position] = Some(d),
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(Some(vector))
+}
+
+/// Whether tiling keeps a dependence with distance `vector` in order: it
+/// varies in one loop only, or it is non-negative in every loop once the
+/// earlier access is taken as its source
+fn permutable(vector: &[Option<i64>]) -> bool {
+    let unknown = vector.iter().filter(|d| d.is_none()).count();
+    if unknown > 0 {
+        return unknown == 1 && vector.iter().flatten().all(|&d| d == 0);
+    }
+    let sign = vector.iter().flatten().find(|&&d| d != 0).map_or(1, |d| d.signum());
+    vector.iter().flatten().all(|&d| d * sign >= 0)
+}
+
+// Example usage:
+/*
+unsafe fn tile_for_host(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef, features: &CPUFeatures) {
+    // Header-tested loops first; the nest is matched in that form
+    run_passes(module, CANONICALIZE, target_machine)?;
+    let mut pass = LoopTilingPass::new(CacheGeometry::from_features(features));
+    let tiled = pass.run(module);
+    let (_, rejected) = pass.stats();
+    println!("{} nest(s) tiled, {} left alone", tiled, rejected);
+    for remark in pass.remarks() {
+        println!("remark: {}: {}", remark.function, remark.message);
+    }
+}
+*/
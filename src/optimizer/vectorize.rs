@@ -0,0 +1,1010 @@
+// src/optimizer/vectorize.rs
+//! Vectorization cost model (-O2)
+//! LLVM's loop vectorizer picks a width and an interleave count from the
+//! target machine, which knows the CPU by name only. This pass makes the
+//! choice for it from the features the target's `FeatureDetector`
+//! reports. Each innermost loop is costed as scalar code and at every
+//! vector width the registers allow, with the latencies and reciprocal
+//! throughputs of SSE, AVX, AVX2 and AVX-512 or NEON and SVE. The cheapest
+//! choice goes on the loop as `llvm.loop.vectorize.width` and
+//! `llvm.loop.interleave.count`, the metadata `#pragma clang loop` sets; a
+//! width of 1 keeps the loop scalar.
+//!
+//! An iteration costs the larger of two bounds. One is the reciprocal
+//! throughputs of its instructions summed, as if they shared a port, plus
+//! the increment, compare and branch. The other is the latency of the
+//! longest reduction chain carried from one iteration to the next.
+//! Interleaving runs independent copies of the vector body, which hides
+//! that latency until the registers run out.
+//!
+//! Operations the vector unit lacks are costed as one scalar operation per
+//! lane plus moving the lanes in and out. Examples are integer division,
+//! or 64-bit multiplies before AVX-512 and on NEON. Strided accesses cost
+//! as gathers and scatters where the ISA has them. The vector loop also
+//! pays, once, for reducing its accumulators and for the scalar epilogue
+//! that runs the iterations left over. Those costs are spread over the
+//! trip count when it is a constant, else over `ASSUMED_TRIP_COUNT`.
+//!
+//! AVX-512 and SVE can mask off lanes, with opmask and predicate
+//! registers. There a store under a condition is a masked store rather
+//! than a load, a blend and a store, and the tail can be folded into the
+//! vector loop: every iteration runs under a mask of the lanes still in
+//! range, the last one partly idle, and there is no scalar epilogue. That
+//! costs building the mask each iteration, so a folded tail is costed
+//! against the epilogue at every width and interleave count, and goes on
+//! the loop as `llvm.loop.vectorize.predicate.enable` when it wins.
+//!
+//! LLVM could not vectorize some loops anyway, and the pass leaves them
+//! alone with a remark saying why:
+//!
+//! - calls other than math intrinsics
+//! - volatile or atomic accesses
+//! - a store to the same address every iteration
+//! - more than one exit
+//! - a value carried between iterations that isn't a reduction
+//! - a floating-point reduction without reassociation (`-ffast-math`)
+//!
+//! Loops that already carry `llvm.loop` hints keep them. Every decision is
+//! a remark under the pass name `vectorize`, for `-Rpass`,
+//! `-Rpass-missed` and `-Rpass-analysis`.
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use llvm_sys::core::*;
+use llvm_sys::debuginfo::{LLVMMetadataReplaceAllUsesWith, LLVMTemporaryMDNode};
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+use llvm_sys::prelude::*;
+use llvm_sys::target_machine::LLVMTargetMachineRef;
+use llvm_sys::transforms::pass_builder::*;
+use llvm_sys::{LLVMAtomicOrdering, LLVMAttributeFunctionIndex, LLVMFastMathAllowReassoc, LLVMOpcode, LLVMTypeKind};
+use crate::analysis::stack_depth::{called_function, value_name};
+use crate::arch::intrinsics::has;
+use crate::arch::CPUFeatures;
+use crate::report::{OptimizationRemark, RemarkKind};
+use super::fenv::attribute_kind;
+use super::loops::{access_stride, find_loops, phis, Loop, LoopForest, Stride};
+use super::tiling::CANONICALIZE;
+
+/// Pass name the remarks are filed under
+pub const REMARK_PASS: &str = "vectorize";
+
+/// Trip count assumed for loops whose bounds aren't constants
+pub const ASSUMED_TRIP_COUNT: u64 = 256;
+
+/// Interleave counts tried at every width
+const INTERLEAVE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// Increment, compare and branch, per iteration of either loop
+const LOOP_OVERHEAD: f64 = 1.0;
+
+/// Integer registers a scalar loop has to work with
+const SCALAR_REGISTERS: u32 = 16;
+
+/// Intrinsics a vector loop may call, and what each costs as
+const VECTOR_INTRINSICS: &[(&str, Op)] = &[
+    ("llvm.fmuladd", Op::FloatMul),
+    ("llvm.fma", Op::FloatMul),
+    ("llvm.sqrt", Op::FloatDiv),
+    ("llvm.fabs", Op::FloatAdd),
+    ("llvm.minnum", Op::FloatAdd),
+    ("llvm.maxnum", Op::FloatAdd),
+    ("llvm.smin", Op::IntAlu),
+    ("llvm.smax", Op::IntAlu),
+    ("llvm.umin", Op::IntAlu),
+    ("llvm.umax", Op::IntAlu),
+    ("llvm.abs", Op::IntAlu),
+];
+
+/// Intrinsics that generate no code
+const FREE_INTRINSICS: &[&str] = &["llvm.dbg.", "llvm.lifetime.", "llvm.assume", "llvm.experimental.noalias.scope.decl"];
+
+/// The SIMD extension loops are costed for: the widest one the features
+/// list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorIsa {
+    /// No vector unit the model knows
+    None,
+    Sse,
+    /// 256-bit floating point; integer operations run as two 128-bit halves
+    Avx,
+    Avx2,
+    Avx512,
+    Neon,
+    /// `bits` is the shortest vector length the code may run with
+    Sve { bits: u32 },
+}
+
+impl VectorIsa {
+    pub fn from_features(features: &CPUFeatures) -> Self {
+        if has(features, "avx512f") {
+            VectorIsa::Avx512
+        } else if has(features, "avx2") {
+            VectorIsa::Avx2
+        } else if has(features, "avx") {
+            VectorIsa::Avx
+        } else if has(features, "sse2") {
+            VectorIsa::Sse
+        } else if has(features, "sve") {
+            VectorIsa::Sve { bits: (features.vector_width as u32 * 8).max(128) }
+        } else if has(features, "neon") || has(features, "asimd") {
+            VectorIsa::Neon
+        } else {
+            VectorIsa::None
+        }
+    }
+
+    /// Bits in a vector register
+    pub fn register_bits(self) -> u32 {
+        match self {
+            VectorIsa::None => 0,
+            VectorIsa::Sse | VectorIsa::Neon => 128,
+            VectorIsa::Avx | VectorIsa::Avx2 => 256,
+            VectorIsa::Avx512 => 512,
+            VectorIsa::Sve { bits } => bits,
+        }
+    }
+
+    /// Whether the ISA can mask off lanes, for conditional stores and a
+    /// folded tail
+    pub fn predicated(self) -> bool {
+        matches!(self, VectorIsa::Avx512 | VectorIsa::Sve { .. })
+    }
+
+    /// Vector registers a loop can keep values in
+    pub fn registers(self) -> u32 {
+        match self {
+            VectorIsa::None => 0,
+            VectorIsa::Sse | VectorIsa::Avx | VectorIsa::Avx2 => 16,
+            VectorIsa::Avx512 | VectorIsa::Neon | VectorIsa::Sve { .. } => 32,
+        }
+    }
+
+    /// One register-wide `op` on lanes of `bits` bits; `None` where there
+    /// is no instruction for it. Figures are for recent cores of each
+    /// extension, rounded.
+    pub fn cost(self, op: Op, bits: u32) -> Option<Cost> {
+        let wide = bits == 64;
+        match self {
+            VectorIsa::None => None,
+            VectorIsa::Sse => match op {
+                Op::IntAlu => cost(1.0, 0.5),
+                Op::IntMul if bits == 16 => cost(5.0, 0.5),
+                Op::IntMul if bits == 32 => cost(10.0, 1.0),
+                Op::FloatAdd | Op::FloatMul => cost(4.0, 0.5),
+                Op::FloatDiv if wide => cost(14.0, 4.0),
+                Op::FloatDiv => cost(11.0, 3.0),
+                Op::Convert => cost(4.0, 1.0),
+                Op::Load => cost(6.0, 0.5),
+                Op::Store => cost(1.0, 1.0),
+                Op::Lane => cost(2.0, 1.0),
+                _ => None,
+            },
+            VectorIsa::Avx => match op {
+                Op::IntAlu => cost(1.0, 1.0),
+                Op::IntMul if bits == 16 => cost(5.0, 1.0),
+                Op::IntMul if bits == 32 => cost(10.0, 2.0),
+                Op::FloatAdd | Op::FloatMul => cost(4.0, 0.5),
+                Op::FloatDiv if wide => cost(13.0, 8.0),
+                Op::FloatDiv => cost(11.0, 5.0),
+                Op::Convert => cost(4.0, 1.0),
+                Op::Load => cost(7.0, 0.5),
+                Op::Store => cost(1.0, 1.0),
+                Op::Lane => cost(3.0, 1.0),
+                _ => None,
+            },
+            VectorIsa::Avx2 => match op {
+                Op::IntAlu => cost(1.0, 0.33),
+                Op::IntMul if bits == 16 => cost(5.0, 0.5),
+                Op::IntMul if bits == 32 => cost(10.0, 1.0),
+                Op::FloatAdd | Op::FloatMul => cost(4.0, 0.5),
+                Op::FloatDiv if wide => cost(13.0, 8.0),
+                Op::FloatDiv => cost(11.0, 5.0),
+                Op::Convert => cost(4.0, 1.0),
+                Op::Load => cost(7.0, 0.5),
+                Op::Store => cost(1.0, 1.0),
+                Op::Gather if bits >= 32 => cost(20.0, 5.0),
+                Op::Lane => cost(3.0, 1.0),
+                _ => None,
+            },
+            VectorIsa::Avx512 => match op {
+                Op::IntAlu => cost(1.0, 0.5),
+                Op::IntMul if bits == 16 => cost(5.0, 0.5),
+                Op::IntMul if bits == 32 => cost(10.0, 1.0),
+                Op::IntMul if wide => cost(15.0, 1.5),
+                Op::FloatAdd | Op::FloatMul => cost(4.0, 0.5),
+                Op::FloatDiv if wide => cost(23.0, 16.0),
+                Op::FloatDiv => cost(18.0, 10.0),
+                Op::Convert => cost(4.0, 1.0),
+                Op::Load => cost(8.0, 0.5),
+                Op::Store => cost(1.0, 1.0),
+                Op::Gather if wide => cost(22.0, 5.0),
+                Op::Gather if bits == 32 => cost(22.0, 10.0),
+                Op::Scatter if wide => cost(20.0, 8.0),
+                Op::Scatter if bits == 32 => cost(20.0, 11.0),
+                Op::Lane => cost(3.0, 1.0),
+                _ => None,
+            },
+            VectorIsa::Neon => match op {
+                Op::IntAlu => cost(2.0, 0.5),
+                Op::IntMul if bits <= 32 => cost(4.0, 1.0),
+                Op::FloatAdd => cost(2.0, 0.5),
+                Op::FloatMul => cost(3.0, 0.5),
+                Op::FloatDiv if wide => cost(15.0, 12.0),
+                Op::FloatDiv => cost(10.0, 7.0),
+                Op::Convert => cost(3.0, 0.5),
+                Op::Load => cost(6.0, 0.5),
+                Op::Store => cost(2.0, 1.0),
+                Op::Lane => cost(2.0, 1.0),
+                _ => None,
+            },
+            VectorIsa::Sve { .. } => match op {
+                Op::IntAlu => cost(2.0, 0.5),
+                Op::IntMul if bits <= 32 => cost(4.0, 1.0),
+                Op::IntMul => cost(5.0, 2.0),
+                Op::IntDiv if wide => cost(20.0, 20.0),
+                Op::IntDiv if bits == 32 => cost(12.0, 12.0),
+                Op::FloatAdd => cost(2.0, 0.5),
+                Op::FloatMul => cost(3.0, 0.5),
+                Op::FloatDiv if wide => cost(15.0, 13.0),
+                Op::FloatDiv => cost(10.0, 8.0),
+                Op::Convert => cost(3.0, 0.5),
+                Op::Load => cost(6.0, 0.5),
+                Op::Store => cost(2.0, 1.0),
+                Op::Gather if wide => cost(9.0, 4.0),
+                Op::Gather if bits == 32 => cost(9.0, 8.0),
+                Op::Scatter if wide => cost(5.0, 4.0),
+                Op::Scatter if bits == 32 => cost(5.0, 8.0),
+                Op::Lane => cost(2.0, 1.0),
+                _ => None,
+            },
+        }
+    }
+
+    /// `op` on `lanes` lanes of `bits` bits, scalarized where there's no
+    /// instruction for it
+    pub fn vector_cost(self, op: Op, bits: u32, lanes: u32) -> Cost {
+        if let Some(cost) = self.cost(op, bits) {
+            return cost;
+        }
+        let scalar = scalar_cost(op, bits);
+        let lane = self.cost(Op::Lane, bits).unwrap_or(scalar_cost(Op::Lane, bits));
+        Cost {
+            latency: scalar.latency + 2.0 * lane.latency,
+            throughput: lanes as f64 * (scalar.throughput + 2.0 * lane.throughput),
+        }
+    }
+}
+
+impl fmt::Display for VectorIsa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorIsa::None => write!(f, "no SIMD"),
+            VectorIsa::Sse => write!(f, "SSE"),
+            VectorIsa::Avx => write!(f, "AVX"),
+            VectorIsa::Avx2 => write!(f, "AVX2"),
+            VectorIsa::Avx512 => write!(f, "AVX-512"),
+            VectorIsa::Neon => write!(f, "NEON"),
+            VectorIsa::Sve { bits } => write!(f, "SVE ({}-bit)", bits),
+        }
+    }
+}
+
+/// What an instruction costs as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Add, logic, shift, compare, select and integer casts
+    IntAlu,
+    IntMul,
+    /// Division and remainder
+    IntDiv,
+    /// Add, subtract, compare, absolute value, minimum and maximum
+    FloatAdd,
+    /// Multiply, fused or not
+    FloatMul,
+    /// Division and square root
+    FloatDiv,
+    /// Between integers and floating point, or between float widths
+    Convert,
+    Load,
+    Store,
+    /// A load of lanes a stride apart
+    Gather,
+    Scatter,
+    /// Moving a lane between a vector and a scalar register
+    Lane,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cost {
+    /// Cycles until the result can be used
+    pub latency: f64,
+    /// Reciprocal throughput: cycles between two independent issues
+    pub throughput: f64,
+}
+
+fn cost(latency: f64, throughput: f64) -> Option<Cost> {
+    Some(Cost { latency, throughput })
+}
+
+/// One scalar `op` on `bits` bits; the cores of both families agree on
+/// these closely enough
+pub fn scalar_cost(op: Op, bits: u32) -> Cost {
+    let wide = bits == 64;
+    let (latency, throughput) = match op {
+        Op::IntAlu => (1.0, 0.33),
+        Op::IntMul => (3.0, 1.0),
+        Op::IntDiv if wide => (40.0, 20.0),
+        Op::IntDiv => (20.0, 6.0),
+        Op::FloatAdd | Op::FloatMul => (4.0, 0.5),
+        Op::FloatDiv if wide => (14.0, 4.0),
+        Op::FloatDiv => (11.0, 3.0),
+        Op::Convert => (4.0, 1.0),
+        Op::Load | Op::Gather => (5.0, 0.5),
+        Op::Store | Op::Scatter => (1.0, 1.0),
+        Op::Lane => (0.0, 0.0),
+    };
+    Cost { latency, throughput }
+}
+
+/// An instruction of the loop body as the model sees it
+#[derive(Debug, Clone, Copy)]
+struct Operation {
+    op: Op,
+    /// Lane width
+    bits: u32,
+    /// Part of a conditional store done as a load and a blend, which a
+    /// masked store doesn't need
+    blend: bool,
+}
+
+/// The parts of an innermost loop the cost model needs
+struct LoopShape {
+    operations: Vec<Operation>,
+    /// The operation each reduction folds a lane into its accumulator with
+    reductions: Vec<Operation>,
+    /// Widest lane, which bounds the vector width
+    widest: u32,
+    trip_count: Option<u64>,
+    /// Values each copy of the body keeps in registers at once, roughly
+    live: u32,
+    latch: LLVMValueRef,
+}
+
+/// Latency/throughput model of one ISA
+#[derive(Debug, Clone, Copy)]
+pub struct CostModel {
+    pub isa: VectorIsa,
+}
+
+impl CostModel {
+    pub fn new(isa: VectorIsa) -> Self {
+        CostModel { isa }
+    }
+
+    /// Cycles per iteration of the original loop, run `width` lanes at a
+    /// time and interleaved `interleave` times, given that the scalar loop
+    /// takes `scalar` cycles per iteration. `predicated` folds the tail
+    /// into the vector loop, which the ISA must be able to mask for.
+    fn cycles(&self, shape: &LoopShape, width: u32, interleave: u32, predicated: bool, scalar: f64) -> f64 {
+        let cost = |operation: &Operation| match width {
+            1 => scalar_cost(operation.op, operation.bits),
+            _ => self.isa.vector_cost(operation.op, operation.bits, width),
+        };
+        let masked = width > 1 && self.isa.predicated();
+        let throughput: f64 = shape
+            .operations
+            .iter()
+            .chain(&shape.reductions)
+            .filter(|o| !(masked && o.blend))
+            .map(|o| cost(o).throughput)
+            .sum();
+        let chain = shape.reductions.iter().map(|o| cost(o).latency).fold(0.0, f64::max);
+        // A folded tail builds the mask of the lanes in range for each copy
+        let mask = if predicated { self.isa.vector_cost(Op::IntAlu, shape.widest, width).throughput } else { 0.0 };
+        let body = (interleave as f64 * (throughput + mask) + LOOP_OVERHEAD).max(chain);
+        if width == 1 && interleave == 1 {
+            return body;
+        }
+
+        // Whole vector iterations, the scalar epilogue and the final
+        // reduction of the accumulators
+        let step = u64::from(width * interleave);
+        let trip_count = shape.trip_count.unwrap_or(ASSUMED_TRIP_COUNT).max(1);
+        let left = match shape.trip_count {
+            Some(trip_count) => (trip_count % step) as f64,
+            None => (step - 1) as f64 / 2.0,
+        };
+        let lane = self.isa.cost(Op::Lane, shape.widest).unwrap_or(scalar_cost(Op::Lane, shape.widest));
+        let reduce: f64 = shape
+            .reductions
+            .iter()
+            .map(|o| (interleave - 1) as f64 * cost(o).latency + width.trailing_zeros() as f64 * (cost(o).latency + lane.latency))
+            .sum();
+        if predicated {
+            // The iterations left over are one more, partial, vector
+            // iteration: always with a constant trip count that leaves
+            // some, else as often as one doesn't divide evenly
+            let partial = match shape.trip_count {
+                Some(_) if left > 0.0 => 1.0,
+                Some(_) => 0.0,
+                None => (step - 1) as f64 / step as f64,
+            };
+            return (((trip_count / step) as f64 + partial) * body + reduce) / trip_count as f64;
+        }
+        ((trip_count / step) as f64 * body + left * scalar + reduce) / trip_count as f64
+    }
+
+    /// Whether the copies of the body fit in the registers
+    fn fits(&self, shape: &LoopShape, width: u32, interleave: u32) -> bool {
+        let registers = if width == 1 { SCALAR_REGISTERS } else { self.isa.registers() };
+        shape.live * interleave <= registers
+    }
+}
+
+/// What the model chose for a loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Plan {
+    width: u32,
+    interleave: u32,
+    /// The tail runs as a masked vector iteration, not a scalar epilogue
+    predicated: bool,
+    cycles: f64,
+}
+
+pub struct VectorizePass {
+    model: CostModel,
+    target_machine: LLVMTargetMachineRef,
+    remarks: Vec<OptimizationRemark>,
+    // Statistics
+    loops_vectorized: usize,
+    loops_scalar: usize,
+    loops_rejected: usize,
+}
+
+impl VectorizePass {
+    pub fn new(features: &CPUFeatures, target_machine: LLVMTargetMachineRef) -> Self {
+        VectorizePass {
+            model: CostModel::new(VectorIsa::from_features(features)),
+            target_machine,
+            remarks: Vec::new(),
+            loops_vectorized: 0,
+            loops_scalar: 0,
+            loops_rejected: 0,
+        }
+    }
+
+    pub fn isa(&self) -> VectorIsa {
+        self.model.isa
+    }
+
+    /// Cost the innermost loops of `module` and record the widths and
+    /// interleave counts on them; returns the number of loops to vectorize
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> Result<usize, VectorizeError> {
+        if self.model.isa == VectorIsa::None {
+            return Ok(0);
+        }
+        let before = self.loops_vectorized;
+        run_passes(module, CANONICALIZE, self.target_machine)?;
+        let context = LLVMGetModuleContext(module);
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            let optnone = !LLVMGetEnumAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute_kind("optnone")).is_null();
+            if LLVMIsDeclaration(function) == 0 && !optnone {
+                self.plan_function(context, function);
+            }
+            function = LLVMGetNextFunction(function);
+        }
+        Ok(self.loops_vectorized - before)
+    }
+
+    unsafe fn plan_function(&mut self, context: LLVMContextRef, function: LLVMValueRef) {
+        let name = value_name(function);
+        let forest = find_loops(function);
+        let loop_kind = LLVMGetMDKindIDInContext(context, c"llvm.loop".as_ptr(), 9);
+        for l in forest.loops.iter().filter(|l| l.innermost) {
+            let at = describe(forest.blocks[l.header]);
+            let shape = match LoopShape::find(&forest, l) {
+                Ok(shape) => shape,
+                Err(reason) => {
+                    self.loops_rejected += 1;
+                    self.remark(&name, RemarkKind::Missed, format!("{} not vectorized: {}", at, reason));
+                    continue;
+                }
+            };
+            if !LLVMGetMetadata(shape.latch, loop_kind).is_null() {
+                self.remark(&name, RemarkKind::Analysis, format!("{} left to its loop hints", at));
+                continue;
+            }
+
+            let (plan, widths) = self.plan(&shape);
+            let isa = self.model.isa;
+            let scalar = widths[0].cycles;
+            let costs: Vec<String> = widths
+                .iter()
+                .map(|w| format!("width {} x{}{}: {:.2}", w.width, w.interleave, if w.predicated { " masked tail" } else { "" }, w.cycles))
+                .collect();
+            let trip_count = shape.trip_count.map_or("unknown".to_string(), |n| n.to_string());
+            self.remark(
+                &name,
+                RemarkKind::Analysis,
+                format!(
+                    "{}: {} operation(s) on lanes of up to {} bits, {} reduction(s), trip count {}; cycles per iteration on {}: {}",
+                    at,
+                    shape.operations.len(),
+                    shape.widest,
+                    shape.reductions.len(),
+                    trip_count,
+                    isa,
+                    costs.join(", ")
+                ),
+            );
+            annotate(context, loop_kind, shape.latch, plan, isa);
+            if plan.width > 1 {
+                self.loops_vectorized += 1;
+                self.remark(
+                    &name,
+                    RemarkKind::Applied,
+                    format!(
+                        "vectorized {} (width {}, interleave {}{}) for {}: {:.2} cycles per iteration against {:.2} scalar",
+                        at,
+                        plan.width,
+                        plan.interleave,
+                        if plan.predicated { ", masked tail" } else { "" },
+                        isa,
+                        plan.cycles,
+                        scalar
+                    ),
+                );
+            } else {
+                self.loops_scalar += 1;
+                let message = match widths.iter().skip(1).min_by(|a, b| a.cycles.total_cmp(&b.cycles)) {
+                    Some(best) => format!(
+                        "{} not vectorized: not beneficial on {}, {:.2} cycles per iteration at width {} against {:.2} scalar",
+                        at, isa, best.cycles, best.width, scalar
+                    ),
+                    None => format!("{} not vectorized: its lanes are as wide as the {} registers", at, isa),
+                };
+                self.remark(&name, RemarkKind::Missed, message);
+            }
+        }
+    }
+
+    /// The cheapest plan, and the best interleave count and tail at each
+    /// width, scalar first
+    fn plan(&self, shape: &LoopShape) -> (Plan, Vec<Plan>) {
+        let scalar = self.model.cycles(shape, 1, 1, false, 0.0);
+        let mut widths = vec![Plan { width: 1, interleave: 1, predicated: false, cycles: scalar }];
+        let tails: &[bool] = if self.model.isa.predicated() { &[false, true] } else { &[false] };
+        let mut width = 2;
+        while width * shape.widest <= self.model.isa.register_bits() {
+            let best = INTERLEAVE_COUNTS
+                .iter()
+                .filter(|&&interleave| self.model.fits(shape, width, interleave))
+                .flat_map(|&interleave| tails.iter().map(move |&predicated| (interleave, predicated)))
+                .map(|(interleave, predicated)| Plan {
+                    width,
+                    interleave,
+                    predicated,
+                    cycles: self.model.cycles(shape, width, interleave, predicated, scalar),
+                })
+                .fold(None, |best: Option<Plan>, plan| match best {
+                    Some(best) if best.cycles <= plan.cycles => Some(best),
+                    _ => Some(plan),
+                });
+            widths.extend(best);
+            width *= 2;
+        }
+        // Ties go to the narrower width and the smaller count
+        let plan = widths.iter().copied().fold(widths[0], |best, plan| if plan.cycles < best.cycles { plan } else { best });
+        (plan, widths)
+    }
+
+    fn remark(&mut self, function: &str, kind: RemarkKind, message: String) {
+        self.remarks.push(OptimizationRemark {
+            pass: REMARK_PASS.to_string(),
+            function: function.to_string(),
+            kind,
+            message,
+        });
+    }
+
+    pub fn remarks(&self) -> &[OptimizationRemark] {
+        &self.remarks
+    }
+
+    /// (loops vectorized, loops kept scalar as cheaper, loops that can't
+    /// be vectorized)
+    pub fn stats(&self) -> (usize, usize, usize) {
+        (self.loops_vectorized, self.loops_scalar, self.loops_rejected)
+    }
+}
+
+impl LoopShape {
+    unsafe fn find(forest: &LoopForest, l: &Loop) -> Result<LoopShape, String> {
+        let blocks = &forest.blocks;
+        let exiting: Vec<usize> = l
+            .body
+            .iter()
+            .copied()
+            .filter(|&block| forest.successors[block].iter().any(|successor| !l.body.contains(successor)))
+            .collect();
+        if exiting.len() != 1 {
+            return Err("it has more than one exit".to_string());
+        }
+        let latches: Vec<usize> = forest.predecessors[l.header].iter().copied().filter(|block| l.body.contains(block)).collect();
+        let [latch] = latches[..] else { return Err("it has more than one latch".to_string()) };
+        let latch_block = blocks[latch];
+
+        // Induction variables step by a constant; every other value carried
+        // around the loop must be a reduction
+        let mut induction = Vec::new();
+        let mut pointer_induction = Vec::new();
+        let mut steps = Vec::new();
+        let mut reductions = Vec::new();
+        let mut skipped = Vec::new();
+        let mut trip_count = None;
+        for phi in phis(blocks[l.header]) {
+            let Some(next) = incoming(phi, latch_block) else { continue };
+            let start = (0..LLVMCountIncoming(phi)).find(|&i| LLVMGetIncomingBlock(phi, i) != latch_block).map(|i| LLVMGetIncomingValue(phi, i));
+            if let Some(step) = induction_step(phi, next) {
+                skipped.push(next);
+                match LLVMGetTypeKind(LLVMTypeOf(phi)) {
+                    LLVMTypeKind::LLVMPointerTypeKind => pointer_induction.push(phi),
+                    _ => {
+                        induction.push(phi);
+                        steps.push(step);
+                    }
+                }
+                trip_count = trip_count.or_else(|| start.and_then(|start| constant_trip_count(blocks[exiting[0]], phi, next, start, step)));
+                continue;
+            }
+            let op = reduction(phi, next, l, blocks)?;
+            reductions.push(Operation { op, bits: lane_bits(LLVMTypeOf(phi)).ok_or("it carries a vector between iterations")?, blend: false });
+            skipped.push(next);
+        }
+        // The exit test is part of the loop overhead
+        let exit = LLVMGetBasicBlockTerminator(blocks[exiting[0]]);
+        if LLVMIsConditional(exit) != 0 {
+            skipped.push(LLVMGetCondition(exit));
+        }
+        if induction.is_empty() && pointer_induction.is_empty() {
+            return Err("it has no induction variable".to_string());
+        }
+        let unit_stride = steps.iter().all(|&step| step == 1 || step == -1);
+
+        let mut operations = Vec::new();
+        let mut loads = 0;
+        for &block in &l.body {
+            let conditional = l.body.len() > 2 && block != l.header && block != latch;
+            let mut instruction = LLVMGetFirstInstruction(blocks[block]);
+            while !instruction.is_null() {
+                let current = instruction;
+                instruction = LLVMGetNextInstruction(instruction);
+                if skipped.contains(&current) || (block == l.header && LLVMGetInstructionOpcode(current) == LLVMOpcode::LLVMPHI) {
+                    continue;
+                }
+                let Some(op) = classify(current, &induction, &pointer_induction, unit_stride)? else { continue };
+                let value = match op {
+                    Op::Store | Op::Scatter => LLVMGetOperand(current, 0),
+                    _ if LLVMGetInstructionOpcode(current) == LLVMOpcode::LLVMICmp || LLVMGetInstructionOpcode(current) == LLVMOpcode::LLVMFCmp => {
+                        LLVMGetOperand(current, 0)
+                    }
+                    _ => current,
+                };
+                let bits = lane_bits(LLVMTypeOf(value)).ok_or("it already operates on vectors")?;
+                if matches!(op, Op::Load | Op::Gather) {
+                    loads += 1;
+                }
+                // A store under a condition becomes a load of what's there
+                // and a blend with it before the store, unless it's masked
+                if conditional && matches!(op, Op::Store | Op::Scatter) {
+                    operations.push(Operation { op: if op == Op::Store { Op::Load } else { Op::Gather }, bits, blend: true });
+                    operations.push(Operation { op: Op::IntAlu, bits, blend: true });
+                }
+                operations.push(Operation { op, bits, blend: false });
+            }
+        }
+        if operations.is_empty() && reductions.is_empty() {
+            return Err("its body does nothing to vectorize".to_string());
+        }
+        let widest = operations.iter().chain(&reductions).map(|o| o.bits).max().unwrap_or(8);
+        Ok(LoopShape {
+            operations,
+            widest,
+            live: loads + reductions.len() as u32 + 1,
+            reductions,
+            trip_count,
+            latch: LLVMGetBasicBlockTerminator(latch_block),
+        })
+    }
+}
+
+/// What `instruction` costs as; `None` for instructions the vector loop
+/// doesn't repeat per lane (address arithmetic, branches, invariant loads)
+unsafe fn classify(
+    instruction: LLVMValueRef,
+    induction: &[LLVMValueRef],
+    pointer_induction: &[LLVMValueRef],
+    unit_stride: bool,
+) -> Result<Option<Op>, String> {
+    let access = |pointer: LLVMValueRef, contiguous: Op, strided: Op| {
+        if based_on(pointer, pointer_induction) {
+            return Some(contiguous);
+        }
+        match access_stride(pointer, induction) {
+            Some(Stride::Contiguous) if unit_stride => Some(contiguous),
+            Some(_) => Some(strided),
+            None => None,
+        }
+    };
+    let op = match LLVMGetInstructionOpcode(instruction) {
+        LLVMOpcode::LLVMLoad | LLVMOpcode::LLVMStore if LLVMGetVolatile(instruction) != 0 => {
+            return Err("it has a volatile access".to_string());
+        }
+        LLVMOpcode::LLVMLoad | LLVMOpcode::LLVMStore if LLVMGetOrdering(instruction) != LLVMAtomicOrdering::LLVMAtomicOrderingNotAtomic => {
+            return Err("it has an atomic access".to_string());
+        }
+        // An invariant load is hoisted and broadcast
+        LLVMOpcode::LLVMLoad => access(LLVMGetOperand(instruction, 0), Op::Load, Op::Gather),
+        LLVMOpcode::LLVMStore => match access(LLVMGetOperand(instruction, 1), Op::Store, Op::Scatter) {
+            Some(op) => Some(op),
+            None => return Err("it stores to the same address every iteration".to_string()),
+        },
+        LLVMOpcode::LLVMAtomicRMW | LLVMOpcode::LLVMAtomicCmpXchg | LLVMOpcode::LLVMFence => {
+            return Err("it has an atomic access".to_string());
+        }
+        LLVMOpcode::LLVMGetElementPtr | LLVMOpcode::LLVMBr | LLVMOpcode::LLVMUnreachable => None,
+        LLVMOpcode::LLVMSExt | LLVMOpcode::LLVMZExt | LLVMOpcode::LLVMTrunc if only_addresses(instruction) => None,
+        LLVMOpcode::LLVMAdd
+        | LLVMOpcode::LLVMSub
+        | LLVMOpcode::LLVMAnd
+        | LLVMOpcode::LLVMOr
+        | LLVMOpcode::LLVMXor
+        | LLVMOpcode::LLVMShl
+        | LLVMOpcode::LLVMLShr
+        | LLVMOpcode::LLVMAShr
+        | LLVMOpcode::LLVMICmp
+        | LLVMOpcode::LLVMSelect
+        | LLVMOpcode::LLVMSExt
+        | LLVMOpcode::LLVMZExt
+        | LLVMOpcode::LLVMTrunc
+        | LLVMOpcode::LLVMFreeze => Some(Op::IntAlu),
+        // A value merged from a branch inside the loop becomes a blend
+        LLVMOpcode::LLVMPHI => Some(Op::IntAlu),
+        LLVMOpcode::LLVMMul => Some(Op::IntMul),
+        LLVMOpcode::LLVMSDiv | LLVMOpcode::LLVMUDiv | LLVMOpcode::LLVMSRem | LLVMOpcode::LLVMURem => Some(Op::IntDiv),
+        LLVMOpcode::LLVMFAdd | LLVMOpcode::LLVMFSub | LLVMOpcode::LLVMFNeg | LLVMOpcode::LLVMFCmp => Some(Op::FloatAdd),
+        LLVMOpcode::LLVMFMul => Some(Op::FloatMul),
+        LLVMOpcode::LLVMFDiv | LLVMOpcode::LLVMFRem => Some(Op::FloatDiv),
+        LLVMOpcode::LLVMSIToFP
+        | LLVMOpcode::LLVMUIToFP
+        | LLVMOpcode::LLVMFPToSI
+        | LLVMOpcode::LLVMFPToUI
+        | LLVMOpcode::LLVMFPExt
+        | LLVMOpcode::LLVMFPTrunc => Some(Op::Convert),
+        LLVMOpcode::LLVMCall => {
+            let callee = called_function(instruction);
+            let name = if callee.is_null() { String::new() } else { value_name(callee) };
+            if FREE_INTRINSICS.iter().any(|prefix| name.starts_with(prefix)) {
+                None
+            } else {
+                let intrinsic = VECTOR_INTRINSICS
+                    .iter()
+                    .find(|(base, _)| name.strip_prefix(base).is_some_and(|rest| rest.is_empty() || rest.starts_with('.')));
+                match intrinsic {
+                    Some(&(_, op)) => Some(op),
+                    None if name.is_empty() => return Err("it calls through a pointer".to_string()),
+                    None => return Err(format!("it calls {}", name)),
+                }
+            }
+        }
+        opcode => return Err(format!("it has a {:?} instruction", opcode).replace("LLVM", "")),
+    };
+    Ok(op)
+}
+
+/// The value `phi` takes coming from `block`
+unsafe fn incoming(phi: LLVMValueRef, block: LLVMBasicBlockRef) -> Option<LLVMValueRef> {
+    (0..LLVMCountIncoming(phi)).find(|&i| LLVMGetIncomingBlock(phi, i) == block).map(|i| LLVMGetIncomingValue(phi, i))
+}
+
+/// The constant `phi` steps by, if `next` is `phi` plus a constant
+unsafe fn induction_step(phi: LLVMValueRef, next: LLVMValueRef) -> Option<i64> {
+    if LLVMIsAInstruction(next).is_null() {
+        return None;
+    }
+    let operand = |i: u32| LLVMGetOperand(next, i);
+    match LLVMGetInstructionOpcode(next) {
+        LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMSub => {
+            let (step, other) = if operand(0) == phi { (operand(1), 0) } else { (operand(0), 1) };
+            if operand(other) != phi || LLVMIsAConstantInt(step).is_null() || (other == 1 && LLVMGetInstructionOpcode(next) == LLVMOpcode::LLVMSub) {
+                return None;
+            }
+            let step = LLVMConstIntGetSExtValue(step);
+            Some(if LLVMGetInstructionOpcode(next) == LLVMOpcode::LLVMSub { -step } else { step })
+        }
+        // p + constant for pointers: the stride is checked per access
+        LLVMOpcode::LLVMGetElementPtr if operand(0) == phi && LLVMGetNumOperands(next) == 2 && !LLVMIsAConstantInt(operand(1)).is_null() => Some(1),
+        _ => None,
+    }
+}
+
+/// The operation `phi` is reduced with, if it's a reduction: `next`
+/// combines it with a value from this iteration, and nothing else in the
+/// loop reads it
+unsafe fn reduction(phi: LLVMValueRef, next: LLVMValueRef, l: &Loop, blocks: &[LLVMBasicBlockRef]) -> Result<Op, String> {
+    let name = value_name(phi);
+    let carried = if name.is_empty() { "a value".to_string() } else { format!("'{}'", name) };
+    let not_reduction = || format!("{} is carried from one iteration to the next and is not a reduction", carried);
+    if LLVMIsAInstruction(next).is_null() || (LLVMGetOperand(next, 0) != phi && LLVMGetOperand(next, 1) != phi) {
+        return Err(not_reduction());
+    }
+    let op = match LLVMGetInstructionOpcode(next) {
+        LLVMOpcode::LLVMAdd | LLVMOpcode::LLVMAnd | LLVMOpcode::LLVMOr | LLVMOpcode::LLVMXor => Op::IntAlu,
+        LLVMOpcode::LLVMSub if LLVMGetOperand(next, 0) == phi => Op::IntAlu,
+        LLVMOpcode::LLVMMul => Op::IntMul,
+        LLVMOpcode::LLVMFAdd | LLVMOpcode::LLVMFSub | LLVMOpcode::LLVMFMul => {
+            if LLVMGetFastMathFlags(next) & LLVMFastMathAllowReassoc == 0 {
+                return Err(format!("reducing {} in floating point needs reassociation (-ffast-math)", carried));
+            }
+            if LLVMGetInstructionOpcode(next) == LLVMOpcode::LLVMFMul { Op::FloatMul } else { Op::FloatAdd }
+        }
+        _ => return Err(not_reduction()),
+    };
+    let in_loop = |user: LLVMValueRef| l.body.iter().any(|&block| blocks[block] == LLVMGetInstructionParent(user));
+    for value in [phi, next] {
+        let mut use_ = LLVMGetFirstUse(value);
+        while !use_.is_null() {
+            let user = LLVMGetUser(use_);
+            if user != next && user != phi && in_loop(user) {
+                return Err(not_reduction());
+            }
+            use_ = LLVMGetNextUse(use_);
+        }
+    }
+    Ok(op)
+}
+
+/// Iterations of a loop that exits from `exiting` when the induction
+/// variable `phi` (or `next`, its value for the next iteration) reaches a
+/// constant, counting from the constant `start`
+unsafe fn constant_trip_count(exiting: LLVMBasicBlockRef, phi: LLVMValueRef, next: LLVMValueRef, start: LLVMValueRef, step: i64) -> Option<u64> {
+    let branch = LLVMGetBasicBlockTerminator(exiting);
+    if LLVMIsConditional(branch) == 0 || LLVMIsAConstantInt(start).is_null() {
+        return None;
+    }
+    let compare = LLVMIsAICmpInst(LLVMGetCondition(branch));
+    if compare.is_null() {
+        return None;
+    }
+    let (a, b) = (LLVMGetOperand(compare, 0), LLVMGetOperand(compare, 1));
+    let bound = if a == phi || a == next { b } else if b == phi || b == next { a } else { return None };
+    if LLVMIsAConstantInt(bound).is_null() || step == 0 {
+        return None;
+    }
+    let distance = LLVMConstIntGetSExtValue(bound).abs_diff(LLVMConstIntGetSExtValue(start));
+    Some((distance / step.unsigned_abs()).max(1))
+}
+
+/// Whether `pointer` is a pointer induction variable or a GEP from one
+unsafe fn based_on(mut pointer: LLVMValueRef, pointer_induction: &[LLVMValueRef]) -> bool {
+    while !pointer.is_null() {
+        if pointer_induction.contains(&pointer) {
+            return true;
+        }
+        pointer = LLVMIsAGetElementPtrInst(pointer);
+        if !pointer.is_null() {
+            pointer = LLVMGetOperand(pointer, 0);
+        }
+    }
+    false
+}
+
+/// Whether every use of `value` is an index of a GEP
+unsafe fn only_addresses(value: LLVMValueRef) -> bool {
+    let mut use_ = LLVMGetFirstUse(value);
+    while !use_.is_null() {
+        if LLVMIsAGetElementPtrInst(LLVMGetUser(use_)).is_null() {
+            return false;
+        }
+        use_ = LLVMGetNextUse(use_);
+    }
+    true
+}
+
+/// Lane width of a value of `ty`; `None` for vectors and aggregates
+unsafe fn lane_bits(ty: LLVMTypeRef) -> Option<u32> {
+    match LLVMGetTypeKind(ty) {
+        LLVMTypeKind::LLVMIntegerTypeKind => Some(LLVMGetIntTypeWidth(ty).max(8)),
+        LLVMTypeKind::LLVMHalfTypeKind | LLVMTypeKind::LLVMBFloatTypeKind => Some(16),
+        LLVMTypeKind::LLVMFloatTypeKind => Some(32),
+        LLVMTypeKind::LLVMDoubleTypeKind | LLVMTypeKind::LLVMPointerTypeKind => Some(64),
+        _ => None,
+    }
+}
+
+/// "loop at line 12", or just "loop" without debug information
+unsafe fn describe(header: LLVMBasicBlockRef) -> String {
+    let terminator = LLVMGetBasicBlockTerminator(header);
+    match if terminator.is_null() { 0 } else { LLVMGetDebugLocLine(terminator) } {
+        0 => "loop".to_string(),
+        line => format!("loop at line {}", line),
+    }
+}
+
+/// Put `plan` on the loop whose latch ends in `latch` as a distinct
+/// `llvm.loop` node, which must refer to itself
+unsafe fn annotate(context: LLVMContextRef, loop_kind: u32, latch: LLVMValueRef, plan: Plan, isa: VectorIsa) {
+    let i1 = LLVMInt1TypeInContext(context);
+    let i32_ty = LLVMInt32TypeInContext(context);
+    let hint = |name: &str, ty: LLVMTypeRef, value: u64| {
+        let mut operands = [
+            LLVMMDStringInContext2(context, name.as_ptr() as *const _, name.len()),
+            LLVMValueAsMetadata(LLVMConstInt(ty, value, 0)),
+        ];
+        LLVMMDNodeInContext2(context, operands.as_mut_ptr(), 2)
+    };
+    let placeholder = LLVMTemporaryMDNode(context, std::ptr::null_mut(), 0);
+    let mut operands = vec![placeholder, hint("llvm.loop.interleave.count", i32_ty, u64::from(plan.interleave))];
+    if plan.width == 1 {
+        operands.push(hint("llvm.loop.vectorize.width", i32_ty, 1));
+    } else {
+        operands.push(hint("llvm.loop.vectorize.enable", i1, 1));
+        match isa {
+            // Lanes per 128 bits, times vscale
+            VectorIsa::Sve { bits } => {
+                operands.push(hint("llvm.loop.vectorize.width", i32_ty, u64::from((plan.width * 128 / bits).max(1))));
+                operands.push(hint("llvm.loop.vectorize.scalable.enable", i1, 1));
+            }
+            _ => operands.push(hint("llvm.loop.vectorize.width", i32_ty, u64::from(plan.width))),
+        }
+        if plan.predicated {
+            operands.push(hint("llvm.loop.vectorize.predicate.enable", i1, 1));
+        }
+    }
+    let node = LLVMMDNodeInContext2(context, operands.as_mut_ptr(), operands.len());
+    LLVMMetadataReplaceAllUsesWith(placeholder, node);
+    LLVMSetMetadata(latch, loop_kind, LLVMMetadataAsValue(context, node));
+}
+
+unsafe fn run_passes(module: LLVMModuleRef, pipeline: &str, target_machine: LLVMTargetMachineRef) -> Result<(), VectorizeError> {
+    let pipeline = CString::new(pipeline).unwrap();
+    let options = LLVMCreatePassBuilderOptions();
+    let error = LLVMRunPasses(module, pipeline.as_ptr(), target_machine, options);
+    LLVMDisposePassBuilderOptions(options);
+    if error.is_null() {
+        return Ok(());
+    }
+    let message = LLVMGetErrorMessage(error);
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(message);
+    Err(VectorizeError::Pipeline(text))
+}
+
+#[derive(Debug)]
+pub enum VectorizeError {
+    /// LLVM rejected or failed the canonicalization pipeline
+    Pipeline(String),
+}
+
+impl fmt::Display for VectorizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorizeError::Pipeline(message) => write!(f, "vectorization cost model: {}", message),
+        }
+    }
+}
+
+// Example usage:
+/*
+unsafe fn plan_vectors(module: LLVMModuleRef, features: &CPUFeatures, target_machine: LLVMTargetMachineRef) -> Result<(), VectorizeError> {
+    let mut pass = VectorizePass::new(features, target_machine);
+    pass.run(module)?;
+    let (vectorized, scalar, rejected) = pass.stats();
+    log::debug!("{}: {} loop(s) vectorized, {} kept scalar, {} not vectorizable", pass.isa(), vectorized, scalar, rejected);
+    for remark in pass.remarks() {
+        eprintln!("remark: {}: {}", remark.function, remark.message);
+    }
+    Ok(())
+}
+*/
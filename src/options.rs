@@ -0,0 +1,528 @@
+// src/options.rs
+//! Compilation options
+//! The one description of how a program is compiled and run. The command
+//! line, the driver, the daemon and `Engine` each used to carry their own
+//! copy of these settings; now they build an `Options`, with
+//! `OptionsBuilder` or from JSON, and convert it into the settings the stage
+//! at hand takes: `compiler::CompilerOptions` for object files,
+//! `compiler::JITOptions` for the JIT, `driver::CompilerOptions` for the
+//! multi-file driver, and the LLVM optimization and codegen levels. The
+//! conversions for LLVM stages only exist with the `llvm` feature.
+//!
+//! A new flag is a field here, a setter on the builder, a check in
+//! `validate` if it can be wrong, and a line in the conversions that use
+//! it. Everything is serializable, so the same value travels to the daemon
+//! and over the remote protocols; missing fields take their defaults.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+#[cfg(feature = "llvm")]
+use llvm_sys::target_machine::LLVMCodeGenOptLevel;
+use serde::{Deserialize, Serialize};
+use crate::arch::Architecture;
+#[cfg(feature = "llvm")]
+use crate::compiler::{CompilerOptions, JITOptions, LinkOptions};
+use crate::driver;
+#[cfg(feature = "llvm")]
+use crate::engine::EngineOptions;
+use crate::lto::LtoMode;
+use crate::memory::leaks::LeakConfig;
+use crate::optimizer::budget::FunctionBudget;
+use crate::optimizer::evaluate::Budget;
+use crate::optimizer::fastmath::FpOptions;
+use crate::optimizer::loops::LoopTransforms;
+use crate::optimizer::overflow::OverflowMode;
+use crate::optimizer::sanitize::SanitizerSet;
+use crate::report::RemarkFilter;
+use crate::runtime::capture::CaptureMode;
+use crate::runtime::deterministic::DeterministicConfig;
+use crate::runtime::dynamic_loader::LibrarySearch;
+use crate::stdlib::bundled::LibcMode;
+
+/// Architectures `--arch` accepts, CPUs first
+pub const ARCHITECTURES: &[&str] = &["x86_64", "aarch64", "arm", "amdgpu", "nvptx"];
+
+/// Stack the JIT gives the program's main thread
+#[cfg(feature = "llvm")]
+const JIT_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    // Code generation
+    /// 0-3, as for `-O`
+    pub optimization_level: u32,
+    /// One of `ARCHITECTURES`, as for `--arch`
+    pub architecture: String,
+    pub target_features: Vec<String>,
+    pub debug_info: bool,
+
+    // Language semantics
+    pub sanitizers: SanitizerSet,
+    /// -ffast-math, -fno-math-errno, -ffp-contract; strict IEEE by default
+    pub fp: FpOptions,
+    /// -fwrapv, -ftrapv; overflow is diagnosed by default
+    pub overflow: OverflowMode,
+
+    // Optimizer limits
+    /// Compile-time evaluation under the JIT; only used from -O1 up
+    pub evaluation_budget: Option<Budget>,
+    /// `--opt-time-budget`, `--opt-memory-budget`
+    pub function_budget: Option<FunctionBudget>,
+    /// `-fno-loop-interchange`, `-fno-loop-fusion`, `-fno-loop-tiling`; only
+    /// used at -O3
+    pub loop_transforms: LoopTransforms,
+    /// `-fno-heap-to-stack`; only used from -O2 up
+    pub heap_to_stack: bool,
+    /// `-Rpass=`, `-Rpass-missed=`, `-Rpass-analysis=`: the optimization
+    /// remarks printed to stderr
+    pub remarks: RemarkFilter,
+
+    // Profile-guided optimization
+    /// `--profile-generate`: count block executions and have the program
+    /// write them here, unless `LLVM_PROFILE_FILE` names another file
+    pub profile_generate: Option<PathBuf>,
+    /// `--profile-use`: a profile from an instrumented build
+    pub profile_use: Option<PathBuf>,
+
+    // Headers and libraries
+    pub libc: LibcMode,
+    /// `-I`, then the include directories of the packages `-l` found
+    pub include_dirs: Vec<PathBuf>,
+    /// Replace the host's system include directories when non-empty
+    pub system_include_dirs: Vec<PathBuf>,
+    /// `-l` and `-L`
+    pub libraries: LibrarySearch,
+
+    // Execution
+    /// `--deterministic`, `--seed`
+    pub deterministic: Option<DeterministicConfig>,
+    /// `--capture-io`, `--replay-io`
+    pub capture: Option<CaptureMode>,
+    /// Compile every function with a prologue `jit::probes` can patch
+    pub patchable_prologues: bool,
+    /// `--detect-leaks`, `--leak-suppressions`: report the blocks the
+    /// program didn't free at exit
+    pub detect_leaks: Option<LeakConfig>,
+
+    // Build
+    /// Persistent object cache; `None` always recompiles
+    pub cache_dir: Option<PathBuf>,
+    /// Translation units compiled concurrently; 0 = one per CPU
+    pub jobs: usize,
+    /// Optimize across the translation units of a multi-file build
+    pub lto: LtoMode,
+    /// `--embed-provenance`: record the tool, options and input digests
+    /// in a note in every object; see `report::provenance`
+    pub embed_provenance: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            optimization_level: 2,
+            architecture: std::env::consts::ARCH.to_string(),
+            target_features: Vec::new(),
+            debug_info: true,
+            sanitizers: SanitizerSet::default(),
+            fp: FpOptions::default(),
+            overflow: OverflowMode::default(),
+            evaluation_budget: Some(Budget::default()),
+            function_budget: None,
+            loop_transforms: LoopTransforms::default(),
+            heap_to_stack: true,
+            remarks: RemarkFilter::default(),
+            profile_generate: None,
+            profile_use: None,
+            libc: LibcMode::Host,
+            include_dirs: Vec::new(),
+            system_include_dirs: Vec::new(),
+            libraries: LibrarySearch::default(),
+            deterministic: None,
+            capture: None,
+            patchable_prologues: false,
+            detect_leaks: None,
+            cache_dir: None,
+            jobs: 0,
+            lto: LtoMode::Off,
+            embed_provenance: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::new()
+    }
+
+    /// Reject settings no stage can honour
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        if self.optimization_level > 3 {
+            return Err(OptionsError::OptimizationLevel(self.optimization_level));
+        }
+        if !ARCHITECTURES.contains(&self.architecture.as_str()) {
+            return Err(OptionsError::Architecture(self.architecture.clone()));
+        }
+        if self.libc == LibcMode::Bundled && self.cpu_architecture().is_none() {
+            return Err(OptionsError::Conflict(format!("the bundled libc does not support '{}'", self.architecture)));
+        }
+        if let Some(budget) = &self.function_budget {
+            if budget.time.is_zero() || budget.memory == Some(0) {
+                return Err(OptionsError::Conflict("an optimization budget must be greater than zero".to_string()));
+            }
+        }
+        if self.profile_generate.is_some() && self.profile_use.is_some() {
+            return Err(OptionsError::Conflict("--profile-generate and --profile-use can't be used together".to_string()));
+        }
+        if let Some(budget) = &self.evaluation_budget {
+            if budget.steps == 0 || budget.total_steps < budget.steps {
+                return Err(OptionsError::Conflict("the evaluation budget allows no steps".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The CPU the code runs on; `None` for the GPU targets
+    pub fn cpu_architecture(&self) -> Option<Architecture> {
+        Architecture::from_str(&self.architecture).ok()
+    }
+
+    pub fn target_triple(&self) -> &'static str {
+        target_triple(&self.architecture)
+    }
+
+    /// LLVM pass pipeline for `LLVMRunPasses`
+    pub fn pass_pipeline(&self) -> String {
+        format!("default<O{}>", self.optimization_level.min(3))
+    }
+
+    #[cfg(feature = "llvm")]
+    pub fn codegen_opt_level(&self) -> LLVMCodeGenOptLevel {
+        match self.optimization_level {
+            0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            _ => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        }
+    }
+
+    /// Settings for compiling one translation unit to an object, linked
+    /// with `link_options` when given
+    #[cfg(feature = "llvm")]
+    pub fn compiler_options(&self, link_options: Option<LinkOptions>) -> CompilerOptions {
+        CompilerOptions {
+            optimization_level: self.optimization_level,
+            link: link_options.is_some(),
+            link_options: link_options.unwrap_or_else(|| LinkOptions {
+                libraries: vec![],
+                library_paths: vec![],
+                static_link: false,
+                strip_symbols: false,
+                nostdlib: false,
+                startup_objects: vec![],
+                builtin_linker: false,
+                gc_sections: false,
+                linker_script: None,
+            }),
+            debug_info: self.debug_info,
+            target_features: self.target_features.clone(),
+            target_architecture: self.cpu_architecture(),
+            sanitizers: self.sanitizers,
+            fp: self.fp,
+            overflow: self.overflow,
+            cache_dir: self.cache_dir.clone(),
+            include_dirs: self.include_dirs.clone(),
+            system_include_dirs: self.system_include_dirs.clone(),
+            function_budget: self.function_budget,
+            loop_transforms: self.loop_transforms,
+            heap_to_stack: self.heap_to_stack,
+            profile_generate: self.profile_generate.clone(),
+            profile_use: self.profile_use.clone(),
+            embed_provenance: self.embed_provenance,
+        }
+    }
+
+    /// Settings for `CompilerSystem::jit_compile`
+    #[cfg(feature = "llvm")]
+    pub fn jit_options(&self) -> JITOptions {
+        JITOptions {
+            optimization_level: self.optimization_level,
+            enable_fast_isel: true,
+            enable_guard_pages: true,
+            stack_size: JIT_STACK_SIZE,
+            target_architecture: self.cpu_architecture(),
+            sanitizers: self.sanitizers,
+            fp: self.fp,
+            overflow: self.overflow,
+            evaluation_budget: self.evaluation_budget.filter(|_| self.optimization_level > 0),
+            loop_transforms: self.loop_transforms,
+            heap_to_stack: self.heap_to_stack,
+            patchable_prologues: self.patchable_prologues,
+            deterministic: self.deterministic,
+            capture: self.capture.clone(),
+            include_dirs: self.include_dirs.clone(),
+            system_include_dirs: self.system_include_dirs.clone(),
+            archives: Vec::new(),
+            shared_libraries: self.libraries.clone(),
+            profile_generate: self.profile_generate.clone(),
+            profile_use: self.profile_use.clone(),
+            detect_leaks: self.detect_leaks.is_some(),
+        }
+    }
+
+    /// Settings for `driver::CompilerDriver`, building `input_files` into
+    /// `output_file`
+    pub fn driver_options(
+        &self,
+        input_files: Vec<PathBuf>,
+        output_file: PathBuf,
+        output_type: driver::OutputType,
+    ) -> driver::CompilerOptions {
+        driver::CompilerOptions {
+            input_files,
+            output_file,
+            output_type,
+            opt_level: match self.optimization_level {
+                0 => driver::OptLevel::None,
+                1 => driver::OptLevel::Less,
+                2 => driver::OptLevel::Default,
+                _ => driver::OptLevel::Aggressive,
+            },
+            target_triple: self.target_triple().to_string(),
+            target_features: self.target_features.clone(),
+            target_cpu: "generic".to_string(),
+            debug_info: self.debug_info,
+            generate_dwarf: self.debug_info,
+            dwarf_version: 4,
+            pic_level: driver::PICLevel::PIE,
+            relocation_model: driver::RelocModel::PIC,
+            code_model: driver::CodeModel::Small,
+            size_level: 0,
+            inline_threshold: 225,
+            unroll_threshold: 250,
+            linker_options: driver::LinkerOptions::default(),
+            jobs: self.jobs,
+            lto: self.lto,
+        }
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl From<&EngineOptions> for Options {
+    fn from(engine: &EngineOptions) -> Self {
+        Options {
+            optimization_level: engine.optimization_level,
+            sanitizers: SanitizerSet { undefined: engine.sanitize_undefined },
+            libraries: LibrarySearch {
+                libraries: engine.libraries.clone(),
+                library_paths: engine.library_paths.clone(),
+            },
+            deterministic: engine.deterministic,
+            patchable_prologues: engine.patchable_prologues,
+            ..Options::default()
+        }
+    }
+}
+
+/// LLVM target triple for one of `ARCHITECTURES`; CPUs get the host's OS,
+/// so `--arch aarch64` on a Mac targets macOS
+pub fn target_triple(architecture: &str) -> &'static str {
+    match architecture {
+        "amdgpu" => "amdgcn-amd-amdhsa",
+        "nvptx" => "nvptx64-nvidia-cuda",
+        _ => Architecture::from_str(architecture).unwrap_or(Architecture::X86_64).host_target_triple(),
+    }
+}
+
+/// Builds a validated `Options`, starting from the defaults
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    pub fn new() -> Self {
+        OptionsBuilder { options: Options::default() }
+    }
+
+    /// Start from `options` instead of the defaults, e.g. a deserialized
+    /// configuration the command line then overrides
+    pub fn from_options(options: Options) -> Self {
+        OptionsBuilder { options }
+    }
+
+    pub fn optimization_level(mut self, level: u32) -> Self {
+        self.options.optimization_level = level;
+        self
+    }
+
+    pub fn architecture(mut self, architecture: &str) -> Self {
+        self.options.architecture = architecture.to_string();
+        self
+    }
+
+    pub fn target_feature(mut self, feature: &str) -> Self {
+        self.options.target_features.push(feature.to_string());
+        self
+    }
+
+    pub fn debug_info(mut self, enabled: bool) -> Self {
+        self.options.debug_info = enabled;
+        self
+    }
+
+    pub fn sanitizers(mut self, sanitizers: SanitizerSet) -> Self {
+        self.options.sanitizers = sanitizers;
+        self
+    }
+
+    pub fn fp(mut self, fp: FpOptions) -> Self {
+        self.options.fp = fp;
+        self
+    }
+
+    pub fn overflow(mut self, overflow: OverflowMode) -> Self {
+        self.options.overflow = overflow;
+        self
+    }
+
+    pub fn evaluation_budget(mut self, budget: Option<Budget>) -> Self {
+        self.options.evaluation_budget = budget;
+        self
+    }
+
+    pub fn function_budget(mut self, budget: Option<FunctionBudget>) -> Self {
+        self.options.function_budget = budget;
+        self
+    }
+
+    pub fn loop_transforms(mut self, transforms: LoopTransforms) -> Self {
+        self.options.loop_transforms = transforms;
+        self
+    }
+
+    pub fn heap_to_stack(mut self, enabled: bool) -> Self {
+        self.options.heap_to_stack = enabled;
+        self
+    }
+
+    pub fn remarks(mut self, filter: RemarkFilter) -> Self {
+        self.options.remarks = filter;
+        self
+    }
+
+    pub fn profile_generate(mut self, path: Option<PathBuf>) -> Self {
+        self.options.profile_generate = path;
+        self
+    }
+
+    pub fn profile_use(mut self, path: Option<PathBuf>) -> Self {
+        self.options.profile_use = path;
+        self
+    }
+
+    pub fn libc(mut self, libc: LibcMode) -> Self {
+        self.options.libc = libc;
+        self
+    }
+
+    pub fn include_dir(mut self, dir: PathBuf) -> Self {
+        self.options.include_dirs.push(dir);
+        self
+    }
+
+    pub fn system_include_dir(mut self, dir: PathBuf) -> Self {
+        self.options.system_include_dirs.push(dir);
+        self
+    }
+
+    pub fn libraries(mut self, libraries: LibrarySearch) -> Self {
+        self.options.libraries = libraries;
+        self
+    }
+
+    pub fn deterministic(mut self, config: Option<DeterministicConfig>) -> Self {
+        self.options.deterministic = config;
+        self
+    }
+
+    pub fn capture(mut self, mode: Option<CaptureMode>) -> Self {
+        self.options.capture = mode;
+        self
+    }
+
+    pub fn patchable_prologues(mut self, enabled: bool) -> Self {
+        self.options.patchable_prologues = enabled;
+        self
+    }
+
+    pub fn detect_leaks(mut self, config: Option<LeakConfig>) -> Self {
+        self.options.detect_leaks = config;
+        self
+    }
+
+    pub fn cache_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.options.cache_dir = dir;
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.options.jobs = jobs;
+        self
+    }
+
+    pub fn lto(mut self, mode: LtoMode) -> Self {
+        self.options.lto = mode;
+        self
+    }
+
+    pub fn embed_provenance(mut self, enabled: bool) -> Self {
+        self.options.embed_provenance = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<Options, OptionsError> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionsError {
+    OptimizationLevel(u32),
+    Architecture(String),
+    /// Settings that can't be used together, or a limit of zero
+    Conflict(String),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::OptimizationLevel(level) => write!(f, "optimization level {} is not 0-3", level),
+            OptionsError::Architecture(arch) => {
+                write!(f, "unsupported architecture '{}' (supported: {})", arch, ARCHITECTURES.join(", "))
+            }
+            OptionsError::Conflict(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::builder()
+        .optimization_level(3)
+        .architecture("aarch64")
+        .overflow(OverflowMode::Trap)
+        .build()?;
+
+    // What the daemon and remote clients exchange
+    let json = serde_json::to_string(&options)?;
+    let received: Options = serde_json::from_str(&json)?;
+    received.validate()?;
+
+    let compiler = unsafe { CompilerSystem::new(received.target_triple())? };
+    unsafe { compiler.compile_file("input.c", "input.o", &received.compiler_options(None))? };
+    Ok(())
+}
+*/
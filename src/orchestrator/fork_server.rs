@@ -0,0 +1,303 @@
+// src/orchestrator/fork_server.rs
+//! Copy-on-write snapshot execution backend
+//! A template process pays for expensive setup once (parsed headers, warmed
+//! JIT cache), then fork()s a fresh child per request. Each child shares the
+//! template's pages copy-on-write, runs one input, and reports back over a pipe.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+use crate::runtime::output_mux::{OutputMultiplexer, OutputStream};
+
+/// Result of running a single input in a forked child
+#[derive(Debug, Clone)]
+pub enum ForkOutcome {
+    /// Child ran the handler and reported a result
+    Completed { output: Vec<u8> },
+    /// Handler returned an error
+    Failed { message: String },
+    /// Child was killed by a signal before reporting (crash, timeout)
+    Signaled { signal: i32 },
+    /// Child exited without reporting a result
+    NoResult { exit_code: i32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct ForkResult {
+    pub outcome: ForkOutcome,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForkServerConfig {
+    /// Kill the child after this long
+    pub timeout: Duration,
+    /// Maximum result payload accepted from a child
+    pub max_result_size: usize,
+}
+
+impl Default for ForkServerConfig {
+    fn default() -> Self {
+        ForkServerConfig {
+            timeout: Duration::from_secs(10),
+            max_result_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+// Result protocol: [tag: u8][len: u32 LE][payload]
+const TAG_OK: u8 = 0;
+const TAG_ERR: u8 = 1;
+
+pub type ForkHandler<T> = fn(&mut T, &[u8]) -> Result<Vec<u8>, String>;
+
+pub struct ForkServer<T> {
+    // Warmed state shared copy-on-write with every child
+    template: T,
+
+    // Per-request work, run only in the child
+    handler: ForkHandler<T>,
+
+    // Limits
+    config: ForkServerConfig,
+
+    // Statistics
+    executions: u64,
+}
+
+impl<T> ForkServer<T> {
+    /// Build the template. `init` runs once in the server process.
+    pub fn new(
+        init: impl FnOnce() -> Result<T, ForkServerError>,
+        handler: ForkHandler<T>,
+        config: ForkServerConfig,
+    ) -> Result<Self, ForkServerError> {
+        Ok(ForkServer {
+            template: init()?,
+            handler,
+            config,
+            executions: 0,
+        })
+    }
+
+    pub fn executions(&self) -> u64 {
+        self.executions
+    }
+
+    /// Run one input in a fresh copy-on-write child
+    pub fn execute(&mut self, input: &[u8]) -> Result<ForkResult, ForkServerError> {
+        self.execute_with_output(input, None)
+    }
+
+    /// `execute`, with what the child prints echoed through `mux` as
+    /// `source`'s output rather than written straight to ours
+    pub fn execute_echoed(&mut self, input: &[u8], mux: &OutputMultiplexer, source: &str) -> Result<ForkResult, ForkServerError> {
+        self.execute_with_output(input, Some((mux, source)))
+    }
+
+    fn execute_with_output(&mut self, input: &[u8], echo: Option<(&OutputMultiplexer, &str)>) -> Result<ForkResult, ForkServerError> {
+        let start = Instant::now();
+        let mut fds = [0i32; 2];
+        let output = match echo {
+            Some(_) => Some((output_pipe()?, output_pipe()?)),
+            None => None,
+        };
+
+        unsafe {
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(ForkServerError::Pipe(io::Error::last_os_error()));
+            }
+
+            let pid = libc::fork();
+            if pid < 0 {
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+                return Err(ForkServerError::Fork(io::Error::last_os_error()));
+            }
+
+            if pid == 0 {
+                // Child: run the handler on our private copy of the template
+                libc::close(fds[0]);
+                if let Some(((_, stdout), (_, stderr))) = &output {
+                    libc::dup2(stdout.as_raw_fd(), 1);
+                    libc::dup2(stderr.as_raw_fd(), 2);
+                }
+                let (tag, payload) = match (self.handler)(&mut self.template, input) {
+                    Ok(output) => (TAG_OK, output),
+                    Err(message) => (TAG_ERR, message.into_bytes()),
+                };
+                write_all(fds[1], &[tag]);
+                write_all(fds[1], &(payload.len() as u32).to_le_bytes());
+                write_all(fds[1], &payload);
+                libc::close(fds[1]);
+                libc::fflush(std::ptr::null_mut());
+                // Skip destructors and atexit handlers inherited from the server
+                libc::_exit(0);
+            }
+
+            libc::close(fds[1]);
+            self.executions += 1;
+
+            // The write ends are the child's alone, so the echo ends with it
+            let echoes = output.zip(echo).map(|(((stdout, _), (stderr, _)), (mux, source))| {
+                [
+                    mux.forward(source, OutputStream::Stdout, File::from(stdout)),
+                    mux.forward(source, OutputStream::Stderr, File::from(stderr)),
+                ]
+            });
+
+            let reply = self.read_reply(fds[0], pid);
+            let status = wait_child(pid);
+            for thread in echoes.into_iter().flatten() {
+                let _ = thread.join();
+            }
+
+            let outcome = match (reply, status) {
+                (Some((TAG_OK, output)), _) => ForkOutcome::Completed { output },
+                (Some((_, message)), _) => ForkOutcome::Failed {
+                    message: String::from_utf8_lossy(&message).into_owned(),
+                },
+                (None, status) if libc::WIFSIGNALED(status) => ForkOutcome::Signaled {
+                    signal: libc::WTERMSIG(status),
+                },
+                (None, status) => ForkOutcome::NoResult {
+                    exit_code: libc::WEXITSTATUS(status),
+                },
+            };
+
+            Ok(ForkResult {
+                outcome,
+                elapsed: start.elapsed(),
+            })
+        }
+    }
+
+    /// Read the child's reply, killing it unless all of it arrives before
+    /// the timeout; a child that sends its header and then hangs is killed too
+    unsafe fn read_reply(&self, fd: i32, pid: libc::pid_t) -> Option<(u8, Vec<u8>)> {
+        let deadline = Instant::now() + self.config.timeout;
+        let pipe = OwnedFd::from_raw_fd(fd);
+
+        let mut reply = None;
+        let mut header = [0u8; 5];
+        if read_before(&pipe, &mut header, deadline) {
+            let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            if len <= self.config.max_result_size {
+                let mut payload = vec![0u8; len];
+                if read_before(&pipe, &mut payload, deadline) {
+                    reply = Some((header[0], payload));
+                }
+            }
+        }
+
+        // Harmless if it already exited: it stays a zombie until waited for
+        if reply.is_none() {
+            libc::kill(pid, libc::SIGKILL);
+        }
+        reply
+    }
+}
+
+/// Fill `buffer` from `fd`; false on EOF, an error, or reaching `deadline`
+unsafe fn read_before(fd: &OwnedFd, buffer: &mut [u8], deadline: Instant) -> bool {
+    let mut pollfd = libc::pollfd { fd: fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return false;
+        }
+        let ready = libc::poll(&mut pollfd, 1, remaining.as_millis().min(i32::MAX as u128) as i32);
+        if ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return false;
+        }
+        if ready <= 0 {
+            continue;
+        }
+
+        let rest = &mut buffer[filled..];
+        let n = libc::read(fd.as_raw_fd(), rest.as_mut_ptr() as *mut libc::c_void, rest.len());
+        if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        if n <= 0 {
+            return false;
+        }
+        filled += n as usize;
+    }
+    true
+}
+
+/// (read end, write end) of a pipe for a child's stdout or stderr
+fn output_pipe() -> Result<(OwnedFd, OwnedFd), ForkServerError> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(ForkServerError::Pipe(io::Error::last_os_error()));
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+unsafe fn write_all(fd: i32, mut data: &[u8]) {
+    while !data.is_empty() {
+        let written = libc::write(fd, data.as_ptr() as *const libc::c_void, data.len());
+        if written <= 0 {
+            return;
+        }
+        data = &data[written as usize..];
+    }
+}
+
+unsafe fn wait_child(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) < 0 {
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            break;
+        }
+    }
+    status
+}
+
+#[derive(Debug)]
+pub enum ForkServerError {
+    Initialization(String),
+    Pipe(io::Error),
+    Fork(io::Error),
+}
+
+// Example usage:
+/*
+struct Warmed {
+    jit: JITCompiler,
+    headers: PrecompiledHeaders,
+}
+
+fn run_submission(state: &mut Warmed, input: &[u8]) -> Result<Vec<u8>, String> {
+    let source = std::str::from_utf8(input).map_err(|e| e.to_string())?;
+    let result: i32 = unsafe { state.jit.compile_and_run(source, "main", &[]) }
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(result.to_le_bytes().to_vec())
+}
+
+fn main() -> Result<(), ForkServerError> {
+    let mut server = ForkServer::new(
+        // Code and tenant heaps on the node the workers are pinned to, the
+        // code region on huge pages
+        || {
+            let placement = MemoryPlacement { numa: Some(NumaPolicy::Local), huge_pages: HugePageMode::Transparent };
+            let jit = unsafe { JITCompiler::with_memory(CodeCacheConfig::default(), &placement) }.unwrap();
+            Ok(Warmed { jit, headers: PrecompiledHeaders::load() })
+        },
+        run_submission,
+        ForkServerConfig::default(),
+    )?;
+
+    for submission in submissions {
+        let result = server.execute(submission.as_bytes())?;
+        println!("{:?} in {:?}", result.outcome, result.elapsed);
+    }
+
+    Ok(())
+}
+*/
@@ -1,6 +1,39 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+pub mod fork_server;
+
+use fork_server::{ForkResult, ForkServer, ForkServerError};
+use crate::runtime::output_mux::{OutputMultiplexer, PrefixConfig};
+use crate::testing::perf::frontend::{BenchError, BenchmarkResults, FrontendBenchSuite, Regression};
+#[cfg(feature = "llvm")]
+use crate::compiler::{self, EmitStage};
+#[cfg(feature = "llvm")]
+use crate::options::Options;
+
+/// What `CompilerOrchestrator::run` checks the build against
+#[derive(Debug, Clone)]
+pub struct OrchestratorConfig {
+    /// C files the frontend benchmark parses, lowers and JIT-compiles
+    pub benchmark_corpus: PathBuf,
+    /// Numbers the benchmark is compared against; written by the first run
+    /// on a machine, since they only mean something on the same hardware
+    pub benchmark_baseline: PathBuf,
+    /// Relative slowdown that fails the run, 0.10 == 10%
+    pub regression_threshold: f64,
+}
+
+impl Default for OrchestratorConfig {
+    fn default() -> Self {
+        OrchestratorConfig {
+            benchmark_corpus: PathBuf::from("benches/corpus"),
+            benchmark_baseline: PathBuf::from("benches/baseline.json"),
+            regression_threshold: 0.10,
+        }
+    }
+}
+
 pub struct CompilerOrchestrator {
     // Core systems
     build_system: Arc<RwLock<BuildSystem>>,
@@ -15,6 +48,9 @@ pub struct CompilerOrchestrator {
     
     // Configuration
     config: OrchestratorConfig,
+
+    // What forked runs print, a whole line at a time, tagged with the input
+    output: OutputMultiplexer,
 }
 
 impl CompilerOrchestrator {
@@ -32,6 +68,7 @@ impl CompilerOrchestrator {
             kata_env,
             status_monitor: StatusMonitor::new(),
             config: OrchestratorConfig::default(),
+            output: OutputMultiplexer::new(PrefixConfig::default()),
         })
     }
 
@@ -51,11 +88,14 @@ impl CompilerOrchestrator {
             test.run_all_tests().await?
         };
         
-        // 4. Generate documentation
+        // 4. Gate on frontend performance regressions
+        self.check_performance_regressions()?;
+        
+        // 5. Generate documentation
         let mut doc_gen = DocumentationGenerator::new();
         doc_gen.generate_all_docs().await?;
         
-        // 5. Run CI pipeline if tests pass
+        // 6. Run CI pipeline if tests pass
         if test_results.is_success() {
             let mut ci = self.ci_system.write().await;
             ci.run_pipeline().await?;
@@ -64,8 +104,59 @@ impl CompilerOrchestrator {
         Ok(())
     }
 
+    /// Benchmark the frontend on the fixed corpus and fail on regressions.
+    /// The first run on a machine records the baseline instead.
+    fn check_performance_regressions(&mut self) -> Result<(), OrchestratorError> {
+        let suite = FrontendBenchSuite::load(&self.config.benchmark_corpus, 5)
+            .map_err(OrchestratorError::Benchmark)?;
+        #[cfg(feature = "llvm")]
+        let suite = with_compiler_stages(suite)?;
+        let results = suite.run().map_err(OrchestratorError::Benchmark)?;
+
+        let baseline_path = &self.config.benchmark_baseline;
+        if !baseline_path.exists() {
+            log::info!("No benchmark baseline found, recording {}", baseline_path.display());
+            return results.save_baseline(baseline_path).map_err(OrchestratorError::Benchmark);
+        }
+
+        let baseline = BenchmarkResults::load_baseline(baseline_path)
+            .map_err(OrchestratorError::Benchmark)?;
+        let regressions = results.regressions_against(&baseline, self.config.regression_threshold);
+
+        for r in &regressions {
+            log::error!(
+                "Performance regression: {} {:.3} -> {:.3} ({:+.1}%)",
+                r.metric, r.baseline, r.current, r.slowdown * 100.0
+            );
+        }
+
+        if regressions.is_empty() {
+            Ok(())
+        } else {
+            Err(OrchestratorError::PerformanceRegression(regressions))
+        }
+    }
+
+    /// Execute each input in a fresh copy-on-write child of a warmed
+    /// template; what input `i` prints is tagged `input i`
+    pub fn run_forked<T>(
+        &mut self,
+        server: &mut ForkServer<T>,
+        inputs: &[Vec<u8>]
+    ) -> Result<Vec<ForkResult>, OrchestratorError> {
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                server
+                    .execute_echoed(input, &self.output, &format!("input {}", i))
+                    .map_err(OrchestratorError::ForkServer)
+            })
+            .collect()
+    }
+
     async fn setup_environment(&mut self) -> Result<(), OrchestratorError> {
-        println!("Setting up development environment...");
+        log::info!("Setting up development environment...");
         
         // Setup Kata environment
         let mut kata = self.kata_env.write().await;
@@ -82,15 +173,49 @@ impl CompilerOrchestrator {
     }
 }
 
+/// IR generation and JIT latency measured with the default options, each
+/// stage on a compiler of its own
+#[cfg(feature = "llvm")]
+fn with_compiler_stages(suite: FrontendBenchSuite) -> Result<FrontendBenchSuite, OrchestratorError> {
+    let options = Options::default();
+    let new_compiler = || unsafe { compiler::Compiler::new() }.map_err(OrchestratorError::Compiler);
+
+    let (ir_compiler, compiler_options) = (new_compiler()?, options.compiler_options(None));
+    let (jit_compiler, jit_options) = (new_compiler()?, options.jit_options());
+    Ok(suite
+        .with_ir_gen(Box::new(move |source| {
+            unsafe { ir_compiler.emit(source, &compiler_options, EmitStage::Ir) }
+                .map(|_| ())
+                .map_err(|e| format!("{:?}", e))
+        }))
+        .with_jit(Box::new(move |source| {
+            unsafe { jit_compiler.jit_compile(source, &jit_options) }
+                .map(|_| ())
+                .map_err(|e| format!("{:?}", e))
+        })))
+}
+
+#[derive(Debug)]
+pub enum OrchestratorError {
+    /// The fork server couldn't start a child for an input
+    ForkServer(ForkServerError),
+    /// The frontend benchmark couldn't run, or its baseline couldn't be read
+    Benchmark(BenchError),
+    /// Metrics slower than the baseline by more than the threshold
+    PerformanceRegression(Vec<Regression>),
+    #[cfg(feature = "llvm")]
+    Compiler(compiler::CompilerError),
+}
+
 // Main entry point
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Starting C23 Compiler Development Environment...");
+    log::info!("Starting C23 Compiler Development Environment...");
     
     // Create and run orchestrator
     let mut orchestrator = CompilerOrchestrator::new().await?;
     orchestrator.run().await?;
     
-    println!("Development environment ready!");
+    log::info!("Development environment ready!");
     Ok(())
 } 
@@ -0,0 +1,477 @@
+// src/pgo/instrprof.rs
+//! LLVM's profile file formats
+//! The raw format (`.profraw`) is the image an instrumented program dumps
+//! at exit: a header, one data record per function, the counters, and the
+//! function names. Its layout follows the LLVM release the compiler links,
+//! version 9 for LLVM 18; version 8 files from LLVM 14-17 are read too.
+//! Data records name functions by the MD5 of their name and refer to their
+//! counters by an offset relative to the record itself.
+//!
+//! The indexed format (`.profdata`) is what `llvm-profdata merge` writes
+//! and clang's `-fprofile-use` reads: a header, the profile summary, and an
+//! on-disk chained hash table from function name to its records. Version 7
+//! is written, which every LLVM since 12 reads; versions up to 12 are read.
+//!
+//! Everything is little-endian and 64-bit. Value profiles (indirect call
+//! targets, memcpy sizes) and MC/DC bitmaps are neither written nor kept.
+
+use std::collections::HashMap;
+use super::profile::{FunctionRecord, Profile, ProfileError, ProfileSummary, SUMMARY_CUTOFFS};
+
+/// `\xfflprofr\x81`: raw, 64-bit, little-endian
+pub const RAW_MAGIC: u64 = 0xff6c70726f667281;
+/// `\xfflprofi\x81`
+pub const INDEXED_MAGIC: u64 = 0x8169666f72706cff;
+
+/// Raw version LLVM 18's runtime writes
+pub const RAW_VERSION: u64 = 9;
+const INDEXED_VERSION: u64 = 7;
+
+/// Feature flags share the version field
+const VARIANT_MASKS_ALL: u64 = 0xffffffff00000000;
+const VARIANT_MASK_BYTE_COVERAGE: u64 = 1 << 60;
+/// The last value profiling kind (memop sizes); the data records have a
+/// site count for each
+const VALUE_KIND_LAST: u64 = 1;
+/// Separates the function names
+const NAME_SEPARATOR: u8 = 0x01;
+
+/// Size of a data record in raw version `version`
+fn raw_data_size(version: u64) -> usize {
+    match version {
+        // NameRef, FuncHash, CounterPtr, FunctionPointer, Values,
+        // NumCounters, NumValueSites[2]
+        8 => 48,
+        // BitmapPtr and NumBitmapBytes added; 60 bytes aligned to 8
+        _ => 64,
+    }
+}
+
+fn raw_header_size(version: u64) -> usize {
+    match version {
+        8 => 11 * 8,
+        _ => 14 * 8,
+    }
+}
+
+/// What a profiled program writes around its counters, which are the only
+/// part that isn't known at compile time
+pub struct RawImage {
+    pub prefix: Vec<u8>,
+    pub suffix: Vec<u8>,
+}
+
+/// The raw profile of `functions` (name, control-flow hash, number of
+/// counters), with the counters laid out one function after another in that
+/// order
+pub fn raw_image(functions: &[(&str, u64, usize)]) -> RawImage {
+    let data_size = raw_data_size(RAW_VERSION);
+    let num_counters: usize = functions.iter().map(|&(_, _, counters)| counters).sum();
+    let names = encode_names(functions.iter().map(|&(name, _, _)| name));
+    let counters_delta = (data_size * functions.len()) as u64;
+
+    let mut prefix = Vec::new();
+    for field in [
+        RAW_MAGIC,
+        RAW_VERSION,
+        0, // BinaryIdsSize
+        functions.len() as u64,
+        0, // PaddingBytesBeforeCounters
+        num_counters as u64,
+        0, // PaddingBytesAfterCounters
+        0, // NumBitmapBytes
+        0, // PaddingBytesAfterBitmapBytes
+        names.len() as u64,
+        counters_delta,
+        counters_delta + num_counters as u64 * 8, // BitmapDelta
+        0, // NamesDelta, only used by value profiling
+        VALUE_KIND_LAST,
+    ] {
+        prefix.extend_from_slice(&field.to_le_bytes());
+    }
+
+    // Each record's counter pointer is relative to the record
+    let mut offset = 0u64;
+    for (index, &(name, hash, counters)) in functions.iter().enumerate() {
+        let record_at = (index * data_size) as u64;
+        let start = prefix.len();
+        prefix.extend_from_slice(&md5_hash(name.as_bytes()).to_le_bytes());
+        prefix.extend_from_slice(&hash.to_le_bytes());
+        prefix.extend_from_slice(&(counters_delta + offset).wrapping_sub(record_at).to_le_bytes());
+        let bitmap = counters_delta + num_counters as u64 * 8;
+        prefix.extend_from_slice(&bitmap.wrapping_sub(record_at).to_le_bytes());
+        prefix.extend_from_slice(&0u64.to_le_bytes()); // FunctionPointer
+        prefix.extend_from_slice(&0u64.to_le_bytes()); // Values
+        prefix.extend_from_slice(&(counters as u32).to_le_bytes());
+        prefix.extend_from_slice(&[0; 4]); // NumValueSites
+        prefix.extend_from_slice(&0u32.to_le_bytes()); // NumBitmapBytes
+        prefix.resize(start + data_size, 0);
+        offset += counters as u64 * 8;
+    }
+
+    let mut suffix = names;
+    suffix.resize(suffix.len().next_multiple_of(8), 0);
+    RawImage { prefix, suffix }
+}
+
+pub fn write_raw(profile: &Profile) -> Vec<u8> {
+    let functions: Vec<(&str, u64, usize)> =
+        profile.records().map(|(name, record)| (name, record.hash, record.counts.len())).collect();
+    let image = raw_image(&functions);
+    let mut data = image.prefix;
+    for (_, record) in profile.records() {
+        for count in &record.counts {
+            data.extend_from_slice(&count.to_le_bytes());
+        }
+    }
+    data.extend_from_slice(&image.suffix);
+    data
+}
+
+pub fn read_raw(data: &[u8]) -> Result<Profile, ProfileError> {
+    let mut reader = Reader::new(data);
+    if reader.u64()? != RAW_MAGIC {
+        return Err(ProfileError::UnsupportedVersion("big-endian or 32-bit raw profile".to_string()));
+    }
+    let flags = reader.u64()?;
+    let version = flags & !VARIANT_MASKS_ALL;
+    if !(8..=9).contains(&version) {
+        return Err(ProfileError::UnsupportedVersion(format!("raw version {}", version)));
+    }
+    if flags & VARIANT_MASK_BYTE_COVERAGE != 0 {
+        return Err(ProfileError::UnsupportedVersion("single-byte coverage counters".to_string()));
+    }
+    let binary_ids_size = reader.u64()? as usize;
+    let num_data = reader.u64()? as usize;
+    let padding_before_counters = reader.u64()? as usize;
+    let num_counters = reader.u64()? as usize;
+    let padding_after_counters = reader.u64()? as usize;
+    let (num_bitmap_bytes, padding_after_bitmap) = match version {
+        8 => (0, 0),
+        _ => (reader.u64()? as usize, reader.u64()? as usize),
+    };
+    let names_size = reader.u64()? as usize;
+    let counters_delta = reader.u64()?;
+
+    let data_size = raw_data_size(version);
+    let data_start = raw_header_size(version) + binary_ids_size;
+    let counters_start = data_start + num_data * data_size + padding_before_counters;
+    let names_start = counters_start + num_counters * 8 + padding_after_counters + num_bitmap_bytes + padding_after_bitmap;
+    let names = data
+        .get(names_start..names_start + names_size)
+        .ok_or_else(|| ProfileError::Malformed("raw profile is truncated".to_string()))?;
+    let by_hash: HashMap<u64, String> = decode_names(names)?
+        .into_iter()
+        .map(|name| (md5_hash(name.as_bytes()), name))
+        .collect();
+
+    let mut profile = Profile::new();
+    for index in 0..num_data {
+        let mut record = Reader::at(data, data_start + index * data_size);
+        let name_ref = record.u64()?;
+        let hash = record.u64()?;
+        let counter_ptr = record.u64()?;
+        if version > 8 {
+            record.u64()?; // BitmapPtr
+        }
+        record.u64()?; // FunctionPointer
+        record.u64()?; // Values
+        let counters = record.u32()? as usize;
+
+        let name = by_hash
+            .get(&name_ref)
+            .ok_or_else(|| ProfileError::Malformed(format!("no name for function {:#x}", name_ref)))?;
+        let relative = counters_delta.wrapping_sub((index * data_size) as u64);
+        let offset = counter_ptr.wrapping_sub(relative) as usize;
+        if !offset.is_multiple_of(8) || offset / 8 + counters > num_counters {
+            return Err(ProfileError::Malformed(format!("counters of {} are out of bounds", name)));
+        }
+        let mut reader = Reader::at(data, counters_start + offset);
+        let counts = (0..counters).map(|_| reader.u64()).collect::<Result<Vec<_>, _>>()?;
+        profile.add(name, FunctionRecord { hash, counts })?;
+    }
+    Ok(profile)
+}
+
+pub fn write_indexed(profile: &Profile) -> Vec<u8> {
+    let mut out = Vec::new();
+    for field in [INDEXED_MAGIC, INDEXED_VERSION, 0, 0 /* MD5 */, 0 /* HashOffset, patched below */] {
+        out.extend_from_slice(&field.to_le_bytes());
+    }
+    write_summary(&mut out, &profile.summary());
+
+    // The hash table: every bucket's items, then the bucket offsets
+    let mut functions: Vec<(&str, Vec<&FunctionRecord>)> = Vec::new();
+    for (name, record) in profile.records() {
+        match functions.last_mut() {
+            Some((last, records)) if *last == name => records.push(record),
+            _ => functions.push((name, vec![record])),
+        }
+    }
+    let num_buckets = match functions.len() {
+        0..=2 => 1,
+        n => (n * 4 / 3 + 1).next_power_of_two(),
+    };
+    let mut buckets: Vec<Vec<(u64, &str, &Vec<&FunctionRecord>)>> = vec![Vec::new(); num_buckets];
+    for (name, records) in &functions {
+        let hash = md5_hash(name.as_bytes());
+        buckets[(hash as usize) & (num_buckets - 1)].push((hash, name, records));
+    }
+    let mut offsets = vec![0u64; num_buckets];
+    for (bucket, items) in buckets.iter().enumerate() {
+        if items.is_empty() {
+            continue;
+        }
+        offsets[bucket] = out.len() as u64;
+        out.extend_from_slice(&(items.len() as u16).to_le_bytes());
+        for (hash, name, records) in items {
+            // Per record: hash, counter count, counters, and 8 bytes of
+            // empty value profile
+            let data_len: usize = records.iter().map(|record| 16 + record.counts.len() * 8 + 8).sum();
+            out.extend_from_slice(&hash.to_le_bytes());
+            out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+            out.extend_from_slice(&(data_len as u64).to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            for record in records.iter() {
+                out.extend_from_slice(&record.hash.to_le_bytes());
+                out.extend_from_slice(&(record.counts.len() as u64).to_le_bytes());
+                for count in &record.counts {
+                    out.extend_from_slice(&count.to_le_bytes());
+                }
+                out.extend_from_slice(&8u32.to_le_bytes()); // TotalSize
+                out.extend_from_slice(&0u32.to_le_bytes()); // NumValueKinds
+            }
+        }
+    }
+    out.resize(out.len().next_multiple_of(8), 0);
+    let hash_offset = out.len() as u64;
+    out.extend_from_slice(&(num_buckets as u64).to_le_bytes());
+    out.extend_from_slice(&(functions.len() as u64).to_le_bytes());
+    for offset in offsets {
+        out.extend_from_slice(&offset.to_le_bytes());
+    }
+    out[32..40].copy_from_slice(&hash_offset.to_le_bytes());
+    out
+}
+
+/// Field count, cutoff count, the six summary fields, then per cutoff
+/// (cutoff, min count, number of counts)
+fn write_summary(out: &mut Vec<u8>, summary: &ProfileSummary) {
+    let fields = [
+        summary.num_functions,
+        summary.num_counts,
+        summary.max_function_count,
+        summary.max_count,
+        summary.max_internal_count,
+        summary.total_count,
+    ];
+    out.extend_from_slice(&(fields.len() as u64).to_le_bytes());
+    out.extend_from_slice(&(SUMMARY_CUTOFFS.len() as u64).to_le_bytes());
+    for field in fields {
+        out.extend_from_slice(&field.to_le_bytes());
+    }
+    for &(cutoff, min_count, num_counts) in &summary.detailed {
+        out.extend_from_slice(&u64::from(cutoff).to_le_bytes());
+        out.extend_from_slice(&min_count.to_le_bytes());
+        out.extend_from_slice(&num_counts.to_le_bytes());
+    }
+}
+
+pub fn read_indexed(data: &[u8]) -> Result<Profile, ProfileError> {
+    let mut reader = Reader::new(data);
+    if reader.u64()? != INDEXED_MAGIC {
+        return Err(ProfileError::UnknownFormat);
+    }
+    let version = reader.u64()? & !VARIANT_MASKS_ALL;
+    if !(3..=12).contains(&version) {
+        return Err(ProfileError::UnsupportedVersion(format!("indexed version {}", version)));
+    }
+    reader.u64()?; // Unused
+    if reader.u64()? != 0 {
+        return Err(ProfileError::UnsupportedVersion("function names not hashed with MD5".to_string()));
+    }
+    let hash_offset = reader.u64()? as usize;
+
+    let mut table = Reader::at(data, hash_offset);
+    let num_buckets = table.u64()? as usize;
+    table.u64()?; // NumEntries
+    let mut profile = Profile::new();
+    for _ in 0..num_buckets {
+        let offset = table.u64()? as usize;
+        if offset == 0 {
+            continue;
+        }
+        let mut bucket = Reader::at(data, offset);
+        for _ in 0..bucket.u16()? {
+            bucket.u64()?; // Hash of the name
+            let key_len = bucket.u64()? as usize;
+            let data_len = bucket.u64()? as usize;
+            let name = String::from_utf8_lossy(bucket.bytes(key_len)?).into_owned();
+            let end = bucket.position + data_len;
+            while bucket.position < end {
+                let hash = bucket.u64()?;
+                let counters = bucket.u64()? as usize;
+                if counters > data_len / 8 {
+                    return Err(ProfileError::Malformed(format!("{} has too many counters", name)));
+                }
+                let counts = (0..counters).map(|_| bucket.u64()).collect::<Result<Vec<_>, _>>()?;
+                if version > 10 {
+                    let bitmap_bytes = bucket.u64()? as usize;
+                    bucket.bytes(bitmap_bytes.saturating_mul(8))?;
+                }
+                // Value profile data, headed by its own size
+                let value_data = bucket.position;
+                let total_size = bucket.u32()? as usize;
+                bucket.position = value_data + total_size.max(8);
+                profile.add(&name, FunctionRecord { hash, counts })?;
+            }
+            bucket.position = end;
+        }
+    }
+    Ok(profile)
+}
+
+/// Uncompressed name lists: ULEB128 size, ULEB128 compressed size (zero),
+/// then the names joined by `NAME_SEPARATOR`
+fn encode_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<u8> {
+    let joined = names.collect::<Vec<_>>().join("\u{1}");
+    let mut out = Vec::new();
+    write_uleb128(&mut out, joined.len() as u64);
+    write_uleb128(&mut out, 0);
+    out.extend_from_slice(joined.as_bytes());
+    out
+}
+
+fn decode_names(mut data: &[u8]) -> Result<Vec<String>, ProfileError> {
+    let mut names = Vec::new();
+    while !data.is_empty() {
+        let (size, rest) = read_uleb128(data)?;
+        let (compressed, rest) = read_uleb128(rest)?;
+        if compressed != 0 {
+            return Err(ProfileError::UnsupportedVersion(
+                "compressed function names; run it through llvm-profdata merge first".to_string(),
+            ));
+        }
+        let list = rest
+            .get(..size as usize)
+            .ok_or_else(|| ProfileError::Malformed("function names are truncated".to_string()))?;
+        names.extend(list.split(|&byte| byte == NAME_SEPARATOR).map(|name| String::from_utf8_lossy(name).into_owned()));
+        // Lists are padded with zeros
+        data = &rest[size as usize..];
+        while data.first() == Some(&0) {
+            data = &data[1..];
+        }
+    }
+    Ok(names)
+}
+
+fn write_uleb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_uleb128(data: &[u8]) -> Result<(u64, &[u8]), ProfileError> {
+    let mut value = 0u64;
+    for (index, &byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok((value, &data[index + 1..]));
+        }
+    }
+    Err(ProfileError::Malformed("bad LEB128 number".to_string()))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    fn at(data: &'a [u8], position: usize) -> Self {
+        Reader { data, position }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ProfileError> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(|| ProfileError::Malformed("profile is truncated".to_string()))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, ProfileError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProfileError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u16(&mut self) -> Result<u16, ProfileError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+}
+
+/// The low 64 bits of the MD5 of `data`, read little-endian, which is how
+/// LLVM names functions in profiles
+pub fn md5_hash(data: &[u8]) -> u64 {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14,
+        20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6,
+        10, 15, 21,
+    ];
+    let constants: Vec<u32> = (0..64).map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32).collect();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks(64) {
+        let words: Vec<u32> = block.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a.wrapping_add(f).wrapping_add(constants[i]).wrapping_add(words[g]).rotate_left(SHIFTS[i]);
+            (a, d, c) = (d, c, b);
+            b = b.wrapping_add(rotated);
+        }
+        state = [state[0].wrapping_add(a), state[1].wrapping_add(b), state[2].wrapping_add(c), state[3].wrapping_add(d)];
+    }
+    u64::from(state[0]) | u64::from(state[1]) << 32
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ProfileError> {
+    let mut profile = Profile::new();
+    profile.add("main", FunctionRecord { hash: 0x1234, counts: vec![1, 10, 0] })?;
+
+    // What llvm-profdata show --all-functions --counts can read
+    std::fs::write("main.profraw", write_raw(&profile)).unwrap();
+    std::fs::write("main.profdata", write_indexed(&profile)).unwrap();
+    assert_eq!(read_indexed(&write_indexed(&profile))?, profile);
+    Ok(())
+}
+*/
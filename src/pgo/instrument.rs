@@ -0,0 +1,469 @@
+// src/pgo/instrument.rs
+//! Profile instrumentation and use on LLVM IR
+//! `ProfileInstrumentation` gives every basic block of every defined
+//! function a 64-bit counter, incremented on entry to the block, and makes
+//! the program dump them as a `.profraw` when it exits: from an `atexit`
+//! handler, and on return from `main` for the JIT, whose child process
+//! leaves with `_exit`. The file is `LLVM_PROFILE_FILE` if set, else the
+//! path given at compile time. Everything but the counters is known at
+//! compile time, so the image around them is emitted as constants (see
+//! `instrprof::raw_image`).
+//!
+//! `ProfileUse` reads the counts back into a later compile of the same
+//! source: entry counts as `function_entry_count`, branch and switch
+//! weights as `branch_weights`, and the profile summary as the module's
+//! `ProfileSummary` flag, which is what LLVM's inliner, block placement and
+//! hot/cold splitting consult. Both passes must run at the same point of
+//! the pipeline so the blocks line up; a function whose control flow no
+//! longer matches the hash it was profiled with is left alone.
+//!
+//! Functions are named as in LLVM's profiles: by their symbol, with the
+//! source file in front for internal ones (`file.c:helper`).
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use llvm_sys::core::*;
+use llvm_sys::prelude::*;
+use llvm_sys::{LLVMLinkage, LLVMModuleFlagBehavior, LLVMOpcode};
+use super::instrprof;
+use super::profile::{Profile, ProfileError, ProfileSummary};
+
+pub struct ProfileInstrumentation {
+    default_file: String,
+
+    // Statistics
+    functions: usize,
+    counters: usize,
+}
+
+impl ProfileInstrumentation {
+    /// `default_file` is where the profile goes unless `LLVM_PROFILE_FILE`
+    /// says otherwise
+    pub fn new(default_file: &str) -> Self {
+        ProfileInstrumentation {
+            default_file: default_file.to_string(),
+            functions: 0,
+            counters: 0,
+        }
+    }
+
+    /// (functions instrumented, counters)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.functions, self.counters)
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) -> Result<(), ProfileError> {
+        let default_file = CString::new(self.default_file.as_str())
+            .map_err(|_| ProfileError::InvalidPath(self.default_file.clone().into()))?;
+        if !LLVMGetNamedGlobal(module, c"__ic_profc".as_ptr()).is_null() {
+            return Ok(());
+        }
+        let functions = defined_functions(module);
+        if functions.is_empty() {
+            return Ok(());
+        }
+
+        let context = LLVMGetModuleContext(module);
+        let i64_ty = LLVMInt64TypeInContext(context);
+        let names: Vec<String> = functions.iter().map(|&function| pgo_name(module, function)).collect();
+        let layout: Vec<(&str, u64, usize)> = functions
+            .iter()
+            .zip(&names)
+            .map(|(&function, name)| (name.as_str(), cfg_hash(function), LLVMCountBasicBlocks(function) as usize))
+            .collect();
+        let total: usize = layout.iter().map(|&(_, _, counters)| counters).sum();
+
+        let counters_ty = LLVMArrayType2(i64_ty, total as u64);
+        let counters = LLVMAddGlobal(module, counters_ty, c"__ic_profc".as_ptr());
+        LLVMSetInitializer(counters, LLVMConstNull(counters_ty));
+        LLVMSetLinkage(counters, LLVMLinkage::LLVMInternalLinkage);
+
+        let builder = LLVMCreateBuilderInContext(context);
+        let mut index = 0u64;
+        for &function in &functions {
+            for block in blocks(function) {
+                let mut indices = [LLVMConstInt(i64_ty, 0, 0), LLVMConstInt(i64_ty, index, 0)];
+                let counter = LLVMConstInBoundsGEP2(counters_ty, counters, indices.as_mut_ptr(), 2);
+                LLVMPositionBuilderBefore(builder, insertion_point(block));
+                let count = LLVMBuildLoad2(builder, i64_ty, counter, c"".as_ptr());
+                let count = LLVMBuildAdd(builder, count, LLVMConstInt(i64_ty, 1, 0), c"".as_ptr());
+                LLVMBuildStore(builder, count, counter);
+                index += 1;
+            }
+        }
+
+        let image = instrprof::raw_image(&layout);
+        let writer = build_writer(module, builder, counters, total, &image, &default_file);
+        if let Some(main) = functions.iter().copied().find(|&function| value_name(function) == "main") {
+            hook_main(module, builder, main, writer);
+        }
+        LLVMDisposeBuilder(builder);
+
+        self.functions = functions.len();
+        self.counters = total;
+        Ok(())
+    }
+}
+
+/// `void __ic_profile_write(void)`: once per process, write prefix,
+/// counters and suffix to the profile file
+unsafe fn build_writer(
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    counters: LLVMValueRef,
+    total: usize,
+    image: &instrprof::RawImage,
+    default_file: &CString,
+) -> LLVMValueRef {
+    let context = LLVMGetModuleContext(module);
+    let void_ty = LLVMVoidTypeInContext(context);
+    let i1_ty = LLVMInt1TypeInContext(context);
+    let i64_ty = LLVMInt64TypeInContext(context);
+    let ptr_ty = LLVMPointerTypeInContext(context, 0);
+
+    let mut getenv_params = [ptr_ty];
+    let getenv_ty = LLVMFunctionType(ptr_ty, getenv_params.as_mut_ptr(), 1, 0);
+    let mut fopen_params = [ptr_ty, ptr_ty];
+    let fopen_ty = LLVMFunctionType(ptr_ty, fopen_params.as_mut_ptr(), 2, 0);
+    let mut fwrite_params = [ptr_ty, i64_ty, i64_ty, ptr_ty];
+    let fwrite_ty = LLVMFunctionType(i64_ty, fwrite_params.as_mut_ptr(), 4, 0);
+    let mut fclose_params = [ptr_ty];
+    let fclose_ty = LLVMFunctionType(LLVMInt32TypeInContext(context), fclose_params.as_mut_ptr(), 1, 0);
+
+    let written = LLVMAddGlobal(module, i1_ty, c"__ic_prof_written".as_ptr());
+    LLVMSetInitializer(written, LLVMConstInt(i1_ty, 0, 0));
+    LLVMSetLinkage(written, LLVMLinkage::LLVMInternalLinkage);
+    let prefix = constant_bytes(module, "__ic_prof_prefix", &image.prefix);
+    let suffix = constant_bytes(module, "__ic_prof_suffix", &image.suffix);
+    let default_file = constant_bytes(module, "__ic_prof_file", default_file.as_bytes_with_nul());
+
+    let writer_ty = LLVMFunctionType(void_ty, std::ptr::null_mut(), 0, 0);
+    let writer = LLVMAddFunction(module, c"__ic_profile_write".as_ptr(), writer_ty);
+    LLVMSetLinkage(writer, LLVMLinkage::LLVMInternalLinkage);
+    let entry = LLVMAppendBasicBlockInContext(context, writer, c"entry".as_ptr());
+    let write = LLVMAppendBasicBlockInContext(context, writer, c"write".as_ptr());
+    let opened = LLVMAppendBasicBlockInContext(context, writer, c"opened".as_ptr());
+    let done = LLVMAppendBasicBlockInContext(context, writer, c"done".as_ptr());
+
+    LLVMPositionBuilderAtEnd(builder, entry);
+    let was_written = LLVMBuildLoad2(builder, i1_ty, written, c"".as_ptr());
+    LLVMBuildCondBr(builder, was_written, done, write);
+
+    LLVMPositionBuilderAtEnd(builder, write);
+    LLVMBuildStore(builder, LLVMConstInt(i1_ty, 1, 0), written);
+    let variable = constant_bytes(module, "__ic_prof_env", b"LLVM_PROFILE_FILE\0");
+    let getenv = declare(module, "getenv", getenv_ty);
+    let mut args = [variable];
+    let from_env = LLVMBuildCall2(builder, getenv_ty, getenv, args.as_mut_ptr(), 1, c"".as_ptr());
+    let unset = LLVMBuildIsNull(builder, from_env, c"".as_ptr());
+    let path = LLVMBuildSelect(builder, unset, default_file, from_env, c"".as_ptr());
+    let mode = constant_bytes(module, "__ic_prof_mode", b"wb\0");
+    let fopen = declare(module, "fopen", fopen_ty);
+    let mut args = [path, mode];
+    let file = LLVMBuildCall2(builder, fopen_ty, fopen, args.as_mut_ptr(), 2, c"".as_ptr());
+    let failed = LLVMBuildIsNull(builder, file, c"".as_ptr());
+    LLVMBuildCondBr(builder, failed, done, opened);
+
+    LLVMPositionBuilderAtEnd(builder, opened);
+    let fwrite = declare(module, "fwrite", fwrite_ty);
+    for (data, size, count) in [
+        (prefix, 1, image.prefix.len()),
+        (counters, 8, total),
+        (suffix, 1, image.suffix.len()),
+    ] {
+        let mut args = [data, LLVMConstInt(i64_ty, size, 0), LLVMConstInt(i64_ty, count as u64, 0), file];
+        LLVMBuildCall2(builder, fwrite_ty, fwrite, args.as_mut_ptr(), 4, c"".as_ptr());
+    }
+    let fclose = declare(module, "fclose", fclose_ty);
+    let mut args = [file];
+    LLVMBuildCall2(builder, fclose_ty, fclose, args.as_mut_ptr(), 1, c"".as_ptr());
+    LLVMBuildBr(builder, done);
+
+    LLVMPositionBuilderAtEnd(builder, done);
+    LLVMBuildRetVoid(builder);
+    writer
+}
+
+/// Register the writer with `atexit` on entry to `main` and call it before
+/// `main` returns
+unsafe fn hook_main(module: LLVMModuleRef, builder: LLVMBuilderRef, main: LLVMValueRef, writer: LLVMValueRef) {
+    let context = LLVMGetModuleContext(module);
+    let ptr_ty = LLVMPointerTypeInContext(context, 0);
+    let mut atexit_params = [ptr_ty];
+    let atexit_ty = LLVMFunctionType(LLVMInt32TypeInContext(context), atexit_params.as_mut_ptr(), 1, 0);
+    let atexit = declare(module, "atexit", atexit_ty);
+    let writer_ty = LLVMGlobalGetValueType(writer);
+
+    for block in blocks(main) {
+        let terminator = LLVMGetBasicBlockTerminator(block);
+        if !terminator.is_null() && LLVMGetInstructionOpcode(terminator) == LLVMOpcode::LLVMRet {
+            LLVMPositionBuilderBefore(builder, terminator);
+            LLVMBuildCall2(builder, writer_ty, writer, std::ptr::null_mut(), 0, c"".as_ptr());
+        }
+    }
+    LLVMPositionBuilderBefore(builder, insertion_point(LLVMGetEntryBasicBlock(main)));
+    let mut args = [writer];
+    LLVMBuildCall2(builder, atexit_ty, atexit, args.as_mut_ptr(), 1, c"".as_ptr());
+}
+
+pub struct ProfileUse<'a> {
+    profile: &'a Profile,
+
+    // Statistics
+    annotated: usize,
+    stale: usize,
+    missing: usize,
+}
+
+impl<'a> ProfileUse<'a> {
+    pub fn new(profile: &'a Profile) -> Self {
+        ProfileUse {
+            profile,
+            annotated: 0,
+            stale: 0,
+            missing: 0,
+        }
+    }
+
+    /// (functions annotated, functions changed since profiling, functions
+    /// the profile doesn't have)
+    pub fn stats(&self) -> (usize, usize, usize) {
+        (self.annotated, self.stale, self.missing)
+    }
+
+    pub unsafe fn run(&mut self, module: LLVMModuleRef) {
+        let context = LLVMGetModuleContext(module);
+        let prof = LLVMGetMDKindIDInContext(context, c"prof".as_ptr(), 4);
+        for function in defined_functions(module) {
+            let name = pgo_name(module, function);
+            let blocks = blocks(function);
+            let record = match self.profile.get(&name, cfg_hash(function)) {
+                Some(record) if record.counts.len() == blocks.len() => record,
+                _ if self.profile.contains(&name) => {
+                    log::warn!("{}: profile is out of date, control flow changed", name);
+                    self.stale += 1;
+                    continue;
+                }
+                _ => {
+                    self.missing += 1;
+                    continue;
+                }
+            };
+
+            let i64_ty = LLVMInt64TypeInContext(context);
+            let mut entry = [md_string(context, "function_entry_count"), md_int(i64_ty, record.entry_count())];
+            LLVMGlobalSetMetadata(function, prof, LLVMMDNodeInContext2(context, entry.as_mut_ptr(), 2));
+            annotate_branches(context, prof, &blocks, &record.counts);
+            self.annotated += 1;
+        }
+        if self.annotated > 0 {
+            add_summary(module, &self.profile.summary());
+        }
+    }
+}
+
+/// `branch_weights` on every branch and switch whose successor counts can
+/// be told from the block counts: a successor reached only from this block
+/// ran as often as the edge, and the last unknown edge of a block gets what
+/// is left of the block's count
+unsafe fn annotate_branches(context: LLVMContextRef, prof: u32, blocks: &[LLVMBasicBlockRef], counts: &[u64]) {
+    let index: HashMap<LLVMBasicBlockRef, usize> = blocks.iter().enumerate().map(|(i, &block)| (block, i)).collect();
+    let mut predecessors = vec![0usize; blocks.len()];
+    for &block in blocks {
+        let terminator = LLVMGetBasicBlockTerminator(block);
+        if terminator.is_null() {
+            continue;
+        }
+        for i in 0..LLVMGetNumSuccessors(terminator) {
+            if let Some(&successor) = index.get(&LLVMGetSuccessor(terminator, i)) {
+                predecessors[successor] += 1;
+            }
+        }
+    }
+
+    let i32_ty = LLVMInt32TypeInContext(context);
+    for (i, &block) in blocks.iter().enumerate() {
+        let terminator = LLVMGetBasicBlockTerminator(block);
+        if terminator.is_null()
+            || !matches!(LLVMGetInstructionOpcode(terminator), LLVMOpcode::LLVMBr | LLVMOpcode::LLVMSwitch)
+            || LLVMGetNumSuccessors(terminator) < 2
+        {
+            continue;
+        }
+        let mut weights: Vec<Option<u64>> = (0..LLVMGetNumSuccessors(terminator))
+            .map(|s| match index.get(&LLVMGetSuccessor(terminator, s)) {
+                Some(&successor) if predecessors[successor] == 1 => Some(counts[successor]),
+                _ => None,
+            })
+            .collect();
+        if weights.iter().filter(|weight| weight.is_none()).count() == 1 {
+            let known: u64 = weights.iter().flatten().sum();
+            let unknown = weights.iter_mut().find(|weight| weight.is_none()).unwrap();
+            *unknown = Some(counts[i].saturating_sub(known));
+        }
+        let Some(weights) = weights.into_iter().collect::<Option<Vec<u64>>>() else { continue };
+        if weights.iter().all(|&weight| weight == 0) {
+            continue;
+        }
+
+        // Weights are 32-bit
+        let scale = weights.iter().max().copied().unwrap_or(0) / u64::from(u32::MAX) + 1;
+        let mut operands = vec![md_string(context, "branch_weights")];
+        operands.extend(weights.iter().map(|&weight| md_int(i32_ty, weight / scale)));
+        let node = LLVMMDNodeInContext2(context, operands.as_mut_ptr(), operands.len());
+        LLVMSetMetadata(terminator, prof, LLVMMetadataAsValue(context, node));
+    }
+}
+
+/// The `ProfileSummary` module flag, as clang's `-fprofile-use` sets it
+unsafe fn add_summary(module: LLVMModuleRef, summary: &ProfileSummary) {
+    let context = LLVMGetModuleContext(module);
+    let i32_ty = LLVMInt32TypeInContext(context);
+    let i64_ty = LLVMInt64TypeInContext(context);
+    let pair = |key: &str, value: LLVMMetadataRef| {
+        let mut operands = [md_string(context, key), value];
+        LLVMMDNodeInContext2(context, operands.as_mut_ptr(), 2)
+    };
+
+    let mut detailed: Vec<LLVMMetadataRef> = summary
+        .detailed
+        .iter()
+        .map(|&(cutoff, min_count, num_counts)| {
+            let mut entry = [md_int(i32_ty, u64::from(cutoff)), md_int(i64_ty, min_count), md_int(i32_ty, num_counts)];
+            LLVMMDNodeInContext2(context, entry.as_mut_ptr(), 3)
+        })
+        .collect();
+    let detailed = LLVMMDNodeInContext2(context, detailed.as_mut_ptr(), detailed.len());
+
+    let mut fields = [
+        pair("ProfileFormat", md_string(context, "InstrProf")),
+        pair("TotalCount", md_int(i64_ty, summary.total_count)),
+        pair("MaxCount", md_int(i64_ty, summary.max_count)),
+        pair("MaxInternalCount", md_int(i64_ty, summary.max_internal_count)),
+        pair("MaxFunctionCount", md_int(i64_ty, summary.max_function_count)),
+        pair("NumCounts", md_int(i64_ty, summary.num_counts)),
+        pair("NumFunctions", md_int(i64_ty, summary.num_functions)),
+        pair("DetailedSummary", detailed),
+    ];
+    let node = LLVMMDNodeInContext2(context, fields.as_mut_ptr(), fields.len());
+    let key = "ProfileSummary";
+    LLVMAddModuleFlag(module, LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorError, key.as_ptr() as *const _, key.len(), node);
+}
+
+/// Hash of the shape of `function`'s control flow: its blocks and their
+/// terminators. Counters are per block, so a change here means they no
+/// longer line up.
+pub unsafe fn cfg_hash(function: LLVMValueRef) -> u64 {
+    let blocks = blocks(function);
+    let mut bytes = (blocks.len() as u64).to_le_bytes().to_vec();
+    for block in blocks {
+        let terminator = LLVMGetBasicBlockTerminator(block);
+        if terminator.is_null() {
+            bytes.extend_from_slice(&[0; 8]);
+            continue;
+        }
+        bytes.extend_from_slice(&(LLVMGetInstructionOpcode(terminator) as u32).to_le_bytes());
+        bytes.extend_from_slice(&LLVMGetNumSuccessors(terminator).to_le_bytes());
+    }
+    crate::report::fnv1a_64(&bytes)
+}
+
+/// The name `function` has in profiles
+pub unsafe fn pgo_name(module: LLVMModuleRef, function: LLVMValueRef) -> String {
+    let name = value_name(function);
+    match LLVMGetLinkage(function) {
+        LLVMLinkage::LLVMInternalLinkage | LLVMLinkage::LLVMPrivateLinkage => {
+            let mut len = 0;
+            let file = LLVMGetSourceFileName(module, &mut len);
+            let file = String::from_utf8_lossy(std::slice::from_raw_parts(file as *const u8, len));
+            format!("{}:{}", file, name)
+        }
+        _ => name,
+    }
+}
+
+unsafe fn defined_functions(module: LLVMModuleRef) -> Vec<LLVMValueRef> {
+    let mut functions = Vec::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        if LLVMCountBasicBlocks(function) > 0 {
+            functions.push(function);
+        }
+        function = LLVMGetNextFunction(function);
+    }
+    functions
+}
+
+unsafe fn blocks(function: LLVMValueRef) -> Vec<LLVMBasicBlockRef> {
+    let mut blocks = Vec::new();
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        blocks.push(block);
+        block = LLVMGetNextBasicBlock(block);
+    }
+    blocks
+}
+
+/// The first instruction of `block` that code may go in front of
+unsafe fn insertion_point(block: LLVMBasicBlockRef) -> LLVMValueRef {
+    let mut inst = LLVMGetFirstInstruction(block);
+    while !LLVMIsAPHINode(inst).is_null() || !LLVMIsALandingPadInst(inst).is_null() {
+        inst = LLVMGetNextInstruction(inst);
+    }
+    inst
+}
+
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut len = 0;
+    let name = LLVMGetValueName2(value, &mut len);
+    String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len)).into_owned()
+}
+
+unsafe fn declare(module: LLVMModuleRef, name: &str, ty: LLVMTypeRef) -> LLVMValueRef {
+    let c_name = CString::new(name).unwrap();
+    let existing = LLVMGetNamedFunction(module, c_name.as_ptr());
+    if !existing.is_null() {
+        return existing;
+    }
+    LLVMAddFunction(module, c_name.as_ptr(), ty)
+}
+
+/// A private constant byte array
+unsafe fn constant_bytes(module: LLVMModuleRef, name: &str, bytes: &[u8]) -> LLVMValueRef {
+    let context = LLVMGetModuleContext(module);
+    let value = LLVMConstStringInContext(context, bytes.as_ptr() as *const _, bytes.len() as u32, 1);
+    let c_name = CString::new(name).unwrap();
+    let global = LLVMAddGlobal(module, LLVMTypeOf(value), c_name.as_ptr());
+    LLVMSetInitializer(global, value);
+    LLVMSetGlobalConstant(global, 1);
+    LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+    global
+}
+
+unsafe fn md_string(context: LLVMContextRef, text: &str) -> LLVMMetadataRef {
+    LLVMMDStringInContext2(context, text.as_ptr() as *const _, text.len())
+}
+
+unsafe fn md_int(ty: LLVMTypeRef, value: u64) -> LLVMMetadataRef {
+    LLVMValueAsMetadata(LLVMConstInt(ty, value, 0))
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ProfileError> {
+    unsafe {
+        // ic --profile-generate prog.c && ./prog
+        let mut instrumentation = ProfileInstrumentation::new(DEFAULT_PROFILE_FILE);
+        instrumentation.run(module)?;
+        println!("{:?}", instrumentation.stats()); // (functions, counters)
+
+        // ic --profile-use default.profraw prog.c
+        let profile = Profile::load(Path::new("default.profraw"))?;
+        let mut profile_use = ProfileUse::new(&profile);
+        profile_use.run(module);
+        let (annotated, stale, missing) = profile_use.stats();
+        println!("{} annotated, {} stale, {} not profiled", annotated, stale, missing);
+    }
+    Ok(())
+}
+*/
@@ -1,6 +1,13 @@
 // src/pgo/mod.rs
+pub mod instrprof;
+#[cfg(feature = "llvm")]
+pub mod instrument;
+pub mod profile;
+pub mod sampling;
+
 use std::sync::Arc;
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use parking_lot::RwLock;
 use crossbeam_channel::{bounded, Sender, Receiver};
 
@@ -86,6 +93,28 @@ impl PGOSystem {
         Ok(plan)
     }
 
+    /// Plan from a sampling profile instead of counters: each function's
+    /// samples stand in for its call count, so the same hot-path analysis
+    /// applies. Branches and loops have no samples of their own.
+    pub fn analyze_samples(
+        &mut self,
+        ir: &IR,
+        samples: &sampling::SampleProfile
+    ) -> Result<OptimizationPlan, PGOError> {
+        {
+            let mut profile = self.profile_data.write();
+            *profile = ProfileData::new();
+            for func in ir.functions() {
+                if let Some(function) = samples.functions.get(func.name()) {
+                    profile.update_counter(CounterId::Function(func.id()), function.samples);
+                }
+            }
+            profile.total_samples = samples.total;
+        }
+        
+        self.analyze_profile()
+    }
+
     pub fn apply_optimizations(
         &mut self,
         ir: &mut IR,
@@ -115,6 +144,53 @@ impl PGOSystem {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterType {
+    Function,
+    Branch,
+    Loop,
+}
+
+/// Execution counter bumped by instrumented code, the interpreter, or the
+/// tiered JIT. Relaxed ordering: counts steer heuristics, not correctness.
+#[derive(Debug)]
+pub struct Counter {
+    kind: CounterType,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub fn new(kind: CounterType) -> Self {
+        Counter { kind, value: AtomicU64::new(0) }
+    }
+
+    pub fn kind(&self) -> CounterType {
+        self.kind
+    }
+
+    /// Add `n` and return the new total
+    pub fn add(&self, n: u64) -> u64 {
+        self.value.fetch_add(n, Ordering::Relaxed).saturating_add(n)
+    }
+
+    pub fn increment(&self) -> u64 {
+        self.add(1)
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.value.store(0, Ordering::Relaxed);
+    }
+
+    /// Address generated code can increment directly
+    pub fn as_ptr(&self) -> *const AtomicU64 {
+        &self.value
+    }
+}
+
 struct ProfileCollector {
     config: ProfileConfig,
     counters: HashMap<CounterId, Arc<Counter>>,
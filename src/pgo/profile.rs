@@ -0,0 +1,340 @@
+// src/pgo/profile.rs
+//! Saved profiles
+//! What `--profile-generate` collects and `--profile-use` reads back: for
+//! every instrumented function, its name, a hash of the control flow the
+//! counters were laid out for, and one execution count per basic block (the
+//! first is the entry count). This is LLVM's model of a profile, so the same
+//! data round-trips through LLVM's files:
+//!
+//! - `.profraw`, what an instrumented program writes when it exits; the
+//!   layout is tied to one LLVM release (see `instrprof`).
+//! - `.profdata`, the indexed form `llvm-profdata merge` produces, which
+//!   later LLVM releases still read.
+//! - A compact JSON fallback for tools that don't speak LLVM's formats.
+//!
+//! Profiles from several runs merge by adding counts. A function whose
+//! counters don't line up, because it was changed and recompiled in
+//! between, keeps one record per control-flow hash as LLVM does.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use super::instrprof;
+
+/// File a profiled program writes unless told otherwise, as with clang;
+/// `LLVM_PROFILE_FILE` overrides it at run time
+pub const DEFAULT_PROFILE_FILE: &str = "default.profraw";
+
+const JSON_FORMAT: &str = "ic-profile";
+const JSON_VERSION: u32 = 1;
+
+/// Counts for one version of one function
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionRecord {
+    /// Hash of the control flow the counters belong to
+    pub hash: u64,
+    /// Per basic block, the entry block first
+    pub counts: Vec<u64>,
+}
+
+impl FunctionRecord {
+    pub fn entry_count(&self) -> u64 {
+        self.counts.first().copied().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// By name; more than one record only when the function changed between
+    /// runs
+    functions: BTreeMap<String, Vec<FunctionRecord>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// LLVM's raw format, `.profraw`
+    Raw,
+    /// LLVM's indexed format, `.profdata`
+    Indexed,
+    /// Compact JSON
+    Json,
+}
+
+impl ProfileFormat {
+    /// The format a file to be written at `path` gets: by extension, and
+    /// indexed for anything else, as `llvm-profdata merge` writes
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("profraw") => ProfileFormat::Raw,
+            Some("json") => ProfileFormat::Json,
+            _ => ProfileFormat::Indexed,
+        }
+    }
+
+    /// The format of a file's contents, by its magic number
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        let magic = data.get(..8).map(|magic| u64::from_le_bytes(magic.try_into().unwrap()));
+        match magic {
+            Some(instrprof::RAW_MAGIC) => Some(ProfileFormat::Raw),
+            Some(instrprof::INDEXED_MAGIC) => Some(ProfileFormat::Indexed),
+            _ if data.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{') => Some(ProfileFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The compact JSON form
+#[derive(Serialize, Deserialize)]
+struct JsonProfile {
+    format: String,
+    version: u32,
+    functions: Vec<JsonFunction>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonFunction {
+    name: String,
+    hash: u64,
+    counts: Vec<u64>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// Add counts for `name`, merging with a record of the same hash
+    pub fn add(&mut self, name: &str, record: FunctionRecord) -> Result<(), ProfileError> {
+        let records = self.functions.entry(name.to_string()).or_default();
+        match records.iter_mut().find(|existing| existing.hash == record.hash) {
+            Some(existing) if existing.counts.len() != record.counts.len() => Err(ProfileError::CounterMismatch {
+                function: name.to_string(),
+                expected: existing.counts.len(),
+                found: record.counts.len(),
+            }),
+            Some(existing) => {
+                for (total, count) in existing.counts.iter_mut().zip(&record.counts) {
+                    *total = total.saturating_add(*count);
+                }
+                Ok(())
+            }
+            None => {
+                records.push(record);
+                Ok(())
+            }
+        }
+    }
+
+    /// Add every record of `other`. Records that can't be merged are left
+    /// out and returned, as `llvm-profdata merge` warns and goes on.
+    pub fn merge(&mut self, other: Profile) -> Vec<ProfileError> {
+        let mut conflicts = Vec::new();
+        for (name, records) in other.functions {
+            for record in records {
+                if let Err(e) = self.add(&name, record) {
+                    conflicts.push(e);
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// The counts for `name` as compiled with control-flow hash `hash`
+    pub fn get(&self, name: &str, hash: u64) -> Option<&FunctionRecord> {
+        self.functions.get(name)?.iter().find(|record| record.hash == hash)
+    }
+
+    /// Whether `name` has counts for some version of it
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Every (name, record), by name
+    pub fn records(&self) -> impl Iterator<Item = (&str, &FunctionRecord)> + '_ {
+        self.functions
+            .iter()
+            .flat_map(|(name, records)| records.iter().map(move |record| (name.as_str(), record)))
+    }
+
+    pub fn summary(&self) -> ProfileSummary {
+        ProfileSummary::compute(self)
+    }
+
+    /// Read a profile in any of the formats, whichever the file is in
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        let data = fs::read(path).map_err(|e| ProfileError::Io(path.to_path_buf(), e))?;
+        Profile::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ProfileError> {
+        match ProfileFormat::detect(data) {
+            Some(ProfileFormat::Raw) => instrprof::read_raw(data),
+            Some(ProfileFormat::Indexed) => instrprof::read_indexed(data),
+            Some(ProfileFormat::Json) => Profile::from_json(data),
+            None => Err(ProfileError::UnknownFormat),
+        }
+    }
+
+    /// Write the profile to `path` in `format`
+    pub fn save(&self, path: &Path, format: ProfileFormat) -> Result<(), ProfileError> {
+        let data = self.to_bytes(format)?;
+        fs::write(path, data).map_err(|e| ProfileError::Io(path.to_path_buf(), e))
+    }
+
+    pub fn to_bytes(&self, format: ProfileFormat) -> Result<Vec<u8>, ProfileError> {
+        match format {
+            ProfileFormat::Raw => Ok(instrprof::write_raw(self)),
+            ProfileFormat::Indexed => Ok(instrprof::write_indexed(self)),
+            ProfileFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_json(&self) -> Result<Vec<u8>, ProfileError> {
+        let json = JsonProfile {
+            format: JSON_FORMAT.to_string(),
+            version: JSON_VERSION,
+            functions: self
+                .records()
+                .map(|(name, record)| JsonFunction { name: name.to_string(), hash: record.hash, counts: record.counts.clone() })
+                .collect(),
+        };
+        let mut data = serde_json::to_vec(&json).map_err(ProfileError::Json)?;
+        data.push(b'\n');
+        Ok(data)
+    }
+
+    fn from_json(data: &[u8]) -> Result<Self, ProfileError> {
+        let json: JsonProfile = serde_json::from_slice(data).map_err(ProfileError::Json)?;
+        if json.format != JSON_FORMAT || json.version != JSON_VERSION {
+            return Err(ProfileError::UnsupportedVersion(format!("{} version {}", json.format, json.version)));
+        }
+        let mut profile = Profile::new();
+        for function in json.functions {
+            profile.add(&function.name, FunctionRecord { hash: function.hash, counts: function.counts })?;
+        }
+        Ok(profile)
+    }
+}
+
+/// Cutoffs of the detailed summary, in millionths of all counts, as LLVM
+/// uses them to tell hot and cold code apart
+pub const SUMMARY_CUTOFFS: [u32; 16] = [
+    10000, 100000, 200000, 300000, 400000, 500000, 600000, 700000, 800000, 900000, 950000, 990000, 999000, 999900,
+    999990, 999999,
+];
+
+pub const SUMMARY_SCALE: u64 = 1_000_000;
+
+/// The profile summary LLVM keeps with an indexed profile and in a
+/// module's `ProfileSummary` flag
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileSummary {
+    pub total_count: u64,
+    pub max_count: u64,
+    pub max_internal_count: u64,
+    pub max_function_count: u64,
+    pub num_counts: u64,
+    pub num_functions: u64,
+    /// (cutoff, smallest count within it, counts within it)
+    pub detailed: Vec<(u32, u64, u64)>,
+}
+
+impl ProfileSummary {
+    fn compute(profile: &Profile) -> Self {
+        let mut summary = ProfileSummary::default();
+        let mut frequencies: BTreeMap<u64, u64> = BTreeMap::new();
+        for (_, record) in profile.records() {
+            summary.num_functions += 1;
+            summary.max_function_count = summary.max_function_count.max(record.entry_count());
+            for (index, &count) in record.counts.iter().enumerate() {
+                if index > 0 {
+                    summary.max_internal_count = summary.max_internal_count.max(count);
+                }
+                summary.total_count = summary.total_count.saturating_add(count);
+                summary.max_count = summary.max_count.max(count);
+                summary.num_counts += 1;
+                *frequencies.entry(count).or_default() += 1;
+            }
+        }
+
+        // Walk the counts from the largest until each cutoff's share of the
+        // total is covered
+        let mut counts = frequencies.iter().rev().peekable();
+        let (mut sum, mut seen, mut count) = (0u128, 0u64, 0u64);
+        for cutoff in SUMMARY_CUTOFFS {
+            let desired = summary.total_count as u128 * cutoff as u128 / SUMMARY_SCALE as u128;
+            while sum < desired {
+                let Some((&value, &frequency)) = counts.next() else { break };
+                count = value;
+                sum += value as u128 * frequency as u128;
+                seen += frequency;
+            }
+            summary.detailed.push((cutoff, count, seen));
+        }
+        summary
+    }
+}
+
+impl fmt::Display for ProfileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "functions:          {}", self.num_functions)?;
+        writeln!(f, "counters:           {}", self.num_counts)?;
+        writeln!(f, "total count:        {}", self.total_count)?;
+        writeln!(f, "max function count: {}", self.max_function_count)?;
+        write!(f, "max internal count: {}", self.max_internal_count)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(PathBuf, io::Error),
+    /// A profile path an instrumented program can't be given
+    InvalidPath(PathBuf),
+    /// Not a raw, indexed or JSON profile
+    UnknownFormat,
+    /// A format version this build can't read
+    UnsupportedVersion(String),
+    /// Truncated or inconsistent data
+    Malformed(String),
+    Json(serde_json::Error),
+    /// Two records for one function and hash with different numbers of
+    /// counters
+    CounterMismatch { function: String, expected: usize, found: usize },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ProfileError::InvalidPath(path) => write!(f, "invalid profile path: {}", path.display()),
+            ProfileError::UnknownFormat => write!(f, "not a .profraw, .profdata or JSON profile"),
+            ProfileError::UnsupportedVersion(version) => write!(f, "unsupported profile version: {}", version),
+            ProfileError::Malformed(message) => write!(f, "malformed profile: {}", message),
+            ProfileError::Json(e) => write!(f, "JSON profile: {}", e),
+            ProfileError::CounterMismatch { function, expected, found } => {
+                write!(f, "{}: {} counters where an earlier profile has {}", function, found, expected)
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ProfileError> {
+    // Two runs of an instrumented program
+    let mut profile = Profile::load(Path::new("run1.profraw"))?;
+    for conflict in profile.merge(Profile::load(Path::new("run2.profraw"))?) {
+        eprintln!("warning: {}", conflict);
+    }
+    profile.save(Path::new("merged.profdata"), ProfileFormat::Indexed)?;
+    println!("{}", profile.summary());
+    Ok(())
+}
+*/
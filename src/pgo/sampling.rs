@@ -0,0 +1,508 @@
+// src/pgo/sampling.rs
+//! Sampling profiler
+//! Counters added by `--profile-generate` slow the program down unevenly,
+//! so a profile of where the time goes is better taken by sampling: the
+//! instruction pointer is recorded a few thousand times a second and each
+//! sample is charged to the function it falls in.
+//!
+//! Samples come from `perf_event_open` (the CPU clock, user space only,
+//! into a ring buffer a helper thread drains) when the kernel allows it,
+//! which needs `perf_event_paranoid` at 2 or below; otherwise from a
+//! `SIGPROF` timer whose handler reads the interrupted instruction pointer
+//! out of the signal context. perf events sample the thread that called
+//! `start`; the timer samples whichever thread is using CPU.
+//!
+//! Addresses in JIT-compiled code, the anonymous executable mappings, are
+//! named from `JitSymbols`; others by `dladdr`, as the shared library
+//! symbol they fall in. The result feeds `PGOSystem::analyze_samples`, the
+//! same analysis instrumented counts go through.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CStr;
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::Serialize;
+use crate::debug::stack_capture::JitSymbols;
+use super::PGOError;
+
+// perf_event_open(2)
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_CPU_CLOCK: u64 = 0;
+const PERF_SAMPLE_IP: u64 = 1 << 0;
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_SAMPLE: u32 = 9;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+// Bits of `perf_event_attr::flags`
+const ATTR_DISABLED: u64 = 1 << 0;
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_EXCLUDE_HV: u64 = 1 << 6;
+const ATTR_FREQ: u64 = 1 << 10;
+/// Offsets of `data_head` and `data_tail` in `perf_event_mmap_page`
+const DATA_HEAD: usize = 1024;
+const DATA_TAIL: usize = 1032;
+/// Pages of ring buffer after the header page; a power of two
+const RING_PAGES: usize = 64;
+
+/// How often the helper thread empties the perf ring buffer
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// `perf_event_attr` up to `PERF_ATTR_SIZE_VER5`
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_freq: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SamplingConfig {
+    /// Samples per second of CPU time
+    pub frequency: u32,
+    /// Use the `SIGPROF` timer even where perf events are available
+    pub force_timer: bool,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig { frequency: 999, force_timer: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SampleSource {
+    PerfEvents,
+    Timer,
+}
+
+/// Samples charged to one function
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FunctionSamples {
+    pub samples: u64,
+    /// JIT-compiled, as opposed to a shared library's
+    pub jit: bool,
+    /// Samples by offset from the function's start, for its hot spots
+    pub offsets: BTreeMap<u64, u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleProfile {
+    pub source: SampleSource,
+    pub frequency: u32,
+    pub functions: BTreeMap<String, FunctionSamples>,
+    /// Samples outside any known function
+    pub unknown: u64,
+    /// Samples the buffers had no room for
+    pub lost: u64,
+    pub total: u64,
+}
+
+impl SampleProfile {
+    /// Functions by samples, most first
+    pub fn hottest(&self) -> Vec<(&str, &FunctionSamples)> {
+        let mut functions: Vec<_> = self.functions.iter().map(|(name, samples)| (name.as_str(), samples)).collect();
+        functions.sort_by(|a, b| b.1.samples.cmp(&a.1.samples).then(a.0.cmp(b.0)));
+        functions
+    }
+
+    /// Fraction of the samples in `function`
+    pub fn share(&self, function: &str) -> f64 {
+        let samples = self.functions.get(function).map_or(0, |function| function.samples);
+        if self.total == 0 { 0.0 } else { samples as f64 / self.total as f64 }
+    }
+}
+
+impl fmt::Display for SampleProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self.source {
+            SampleSource::PerfEvents => "perf events",
+            SampleSource::Timer => "SIGPROF timer",
+        };
+        writeln!(f, "{} samples at {} Hz ({})", self.total, self.frequency, source)?;
+        for (name, samples) in self.hottest() {
+            let kind = if samples.jit { "" } else { " [native]" };
+            writeln!(f, "  {:>6.2}% {:>8}  {}{}", 100.0 * self.share(name), samples.samples, name, kind)?;
+        }
+        if self.unknown > 0 {
+            writeln!(f, "  {:>6.2}% {:>8}  [unknown]", 100.0 * self.unknown as f64 / self.total as f64, self.unknown)?;
+        }
+        if self.lost > 0 {
+            writeln!(f, "  {} samples lost", self.lost)?;
+        }
+        Ok(())
+    }
+}
+
+/// Raw instruction pointers and how often each was seen
+#[derive(Default)]
+struct Samples {
+    addresses: HashMap<u64, u64>,
+    lost: u64,
+}
+
+enum Collector {
+    Perf { fd: libc::c_int, ring: *mut u8, drainer: Option<JoinHandle<()>> },
+    Timer,
+}
+
+/// A running profile of the process. One at a time: the timer fallback
+/// and `report_at_exit` are process-wide.
+pub struct SamplingProfiler {
+    collector: Collector,
+    config: SamplingConfig,
+    samples: Arc<Mutex<Samples>>,
+    stop: Arc<AtomicBool>,
+}
+
+// The ring is only touched by the drainer until it's joined
+unsafe impl Send for SamplingProfiler {}
+
+impl SamplingProfiler {
+    pub fn start(config: SamplingConfig) -> Result<Self, PGOError> {
+        if config.frequency == 0 {
+            return Err(PGOError::CollectionError("sampling frequency must be at least 1 Hz".to_string()));
+        }
+        let samples = Arc::new(Mutex::new(Samples::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let collector = match (!config.force_timer).then(|| unsafe { open_perf(config.frequency) }).flatten() {
+            Some((fd, ring)) => {
+                let (samples, stop) = (Arc::clone(&samples), Arc::clone(&stop));
+                // Raw pointers aren't Send; the address is, and the ring
+                // outlives the thread
+                let address = ring as usize;
+                let drainer = thread::Builder::new()
+                    .name("sampling-profiler".to_string())
+                    .spawn(move || {
+                        while !stop.load(Ordering::Acquire) {
+                            thread::sleep(DRAIN_INTERVAL);
+                            unsafe { drain(address as *mut u8, &samples) };
+                        }
+                    })
+                    .map_err(|e| PGOError::CollectionError(e.to_string()))?;
+                unsafe { libc::ioctl(fd, PERF_EVENT_IOC_ENABLE, 0) };
+                Collector::Perf { fd, ring, drainer: Some(drainer) }
+            }
+            None => {
+                unsafe { start_timer(config.frequency)? };
+                Collector::Timer
+            }
+        };
+        Ok(SamplingProfiler { collector, config, samples, stop })
+    }
+
+    pub fn source(&self) -> SampleSource {
+        match self.collector {
+            Collector::Perf { .. } => SampleSource::PerfEvents,
+            Collector::Timer => SampleSource::Timer,
+        }
+    }
+
+    /// Stop sampling and charge the samples to functions, naming JIT code
+    /// from `symbols`
+    pub fn finish(mut self, symbols: &JitSymbols) -> SampleProfile {
+        self.stop_collecting();
+        let samples = std::mem::take(&mut *self.samples.lock().unwrap_or_else(|p| p.into_inner()));
+        let jit_code = anonymous_executable_mappings();
+        let mut profile = SampleProfile {
+            source: self.source(),
+            frequency: self.config.frequency,
+            functions: BTreeMap::new(),
+            unknown: 0,
+            lost: samples.lost,
+            total: 0,
+        };
+        for (&address, &count) in &samples.addresses {
+            profile.total += count;
+            let jit = jit_code.iter().any(|&(start, end)| (start..end).contains(&address));
+            let function = if jit {
+                symbols.lookup(address as usize).map(|(name, start)| (name.to_string(), start as u64))
+            } else {
+                native_symbol(address)
+            };
+            match function {
+                Some((name, start)) => {
+                    let entry = profile.functions.entry(name).or_default();
+                    entry.samples += count;
+                    entry.jit = jit;
+                    *entry.offsets.entry(address - start).or_default() += count;
+                }
+                None => profile.unknown += count,
+            }
+        }
+        profile
+    }
+
+    /// Finish and hand the profile to `report` when the process exits
+    /// through `exit()`, or at the latest `flush_at_exit` call. For a
+    /// program run in a child process that may end either way.
+    pub fn report_at_exit(self, symbols: Arc<parking_lot::RwLock<JitSymbols>>, report: impl FnOnce(SampleProfile) + Send + 'static) {
+        *AT_EXIT.lock().unwrap_or_else(|p| p.into_inner()) = Some(Box::new(move || report(self.finish(&symbols.read()))));
+        unsafe { libc::atexit(run_at_exit) };
+    }
+
+    fn stop_collecting(&mut self) {
+        match &mut self.collector {
+            Collector::Perf { fd, ring, drainer } => {
+                if let Some(drainer) = drainer.take() {
+                    unsafe { libc::ioctl(*fd, PERF_EVENT_IOC_DISABLE, 0) };
+                    self.stop.store(true, Ordering::Release);
+                    let _ = drainer.join();
+                    unsafe { drain(*ring, &self.samples) };
+                }
+            }
+            Collector::Timer => unsafe {
+                let off: libc::itimerval = std::mem::zeroed();
+                libc::setitimer(libc::ITIMER_PROF, &off, std::ptr::null_mut());
+                let mut samples = self.samples.lock().unwrap_or_else(|p| p.into_inner());
+                collect_timer_samples(&mut samples);
+            },
+        }
+    }
+}
+
+impl Drop for SamplingProfiler {
+    fn drop(&mut self) {
+        self.stop_collecting();
+        if let Collector::Perf { fd, ring, .. } = self.collector {
+            unsafe {
+                libc::munmap(ring as *mut libc::c_void, (RING_PAGES + 1) * page_size());
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+type ExitReport = Box<dyn FnOnce() + Send>;
+
+static AT_EXIT: Mutex<Option<ExitReport>> = Mutex::new(None);
+
+/// Run the `report_at_exit` report now, if it hasn't run yet
+pub fn flush_at_exit() {
+    let report = AT_EXIT.lock().unwrap_or_else(|p| p.into_inner()).take();
+    if let Some(report) = report {
+        report();
+    }
+}
+
+extern "C" fn run_at_exit() {
+    flush_at_exit();
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// A CPU-clock sampling event on this thread and its ring buffer, or
+/// `None` if the kernel won't give us one
+unsafe fn open_perf(frequency: u32) -> Option<(libc::c_int, *mut u8)> {
+    let attr = PerfEventAttr {
+        kind: PERF_TYPE_SOFTWARE,
+        size: std::mem::size_of::<PerfEventAttr>() as u32,
+        config: PERF_COUNT_SW_CPU_CLOCK,
+        sample_freq: frequency as u64,
+        sample_type: PERF_SAMPLE_IP | PERF_SAMPLE_TID,
+        flags: ATTR_DISABLED | ATTR_EXCLUDE_KERNEL | ATTR_EXCLUDE_HV | ATTR_FREQ,
+        ..PerfEventAttr::default()
+    };
+    let fd = libc::syscall(libc::SYS_perf_event_open, &attr, 0, -1, -1, PERF_FLAG_FD_CLOEXEC) as libc::c_int;
+    if fd < 0 {
+        log::debug!("perf_event_open: {}; sampling with a SIGPROF timer", std::io::Error::last_os_error());
+        return None;
+    }
+    let length = (RING_PAGES + 1) * page_size();
+    let ring = libc::mmap(std::ptr::null_mut(), length, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0);
+    if ring == libc::MAP_FAILED {
+        log::debug!("mapping the perf ring buffer: {}", std::io::Error::last_os_error());
+        libc::close(fd);
+        return None;
+    }
+    Some((fd, ring as *mut u8))
+}
+
+/// Move the records between `data_tail` and `data_head` into `samples`
+unsafe fn drain(ring: *mut u8, samples: &Mutex<Samples>) {
+    let head_pointer = ring.add(DATA_HEAD) as *const AtomicU64;
+    let tail_pointer = ring.add(DATA_TAIL) as *const AtomicU64;
+    let head = (*head_pointer).load(Ordering::Acquire);
+    let mut tail = (*tail_pointer).load(Ordering::Relaxed);
+    let data = ring.add(page_size());
+    let size = (RING_PAGES * page_size()) as u64;
+    // A record can wrap around the end of the buffer
+    let read = |offset: u64, bytes: &mut [u8]| {
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = *data.add(((offset + index as u64) % size) as usize);
+        }
+    };
+    let mut samples = samples.lock().unwrap_or_else(|p| p.into_inner());
+    while tail < head {
+        let mut header = [0u8; 8];
+        read(tail, &mut header);
+        let kind = u32::from_ne_bytes(header[..4].try_into().unwrap());
+        let length = u16::from_ne_bytes(header[6..8].try_into().unwrap()) as u64;
+        if length == 0 {
+            break;
+        }
+        let mut body = [0u8; 16];
+        match kind {
+            PERF_RECORD_SAMPLE => {
+                read(tail + 8, &mut body[..8]);
+                let ip = u64::from_ne_bytes(body[..8].try_into().unwrap());
+                *samples.addresses.entry(ip).or_default() += 1;
+            }
+            PERF_RECORD_LOST => {
+                read(tail + 8, &mut body);
+                samples.lost += u64::from_ne_bytes(body[8..].try_into().unwrap());
+            }
+            _ => {}
+        }
+        tail += length;
+    }
+    fence(Ordering::Release);
+    (*tail_pointer).store(head, Ordering::Relaxed);
+}
+
+// The SIGPROF fallback: the handler appends to a fixed ring that
+// `collect_timer_samples` empties
+const TIMER_SLOTS: usize = 1 << 16;
+static TIMER_RING: [AtomicU64; TIMER_SLOTS] = [const { AtomicU64::new(0) }; TIMER_SLOTS];
+static TIMER_WRITTEN: AtomicUsize = AtomicUsize::new(0);
+static TIMER_READ: AtomicUsize = AtomicUsize::new(0);
+static TIMER_LOST: AtomicU64 = AtomicU64::new(0);
+
+unsafe fn start_timer(frequency: u32) -> Result<(), PGOError> {
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = on_sigprof as *const () as usize;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+    libc::sigemptyset(&mut action.sa_mask);
+    if libc::sigaction(libc::SIGPROF, &action, std::ptr::null_mut()) != 0 {
+        return Err(PGOError::CollectionError(format!("sigaction(SIGPROF): {}", std::io::Error::last_os_error())));
+    }
+    let period = Duration::from_secs(1) / frequency;
+    let interval = libc::timeval { tv_sec: period.as_secs() as libc::time_t, tv_usec: period.subsec_micros().max(1) as libc::suseconds_t };
+    let timer = libc::itimerval { it_interval: interval, it_value: interval };
+    if libc::setitimer(libc::ITIMER_PROF, &timer, std::ptr::null_mut()) != 0 {
+        return Err(PGOError::CollectionError(format!("setitimer(ITIMER_PROF): {}", std::io::Error::last_os_error())));
+    }
+    Ok(())
+}
+
+extern "C" fn on_sigprof(_signal: libc::c_int, _info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    let Some(ip) = (unsafe { interrupted_ip(context) }) else {
+        return;
+    };
+    let slot = TIMER_WRITTEN.fetch_add(1, Ordering::AcqRel);
+    if slot - TIMER_READ.load(Ordering::Acquire) >= TIMER_SLOTS {
+        TIMER_LOST.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    TIMER_RING[slot % TIMER_SLOTS].store(ip, Ordering::Release);
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+unsafe fn interrupted_ip(context: *mut libc::c_void) -> Option<u64> {
+    let context = context as *const libc::ucontext_t;
+    Some((*context).uc_mcontext.gregs[libc::REG_RIP as usize] as u64)
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+unsafe fn interrupted_ip(context: *mut libc::c_void) -> Option<u64> {
+    let context = context as *const libc::ucontext_t;
+    Some((*context).uc_mcontext.pc)
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+unsafe fn interrupted_ip(_context: *mut libc::c_void) -> Option<u64> {
+    None
+}
+
+fn collect_timer_samples(samples: &mut Samples) {
+    let written = TIMER_WRITTEN.load(Ordering::Acquire);
+    let mut read = TIMER_READ.load(Ordering::Acquire);
+    while read < written {
+        // Zero until the handler that took the slot has stored it
+        let ip = TIMER_RING[read % TIMER_SLOTS].swap(0, Ordering::AcqRel);
+        if ip == 0 {
+            break;
+        }
+        *samples.addresses.entry(ip).or_default() += 1;
+        read += 1;
+    }
+    TIMER_READ.store(read, Ordering::Release);
+    samples.lost += TIMER_LOST.swap(0, Ordering::Relaxed);
+}
+
+/// Where JIT-compiled code lives: executable mappings without a file
+fn anonymous_executable_mappings() -> Vec<(u64, u64)> {
+    let maps = fs::read_to_string("/proc/self/maps").unwrap_or_default();
+    maps.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let range = fields.next()?;
+            let permissions = fields.next()?;
+            let path = fields.nth(3);
+            if !permissions.contains('x') || path.is_some_and(|path| !path.is_empty()) {
+                return None;
+            }
+            let (start, end) = range.split_once('-')?;
+            Some((u64::from_str_radix(start, 16).ok()?, u64::from_str_radix(end, 16).ok()?))
+        })
+        .collect()
+}
+
+/// The shared library symbol containing `address`, or the library itself
+fn native_symbol(address: u64) -> Option<(String, u64)> {
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    if unsafe { libc::dladdr(address as *const libc::c_void, &mut info) } == 0 {
+        return None;
+    }
+    if !info.dli_sname.is_null() {
+        let name = unsafe { CStr::from_ptr(info.dli_sname) }.to_string_lossy().into_owned();
+        return Some((name, info.dli_saddr as u64));
+    }
+    if info.dli_fname.is_null() {
+        return None;
+    }
+    let file = unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy().into_owned();
+    let file = file.rsplit('/').next().unwrap_or(&file).to_string();
+    Some((format!("[{}]", file), info.dli_fbase as u64))
+}
+
+// Example usage:
+/*
+fn profile_jit(compiler: &Compiler, main: extern "C" fn(i32, *const *const i8) -> i32) -> Result<(), PGOError> {
+    let profiler = SamplingProfiler::start(SamplingConfig::default())?;
+    main(0, std::ptr::null());
+    let profile = profiler.finish(&compiler.jit_symbols().read());
+    eprint!("{}", profile);
+
+    // Hot functions become inlining candidates, as with counters
+    let mut pgo = PGOSystem::new()?;
+    let plan = pgo.analyze_samples(&ir, &profile)?;
+    pgo.apply_optimizations(&mut ir, &plan)?;
+    Ok(())
+}
+*/
@@ -0,0 +1,359 @@
+// src/pipeline/cache.rs
+//! Persistent compilation cache
+//! Translation units are content-addressed by the SHA-256 of their
+//! preprocessed source, a fingerprint of the compiler options and target,
+//! and the compiler version. A hit hands back the cached object code and DWARF sections, so
+//! the frontend, middle-end and backend are skipped entirely.
+//!
+//! Entries can also hold IR instead (`crate::ir`): bytecode programs for
+//! the interpreter and ThinLTO module summaries. Their keys name the IR
+//! format's major version instead of the compiler version, so they stay
+//! valid across upgrades that keep the format.
+//!
+//! Layout: `<root>/<first two key chars>/<key>/{object.o, debug.json, meta.json}`,
+//! or `{ir.bin, meta.json}` for IR.
+//! Entries are written to a temporary directory and renamed into place, so
+//! concurrent builds never observe a half-written entry. Every store is
+//! followed by a `prune` back under the size limit.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use object::{Object, ObjectSection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use crate::ir;
+
+/// Bump when the entry layout or the meaning of a key changes
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+/// Default size limit before least recently used entries are evicted
+pub const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    hex: String,
+    fingerprint: String,
+}
+
+impl CacheKey {
+    /// `options` is any stable rendering of the options that affect codegen,
+    /// e.g. the `Debug` output of `CompilerOptions`
+    pub fn new(preprocessed_source: &str, options: &str, target_triple: &str) -> Self {
+        let fingerprint = format!(
+            "v{};{};{};{}",
+            CACHE_FORMAT_VERSION,
+            env!("CARGO_PKG_VERSION"),
+            target_triple,
+            options
+        );
+        Self::with_fingerprint(preprocessed_source.as_bytes(), fingerprint)
+    }
+
+    /// Key of the IR made from `input` (source or bitcode) with `options`,
+    /// shared by every release that writes the current IR major version
+    pub fn for_ir(input: &[u8], options: &str, target_triple: &str) -> Self {
+        let fingerprint = format!("v{};ir{};{};{}", CACHE_FORMAT_VERSION, ir::FORMAT_MAJOR, target_triple, options);
+        Self::with_fingerprint(input, fingerprint)
+    }
+
+    fn with_fingerprint(input: &[u8], fingerprint: String) -> Self {
+        // Length-prefixed, so no fingerprint and input pair runs into another
+        let mut hasher = Sha256::new();
+        hasher.update((fingerprint.len() as u64).to_le_bytes());
+        hasher.update(fingerprint.as_bytes());
+        hasher.update(input);
+        let hex = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        CacheKey { hex, fingerprint }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.hex
+    }
+}
+
+/// What a cache entry holds
+#[derive(Debug, Clone)]
+pub struct CachedArtifact {
+    pub object: Vec<u8>,
+    /// `.debug_*` sections, for JIT debugger registration
+    pub debug_sections: Vec<(String, Vec<u8>)>,
+}
+
+impl CachedArtifact {
+    /// Wrap an object file, pulling out its DWARF sections
+    pub fn from_object(object: Vec<u8>) -> Self {
+        let debug_sections = match object::File::parse(&*object) {
+            Ok(file) => file
+                .sections()
+                .filter_map(|section| {
+                    let name = section.name().ok()?;
+                    if !name.starts_with(".debug_") {
+                        return None;
+                    }
+                    Some((name.to_string(), section.data().ok()?.to_vec()))
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        CachedArtifact { object, debug_sections }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EntryMeta {
+    format_version: u32,
+    fingerprint: String,
+    /// Of `object.o`, or of `ir.bin` for IR
+    object_size: u64,
+    created: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub stores: u64,
+    pub evictions: u64,
+}
+
+pub struct CompilationCache {
+    // Location and limits
+    root: PathBuf,
+    max_bytes: u64,
+
+    // Counters for --verbose output
+    stats: CacheStats,
+}
+
+impl CompilationCache {
+    pub fn open(root: &Path) -> Result<Self, CacheError> {
+        fs::create_dir_all(root).map_err(|e| CacheError::Io(root.to_path_buf(), e))?;
+        Ok(CompilationCache {
+            root: root.to_path_buf(),
+            max_bytes: DEFAULT_MAX_BYTES,
+            stats: CacheStats::default(),
+        })
+    }
+
+    /// `$C_INTERPRETER_CACHE_DIR`, else `$XDG_CACHE_HOME/c-interpreter`,
+    /// else `~/.cache/c-interpreter`
+    pub fn default_root() -> PathBuf {
+        if let Some(dir) = std::env::var_os("C_INTERPRETER_CACHE_DIR") {
+            return PathBuf::from(dir);
+        }
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir);
+        base.join("c-interpreter")
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn entry_dir(&self, key: &CacheKey) -> PathBuf {
+        self.root.join(&key.hex[..2]).join(&key.hex)
+    }
+
+    /// Look up a translation unit; any unreadable or mismatched entry is a miss
+    pub fn get(&mut self, key: &CacheKey) -> Option<CachedArtifact> {
+        let dir = self.entry_dir(key);
+        match Self::read_entry(&dir, key) {
+            Some(artifact) => {
+                self.stats.hits += 1;
+                // Refresh the LRU timestamp
+                let _ = fs::File::options()
+                    .append(true)
+                    .open(dir.join("meta.json"))
+                    .and_then(|f| f.set_modified(SystemTime::now()));
+                log::debug!("cache hit {}", key.hex);
+                Some(artifact)
+            }
+            None => {
+                self.stats.misses += 1;
+                log::debug!("cache miss {}", key.hex);
+                None
+            }
+        }
+    }
+
+    fn read_entry(dir: &Path, key: &CacheKey) -> Option<CachedArtifact> {
+        let object = Self::read_payload(dir, key, "object.o")?;
+        let debug_sections = serde_json::from_slice(&fs::read(dir.join("debug.json")).ok()?).ok()?;
+        Some(CachedArtifact { object, debug_sections })
+    }
+
+    /// `file` of the entry at `dir`, if the entry is `key`'s and complete
+    fn read_payload(dir: &Path, key: &CacheKey, file: &str) -> Option<Vec<u8>> {
+        let meta: EntryMeta = serde_json::from_slice(&fs::read(dir.join("meta.json")).ok()?).ok()?;
+        if meta.format_version != CACHE_FORMAT_VERSION || meta.fingerprint != key.fingerprint {
+            return None;
+        }
+
+        let payload = fs::read(dir.join(file)).ok()?;
+        if payload.len() as u64 != meta.object_size {
+            return None;
+        }
+        Some(payload)
+    }
+
+    /// Look up IR stored with `put_ir`. The caller still has to read it,
+    /// which fails for a file from a newer release that needs something
+    /// this one doesn't know; that counts as a miss too.
+    pub fn get_ir(&mut self, key: &CacheKey) -> Option<Vec<u8>> {
+        let dir = self.entry_dir(key);
+        match Self::read_payload(&dir, key, "ir.bin") {
+            Some(ir) => {
+                self.stats.hits += 1;
+                let _ = fs::File::options()
+                    .append(true)
+                    .open(dir.join("meta.json"))
+                    .and_then(|f| f.set_modified(SystemTime::now()));
+                log::debug!("cache hit {} (ir)", key.hex);
+                Some(ir)
+            }
+            None => {
+                self.stats.misses += 1;
+                log::debug!("cache miss {} (ir)", key.hex);
+                None
+            }
+        }
+    }
+
+    /// Store a freshly compiled translation unit
+    pub fn put(&mut self, key: &CacheKey, artifact: &CachedArtifact) -> Result<(), CacheError> {
+        let debug = serde_json::to_vec(&artifact.debug_sections).map_err(|e| CacheError::Io(self.entry_dir(key), e.into()))?;
+        self.store(key, &[("object.o", &artifact.object), ("debug.json", &debug)])
+    }
+
+    /// Store IR written by `crate::ir`, under a key from `CacheKey::for_ir`
+    pub fn put_ir(&mut self, key: &CacheKey, ir: &[u8]) -> Result<(), CacheError> {
+        self.store(key, &[("ir.bin", ir)])
+    }
+
+    /// Write an entry of `files`; the first is the payload `meta.json` sizes
+    fn store(&mut self, key: &CacheKey, files: &[(&str, &[u8])]) -> Result<(), CacheError> {
+        let dir = self.entry_dir(key);
+        let parent = dir.parent().unwrap().to_path_buf();
+        fs::create_dir_all(&parent).map_err(|e| CacheError::Io(parent.clone(), e))?;
+
+        let staging = parent.join(format!(".{}.{}.tmp", key.hex, std::process::id()));
+        let write = || -> io::Result<()> {
+            fs::create_dir_all(&staging)?;
+            for (name, contents) in files {
+                fs::write(staging.join(name), contents)?;
+            }
+            let meta = EntryMeta {
+                format_version: CACHE_FORMAT_VERSION,
+                fingerprint: key.fingerprint.clone(),
+                object_size: files[0].1.len() as u64,
+                created: SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            };
+            fs::write(staging.join("meta.json"), serde_json::to_vec_pretty(&meta)?)?;
+            Ok(())
+        };
+
+        if let Err(e) = write() {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(CacheError::Io(staging, e));
+        }
+
+        // Another process may have stored the same entry first; either copy is fine
+        if fs::rename(&staging, &dir).is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        self.stats.stores += 1;
+
+        // Eviction failing leaves the cache too big, not wrong
+        if let Err(e) = self.prune() {
+            log::debug!("could not prune the compilation cache: {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Evict least recently used entries until the cache fits in `max_bytes`
+    pub fn prune(&mut self) -> Result<u64, CacheError> {
+        let mut entries = Vec::new();
+        let mut total = 0u64;
+
+        let shards = fs::read_dir(&self.root).map_err(|e| CacheError::Io(self.root.clone(), e))?;
+        for shard in shards.flatten() {
+            let Ok(dirs) = fs::read_dir(shard.path()) else { continue };
+            for entry in dirs.flatten() {
+                // Another process's entry, still being written
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let path = entry.path();
+                let Ok(meta) = fs::metadata(path.join("meta.json")) else { continue };
+                let size = dir_size(&path);
+                let used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                total += size;
+                entries.push((used, size, path));
+            }
+        }
+
+        entries.sort_by_key(|(used, _, _)| *used);
+        let mut freed = 0;
+        for (_, size, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_dir_all(&path).is_ok() {
+                total -= size;
+                freed += size;
+                self.stats.evictions += 1;
+            }
+        }
+        Ok(freed)
+    }
+
+    /// Remove every entry
+    pub fn clear(&mut self) -> Result<(), CacheError> {
+        fs::remove_dir_all(&self.root).map_err(|e| CacheError::Io(self.root.clone(), e))?;
+        fs::create_dir_all(&self.root).map_err(|e| CacheError::Io(self.root.clone(), e))
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|e| e.metadata().ok())
+                .map(|m| m.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(PathBuf, io::Error),
+}
+
+// Example usage:
+/*
+fn compile_cached(cache: &mut CompilationCache, source: &str, options: &CompilerOptions) -> Vec<u8> {
+    let key = CacheKey::new(source, &format!("{:?}", options), "x86_64-unknown-linux-gnu");
+    if let Some(hit) = cache.get(&key) {
+        return hit.object;
+    }
+
+    let object = compile_to_object(source, options);
+    cache.put(&key, &CachedArtifact::from_object(object.clone())).ok();
+    object
+}
+*/
@@ -3,6 +3,8 @@ use std::sync::Arc;
 use parking_lot::RwLock;
 use crossbeam_channel::{bounded, Sender, Receiver};
 
+pub mod cache;
+
 pub struct CompilationPipeline {
     // Core components
     memory_manager: Arc<MemoryManager>,
@@ -137,21 +139,22 @@ impl CompilationPipeline {
             context.set_debug_info(debug_info);
         }
         
-        // Allocate executable memory
-        let code_buffer = self.memory_manager.allocate_executable(code.size())?;
+        // Allocate code memory, written and run at different addresses
+        let code_buffer = unsafe { self.memory_manager.allocate_code(code.size())? };
         
-        // Copy code to executable memory
+        // Copy the code in and make it executable
         unsafe {
             std::ptr::copy_nonoverlapping(
                 code.data().as_ptr(),
-                code_buffer,
+                code_buffer.writable,
                 code.size()
             );
+            self.memory_manager.finalize_code(&code_buffer)?;
         }
         
         // Create compiled function
         let function = CompiledFunction {
-            address: code_buffer,
+            address: code_buffer.executable as *mut u8,
             size: code.size(),
             debug_info: context.take_debug_info(),
         };
@@ -0,0 +1,392 @@
+// src/report/mod.rs
+//! Machine-readable compilation reports
+//! Captures options, target, per-function sizes, remarks, diagnostics,
+//! timing, cache statistics and artifact hashes in a versioned JSON schema.
+//!
+//! A run with `--report` starts a session with `begin`. The compiler, the
+//! cache and the diagnostics engine add to it through `timed` and the
+//! `note_*` functions, which do nothing without one, and the report is written at
+//! `finish` or when the process exits, as error paths end in `exit`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::diagnostics::engine::{Diagnostic, Severity};
+
+pub mod provenance;
+
+/// Version of the report schema. Bump whenever a field is renamed or removed;
+/// adding optional fields does not require a bump.
+pub const REPORT_SCHEMA_VERSION: u32 = 2;
+
+/// A complete record of a single compilation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompilationReport {
+    /// Schema version of this report
+    pub schema_version: u32,
+    /// Version of the tool that produced the report
+    pub tool_version: String,
+    /// Execution mode (jit, interpret, compile)
+    pub mode: String,
+    /// Options the compilation was run with
+    pub options: ReportOptions,
+    /// Target the code was generated for
+    pub target: ReportTarget,
+    /// Generated functions, keyed by name for stable diffs
+    pub functions: BTreeMap<String, FunctionReport>,
+    /// Optimization remarks emitted by the middle-end
+    pub remarks: Vec<OptimizationRemark>,
+    /// Diagnostics emitted while compiling
+    pub diagnostics: Vec<ReportDiagnostic>,
+    /// Wall-clock time spent per stage
+    pub timings: Vec<StageTiming>,
+    /// Cache statistics
+    pub cache: CacheStats,
+    /// Produced artifacts and their content hashes
+    pub artifacts: Vec<ArtifactRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportOptions {
+    pub optimization_level: u32,
+    pub include_paths: Vec<String>,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportTarget {
+    pub architecture: String,
+    pub triple: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionReport {
+    /// Size of the generated machine code in bytes
+    pub code_size: usize,
+    /// Number of IR instructions after optimization
+    pub ir_instructions: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptimizationRemark {
+    pub pass: String,
+    pub function: String,
+    pub kind: RemarkKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemarkKind {
+    Applied,
+    Missed,
+    Analysis,
+}
+
+impl RemarkKind {
+    /// The flag that asks for remarks of this kind
+    pub fn flag(self) -> &'static str {
+        match self {
+            RemarkKind::Applied => "-Rpass",
+            RemarkKind::Missed => "-Rpass-missed",
+            RemarkKind::Analysis => "-Rpass-analysis",
+        }
+    }
+}
+
+impl OptimizationRemark {
+    /// As clang prints it: `remark: f: message [-Rpass-missed=vectorize]`
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::remark(format!("{}: {} [{}={}]", self.function, self.message, self.kind.flag(), self.pass))
+    }
+}
+
+/// The remarks to print, by pass name: `-Rpass=`, `-Rpass-missed=` and
+/// `-Rpass-analysis=` each take names separated by `|`. A name ending in
+/// `.*` matches the passes it's a prefix of, so `.*` matches them all.
+/// clang takes a regular expression; these are the ones people write.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemarkFilter {
+    pub applied: Option<String>,
+    pub missed: Option<String>,
+    pub analysis: Option<String>,
+}
+
+impl RemarkFilter {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_none() && self.missed.is_none() && self.analysis.is_none()
+    }
+
+    pub fn matches(&self, remark: &OptimizationRemark) -> bool {
+        let pattern = match remark.kind {
+            RemarkKind::Applied => &self.applied,
+            RemarkKind::Missed => &self.missed,
+            RemarkKind::Analysis => &self.analysis,
+        };
+        pattern.as_deref().is_some_and(|pattern| {
+            pattern.split('|').any(|name| match name.strip_suffix(".*") {
+                Some(prefix) => remark.pass.starts_with(prefix),
+                None => name == remark.pass,
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportDiagnostic {
+    pub severity: String,
+    pub code: Option<String>,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub micros: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactRecord {
+    pub path: String,
+    pub size: u64,
+    /// SHA-256 of the contents, hex encoded
+    pub sha256: String,
+}
+
+impl CompilationReport {
+    pub fn new(mode: &str, options: ReportOptions, target: ReportTarget) -> Self {
+        CompilationReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            mode: mode.to_string(),
+            options,
+            target,
+            functions: BTreeMap::new(),
+            remarks: Vec::new(),
+            diagnostics: Vec::new(),
+            timings: Vec::new(),
+            cache: CacheStats::default(),
+            artifacts: Vec::new(),
+        }
+    }
+
+    /// Record the generated size of a function
+    pub fn record_function(&mut self, name: &str, code_size: usize, ir_instructions: usize) {
+        self.functions.insert(name.to_string(), FunctionReport {
+            code_size,
+            ir_instructions,
+        });
+    }
+
+    pub fn add_remark(&mut self, remark: OptimizationRemark) {
+        self.remarks.push(remark);
+    }
+
+    pub fn add_diagnostic(&mut self, diagnostic: ReportDiagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Record how long a pipeline stage took
+    pub fn record_timing(&mut self, stage: &str, elapsed: Duration) {
+        self.timings.push(StageTiming {
+            stage: stage.to_string(),
+            micros: elapsed.as_micros() as u64,
+        });
+    }
+
+    pub fn record_cache_access(&mut self, hit: bool) {
+        if hit {
+            self.cache.hits += 1;
+        } else {
+            self.cache.misses += 1;
+        }
+    }
+
+    /// Hash a produced artifact and add it to the report
+    pub fn record_artifact(&mut self, path: &Path) -> Result<(), ReportError> {
+        let data = fs::read(path).map_err(ReportError::Io)?;
+
+        self.artifacts.push(ArtifactRecord {
+            path: path.display().to_string(),
+            size: data.len() as u64,
+            sha256: provenance::sha256(&data),
+        });
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, ReportError> {
+        serde_json::to_string_pretty(self).map_err(ReportError::Serialization)
+    }
+
+    /// Write the report to disk
+    pub fn write_to(&self, path: &Path) -> Result<(), ReportError> {
+        let json = self.to_json()?;
+        fs::write(path, json).map_err(ReportError::Io)
+    }
+
+    /// Load a previously written report, rejecting newer schema versions
+    pub fn load(path: &Path) -> Result<Self, ReportError> {
+        let json = fs::read_to_string(path).map_err(ReportError::Io)?;
+        let report: CompilationReport = serde_json::from_str(&json)
+            .map_err(ReportError::Serialization)?;
+
+        if report.schema_version > REPORT_SCHEMA_VERSION {
+            return Err(ReportError::UnsupportedSchema(report.schema_version));
+        }
+
+        Ok(report)
+    }
+}
+
+impl ReportDiagnostic {
+    /// The English text of `diagnostic`, with its code and location
+    pub fn from_diagnostic(diagnostic: &Diagnostic) -> Self {
+        let severity = match diagnostic.severity {
+            Severity::Remark => "remark",
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Fatal => "fatal",
+        };
+        ReportDiagnostic {
+            severity: severity.to_string(),
+            code: diagnostic.code.map(str::to_string),
+            message: diagnostic.message.clone(),
+            file: diagnostic.location.as_ref().map(|location| location.file.clone()),
+            line: diagnostic.location.as_ref().map(|location| location.line),
+            column: diagnostic.location.as_ref().map(|location| location.column),
+        }
+    }
+}
+
+struct Session {
+    /// A forked child inherits the session and must not write it
+    pid: u32,
+    path: PathBuf,
+    report: CompilationReport,
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+static AT_EXIT: Once = Once::new();
+
+/// Collect this run's report, to be written to `path`
+pub fn begin(path: &Path, report: CompilationReport) {
+    *SESSION.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some(Session { pid: std::process::id(), path: path.to_path_buf(), report });
+
+    // Failed compilations end in `process::exit`; their report says why
+    AT_EXIT.call_once(|| unsafe {
+        libc::atexit(finish_at_exit);
+    });
+}
+
+/// Whether a report is being collected, for work only it needs
+pub fn is_active() -> bool {
+    SESSION.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Change the report being collected, if there is one
+pub fn update(change: impl FnOnce(&mut CompilationReport)) {
+    if let Some(session) = SESSION.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        change(&mut session.report);
+    }
+}
+
+pub fn note_function(name: &str, code_size: usize, ir_instructions: usize) {
+    update(|report| report.record_function(name, code_size, ir_instructions));
+}
+
+pub fn note_remark(remark: &OptimizationRemark) {
+    update(|report| report.add_remark(remark.clone()));
+}
+
+pub fn note_diagnostic(diagnostic: &Diagnostic) {
+    update(|report| report.add_diagnostic(ReportDiagnostic::from_diagnostic(diagnostic)));
+}
+
+pub fn note_cache_access(hit: bool) {
+    update(|report| report.record_cache_access(hit));
+}
+
+/// Run one pipeline stage, recording how long it took
+pub fn timed<T>(stage: &str, run: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = run();
+    let elapsed = start.elapsed();
+    update(|report| report.record_timing(stage, elapsed));
+    result
+}
+
+/// Write the report and end the session
+pub fn finish() -> Result<(), ReportError> {
+    let Some(session) = SESSION.lock().unwrap_or_else(|e| e.into_inner()).take() else { return Ok(()) };
+    if session.pid != std::process::id() {
+        return Ok(());
+    }
+    session.report.write_to(&session.path)
+}
+
+extern "C" fn finish_at_exit() {
+    if let Err(e) = finish() {
+        eprintln!("Error: failed to write the compilation report: {:?}", e);
+    }
+}
+
+/// A cheap hash for checksums that have to fit a word, like a profile's CFG
+/// hash; report artifacts and cache keys use `provenance::sha256`.
+pub fn fnv1a_64(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[derive(Debug)]
+pub enum ReportError {
+    Io(std::io::Error),
+    Serialization(serde_json::Error),
+    UnsupportedSchema(u32),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ReportError> {
+    let mut report = CompilationReport::new(
+        "compile",
+        ReportOptions {
+            optimization_level: 2,
+            include_paths: vec![],
+            source: Some("hello.c".to_string()),
+        },
+        ReportTarget {
+            architecture: "x86_64".to_string(),
+            triple: "x86_64-unknown-linux-gnu".to_string(),
+        },
+    );
+
+    let start = Instant::now();
+    // ... parse ...
+    report.record_timing("parse", start.elapsed());
+    report.record_function("main", 42, 12);
+    report.record_artifact(Path::new("a.out"))?;
+
+    report.write_to(Path::new("out.json"))?;
+    Ok(())
+}
+*/
@@ -0,0 +1,363 @@
+// src/report/provenance.rs
+//! Artifact provenance
+//! With `--embed-provenance`, each object records how it was made: the
+//! tool and its version, the target, a digest of the options that shape
+//! the code, and SHA-256 digests of the source as given, of the
+//! preprocessed translation unit (which covers every header it included)
+//! and of the `--profile-use` profile. The record goes in a note section
+//! that linking carries into the executable, one note per translation
+//! unit. `c-interpreter verify-provenance` reads the notes back, checks
+//! them against the files at hand and can print them as an in-toto
+//! statement with a SLSA provenance predicate, for attestations.
+//!
+//! Each note is laid out as an ELF note in every object format: `namesz`,
+//! `descsz` and `type` as little-endian words, the name `c-interpreter`,
+//! then the JSON record, both padded to 4 bytes. The section is
+//! `.note.c-interpreter.provenance` on ELF, `__DATA,__c_provenance` on
+//! Mach-O and `.cprov` on COFF, and the note is listed in `llvm.used`, so
+//! neither the optimizer nor `--gc-sections` drops it. Paths are kept as
+//! given rather than made absolute, so the same build from another
+//! checkout embeds the same bytes.
+
+use std::fmt;
+#[cfg(feature = "llvm")]
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::LLVMLinkage;
+use object::{Object, ObjectSection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest as _, Sha256};
+
+/// Version of the record. Bump whenever a field is renamed or removed;
+/// adding optional fields does not require a bump.
+pub const PROVENANCE_SCHEMA_VERSION: u32 = 1;
+
+/// Owner name of the notes
+pub const NOTE_NAME: &str = "c-interpreter";
+/// Note type of a provenance record
+pub const NT_PROVENANCE: u32 = 1;
+
+pub const ELF_SECTION: &str = ".note.c-interpreter.provenance";
+/// In the `__DATA` segment
+pub const MACHO_SECTION: &str = "__c_provenance";
+pub const COFF_SECTION: &str = ".cprov";
+
+/// `predicate.buildDefinition.buildType` of the attestation
+pub const BUILD_TYPE: &str = "https://github.com/TheMapleseed/Interpreter-C/provenance/v1";
+
+/// How one translation unit was compiled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub schema_version: u32,
+    pub tool: String,
+    pub tool_version: String,
+    /// Target triple
+    pub target: String,
+    /// SHA-256 of `CompilerOptions::codegen_fingerprint`
+    pub options_sha256: String,
+    pub source: FileDigest,
+    /// SHA-256 of the unit after preprocessing
+    pub preprocessed_sha256: String,
+    /// `--profile-use`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<FileDigest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDigest {
+    /// As given on the command line
+    pub path: String,
+    pub sha256: String,
+}
+
+impl FileDigest {
+    pub fn of_file(path: &Path) -> Result<Self, ProvenanceError> {
+        let data = fs::read(path).map_err(|e| ProvenanceError::Io(path.to_path_buf(), e))?;
+        Ok(FileDigest { path: path.display().to_string(), sha256: sha256(&data) })
+    }
+}
+
+impl Provenance {
+    /// The record for `input`, compiled for `target` with the options
+    /// whose fingerprint is `fingerprint`
+    pub fn collect(
+        input: &Path,
+        preprocessed: &str,
+        fingerprint: &str,
+        target: &str,
+        profile: Option<&Path>,
+    ) -> Result<Self, ProvenanceError> {
+        Ok(Provenance {
+            schema_version: PROVENANCE_SCHEMA_VERSION,
+            tool: NOTE_NAME.to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            target: target.to_string(),
+            options_sha256: sha256(fingerprint.as_bytes()),
+            source: FileDigest::of_file(input)?,
+            preprocessed_sha256: sha256(preprocessed.as_bytes()),
+            profile: profile.map(FileDigest::of_file).transpose()?,
+        })
+    }
+
+    /// The record as a note
+    pub fn to_note(&self) -> Result<Vec<u8>, ProvenanceError> {
+        let desc = serde_json::to_vec(self).map_err(ProvenanceError::Serialization)?;
+        let mut note = Vec::with_capacity(12 + 16 + desc.len() + 3);
+        note.extend_from_slice(&(NOTE_NAME.len() as u32 + 1).to_le_bytes());
+        note.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        note.extend_from_slice(&NT_PROVENANCE.to_le_bytes());
+        note.extend_from_slice(NOTE_NAME.as_bytes());
+        note.push(0);
+        pad(&mut note);
+        note.extend_from_slice(&desc);
+        pad(&mut note);
+        Ok(note)
+    }
+}
+
+fn pad(bytes: &mut Vec<u8>) {
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+}
+
+/// Lowercase hex SHA-256 of `data`
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The section a note goes in for an object of `triple`, as LLVM spells it
+pub fn section_for(triple: &str) -> String {
+    if triple.contains("apple") || triple.contains("darwin") {
+        format!("__DATA,{}", MACHO_SECTION)
+    } else if triple.contains("windows") {
+        COFF_SECTION.to_string()
+    } else {
+        ELF_SECTION.to_string()
+    }
+}
+
+/// Add `note` to `module` in the provenance section, listed in `llvm.used`
+#[cfg(feature = "llvm")]
+pub unsafe fn embed(module: LLVMModuleRef, triple: &str, note: &[u8]) {
+    let context = LLVMGetModuleContext(module);
+    let data = LLVMConstStringInContext(context, note.as_ptr() as *const _, note.len() as u32, 1);
+    let global = LLVMAddGlobal(module, LLVMTypeOf(data), c"__c_interpreter_provenance".as_ptr());
+    LLVMSetInitializer(global, data);
+    LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+    LLVMSetGlobalConstant(global, 1);
+    LLVMSetAlignment(global, 4);
+    let section = CString::new(section_for(triple)).unwrap();
+    LLVMSetSection(global, section.as_ptr());
+
+    // llvm.used can't be extended in place: rebuild it with the note added
+    let mut used = Vec::new();
+    let existing = LLVMGetNamedGlobal(module, c"llvm.used".as_ptr());
+    if !existing.is_null() {
+        let list = LLVMGetInitializer(existing);
+        if !list.is_null() {
+            for index in 0..LLVMGetNumOperands(list) {
+                used.push(LLVMGetOperand(list, index as u32));
+            }
+        }
+        LLVMDeleteGlobal(existing);
+    }
+    used.push(global);
+    let list = LLVMConstArray2(LLVMPointerTypeInContext(context, 0), used.as_mut_ptr(), used.len() as u64);
+    let global = LLVMAddGlobal(module, LLVMTypeOf(list), c"llvm.used".as_ptr());
+    LLVMSetInitializer(global, list);
+    LLVMSetLinkage(global, LLVMLinkage::LLVMAppendingLinkage);
+    LLVMSetSection(global, c"llvm.metadata".as_ptr());
+}
+
+/// The records in the provenance notes of `data`, in section order
+pub fn parse_notes(data: &[u8]) -> Result<Vec<Provenance>, ProvenanceError> {
+    let word = |at: usize| data.get(at..at + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize);
+    let mut records = Vec::new();
+    let mut at = 0;
+    while at < data.len() {
+        // Linkers may pad between the notes of different objects
+        if word(at) == Some(0) {
+            at += 4;
+            continue;
+        }
+        let (Some(namesz), Some(descsz), Some(kind)) = (word(at), word(at + 4), word(at + 8)) else {
+            return Err(ProvenanceError::Malformed(format!("truncated note header at offset {}", at)));
+        };
+        let name_at = at + 12;
+        let desc_at = name_at + namesz.next_multiple_of(4);
+        let end = desc_at + descsz.next_multiple_of(4);
+        let (Some(name), Some(desc)) = (data.get(name_at..name_at + namesz), data.get(desc_at..desc_at + descsz)) else {
+            return Err(ProvenanceError::Malformed(format!("note at offset {} runs past the section", at)));
+        };
+        if name.strip_suffix(&[0]) == Some(NOTE_NAME.as_bytes()) && kind as u32 == NT_PROVENANCE {
+            records.push(serde_json::from_slice(desc).map_err(ProvenanceError::Serialization)?);
+        }
+        at = end;
+    }
+    Ok(records)
+}
+
+/// The records embedded in an object or executable
+pub fn read_artifact(data: &[u8]) -> Result<Vec<Provenance>, ProvenanceError> {
+    let file = object::File::parse(data).map_err(|e| ProvenanceError::Parse(e.to_string()))?;
+    let mut records = Vec::new();
+    for section in file.sections() {
+        let name = section.name().unwrap_or("");
+        if name == ELF_SECTION || name == MACHO_SECTION || name == COFF_SECTION {
+            let data = section.data().map_err(|e| ProvenanceError::Parse(e.to_string()))?;
+            records.extend(parse_notes(data)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Something an artifact's records and the files at hand disagree on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// (path, recorded, now)
+    SourceChanged(String, String, String),
+    /// A source given to check that no unit was compiled from
+    SourceNotBuilt(String),
+    /// (unit source, recorded, now)
+    ProfileChanged(String, String, String),
+    /// A unit built without the profile given to check
+    NoProfile(String),
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::SourceChanged(path, recorded, now) => {
+                write!(f, "{} changed since the build (recorded {}, now {})", path, recorded, now)
+            }
+            Finding::SourceNotBuilt(path) => write!(f, "{} is not a source of the artifact", path),
+            Finding::ProfileChanged(source, recorded, now) => {
+                write!(f, "{} was built with another profile (recorded {}, now {})", source, recorded, now)
+            }
+            Finding::NoProfile(source) => write!(f, "{} was built without a profile", source),
+        }
+    }
+}
+
+/// Check `records` against the current contents of `sources` and
+/// `profile`. A source matches the unit recorded under the same path, or
+/// failing that under the same file name.
+pub fn verify(records: &[Provenance], sources: &[PathBuf], profile: Option<&Path>) -> Result<Vec<Finding>, ProvenanceError> {
+    let mut findings = Vec::new();
+    for source in sources {
+        let current = FileDigest::of_file(source)?;
+        let name = source.file_name();
+        let unit = records
+            .iter()
+            .find(|record| record.source.path == current.path)
+            .or_else(|| records.iter().find(|record| Path::new(&record.source.path).file_name() == name));
+        match unit {
+            Some(unit) if unit.source.sha256 != current.sha256 => findings.push(Finding::SourceChanged(
+                current.path,
+                unit.source.sha256.clone(),
+                current.sha256,
+            )),
+            Some(_) => {}
+            None => findings.push(Finding::SourceNotBuilt(current.path)),
+        }
+    }
+    if let Some(profile) = profile {
+        let current = FileDigest::of_file(profile)?;
+        for record in records {
+            match &record.profile {
+                Some(recorded) if recorded.sha256 != current.sha256 => findings.push(Finding::ProfileChanged(
+                    record.source.path.clone(),
+                    recorded.sha256.clone(),
+                    current.sha256.clone(),
+                )),
+                Some(_) => {}
+                None => findings.push(Finding::NoProfile(record.source.path.clone())),
+            }
+        }
+    }
+    Ok(findings)
+}
+
+/// An in-toto statement with a SLSA provenance predicate for the artifact
+/// `name` with contents `data`
+pub fn attestation(name: &str, data: &[u8], records: &[Provenance]) -> serde_json::Value {
+    let mut dependencies: Vec<&FileDigest> = Vec::new();
+    for digest in records.iter().flat_map(|record| std::iter::once(&record.source).chain(&record.profile)) {
+        if !dependencies.contains(&digest) {
+            dependencies.push(digest);
+        }
+    }
+    let units: Vec<_> = records
+        .iter()
+        .map(|record| {
+            json!({
+                "source": record.source.path,
+                "target": record.target,
+                "options_sha256": record.options_sha256,
+                "preprocessed_sha256": record.preprocessed_sha256,
+            })
+        })
+        .collect();
+    let version = records.first().map_or(env!("CARGO_PKG_VERSION"), |record| record.tool_version.as_str());
+
+    json!({
+        "_type": "https://in-toto.io/Statement/v1",
+        "subject": [{ "name": name, "digest": { "sha256": sha256(data) } }],
+        "predicateType": "https://slsa.dev/provenance/v1",
+        "predicate": {
+            "buildDefinition": {
+                "buildType": BUILD_TYPE,
+                "externalParameters": { "units": units },
+                "resolvedDependencies": dependencies
+                    .iter()
+                    .map(|digest| json!({ "name": digest.path, "digest": { "sha256": digest.sha256 } }))
+                    .collect::<Vec<_>>(),
+            },
+            "runDetails": {
+                "builder": { "id": format!("{}@{}", NOTE_NAME, version) },
+            },
+        },
+    })
+}
+
+#[derive(Debug)]
+pub enum ProvenanceError {
+    Io(PathBuf, io::Error),
+    Serialization(serde_json::Error),
+    /// Not an object file or executable
+    Parse(String),
+    Malformed(String),
+}
+
+impl fmt::Display for ProvenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvenanceError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ProvenanceError::Serialization(e) => write!(f, "provenance record: {}", e),
+            ProvenanceError::Parse(message) => write!(f, "not an object file: {}", message),
+            ProvenanceError::Malformed(message) => write!(f, "malformed provenance note: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ProvenanceError {}
+
+// Example usage:
+/*
+fn audit(executable: &Path, sources: &[PathBuf]) -> Result<bool, ProvenanceError> {
+    let data = fs::read(executable).map_err(|e| ProvenanceError::Io(executable.to_path_buf(), e))?;
+    let records = read_artifact(&data)?;
+    for finding in verify(&records, sources, None)? {
+        eprintln!("error: {}", finding);
+    }
+    println!("{}", attestation("a.out", &data, &records));
+    Ok(!records.is_empty())
+}
+*/
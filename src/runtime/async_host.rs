@@ -0,0 +1,229 @@
+// src/runtime/async_host.rs
+//! Async embedding API for Tokio hosts
+//! Guest programs run as Tokio tasks. Whenever a guest makes a blocking call
+//! (read, write, sleep, accept) it suspends and hands the request to the
+//! driver, which awaits it on the runtime instead of parking an OS thread.
+//! Thousands of C scripts can then share a handful of worker threads.
+//!
+//! A guest is anything implementing `ResumableProgram`. The bytecode VM
+//! does as `interpreter::bytecode::BytecodeGuest`; the tree walker runs a
+//! program to completion on the calling thread and can't be a guest.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use super::RuntimeError;
+
+/// A blocking operation requested by a suspended guest
+#[derive(Debug, Clone)]
+pub enum GuestSyscall {
+    Read { fd: i32, len: usize },
+    Write { fd: i32, data: Vec<u8> },
+    Sleep(Duration),
+    Accept { fd: i32 },
+    Close { fd: i32 },
+}
+
+/// Value delivered back to the guest when it resumes
+#[derive(Debug, Clone)]
+pub enum SyscallReply {
+    /// Bytes read (empty on EOF)
+    Data(Vec<u8>),
+    /// Non-negative return value (bytes written, new fd, 0)
+    Value(i64),
+    /// errno value
+    Errno(i32),
+}
+
+/// Outcome of running a guest until it can make no more progress
+#[derive(Debug)]
+pub enum StepResult {
+    /// Instruction budget exhausted; the guest can continue immediately
+    Yield,
+    /// Guest is waiting for a syscall to complete
+    Blocked(GuestSyscall),
+    /// Guest finished with an exit status
+    Exited(i32),
+}
+
+/// A program that can be suspended at syscall boundaries, supplied by the
+/// embedder. `step` must not block: the driver calls it on a Tokio worker.
+pub trait ResumableProgram: Send + 'static {
+    /// Run until the budget is spent, a blocking call is made, or exit
+    fn step(&mut self, budget: u64) -> Result<StepResult, RuntimeError>;
+
+    /// Deliver the result of the syscall the program last blocked on
+    fn resume_with(&mut self, reply: SyscallReply);
+}
+
+/// Host objects backing guest file descriptors
+enum HostResource {
+    Stdin,
+    Stdout,
+    Stderr,
+    Listener(TcpListener),
+    Stream(TcpStream),
+}
+
+pub struct AsyncEngineConfig {
+    /// Instructions executed between cooperative yields
+    pub step_budget: u64,
+    /// Upper bound on concurrently running guests
+    pub max_concurrent: usize,
+}
+
+impl Default for AsyncEngineConfig {
+    fn default() -> Self {
+        AsyncEngineConfig {
+            step_budget: 10_000,
+            max_concurrent: 4096,
+        }
+    }
+}
+
+pub struct AsyncEngine {
+    // Engine configuration
+    config: Arc<AsyncEngineConfig>,
+
+    // Admission control
+    permits: Arc<Semaphore>,
+}
+
+impl AsyncEngine {
+    pub fn new(config: AsyncEngineConfig) -> Self {
+        let permits = Arc::new(Semaphore::new(config.max_concurrent));
+        AsyncEngine {
+            config: Arc::new(config),
+            permits,
+        }
+    }
+
+    /// Spawn a guest program as a Tokio task.
+    ///
+    /// `listeners` pre-opens host sockets for the guest, keyed by the guest
+    /// fd they should appear as.
+    pub fn spawn<P: ResumableProgram>(
+        &self,
+        program: P,
+        listeners: Vec<(i32, TcpListener)>,
+    ) -> JoinHandle<Result<i32, RuntimeError>> {
+        let config = self.config.clone();
+        let permits = self.permits.clone();
+
+        tokio::spawn(async move {
+            let _permit = permits
+                .acquire_owned()
+                .await
+                .map_err(|_| RuntimeError::ExceptionError("engine shut down".to_string()))?;
+
+            let mut fds: HashMap<i32, HostResource> = HashMap::new();
+            fds.insert(0, HostResource::Stdin);
+            fds.insert(1, HostResource::Stdout);
+            fds.insert(2, HostResource::Stderr);
+            for (fd, listener) in listeners {
+                fds.insert(fd, HostResource::Listener(listener));
+            }
+
+            drive(program, &config, &mut fds).await
+        })
+    }
+}
+
+async fn drive<P: ResumableProgram>(
+    mut program: P,
+    config: &AsyncEngineConfig,
+    fds: &mut HashMap<i32, HostResource>,
+) -> Result<i32, RuntimeError> {
+    loop {
+        match program.step(config.step_budget)? {
+            StepResult::Yield => tokio::task::yield_now().await,
+            StepResult::Blocked(call) => {
+                let reply = perform(call, fds).await;
+                program.resume_with(reply);
+            }
+            StepResult::Exited(status) => return Ok(status),
+        }
+    }
+}
+
+async fn perform(call: GuestSyscall, fds: &mut HashMap<i32, HostResource>) -> SyscallReply {
+    match call {
+        GuestSyscall::Sleep(duration) => {
+            tokio::time::sleep(duration).await;
+            SyscallReply::Value(0)
+        }
+        GuestSyscall::Read { fd, len } => {
+            let mut buf = vec![0u8; len];
+            let result = match fds.get_mut(&fd) {
+                Some(HostResource::Stdin) => tokio::io::stdin().read(&mut buf).await,
+                Some(HostResource::Stream(stream)) => stream.read(&mut buf).await,
+                Some(_) => return SyscallReply::Errno(libc::EINVAL),
+                None => return SyscallReply::Errno(libc::EBADF),
+            };
+            match result {
+                Ok(n) => {
+                    buf.truncate(n);
+                    SyscallReply::Data(buf)
+                }
+                Err(e) => SyscallReply::Errno(e.raw_os_error().unwrap_or(libc::EIO)),
+            }
+        }
+        GuestSyscall::Write { fd, data } => {
+            let result = match fds.get_mut(&fd) {
+                Some(HostResource::Stdout) => tokio::io::stdout().write_all(&data).await,
+                Some(HostResource::Stderr) => tokio::io::stderr().write_all(&data).await,
+                Some(HostResource::Stream(stream)) => stream.write_all(&data).await,
+                Some(_) => return SyscallReply::Errno(libc::EINVAL),
+                None => return SyscallReply::Errno(libc::EBADF),
+            };
+            match result {
+                Ok(()) => SyscallReply::Value(data.len() as i64),
+                Err(e) => SyscallReply::Errno(e.raw_os_error().unwrap_or(libc::EIO)),
+            }
+        }
+        GuestSyscall::Accept { fd } => {
+            let accepted = match fds.get(&fd) {
+                Some(HostResource::Listener(listener)) => listener.accept().await,
+                Some(_) => return SyscallReply::Errno(libc::ENOTSOCK),
+                None => return SyscallReply::Errno(libc::EBADF),
+            };
+            match accepted {
+                Ok((stream, _)) => {
+                    let new_fd = (3..).find(|candidate| !fds.contains_key(candidate)).unwrap();
+                    fds.insert(new_fd, HostResource::Stream(stream));
+                    SyscallReply::Value(new_fd as i64)
+                }
+                Err(e) => SyscallReply::Errno(e.raw_os_error().unwrap_or(libc::EIO)),
+            }
+        }
+        GuestSyscall::Close { fd } => match fds.remove(&fd) {
+            Some(_) => SyscallReply::Value(0),
+            None => SyscallReply::Errno(libc::EBADF),
+        },
+    }
+}
+
+// Example usage:
+/*
+#[tokio::main]
+async fn main() -> Result<(), RuntimeError> {
+    let engine = AsyncEngine::new(AsyncEngineConfig::default());
+
+    let mut handles = Vec::new();
+    for script in scripts {
+        let guest = BytecodeGuest::new(Arc::new(compile(&script)?), CRuntimeEnvironment::new()?, &[])?;
+        handles.push(engine.spawn(guest, vec![]));
+    }
+
+    for handle in handles {
+        let status = handle.await.unwrap()?;
+        println!("script exited with {}", status);
+    }
+
+    Ok(())
+}
+*/
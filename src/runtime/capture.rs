@@ -0,0 +1,1163 @@
+// src/runtime/capture.rs
+//! Input capture and replay (`--capture-io`, `--replay-io`)
+//! A bug report for an interactive program is only as good as the
+//! description of what was typed, which files were around and what the
+//! server answered. A capture run writes all of that to a bundle as the
+//! program consumes it; a replay run serves the program from the bundle
+//! alone, with no terminal, file or network behind it, so whoever gets the
+//! bundle sees the same run.
+//!
+//! - stdin is read through a pipe a helper thread fills from the real one,
+//!   recording each chunk; on replay the pipe is filled from the bundle.
+//!   `isatty(0)` answers what it did in the captured run.
+//! - A regular file is captured whole when the program opens it (`open`,
+//!   `openat`, `fopen`) and replayed from an in-memory copy, so seeking
+//!   and `fstat` work and writes go nowhere. Opens are matched by path and
+//!   order; a failed open fails the same way on replay. Devices and FIFOs
+//!   are captured like stdin.
+//! - A socket's connection (`connect`, `accept`) and the result of every
+//!   `read`, `recv` and `recvfrom` on it are captured, with the bytes, and
+//!   replayed call for call; sends report what they reported. On replay
+//!   the socket is a local stand-in, so `poll` and `select` always find
+//!   it ready.
+//!
+//! Both work on JIT-compiled code, through host functions that wrap the C
+//! library calls. Name lookups (`getaddrinfo`) aren't captured and still
+//! go to the resolver; a socket duplicated with `dup` is no longer
+//! recognized. The bundle is written and flushed record by record, so a
+//! run that crashes still leaves everything it read.
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use serde::{Deserialize, Serialize};
+use crate::interpreter::record::source_hash;
+use crate::jit::host::{HostExport, HostFunction, HostSignature};
+use crate::jit::JITType;
+
+const MAGIC: &[u8; 8] = b"CICAPT\0\0";
+const VERSION: u32 = 1;
+
+// Record tags
+const TAG_STREAM: u8 = 1;
+const TAG_CONTENTS: u8 = 2;
+const TAG_RECEIVE: u8 = 3;
+const TAG_SEND: u8 = 4;
+
+/// Header flag: stdin was a terminal
+const FLAG_STDIN_TTY: u8 = 1;
+
+/// The stream stdin's chunks belong to
+const STDIN_STREAM: u32 = 0;
+
+/// Largest read the helper threads make
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureMode {
+    /// Write what the program reads to a bundle
+    Record(PathBuf),
+    /// Serve the program from a bundle
+    Replay(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Stdin,
+    /// A regular file, captured whole at open
+    File,
+    /// A device or FIFO, captured as it's read
+    Device,
+    /// A socket the program connected
+    Connection,
+    /// A connection the program accepted
+    Accepted,
+}
+
+impl StreamKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            StreamKind::Stdin => 0,
+            StreamKind::File => 1,
+            StreamKind::Device => 2,
+            StreamKind::Connection => 3,
+            StreamKind::Accepted => 4,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0 => StreamKind::Stdin,
+            1 => StreamKind::File,
+            2 => StreamKind::Device,
+            3 => StreamKind::Connection,
+            4 => StreamKind::Accepted,
+            _ => return None,
+        })
+    }
+}
+
+/// One `read`, `recv` or `recvfrom`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receive {
+    pub result: i64,
+    pub errno: i32,
+    pub bytes: Vec<u8>,
+    /// The sender's `struct sockaddr`, for `recvfrom`
+    pub address: Vec<u8>,
+}
+
+/// Something the program read from, in the order it was opened
+#[derive(Debug, Clone)]
+pub struct Stream {
+    pub id: u32,
+    pub kind: StreamKind,
+    /// The path as the program gave it, or the peer address
+    pub name: String,
+    /// The peer's `struct sockaddr`, for accepted connections
+    pub address: Vec<u8>,
+    /// Of the open, connect or accept; 0 if it succeeded
+    pub errno: i32,
+    /// A file's contents when it was opened
+    pub contents: Vec<u8>,
+    pub receives: Vec<Receive>,
+    /// Results of the sends, with their errno
+    pub sends: Vec<(i64, i32)>,
+}
+
+impl Stream {
+    /// Everything received, for streams that are replayed through a pipe
+    fn received(&self) -> Vec<u8> {
+        self.receives.iter().flat_map(|receive| receive.bytes.iter().copied()).collect()
+    }
+}
+
+/// A parsed bundle
+#[derive(Debug, Clone)]
+pub struct Capture {
+    /// Of the captured program, to warn when a different one replays it
+    pub source_hash: u64,
+    pub stdin_tty: bool,
+    pub streams: Vec<Stream>,
+}
+
+impl Capture {
+    pub fn load(path: &Path) -> Result<Self, CaptureError> {
+        let data = fs::read(path).map_err(CaptureError::Io)?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, CaptureError> {
+        let mut reader = Reader { data, offset: 0 };
+        if reader.bytes(MAGIC.len()) != Some(&MAGIC[..]) {
+            return Err(CaptureError::NotACapture);
+        }
+        match reader.u32() {
+            Some(VERSION) => {}
+            Some(version) => return Err(CaptureError::UnsupportedVersion(version)),
+            None => return Err(CaptureError::NotACapture),
+        }
+        let flags = reader.u8().ok_or(CaptureError::NotACapture)?;
+        let source_hash = reader.u64().ok_or(CaptureError::NotACapture)?;
+
+        let mut capture = Capture { source_hash, stdin_tty: flags & FLAG_STDIN_TTY != 0, streams: Vec::new() };
+        let mut index: HashMap<u32, usize> = HashMap::new();
+        while reader.offset < data.len() {
+            // A partial record at the end means the program died mid-write
+            let start = reader.offset;
+            if reader.record(&mut capture, &mut index)?.is_none() {
+                log::warn!("capture is truncated after {} bytes", start);
+                break;
+            }
+        }
+        Ok(capture)
+    }
+}
+
+impl fmt::Display for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stream in &self.streams {
+            let kind = match stream.kind {
+                StreamKind::Stdin if self.stdin_tty => "stdin (terminal)",
+                StreamKind::Stdin => "stdin",
+                StreamKind::File => "file",
+                StreamKind::Device => "device",
+                StreamKind::Connection => "connect",
+                StreamKind::Accepted => "accept",
+            };
+            write!(f, "#{:<3} {:<16} {}", stream.id, kind, stream.name)?;
+            if stream.errno != 0 {
+                writeln!(f, ": {}", io::Error::from_raw_os_error(stream.errno))?;
+                continue;
+            }
+            let received: usize = stream.receives.iter().map(|receive| receive.bytes.len()).sum();
+            writeln!(f, ": {} bytes, {} bytes received", stream.contents.len(), received)?;
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.u32().map(|value| value as i32)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8).map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    /// u32 length, then the bytes
+    fn blob(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        self.bytes(len).map(<[u8]>::to_vec)
+    }
+
+    /// One record; `None` if it's cut short
+    fn record(&mut self, capture: &mut Capture, index: &mut HashMap<u32, usize>) -> Result<Option<()>, CaptureError> {
+        let Some(tag) = self.u8() else { return Ok(None) };
+        if tag == TAG_STREAM {
+            let (Some(id), Some(kind), Some(errno), Some(name), Some(address)) =
+                (self.u32(), self.u8(), self.i32(), self.blob(), self.blob())
+            else {
+                return Ok(None);
+            };
+            let kind = StreamKind::from_byte(kind)
+                .ok_or_else(|| CaptureError::Corrupt(format!("unknown stream kind {}", kind)))?;
+            index.insert(id, capture.streams.len());
+            capture.streams.push(Stream {
+                id,
+                kind,
+                name: String::from_utf8_lossy(&name).into_owned(),
+                address,
+                errno,
+                contents: Vec::new(),
+                receives: Vec::new(),
+                sends: Vec::new(),
+            });
+            return Ok(Some(()));
+        }
+
+        let Some(id) = self.u32() else { return Ok(None) };
+        let stream = match index.get(&id) {
+            Some(&stream) => &mut capture.streams[stream],
+            None => return Err(CaptureError::Corrupt(format!("record for undeclared stream #{}", id))),
+        };
+        match tag {
+            TAG_CONTENTS => {
+                let Some(len) = self.u64() else { return Ok(None) };
+                let Some(contents) = usize::try_from(len).ok().and_then(|len| self.bytes(len)) else { return Ok(None) };
+                stream.contents = contents.to_vec();
+            }
+            TAG_RECEIVE => {
+                let (Some(result), Some(errno), Some(bytes), Some(address)) =
+                    (self.u64(), self.i32(), self.blob(), self.blob())
+                else {
+                    return Ok(None);
+                };
+                stream.receives.push(Receive { result: result as i64, errno, bytes, address });
+            }
+            TAG_SEND => {
+                let (Some(result), Some(errno)) = (self.u64(), self.i32()) else { return Ok(None) };
+                stream.sends.push((result as i64, errno));
+            }
+            _ => return Err(CaptureError::Corrupt(format!("unknown record tag {}", tag))),
+        }
+        Ok(Some(()))
+    }
+}
+
+/// Appends records to the bundle, flushing each. After the first write
+/// error nothing more is written; the program runs on regardless.
+struct BundleWriter {
+    out: BufWriter<File>,
+    error: Option<io::Error>,
+}
+
+impl BundleWriter {
+    fn create(path: &Path, source: &str, stdin_tty: bool) -> Result<Self, CaptureError> {
+        let file = File::create(path).map_err(CaptureError::Io)?;
+        let mut writer = BundleWriter { out: BufWriter::new(file), error: None };
+        let flags = if stdin_tty { FLAG_STDIN_TTY } else { 0 };
+        writer.emit(|out| {
+            out.write_all(MAGIC)?;
+            out.write_all(&VERSION.to_le_bytes())?;
+            out.write_all(&[flags])?;
+            out.write_all(&source_hash(source).to_le_bytes())
+        });
+        writer.error.take().map_or(Ok(writer), |e| Err(CaptureError::Io(e)))
+    }
+
+    fn stream(&mut self, id: u32, kind: StreamKind, errno: i32, name: &str, address: &[u8]) {
+        self.emit(|out| {
+            out.write_all(&[TAG_STREAM])?;
+            out.write_all(&id.to_le_bytes())?;
+            out.write_all(&[kind.to_byte()])?;
+            out.write_all(&errno.to_le_bytes())?;
+            write_blob(out, name.as_bytes())?;
+            write_blob(out, address)
+        });
+    }
+
+    fn contents(&mut self, id: u32, contents: &[u8]) {
+        self.emit(|out| {
+            out.write_all(&[TAG_CONTENTS])?;
+            out.write_all(&id.to_le_bytes())?;
+            out.write_all(&(contents.len() as u64).to_le_bytes())?;
+            out.write_all(contents)
+        });
+    }
+
+    fn receive(&mut self, id: u32, result: i64, errno: i32, bytes: &[u8], address: &[u8]) {
+        self.emit(|out| {
+            out.write_all(&[TAG_RECEIVE])?;
+            out.write_all(&id.to_le_bytes())?;
+            out.write_all(&result.to_le_bytes())?;
+            out.write_all(&errno.to_le_bytes())?;
+            write_blob(out, bytes)?;
+            write_blob(out, address)
+        });
+    }
+
+    fn send(&mut self, id: u32, result: i64, errno: i32) {
+        self.emit(|out| {
+            out.write_all(&[TAG_SEND])?;
+            out.write_all(&id.to_le_bytes())?;
+            out.write_all(&result.to_le_bytes())?;
+            out.write_all(&errno.to_le_bytes())
+        });
+    }
+
+    fn emit(&mut self, record: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) {
+        if self.error.is_none() {
+            if let Err(e) = record(&mut self.out).and_then(|()| self.out.flush()) {
+                log::warn!("capture stopped: {}", e);
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+fn write_blob(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+/// A capture run
+struct Recorder {
+    bundle: Mutex<BundleWriter>,
+    next_stream: AtomicU32,
+    stdin_tty: bool,
+    // Socket descriptors, and their stream once connected
+    sockets: Mutex<HashMap<c_int, Option<u32>>>,
+}
+
+/// A replay run
+struct Player {
+    stdin_tty: bool,
+    state: Mutex<PlayerState>,
+}
+
+#[derive(Default)]
+struct PlayerState {
+    /// Opens not yet replayed, by path
+    files: HashMap<String, VecDeque<Stream>>,
+    connections: VecDeque<Stream>,
+    accepted: VecDeque<Stream>,
+    /// Stand-in sockets: the descriptor the program has, the other end of
+    /// its pair, and the connection it's playing once there is one
+    sockets: HashMap<c_int, FakeSocket>,
+}
+
+struct FakeSocket {
+    peer: c_int,
+    stream: Option<Stream>,
+}
+
+enum Session {
+    Recording(Recorder),
+    Replaying(Player),
+}
+
+static SESSION: OnceLock<Session> = OnceLock::new();
+
+/// Start capturing or replaying in the process that runs the program,
+/// before it runs; `source` is the program's. Connects stdin to the
+/// capture. Once per process.
+pub fn start(mode: &CaptureMode, source: &str) -> Result<(), CaptureError> {
+    if SESSION.get().is_some() {
+        return Err(CaptureError::AlreadyStarted);
+    }
+    let session = match mode {
+        CaptureMode::Record(path) => {
+            let stdin_tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+            let mut bundle = BundleWriter::create(path, source, stdin_tty)?;
+            bundle.stream(STDIN_STREAM, StreamKind::Stdin, 0, "stdin", &[]);
+            Session::Recording(Recorder {
+                bundle: Mutex::new(bundle),
+                next_stream: AtomicU32::new(STDIN_STREAM + 1),
+                stdin_tty,
+                sockets: Mutex::new(HashMap::new()),
+            })
+        }
+        CaptureMode::Replay(path) => {
+            let capture = Capture::load(path)?;
+            if capture.source_hash != source_hash(source) {
+                log::warn!("{} was captured from a different version of the program", path.display());
+            }
+            let mut state = PlayerState::default();
+            let mut stdin = Vec::new();
+            for stream in capture.streams {
+                match stream.kind {
+                    StreamKind::Stdin => stdin = stream.received(),
+                    StreamKind::File | StreamKind::Device => {
+                        state.files.entry(stream.name.clone()).or_default().push_back(stream)
+                    }
+                    StreamKind::Connection => state.connections.push_back(stream),
+                    StreamKind::Accepted => state.accepted.push_back(stream),
+                }
+            }
+            let pipe = feed(stdin).map_err(CaptureError::Io)?;
+            replace_fd(pipe, libc::STDIN_FILENO).map_err(CaptureError::Io)?;
+            Session::Replaying(Player { stdin_tty: capture.stdin_tty, state: Mutex::new(state) })
+        }
+    };
+    let _ = SESSION.set(session);
+
+    if let Some(Session::Recording(recorder)) = SESSION.get() {
+        let real = unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 0) };
+        if real < 0 {
+            return Err(CaptureError::Io(io::Error::last_os_error()));
+        }
+        let pipe = recorder.tee(real, STDIN_STREAM).map_err(CaptureError::Io)?;
+        replace_fd(pipe, libc::STDIN_FILENO).map_err(CaptureError::Io)?;
+    }
+    Ok(())
+}
+
+impl Recorder {
+    fn new_stream(&self, kind: StreamKind, errno: i32, name: &str, address: &[u8]) -> u32 {
+        let id = self.next_stream.fetch_add(1, Ordering::Relaxed);
+        self.bundle().stream(id, kind, errno, name, address);
+        id
+    }
+
+    fn bundle(&self) -> std::sync::MutexGuard<'_, BundleWriter> {
+        self.bundle.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// A pipe a helper thread fills from `real`, recording what it reads
+    /// as `stream`'s; the read end is returned
+    fn tee(&'static self, real: c_int, stream: u32) -> io::Result<c_int> {
+        let (read_end, write_end) = pipe()?;
+        thread::Builder::new().name("capture-tee".to_string()).spawn(move || {
+            block_signals();
+            let mut buffer = vec![0u8; CHUNK_SIZE];
+            loop {
+                let n = unsafe { libc::read(real, buffer.as_mut_ptr() as *mut c_void, buffer.len()) };
+                let errno = if n < 0 { errno() } else { 0 };
+                if n < 0 && errno == libc::EINTR {
+                    continue;
+                }
+                let bytes = &buffer[..n.max(0) as usize];
+                self.bundle().receive(stream, n as i64, errno, bytes, &[]);
+                if n <= 0 || write_all(write_end, bytes).is_err() {
+                    break;
+                }
+            }
+            unsafe {
+                libc::close(write_end);
+                libc::close(real);
+            }
+        })?;
+        Ok(read_end)
+    }
+
+    /// After `fd` was opened as `path`: take a copy of a regular file, or
+    /// put a device behind a tee
+    fn opened(&'static self, path: &str, flags: c_int, fd: c_int) {
+        if fd < 0 {
+            self.new_stream(StreamKind::File, errno(), path, &[]);
+            return;
+        }
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+            return;
+        }
+        let readable = flags & libc::O_ACCMODE != libc::O_WRONLY;
+        match stat.st_mode & libc::S_IFMT {
+            libc::S_IFREG => {
+                let id = self.new_stream(StreamKind::File, 0, path, &[]);
+                if readable {
+                    match read_whole(fd) {
+                        Ok(contents) => self.bundle().contents(id, &contents),
+                        Err(e) => log::warn!("capture: cannot copy {}: {}", path, e),
+                    }
+                }
+            }
+            libc::S_IFCHR | libc::S_IFIFO if readable => {
+                let id = self.new_stream(StreamKind::Device, 0, path, &[]);
+                let tee = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+                match (tee >= 0).then(|| self.tee(tee, id)) {
+                    Some(Ok(pipe)) => {
+                        if let Err(e) = replace_fd(pipe, fd) {
+                            log::warn!("capture: cannot capture {}: {}", path, e);
+                        }
+                    }
+                    _ => log::warn!("capture: cannot capture {}: {}", path, io::Error::last_os_error()),
+                }
+            }
+            // Directories, and devices only written to
+            _ => {}
+        }
+    }
+
+    fn connected(&self, fd: c_int, kind: StreamKind, errno: i32, address: &[u8]) -> Option<u32> {
+        let id = self.new_stream(kind, errno, &describe_address(address), address);
+        (errno == 0 || errno == libc::EINPROGRESS).then(|| {
+            self.sockets().insert(fd, Some(id));
+            id
+        })
+    }
+
+    fn sockets(&self) -> std::sync::MutexGuard<'_, HashMap<c_int, Option<u32>>> {
+        self.sockets.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn stream_of(&self, fd: c_int) -> Option<u32> {
+        self.sockets().get(&fd).copied().flatten()
+    }
+}
+
+impl Player {
+    fn state(&self) -> std::sync::MutexGuard<'_, PlayerState> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// The descriptor standing in for the next open of `path`
+    fn open(&self, path: &str, flags: c_int) -> c_int {
+        let stream = self.state().files.get_mut(path).and_then(VecDeque::pop_front);
+        let Some(stream) = stream else {
+            log::warn!("replay: {} wasn't opened in the captured run", path);
+            return fail(libc::ENOENT);
+        };
+        if stream.errno != 0 {
+            return fail(stream.errno);
+        }
+        let fd = match stream.kind {
+            StreamKind::Device => feed(stream.received()),
+            _ => memory_file(path, &stream.contents, flags),
+        };
+        match fd {
+            Ok(fd) => {
+                if flags & libc::O_CLOEXEC == 0 {
+                    unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+                }
+                fd
+            }
+            Err(e) => fail(e.raw_os_error().unwrap_or(libc::EIO)),
+        }
+    }
+
+    /// A local socket pair for the program to hold in place of a real one
+    fn socket(&self) -> c_int {
+        let mut pair = [0; 2];
+        if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0, pair.as_mut_ptr()) } != 0 {
+            return -1;
+        }
+        self.state().sockets.insert(pair[0], FakeSocket { peer: pair[1], stream: None });
+        pair[0]
+    }
+
+    fn is_socket(&self, fd: c_int) -> bool {
+        self.state().sockets.contains_key(&fd)
+    }
+
+    fn connect(&self, fd: c_int, address: &[u8]) -> c_int {
+        let mut state = self.state();
+        let Some(stream) = state.connections.pop_front() else {
+            log::warn!("replay: connection to {} wasn't made in the captured run", describe_address(address));
+            return fail(libc::ECONNREFUSED);
+        };
+        if stream.name != describe_address(address) {
+            log::warn!("replay: connecting to {}, captured a connection to {}", describe_address(address), stream.name);
+        }
+        let errno = stream.errno;
+        if let Some(socket) = state.sockets.get_mut(&fd) {
+            socket.stream = Some(stream);
+        }
+        if errno == 0 { 0 } else { fail(errno) }
+    }
+
+    unsafe fn accept(&self, address: *mut libc::sockaddr, length: *mut libc::socklen_t) -> c_int {
+        let Some(stream) = self.state().accepted.pop_front() else {
+            log::warn!("replay: no more connections were accepted in the captured run");
+            return fail(libc::EAGAIN);
+        };
+        if stream.errno != 0 {
+            return fail(stream.errno);
+        }
+        copy_address(&stream.address, address, length);
+        let fd = self.socket();
+        if let Some(socket) = self.state().sockets.get_mut(&fd) {
+            socket.stream = Some(stream);
+        }
+        fd
+    }
+
+    /// The next receive captured on `fd`, or end of file
+    unsafe fn receive(
+        &self,
+        fd: c_int,
+        buffer: *mut c_void,
+        capacity: usize,
+        address: *mut libc::sockaddr,
+        length: *mut libc::socklen_t,
+    ) -> i64 {
+        let mut state = self.state();
+        let Some(receive) = state.sockets.get_mut(&fd).and_then(|socket| socket.stream.as_mut()).and_then(|stream| {
+            (!stream.receives.is_empty()).then(|| stream.receives.remove(0))
+        }) else {
+            return 0;
+        };
+        if receive.result < 0 {
+            return fail(receive.errno) as i64;
+        }
+        // A smaller buffer than the captured run had: the rest comes next time
+        let len = receive.bytes.len().min(capacity);
+        std::ptr::copy_nonoverlapping(receive.bytes.as_ptr(), buffer as *mut u8, len);
+        copy_address(&receive.address, address, length);
+        if len < receive.bytes.len() {
+            let rest = Receive { result: (receive.bytes.len() - len) as i64, bytes: receive.bytes[len..].to_vec(), ..receive };
+            if let Some(stream) = state.sockets.get_mut(&fd).and_then(|socket| socket.stream.as_mut()) {
+                stream.receives.insert(0, rest);
+            }
+        }
+        len as i64
+    }
+
+    fn send(&self, fd: c_int, len: usize) -> i64 {
+        let mut state = self.state();
+        let sent = state.sockets.get_mut(&fd).and_then(|socket| socket.stream.as_mut()).and_then(|stream| {
+            (!stream.sends.is_empty()).then(|| stream.sends.remove(0))
+        });
+        match sent {
+            Some((result, errno)) if result < 0 => fail(errno) as i64,
+            Some((result, _)) => result,
+            None => len as i64,
+        }
+    }
+
+    fn close(&self, fd: c_int) {
+        if let Some(socket) = self.state().sockets.remove(&fd) {
+            unsafe { libc::close(socket.peer) };
+        }
+    }
+}
+
+fn session() -> Option<&'static Session> {
+    SESSION.get()
+}
+
+fn errno() -> c_int {
+    io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/// Set errno and return -1
+fn fail(errno: c_int) -> c_int {
+    set_errno(errno);
+    -1
+}
+
+fn set_errno(errno: c_int) {
+    unsafe { *libc::__errno_location() = errno };
+}
+
+fn block_signals() {
+    unsafe {
+        let mut all: libc::sigset_t = std::mem::zeroed();
+        libc::sigfillset(&mut all);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &all, std::ptr::null_mut());
+    }
+}
+
+fn pipe() -> io::Result<(c_int, c_int)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+fn write_all(fd: c_int, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        let n = unsafe { libc::write(fd, bytes.as_ptr() as *const c_void, bytes.len()) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        bytes = &bytes[n as usize..];
+    }
+    Ok(())
+}
+
+/// A pipe a helper thread fills with `data`; the read end is returned. The
+/// thread's signals are blocked, so a reader that goes away doesn't raise
+/// `SIGPIPE` in the program.
+fn feed(data: Vec<u8>) -> io::Result<c_int> {
+    let (read_end, write_end) = pipe()?;
+    thread::Builder::new().name("capture-feed".to_string()).spawn(move || {
+        block_signals();
+        let _ = write_all(write_end, &data);
+        unsafe { libc::close(write_end) };
+    })?;
+    Ok(read_end)
+}
+
+/// Move `from` to descriptor `to`, keeping `to`'s close-on-exec flag
+fn replace_fd(from: c_int, to: c_int) -> io::Result<()> {
+    let cloexec = unsafe { libc::fcntl(to, libc::F_GETFD) } & libc::FD_CLOEXEC;
+    let result = unsafe { libc::dup3(from, to, if cloexec != 0 { libc::O_CLOEXEC } else { 0 }) };
+    unsafe { libc::close(from) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A regular file's contents, without moving its offset
+fn read_whole(fd: c_int) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = unsafe { libc::pread(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len(), contents.len() as libc::off_t) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if n == 0 {
+            return Ok(contents);
+        }
+        contents.extend_from_slice(&buffer[..n as usize]);
+    }
+}
+
+/// An in-memory file holding `contents`, positioned as an open with
+/// `flags` would leave it
+fn memory_file(path: &str, contents: &[u8], flags: c_int) -> io::Result<c_int> {
+    let name = std::ffi::CString::new(path.rsplit('/').next().unwrap_or(path).replace('\0', "")).unwrap_or_default();
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let loaded = if flags & libc::O_TRUNC != 0 { Ok(()) } else { write_all(fd, contents) };
+    if let Err(e) = loaded {
+        unsafe { libc::close(fd) };
+        return Err(e);
+    }
+    let position = if flags & libc::O_APPEND != 0 { libc::SEEK_END } else { libc::SEEK_SET };
+    unsafe { libc::lseek(fd, 0, position) };
+    Ok(fd)
+}
+
+/// `struct sockaddr` bytes at `address`
+unsafe fn address_bytes(address: *const libc::sockaddr, length: libc::socklen_t) -> Vec<u8> {
+    if address.is_null() {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(address as *const u8, length as usize).to_vec()
+}
+
+unsafe fn copy_address(bytes: &[u8], address: *mut libc::sockaddr, length: *mut libc::socklen_t) {
+    if address.is_null() || length.is_null() {
+        return;
+    }
+    let len = bytes.len().min(*length as usize);
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, len);
+    *length = bytes.len() as libc::socklen_t;
+}
+
+/// `host:port` for IP addresses, the path for Unix sockets
+fn describe_address(bytes: &[u8]) -> String {
+    let family = match bytes.get(..2) {
+        Some(family) => u16::from_ne_bytes(family.try_into().unwrap()) as c_int,
+        None => return "unknown".to_string(),
+    };
+    let port = |bytes: &[u8]| bytes.get(2..4).map_or(0, |port| u16::from_be_bytes(port.try_into().unwrap()));
+    match family {
+        libc::AF_INET if bytes.len() >= 8 => {
+            let ip = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+            format!("{}:{}", ip, port(bytes))
+        }
+        libc::AF_INET6 if bytes.len() >= 24 => {
+            let ip: [u8; 16] = bytes[8..24].try_into().unwrap();
+            format!("[{}]:{}", Ipv6Addr::from(ip), port(bytes))
+        }
+        libc::AF_UNIX => {
+            let path = &bytes[2..];
+            let end = path.iter().position(|&byte| byte == 0).unwrap_or(path.len());
+            format!("unix:{}", String::from_utf8_lossy(&path[..end]))
+        }
+        family => format!("family {}", family),
+    }
+}
+
+unsafe fn path_of(path: *const c_char) -> String {
+    if path.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(path).to_string_lossy().into_owned()
+}
+
+/// `fopen`'s mode as `open` flags
+fn fopen_flags(mode: &str) -> c_int {
+    let access = if mode.contains('+') { libc::O_RDWR } else if mode.starts_with('r') { libc::O_RDONLY } else { libc::O_WRONLY };
+    let create = match mode.chars().next() {
+        Some('w') => libc::O_CREAT | libc::O_TRUNC,
+        Some('a') => libc::O_CREAT | libc::O_APPEND,
+        _ => 0,
+    };
+    let cloexec = if mode.contains('e') { libc::O_CLOEXEC } else { 0 };
+    access | create | cloexec
+}
+
+/// Host functions wrapping the C library's I/O for `start`'s session. They
+/// pass straight through in a process where `start` wasn't called.
+pub fn host_overrides() -> Vec<HostExport> {
+    let pointer = || JITType::Pointer(Box::new(JITType::Void));
+    let export = |name: &'static str, signature: HostSignature, function: *const ()| HostExport {
+        name,
+        signature,
+        function: HostFunction::Pointer(function as *const c_void),
+    };
+    let i32 = || JITType::Int32;
+    let i64 = || JITType::Int64;
+    vec![
+        export("open", HostSignature::variadic(vec![pointer(), i32()], i32()), guest_open as *const ()),
+        export("openat", HostSignature::variadic(vec![i32(), pointer(), i32()], i32()), guest_openat as *const ()),
+        export("fopen", HostSignature::new(vec![pointer(), pointer()], pointer()), guest_fopen as *const ()),
+        export("isatty", HostSignature::new(vec![i32()], i32()), guest_isatty as *const ()),
+        export("close", HostSignature::new(vec![i32()], i32()), guest_close as *const ()),
+        export("read", HostSignature::new(vec![i32(), pointer(), i64()], i64()), guest_read as *const ()),
+        export("recv", HostSignature::new(vec![i32(), pointer(), i64(), i32()], i64()), guest_recv as *const ()),
+        export("recvfrom", HostSignature::new(vec![i32(), pointer(), i64(), i32(), pointer(), pointer()], i64()), guest_recvfrom as *const ()),
+        export("write", HostSignature::new(vec![i32(), pointer(), i64()], i64()), guest_write as *const ()),
+        export("send", HostSignature::new(vec![i32(), pointer(), i64(), i32()], i64()), guest_send as *const ()),
+        export("sendto", HostSignature::new(vec![i32(), pointer(), i64(), i32(), pointer(), i32()], i64()), guest_sendto as *const ()),
+        export("socket", HostSignature::new(vec![i32(), i32(), i32()], i32()), guest_socket as *const ()),
+        export("connect", HostSignature::new(vec![i32(), pointer(), i32()], i32()), guest_connect as *const ()),
+        export("accept", HostSignature::new(vec![i32(), pointer(), pointer()], i32()), guest_accept as *const ()),
+        export("accept4", HostSignature::new(vec![i32(), pointer(), pointer(), i32()], i32()), guest_accept4 as *const ()),
+        export("bind", HostSignature::new(vec![i32(), pointer(), i32()], i32()), guest_bind as *const ()),
+        export("listen", HostSignature::new(vec![i32(), i32()], i32()), guest_listen as *const ()),
+        export("setsockopt", HostSignature::new(vec![i32(), i32(), i32(), pointer(), i32()], i32()), guest_setsockopt as *const ()),
+    ]
+}
+
+/// `open` with `path` made by `live`, or replayed
+unsafe fn open_file(path: *const c_char, flags: c_int, live: impl FnOnce() -> c_int) -> c_int {
+    match session() {
+        None => live(),
+        Some(Session::Recording(recorder)) => {
+            let fd = live();
+            let saved = errno();
+            recorder.opened(&path_of(path), flags, fd);
+            set_errno(saved);
+            fd
+        }
+        Some(Session::Replaying(player)) => player.open(&path_of(path), flags),
+    }
+}
+
+// The mode is only passed with O_CREAT or O_TMPFILE, and only read then
+extern "C" fn guest_open(path: *const c_char, flags: c_int, mode: c_uint) -> c_int {
+    unsafe { open_file(path, flags, || libc::open(path, flags, mode)) }
+}
+
+extern "C" fn guest_openat(dir: c_int, path: *const c_char, flags: c_int, mode: c_uint) -> c_int {
+    unsafe { open_file(path, flags, || libc::openat(dir, path, flags, mode)) }
+}
+
+extern "C" fn guest_fopen(path: *const c_char, mode: *const c_char) -> *mut libc::FILE {
+    unsafe {
+        let flags = fopen_flags(&path_of(mode));
+        match session() {
+            Some(Session::Replaying(player)) => {
+                let fd = player.open(&path_of(path), flags);
+                if fd < 0 {
+                    return std::ptr::null_mut();
+                }
+                let file = libc::fdopen(fd, mode);
+                if file.is_null() {
+                    libc::close(fd);
+                }
+                file
+            }
+            _ => {
+                let file = libc::fopen(path, mode);
+                let fd = if file.is_null() { -1 } else { libc::fileno(file) };
+                open_file(path, flags, || fd);
+                file
+            }
+        }
+    }
+}
+
+extern "C" fn guest_isatty(fd: c_int) -> c_int {
+    match session() {
+        Some(Session::Recording(Recorder { stdin_tty, .. })) | Some(Session::Replaying(Player { stdin_tty, .. }))
+            if fd == libc::STDIN_FILENO =>
+        {
+            if *stdin_tty {
+                return 1;
+            }
+            set_errno(libc::ENOTTY);
+            0
+        }
+        _ => unsafe { libc::isatty(fd) },
+    }
+}
+
+extern "C" fn guest_close(fd: c_int) -> c_int {
+    match session() {
+        Some(Session::Recording(recorder)) => {
+            recorder.sockets().remove(&fd);
+        }
+        Some(Session::Replaying(player)) => player.close(fd),
+        None => {}
+    }
+    unsafe { libc::close(fd) }
+}
+
+extern "C" fn guest_read(fd: c_int, buffer: *mut c_void, len: usize) -> isize {
+    guest_recvfrom(fd, buffer, len, 0, std::ptr::null_mut(), std::ptr::null_mut())
+}
+
+extern "C" fn guest_recv(fd: c_int, buffer: *mut c_void, len: usize, flags: c_int) -> isize {
+    guest_recvfrom(fd, buffer, len, flags, std::ptr::null_mut(), std::ptr::null_mut())
+}
+
+/// `read` and `recv` too: on a socket they're all the same receive
+extern "C" fn guest_recvfrom(
+    fd: c_int,
+    buffer: *mut c_void,
+    len: usize,
+    flags: c_int,
+    address: *mut libc::sockaddr,
+    length: *mut libc::socklen_t,
+) -> isize {
+    unsafe {
+        match session() {
+            Some(Session::Recording(recorder)) => match recorder.stream_of(fd) {
+                Some(stream) => {
+                    let n = libc::recvfrom(fd, buffer, len, flags, address, length);
+                    let errno = if n < 0 { errno() } else { 0 };
+                    let bytes = if n > 0 { std::slice::from_raw_parts(buffer as *const u8, n as usize) } else { &[] };
+                    let from = if n >= 0 && !length.is_null() { address_bytes(address, *length) } else { Vec::new() };
+                    recorder.bundle().receive(stream, n as i64, errno, bytes, &from);
+                    set_errno(errno);
+                    n
+                }
+                None if address.is_null() && flags == 0 => libc::read(fd, buffer, len),
+                None => libc::recvfrom(fd, buffer, len, flags, address, length),
+            },
+            Some(Session::Replaying(player)) if player.is_socket(fd) => {
+                player.receive(fd, buffer, len, address, length) as isize
+            }
+            _ if address.is_null() && flags == 0 => libc::read(fd, buffer, len),
+            _ => libc::recvfrom(fd, buffer, len, flags, address, length),
+        }
+    }
+}
+
+extern "C" fn guest_write(fd: c_int, buffer: *const c_void, len: usize) -> isize {
+    guest_sendto(fd, buffer, len, 0, std::ptr::null(), 0)
+}
+
+extern "C" fn guest_send(fd: c_int, buffer: *const c_void, len: usize, flags: c_int) -> isize {
+    guest_sendto(fd, buffer, len, flags, std::ptr::null(), 0)
+}
+
+extern "C" fn guest_sendto(
+    fd: c_int,
+    buffer: *const c_void,
+    len: usize,
+    flags: c_int,
+    address: *const libc::sockaddr,
+    length: libc::socklen_t,
+) -> isize {
+    unsafe {
+        let live = || {
+            if address.is_null() && flags == 0 {
+                libc::write(fd, buffer, len)
+            } else {
+                libc::sendto(fd, buffer, len, flags, address, length)
+            }
+        };
+        match session() {
+            Some(Session::Recording(recorder)) => {
+                let n = live();
+                if let Some(stream) = recorder.stream_of(fd) {
+                    let errno = if n < 0 { errno() } else { 0 };
+                    recorder.bundle().send(stream, n as i64, errno);
+                    set_errno(errno);
+                }
+                n
+            }
+            Some(Session::Replaying(player)) if player.is_socket(fd) => player.send(fd, len) as isize,
+            _ => live(),
+        }
+    }
+}
+
+extern "C" fn guest_socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int {
+    match session() {
+        Some(Session::Replaying(player)) => player.socket(),
+        Some(Session::Recording(recorder)) => {
+            let fd = unsafe { libc::socket(domain, kind, protocol) };
+            if fd >= 0 {
+                recorder.sockets().insert(fd, None);
+            }
+            fd
+        }
+        None => unsafe { libc::socket(domain, kind, protocol) },
+    }
+}
+
+extern "C" fn guest_connect(fd: c_int, address: *const libc::sockaddr, length: libc::socklen_t) -> c_int {
+    unsafe {
+        let bytes = address_bytes(address, length);
+        match session() {
+            Some(Session::Replaying(player)) if player.is_socket(fd) => player.connect(fd, &bytes),
+            Some(Session::Recording(recorder)) => {
+                let result = libc::connect(fd, address, length);
+                let errno = if result < 0 { errno() } else { 0 };
+                recorder.connected(fd, StreamKind::Connection, errno, &bytes);
+                set_errno(errno);
+                result
+            }
+            _ => libc::connect(fd, address, length),
+        }
+    }
+}
+
+extern "C" fn guest_accept(fd: c_int, address: *mut libc::sockaddr, length: *mut libc::socklen_t) -> c_int {
+    guest_accept4(fd, address, length, 0)
+}
+
+extern "C" fn guest_accept4(fd: c_int, address: *mut libc::sockaddr, length: *mut libc::socklen_t, flags: c_int) -> c_int {
+    unsafe {
+        match session() {
+            Some(Session::Replaying(player)) if player.is_socket(fd) => player.accept(address, length),
+            Some(Session::Recording(recorder)) => {
+                // The peer is recorded whether or not the program asks for it
+                let mut peer: libc::sockaddr_storage = std::mem::zeroed();
+                let mut peer_length = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                let accepted = libc::accept4(fd, &mut peer as *mut _ as *mut libc::sockaddr, &mut peer_length, flags);
+                let errno = if accepted < 0 { errno() } else { 0 };
+                // A non-blocking accept with no one waiting isn't a connection
+                if errno != libc::EAGAIN && errno != libc::EINTR {
+                    let bytes = if accepted >= 0 { address_bytes(&peer as *const _ as *const libc::sockaddr, peer_length) } else { Vec::new() };
+                    recorder.connected(accepted, StreamKind::Accepted, errno, &bytes);
+                    copy_address(&bytes, address, length);
+                }
+                set_errno(errno);
+                accepted
+            }
+            _ => libc::accept4(fd, address, length, flags),
+        }
+    }
+}
+
+extern "C" fn guest_bind(fd: c_int, address: *const libc::sockaddr, length: libc::socklen_t) -> c_int {
+    match session() {
+        Some(Session::Replaying(player)) if player.is_socket(fd) => 0,
+        _ => unsafe { libc::bind(fd, address, length) },
+    }
+}
+
+extern "C" fn guest_listen(fd: c_int, backlog: c_int) -> c_int {
+    match session() {
+        Some(Session::Replaying(player)) if player.is_socket(fd) => 0,
+        _ => unsafe { libc::listen(fd, backlog) },
+    }
+}
+
+extern "C" fn guest_setsockopt(fd: c_int, level: c_int, name: c_int, value: *const c_void, length: libc::socklen_t) -> c_int {
+    match session() {
+        // TCP options mean nothing to the stand-in
+        Some(Session::Replaying(player)) if player.is_socket(fd) => 0,
+        _ => unsafe { libc::setsockopt(fd, level, name, value, length) },
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    NotACapture,
+    UnsupportedVersion(u32),
+    Corrupt(String),
+    /// `start` was called twice in one process
+    AlreadyStarted,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Io(e) => write!(f, "{}", e),
+            CaptureError::NotACapture => write!(f, "not an I/O capture"),
+            CaptureError::UnsupportedVersion(version) => write!(f, "unsupported capture version {}", version),
+            CaptureError::Corrupt(what) => write!(f, "corrupt capture: {}", what),
+            CaptureError::AlreadyStarted => write!(f, "capture or replay already started in this process"),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), CaptureError> {
+    let source = std::fs::read_to_string("chat.c").unwrap();
+    let mut options = JITOptions::default();
+    options.capture = Some(CaptureMode::Record(PathBuf::from("chat.capture")));
+    // ... compile with `options`, which binds `host_overrides` ...
+    start(options.capture.as_ref().unwrap(), &source)?;
+    // ... run main: every line typed, file read and byte received is kept ...
+
+    // Later, elsewhere, with none of the files or servers around
+    print!("{}", Capture::load(Path::new("chat.capture"))?);
+    start(&CaptureMode::Replay(PathBuf::from("chat.capture")), &source)?;
+    // ... run main again ...
+    Ok(())
+}
+*/
@@ -0,0 +1,554 @@
+// src/runtime/coroutine.rs
+//! Coroutines
+//! `ic_coroutine_*` gives C programs cooperative green threads, for event
+//! loops that would otherwise need a state machine per connection:
+//!
+//! ```c
+//! int ic_coroutine_create(ic_coroutine_t *co, void *(*entry)(void *), void *arg, size_t stack_size);
+//! void *ic_coroutine_resume(ic_coroutine_t co, void *value);
+//! void *ic_coroutine_yield(void *value);
+//! int ic_coroutine_status(ic_coroutine_t co);
+//! ic_coroutine_t ic_coroutine_self(void);
+//! int ic_coroutine_destroy(ic_coroutine_t co);
+//! ```
+//!
+//! `resume` runs the coroutine until it yields or `entry` returns, and
+//! evaluates to the value yielded or returned; `yield` evaluates to the
+//! value of the next `resume`. The first `resume` starts `entry(arg)` and
+//! its value is dropped. A coroutine may resume another, which yields back
+//! to it. Failures return an error number (`NULL` and `errno` for `resume`
+//! and `yield`). `include/ic_coroutine.h` declares the API.
+//!
+//! `Scheduler` keeps the ids, states and chain of resumers; each engine
+//! keeps its own contexts in it:
+//!
+//! - Compiled code gets `host_exports`. Each coroutine runs on a stack from
+//!   the `MemoryManager` with a guard page at the bottom, and `switch_stack`
+//!   moves between stacks, saving only callee-saved registers. The
+//!   scheduler is per thread: a coroutine is resumed on the thread that
+//!   created it.
+//! - The bytecode VM intercepts the calls and swaps its frames, registers
+//!   and frame memory instead, so its coroutines need no host stack.
+//!
+//! Destroying a suspended coroutine discards its frames without running
+//! anything; whatever it allocated leaks. A `longjmp` from one coroutine
+//! into another is undefined, as it is for `swapcontext`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fmt;
+use std::sync::OnceLock;
+use crate::jit::host::{HostExport, HostFunction, HostSignature};
+use crate::jit::memory::MemoryManager;
+use crate::runtime::stack_guard::GuardRegistration;
+use crate::jit::JITType;
+
+/// What `ic_coroutine_self` returns outside any coroutine
+pub const MAIN: CoroutineId = 0;
+
+/// `stack_size` 0
+pub const DEFAULT_STACK_SIZE: usize = 256 << 10;
+
+/// Smaller stacks are rounded up to this
+pub const MIN_STACK_SIZE: usize = 16 << 10;
+
+pub type CoroutineId = u64;
+
+/// `ic_coroutine_status`, with Lua's names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    /// Created, or yielded; `resume` runs it
+    Suspended = 0,
+    /// The one running now
+    Running = 1,
+    /// Resumed another coroutine and waits for it to yield
+    Normal = 2,
+    /// `entry` returned
+    Dead = 3,
+}
+
+/// The `ic_coroutine_*` function a call is to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineCall {
+    Create,
+    Resume,
+    Yield,
+    Status,
+    Current,
+    Destroy,
+}
+
+impl CoroutineCall {
+    pub fn of(name: &str) -> Option<Self> {
+        Some(match name {
+            "ic_coroutine_create" => CoroutineCall::Create,
+            "ic_coroutine_resume" => CoroutineCall::Resume,
+            "ic_coroutine_yield" => CoroutineCall::Yield,
+            "ic_coroutine_status" => CoroutineCall::Status,
+            "ic_coroutine_self" => CoroutineCall::Current,
+            "ic_coroutine_destroy" => CoroutineCall::Destroy,
+            _ => return None,
+        })
+    }
+}
+
+struct Coroutine<C> {
+    status: CoroutineStatus,
+    context: C,
+}
+
+/// The coroutines of one thread. `C` is what an engine saves to switch
+/// away from a coroutine, or from the code outside all of them.
+pub struct Scheduler<C> {
+    main: C,
+    coroutines: HashMap<CoroutineId, Coroutine<C>>,
+    /// Ids aren't reused, so a stale one fails instead of naming another
+    next_id: CoroutineId,
+    /// Coroutines resumed and not yet yielded, outermost first
+    active: Vec<CoroutineId>,
+}
+
+impl<C> Scheduler<C> {
+    pub fn new(main: C) -> Self {
+        Scheduler { main, coroutines: HashMap::new(), next_id: MAIN + 1, active: Vec::new() }
+    }
+
+    /// A suspended coroutine that will start from `context`
+    pub fn insert(&mut self, context: C) -> CoroutineId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.coroutines.insert(id, Coroutine { status: CoroutineStatus::Suspended, context });
+        id
+    }
+
+    /// The running coroutine, or `MAIN`
+    pub fn current(&self) -> CoroutineId {
+        self.active.last().copied().unwrap_or(MAIN)
+    }
+
+    pub fn status(&self, id: CoroutineId) -> Result<CoroutineStatus, CoroutineError> {
+        self.coroutines.get(&id).map(|coroutine| coroutine.status).ok_or(CoroutineError::Unknown(id))
+    }
+
+    /// `resume(id)`: the ids to switch from and to
+    pub fn resume(&mut self, id: CoroutineId) -> Result<(CoroutineId, CoroutineId), CoroutineError> {
+        match self.status(id)? {
+            CoroutineStatus::Suspended => {}
+            CoroutineStatus::Dead => return Err(CoroutineError::Dead(id)),
+            CoroutineStatus::Running | CoroutineStatus::Normal => return Err(CoroutineError::Active(id)),
+        }
+        let from = self.current();
+        self.set_status(from, CoroutineStatus::Normal);
+        self.set_status(id, CoroutineStatus::Running);
+        self.active.push(id);
+        Ok((from, id))
+    }
+
+    /// `yield`: back to whoever resumed the running coroutine
+    pub fn suspend(&mut self) -> Result<(CoroutineId, CoroutineId), CoroutineError> {
+        let from = self.active.pop().ok_or(CoroutineError::NotInCoroutine)?;
+        self.set_status(from, CoroutineStatus::Suspended);
+        let to = self.current();
+        self.set_status(to, CoroutineStatus::Running);
+        Ok((from, to))
+    }
+
+    /// The running coroutine's `entry` returned
+    pub fn finish(&mut self) -> (CoroutineId, CoroutineId) {
+        let from = self.active.pop().expect("a running coroutine");
+        self.set_status(from, CoroutineStatus::Dead);
+        let to = self.current();
+        self.set_status(to, CoroutineStatus::Running);
+        (from, to)
+    }
+
+    /// `destroy(id)`, handing back its context to be freed
+    pub fn remove(&mut self, id: CoroutineId) -> Result<C, CoroutineError> {
+        match self.status(id)? {
+            CoroutineStatus::Running | CoroutineStatus::Normal => Err(CoroutineError::Active(id)),
+            _ => Ok(self.coroutines.remove(&id).expect("a known coroutine").context),
+        }
+    }
+
+    pub fn context_mut(&mut self, id: CoroutineId) -> &mut C {
+        match id {
+            MAIN => &mut self.main,
+            _ => &mut self.coroutines.get_mut(&id).expect("a known coroutine").context,
+        }
+    }
+
+    fn set_status(&mut self, id: CoroutineId, status: CoroutineStatus) {
+        if let Some(coroutine) = self.coroutines.get_mut(&id) {
+            coroutine.status = status;
+        }
+    }
+}
+
+/// A coroutine's C stack: `size` usable bytes above a guard page
+pub struct CoroutineStack {
+    base: usize,
+    size: usize,
+    /// Start of the allocation, the guard page
+    mapping: usize,
+    /// Makes an overflow onto the guard page recoverable
+    _guard: GuardRegistration,
+}
+
+impl CoroutineStack {
+    pub fn allocate(size: usize) -> Result<Self, CoroutineError> {
+        let memory = memory()?;
+        unsafe {
+            let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+            let size = size.max(MIN_STACK_SIZE).div_ceil(page) * page;
+            let mapping = memory.allocate_data(size + page).map_err(|e| CoroutineError::Memory(format!("{:?}", e)))?;
+            // Overflowing the stack faults instead of running into other memory
+            if libc::mprotect(mapping as *mut c_void, page, libc::PROT_NONE) != 0 {
+                let _ = memory.free(mapping);
+                return Err(CoroutineError::Memory(std::io::Error::last_os_error().to_string()));
+            }
+            let base = mapping as usize + page;
+            let guard = GuardRegistration::new(mapping as usize, base, base + size, base + size);
+            Ok(CoroutineStack { base, size, mapping: mapping as usize, _guard: guard })
+        }
+    }
+
+    /// One past the highest usable byte; stacks grow down from here
+    pub fn top(&self) -> usize {
+        self.base + self.size
+    }
+}
+
+impl Drop for CoroutineStack {
+    fn drop(&mut self) {
+        let Some(memory) = MEMORY.get() else { return };
+        unsafe {
+            // The allocation may go back to a pool: make the guard page usable
+            libc::mprotect(self.mapping as *mut c_void, self.base - self.mapping, libc::PROT_READ | libc::PROT_WRITE);
+            let _ = memory.free(self.mapping as *mut u8);
+        }
+    }
+}
+
+static MEMORY: OnceLock<MemoryManager> = OnceLock::new();
+
+fn memory() -> Result<&'static MemoryManager, CoroutineError> {
+    if MEMORY.get().is_none() {
+        let memory = unsafe { MemoryManager::new() }.map_err(|e| CoroutineError::Memory(format!("{:?}", e)))?;
+        let _ = MEMORY.set(memory);
+    }
+    Ok(MEMORY.get().expect("set above"))
+}
+
+/// Where compiled code failing an `ic_coroutine_*` call finds out why
+pub fn set_errno(value: i32) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        *libc::__errno_location() = value
+    };
+    #[cfg(target_os = "macos")]
+    unsafe {
+        *libc::__error() = value
+    };
+}
+
+type Entry = extern "C" fn(*mut c_void) -> *mut c_void;
+
+/// A compiled coroutine, or the thread's own stack as `MAIN`
+#[derive(Default)]
+struct NativeContext {
+    /// Saved by `switch_stack` while switched away
+    sp: usize,
+    stack: Option<CoroutineStack>,
+    /// Until the first `resume` starts it
+    entry: Option<(Entry, usize)>,
+}
+
+thread_local! {
+    static NATIVE: RefCell<Scheduler<NativeContext>> = RefCell::new(Scheduler::new(NativeContext::default()));
+}
+
+/// `ic_coroutine_*` for compiled code
+pub fn host_exports() -> Vec<HostExport> {
+    let pointer = || JITType::Pointer(Box::new(JITType::Void));
+    let export = |name: &'static str, params: Vec<JITType>, return_type: JITType, function: *const ()| HostExport {
+        name,
+        signature: HostSignature::new(params, return_type),
+        function: HostFunction::Pointer(function as *const c_void),
+    };
+    vec![
+        export("ic_coroutine_create", vec![pointer(), pointer(), pointer(), JITType::Int64], JITType::Int32, native_create as *const ()),
+        export("ic_coroutine_resume", vec![JITType::Int64, pointer()], pointer(), native_resume as *const ()),
+        export("ic_coroutine_yield", vec![pointer()], pointer(), native_yield as *const ()),
+        export("ic_coroutine_status", vec![JITType::Int64], JITType::Int32, native_status as *const ()),
+        export("ic_coroutine_self", vec![], JITType::Int64, native_self as *const ()),
+        export("ic_coroutine_destroy", vec![JITType::Int64], JITType::Int32, native_destroy as *const ()),
+    ]
+}
+
+extern "C" fn native_create(result: *mut u64, entry: Option<Entry>, arg: *mut c_void, stack_size: usize) -> i32 {
+    let Some(entry) = entry else { return libc::EINVAL };
+    let stack = match CoroutineStack::allocate(if stack_size == 0 { DEFAULT_STACK_SIZE } else { stack_size }) {
+        Ok(stack) => stack,
+        Err(e) => return e.errno(),
+    };
+    let top = stack.top();
+    let id = NATIVE.with(|native| {
+        let mut scheduler = native.borrow_mut();
+        let id = scheduler.insert(NativeContext { sp: 0, stack: Some(stack), entry: Some((entry, arg as usize)) });
+        scheduler.context_mut(id).sp = unsafe { seed_frame(top, id) };
+        id
+    });
+    if !result.is_null() {
+        unsafe { *result = id };
+    }
+    0
+}
+
+extern "C" fn native_resume(id: u64, value: *mut c_void) -> *mut c_void {
+    switch(|scheduler| scheduler.resume(id), value)
+}
+
+extern "C" fn native_yield(value: *mut c_void) -> *mut c_void {
+    switch(Scheduler::suspend, value)
+}
+
+extern "C" fn native_status(id: u64) -> i32 {
+    match NATIVE.with(|native| native.borrow().status(id)) {
+        Ok(status) => status as i32,
+        Err(e) => {
+            set_errno(e.errno());
+            -1
+        }
+    }
+}
+
+extern "C" fn native_self() -> u64 {
+    NATIVE.with(|native| native.borrow().current())
+}
+
+extern "C" fn native_destroy(id: u64) -> i32 {
+    // The stack is freed when the context drops, outside the borrow
+    match NATIVE.with(|native| native.borrow_mut().remove(id)) {
+        Ok(_context) => 0,
+        Err(e) => e.errno(),
+    }
+}
+
+/// Move to the coroutine `step` picks, passing `value`; returns what is
+/// passed back when this one runs again
+fn switch(
+    step: impl FnOnce(&mut Scheduler<NativeContext>) -> Result<(CoroutineId, CoroutineId), CoroutineError>,
+    value: *mut c_void,
+) -> *mut c_void {
+    let switched = NATIVE.with(|native| {
+        let mut scheduler = native.borrow_mut();
+        let (from, to) = step(&mut scheduler)?;
+        let target = scheduler.context_mut(to).sp;
+        // Written before the switch, while the context can't move
+        Ok::<_, CoroutineError>((&mut scheduler.context_mut(from).sp as *mut usize, target))
+    });
+    match switched {
+        Ok((save, target)) => unsafe { switch_stack(save, target, value as usize) as *mut c_void },
+        Err(e) => {
+            set_errno(e.errno());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Where a new coroutine's first `switch_stack` lands: calls `entry(arg)`
+/// and hands its result to the last resumer
+extern "C" fn coroutine_main(id: CoroutineId) -> ! {
+    let (entry, arg) = NATIVE
+        .with(|native| native.borrow_mut().context_mut(id).entry.take())
+        .expect("a coroutine starts once");
+    let result = entry(arg as *mut c_void);
+    let (save, target) = NATIVE.with(|native| {
+        let mut scheduler = native.borrow_mut();
+        let (from, to) = scheduler.finish();
+        let target = scheduler.context_mut(to).sp;
+        (&mut scheduler.context_mut(from).sp as *mut usize, target)
+    });
+    unsafe { switch_stack(save, target, result as usize) };
+    // Nothing resumes a dead coroutine
+    std::process::abort();
+}
+
+/// Lay out below `top` what `switch_stack` pops, so that it "returns" into
+/// `coroutine_start` with the id in a callee-saved register and a null
+/// frame pointer ending backtraces
+#[cfg(target_arch = "x86_64")]
+unsafe fn seed_frame(top: usize, id: CoroutineId) -> usize {
+    // r15, r14, r13, r12, rbx, rbp, return address, padding
+    let sp = (top & !15) - 8 * 8;
+    let words = sp as *mut usize;
+    for slot in 0..8 {
+        *words.add(slot) = 0;
+    }
+    *words.add(3) = id as usize;
+    *words.add(6) = coroutine_start as *const () as usize;
+    sp
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn seed_frame(top: usize, id: CoroutineId) -> usize {
+    // x19..x28, x29, x30, d8..d15
+    let sp = (top & !15) - 160;
+    let words = sp as *mut usize;
+    for slot in 0..20 {
+        *words.add(slot) = 0;
+    }
+    *words = id as usize;
+    *words.add(11) = coroutine_start as *const () as usize;
+    sp
+}
+
+/// Save the callee-saved registers on this stack and its pointer in
+/// `*save`, then restore them from the stack at `target` and return
+/// `value` there
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn switch_stack(save: *mut usize, target: usize, value: usize) -> usize {
+    core::arch::naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "mov rax, rdx",
+        "ret",
+    )
+}
+
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn coroutine_start() -> ! {
+    core::arch::naked_asm!(
+        "mov rdi, r12",
+        "and rsp, -16",
+        "call {main}",
+        "ud2",
+        main = sym coroutine_main,
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+#[unsafe(naked)]
+unsafe extern "C" fn switch_stack(save: *mut usize, target: usize, value: usize) -> usize {
+    core::arch::naked_asm!(
+        "sub sp, sp, #160",
+        "stp x19, x20, [sp, #0]",
+        "stp x21, x22, [sp, #16]",
+        "stp x23, x24, [sp, #32]",
+        "stp x25, x26, [sp, #48]",
+        "stp x27, x28, [sp, #64]",
+        "stp x29, x30, [sp, #80]",
+        "stp d8, d9, [sp, #96]",
+        "stp d10, d11, [sp, #112]",
+        "stp d12, d13, [sp, #128]",
+        "stp d14, d15, [sp, #144]",
+        "mov x16, sp",
+        "str x16, [x0]",
+        "mov sp, x1",
+        "ldp x19, x20, [sp, #0]",
+        "ldp x21, x22, [sp, #16]",
+        "ldp x23, x24, [sp, #32]",
+        "ldp x25, x26, [sp, #48]",
+        "ldp x27, x28, [sp, #64]",
+        "ldp x29, x30, [sp, #80]",
+        "ldp d8, d9, [sp, #96]",
+        "ldp d10, d11, [sp, #112]",
+        "ldp d12, d13, [sp, #128]",
+        "ldp d14, d15, [sp, #144]",
+        "add sp, sp, #160",
+        "mov x0, x2",
+        "ret",
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+#[unsafe(naked)]
+unsafe extern "C" fn coroutine_start() -> ! {
+    core::arch::naked_asm!(
+        "mov x0, x19",
+        "bl {main}",
+        "brk #1",
+        main = sym coroutine_main,
+    )
+}
+
+#[derive(Debug)]
+pub enum CoroutineError {
+    /// No coroutine has the id, or it was destroyed
+    Unknown(CoroutineId),
+    /// Resuming a coroutine whose `entry` returned
+    Dead(CoroutineId),
+    /// Resuming or destroying the running coroutine, or one waiting for
+    /// another it resumed
+    Active(CoroutineId),
+    /// `ic_coroutine_yield` outside any coroutine
+    NotInCoroutine,
+    /// An `entry` the engine can't start: the bytecode VM only starts
+    /// interpreted functions
+    BadEntry(u64),
+    Memory(String),
+}
+
+impl CoroutineError {
+    /// The error number the `ic_coroutine_*` function reports
+    pub fn errno(&self) -> i32 {
+        match self {
+            CoroutineError::Unknown(_) => libc::ESRCH,
+            CoroutineError::Dead(_) | CoroutineError::BadEntry(_) => libc::EINVAL,
+            CoroutineError::Active(_) => libc::EBUSY,
+            CoroutineError::NotInCoroutine => libc::EPERM,
+            CoroutineError::Memory(_) => libc::ENOMEM,
+        }
+    }
+}
+
+impl fmt::Display for CoroutineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoroutineError::Unknown(id) => write!(f, "no coroutine {}", id),
+            CoroutineError::Dead(id) => write!(f, "coroutine {} has finished", id),
+            CoroutineError::Active(id) => write!(f, "coroutine {} is running", id),
+            CoroutineError::NotInCoroutine => write!(f, "yield outside a coroutine"),
+            CoroutineError::BadEntry(entry) => write!(f, "cannot start a coroutine at {:#x}", entry),
+            CoroutineError::Memory(e) => write!(f, "coroutine stack: {}", e),
+        }
+    }
+}
+
+// Example usage:
+/*
+// generator.c, under the JIT or `--engine=bytecode`:
+//
+// void *count(void *limit) {
+//     for (long i = 0; i < (long)limit; i++)
+//         ic_coroutine_yield((void *)i);
+//     return NULL;
+// }
+//
+// ic_coroutine_t co;
+// ic_coroutine_create(&co, count, (void *)3, 0);
+// while (ic_coroutine_status(co) != IC_COROUTINE_DEAD)
+//     printf("%ld\n", (long)ic_coroutine_resume(co, NULL));   // 0 1 2 0
+
+fn register(compiler: &mut CompilerSystem) -> Result<(), HostError> {
+    for export in coroutine::host_exports() {
+        compiler.register_host_function(export.name, export.signature, export.function)?;
+    }
+    Ok(())
+}
+*/
@@ -0,0 +1,482 @@
+// src/runtime/deterministic.rs
+//! Deterministic execution
+//! With `--deterministic` (or `EngineOptions::deterministic`) a program
+//! behaves the same on every run, so its output can be compared byte for
+//! byte by a grader or a CI job:
+//!
+//! - `srand` always seeds with the configured seed, so `srand(time(NULL))`
+//!   and `srand(getpid())` repeat.
+//! - `time`, `clock`, `clock_gettime` and `gettimeofday` read a virtual clock
+//!   that starts at a fixed date and moves on by one tick per reading.
+//! - `disable_aslr` re-executes the process with address randomization off,
+//!   so stack, heap and mapping addresses, and what `%p` prints, repeat.
+//! - Guest threads run one at a time. The running thread keeps the turn
+//!   until it blocks in a pthread call, calls `sched_yield`, ends, or (when
+//!   interpreted) has run a quantum of statements; the turn then goes to the
+//!   next thread by id. A wait no thread can ever end fails with `EDEADLK`
+//!   instead of hanging.
+//!
+//! Compiled code isn't preempted: a thread that spins on a flag without a
+//! pthread call or `sched_yield` keeps the turn forever.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::c_void;
+use std::fmt;
+use std::io;
+use std::ops::Bound;
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::jit::host::{HostExport, HostFunction, HostSignature};
+use crate::jit::memory::MemoryManager;
+use crate::jit::JITType;
+use super::posix::{self, POSIXModule, StartRoutine, ThreadAttributes, ThreadId, MAIN_THREAD};
+
+/// 2024-01-01T00:00:00Z
+const DEFAULT_EPOCH: Duration = Duration::from_secs(1_704_067_200);
+
+/// `CLOCKS_PER_SEC` on POSIX systems
+const CLOCKS_PER_SEC: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeterministicConfig {
+    /// What `srand` seeds with, whatever it is given (`--seed`)
+    pub seed: u32,
+    /// `CLOCK_REALTIME` when the program starts
+    pub epoch: Duration,
+    /// How far each clock reading moves time on
+    pub tick: Duration,
+    /// Interpreted statements a thread runs before the next one gets a turn
+    pub quantum: u32,
+}
+
+impl Default for DeterministicConfig {
+    fn default() -> Self {
+        DeterministicConfig {
+            seed: 1,
+            epoch: DEFAULT_EPOCH,
+            tick: Duration::from_micros(1),
+            quantum: 10_000,
+        }
+    }
+}
+
+/// The clocks a program sees. Every reading advances time by one tick, so
+/// a loop waiting for the clock to move still ends, after the same number
+/// of readings on every run.
+pub struct VirtualClock {
+    epoch: Duration,
+    tick: u64,
+    /// Nanoseconds since the program started
+    elapsed: AtomicU64,
+}
+
+impl VirtualClock {
+    pub fn new(epoch: Duration, tick: Duration) -> Self {
+        VirtualClock { epoch, tick: tick.as_nanos().max(1) as u64, elapsed: AtomicU64::new(0) }
+    }
+
+    /// `CLOCK_REALTIME`, without advancing it
+    pub fn now(&self) -> Duration {
+        self.epoch + Duration::from_nanos(self.elapsed.load(Ordering::Acquire))
+    }
+
+    /// A reading of `clock` by the program. `CLOCK_REALTIME` counts from the
+    /// epoch; the monotonic and CPU-time clocks from the program's start.
+    pub fn read(&self, clock: libc::clockid_t) -> Duration {
+        let elapsed = Duration::from_nanos(self.elapsed.fetch_add(self.tick, Ordering::AcqRel) + self.tick);
+        match clock {
+            libc::CLOCK_REALTIME | libc::CLOCK_REALTIME_COARSE | libc::CLOCK_TAI => self.epoch + elapsed,
+            _ => elapsed,
+        }
+    }
+
+    /// Move `CLOCK_REALTIME` on to `realtime`, if it is still before it
+    pub fn advance_to(&self, realtime: Duration) {
+        let target = realtime.saturating_sub(self.epoch).as_nanos() as u64;
+        self.elapsed.fetch_max(target, Ordering::AcqRel);
+    }
+}
+
+struct Turns {
+    /// The thread allowed to run
+    current: ThreadId,
+    /// Threads that have started and not ended
+    threads: BTreeSet<ThreadId>,
+    /// Turns handed over in a row by threads that couldn't make progress
+    stalled: usize,
+    /// `CLOCK_REALTIME` deadline of each thread in a timed wait
+    deadlines: BTreeMap<ThreadId, Duration>,
+}
+
+/// Runs guest threads one at a time, handing the turn over in id order
+pub struct OrderedScheduler {
+    turns: Mutex<Turns>,
+    turn: Condvar,
+    quantum: u32,
+    /// Statements the running thread has left in its quantum
+    statements: AtomicU32,
+}
+
+impl OrderedScheduler {
+    /// The main thread holds the first turn
+    pub fn new(quantum: u32) -> Self {
+        OrderedScheduler {
+            turns: Mutex::new(Turns {
+                current: MAIN_THREAD,
+                threads: BTreeSet::from([MAIN_THREAD]),
+                stalled: 0,
+                deadlines: BTreeMap::new(),
+            }),
+            turn: Condvar::new(),
+            quantum: quantum.max(1),
+            statements: AtomicU32::new(0),
+        }
+    }
+
+    /// A thread was created; it gets a turn after the threads before it
+    pub fn spawn(&self, id: ThreadId) {
+        let mut turns = self.turns.lock().unwrap();
+        turns.threads.insert(id);
+        turns.stalled = 0;
+    }
+
+    /// Called first on a new thread: wait for its first turn
+    pub fn begin(&self, id: ThreadId) {
+        let turns = self.turns.lock().unwrap();
+        drop(self.turn.wait_while(turns, |turns| turns.current != id).unwrap());
+    }
+
+    /// Called last on an ending thread: hand the turn on for good
+    pub fn exit(&self, id: ThreadId) {
+        let mut turns = self.turns.lock().unwrap();
+        turns.threads.remove(&id);
+        turns.deadlines.remove(&id);
+        turns.stalled = 0;
+        self.pass(&mut turns, id);
+    }
+
+    /// Something another thread may be waiting for happened: an unlock, a
+    /// signal, a thread ending
+    pub fn progress(&self) {
+        self.turns.lock().unwrap().stalled = 0;
+    }
+
+    /// `sched_yield`, or the end of a quantum: let the others run once
+    pub fn yield_now(&self, me: ThreadId) {
+        let mut turns = self.turns.lock().unwrap();
+        turns.stalled = 0;
+        self.pass(&mut turns, me);
+        drop(self.turn.wait_while(turns, |turns| turns.current != me).unwrap());
+    }
+
+    /// Called before every interpreted statement
+    pub fn statement(&self, me: ThreadId) {
+        if self.statements.fetch_add(1, Ordering::Relaxed) + 1 >= self.quantum {
+            self.statements.store(0, Ordering::Relaxed);
+            self.yield_now(me);
+        }
+    }
+
+    /// `me` is waiting for another thread; let the others run once. When
+    /// every thread has stalled since the last progress, time jumps to the
+    /// earliest deadline of a timed wait; without one the wait can never
+    /// end and `Stalled` is returned.
+    pub fn stall(&self, me: ThreadId, deadline: Option<Duration>, clock: &VirtualClock) -> Result<(), Stalled> {
+        let mut turns = self.turns.lock().unwrap();
+        turns.stalled += 1;
+        if turns.stalled > turns.threads.len() {
+            match turns.deadlines.values().chain(deadline.as_ref()).min() {
+                Some(&earliest) => {
+                    clock.advance_to(earliest);
+                    turns.stalled = 0;
+                }
+                None => {
+                    turns.stalled = 0;
+                    return Err(Stalled);
+                }
+            }
+        }
+        if let Some(deadline) = deadline {
+            turns.deadlines.insert(me, deadline);
+        }
+        self.pass(&mut turns, me);
+        let mut turns = self.turn.wait_while(turns, |turns| turns.current != me).unwrap();
+        turns.deadlines.remove(&me);
+        Ok(())
+    }
+
+    /// Give the turn to the next thread after `from` by id, wrapping around
+    fn pass(&self, turns: &mut Turns, from: ThreadId) {
+        let next = turns
+            .threads
+            .range((Bound::Excluded(from), Bound::Unbounded))
+            .next()
+            .or_else(|| turns.threads.iter().next())
+            .copied();
+        if let Some(next) = next {
+            turns.current = next;
+            self.statements.store(0, Ordering::Relaxed);
+            self.turn.notify_all();
+        }
+    }
+}
+
+/// A wait no other thread can end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stalled;
+
+/// The state deterministic mode shares between the runtime's parts
+pub struct Determinism {
+    config: DeterministicConfig,
+    clock: VirtualClock,
+    scheduler: OrderedScheduler,
+}
+
+impl Determinism {
+    pub fn new(config: DeterministicConfig) -> Self {
+        Determinism {
+            clock: VirtualClock::new(config.epoch, config.tick),
+            scheduler: OrderedScheduler::new(config.quantum),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &DeterministicConfig {
+        &self.config
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    pub fn scheduler(&self) -> &OrderedScheduler {
+        &self.scheduler
+    }
+
+    /// What `srand(requested)` seeds with
+    pub fn seed(&self, _requested: u32) -> u32 {
+        self.config.seed
+    }
+
+    /// `OrderedScheduler::stall` on this clock
+    pub fn stall(&self, me: ThreadId, deadline: Option<Duration>) -> Result<(), Stalled> {
+        self.scheduler.stall(me, deadline, &self.clock)
+    }
+}
+
+/// Re-execute this process with address space randomization off, as
+/// `setarch -R` would, so the program's addresses repeat from run to run.
+/// Returns `Ok` once it is off; only returns an error if it can't be
+/// turned off. Call it first thing: the process starts over.
+pub fn disable_aslr() -> io::Result<()> {
+    let persona = unsafe { libc::personality(0xffff_ffff) };
+    if persona == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if persona & libc::ADDR_NO_RANDOMIZE != 0 {
+        return Ok(());
+    }
+    if unsafe { libc::personality((persona | libc::ADDR_NO_RANDOMIZE) as libc::c_ulong) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // The personality takes effect at the next exec
+    let mut args = std::env::args_os();
+    let mut command = std::process::Command::new("/proc/self/exe");
+    if let Some(argv0) = args.next() {
+        command.arg0(argv0);
+    }
+    Err(command.args(args).exec())
+}
+
+/// What the host overrides below act on: one per process, since the
+/// overrides are plain `extern "C"` functions
+struct Guest {
+    determinism: Arc<Determinism>,
+    posix: POSIXModule,
+}
+
+static GUEST: OnceLock<Guest> = OnceLock::new();
+
+/// Host functions replacing the clock, `srand` and pthread calls of
+/// JIT-compiled code with the deterministic ones. The first call fixes the
+/// configuration for the rest of the process; later ones share it.
+pub fn host_overrides(config: &DeterministicConfig) -> Result<Vec<HostExport>, DeterministicError> {
+    if GUEST.get().is_none() {
+        let memory = unsafe { MemoryManager::new() }.map_err(|e| DeterministicError::Memory(format!("{:?}", e)))?;
+        let determinism = Arc::new(Determinism::new(*config));
+        let posix = POSIXModule::new(Arc::new(memory));
+        posix.set_deterministic(Arc::clone(&determinism));
+        let _ = GUEST.set(Guest { determinism, posix });
+    }
+
+    let pointer = || JITType::Pointer(Box::new(JITType::Void));
+    let export = |name: &'static str, params: Vec<JITType>, return_type: JITType, function: *const ()| HostExport {
+        name,
+        signature: HostSignature::new(params, return_type),
+        function: HostFunction::Pointer(function as *const c_void),
+    };
+    Ok(vec![
+        export("time", vec![pointer()], JITType::Int64, guest_time as *const ()),
+        export("clock", vec![], JITType::Int64, guest_clock as *const ()),
+        export("clock_gettime", vec![JITType::Int32, pointer()], JITType::Int32, guest_clock_gettime as *const ()),
+        export("gettimeofday", vec![pointer(), pointer()], JITType::Int32, guest_gettimeofday as *const ()),
+        export("srand", vec![JITType::Int32], JITType::Void, guest_srand as *const ()),
+        export("sched_yield", vec![], JITType::Int32, guest_sched_yield as *const ()),
+        export("pthread_self", vec![], JITType::Int64, guest_pthread_self as *const ()),
+        export("pthread_create", vec![pointer(), pointer(), pointer(), pointer()], JITType::Int32, guest_pthread_create as *const ()),
+        export("pthread_join", vec![JITType::Int64, pointer()], JITType::Int32, guest_pthread_join as *const ()),
+        export("pthread_mutex_lock", vec![pointer()], JITType::Int32, guest_mutex_lock as *const ()),
+        export("pthread_mutex_trylock", vec![pointer()], JITType::Int32, guest_mutex_trylock as *const ()),
+        export("pthread_mutex_unlock", vec![pointer()], JITType::Int32, guest_mutex_unlock as *const ()),
+        export("pthread_cond_wait", vec![pointer(), pointer()], JITType::Int32, guest_cond_wait as *const ()),
+        export("pthread_cond_timedwait", vec![pointer(), pointer(), pointer()], JITType::Int32, guest_cond_timedwait as *const ()),
+        export("pthread_cond_signal", vec![pointer()], JITType::Int32, guest_cond_signal as *const ()),
+        export("pthread_cond_broadcast", vec![pointer()], JITType::Int32, guest_cond_broadcast as *const ()),
+    ])
+}
+
+fn guest() -> &'static Guest {
+    GUEST.get().expect("deterministic host functions called before host_overrides")
+}
+
+fn status(result: Result<(), posix::PosixError>) -> i32 {
+    result.err().map_or(0, |e| e.errno())
+}
+
+extern "C" fn guest_time(result: *mut i64) -> i64 {
+    let now = guest().determinism.clock().read(libc::CLOCK_REALTIME).as_secs() as i64;
+    if !result.is_null() {
+        unsafe { *result = now };
+    }
+    now
+}
+
+extern "C" fn guest_clock() -> i64 {
+    let cpu = guest().determinism.clock().read(libc::CLOCK_PROCESS_CPUTIME_ID);
+    (cpu.as_micros() as u64 * CLOCKS_PER_SEC / 1_000_000) as i64
+}
+
+extern "C" fn guest_clock_gettime(clock: i32, result: *mut libc::timespec) -> i32 {
+    let time = guest().determinism.clock().read(clock);
+    unsafe {
+        (*result).tv_sec = time.as_secs() as libc::time_t;
+        (*result).tv_nsec = time.subsec_nanos() as libc::c_long;
+    }
+    0
+}
+
+extern "C" fn guest_gettimeofday(result: *mut libc::timeval, _zone: *mut c_void) -> i32 {
+    let time = guest().determinism.clock().read(libc::CLOCK_REALTIME);
+    if !result.is_null() {
+        unsafe {
+            (*result).tv_sec = time.as_secs() as libc::time_t;
+            (*result).tv_usec = time.subsec_micros() as libc::suseconds_t;
+        }
+    }
+    0
+}
+
+extern "C" fn guest_srand(seed: u32) {
+    unsafe { libc::srand(guest().determinism.seed(seed)) };
+}
+
+extern "C" fn guest_sched_yield() -> i32 {
+    guest().posix.yield_now();
+    0
+}
+
+extern "C" fn guest_pthread_self() -> u64 {
+    guest().posix.current()
+}
+
+extern "C" fn guest_pthread_create(
+    thread: *mut u64,
+    _attributes: *const c_void,
+    routine: *const c_void,
+    argument: *mut c_void,
+) -> i32 {
+    let routine: extern "C" fn(*mut c_void) -> *mut c_void = unsafe { std::mem::transmute(routine) };
+    match guest().posix.create(StartRoutine::Native(routine), argument as u64, ThreadAttributes::default()) {
+        Ok(id) => {
+            unsafe { *thread = id };
+            0
+        }
+        Err(e) => e.errno(),
+    }
+}
+
+extern "C" fn guest_pthread_join(thread: u64, result: *mut *mut c_void) -> i32 {
+    match guest().posix.join(thread) {
+        Ok(value) => {
+            if !result.is_null() {
+                unsafe { *result = value as *mut c_void };
+            }
+            0
+        }
+        Err(e) => e.errno(),
+    }
+}
+
+extern "C" fn guest_mutex_lock(mutex: *mut c_void) -> i32 {
+    status(guest().posix.mutex_lock(mutex as usize))
+}
+
+extern "C" fn guest_mutex_trylock(mutex: *mut c_void) -> i32 {
+    status(guest().posix.mutex_trylock(mutex as usize))
+}
+
+extern "C" fn guest_mutex_unlock(mutex: *mut c_void) -> i32 {
+    status(guest().posix.mutex_unlock(mutex as usize))
+}
+
+extern "C" fn guest_cond_wait(cond: *mut c_void, mutex: *mut c_void) -> i32 {
+    status(guest().posix.cond_wait(cond as usize, mutex as usize, None))
+}
+
+extern "C" fn guest_cond_timedwait(cond: *mut c_void, mutex: *mut c_void, deadline: *const libc::timespec) -> i32 {
+    status(guest().posix.cond_wait(cond as usize, mutex as usize, Some(unsafe { *deadline })))
+}
+
+extern "C" fn guest_cond_signal(cond: *mut c_void) -> i32 {
+    status(guest().posix.cond_signal(cond as usize, false))
+}
+
+extern "C" fn guest_cond_broadcast(cond: *mut c_void) -> i32 {
+    status(guest().posix.cond_signal(cond as usize, true))
+}
+
+#[derive(Debug)]
+pub enum DeterministicError {
+    /// The thread stacks' memory manager couldn't be created
+    Memory(String),
+}
+
+impl fmt::Display for DeterministicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeterministicError::Memory(e) => write!(f, "cannot set up deterministic threads: {}", e),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), EngineError> {
+    // Before anything else: the process may start over
+    let _ = deterministic::disable_aslr();
+
+    let mut engine = Engine::with_options(EngineOptions {
+        deterministic: Some(DeterministicConfig { seed: 42, ..DeterministicConfig::default() }),
+        ..EngineOptions::default()
+    })?;
+    // Prints the same time, random numbers and pointer on every run
+    engine.eval_string(
+        "#include <stdio.h>\n#include <stdlib.h>\n#include <time.h>\n\
+         int main(void) { srand(time(NULL)); int x; printf(\"%ld %d %p\\n\", time(NULL), rand(), (void *)&x); return 0; }",
+    )?;
+    Ok(())
+}
+*/
@@ -0,0 +1,275 @@
+// src/runtime/dynamic_loader.rs
+//! Shared libraries for JIT-compiled programs
+//! `-lfoo` makes the JIT dlopen `libfoo.so` and bind the program's external
+//! functions and variables to it, as the dynamic linker would for a linked
+//! executable. Libraries are searched in the `-L` directories, then
+//! `LD_LIBRARY_PATH`, then the system directories; `-l:name` names a file
+//! exactly, as with GNU ld. When several libraries define a symbol, the
+//! first on the command line wins.
+//!
+//! Symbols no library defines are left to the execution engine, which finds
+//! libc and the rest of the host process itself. Symbols found nowhere are
+//! reported before the program runs instead of crashing when first called.
+//!
+//! Handles are never closed: JIT-compiled code may keep addresses into a
+//! library for as long as the process lives.
+
+#[cfg(feature = "llvm")]
+use std::ffi::{CStr, CString};
+#[cfg(feature = "llvm")]
+use std::os::raw::c_void;
+#[cfg(feature = "llvm")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "llvm")]
+use llvm_sys::core::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::execution_engine::{LLVMAddGlobalMapping, LLVMExecutionEngineRef};
+#[cfg(feature = "llvm")]
+use llvm_sys::prelude::*;
+#[cfg(feature = "llvm")]
+use llvm_sys::LLVMLinkage;
+use serde::{Deserialize, Serialize};
+
+/// What `-l` and `-L` asked for
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibrarySearch {
+    /// `-l` names, in command-line order
+    pub libraries: Vec<String>,
+    /// `-L` directories, searched before the system ones
+    pub library_paths: Vec<String>,
+}
+
+impl LibrarySearch {
+    pub fn is_empty(&self) -> bool {
+        self.libraries.is_empty()
+    }
+}
+
+#[cfg(feature = "llvm")]
+pub struct LoadedLibrary {
+    /// As given to `-l`
+    pub name: String,
+    pub path: PathBuf,
+    handle: *mut c_void,
+}
+
+/// Outcome of binding one module
+#[derive(Debug, Default)]
+pub struct ResolvedSymbols {
+    /// Declarations mapped to a loaded library
+    pub bound: usize,
+    /// Defined neither by a loaded library nor by the host process
+    pub unresolved: Vec<String>,
+}
+
+#[cfg(feature = "llvm")]
+pub struct DynamicLoader {
+    search_dirs: Vec<PathBuf>,
+    libraries: Vec<LoadedLibrary>,
+
+    // Statistics
+    symbols_bound: usize,
+}
+
+#[cfg(feature = "llvm")]
+impl DynamicLoader {
+    /// Open every library in `search`, in order
+    pub fn new(search: &LibrarySearch) -> Result<Self, DynamicLoaderError> {
+        let mut search_dirs: Vec<PathBuf> = search.library_paths.iter().map(PathBuf::from).collect();
+        if let Some(paths) = std::env::var_os("LD_LIBRARY_PATH") {
+            search_dirs.extend(std::env::split_paths(&paths).filter(|dir| !dir.as_os_str().is_empty()));
+        }
+        search_dirs.push(PathBuf::from(format!("/usr/lib/{}-linux-gnu", std::env::consts::ARCH)));
+        search_dirs.extend(["/usr/local/lib", "/usr/lib64", "/usr/lib", "/lib64", "/lib"].map(PathBuf::from));
+
+        let mut loader = DynamicLoader {
+            search_dirs,
+            libraries: Vec::new(),
+            symbols_bound: 0,
+        };
+        for name in &search.libraries {
+            loader.load(name)?;
+        }
+        Ok(loader)
+    }
+
+    /// dlopen the first candidate for `name` that loads. Candidates that
+    /// fail to load, such as glibc's linker-script `libm.so`, are skipped.
+    pub fn load(&mut self, name: &str) -> Result<&LoadedLibrary, DynamicLoaderError> {
+        let candidates = self.candidates(name);
+        if candidates.is_empty() {
+            return Err(DynamicLoaderError::NotFound {
+                name: name.to_string(),
+                searched: self.search_dirs.clone(),
+            });
+        }
+
+        let mut failure = None;
+        for path in candidates {
+            match open(&path) {
+                Ok(handle) => {
+                    log::debug!("-l{}: loaded {}", name, path.display());
+                    self.libraries.push(LoadedLibrary { name: name.to_string(), path, handle });
+                    return Ok(self.libraries.last().unwrap());
+                }
+                Err(message) => failure = Some(DynamicLoaderError::Open { path, message }),
+            }
+        }
+        Err(failure.unwrap())
+    }
+
+    /// Files `-l<name>` could mean, in search order. Within a directory the
+    /// unversioned `libname.so` comes first, then `libname.so.N`, newest first.
+    fn candidates(&self, name: &str) -> Vec<PathBuf> {
+        if name.contains('/') {
+            return vec![PathBuf::from(name)];
+        }
+
+        let mut candidates = Vec::new();
+        for dir in &self.search_dirs {
+            if let Some(file) = name.strip_prefix(':') {
+                let path = dir.join(file);
+                if path.is_file() {
+                    candidates.push(path);
+                }
+                continue;
+            }
+
+            let unversioned = format!("lib{}.so", name);
+            let path = dir.join(&unversioned);
+            if path.is_file() {
+                candidates.push(path);
+            }
+
+            let prefix = format!("{}.", unversioned);
+            let mut versioned: Vec<PathBuf> = std::fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+                .map(|entry| entry.path())
+                .collect();
+            versioned.sort_by(|a, b| b.cmp(a));
+            candidates.extend(versioned);
+        }
+        candidates
+    }
+
+    /// Address of `symbol` in the first loaded library that defines it
+    pub fn lookup(&self, symbol: &str) -> Option<*mut c_void> {
+        let name = CString::new(symbol).ok()?;
+        self.libraries.iter().find_map(|library| {
+            let address = unsafe { libc::dlsym(library.handle, name.as_ptr()) };
+            (!address.is_null()).then_some(address)
+        })
+    }
+
+    /// Map every external function and variable declared in `module` that a
+    /// loaded library defines. Must run before the engine compiles `module`.
+    pub unsafe fn resolve_module(&mut self, engine: LLVMExecutionEngineRef, module: LLVMModuleRef) -> ResolvedSymbols {
+        let mut resolved = ResolvedSymbols::default();
+
+        for (value, name) in external_declarations(module) {
+            if name.starts_with("llvm.") {
+                continue;
+            }
+            match self.lookup(&name) {
+                Some(address) => {
+                    LLVMAddGlobalMapping(engine, value, address);
+                    resolved.bound += 1;
+                }
+                None if in_host_process(&name) => {}
+                // A weak reference may legitimately stay null
+                None if LLVMGetLinkage(value) == LLVMLinkage::LLVMExternalWeakLinkage => {}
+                None => resolved.unresolved.push(name),
+            }
+        }
+
+        self.symbols_bound += resolved.bound;
+        resolved
+    }
+
+    pub fn libraries(&self) -> &[LoadedLibrary] {
+        &self.libraries
+    }
+
+    /// (libraries loaded, symbols bound)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.libraries.len(), self.symbols_bound)
+    }
+}
+
+#[cfg(feature = "llvm")]
+fn open(path: &Path) -> Result<*mut c_void, String> {
+    let c_path = CString::new(path.to_string_lossy().into_owned()).map_err(|e| e.to_string())?;
+    // GLOBAL so a library loaded later can use symbols from an earlier one
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL) };
+    if handle.is_null() {
+        let error = unsafe { libc::dlerror() };
+        return Err(if error.is_null() {
+            "dlopen failed".to_string()
+        } else {
+            unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+        });
+    }
+    Ok(handle)
+}
+
+#[cfg(feature = "llvm")]
+fn in_host_process(symbol: &str) -> bool {
+    let Ok(name) = CString::new(symbol) else { return false };
+    !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+}
+
+/// Functions and globals the module uses but doesn't define
+#[cfg(feature = "llvm")]
+unsafe fn external_declarations(module: LLVMModuleRef) -> Vec<(LLVMValueRef, String)> {
+    let mut declarations = Vec::new();
+    let mut push = |value: LLVMValueRef| {
+        if LLVMIsDeclaration(value) != 0 {
+            let mut len = 0;
+            let name = LLVMGetValueName2(value, &mut len);
+            let name = std::slice::from_raw_parts(name as *const u8, len);
+            declarations.push((value, String::from_utf8_lossy(name).into_owned()));
+        }
+    };
+
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        push(function);
+        function = LLVMGetNextFunction(function);
+    }
+    let mut global = LLVMGetFirstGlobal(module);
+    while !global.is_null() {
+        push(global);
+        global = LLVMGetNextGlobal(global);
+    }
+    declarations
+}
+
+#[derive(Debug)]
+pub enum DynamicLoaderError {
+    NotFound { name: String, searched: Vec<PathBuf> },
+    /// Every candidate failed; this is the last dlopen error
+    Open { path: PathBuf, message: String },
+    UnresolvedSymbols(Vec<String>),
+}
+
+// Example usage:
+/*
+unsafe fn bind(engine: LLVMExecutionEngineRef, module: LLVMModuleRef) -> Result<(), DynamicLoaderError> {
+    // c-interpreter run -L./build -lsqlite3 -lz app.c
+    let search = LibrarySearch {
+        libraries: vec!["sqlite3".to_string(), "z".to_string()],
+        library_paths: vec!["./build".to_string()],
+    };
+    let mut loader = DynamicLoader::new(&search)?;
+
+    let resolved = loader.resolve_module(engine, module);
+    if !resolved.unresolved.is_empty() {
+        return Err(DynamicLoaderError::UnresolvedSymbols(resolved.unresolved));
+    }
+    println!("{:?}", loader.stats()); // (2, 14)
+    Ok(())
+}
+*/
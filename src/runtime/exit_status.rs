@@ -0,0 +1,113 @@
+// src/runtime/exit_status.rs
+//! Exit status propagation for executed programs
+//! The guest runs in a forked child so that a crash or a call to exit()
+//! terminates only the child. The parent then reproduces the status the way
+//! a shell would: the exit code verbatim, or 128+N for death by signal N.
+
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramExit {
+    /// Returned from main or called exit() with this status
+    Exited(i32),
+    /// Terminated by this signal
+    Signaled(i32),
+}
+
+impl ProgramExit {
+    /// Status to pass to `process::exit`, following shell conventions
+    pub fn code(&self) -> i32 {
+        match self {
+            ProgramExit::Exited(status) => *status,
+            ProgramExit::Signaled(signal) => 128 + signal,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        *self == ProgramExit::Exited(0)
+    }
+
+    fn from_wait_status(status: i32) -> Self {
+        if libc::WIFSIGNALED(status) {
+            ProgramExit::Signaled(libc::WTERMSIG(status))
+        } else {
+            ProgramExit::Exited(libc::WEXITSTATUS(status))
+        }
+    }
+}
+
+impl fmt::Display for ProgramExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramExit::Exited(status) => write!(f, "exited with status {}", status),
+            ProgramExit::Signaled(signal) => {
+                write!(f, "terminated by signal {} ({})", signal_name(*signal), signal)
+            }
+        }
+    }
+}
+
+/// Run `program` in a forked child and wait for it.
+///
+/// The closure's return value becomes the child's exit status. Both Rust and
+/// C stdio buffers are flushed before the child exits so no output is lost.
+pub fn run_in_child(program: impl FnOnce() -> i32) -> io::Result<ProgramExit> {
+    // Anything buffered now would otherwise be written twice
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+
+    unsafe {
+        let pid = libc::fork();
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if pid == 0 {
+            let status = program();
+            let _ = io::stdout().flush();
+            libc::fflush(std::ptr::null_mut());
+            libc::_exit(status);
+        }
+
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        Ok(ProgramExit::from_wait_status(status))
+    }
+}
+
+pub fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => "unknown signal",
+    }
+}
+
+// Example usage:
+/*
+fn main() -> io::Result<()> {
+    let exit = run_in_child(|| unsafe { main_fn(0, argv.as_ptr()) })?;
+    eprintln!("program {}", exit);   // "program terminated by signal SIGSEGV (11)"
+    std::process::exit(exit.code()); // 139
+}
+*/
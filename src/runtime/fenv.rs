@@ -0,0 +1,478 @@
+// src/runtime/fenv.rs
+//! <fenv.h> for interpreted programs
+//! The interpreter evaluates C floating-point operations with the host FPU,
+//! so the program's rounding mode and exception flags simply live in the
+//! host's control/status registers (MXCSR and the x87 words on x86_64,
+//! FPCR/FPSR on aarch64). These functions read and write those registers
+//! with the same constants and `fenv_t` layout as the C headers, which keeps
+//! interpreted and JIT-compiled programs in agreement.
+//!
+//! A `FenvSession` starts each program from the default environment and
+//! puts the host's back afterwards, so a program that leaves FE_UPWARD set
+//! can't affect the interpreter itself.
+//!
+//! On other hosts there is no environment to reach: like a C library
+//! without floating-point support, only FE_TONEAREST exists and no
+//! exception is reported.
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use std::arch::asm;
+
+#[cfg(target_arch = "x86_64")]
+mod consts {
+    pub const FE_INVALID: i32 = 0x01;
+    pub const FE_DIVBYZERO: i32 = 0x04;
+    pub const FE_OVERFLOW: i32 = 0x08;
+    pub const FE_UNDERFLOW: i32 = 0x10;
+    pub const FE_INEXACT: i32 = 0x20;
+    pub const FE_ALL_EXCEPT: i32 = 0x3d;
+
+    pub const FE_TONEAREST: i32 = 0x000;
+    pub const FE_DOWNWARD: i32 = 0x400;
+    pub const FE_UPWARD: i32 = 0x800;
+    pub const FE_TOWARDZERO: i32 = 0xc00;
+
+    /// x87 `fnstenv` image followed by MXCSR
+    pub const FENV_SIZE: usize = 32;
+    pub const FEXCEPT_SIZE: usize = 2;
+}
+
+#[cfg(target_arch = "aarch64")]
+mod consts {
+    pub const FE_INVALID: i32 = 0x01;
+    pub const FE_DIVBYZERO: i32 = 0x02;
+    pub const FE_OVERFLOW: i32 = 0x04;
+    pub const FE_UNDERFLOW: i32 = 0x08;
+    pub const FE_INEXACT: i32 = 0x10;
+    pub const FE_ALL_EXCEPT: i32 = 0x1f;
+
+    pub const FE_TONEAREST: i32 = 0x000000;
+    pub const FE_UPWARD: i32 = 0x400000;
+    pub const FE_DOWNWARD: i32 = 0x800000;
+    pub const FE_TOWARDZERO: i32 = 0xc00000;
+
+    /// FPCR, FPSR
+    pub const FENV_SIZE: usize = 8;
+    pub const FEXCEPT_SIZE: usize = 4;
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod consts {
+    pub const FE_ALL_EXCEPT: i32 = 0;
+    pub const FE_TONEAREST: i32 = 0;
+
+    pub const FENV_SIZE: usize = 4;
+    pub const FEXCEPT_SIZE: usize = 4;
+}
+
+pub use consts::*;
+
+/// Opaque `fenv_t` with the C header's layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloatEnv {
+    bytes: [u8; FENV_SIZE],
+}
+
+#[cfg(target_arch = "x86_64")]
+mod host {
+    use super::*;
+
+    const MXCSR_ROUND_SHIFT: u32 = 3;
+    const MXCSR_DEFAULT: u32 = 0x1f80;
+    const X87_CONTROL_DEFAULT: u16 = 0x37f;
+    const X87_ENV_SIZE: usize = 28;
+
+    fn mxcsr() -> u32 {
+        let mut value: u32 = 0;
+        unsafe { asm!("stmxcsr [{}]", in(reg) &mut value, options(nostack, preserves_flags)) };
+        value
+    }
+
+    fn set_mxcsr(value: u32) {
+        unsafe { asm!("ldmxcsr [{}]", in(reg) &value, options(nostack, preserves_flags)) };
+    }
+
+    /// `fnstenv` masks every x87 exception, so reload the image right away
+    fn x87_env() -> [u8; X87_ENV_SIZE] {
+        let mut env = [0u8; X87_ENV_SIZE];
+        unsafe {
+            asm!("fnstenv [{0}]", "fldenv [{0}]", in(reg) env.as_mut_ptr(), options(nostack, preserves_flags))
+        };
+        env
+    }
+
+    fn set_x87_env(env: &[u8; X87_ENV_SIZE]) {
+        unsafe { asm!("fldenv [{}]", in(reg) env.as_ptr(), options(nostack, preserves_flags)) };
+    }
+
+    fn word(env: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([env[offset], env[offset + 1]])
+    }
+
+    fn set_word(env: &mut [u8], offset: usize, value: u16) {
+        env[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // Offsets in the fnstenv image
+    const CONTROL: usize = 0;
+    const STATUS: usize = 4;
+    const TAGS: usize = 8;
+
+    pub fn test(excepts: i32) -> i32 {
+        let status = word(&x87_env(), STATUS) as u32;
+        ((status | mxcsr()) as i32) & excepts & FE_ALL_EXCEPT
+    }
+
+    pub fn clear(excepts: i32) {
+        let excepts = excepts & FE_ALL_EXCEPT;
+        let mut env = x87_env();
+        let status = word(&env, STATUS) & !(excepts as u16);
+        set_word(&mut env, STATUS, status);
+        set_x87_env(&env);
+        set_mxcsr(mxcsr() & !(excepts as u32));
+    }
+
+    pub fn raise(excepts: i32) {
+        set_mxcsr(mxcsr() | (excepts & FE_ALL_EXCEPT) as u32);
+    }
+
+    pub fn round() -> i32 {
+        ((mxcsr() >> MXCSR_ROUND_SHIFT) as i32) & FE_TOWARDZERO
+    }
+
+    pub fn set_round(round: i32) -> bool {
+        if round & !FE_TOWARDZERO != 0 {
+            return false;
+        }
+        let mut env = x87_env();
+        let control = (word(&env, CONTROL) & !(FE_TOWARDZERO as u16)) | round as u16;
+        set_word(&mut env, CONTROL, control);
+        set_x87_env(&env);
+        let mode = (FE_TOWARDZERO as u32) << MXCSR_ROUND_SHIFT;
+        set_mxcsr((mxcsr() & !mode) | ((round as u32) << MXCSR_ROUND_SHIFT));
+        true
+    }
+
+    pub fn get_env() -> FloatEnv {
+        let mut bytes = [0u8; FENV_SIZE];
+        bytes[..X87_ENV_SIZE].copy_from_slice(&x87_env());
+        bytes[X87_ENV_SIZE..].copy_from_slice(&mxcsr().to_le_bytes());
+        FloatEnv { bytes }
+    }
+
+    pub fn set_env(env: &FloatEnv) {
+        let mut x87 = [0u8; X87_ENV_SIZE];
+        x87.copy_from_slice(&env.bytes[..X87_ENV_SIZE]);
+        set_x87_env(&x87);
+        set_mxcsr(u32::from_le_bytes(env.bytes[X87_ENV_SIZE..].try_into().unwrap()));
+    }
+
+    pub fn default_env() -> FloatEnv {
+        let mut x87 = x87_env();
+        set_word(&mut x87, CONTROL, X87_CONTROL_DEFAULT);
+        set_word(&mut x87, STATUS, 0);
+        set_word(&mut x87, TAGS, 0xffff);
+        let mut bytes = [0u8; FENV_SIZE];
+        bytes[..X87_ENV_SIZE].copy_from_slice(&x87);
+        bytes[X87_ENV_SIZE..].copy_from_slice(&MXCSR_DEFAULT.to_le_bytes());
+        FloatEnv { bytes }
+    }
+
+    /// Non-stop mode: every exception masked
+    pub fn mask_all() {
+        let mut env = x87_env();
+        let control = word(&env, CONTROL) | 0x3f;
+        set_word(&mut env, CONTROL, control);
+        set_x87_env(&env);
+        set_mxcsr(mxcsr() | MXCSR_DEFAULT);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod host {
+    use super::*;
+
+    const FPCR_TRAP_SHIFT: u32 = 8;
+
+    fn fpcr() -> u64 {
+        let value: u64;
+        unsafe { asm!("mrs {}, fpcr", out(reg) value, options(nomem, nostack, preserves_flags)) };
+        value
+    }
+
+    fn set_fpcr(value: u64) {
+        unsafe { asm!("msr fpcr, {}", in(reg) value, options(nomem, nostack, preserves_flags)) };
+    }
+
+    fn fpsr() -> u64 {
+        let value: u64;
+        unsafe { asm!("mrs {}, fpsr", out(reg) value, options(nomem, nostack, preserves_flags)) };
+        value
+    }
+
+    fn set_fpsr(value: u64) {
+        unsafe { asm!("msr fpsr, {}", in(reg) value, options(nomem, nostack, preserves_flags)) };
+    }
+
+    pub fn test(excepts: i32) -> i32 {
+        (fpsr() as i32) & excepts & FE_ALL_EXCEPT
+    }
+
+    pub fn clear(excepts: i32) {
+        set_fpsr(fpsr() & !((excepts & FE_ALL_EXCEPT) as u64));
+    }
+
+    pub fn raise(excepts: i32) {
+        set_fpsr(fpsr() | (excepts & FE_ALL_EXCEPT) as u64);
+    }
+
+    pub fn round() -> i32 {
+        (fpcr() as i32) & FE_TOWARDZERO
+    }
+
+    pub fn set_round(round: i32) -> bool {
+        if round & !FE_TOWARDZERO != 0 {
+            return false;
+        }
+        set_fpcr((fpcr() & !(FE_TOWARDZERO as u64)) | round as u64);
+        true
+    }
+
+    pub fn get_env() -> FloatEnv {
+        let mut bytes = [0u8; FENV_SIZE];
+        bytes[..4].copy_from_slice(&(fpcr() as u32).to_le_bytes());
+        bytes[4..].copy_from_slice(&(fpsr() as u32).to_le_bytes());
+        FloatEnv { bytes }
+    }
+
+    pub fn set_env(env: &FloatEnv) {
+        set_fpcr(u32::from_le_bytes(env.bytes[..4].try_into().unwrap()) as u64);
+        set_fpsr(u32::from_le_bytes(env.bytes[4..].try_into().unwrap()) as u64);
+    }
+
+    pub fn default_env() -> FloatEnv {
+        FloatEnv { bytes: [0u8; FENV_SIZE] }
+    }
+
+    /// Non-stop mode: every trap disabled
+    pub fn mask_all() {
+        set_fpcr(fpcr() & !((FE_ALL_EXCEPT as u64) << FPCR_TRAP_SHIFT));
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod host {
+    use super::*;
+
+    pub fn test(_excepts: i32) -> i32 {
+        0
+    }
+
+    pub fn clear(_excepts: i32) {}
+
+    pub fn raise(_excepts: i32) {}
+
+    pub fn round() -> i32 {
+        FE_TONEAREST
+    }
+
+    pub fn set_round(round: i32) -> bool {
+        round == FE_TONEAREST
+    }
+
+    pub fn get_env() -> FloatEnv {
+        default_env()
+    }
+
+    pub fn set_env(_env: &FloatEnv) {}
+
+    pub fn default_env() -> FloatEnv {
+        FloatEnv { bytes: [0u8; FENV_SIZE] }
+    }
+
+    pub fn mask_all() {}
+}
+
+pub fn feclearexcept(excepts: i32) -> i32 {
+    host::clear(excepts);
+    0
+}
+
+pub fn fetestexcept(excepts: i32) -> i32 {
+    host::test(excepts)
+}
+
+/// Sets the sticky flags; like the bundled libc, unmasked exceptions don't trap
+pub fn feraiseexcept(excepts: i32) -> i32 {
+    host::raise(excepts);
+    0
+}
+
+pub fn fegetexceptflag(excepts: i32) -> i32 {
+    host::test(excepts)
+}
+
+pub fn fesetexceptflag(flags: i32, excepts: i32) -> i32 {
+    host::clear(excepts);
+    host::raise(flags & excepts);
+    0
+}
+
+pub fn fegetround() -> i32 {
+    host::round()
+}
+
+/// Nonzero for anything but the host's FE_* rounding modes, as C requires
+pub fn fesetround(round: i32) -> i32 {
+    if host::set_round(round) {
+        0
+    } else {
+        1
+    }
+}
+
+pub fn fegetenv() -> FloatEnv {
+    host::get_env()
+}
+
+/// `None` is FE_DFL_ENV
+pub fn fesetenv(env: Option<&FloatEnv>) -> i32 {
+    host::set_env(&env.copied().unwrap_or_else(host::default_env));
+    0
+}
+
+pub fn feholdexcept() -> FloatEnv {
+    let saved = host::get_env();
+    host::clear(FE_ALL_EXCEPT);
+    host::mask_all();
+    saved
+}
+
+pub fn feupdateenv(env: Option<&FloatEnv>) -> i32 {
+    let raised = host::test(FE_ALL_EXCEPT);
+    fesetenv(env);
+    host::raise(raised);
+    0
+}
+
+/// The <fenv.h> functions, which interpreters handle themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenvCall {
+    ClearExcept,
+    GetExceptFlag,
+    RaiseExcept,
+    SetExceptFlag,
+    TestExcept,
+    GetRound,
+    SetRound,
+    GetEnv,
+    HoldExcept,
+    SetEnv,
+    UpdateEnv,
+}
+
+impl FenvCall {
+    pub fn of(name: &str) -> Option<Self> {
+        Some(match name {
+            "feclearexcept" => FenvCall::ClearExcept,
+            "fegetexceptflag" => FenvCall::GetExceptFlag,
+            "feraiseexcept" => FenvCall::RaiseExcept,
+            "fesetexceptflag" => FenvCall::SetExceptFlag,
+            "fetestexcept" => FenvCall::TestExcept,
+            "fegetround" => FenvCall::GetRound,
+            "fesetround" => FenvCall::SetRound,
+            "fegetenv" => FenvCall::GetEnv,
+            "feholdexcept" => FenvCall::HoldExcept,
+            "fesetenv" => FenvCall::SetEnv,
+            "feupdateenv" => FenvCall::UpdateEnv,
+            _ => return None,
+        })
+    }
+
+    /// Make the call with C's arguments, as integers. `fenv_t *` and
+    /// `fexcept_t *` arguments must be valid, except for FE_DFL_ENV.
+    pub unsafe fn call(self, arguments: &[u64]) -> i32 {
+        let argument = |index: usize| arguments.get(index).copied().unwrap_or(0);
+        let int = |index: usize| argument(index) as i32;
+        let env = |index: usize| match argument(index) {
+            FE_DFL_ENV => None,
+            ptr => Some(FloatEnv::read(ptr as *const u8)),
+        };
+        match self {
+            FenvCall::ClearExcept => feclearexcept(int(0)),
+            FenvCall::GetExceptFlag => {
+                let flags = (fegetexceptflag(int(1)) as u32).to_le_bytes();
+                std::ptr::copy_nonoverlapping(flags.as_ptr(), argument(0) as *mut u8, FEXCEPT_SIZE);
+                0
+            }
+            FenvCall::RaiseExcept => feraiseexcept(int(0)),
+            FenvCall::SetExceptFlag => {
+                let mut flags = [0u8; 4];
+                std::ptr::copy_nonoverlapping(argument(0) as *const u8, flags.as_mut_ptr(), FEXCEPT_SIZE);
+                fesetexceptflag(u32::from_le_bytes(flags) as i32, int(1))
+            }
+            FenvCall::TestExcept => fetestexcept(int(0)),
+            FenvCall::GetRound => fegetround(),
+            FenvCall::SetRound => fesetround(int(0)),
+            FenvCall::GetEnv => {
+                fegetenv().write(argument(0) as *mut u8);
+                0
+            }
+            FenvCall::HoldExcept => {
+                feholdexcept().write(argument(0) as *mut u8);
+                0
+            }
+            FenvCall::SetEnv => fesetenv(env(0).as_ref()),
+            FenvCall::UpdateEnv => feupdateenv(env(0).as_ref()),
+        }
+    }
+}
+
+/// `FE_DFL_ENV`, `(const fenv_t *)-1` in the C headers
+pub const FE_DFL_ENV: u64 = u64::MAX;
+
+impl FloatEnv {
+    /// Read a `fenv_t` from program memory
+    pub unsafe fn read(ptr: *const u8) -> FloatEnv {
+        let mut bytes = [0u8; FENV_SIZE];
+        std::ptr::copy_nonoverlapping(ptr, bytes.as_mut_ptr(), FENV_SIZE);
+        FloatEnv { bytes }
+    }
+
+    /// Store as a `fenv_t` in program memory
+    pub unsafe fn write(&self, ptr: *mut u8) {
+        std::ptr::copy_nonoverlapping(self.bytes.as_ptr(), ptr, FENV_SIZE);
+    }
+}
+
+/// Gives a program the default environment and restores the host's on drop
+pub struct FenvSession {
+    host: FloatEnv,
+}
+
+impl FenvSession {
+    pub fn begin() -> Self {
+        let host = host::get_env();
+        host::set_env(&host::default_env());
+        FenvSession { host }
+    }
+}
+
+impl Drop for FenvSession {
+    fn drop(&mut self) {
+        host::set_env(&self.host);
+    }
+}
+
+// Example usage:
+/*
+fn main() {
+    let _session = FenvSession::begin();
+
+    fesetround(FE_UPWARD);
+    let third = std::hint::black_box(1.0f64) / 3.0;
+    assert_eq!(fegetround(), FE_UPWARD);
+    assert_ne!(fetestexcept(FE_INEXACT), 0);
+    println!("{:.20}", third);
+}
+*/
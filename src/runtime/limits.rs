@@ -0,0 +1,257 @@
+// src/runtime/limits.rs
+//! Resource limits for interpreted programs
+//! A `ResourceLimiter` bounds what a guest may use: wall-clock and CPU
+//! time, heap bytes live at once, host stack taken by the interpreter's
+//! recursion, and bytes written to stdout. The interpreter reports each use
+//! through the `CRuntimeEnvironment` hooks; the first limit crossed ends the
+//! program with `LimitExceeded` instead of hanging or exhausting the host.
+//!
+//! A program stopped by a limit exits the way one the kernel stopped for
+//! the matching rlimit would: SIGXCPU for time, SIGXFSZ for output and
+//! SIGKILL for memory, so scripts see the same status either way.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Statements between two reads of the clocks
+const CHECK_INTERVAL: u32 = 1024;
+
+/// What a guest may use; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Real time since the program started (`--max-time`)
+    pub wall_time: Option<Duration>,
+    /// CPU time of this process since the program started (`--max-cpu-time`)
+    pub cpu_time: Option<Duration>,
+    /// Heap bytes allocated and not yet freed (`--max-memory`)
+    pub heap: Option<usize>,
+    /// Host stack below the program's entry (`--max-stack`)
+    pub stack: Option<usize>,
+    /// Bytes written to stdout (`--max-output`)
+    pub output: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == ResourceLimits::default()
+    }
+}
+
+/// What the program had used when it was last checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub wall_time: Duration,
+    pub cpu_time: Duration,
+    pub peak_heap: usize,
+    pub peak_stack: usize,
+    pub output: u64,
+}
+
+pub struct ResourceLimiter {
+    limits: ResourceLimits,
+
+    // Set by `start`
+    started: Option<Instant>,
+    cpu_started: Duration,
+    stack_base: usize,
+
+    // Usage
+    usage: ResourceUsage,
+    heap: usize,
+    statements: u32,
+}
+
+impl ResourceLimiter {
+    pub fn new(limits: ResourceLimits) -> Self {
+        ResourceLimiter {
+            limits,
+            started: None,
+            cpu_started: Duration::ZERO,
+            stack_base: 0,
+            usage: ResourceUsage::default(),
+            heap: 0,
+            statements: 0,
+        }
+    }
+
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.limits
+    }
+
+    pub fn usage(&self) -> ResourceUsage {
+        self.usage
+    }
+
+    /// Start the clocks, and measure the stack from the caller's frame
+    #[inline(never)]
+    pub fn start(&mut self) {
+        self.started = Some(Instant::now());
+        self.cpu_started = process_cpu_time();
+        self.stack_base = stack_pointer();
+    }
+
+    /// Called before every statement; reads the clocks every `CHECK_INTERVAL`
+    pub fn statement(&mut self) -> Result<(), LimitExceeded> {
+        self.statements += 1;
+        if self.statements < CHECK_INTERVAL {
+            return Ok(());
+        }
+        self.statements = 0;
+        self.check_time()
+    }
+
+    pub fn check_time(&mut self) -> Result<(), LimitExceeded> {
+        let Some(started) = self.started else { return Ok(()) };
+        self.usage.wall_time = started.elapsed();
+        if let Some(limit) = self.limits.wall_time.filter(|&limit| self.usage.wall_time > limit) {
+            return Err(LimitExceeded::WallTime(limit));
+        }
+        if let Some(limit) = self.limits.cpu_time {
+            self.usage.cpu_time = process_cpu_time().saturating_sub(self.cpu_started);
+            if self.usage.cpu_time > limit {
+                return Err(LimitExceeded::CpuTime(limit));
+            }
+        }
+        Ok(())
+    }
+
+    /// Called on entry to every interpreted function, whose recursion is
+    /// the interpreter's
+    #[inline(never)]
+    pub fn enter_function(&mut self) -> Result<(), LimitExceeded> {
+        if self.started.is_none() {
+            return Ok(());
+        }
+        // The stack grows down on every supported host
+        let used = self.stack_base.saturating_sub(stack_pointer());
+        self.usage.peak_stack = self.usage.peak_stack.max(used);
+        match self.limits.stack {
+            Some(limit) if used > limit => Err(LimitExceeded::Stack(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// `bytes` more of heap are about to be live (`malloc`, `calloc`, or
+    /// `realloc` growing a block). On error nothing is charged.
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), LimitExceeded> {
+        let live = self.heap.saturating_add(bytes);
+        if let Some(limit) = self.limits.heap.filter(|&limit| live > limit) {
+            return Err(LimitExceeded::Heap { limit, requested: bytes });
+        }
+        self.heap = live;
+        self.usage.peak_heap = self.usage.peak_heap.max(live);
+        Ok(())
+    }
+
+    /// `bytes` of heap were freed
+    pub fn free(&mut self, bytes: usize) {
+        self.heap = self.heap.saturating_sub(bytes);
+    }
+
+    /// `bytes` are about to be written to stdout. A write that would cross
+    /// the limit is refused whole.
+    pub fn output(&mut self, bytes: usize) -> Result<(), LimitExceeded> {
+        let written = self.usage.output.saturating_add(bytes as u64);
+        if let Some(limit) = self.limits.output.filter(|&limit| written > limit) {
+            return Err(LimitExceeded::Output(limit));
+        }
+        self.usage.output = written;
+        Ok(())
+    }
+}
+
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+fn process_cpu_time() -> Duration {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut time) };
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// `30`, `1.5s`, `500ms` or `2m`; a bare number is seconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let value: f64 = number.parse().map_err(|_| format!("invalid duration '{}'", text))?;
+    let seconds = match unit {
+        "" | "s" => value,
+        "ms" => value / 1000.0,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("invalid duration '{}': use s, ms, m or h", text)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration '{}'", text))
+}
+
+/// `4096`, `512K`, `64M` or `2G` (powers of 1024)
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let (number, shift) = match text.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&text[..index], 10),
+        Some((index, 'M' | 'm')) => (&text[..index], 20),
+        Some((index, 'G' | 'g')) => (&text[..index], 30),
+        _ => (text, 0),
+    };
+    let value: u64 = number.parse().map_err(|_| format!("invalid size '{}'", text))?;
+    value.checked_mul(1 << shift).ok_or_else(|| format!("size '{}' is too large", text))
+}
+
+/// The limit a program crossed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    WallTime(Duration),
+    CpuTime(Duration),
+    Heap { limit: usize, requested: usize },
+    Stack(usize),
+    Output(u64),
+}
+
+impl LimitExceeded {
+    /// The signal the kernel would end the program with for the matching rlimit
+    pub fn signal(&self) -> i32 {
+        match self {
+            LimitExceeded::WallTime(_) | LimitExceeded::CpuTime(_) => libc::SIGXCPU,
+            LimitExceeded::Output(_) => libc::SIGXFSZ,
+            LimitExceeded::Heap { .. } | LimitExceeded::Stack(_) => libc::SIGKILL,
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::WallTime(limit) => write!(f, "program ran longer than {:?} (--max-time)", limit),
+            LimitExceeded::CpuTime(limit) => write!(f, "program used more than {:?} of CPU time (--max-cpu-time)", limit),
+            LimitExceeded::Heap { limit, requested } => write!(
+                f,
+                "allocating {} bytes would take the heap over {} bytes (--max-memory)",
+                requested, limit
+            ),
+            LimitExceeded::Stack(limit) => write!(f, "stack grew past {} bytes (--max-stack)", limit),
+            LimitExceeded::Output(limit) => write!(f, "program wrote more than {} bytes to stdout (--max-output)", limit),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn example(runtime: &mut CRuntimeEnvironment, ast: &TranslationUnit) {
+    runtime.set_limits(ResourceLimits {
+        wall_time: Some(Duration::from_secs(10)),
+        heap: Some(256 << 20),
+        output: Some(1 << 20),
+        ..ResourceLimits::default()
+    });
+
+    match runtime.execute(ast) {
+        Err(RuntimeError::Limit(exceeded)) => eprintln!("Error: {}", exceeded),
+        _ => {}
+    }
+    println!("{:?}", runtime.resource_usage());
+}
+*/
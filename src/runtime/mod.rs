@@ -4,6 +4,30 @@ use std::collections::HashMap;
 use parking_lot::RwLock;
 use nix::sys::mman::*;
 use nix::sys::syscall;
+use crate::abi::aggregate::{marshal, Abi, Argument, CType};
+use crate::abi::call;
+use crate::abi::varargs::marshal_variadic;
+use crate::debug::stack_capture::JitSymbols;
+
+pub mod async_host;
+pub mod capture;
+pub mod coroutine;
+pub mod deterministic;
+pub mod dynamic_loader;
+pub mod exit_status;
+pub mod fenv;
+pub mod limits;
+pub mod output_mux;
+pub mod posix;
+pub mod setjmp;
+pub mod signal_safety;
+pub mod signals;
+pub mod stack_guard;
+pub mod stdio;
+pub mod varargs;
+pub mod virtual_time;
+pub mod vla;
+pub mod wasi;
 
 pub struct RuntimeSupport {
     // System call handling
@@ -20,6 +44,14 @@ pub struct RuntimeSupport {
     
     // Exception handling
     exception_handler: ExceptionHandler,
+
+    // Threads and their synchronization objects
+    posix: Arc<posix::POSIXModule>,
+
+    // Stack overflow recovery: the guarded stack's size, and names for
+    // the frames of its trace
+    guarded_stack: Option<usize>,
+    jit_symbols: Option<Arc<RwLock<JitSymbols>>>,
 }
 
 impl RuntimeSupport {
@@ -28,11 +60,35 @@ impl RuntimeSupport {
             syscall_handler: SyscallHandler::new()?,
             abi_handler: ABIHandler::new()?,
             function_table: RwLock::new(HashMap::new()),
+            posix: Arc::new(posix::POSIXModule::new(Arc::clone(&memory_manager))),
             memory_manager,
             exception_handler: ExceptionHandler::new()?,
+            guarded_stack: None,
+            jit_symbols: None,
         })
     }
 
+    /// Run compiled code on a stack of `stack_size` bytes between guard
+    /// pages, so that overflowing it fails the call with
+    /// `RuntimeError::StackOverflow` (`JITOptions::enable_guard_pages`)
+    pub fn with_guard_pages(mut self, stack_size: usize) -> Result<Self, RuntimeError> {
+        stack_guard::install()?;
+        self.guarded_stack = Some(stack_size);
+        Ok(self)
+    }
+
+    /// Name JIT-compiled functions in stack overflow traces
+    pub fn with_jit_symbols(mut self, symbols: Arc<RwLock<JitSymbols>>) -> Self {
+        self.jit_symbols = Some(symbols);
+        self
+    }
+
+    /// pthreads for the program. Threads share `function_table`, which is
+    /// why it is behind a lock.
+    pub fn posix(&self) -> &Arc<posix::POSIXModule> {
+        &self.posix
+    }
+
     pub unsafe fn execute_function(
         &self,
         func_ptr: *const u8,
@@ -52,6 +108,45 @@ impl RuntimeSupport {
         self.abi_handler.convert_return(result, ret_type)
     }
 
+    /// Call a function that takes or returns structs or unions by value,
+    /// classifying them for the host ABI. Returns the result's bytes, laid
+    /// out as `ret` (`None` for `void`).
+    pub unsafe fn execute_aggregate_function(
+        &self,
+        func_ptr: *const u8,
+        args: &[Argument],
+        ret: Option<&CType>
+    ) -> Result<Vec<u8>, RuntimeError> {
+        let abi = Abi::host().ok_or_else(|| RuntimeError::ABIError("unsupported host".to_string()))?;
+        let call = marshal(abi, args, ret).map_err(|e| RuntimeError::ABIError(format!("{:?}", e)))?;
+
+        let guard = self.exception_handler.guard(func_ptr)?;
+
+        let mut bytes = call::invoke(func_ptr, &call).map_err(|e| RuntimeError::ABIError(format!("{:?}", e)))?;
+        bytes.truncate(ret.map_or(0, CType::size));
+        Ok(bytes)
+    }
+
+    /// Call a variadic function such as `printf` with `fixed` named
+    /// arguments followed by the variadic ones, which get the default
+    /// argument promotions
+    pub unsafe fn execute_variadic_function(
+        &self,
+        func_ptr: *const u8,
+        args: &[Argument],
+        fixed: usize,
+        ret: Option<&CType>
+    ) -> Result<Vec<u8>, RuntimeError> {
+        let abi = Abi::host().ok_or_else(|| RuntimeError::ABIError("unsupported host".to_string()))?;
+        let call = marshal_variadic(abi, args, fixed, ret).map_err(|e| RuntimeError::ABIError(format!("{:?}", e)))?;
+
+        let guard = self.exception_handler.guard(func_ptr)?;
+
+        let mut bytes = call::invoke(func_ptr, &call).map_err(|e| RuntimeError::ABIError(format!("{:?}", e)))?;
+        bytes.truncate(ret.map_or(0, CType::size));
+        Ok(bytes)
+    }
+
     unsafe fn call_function(
         &self,
         func_ptr: *const u8,
@@ -59,8 +154,20 @@ impl RuntimeSupport {
     ) -> Result<u64, RuntimeError> {
         let func: extern "C" fn(*const CallFrame) -> u64 = std::mem::transmute(func_ptr);
         
-        // Call with frame pointer
-        Ok(func(frame))
+        // Call with frame pointer; a `longjmp` to interpreted code comes back here
+        let call = || setjmp::call_native(|| func(frame));
+        let result = match self.guarded_stack {
+            Some(stack_size) => stack_guard::run_guarded(stack_size, call)?,
+            None => call(),
+        };
+        result.map_err(|interrupted| match interrupted {
+            setjmp::Interrupted::LongJump(pending) => RuntimeError::NativeLongJump(pending),
+            setjmp::Interrupted::Fault(signal) => RuntimeError::NativeFault(signal),
+            setjmp::Interrupted::StackOverflow(address) => {
+                let symbols = self.jit_symbols.as_ref().map(|symbols| symbols.read());
+                RuntimeError::StackOverflow(stack_guard::take_overflow(address, symbols.as_deref()))
+            }
+        })
     }
 
     pub unsafe fn handle_syscall(
@@ -262,8 +369,10 @@ impl ABIHandler {
             },
             ReturnType::Pointer => Ok(value),
             ReturnType::Struct { size } => {
-                // Handle struct return through memory
-                self.handle_struct_return(value, size)
+                // One u64 can't carry a struct; the caller must classify it
+                Err(RuntimeError::ABIError(format!(
+                    "{}-byte struct result: use execute_aggregate_function", size
+                )))
             },
         }
     }
@@ -299,6 +408,13 @@ pub enum RuntimeError {
     MemoryError(String),
     ABIError(String),
     ExceptionError(String),
+    /// Compiled code `longjmp`ed to a `jmp_buf` of the interpreted caller
+    NativeLongJump(setjmp::PendingLongJump),
+    /// Compiled code faulted and the handler is interpreted; the signal is
+    /// pending for `CRuntimeEnvironment::check_signals`
+    NativeFault(i32),
+    /// Compiled code overflowed its guarded stack
+    StackOverflow(stack_guard::StackOverflow),
 }
 
 // Example usage:
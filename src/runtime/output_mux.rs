@@ -0,0 +1,279 @@
+// src/runtime/output_mux.rs
+//! Interleave-safe output for concurrently running programs
+//! Each program (or test) writes through its own handle; complete lines are
+//! tagged with the program id, stream and timestamp and written by a single
+//! sink thread, so lines never tear and per-stream ordering is preserved.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefixConfig {
+    /// Prefix each line with the program/test id
+    pub show_id: bool,
+    /// Prefix each line with the time since the multiplexer started
+    pub show_timestamp: bool,
+    /// Prefix each line with the stream it was written to
+    pub show_stream: bool,
+}
+
+impl Default for PrefixConfig {
+    fn default() -> Self {
+        PrefixConfig {
+            show_id: true,
+            show_timestamp: false,
+            show_stream: false,
+        }
+    }
+}
+
+enum MuxMessage {
+    Data {
+        source: String,
+        stream: OutputStream,
+        data: Vec<u8>,
+        at: Instant,
+    },
+    Close {
+        source: String,
+        stream: OutputStream,
+    },
+    Shutdown,
+}
+
+pub struct OutputMultiplexer {
+    // Channel into the sink thread
+    sender: Sender<MuxMessage>,
+
+    // Sink thread, joined on shutdown
+    sink: Option<JoinHandle<()>>,
+}
+
+impl OutputMultiplexer {
+    /// Create a multiplexer writing to the host's stdout and stderr
+    pub fn new(config: PrefixConfig) -> Self {
+        Self::with_writers(config, Box::new(io::stdout()), Box::new(io::stderr()))
+    }
+
+    pub fn with_writers(
+        config: PrefixConfig,
+        stdout: Box<dyn Write + Send>,
+        stderr: Box<dyn Write + Send>,
+    ) -> Self {
+        let (sender, receiver) = channel();
+        let start = Instant::now();
+
+        let sink = std::thread::spawn(move || {
+            let mut sink = OutputSink {
+                config,
+                start,
+                stdout,
+                stderr,
+                partial: HashMap::new(),
+            };
+            sink.run(receiver);
+        });
+
+        OutputMultiplexer {
+            sender,
+            sink: Some(sink),
+        }
+    }
+
+    /// Create a writer for one stream of one program
+    pub fn handle(&self, source: &str, stream: OutputStream) -> OutputHandle {
+        OutputHandle {
+            source: source.to_string(),
+            stream,
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Echo what `from` yields as `stream` of `source` until it ends, e.g.
+    /// the read end of a child process's stdout pipe
+    pub fn forward(&self, source: &str, stream: OutputStream, mut from: impl Read + Send + 'static) -> JoinHandle<()> {
+        let mut handle = self.handle(source, stream);
+        std::thread::spawn(move || {
+            let _ = io::copy(&mut from, &mut handle);
+        })
+    }
+
+    /// Flush all pending partial lines and stop the sink thread
+    pub fn shutdown(&mut self) {
+        let _ = self.sender.send(MuxMessage::Shutdown);
+        if let Some(sink) = self.sink.take() {
+            let _ = sink.join();
+        }
+    }
+}
+
+impl Drop for OutputMultiplexer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Writer for a single program stream. Partial lines are held by the sink
+/// until a newline arrives or the handle is dropped.
+pub struct OutputHandle {
+    source: String,
+    stream: OutputStream,
+    sender: Sender<MuxMessage>,
+}
+
+impl Write for OutputHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .send(MuxMessage::Data {
+                source: self.source.clone(),
+                stream: self.stream,
+                data: buf.to_vec(),
+                at: Instant::now(),
+            })
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output multiplexer closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for OutputHandle {
+    fn drop(&mut self) {
+        let _ = self.sender.send(MuxMessage::Close {
+            source: self.source.clone(),
+            stream: self.stream,
+        });
+    }
+}
+
+struct OutputSink {
+    config: PrefixConfig,
+    start: Instant,
+    stdout: Box<dyn Write + Send>,
+    stderr: Box<dyn Write + Send>,
+
+    // Incomplete trailing lines per (source, stream)
+    partial: HashMap<(String, OutputStream), (Vec<u8>, Instant)>,
+}
+
+impl OutputSink {
+    fn run(&mut self, receiver: Receiver<MuxMessage>) {
+        while let Ok(message) = receiver.recv() {
+            match message {
+                MuxMessage::Data { source, stream, data, at } => {
+                    self.accept(source, stream, &data, at);
+                }
+                MuxMessage::Close { source, stream } => {
+                    self.flush_partial(&(source, stream));
+                }
+                MuxMessage::Shutdown => break,
+            }
+        }
+
+        // Emit whatever is left, in a stable order
+        let mut keys: Vec<_> = self.partial.keys().cloned().collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        for key in keys {
+            self.flush_partial(&key);
+        }
+        let _ = self.stdout.flush();
+        let _ = self.stderr.flush();
+    }
+
+    fn accept(&mut self, source: String, stream: OutputStream, data: &[u8], at: Instant) {
+        let key = (source, stream);
+        let (mut buffer, first_at) = self.partial.remove(&key).unwrap_or((Vec::new(), at));
+        buffer.extend_from_slice(data);
+
+        let mut line_start = 0;
+        let mut line_at = first_at;
+        while let Some(pos) = buffer[line_start..].iter().position(|&b| b == b'\n') {
+            let end = line_start + pos;
+            let line = buffer[line_start..end].to_vec();
+            self.emit(&key.0, stream, &line, line_at);
+            line_start = end + 1;
+            line_at = at;
+        }
+
+        if line_start < buffer.len() {
+            self.partial.insert(key, (buffer[line_start..].to_vec(), line_at));
+        }
+    }
+
+    fn flush_partial(&mut self, key: &(String, OutputStream)) {
+        if let Some((line, at)) = self.partial.remove(key) {
+            self.emit(&key.0, key.1, &line, at);
+        }
+    }
+
+    fn emit(&mut self, source: &str, stream: OutputStream, line: &[u8], at: Instant) {
+        let mut prefix = String::new();
+        if self.config.show_timestamp {
+            prefix.push_str(&format_elapsed(at.saturating_duration_since(self.start)));
+            prefix.push(' ');
+        }
+        if self.config.show_id {
+            prefix.push_str(&format!("[{}] ", source));
+        }
+        if self.config.show_stream {
+            prefix.push_str(match stream {
+                OutputStream::Stdout => "out| ",
+                OutputStream::Stderr => "err| ",
+            });
+        }
+
+        // One write, so the line can't tear against the host's own output
+        let mut text = prefix.into_bytes();
+        text.extend_from_slice(line);
+        text.push(b'\n');
+        let out = match stream {
+            OutputStream::Stdout => &mut self.stdout,
+            OutputStream::Stderr => &mut self.stderr,
+        };
+        let _ = out.write_all(&text);
+    }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let millis = elapsed.as_millis();
+    format!(
+        "{:02}:{:02}.{:03}",
+        millis / 60_000,
+        (millis / 1000) % 60,
+        millis % 1000
+    )
+}
+
+// Example usage:
+/*
+fn main() {
+    let mut mux = OutputMultiplexer::new(PrefixConfig {
+        show_id: true,
+        show_timestamp: true,
+        show_stream: false,
+    });
+
+    let mut a = mux.handle("test_alloc", OutputStream::Stdout);
+    let mut b = mux.handle("test_strings", OutputStream::Stdout);
+
+    // Written concurrently, printed as whole lines:
+    //   00:00.001 [test_alloc] hello from a
+    //   00:00.001 [test_strings] hello from b
+    write!(a, "hello ").unwrap();
+    writeln!(b, "hello from b").unwrap();
+    writeln!(a, "from a").unwrap();
+
+    mux.shutdown();
+}
+*/
@@ -0,0 +1,833 @@
+// src/runtime/posix.rs
+//! POSIX threads
+//! `pthread_create` runs compiled start routines on a host pthread and
+//! interpreted ones on a `std::thread` that drives its own interpreter
+//! activation. Either way the thread's C stack comes from the JIT's
+//! `MemoryManager`, with a guard page at the bottom: for compiled code it
+//! is the machine stack, for interpreted code the memory its automatic
+//! variables live in.
+//!
+//! Mutexes, condition variables, rwlocks and TLS keys are runtime objects
+//! keyed by the address of the C object (`pthread_mutex_t *` and so on),
+//! created on first use so that `PTHREAD_MUTEX_INITIALIZER` and friends
+//! work without an `_init` call. Thread ids are small integers, the main
+//! thread being 1.
+//!
+//! Code shared between threads, the function table and globals, stays in
+//! `RuntimeSupport` behind its locks; `multithreaded` tells the interpreter
+//! when it must start taking them.
+//!
+//! In deterministic mode (`set_deterministic`) threads take turns instead
+//! of running at once, and a thread that would block hands its turn on; see
+//! `deterministic::OrderedScheduler`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use parking_lot::RwLock;
+use crate::jit::memory::MemoryManager;
+use super::deterministic::Determinism;
+
+/// glibc's default, and what `pthread_attr_init` reports
+pub const DEFAULT_STACK_SIZE: usize = 8 << 20;
+/// `PTHREAD_STACK_MIN` on the supported hosts
+pub const MIN_STACK_SIZE: usize = 16 << 10;
+/// `PTHREAD_DESTRUCTOR_ITERATIONS`
+pub const DESTRUCTOR_ITERATIONS: usize = 4;
+/// `PTHREAD_KEYS_MAX` in glibc
+pub const KEYS_MAX: usize = 1024;
+
+pub type ThreadId = u64;
+pub const MAIN_THREAD: ThreadId = 1;
+
+thread_local! {
+    static CURRENT: Cell<ThreadId> = const { Cell::new(MAIN_THREAD) };
+    /// This thread's value for each TLS key
+    static VALUES: RefCell<HashMap<usize, u64>> = RefCell::new(HashMap::new());
+}
+
+/// `pthread_attr_t`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadAttributes {
+    pub stack_size: usize,
+    pub detached: bool,
+}
+
+impl Default for ThreadAttributes {
+    fn default() -> Self {
+        ThreadAttributes { stack_size: DEFAULT_STACK_SIZE, detached: false }
+    }
+}
+
+/// The C stack of a thread: `size` usable bytes above a guard page
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadStack {
+    pub id: ThreadId,
+    /// Lowest usable address
+    pub base: usize,
+    pub size: usize,
+    /// Start of the allocation, the guard page
+    mapping: usize,
+}
+
+/// What a new thread runs
+pub enum StartRoutine {
+    /// Compiled `void *(*)(void *)`
+    Native(extern "C" fn(*mut c_void) -> *mut c_void),
+    /// An interpreted start routine: the interpreter's closure calls it with
+    /// the thread's argument on a fresh activation using `stack`, runs
+    /// `exit_destructors`, and returns the routine's result or the value
+    /// given to `pthread_exit`
+    Interpreted(InterpretedStart),
+}
+
+pub type InterpretedStart = Box<dyn FnOnce(&ThreadStack, u64) -> u64 + Send>;
+
+/// A TLS key's destructor
+#[derive(Debug, Clone, Copy)]
+pub enum Destructor {
+    Native(extern "C" fn(*mut c_void)),
+    /// An interpreted function, as the interpreter represents function pointers
+    Interpreted(u64),
+}
+
+/// `PTHREAD_MUTEX_*` types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MutexKind {
+    #[default]
+    Normal,
+    Recursive,
+    ErrorCheck,
+}
+
+enum Running {
+    Native(libc::pthread_t),
+    Interpreted(JoinHandle<u64>),
+}
+
+struct Thread {
+    running: Running,
+    stack: ThreadStack,
+    detached: bool,
+    /// Set as the start routine returns, for reaping detached threads
+    finished: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct MutexState {
+    owner: Option<ThreadId>,
+    count: usize,
+}
+
+struct PthreadMutex {
+    kind: MutexKind,
+    state: Mutex<MutexState>,
+    released: Condvar,
+}
+
+struct PthreadCond {
+    /// Bumped by every signal and broadcast
+    sequence: Mutex<u64>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct RwState {
+    readers: usize,
+    writer: Option<ThreadId>,
+    waiting_writers: usize,
+}
+
+struct PthreadRwLock {
+    state: Mutex<RwState>,
+    changed: Condvar,
+}
+
+struct Shared {
+    memory: Arc<MemoryManager>,
+    threads: Mutex<HashMap<ThreadId, Thread>>,
+    next_thread: AtomicU64,
+    multithreaded: AtomicBool,
+    mutexes: Mutex<HashMap<usize, Arc<PthreadMutex>>>,
+    conds: Mutex<HashMap<usize, Arc<PthreadCond>>>,
+    rwlocks: Mutex<HashMap<usize, Arc<PthreadRwLock>>>,
+    /// Destructor of each key; `None` for a deleted key
+    keys: RwLock<Vec<Option<Option<Destructor>>>>,
+    /// Turn order and clock, in deterministic mode
+    determinism: OnceLock<Arc<Determinism>>,
+}
+
+pub struct POSIXModule {
+    shared: Arc<Shared>,
+}
+
+impl POSIXModule {
+    pub fn new(memory: Arc<MemoryManager>) -> Self {
+        POSIXModule {
+            shared: Arc::new(Shared {
+                memory,
+                threads: Mutex::new(HashMap::new()),
+                next_thread: AtomicU64::new(MAIN_THREAD + 1),
+                multithreaded: AtomicBool::new(false),
+                mutexes: Mutex::new(HashMap::new()),
+                conds: Mutex::new(HashMap::new()),
+                rwlocks: Mutex::new(HashMap::new()),
+                keys: RwLock::new(Vec::new()),
+                determinism: OnceLock::new(),
+            }),
+        }
+    }
+
+    /// Whether a second thread was ever started: from then on globals and
+    /// the function table are shared
+    pub fn multithreaded(&self) -> bool {
+        self.shared.multithreaded.load(Ordering::Acquire)
+    }
+
+    /// Run threads one at a time, in the order `determinism` gives them
+    /// turns. Must be called before the first `create`; only the first call
+    /// has an effect.
+    pub fn set_deterministic(&self, determinism: Arc<Determinism>) {
+        let _ = self.shared.determinism.set(determinism);
+    }
+
+    /// `pthread_self()`
+    pub fn current(&self) -> ThreadId {
+        current_thread()
+    }
+
+    /// `sched_yield()`
+    pub fn yield_now(&self) {
+        match self.shared.determinism.get() {
+            Some(determinism) => determinism.scheduler().yield_now(self.current()),
+            None => std::thread::yield_now(),
+        }
+    }
+
+    /// `pthread_create`
+    pub fn create(&self, start: StartRoutine, argument: u64, attributes: ThreadAttributes) -> Result<ThreadId, PosixError> {
+        if attributes.stack_size < MIN_STACK_SIZE {
+            return Err(PosixError::Invalid);
+        }
+        self.reap();
+        let id = self.shared.next_thread.fetch_add(1, Ordering::Relaxed);
+        let stack = unsafe { self.allocate_stack(id, attributes.stack_size)? };
+        let finished = Arc::new(AtomicBool::new(false));
+        self.shared.multithreaded.store(true, Ordering::Release);
+
+        let running = match start {
+            StartRoutine::Native(routine) => {
+                let entry = Box::into_raw(Box::new(NativeEntry {
+                    id,
+                    routine,
+                    argument,
+                    shared: Arc::clone(&self.shared),
+                    finished: Arc::clone(&finished),
+                }));
+                match unsafe { spawn_native(&stack, entry) } {
+                    Ok(handle) => Running::Native(handle),
+                    Err(e) => {
+                        drop(unsafe { Box::from_raw(entry) });
+                        unsafe { self.free_stack(&stack) };
+                        return Err(e);
+                    }
+                }
+            }
+            StartRoutine::Interpreted(routine) => {
+                let shared = Arc::clone(&self.shared);
+                let done = Arc::clone(&finished);
+                let spawned = std::thread::Builder::new().name(format!("c-thread-{}", id)).spawn(move || {
+                    CURRENT.with(|current| current.set(id));
+                    shared.begin(id);
+                    let result = routine(&stack, argument);
+                    shared.run_destructors();
+                    done.store(true, Ordering::Release);
+                    shared.end(id);
+                    result
+                });
+                match spawned {
+                    Ok(handle) => Running::Interpreted(handle),
+                    Err(_) => {
+                        unsafe { self.free_stack(&stack) };
+                        return Err(PosixError::Again);
+                    }
+                }
+            }
+        };
+
+        // The thread waits in `begin` until it is handed a turn
+        if let Some(determinism) = self.shared.determinism.get() {
+            determinism.scheduler().spawn(id);
+        }
+        let thread = Thread { running, stack, detached: attributes.detached, finished };
+        self.shared.threads.lock().unwrap().insert(id, thread);
+        Ok(id)
+    }
+
+    /// `pthread_join`: wait for `id` and return its result
+    pub fn join(&self, id: ThreadId) -> Result<u64, PosixError> {
+        if id == self.current() {
+            return Err(PosixError::Deadlock);
+        }
+        // Joining a host thread that hasn't had its turn would block the
+        // one that has: let it run until it ends
+        if let Some(determinism) = self.shared.determinism.get() {
+            loop {
+                let finished = match self.shared.threads.lock().unwrap().get(&id) {
+                    None => return Err(PosixError::NoSuchThread),
+                    Some(thread) if thread.detached => return Err(PosixError::Invalid),
+                    Some(thread) => thread.finished.load(Ordering::Acquire),
+                };
+                if finished {
+                    break;
+                }
+                determinism.stall(self.current(), None).map_err(|_| PosixError::Deadlock)?;
+            }
+        }
+        let thread = {
+            let mut threads = self.shared.threads.lock().unwrap();
+            match threads.get(&id) {
+                None => return Err(PosixError::NoSuchThread),
+                Some(thread) if thread.detached => return Err(PosixError::Invalid),
+                Some(_) => threads.remove(&id).unwrap(),
+            }
+        };
+        let result = unsafe { self.finish(thread) };
+        Ok(result)
+    }
+
+    /// `pthread_detach`: the thread's resources go when it ends
+    pub fn detach(&self, id: ThreadId) -> Result<(), PosixError> {
+        let mut threads = self.shared.threads.lock().unwrap();
+        let thread = threads.get_mut(&id).ok_or(PosixError::NoSuchThread)?;
+        if thread.detached {
+            return Err(PosixError::Invalid);
+        }
+        thread.detached = true;
+        Ok(())
+    }
+
+    /// Join the detached threads that have ended, to free their stacks.
+    /// The stack of a thread can't be freed while it is still on it, so this
+    /// waits for the host thread rather than freeing from the thread itself.
+    pub fn reap(&self) {
+        let ended: Vec<Thread> = {
+            let mut threads = self.shared.threads.lock().unwrap();
+            let ids: Vec<ThreadId> = threads
+                .iter()
+                .filter(|(_, thread)| thread.detached && thread.finished.load(Ordering::Acquire))
+                .map(|(&id, _)| id)
+                .collect();
+            ids.iter().filter_map(|id| threads.remove(id)).collect()
+        };
+        for thread in ended {
+            unsafe { self.finish(thread) };
+        }
+    }
+
+    unsafe fn finish(&self, thread: Thread) -> u64 {
+        let result = match thread.running {
+            Running::Native(handle) => {
+                let mut value: *mut c_void = std::ptr::null_mut();
+                libc::pthread_join(handle, &mut value);
+                value as u64
+            }
+            // A panic in the interpreter already reported itself
+            Running::Interpreted(handle) => handle.join().unwrap_or(0),
+        };
+        self.free_stack(&thread.stack);
+        result
+    }
+
+    unsafe fn allocate_stack(&self, id: ThreadId, size: usize) -> Result<ThreadStack, PosixError> {
+        let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        let size = size.div_ceil(page) * page;
+        let mapping = self.shared.memory.allocate_data(size + page).map_err(|e| PosixError::Memory(format!("{:?}", e)))?;
+        // Overflowing the stack faults instead of running into other memory
+        if libc::mprotect(mapping as *mut c_void, page, libc::PROT_NONE) != 0 {
+            let _ = self.shared.memory.free(mapping);
+            return Err(PosixError::Memory(std::io::Error::last_os_error().to_string()));
+        }
+        Ok(ThreadStack { id, base: mapping as usize + page, size, mapping: mapping as usize })
+    }
+
+    unsafe fn free_stack(&self, stack: &ThreadStack) {
+        // The allocation may go back to a pool: make the guard page usable
+        let guard = stack.base - stack.mapping;
+        libc::mprotect(stack.mapping as *mut c_void, guard, libc::PROT_READ | libc::PROT_WRITE);
+        let _ = self.shared.memory.free(stack.mapping as *mut u8);
+    }
+
+    /// `pthread_mutex_init` at `address`
+    pub fn mutex_init(&self, address: usize, kind: MutexKind) -> Result<(), PosixError> {
+        let mutex = Arc::new(PthreadMutex { kind, state: Mutex::new(MutexState::default()), released: Condvar::new() });
+        self.shared.mutexes.lock().unwrap().insert(address, mutex);
+        Ok(())
+    }
+
+    /// `pthread_mutex_destroy`
+    pub fn mutex_destroy(&self, address: usize) -> Result<(), PosixError> {
+        let mut mutexes = self.shared.mutexes.lock().unwrap();
+        if let Some(mutex) = mutexes.get(&address) {
+            if mutex.state.lock().unwrap().owner.is_some() {
+                return Err(PosixError::Busy);
+            }
+        }
+        mutexes.remove(&address);
+        Ok(())
+    }
+
+    /// `pthread_mutex_lock`
+    pub fn mutex_lock(&self, address: usize) -> Result<(), PosixError> {
+        self.lock_mutex(&self.mutex(address), true)
+    }
+
+    /// `pthread_mutex_trylock`
+    pub fn mutex_trylock(&self, address: usize) -> Result<(), PosixError> {
+        self.lock_mutex(&self.mutex(address), false)
+    }
+
+    /// `pthread_mutex_unlock`
+    pub fn mutex_unlock(&self, address: usize) -> Result<(), PosixError> {
+        let mutex = self.mutex(address);
+        let me = self.current();
+        let mut state = mutex.state.lock().unwrap();
+        if state.owner != Some(me) {
+            // Undefined for a normal mutex; an error beats corrupting it
+            return Err(PosixError::NotOwner);
+        }
+        state.count -= 1;
+        if state.count == 0 {
+            state.owner = None;
+            mutex.released.notify_one();
+            self.shared.progress();
+        }
+        Ok(())
+    }
+
+    fn mutex(&self, address: usize) -> Arc<PthreadMutex> {
+        let mut mutexes = self.shared.mutexes.lock().unwrap();
+        Arc::clone(mutexes.entry(address).or_insert_with(|| {
+            Arc::new(PthreadMutex { kind: MutexKind::Normal, state: Mutex::new(MutexState::default()), released: Condvar::new() })
+        }))
+    }
+
+    fn lock_mutex(&self, mutex: &PthreadMutex, wait: bool) -> Result<(), PosixError> {
+        let me = self.current();
+        let mut state = mutex.state.lock().unwrap();
+        if state.owner == Some(me) {
+            return match mutex.kind {
+                MutexKind::Recursive => {
+                    state.count += 1;
+                    Ok(())
+                }
+                MutexKind::ErrorCheck => Err(PosixError::Deadlock),
+                // Would hang forever; report it as errorcheck would
+                MutexKind::Normal if wait => Err(PosixError::Deadlock),
+                MutexKind::Normal => Err(PosixError::Busy),
+            };
+        }
+        while state.owner.is_some() {
+            if !wait {
+                return Err(PosixError::Busy);
+            }
+            state = match self.shared.determinism.get() {
+                Some(determinism) => {
+                    drop(state);
+                    determinism.stall(me, None).map_err(|_| PosixError::Deadlock)?;
+                    mutex.state.lock().unwrap()
+                }
+                None => mutex.released.wait(state).unwrap(),
+            };
+        }
+        state.owner = Some(me);
+        state.count = 1;
+        Ok(())
+    }
+
+    /// `pthread_cond_wait(cond, mutex)`, or `pthread_cond_timedwait` with a
+    /// `CLOCK_REALTIME` deadline
+    pub fn cond_wait(&self, cond: usize, mutex: usize, deadline: Option<libc::timespec>) -> Result<(), PosixError> {
+        let condition = self.cond(cond);
+        let lock = self.mutex(mutex);
+
+        // Take the sequence before releasing the mutex, so a signal sent by
+        // whoever takes the mutex next can't be missed
+        let sequence = condition.sequence.lock().unwrap();
+        let seen = *sequence;
+        let saved = {
+            let mut state = lock.state.lock().unwrap();
+            if state.owner != Some(self.current()) {
+                return Err(PosixError::NotOwner);
+            }
+            let count = state.count;
+            state.owner = None;
+            state.count = 0;
+            lock.released.notify_one();
+            count
+        };
+        self.shared.progress();
+
+        let mut timed_out = false;
+        let mut stalled = false;
+        let still = |current: &mut u64| *current == seen;
+        match (self.shared.determinism.get(), deadline) {
+            // Hand the turn on until a signal comes or virtual time passes
+            // the deadline
+            (Some(determinism), deadline) => {
+                drop(sequence);
+                let deadline = deadline.map(timespec_duration);
+                while *condition.sequence.lock().unwrap() == seen {
+                    if deadline.is_some_and(|deadline| determinism.clock().now() >= deadline) {
+                        timed_out = true;
+                        break;
+                    }
+                    if determinism.stall(self.current(), deadline).is_err() {
+                        stalled = true;
+                        break;
+                    }
+                }
+            }
+            (None, None) => drop(condition.changed.wait_while(sequence, still).unwrap()),
+            (None, Some(deadline)) => {
+                let (sequence, result) = condition.changed.wait_timeout_while(sequence, remaining(deadline), still).unwrap();
+                drop(sequence);
+                timed_out = result.timed_out();
+            }
+        }
+
+        // Reacquire at the same recursion depth, even after a timeout
+        self.lock_mutex(&lock, true)?;
+        lock.state.lock().unwrap().count = saved;
+        if timed_out {
+            return Err(PosixError::TimedOut);
+        }
+        if stalled {
+            return Err(PosixError::Deadlock);
+        }
+        Ok(())
+    }
+
+    /// `pthread_cond_signal`, or `pthread_cond_broadcast` with `all`
+    pub fn cond_signal(&self, cond: usize, all: bool) -> Result<(), PosixError> {
+        let condition = self.cond(cond);
+        *condition.sequence.lock().unwrap() += 1;
+        self.shared.progress();
+        if all {
+            condition.changed.notify_all();
+        } else {
+            condition.changed.notify_one();
+        }
+        Ok(())
+    }
+
+    /// `pthread_cond_destroy`
+    pub fn cond_destroy(&self, cond: usize) -> Result<(), PosixError> {
+        self.shared.conds.lock().unwrap().remove(&cond);
+        Ok(())
+    }
+
+    fn cond(&self, address: usize) -> Arc<PthreadCond> {
+        let mut conds = self.shared.conds.lock().unwrap();
+        Arc::clone(conds.entry(address).or_insert_with(|| Arc::new(PthreadCond { sequence: Mutex::new(0), changed: Condvar::new() })))
+    }
+
+    /// `pthread_rwlock_rdlock`, or `pthread_rwlock_tryrdlock` without `wait`.
+    /// Waiting writers go first, so readers can't starve them.
+    pub fn rwlock_read(&self, address: usize, wait: bool) -> Result<(), PosixError> {
+        let lock = self.rwlock(address);
+        let me = self.current();
+        let mut state = lock.state.lock().unwrap();
+        if state.writer == Some(me) {
+            return Err(PosixError::Deadlock);
+        }
+        while state.writer.is_some() || state.waiting_writers > 0 {
+            if !wait {
+                return Err(PosixError::Busy);
+            }
+            state = self.wait_rwlock(&lock, state, me)?;
+        }
+        state.readers += 1;
+        Ok(())
+    }
+
+    /// `pthread_rwlock_wrlock`, or `pthread_rwlock_trywrlock` without `wait`
+    pub fn rwlock_write(&self, address: usize, wait: bool) -> Result<(), PosixError> {
+        let lock = self.rwlock(address);
+        let me = self.current();
+        let mut state = lock.state.lock().unwrap();
+        if state.writer == Some(me) {
+            return Err(PosixError::Deadlock);
+        }
+        if state.writer.is_some() || state.readers > 0 {
+            if !wait {
+                return Err(PosixError::Busy);
+            }
+            state.waiting_writers += 1;
+            while state.writer.is_some() || state.readers > 0 {
+                state = match self.wait_rwlock(&lock, state, me) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        lock.state.lock().unwrap().waiting_writers -= 1;
+                        return Err(e);
+                    }
+                };
+            }
+            state.waiting_writers -= 1;
+        }
+        state.writer = Some(me);
+        Ok(())
+    }
+
+    /// `pthread_rwlock_unlock`
+    pub fn rwlock_unlock(&self, address: usize) -> Result<(), PosixError> {
+        let lock = self.rwlock(address);
+        let mut state = lock.state.lock().unwrap();
+        if state.writer == Some(self.current()) {
+            state.writer = None;
+        } else if state.readers > 0 {
+            state.readers -= 1;
+        } else {
+            return Err(PosixError::NotOwner);
+        }
+        lock.changed.notify_all();
+        self.shared.progress();
+        Ok(())
+    }
+
+    /// `pthread_rwlock_destroy`
+    pub fn rwlock_destroy(&self, address: usize) -> Result<(), PosixError> {
+        let mut rwlocks = self.shared.rwlocks.lock().unwrap();
+        if let Some(lock) = rwlocks.get(&address) {
+            let state = lock.state.lock().unwrap();
+            if state.writer.is_some() || state.readers > 0 {
+                return Err(PosixError::Busy);
+            }
+        }
+        rwlocks.remove(&address);
+        Ok(())
+    }
+
+    /// Wait for the next change to `lock`
+    fn wait_rwlock<'a>(
+        &self,
+        lock: &'a PthreadRwLock,
+        state: std::sync::MutexGuard<'a, RwState>,
+        me: ThreadId,
+    ) -> Result<std::sync::MutexGuard<'a, RwState>, PosixError> {
+        match self.shared.determinism.get() {
+            Some(determinism) => {
+                drop(state);
+                determinism.stall(me, None).map_err(|_| PosixError::Deadlock)?;
+                Ok(lock.state.lock().unwrap())
+            }
+            None => Ok(lock.changed.wait(state).unwrap()),
+        }
+    }
+
+    fn rwlock(&self, address: usize) -> Arc<PthreadRwLock> {
+        let mut rwlocks = self.shared.rwlocks.lock().unwrap();
+        Arc::clone(rwlocks.entry(address).or_insert_with(|| {
+            Arc::new(PthreadRwLock { state: Mutex::new(RwState::default()), changed: Condvar::new() })
+        }))
+    }
+
+    /// `pthread_key_create`
+    pub fn key_create(&self, destructor: Option<Destructor>) -> Result<usize, PosixError> {
+        let mut keys = self.shared.keys.write();
+        if keys.len() >= KEYS_MAX {
+            return Err(PosixError::Again);
+        }
+        keys.push(Some(destructor));
+        Ok(keys.len() - 1)
+    }
+
+    /// `pthread_key_delete`. Doesn't run destructors, as in POSIX.
+    pub fn key_delete(&self, key: usize) -> Result<(), PosixError> {
+        let mut keys = self.shared.keys.write();
+        match keys.get_mut(key) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(PosixError::Invalid),
+        }
+    }
+
+    /// `pthread_setspecific`
+    pub fn set_specific(&self, key: usize, value: u64) -> Result<(), PosixError> {
+        if !matches!(self.shared.keys.read().get(key), Some(Some(_))) {
+            return Err(PosixError::Invalid);
+        }
+        VALUES.with(|values| values.borrow_mut().insert(key, value));
+        Ok(())
+    }
+
+    /// `pthread_getspecific`: NULL for a key this thread never set
+    pub fn get_specific(&self, key: usize) -> u64 {
+        VALUES.with(|values| values.borrow().get(&key).copied().unwrap_or(0))
+    }
+
+    /// The interpreted destructors to call as the current thread exits,
+    /// after running the compiled ones. The interpreter calls this, and
+    /// the destructors it returns, until it comes back empty.
+    pub fn exit_destructors(&self) -> Vec<(u64, u64)> {
+        self.shared.take_destructors()
+    }
+}
+
+impl Shared {
+    /// Called first on a new thread
+    fn begin(&self, id: ThreadId) {
+        if let Some(determinism) = self.determinism.get() {
+            determinism.scheduler().begin(id);
+        }
+    }
+
+    /// Called last on an ending thread, once `finished` is set
+    fn end(&self, id: ThreadId) {
+        if let Some(determinism) = self.determinism.get() {
+            determinism.scheduler().exit(id);
+        }
+    }
+
+    /// A thread may be able to go on: a lock was released or a condition signalled
+    fn progress(&self) {
+        if let Some(determinism) = self.determinism.get() {
+            determinism.scheduler().progress();
+        }
+    }
+
+    /// Clear the current thread's non-NULL values, running their compiled
+    /// destructors, and return the (destructor, value) pairs to run in the
+    /// interpreter
+    fn take_destructors(&self) -> Vec<(u64, u64)> {
+        let keys = self.keys.read().clone();
+        let values: Vec<(usize, u64)> =
+            VALUES.with(|values| values.borrow_mut().drain().filter(|&(_, value)| value != 0).collect());
+        let mut interpreted = Vec::new();
+        for (key, value) in values {
+            match keys.get(key) {
+                Some(Some(Some(Destructor::Native(destructor)))) => destructor(value as *mut c_void),
+                Some(Some(Some(Destructor::Interpreted(function)))) => interpreted.push((*function, value)),
+                _ => {}
+            }
+        }
+        interpreted
+    }
+
+    /// Run the compiled destructors until no values are left, or for
+    /// `DESTRUCTOR_ITERATIONS` rounds since they may set values again.
+    /// Interpreted ones left at this point had no interpreter to run them.
+    fn run_destructors(&self) {
+        for _ in 0..DESTRUCTOR_ITERATIONS {
+            let pending = VALUES.with(|values| values.borrow().values().any(|&value| value != 0));
+            if !pending {
+                break;
+            }
+            self.take_destructors();
+        }
+    }
+}
+
+struct NativeEntry {
+    id: ThreadId,
+    routine: extern "C" fn(*mut c_void) -> *mut c_void,
+    argument: u64,
+    shared: Arc<Shared>,
+    finished: Arc<AtomicBool>,
+}
+
+unsafe fn spawn_native(stack: &ThreadStack, entry: *mut NativeEntry) -> Result<libc::pthread_t, PosixError> {
+    let mut attributes: libc::pthread_attr_t = std::mem::zeroed();
+    libc::pthread_attr_init(&mut attributes);
+    libc::pthread_attr_setstack(&mut attributes, stack.base as *mut c_void, stack.size);
+    let mut handle: libc::pthread_t = std::mem::zeroed();
+    let status = libc::pthread_create(&mut handle, &attributes, native_start, entry as *mut c_void);
+    libc::pthread_attr_destroy(&mut attributes);
+    match status {
+        0 => Ok(handle),
+        libc::EAGAIN => Err(PosixError::Again),
+        _ => Err(PosixError::Invalid),
+    }
+}
+
+extern "C" fn native_start(entry: *mut c_void) -> *mut c_void {
+    let entry = unsafe { Box::from_raw(entry as *mut NativeEntry) };
+    CURRENT.with(|current| current.set(entry.id));
+    entry.shared.begin(entry.id);
+    let result = (entry.routine)(entry.argument as *mut c_void);
+    entry.shared.run_destructors();
+    entry.finished.store(true, Ordering::Release);
+    entry.shared.end(entry.id);
+    result
+}
+
+/// The calling thread's id, as `pthread_self` reports it
+pub fn current_thread() -> ThreadId {
+    CURRENT.with(Cell::get)
+}
+
+fn timespec_duration(time: libc::timespec) -> Duration {
+    Duration::new(time.tv_sec.max(0) as u64, time.tv_nsec.clamp(0, 999_999_999) as u32)
+}
+
+/// Time from now to a `CLOCK_REALTIME` deadline, zero if it has passed
+fn remaining(deadline: libc::timespec) -> Duration {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    timespec_duration(deadline).saturating_sub(now)
+}
+
+#[derive(Debug)]
+pub enum PosixError {
+    Again,
+    Busy,
+    Deadlock,
+    Invalid,
+    NoSuchThread,
+    NotOwner,
+    TimedOut,
+    Memory(String),
+}
+
+impl PosixError {
+    /// The error number the pthread function returns
+    pub fn errno(&self) -> c_int {
+        match self {
+            PosixError::Again => libc::EAGAIN,
+            PosixError::Busy => libc::EBUSY,
+            PosixError::Deadlock => libc::EDEADLK,
+            PosixError::Invalid => libc::EINVAL,
+            PosixError::NoSuchThread => libc::ESRCH,
+            PosixError::NotOwner => libc::EPERM,
+            PosixError::TimedOut => libc::ETIMEDOUT,
+            PosixError::Memory(_) => libc::ENOMEM,
+        }
+    }
+}
+
+// Example usage:
+/*
+extern "C" fn worker(counter: *mut c_void) -> *mut c_void {
+    // compiled: pthread_mutex_lock(&m); ++*counter; pthread_mutex_unlock(&m);
+    counter
+}
+
+fn main() -> Result<(), PosixError> {
+    let memory = Arc::new(unsafe { MemoryManager::new() }.expect("memory manager"));
+    let posix = POSIXModule::new(memory);
+    let mut counter = 0u64;
+    let threads: Vec<ThreadId> = (0..4)
+        .map(|_| posix.create(StartRoutine::Native(worker), &mut counter as *mut u64 as u64, ThreadAttributes::default()))
+        .collect::<Result<_, _>>()?;
+    for id in threads {
+        posix.join(id)?;
+    }
+    println!("multithreaded: {}", posix.multithreaded());
+    Ok(())
+}
+*/
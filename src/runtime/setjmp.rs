@@ -0,0 +1,535 @@
+// src/runtime/setjmp.rs
+//! `<setjmp.h>` for interpreted programs
+//! An interpreted `setjmp` can't save registers that mean anything: the
+//! state to come back to is the interpreter's own. So `setjmp` records a
+//! checkpoint (the calling function's activation, where in it to resume,
+//! and the VLA and variadic stack heights) and writes a token for it into
+//! the program's `jmp_buf`. `longjmp` turns the token back into a
+//! `LongJump`, which the interpreter propagates as an error through the
+//! frames in between until the activation that called `setjmp` catches it
+//! and resumes there with `setjmp` evaluating to the value passed. Locals
+//! keep the values they had at the `longjmp`, which is what C promises for
+//! `volatile` ones and allows for the rest.
+//!
+//! A checkpoint lives until its function returns, after which `longjmp`
+//! to it is reported instead of being undefined. `sigsetjmp` with a
+//! non-zero `savemask` also saves the signal mask for `longjmp` to restore.
+//!
+//! Compiled code uses the host's `setjmp`, declared `returns_twice` by
+//! `compiler::setjmp`. The two meet where the interpreter calls into
+//! compiled code: `call_native` saves the callee-saved registers there, and
+//! a compiled `longjmp` to an interpreted `jmp_buf` (`jit_longjmp`) returns
+//! to that point, discarding the compiled frames, for the interpreter to
+//! carry on as if it had been called from interpreted code. The reverse, an
+//! interpreted `longjmp` to a `jmp_buf` that compiled code filled in, would
+//! skip the interpreter's own frames and is refused.
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_int, c_void};
+use std::mem::MaybeUninit;
+use std::ptr;
+use crate::runtime::varargs::VarargsMark;
+use crate::runtime::vla::VlaMark;
+
+/// First word of a `jmp_buf` that interpreted code filled in
+const INTERPRETED_JMP_BUF: u64 = 0x6a6d_705f_6275_6601;
+
+/// Bytes of `jmp_buf` an interpreted `setjmp` writes: the marker, a slot
+/// and the slot's generation. Every host `jmp_buf` is larger.
+pub const INTERPRETED_JMP_BUF_SIZE: usize = 3 * 8;
+
+/// One call of an interpreted function, from entry to return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activation {
+    depth: usize,
+    serial: u64,
+}
+
+impl Activation {
+    /// Calls in progress below this one
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// A `longjmp` on its way to the activation that called `setjmp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LongJump {
+    pub activation: Activation,
+    /// The interpreter's position of the `setjmp` call in that activation
+    pub resume: usize,
+    pub vla: VlaMark,
+    pub varargs: VarargsMark,
+    /// What `setjmp` evaluates to this time; never 0
+    pub value: i32,
+}
+
+struct Checkpoint {
+    activation: Activation,
+    resume: usize,
+    vla: VlaMark,
+    varargs: VarargsMark,
+    generation: u64,
+    signal_mask: Option<libc::sigset_t>,
+}
+
+pub struct SetjmpTable {
+    /// Serial of each interpreted call in progress, outermost first
+    activations: Vec<u64>,
+    next_serial: u64,
+
+    // Checkpoints by slot; a slot's generation changes when it is reused
+    checkpoints: Vec<Option<Checkpoint>>,
+    free: Vec<usize>,
+    next_generation: u64,
+
+    // Statistics
+    setjmps: usize,
+    longjmps: usize,
+}
+
+impl SetjmpTable {
+    pub fn new() -> Self {
+        SetjmpTable {
+            activations: Vec::new(),
+            next_serial: 0,
+            checkpoints: Vec::new(),
+            free: Vec::new(),
+            next_generation: 1,
+            setjmps: 0,
+            longjmps: 0,
+        }
+    }
+
+    /// On entry to an interpreted function
+    pub fn enter(&mut self) -> Activation {
+        let activation = Activation { depth: self.activations.len(), serial: self.next_serial };
+        self.next_serial += 1;
+        self.activations.push(activation.serial);
+        activation
+    }
+
+    /// Interpreted calls in progress
+    pub fn depth(&self) -> usize {
+        self.activations.len()
+    }
+
+    /// When `activation` returns: its checkpoints, and those of any call it
+    /// made that never returned, are gone. Leaving an activation a
+    /// `longjmp` already left does nothing.
+    pub fn leave(&mut self, activation: Activation) {
+        if self.is_live(activation) {
+            self.unwind_to(activation.depth);
+        }
+    }
+
+    /// `setjmp(buf)` called by `activation` at position `resume`. Writes the
+    /// token into `buf`; the call evaluates to 0.
+    ///
+    /// # Safety
+    /// `buf` must be valid for `INTERPRETED_JMP_BUF_SIZE` bytes.
+    pub unsafe fn setjmp(
+        &mut self,
+        buf: *mut u8,
+        activation: Activation,
+        resume: usize,
+        vla: VlaMark,
+        varargs: VarargsMark,
+        save_mask: bool,
+    ) -> Result<(), SetjmpError> {
+        if !self.is_live(activation) {
+            return Err(SetjmpError::NotActive);
+        }
+        let signal_mask = if save_mask { Some(current_signal_mask()?) } else { None };
+
+        // A `setjmp` in a loop refills the same `jmp_buf`; reuse its slot
+        // rather than piling up checkpoints until the function returns
+        let reused = self
+            .lookup(buf)
+            .ok()
+            .filter(|&slot| self.checkpoints[slot].as_ref().is_some_and(|c| c.activation == activation));
+        let slot = match reused.or_else(|| self.free.pop()) {
+            Some(slot) => slot,
+            None => {
+                self.checkpoints.push(None);
+                self.checkpoints.len() - 1
+            }
+        };
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        self.checkpoints[slot] = Some(Checkpoint { activation, resume, vla, varargs, generation, signal_mask });
+
+        let words = buf as *mut u64;
+        ptr::write_unaligned(words, INTERPRETED_JMP_BUF);
+        ptr::write_unaligned(words.add(1), slot as u64);
+        ptr::write_unaligned(words.add(2), generation);
+        self.setjmps += 1;
+        Ok(())
+    }
+
+    /// `longjmp(buf, value)`: where to land. Restores the signal mask if
+    /// `sigsetjmp` saved one.
+    ///
+    /// # Safety
+    /// `buf` must be valid for `INTERPRETED_JMP_BUF_SIZE` bytes.
+    pub unsafe fn longjmp(&mut self, buf: *const u8, value: c_int) -> Result<LongJump, SetjmpError> {
+        if !is_interpreted(buf) {
+            return Err(SetjmpError::CompiledJmpBuf);
+        }
+        let slot = self.lookup(buf)?;
+        let checkpoint = self.checkpoints[slot].as_ref().ok_or(SetjmpError::Expired)?;
+        if let Some(mask) = &checkpoint.signal_mask {
+            set_signal_mask(mask)?;
+        }
+        self.longjmps += 1;
+        Ok(LongJump {
+            activation: checkpoint.activation,
+            resume: checkpoint.resume,
+            vla: checkpoint.vla,
+            varargs: checkpoint.varargs,
+            value: if value == 0 { 1 } else { value },
+        })
+    }
+
+    /// `jump` has reached its activation: the calls above it are gone
+    pub fn land(&mut self, jump: &LongJump) {
+        self.unwind_to(jump.activation.depth + 1);
+    }
+
+    /// (setjmp calls, longjmp calls, checkpoints live)
+    pub fn stats(&self) -> (usize, usize, usize) {
+        let live = self.checkpoints.len() - self.free.len();
+        (self.setjmps, self.longjmps, live)
+    }
+
+    fn is_live(&self, activation: Activation) -> bool {
+        self.activations.get(activation.depth) == Some(&activation.serial)
+    }
+
+    /// The slot `buf`'s token names, if its checkpoint still exists
+    unsafe fn lookup(&self, buf: *const u8) -> Result<usize, SetjmpError> {
+        let words = buf as *const u64;
+        if ptr::read_unaligned(words) != INTERPRETED_JMP_BUF {
+            return Err(SetjmpError::Expired);
+        }
+        let slot = ptr::read_unaligned(words.add(1)) as usize;
+        let generation = ptr::read_unaligned(words.add(2));
+        match self.checkpoints.get(slot) {
+            Some(Some(checkpoint)) if checkpoint.generation == generation && self.is_live(checkpoint.activation) => {
+                Ok(slot)
+            }
+            _ => Err(SetjmpError::Expired),
+        }
+    }
+
+    /// Keep the outermost `depth` activations and their checkpoints
+    fn unwind_to(&mut self, depth: usize) {
+        self.activations.truncate(depth);
+        if self.checkpoints.len() == self.free.len() {
+            return;
+        }
+        for (slot, entry) in self.checkpoints.iter_mut().enumerate() {
+            if entry.as_ref().is_some_and(|c| c.activation.depth >= depth) {
+                *entry = None;
+                self.free.push(slot);
+            }
+        }
+    }
+}
+
+impl Default for SetjmpTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether interpreted code filled in `buf`, rather than the host `setjmp`
+///
+/// # Safety
+/// `buf` must be valid for 8 bytes.
+pub unsafe fn is_interpreted(buf: *const u8) -> bool {
+    ptr::read_unaligned(buf as *const u64) == INTERPRETED_JMP_BUF
+}
+
+fn current_signal_mask() -> Result<libc::sigset_t, SetjmpError> {
+    let mut mask = MaybeUninit::<libc::sigset_t>::uninit();
+    let status = unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, ptr::null(), mask.as_mut_ptr()) };
+    if status != 0 {
+        return Err(SetjmpError::SignalMask(status));
+    }
+    Ok(unsafe { mask.assume_init() })
+}
+
+fn set_signal_mask(mask: &libc::sigset_t) -> Result<(), SetjmpError> {
+    match unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, mask, ptr::null_mut()) } {
+        0 => Ok(()),
+        status => Err(SetjmpError::SignalMask(status)),
+    }
+}
+
+/// A compiled `longjmp` to an interpreted `jmp_buf`, caught where the
+/// interpreter called into compiled code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingLongJump {
+    pub buf: *mut u8,
+    pub value: c_int,
+}
+
+/// Why compiled code under `call_native` was left early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupted {
+    LongJump(PendingLongJump),
+    /// It faulted, and the program's handler for the signal is interpreted
+    /// (`runtime::signals`)
+    Fault(c_int),
+    /// It ran into a guard page of its stack at this address
+    /// (`runtime::stack_guard`)
+    StackOverflow(usize),
+}
+
+/// Callee-saved registers, stack pointer and return address of a
+/// `context_call`, for `context_resume` to return from it a second time
+#[repr(C)]
+struct NativeContext {
+    words: [u64; 21],
+}
+
+/// What `context_call` returns: the entry's result, or a jump back to it
+#[repr(C)]
+struct Landing {
+    value: u64,
+    jumped: u64,
+}
+
+type NativeEntry = unsafe extern "C" fn(*mut c_void) -> u64;
+
+thread_local! {
+    /// The `call_native`s in progress on this thread, innermost last
+    static BOUNDARIES: RefCell<Vec<*mut NativeContext>> = const { RefCell::new(Vec::new()) };
+    static PENDING: Cell<Option<Interrupted>> = const { Cell::new(None) };
+}
+
+/// Run `f`, which calls compiled code, so that a compiled `longjmp` to an
+/// interpreted `jmp_buf`, or a fault for an interpreted signal handler,
+/// comes back here as `Err`. The frames it discards are compiled C and
+/// `f`'s own, so `f` must not hold anything that needs dropping across the
+/// call.
+///
+/// # Safety
+/// Only compiled C may run between `f` and a `longjmp` that reaches here.
+pub unsafe fn call_native<F: FnOnce() -> R, R>(f: F) -> Result<R, Interrupted> {
+    struct Call<F, R> {
+        f: Option<F>,
+        result: Option<R>,
+    }
+
+    unsafe extern "C" fn entry<F: FnOnce() -> R, R>(call: *mut c_void) -> u64 {
+        let call = &mut *(call as *mut Call<F, R>);
+        let f = call.f.take().expect("entered once");
+        call.result = Some(f());
+        0
+    }
+
+    let mut call = Call { f: Some(f), result: None };
+    let mut context = NativeContext { words: [0; 21] };
+    let context_ptr: *mut NativeContext = &mut context;
+    BOUNDARIES.with(|boundaries| boundaries.borrow_mut().push(context_ptr));
+    let landing = context_call(context_ptr, entry::<F, R>, &mut call as *mut Call<F, R> as *mut c_void);
+    BOUNDARIES.with(|boundaries| boundaries.borrow_mut().pop());
+
+    if landing.jumped == 0 {
+        Ok(call.result.take().expect("entry ran to completion"))
+    } else {
+        Err(PENDING.with(Cell::take).expect("a jump leaves its reason"))
+    }
+}
+
+/// Whether this thread is running code under `call_native`. Safe to call
+/// from a signal handler.
+pub fn in_native() -> bool {
+    BOUNDARIES
+        .try_with(|boundaries| boundaries.try_borrow().is_ok_and(|boundaries| !boundaries.is_empty()))
+        .unwrap_or(false)
+}
+
+/// Leave the compiled code under the innermost `call_native`, which
+/// returns `Err(reason)`. Returns only if there is none.
+///
+/// # Safety
+/// Only compiled C, or a signal handler that interrupted it, may be on the
+/// stack above that `call_native`.
+pub unsafe fn interrupt_native(reason: Interrupted) {
+    let innermost = BOUNDARIES
+        .try_with(|boundaries| boundaries.try_borrow().ok().and_then(|boundaries| boundaries.last().copied()))
+        .ok()
+        .flatten();
+    if let Some(context) = innermost {
+        PENDING.with(|pending| pending.set(Some(reason)));
+        context_resume(context)
+    }
+}
+
+unsafe extern "C" {
+    #[link_name = "longjmp"]
+    fn host_longjmp(env: *mut c_void, value: c_int) -> !;
+    #[link_name = "siglongjmp"]
+    fn host_siglongjmp(env: *mut c_void, value: c_int) -> !;
+}
+
+/// `longjmp` and `_longjmp` for compiled code
+///
+/// # Safety
+/// `buf` must have been filled in by `setjmp`, compiled or interpreted.
+pub unsafe extern "C" fn jit_longjmp(buf: *mut c_void, value: c_int) -> ! {
+    if is_interpreted(buf as *const u8) {
+        jump_to_interpreter(buf as *mut u8, value);
+    }
+    host_longjmp(buf, value)
+}
+
+/// `siglongjmp` for compiled code
+///
+/// # Safety
+/// As for `jit_longjmp`.
+pub unsafe extern "C" fn jit_siglongjmp(buf: *mut c_void, value: c_int) -> ! {
+    if is_interpreted(buf as *const u8) {
+        jump_to_interpreter(buf as *mut u8, value);
+    }
+    host_siglongjmp(buf, value)
+}
+
+unsafe fn jump_to_interpreter(buf: *mut u8, value: c_int) -> ! {
+    interrupt_native(Interrupted::LongJump(PendingLongJump { buf, value }));
+    // Compiled code that the interpreter didn't call has nowhere to go
+    eprintln!("longjmp: jmp_buf was filled in by interpreted code that isn't running");
+    std::process::abort();
+}
+
+/// Save the context in `context`, then return `entry(arg)` with `jumped`
+/// clear, or whatever `context_resume` passes with it set
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn context_call(context: *mut NativeContext, entry: NativeEntry, arg: *mut c_void) -> Landing {
+    core::arch::naked_asm!(
+        "mov [rdi], rbx",
+        "mov [rdi + 8], rbp",
+        "mov [rdi + 16], r12",
+        "mov [rdi + 24], r13",
+        "mov [rdi + 32], r14",
+        "mov [rdi + 40], r15",
+        // The stack pointer after returning, and the return address
+        "lea rax, [rsp + 8]",
+        "mov [rdi + 48], rax",
+        "mov rax, [rsp]",
+        "mov [rdi + 56], rax",
+        // Keep the stack 16-byte aligned at the call
+        "sub rsp, 8",
+        "mov rdi, rdx",
+        "call rsi",
+        "add rsp, 8",
+        "xor edx, edx",
+        "ret",
+    )
+}
+
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn context_resume(context: *mut NativeContext) -> ! {
+    core::arch::naked_asm!(
+        "mov rbx, [rdi]",
+        "mov rbp, [rdi + 8]",
+        "mov r12, [rdi + 16]",
+        "mov r13, [rdi + 24]",
+        "mov r14, [rdi + 32]",
+        "mov r15, [rdi + 40]",
+        "mov rsp, [rdi + 48]",
+        "xor eax, eax",
+        "mov edx, 1",
+        "jmp qword ptr [rdi + 56]",
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+#[unsafe(naked)]
+unsafe extern "C" fn context_call(context: *mut NativeContext, entry: NativeEntry, arg: *mut c_void) -> Landing {
+    core::arch::naked_asm!(
+        "stp x19, x20, [x0, #0]",
+        "stp x21, x22, [x0, #16]",
+        "stp x23, x24, [x0, #32]",
+        "stp x25, x26, [x0, #48]",
+        "stp x27, x28, [x0, #64]",
+        // The frame pointer, and the link register to return through
+        "stp x29, x30, [x0, #80]",
+        "mov x16, sp",
+        "str x16, [x0, #96]",
+        "stp d8, d9, [x0, #104]",
+        "stp d10, d11, [x0, #120]",
+        "stp d12, d13, [x0, #136]",
+        "stp d14, d15, [x0, #152]",
+        "stp x29, x30, [sp, #-16]!",
+        "mov x29, sp",
+        "mov x16, x1",
+        "mov x0, x2",
+        "blr x16",
+        "ldp x29, x30, [sp], #16",
+        "mov x1, #0",
+        "ret",
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+#[unsafe(naked)]
+unsafe extern "C" fn context_resume(context: *mut NativeContext) -> ! {
+    core::arch::naked_asm!(
+        "ldp x19, x20, [x0, #0]",
+        "ldp x21, x22, [x0, #16]",
+        "ldp x23, x24, [x0, #32]",
+        "ldp x25, x26, [x0, #48]",
+        "ldp x27, x28, [x0, #64]",
+        "ldp x29, x30, [x0, #80]",
+        "ldr x16, [x0, #96]",
+        "mov sp, x16",
+        "ldp d8, d9, [x0, #104]",
+        "ldp d10, d11, [x0, #120]",
+        "ldp d12, d13, [x0, #136]",
+        "ldp d14, d15, [x0, #152]",
+        "mov x0, #0",
+        "mov x1, #1",
+        "ret",
+    )
+}
+
+#[derive(Debug)]
+pub enum SetjmpError {
+    /// `setjmp` from an activation that has returned or been unwound
+    NotActive,
+    /// `longjmp` to a `jmp_buf` whose `setjmp` caller has returned, or that
+    /// `setjmp` never filled in
+    Expired,
+    /// `longjmp` from interpreted code to a `jmp_buf` of compiled code
+    CompiledJmpBuf,
+    /// `pthread_sigmask` failed for `sigsetjmp`/`siglongjmp`
+    SignalMask(c_int),
+}
+
+// Example usage:
+/*
+// if (setjmp(env) == 0) { f(); } else { puts("caught"); }
+// where f() calls longjmp(env, 2)
+fn main() -> Result<(), SetjmpError> {
+    let mut table = SetjmpTable::new();
+    let vla = VlaStack::new(DEFAULT_VLA_STACK_SIZE).unwrap();
+    let varargs = VarargsStack::new().unwrap();
+    let mut env = [0u8; 200];
+
+    let main = table.enter();
+    unsafe { table.setjmp(env.as_mut_ptr(), main, 7, vla.mark(), varargs.mark(), false)? };
+    let f = table.enter();
+    let jump = unsafe { table.longjmp(env.as_ptr(), 2)? };
+    assert_eq!((jump.activation, jump.resume, jump.value), (main, 7, 2));
+    table.land(&jump);
+    table.leave(f); // already gone: no effect
+    table.leave(main);
+    Ok(())
+}
+*/
@@ -0,0 +1,274 @@
+// src/runtime/signal_safety.rs
+//! Signal-safety and reentrancy auditing (`--audit-signals`)
+//! A handler that calls `printf` or `malloc` works until the signal lands
+//! while the program is inside one of them, and then deadlocks on the
+//! stdio lock or corrupts the heap. The interpreter can see every library
+//! call a handler makes, so in audit mode it checks them as they happen
+//! instead of waiting for the unlucky timing.
+//!
+//! Two mistakes are reported, each with the interpreted stack at the call:
+//! a call from a handler to a function POSIX doesn't list as
+//! async-signal-safe (signal-safety(7)), and a handler entering a
+//! non-reentrant service (the allocator, stdio, `strtok`'s state, ...)
+//! that the code it interrupted was in the middle of. The second is only
+//! possible when the service runs interpreted code while it's in progress,
+//! e.g. an interpreted libc or a `qsort` comparator, and is an error even
+//! for functions that are async-signal-safe in name only.
+//!
+//! Only the call a handler makes itself is checked: whatever an
+//! interpreted library does underneath it is the library's business.
+//! Compiled handlers run natively and aren't seen here; the static
+//! `signal-safety` analysis covers them.
+
+use std::ffi::c_int;
+use std::fmt;
+use crate::debug::stack_capture::InterpretedFrame;
+use super::exit_status::signal_name;
+
+/// The functions POSIX.1-2008 (with the 2016 additions) requires to be
+/// async-signal-safe, sorted
+pub const ASYNC_SIGNAL_SAFE: &[&str] = &[
+    "_Exit", "_exit", "abort", "accept", "access", "aio_error", "aio_return", "aio_suspend", "alarm", "bind",
+    "cfgetispeed", "cfgetospeed", "cfsetispeed", "cfsetospeed", "chdir", "chmod", "chown", "clock_gettime",
+    "close", "connect", "creat", "dup", "dup2", "execl", "execle", "execv", "execve", "faccessat", "fchdir",
+    "fchmod", "fchmodat", "fchown", "fchownat", "fcntl", "fdatasync", "fexecve", "ffs", "fork", "fstat",
+    "fstatat", "fsync", "ftruncate", "futimens", "getegid", "geteuid", "getgid", "getgroups", "getpeername",
+    "getpgrp", "getpid", "getppid", "getsockname", "getsockopt", "getuid", "htonl", "htons", "kill", "link",
+    "linkat", "listen", "longjmp", "lseek", "lstat", "memccpy", "memchr", "memcmp", "memcpy", "memmove",
+    "memset", "mkdir", "mkdirat", "mkfifo", "mkfifoat", "mknod", "mknodat", "ntohl", "ntohs", "open",
+    "openat", "pause", "pipe", "poll", "posix_trace_event", "pselect", "pthread_kill", "pthread_self",
+    "pthread_sigmask", "raise", "read", "readlink", "readlinkat", "recv", "recvfrom", "recvmsg", "rename",
+    "renameat", "rmdir", "select", "sem_post", "send", "sendmsg", "sendto", "setgid", "setpgid", "setsid",
+    "setsockopt", "setuid", "shutdown", "sigaction", "sigaddset", "sigdelset", "sigemptyset", "sigfillset",
+    "sigismember", "siglongjmp", "signal", "sigpause", "sigpending", "sigprocmask", "sigqueue", "sigset",
+    "sigsuspend", "sleep", "sockatmark", "socket", "socketpair", "stat", "stpcpy", "stpncpy", "strcat",
+    "strchr", "strcmp", "strcpy", "strcspn", "strlen", "strncat", "strncmp", "strncpy", "strnlen", "strpbrk",
+    "strrchr", "strspn", "strstr", "strtok_r", "symlink", "symlinkat", "tcdrain", "tcflow", "tcflush",
+    "tcgetattr", "tcgetpgrp", "tcsendbreak", "tcsetattr", "tcsetpgrp", "time", "timer_getoverrun",
+    "timer_gettime", "timer_settime", "times", "umask", "uname", "unlink", "unlinkat", "utime", "utimensat",
+    "utimes", "wait", "waitpid", "wcpcpy", "wcpncpy", "wcscat", "wcschr", "wcscmp", "wcscpy", "wcscspn",
+    "wcslen", "wcsncat", "wcsncmp", "wcsncpy", "wcsnlen", "wcspbrk", "wcsrchr", "wcsspn", "wcsstr", "wcstok",
+    "wmemchr", "wmemcmp", "wmemcpy", "wmemmove", "wmemset", "write",
+];
+
+pub fn is_async_signal_safe(function: &str) -> bool {
+    ASYNC_SIGNAL_SAFE.binary_search(&function).is_ok()
+}
+
+/// Library state that a call can't safely be made into while another call
+/// is halfway through it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Service {
+    Allocator,
+    Stdio,
+    Environment,
+    Locale,
+    /// The static buffers of `localtime`, `ctime` and friends
+    Time,
+    /// The hidden state of `rand` and `random`
+    Random,
+    /// `strtok`'s saved position
+    Tokenizer,
+    /// `atexit` handlers and the exit sequence
+    Exit,
+}
+
+impl Service {
+    /// The service `function` uses, if it's one of the non-reentrant ones
+    pub fn of(function: &str) -> Option<Service> {
+        let service = match function {
+            "malloc" | "calloc" | "realloc" | "reallocarray" | "free" | "aligned_alloc" | "posix_memalign"
+            | "memalign" | "valloc" | "strdup" | "strndup" => Service::Allocator,
+            "printf" | "fprintf" | "vprintf" | "vfprintf" | "sprintf" | "snprintf" | "vsprintf" | "vsnprintf"
+            | "dprintf" | "puts" | "fputs" | "putchar" | "fputc" | "putc" | "getchar" | "fgetc" | "getc"
+            | "fgets" | "gets" | "ungetc" | "scanf" | "fscanf" | "sscanf" | "fopen" | "fdopen" | "freopen"
+            | "fclose" | "fflush" | "fread" | "fwrite" | "fseek" | "ftell" | "rewind" | "setvbuf" | "setbuf"
+            | "perror" | "getline" | "getdelim" | "popen" | "pclose" | "tmpfile" => Service::Stdio,
+            "getenv" | "setenv" | "unsetenv" | "putenv" | "clearenv" => Service::Environment,
+            "setlocale" | "localeconv" | "nl_langinfo" => Service::Locale,
+            "localtime" | "gmtime" | "ctime" | "asctime" | "mktime" | "strftime" | "tzset" => Service::Time,
+            "rand" | "srand" | "random" | "srandom" | "drand48" | "lrand48" | "mrand48" | "srand48" => Service::Random,
+            "strtok" => Service::Tokenizer,
+            "exit" | "atexit" | "at_quick_exit" | "quick_exit" => Service::Exit,
+            _ => return None,
+        };
+        Some(service)
+    }
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Service::Allocator => "the allocator",
+            Service::Stdio => "stdio",
+            Service::Environment => "the environment",
+            Service::Locale => "the locale",
+            Service::Time => "the time conversion buffers",
+            Service::Random => "the random number state",
+            Service::Tokenizer => "strtok's state",
+            Service::Exit => "the exit handlers",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// Called from a handler but not async-signal-safe
+    UnsafeCall,
+    /// Re-entered `service` while `interrupted` was still in it
+    Reentry { service: Service, interrupted: String },
+}
+
+/// One finding, which ends the audited program
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub function: String,
+    /// The signal whose handler made the call
+    pub signal: c_int,
+    /// Innermost frame first
+    pub stack: Vec<InterpretedFrame>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ViolationKind::UnsafeCall => writeln!(
+                f,
+                "signal-safety: '{}' is not async-signal-safe but is called from the {} handler",
+                self.function,
+                signal_name(self.signal)
+            )?,
+            ViolationKind::Reentry { service, interrupted } => writeln!(
+                f,
+                "signal-safety: the {} handler calls '{}', re-entering {} while the interrupted code is inside '{}'",
+                signal_name(self.signal),
+                self.function,
+                service,
+                interrupted
+            )?,
+        }
+        for (index, frame) in self.stack.iter().enumerate() {
+            match &frame.function {
+                Some(function) => writeln!(f, "  #{:<3} {} line {}", index, function, frame.line)?,
+                None => writeln!(f, "  #{:<3} <entering>", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A handler running, delivered with `depth` interpreted calls in progress
+struct HandlerEntry {
+    signal: c_int,
+    depth: usize,
+}
+
+/// A library call in progress
+struct CallEntry {
+    function: String,
+    service: Option<Service>,
+    depth: usize,
+    /// Handlers running when it was made
+    handlers: usize,
+}
+
+/// Returned by `enter_call`, to give back to `leave_call`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallMark(usize);
+
+/// The handlers and library calls in progress on one interpreter thread
+#[derive(Default)]
+pub struct SignalAudit {
+    handlers: Vec<HandlerEntry>,
+    calls: Vec<CallEntry>,
+
+    // Statistics
+    handlers_run: usize,
+    calls_checked: usize,
+}
+
+impl SignalAudit {
+    pub fn new() -> Self {
+        SignalAudit::default()
+    }
+
+    /// An interpreted handler for `signal` is about to be called with
+    /// `depth` interpreted calls in progress
+    pub fn enter_handler(&mut self, signal: c_int, depth: usize) {
+        self.handlers.push(HandlerEntry { signal, depth });
+        self.handlers_run += 1;
+    }
+
+    /// The innermost handler returned
+    pub fn leave_handler(&mut self) {
+        self.handlers.pop();
+    }
+
+    /// A `longjmp` landed in a function with `depth` calls below it: the
+    /// handlers and library calls made from it or deeper were abandoned
+    pub fn unwind(&mut self, depth: usize) {
+        self.handlers.retain(|handler| handler.depth <= depth);
+        self.calls.retain(|call| call.depth <= depth);
+    }
+
+    /// The program is calling library `function` with `depth` interpreted
+    /// calls in progress. `stack` is only taken for a violation.
+    pub fn enter_call(
+        &mut self,
+        function: &str,
+        depth: usize,
+        stack: impl FnOnce() -> Vec<InterpretedFrame>,
+    ) -> Result<CallMark, Violation> {
+        let service = Service::of(function);
+        let handlers = self.handlers.len();
+        // Made by the handler itself, not by a library it called
+        let direct = self.calls.last().is_none_or(|call| call.handlers < handlers);
+        if let (Some(handler), true) = (self.handlers.last(), direct) {
+            self.calls_checked += 1;
+            let interrupted = service.and_then(|service| {
+                self.calls.iter().find(|call| call.service == Some(service) && call.handlers < handlers)
+            });
+            let kind = match interrupted {
+                Some(call) => Some(ViolationKind::Reentry {
+                    service: service.unwrap(),
+                    interrupted: call.function.clone(),
+                }),
+                None if !is_async_signal_safe(function) => Some(ViolationKind::UnsafeCall),
+                None => None,
+            };
+            if let Some(kind) = kind {
+                return Err(Violation { kind, function: function.to_string(), signal: handler.signal, stack: stack() });
+            }
+        }
+        self.calls.push(CallEntry { function: function.to_string(), service, depth, handlers });
+        Ok(CallMark(self.calls.len() - 1))
+    }
+
+    /// The call `mark` returned, however it returned
+    pub fn leave_call(&mut self, mark: CallMark) {
+        self.calls.truncate(mark.0);
+    }
+
+    /// (handlers run, handler calls checked)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.handlers_run, self.calls_checked)
+    }
+}
+
+// Example usage:
+/*
+// void on_int(int sig) { printf("bye\n"); }
+fn main() {
+    let mut audit = SignalAudit::new();
+    audit.enter_handler(libc::SIGINT, 2);
+    let violation = audit.enter_call("printf", 3, Vec::new).unwrap_err();
+    assert_eq!(violation.kind, ViolationKind::UnsafeCall);
+    eprint!("{}", violation);
+
+    let mark = audit.enter_call("write", 3, Vec::new).unwrap();
+    audit.leave_call(mark);
+    audit.leave_handler();
+}
+*/
@@ -0,0 +1,432 @@
+// src/runtime/signals.rs
+//! Signal handlers installed by C programs
+//! `signal()` and `sigaction()` can't hand an interpreted function to the
+//! kernel, and the interpreter can't run C from inside a host signal
+//! handler: it may have been interrupted halfway through updating its own
+//! state. So the host handler only records the signal, and the interpreter
+//! delivers it at its next safe point (`SignalBridge::poll`, called between
+//! statements), blocking the signal and the action's `sa_mask` for the
+//! duration of the handler as the kernel would.
+//!
+//! A compiled handler is native code and runs straight from the host
+//! handler, like it would outside the interpreter, but only while the
+//! thread is in compiled code: in the middle of the interpreter, or inside
+//! a `critical()` section (the allocator, a GC root walk, a host function),
+//! the signal waits too. Leaving the last critical section re-raises the
+//! signals that waited for it, so a compiled program isn't held up until
+//! the interpreter next polls.
+//!
+//! Faults (`SIGSEGV`, `SIGBUS`, `SIGFPE`, `SIGILL` from the hardware) can't
+//! wait: returning would fault again. One in compiled code with an
+//! interpreted handler leaves the compiled code through
+//! `setjmp::interrupt_native`, and the interpreter runs the handler at
+//! once; if the handler returns instead of jumping away, the program ends
+//! with the signal as it would have by faulting again. The exception is a
+//! stack overflow onto a guard page (`stack_guard`), which fails the call
+//! into compiled code whatever the program's handler.
+//!
+//! The signal mask a program sets with `sigprocmask` is virtual: it only
+//! holds back delivery, so the host threads keep receiving signals. The
+//! state is process-wide, like signal dispositions, so there is one
+//! `SignalBridge` at a time.
+
+use std::ffi::{c_int, c_void};
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use super::setjmp::{self, Interrupted};
+use super::stack_guard;
+use crate::debug::stack_capture;
+
+/// Highest signal number; bit N-1 of a mask is signal N
+pub const MAX_SIGNAL: c_int = 64;
+const SLOTS: usize = MAX_SIGNAL as usize + 1;
+
+/// Signals recorded and not yet delivered
+static PENDING: AtomicU64 = AtomicU64::new(0);
+/// Of those, the ones that were hardware faults
+static FAULTED: AtomicU64 = AtomicU64::new(0);
+/// The program's signal mask
+static BLOCKED: AtomicU64 = AtomicU64::new(0);
+/// Nesting of `critical()` sections
+static CRITICAL: AtomicUsize = AtomicUsize::new(0);
+/// Address of each signal's compiled handler; 0 if it isn't compiled
+static COMPILED: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+/// `sa_flags` of each compiled handler
+static FLAGS: [AtomicI32; SLOTS] = [const { AtomicI32::new(0) }; SLOTS];
+/// `si_code` and `si_addr` of each pending signal's latest occurrence
+static CODES: [AtomicI32; SLOTS] = [const { AtomicI32::new(0) }; SLOTS];
+static ADDRESSES: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+
+/// What a signal does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handler {
+    Default,
+    Ignore,
+    /// An interpreted function, as the interpreter represents function pointers
+    Interpreted(u64),
+    /// The address of a compiled `void (*)(int)`, or with `SA_SIGINFO` a
+    /// `void (*)(int, siginfo_t *, void *)`
+    Compiled(usize),
+}
+
+/// A program's `struct sigaction`, its `sa_mask` as a bit mask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Action {
+    pub handler: Handler,
+    pub mask: u64,
+    pub flags: c_int,
+}
+
+impl Action {
+    pub const DEFAULT: Action = Action { handler: Handler::Default, mask: 0, flags: 0 };
+
+    /// What `signal(sig, handler)` installs: glibc's BSD semantics, where
+    /// the handler stays installed and interrupted calls restart
+    pub fn bsd(handler: Handler) -> Action {
+        Action { handler, mask: 0, flags: libc::SA_RESTART }
+    }
+}
+
+/// The part of `siginfo_t` the interpreter hands to `SA_SIGINFO` handlers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalInfo {
+    pub code: c_int,
+    pub address: usize,
+}
+
+/// A signal to run an interpreted or compiled handler for now
+#[derive(Debug, Clone, Copy)]
+pub struct Delivery {
+    pub signal: c_int,
+    pub action: Action,
+    pub info: SignalInfo,
+    /// A hardware fault, which the handler must not return from
+    pub fault: bool,
+    /// The program's mask to restore when the handler returns
+    saved_mask: u64,
+}
+
+pub struct SignalBridge {
+    actions: Vec<Action>,
+    /// The host's action for each signal the program changed, restored on drop
+    previous: Vec<Option<libc::sigaction>>,
+
+    // Statistics
+    delivered: usize,
+}
+
+impl SignalBridge {
+    pub fn new() -> Self {
+        SignalBridge {
+            actions: vec![Action::DEFAULT; SLOTS],
+            previous: vec![None; SLOTS],
+            delivered: 0,
+        }
+    }
+
+    /// `sigaction(signal, new, &old)`; `None` only queries
+    pub fn sigaction(&mut self, signal: c_int, new: Option<Action>) -> Result<Action, SignalError> {
+        let index = slot(signal)?;
+        // A compiled `SA_RESETHAND` handler reset itself from the host handler
+        if let Handler::Compiled(_) = self.actions[index].handler {
+            if COMPILED[index].load(Ordering::Acquire) == 0 {
+                self.actions[index] = Action::DEFAULT;
+            }
+        }
+        let old = self.actions[index];
+        let Some(action) = new else { return Ok(old) };
+        if signal == libc::SIGKILL || signal == libc::SIGSTOP {
+            return Err(SignalError::Uncatchable(signal));
+        }
+        if signal == stack_capture::capture_signal() {
+            return Err(SignalError::Reserved(signal));
+        }
+
+        let mut host: libc::sigaction = unsafe { std::mem::zeroed() };
+        host.sa_sigaction = match action.handler {
+            Handler::Default => libc::SIG_DFL,
+            Handler::Ignore => libc::SIG_IGN,
+            Handler::Interpreted(_) | Handler::Compiled(_) => host_handler as *const () as usize,
+        };
+        // The kernel applies `sa_mask` and `SA_NODEFER` to compiled handlers;
+        // interpreted ones are masked at delivery
+        host.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK | (action.flags & (libc::SA_RESTART | libc::SA_NODEFER));
+        host.sa_mask = sigset_from_mask(if matches!(action.handler, Handler::Compiled(_)) { action.mask } else { 0 });
+
+        let bit = bit(signal);
+        match action.handler {
+            Handler::Compiled(address) => {
+                FLAGS[index].store(action.flags, Ordering::Relaxed);
+                COMPILED[index].store(address, Ordering::Release);
+            }
+            _ => COMPILED[index].store(0, Ordering::Release),
+        }
+        if action.handler == Handler::Ignore {
+            PENDING.fetch_and(!bit, Ordering::AcqRel);
+        }
+
+        let mut previous = MaybeUninit::<libc::sigaction>::uninit();
+        if unsafe { libc::sigaction(signal, &host, previous.as_mut_ptr()) } != 0 {
+            return Err(SignalError::Host(std::io::Error::last_os_error()));
+        }
+        if self.previous[index].is_none() {
+            self.previous[index] = Some(unsafe { previous.assume_init() });
+        }
+        self.actions[index] = action;
+        Ok(old)
+    }
+
+    /// `sigprocmask(how, set, &old)` for the program; `None` only queries
+    pub fn sigprocmask(&mut self, how: c_int, set: Option<u64>) -> Result<u64, SignalError> {
+        let old = BLOCKED.load(Ordering::Acquire);
+        let Some(set) = set else { return Ok(old) };
+        // SIGKILL and SIGSTOP can't be blocked
+        let set = set & !(bit(libc::SIGKILL) | bit(libc::SIGSTOP));
+        let new = match how {
+            libc::SIG_BLOCK => old | set,
+            libc::SIG_UNBLOCK => old & !set,
+            libc::SIG_SETMASK => set,
+            other => return Err(SignalError::InvalidHow(other)),
+        };
+        BLOCKED.store(new, Ordering::Release);
+        Ok(old)
+    }
+
+    /// `sigpending()`: recorded signals the program's mask holds back
+    pub fn pending(&self) -> u64 {
+        PENDING.load(Ordering::Acquire) & BLOCKED.load(Ordering::Acquire)
+    }
+
+    /// The next signal to deliver, lowest number first, if any is pending
+    /// and unblocked and no critical section is open. Masks the signal and
+    /// the action's `sa_mask` until `finish`.
+    pub fn poll(&mut self) -> Option<Delivery> {
+        if CRITICAL.load(Ordering::Acquire) != 0 {
+            return None;
+        }
+        loop {
+            let blocked = BLOCKED.load(Ordering::Acquire);
+            let ready = PENDING.load(Ordering::Acquire) & !blocked;
+            if ready == 0 {
+                return None;
+            }
+            let signal = ready.trailing_zeros() as c_int + 1;
+            let bit = bit(signal);
+            PENDING.fetch_and(!bit, Ordering::AcqRel);
+            let fault = FAULTED.fetch_and(!bit, Ordering::AcqRel) & bit != 0;
+
+            let index = signal as usize;
+            let action = self.actions[index];
+            if matches!(action.handler, Handler::Default | Handler::Ignore) {
+                // Changed since it was recorded
+                continue;
+            }
+            let deferred = if action.flags & libc::SA_NODEFER != 0 { 0 } else { bit };
+            BLOCKED.store(blocked | action.mask | deferred, Ordering::Release);
+            if action.flags & libc::SA_RESETHAND != 0 {
+                // Best effort: the handler runs either way
+                let _ = self.sigaction(signal, Some(Action::DEFAULT));
+            }
+            self.delivered += 1;
+            return Some(Delivery {
+                signal,
+                action,
+                info: SignalInfo {
+                    code: CODES[index].load(Ordering::Relaxed),
+                    address: ADDRESSES[index].load(Ordering::Relaxed),
+                },
+                fault,
+                saved_mask: blocked,
+            });
+        }
+    }
+
+    /// The handler for `delivery` returned: restore the program's mask
+    pub fn finish(&mut self, delivery: &Delivery) {
+        BLOCKED.store(delivery.saved_mask, Ordering::Release);
+    }
+
+    /// Signals delivered through `poll`
+    pub fn stats(&self) -> usize {
+        self.delivered
+    }
+}
+
+impl Default for SignalBridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SignalBridge {
+    fn drop(&mut self) {
+        for (index, previous) in self.previous.iter().enumerate() {
+            if let Some(previous) = previous {
+                COMPILED[index].store(0, Ordering::Release);
+                unsafe { libc::sigaction(index as c_int, previous, ptr::null_mut()) };
+            }
+        }
+        PENDING.store(0, Ordering::Release);
+        FAULTED.store(0, Ordering::Release);
+        BLOCKED.store(0, Ordering::Release);
+    }
+}
+
+/// Hold back signals until the returned guard is dropped: around the
+/// allocator, a GC root walk, or any Rust code compiled C calls into,
+/// none of which a compiled handler may interrupt
+pub fn critical() -> CriticalSection {
+    CRITICAL.fetch_add(1, Ordering::AcqRel);
+    CriticalSection(())
+}
+
+pub struct CriticalSection(());
+
+impl Drop for CriticalSection {
+    fn drop(&mut self) {
+        if CRITICAL.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        // Give the compiled handlers that had to wait their turn now
+        let waiting = PENDING.load(Ordering::Acquire) & !BLOCKED.load(Ordering::Acquire);
+        for signal in 1..=MAX_SIGNAL {
+            let bit = bit(signal);
+            if waiting & bit != 0 && COMPILED[signal as usize].load(Ordering::Acquire) != 0 && setjmp::in_native() {
+                PENDING.fetch_and(!bit, Ordering::AcqRel);
+                unsafe { libc::raise(signal) };
+            }
+        }
+    }
+}
+
+fn slot(signal: c_int) -> Result<usize, SignalError> {
+    if (1..=MAX_SIGNAL).contains(&signal) {
+        Ok(signal as usize)
+    } else {
+        Err(SignalError::InvalidSignal(signal))
+    }
+}
+
+fn bit(signal: c_int) -> u64 {
+    1 << (signal - 1)
+}
+
+fn is_fault(signal: c_int) -> bool {
+    matches!(signal, libc::SIGSEGV | libc::SIGBUS | libc::SIGFPE | libc::SIGILL)
+}
+
+/// The host's handler for every signal the program handles. Only touches
+/// atomics and async-signal-safe calls.
+extern "C" fn host_handler(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    let index = signal as usize;
+    let bit = bit(signal);
+    // Sent by the kernel for an instruction, not by kill() or raise()
+    let fault = is_fault(signal) && unsafe { (*info).si_code } > 0;
+    if fault {
+        // An overflow onto a stack guard is the runtime's to report; only
+        // returns if it isn't one
+        unsafe { stack_guard::recover(signal, info, context) };
+    }
+    let blocked = BLOCKED.load(Ordering::Acquire) & bit != 0;
+    if fault && blocked {
+        // As the kernel does for a blocked fault: the default action
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+        return;
+    }
+
+    let compiled = COMPILED[index].load(Ordering::Acquire);
+    if compiled != 0 && !blocked && CRITICAL.load(Ordering::Acquire) == 0 && setjmp::in_native() {
+        let flags = FLAGS[index].load(Ordering::Relaxed);
+        if flags & libc::SA_RESETHAND != 0 {
+            COMPILED[index].store(0, Ordering::Release);
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+        unsafe {
+            if flags & libc::SA_SIGINFO != 0 {
+                let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) = std::mem::transmute(compiled);
+                handler(signal, info, context);
+            } else {
+                let handler: extern "C" fn(c_int) = std::mem::transmute(compiled);
+                handler(signal);
+            }
+        }
+        return;
+    }
+
+    // Record it for the interpreter
+    unsafe {
+        CODES[index].store((*info).si_code, Ordering::Relaxed);
+        ADDRESSES[index].store((*info).si_addr() as usize, Ordering::Relaxed);
+    }
+    if fault {
+        FAULTED.fetch_or(bit, Ordering::AcqRel);
+    }
+    PENDING.fetch_or(bit, Ordering::AcqRel);
+
+    if fault {
+        if compiled == 0 && setjmp::in_native() {
+            // Leaving through the boundary doesn't unmask the signal the
+            // way returning from here would
+            let unblock = sigset_from_mask(bit);
+            unsafe {
+                libc::pthread_sigmask(libc::SIG_UNBLOCK, &unblock, ptr::null_mut());
+                // Only returns if there's no boundary after all
+                setjmp::interrupt_native(Interrupted::Fault(signal));
+            }
+        }
+        // A fault in the interpreter or the runtime: nothing can handle it
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// A bit mask of signals as a host `sigset_t`
+pub fn sigset_from_mask(mask: u64) -> libc::sigset_t {
+    let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+    unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        for signal in 1..=MAX_SIGNAL {
+            if mask & bit(signal) != 0 {
+                libc::sigaddset(set.as_mut_ptr(), signal);
+            }
+        }
+        set.assume_init()
+    }
+}
+
+/// A host `sigset_t`, e.g. a program's `sa_mask`, as a bit mask
+pub fn mask_from_sigset(set: &libc::sigset_t) -> u64 {
+    (1..=MAX_SIGNAL)
+        .filter(|&signal| unsafe { libc::sigismember(set, signal) } == 1)
+        .fold(0, |mask, signal| mask | bit(signal))
+}
+
+#[derive(Debug)]
+pub enum SignalError {
+    InvalidSignal(c_int),
+    /// `SIGKILL` and `SIGSTOP` can't be caught or ignored
+    Uncatchable(c_int),
+    /// Taken by `debug::stack_capture`
+    Reserved(c_int),
+    InvalidHow(c_int),
+    Host(std::io::Error),
+}
+
+// Example usage:
+/*
+// void on_alarm(int sig) { ticks++; }  ...  signal(SIGALRM, on_alarm); alarm(1);
+fn main() -> Result<(), SignalError> {
+    let mut bridge = SignalBridge::new();
+    let on_alarm = 0x1000; // the interpreter's value for &on_alarm
+    bridge.sigaction(libc::SIGALRM, Some(Action::bsd(Handler::Interpreted(on_alarm))))?;
+    unsafe { libc::raise(libc::SIGALRM) };
+
+    // At the interpreter's next statement boundary
+    if let Some(delivery) = bridge.poll() {
+        assert_eq!(delivery.action.handler, Handler::Interpreted(on_alarm));
+        // ... call on_alarm(delivery.signal) ...
+        bridge.finish(&delivery);
+    }
+    Ok(())
+}
+*/
@@ -0,0 +1,446 @@
+// src/runtime/stack_guard.rs
+//! Recoverable stack overflow in compiled code
+//! With `JITOptions::enable_guard_pages`, compiled code runs on a stack of
+//! `JITOptions::stack_size` bytes from `run_guarded`, with `GUARD_SIZE`
+//! bytes of inaccessible pages below and above it. Coroutine stacks
+//! register their own guard page the same way.
+//!
+//! Running into a guard page raises `SIGSEGV` (or `SIGBUS`), which the
+//! handler installed here takes on the thread's alternate signal stack:
+//! the faulting stack has no room left for it. If the address is on a
+//! registered guard and compiled code under `setjmp::call_native` was
+//! running, the handler walks the frame pointers of the faulting stack
+//! into a per-thread buffer and leaves through
+//! `setjmp::interrupt_native(Interrupted::StackOverflow)`, so the call
+//! fails with `RuntimeError::StackOverflow` and its trace instead of the
+//! process dying. Any other fault goes to the handler that was installed
+//! before, normally the Rust runtime's, which ends the process.
+//!
+//! What the overflowing code allocated, locks it held and coroutines it
+//! was running are abandoned, as after a `longjmp` past them. A frame
+//! larger than `GUARD_SIZE` can step over the guard without touching it;
+//! that is still undefined, as it is natively.
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_int, c_void};
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use super::setjmp::{self, Interrupted};
+use super::RuntimeError;
+use crate::debug::stack_capture::{self, JitSymbols, StackTrace, MAX_FRAMES};
+
+/// Inaccessible bytes on each side of a `GuardedStack`
+pub const GUARD_SIZE: usize = 64 << 10;
+
+/// Stacks whose guards are recognized at the same time; more still fault,
+/// but end the process
+pub const MAX_GUARDED: usize = 1024;
+
+/// Alternate signal stack for threads that don't have one
+const ALTERNATE_STACK_SIZE: usize = 64 << 10;
+
+// A registered stack: the guards are `low..stack_low` and
+// `stack_high..high`. `low` is 0 while the slot is free.
+struct Guarded {
+    low: AtomicUsize,
+    stack_low: AtomicUsize,
+    stack_high: AtomicUsize,
+    high: AtomicUsize,
+}
+
+static GUARDED: [Guarded; MAX_GUARDED] = [const {
+    Guarded {
+        low: AtomicUsize::new(0),
+        stack_low: AtomicUsize::new(0),
+        stack_high: AtomicUsize::new(0),
+        high: AtomicUsize::new(0),
+    }
+}; MAX_GUARDED];
+
+/// The `SIGSEGV` and `SIGBUS` actions from before `install`
+static PREVIOUS: OnceLock<Result<[libc::sigaction; 2], i32>> = OnceLock::new();
+
+/// Frames of the last overflow on a thread, for `take_overflow`
+struct Trace {
+    frames: [usize; MAX_FRAMES],
+    count: usize,
+}
+
+thread_local! {
+    static TRACE: RefCell<Trace> = const { RefCell::new(Trace { frames: [0; MAX_FRAMES], count: 0 }) };
+    static STACK: RefCell<Option<GuardedStack>> = const { RefCell::new(None) };
+    /// Whether `run_guarded` already moved this thread to `STACK`
+    static ON_STACK: Cell<bool> = const { Cell::new(false) };
+    static ALTERNATE: RefCell<Option<AlternateStack>> = const { RefCell::new(None) };
+}
+
+/// A registered stack's slot; unregisters it when dropped
+#[derive(Debug)]
+pub struct GuardRegistration(Option<usize>);
+
+impl GuardRegistration {
+    /// Recognize faults on `low..stack_low` and `stack_high..high` as
+    /// overflows of the stack `stack_low..stack_high`. Either guard may be
+    /// empty. If every slot is taken the stack isn't registered.
+    pub fn new(low: usize, stack_low: usize, stack_high: usize, high: usize) -> Self {
+        for (index, slot) in GUARDED.iter().enumerate() {
+            if slot.low.compare_exchange(0, usize::MAX, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                slot.stack_low.store(stack_low, Ordering::Relaxed);
+                slot.stack_high.store(stack_high, Ordering::Relaxed);
+                slot.high.store(high, Ordering::Relaxed);
+                // Published last: until then no address matches the slot
+                slot.low.store(low, Ordering::Release);
+                return GuardRegistration(Some(index));
+            }
+        }
+        log::warn!("stack guard: {} stacks registered already; overflows of 0x{:x}..0x{:x} will be fatal", MAX_GUARDED, stack_low, stack_high);
+        GuardRegistration(None)
+    }
+}
+
+impl Drop for GuardRegistration {
+    fn drop(&mut self) {
+        if let Some(index) = self.0 {
+            GUARDED[index].low.store(0, Ordering::Release);
+        }
+    }
+}
+
+/// The registered stack `address` is a guard of, as `(stack_low, stack_high)`
+fn guarded(address: usize) -> Option<(usize, usize)> {
+    GUARDED.iter().find_map(|slot| {
+        let low = slot.low.load(Ordering::Acquire);
+        if low == 0 || low == usize::MAX || address < low {
+            return None;
+        }
+        let (stack_low, stack_high) = (slot.stack_low.load(Ordering::Relaxed), slot.stack_high.load(Ordering::Relaxed));
+        let high = slot.high.load(Ordering::Relaxed);
+        (address < stack_low || (stack_high..high).contains(&address)).then_some((stack_low, stack_high))
+    })
+}
+
+/// The registered stack `address` is on, as `(stack_low, stack_high)`
+pub fn stack_containing(address: usize) -> Option<(usize, usize)> {
+    GUARDED.iter().find_map(|slot| {
+        let low = slot.low.load(Ordering::Acquire);
+        if low == 0 || low == usize::MAX {
+            return None;
+        }
+        let (stack_low, stack_high) = (slot.stack_low.load(Ordering::Relaxed), slot.stack_high.load(Ordering::Relaxed));
+        (stack_low..stack_high).contains(&address).then_some((stack_low, stack_high))
+    })
+}
+
+/// A stack for compiled code with `GUARD_SIZE` guards on both sides
+pub struct GuardedStack {
+    mapping: usize,
+    length: usize,
+    guard: usize,
+    size: usize,
+    _registration: GuardRegistration,
+}
+
+impl GuardedStack {
+    pub fn allocate(size: usize) -> Result<Self, RuntimeError> {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let guard = GUARD_SIZE.div_ceil(page) * page;
+        let size = size.div_ceil(page) * page;
+        let length = size + 2 * guard;
+        unsafe {
+            // Reserved inaccessible, then the stack between the guards opened up
+            let mapping = libc::mmap(
+                ptr::null_mut(),
+                length,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            );
+            if mapping == libc::MAP_FAILED {
+                return Err(RuntimeError::MemoryError(std::io::Error::last_os_error().to_string()));
+            }
+            let mapping = mapping as usize;
+            if libc::mprotect((mapping + guard) as *mut c_void, size, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+                let error = std::io::Error::last_os_error();
+                libc::munmap(mapping as *mut c_void, length);
+                return Err(RuntimeError::MemoryError(error.to_string()));
+            }
+            let registration = GuardRegistration::new(mapping, mapping + guard, mapping + guard + size, mapping + length);
+            Ok(GuardedStack { mapping, length, guard, size, _registration: registration })
+        }
+    }
+
+    /// Usable bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// One past the highest usable byte; stacks grow down from here
+    pub fn top(&self) -> usize {
+        self.mapping + self.guard + self.size
+    }
+}
+
+impl Drop for GuardedStack {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.mapping as *mut c_void, self.length) };
+    }
+}
+
+/// A signal stack this module gave a thread, taken down when it exits
+struct AlternateStack {
+    mapping: *mut c_void,
+}
+
+impl Drop for AlternateStack {
+    fn drop(&mut self) {
+        unsafe {
+            let disable = libc::stack_t { ss_sp: ptr::null_mut(), ss_flags: libc::SS_DISABLE, ss_size: 0 };
+            libc::sigaltstack(&disable, ptr::null_mut());
+            libc::munmap(self.mapping, ALTERNATE_STACK_SIZE);
+        }
+    }
+}
+
+/// Give the calling thread an alternate signal stack if it has none, so
+/// that the handler can run once the thread's own stack is used up. Rust
+/// threads already have one.
+pub fn ensure_alternate_stack() -> Result<(), RuntimeError> {
+    unsafe {
+        let mut current: libc::stack_t = std::mem::zeroed();
+        if libc::sigaltstack(ptr::null(), &mut current) != 0 {
+            return Err(RuntimeError::MemoryError(std::io::Error::last_os_error().to_string()));
+        }
+        if current.ss_flags & libc::SS_DISABLE == 0 {
+            return Ok(());
+        }
+        let mapping = libc::mmap(
+            ptr::null_mut(),
+            ALTERNATE_STACK_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        if mapping == libc::MAP_FAILED {
+            return Err(RuntimeError::MemoryError(std::io::Error::last_os_error().to_string()));
+        }
+        let stack = libc::stack_t { ss_sp: mapping, ss_flags: 0, ss_size: ALTERNATE_STACK_SIZE };
+        if libc::sigaltstack(&stack, ptr::null_mut()) != 0 {
+            let error = std::io::Error::last_os_error();
+            libc::munmap(mapping, ALTERNATE_STACK_SIZE);
+            return Err(RuntimeError::MemoryError(error.to_string()));
+        }
+        ALTERNATE.with(|alternate| *alternate.borrow_mut() = Some(AlternateStack { mapping }));
+    }
+    Ok(())
+}
+
+/// Take `SIGSEGV` and `SIGBUS` for the process, keeping the previous
+/// actions for the faults that aren't overflows. Idempotent.
+pub fn install() -> Result<(), RuntimeError> {
+    let previous = PREVIOUS.get_or_init(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = overflow_handler as *const () as usize;
+        // The faulting stack is full: the handler needs the alternate one
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut previous: [libc::sigaction; 2] = std::mem::zeroed();
+        for (signal, previous) in [libc::SIGSEGV, libc::SIGBUS].into_iter().zip(previous.iter_mut()) {
+            if libc::sigaction(signal, &action, previous) != 0 {
+                return Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(0));
+            }
+        }
+        Ok(previous)
+    });
+    match previous {
+        Ok(_) => Ok(()),
+        Err(errno) => Err(RuntimeError::MemoryError(std::io::Error::from_raw_os_error(*errno).to_string())),
+    }
+}
+
+extern "C" fn overflow_handler(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    unsafe {
+        recover(signal, info, context);
+        chain(signal, info, context);
+    }
+}
+
+/// Leave the compiled code if `signal` is an overflow onto a registered
+/// guard while it runs under `setjmp::call_native`; returns otherwise.
+/// `signals` calls this first too, while a program's own `SIGSEGV` handler
+/// has replaced `overflow_handler`.
+///
+/// # Safety
+/// Only from a signal handler, with the arguments it was passed.
+pub unsafe fn recover(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    // Only the kernel's faults, not a kill() or raise()
+    if !matches!(signal, libc::SIGSEGV | libc::SIGBUS) || (*info).si_code <= 0 || !setjmp::in_native() {
+        return;
+    }
+    let address = (*info).si_addr() as usize;
+    let Some(stack) = guarded(address) else { return };
+
+    let _ = TRACE.try_with(|trace| {
+        if let Ok(mut trace) = trace.try_borrow_mut() {
+            let Trace { frames, count } = &mut *trace;
+            *count = stack_capture::walk_frames(context, stack, frames);
+        }
+    });
+    // Leaving through the boundary doesn't unmask the signal the way
+    // returning from here would
+    let mut unblock = std::mem::MaybeUninit::<libc::sigset_t>::uninit();
+    libc::sigemptyset(unblock.as_mut_ptr());
+    libc::sigaddset(unblock.as_mut_ptr(), signal);
+    libc::pthread_sigmask(libc::SIG_UNBLOCK, unblock.as_ptr(), ptr::null_mut());
+    setjmp::interrupt_native(Interrupted::StackOverflow(address));
+}
+
+/// Hand a fault that isn't ours to the action from before `install`
+unsafe fn chain(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    let previous = match PREVIOUS.get() {
+        Some(Ok(previous)) => &previous[usize::from(signal == libc::SIGBUS)],
+        _ => {
+            libc::signal(signal, libc::SIG_DFL);
+            return;
+        }
+    };
+    match previous.sa_sigaction {
+        // Returning faults again, and then the default action ends the process
+        libc::SIG_DFL | libc::SIG_IGN => {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+        handler if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+            let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) = std::mem::transmute(handler);
+            handler(signal, info, context);
+        }
+        handler => {
+            let handler: extern "C" fn(c_int) = std::mem::transmute(handler);
+            handler(signal);
+        }
+    }
+}
+
+/// Run `f` on this thread's guarded stack of at least `size` bytes,
+/// allocated on first use. `f` should call compiled code through
+/// `setjmp::call_native`, so that an overflow comes back to it. A call
+/// already on the guarded stack stays there.
+///
+/// # Safety
+/// `f` must not unwind.
+pub unsafe fn run_guarded<F: FnOnce() -> R, R>(size: usize, f: F) -> Result<R, RuntimeError> {
+    if ON_STACK.with(Cell::get) {
+        return Ok(f());
+    }
+    install()?;
+    ensure_alternate_stack()?;
+    let top = STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        if stack.as_ref().is_none_or(|stack| stack.size() < size) {
+            // The old stack isn't in use: nothing runs on it outside `run_guarded`
+            *stack = None;
+            *stack = Some(GuardedStack::allocate(size)?);
+        }
+        Ok::<_, RuntimeError>(stack.as_ref().expect("allocated above").top())
+    })?;
+
+    struct Call<F, R> {
+        f: Option<F>,
+        result: Option<R>,
+    }
+
+    unsafe extern "C" fn entry<F: FnOnce() -> R, R>(call: *mut c_void) -> u64 {
+        let call = &mut *(call as *mut Call<F, R>);
+        let f = call.f.take().expect("entered once");
+        call.result = Some(f());
+        0
+    }
+
+    let mut call = Call { f: Some(f), result: None };
+    ON_STACK.with(|on_stack| on_stack.set(true));
+    call_on_stack(top & !15, entry::<F, R>, &mut call as *mut Call<F, R> as *mut c_void);
+    ON_STACK.with(|on_stack| on_stack.set(false));
+    Ok(call.result.take().expect("entry ran to completion"))
+}
+
+/// The overflow that interrupted compiled code with `Interrupted::StackOverflow(address)`
+/// on this thread, its frames named with `symbols`
+pub fn take_overflow(address: usize, symbols: Option<&JitSymbols>) -> StackOverflow {
+    let addresses = TRACE.with(|trace| {
+        let mut trace = trace.borrow_mut();
+        let count = std::mem::take(&mut trace.count);
+        trace.frames[..count].to_vec()
+    });
+    let native = stack_capture::symbolize_frames(&addresses, symbols);
+    StackOverflow { address, trace: StackTrace { native, interpreted: Vec::new() } }
+}
+
+/// Compiled code ran into a guard page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackOverflow {
+    /// Where it faulted, on the guard
+    pub address: usize,
+    /// From the faulting instruction outwards, as far as the guarded stack goes
+    pub trace: StackTrace,
+}
+
+impl fmt::Display for StackOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "stack overflow: fault at 0x{:x} on a stack guard page", self.address)?;
+        write!(f, "{}", self.trace)
+    }
+}
+
+type Entry = unsafe extern "C" fn(*mut c_void) -> u64;
+
+/// Call `entry(arg)` with the stack pointer at `top`, then switch back.
+/// The caller's frame pointer stays in a callee-saved register meanwhile.
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn call_on_stack(top: usize, entry: Entry, arg: *mut c_void) -> u64 {
+    core::arch::naked_asm!(
+        "push rbp",
+        "mov rbp, rsp",
+        "mov rsp, rdi",
+        "mov rdi, rdx",
+        "call rsi",
+        "mov rsp, rbp",
+        "pop rbp",
+        "ret",
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+#[unsafe(naked)]
+unsafe extern "C" fn call_on_stack(top: usize, entry: Entry, arg: *mut c_void) -> u64 {
+    core::arch::naked_asm!(
+        "stp x29, x30, [sp, #-16]!",
+        "mov x29, sp",
+        "mov sp, x0",
+        "mov x0, x2",
+        "blr x1",
+        "mov sp, x29",
+        "ldp x29, x30, [sp], #16",
+        "ret",
+    )
+}
+
+/// Elsewhere compiled code runs on the thread's own stack
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+unsafe extern "C" fn call_on_stack(_top: usize, entry: Entry, arg: *mut c_void) -> u64 {
+    entry(arg)
+}
+
+// Example usage:
+/*
+// int depth(int n) { char pad[256]; pad[0] = n; return depth(n + 1) + pad[0]; }
+unsafe fn run(runtime: &RuntimeSupport, depth: *const u8) {
+    match runtime.execute_function(depth, &[0], ReturnType::Integer) {
+        Err(RuntimeError::StackOverflow(overflow)) => eprint!("{}", overflow),
+        other => println!("{:?}", other),
+    }
+    // The runtime, and the host, carry on
+}
+*/
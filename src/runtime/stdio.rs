@@ -0,0 +1,68 @@
+// src/runtime/stdio.rs
+//! Standard input wiring for the executed program
+//! When the program's source arrives on stdin, that stream is exhausted by
+//! the time the program runs. The program instead gets the controlling
+//! terminal (so isatty(0) still reports a tty for interactive use), an
+//! explicit --stdin-file, or /dev/null.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgramStdin {
+    /// Leave fd 0 as it is
+    Inherit,
+    /// Redirect from a file
+    File(PathBuf),
+    /// Reconnect to the controlling terminal, or /dev/null without one
+    Terminal,
+}
+
+impl ProgramStdin {
+    /// Pick the program's stdin given how the source was read
+    pub fn select(stdin_file: Option<PathBuf>, source_from_stdin: bool) -> Self {
+        match stdin_file {
+            Some(path) => ProgramStdin::File(path),
+            None if source_from_stdin => ProgramStdin::Terminal,
+            None => ProgramStdin::Inherit,
+        }
+    }
+
+    /// Install the selection as fd 0 of this process (inherited by children)
+    pub fn connect(&self) -> io::Result<()> {
+        let file = match self {
+            ProgramStdin::Inherit => return Ok(()),
+            ProgramStdin::File(path) => File::open(path)?,
+            ProgramStdin::Terminal => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")
+                .or_else(|_| File::open("/dev/null"))?,
+        };
+
+        if unsafe { libc::dup2(file.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Whether fd 0 is a terminal
+pub fn stdin_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+// Example usage:
+/*
+fn main() -> io::Result<()> {
+    // c-interpreter - < prog.c   ->   the program reads from the terminal
+    let source = io::read_to_string(io::stdin())?;
+    ProgramStdin::select(None, true).connect()?;
+
+    // c-interpreter prog.c --stdin-file input.txt
+    ProgramStdin::select(Some(PathBuf::from("input.txt")), false).connect()?;
+    Ok(())
+}
+*/
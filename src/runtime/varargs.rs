@@ -0,0 +1,139 @@
+// src/runtime/varargs.rs
+//! `<stdarg.h>` for interpreted programs
+//! An interpreted call to a variadic function never goes through a
+//! prologue that spills the argument registers, so on entry the
+//! interpreter lays the arguments out the way that prologue would have
+//! (`abi::varargs::SaveArea`) and keeps the areas until the call returns.
+//! `va_start` then writes a real `va_list` for the host ABI into the
+//! program's memory, which means a `va_list` built here can be passed to
+//! `vprintf` in the host libc, and a `va_list` that compiled code started
+//! can be read here with `va_arg`.
+//!
+//! Frames are pushed only for variadic functions; the frontend rejects
+//! `va_start` anywhere else, so the top frame always belongs to the
+//! function executing it.
+
+use crate::abi::aggregate::{Abi, AbiError, Argument, CType, Scalar};
+use crate::abi::varargs::{SaveArea, VaList};
+use crate::jit::JITValue;
+
+/// Frame depth to return to when a variadic call returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VarargsMark(usize);
+
+pub struct VarargsStack {
+    abi: Abi,
+    frames: Vec<SaveArea>,
+
+    // Statistics
+    calls: usize,
+    deepest: usize,
+}
+
+impl VarargsStack {
+    pub fn new() -> Result<Self, VarargsError> {
+        Ok(VarargsStack {
+            abi: Abi::host().ok_or(VarargsError::UnsupportedHost)?,
+            frames: Vec::new(),
+            calls: 0,
+            deepest: 0,
+        })
+    }
+
+    /// Bytes an interpreted `va_list` object takes
+    pub fn va_list_size(&self) -> usize {
+        self.abi.va_list_size()
+    }
+
+    /// On entry to a variadic function with `fixed` named parameters, called
+    /// with `args`. Returns the mark to pass to `leave` when it returns.
+    pub fn enter(&mut self, args: &[Argument], fixed: usize, result: Option<&CType>) -> Result<VarargsMark, VarargsError> {
+        let mark = VarargsMark(self.frames.len());
+        self.frames.push(SaveArea::new(self.abi, args, fixed, result).map_err(VarargsError::Abi)?);
+        self.calls += 1;
+        self.deepest = self.deepest.max(self.frames.len());
+        Ok(mark)
+    }
+
+    /// Current depth, for `leave` to come back to from a `longjmp`
+    pub fn mark(&self) -> VarargsMark {
+        VarargsMark(self.frames.len())
+    }
+
+    /// `va_start(ap, last)` in the innermost variadic function
+    ///
+    /// # Safety
+    /// `ap` must be valid for `va_list_size()` bytes.
+    pub unsafe fn start(&mut self, ap: *mut u8) -> Result<(), VarargsError> {
+        let frame = self.frames.last_mut().ok_or(VarargsError::NotVariadic)?;
+        frame.start(ap);
+        Ok(())
+    }
+
+    /// `va_arg(ap, T)` for a scalar `T`
+    ///
+    /// # Safety
+    /// `ap` must be a started `va_list` whose function hasn't returned.
+    pub unsafe fn arg_value(&self, ap: *mut u8, scalar: Scalar) -> Result<JITValue, VarargsError> {
+        VaList::from_raw(self.abi, ap).next_value(scalar).map_err(VarargsError::Abi)
+    }
+
+    /// `va_arg(ap, T)` for a struct or union `T`: its bytes
+    ///
+    /// # Safety
+    /// As for `arg_value`.
+    pub unsafe fn arg(&self, ap: *mut u8, ty: &CType) -> Result<Vec<u8>, VarargsError> {
+        VaList::from_raw(self.abi, ap).next(ty).map_err(VarargsError::Abi)
+    }
+
+    /// `va_copy(dest, src)`
+    ///
+    /// # Safety
+    /// `src` must be a started `va_list` and `dest` valid for
+    /// `va_list_size()` bytes.
+    pub unsafe fn copy(&self, dest: *mut u8, src: *mut u8) {
+        VaList::from_raw(self.abi, src).copy_to(dest);
+    }
+
+    /// Drop the frames of calls since `mark`. An older mark also covers
+    /// calls left without their own `leave`, e.g. by `longjmp`.
+    pub fn leave(&mut self, mark: VarargsMark) {
+        debug_assert!(mark.0 <= self.frames.len(), "variadic frame left twice or out of order");
+        self.frames.truncate(mark.0);
+    }
+
+    /// (variadic calls entered, deepest nesting)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.calls, self.deepest)
+    }
+}
+
+#[derive(Debug)]
+pub enum VarargsError {
+    /// Only x86-64 and AArch64 have a `va_list` layout here
+    UnsupportedHost,
+    /// `va_start` outside a variadic function
+    NotVariadic,
+    Abi(AbiError),
+}
+
+// Example usage:
+/*
+// int sum(int n, ...) called as sum(2, 1, 2)
+fn main() -> Result<(), VarargsError> {
+    let mut stack = VarargsStack::new()?;
+    let args = [1, 2].map(|v| Argument::Value(JITValue::Int32(v)));
+    let call = [&[Argument::Value(JITValue::Int32(2))][..], &args].concat();
+    let mark = stack.enter(&call, 1, Some(&CType::Scalar(Scalar::I32)))?;
+
+    let mut ap = vec![0u8; stack.va_list_size()];
+    unsafe {
+        stack.start(ap.as_mut_ptr())?;
+        let first = stack.arg_value(ap.as_mut_ptr(), Scalar::I32)?;
+        let second = stack.arg_value(ap.as_mut_ptr(), Scalar::I32)?;
+        println!("{:?} {:?}", first, second); // Int32(1) Int32(2)
+    }
+    stack.leave(mark);
+    Ok(())
+}
+*/
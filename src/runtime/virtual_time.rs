@@ -0,0 +1,594 @@
+// src/runtime/virtual_time.rs
+//! Virtual time for embedders
+//! A clock the host application controls, for testing time-dependent C code
+//! the way a simulation would: timeouts, retry back-off, rate limiters,
+//! watchdogs. The embedder can pause it, run it faster or slower than real
+//! time, or jump it forward, and the program's `time`, `clock_gettime`,
+//! `gettimeofday`, sleeps and `alarm`/`setitimer(ITIMER_REAL)` timers all
+//! follow. With `set_skip_sleeps` a sleep moves the clock on by its
+//! duration and returns at once, so an hour of back-off takes no real time.
+//!
+//! Unlike `deterministic::VirtualClock`, readings don't move the clock:
+//! it runs at `scale` times real time unless paused, and the embedder moves
+//! it with `advance`. Both can't bind the same functions; an `Engine` that
+//! has both uses this one.
+//!
+//! A timer that expires sends `SIGALRM` to the process, as the kernel's
+//! would, from a helper thread that has every signal blocked; so the
+//! signal reaches the program's own threads and its handler, interpreted
+//! or compiled. A sleep a timer's signal cuts short returns early with
+//! the time left, unless `SIGALRM` is ignored. `CLOCK_MONOTONIC` and the
+//! CPU-time clocks count virtual time from the start, so `clock()` goes
+//! on during sleeps.
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::jit::host::{HostExport, HostFunction, HostSignature};
+use crate::jit::{JITType, JITValue};
+use super::coroutine::set_errno;
+
+/// `CLOCKS_PER_SEC` on POSIX systems
+const CLOCKS_PER_SEC: u64 = 1_000_000;
+
+/// An interval timer: `alarm`, or `setitimer(ITIMER_REAL)`
+#[derive(Debug, Clone, Copy)]
+struct Timer {
+    /// Virtual time since the start at which it expires
+    deadline: Duration,
+    /// Rearmed this far after each expiry; zero for a one-shot timer
+    interval: Duration,
+}
+
+struct State {
+    /// `CLOCK_REALTIME` at the start
+    epoch: Duration,
+    /// Virtual time since the start as of `anchor`
+    base: Duration,
+    /// When `base` was taken, on the host's monotonic clock
+    anchor: Instant,
+    scale: f64,
+    paused: bool,
+    skip_sleeps: bool,
+    timer: Option<Timer>,
+    /// Bumped by every expiry, so sleepers can tell they were interrupted
+    expiries: u64,
+    timer_thread: bool,
+    closed: bool,
+
+    // Statistics
+    sleeps: usize,
+    skipped: Duration,
+    signals_sent: usize,
+}
+
+impl State {
+    fn elapsed(&self) -> Duration {
+        if self.paused {
+            self.base
+        } else {
+            self.base + self.anchor.elapsed().mul_f64(self.scale)
+        }
+    }
+
+    /// Take `base` now, before the rate changes
+    fn rebase(&mut self) {
+        self.base = self.elapsed();
+        self.anchor = Instant::now();
+    }
+
+    /// Real time until the clock reads `target`; `None` while paused
+    fn real_until(&self, target: Duration) -> Option<Duration> {
+        (!self.paused).then(|| target.saturating_sub(self.elapsed()).div_f64(self.scale))
+    }
+
+    /// Send the timer's signal if it has expired, and rearm or disarm it
+    fn expire(&mut self) {
+        let now = self.elapsed();
+        let Some(timer) = &mut self.timer else {
+            return;
+        };
+        if timer.deadline > now {
+            return;
+        }
+        if timer.interval.is_zero() {
+            self.timer = None;
+        } else {
+            // Expiries missed while nothing looked coalesce into one signal,
+            // as pending signals do
+            let interval = timer.interval.as_nanos();
+            let late = (now - timer.deadline).as_nanos() % interval;
+            timer.deadline = now + Duration::from_nanos((interval - late) as u64);
+        }
+        self.expiries += 1;
+        self.signals_sent += 1;
+        unsafe { libc::kill(libc::getpid(), libc::SIGALRM) };
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Notified whenever the clock's rate, position or timer changes
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A clock the embedder controls. Share it as an `Arc` between the
+/// application and `Engine::use_virtual_time`, or
+/// `CRuntimeEnvironment::set_virtual_time` for the interpreter.
+pub struct VirtualTime {
+    shared: Arc<Shared>,
+}
+
+impl VirtualTime {
+    /// A clock that starts at `epoch` (as `CLOCK_REALTIME`) and runs at real
+    /// speed
+    pub fn new(epoch: Duration) -> Self {
+        VirtualTime {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    epoch,
+                    base: Duration::ZERO,
+                    anchor: Instant::now(),
+                    scale: 1.0,
+                    paused: false,
+                    skip_sleeps: false,
+                    timer: None,
+                    expiries: 0,
+                    timer_thread: false,
+                    closed: false,
+                    sleeps: 0,
+                    skipped: Duration::ZERO,
+                    signals_sent: 0,
+                }),
+                changed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// A clock that starts at the host's current time
+    pub fn from_host() -> Self {
+        Self::new(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+    }
+
+    /// `CLOCK_REALTIME`
+    pub fn now(&self) -> Duration {
+        let state = self.shared.lock();
+        state.epoch + state.elapsed()
+    }
+
+    /// Virtual time since the start
+    pub fn elapsed(&self) -> Duration {
+        self.shared.lock().elapsed()
+    }
+
+    /// A reading of `clock` by the program. `CLOCK_REALTIME` counts from the
+    /// epoch; the monotonic and CPU-time clocks from the start.
+    pub fn read(&self, clock: libc::clockid_t) -> Duration {
+        let mut state = self.shared.lock();
+        state.expire();
+        let elapsed = state.elapsed();
+        match clock {
+            libc::CLOCK_REALTIME | libc::CLOCK_REALTIME_COARSE | libc::CLOCK_TAI => state.epoch + elapsed,
+            _ => elapsed,
+        }
+    }
+
+    /// Stop the clock; sleeps and timers wait until it resumes or advances
+    pub fn pause(&self) {
+        self.update(|state| {
+            state.rebase();
+            state.paused = true;
+        });
+    }
+
+    pub fn resume(&self) {
+        self.update(|state| {
+            if state.paused {
+                state.anchor = Instant::now();
+                state.paused = false;
+            }
+        });
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.lock().paused
+    }
+
+    /// Run at `scale` times real time from now on: 60.0 makes a minute pass
+    /// every second
+    pub fn set_scale(&self, scale: f64) -> Result<(), VirtualTimeError> {
+        if !scale.is_finite() || scale <= 0.0 {
+            return Err(VirtualTimeError::InvalidScale(scale));
+        }
+        self.update(|state| {
+            state.rebase();
+            state.scale = scale;
+        });
+        Ok(())
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.shared.lock().scale
+    }
+
+    /// Jump forward by `by`, waking the sleeps and expiring the timers it
+    /// passes. A timer that expires more than once in the jump sends one
+    /// signal, as it would to a program that didn't run in between.
+    pub fn advance(&self, by: Duration) {
+        self.update(|state| {
+            state.rebase();
+            state.base += by;
+        });
+    }
+
+    /// Jump forward to the next expiry of the timer, if one is armed, and
+    /// return how far that was
+    pub fn advance_to_timer(&self) -> Option<Duration> {
+        let mut jumped = None;
+        self.update(|state| {
+            if let Some(timer) = state.timer {
+                state.rebase();
+                jumped = Some(timer.deadline.saturating_sub(state.base));
+                state.base = state.base.max(timer.deadline);
+            }
+        });
+        jumped
+    }
+
+    /// With `skip`, a sleep moves the clock on by its duration instead of
+    /// waiting for it to pass
+    pub fn set_skip_sleeps(&self, skip: bool) {
+        self.update(|state| state.skip_sleeps = skip);
+    }
+
+    /// Sleep for `duration` of virtual time. Returns what was left of it if
+    /// the timer's signal cut it short, else zero.
+    pub fn sleep(&self, duration: Duration) -> Duration {
+        let mut state = self.shared.lock();
+        let target = state.elapsed() + duration;
+        state.sleeps += 1;
+        self.wait_until(state, target)
+    }
+
+    /// Sleep until `clock` reads `deadline`; as `sleep` otherwise
+    pub fn sleep_until(&self, clock: libc::clockid_t, deadline: Duration) -> Duration {
+        let mut state = self.shared.lock();
+        let target = match clock {
+            libc::CLOCK_REALTIME | libc::CLOCK_REALTIME_COARSE | libc::CLOCK_TAI => deadline.saturating_sub(state.epoch),
+            _ => deadline,
+        };
+        state.sleeps += 1;
+        self.wait_until(state, target)
+    }
+
+    /// `setitimer(ITIMER_REAL)`: expire after `value` and then every
+    /// `interval`; a zero `value` disarms. Returns the time that was left
+    /// on the previous timer and its interval.
+    pub fn set_timer(&self, value: Duration, interval: Duration) -> (Duration, Duration) {
+        let mut old = (Duration::ZERO, Duration::ZERO);
+        self.update(|state| {
+            let now = state.elapsed();
+            if let Some(timer) = state.timer {
+                old = (timer.deadline.saturating_sub(now), timer.interval);
+            }
+            state.timer = (!value.is_zero()).then_some(Timer { deadline: now + value, interval });
+        });
+        if !value.is_zero() {
+            self.start_timer_thread();
+        }
+        old
+    }
+
+    /// `getitimer(ITIMER_REAL)`: time left and interval
+    pub fn timer(&self) -> (Duration, Duration) {
+        let state = self.shared.lock();
+        state.timer.map_or((Duration::ZERO, Duration::ZERO), |timer| {
+            (timer.deadline.saturating_sub(state.elapsed()), timer.interval)
+        })
+    }
+
+    /// (sleeps, virtual time skipped by sleeps, timer signals sent)
+    pub fn stats(&self) -> (usize, Duration, usize) {
+        let state = self.shared.lock();
+        (state.sleeps, state.skipped, state.signals_sent)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        let mut state = self.shared.lock();
+        change(&mut state);
+        state.expire();
+        drop(state);
+        self.shared.changed.notify_all();
+    }
+
+    fn wait_until(&self, mut state: MutexGuard<'_, State>, target: Duration) -> Duration {
+        let expiries = state.expiries;
+        loop {
+            state.expire();
+            let now = state.elapsed();
+            if state.expiries != expiries && !alarm_ignored() {
+                return target.saturating_sub(now);
+            }
+            if now >= target {
+                return Duration::ZERO;
+            }
+            if state.skip_sleeps {
+                // Stop at the timer on the way, as a real sleep would wake
+                let stop = state.timer.map_or(target, |timer| timer.deadline.min(target));
+                state.rebase();
+                if stop > state.base {
+                    let skipped = stop - state.base;
+                    state.skipped += skipped;
+                    state.base = stop;
+                }
+                self.shared.changed.notify_all();
+                continue;
+            }
+            state = match state.real_until(target) {
+                Some(wait) => self.shared.changed.wait_timeout(state, wait).unwrap_or_else(|p| p.into_inner()).0,
+                None => self.shared.changed.wait(state).unwrap_or_else(|p| p.into_inner()),
+            };
+        }
+    }
+
+    /// Expire timers in real time, for programs that wait for one without
+    /// reading the clock
+    fn start_timer_thread(&self) {
+        let mut state = self.shared.lock();
+        if state.timer_thread {
+            return;
+        }
+        state.timer_thread = true;
+        drop(state);
+        let shared = Arc::clone(&self.shared);
+        thread::Builder::new()
+            .name("virtual-time".to_string())
+            .spawn(move || {
+                // SIGALRM must go to the program's threads, not this one
+                unsafe {
+                    let mut all: libc::sigset_t = std::mem::zeroed();
+                    libc::sigfillset(&mut all);
+                    libc::pthread_sigmask(libc::SIG_BLOCK, &all, std::ptr::null_mut());
+                }
+                let mut state = shared.lock();
+                while !state.closed {
+                    state.expire();
+                    let wait = state.timer.and_then(|timer| state.real_until(timer.deadline));
+                    state = match wait {
+                        Some(wait) => shared.changed.wait_timeout(state, wait).unwrap_or_else(|p| p.into_inner()).0,
+                        None => shared.changed.wait(state).unwrap_or_else(|p| p.into_inner()),
+                    };
+                }
+            })
+            .expect("spawning the virtual time thread");
+    }
+}
+
+impl Drop for VirtualTime {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// The program set `SIGALRM` to `SIG_IGN`, so expiries don't interrupt sleeps
+fn alarm_ignored() -> bool {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(libc::SIGALRM, std::ptr::null(), &mut action) == 0 && action.sa_sigaction == libc::SIG_IGN
+    }
+}
+
+/// Host functions binding the time, sleep and `ITIMER_REAL` calls of
+/// compiled code to `time`
+pub fn host_exports(time: &Arc<VirtualTime>) -> Vec<HostExport> {
+    let pointer = || JITType::Pointer(Box::new(JITType::Void));
+    let export = |name: &'static str,
+                  params: Vec<JITType>,
+                  return_type: JITType,
+                  body: fn(&VirtualTime, &[u64]) -> i64| {
+        let time = Arc::clone(time);
+        let wide = matches!(return_type, JITType::Int64);
+        HostExport {
+            name,
+            signature: HostSignature::new(params, return_type),
+            function: HostFunction::Closure(Box::new(move |args: &[JITValue]| {
+                let raw: Vec<u64> = args.iter().map(JITValue::to_raw).collect();
+                let result = body(&time, &raw);
+                if wide {
+                    JITValue::Int64(result)
+                } else {
+                    JITValue::Int32(result as i32)
+                }
+            })),
+        }
+    };
+    vec![
+        export("time", vec![pointer()], JITType::Int64, guest_time),
+        export("clock", vec![], JITType::Int64, guest_clock),
+        export("clock_gettime", vec![JITType::Int32, pointer()], JITType::Int32, guest_clock_gettime),
+        export("gettimeofday", vec![pointer(), pointer()], JITType::Int32, guest_gettimeofday),
+        export("nanosleep", vec![pointer(), pointer()], JITType::Int32, guest_nanosleep),
+        export("clock_nanosleep", vec![JITType::Int32, JITType::Int32, pointer(), pointer()], JITType::Int32, guest_clock_nanosleep),
+        export("usleep", vec![JITType::Int32], JITType::Int32, guest_usleep),
+        export("sleep", vec![JITType::Int32], JITType::Int32, guest_sleep),
+        export("alarm", vec![JITType::Int32], JITType::Int32, guest_alarm),
+        export("setitimer", vec![JITType::Int32, pointer(), pointer()], JITType::Int32, guest_setitimer),
+        export("getitimer", vec![JITType::Int32, pointer()], JITType::Int32, guest_getitimer),
+    ]
+}
+
+fn timespec(time: Duration) -> libc::timespec {
+    libc::timespec { tv_sec: time.as_secs() as libc::time_t, tv_nsec: time.subsec_nanos() as libc::c_long }
+}
+
+/// A `timespec` from the program, or `None` if it's out of range
+unsafe fn duration(time: *const libc::timespec) -> Option<Duration> {
+    let time = *time;
+    (time.tv_sec >= 0 && (0..1_000_000_000).contains(&time.tv_nsec))
+        .then(|| Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+fn timeval(time: Duration) -> libc::timeval {
+    libc::timeval { tv_sec: time.as_secs() as libc::time_t, tv_usec: time.subsec_micros() as libc::suseconds_t }
+}
+
+fn whole_seconds_up(time: Duration) -> i64 {
+    (time.as_secs() + u64::from(time.subsec_nanos() > 0)) as i64
+}
+
+fn guest_time(time: &VirtualTime, args: &[u64]) -> i64 {
+    let now = time.read(libc::CLOCK_REALTIME).as_secs() as i64;
+    let result = args[0] as *mut i64;
+    if !result.is_null() {
+        unsafe { *result = now };
+    }
+    now
+}
+
+fn guest_clock(time: &VirtualTime, _args: &[u64]) -> i64 {
+    let cpu = time.read(libc::CLOCK_PROCESS_CPUTIME_ID);
+    (cpu.as_micros() as u64 * CLOCKS_PER_SEC / 1_000_000) as i64
+}
+
+fn guest_clock_gettime(time: &VirtualTime, args: &[u64]) -> i64 {
+    let reading = time.read(args[0] as i32);
+    unsafe { *(args[1] as *mut libc::timespec) = timespec(reading) };
+    0
+}
+
+fn guest_gettimeofday(time: &VirtualTime, args: &[u64]) -> i64 {
+    let now = time.read(libc::CLOCK_REALTIME);
+    let result = args[0] as *mut libc::timeval;
+    if !result.is_null() {
+        unsafe { *result = timeval(now) };
+    }
+    0
+}
+
+fn guest_nanosleep(time: &VirtualTime, args: &[u64]) -> i64 {
+    let Some(request) = (unsafe { duration(args[0] as *const libc::timespec) }) else {
+        set_errno(libc::EINVAL);
+        return -1;
+    };
+    let left = time.sleep(request);
+    if left.is_zero() {
+        return 0;
+    }
+    let remaining = args[1] as *mut libc::timespec;
+    if !remaining.is_null() {
+        unsafe { *remaining = timespec(left) };
+    }
+    set_errno(libc::EINTR);
+    -1
+}
+
+/// Returns the error number instead of setting `errno`
+fn guest_clock_nanosleep(time: &VirtualTime, args: &[u64]) -> i64 {
+    let (clock, flags) = (args[0] as i32, args[1] as i32);
+    let Some(request) = (unsafe { duration(args[2] as *const libc::timespec) }) else {
+        return libc::EINVAL as i64;
+    };
+    let left = if flags & libc::TIMER_ABSTIME != 0 {
+        time.sleep_until(clock, request)
+    } else {
+        time.sleep(request)
+    };
+    if left.is_zero() {
+        return 0;
+    }
+    let remaining = args[3] as *mut libc::timespec;
+    if flags & libc::TIMER_ABSTIME == 0 && !remaining.is_null() {
+        unsafe { *remaining = timespec(left) };
+    }
+    libc::EINTR as i64
+}
+
+fn guest_usleep(time: &VirtualTime, args: &[u64]) -> i64 {
+    if time.sleep(Duration::from_micros(args[0] as u32 as u64)).is_zero() {
+        0
+    } else {
+        set_errno(libc::EINTR);
+        -1
+    }
+}
+
+/// Seconds left, rounded up, if cut short
+fn guest_sleep(time: &VirtualTime, args: &[u64]) -> i64 {
+    whole_seconds_up(time.sleep(Duration::from_secs(args[0] as u32 as u64)))
+}
+
+fn guest_alarm(time: &VirtualTime, args: &[u64]) -> i64 {
+    let (left, _) = time.set_timer(Duration::from_secs(args[0] as u32 as u64), Duration::ZERO);
+    whole_seconds_up(left)
+}
+
+fn guest_setitimer(time: &VirtualTime, args: &[u64]) -> i64 {
+    let (which, new, old) = (args[0] as i32, args[1] as *const libc::itimerval, args[2] as *mut libc::itimerval);
+    if which != libc::ITIMER_REAL {
+        // The CPU-time timers stay the host's
+        return unsafe { libc::setitimer(which, new, old) } as i64;
+    }
+    let to_duration = |value: libc::timeval| Duration::from_secs(value.tv_sec.max(0) as u64) + Duration::from_micros(value.tv_usec.max(0) as u64);
+    let new = unsafe { *new };
+    let (left, interval) = time.set_timer(to_duration(new.it_value), to_duration(new.it_interval));
+    if !old.is_null() {
+        unsafe { *old = libc::itimerval { it_interval: timeval(interval), it_value: timeval(left) } };
+    }
+    0
+}
+
+fn guest_getitimer(time: &VirtualTime, args: &[u64]) -> i64 {
+    let (which, current) = (args[0] as i32, args[1] as *mut libc::itimerval);
+    if which != libc::ITIMER_REAL {
+        return unsafe { libc::getitimer(which, current) } as i64;
+    }
+    let (left, interval) = time.timer();
+    unsafe { *current = libc::itimerval { it_interval: timeval(interval), it_value: timeval(left) } };
+    0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VirtualTimeError {
+    /// Scales must be finite and positive; `pause` stops the clock
+    InvalidScale(f64),
+}
+
+impl fmt::Display for VirtualTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VirtualTimeError::InvalidScale(scale) => write!(f, "invalid time scale {}: must be finite and positive", scale),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn test_retry_backoff() -> Result<(), EngineError> {
+    let time = Arc::new(VirtualTime::new(Duration::from_secs(1_704_067_200)));
+    time.set_skip_sleeps(true);
+
+    let mut engine = Engine::new()?;
+    engine.use_virtual_time(Arc::clone(&time))?;
+    // Sleeps 1 + 2 + 4 + ... seconds between attempts: over an hour
+    engine.eval_file("retry.c")?;
+    let (sleeps, skipped, _) = time.stats();
+    println!("{} sleeps, {:?} skipped in no time", sleeps, skipped);
+
+    // A watchdog that fires after 30 s of inactivity
+    time.set_skip_sleeps(false);
+    time.pause();
+    unsafe { engine.call_function::<()>("arm_watchdog", &[])? };
+    time.advance(Duration::from_secs(29));
+    assert!(!unsafe { engine.get_global::<bool>("watchdog_fired")? });
+    time.advance(Duration::from_secs(1));
+    Ok(())
+}
+*/
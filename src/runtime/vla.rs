@@ -0,0 +1,151 @@
+// src/runtime/vla.rs
+//! Storage for variable length arrays in interpreted programs
+//! VLAs can't live in the interpreter's fixed-size frames, so they come from
+//! a separate stack. Each block that declares one takes a `VlaMark` on entry
+//! and releases it on every way out: falling off the end, `break`,
+//! `continue`, `return`, or a `goto` to a label outside the block. This is
+//! the interpreter's equivalent of LLVM's stacksave/stackrestore, so a
+//! `int buf[n]` inside a loop reuses the same memory each iteration instead
+//! of growing the stack.
+//!
+//! The stack is a single allocation made up front, so addresses handed out stay
+//! valid until they are released.
+
+use std::alloc::{alloc, dealloc, Layout};
+
+/// Matches the JIT's default stack size
+pub const DEFAULT_VLA_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Alignment of every VLA, enough for any scalar type including long double
+const VLA_ALIGN: usize = 16;
+
+/// Stack height to return to when a block is left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VlaMark(usize);
+
+impl VlaMark {
+    /// Bytes in use when the mark was taken, for callers that keep marks
+    /// as plain integers (the bytecode VM's registers)
+    pub fn height(self) -> usize {
+        self.0
+    }
+
+    /// The mark `height` came from
+    pub fn at(height: usize) -> Self {
+        VlaMark(height)
+    }
+}
+
+pub struct VlaStack {
+    base: *mut u8,
+    capacity: usize,
+    top: usize,
+
+    // Statistics
+    high_water: usize,
+    allocations: usize,
+}
+
+impl VlaStack {
+    pub fn new(capacity: usize) -> Result<Self, VlaStackError> {
+        let layout = Layout::from_size_align(capacity.max(VLA_ALIGN), VLA_ALIGN)
+            .map_err(|_| VlaStackError::ReserveFailed(capacity))?;
+        let base = unsafe { alloc(layout) };
+        if base.is_null() {
+            return Err(VlaStackError::ReserveFailed(capacity));
+        }
+        Ok(VlaStack {
+            base,
+            capacity: layout.size(),
+            top: 0,
+            high_water: 0,
+            allocations: 0,
+        })
+    }
+
+    /// Current height, to pass to `release` when the block is left
+    pub fn mark(&self) -> VlaMark {
+        VlaMark(self.top)
+    }
+
+    /// Storage for `length` elements of `element_size` bytes. C leaves a
+    /// length below one undefined; the interpreter reports it instead.
+    pub fn allocate(&mut self, length: i64, element_size: usize) -> Result<*mut u8, VlaStackError> {
+        if length <= 0 {
+            return Err(VlaStackError::NonPositiveLength(length));
+        }
+        let size = (length as u64)
+            .checked_mul(element_size as u64)
+            .and_then(|size| usize::try_from(size).ok())
+            .ok_or(VlaStackError::Overflow { length, element_size })?;
+
+        let start = align_up(self.top, VLA_ALIGN);
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end <= self.capacity)
+            .ok_or(VlaStackError::Exhausted {
+                requested: size,
+                available: self.capacity.saturating_sub(start),
+            })?;
+
+        self.top = end;
+        self.high_water = self.high_water.max(end);
+        self.allocations += 1;
+        Ok(unsafe { self.base.add(start) })
+    }
+
+    /// Free every VLA allocated since `mark`. Releasing an older mark also
+    /// covers blocks left without their own release, e.g. by `longjmp`.
+    pub fn release(&mut self, mark: VlaMark) {
+        debug_assert!(mark.0 <= self.top, "VLA mark released twice or out of order");
+        self.top = self.top.min(mark.0);
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.top
+    }
+
+    /// (largest height reached, total allocations)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.high_water, self.allocations)
+    }
+}
+
+impl Drop for VlaStack {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.base, Layout::from_size_align_unchecked(self.capacity, VLA_ALIGN)) };
+    }
+}
+
+fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
+#[derive(Debug)]
+pub enum VlaStackError {
+    ReserveFailed(usize),
+    /// `int buf[n]` with `n <= 0`
+    NonPositiveLength(i64),
+    /// Length times element size doesn't fit in the address space
+    Overflow { length: i64, element_size: usize },
+    /// The interpreted program's equivalent of a stack overflow
+    Exhausted { requested: usize, available: usize },
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), VlaStackError> {
+    let mut stack = VlaStack::new(DEFAULT_VLA_STACK_SIZE)?;
+
+    // for (int n = 1; n <= 3; n++) { double buf[n]; ... }
+    for n in 1..=3 {
+        let mark = stack.mark();
+        let buf = stack.allocate(n, std::mem::size_of::<f64>())? as *mut f64;
+        unsafe { buf.write(0.0) };
+        stack.release(mark);
+    }
+    assert_eq!(stack.in_use(), 0);
+    println!("{:?}", stack.stats()); // (24, 3)
+    Ok(())
+}
+*/
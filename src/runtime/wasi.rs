@@ -0,0 +1,795 @@
+// src/runtime/wasi.rs
+//! WASI host calls over a sandboxed file system
+//! `WasiHost` is the one sandbox both execution paths go through. A program
+//! sees only the directories preopened with `--dir`, stdio, its arguments
+//! and environment, the clocks and a random source; there is no way to name
+//! a host path outside a preopen, through `..` or through a symlink.
+//!
+//! - C compiled for wasm32-wasi imports `wasi_snapshot_preview1`;
+//!   `add_to_linker` provides it to wasmtime, decoding iovecs and strings
+//!   from the module's linear memory.
+//! - Natively interpreted C calls the same methods directly: `open` maps a
+//!   POSIX path onto the preopen that contains it, as wasi-libc does, and
+//!   `read`, `write`, `lseek` and `close` become `fd_read`, `fd_write`,
+//!   `fd_seek` and `fd_close`.
+//!
+//! Only the calls C programs need are implemented: arguments, environment,
+//! clocks, randomness, file descriptors and `path_open`. Sockets, polling
+//! and directory listing return `Errno::Nosys`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub type Fd = u32;
+
+/// `wasi_snapshot_preview1` error codes, in the specification's order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Errno {
+    Success = 0,
+    Acces = 2,
+    Badf = 8,
+    Exist = 20,
+    Fault = 21,
+    Inval = 28,
+    Io = 29,
+    Isdir = 31,
+    Loop = 32,
+    Mfile = 33,
+    Nametoolong = 37,
+    Noent = 44,
+    Nospc = 51,
+    Nosys = 52,
+    Notdir = 54,
+    Notempty = 55,
+    Overflow = 61,
+    Perm = 63,
+    Rofs = 69,
+    Spipe = 70,
+    /// The sandbox doesn't grant this
+    Notcapable = 76,
+}
+
+impl From<io::Error> for Errno {
+    fn from(e: io::Error) -> Self {
+        match e.raw_os_error() {
+            Some(libc::ENOENT) => Errno::Noent,
+            Some(libc::EACCES) => Errno::Acces,
+            Some(libc::EPERM) => Errno::Perm,
+            Some(libc::EEXIST) => Errno::Exist,
+            Some(libc::EISDIR) => Errno::Isdir,
+            Some(libc::ENOTDIR) => Errno::Notdir,
+            Some(libc::ENOTEMPTY) => Errno::Notempty,
+            Some(libc::ENOSPC) => Errno::Nospc,
+            Some(libc::EROFS) => Errno::Rofs,
+            Some(libc::ELOOP) => Errno::Loop,
+            Some(libc::ENAMETOOLONG) => Errno::Nametoolong,
+            Some(libc::ESPIPE) => Errno::Spipe,
+            Some(libc::EINVAL) => Errno::Inval,
+            _ => match e.kind() {
+                io::ErrorKind::NotFound => Errno::Noent,
+                io::ErrorKind::PermissionDenied => Errno::Acces,
+                io::ErrorKind::AlreadyExists => Errno::Exist,
+                io::ErrorKind::InvalidInput => Errno::Inval,
+                _ => Errno::Io,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self, *self as u16)
+    }
+}
+
+// path_open oflags
+pub const OFLAGS_CREAT: u16 = 1 << 0;
+pub const OFLAGS_DIRECTORY: u16 = 1 << 1;
+pub const OFLAGS_EXCL: u16 = 1 << 2;
+pub const OFLAGS_TRUNC: u16 = 1 << 3;
+
+// fdflags
+pub const FDFLAGS_APPEND: u16 = 1 << 0;
+
+// Rights checked by this host; the rest are granted as requested
+pub const RIGHTS_FD_READ: u64 = 1 << 1;
+pub const RIGHTS_FD_SEEK: u64 = 1 << 2;
+pub const RIGHTS_FD_WRITE: u64 = 1 << 6;
+
+// Whence for fd_seek
+const WHENCE_SET: u8 = 0;
+const WHENCE_CUR: u8 = 1;
+const WHENCE_END: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FileType {
+    Unknown = 0,
+    CharacterDevice = 2,
+    Directory = 3,
+    RegularFile = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime,
+    Monotonic,
+    ProcessCputime,
+    ThreadCputime,
+}
+
+impl ClockId {
+    pub fn from_raw(id: u32) -> Result<Self, Errno> {
+        Ok(match id {
+            0 => ClockId::Realtime,
+            1 => ClockId::Monotonic,
+            2 => ClockId::ProcessCputime,
+            3 => ClockId::ThreadCputime,
+            _ => return Err(Errno::Inval),
+        })
+    }
+}
+
+/// What a preopened directory lets the program do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirAccess {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// `fd_fdstat_get`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdStat {
+    pub filetype: FileType,
+    pub flags: u16,
+    pub rights_base: u64,
+    pub rights_inheriting: u64,
+}
+
+struct Preopen {
+    /// The path the program uses, such as `/data` or `.`
+    guest: String,
+    /// Canonical host directory every lookup must stay inside
+    root: PathBuf,
+    access: DirAccess,
+}
+
+enum Descriptor {
+    Stdin(Box<dyn Read + Send>),
+    Stdout(Box<dyn Write + Send>),
+    Stderr(Box<dyn Write + Send>),
+    Preopen(Preopen),
+    File {
+        file: File,
+        readable: bool,
+        writable: bool,
+        append: bool,
+    },
+    /// A directory opened below a preopen, usable as a `path_open` base
+    Directory {
+        path: PathBuf,
+        root: PathBuf,
+        access: DirAccess,
+    },
+}
+
+pub struct WasiHost {
+    // Program-visible state
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    fds: BTreeMap<Fd, Descriptor>,
+    started: Instant,
+
+    // Statistics
+    calls: u64,
+    denied: u64,
+}
+
+impl Default for WasiHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WasiHost {
+    /// A sandbox with the host's stdio and no directories
+    pub fn new() -> Self {
+        let mut fds = BTreeMap::new();
+        fds.insert(0, Descriptor::Stdin(Box::new(io::stdin())));
+        fds.insert(1, Descriptor::Stdout(Box::new(io::stdout())));
+        fds.insert(2, Descriptor::Stderr(Box::new(io::stderr())));
+        WasiHost {
+            args: Vec::new(),
+            env: Vec::new(),
+            fds,
+            started: Instant::now(),
+            calls: 0,
+            denied: 0,
+        }
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn with_env(mut self, env: Vec<(String, String)>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_stdio(
+        mut self,
+        stdin: Box<dyn Read + Send>,
+        stdout: Box<dyn Write + Send>,
+        stderr: Box<dyn Write + Send>,
+    ) -> Self {
+        self.fds.insert(0, Descriptor::Stdin(stdin));
+        self.fds.insert(1, Descriptor::Stdout(stdout));
+        self.fds.insert(2, Descriptor::Stderr(stderr));
+        self
+    }
+
+    /// Make host directory `host` visible to the program as `guest`. Each
+    /// preopen takes the next descriptor, from 3, as WASI requires.
+    pub fn with_preopen(mut self, host: &Path, guest: &str, access: DirAccess) -> Result<Self, WasiError> {
+        let root = host
+            .canonicalize()
+            .map_err(|e| WasiError::Preopen(host.to_path_buf(), e))?;
+        if !root.is_dir() {
+            return Err(WasiError::Preopen(host.to_path_buf(), io::Error::from_raw_os_error(libc::ENOTDIR)));
+        }
+        let fd = self.next_fd().map_err(|_| WasiError::TooManyFiles)?;
+        let guest = guest.trim_end_matches('/').to_string();
+        let guest = if guest.is_empty() { "/".to_string() } else { guest };
+        self.fds.insert(fd, Descriptor::Preopen(Preopen { guest, root, access }));
+        Ok(self)
+    }
+
+    /// Parse `HOST[::GUEST]` as given to `--dir`; the guest path defaults
+    /// to the host path
+    pub fn with_dir_spec(self, spec: &str, access: DirAccess) -> Result<Self, WasiError> {
+        let (host, guest) = spec.split_once("::").unwrap_or((spec, spec));
+        self.with_preopen(Path::new(host), guest, access)
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// `KEY=VALUE` strings, as `environ_get` returns them
+    pub fn environ(&self) -> Vec<String> {
+        self.env.iter().map(|(key, value)| format!("{}={}", key, value)).collect()
+    }
+
+    pub fn fd_read(&mut self, fd: Fd, bufs: &mut [&mut [u8]]) -> Result<usize, Errno> {
+        self.calls += 1;
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let read = match self.fds.get_mut(&fd).ok_or(Errno::Badf)? {
+                Descriptor::Stdin(stdin) => stdin.read(buf)?,
+                Descriptor::File { file, readable: true, .. } => file.read(buf)?,
+                Descriptor::Preopen(_) | Descriptor::Directory { .. } => return Err(Errno::Isdir),
+                _ => return Err(Errno::Badf),
+            };
+            total += read;
+            // A short read means there is nothing more right now
+            if read < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    pub fn fd_write(&mut self, fd: Fd, bufs: &[&[u8]]) -> Result<usize, Errno> {
+        self.calls += 1;
+        let out: &mut dyn Write = match self.fds.get_mut(&fd).ok_or(Errno::Badf)? {
+            Descriptor::Stdout(stdout) => stdout.as_mut(),
+            Descriptor::Stderr(stderr) => stderr.as_mut(),
+            Descriptor::File { file, writable: true, append, .. } => {
+                if *append {
+                    file.seek(SeekFrom::End(0))?;
+                }
+                file
+            }
+            Descriptor::Preopen(_) | Descriptor::Directory { .. } => return Err(Errno::Isdir),
+            _ => return Err(Errno::Badf),
+        };
+        let mut total = 0;
+        for buf in bufs {
+            out.write_all(buf)?;
+            total += buf.len();
+        }
+        out.flush()?;
+        Ok(total)
+    }
+
+    pub fn fd_seek(&mut self, fd: Fd, offset: i64, whence: u8) -> Result<u64, Errno> {
+        self.calls += 1;
+        let position = match whence {
+            WHENCE_SET => SeekFrom::Start(u64::try_from(offset).map_err(|_| Errno::Inval)?),
+            WHENCE_CUR => SeekFrom::Current(offset),
+            WHENCE_END => SeekFrom::End(offset),
+            _ => return Err(Errno::Inval),
+        };
+        match self.fds.get_mut(&fd).ok_or(Errno::Badf)? {
+            Descriptor::File { file, .. } => Ok(file.seek(position)?),
+            Descriptor::Preopen(_) | Descriptor::Directory { .. } => Err(Errno::Isdir),
+            _ => Err(Errno::Spipe),
+        }
+    }
+
+    pub fn fd_close(&mut self, fd: Fd) -> Result<(), Errno> {
+        self.calls += 1;
+        match self.fds.get(&fd) {
+            // wasi-libc never closes preopens; refusing keeps them stable
+            Some(Descriptor::Preopen(_)) => Err(Errno::Notcapable),
+            Some(_) => {
+                self.fds.remove(&fd);
+                Ok(())
+            }
+            None => Err(Errno::Badf),
+        }
+    }
+
+    pub fn fd_fdstat_get(&mut self, fd: Fd) -> Result<FdStat, Errno> {
+        self.calls += 1;
+        let (filetype, flags, rights) = match self.fds.get(&fd).ok_or(Errno::Badf)? {
+            Descriptor::Stdin(_) => (FileType::CharacterDevice, 0, RIGHTS_FD_READ),
+            Descriptor::Stdout(_) | Descriptor::Stderr(_) => (FileType::CharacterDevice, 0, RIGHTS_FD_WRITE),
+            Descriptor::File { readable, writable, append, .. } => {
+                let mut rights = RIGHTS_FD_SEEK;
+                if *readable {
+                    rights |= RIGHTS_FD_READ;
+                }
+                if *writable {
+                    rights |= RIGHTS_FD_WRITE;
+                }
+                (FileType::RegularFile, if *append { FDFLAGS_APPEND } else { 0 }, rights)
+            }
+            // Directories grant everything their files may be opened with
+            Descriptor::Preopen(_) | Descriptor::Directory { .. } => (FileType::Directory, 0, u64::MAX),
+        };
+        Ok(FdStat { filetype, flags, rights_base: rights, rights_inheriting: rights })
+    }
+
+    /// Length of a preopen's guest path; `Badf` past the last preopen,
+    /// which is how wasi-libc finds them all
+    pub fn fd_prestat_get(&mut self, fd: Fd) -> Result<usize, Errno> {
+        self.fd_prestat_dir_name(fd).map(str::len)
+    }
+
+    pub fn fd_prestat_dir_name(&mut self, fd: Fd) -> Result<&str, Errno> {
+        self.calls += 1;
+        match self.fds.get(&fd) {
+            Some(Descriptor::Preopen(preopen)) => Ok(&preopen.guest),
+            _ => Err(Errno::Badf),
+        }
+    }
+
+    /// Open `path` relative to the directory `dirfd`. Read and write access
+    /// come from `RIGHTS_FD_READ` and `RIGHTS_FD_WRITE` in `rights`.
+    pub fn path_open(&mut self, dirfd: Fd, path: &str, oflags: u16, rights: u64, fdflags: u16) -> Result<Fd, Errno> {
+        self.calls += 1;
+        let (base, root, access) = match self.fds.get(&dirfd).ok_or(Errno::Badf)? {
+            Descriptor::Preopen(preopen) => (preopen.root.clone(), preopen.root.clone(), preopen.access),
+            Descriptor::Directory { path, root, access } => (path.clone(), root.clone(), *access),
+            _ => return Err(Errno::Notdir),
+        };
+        let host_path = self.confine(&base, &root, path)?;
+
+        let readable = rights & RIGHTS_FD_READ != 0;
+        let writable = rights & RIGHTS_FD_WRITE != 0;
+        let modifies = writable || oflags & (OFLAGS_CREAT | OFLAGS_TRUNC) != 0;
+        if modifies && access == DirAccess::ReadOnly {
+            self.denied += 1;
+            return Err(Errno::Rofs);
+        }
+
+        let fd = self.next_fd()?;
+        if oflags & OFLAGS_DIRECTORY != 0 || (host_path.is_dir() && !writable) {
+            if !host_path.is_dir() {
+                return Err(Errno::Notdir);
+            }
+            self.fds.insert(fd, Descriptor::Directory { path: host_path, root, access });
+            return Ok(fd);
+        }
+
+        let mut options = OpenOptions::new();
+        options.read(readable || !writable).write(writable);
+        if oflags & OFLAGS_CREAT != 0 {
+            if oflags & OFLAGS_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        if oflags & OFLAGS_TRUNC != 0 {
+            options.truncate(true);
+        }
+        let file = options.open(&host_path)?;
+        self.fds.insert(fd, Descriptor::File {
+            file,
+            readable: readable || !writable,
+            writable,
+            append: fdflags & FDFLAGS_APPEND != 0,
+        });
+        Ok(fd)
+    }
+
+    /// POSIX `open` for natively interpreted code: the longest preopen that
+    /// contains `path` is the base. Relative paths resolve against `.`.
+    pub fn open(&mut self, path: &str, oflags: u16, rights: u64, fdflags: u16) -> Result<Fd, Errno> {
+        let (dirfd, relative) = self.find_preopen(path).ok_or_else(|| {
+            self.denied += 1;
+            Errno::Notcapable
+        })?;
+        self.path_open(dirfd, &relative, oflags, rights, fdflags)
+    }
+
+    /// Nanoseconds on `clock`. Monotonic time starts when the sandbox was
+    /// created; both CPU clocks report it too, as WASI allows.
+    pub fn clock_time_get(&mut self, clock: ClockId, _precision: u64) -> Result<u64, Errno> {
+        self.calls += 1;
+        let nanos = match clock {
+            ClockId::Realtime => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| Errno::Overflow)?
+                .as_nanos(),
+            ClockId::Monotonic | ClockId::ProcessCputime | ClockId::ThreadCputime => self.started.elapsed().as_nanos(),
+        };
+        u64::try_from(nanos).map_err(|_| Errno::Overflow)
+    }
+
+    pub fn clock_res_get(&mut self, _clock: ClockId) -> Result<u64, Errno> {
+        self.calls += 1;
+        Ok(1)
+    }
+
+    pub fn random_get(&mut self, buf: &mut [u8]) -> Result<(), Errno> {
+        self.calls += 1;
+        File::open("/dev/urandom")?.read_exact(buf)?;
+        Ok(())
+    }
+
+    /// (calls handled, calls refused by the sandbox)
+    pub fn stats(&self) -> (u64, u64) {
+        (self.calls, self.denied)
+    }
+
+    fn next_fd(&self) -> Result<Fd, Errno> {
+        (0..=Fd::MAX).find(|fd| !self.fds.contains_key(fd)).ok_or(Errno::Mfile)
+    }
+
+    fn find_preopen(&self, path: &str) -> Option<(Fd, String)> {
+        self.fds
+            .iter()
+            .filter_map(|(&fd, descriptor)| match descriptor {
+                Descriptor::Preopen(preopen) => {
+                    let relative = if preopen.guest == "." {
+                        (!path.starts_with('/')).then_some(path)
+                    } else if preopen.guest == "/" {
+                        path.strip_prefix('/')
+                    } else {
+                        path.strip_prefix(preopen.guest.as_str())
+                            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+                            .map(|rest| rest.trim_start_matches('/'))
+                    }?;
+                    Some((preopen.guest.len(), fd, relative))
+                }
+                _ => None,
+            })
+            .max_by_key(|&(len, _, _)| len)
+            .map(|(_, fd, relative)| (fd, if relative.is_empty() { ".".to_string() } else { relative.to_string() }))
+    }
+
+    /// Host path for `path` below `base`, refused unless it stays inside
+    /// `root` both lexically and after resolving symlinks
+    fn confine(&mut self, base: &Path, root: &Path, path: &str) -> Result<PathBuf, Errno> {
+        let escape = |host: &mut Self| {
+            host.denied += 1;
+            Errno::Notcapable
+        };
+        let mut resolved = base.to_path_buf();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                Component::ParentDir if resolved != root => {
+                    resolved.pop();
+                }
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Err(escape(self)),
+            }
+        }
+
+        // A symlink may still point outside; check what it really names
+        let real = match resolved.canonicalize() {
+            Ok(real) => real,
+            Err(_) => match (resolved.parent(), resolved.file_name()) {
+                (Some(parent), Some(name)) => fs::canonicalize(parent)?.join(name),
+                _ => return Err(Errno::Inval),
+            },
+        };
+        if !real.starts_with(root) {
+            return Err(escape(self));
+        }
+        Ok(real)
+    }
+}
+
+/// Memory the ABI layer reads arguments from and writes results to
+pub trait GuestMemory {
+    fn read(&self, address: u32, buf: &mut [u8]) -> Result<(), Errno>;
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Errno>;
+}
+
+/// A wasm linear memory
+impl GuestMemory for [u8] {
+    fn read(&self, address: u32, buf: &mut [u8]) -> Result<(), Errno> {
+        let start = address as usize;
+        let bytes = self.get(start..start + buf.len()).ok_or(Errno::Fault)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Errno> {
+        let start = address as usize;
+        self.get_mut(start..start + data.len())
+            .ok_or(Errno::Fault)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+}
+
+fn load_u32<M: GuestMemory + ?Sized>(memory: &M, address: u32) -> Result<u32, Errno> {
+    let mut bytes = [0; 4];
+    memory.read(address, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn store_u32<M: GuestMemory + ?Sized>(memory: &mut M, address: u32, value: u32) -> Result<(), Errno> {
+    memory.write(address, &value.to_le_bytes())
+}
+
+fn store_u64<M: GuestMemory + ?Sized>(memory: &mut M, address: u32, value: u64) -> Result<(), Errno> {
+    memory.write(address, &value.to_le_bytes())
+}
+
+/// `(ptr, len)` pairs of an iovec array
+fn iovecs<M: GuestMemory + ?Sized>(memory: &M, iovs: u32, count: u32) -> Result<Vec<(u32, u32)>, Errno> {
+    (0..count)
+        .map(|index| {
+            let entry = iovs.checked_add(index.checked_mul(8).ok_or(Errno::Fault)?).ok_or(Errno::Fault)?;
+            Ok((load_u32(memory, entry)?, load_u32(memory, entry + 4)?))
+        })
+        .collect()
+}
+
+/// Write NUL-terminated `strings` at `buf` and their addresses at `ptrs`
+fn store_strings<M: GuestMemory + ?Sized>(memory: &mut M, strings: &[String], ptrs: u32, mut buf: u32) -> Result<(), Errno> {
+    for (index, s) in strings.iter().enumerate() {
+        store_u32(memory, ptrs + index as u32 * 4, buf)?;
+        memory.write(buf, s.as_bytes())?;
+        memory.write(buf + s.len() as u32, &[0])?;
+        buf += s.len() as u32 + 1;
+    }
+    Ok(())
+}
+
+/// `proc_exit` unwinds the whole program rather than returning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasiExit(pub i32);
+
+impl fmt::Display for WasiExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "exit({})", self.0)
+    }
+}
+
+impl std::error::Error for WasiExit {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    I32,
+    I64,
+}
+
+use ParamType::{I32, I64};
+
+/// The `wasi_snapshot_preview1` functions `dispatch` answers, with their
+/// parameter types; all but `proc_exit` return an i32 errno
+pub const PREVIEW1: &[(&str, &[ParamType])] = &[
+    ("args_get", &[I32, I32]),
+    ("args_sizes_get", &[I32, I32]),
+    ("environ_get", &[I32, I32]),
+    ("environ_sizes_get", &[I32, I32]),
+    ("clock_res_get", &[I32, I32]),
+    ("clock_time_get", &[I32, I64, I32]),
+    ("fd_close", &[I32]),
+    ("fd_fdstat_get", &[I32, I32]),
+    ("fd_prestat_get", &[I32, I32]),
+    ("fd_prestat_dir_name", &[I32, I32, I32]),
+    ("fd_read", &[I32, I32, I32, I32]),
+    ("fd_seek", &[I32, I64, I32, I32]),
+    ("fd_write", &[I32, I32, I32, I32]),
+    ("path_open", &[I32, I32, I32, I32, I32, I64, I64, I32, I32]),
+    ("proc_exit", &[I32]),
+    ("random_get", &[I32, I32]),
+    ("sched_yield", &[]),
+];
+
+impl WasiHost {
+    /// Run the preview1 function `name` with raw wasm arguments, reading
+    /// and writing `memory`. Unknown names return `Nosys`.
+    pub fn dispatch<M: GuestMemory + ?Sized>(&mut self, memory: &mut M, name: &str, args: &[i64]) -> Result<Errno, WasiExit> {
+        let arg = |index: usize| args.get(index).copied().unwrap_or(0) as u32;
+        let arg64 = |index: usize| args.get(index).copied().unwrap_or(0) as u64;
+
+        let result = match name {
+            "proc_exit" => return Err(WasiExit(arg(0) as i32)),
+            "sched_yield" => Ok(()),
+            "args_sizes_get" => {
+                let size = self.args.iter().map(|a| a.len() + 1).sum::<usize>();
+                store_u32(memory, arg(0), self.args.len() as u32).and_then(|_| store_u32(memory, arg(1), size as u32))
+            }
+            "args_get" => store_strings(memory, &self.args.clone(), arg(0), arg(1)),
+            "environ_sizes_get" => {
+                let environ = self.environ();
+                let size = environ.iter().map(|e| e.len() + 1).sum::<usize>();
+                store_u32(memory, arg(0), environ.len() as u32).and_then(|_| store_u32(memory, arg(1), size as u32))
+            }
+            "environ_get" => store_strings(memory, &self.environ(), arg(0), arg(1)),
+            "clock_res_get" => ClockId::from_raw(arg(0))
+                .and_then(|clock| self.clock_res_get(clock))
+                .and_then(|res| store_u64(memory, arg(1), res)),
+            "clock_time_get" => ClockId::from_raw(arg(0))
+                .and_then(|clock| self.clock_time_get(clock, arg64(1)))
+                .and_then(|now| store_u64(memory, arg(2), now)),
+            "fd_close" => self.fd_close(arg(0)),
+            "fd_fdstat_get" => self.fd_fdstat_get(arg(0)).and_then(|stat| {
+                // struct fdstat { u8 filetype; u16 flags; u64 rights_base; u64 rights_inheriting; }
+                let mut bytes = [0u8; 24];
+                bytes[0] = stat.filetype as u8;
+                bytes[2..4].copy_from_slice(&stat.flags.to_le_bytes());
+                bytes[8..16].copy_from_slice(&stat.rights_base.to_le_bytes());
+                bytes[16..24].copy_from_slice(&stat.rights_inheriting.to_le_bytes());
+                memory.write(arg(1), &bytes)
+            }),
+            "fd_prestat_get" => self.fd_prestat_get(arg(0)).and_then(|len| {
+                // struct prestat { u8 tag = 0 (dir); u32 name_len; }
+                memory.write(arg(1), &[0; 4]).and_then(|_| store_u32(memory, arg(1) + 4, len as u32))
+            }),
+            "fd_prestat_dir_name" => {
+                let name = self.fd_prestat_dir_name(arg(0)).map(|name| name.as_bytes().to_vec());
+                name.and_then(|name| {
+                    let len = (arg(2) as usize).min(name.len());
+                    memory.write(arg(1), &name[..len])
+                })
+            }
+            "fd_read" => iovecs(memory, arg(1), arg(2)).and_then(|iovs| {
+                let mut bufs: Vec<Vec<u8>> = iovs.iter().map(|&(_, len)| vec![0; len as usize]).collect();
+                let mut slices: Vec<&mut [u8]> = bufs.iter_mut().map(Vec::as_mut_slice).collect();
+                let mut remaining = self.fd_read(arg(0), &mut slices)?;
+                let total = remaining;
+                for (&(ptr, _), buf) in iovs.iter().zip(&bufs) {
+                    let len = remaining.min(buf.len());
+                    memory.write(ptr, &buf[..len])?;
+                    remaining -= len;
+                }
+                store_u32(memory, arg(3), total as u32)
+            }),
+            "fd_write" => iovecs(memory, arg(1), arg(2)).and_then(|iovs| {
+                let mut bufs = Vec::with_capacity(iovs.len());
+                for (ptr, len) in iovs {
+                    let mut buf = vec![0; len as usize];
+                    memory.read(ptr, &mut buf)?;
+                    bufs.push(buf);
+                }
+                let slices: Vec<&[u8]> = bufs.iter().map(Vec::as_slice).collect();
+                let written = self.fd_write(arg(0), &slices)?;
+                store_u32(memory, arg(3), written as u32)
+            }),
+            "fd_seek" => self
+                .fd_seek(arg(0), arg64(1) as i64, arg(2) as u8)
+                .and_then(|position| store_u64(memory, arg(3), position)),
+            "path_open" => {
+                // (dirfd, lookupflags, path, path_len, oflags, rights_base, rights_inheriting, fdflags, *fd)
+                let mut path = vec![0; arg(3) as usize];
+                memory
+                    .read(arg(2), &mut path)
+                    .and_then(|_| String::from_utf8(path).map_err(|_| Errno::Inval))
+                    .and_then(|path| self.path_open(arg(0), &path, arg(4) as u16, arg64(5), arg(7) as u16))
+                    .and_then(|fd| store_u32(memory, arg(8), fd))
+            }
+            "random_get" => {
+                let mut buf = vec![0; arg(1) as usize];
+                self.random_get(&mut buf).and_then(|_| memory.write(arg(0), &buf))
+            }
+            _ => {
+                self.calls += 1;
+                Err(Errno::Nosys)
+            }
+        };
+        Ok(result.err().unwrap_or(Errno::Success))
+    }
+}
+
+/// Provide `wasi_snapshot_preview1` to modules instantiated with `linker`;
+/// `host` finds the sandbox in the store's data. A module calling
+/// `proc_exit` traps with a `WasiExit` error.
+pub fn add_to_linker<T: 'static>(
+    linker: &mut wasmtime::Linker<T>,
+    host: fn(&mut T) -> &mut WasiHost,
+) -> wasmtime::Result<()> {
+    use wasmtime::{Caller, Extern, FuncType, Val, ValType};
+
+    for &(name, params) in PREVIEW1 {
+        let params = params.iter().map(|param| match param {
+            ParamType::I32 => ValType::I32,
+            ParamType::I64 => ValType::I64,
+        });
+        let results = if name == "proc_exit" { None } else { Some(ValType::I32) };
+        let ty = FuncType::new(params, results);
+        linker.func_new("wasi_snapshot_preview1", name, ty, move |mut caller: Caller<'_, T>, args: &[Val], results: &mut [Val]| {
+            let args: Vec<i64> = args
+                .iter()
+                .map(|arg| match arg {
+                    Val::I32(v) => *v as i64,
+                    Val::I64(v) => *v,
+                    _ => 0,
+                })
+                .collect();
+            let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+                results[0] = Val::I32(Errno::Fault as i32);
+                return Ok(());
+            };
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let errno = host(state).dispatch(data, name, &args)?;
+            results[0] = Val::I32(errno as i32);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum WasiError {
+    Preopen(PathBuf, io::Error),
+    TooManyFiles,
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let sandbox = WasiHost::new()
+        .with_args(vec!["prog.wasm".to_string()])
+        .with_preopen(Path::new("./data"), "/data", DirAccess::ReadOnly)?;
+
+    // wasm32-wasi module
+    let engine = wasmtime::Engine::default();
+    let mut store = wasmtime::Store::new(&engine, sandbox);
+    let mut linker = wasmtime::Linker::new(&engine);
+    add_to_linker(&mut linker, |sandbox| sandbox)?;
+    let module = wasmtime::Module::from_file(&engine, "prog.wasm")?;
+    let instance = linker.instantiate(&mut store, &module)?;
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    if let Err(trap) = start.call(&mut store, ()) {
+        let code = trap.downcast_ref::<WasiExit>().map_or(1, |exit| exit.0);
+        println!("exited with {}", code);
+    }
+
+    // The same sandbox for native code: fopen("/data/in.txt", "r")
+    let sandbox = store.data_mut();
+    let fd = sandbox.open("/data/in.txt", 0, RIGHTS_FD_READ, 0)?;
+    let mut buf = [0u8; 64];
+    let n = sandbox.fd_read(fd, &mut [&mut buf])?;
+    println!("{:?}", &buf[..n]);
+    assert_eq!(sandbox.open("/etc/passwd", 0, RIGHTS_FD_READ, 0), Err(Errno::Notcapable));
+    Ok(())
+}
+*/
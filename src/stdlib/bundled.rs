@@ -0,0 +1,364 @@
+// src/stdlib/bundled.rs
+//! Bundled minimal libc for `--libc=bundled`
+//! The headers and sources under `libc/` are embedded in the binary. On
+//! first use for a target they are written to the cache directory and
+//! compiled by this compiler into `crt1.o` and `libc.a`. Programs built
+//! with `--libc=bundled` then see only these headers and link only these
+//! objects, so the result doesn't depend on the host's headers or libc.
+//! The interpreter can't be given an include path, so `inline_headers`
+//! pastes the headers into the source instead, and its library calls go to
+//! `libc.a` loaded into a JIT.
+//!
+//! Scope is deliberately small: stdio on file descriptors, malloc over mmap,
+//! string/ctype/conversion routines, <fenv.h>, and exit/atexit/abort. There
+//! is no floating-point printf, locale or threads. Linux on x86_64 and
+//! aarch64 only.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use object::{Object, ObjectSymbol};
+use serde::{Deserialize, Serialize};
+use crate::arch::Architecture;
+use crate::report::provenance;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LibcMode {
+    /// System headers and the host libc
+    Host,
+    /// The embedded libc, compiled on first use
+    Bundled,
+}
+
+impl FromStr for LibcMode {
+    type Err = BundledLibcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(LibcMode::Host),
+            "bundled" => Ok(LibcMode::Bundled),
+            other => Err(BundledLibcError::UnknownMode(other.to_string())),
+        }
+    }
+}
+
+const HEADERS: &[(&str, &str)] = &[
+    ("assert.h", include_str!("libc/include/assert.h")),
+    ("ctype.h", include_str!("libc/include/ctype.h")),
+    ("errno.h", include_str!("libc/include/errno.h")),
+    ("fenv.h", include_str!("libc/include/fenv.h")),
+    ("limits.h", include_str!("libc/include/limits.h")),
+    ("stdarg.h", include_str!("libc/include/stdarg.h")),
+    ("stdbool.h", include_str!("libc/include/stdbool.h")),
+    ("stddef.h", include_str!("libc/include/stddef.h")),
+    ("stdint.h", include_str!("libc/include/stdint.h")),
+    ("stdio.h", include_str!("libc/include/stdio.h")),
+    ("stdlib.h", include_str!("libc/include/stdlib.h")),
+    ("string.h", include_str!("libc/include/string.h")),
+    ("unistd.h", include_str!("libc/include/unistd.h")),
+];
+
+/// Compiled into libc.a, one object each (`internal.h` is only included)
+const SOURCES: &[(&str, &str)] = &[
+    ("internal.h", include_str!("libc/src/internal.h")),
+    ("ctype.c", include_str!("libc/src/ctype.c")),
+    ("fenv.c", include_str!("libc/src/fenv.c")),
+    ("malloc.c", include_str!("libc/src/malloc.c")),
+    ("start.c", include_str!("libc/src/start.c")),
+    ("stdio.c", include_str!("libc/src/stdio.c")),
+    ("stdlib.c", include_str!("libc/src/stdlib.c")),
+    ("string.c", include_str!("libc/src/string.c")),
+    ("unistd.c", include_str!("libc/src/unistd.c")),
+];
+
+/// (crt1, assembly members of libc.a) per architecture
+const ARCH_SOURCES: &[(&str, (&str, &[(&str, &str)]))] = &[
+    ("x86_64", (include_str!("libc/src/crt1-x86_64.s"), &[
+        ("syscall-x86_64.s", include_str!("libc/src/syscall-x86_64.s")),
+        ("fpu-x86_64.s", include_str!("libc/src/fpu-x86_64.s")),
+    ])),
+    ("aarch64", (include_str!("libc/src/crt1-aarch64.s"), &[
+        ("syscall-aarch64.s", include_str!("libc/src/syscall-aarch64.s")),
+        ("fpu-aarch64.s", include_str!("libc/src/fpu-aarch64.s")),
+    ])),
+];
+
+/// Marks a fully built directory; written last
+const READY_FILE: &str = "READY";
+
+/// Compiles one C or assembly file (by extension) to an object, searching
+/// only the given include directories for `<...>` headers
+pub type SourceCompiler<'a> = &'a mut dyn FnMut(&Path, &Path, &[PathBuf]) -> Result<(), String>;
+
+#[derive(Debug, Clone)]
+pub struct BundledLibc {
+    root: PathBuf,
+}
+
+impl BundledLibc {
+    /// Reuse the build under `cache_root` for `arch`, or build it now
+    pub fn prepare(
+        cache_root: &Path,
+        arch: Architecture,
+        compile: SourceCompiler,
+    ) -> Result<Self, BundledLibcError> {
+        let arch_name = match arch {
+            Architecture::X86_64 => "x86_64",
+            Architecture::AArch64 => "aarch64",
+            other => return Err(BundledLibcError::UnsupportedArchitecture(format!("{:?}", other))),
+        };
+        let (crt1, arch_units) = ARCH_SOURCES
+            .iter()
+            .find(|(name, _)| *name == arch_name)
+            .map(|(_, sources)| *sources)
+            .unwrap();
+
+        // Any change to the sources or the compiler gets a fresh directory
+        let mut fingerprint = format!("{};{};", env!("CARGO_PKG_VERSION"), arch_name).into_bytes();
+        for (name, text) in HEADERS.iter().chain(SOURCES) {
+            fingerprint.extend_from_slice(name.as_bytes());
+            fingerprint.extend_from_slice(text.as_bytes());
+        }
+        fingerprint.extend_from_slice(crt1.as_bytes());
+        for (name, text) in arch_units {
+            fingerprint.extend_from_slice(name.as_bytes());
+            fingerprint.extend_from_slice(text.as_bytes());
+        }
+
+        let root = cache_root
+            .join("bundled-libc")
+            .join(format!("{}-{}", arch_name, provenance::sha256(&fingerprint)));
+        let libc = BundledLibc { root: root.clone() };
+        if root.join(READY_FILE).is_file() {
+            return Ok(libc);
+        }
+
+        log::info!("building bundled libc for {} in {}", arch_name, root.display());
+        let staging = root.with_extension(format!("tmp{}", std::process::id()));
+        let result = Self::build(&staging, crt1, arch_units, arch_name, compile);
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+
+        // A concurrent build may have won the race; its copy is just as good
+        if fs::rename(&staging, &root).is_err() {
+            let _ = fs::remove_dir_all(&staging);
+            if !root.join(READY_FILE).is_file() {
+                return Err(BundledLibcError::Io(root, io::Error::new(io::ErrorKind::Other, "could not install build")));
+            }
+        }
+        Ok(libc)
+    }
+
+    fn build(
+        dir: &Path,
+        crt1: &str,
+        arch_units: &[(&str, &str)],
+        arch_name: &str,
+        compile: SourceCompiler,
+    ) -> Result<(), BundledLibcError> {
+        let io_err = |path: &Path| {
+            let path = path.to_path_buf();
+            move |e| BundledLibcError::Io(path, e)
+        };
+
+        let include = dir.join("include");
+        let src = dir.join("src");
+        let obj = dir.join("obj");
+        for d in [&include, &src, &obj] {
+            fs::create_dir_all(d).map_err(io_err(d))?;
+        }
+        for (name, text) in HEADERS {
+            fs::write(include.join(name), text).map_err(io_err(&include.join(name)))?;
+        }
+
+        let crt1_name = format!("crt1-{}.s", arch_name);
+        let mut units: Vec<(String, &str)> = SOURCES
+            .iter()
+            .chain(arch_units)
+            .map(|(name, text)| (name.to_string(), *text))
+            .collect();
+        units.push((crt1_name.clone(), crt1));
+
+        let include_dirs = vec![include.clone()];
+        let mut members = Vec::new();
+        for (name, text) in &units {
+            let path = src.join(name);
+            fs::write(&path, text).map_err(io_err(&path))?;
+            if name.ends_with(".h") {
+                continue;
+            }
+
+            // `syscall-x86_64.s` -> `syscall.o`; archive member names must stay short
+            let stem = Path::new(name).file_stem().unwrap().to_string_lossy().into_owned();
+            let stem = stem.trim_end_matches(&format!("-{}", arch_name)).to_string();
+            let object = if *name == crt1_name { dir.join("crt1.o") } else { obj.join(format!("{}.o", stem)) };
+            compile(&path, &object, &include_dirs).map_err(|e| BundledLibcError::Compile(name.clone(), e))?;
+
+            if *name != crt1_name {
+                let data = fs::read(&object).map_err(io_err(&object))?;
+                members.push((format!("{}.o", stem), data));
+            }
+        }
+
+        write_archive(&dir.join("libc.a"), &members).map_err(io_err(&dir.join("libc.a")))?;
+        fs::write(dir.join(READY_FILE), env!("CARGO_PKG_VERSION")).map_err(io_err(&dir.join(READY_FILE)))?;
+        Ok(())
+    }
+
+    /// The only system include directory programs should see
+    pub fn include_dir(&self) -> PathBuf {
+        self.root.join("include")
+    }
+
+    /// Contains `libc.a`; link with `-L<dir> -lc`
+    pub fn library_dir(&self) -> PathBuf {
+        self.root.clone()
+    }
+
+    pub fn archive(&self) -> PathBuf {
+        self.root.join("libc.a")
+    }
+
+    /// Startup object defining `_start`; replaces the built-in crt0
+    pub fn crt1(&self) -> PathBuf {
+        self.root.join("crt1.o")
+    }
+}
+
+/// `source` with every `#include <...>` replaced by the bundled header, so
+/// it parses without an include path. Each directive in the program is
+/// followed by a `#line` putting its next line back where it was; includes
+/// in quotes are left alone.
+pub fn inline_headers(source: &str) -> Result<String, BundledLibcError> {
+    let mut out = String::with_capacity(source.len());
+    expand(source, true, &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+/// `open` holds the headers being expanded, so a cycle stops instead of
+/// recursing; `assert.h` has no guard and is pasted every time
+fn expand(text: &str, top: bool, open: &mut Vec<&'static str>, out: &mut String) -> Result<(), BundledLibcError> {
+    for (index, line) in text.lines().enumerate() {
+        let Some(name) = system_include(line) else {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        };
+        let (name, header) = HEADERS
+            .iter()
+            .copied()
+            .find(|(header, _)| *header == name)
+            .ok_or_else(|| BundledLibcError::MissingHeader(name.to_string()))?;
+        if !open.contains(&name) {
+            open.push(name);
+            expand(header, false, open, out)?;
+            open.pop();
+        }
+        if top {
+            let _ = writeln!(out, "#line {}", index + 2);
+        }
+    }
+    Ok(())
+}
+
+/// The `name` of an `#include <name>` line
+fn system_include(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim();
+    rest.strip_prefix('<')?.split_once('>').map(|(name, _)| name.trim())
+}
+
+/// System V `ar` archive with a GNU symbol index, so linkers pull members
+/// on demand without running ranlib
+pub(crate) fn write_archive(path: &Path, members: &[(String, Vec<u8>)]) -> io::Result<()> {
+    fn header(name: &str, size: usize) -> String {
+        format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 644, size)
+    }
+    fn padded(size: usize) -> usize {
+        size + (size & 1)
+    }
+
+    // Global symbols each member defines
+    let mut symbols: Vec<(usize, String)> = Vec::new();
+    for (index, (_, data)) in members.iter().enumerate() {
+        let file = object::File::parse(&**data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        for symbol in file.symbols() {
+            if symbol.is_global() && !symbol.is_undefined() {
+                if let Ok(name) = symbol.name() {
+                    if !name.is_empty() {
+                        symbols.push((index, name.to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    let names_len: usize = symbols.iter().map(|(_, name)| name.len() + 1).sum();
+    let index_size = 4 + 4 * symbols.len() + names_len;
+
+    // Member header offsets, counted from the start of the file
+    let mut offsets = Vec::with_capacity(members.len());
+    let mut offset = 8 + 60 + padded(index_size);
+    for (_, data) in members {
+        offsets.push(offset as u32);
+        offset += 60 + padded(data.len());
+    }
+
+    let mut out = Vec::with_capacity(offset);
+    out.extend_from_slice(b"!<arch>\n");
+    out.extend_from_slice(header("/", index_size).as_bytes());
+    out.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+    for (index, _) in &symbols {
+        out.extend_from_slice(&offsets[*index].to_be_bytes());
+    }
+    for (_, name) in &symbols {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+    }
+    if index_size % 2 == 1 {
+        out.push(b'\n');
+    }
+
+    for (name, data) in members {
+        // Short names only: callers keep member names to 15 characters
+        out.extend_from_slice(header(&format!("{}/", name), data.len()).as_bytes());
+        out.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            out.push(b'\n');
+        }
+    }
+
+    fs::write(path, out)
+}
+
+#[derive(Debug)]
+pub enum BundledLibcError {
+    UnknownMode(String),
+    UnsupportedArchitecture(String),
+    /// A program includes a header the bundled libc doesn't have
+    MissingHeader(String),
+    Compile(String, String),
+    Io(PathBuf, io::Error),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), BundledLibcError> {
+    let libc = BundledLibc::prepare(&CompilationCache::default_root(), Architecture::X86_64, &mut |source, object, include_dirs| {
+        compile_with_system_includes(source, object, include_dirs)
+    })?;
+
+    // cc -nostdinc -isystem <include> -nostdlib <crt1.o> prog.o -L<dir> -lc
+    println!("{} {} {}", libc.include_dir().display(), libc.crt1().display(), libc.archive().display());
+
+    // For the interpreter: "#include <stdio.h>\nint main..." becomes the
+    // header's text, "#line 2", then "int main..."
+    let source = inline_headers("#include <stdio.h>\nint main(void) { return puts(\"hi\"); }\n")?;
+    Ok(())
+}
+*/
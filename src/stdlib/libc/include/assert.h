@@ -0,0 +1,13 @@
+/* assert.h - bundled libc; deliberately no include guard */
+#undef assert
+
+#ifdef NDEBUG
+#define assert(expr) ((void)0)
+#else
+_Noreturn void __assert_fail(const char *expr, const char *file, int line, const char *func);
+#define assert(expr) ((expr) ? (void)0 : __assert_fail(#expr, __FILE__, __LINE__, __func__))
+#endif
+
+#if __STDC_VERSION__ >= 201112L && __STDC_VERSION__ < 202311L
+#define static_assert _Static_assert
+#endif
@@ -0,0 +1,79 @@
+/* fenv.h - bundled libc */
+#ifndef _FENV_H
+#define _FENV_H
+
+#if defined(__x86_64__)
+
+#define FE_INVALID    0x01
+#define FE_DIVBYZERO  0x04
+#define FE_OVERFLOW   0x08
+#define FE_UNDERFLOW  0x10
+#define FE_INEXACT    0x20
+#define FE_ALL_EXCEPT 0x3d
+
+#define FE_TONEAREST  0x000
+#define FE_DOWNWARD   0x400
+#define FE_UPWARD     0x800
+#define FE_TOWARDZERO 0xc00
+
+typedef unsigned short fexcept_t;
+
+/* x87 environment as stored by fnstenv, followed by MXCSR */
+typedef struct {
+    unsigned short __control_word;
+    unsigned short __reserved1;
+    unsigned short __status_word;
+    unsigned short __reserved2;
+    unsigned short __tags;
+    unsigned short __reserved3;
+    unsigned int __eip;
+    unsigned short __cs_selector;
+    unsigned short __opcode;
+    unsigned int __data_offset;
+    unsigned short __data_selector;
+    unsigned short __reserved4;
+    unsigned int __mxcsr;
+} fenv_t;
+
+#elif defined(__aarch64__)
+
+#define FE_INVALID    0x01
+#define FE_DIVBYZERO  0x02
+#define FE_OVERFLOW   0x04
+#define FE_UNDERFLOW  0x08
+#define FE_INEXACT    0x10
+#define FE_ALL_EXCEPT 0x1f
+
+#define FE_TONEAREST  0x000000
+#define FE_UPWARD     0x400000
+#define FE_DOWNWARD   0x800000
+#define FE_TOWARDZERO 0xc00000
+
+typedef unsigned int fexcept_t;
+
+typedef struct {
+    unsigned int __fpcr;
+    unsigned int __fpsr;
+} fenv_t;
+
+#else
+#error "the bundled libc supports x86_64 and aarch64 only"
+#endif
+
+#define FE_DFL_ENV ((const fenv_t *)-1)
+
+int feclearexcept(int excepts);
+int fegetexceptflag(fexcept_t *flagp, int excepts);
+int feraiseexcept(int excepts);
+int fesetexceptflag(const fexcept_t *flagp, int excepts);
+int fetestexcept(int excepts);
+
+int fegetround(void);
+int fesetround(int round);
+
+int fegetenv(fenv_t *envp);
+int feholdexcept(fenv_t *envp);
+int fesetenv(const fenv_t *envp);
+int feupdateenv(const fenv_t *envp);
+
+#endif
@@ -0,0 +1,48 @@
+/* stdio.h - bundled libc */
+#ifndef _STDIO_H
+#define _STDIO_H
+
+#include <stddef.h>
+#include <stdarg.h>
+
+typedef struct __FILE FILE;
+
+extern FILE *const stdin;
+extern FILE *const stdout;
+extern FILE *const stderr;
+
+#define EOF (-1)
+#define BUFSIZ 4096
+#define SEEK_SET 0
+#define SEEK_CUR 1
+#define SEEK_END 2
+
+FILE *fopen(const char *restrict path, const char *restrict mode);
+int fclose(FILE *f);
+int fflush(FILE *f);
+size_t fread(void *restrict ptr, size_t size, size_t count, FILE *restrict f);
+size_t fwrite(const void *restrict ptr, size_t size, size_t count, FILE *restrict f);
+int feof(FILE *f);
+int ferror(FILE *f);
+
+int fgetc(FILE *f);
+int getc(FILE *f);
+int getchar(void);
+char *fgets(char *restrict s, int size, FILE *restrict f);
+int fputc(int c, FILE *f);
+int putc(int c, FILE *f);
+int putchar(int c);
+int fputs(const char *restrict s, FILE *restrict f);
+int puts(const char *s);
+void perror(const char *prefix);
+
+int printf(const char *restrict fmt, ...);
+int fprintf(FILE *restrict f, const char *restrict fmt, ...);
+int sprintf(char *restrict buf, const char *restrict fmt, ...);
+int snprintf(char *restrict buf, size_t size, const char *restrict fmt, ...);
+int vprintf(const char *restrict fmt, va_list ap);
+int vfprintf(FILE *restrict f, const char *restrict fmt, va_list ap);
+int vsprintf(char *restrict buf, const char *restrict fmt, va_list ap);
+int vsnprintf(char *restrict buf, size_t size, const char *restrict fmt, va_list ap);
+
+#endif
@@ -0,0 +1,22 @@
+// crt1-aarch64.s - bundled libc entry point
+
+    .text
+
+// _start: argc at [sp], argv after it, envp after argv's NULL
+    .globl _start
+    .type _start, %function
+_start:
+    mov     x29, #0
+    mov     x30, #0
+    ldr     x0, [sp]
+    add     x1, sp, #8
+    add     x2, x1, x0, lsl #3
+    add     x2, x2, #8
+    mov     x3, sp
+    and     x3, x3, #-16
+    mov     sp, x3
+    bl      __libc_start_main
+    brk     #0
+    .size _start, .-_start
+
+    .section .note.GNU-stack,"",%progbits
@@ -0,0 +1,18 @@
+# crt1-x86_64.s - bundled libc entry point
+
+    .text
+
+# _start: argc at (%rsp), argv after it, envp after argv's NULL
+    .globl _start
+    .type _start, @function
+_start:
+    xor     %ebp, %ebp
+    mov     (%rsp), %rdi
+    lea     8(%rsp), %rsi
+    lea     16(%rsp,%rdi,8), %rdx
+    and     $-16, %rsp
+    call    __libc_start_main
+    hlt
+    .size _start, .-_start
+
+    .section .note.GNU-stack,"",@progbits
@@ -0,0 +1,191 @@
+/* fenv.c - <fenv.h> */
+#include <fenv.h>
+
+#if defined(__x86_64__)
+
+/* Both units are kept in step: SSE does float/double arithmetic, the x87
+   does long double. Exception flags are the union of the two. */
+unsigned __fe_get_mxcsr(void);
+void __fe_set_mxcsr(unsigned value);
+void __fe_get_x87(fenv_t *env);
+void __fe_set_x87(const fenv_t *env);
+
+#define MXCSR_ROUND_SHIFT 3
+#define MXCSR_MASKS 0x1f80
+#define X87_MASKS 0x3f
+
+int feclearexcept(int excepts)
+{
+    fenv_t env;
+    excepts &= FE_ALL_EXCEPT;
+    __fe_get_x87(&env);
+    env.__status_word &= ~excepts;
+    __fe_set_x87(&env);
+    __fe_set_mxcsr(__fe_get_mxcsr() & ~excepts);
+    return 0;
+}
+
+int fetestexcept(int excepts)
+{
+    fenv_t env;
+    __fe_get_x87(&env);
+    return (env.__status_word | __fe_get_mxcsr()) & excepts & FE_ALL_EXCEPT;
+}
+
+/* Sets the sticky flags directly; unmasked exceptions do not trap */
+int feraiseexcept(int excepts)
+{
+    __fe_set_mxcsr(__fe_get_mxcsr() | (excepts & FE_ALL_EXCEPT));
+    return 0;
+}
+
+int fesetexceptflag(const fexcept_t *flagp, int excepts)
+{
+    excepts &= FE_ALL_EXCEPT;
+    feclearexcept(excepts);
+    __fe_set_mxcsr(__fe_get_mxcsr() | (*flagp & excepts));
+    return 0;
+}
+
+int fegetround(void)
+{
+    return (__fe_get_mxcsr() >> MXCSR_ROUND_SHIFT) & FE_TOWARDZERO;
+}
+
+int fesetround(int round)
+{
+    fenv_t env;
+    if (round & ~FE_TOWARDZERO)
+        return 1;
+    __fe_get_x87(&env);
+    env.__control_word = (env.__control_word & ~FE_TOWARDZERO) | round;
+    __fe_set_x87(&env);
+    __fe_set_mxcsr((__fe_get_mxcsr() & ~(FE_TOWARDZERO << MXCSR_ROUND_SHIFT)) | (round << MXCSR_ROUND_SHIFT));
+    return 0;
+}
+
+int fegetenv(fenv_t *envp)
+{
+    __fe_get_x87(envp);
+    envp->__mxcsr = __fe_get_mxcsr();
+    return 0;
+}
+
+int fesetenv(const fenv_t *envp)
+{
+    fenv_t env;
+    if (envp == FE_DFL_ENV) {
+        __fe_get_x87(&env);
+        env.__control_word = 0x37f;
+        env.__status_word = 0;
+        env.__tags = 0xffff;
+        __fe_set_x87(&env);
+        __fe_set_mxcsr(MXCSR_MASKS);
+        return 0;
+    }
+    __fe_set_x87(envp);
+    __fe_set_mxcsr(envp->__mxcsr);
+    return 0;
+}
+
+int feholdexcept(fenv_t *envp)
+{
+    fenv_t env;
+    fegetenv(envp);
+    env = *envp;
+    env.__control_word |= X87_MASKS;
+    env.__status_word &= ~FE_ALL_EXCEPT;
+    __fe_set_x87(&env);
+    __fe_set_mxcsr((envp->__mxcsr | MXCSR_MASKS) & ~FE_ALL_EXCEPT);
+    return 0;
+}
+
+#elif defined(__aarch64__)
+
+unsigned __fe_get_fpcr(void);
+void __fe_set_fpcr(unsigned value);
+unsigned __fe_get_fpsr(void);
+void __fe_set_fpsr(unsigned value);
+
+#define FPCR_TRAP_SHIFT 8
+
+int feclearexcept(int excepts)
+{
+    __fe_set_fpsr(__fe_get_fpsr() & ~(excepts & FE_ALL_EXCEPT));
+    return 0;
+}
+
+int fetestexcept(int excepts)
+{
+    return __fe_get_fpsr() & excepts & FE_ALL_EXCEPT;
+}
+
+/* Sets the sticky flags directly; enabled traps do not fire */
+int feraiseexcept(int excepts)
+{
+    __fe_set_fpsr(__fe_get_fpsr() | (excepts & FE_ALL_EXCEPT));
+    return 0;
+}
+
+int fesetexceptflag(const fexcept_t *flagp, int excepts)
+{
+    excepts &= FE_ALL_EXCEPT;
+    __fe_set_fpsr((__fe_get_fpsr() & ~excepts) | (*flagp & excepts));
+    return 0;
+}
+
+int fegetround(void)
+{
+    return __fe_get_fpcr() & FE_TOWARDZERO;
+}
+
+int fesetround(int round)
+{
+    if (round & ~FE_TOWARDZERO)
+        return 1;
+    __fe_set_fpcr((__fe_get_fpcr() & ~FE_TOWARDZERO) | round);
+    return 0;
+}
+
+int fegetenv(fenv_t *envp)
+{
+    envp->__fpcr = __fe_get_fpcr();
+    envp->__fpsr = __fe_get_fpsr();
+    return 0;
+}
+
+int fesetenv(const fenv_t *envp)
+{
+    if (envp == FE_DFL_ENV) {
+        __fe_set_fpcr(0);
+        __fe_set_fpsr(0);
+        return 0;
+    }
+    __fe_set_fpcr(envp->__fpcr);
+    __fe_set_fpsr(envp->__fpsr);
+    return 0;
+}
+
+int feholdexcept(fenv_t *envp)
+{
+    fegetenv(envp);
+    __fe_set_fpcr(envp->__fpcr & ~(FE_ALL_EXCEPT << FPCR_TRAP_SHIFT));
+    __fe_set_fpsr(envp->__fpsr & ~FE_ALL_EXCEPT);
+    return 0;
+}
+
+#endif
+
+int fegetexceptflag(fexcept_t *flagp, int excepts)
+{
+    *flagp = fetestexcept(excepts);
+    return 0;
+}
+
+int feupdateenv(const fenv_t *envp)
+{
+    int raised = fetestexcept(FE_ALL_EXCEPT);
+    fesetenv(envp);
+    feraiseexcept(raised);
+    return 0;
+}
@@ -0,0 +1,39 @@
+// fpu-aarch64.s - bundled libc floating-point environment access
+
+    .text
+
+// unsigned __fe_get_fpcr(void)
+    .globl __fe_get_fpcr
+    .type __fe_get_fpcr, %function
+__fe_get_fpcr:
+    mrs     x0, fpcr
+    ret
+    .size __fe_get_fpcr, .-__fe_get_fpcr
+
+// void __fe_set_fpcr(unsigned value)
+    .globl __fe_set_fpcr
+    .type __fe_set_fpcr, %function
+__fe_set_fpcr:
+    mov     w0, w0
+    msr     fpcr, x0
+    ret
+    .size __fe_set_fpcr, .-__fe_set_fpcr
+
+// unsigned __fe_get_fpsr(void)
+    .globl __fe_get_fpsr
+    .type __fe_get_fpsr, %function
+__fe_get_fpsr:
+    mrs     x0, fpsr
+    ret
+    .size __fe_get_fpsr, .-__fe_get_fpsr
+
+// void __fe_set_fpsr(unsigned value)
+    .globl __fe_set_fpsr
+    .type __fe_set_fpsr, %function
+__fe_set_fpsr:
+    mov     w0, w0
+    msr     fpsr, x0
+    ret
+    .size __fe_set_fpsr, .-__fe_set_fpsr
+
+    .section .note.GNU-stack,"",%progbits
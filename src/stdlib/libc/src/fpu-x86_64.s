@@ -0,0 +1,41 @@
+# fpu-x86_64.s - bundled libc floating-point environment access
+
+    .text
+
+# unsigned __fe_get_mxcsr(void)
+    .globl __fe_get_mxcsr
+    .type __fe_get_mxcsr, @function
+__fe_get_mxcsr:
+    stmxcsr -4(%rsp)
+    mov     -4(%rsp), %eax
+    ret
+    .size __fe_get_mxcsr, .-__fe_get_mxcsr
+
+# void __fe_set_mxcsr(unsigned value)
+    .globl __fe_set_mxcsr
+    .type __fe_set_mxcsr, @function
+__fe_set_mxcsr:
+    mov     %edi, -4(%rsp)
+    ldmxcsr -4(%rsp)
+    ret
+    .size __fe_set_mxcsr, .-__fe_set_mxcsr
+
+# void __fe_get_x87(void *env): 28-byte fnstenv image. fnstenv masks all
+# x87 exceptions as a side effect, so load the image straight back.
+    .globl __fe_get_x87
+    .type __fe_get_x87, @function
+__fe_get_x87:
+    fnstenv (%rdi)
+    fldenv  (%rdi)
+    ret
+    .size __fe_get_x87, .-__fe_get_x87
+
+# void __fe_set_x87(const void *env)
+    .globl __fe_set_x87
+    .type __fe_set_x87, @function
+__fe_set_x87:
+    fldenv  (%rdi)
+    ret
+    .size __fe_set_x87, .-__fe_set_x87
+
+    .section .note.GNU-stack,"",@progbits
@@ -0,0 +1,61 @@
+/* internal.h - bundled libc private declarations */
+#ifndef _LIBC_INTERNAL_H
+#define _LIBC_INTERNAL_H
+
+#include <stddef.h>
+
+/* Raw system call, defined in syscall-<arch>.s; returns -errno on failure */
+long __syscall6(long n, long a, long b, long c, long d, long e, long f);
+
+#if defined(__x86_64__)
+#define SYS_read 0
+#define SYS_write 1
+#define SYS_close 3
+#define SYS_lseek 8
+#define SYS_mmap 9
+#define SYS_munmap 11
+#define SYS_getpid 39
+#define SYS_kill 62
+#define SYS_openat 257
+#define SYS_exit_group 231
+#elif defined(__aarch64__)
+#define SYS_openat 56
+#define SYS_close 57
+#define SYS_lseek 62
+#define SYS_read 63
+#define SYS_write 64
+#define SYS_exit_group 94
+#define SYS_kill 129
+#define SYS_getpid 172
+#define SYS_munmap 215
+#define SYS_mmap 222
+#else
+#error "the bundled libc supports x86_64 and aarch64 only"
+#endif
+
+#define AT_FDCWD (-100)
+#define O_RDONLY 0
+#define O_WRONLY 1
+#define O_RDWR 2
+#define O_CREAT 0100
+#define O_TRUNC 01000
+#define O_APPEND 02000
+
+#define PROT_READ 1
+#define PROT_WRITE 2
+#define MAP_PRIVATE 2
+#define MAP_ANONYMOUS 0x20
+#define MAP_FAILED ((void *)-1)
+
+#define SIGABRT 6
+
+/* Set errno from a raw syscall result and return -1, or pass it through */
+long __syscall_ret(long result);
+
+void *__mmap(size_t size);
+void __munmap(void *ptr, size_t size);
+
+/* Flushes stdout and stderr; called by exit() */
+void __stdio_exit(void);
+
+#endif
@@ -0,0 +1,139 @@
+/* malloc.c - size-class free lists over mmap
+ *
+ * Small blocks come from 64 KiB arenas and are recycled through one free
+ * list per power-of-two class; anything larger than the biggest class gets
+ * its own mapping and is unmapped on free.
+ */
+#include <stdlib.h>
+#include <string.h>
+#include <errno.h>
+#include "internal.h"
+
+#define ARENA_SIZE (64 * 1024)
+#define MIN_CLASS 4  /* 16 bytes */
+#define MAX_CLASS 12 /* 4096 bytes */
+#define ALIGN 16
+
+/* Sits in front of every block; keeps the payload 16-byte aligned */
+struct header {
+    size_t size;  /* payload size: the class size, or the mapping size for large blocks */
+    size_t large;
+};
+
+struct free_block {
+    struct free_block *next;
+};
+
+static struct free_block *free_lists[MAX_CLASS + 1];
+static char *arena_next;
+static char *arena_end;
+
+static int size_class(size_t size)
+{
+    int cls = MIN_CLASS;
+    while (((size_t)1 << cls) < size)
+        cls++;
+    return cls;
+}
+
+static void *carve(size_t total)
+{
+    if (arena_next == NULL || (size_t)(arena_end - arena_next) < total) {
+        char *arena = __mmap(ARENA_SIZE);
+        if (arena == MAP_FAILED)
+            return NULL;
+        arena_next = arena;
+        arena_end = arena + ARENA_SIZE;
+    }
+    void *block = arena_next;
+    arena_next += total;
+    return block;
+}
+
+void *malloc(size_t size)
+{
+    if (size == 0)
+        size = 1;
+
+    if (size > ((size_t)1 << MAX_CLASS)) {
+        size_t total = (size + sizeof(struct header) + 4095) & ~(size_t)4095;
+        if (total < size) {
+            errno = ENOMEM;
+            return NULL;
+        }
+        struct header *h = __mmap(total);
+        if (h == MAP_FAILED)
+            return NULL;
+        h->size = total - sizeof(struct header);
+        h->large = 1;
+        return h + 1;
+    }
+
+    int cls = size_class(size);
+    struct free_block *block = free_lists[cls];
+    if (block) {
+        free_lists[cls] = block->next;
+        return block;
+    }
+
+    struct header *h = carve(sizeof(struct header) + ((size_t)1 << cls));
+    if (!h) {
+        errno = ENOMEM;
+        return NULL;
+    }
+    h->size = (size_t)1 << cls;
+    h->large = 0;
+    return h + 1;
+}
+
+void free(void *ptr)
+{
+    if (!ptr)
+        return;
+    struct header *h = (struct header *)ptr - 1;
+    if (h->large) {
+        __munmap(h, h->size + sizeof(struct header));
+        return;
+    }
+    int cls = size_class(h->size);
+    struct free_block *block = ptr;
+    block->next = free_lists[cls];
+    free_lists[cls] = block;
+}
+
+void *calloc(size_t count, size_t size)
+{
+    if (size && count > (size_t)-1 / size) {
+        errno = ENOMEM;
+        return NULL;
+    }
+    void *ptr = malloc(count * size);
+    if (ptr)
+        memset(ptr, 0, count * size);
+    return ptr;
+}
+
+void *realloc(void *ptr, size_t size)
+{
+    if (!ptr)
+        return malloc(size);
+    struct header *h = (struct header *)ptr - 1;
+    if (size <= h->size)
+        return ptr;
+    void *bigger = malloc(size);
+    if (bigger) {
+        memcpy(bigger, ptr, h->size);
+        free(ptr);
+    }
+    return bigger;
+}
+
+void *aligned_alloc(size_t alignment, size_t size)
+{
+    /* Every block is 16-byte aligned; larger alignments aren't supported */
+    if (alignment > ALIGN) {
+        errno = EINVAL;
+        return NULL;
+    }
+    return malloc(size);
+}
@@ -0,0 +1,86 @@
+/* start.c - process startup and shutdown */
+#include <stdlib.h>
+#include <unistd.h>
+#include <errno.h>
+#include "internal.h"
+
+int errno;
+char **environ;
+
+typedef void (*init_fn)(int, char **, char **);
+extern init_fn __init_array_start[];
+extern init_fn __init_array_end[];
+
+int main(int argc, char **argv, char **envp);
+
+#define ATEXIT_MAX 32
+static void (*atexit_fns[ATEXIT_MAX])(void);
+static int atexit_count;
+
+/* Called from _start with the stack aligned */
+_Noreturn void __libc_start_main(int argc, char **argv, char **envp)
+{
+    environ = envp;
+    for (init_fn *fn = __init_array_start; fn < __init_array_end; fn++)
+        (*fn)(argc, argv, envp);
+    exit(main(argc, argv, envp));
+}
+
+long __syscall_ret(long result)
+{
+    if (result < 0 && result > -4096) {
+        errno = (int)-result;
+        return -1;
+    }
+    return result;
+}
+
+int atexit(void (*fn)(void))
+{
+    if (atexit_count == ATEXIT_MAX)
+        return -1;
+    atexit_fns[atexit_count++] = fn;
+    return 0;
+}
+
+_Noreturn void exit(int status)
+{
+    while (atexit_count > 0)
+        atexit_fns[--atexit_count]();
+    __stdio_exit();
+    _Exit(status);
+}
+
+_Noreturn void _Exit(int status)
+{
+    for (;;)
+        __syscall6(SYS_exit_group, status, 0, 0, 0, 0, 0);
+}
+
+_Noreturn void _exit(int status)
+{
+    _Exit(status);
+}
+
+_Noreturn void abort(void)
+{
+    __syscall6(SYS_kill, __syscall6(SYS_getpid, 0, 0, 0, 0, 0, 0), SIGABRT, 0, 0, 0, 0);
+    _Exit(127);
+}
+
+char *getenv(const char *name)
+{
+    if (!environ)
+        return NULL;
+    for (char **env = environ; *env; env++) {
+        const char *n = name;
+        const char *e = *env;
+        while (*n && *n == *e) {
+            n++;
+            e++;
+        }
+        if (!*n && *e == '=')
+            return (char *)e + 1;
+    }
+    return NULL;
+}
@@ -0,0 +1,498 @@
+/* stdio.c - <stdio.h> over file descriptors */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <errno.h>
+#include "internal.h"
+
+#define F_READ 1
+#define F_WRITE 2
+#define F_EOF 4
+#define F_ERR 8
+#define F_LINEBUF 16
+#define F_UNBUF 32
+#define F_STATIC 64
+
+struct __FILE {
+    int fd;
+    int flags;
+    /* Write buffer, or read buffer with pos..len unread */
+    size_t pos;
+    size_t len;
+    unsigned char buf[BUFSIZ];
+};
+
+static FILE stdin_file = { 0, F_READ | F_STATIC, 0, 0, {0} };
+static FILE stdout_file = { 1, F_WRITE | F_LINEBUF | F_STATIC, 0, 0, {0} };
+static FILE stderr_file = { 2, F_WRITE | F_UNBUF | F_STATIC, 0, 0, {0} };
+
+FILE *const stdin = &stdin_file;
+FILE *const stdout = &stdout_file;
+FILE *const stderr = &stderr_file;
+
+static int write_all(int fd, const unsigned char *data, size_t n)
+{
+    while (n > 0) {
+        ssize_t written = write(fd, data, n);
+        if (written < 0) {
+            if (errno == EINTR)
+                continue;
+            return -1;
+        }
+        data += written;
+        n -= (size_t)written;
+    }
+    return 0;
+}
+
+int fflush(FILE *f)
+{
+    if (!f) {
+        int a = fflush(stdout);
+        int b = fflush(stderr);
+        return a | b;
+    }
+    if (!(f->flags & F_WRITE) || f->pos == 0)
+        return 0;
+    int result = write_all(f->fd, f->buf, f->pos);
+    f->pos = 0;
+    if (result) {
+        f->flags |= F_ERR;
+        return EOF;
+    }
+    return 0;
+}
+
+void __stdio_exit(void)
+{
+    fflush(NULL);
+}
+
+size_t fwrite(const void *restrict ptr, size_t size, size_t count, FILE *restrict f)
+{
+    const unsigned char *data = ptr;
+    size_t total = size * count;
+    if (!(f->flags & F_WRITE) || total == 0)
+        return 0;
+
+    if (f->flags & F_UNBUF || total >= BUFSIZ) {
+        if (fflush(f) || write_all(f->fd, data, total)) {
+            f->flags |= F_ERR;
+            return 0;
+        }
+        return count;
+    }
+
+    for (size_t i = 0; i < total; i++) {
+        f->buf[f->pos++] = data[i];
+        if (f->pos == BUFSIZ || (data[i] == '\n' && f->flags & F_LINEBUF)) {
+            if (fflush(f))
+                return i / size;
+        }
+    }
+    return count;
+}
+
+static int refill(FILE *f)
+{
+    if (f == stdin)
+        fflush(stdout);
+    ssize_t n;
+    do {
+        n = read(f->fd, f->buf, BUFSIZ);
+    } while (n < 0 && errno == EINTR);
+    if (n <= 0) {
+        f->flags |= n == 0 ? F_EOF : F_ERR;
+        return EOF;
+    }
+    f->pos = 0;
+    f->len = (size_t)n;
+    return 0;
+}
+
+int fgetc(FILE *f)
+{
+    if (!(f->flags & F_READ))
+        return EOF;
+    if (f->pos == f->len && refill(f))
+        return EOF;
+    return f->buf[f->pos++];
+}
+
+size_t fread(void *restrict ptr, size_t size, size_t count, FILE *restrict f)
+{
+    unsigned char *out = ptr;
+    size_t total = size * count;
+    size_t i = 0;
+    for (; i < total; i++) {
+        int c = fgetc(f);
+        if (c == EOF)
+            break;
+        out[i] = (unsigned char)c;
+    }
+    return size ? i / size : 0;
+}
+
+char *fgets(char *restrict s, int size, FILE *restrict f)
+{
+    int i = 0;
+    while (i + 1 < size) {
+        int c = fgetc(f);
+        if (c == EOF)
+            break;
+        s[i++] = (char)c;
+        if (c == '\n')
+            break;
+    }
+    if (i == 0)
+        return NULL;
+    s[i] = 0;
+    return s;
+}
+
+int getc(FILE *f) { return fgetc(f); }
+int getchar(void) { return fgetc(stdin); }
+int feof(FILE *f) { return (f->flags & F_EOF) != 0; }
+int ferror(FILE *f) { return (f->flags & F_ERR) != 0; }
+
+int fputc(int c, FILE *f)
+{
+    unsigned char byte = (unsigned char)c;
+    return fwrite(&byte, 1, 1, f) == 1 ? byte : EOF;
+}
+
+int putc(int c, FILE *f) { return fputc(c, f); }
+int putchar(int c) { return fputc(c, stdout); }
+
+int fputs(const char *restrict s, FILE *restrict f)
+{
+    size_t n = strlen(s);
+    return fwrite(s, 1, n, f) == n ? 0 : EOF;
+}
+
+int puts(const char *s)
+{
+    if (fputs(s, stdout) == EOF || fputc('\n', stdout) == EOF)
+        return EOF;
+    return 0;
+}
+
+void perror(const char *prefix)
+{
+    int saved = errno;
+    if (prefix && *prefix) {
+        fputs(prefix, stderr);
+        fputs(": ", stderr);
+    }
+    fputs(strerror(saved), stderr);
+    fputc('\n', stderr);
+}
+
+FILE *fopen(const char *restrict path, const char *restrict mode)
+{
+    int flags;
+    int access;
+    switch (mode[0]) {
+    case 'r': flags = O_RDONLY; access = F_READ; break;
+    case 'w': flags = O_WRONLY | O_CREAT | O_TRUNC; access = F_WRITE; break;
+    case 'a': flags = O_WRONLY | O_CREAT | O_APPEND; access = F_WRITE; break;
+    default:
+        errno = EINVAL;
+        return NULL;
+    }
+    if (strchr(mode, '+')) {
+        flags = (flags & ~(O_WRONLY | O_RDONLY)) | O_RDWR;
+        access = F_READ | F_WRITE;
+    }
+
+    long fd = __syscall_ret(__syscall6(SYS_openat, AT_FDCWD, (long)path, flags, 0666, 0, 0));
+    if (fd < 0)
+        return NULL;
+
+    FILE *f = calloc(1, sizeof(FILE));
+    if (!f) {
+        close((int)fd);
+        return NULL;
+    }
+    f->fd = (int)fd;
+    f->flags = access;
+    return f;
+}
+
+int fclose(FILE *f)
+{
+    int result = fflush(f);
+    if (close(f->fd) < 0)
+        result = EOF;
+    if (!(f->flags & F_STATIC))
+        free(f);
+    return result;
+}
+
+/* printf family: one formatter writing through a sink */
+
+struct sink {
+    FILE *file;   /* either a stream... */
+    char *buf;    /* ...or a buffer of `size` bytes */
+    size_t size;
+    size_t count; /* characters produced, even past `size` */
+};
+
+static void emit(struct sink *out, const char *s, size_t n)
+{
+    if (out->file) {
+        fwrite(s, 1, n, out->file);
+    } else {
+        for (size_t i = 0; i < n; i++) {
+            if (out->count + i + 1 < out->size)
+                out->buf[out->count + i] = s[i];
+        }
+    }
+    out->count += n;
+}
+
+static void pad(struct sink *out, char c, int n)
+{
+    while (n-- > 0)
+        emit(out, &c, 1);
+}
+
+static int format(struct sink *out, const char *fmt, va_list ap)
+{
+    char digits[64];
+
+    for (; *fmt; fmt++) {
+        if (*fmt != '%') {
+            const char *run = fmt;
+            while (fmt[1] && fmt[1] != '%')
+                fmt++;
+            emit(out, run, (size_t)(fmt - run) + 1);
+            continue;
+        }
+        fmt++;
+
+        int left = 0, zero = 0, plus = 0, space = 0, alt = 0;
+        for (;; fmt++) {
+            if (*fmt == '-') left = 1;
+            else if (*fmt == '0') zero = 1;
+            else if (*fmt == '+') plus = 1;
+            else if (*fmt == ' ') space = 1;
+            else if (*fmt == '#') alt = 1;
+            else break;
+        }
+
+        int width = 0;
+        if (*fmt == '*') {
+            width = va_arg(ap, int);
+            if (width < 0) {
+                left = 1;
+                width = -width;
+            }
+            fmt++;
+        } else {
+            while (*fmt >= '0' && *fmt <= '9')
+                width = width * 10 + (*fmt++ - '0');
+        }
+
+        int precision = -1;
+        if (*fmt == '.') {
+            fmt++;
+            precision = 0;
+            if (*fmt == '*') {
+                precision = va_arg(ap, int);
+                fmt++;
+            } else {
+                while (*fmt >= '0' && *fmt <= '9')
+                    precision = precision * 10 + (*fmt++ - '0');
+            }
+        }
+
+        /* 0 = int, 1 = long, 2 = long long, -1 = short, -2 = char */
+        int length = 0;
+        for (;; fmt++) {
+            if (*fmt == 'l') length++;
+            else if (*fmt == 'h') length--;
+            else if (*fmt == 'z' || *fmt == 't' || *fmt == 'j') length = 1;
+            else break;
+        }
+
+        const char *prefix = "";
+        const char *text = digits;
+        size_t len = 0;
+        unsigned long long value;
+        int base = 10;
+        int negative = 0;
+
+        switch (*fmt) {
+        case '%':
+            emit(out, "%", 1);
+            continue;
+        case 'c':
+            digits[0] = (char)va_arg(ap, int);
+            len = 1;
+            precision = -1;
+            goto emit_text;
+        case 's':
+            text = va_arg(ap, const char *);
+            if (!text)
+                text = "(null)";
+            len = precision >= 0 ? strnlen(text, (size_t)precision) : strlen(text);
+            precision = -1;
+            goto emit_text;
+        case 'n':
+            *va_arg(ap, int *) = (int)out->count;
+            continue;
+        case 'd':
+        case 'i': {
+            long long sv = length >= 2 ? va_arg(ap, long long)
+                         : length == 1 ? va_arg(ap, long)
+                         : va_arg(ap, int);
+            if (length == -1) sv = (short)sv;
+            if (length <= -2) sv = (signed char)sv;
+            negative = sv < 0;
+            value = negative ? 0 - (unsigned long long)sv : (unsigned long long)sv;
+            prefix = negative ? "-" : plus ? "+" : space ? " " : "";
+            break;
+        }
+        case 'p':
+            value = (unsigned long long)(unsigned long)va_arg(ap, void *);
+            base = 16;
+            prefix = "0x";
+            break;
+        case 'x':
+        case 'X':
+        case 'o':
+        case 'u':
+            value = length >= 2 ? va_arg(ap, unsigned long long)
+                  : length == 1 ? va_arg(ap, unsigned long)
+                  : va_arg(ap, unsigned int);
+            if (length == -1) value = (unsigned short)value;
+            if (length <= -2) value = (unsigned char)value;
+            base = *fmt == 'u' ? 10 : *fmt == 'o' ? 8 : 16;
+            if (alt && value && base == 16)
+                prefix = *fmt == 'X' ? "0X" : "0x";
+            if (alt && base == 8)
+                prefix = "0";
+            break;
+        default:
+            /* Unknown conversion (including floating point): print it verbatim */
+            emit(out, fmt - 1, 2);
+            continue;
+        }
+
+        {
+            const char *alphabet = *fmt == 'X' ? "0123456789ABCDEF" : "0123456789abcdef";
+            char *end = digits + sizeof(digits);
+            char *p = end;
+            while (value) {
+                *--p = alphabet[value % (unsigned)base];
+                value /= (unsigned)base;
+            }
+            if (p == end && precision != 0)
+                *--p = '0';
+            if (*prefix == '0' && base == 8 && *p == '0')
+                prefix = "";
+            text = p;
+            len = (size_t)(end - p);
+        }
+
+        {
+            size_t zeros = precision > 0 && (size_t)precision > len ? (size_t)precision - len : 0;
+            size_t body = strlen(prefix) + zeros + len;
+            int padding = width > (int)body ? width - (int)body : 0;
+            if (zero && !left && precision < 0) {
+                zeros += (size_t)padding;
+                padding = 0;
+            }
+            if (!left)
+                pad(out, ' ', padding);
+            emit(out, prefix, strlen(prefix));
+            pad(out, '0', (int)zeros);
+            emit(out, text, len);
+            if (left)
+                pad(out, ' ', padding);
+        }
+        continue;
+
+    emit_text:
+        {
+            int padding = width > (int)len ? width - (int)len : 0;
+            if (!left)
+                pad(out, ' ', padding);
+            emit(out, text, len);
+            if (left)
+                pad(out, ' ', padding);
+        }
+    }
+
+    if (!out->file && out->size)
+        out->buf[out->count < out->size ? out->count : out->size - 1] = 0;
+    return out->count > 2147483647 ? -1 : (int)out->count;
+}
+
+int vfprintf(FILE *restrict f, const char *restrict fmt, va_list ap)
+{
+    struct sink out = { f, NULL, 0, 0 };
+    int n = format(&out, fmt, ap);
+    return ferror(f) ? -1 : n;
+}
+
+int vsnprintf(char *restrict buf, size_t size, const char *restrict fmt, va_list ap)
+{
+    struct sink out = { NULL, buf, size, 0 };
+    return format(&out, fmt, ap);
+}
+
+int vsprintf(char *restrict buf, const char *restrict fmt, va_list ap)
+{
+    return vsnprintf(buf, (size_t)-1 / 2, fmt, ap);
+}
+
+int vprintf(const char *restrict fmt, va_list ap)
+{
+    return vfprintf(stdout, fmt, ap);
+}
+
+int printf(const char *restrict fmt, ...)
+{
+    va_list ap;
+    va_start(ap, fmt);
+    int n = vfprintf(stdout, fmt, ap);
+    va_end(ap);
+    return n;
+}
+
+int fprintf(FILE *restrict f, const char *restrict fmt, ...)
+{
+    va_list ap;
+    va_start(ap, fmt);
+    int n = vfprintf(f, fmt, ap);
+    va_end(ap);
+    return n;
+}
+
+int sprintf(char *restrict buf, const char *restrict fmt, ...)
+{
+    va_list ap;
+    va_start(ap, fmt);
+    int n = vsprintf(buf, fmt, ap);
+    va_end(ap);
+    return n;
+}
+
+int snprintf(char *restrict buf, size_t size, const char *restrict fmt, ...)
+{
+    va_list ap;
+    va_start(ap, fmt);
+    int n = vsnprintf(buf, size, fmt, ap);
+    va_end(ap);
+    return n;
+}
+
+/* Used by <assert.h> */
+_Noreturn void __assert_fail(const char *expr, const char *file, int line, const char *func)
+{
+    fprintf(stderr, "%s:%d: %s: Assertion `%s' failed.\n", file, line, func, expr);
+    abort();
+}
@@ -0,0 +1,140 @@
+/* stdlib.c - conversions, sorting, random numbers */
+#include <stdlib.h>
+#include <string.h>
+#include <ctype.h>
+#include <limits.h>
+#include <errno.h>
+
+static unsigned long long parse_unsigned(const char *s, char **end, int base, int *negative, int *overflow)
+{
+    const char *p = s;
+    unsigned long long value = 0;
+    int any = 0;
+
+    while (isspace((unsigned char)*p))
+        p++;
+    *negative = 0;
+    if (*p == '+' || *p == '-')
+        *negative = *p++ == '-';
+
+    if ((base == 0 || base == 16) && p[0] == '0' && (p[1] == 'x' || p[1] == 'X') && isxdigit((unsigned char)p[2])) {
+        p += 2;
+        base = 16;
+    } else if (base == 0) {
+        base = *p == '0' ? 8 : 10;
+    }
+
+    *overflow = 0;
+    for (;; p++) {
+        int digit;
+        if (isdigit((unsigned char)*p))
+            digit = *p - '0';
+        else if (isalpha((unsigned char)*p))
+            digit = tolower((unsigned char)*p) - 'a' + 10;
+        else
+            break;
+        if (digit >= base)
+            break;
+        if (value > (ULLONG_MAX - digit) / base)
+            *overflow = 1;
+        value = value * base + digit;
+        any = 1;
+    }
+
+    if (end)
+        *end = (char *)(any ? p : s);
+    return value;
+}
+
+long long strtoll(const char *restrict s, char **restrict end, int base)
+{
+    int negative, overflow;
+    unsigned long long value = parse_unsigned(s, end, base, &negative, &overflow);
+    if (overflow || value > (unsigned long long)LLONG_MAX + negative) {
+        errno = ERANGE;
+        return negative ? LLONG_MIN : LLONG_MAX;
+    }
+    return negative ? -(long long)value : (long long)value;
+}
+
+unsigned long long strtoull(const char *restrict s, char **restrict end, int base)
+{
+    int negative, overflow;
+    unsigned long long value = parse_unsigned(s, end, base, &negative, &overflow);
+    if (overflow) {
+        errno = ERANGE;
+        return ULLONG_MAX;
+    }
+    return negative ? -value : value;
+}
+
+long strtol(const char *restrict s, char **restrict end, int base)
+{
+    return (long)strtoll(s, end, base);
+}
+
+unsigned long strtoul(const char *restrict s, char **restrict end, int base)
+{
+    return (unsigned long)strtoull(s, end, base);
+}
+
+int atoi(const char *s) { return (int)strtol(s, NULL, 10); }
+long atol(const char *s) { return strtol(s, NULL, 10); }
+long long atoll(const char *s) { return strtoll(s, NULL, 10); }
+
+int abs(int x) { return x < 0 ? -x : x; }
+long labs(long x) { return x < 0 ? -x : x; }
+long long llabs(long long x) { return x < 0 ? -x : x; }
+
+static unsigned long long rand_state = 1;
+
+int rand(void)
+{
+    rand_state = rand_state * 6364136223846793005ULL + 1;
+    return (int)(rand_state >> 33);
+}
+
+void srand(unsigned seed)
+{
+    rand_state = seed;
+}
+
+static void swap(char *a, char *b, size_t size)
+{
+    while (size--) {
+        char t = *a;
+        *a++ = *b;
+        *b++ = t;
+    }
+}
+
+/* Shell sort: no recursion and no allocation */
+void qsort(void *base, size_t count, size_t size, int (*cmp)(const void *, const void *))
+{
+    char *items = base;
+    for (size_t gap = count / 2; gap > 0; gap /= 2) {
+        for (size_t i = gap; i < count; i++) {
+            for (size_t j = i; j >= gap && cmp(items + (j - gap) * size, items + j * size) > 0; j -= gap)
+                swap(items + (j - gap) * size, items + j * size, size);
+        }
+    }
+}
+
+void *bsearch(const void *key, const void *base, size_t count, size_t size,
+              int (*cmp)(const void *, const void *))
+{
+    const char *items = base;
+    while (count > 0) {
+        const char *mid = items + (count / 2) * size;
+        int c = cmp(key, mid);
+        if (c == 0)
+            return (void *)mid;
+        if (c > 0) {
+            items = mid + size;
+            count -= count / 2 + 1;
+        } else {
+            count /= 2;
+        }
+    }
+    return NULL;
+}
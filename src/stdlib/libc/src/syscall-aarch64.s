@@ -0,0 +1,20 @@
+// syscall-aarch64.s - bundled libc system call stub
+
+    .text
+
+// long __syscall6(long n, long a, long b, long c, long d, long e, long f)
+    .globl __syscall6
+    .type __syscall6, %function
+__syscall6:
+    mov     x8, x0
+    mov     x0, x1
+    mov     x1, x2
+    mov     x2, x3
+    mov     x3, x4
+    mov     x4, x5
+    mov     x5, x6
+    svc     #0
+    ret
+    .size __syscall6, .-__syscall6
+
+    .section .note.GNU-stack,"",%progbits
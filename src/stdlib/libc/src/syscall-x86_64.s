@@ -0,0 +1,20 @@
+# syscall-x86_64.s - bundled libc system call stub
+
+    .text
+
+# long __syscall6(long n, long a, long b, long c, long d, long e, long f)
+    .globl __syscall6
+    .type __syscall6, @function
+__syscall6:
+    mov     %rdi, %rax
+    mov     %rsi, %rdi
+    mov     %rdx, %rsi
+    mov     %rcx, %rdx
+    mov     %r8, %r10
+    mov     %r9, %r8
+    mov     8(%rsp), %r9
+    syscall
+    ret
+    .size __syscall6, .-__syscall6
+
+    .section .note.GNU-stack,"",@progbits
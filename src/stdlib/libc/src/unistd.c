@@ -0,0 +1,44 @@
+/* unistd.c - thin system call wrappers */
+#include <unistd.h>
+#include "internal.h"
+
+ssize_t read(int fd, void *buf, size_t n)
+{
+    return __syscall_ret(__syscall6(SYS_read, fd, (long)buf, (long)n, 0, 0, 0));
+}
+
+ssize_t write(int fd, const void *buf, size_t n)
+{
+    return __syscall_ret(__syscall6(SYS_write, fd, (long)buf, (long)n, 0, 0, 0));
+}
+
+int close(int fd)
+{
+    return (int)__syscall_ret(__syscall6(SYS_close, fd, 0, 0, 0, 0, 0));
+}
+
+off_t lseek(int fd, off_t offset, int whence)
+{
+    return __syscall_ret(__syscall6(SYS_lseek, fd, offset, whence, 0, 0, 0));
+}
+
+pid_t getpid(void)
+{
+    return (pid_t)__syscall6(SYS_getpid, 0, 0, 0, 0, 0, 0);
+}
+
+void *__mmap(size_t size)
+{
+    long result = __syscall6(SYS_mmap, 0, (long)size, PROT_READ | PROT_WRITE,
+                             MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (result < 0 && result > -4096) {
+        __syscall_ret(result);
+        return MAP_FAILED;
+    }
+    return (void *)result;
+}
+
+void __munmap(void *ptr, size_t size)
+{
+    __syscall6(SYS_munmap, (long)ptr, (long)size, 0, 0, 0, 0);
+}
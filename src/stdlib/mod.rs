@@ -0,0 +1,6 @@
+// src/stdlib/mod.rs
+//! C standard library support: the runtime implementation used by the
+//! interpreter and the bundled libc compiled for `--libc=bundled`
+
+pub mod bundled;
+pub mod implementation;
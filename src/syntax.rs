@@ -0,0 +1,86 @@
+// src/syntax.rs
+//! C syntax trees for source-to-source tools
+//! `parse` turns C source into a `TranslationUnit`, `Visit` and `VisitMut`
+//! walk it, and `Rewriter` edits the source along it, so instrumentation
+//! passes and API migration scripts can be written against this crate
+//! without reaching into the parser. Like `Engine`, this module's API
+//! follows semver: the node kind enums are `#[non_exhaustive]`, and new
+//! node kinds come with default visitor methods.
+//!
+//! Every node carries the `Span` of its text in the source given to
+//! `parse`. Rewrites go through those spans, so whatever a tool doesn't
+//! touch, comments and macro uses included, comes out as written.
+
+use std::fmt;
+use crate::frontend::c23::C23Parser;
+
+pub use crate::frontend::ast::*;
+pub use crate::frontend::rewrite::{Printer, RewriteError, Rewriter};
+pub use crate::frontend::visit::*;
+
+/// Parse one translation unit. `file` names it in `TranslationUnit::file`
+/// and in errors.
+pub fn parse(source: &str, file: &str) -> Result<TranslationUnit, SyntaxError> {
+    let mut parser = C23Parser::new();
+    let mut unit = parser.parse(source).map_err(|e| SyntaxError {
+        file: file.to_string(),
+        message: format!("{:?}", e),
+    })?;
+    unit.file = file.to_string();
+    Ok(unit)
+}
+
+/// Parse `source`, let `transform` edit it, and return the edited source
+pub fn rewrite<F>(source: &str, file: &str, transform: F) -> Result<String, SyntaxError>
+where
+    F: FnOnce(&TranslationUnit, &mut Rewriter<'_>),
+{
+    let unit = parse(source, file)?;
+    let mut rewriter = Rewriter::new(source);
+    transform(&unit, &mut rewriter);
+    rewriter.finish().map_err(|e| SyntaxError {
+        file: file.to_string(),
+        message: e.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub file: String,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file, self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+// Example usage:
+/*
+use interpreter_c::syntax::{self, Expression, ExpressionKind, Visit};
+
+/// Rename calls of `old_api` to `new_api`, leaving everything else as is
+fn migrate(source: &str) -> Result<String, syntax::SyntaxError> {
+    struct Migrate<'a, 'src> {
+        rewriter: &'a mut syntax::Rewriter<'src>,
+    }
+
+    impl Visit for Migrate<'_, '_> {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let ExpressionKind::Call { function, .. } = &expression.kind {
+                if matches!(&function.kind, ExpressionKind::Identifier(name) if name == "old_api") {
+                    self.rewriter.replace(function.span, "new_api");
+                }
+            }
+            syntax::walk_expression(self, expression);
+        }
+    }
+
+    syntax::rewrite(source, "input.c", |unit, rewriter| {
+        Migrate { rewriter }.visit_translation_unit(unit);
+    })
+}
+*/
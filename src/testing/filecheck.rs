@@ -0,0 +1,781 @@
+// src/testing/filecheck.rs
+//! FileCheck-style assertions on emitted IR and assembly
+//! A codegen test is a C file whose comments say what the compiler must emit
+//! for it. `c-interpreter test` compiles it to the stage named by
+//! `// check-emit:` (`ir`, `opt-ir` or `asm`; `ir` by default) and matches
+//! the output against its directives, as LLVM's FileCheck would:
+//!
+//! ```c
+//! // check-emit: opt-ir
+//! // CHECK-LABEL: define {{.*}} @square(
+//! // CHECK:       [[X:%[0-9]+]] = mul nsw i32
+//! // CHECK-NEXT:  ret i32 [[X]]
+//! // CHECK-NOT:   call
+//! int square(int x) { return x * x; }
+//! ```
+//!
+//! - `CHECK:` matches at or after the end of the previous match.
+//! - `CHECK-NEXT:` must match on the following line, `CHECK-SAME:` on the
+//!   same line, and `CHECK-EMPTY:` is an empty following line.
+//! - `CHECK-NOT:` must not occur between the surrounding matches.
+//! - Consecutive `CHECK-DAG:` lines match in any order.
+//! - `CHECK-LABEL:` lines are found first and split the output into blocks
+//!   no other directive matches across, so one failing function doesn't
+//!   make every later check fail too.
+//!
+//! Runs of spaces and tabs in a pattern match any horizontal whitespace.
+//! `{{re}}` is a regular expression, `[[NAME:re]]` captures what `re`
+//! matched and `[[NAME]]` matches it again, here or in any later directive
+//! except labels. The regex dialect is the usual subset: `. [] [^]
+//! \d \w \s` and their negations, `* + ? {n,m}`, `|`, `()` and `^ $`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Directive {
+    Check,
+    Next,
+    Same,
+    Empty,
+    Not,
+    Dag,
+    Label,
+}
+
+impl Directive {
+    /// Spelling after the prefix, longest first so `-NEXT:` wins over `:`
+    const SUFFIXES: [(&'static str, Directive); 7] = [
+        ("-LABEL:", Directive::Label),
+        ("-EMPTY:", Directive::Empty),
+        ("-NEXT:", Directive::Next),
+        ("-SAME:", Directive::Same),
+        ("-NOT:", Directive::Not),
+        ("-DAG:", Directive::Dag),
+        (":", Directive::Check),
+    ];
+
+    fn suffix(self) -> &'static str {
+        let (suffix, _) = Self::SUFFIXES.iter().find(|(_, d)| *d == self).unwrap();
+        suffix.trim_end_matches(':')
+    }
+}
+
+/// One directive from the test file
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub directive: Directive,
+    /// Line of the test file, from 1
+    pub line: usize,
+    /// The pattern as written
+    pub text: String,
+    pattern: Vec<Piece>,
+    /// Names of the `[[NAME:re]]` captures, by group index
+    defines: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Nodes(Vec<Node>),
+    /// `[[NAME]]`
+    Use(String),
+}
+
+/// Position in the output: line and column, both from 0, in chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Pos {
+    line: usize,
+    col: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Found {
+    start: Pos,
+    end: Pos,
+    captures: Vec<(String, String)>,
+}
+
+pub struct CheckFile {
+    prefix: String,
+    checks: Vec<Check>,
+}
+
+impl CheckFile {
+    /// Collect the `prefix` directives of `source`, such as `CHECK:` for
+    /// the prefix `CHECK`
+    pub fn parse(source: &str, prefix: &str) -> Result<Self, FileCheckError> {
+        let mut checks = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let Some((directive, text)) = find_directive(line, prefix) else {
+                continue;
+            };
+            let line = index + 1;
+            let text = text.trim();
+            let error = |message: String| FileCheckError::Pattern { line, message };
+
+            if directive == Directive::Empty && !text.is_empty() {
+                return Err(error(format!("{}{} takes no pattern", prefix, directive.suffix())));
+            }
+            if directive != Directive::Empty && text.is_empty() {
+                return Err(error(format!("{}{} has an empty pattern", prefix, directive.suffix())));
+            }
+            if checks.is_empty() && matches!(directive, Directive::Next | Directive::Same | Directive::Empty) {
+                return Err(error(format!("{}{} can't be the first directive", prefix, directive.suffix())));
+            }
+            let (pattern, defines) = parse_pattern(text).map_err(error)?;
+            if !defines.is_empty() && matches!(directive, Directive::Not | Directive::Label) {
+                return Err(error(format!("{}{} can't define variables", prefix, directive.suffix())));
+            }
+            checks.push(Check {
+                directive,
+                line,
+                text: text.to_string(),
+                pattern,
+                defines,
+            });
+        }
+        Ok(CheckFile {
+            prefix: prefix.to_string(),
+            checks,
+        })
+    }
+
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Match `output` against every directive; the first failure is returned
+    pub fn run(&self, output: &str) -> Result<(), FileCheckError> {
+        let lines: Vec<Vec<char>> = output.lines().map(|line| line.chars().collect()).collect();
+        let end_of_output = Pos { line: lines.len(), col: 0 };
+        let mut vars: HashMap<String, String> = HashMap::new();
+
+        // Labels first; each bounds the block the directives after it search
+        let mut labels: HashMap<usize, Found> = HashMap::new();
+        let mut cursor = Pos { line: 0, col: 0 };
+        for (index, check) in self.checks.iter().enumerate() {
+            if check.directive == Directive::Label {
+                let found = self
+                    .search(check, &lines, cursor, end_of_output, &vars)?
+                    .ok_or_else(|| self.not_found(check, &lines, cursor))?;
+                cursor = found.end;
+                labels.insert(index, found);
+            }
+        }
+
+        let mut pos = Pos { line: 0, col: 0 };
+        let mut limit = self.block_end(0, &labels, end_of_output);
+        let mut nots: Vec<&Check> = Vec::new();
+        let mut index = 0;
+        while index < self.checks.len() {
+            let check = &self.checks[index];
+            let found = match check.directive {
+                Directive::Not => {
+                    nots.push(check);
+                    index += 1;
+                    continue;
+                }
+                Directive::Label => {
+                    limit = self.block_end(index + 1, &labels, end_of_output);
+                    labels[&index].clone()
+                }
+                Directive::Dag => {
+                    let group_end = self.checks[index..]
+                        .iter()
+                        .position(|c| c.directive != Directive::Dag)
+                        .map_or(self.checks.len(), |len| index + len);
+                    let found = self.match_dags(&self.checks[index..group_end], &lines, pos, limit, &mut vars)?;
+                    self.check_nots(&nots, &lines, pos, found.start, &vars)?;
+                    nots.clear();
+                    pos = found.end;
+                    index = group_end;
+                    continue;
+                }
+                Directive::Check => self
+                    .search(check, &lines, pos, limit, &vars)?
+                    .ok_or_else(|| self.not_found(check, &lines, pos))?,
+                Directive::Next | Directive::Same => {
+                    let line = if check.directive == Directive::Next { pos.line + 1 } else { pos.line };
+                    let from = if check.directive == Directive::Next { Pos { line, col: 0 } } else { pos };
+                    let to = Pos { line, col: lines.get(line).map_or(0, Vec::len) }.min(limit);
+                    self.search(check, &lines, from, to, &vars)?
+                        .ok_or_else(|| self.not_found(check, &lines, pos))?
+                }
+                Directive::Empty => {
+                    let next = Pos { line: pos.line + 1, col: 0 };
+                    if next.line >= lines.len() || next > limit || !lines[next.line].is_empty() {
+                        return Err(self.not_found(check, &lines, pos));
+                    }
+                    Found { start: next, end: next, captures: Vec::new() }
+                }
+            };
+            self.check_nots(&nots, &lines, pos, found.start, &vars)?;
+            nots.clear();
+            vars.extend(found.captures);
+            pos = found.end;
+            index += 1;
+        }
+        self.check_nots(&nots, &lines, pos, limit, &vars)
+    }
+
+    /// Where the block started by the label before `index` ends
+    fn block_end(&self, index: usize, labels: &HashMap<usize, Found>, end_of_output: Pos) -> Pos {
+        (index..self.checks.len())
+            .find_map(|next| labels.get(&next))
+            .map_or(end_of_output, |label| label.start)
+    }
+
+    /// Match a `CHECK-DAG` group without overlaps; the result spans the
+    /// earliest start to the latest end
+    fn match_dags(
+        &self,
+        group: &[Check],
+        lines: &[Vec<char>],
+        from: Pos,
+        to: Pos,
+        vars: &mut HashMap<String, String>,
+    ) -> Result<Found, FileCheckError> {
+        let mut matched: Vec<Found> = Vec::new();
+        for check in group {
+            let mut start = from;
+            let found = loop {
+                let found = self
+                    .search(check, lines, start, to, vars)?
+                    .ok_or_else(|| self.not_found(check, lines, from))?;
+                match matched.iter().find(|m| found.start < m.end && m.start < found.end) {
+                    // Try again past the match it collided with
+                    Some(other) => start = Pos { line: other.start.line, col: other.start.col + 1 },
+                    None => break found,
+                }
+            };
+            vars.extend(found.captures.iter().cloned());
+            matched.push(found);
+        }
+        Ok(Found {
+            start: matched.iter().map(|m| m.start).min().unwrap_or(from),
+            end: matched.iter().map(|m| m.end).max().unwrap_or(from),
+            captures: Vec::new(),
+        })
+    }
+
+    fn check_nots(
+        &self,
+        nots: &[&Check],
+        lines: &[Vec<char>],
+        from: Pos,
+        to: Pos,
+        vars: &HashMap<String, String>,
+    ) -> Result<(), FileCheckError> {
+        for check in nots {
+            if let Some(found) = self.search(check, lines, from, to, vars)? {
+                return Err(FileCheckError::Excluded {
+                    line: check.line,
+                    directive: self.spelling(check.directive),
+                    pattern: check.text.clone(),
+                    output_line: found.start.line + 1,
+                    text: lines[found.start.line].iter().collect(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// First match of `check` starting at or after `from` and ending at or
+    /// before `to`
+    fn search(
+        &self,
+        check: &Check,
+        lines: &[Vec<char>],
+        from: Pos,
+        to: Pos,
+        vars: &HashMap<String, String>,
+    ) -> Result<Option<Found>, FileCheckError> {
+        let nodes = self.compile(check, vars)?;
+        for (line, text) in lines.iter().enumerate().skip(from.line) {
+            if line > to.line {
+                break;
+            }
+            let first = if line == from.line { from.col } else { 0 };
+            let last = if line == to.line { to.col.min(text.len()) } else { text.len() };
+            if first > last {
+                continue;
+            }
+            if let Some((start, end, groups)) = find(&nodes, check.defines.len(), &text[..last], first) {
+                let captures = check
+                    .defines
+                    .iter()
+                    .zip(groups)
+                    .filter_map(|(name, group)| Some((name.clone(), text[group?.0..group?.1].iter().collect())))
+                    .collect();
+                return Ok(Some(Found {
+                    start: Pos { line, col: start },
+                    end: Pos { line, col: end },
+                    captures,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The pattern with `[[NAME]]` replaced by what `NAME` captured
+    fn compile(&self, check: &Check, vars: &HashMap<String, String>) -> Result<Vec<Node>, FileCheckError> {
+        let mut nodes = Vec::new();
+        for piece in &check.pattern {
+            match piece {
+                Piece::Nodes(more) => nodes.extend(more.iter().cloned()),
+                Piece::Use(name) => match check.defines.iter().position(|defined| defined == name) {
+                    Some(group) => nodes.push(Node::Backref(group)),
+                    None => {
+                        let value = vars.get(name).ok_or_else(|| FileCheckError::UndefinedVariable {
+                            line: check.line,
+                            name: name.clone(),
+                        })?;
+                        nodes.extend(value.chars().map(Node::Char));
+                    }
+                },
+            }
+        }
+        Ok(nodes)
+    }
+
+    fn not_found(&self, check: &Check, lines: &[Vec<char>], from: Pos) -> FileCheckError {
+        FileCheckError::NotFound {
+            line: check.line,
+            directive: self.spelling(check.directive),
+            pattern: check.text.clone(),
+            output_line: from.line + 1,
+            near: lines.get(from.line).map(|text| text.iter().collect()),
+        }
+    }
+
+    fn spelling(&self, directive: Directive) -> String {
+        format!("{}{}", self.prefix, directive.suffix())
+    }
+}
+
+/// Whether `source` has any `prefix` directives, without parsing them
+pub fn has_directives(source: &str, prefix: &str) -> bool {
+    source.lines().any(|line| find_directive(line, prefix).is_some())
+}
+
+/// The directive on `line` and the text after its colon
+fn find_directive<'a>(line: &'a str, prefix: &str) -> Option<(Directive, &'a str)> {
+    line.match_indices(prefix).find_map(|(at, _)| {
+        // `XCHECK:` and `MY-CHECK:` belong to other prefixes
+        let standalone = line[..at]
+            .chars()
+            .next_back()
+            .map_or(true, |c| !(c.is_alphanumeric() || c == '_' || c == '-'));
+        let rest = &line[at + prefix.len()..];
+        Directive::SUFFIXES
+            .iter()
+            .find(|(suffix, _)| rest.starts_with(suffix))
+            .filter(|_| standalone)
+            .map(|&(suffix, directive)| (directive, &rest[suffix.len()..]))
+    })
+}
+
+/// Split a pattern into literal text, `{{re}}`, `[[NAME:re]]` and `[[NAME]]`
+fn parse_pattern(text: &str) -> Result<(Vec<Piece>, Vec<String>), String> {
+    let mut pieces = Vec::new();
+    let mut defines = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{{") {
+            let end = regex_end(after).ok_or("unterminated '{{'")?;
+            pieces.push(Piece::Nodes(literal_nodes(&std::mem::take(&mut literal))));
+            pieces.push(Piece::Nodes(vec![Node::Group(parse_regex(&after[..end])?, None)]));
+            rest = &after[end + 2..];
+        } else if let Some(after) = rest.strip_prefix("[[") {
+            let end = after.find("]]").ok_or("unterminated '[['")?;
+            pieces.push(Piece::Nodes(literal_nodes(&std::mem::take(&mut literal))));
+            let body = &after[..end];
+            let (name, regex) = match body.split_once(':') {
+                Some((name, regex)) => (name, Some(regex)),
+                None => (body, None),
+            };
+            let valid = name.chars().next().map_or(false, |c| c == '_' || c.is_ascii_alphabetic())
+                && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric());
+            if !valid {
+                return Err(format!("invalid variable name '{}'", name));
+            }
+            match regex {
+                Some(regex) => {
+                    pieces.push(Piece::Nodes(vec![Node::Group(parse_regex(regex)?, Some(defines.len()))]));
+                    defines.push(name.to_string());
+                }
+                None => pieces.push(Piece::Use(name.to_string())),
+            }
+            rest = &after[end + 2..];
+        } else {
+            literal.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    pieces.push(Piece::Nodes(literal_nodes(&literal)));
+    Ok((pieces, defines))
+}
+
+/// Offset of the `}}` closing a `{{`, skipping braces of `{n,m}` and
+/// inside `[]`
+fn regex_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut class = None;
+    for (at, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ']' if class.map_or(false, |start| at > start + 1) => class = None,
+            _ if class.is_some() => {}
+            '[' => class = Some(at),
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            '}' if text[at..].starts_with("}}") => return Some(at),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Literal text, with each run of blanks matching any run of blanks
+fn literal_nodes(text: &str) -> Vec<Node> {
+    let blank = || Node::Class { negated: false, ranges: vec![(' ', ' '), ('\t', '\t')] };
+    let mut nodes = Vec::new();
+    for c in text.chars() {
+        if c == ' ' || c == '\t' {
+            if !matches!(nodes.last(), Some(Node::Repeat(..))) {
+                nodes.push(Node::Repeat(Box::new(blank()), 1, usize::MAX));
+            }
+        } else {
+            nodes.push(Node::Char(c));
+        }
+    }
+    nodes
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    /// `.`
+    Any,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+    /// Alternatives, and the capture index of `[[NAME:re]]`
+    Group(Vec<Vec<Node>>, Option<usize>),
+    Repeat(Box<Node>, usize, usize),
+    /// `[[NAME]]` after `[[NAME:re]]` in the same directive
+    Backref(usize),
+    LineStart,
+    LineEnd,
+}
+
+fn parse_regex(text: &str) -> Result<Vec<Vec<Node>>, String> {
+    let mut parser = RegexParser { chars: text.chars().collect(), pos: 0 };
+    let alternatives = parser.alternation()?;
+    match parser.peek() {
+        None => Ok(alternatives),
+        Some(c) => Err(format!("unexpected '{}' in regex '{}'", c, text)),
+    }
+}
+
+struct RegexParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl RegexParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn alternation(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat('|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some('(') => {
+                if self.eat('?') && !self.eat(':') {
+                    return Err("only (?:...) groups are supported".to_string());
+                }
+                let alternatives = self.alternation()?;
+                if !self.eat(')') {
+                    return Err("unclosed '('".to_string());
+                }
+                Ok(Node::Group(alternatives, None))
+            }
+            Some('[') => self.class(),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::LineStart),
+            Some('$') => Ok(Node::LineEnd),
+            Some('\\') => self.escape(),
+            Some(c @ ('*' | '+' | '?' | '{')) => Err(format!("nothing for '{}' to repeat", c)),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("unexpected end of regex".to_string()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self.next().ok_or("trailing '\\'")?;
+        Ok(match class_escape(c) {
+            Some((negated, ranges)) => Node::Class { negated, ranges },
+            None => Node::Char(match c {
+                'n' => '\n',
+                't' => '\t',
+                other => other,
+            }),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or("unclosed '['")?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let low = if c == '\\' {
+                let escaped = self.next().ok_or("unclosed '['")?;
+                match class_escape(escaped) {
+                    Some((false, more)) => {
+                        ranges.extend(more);
+                        continue;
+                    }
+                    Some((true, _)) => return Err(format!("'\\{}' isn't supported inside []", escaped)),
+                    None => escaped,
+                }
+            } else {
+                c
+            };
+            let is_range = self.peek() == Some('-') && self.chars.get(self.pos + 1).map_or(false, |&c| c != ']');
+            if is_range {
+                self.pos += 1;
+                let high = self.next().ok_or("unclosed '['")?;
+                if high < low {
+                    return Err(format!("invalid range '{}-{}'", low, high));
+                }
+                ranges.push((low, high));
+            } else {
+                ranges.push((low, low));
+            }
+        }
+        Ok(Node::Class { negated, ranges })
+    }
+
+    fn quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, usize::MAX),
+            Some('+') => (1, usize::MAX),
+            Some('?') => (0, 1),
+            Some('{') => {
+                let close = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .ok_or("unclosed '{'")?;
+                let body: String = self.chars[self.pos + 1..self.pos + close].iter().collect();
+                let bound = |s: &str| s.trim().parse::<usize>().map_err(|_| format!("invalid repeat '{{{}}}'", body));
+                let (min, max) = match body.split_once(',') {
+                    Some((min, "")) => (bound(min)?, usize::MAX),
+                    Some((min, max)) => (bound(min)?, bound(max)?),
+                    None => (bound(&body)?, bound(&body)?),
+                };
+                if max < min {
+                    return Err(format!("invalid repeat '{{{}}}'", body));
+                }
+                self.pos += close;
+                (min, max)
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        Ok(Node::Repeat(Box::new(atom), min, max))
+    }
+}
+
+/// `\d \w \s` and their negations
+fn class_escape(c: char) -> Option<(bool, Vec<(char, char)>)> {
+    let ranges = match c.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\r')],
+        _ => return None,
+    };
+    Some((c.is_ascii_uppercase(), ranges))
+}
+
+type Groups = Vec<Option<(usize, usize)>>;
+
+/// Leftmost match of `nodes` in `text` at or after `from`: start, end and
+/// the capture groups
+fn find(nodes: &[Node], groups: usize, text: &[char], from: usize) -> Option<(usize, usize, Groups)> {
+    (from..=text.len()).find_map(|start| {
+        let mut matcher = Matcher { text, groups: vec![None; groups] };
+        let mut end = None;
+        matcher
+            .sequence(nodes, start, &mut |_, at| {
+                end = Some(at);
+                true
+            })
+            .then(|| (start, end.unwrap(), matcher.groups))
+    })
+}
+
+/// Backtracking matcher; each step passes its end position on to `next`,
+/// which decides whether the rest of the pattern matches from there
+struct Matcher<'a> {
+    text: &'a [char],
+    groups: Groups,
+}
+
+type Next<'n, 'a> = &'n mut dyn FnMut(&mut Matcher<'a>, usize) -> bool;
+
+impl<'a> Matcher<'a> {
+    fn sequence(&mut self, nodes: &[Node], at: usize, next: Next<'_, 'a>) -> bool {
+        match nodes.split_first() {
+            None => next(self, at),
+            Some((first, rest)) => self.node(first, at, &mut |m, after| m.sequence(rest, after, next)),
+        }
+    }
+
+    fn node(&mut self, node: &Node, at: usize, next: Next<'_, 'a>) -> bool {
+        let text = self.text;
+        match node {
+            Node::Char(c) => text.get(at) == Some(c) && next(self, at + 1),
+            Node::Any => at < text.len() && next(self, at + 1),
+            Node::Class { negated, ranges } => match text.get(at) {
+                Some(&c) if ranges.iter().any(|&(low, high)| low <= c && c <= high) != *negated => next(self, at + 1),
+                _ => false,
+            },
+            Node::LineStart => at == 0 && next(self, at),
+            Node::LineEnd => at == text.len() && next(self, at),
+            Node::Backref(group) => match self.groups[*group] {
+                Some((start, end)) if text.get(at..at + (end - start)) == Some(&text[start..end]) => {
+                    next(self, at + (end - start))
+                }
+                _ => false,
+            },
+            Node::Group(alternatives, capture) => alternatives.iter().any(|alternative| {
+                self.sequence(alternative, at, &mut |m, end| match capture {
+                    Some(group) => {
+                        let saved = m.groups[*group];
+                        m.groups[*group] = Some((at, end));
+                        next(m, end) || {
+                            m.groups[*group] = saved;
+                            false
+                        }
+                    }
+                    None => next(m, end),
+                })
+            }),
+            Node::Repeat(inner, min, max) => self.repeat(inner, *min, *max, 0, at, next),
+        }
+    }
+
+    /// Greedy: take another `inner` if the rest still matches, else stop here
+    fn repeat(&mut self, inner: &Node, min: usize, max: usize, count: usize, at: usize, next: Next<'_, 'a>) -> bool {
+        // An empty iteration past `min` would loop forever
+        let more = count < max
+            && self.node(inner, at, &mut |m, after| {
+                (after != at || count < min) && m.repeat(inner, min, max, count + 1, after, next)
+            });
+        more || (count >= min && next(self, at))
+    }
+}
+
+#[derive(Debug)]
+pub enum FileCheckError {
+    /// A directive in the test file doesn't parse
+    Pattern { line: usize, message: String },
+    UndefinedVariable { line: usize, name: String },
+    /// A positive directive matched nothing
+    NotFound {
+        line: usize,
+        directive: String,
+        pattern: String,
+        output_line: usize,
+        near: Option<String>,
+    },
+    /// A `CHECK-NOT` pattern occurs
+    Excluded {
+        line: usize,
+        directive: String,
+        pattern: String,
+        output_line: usize,
+        text: String,
+    },
+}
+
+impl fmt::Display for FileCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileCheckError::Pattern { line, message } => write!(f, "line {}: {}", line, message),
+            FileCheckError::UndefinedVariable { line, name } => {
+                write!(f, "line {}: variable '{}' is used before it is defined", line, name)
+            }
+            FileCheckError::NotFound { line, directive, pattern, output_line, near } => {
+                writeln!(f, "line {}: {}: {}", line, directive, pattern)?;
+                write!(f, "  expected pattern not found after output line {}", output_line)?;
+                if let Some(near) = near {
+                    write!(f, ":\n  {}", near)?;
+                }
+                Ok(())
+            }
+            FileCheckError::Excluded { line, directive, pattern, output_line, text } => {
+                writeln!(f, "line {}: {}: {}", line, directive, pattern)?;
+                write!(f, "  excluded pattern found on output line {}:\n  {}", output_line, text)
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), FileCheckError> {
+    let test = r#"
+        // CHECK-LABEL: define i32 @square(
+        // CHECK:       [[X:%[0-9]+]] = mul nsw i32 %0, %0
+        // CHECK-NEXT:  ret i32 [[X]]
+    "#;
+    let ir = "define i32 @square(i32 %0) {\n  %2 = mul nsw i32 %0, %0\n  ret i32 %2\n}\n";
+    CheckFile::parse(test, "CHECK")?.run(ir)
+}
+*/
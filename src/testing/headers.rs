@@ -0,0 +1,331 @@
+// src/testing/headers.rs
+//! libc header compatibility run for `c-interpreter test --libc-headers`
+//! Preprocesses and parses each public header of the system libc (glibc or
+//! musl) in its own translation unit, groups the failures by the construct
+//! that tripped us up, and appends the pass rate to a history file. A header
+//! that passed in the previous run for the same libc and fails now is a
+//! regression, and so is a lower pass rate.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+
+/// Default location of the history file, relative to the working directory
+pub const DEFAULT_HISTORY_FILE: &str = "header-compat.json";
+
+/// C standard and POSIX headers shipped by both glibc and musl
+const LIBC_HEADERS: &[&str] = &[
+    // ISO C
+    "assert.h", "complex.h", "ctype.h", "errno.h", "fenv.h", "float.h", "inttypes.h",
+    "iso646.h", "limits.h", "locale.h", "math.h", "setjmp.h", "signal.h", "stdalign.h",
+    "stdarg.h", "stdatomic.h", "stdbool.h", "stddef.h", "stdint.h", "stdio.h", "stdlib.h",
+    "stdnoreturn.h", "string.h", "tgmath.h", "threads.h", "time.h", "uchar.h", "wchar.h",
+    "wctype.h",
+    // POSIX
+    "aio.h", "arpa/inet.h", "cpio.h", "dirent.h", "dlfcn.h", "fcntl.h", "fmtmsg.h",
+    "fnmatch.h", "ftw.h", "glob.h", "grp.h", "iconv.h", "langinfo.h", "libgen.h",
+    "monetary.h", "mqueue.h", "net/if.h", "netdb.h", "netinet/in.h", "netinet/tcp.h",
+    "nl_types.h", "poll.h", "pthread.h", "pwd.h", "regex.h", "sched.h", "search.h",
+    "semaphore.h", "spawn.h", "strings.h", "sys/ipc.h", "sys/mman.h", "sys/msg.h",
+    "sys/resource.h", "sys/select.h", "sys/sem.h", "sys/shm.h", "sys/socket.h",
+    "sys/stat.h", "sys/statvfs.h", "sys/time.h", "sys/times.h", "sys/types.h",
+    "sys/uio.h", "sys/un.h", "sys/utsname.h", "sys/wait.h", "syslog.h", "tar.h",
+    "termios.h", "ulimit.h", "unistd.h", "utime.h", "utmpx.h", "wordexp.h",
+    // Common extensions
+    "alloca.h", "byteswap.h", "endian.h", "err.h", "getopt.h", "malloc.h", "paths.h",
+    "sys/epoll.h", "sys/eventfd.h", "sys/ioctl.h", "sys/prctl.h", "sys/random.h",
+    "sys/sysinfo.h",
+];
+
+/// Preprocesses and parses `source` with the given system include dirs
+pub type HeaderChecker<'a> = &'a mut dyn FnMut(&str, &[PathBuf]) -> Result<(), String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LibcFlavor {
+    Glibc,
+    Musl,
+}
+
+impl LibcFlavor {
+    /// Where CI images put each libc's headers
+    fn candidate_roots(self) -> &'static [&'static str] {
+        match self {
+            LibcFlavor::Glibc => &["/usr/include"],
+            LibcFlavor::Musl => &[
+                "/usr/include/x86_64-linux-musl",
+                "/usr/include/aarch64-linux-musl",
+                "/usr/lib/musl/include",
+                "/usr/local/musl/include",
+            ],
+        }
+    }
+
+    /// Guess the flavor of an include directory
+    pub fn detect(root: &Path) -> LibcFlavor {
+        let features = fs::read_to_string(root.join("features.h")).unwrap_or_default();
+        if features.contains("__GLIBC__") {
+            LibcFlavor::Glibc
+        } else {
+            LibcFlavor::Musl
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LibcHeaders {
+    pub flavor: LibcFlavor,
+    pub root: PathBuf,
+    /// Extra system include dirs, e.g. the multiarch directory for glibc
+    pub include_dirs: Vec<PathBuf>,
+    pub headers: Vec<String>,
+}
+
+impl LibcHeaders {
+    /// Every installed libc: glibc and/or musl, whichever are present
+    pub fn discover() -> Vec<LibcHeaders> {
+        [LibcFlavor::Glibc, LibcFlavor::Musl]
+            .into_iter()
+            .filter_map(|flavor| {
+                flavor
+                    .candidate_roots()
+                    .iter()
+                    .map(Path::new)
+                    .find(|root| root.join("stdio.h").is_file() && LibcFlavor::detect(root) == flavor)
+                    .map(|root| LibcHeaders::at(root, flavor))
+            })
+            .collect()
+    }
+
+    pub fn at(root: &Path, flavor: LibcFlavor) -> LibcHeaders {
+        let mut include_dirs = vec![root.to_path_buf()];
+        // glibc puts bits/ and gnu/ under a multiarch directory on Debian-style systems
+        if let Ok(entries) = fs::read_dir(root) {
+            let mut multiarch: Vec<PathBuf> = entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.join("bits").is_dir() && p.file_name().map_or(false, |n| n.to_string_lossy().contains("-linux-")))
+                .collect();
+            multiarch.sort();
+            include_dirs.extend(multiarch);
+        }
+
+        let headers = LIBC_HEADERS
+            .iter()
+            .filter(|h| include_dirs.iter().any(|dir| dir.join(h).is_file()))
+            .map(|h| h.to_string())
+            .collect();
+
+        LibcHeaders {
+            flavor,
+            root: root.to_path_buf(),
+            include_dirs,
+            headers,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeaderResult {
+    pub header: String,
+    /// First error for the header, if it failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompatReport {
+    pub flavor: LibcFlavor,
+    pub root: PathBuf,
+    pub results: Vec<HeaderResult>,
+}
+
+impl CompatReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.error.is_none()).count()
+    }
+
+    pub fn percentage(&self) -> f64 {
+        if self.results.is_empty() {
+            return 100.0;
+        }
+        self.passed() as f64 * 100.0 / self.results.len() as f64
+    }
+
+    /// Failures grouped by error text, with the headers that hit each one
+    pub fn unsupported_constructs(&self) -> Vec<(String, Vec<String>)> {
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for result in &self.results {
+            if let Some(error) = &result.error {
+                groups.entry(construct_key(error)).or_default().push(result.header.clone());
+            }
+        }
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+        groups
+    }
+}
+
+/// Strip the file/line prefix and quoted identifiers so the same construct
+/// in different headers lands in one group
+fn construct_key(error: &str) -> String {
+    // `file.h:12:3: error[E0001]: parse error: ...` -> `parse error: ...`
+    let message = match error.find("error") {
+        Some(pos) => error[pos..].splitn(2, ": ").nth(1).unwrap_or(error),
+        None => error,
+    };
+
+    let mut key = String::with_capacity(message.len());
+    let mut in_quote = false;
+    for c in message.chars() {
+        match c {
+            '\'' | '"' => {
+                if !in_quote {
+                    key.push_str("'…'");
+                }
+                in_quote = !in_quote;
+            }
+            _ if in_quote => {}
+            _ => key.push(c),
+        }
+    }
+    key.lines().next().unwrap_or("").trim().to_string()
+}
+
+/// Check every header, printing one line per header and a summary
+pub fn run(libc: &LibcHeaders, checker: HeaderChecker) -> CompatReport {
+    let mut results = Vec::with_capacity(libc.headers.len());
+
+    for header in &libc.headers {
+        let start = Instant::now();
+        let source = format!("#include <{}>\nint main(void) {{ return 0; }}\n", header);
+        let error = checker(&source, &libc.include_dirs).err();
+
+        match &error {
+            None => println!("PASS <{}> ({:?})", header, start.elapsed()),
+            Some(e) => println!("FAIL <{}>: {}", header, e.lines().next().unwrap_or("")),
+        }
+        results.push(HeaderResult { header: header.clone(), error });
+    }
+
+    let report = CompatReport {
+        flavor: libc.flavor,
+        root: libc.root.clone(),
+        results,
+    };
+
+    println!(
+        "\n{:?} ({}): {}/{} headers parse ({:.1}%)",
+        report.flavor,
+        report.root.display(),
+        report.passed(),
+        report.results.len(),
+        report.percentage()
+    );
+    for (construct, headers) in report.unsupported_constructs() {
+        println!("  {:>3}  {}  [{}]", headers.len(), construct, headers.join(", "));
+    }
+    report
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub compiler_version: String,
+    pub flavor: LibcFlavor,
+    pub root: PathBuf,
+    pub total: usize,
+    pub passed: usize,
+    pub percentage: f64,
+    pub passing: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Regression {
+    /// Passed in the previous run, fails now
+    Header(String),
+    Percentage { previous: f64, current: f64 },
+}
+
+/// Append `report` to the history file and compare it with the previous
+/// run for the same libc
+pub fn record(history_file: &Path, report: &CompatReport) -> Result<Vec<Regression>, HeaderCompatError> {
+    let mut history: Vec<HistoryEntry> = match fs::read(history_file) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| HeaderCompatError::History(history_file.to_path_buf(), e.to_string()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(HeaderCompatError::Io(history_file.to_path_buf(), e)),
+    };
+
+    let entry = HistoryEntry {
+        timestamp: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        compiler_version: env!("CARGO_PKG_VERSION").to_string(),
+        flavor: report.flavor,
+        root: report.root.clone(),
+        total: report.results.len(),
+        passed: report.passed(),
+        percentage: report.percentage(),
+        passing: report
+            .results
+            .iter()
+            .filter(|r| r.error.is_none())
+            .map(|r| r.header.clone())
+            .collect(),
+    };
+
+    let mut regressions = Vec::new();
+    if let Some(previous) = history
+        .iter()
+        .rev()
+        .find(|h| h.flavor == entry.flavor && h.root == entry.root)
+    {
+        for header in &previous.passing {
+            let still_checked = report.results.iter().any(|r| &r.header == header);
+            if still_checked && !entry.passing.contains(header) {
+                regressions.push(Regression::Header(header.clone()));
+            }
+        }
+        if entry.percentage + f64::EPSILON < previous.percentage {
+            regressions.push(Regression::Percentage {
+                previous: previous.percentage,
+                current: entry.percentage,
+            });
+        }
+    }
+
+    history.push(entry);
+    let json = serde_json::to_vec_pretty(&history)
+        .map_err(|e| HeaderCompatError::History(history_file.to_path_buf(), e.to_string()))?;
+    fs::write(history_file, json).map_err(|e| HeaderCompatError::Io(history_file.to_path_buf(), e))?;
+
+    Ok(regressions)
+}
+
+#[derive(Debug)]
+pub enum HeaderCompatError {
+    Io(PathBuf, std::io::Error),
+    History(PathBuf, String),
+    NoLibcFound,
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), HeaderCompatError> {
+    let installed = LibcHeaders::discover();
+    if installed.is_empty() {
+        return Err(HeaderCompatError::NoLibcFound);
+    }
+
+    for libc in &installed {
+        let report = run(libc, &mut |source, include_dirs| parse_with_includes(source, include_dirs));
+        for regression in record(Path::new(DEFAULT_HISTORY_FILE), &report)? {
+            eprintln!("regression: {:?}", regression);
+        }
+    }
+    Ok(())
+}
+*/
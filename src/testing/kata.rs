@@ -104,7 +104,7 @@ ENTRYPOINT ["./run-tests.sh"]
 
 // Installation helper
 pub async fn setup_kata_environment() -> Result<(), SetupError> {
-    println!("Setting up Kata Containers environment...");
+    log::info!("Setting up Kata Containers environment...");
 
     // Install Kata Containers
     #[cfg(target_os = "linux")]
@@ -1,3 +1,12 @@
+pub mod encoder;
+pub mod filecheck;
+pub mod headers;
+pub mod perf;
+// Runs programs and `// check-emit:` tests through the compiler
+#[cfg(feature = "llvm")]
+pub mod programs;
+pub mod reduce;
+
 pub struct TestingFramework {
     // Unit testing
     unit_tests: UnitTestRunner,
@@ -0,0 +1,283 @@
+// src/testing/perf/dispatch.rs
+//! Bytecode dispatch benchmark and regression gate
+//! Runs a fixed set of dispatch-bound kernels (recursion, a counted loop, a
+//! sieve, a switch-driven state machine, floating-point loops) on the
+//! bytecode VM three ways: the checked switch loop, the threaded loop, and
+//! the threaded loop over fused superinstructions. All three must agree on
+//! every kernel's exit status. The gate holds while the threaded loop with
+//! fusion stays `min_speedup` times faster than the switch loop, and the
+//! numbers can be kept as a JSON baseline to flag regressions.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::frontend::c23::C23Parser;
+use crate::interpreter::bytecode::{self, Dispatch, Program, Vm};
+use crate::interpreter::c_runtime::CRuntimeEnvironment;
+
+/// Kernels that spend their time in the dispatch loop, not in native calls
+pub const KERNELS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+         int main(void) { return fib(27) & 0xff; }\n",
+    ),
+    (
+        "loop",
+        "int main(void) {\n\
+             long sum = 0;\n\
+             for (int i = 0; i < 20000000; i++) sum += i ^ 3;\n\
+             return (int)(sum & 0xff);\n\
+         }\n",
+    ),
+    (
+        "sieve",
+        "int main(void) {\n\
+             static char composite[200000];\n\
+             int count = 0;\n\
+             for (int round = 0; round < 10; round++) {\n\
+                 count = 0;\n\
+                 for (int i = 2; i < 200000; i++) composite[i] = 0;\n\
+                 for (int i = 2; i < 200000; i++) {\n\
+                     if (composite[i]) continue;\n\
+                     count++;\n\
+                     for (int k = i + i; k < 200000; k += i) composite[k] = 1;\n\
+                 }\n\
+             }\n\
+             return count & 0xff;\n\
+         }\n",
+    ),
+    (
+        "state_machine",
+        "int main(void) {\n\
+             unsigned state = 1, out = 0;\n\
+             for (int i = 0; i < 10000000; i++) {\n\
+                 switch (state & 7) {\n\
+                 case 0: out += 3; state = state * 5 + 1; break;\n\
+                 case 1: out ^= state; state += 7; break;\n\
+                 case 2: out -= 1; state >>= 1; break;\n\
+                 case 3: state = state * 3 + out; break;\n\
+                 default: state++; break;\n\
+                 }\n\
+             }\n\
+             return (int)(out & 0xff);\n\
+         }\n",
+    ),
+    (
+        "matrix",
+        "int main(void) {\n\
+             static double a[64][64], b[64][64], c[64][64];\n\
+             for (int i = 0; i < 64; i++)\n\
+                 for (int j = 0; j < 64; j++) { a[i][j] = i + j; b[i][j] = i - j; }\n\
+             for (int n = 0; n < 8; n++)\n\
+                 for (int i = 0; i < 64; i++)\n\
+                     for (int j = 0; j < 64; j++) {\n\
+                         double sum = 0.0;\n\
+                         for (int k = 0; k < 64; k++) sum += a[i][k] * b[k][j];\n\
+                         c[i][j] = sum;\n\
+                     }\n\
+             return (int)c[5][7] & 0xff;\n\
+         }\n",
+    ),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KernelTiming {
+    pub kernel: String,
+    /// Median run time with `Dispatch::Switch`, unfused, in milliseconds
+    pub switch_ms: f64,
+    /// With `Dispatch::Threaded`, unfused
+    pub threaded_ms: f64,
+    /// With `Dispatch::Threaded` over superinstructions
+    pub fused_ms: f64,
+    /// Superinstructions `fuse` made in the kernel
+    pub superinstructions: usize,
+}
+
+impl KernelTiming {
+    /// Switch loop time over threaded-and-fused time (higher is better)
+    pub fn speedup(&self) -> f64 {
+        self.switch_ms / self.fused_ms.max(1e-9)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DispatchResults {
+    pub kernels: Vec<KernelTiming>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DispatchRegression {
+    pub kernel: String,
+    pub baseline_ms: f64,
+    pub current_ms: f64,
+    /// Relative slowdown, 0.10 == 10% worse
+    pub slowdown: f64,
+}
+
+pub struct DispatchBenchSuite {
+    // Kernel names and C sources
+    kernels: Vec<(String, String)>,
+
+    // Repetitions per measurement; the median is reported
+    repetitions: usize,
+}
+
+impl DispatchBenchSuite {
+    /// The built-in `KERNELS`
+    pub fn new(repetitions: usize) -> Self {
+        DispatchBenchSuite {
+            kernels: KERNELS.iter().map(|(name, source)| (name.to_string(), source.to_string())).collect(),
+            repetitions: repetitions.max(1),
+        }
+    }
+
+    pub fn with_kernel(mut self, name: &str, source: &str) -> Self {
+        self.kernels.push((name.to_string(), source.to_string()));
+        self
+    }
+
+    pub fn run(&self) -> Result<DispatchResults, DispatchBenchError> {
+        let mut kernels = Vec::with_capacity(self.kernels.len());
+        for (name, source) in &self.kernels {
+            let failed = |e: String| DispatchBenchError::KernelFailed(name.clone(), e);
+
+            let mut parser = C23Parser::new();
+            let ast = parser.parse(source).map_err(|e| failed(format!("{:?}", e)))?;
+            let plain = bytecode::compile(&ast).map_err(|e| failed(e.to_string()))?;
+            let mut fused = plain.clone();
+            let superinstructions = bytecode::fuse(&mut fused);
+
+            let (switch, expected) = self.median(name, &plain, Dispatch::Switch)?;
+            let (threaded, status) = self.median(name, &plain, Dispatch::Threaded)?;
+            let (fused_time, fused_status) = self.median(name, &fused, Dispatch::Threaded)?;
+            for (variant, status) in [("threaded", status), ("fused", fused_status)] {
+                if status != expected {
+                    return Err(DispatchBenchError::Mismatch { kernel: name.clone(), variant, expected, actual: status });
+                }
+            }
+
+            kernels.push(KernelTiming {
+                kernel: name.clone(),
+                switch_ms: switch.as_secs_f64() * 1000.0,
+                threaded_ms: threaded.as_secs_f64() * 1000.0,
+                fused_ms: fused_time.as_secs_f64() * 1000.0,
+                superinstructions,
+            });
+        }
+        Ok(DispatchResults { kernels })
+    }
+
+    /// Median time of one kernel run, and its exit status
+    fn median(&self, name: &str, program: &Program, dispatch: Dispatch) -> Result<(Duration, i32), DispatchBenchError> {
+        let failed = |e: String| DispatchBenchError::KernelFailed(name.to_string(), e);
+        let mut samples = Vec::with_capacity(self.repetitions);
+        let mut status = 0;
+        for _ in 0..self.repetitions {
+            let mut runtime = CRuntimeEnvironment::new().map_err(|e| failed(format!("{:?}", e)))?;
+            let mut vm = Vm::new(program, &mut runtime).map_err(|e| failed(format!("{:?}", e)))?.with_dispatch(dispatch);
+            let start = Instant::now();
+            status = vm.run_main(&[name.to_string()]).map_err(|e| failed(format!("{:?}", e)))?;
+            samples.push(start.elapsed());
+        }
+        samples.sort();
+        Ok((samples[samples.len() / 2], status))
+    }
+}
+
+impl DispatchResults {
+    /// Geometric mean of the per-kernel speedups
+    pub fn speedup(&self) -> f64 {
+        if self.kernels.is_empty() {
+            return 1.0;
+        }
+        let log_sum: f64 = self.kernels.iter().map(|kernel| kernel.speedup().ln()).sum();
+        (log_sum / self.kernels.len() as f64).exp()
+    }
+
+    /// Fails when the threaded loop with fusion isn't at least `min_speedup`
+    /// times faster than the switch loop, over all kernels
+    pub fn gate(&self, min_speedup: f64) -> Result<(), DispatchBenchError> {
+        let speedup = self.speedup();
+        if speedup < min_speedup {
+            return Err(DispatchBenchError::BelowGate { speedup, required: min_speedup });
+        }
+        Ok(())
+    }
+
+    pub fn load_baseline(path: &Path) -> Result<Self, DispatchBenchError> {
+        let json = fs::read_to_string(path).map_err(DispatchBenchError::Io)?;
+        serde_json::from_str(&json).map_err(DispatchBenchError::Serialization)
+    }
+
+    pub fn save_baseline(&self, path: &Path) -> Result<(), DispatchBenchError> {
+        let json = serde_json::to_string_pretty(self).map_err(DispatchBenchError::Serialization)?;
+        fs::write(path, json).map_err(DispatchBenchError::Io)
+    }
+
+    /// Kernels whose threaded-and-fused time got slower than `baseline`'s by
+    /// more than `threshold`, relative
+    pub fn regressions_against(&self, baseline: &DispatchResults, threshold: f64) -> Vec<DispatchRegression> {
+        let mut regressions = Vec::new();
+        for current in &self.kernels {
+            let Some(base) = baseline.kernels.iter().find(|base| base.kernel == current.kernel) else { continue };
+            if base.fused_ms > 0.0 {
+                let slowdown = current.fused_ms / base.fused_ms - 1.0;
+                if slowdown > threshold {
+                    regressions.push(DispatchRegression {
+                        kernel: current.kernel.clone(),
+                        baseline_ms: base.fused_ms,
+                        current_ms: current.fused_ms,
+                        slowdown,
+                    });
+                }
+            }
+        }
+        regressions
+    }
+}
+
+#[derive(Debug)]
+pub enum DispatchBenchError {
+    Io(std::io::Error),
+    Serialization(serde_json::Error),
+    KernelFailed(String, String),
+    /// A dispatch variant computed something else than the switch loop
+    Mismatch { kernel: String, variant: &'static str, expected: i32, actual: i32 },
+    BelowGate { speedup: f64, required: f64 },
+}
+
+impl std::fmt::Display for DispatchBenchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DispatchBenchError::Io(e) => write!(f, "{}", e),
+            DispatchBenchError::Serialization(e) => write!(f, "baseline: {}", e),
+            DispatchBenchError::KernelFailed(kernel, e) => write!(f, "{}: {}", kernel, e),
+            DispatchBenchError::Mismatch { kernel, variant, expected, actual } => {
+                write!(f, "{}: {} dispatch exited with {}, the switch loop with {}", kernel, variant, actual, expected)
+            }
+            DispatchBenchError::BelowGate { speedup, required } => {
+                write!(f, "threaded dispatch is {:.2}x the switch loop, below the {:.2}x gate", speedup, required)
+            }
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), DispatchBenchError> {
+    let results = DispatchBenchSuite::new(5).run()?;
+    for kernel in &results.kernels {
+        println!("{:<14} switch {:>8.1} ms  threaded {:>8.1} ms  fused {:>8.1} ms ({} superinstructions)",
+            kernel.kernel, kernel.switch_ms, kernel.threaded_ms, kernel.fused_ms, kernel.superinstructions);
+    }
+    results.gate(1.15)?;
+
+    let baseline = DispatchResults::load_baseline(Path::new("benches/dispatch.json"))?;
+    for r in results.regressions_against(&baseline, 0.10) {
+        eprintln!("{} regressed by {:.1}%", r.kernel, r.slowdown * 100.0);
+    }
+    Ok(())
+}
+*/
@@ -0,0 +1,217 @@
+// src/testing/perf/frontend.rs
+//! Frontend benchmark suite and regression gate
+//! Measures parser throughput, IR generation time and JIT latency over a
+//! fixed corpus of C files, stores the numbers as a JSON baseline and flags
+//! regressions beyond a configurable threshold.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::frontend::c23::C23Parser;
+
+/// Runs one compilation stage on a source file
+pub type StageRunner = Box<dyn Fn(&str) -> Result<(), String>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResults {
+    /// Total bytes of C source in the corpus
+    pub corpus_bytes: usize,
+    /// Number of files in the corpus
+    pub corpus_files: usize,
+    /// Parser throughput in MB/s (higher is better)
+    pub parse_mb_per_sec: f64,
+    /// Total IR generation time in milliseconds (lower is better)
+    pub ir_gen_ms: f64,
+    /// Median time from source to callable code in milliseconds (lower is better)
+    pub jit_latency_ms: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Regression {
+    pub metric: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    /// Relative slowdown, 0.10 == 10% worse
+    pub slowdown: f64,
+}
+
+pub struct FrontendBenchSuite {
+    // Fixed corpus of C sources
+    corpus: Vec<(PathBuf, String)>,
+
+    // Repetitions per measurement; the median is reported
+    repetitions: usize,
+
+    // Later pipeline stages
+    ir_gen: Option<StageRunner>,
+    jit: Option<StageRunner>,
+}
+
+impl FrontendBenchSuite {
+    /// Load every `.c` file under `corpus_dir`, sorted for stable ordering
+    pub fn load(corpus_dir: &Path, repetitions: usize) -> Result<Self, BenchError> {
+        let mut corpus = Vec::new();
+        for entry in fs::read_dir(corpus_dir).map_err(BenchError::Io)? {
+            let path = entry.map_err(BenchError::Io)?.path();
+            if path.extension().map_or(false, |ext| ext == "c") {
+                let source = fs::read_to_string(&path).map_err(BenchError::Io)?;
+                corpus.push((path, source));
+            }
+        }
+        corpus.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if corpus.is_empty() {
+            return Err(BenchError::EmptyCorpus(corpus_dir.to_path_buf()));
+        }
+
+        Ok(FrontendBenchSuite {
+            corpus,
+            repetitions: repetitions.max(1),
+            ir_gen: None,
+            jit: None,
+        })
+    }
+
+    pub fn with_ir_gen(mut self, runner: StageRunner) -> Self {
+        self.ir_gen = Some(runner);
+        self
+    }
+
+    pub fn with_jit(mut self, runner: StageRunner) -> Self {
+        self.jit = Some(runner);
+        self
+    }
+
+    pub fn run(&self) -> Result<BenchmarkResults, BenchError> {
+        let corpus_bytes: usize = self.corpus.iter().map(|(_, src)| src.len()).sum();
+
+        // Parser throughput over the whole corpus
+        let parse_time = self.median(|| {
+            for (path, source) in &self.corpus {
+                let mut parser = C23Parser::new();
+                parser.parse(source)
+                    .map_err(|e| BenchError::StageFailed(path.clone(), format!("{:?}", e)))?;
+            }
+            Ok(())
+        })?;
+
+        let ir_gen_time = match &self.ir_gen {
+            Some(runner) => self.median(|| self.run_stage(runner))?,
+            None => Duration::ZERO,
+        };
+
+        // JIT latency is per file, so report the median file
+        let jit_latency = match &self.jit {
+            Some(runner) => {
+                let mut per_file = Vec::new();
+                for (path, source) in &self.corpus {
+                    let start = Instant::now();
+                    runner(source).map_err(|e| BenchError::StageFailed(path.clone(), e))?;
+                    per_file.push(start.elapsed());
+                }
+                per_file.sort();
+                per_file[per_file.len() / 2]
+            }
+            None => Duration::ZERO,
+        };
+
+        Ok(BenchmarkResults {
+            corpus_bytes,
+            corpus_files: self.corpus.len(),
+            parse_mb_per_sec: corpus_bytes as f64 / 1_000_000.0 / parse_time.as_secs_f64().max(1e-9),
+            ir_gen_ms: ir_gen_time.as_secs_f64() * 1000.0,
+            jit_latency_ms: jit_latency.as_secs_f64() * 1000.0,
+        })
+    }
+
+    fn run_stage(&self, runner: &StageRunner) -> Result<(), BenchError> {
+        for (path, source) in &self.corpus {
+            runner(source).map_err(|e| BenchError::StageFailed(path.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    fn median(&self, mut f: impl FnMut() -> Result<(), BenchError>) -> Result<Duration, BenchError> {
+        let mut samples = Vec::with_capacity(self.repetitions);
+        for _ in 0..self.repetitions {
+            let start = Instant::now();
+            f()?;
+            samples.push(start.elapsed());
+        }
+        samples.sort();
+        Ok(samples[samples.len() / 2])
+    }
+}
+
+impl BenchmarkResults {
+    pub fn load_baseline(path: &Path) -> Result<Self, BenchError> {
+        let json = fs::read_to_string(path).map_err(BenchError::Io)?;
+        serde_json::from_str(&json).map_err(BenchError::Serialization)
+    }
+
+    pub fn save_baseline(&self, path: &Path) -> Result<(), BenchError> {
+        let json = serde_json::to_string_pretty(self).map_err(BenchError::Serialization)?;
+        fs::write(path, json).map_err(BenchError::Io)
+    }
+
+    /// Compare against a baseline; `threshold` is the allowed relative slowdown
+    pub fn regressions_against(&self, baseline: &BenchmarkResults, threshold: f64) -> Vec<Regression> {
+        let mut regressions = Vec::new();
+
+        // Throughput: lower is worse
+        if baseline.parse_mb_per_sec > 0.0 {
+            let slowdown = baseline.parse_mb_per_sec / self.parse_mb_per_sec.max(1e-9) - 1.0;
+            if slowdown > threshold {
+                regressions.push(Regression {
+                    metric: "parse_mb_per_sec",
+                    baseline: baseline.parse_mb_per_sec,
+                    current: self.parse_mb_per_sec,
+                    slowdown,
+                });
+            }
+        }
+
+        // Latencies: higher is worse
+        for (metric, base, current) in [
+            ("ir_gen_ms", baseline.ir_gen_ms, self.ir_gen_ms),
+            ("jit_latency_ms", baseline.jit_latency_ms, self.jit_latency_ms),
+        ] {
+            if base > 0.0 {
+                let slowdown = current / base - 1.0;
+                if slowdown > threshold {
+                    regressions.push(Regression { metric, baseline: base, current, slowdown });
+                }
+            }
+        }
+
+        regressions
+    }
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    Io(std::io::Error),
+    Serialization(serde_json::Error),
+    EmptyCorpus(PathBuf),
+    StageFailed(PathBuf, String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), BenchError> {
+    let suite = FrontendBenchSuite::load(Path::new("benches/corpus"), 5)?
+        .with_jit(Box::new(|src| unsafe {
+            compiler.jit_compile(src, &jit_options).map(|_| ()).map_err(|e| format!("{:?}", e))
+        }));
+
+    let results = suite.run()?;
+    let baseline = BenchmarkResults::load_baseline(Path::new("benches/baseline.json"))?;
+
+    for r in results.regressions_against(&baseline, 0.10) {
+        eprintln!("{} regressed by {:.1}%", r.metric, r.slowdown * 100.0);
+    }
+
+    Ok(())
+}
+*/
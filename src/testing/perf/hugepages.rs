@@ -0,0 +1,132 @@
+// src/testing/perf/hugepages.rs
+//! iTLB benchmark for huge-page backed JIT code
+//! Lays out many tiny functions one base page apart across a large code
+//! region (the shape of a big compiled program), calls them all repeatedly,
+//! and compares iTLB misses between 4K and huge-page backed regions.
+
+use crate::memory::hugepages::{self, HugePageMode, HugePageRegion, PageBacking, DEFAULT_HUGE_PAGE_THRESHOLD};
+use super::{HardwareEvent, PerfCounter, PerfError};
+
+const BASE_PAGE: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct HugePageBenchConfig {
+    /// Size of the synthetic code region
+    pub code_size: usize,
+    /// Number of passes over all functions
+    pub iterations: usize,
+}
+
+impl Default for HugePageBenchConfig {
+    fn default() -> Self {
+        HugePageBenchConfig {
+            code_size: 64 * 1024 * 1024,
+            iterations: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HugePageBenchResult {
+    pub mode: HugePageMode,
+    pub backing: PageBacking,
+    pub functions: usize,
+    pub itlb_misses: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HugePageComparison {
+    pub base_pages: HugePageBenchResult,
+    pub huge_pages: HugePageBenchResult,
+}
+
+impl HugePageComparison {
+    /// Fraction of iTLB misses removed by huge pages (0.0 - 1.0)
+    pub fn itlb_miss_reduction(&self) -> f64 {
+        if self.base_pages.itlb_misses == 0 {
+            return 0.0;
+        }
+        1.0 - self.huge_pages.itlb_misses as f64 / self.base_pages.itlb_misses as f64
+    }
+}
+
+/// Run the benchmark with base pages and with the requested huge-page mode
+pub fn compare(config: &HugePageBenchConfig, mode: HugePageMode) -> Result<HugePageComparison, PerfError> {
+    Ok(HugePageComparison {
+        base_pages: run(config, HugePageMode::Disabled)?,
+        huge_pages: run(config, mode)?,
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn run(config: &HugePageBenchConfig, mode: HugePageMode) -> Result<HugePageBenchResult, PerfError> {
+    unsafe {
+        let region = hugepages::map_anonymous(config.code_size, mode, DEFAULT_HUGE_PAGE_THRESHOLD)
+            .map_err(|e| PerfError::Setup(format!("{:?}", e)))?;
+        // Unmapped on every way out, including counters that fail to open
+        let mapping = Mapping(region);
+        let region = &mapping.0;
+
+        // One `ret` per base page
+        let functions = region.size / BASE_PAGE;
+        for i in 0..functions {
+            *region.ptr.add(i * BASE_PAGE) = 0xC3;
+        }
+
+        if libc::mprotect(region.ptr as *mut libc::c_void, region.size, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+            return Err(PerfError::Setup("mprotect failed".to_string()));
+        }
+
+        let itlb = PerfCounter::open(HardwareEvent::ITlbReadMisses)?;
+        let cycles = PerfCounter::open(HardwareEvent::CpuCycles)?;
+
+        itlb.start();
+        cycles.start();
+        for _ in 0..config.iterations {
+            for i in 0..functions {
+                let f: extern "C" fn() = std::mem::transmute(region.ptr.add(i * BASE_PAGE));
+                f();
+            }
+        }
+        let cycle_count = cycles.stop()?;
+        let miss_count = itlb.stop()?;
+
+        Ok(HugePageBenchResult {
+            mode,
+            backing: region.backing,
+            functions,
+            itlb_misses: miss_count,
+            cycles: cycle_count,
+        })
+    }
+}
+
+/// The benchmark's code region, unmapped when dropped
+#[cfg(target_arch = "x86_64")]
+struct Mapping(HugePageRegion);
+
+#[cfg(target_arch = "x86_64")]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.0.ptr as *mut libc::c_void, self.0.size);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn run(_config: &HugePageBenchConfig, _mode: HugePageMode) -> Result<HugePageBenchResult, PerfError> {
+    Err(PerfError::Setup("huge page benchmark is only implemented for x86_64".to_string()))
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), PerfError> {
+    let cmp = compare(&HugePageBenchConfig::default(), HugePageMode::Transparent)?;
+    println!("4K pages:   {} iTLB misses", cmp.base_pages.itlb_misses);
+    println!("huge pages: {} iTLB misses ({:?})", cmp.huge_pages.itlb_misses, cmp.huge_pages.backing);
+    println!("reduction:  {:.1}%", cmp.itlb_miss_reduction() * 100.0);
+    Ok(())
+}
+*/
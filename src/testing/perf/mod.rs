@@ -0,0 +1,129 @@
+// src/testing/perf/mod.rs
+//! Performance benchmarks and hardware counter access
+
+pub mod dispatch;
+pub mod frontend;
+pub mod hugepages;
+
+/// Hardware events that benchmarks can count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareEvent {
+    Instructions,
+    CpuCycles,
+    ITlbReadMisses,
+    DTlbReadMisses,
+}
+
+// Values from <linux/perf_event.h>
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_HW_CACHE: u32 = 3;
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_DTLB: u64 = 3;
+const PERF_COUNT_HW_CACHE_ITLB: u64 = 4;
+const PERF_COUNT_HW_CACHE_OP_READ: u64 = 0;
+const PERF_COUNT_HW_CACHE_RESULT_MISS: u64 = 1;
+const PERF_EVENT_IOC_ENABLE: u64 = 0x2400;
+const PERF_EVENT_IOC_DISABLE: u64 = 0x2401;
+const PERF_EVENT_IOC_RESET: u64 = 0x2403;
+
+/// First published perf_event_attr layout (PERF_ATTR_SIZE_VER0)
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+const FLAG_DISABLED: u64 = 1 << 0;
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+/// A user-space-only hardware counter for the calling thread
+pub struct PerfCounter {
+    fd: i32,
+}
+
+impl PerfCounter {
+    /// Open a counter. Fails when perf events are unavailable (containers,
+    /// perf_event_paranoid, or unsupported hardware).
+    pub fn open(event: HardwareEvent) -> Result<Self, PerfError> {
+        let (type_, config) = match event {
+            HardwareEvent::Instructions => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS),
+            HardwareEvent::CpuCycles => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+            HardwareEvent::ITlbReadMisses => (PERF_TYPE_HW_CACHE, cache_config(PERF_COUNT_HW_CACHE_ITLB)),
+            HardwareEvent::DTlbReadMisses => (PERF_TYPE_HW_CACHE, cache_config(PERF_COUNT_HW_CACHE_DTLB)),
+        };
+
+        let attr = PerfEventAttr {
+            type_,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config,
+            flags: FLAG_DISABLED | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+            ..Default::default()
+        };
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                0 as libc::pid_t,  // this thread
+                -1 as libc::c_int, // any CPU
+                -1 as libc::c_int, // no group
+                0 as libc::c_ulong,
+            )
+        };
+
+        if fd < 0 {
+            return Err(PerfError::Unavailable(std::io::Error::last_os_error()));
+        }
+
+        Ok(PerfCounter { fd: fd as i32 })
+    }
+
+    pub fn start(&self) {
+        unsafe {
+            libc::ioctl(self.fd, PERF_EVENT_IOC_RESET as _, 0);
+            libc::ioctl(self.fd, PERF_EVENT_IOC_ENABLE as _, 0);
+        }
+    }
+
+    pub fn stop(&self) -> Result<u64, PerfError> {
+        let mut value: u64 = 0;
+        unsafe {
+            libc::ioctl(self.fd, PERF_EVENT_IOC_DISABLE as _, 0);
+            let read = libc::read(self.fd, &mut value as *mut u64 as *mut libc::c_void, 8);
+            if read != 8 {
+                return Err(PerfError::ReadFailed);
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl Drop for PerfCounter {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn cache_config(cache: u64) -> u64 {
+    cache | (PERF_COUNT_HW_CACHE_OP_READ << 8) | (PERF_COUNT_HW_CACHE_RESULT_MISS << 16)
+}
+
+#[derive(Debug)]
+pub enum PerfError {
+    Unavailable(std::io::Error),
+    ReadFailed,
+    Setup(String),
+}
@@ -0,0 +1,381 @@
+// src/testing/programs.rs
+//! Runner for `c-interpreter test`
+//! Each `.c` file is a test program. It passes when `main` returns the
+//! expected status: 0 unless the file contains a `// expect-exit: N` line.
+//!
+//! A file with `// CHECK:` lines is a codegen test instead: its IR or
+//! assembly must match them (see `filecheck`). It is also run if it has an
+//! `// expect-exit:` line.
+//!
+//! `run_parallel` runs each test in a forked child, several at once. What
+//! the tests print goes through an `OutputMultiplexer`, whole lines tagged
+//! with the test's path, and each child sends its outcome back over a pipe.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::compiler::EmitStage;
+use crate::runtime::exit_status::signal_name;
+use crate::runtime::output_mux::{OutputMultiplexer, OutputStream};
+use super::filecheck::{self, CheckFile};
+
+/// Compiles and runs a source file, returning `main`'s result
+pub type ProgramRunner<'a> = &'a mut dyn FnMut(&str) -> Result<i32, String>;
+
+/// Compiles a source file to IR or assembly text
+pub type EmitRunner<'a> = &'a mut dyn FnMut(&str, EmitStage) -> Result<String, String>;
+
+const CHECK_PREFIX: &str = "CHECK";
+
+#[derive(Debug, Clone)]
+pub struct ProgramTest {
+    pub path: PathBuf,
+    pub expected_exit: i32,
+    /// Output the `CHECK` lines match, for codegen tests
+    pub emit: Option<EmitStage>,
+    /// False for codegen tests without `// expect-exit:`
+    pub execute: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProgramOutcome {
+    Passed,
+    WrongExit { expected: i32, actual: i32 },
+    /// Emitted code didn't match the `CHECK` lines
+    CheckFailed(String),
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgramResult {
+    pub test: ProgramTest,
+    pub outcome: ProgramOutcome,
+    pub elapsed: Duration,
+}
+
+impl ProgramResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, ProgramOutcome::Passed)
+    }
+}
+
+/// Collect test programs from files and directories (recursively), sorted
+pub fn discover(paths: &[PathBuf]) -> Result<Vec<ProgramTest>, ProgramTestError> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect(path, &mut files)?;
+    }
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|path| {
+            let source = fs::read_to_string(&path).map_err(|e| ProgramTestError::Io(path.clone(), e))?;
+            let emit = if filecheck::has_directives(&source, CHECK_PREFIX) {
+                let stage = header(&source, "// check-emit:").unwrap_or("ir");
+                let stage = stage.parse().map_err(|e| ProgramTestError::Header(path.clone(), e))?;
+                Some(stage)
+            } else {
+                None
+            };
+            Ok(ProgramTest {
+                expected_exit: expected_exit(&source),
+                execute: emit.is_none() || header(&source, "// expect-exit:").is_some(),
+                emit,
+                path,
+            })
+        })
+        .collect()
+}
+
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), ProgramTestError> {
+    if path.is_dir() {
+        let entries = fs::read_dir(path).map_err(|e| ProgramTestError::Io(path.to_path_buf(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| ProgramTestError::Io(path.to_path_buf(), e))?;
+            collect(&entry.path(), files)?;
+        }
+    } else if path.extension().map_or(false, |ext| ext == "c") {
+        files.push(path.to_path_buf());
+    } else if !path.exists() {
+        return Err(ProgramTestError::NotFound(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+fn expected_exit(source: &str) -> i32 {
+    header(source, "// expect-exit:")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Value of the first `// name: value` line
+fn header<'a>(source: &'a str, name: &str) -> Option<&'a str> {
+    source
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(name))
+        .map(str::trim)
+}
+
+/// Run every test, printing one line per test and a summary
+pub fn run_all(tests: &[ProgramTest], runner: ProgramRunner, emitter: EmitRunner) -> Vec<ProgramResult> {
+    let mut results = Vec::with_capacity(tests.len());
+
+    for test in tests {
+        let start = Instant::now();
+        let outcome = match fs::read_to_string(&test.path) {
+            Ok(source) => run_one(test, &source, runner, emitter),
+            Err(e) => ProgramOutcome::Error(e.to_string()),
+        };
+
+        let result = ProgramResult {
+            test: test.clone(),
+            outcome,
+            elapsed: start.elapsed(),
+        };
+        print_result(&result);
+        results.push(result);
+    }
+
+    print_summary(&results);
+    results
+}
+
+/// `run_all` with up to `jobs` tests at once, each in a forked child whose
+/// output is echoed through `mux`. Results are printed as tests finish and
+/// returned in `tests` order.
+pub fn run_parallel(
+    tests: &[ProgramTest],
+    jobs: usize,
+    mux: &OutputMultiplexer,
+    runner: ProgramRunner,
+    emitter: EmitRunner,
+) -> Vec<ProgramResult> {
+    let mut results: Vec<Option<ProgramResult>> = vec![None; tests.len()];
+    let mut queue = tests.iter().enumerate();
+    let mut running: HashMap<libc::pid_t, ForkedTest> = HashMap::new();
+
+    loop {
+        while running.len() < jobs.max(1) {
+            let Some((index, test)) = queue.next() else { break };
+            match ForkedTest::spawn(index, test, mux, &mut *runner, &mut *emitter) {
+                Ok((pid, child)) => {
+                    running.insert(pid, child);
+                }
+                Err(e) => {
+                    let result = ProgramResult { test: test.clone(), outcome: ProgramOutcome::Error(e.to_string()), elapsed: Duration::ZERO };
+                    print_result(&result);
+                    results[index] = Some(result);
+                }
+            }
+        }
+        if running.is_empty() {
+            break;
+        }
+
+        let mut status = 0;
+        let pid = unsafe { libc::waitpid(-1, &mut status, 0) };
+        if pid < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+        let Some(child) = running.remove(&pid) else { continue };
+        let index = child.index;
+        let result = child.finish(&tests[index], status);
+        print_result(&result);
+        results[index] = Some(result);
+    }
+
+    let results: Vec<ProgramResult> = results.into_iter().flatten().collect();
+    print_summary(&results);
+    results
+}
+
+fn print_result(result: &ProgramResult) {
+    let path = result.test.path.display();
+    match &result.outcome {
+        ProgramOutcome::Passed => println!("PASS {} ({:?})", path, result.elapsed),
+        ProgramOutcome::WrongExit { expected, actual } => println!("FAIL {}: expected exit {}, got {}", path, expected, actual),
+        ProgramOutcome::CheckFailed(e) => println!("FAIL {}: {}", path, e),
+        ProgramOutcome::Error(e) => println!("FAIL {}: {}", path, e),
+    }
+}
+
+fn print_summary(results: &[ProgramResult]) {
+    let passed = results.iter().filter(|r| r.passed()).count();
+    println!("\n{} passed, {} failed", passed, results.len() - passed);
+}
+
+/// A test running in a child of `run_parallel`
+struct ForkedTest {
+    index: usize,
+    started: Instant,
+    /// Echo threads for its stdout and stderr
+    output: [JoinHandle<()>; 2],
+    /// Reads the encoded outcome
+    outcome: JoinHandle<Vec<u8>>,
+}
+
+impl ForkedTest {
+    fn spawn(
+        index: usize,
+        test: &ProgramTest,
+        mux: &OutputMultiplexer,
+        runner: ProgramRunner,
+        emitter: EmitRunner,
+    ) -> io::Result<(libc::pid_t, Self)> {
+        let source = fs::read_to_string(&test.path)?;
+        let (stdout_read, stdout_write) = pipe()?;
+        let (stderr_read, stderr_write) = pipe()?;
+        let (outcome_read, outcome_write) = pipe()?;
+
+        // Buffered output would otherwise be written again by the child
+        unsafe { libc::fflush(std::ptr::null_mut()) };
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if pid == 0 {
+            unsafe {
+                libc::dup2(stdout_write.as_raw_fd(), 1);
+                libc::dup2(stderr_write.as_raw_fd(), 2);
+            }
+            let outcome = run_one(test, &source, runner, emitter).encode();
+            unsafe {
+                libc::fflush(std::ptr::null_mut());
+                libc::write(outcome_write.as_raw_fd(), outcome.as_ptr() as *const libc::c_void, outcome.len());
+                libc::_exit(0)
+            }
+        }
+
+        // Only the child may hold the write ends, or the readers never see
+        // the end of the pipes; later children would inherit them too
+        drop((stdout_write, stderr_write, outcome_write));
+        let name = test.path.display().to_string();
+        let output = [
+            mux.forward(&name, OutputStream::Stdout, File::from(stdout_read)),
+            mux.forward(&name, OutputStream::Stderr, File::from(stderr_read)),
+        ];
+        let outcome = std::thread::spawn(move || {
+            let mut data = Vec::new();
+            let _ = File::from(outcome_read).read_to_end(&mut data);
+            data
+        });
+        Ok((pid, ForkedTest { index, started: Instant::now(), output, outcome }))
+    }
+
+    /// The result once the child exited with wait `status`
+    fn finish(self, test: &ProgramTest, status: i32) -> ProgramResult {
+        let elapsed = self.started.elapsed();
+        for thread in self.output {
+            let _ = thread.join();
+        }
+        let data = self.outcome.join().unwrap_or_default();
+        let outcome = if libc::WIFSIGNALED(status) {
+            ProgramOutcome::Error(format!("killed by {}", signal_name(libc::WTERMSIG(status))))
+        } else {
+            ProgramOutcome::decode(&data)
+                .unwrap_or_else(|| ProgramOutcome::Error(format!("exited with status {} before reporting", libc::WEXITSTATUS(status))))
+        };
+        ProgramResult { test: test.clone(), outcome, elapsed }
+    }
+}
+
+impl ProgramOutcome {
+    /// A tag byte, then the exit statuses or the message
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            ProgramOutcome::Passed => vec![0],
+            ProgramOutcome::WrongExit { expected, actual } => {
+                let mut data = vec![1];
+                data.extend_from_slice(&expected.to_le_bytes());
+                data.extend_from_slice(&actual.to_le_bytes());
+                data
+            }
+            ProgramOutcome::CheckFailed(message) => [&[2], message.as_bytes()].concat(),
+            ProgramOutcome::Error(message) => [&[3], message.as_bytes()].concat(),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (&tag, rest) = data.split_first()?;
+        let message = || String::from_utf8_lossy(rest).into_owned();
+        match tag {
+            0 => Some(ProgramOutcome::Passed),
+            1 if rest.len() == 8 => Some(ProgramOutcome::WrongExit {
+                expected: i32::from_le_bytes(rest[..4].try_into().ok()?),
+                actual: i32::from_le_bytes(rest[4..].try_into().ok()?),
+            }),
+            2 => Some(ProgramOutcome::CheckFailed(message())),
+            3 => Some(ProgramOutcome::Error(message())),
+            _ => None,
+        }
+    }
+}
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn run_one(test: &ProgramTest, source: &str, runner: ProgramRunner, emitter: EmitRunner) -> ProgramOutcome {
+    if let Some(stage) = test.emit {
+        let checks = match CheckFile::parse(source, CHECK_PREFIX) {
+            Ok(checks) => checks,
+            Err(e) => return ProgramOutcome::Error(e.to_string()),
+        };
+        let output = match emitter(source, stage) {
+            Ok(output) => output,
+            Err(e) => return ProgramOutcome::Error(e),
+        };
+        if let Err(e) = checks.run(&output) {
+            // The directive's line in the test file, and what went wrong
+            return ProgramOutcome::CheckFailed(e.to_string());
+        }
+    }
+    if !test.execute {
+        return ProgramOutcome::Passed;
+    }
+    match runner(source) {
+        Ok(actual) if actual == test.expected_exit => ProgramOutcome::Passed,
+        Ok(actual) => ProgramOutcome::WrongExit {
+            expected: test.expected_exit,
+            actual,
+        },
+        Err(e) => ProgramOutcome::Error(e),
+    }
+}
+
+#[derive(Debug)]
+pub enum ProgramTestError {
+    Io(PathBuf, std::io::Error),
+    NotFound(PathBuf),
+    /// A `// check-emit:` value that isn't a stage
+    Header(PathBuf, String),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), ProgramTestError> {
+    let tests = discover(&[PathBuf::from("tests")])?;
+    // [tests/hello.c] hello, world
+    let mux = OutputMultiplexer::new(PrefixConfig::default());
+    let results = run_parallel(
+        &tests,
+        8,
+        &mux,
+        &mut |source| jit_eval(source, 2, "x86_64"),
+        &mut |source, stage| emit(source, stage, 2, "x86_64"),
+    );
+    std::process::exit(if results.iter().all(|r| r.passed()) { 0 } else { 1 });
+}
+*/
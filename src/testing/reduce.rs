@@ -0,0 +1,451 @@
+// src/testing/reduce.rs
+//! Test-case reduction for `c-interpreter reduce`
+//! Shrinks a C file while it stays "interesting": still crashes the
+//! compiler the same way, or still makes two execution modes disagree.
+//! Like C-Reduce, the reducer doesn't need to know why the file is
+//! interesting. It only asks a test, and keeps every change the test still
+//! accepts.
+//!
+//! Changes are made on the syntax tree and applied through
+//! `syntax::Rewriter`, so they are whole declarations, statements or
+//! expressions. A candidate that no longer parses is thrown away without
+//! running the test. Each pass lists its possible changes in source order
+//! and tries them in chunks, halving the chunk size down to one change at a
+//! time (as ddmin does). The passes repeat until a whole round changes
+//! nothing.
+//!
+//! Passes:
+//!  - drop top-level declarations and function definitions
+//!  - drop statements and local declarations
+//!  - replace `if`, loops and labels with one of their sub-statements
+//!  - replace expressions with `0` or with one of their operands
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use crate::runtime::exit_status::signal_name;
+use crate::syntax::{self, walk_block, walk_expression, walk_statement, Block, BlockItem, Expression, ExpressionKind};
+use crate::syntax::{ExternalDeclaration, Rewriter, Span, Statement, StatementKind, SyntaxError, Visit};
+
+/// Decides whether a candidate source is still interesting
+pub type Interesting<'a> = &'a mut dyn FnMut(&str) -> bool;
+
+/// How often a running test is checked on
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    TopLevel,
+    Statements,
+    Hoist,
+    Expressions,
+}
+
+const PASSES: [Pass; 4] = [Pass::TopLevel, Pass::Statements, Pass::Hoist, Pass::Expressions];
+
+/// Replace the text at `span` with `text`
+#[derive(Debug, Clone)]
+struct Change {
+    span: Span,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reduction {
+    pub source: String,
+    pub original_len: usize,
+    /// How many times the test ran
+    pub tests: usize,
+}
+
+/// Reduce `source`, which `interesting` must accept. `progress` is given
+/// each smaller interesting source as it is found, so an interrupted run
+/// still leaves its best result behind.
+pub fn reduce(
+    source: &str,
+    file: &str,
+    interesting: Interesting<'_>,
+    progress: &mut dyn FnMut(&str),
+) -> Result<Reduction, ReduceError> {
+    syntax::parse(source, file)?;
+    if !interesting(source) {
+        return Err(ReduceError::NotInteresting);
+    }
+
+    let mut current = source.to_string();
+    let mut tests = 1;
+    loop {
+        let before = current.len();
+        for pass in PASSES {
+            run_pass(pass, &mut current, file, interesting, progress, &mut tests);
+        }
+        if current.len() >= before {
+            break;
+        }
+    }
+
+    // Removals leave blank lines behind
+    let tidy = collapse_blank_lines(&current);
+    if tidy.len() < current.len() && syntax::parse(&tidy, file).is_ok() {
+        tests += 1;
+        if interesting(&tidy) {
+            current = tidy;
+            progress(&current);
+        }
+    }
+    Ok(Reduction { source: current, original_len: source.len(), tests })
+}
+
+/// Try `pass`'s changes in halving chunks, keeping those the test accepts
+fn run_pass(
+    pass: Pass,
+    current: &mut String,
+    file: &str,
+    interesting: Interesting<'_>,
+    progress: &mut dyn FnMut(&str),
+    tests: &mut usize,
+) {
+    let Ok(unit) = syntax::parse(current, file) else { return };
+    let mut size = changes(pass, current, &unit).len();
+    while size >= 1 {
+        let mut start = 0;
+        loop {
+            // Earlier successes moved the text, so list the changes afresh
+            let Ok(unit) = syntax::parse(current, file) else { return };
+            let changes = changes(pass, current, &unit);
+            if start >= changes.len() {
+                break;
+            }
+            let chunk = &changes[start..(start + size).min(changes.len())];
+            let accepted = match apply(current, chunk) {
+                Some(candidate) if candidate.len() < current.len() && syntax::parse(&candidate, file).is_ok() => {
+                    *tests += 1;
+                    if interesting(&candidate) {
+                        *current = candidate;
+                        progress(current);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
+            };
+            // An accepted chunk is gone, so the next one starts here
+            if !accepted {
+                start += size;
+            }
+        }
+        size /= 2;
+    }
+}
+
+/// Trailing whitespace and runs of blank lines removed
+fn collapse_blank_lines(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut blank = true;
+    for line in source.lines().map(str::trim_end) {
+        if line.is_empty() && blank {
+            continue;
+        }
+        blank = line.is_empty();
+        out.push_str(line);
+        out.push('\n');
+    }
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    out
+}
+
+/// `source` with `changes` made, skipping any inside an earlier one
+fn apply(source: &str, changes: &[Change]) -> Option<String> {
+    let mut rewriter = Rewriter::new(source);
+    let mut end = 0;
+    let mut sorted: Vec<&Change> = changes.iter().collect();
+    sorted.sort_by_key(|change| change.span.start);
+    for change in sorted {
+        if change.span.start < end {
+            continue;
+        }
+        end = change.span.end;
+        rewriter.replace(change.span, change.text.clone());
+    }
+    rewriter.finish().ok()
+}
+
+fn changes(pass: Pass, source: &str, unit: &syntax::TranslationUnit) -> Vec<Change> {
+    match pass {
+        Pass::TopLevel => unit
+            .items
+            .iter()
+            .map(|item| match item {
+                ExternalDeclaration::Function(function) => function.span,
+                ExternalDeclaration::Declaration(declaration) => declaration.span,
+            })
+            .map(|span| Change { span, text: String::new() })
+            .collect(),
+        _ => {
+            let mut collector = Collector { pass, source, changes: Vec::new() };
+            collector.visit_translation_unit(unit);
+            collector.changes
+        }
+    }
+}
+
+/// Lists the changes of the passes that work inside functions
+struct Collector<'src> {
+    pass: Pass,
+    source: &'src str,
+    changes: Vec<Change>,
+}
+
+impl Collector<'_> {
+    fn replace_with(&mut self, span: Span, inner: Span) {
+        if let Some(text) = self.source.get(inner.start..inner.end) {
+            self.changes.push(Change { span, text: text.to_string() });
+        }
+    }
+}
+
+impl Visit for Collector<'_> {
+    fn visit_block(&mut self, block: &Block) {
+        if self.pass == Pass::Statements {
+            for item in &block.items {
+                let span = match item {
+                    BlockItem::Declaration(declaration) => declaration.span,
+                    BlockItem::Statement(statement) => statement.span,
+                };
+                self.changes.push(Change { span, text: String::new() });
+            }
+        }
+        walk_block(self, block);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        if self.pass == Pass::Hoist {
+            match &statement.kind {
+                StatementKind::If { then, otherwise, .. } => {
+                    self.replace_with(statement.span, then.span);
+                    if let Some(otherwise) = otherwise {
+                        self.replace_with(statement.span, otherwise.span);
+                    }
+                }
+                StatementKind::While { body, .. }
+                | StatementKind::DoWhile { body, .. }
+                | StatementKind::For { body, .. }
+                | StatementKind::Labeled { body, .. } => self.replace_with(statement.span, body.span),
+                _ => {}
+            }
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if self.pass == Pass::Expressions {
+            // Literals and names are as small as it gets
+            let operands = match &expression.kind {
+                ExpressionKind::Integer { .. }
+                | ExpressionKind::Float { .. }
+                | ExpressionKind::Character(_)
+                | ExpressionKind::String(_)
+                | ExpressionKind::Identifier(_) => None,
+                ExpressionKind::Binary { left, right, .. } | ExpressionKind::Comma(left, right) => {
+                    Some(vec![left.span, right.span])
+                }
+                ExpressionKind::Assign { value, .. } => Some(vec![value.span]),
+                ExpressionKind::Conditional { then, otherwise, .. } => Some(vec![then.span, otherwise.span]),
+                ExpressionKind::Unary { operand, .. } | ExpressionKind::Cast { operand, .. } => Some(vec![operand.span]),
+                _ => Some(Vec::new()),
+            };
+            if let Some(operands) = operands {
+                self.changes.push(Change { span: expression.span, text: "0".to_string() });
+                for operand in operands {
+                    self.replace_with(expression.span, operand);
+                }
+            }
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/// How a test command ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Exited(i32),
+    Signaled(i32),
+    TimedOut,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Exited(code) => write!(f, "exit status {}", code),
+            Outcome::Signaled(signal) => write!(f, "signal {}", signal_name(*signal)),
+            Outcome::TimedOut => write!(f, "timeout"),
+        }
+    }
+}
+
+impl Outcome {
+    /// Anything but a clean exit, hangs included
+    pub fn failed(self) -> bool {
+        self != Outcome::Exited(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub outcome: Outcome,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Run {
+    /// Whether `text` appears in either output
+    pub fn printed(&self, text: &str) -> bool {
+        let text = text.as_bytes();
+        text.is_empty() || [&self.stdout, &self.stderr].iter().any(|output| output.windows(text.len()).any(|w| w == text))
+    }
+}
+
+/// Runs a shell command on each candidate, written to a file named like
+/// the original in a private directory. `{}` in the command stands for
+/// that file; without it the file is the last argument.
+pub struct CommandTest {
+    command: String,
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl CommandTest {
+    pub fn new(command: &str, file_name: &str, timeout: Duration) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "c-interpreter-reduce-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        let name = Path::new(file_name).file_name().map_or("reduce.c".into(), |name| name.to_owned());
+        let path = dir.join(name);
+        let quoted = shell_quote(&path.to_string_lossy());
+        let command = if command.contains("{}") {
+            command.replace("{}", &quoted)
+        } else {
+            format!("{} {}", command, quoted)
+        };
+        Ok(CommandTest { command, path, timeout })
+    }
+
+    pub fn run(&self, source: &str) -> io::Result<Run> {
+        fs::write(&self.path, source)?;
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(self.path.parent().unwrap_or(Path::new(".")))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Its own group, so a timeout kills what the shell started too
+            .process_group(0)
+            .spawn()?;
+
+        // Drained on threads so a chatty command can't fill a pipe and stall
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            std::thread::spawn(move || {
+                let mut buffer = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buffer);
+                }
+                buffer
+            })
+        };
+        let stdout = drain(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+        let stderr = drain(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+        let deadline = Instant::now() + self.timeout;
+        let outcome = loop {
+            if let Some(status) = child.try_wait()? {
+                break match (status.code(), status.signal()) {
+                    (Some(code), _) => Outcome::Exited(code),
+                    (None, Some(signal)) => Outcome::Signaled(signal),
+                    (None, None) => Outcome::Exited(-1),
+                };
+            }
+            if Instant::now() >= deadline {
+                unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+                child.wait()?;
+                break Outcome::TimedOut;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        Ok(Run {
+            outcome,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+impl Drop for CommandTest {
+    fn drop(&mut self) {
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// `text` as one word for `sh`
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[derive(Debug)]
+pub enum ReduceError {
+    /// The original doesn't parse, so there is no tree to reduce
+    Syntax(SyntaxError),
+    /// The test rejects the original
+    NotInteresting,
+    Io(io::Error),
+}
+
+impl From<SyntaxError> for ReduceError {
+    fn from(e: SyntaxError) -> Self {
+        ReduceError::Syntax(e)
+    }
+}
+
+impl From<io::Error> for ReduceError {
+    fn from(e: io::Error) -> Self {
+        ReduceError::Io(e)
+    }
+}
+
+impl fmt::Display for ReduceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReduceError::Syntax(e) => write!(f, "{}", e),
+            ReduceError::NotInteresting => write!(f, "the test doesn't accept the original file, so there is nothing to preserve"),
+            ReduceError::Io(e) => write!(f, "running the test: {}", e),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn minimize_crash(source: &str) -> Result<String, ReduceError> {
+    let test = CommandTest::new("c-interpreter compile -O3 {}", "crash.c", Duration::from_secs(10))?;
+    let original = test.run(source)?.outcome;
+    let mut interesting = |candidate: &str| test.run(candidate).map_or(false, |run| run.outcome == original);
+    let reduction = reduce(source, "crash.c", &mut interesting, &mut |best| {
+        let _ = fs::write("crash.reduced.c", best);
+    })?;
+    println!("{} -> {} bytes in {} tests", reduction.original_len, reduction.source.len(), reduction.tests);
+    Ok(reduction.source)
+}
+*/
@@ -0,0 +1,257 @@
+// src/types/inference.rs
+//! Type rules for C23 `_Generic` and `auto`
+//! Both are settled at compile time from the type of an operand the
+//! compiler has already typed; code generation only ever sees the result.
+//! `_Generic` lowers to the selected association's expression alone (the
+//! controlling expression is never evaluated), and `auto` declarations
+//! lower exactly like ones spelled with the deduced type.
+//!
+//! The rules are written once over `InferenceType`, which the frontend's
+//! `CType` and the bytecode compiler's own types implement. `typeof` needs
+//! none: it denotes the operand's type as the resolver `sizeof` uses finds
+//! it (`types::typeof`), without qualifiers for `typeof_unqual`.
+//!
+//! Types passed in must have their typedefs resolved.
+
+use std::fmt;
+use crate::frontend::types::{CType, Qualifiers};
+use super::vla::is_variably_modified;
+
+/// What the rules need to know about a type
+pub trait InferenceType: Clone + fmt::Debug {
+    /// Top-level qualifiers, `_Atomic` included
+    fn qualifiers(&self) -> Qualifiers;
+
+    /// Without top-level qualifiers
+    fn unqualified(&self) -> Self;
+
+    /// With `extra` qualifiers added at the top
+    fn qualify(self, extra: Qualifiers) -> Self;
+
+    /// The pointer an array or function converts to; `None` for other types
+    fn decay(&self) -> Option<Self>;
+
+    /// Type compatibility (C23 6.2.7)
+    fn compatible(&self, other: &Self) -> bool;
+
+    fn is_void(&self) -> bool;
+}
+
+/// Lvalue conversion plus array-to-pointer and function-to-pointer decay:
+/// the type an expression has when its value is used
+pub fn value_type<T: InferenceType>(ty: &T) -> T {
+    ty.decay().unwrap_or_else(|| ty.unqualified())
+}
+
+impl InferenceType for CType {
+    /// Qualifiers on an array's elements count as the array's own (C23 6.7.3)
+    fn qualifiers(&self) -> Qualifiers {
+        match self {
+            CType::Qualified(q, inner) => *q | inner.qualifiers(),
+            CType::Array(element, _) | CType::VariableArray(element, _) => element.qualifiers(),
+            _ => Qualifiers::empty(),
+        }
+    }
+
+    fn unqualified(&self) -> CType {
+        match self {
+            CType::Qualified(_, inner) => inner.unqualified(),
+            CType::Array(element, len) => CType::Array(Box::new(element.unqualified()), *len),
+            CType::VariableArray(element, bound) => CType::VariableArray(Box::new(element.unqualified()), *bound),
+            other => other.clone(),
+        }
+    }
+
+    fn qualify(self, extra: Qualifiers) -> CType {
+        if extra.is_empty() {
+            return self;
+        }
+        match self {
+            CType::Qualified(q, inner) => CType::Qualified(q | extra, inner),
+            other => CType::Qualified(extra, Box::new(other)),
+        }
+    }
+
+    fn decay(&self) -> Option<CType> {
+        match strip_top(self) {
+            // The elements keep their qualifiers: `const int[3]` decays to `const int *`
+            CType::Array(element, _) | CType::VariableArray(element, _) => {
+                Some(CType::Pointer(Box::new((**element).clone().qualify(self.qualifiers()))))
+            }
+            function @ CType::Function(_) => Some(CType::Pointer(Box::new(function.clone()))),
+            _ => None,
+        }
+    }
+
+    fn compatible(&self, other: &CType) -> bool {
+        if self.qualifiers() != other.qualifiers() {
+            return false;
+        }
+        match (strip_top(self), strip_top(other)) {
+            (CType::Pointer(x), CType::Pointer(y)) => x.compatible(y),
+            // An array of unknown size is compatible with any size
+            (CType::Array(x, n), CType::Array(y, m)) => {
+                x.compatible(y) && (n.is_none() || m.is_none() || n == m)
+            }
+            // A VLA is compatible with any array; differing lengths at run time are UB
+            (CType::VariableArray(x, _), CType::Array(y, _) | CType::VariableArray(y, _))
+            | (CType::Array(x, _), CType::VariableArray(y, _)) => x.compatible(y),
+            (x, y) => x == y,
+        }
+    }
+
+    fn is_void(&self) -> bool {
+        matches!(strip_top(self), CType::Void)
+    }
+}
+
+fn strip_top(ty: &CType) -> &CType {
+    match ty {
+        CType::Qualified(_, inner) => strip_top(inner),
+        other => other,
+    }
+}
+
+/// What a `_Generic` association may name: a complete object type that
+/// isn't variably modified. Structs count as complete here; pass a check
+/// that knows their definitions where it matters.
+pub fn is_complete_object(ty: &CType) -> bool {
+    !matches!(
+        strip_top(ty),
+        CType::Void | CType::Function(_) | CType::Array(_, None)
+    ) && !is_variably_modified(ty)
+}
+
+/// The controlling operand of a `_Generic` selection
+#[derive(Debug, Clone)]
+pub enum GenericControl<T> {
+    /// `_Generic(expr, ...)`: matched after lvalue conversion, so
+    /// qualifiers are dropped and arrays decay
+    Expression(T),
+    /// `_Generic(type-name, ...)`: matched as written
+    TypeName(T),
+}
+
+/// Index of the association `control` selects. `associations` lists the
+/// association types in source order, with `None` for `default`;
+/// `complete_object` says which types the expression form may name.
+pub fn select_generic<T: InferenceType>(
+    control: &GenericControl<T>,
+    associations: &[Option<T>],
+    complete_object: impl Fn(&T) -> bool,
+) -> Result<usize, TypeError> {
+    let (controlling, by_type_name) = match control {
+        GenericControl::Expression(ty) => (value_type(ty), false),
+        GenericControl::TypeName(ty) => (ty.clone(), true),
+    };
+
+    let mut default = None;
+    let mut selected = None;
+    for (index, association) in associations.iter().enumerate() {
+        let ty = match association {
+            None => {
+                if default.is_some() {
+                    return Err(TypeError::DuplicateGenericDefault);
+                }
+                default = Some(index);
+                continue;
+            }
+            Some(ty) => ty,
+        };
+
+        // Only the type-name form can name incomplete or function types
+        if !by_type_name && !complete_object(ty) {
+            return Err(TypeError::InvalidGenericAssociation(index, format!("{:?}", ty)));
+        }
+        if let Some(earlier) = associations[..index]
+            .iter()
+            .position(|other| other.as_ref().map_or(false, |other| other.compatible(ty)))
+        {
+            return Err(TypeError::DuplicateGenericAssociation(earlier, index));
+        }
+        if controlling.compatible(ty) {
+            selected = Some(index);
+        }
+    }
+
+    selected
+        .or(default)
+        .ok_or_else(|| TypeError::NoGenericMatch(format!("{:?}", controlling)))
+}
+
+/// One declarator of an `auto` object declaration
+#[derive(Debug, Clone)]
+pub struct AutoDeclarator<T> {
+    /// Type of the initializer expression, braces removed
+    pub initializer: Option<T>,
+    /// False for `auto *p = ...`, `auto a[] = ...` and the like
+    pub plain_identifier: bool,
+    /// Qualifiers written next to `auto`, e.g. `const auto x = ...`
+    pub qualifiers: Qualifiers,
+}
+
+/// The inferred type of an `auto` object (C23 6.7.10): the initializer's
+/// type after lvalue conversion and decay, unqualified and non-atomic, with
+/// the declaration's own qualifiers applied on top
+pub fn deduce_auto<T: InferenceType>(declarator: &AutoDeclarator<T>) -> Result<T, TypeError> {
+    if !declarator.plain_identifier {
+        return Err(TypeError::AutoDeclarator);
+    }
+    let initializer = declarator
+        .initializer
+        .as_ref()
+        .ok_or(TypeError::AutoWithoutInitializer)?;
+    if initializer.is_void() {
+        return Err(TypeError::AutoVoid);
+    }
+    Ok(value_type(initializer).qualify(declarator.qualifiers))
+}
+
+#[derive(Debug)]
+pub enum TypeError {
+    DuplicateGenericDefault,
+    /// Indices of two associations with compatible types
+    DuplicateGenericAssociation(usize, usize),
+    InvalidGenericAssociation(usize, String),
+    NoGenericMatch(String),
+    AutoWithoutInitializer,
+    AutoDeclarator,
+    AutoVoid,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::DuplicateGenericDefault => write!(f, "_Generic has more than one default association"),
+            TypeError::DuplicateGenericAssociation(earlier, index) => {
+                write!(f, "_Generic associations {} and {} have compatible types", earlier + 1, index + 1)
+            }
+            TypeError::InvalidGenericAssociation(index, ty) => {
+                write!(f, "_Generic association {} names {}, which isn't a complete object type", index + 1, ty)
+            }
+            TypeError::NoGenericMatch(ty) => write!(f, "no _Generic association matches {} and there is no default", ty),
+            TypeError::AutoWithoutInitializer => write!(f, "an `auto` declaration needs an initializer"),
+            TypeError::AutoDeclarator => write!(f, "`auto` can only declare a plain identifier"),
+            TypeError::AutoVoid => write!(f, "an `auto` object can't have type void"),
+        }
+    }
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), TypeError> {
+    // _Generic(x, int: 1, const int: 2, default: 3) with `const int x;`
+    let const_int = CType::Qualified(Qualifiers::CONST, Box::new(CType::Int { signed: true }));
+    let associations = [Some(CType::Int { signed: true }), Some(const_int.clone()), None];
+    let selected = select_generic(&GenericControl::Expression(const_int.clone()), &associations, is_complete_object)?;
+    assert_eq!(selected, 0);
+
+    // `auto y = x;` is `int`
+    println!("{:?}", deduce_auto(&AutoDeclarator {
+        initializer: Some(const_int),
+        plain_identifier: true,
+        qualifiers: Qualifiers::empty(),
+    })?);
+    Ok(())
+}
+*/
@@ -0,0 +1,7 @@
+// src/types/mod.rs
+//! Type-level semantics shared by the frontend and code generation
+
+pub mod bit_precise;
+pub mod inference;
+pub mod r#typeof;
+pub mod vla;
@@ -0,0 +1,211 @@
+// src/types/vla.rs
+//! Variable length arrays
+//! An array declarator whose length isn't an integer constant expression
+//! declares a VLA (`int buf[n]`). Its length is evaluated once, when
+//! execution reaches the declaration, and its storage lives until control
+//! leaves the enclosing block, including by `goto`, `break` or `continue`.
+//! `sizeof` on a variably modified type is computed at run time from the
+//! saved lengths.
+//!
+//! The parser builds array types with `array_declarator`, checks each
+//! declaration with `check_declaration` and every `goto`/`case` with
+//! `check_jump`. The bytecode engine allocates the storage from the
+//! runtime's `runtime::vla::VlaStack`.
+
+use crate::frontend::types::{CType, VlaBound};
+
+/// The length written between the brackets of an array declarator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayLength {
+    /// `T[]`
+    Omitted,
+    /// An integer constant expression
+    Constant(i128),
+    /// `T[*]`
+    Star,
+    /// Any other expression, already evaluated into this local slot
+    Runtime(u32),
+}
+
+/// Storage duration of the object being declared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageDuration {
+    Static,
+    Thread,
+    Automatic,
+}
+
+/// Where a declaration with a variably modified type appears
+#[derive(Debug, Clone, Copy)]
+pub struct DeclarationContext {
+    pub storage: StorageDuration,
+    pub file_scope: bool,
+    pub struct_member: bool,
+    pub prototype_scope: bool,
+    /// Has `extern` linkage
+    pub linkage: bool,
+    /// An initializer other than C23's empty `= {}`
+    pub initialized: bool,
+}
+
+/// The array type `element[length]`
+pub fn array_declarator(element: CType, length: ArrayLength) -> Result<CType, VlaError> {
+    match length {
+        ArrayLength::Omitted => Ok(CType::Array(Box::new(element), None)),
+        ArrayLength::Constant(n) if n <= 0 => Err(VlaError::NonPositiveLength(n)),
+        ArrayLength::Constant(n) => Ok(CType::Array(Box::new(element), Some(n as usize))),
+        ArrayLength::Star => Ok(CType::VariableArray(Box::new(element), VlaBound::Star)),
+        ArrayLength::Runtime(slot) => Ok(CType::VariableArray(Box::new(element), VlaBound::Runtime(slot))),
+    }
+}
+
+/// Whether `ty` involves a VLA anywhere in its declarator chain, e.g.
+/// `int (*p)[n]` is variably modified though `p` itself has a fixed size
+pub fn is_variably_modified(ty: &CType) -> bool {
+    match ty {
+        CType::VariableArray(..) => true,
+        CType::Array(element, _) | CType::Pointer(element) | CType::Qualified(_, element) => {
+            is_variably_modified(element)
+        }
+        _ => false,
+    }
+}
+
+/// Whether objects of `ty` need run-time sized storage
+pub fn is_vla(ty: &CType) -> bool {
+    match ty {
+        CType::VariableArray(..) => true,
+        CType::Array(element, _) | CType::Qualified(_, element) => is_vla(element),
+        _ => false,
+    }
+}
+
+/// C23 6.7.6.2: only block-scope or prototype-scope identifiers without
+/// linkage may have a variably modified type, and only automatic objects
+/// may be VLAs
+pub fn check_declaration(ty: &CType, context: &DeclarationContext) -> Result<(), VlaError> {
+    if !is_variably_modified(ty) {
+        return Ok(());
+    }
+    if has_star(ty) && !context.prototype_scope {
+        return Err(VlaError::StarOutsidePrototype);
+    }
+    if context.file_scope {
+        return Err(VlaError::FileScope);
+    }
+    if context.struct_member {
+        return Err(VlaError::StructMember);
+    }
+    if context.linkage {
+        return Err(VlaError::Linkage);
+    }
+    if is_vla(ty) {
+        if context.storage != StorageDuration::Automatic {
+            return Err(VlaError::StaticStorage);
+        }
+        if context.initialized {
+            return Err(VlaError::Initializer);
+        }
+    }
+    Ok(())
+}
+
+fn has_star(ty: &CType) -> bool {
+    match ty {
+        CType::VariableArray(_, VlaBound::Star) => true,
+        CType::VariableArray(element, _)
+        | CType::Array(element, _)
+        | CType::Pointer(element)
+        | CType::Qualified(_, element) => has_star(element),
+        _ => false,
+    }
+}
+
+/// `goto`, `switch` and `case` may not jump into the scope of a VLA from
+/// outside it. `from` and `to` are the VLA slots in scope at the jump and at
+/// its target.
+pub fn check_jump(from: &[u32], to: &[u32]) -> Result<(), VlaError> {
+    match to.iter().find(|slot| !from.contains(slot)) {
+        Some(&slot) => Err(VlaError::JumpIntoScope(slot)),
+        None => Ok(()),
+    }
+}
+
+/// `sizeof` of a variably modified type: a constant number of bytes times
+/// the lengths saved in `bounds`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeSize {
+    pub constant: u64,
+    pub bounds: Vec<u32>,
+}
+
+impl RuntimeSize {
+    /// Evaluate with the saved lengths; `None` on overflow
+    pub fn evaluate(&self, length: impl Fn(u32) -> u64) -> Option<u64> {
+        self.bounds
+            .iter()
+            .try_fold(self.constant, |size, &slot| size.checked_mul(length(slot)))
+    }
+}
+
+/// The size of `ty`, with `object_size` giving the size of each non-array
+/// element type. `None` for incomplete types, including `T[*]`.
+pub fn runtime_size(ty: &CType, object_size: &impl Fn(&CType) -> Option<u64>) -> Option<RuntimeSize> {
+    match ty {
+        CType::VariableArray(element, VlaBound::Runtime(slot)) => {
+            let mut size = runtime_size(element, object_size)?;
+            size.bounds.push(*slot);
+            Some(size)
+        }
+        CType::VariableArray(_, VlaBound::Star) | CType::Array(_, None) => None,
+        CType::Array(element, Some(n)) => {
+            let mut size = runtime_size(element, object_size)?;
+            size.constant = size.constant.checked_mul(*n as u64)?;
+            Some(size)
+        }
+        CType::Qualified(_, inner) => runtime_size(inner, object_size),
+        other => Some(RuntimeSize {
+            constant: object_size(other)?,
+            bounds: Vec::new(),
+        }),
+    }
+}
+
+#[derive(Debug)]
+pub enum VlaError {
+    /// A constant array length of zero or less
+    NonPositiveLength(i128),
+    StarOutsidePrototype,
+    FileScope,
+    StructMember,
+    Linkage,
+    StaticStorage,
+    Initializer,
+    /// The jump enters the scope of the VLA in this slot
+    JumpIntoScope(u32),
+}
+
+// Example usage:
+/*
+fn main() -> Result<(), VlaError> {
+    // void f(int n) { int buf[n][4]; ... sizeof buf ... }
+    let int = CType::Int { signed: true };
+    let row = array_declarator(int, ArrayLength::Constant(4))?;
+    let buf = array_declarator(row, ArrayLength::Runtime(0))?;
+    check_declaration(&buf, &DeclarationContext {
+        storage: StorageDuration::Automatic,
+        file_scope: false,
+        struct_member: false,
+        prototype_scope: false,
+        linkage: false,
+        initialized: false,
+    })?;
+
+    let size = runtime_size(&buf, &|_| Some(4)).unwrap();
+    assert_eq!(size.evaluate(|_| 10), Some(160));
+
+    // goto past `int buf[n];` into its scope
+    assert!(check_jump(&[], &[0]).is_err());
+    Ok(())
+}
+*/
@@ -0,0 +1,93 @@
+// tests/async_guests.rs
+//! Bytecode programs as guests of the async driver
+//! Two programs share one single-threaded Tokio runtime. Each accepts a
+//! connection on descriptor 3, reads a message and echoes it back; one
+//! sleeps first. Served one after the other on the only thread, the
+//! second would never get its reply while the first sleeps or waits.
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use interpreter_c::frontend::c23::C23Parser;
+use interpreter_c::interpreter::bytecode::{self, BytecodeGuest};
+use interpreter_c::interpreter::c_runtime::CRuntimeEnvironment;
+use interpreter_c::runtime::async_host::{AsyncEngine, AsyncEngineConfig};
+
+const ECHO: &str = r#"
+long read(int fd, void *buffer, unsigned long length);
+long write(int fd, const void *buffer, unsigned long length);
+int accept(int fd, void *address, void *length);
+int close(int fd);
+int usleep(unsigned int microseconds);
+
+int main(void) {
+    int connection = accept(3, 0, 0);
+    char buffer[32];
+    long length = read(connection, buffer, sizeof buffer);
+    write(connection, buffer, length);
+    close(connection);
+    return (int)length;
+}
+"#;
+
+const SHOUT: &str = r#"
+long read(int fd, void *buffer, unsigned long length);
+long write(int fd, const void *buffer, unsigned long length);
+int accept(int fd, void *address, void *length);
+int close(int fd);
+int usleep(unsigned int microseconds);
+
+int main(void) {
+    int connection = accept(3, 0, 0);
+    char buffer[32];
+    long length = read(connection, buffer, sizeof buffer);
+    usleep(200000);
+    for (long i = 0; i < length; i++)
+        if (buffer[i] >= 'a' && buffer[i] <= 'z')
+            buffer[i] -= 'a' - 'A';
+    write(connection, buffer, length);
+    close(connection);
+    return 100 + (int)length;
+}
+"#;
+
+fn guest(source: &str) -> BytecodeGuest {
+    let unit = C23Parser::new().parse(source).expect("the program parses");
+    let program = bytecode::compile(&unit).expect("the bytecode engine takes it");
+    let runtime = CRuntimeEnvironment::new().expect("a runtime");
+    BytecodeGuest::new(Arc::new(program), runtime, &["guest".to_string()]).expect("main starts")
+}
+
+async fn exchange(address: std::net::SocketAddr, message: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(message).await.unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.unwrap();
+    reply
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn runs_two_programs_on_one_runtime() {
+    let engine = AsyncEngine::new(AsyncEngineConfig { step_budget: 16, ..AsyncEngineConfig::default() });
+    let echo_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let shout_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (echo_address, shout_address) = (echo_listener.local_addr().unwrap(), shout_listener.local_addr().unwrap());
+
+    let shout = engine.spawn(guest(SHOUT), vec![(3, shout_listener)]);
+    let echo = engine.spawn(guest(ECHO), vec![(3, echo_listener)]);
+
+    // The shouting guest is asleep while the echo is served
+    let shouted = tokio::spawn(exchange(shout_address, b"hello"));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let echoed = tokio::time::timeout(Duration::from_millis(150), exchange(echo_address, b"ping"))
+        .await
+        .expect("the echo isn't held up by the sleeping guest");
+    assert_eq!(echoed, b"ping");
+    assert!(!shouted.is_finished());
+
+    assert_eq!(shouted.await.unwrap(), b"HELLO");
+    assert_eq!(echo.await.unwrap().unwrap(), 4);
+    assert_eq!(shout.await.unwrap().unwrap(), 105);
+}
@@ -0,0 +1,81 @@
+// tests/c23_parser.rs
+//! C23's type inference parsed from source
+//! `typeof`, `typeof_unqual`, `_Generic` and a lone `auto` come out of
+//! `C23Parser` as the tree nodes the bytecode compiler resolves, and the
+//! program they're in runs.
+
+use interpreter_c::frontend::ast::*;
+use interpreter_c::frontend::c23::C23Parser;
+use interpreter_c::interpreter::bytecode::{self, Vm};
+use interpreter_c::interpreter::c_runtime::CRuntimeEnvironment;
+
+const PROGRAM: &str = r#"
+typedef long length;
+
+int main(void) {
+    auto count = 3;
+    typeof(count) twice = count * 2;
+    typeof_unqual(const length) total = twice + 1;
+    int kind = _Generic(total, int: 1, length: 2, default: 3);
+    return kind * 10 + (int)total;
+}
+"#;
+
+/// The declarations at the top of `main`'s body
+fn declarations(unit: &TranslationUnit) -> Vec<&Declarator> {
+    let Some(ExternalDeclaration::Function(main)) = unit.items.last() else {
+        panic!("main is the last item");
+    };
+    main.body
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            BlockItem::Declaration(declaration) => Some(&declaration.declarators[0]),
+            BlockItem::Statement(_) => None,
+        })
+        .collect()
+}
+
+#[test]
+fn parses_inferred_types() {
+    let unit = C23Parser::new().parse(PROGRAM).expect("the program parses");
+    let declarations = declarations(&unit);
+
+    assert_eq!(declarations[0].name, "count");
+    assert_eq!(declarations[0].ty, TypeName::Auto);
+
+    let TypeName::Typeof { unqual: false, operand } = &declarations[1].ty else {
+        panic!("twice has a typeof type: {:?}", declarations[1].ty);
+    };
+    assert!(matches!(&**operand, TypeOperand::Expression(Expression { kind: ExpressionKind::Identifier(name), .. }) if name == "count"));
+
+    let TypeName::Typeof { unqual: true, operand } = &declarations[2].ty else {
+        panic!("total has a typeof_unqual type: {:?}", declarations[2].ty);
+    };
+    assert_eq!(**operand, TypeOperand::TypeName(TypeName::Named("length".to_string())));
+
+    let Some(Initializer::Expression(Expression { kind: ExpressionKind::Generic { control, associations }, .. })) =
+        &declarations[3].initializer
+    else {
+        panic!("kind is initialized by _Generic: {:?}", declarations[3].initializer);
+    };
+    assert!(matches!(&**control, TypeOperand::Expression(Expression { kind: ExpressionKind::Identifier(name), .. }) if name == "total"));
+    let types: Vec<_> = associations.iter().map(|(ty, _)| ty.clone()).collect();
+    assert_eq!(types, [Some(TypeName::Int { signed: true }), Some(TypeName::Named("length".to_string())), None]);
+}
+
+#[test]
+fn runs_inferred_types() {
+    let unit = C23Parser::new().parse(PROGRAM).unwrap();
+    let program = bytecode::compile(&unit).expect("the bytecode engine takes it");
+    let mut runtime = CRuntimeEnvironment::new().unwrap();
+    let mut vm = Vm::new(&program, &mut runtime).unwrap();
+    // `total` is a long, so `_Generic` picks 2
+    assert_eq!(vm.run_main(&["inferred".to_string()]).unwrap(), 27);
+}
+
+#[test]
+fn reports_where_parsing_stopped() {
+    let error = C23Parser::new().parse("int main(void) {\n    auto = 1;\n}\n").unwrap_err();
+    assert_eq!(error.to_string(), "2:10: expected an identifier, found '='");
+}
@@ -0,0 +1,91 @@
+// tests/inline_asm.rs
+//! GNU `asm` in C source
+//! `C23Parser` reads the statement and its operands, the bytecode compiler
+//! lowers it, and the native tier runs it; the VM alone can't. Constraints
+//! no register allocation can satisfy are compile errors.
+
+use interpreter_c::frontend::ast::*;
+use interpreter_c::frontend::c23::C23Parser;
+use interpreter_c::interpreter::bytecode::{self, Vm};
+use interpreter_c::interpreter::c_runtime::CRuntimeEnvironment;
+
+// `main` runs in the VM, which calls into native code for `add`
+const PROGRAM: &str = r#"
+static int add(int a, int b) {
+    int sum;
+    __asm__ volatile("addl %2, %0" : "=r"(sum) : "0"(a), "r"(b));
+    return sum;
+}
+
+int main(void) {
+    return add(2, 40);
+}
+"#;
+
+#[test]
+fn parses_operands() {
+    let unit = C23Parser::new().parse(PROGRAM).expect("the program parses");
+    let Some(ExternalDeclaration::Function(add)) = unit.items.first() else {
+        panic!("add is the first item");
+    };
+    let Some(BlockItem::Statement(Statement { kind: StatementKind::Asm { statement, outputs, inputs }, .. })) = add.body.items.get(1)
+    else {
+        panic!("add's second item is the asm statement: {:?}", add.body.items.get(1));
+    };
+    assert!(statement.qualifiers.volatile);
+    assert_eq!(statement.template, "addl %2, %0");
+    let constraints: Vec<_> = statement.outputs.iter().chain(&statement.inputs).map(|operand| operand.constraint.as_str()).collect();
+    assert_eq!(constraints, ["=r", "0", "r"]);
+    let names: Vec<_> = outputs
+        .iter()
+        .chain(inputs)
+        .map(|expression| match &expression.kind {
+            ExpressionKind::Identifier(name) => name.as_str(),
+            other => panic!("operands are identifiers: {:?}", other),
+        })
+        .collect();
+    assert_eq!(names, ["sum", "a", "b"]);
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+#[test]
+fn runs_natively() {
+    let unit = C23Parser::new().parse(PROGRAM).unwrap();
+    let program = bytecode::compile(&unit).expect("the bytecode engine takes it");
+    let mut runtime = CRuntimeEnvironment::new().unwrap();
+    let mut vm = Vm::new(&program, &mut runtime).unwrap().with_native();
+    assert_eq!(vm.run_main(&["asm".to_string()]).unwrap(), 42);
+}
+
+#[test]
+fn interpreting_asm_fails() {
+    let unit = C23Parser::new().parse(PROGRAM).unwrap();
+    let program = bytecode::compile(&unit).unwrap();
+    let mut runtime = CRuntimeEnvironment::new().unwrap();
+    let mut vm = Vm::new(&program, &mut runtime).unwrap();
+    let error = vm.run_main(&["asm".to_string()]).unwrap_err();
+    assert!(error.to_string().contains("'add' runs inline assembly"), "{}", error);
+}
+
+/// The compiler's error for `statement` as the body of a function
+fn compile_error(statement: &str) -> (String, u32) {
+    let source = format!("void f(long x, long y) {{\n    {}\n}}\n", statement);
+    let unit = C23Parser::new().parse(&source).expect("the statement parses");
+    match bytecode::compile(&unit) {
+        Err(bytecode::BytecodeError::Invalid { message, line }) => (message, line),
+        other => panic!("`{}` compiled to {:?}", statement, other.map(|_| ())),
+    }
+}
+
+#[test]
+fn rejects_impossible_constraints() {
+    let (message, line) = compile_error(r#"asm("" : : "a"(x), "a"(y));"#);
+    assert!(message.starts_with("impossible constraints: \"a\"(x) and \"a\"(y) both need"), "{}", message);
+    assert_eq!(line, 2);
+
+    let (message, _) = compile_error(r#"asm("" : "=r"(x) : "r"(y) : "rsp");"#);
+    assert_eq!(message, "the stack or frame pointer 'rsp' can't be clobbered");
+
+    let (message, _) = compile_error(r#"asm("" : "r"(x));"#);
+    assert_eq!(message, "output constraint \"r\" must start with '=' or '+'");
+}
@@ -0,0 +1,146 @@
+// tests/ir_compatibility.rs
+//! Readers take any minor version of their major
+//! Programs are written by hand, field by field as `ir::program` documents
+//! them, as an older or a newer release would have: a 1.0 program with the
+//! retired 64-bit shift opcodes, and a program with instructions from a
+//! minor version this release doesn't know. Both have to read, and run as
+//! far as their instructions allow.
+
+use interpreter_c::interpreter::bytecode::{Instruction, Program, Vm};
+use interpreter_c::interpreter::c_runtime::CRuntimeEnvironment;
+use interpreter_c::ir::{self, ArtifactKind, Encoder, Reader, Writer};
+
+// Section tags of `ir::program`
+const FILE: u8 = 0x01;
+const FUNCTIONS: u8 = 0x02;
+
+/// A function record: name, parameters, registers, frame size and
+/// instructions, each written by one closure; `signature` is 1.2's flag
+fn function(e: &mut Encoder, name: &str, registers: u64, code: &[&dyn Fn(&mut Encoder)], signature: bool) {
+    e.str(name);
+    e.uint(0);
+    e.uint(registers);
+    e.uint(0);
+    e.uint(code.len() as u64);
+    for instruction in code {
+        e.record(|e| instruction(e));
+    }
+    if signature {
+        e.bool(false);
+    }
+}
+
+/// A program file of `functions`, whose header says `minor`
+fn program(minor: u16, functions: &[&dyn Fn(&mut Encoder)]) -> Vec<u8> {
+    let mut writer = Writer::new(ArtifactKind::Program);
+    let mut file = Encoder::new();
+    file.str("compat.c");
+    writer.section(FILE, file);
+    let mut table = Encoder::new();
+    table.uint(functions.len() as u64);
+    for write in functions {
+        table.record(|e| write(e));
+    }
+    writer.section(FUNCTIONS, table);
+
+    let mut bytes = writer.finish();
+    bytes[6..8].copy_from_slice(&minor.to_le_bytes());
+    bytes
+}
+
+fn run(program: &Program) -> Result<i32, String> {
+    let mut runtime = CRuntimeEnvironment::new().expect("a runtime");
+    let mut vm = Vm::new(program, &mut runtime).map_err(|error| error.to_string())?;
+    vm.run_main(&["compat".to_string()]).map_err(|error| error.to_string())
+}
+
+#[test]
+fn reads_and_runs_a_1_0_program() {
+    // main: r0 = 1; r1 = 4; r2 = r0 << r1 (opcode 13, retired in 1.1); return r2
+    let main = |e: &mut Encoder| {
+        let code: [&dyn Fn(&mut Encoder); 4] = [
+            &|e| {
+                e.uint(2);
+                e.uint(0);
+                e.int(1);
+            },
+            &|e| {
+                e.uint(2);
+                e.uint(1);
+                e.int(4);
+            },
+            &|e| [13, 2, 0, 1].into_iter().for_each(|field| e.uint(field)),
+            &|e| [55, 2].into_iter().for_each(|field| e.uint(field)),
+        ];
+        function(e, "main", 3, &code, false);
+    };
+    let bytes = program(0, &[&main]);
+    assert_eq!(Reader::new(&bytes).expect("a header").minor, 0);
+
+    let program = ir::read_program(&bytes).expect("a 1.0 program reads");
+    let main = &program.functions[0];
+    assert_eq!(main.code[2], Instruction::Shl { dst: 2, a: 0, b: 1, bits: 64 });
+    assert!(main.signature.is_none());
+    program.verify().expect("a 1.0 program verifies");
+    assert_eq!(run(&program), Ok(16));
+}
+
+#[test]
+fn reads_instructions_from_a_newer_minor_version() {
+    // future: opcode 1000 with two fields, then a known opcode with a
+    // comparison code past the known ones; return
+    let future = |e: &mut Encoder| {
+        let code: [&dyn Fn(&mut Encoder); 3] = [
+            &|e| [1000, 1, 2].into_iter().for_each(|field| e.uint(field)),
+            &|e| {
+                e.uint(61);
+                e.u8(99);
+                [0, 0, 1].into_iter().for_each(|register| e.uint(register));
+                e.int(3);
+            },
+            &|e| e.uint(56),
+        ];
+        function(e, "future", 2, &code, true);
+    };
+    // main: r0 = 7; return r0
+    let main = |e: &mut Encoder| {
+        let code: [&dyn Fn(&mut Encoder); 2] = [
+            &|e| {
+                e.uint(2);
+                e.uint(0);
+                e.int(7);
+            },
+            &|e| [55, 0].into_iter().for_each(|field| e.uint(field)),
+        ];
+        function(e, "main", 1, &code, true);
+    };
+    // main: call future; return 0
+    let calling = |e: &mut Encoder| {
+        let code: [&dyn Fn(&mut Encoder); 3] = [
+            &|e| {
+                [52, 0, 0, 0].into_iter().for_each(|field| e.uint(field));
+                e.u8(0);
+            },
+            &|e| {
+                e.uint(2);
+                e.uint(0);
+                e.int(0);
+            },
+            &|e| [55, 0].into_iter().for_each(|field| e.uint(field)),
+        ];
+        function(e, "main", 1, &code, true);
+    };
+    let minor = ir::FORMAT_MINOR + 1;
+
+    let program = ir::read_program(&program(minor, &[&future, &main])).expect("unknown instructions read");
+    let code = &program.functions[0].code;
+    assert_eq!(code[0], Instruction::Unknown { opcode: 1000 });
+    assert_eq!(code[1], Instruction::Unknown { opcode: 61 });
+    assert_eq!(code[2], Instruction::ReturnVoid);
+    // `future` is never called
+    assert_eq!(run(&program), Ok(7));
+
+    let program = ir::read_program(&program(minor, &[&future, &calling])).expect("unknown instructions read");
+    let error = run(&program).expect_err("running an unknown instruction fails");
+    assert!(error.contains("opcode 1000"), "{}", error);
+}
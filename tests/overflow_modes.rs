@@ -0,0 +1,101 @@
+// tests/overflow_modes.rs
+//! Signed overflow behaves the same in the interpreter and the JIT
+//! Every operation `optimizer::overflow` covers is run under the default
+//! mode, `-fwrapv` and `-ftrapv`, once with `-i --engine=bytecode` and once
+//! JIT-compiled. The exit status, stdout and overflow reports must agree.
+//! The interpreter reports a line without a column, so reports are
+//! compared by line and message.
+#![cfg(feature = "llvm")]
+
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// What each case computes: the report's name for the operation, the two
+/// `volatile` operands' type and values, the expression, and the
+/// two's-complement result
+const CASES: &[(&str, &str, &str, &str, &str, &str)] = &[
+    ("addition", "int", "INT_MAX", "1", "a + b", "-2147483648"),
+    ("subtraction", "int", "INT_MIN", "1", "a - b", "2147483647"),
+    ("multiplication", "int", "INT_MAX", "2", "a * b", "-2"),
+    ("negation", "int", "INT_MIN", "0", "-a", "-2147483648"),
+    ("division", "int", "INT_MIN", "-1", "a / b", "-2147483648"),
+    ("remainder", "int", "INT_MIN", "-1", "a % b", "0"),
+    ("left shift", "int", "1", "31", "a << b", "-2147483648"),
+    ("addition", "long long", "LLONG_MAX", "1", "a + b", "-9223372036854775808"),
+];
+
+const MODES: &[&[&str]] = &[&[], &["-fwrapv"], &["-ftrapv"]];
+
+fn program(ty: &str, a: &str, b: &str, expression: &str) -> String {
+    let format = if ty == "int" { "%d" } else { "%lld" };
+    format!(
+        "#include <limits.h>\n#include <stdio.h>\n\nint main(void) {{\n    volatile {ty} a = {a}, b = {b};\n    \
+         {ty} r = {expression};\n    (void)b;\n    printf(\"{format}\\n\", r);\n    return 0;\n}}\n"
+    )
+}
+
+fn run(path: &PathBuf, mode: &[&str], interpret: bool) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_c_ide"));
+    command.arg("run").args(mode);
+    if interpret {
+        command.args(["-i", "--engine=bytecode"]);
+    }
+    command.arg(path).output().expect("the binary runs")
+}
+
+/// (line, message) of every overflow report on stderr
+fn reports(output: &Output) -> Vec<(String, String)> {
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|line| {
+            let (location, message) = line.split_once(": runtime error: ")?;
+            let fields: Vec<&str> = location.rsplit(':').take(2).collect();
+            // `file:line:column` from the JIT, `file:line` from the interpreter
+            let line = match fields.as_slice() {
+                [column, line] if column.parse::<u32>().is_ok() && line.parse::<u32>().is_ok() => line,
+                [line, ..] => line,
+                [] => return None,
+            };
+            Some((line.to_string(), message.to_string()))
+        })
+        .collect()
+}
+
+#[test]
+fn interpreter_and_jit_agree_in_every_mode() {
+    let directory = std::env::temp_dir().join(format!("overflow-modes-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    for (index, &(name, ty, a, b, expression, wrapped)) in CASES.iter().enumerate() {
+        let path = directory.join(format!("case{}.c", index));
+        fs::write(&path, program(ty, a, b, expression)).unwrap();
+        let report = format!("signed integer overflow in {} in 'main'", name);
+
+        for &mode in MODES {
+            let context = format!("{} ({}) with {:?}", expression, ty, mode);
+            let interpreted = run(&path, mode, true);
+            let compiled = run(&path, mode, false);
+
+            assert_eq!(interpreted.status, compiled.status, "exit status of {}", context);
+            assert_eq!(interpreted.stdout, compiled.stdout, "stdout of {}", context);
+            assert_eq!(reports(&interpreted), reports(&compiled), "reports of {}", context);
+
+            let expected_reports = match mode {
+                ["-fwrapv"] => vec![],
+                _ => vec![("6".to_string(), report.clone())],
+            };
+            assert_eq!(reports(&compiled), expected_reports, "reports of {}", context);
+            match mode {
+                ["-ftrapv"] => assert_eq!(compiled.status.signal(), Some(libc::SIGABRT), "{}", context),
+                _ => {
+                    assert!(compiled.status.success(), "{}", context);
+                    assert_eq!(String::from_utf8_lossy(&compiled.stdout), format!("{}\n", wrapped), "{}", context);
+                }
+            }
+        }
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+}
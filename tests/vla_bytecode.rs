@@ -0,0 +1,76 @@
+// tests/vla_bytecode.rs
+//! Multi-dimensional VLAs on the bytecode engine
+//! The program is run with `-i --engine=bytecode --emit-ir`: the IR file is
+//! only written when the bytecode compiler took the program, so the test
+//! also fails if it fell back to the tree walker.
+
+use std::fs;
+use std::process::Command;
+
+use interpreter_c::interpreter::bytecode::Instruction;
+use interpreter_c::ir;
+
+const PROGRAM: &str = r#"#include <stdio.h>
+
+static void grid(int rows, int columns) {
+    int grid[rows][columns];
+    for (int i = 0; i < rows; i++)
+        for (int j = 0; j < columns; j++)
+            grid[i][j] = i * columns + j;
+    printf("%zu %zu\n", sizeof grid, sizeof grid[0]);
+    printf("%d %ld %ld\n", grid[2][3], (long)(&grid[2][0] - &grid[0][0]), (long)((grid + 2) - grid));
+
+    int total = 0;
+    int (*row)[columns] = grid;
+    for (int i = 0; i < rows; i++, row++)
+        total += (*row)[columns - 1];
+    printf("%d\n", total);
+}
+
+static void cube(int n) {
+    int cube[2][n][3];
+    int *cell = &cube[0][0][0];
+    for (int i = 0; i < 2 * n * 3; i++)
+        cell[i] = i;
+    printf("%zu %zu %d\n", sizeof cube, sizeof cube[1], cube[1][3][2]);
+}
+
+int main(void) {
+    for (int pass = 0; pass < 2; pass++) {
+        grid(3, 4);
+        cube(4);
+    }
+    return 0;
+}
+"#;
+
+#[test]
+fn runs_multi_dimensional_vlas() {
+    let directory = std::env::temp_dir().join(format!("vla-bytecode-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let source = directory.join("grid.c");
+    let emitted = directory.join("grid.ir");
+    fs::write(&source, PROGRAM).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_c_ide"))
+        .args(["run", "-i", "--engine=bytecode", "--no-cache", "--emit-ir"])
+        .arg(&emitted)
+        .arg(&source)
+        .output()
+        .expect("the binary runs");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let once = "48 16\n11 8 2\n21\n96 48 23\n";
+    assert_eq!(String::from_utf8_lossy(&output.stdout), once.repeat(2));
+
+    let program = ir::read_program(&fs::read(&emitted).expect("the bytecode engine ran it")).unwrap();
+    let allocations = program
+        .functions
+        .iter()
+        .flat_map(|function| &function.code)
+        .filter(|instruction| matches!(instruction, Instruction::VlaAllocate { .. }))
+        .count();
+    // One allocation per VLA, however many dimensions it has
+    assert_eq!(allocations, 2);
+
+    fs::remove_dir_all(&directory).unwrap();
+}